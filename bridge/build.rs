@@ -1,4 +1,6 @@
+use std::env;
 use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn main() {
     // rerun build script if bridge contract has changed.
@@ -22,6 +24,18 @@ fn main() {
     let solc_version = output_string.lines().last().unwrap();
     println!("cargo:rustc-env=SOLC_VERSION={}", solc_version);
 
+    // make the build time (unix seconds) and the cargo version (`cargo --version`)
+    // available via `env!("BUILD_TIMESTAMP")` and `env!("CARGO_VERSION")` in sources
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let output = Command::new(cargo).args(&["--version"]).output().unwrap();
+    let cargo_version = String::from_utf8(output.stdout).unwrap();
+    println!("cargo:rustc-env=CARGO_VERSION={}", cargo_version.trim());
+
     // compile contracts for inclusion with ethabis `use_contract!`
     match Command::new("solc")
         .arg("--abi")
@@ -0,0 +1,45 @@
+//! Verifies a webhook of the bridge alerts.
+//!
+//! reads the body of the request from stdin, e.g. as captured by a webhook receiver:
+//!
+//! ```text
+//! cargo run --example verify_webhook -- <secret_file> <signature> < body.json
+//! ```
+//!
+//! `<signature>` is the value of the `X-Bridge-Signature` header. prints the payload
+//! and exits with status 1 unless the body was signed with the secret.
+
+extern crate bridge;
+
+use std::env;
+use std::io::{self, Read};
+use std::process;
+use bridge::config::Password;
+use bridge::webhook::verify_webhook;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: verify_webhook <secret_file> <signature> < body.json");
+        process::exit(2);
+    }
+    let secret = match Password::from_file(&args[1]) {
+        Ok(secret) => secret,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+    let mut body = Vec::new();
+    if let Err(err) = io::stdin().read_to_end(&mut body) {
+        eprintln!("cannot read the body from stdin: {}", err);
+        process::exit(2);
+    }
+    match verify_webhook(secret.as_str().as_bytes(), &body, &args[2]) {
+        Ok(payload) => println!("{}", payload),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use ethabi::{self, Token};
+use tiny_keccak::keccak256;
+use web3::types::{Address, H256, H520};
+use error::{Error, ErrorKind};
+
+/// version of the acknowledgement format written by this version of the bridge.
+/// the first byte of every acknowledgement.
+pub const ACKNOWLEDGEMENT_VERSION: u8 = 1;
+
+/// length of a version 1 `Acknowledgement.to_bytes()` in bytes
+pub const ACKNOWLEDGEMENT_LENGTH: usize = 93;
+
+/// function of the registry contract acknowledgements are published to.
+/// called with the acknowledgement and the signature of the authority over it.
+pub const REGISTRY_FUNCTION: &str = "acknowledge(bytes,bytes)";
+
+/// statement of an authority that it relayed the deposit of a home transaction
+/// in a foreign transaction. signed with the authority key and published to foreign.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Acknowledgement {
+    /// transaction on home (main) that emitted the deposit
+    pub home_transaction_hash: H256,
+    /// transaction on foreign (side) that relayed the deposit
+    pub foreign_transaction_hash: H256,
+    /// authority that relayed the deposit
+    pub authority: Address,
+    /// seconds since the unix epoch at which the deposit was relayed
+    pub timestamp: u64,
+}
+
+impl Acknowledgement {
+    /// parses an acknowledgement from a byte slice.
+    /// fails with `ErrorKind::InvalidAcknowledgement` on an unknown version
+    /// or if `bytes` are not exactly as long as the version requires.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes.first() {
+            Some(&ACKNOWLEDGEMENT_VERSION) => (),
+            Some(version) => {
+                return Err(ErrorKind::InvalidAcknowledgement(format!(
+                    "unsupported version {}",
+                    version
+                )).into())
+            }
+            None => return Err(ErrorKind::InvalidAcknowledgement("empty".into()).into()),
+        }
+        if bytes.len() != ACKNOWLEDGEMENT_LENGTH {
+            return Err(ErrorKind::InvalidAcknowledgement(format!(
+                "must be {} bytes long, got {} bytes",
+                ACKNOWLEDGEMENT_LENGTH,
+                bytes.len()
+            )).into());
+        }
+        let timestamp = bytes[85..ACKNOWLEDGEMENT_LENGTH]
+            .iter()
+            .fold(0u64, |timestamp, byte| (timestamp << 8) | *byte as u64);
+        Ok(Acknowledgement {
+            home_transaction_hash: bytes[1..33].into(),
+            foreign_transaction_hash: bytes[33..65].into(),
+            authority: bytes[65..85].into(),
+            timestamp,
+        })
+    }
+
+    /// serializes the acknowledgement in the current version.
+    /// these are the bytes the authority signs.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = vec![0u8; ACKNOWLEDGEMENT_LENGTH];
+        result[0] = ACKNOWLEDGEMENT_VERSION;
+        result[1..33].copy_from_slice(&self.home_transaction_hash.0[..]);
+        result[33..65].copy_from_slice(&self.foreign_transaction_hash.0[..]);
+        result[65..85].copy_from_slice(&self.authority.0[..]);
+        for (index, byte) in result[85..ACKNOWLEDGEMENT_LENGTH].iter_mut().enumerate() {
+            *byte = (self.timestamp >> (8 * (7 - index))) as u8;
+        }
+        result
+    }
+
+    /// call data of `REGISTRY_FUNCTION` publishing the acknowledgement with `signature`
+    pub fn to_registry_payload(&self, signature: &H520) -> Vec<u8> {
+        let mut payload = keccak256(REGISTRY_FUNCTION.as_bytes())[..4].to_vec();
+        payload.extend(ethabi::encode(&[
+            Token::Bytes(self.to_bytes()),
+            Token::Bytes(signature.0.to_vec()),
+        ]));
+        payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hex::FromHex;
+    use error::ErrorKind;
+    use super::*;
+
+    fn acknowledgement() -> Acknowledgement {
+        Acknowledgement {
+            home_transaction_hash: 1.into(),
+            foreign_transaction_hash: 2.into(),
+            authority: 3.into(),
+            timestamp: 0x5b00_0000,
+        }
+    }
+
+    #[test]
+    fn test_acknowledgement_to_and_from_bytes() {
+        let bytes = acknowledgement().to_bytes();
+        assert_eq!(
+            "01\
+             0000000000000000000000000000000000000000000000000000000000000001\
+             0000000000000000000000000000000000000000000000000000000000000002\
+             0000000000000000000000000000000000000003\
+             000000005b000000"
+                .from_hex()
+                .unwrap(),
+            bytes
+        );
+        assert_eq!(acknowledgement(), Acknowledgement::parse(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_acknowledgement_parse_checks_version_and_length() {
+        let mut bytes = acknowledgement().to_bytes();
+        bytes[0] = 2;
+        match *Acknowledgement::parse(&bytes).unwrap_err().kind() {
+            ErrorKind::InvalidAcknowledgement(ref reason) => {
+                assert_eq!("unsupported version 2", reason.as_str())
+            }
+            ref kind => panic!("expected invalid acknowledgement, got {:?}", kind),
+        }
+        bytes[0] = ACKNOWLEDGEMENT_VERSION;
+        assert!(Acknowledgement::parse(&bytes[..ACKNOWLEDGEMENT_LENGTH - 1]).is_err());
+        bytes.push(0);
+        assert!(Acknowledgement::parse(&bytes).is_err());
+        assert!(Acknowledgement::parse(&[]).is_err());
+    }
+
+    #[test]
+    fn test_acknowledgement_registry_payload() {
+        let signature: H520 = [4u8; 65].into();
+        let payload = acknowledgement().to_registry_payload(&signature);
+        assert_eq!(&keccak256(b"acknowledge(bytes,bytes)")[..4], &payload[..4]);
+        let decoded = ethabi::decode(
+            &[ethabi::ParamType::Bytes, ethabi::ParamType::Bytes],
+            &payload[4..],
+        ).unwrap();
+        assert_eq!(
+            vec![
+                Token::Bytes(acknowledgement().to_bytes()),
+                Token::Bytes(signature.0.to_vec()),
+            ],
+            decoded
+        );
+    }
+}
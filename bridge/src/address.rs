@@ -0,0 +1,115 @@
+use rustc_hex::{FromHex, ToHex};
+use tiny_keccak::keccak256;
+use web3::types::Address;
+use error::{ErrorKind, Result};
+
+/// parses the address `value` of the config option or cli argument `key`.
+///
+/// accepts 40 hex digits with or without `0x` prefix. all lowercase or all uppercase
+/// digits are taken as they are, mixed case digits must carry a valid EIP-55 checksum.
+pub fn parse_address(key: &str, value: &str) -> Result<Address> {
+    let invalid = |reason: String| ErrorKind::InvalidAddress(key.into(), value.into(), reason);
+    let hex = if value.starts_with("0x") || value.starts_with("0X") {
+        &value[2..]
+    } else {
+        value
+    };
+    if hex.len() != 40 {
+        bail!(invalid(format!("expected 40 hex digits, got {}", hex.len())));
+    }
+    let bytes: Vec<u8> = hex.from_hex()
+        .map_err(|_| invalid("not a hex string".into()))?;
+    let address: Address = bytes.as_slice().into();
+    let upper = hex.chars().any(|c| c.is_ascii_uppercase());
+    let lower = hex.chars().any(|c| c.is_ascii_lowercase());
+    if upper && lower {
+        let expected = checksummed(&address);
+        if expected[2..] != *hex {
+            bail!(invalid(format!("invalid EIP-55 checksum, expected {}", expected)));
+        }
+    }
+    Ok(address)
+}
+
+/// `address` as 0x-prefixed hex with the EIP-55 checksum:
+/// a letter is uppercase if the nibble of the keccak256 hash of the lowercase hex at its
+/// position is 8 or greater.
+pub fn checksummed(address: &Address) -> String {
+    let hex: String = address.0[..].to_hex();
+    let hash = keccak256(hex.as_bytes());
+    let digits = hex.chars().enumerate().map(|(i, c)| {
+        let nibble = if i % 2 == 0 {
+            hash[i / 2] >> 4
+        } else {
+            hash[i / 2] & 0x0f
+        };
+        if nibble >= 8 {
+            c.to_ascii_uppercase()
+        } else {
+            c
+        }
+    });
+    format!("0x{}", digits.collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::Address;
+    use super::{checksummed, parse_address};
+
+    // test vectors of EIP-55
+    const CHECKSUMMED: [&str; 4] = [
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+        "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+    ];
+
+    #[test]
+    fn test_checksummed() {
+        for address in &CHECKSUMMED {
+            let parsed: Address = address[2..].to_lowercase().parse().unwrap();
+            assert_eq!(*address, checksummed(&parsed));
+        }
+    }
+
+    #[test]
+    fn test_parse_address() {
+        let expected: Address = "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".parse().unwrap();
+        let accepted = [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+            "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+            "0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED",
+            "0X5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED",
+        ];
+        for value in &accepted {
+            assert_eq!(expected, parse_address("home.account", value).unwrap(), "{}", value);
+        }
+
+        let rejected = [
+            (
+                "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD",
+                "invalid EIP-55 checksum, expected 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            ),
+            (
+                "5AaEb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+                "invalid EIP-55 checksum, expected 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            ),
+            ("0x5aaeb6053f3e94c9b9a09f33669435e7ef1bea", "expected 40 hex digits, got 38"),
+            ("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed00", "expected 40 hex digits, got 42"),
+            ("", "expected 40 hex digits, got 0"),
+            ("0x", "expected 40 hex digits, got 0"),
+            ("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaeg", "not a hex string"),
+            ("0x 5aaeb6053f3e94c9b9a09f33669435e7ef1beae", "not a hex string"),
+        ];
+        for &(value, reason) in &rejected {
+            let err = parse_address("authorities.accounts[1]", value).unwrap_err();
+            assert_eq!(
+                format!("invalid authorities.accounts[1] {:?}: {}", value, reason),
+                err.to_string()
+            );
+        }
+    }
+}
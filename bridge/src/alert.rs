@@ -0,0 +1,817 @@
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use base64;
+use reqwest;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use serde_json::Value;
+use config::{AlertsConfig, Password};
+use error::{Error, ResultExt};
+use webhook::{canonical_json, sign_webhook, PAYLOAD_VERSION, SIGNATURE_HEADER};
+
+/// How urgent an alert is. sinks only deliver alerts at or above their `min_severity`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    /// severity of a PagerDuty Events API v2 event
+    pub fn pagerduty(&self) -> &'static str {
+        match *self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Severity::Info => "INFO",
+            Severity::Warning => "WARNING",
+            Severity::Critical => "CRITICAL",
+        })
+    }
+}
+
+/// A condition operators have to know about.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Alert {
+    pub severity: Severity,
+    /// kind of condition, e.g. `circuit_open`
+    pub condition: &'static str,
+    /// what the condition is about, e.g. a component or a transaction.
+    /// alerts of the same condition and subject are the same incident
+    pub subject: String,
+    pub summary: String,
+}
+
+impl Alert {
+    pub fn new<S: Into<String>, M: Into<String>>(
+        severity: Severity,
+        condition: &'static str,
+        subject: S,
+        summary: M,
+    ) -> Self {
+        Alert {
+            severity,
+            condition,
+            subject: subject.into(),
+            summary: summary.into(),
+        }
+    }
+
+    /// PagerDuty groups events with the same key into one incident
+    pub fn dedup_key(&self) -> String {
+        format!("parity-bridge/{}/{}", self.condition, self.subject)
+    }
+}
+
+/// Delivers alerts to operators.
+///
+/// every sink runs on a thread of its own: `send` may block until the alert is delivered.
+pub trait AlertSink: Send {
+    /// name of the sink in logs
+    fn name(&self) -> &str;
+
+    fn send(&mut self, alert: &Alert) -> Result<(), Error>;
+}
+
+pub fn http_client(timeout: Duration) -> Result<reqwest::Client, Error> {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .chain_err(|| "Cannot create http client")
+}
+
+/// sets the header `name` of `headers` to `value`. fails if `value` isn't a valid header value.
+pub fn set_header(headers: &mut HeaderMap, name: &str, value: &str) -> Result<(), Error> {
+    let name = HeaderName::from_bytes(name.as_bytes())
+        .chain_err(|| format!("Invalid header name {}", name))?;
+    let value = HeaderValue::from_str(value)
+        .chain_err(|| format!("Invalid value of header {}", name))?;
+    headers.insert(name, value);
+    Ok(())
+}
+
+/// posts the json `body` to `url` with `headers`. errors never contain `url`: it is usually
+/// a secret. `sink` names the receiver in errors
+pub fn post(
+    client: &reqwest::Client,
+    url: &str,
+    mut headers: HeaderMap,
+    body: String,
+    sink: &str,
+) -> Result<(), Error> {
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let response = client
+        .post(url)
+        .headers(headers)
+        .body(body)
+        .send()
+        .chain_err(|| format!("Cannot post to {}", sink))?;
+    if !response.status().is_success() {
+        bail!("{} responded with status {}", sink, response.status());
+    }
+    Ok(())
+}
+
+fn post_json(client: &reqwest::Client, url: &str, body: &Value, sink: &str) -> Result<(), Error> {
+    post(client, url, HeaderMap::new(), body.to_string(), sink)
+}
+
+/// payload posted by `WebhookSink` as its canonical json, see `webhook`.
+/// `text` is what Slack incoming webhooks display
+pub fn webhook_payload(alert: &Alert) -> Value {
+    json!({
+        "payload_version": PAYLOAD_VERSION,
+        "text": format!("[{}] {}", alert.severity, alert.summary),
+        "severity": alert.severity,
+        "condition": alert.condition,
+        "subject": alert.subject,
+    })
+}
+
+/// Posts alerts as canonical JSON to a webhook, signed in `SIGNATURE_HEADER` if it has a secret.
+pub struct WebhookSink {
+    name: String,
+    url: String,
+    secret: Option<Password>,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new<S: Into<String>>(name: S, url: &Password, timeout: Duration) -> Result<Self, Error> {
+        Ok(WebhookSink {
+            name: name.into(),
+            url: url.as_str().into(),
+            secret: None,
+            client: http_client(timeout)?,
+        })
+    }
+
+    /// signs the posted bodies with `secret`.
+    pub fn signed_with(mut self, secret: Password) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+}
+
+impl AlertSink for WebhookSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, alert: &Alert) -> Result<(), Error> {
+        // the signature covers the exact bytes posted
+        let body = canonical_json(&webhook_payload(alert))?;
+        let mut headers = HeaderMap::new();
+        if let Some(ref secret) = self.secret {
+            let signature = sign_webhook(secret.as_str().as_bytes(), body.as_bytes());
+            set_header(&mut headers, SIGNATURE_HEADER, &signature)?;
+        }
+        post(&self.client, &self.url, headers, body, &self.name)
+    }
+}
+
+/// PagerDuty Events API v2 event triggering an incident for `alert`
+pub fn pagerduty_event(routing_key: &str, source: &str, alert: &Alert) -> Value {
+    json!({
+        "routing_key": routing_key,
+        "event_action": "trigger",
+        "dedup_key": alert.dedup_key(),
+        "payload": {
+            "summary": alert.summary,
+            "source": source,
+            "severity": alert.severity.pagerduty(),
+            "component": alert.condition,
+            "custom_details": {
+                "subject": alert.subject,
+            },
+        },
+    })
+}
+
+/// Triggers PagerDuty incidents.
+pub struct PagerDutySink {
+    url: String,
+    routing_key: Password,
+    source: String,
+    client: reqwest::Client,
+}
+
+impl PagerDutySink {
+    pub fn new<S: Into<String>, O: Into<String>>(
+        url: S,
+        routing_key: Password,
+        source: O,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        Ok(PagerDutySink {
+            url: url.into(),
+            routing_key,
+            source: source.into(),
+            client: http_client(timeout)?,
+        })
+    }
+}
+
+impl AlertSink for PagerDutySink {
+    fn name(&self) -> &str {
+        "pagerduty"
+    }
+
+    fn send(&mut self, alert: &Alert) -> Result<(), Error> {
+        let event = pagerduty_event(self.routing_key.as_str(), &self.source, alert);
+        post_json(&self.client, &self.url, &event, "pagerduty")
+    }
+}
+
+/// email sent by `SmtpSink` with `\r\n` line endings and without dot stuffing
+pub fn email_message(from: &str, to: &[String], alert: &Alert) -> String {
+    let to: Vec<_> = to.iter().map(|to| format!("<{}>", to)).collect();
+    let mut message = format!(
+        "From: <{}>\r\nTo: {}\r\nSubject: [{}] parity-bridge {}: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        from,
+        to.join(", "),
+        alert.severity,
+        alert.condition,
+        alert.subject
+    );
+    for line in alert.summary.lines() {
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message
+}
+
+/// Sends alerts as emails through an unencrypted SMTP connection.
+pub struct SmtpSink {
+    server: String,
+    from: String,
+    to: Vec<String>,
+    credentials: Option<(String, Password)>,
+    timeout: Duration,
+}
+
+impl SmtpSink {
+    /// `server` is `host:port`. authenticates with `AUTH PLAIN` if `credentials` are given
+    pub fn new<S: Into<String>, F: Into<String>>(
+        server: S,
+        from: F,
+        to: Vec<String>,
+        credentials: Option<(String, Password)>,
+        timeout: Duration,
+    ) -> Self {
+        SmtpSink {
+            server: server.into(),
+            from: from.into(),
+            to,
+            credentials,
+            timeout,
+        }
+    }
+}
+
+/// reads a reply of possibly several lines and checks its code
+fn smtp_reply<R: BufRead>(reader: &mut R, expected: u16) -> Result<(), Error> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("smtp server closed the connection");
+        }
+        // `250-` continues the reply, `250 ` ends it
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        let code: Option<u16> = line.get(..3).and_then(|code| code.parse().ok());
+        if code != Some(expected) {
+            bail!(
+                "smtp server replied {:?}, expected {}",
+                line.trim(),
+                expected
+            );
+        }
+        return Ok(());
+    }
+}
+
+fn smtp_command<W: Write, R: BufRead>(
+    writer: &mut W,
+    reader: &mut R,
+    command: &str,
+    expected: u16,
+) -> Result<(), Error> {
+    writer.write_all(command.as_bytes())?;
+    writer.write_all(b"\r\n")?;
+    writer.flush()?;
+    smtp_reply(reader, expected)
+}
+
+impl AlertSink for SmtpSink {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    fn send(&mut self, alert: &Alert) -> Result<(), Error> {
+        let mut stream = TcpStream::connect(&*self.server)
+            .chain_err(|| format!("Cannot connect to smtp server {}", self.server))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        smtp_reply(&mut reader, 220)?;
+        smtp_command(&mut stream, &mut reader, "EHLO parity-bridge", 250)?;
+        if let Some((ref username, ref password)) = self.credentials {
+            let token = base64::encode(format!("\0{}\0{}", username, password.as_str()).as_bytes());
+            smtp_command(&mut stream, &mut reader, &format!("AUTH PLAIN {}", token), 235)
+                .chain_err(|| "smtp authentication failed")?;
+        }
+        smtp_command(&mut stream, &mut reader, &format!("MAIL FROM:<{}>", self.from), 250)?;
+        for to in &self.to {
+            smtp_command(&mut stream, &mut reader, &format!("RCPT TO:<{}>", to), 250)?;
+        }
+        smtp_command(&mut stream, &mut reader, "DATA", 354)?;
+        let mut data = String::new();
+        for line in email_message(&self.from, &self.to, alert).split("\r\n") {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        // `email_message` ends in `\r\n`, leaving an empty last line
+        data.truncate(data.len() - 2);
+        data.push('.');
+        smtp_command(&mut stream, &mut reader, &data, 250)?;
+        // the email was accepted, a failed goodbye doesn't matter
+        let _ = smtp_command(&mut stream, &mut reader, "QUIT", 221);
+        Ok(())
+    }
+}
+
+struct Worker {
+    name: String,
+    min_severity: Severity,
+    /// `None` once the alerts are dropped
+    sender: Option<mpsc::Sender<Alert>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+/// Delivers alerts to all configured sinks.
+///
+/// sinks deliver on threads of their own: a slow or failing sink
+/// holds up neither the bridge nor the other sinks.
+/// failed deliveries are logged and not retried.
+#[derive(Default)]
+pub struct Alerts {
+    workers: Vec<Worker>,
+}
+
+impl Alerts {
+    /// sinks of `config`. fails if a secret wasn't read from its file.
+    pub fn from_config(config: &AlertsConfig) -> Result<Self, Error> {
+        let mut alerts = Alerts::default();
+        for (index, webhook) in config.webhooks.iter().enumerate() {
+            let url = match webhook.url {
+                Some(ref url) => url,
+                None => bail!("url of alerts.webhooks[{}] was not read from url_file", index),
+            };
+            let mut sink = WebhookSink::new(format!("webhook {}", index), url, config.timeout)?;
+            if webhook.secret_file.is_some() {
+                match webhook.secret {
+                    Some(ref secret) => sink = sink.signed_with(secret.clone()),
+                    None => bail!(
+                        "secret of alerts.webhooks[{}] was not read from secret_file",
+                        index
+                    ),
+                }
+            }
+            alerts.add(sink, webhook.min_severity)?;
+        }
+        if let Some(ref pagerduty) = config.pagerduty {
+            let routing_key = match pagerduty.routing_key {
+                Some(ref routing_key) => routing_key.clone(),
+                None => bail!("alerts.pagerduty.routing_key was not read from routing_key_file"),
+            };
+            let sink = PagerDutySink::new(
+                pagerduty.url.clone(),
+                routing_key,
+                pagerduty.source.clone(),
+                config.timeout,
+            )?;
+            alerts.add(sink, pagerduty.min_severity)?;
+        }
+        if let Some(ref email) = config.email {
+            let credentials = match (&email.username, &email.password) {
+                (&Some(ref username), &Some(ref password)) => {
+                    Some((username.clone(), password.clone()))
+                }
+                (&None, _) => None,
+                (&Some(_), &None) => {
+                    bail!("alerts.email.password was not read from password_file")
+                }
+            };
+            let sink = SmtpSink::new(
+                email.smtp_server.clone(),
+                email.from.clone(),
+                email.to.clone(),
+                credentials,
+                config.timeout,
+            );
+            alerts.add(sink, email.min_severity)?;
+        }
+        Ok(alerts)
+    }
+
+    /// delivers alerts at or above `min_severity` to `sink`
+    pub fn add<S: AlertSink + 'static>(&mut self, sink: S, min_severity: Severity) -> Result<(), Error> {
+        let name = sink.name().to_owned();
+        let (sender, receiver) = mpsc::channel::<Alert>();
+        let thread = thread::Builder::new()
+            .name(format!("alerts ({})", name))
+            .spawn(move || {
+                let mut sink = sink;
+                for alert in receiver {
+                    if let Err(err) = sink.send(&alert) {
+                        warn!(
+                            target: "bridge::alert",
+                            "cannot deliver alert to {}: {}. alert: [{}] {}",
+                            sink.name(),
+                            err,
+                            alert.severity,
+                            alert.summary
+                        );
+                    }
+                }
+            })?;
+        self.workers.push(Worker {
+            name,
+            min_severity,
+            sender: Some(sender),
+            thread: Some(thread),
+        });
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    /// queues `alert` for delivery by every sink it is severe enough for. never blocks.
+    pub fn alert(&self, alert: Alert) {
+        for worker in &self.workers {
+            if alert.severity < worker.min_severity {
+                continue;
+            }
+            if let Some(ref sender) = worker.sender {
+                if sender.send(alert.clone()).is_err() {
+                    warn!(
+                        target: "bridge::alert",
+                        "alert sink {} stopped. dropping alert: [{}] {}",
+                        worker.name,
+                        alert.severity,
+                        alert.summary
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Alerts {
+    /// waits for queued alerts to be delivered. deliveries time out after `alerts.timeout`
+    fn drop(&mut self) {
+        for worker in &mut self.workers {
+            worker.sender.take();
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use serde_json::{self, Value};
+    use config::Password;
+    use error::Error;
+    use webhook::{canonical_json, verify_webhook};
+    use super::{email_message, pagerduty_event, webhook_payload, Alert, AlertSink, Alerts,
+                PagerDutySink, Severity, SmtpSink, WebhookSink};
+
+    fn alert() -> Alert {
+        Alert::new(
+            Severity::Critical,
+            "circuit_open",
+            "DepositRelay",
+            "circuit of DepositRelay opened",
+        )
+    }
+
+    /// accepts one http request, responds with `status` and sends the header lines and the body
+    /// of the request to the returned receiver
+    fn http_requests(status: &'static str) -> (String, mpsc::Receiver<(Vec<String>, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alert", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                let lowercase = line.to_lowercase();
+                if lowercase.starts_with("content-length:") {
+                    length = lowercase[15..].trim().parse().unwrap();
+                }
+                headers.push(line.trim_right().to_owned());
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+            sender.send((headers, body)).unwrap();
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            ).unwrap();
+        });
+        (url, receiver)
+    }
+
+    /// accepts one http request, responds with `status` and sends the request body to the returned receiver
+    fn http_server(status: &'static str) -> (String, mpsc::Receiver<Value>) {
+        let (url, requests) = http_requests(status);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            if let Ok((_, body)) = requests.recv() {
+                let _ = sender.send(serde_json::from_slice(&body).unwrap());
+            }
+        });
+        (url, receiver)
+    }
+
+    /// accepts one smtp session and sends the lines received to the returned receiver
+    fn smtp_server() -> (String, mpsc::Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut lines = Vec::new();
+            let mut data = false;
+            stream.write_all(b"220 localhost\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_matches(|c| c == '\r' || c == '\n').to_owned();
+                let reply: &[u8] = if data {
+                    if line == "." {
+                        data = false;
+                        b"250 queued\r\n"
+                    } else {
+                        b""
+                    }
+                } else if line.starts_with("EHLO") {
+                    b"250-localhost\r\n250 AUTH PLAIN\r\n"
+                } else if line.starts_with("AUTH") {
+                    b"235 authenticated\r\n"
+                } else if line == "DATA" {
+                    data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    b"221 bye\r\n"
+                } else {
+                    b"250 ok\r\n"
+                };
+                lines.push(line);
+                stream.write_all(reply).unwrap();
+                if lines.last().map_or(false, |line| line == "QUIT") {
+                    break;
+                }
+            }
+            sender.send(lines).unwrap();
+        });
+        (address, receiver)
+    }
+
+    #[test]
+    fn test_severity_order_and_serialization() {
+        assert!(Severity::Info < Severity::Warning);
+        assert!(Severity::Warning < Severity::Critical);
+        assert_eq!("\"critical\"", serde_json::to_string(&Severity::Critical).unwrap());
+        assert_eq!(
+            Severity::Warning,
+            serde_json::from_str::<Severity>("\"warning\"").unwrap()
+        );
+        assert_eq!("CRITICAL", Severity::Critical.to_string());
+        assert_eq!("warning", Severity::Warning.pagerduty());
+    }
+
+    #[test]
+    fn test_webhook_payload() {
+        assert_eq!(
+            json!({
+                "payload_version": 2,
+                "text": "[CRITICAL] circuit of DepositRelay opened",
+                "severity": "critical",
+                "condition": "circuit_open",
+                "subject": "DepositRelay",
+            }),
+            webhook_payload(&alert())
+        );
+    }
+
+    #[test]
+    fn test_pagerduty_event() {
+        assert_eq!(
+            json!({
+                "routing_key": "key",
+                "event_action": "trigger",
+                "dedup_key": "parity-bridge/circuit_open/DepositRelay",
+                "payload": {
+                    "summary": "circuit of DepositRelay opened",
+                    "source": "bridge-1",
+                    "severity": "critical",
+                    "component": "circuit_open",
+                    "custom_details": {
+                        "subject": "DepositRelay",
+                    },
+                },
+            }),
+            pagerduty_event("key", "bridge-1", &alert())
+        );
+    }
+
+    #[test]
+    fn test_email_message() {
+        let mut alert = alert();
+        alert.summary = "first line\nsecond line".into();
+        assert_eq!(
+            "From: <bridge@example.com>\r\nTo: <a@example.com>, <b@example.com>\r\nSubject: [CRITICAL] parity-bridge circuit_open: DepositRelay\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nfirst line\r\nsecond line\r\n",
+            email_message(
+                "bridge@example.com",
+                &["a@example.com".into(), "b@example.com".into()],
+                &alert
+            )
+        );
+    }
+
+    #[test]
+    fn test_webhook_sink_send() {
+        let (url, requests) = http_server("200 OK");
+        let mut sink = WebhookSink::new("webhook", &Password::new(url), Duration::from_secs(5)).unwrap();
+        sink.send(&alert()).unwrap();
+        assert_eq!(webhook_payload(&alert()), requests.recv().unwrap());
+
+        let (url, _requests) = http_server("500 Internal Server Error");
+        let mut sink = WebhookSink::new("webhook", &Password::new(url), Duration::from_secs(5)).unwrap();
+        assert!(sink.send(&alert()).is_err());
+    }
+
+    #[test]
+    fn test_webhook_sink_signs_the_posted_bytes() {
+        let (url, requests) = http_requests("200 OK");
+        let mut sink = WebhookSink::new("webhook", &Password::new(url), Duration::from_secs(5))
+            .unwrap()
+            .signed_with(Password::new("secret"));
+        sink.send(&alert()).unwrap();
+        let (headers, body) = requests.recv().unwrap();
+        assert_eq!(canonical_json(&webhook_payload(&alert())).unwrap().as_bytes(), &body[..]);
+        let signature = headers
+            .iter()
+            .find(|header| header.to_lowercase().starts_with("x-bridge-signature:"))
+            .map(|header| header["x-bridge-signature:".len()..].trim().to_owned())
+            .unwrap();
+        assert_eq!(
+            webhook_payload(&alert()),
+            verify_webhook(b"secret", &body, &signature).unwrap()
+        );
+
+        // unsigned without a secret
+        let (url, requests) = http_requests("200 OK");
+        let mut sink = WebhookSink::new("webhook", &Password::new(url), Duration::from_secs(5)).unwrap();
+        sink.send(&alert()).unwrap();
+        let (headers, _) = requests.recv().unwrap();
+        assert!(!headers
+            .iter()
+            .any(|header| header.to_lowercase().starts_with("x-bridge-signature:")));
+    }
+
+    #[test]
+    fn test_pagerduty_sink_send() {
+        let (url, requests) = http_server("202 Accepted");
+        let mut sink = PagerDutySink::new(url, Password::new("key"), "bridge-1", Duration::from_secs(5)).unwrap();
+        sink.send(&alert()).unwrap();
+        assert_eq!(
+            pagerduty_event("key", "bridge-1", &alert()),
+            requests.recv().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_smtp_sink_send() {
+        let (address, sessions) = smtp_server();
+        let mut sink = SmtpSink::new(
+            address,
+            "bridge@example.com",
+            vec!["oncall@example.com".into()],
+            Some(("bridge".into(), Password::new("secret"))),
+            Duration::from_secs(5),
+        );
+        let mut alert = alert();
+        alert.summary = ".starts with a dot".into();
+        sink.send(&alert).unwrap();
+        let lines = sessions.recv().unwrap();
+        assert_eq!("EHLO parity-bridge", lines[0]);
+        // base64 of "\0bridge\0secret"
+        assert_eq!("AUTH PLAIN AGJyaWRnZQBzZWNyZXQ=", lines[1]);
+        assert_eq!("MAIL FROM:<bridge@example.com>", lines[2]);
+        assert_eq!("RCPT TO:<oncall@example.com>", lines[3]);
+        assert_eq!("DATA", lines[4]);
+        assert!(lines.contains(&"Subject: [CRITICAL] parity-bridge circuit_open: DepositRelay".to_owned()));
+        assert!(lines.contains(&"..starts with a dot".to_owned()));
+        assert_eq!(vec![".", "QUIT"], lines[lines.len() - 2..].to_vec());
+    }
+
+    struct FailingSink;
+
+    impl AlertSink for FailingSink {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn send(&mut self, _alert: &Alert) -> Result<(), Error> {
+            bail!("unreachable")
+        }
+    }
+
+    #[test]
+    fn test_alerts_deliver_to_all_sinks_above_threshold() {
+        let (webhook_url, webhook_requests) = http_server("200 OK");
+        let (pagerduty_url, pagerduty_requests) = http_server("202 Accepted");
+        let (smtp_address, smtp_sessions) = smtp_server();
+        let mut alerts = Alerts::default();
+        alerts.add(FailingSink, Severity::Info).unwrap();
+        alerts
+            .add(
+                WebhookSink::new("webhook", &Password::new(webhook_url), Duration::from_secs(5)).unwrap(),
+                Severity::Warning,
+            )
+            .unwrap();
+        alerts
+            .add(
+                PagerDutySink::new(pagerduty_url, Password::new("key"), "bridge-1", Duration::from_secs(5)).unwrap(),
+                Severity::Critical,
+            )
+            .unwrap();
+        alerts
+            .add(
+                SmtpSink::new(smtp_address, "bridge@example.com", vec!["oncall@example.com".into()], None, Duration::from_secs(5)),
+                Severity::Critical,
+            )
+            .unwrap();
+        assert!(!alerts.is_empty());
+
+        // below the threshold of pagerduty and email. the webhook gets it despite the failing sink
+        let warning = Alert::new(Severity::Warning, "stalled", "WithdrawRelay", "stalled");
+        alerts.alert(warning.clone());
+        assert_eq!(webhook_payload(&warning), webhook_requests.recv().unwrap());
+
+        alerts.alert(alert());
+        // waits for the deliveries
+        drop(alerts);
+        assert_eq!(
+            pagerduty_event("key", "bridge-1", &alert()),
+            pagerduty_requests.recv().unwrap()
+        );
+        let lines = smtp_sessions.recv().unwrap();
+        assert_eq!("MAIL FROM:<bridge@example.com>", lines[1]);
+        // the pagerduty server only accepts one request: the warning never reached it
+        assert!(pagerduty_requests.try_recv().is_err());
+    }
+}
@@ -1,68 +1,551 @@
-use std::time::Duration;
-use serde::de::DeserializeOwned;
-use serde_json::Value;
-use futures::{Future, Poll, Stream};
-use tokio_timer::{Interval, Timeout, Timer};
-use web3::{self, api, Transport};
-use web3::api::Namespace;
-use web3::types::{Address, Bytes, CallRequest, Filter, FilterBuilder, H256, H520, Log,
-                  TransactionRequest, U256};
-use web3::helpers::CallResult;
+use std::{cmp, fmt, mem};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Deserializer};
+use serde::de::{self, DeserializeOwned};
+use serde_json::{self, Value};
+use futures::{Async, Future, Poll, Stream};
+use timer::{Interval, Sleep, Timer};
+use tracing::{field, Span};
+use web3::{self, Transport};
+use rpc;
+use web3::types::{Address, Block, BlockNumber, Bytes, CallRequest, Filter, FilterBuilder, H256,
+                  H520, Log, Transaction, TransactionReceipt, TransactionRequest, U256};
+use web3::helpers;
+use config::{FinalityRpc, FinalitySource, HeadRegression, LogsSanityCheck, Node, Password, Signer,
+             TxpoolClient};
 use error::{Error, ErrorKind};
+use finality::SharedFinality;
+use jitter::{Jitter, JitterRange};
+use quantity::{normalize_result, quantities};
+use remote_signer::is_remote_signer_error;
+use transport::truncate;
 
 /// Imperative alias for web3 function.
 pub use web3::confirm::send_transaction_with_confirmation;
 
-/// Wrapper type for `CallResult`
+/// bytes of a result kept in `ErrorKind::InvalidRpcResponse`
+const MAX_INVALID_RESULT_SIZE: usize = 1024;
+
+/// json-rpc requests failed since the start of the process.
+static RPC_ERRORS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// number of failed json-rpc requests of all connections since the start of the process.
+pub fn rpc_errors() -> u64 {
+    RPC_ERRORS.load(Ordering::Relaxed) as u64
+}
+
+/// log ranges split because the node refused to return that many logs.
+static LOG_BISECTIONS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// number of `eth_getLogs` ranges of all log streams split in two since the start of the process.
+pub fn log_bisections() -> u64 {
+    LOG_BISECTIONS.load(Ordering::Relaxed) as u64
+}
+
+/// logs dropped because their data exceeded `max_log_data`.
+static OVERSIZED_LOGS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// number of logs of all log streams dropped for their size since the start of the process.
+pub fn oversized_logs() -> u64 {
+    OVERSIZED_LOGS.load(Ordering::Relaxed) as u64
+}
+
+/// oversized logs a log stream keeps until they are taken.
+const KEPT_OVERSIZED_LOGS: usize = 256;
+
+/// Result of the json-rpc call `message`, decoded into `T`.
+/// the quantities of the result are normalized first, see `quantity`.
 pub struct ApiCall<T, F> {
-    future: CallResult<T, F>,
+    future: F,
     message: &'static str,
+    result: PhantomData<T>,
+    /// time of the first poll and the span of the call, nested in the span polling it.
+    /// used to log the duration of the call.
+    started: Option<(Instant, Span)>,
 }
 
 impl<T, F> ApiCall<T, F> {
+    fn new(future: F, message: &'static str) -> Self {
+        ApiCall {
+            future,
+            message,
+            result: PhantomData,
+            started: None,
+        }
+    }
+
     pub fn message(&self) -> &'static str {
         self.message
     }
 }
 
+/// decodes the `result` of the json-rpc call `method`.
+/// fails with `ErrorKind::InvalidRpcResponse` if it doesn't decode.
+pub fn decode_result<T: DeserializeOwned>(
+    method: &'static str,
+    mut result: Value,
+) -> Result<T, Error> {
+    let invalid = |result: &Value, reason: String| {
+        let result = truncate(result.to_string(), MAX_INVALID_RESULT_SIZE);
+        Error::from(ErrorKind::InvalidRpcResponse(method, result, reason))
+    };
+    if let Err(reason) = normalize_result(&mut result, quantities(method)) {
+        return Err(invalid(&result, reason));
+    }
+    serde_json::from_value(result.clone()).map_err(|err| invalid(&result, err.to_string()))
+}
+
+fn elapsed_ms(since: Instant) -> u64 {
+    let elapsed = since.elapsed();
+    elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64
+}
+
 impl<T: DeserializeOwned, F: Future<Item = Value, Error = web3::Error>> Future for ApiCall<T, F> {
     type Item = T;
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         trace!(target: "bridge", "{}", self.message);
-        self.future
-            .poll()
-            .map_err(ErrorKind::Web3)
-            .map_err(Into::into)
+        let message = self.message;
+        let (started, ref span) = *self.started.get_or_insert_with(|| {
+            let span = debug_span!(
+                target: "bridge::rpc",
+                "rpc",
+                method = message,
+                duration_ms = field::Empty
+            );
+            (Instant::now(), span)
+        });
+        let _entered = span.enter();
+        let result = match self.future.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => decode_result(message, result).map(Async::Ready),
+            // only rejections of the node are about the personal api,
+            // a personal call that doesn't reach it fails like any other
+            Err(web3::Error::Rpc(rpc)) if message.starts_with("personal_") => {
+                let err = Error::from(ErrorKind::Web3(web3::Error::Rpc(rpc.clone())));
+                Err(Error::with_chain(err, ErrorKind::PersonalApi(message, rpc)))
+            }
+            Err(err) => Err(ErrorKind::Web3(err).into()),
+        };
+        match result {
+            Ok(Async::NotReady) => (),
+            Ok(Async::Ready(_)) => {
+                span.record("duration_ms", &elapsed_ms(started));
+                trace!(
+                    target: "bridge::rpc",
+                    "{} completed in {} ms",
+                    message,
+                    elapsed_ms(started)
+                );
+            }
+            Err(ref err) => {
+                RPC_ERRORS.fetch_add(1, Ordering::Relaxed);
+                span.record("duration_ms", &elapsed_ms(started));
+                debug!(
+                    target: "bridge::rpc",
+                    "{} failed after {} ms: {}",
+                    message,
+                    elapsed_ms(started),
+                    err
+                );
+            }
+        }
+        result
+    }
+}
+
+/// What a failed request tells about the node, see `node_failure`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeFailure {
+    /// the node answered with a json-rpc error, e.g. for invalid params, a revert or a nonce
+    /// in use. the request had no effect and fails the same way if it's repeated unchanged
+    Rejected {
+        code: i64,
+        message: String,
+        data: Option<Value>,
+    },
+    /// the node wasn't reached or didn't answer in time. repeating the request is safe
+    Unreachable { source: String },
+    /// a transaction was sent, but the node didn't answer with its hash.
+    /// `maybe_sent` unless the send failed before it reached the node
+    AmbiguousSend { maybe_sent: bool },
+}
+
+/// what the failure `err` of a request tells about the node, `sending` if the request sends a
+/// transaction. `None` for failures of the bridge itself, like invalid config or call data.
+///
+/// the json-rpc error of a rejection is kept whole, with its code and data. a send that timed
+/// out or lost its connection may still be taken by the node, so only a rejection of the node
+/// or a refusal of the remote signer means it wasn't sent.
+pub fn node_failure(err: &Error, sending: bool) -> Option<NodeFailure> {
+    let rejected = |rpc: &rpc::Error| NodeFailure::Rejected {
+        code: rpc.code.code(),
+        message: rpc.message.clone(),
+        data: rpc.data.clone(),
+    };
+    let unreachable = |source: String| {
+        if sending {
+            NodeFailure::AmbiguousSend { maybe_sent: true }
+        } else {
+            NodeFailure::Unreachable { source }
+        }
+    };
+    let failure = match *err.kind() {
+        ErrorKind::Web3(web3::Error::Rpc(ref rpc)) | ErrorKind::PersonalApi(_, ref rpc) => {
+            rejected(rpc)
+        }
+        ErrorKind::Web3(web3::Error::Transport(ref message)) if is_remote_signer_error(message) => {
+            if !sending {
+                return None;
+            }
+            NodeFailure::AmbiguousSend { maybe_sent: false }
+        }
+        ErrorKind::Web3(_) | ErrorKind::Timeout(..) => unreachable(err.to_string()),
+        // the node answered, but with something that isn't the hash of the transaction
+        ErrorKind::InvalidRpcResponse(..) if sending => {
+            NodeFailure::AmbiguousSend { maybe_sent: true }
+        }
+        ErrorKind::AmbiguousSend(..) => NodeFailure::AmbiguousSend { maybe_sent: true },
+        _ => return None,
+    };
+    Some(failure)
+}
+
+thread_local! {
+    /// requests that timed out by operation, see `timeouts`
+    static TIMEOUTS: RefCell<BTreeMap<&'static str, u64>> = RefCell::new(BTreeMap::new());
+}
+
+/// number of requests wrapped in `Timeout` that timed out since the start, by operation.
+/// counted on the thread polling them, all requests of the bridge run on its event loop.
+pub fn timeouts() -> BTreeMap<&'static str, u64> {
+    TIMEOUTS.with(|timeouts| timeouts.borrow().clone())
+}
+
+/// Request to the node of a chain with a deadline.
+/// fails with `ErrorKind::Timeout` naming the operation, the chain, what it is about
+/// and the deadline if it isn't done `after` it was created.
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+    operation: &'static str,
+    chain: &'static str,
+    subject: Option<String>,
+    after: Duration,
+}
+
+/// `call` to the node of `chain` failing with `ErrorKind::Timeout` after `after`.
+pub fn timeout<T, F>(
+    timer: &Timer,
+    call: ApiCall<T, F>,
+    chain: &'static str,
+    after: Duration,
+) -> Timeout<ApiCall<T, F>> {
+    Timeout {
+        operation: call.message(),
+        future: call,
+        sleep: timer.sleep(after),
+        chain,
+        subject: None,
+        after,
+    }
+}
+
+impl<F> Timeout<F> {
+    /// names what the request is about in its timeout, e.g. the transaction of a receipt.
+    pub fn about<S: fmt::Debug>(mut self, subject: S) -> Self {
+        self.subject = Some(format!("{:?}", subject));
+        self
+    }
+
+    fn timed_out(&self) -> ErrorKind {
+        ErrorKind::Timeout(self.operation, self.chain, self.subject.clone(), self.after)
+    }
+}
+
+impl<F: Future> Future for Timeout<F>
+where
+    Error: From<F::Error>,
+{
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(item) = self.future.poll()? {
+            return Ok(Async::Ready(item));
+        }
+        match self.sleep.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) => {
+                TIMEOUTS.with(|timeouts| {
+                    *timeouts.borrow_mut().entry(self.operation).or_insert(0) += 1;
+                });
+                let err = Error::from(self.timed_out());
+                debug!(target: "bridge::rpc", "{}", err);
+                Err(err)
+            }
+            Err(err) => Err(Error::with_chain(err, self.timed_out())),
+        }
     }
 }
 
 /// Imperative wrapper for web3 function.
 pub fn logs<T: Transport>(transport: T, filter: &Filter) -> ApiCall<Vec<Log>, T::Out> {
-    ApiCall {
-        future: api::Eth::new(transport).logs(filter),
-        message: "eth_getLogs",
-    }
+    ApiCall::new(
+        transport.execute("eth_getLogs", vec![helpers::serialize(filter)]),
+        "eth_getLogs",
+    )
 }
 
 /// Imperative wrapper for web3 function.
 pub fn block_number<T: Transport>(transport: T) -> ApiCall<U256, T::Out> {
-    ApiCall {
-        future: api::Eth::new(transport).block_number(),
-        message: "eth_blockNumber",
+    ApiCall::new(transport.execute("eth_blockNumber", vec![]), "eth_blockNumber")
+}
+
+/// Imperative wrapper for web3 function.
+pub fn latest_block<T: Transport>(transport: T) -> ApiCall<Block<H256>, T::Out> {
+    block_by_number_tag(transport, BlockNumber::Latest)
+}
+
+/// Imperative wrapper for web3 function.
+pub fn block_by_number<T: Transport>(transport: T, number: u64) -> ApiCall<Block<H256>, T::Out> {
+    block_by_number_tag(transport, BlockNumber::Number(number))
+}
+
+fn block_by_number_tag<T: Transport>(
+    transport: T,
+    block: BlockNumber,
+) -> ApiCall<Block<H256>, T::Out> {
+    ApiCall::new(
+        transport.execute(
+            "eth_getBlockByNumber",
+            vec![helpers::serialize(&block), Value::Bool(false)],
+        ),
+        "eth_getBlockByNumber",
+    )
+}
+
+/// Calls the rollup specific method of `rpc`.
+pub fn finality_rpc<T: Transport>(transport: T, rpc: &FinalityRpc) -> ApiCall<Value, T::Out> {
+    ApiCall::new(
+        transport.execute(&rpc.method, vec![]),
+        "finality_rpc",
+    )
+}
+
+/// Imperative wrapper for the rpc call of a `FinalitySource`.
+/// resolves to the block for a tag and to the result of the method otherwise.
+pub fn finalized_block<T: Transport>(
+    transport: T,
+    source: &FinalitySource,
+) -> ApiCall<Value, T::Out> {
+    let call = match *source {
+        FinalitySource::Tag(ref tag) => transport.execute(
+            "eth_getBlockByNumber",
+            vec![Value::from(tag.as_str()), Value::Bool(false)],
+        ),
+        FinalitySource::Method(ref method) => transport.execute(method, vec![]),
+    };
+    ApiCall::new(call, "finalized_block")
+}
+
+/// number of the finalized block in the `result` of `finalized_block`.
+pub fn finalized_block_number(result: &Value, source: &FinalitySource) -> Result<u64, Error> {
+    match *source {
+        FinalitySource::Tag(_) => block_at_path(result, "number"),
+        FinalitySource::Method(_) => block_at_path(result, ""),
+    }
+}
+
+/// Extracts the block number at the dot separated `path` of `result`.
+/// the number may be a json number or a hex string.
+pub fn block_at_path(result: &Value, path: &str) -> Result<u64, Error> {
+    let value = path.split('.')
+        .filter(|key| !key.is_empty())
+        .fold(Some(result), |value, key| value.and_then(|value| value.get(key)))
+        .ok_or_else(|| format!("finality rpc result {} has no value at {:?}", result, path))?;
+    let block = match *value {
+        Value::Number(ref number) => number.as_u64(),
+        Value::String(ref hex) if hex.starts_with("0x") => {
+            u64::from_str_radix(&hex[2..], 16).ok()
+        }
+        _ => None,
+    };
+    block.ok_or_else(|| {
+        format!(
+            "finality rpc result {} at {:?} is not a block number",
+            value, path
+        ).into()
+    })
+}
+
+/// Fetches the transaction pool of the node with the method of `client`.
+pub fn txpool<T: Transport>(transport: T, client: TxpoolClient) -> ApiCall<Value, T::Out> {
+    ApiCall::new(
+        transport.execute(client.method(), vec![]),
+        client.method(),
+    )
+}
+
+/// Number of pending transactions in the `txpool` `result` of `client`.
+/// parity lists the transactions, only those sent by `account` are counted.
+/// geth only reports the number of pending transactions of all accounts.
+pub fn pending_count(client: TxpoolClient, result: &Value, account: Address) -> Result<usize, Error> {
+    match client {
+        TxpoolClient::Parity => {
+            let transactions = result
+                .as_array()
+                .ok_or_else(|| format!("parity_pendingTransactions result {} is not a list", result))?;
+            let from = |transaction: &Value| {
+                transaction
+                    .get("from")
+                    .and_then(Value::as_str)
+                    .and_then(|from| from.trim_left_matches("0x").parse::<Address>().ok())
+            };
+            Ok(transactions
+                .iter()
+                .filter(|transaction| from(transaction) == Some(account))
+                .count())
+        }
+        TxpoolClient::Geth => {
+            let pending = result
+                .get("pending")
+                .and_then(Value::as_str)
+                .and_then(|pending| {
+                    usize::from_str_radix(pending.trim_left_matches("0x"), 16).ok()
+                });
+            pending.ok_or_else(|| format!("txpool_status result {} has no pending count", result).into())
+        }
+    }
+}
+
+/// hash of the transaction sent for `tx` in the `parity_pendingTransactions` `result`,
+/// the first one from `tx.from` to `tx.to` with the data of `tx`.
+pub fn pending_transaction(result: &Value, tx: &TransactionRequest) -> Option<H256> {
+    let field = |transaction: &Value, name: &str| {
+        transaction.get(name).cloned().unwrap_or(Value::Null)
+    };
+    let data = tx.data.as_ref().map_or(&[][..], |data| &data.0[..]);
+    let sent = |transaction: &&Value| {
+        let from = serde_json::from_value::<Address>(field(transaction, "from")).ok();
+        let to = serde_json::from_value::<Option<Address>>(field(transaction, "to")).ok();
+        let input = serde_json::from_value::<Bytes>(field(transaction, "input")).ok();
+        from == Some(tx.from) && to == Some(tx.to) && input.map_or(false, |input| input.0 == data)
+    };
+    let transaction = result.as_array()?.iter().find(sent)?;
+    serde_json::from_value(field(transaction, "hash")).ok()
+}
+
+/// Imperative wrapper for web3 function.
+pub fn transaction<T: Transport>(transport: T, hash: H256) -> ApiCall<Option<Transaction>, T::Out> {
+    ApiCall::new(
+        transport.execute("eth_getTransactionByHash", vec![helpers::serialize(&hash)]),
+        "eth_getTransactionByHash",
+    )
+}
+
+/// Imperative wrapper for web3 function.
+pub fn transaction_receipt<T: Transport>(
+    transport: T,
+    hash: H256,
+) -> ApiCall<Option<TransactionReceipt>, T::Out> {
+    ApiCall::new(
+        transport.execute("eth_getTransactionReceipt", vec![helpers::serialize(&hash)]),
+        "eth_getTransactionReceipt",
+    )
+}
+
+/// `TransactionReceipt` with the `status` of the transaction,
+/// `1` if it succeeded and `0` if it reverted. `status` is `None` on chains before byzantium.
+#[derive(Debug, Clone)]
+pub struct ReceiptWithStatus {
+    pub receipt: TransactionReceipt,
+    pub status: Option<U256>,
+}
+
+impl ReceiptWithStatus {
+    pub fn reverted(&self) -> bool {
+        self.status.map_or(false, |status| status.is_zero())
     }
 }
 
+impl<'de> Deserialize<'de> for ReceiptWithStatus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let status = match value.get("status") {
+            None | Some(&Value::Null) => None,
+            Some(status) => Some(serde_json::from_value(status.clone()).map_err(de::Error::custom)?),
+        };
+        let receipt = serde_json::from_value(value).map_err(de::Error::custom)?;
+        Ok(ReceiptWithStatus { receipt, status })
+    }
+}
+
+/// receipt of the transaction `hash` with its status. `None` if it isn't mined yet.
+pub fn transaction_receipt_with_status<T: Transport>(
+    transport: T,
+    hash: H256,
+) -> ApiCall<Option<ReceiptWithStatus>, T::Out> {
+    ApiCall::new(
+        transport.execute(
+            "eth_getTransactionReceipt",
+            vec![helpers::serialize(&hash)],
+        ),
+        "eth_getTransactionReceipt",
+    )
+}
+
+/// block `hash` as json with the hashes of its transactions. `None` if it's unknown.
+/// the json has the header fields parity and geth return, which `Block` lacks some of.
+pub fn raw_block_by_hash<T: Transport>(transport: T, hash: H256) -> ApiCall<Option<Value>, T::Out> {
+    ApiCall::new(
+        transport.execute(
+            "eth_getBlockByHash",
+            vec![helpers::serialize(&hash), Value::Bool(false)],
+        ),
+        "eth_getBlockByHash",
+    )
+}
+
+/// latest block as json with the hashes of its transactions, see `raw_block_by_hash`.
+pub fn raw_latest_block<T: Transport>(transport: T) -> ApiCall<Option<Value>, T::Out> {
+    ApiCall::new(
+        transport.execute(
+            "eth_getBlockByNumber",
+            vec![helpers::serialize(&BlockNumber::Latest), Value::Bool(false)],
+        ),
+        "eth_getBlockByNumber",
+    )
+}
+
+/// receipt of the transaction `hash` as json with all of its fields. `None` if it isn't mined yet.
+pub fn raw_transaction_receipt<T: Transport>(
+    transport: T,
+    hash: H256,
+) -> ApiCall<Option<Value>, T::Out> {
+    ApiCall::new(
+        transport.execute(
+            "eth_getTransactionReceipt",
+            vec![helpers::serialize(&hash)],
+        ),
+        "eth_getTransactionReceipt",
+    )
+}
+
 /// Imperative wrapper for web3 function.
 pub fn send_transaction<T: Transport>(
     transport: T,
     tx: TransactionRequest,
 ) -> ApiCall<H256, T::Out> {
-    ApiCall {
-        future: api::Eth::new(transport).send_transaction(tx),
-        message: "eth_sendTransaction",
-    }
+    ApiCall::new(
+        transport.execute("eth_sendTransaction", vec![helpers::serialize(&tx)]),
+        "eth_sendTransaction",
+    )
 }
 
 /// Imperative wrapper for web3 function.
@@ -71,28 +554,355 @@ pub fn call<T: Transport>(
     address: Address,
     payload: Bytes,
 ) -> ApiCall<Bytes, T::Out> {
-    let future = api::Eth::new(transport).call(
-        CallRequest {
-            from: None,
-            to: address,
-            gas: None,
-            gas_price: None,
-            value: None,
-            data: Some(payload),
-        },
-        None,
-    );
+    call_at(transport, address, payload, None)
+}
+
+/// Imperative wrapper for web3 function.
+/// executes the call at `block`, or at the latest block if `None`.
+/// used to get consistent results from several calls.
+pub fn call_at<T: Transport>(
+    transport: T,
+    address: Address,
+    payload: Bytes,
+    block: Option<u64>,
+) -> ApiCall<Bytes, T::Out> {
+    let request = CallRequest {
+        from: None,
+        to: address,
+        gas: None,
+        gas_price: None,
+        value: None,
+        data: Some(payload),
+    };
+    eth_call(transport, request, block.map(BlockNumber::Number))
+}
+
+/// `eth_call` of `request` at `block`, or at the latest block if `None`.
+fn eth_call<T: Transport>(
+    transport: T,
+    request: CallRequest,
+    block: Option<BlockNumber>,
+) -> ApiCall<Bytes, T::Out> {
+    let block = block.unwrap_or(BlockNumber::Latest);
+    ApiCall::new(
+        transport.execute(
+            "eth_call",
+            vec![helpers::serialize(&request), helpers::serialize(&block)],
+        ),
+        "eth_call",
+    )
+}
+
+/// Imperative wrapper for web3 function.
+/// code of the contract at `address` at `block`. empty if there is no contract.
+pub fn code<T: Transport>(transport: T, address: Address, block: u64) -> ApiCall<Bytes, T::Out> {
+    code_at(transport, address, BlockNumber::Number(block))
+}
+
+/// Imperative wrapper for web3 function.
+/// code of the contract at `address` at the latest block. empty if there is no contract.
+pub fn latest_code<T: Transport>(transport: T, address: Address) -> ApiCall<Bytes, T::Out> {
+    code_at(transport, address, BlockNumber::Latest)
+}
+
+fn code_at<T: Transport>(
+    transport: T,
+    address: Address,
+    block: BlockNumber,
+) -> ApiCall<Bytes, T::Out> {
+    ApiCall::new(
+        transport.execute(
+            "eth_getCode",
+            vec![helpers::serialize(&address), helpers::serialize(&block)],
+        ),
+        "eth_getCode",
+    )
+}
+
+/// `true` if `err` means that the node doesn't have the state of the block
+/// a call was made at anymore, for example because it prunes old state.
+pub fn is_missing_state(err: &Error) -> bool {
+    const MESSAGES: &[&str] = &[
+        "missing trie node",
+        "pruned",
+        "header not found",
+        "state not available",
+        "state is not available",
+    ];
+    err.iter().any(|err| {
+        let message = err.to_string().to_lowercase();
+        MESSAGES.iter().any(|expected| message.contains(expected))
+    })
+}
+
+/// `true` if `err` means that the node refused to return the logs of a range because there
+/// are too many of them or the range is too large. `extra` are lowercase messages recognized
+/// besides those of the known nodes and providers.
+pub fn is_log_limit_error(err: &Error, extra: &[String]) -> bool {
+    const MESSAGES: &[&str] = &[
+        // infura and geth
+        "query returned more than",
+        // alchemy
+        "log response size exceeded",
+        // geth
+        "exceed maximum block range",
+        // parity and openethereum
+        "too many results",
+        "too many logs",
+    ];
+    err.iter().any(|err| {
+        let message = err.to_string().to_lowercase();
+        MESSAGES.iter().any(|expected| message.contains(expected))
+            || extra.iter().any(|expected| message.contains(expected.as_str()))
+    })
+}
+
+/// Imperative wrapper for web3 function.
+pub fn balance<T: Transport>(transport: T, address: Address) -> ApiCall<U256, T::Out> {
+    ApiCall::new(
+        transport.execute(
+            "eth_getBalance",
+            vec![helpers::serialize(&address), helpers::serialize(&BlockNumber::Latest)],
+        ),
+        "eth_getBalance",
+    )
+}
+
+/// number of transactions sent by `address` and mined, or also pending if `pending`.
+pub fn transaction_count<T: Transport>(
+    transport: T,
+    address: Address,
+    pending: bool,
+) -> ApiCall<U256, T::Out> {
+    let block = if pending {
+        BlockNumber::Pending
+    } else {
+        BlockNumber::Latest
+    };
+    ApiCall::new(
+        transport.execute(
+            "eth_getTransactionCount",
+            vec![helpers::serialize(&address), helpers::serialize(&block)],
+        ),
+        "eth_getTransactionCount",
+    )
+}
+
+/// chain id of the node, `eth_chainId`.
+pub fn chain_id<T: Transport>(transport: T) -> ApiCall<U256, T::Out> {
+    ApiCall::new(transport.execute("eth_chainId", vec![]), "eth_chainId")
+}
+
+/// name and version of the client of the node, `web3_clientVersion`.
+pub fn client_version<T: Transport>(transport: T) -> ApiCall<String, T::Out> {
+    ApiCall::new(transport.execute("web3_clientVersion", vec![]), "web3_clientVersion")
+}
+
+/// signers of a clique chain at the latest block, `clique_getSigners`.
+pub fn clique_signers<T: Transport>(transport: T) -> ApiCall<Vec<Address>, T::Out> {
+    ApiCall::new(transport.execute("clique_getSigners", vec![]), "clique_getSigners")
+}
+
+/// Imperative wrapper for web3 function.
+pub fn gas_price<T: Transport>(transport: T) -> ApiCall<U256, T::Out> {
+    ApiCall::new(transport.execute("eth_gasPrice", vec![]), "eth_gasPrice")
+}
 
-    ApiCall {
-        future,
-        message: "eth_call",
+/// `eth_feeHistory` of the latest `blocks` blocks with the priority fees at `percentile`.
+pub fn fee_history<T: Transport>(
+    transport: T,
+    blocks: u64,
+    percentile: u32,
+) -> ApiCall<Value, T::Out> {
+    ApiCall::new(
+        transport.execute(
+            "eth_feeHistory",
+            vec![
+                format!("0x{:x}", blocks).into(),
+                "latest".into(),
+                vec![Value::from(percentile)].into(),
+            ],
+        ),
+        "eth_feeHistory",
+    )
+}
+
+/// calls `method` with `params` as `--self-test` probes it.
+pub fn probe<T: Transport>(
+    transport: T,
+    method: &str,
+    params: Vec<Value>,
+) -> ApiCall<Value, T::Out> {
+    ApiCall::new(transport.execute(method, params), "self_test_probe")
+}
+
+/// `true` if `err` means that the node doesn't implement the method called.
+pub fn is_method_not_found(err: &Error) -> bool {
+    const MESSAGES: &[&str] = &[
+        "method not found",
+        "does not exist",
+        "not available",
+        "not supported",
+        "unsupported method",
+    ];
+    err.iter().any(|err| {
+        let message = err.to_string().to_lowercase();
+        MESSAGES.iter().any(|expected| message.contains(expected))
+    })
+}
+
+/// Executes `tx` with `eth_call` at the latest block without broadcasting it.
+/// used to predict whether `tx` would succeed.
+pub fn simulate_transaction<T: Transport>(
+    transport: T,
+    tx: &TransactionRequest,
+) -> ApiCall<Bytes, T::Out> {
+    let request = CallRequest {
+        from: Some(tx.from),
+        to: tx.to.unwrap_or_default(),
+        gas: tx.gas,
+        gas_price: tx.gas_price,
+        value: tx.value,
+        data: tx.data.clone(),
+    };
+    eth_call(transport, request, None)
+}
+
+/// Executes the mined `tx` again with `eth_call` at `block`, e.g. to get its revert reason.
+pub fn replay_transaction<T: Transport>(
+    transport: T,
+    tx: &Transaction,
+    block: u64,
+) -> ApiCall<Bytes, T::Out> {
+    let request = CallRequest {
+        from: Some(tx.from),
+        to: tx.to.unwrap_or_default(),
+        gas: Some(tx.gas),
+        gas_price: Some(tx.gas_price),
+        value: Some(tx.value),
+        data: Some(tx.input.clone()),
+    };
+    eth_call(transport, request, Some(BlockNumber::Number(block)))
+}
+
+/// Future resolving to the result of `F` instead of failing.
+/// used to join calls of which some are expected to fail.
+pub struct Settle<F> {
+    future: F,
+}
+
+pub fn settle<F>(future: F) -> Settle<F> {
+    Settle { future }
+}
+
+impl<F: Future> Future for Settle<F>
+where
+    Error: From<F::Error>,
+{
+    type Item = Result<F::Item, Error>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.future.poll() {
+            Ok(Async::Ready(item)) => Ok(Async::Ready(Ok(item))),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => Ok(Async::Ready(Err(err.into()))),
+        }
     }
 }
 
 pub fn sign<T: Transport>(transport: T, address: Address, data: Bytes) -> ApiCall<H520, T::Out> {
-    ApiCall {
-        future: api::Eth::new(transport).sign(address, data),
-        message: "eth_sign",
+    ApiCall::new(
+        transport.execute(
+            "eth_sign",
+            vec![helpers::serialize(&address), helpers::serialize(&data)],
+        ),
+        "eth_sign",
+    )
+}
+
+/// Imperative wrapper for `personal_sendTransaction`.
+/// the account is unlocked with `password` for this transaction only.
+pub fn personal_send_transaction<T: Transport>(
+    transport: T,
+    tx: TransactionRequest,
+    password: &Password,
+) -> ApiCall<H256, T::Out> {
+    let params = vec![
+        helpers::serialize(&tx),
+        helpers::serialize(&password.as_str()),
+    ];
+    ApiCall::new(
+        transport.execute("personal_sendTransaction", params),
+        "personal_sendTransaction",
+    )
+}
+
+/// Imperative wrapper for `personal_sign`.
+pub fn personal_sign<T: Transport>(
+    transport: T,
+    address: Address,
+    data: Bytes,
+    password: &Password,
+) -> ApiCall<H520, T::Out> {
+    let params = vec![
+        helpers::serialize(&data),
+        helpers::serialize(&address),
+        helpers::serialize(&password.as_str()),
+    ];
+    ApiCall::new(
+        transport.execute("personal_sign", params),
+        "personal_sign",
+    )
+}
+
+/// Imperative wrapper for `personal_ecRecover`.
+/// the account whose `eth_sign` or `personal_sign` of `data` is `signature`.
+pub fn ec_recover<T: Transport>(
+    transport: T,
+    data: Bytes,
+    signature: Bytes,
+) -> ApiCall<Address, T::Out> {
+    let params = vec![helpers::serialize(&data), helpers::serialize(&signature)];
+    ApiCall::new(
+        transport.execute("personal_ecRecover", params),
+        "personal_ecRecover",
+    )
+}
+
+fn personal_password(node: &Node) -> &Password {
+    node.password
+        .as_ref()
+        .expect("password is required for personal signer in Config::validate; qed")
+}
+
+fn personal_sender_password(node: &Node) -> &Password {
+    node.sender_password()
+        .expect("passwords are required for personal signer in Config::validate; qed")
+}
+
+/// Sends `tx` using the signer configured for `node`.
+/// `tx` is sent from `node.sender()`.
+pub fn node_send_transaction<T: Transport>(
+    transport: T,
+    node: &Node,
+    tx: TransactionRequest,
+) -> ApiCall<H256, T::Out> {
+    match node.signer {
+        // `RemoteSigningTransport` signs `eth_sendTransaction` of remote signers
+        Signer::Node | Signer::Remote => send_transaction(transport, tx),
+        Signer::Personal => {
+            personal_send_transaction(transport, tx, personal_sender_password(node))
+        }
+    }
+}
+
+/// Signs `data` with `node.account` using the signer configured for `node`.
+pub fn node_sign<T: Transport>(transport: T, node: &Node, data: Bytes) -> ApiCall<H520, T::Out> {
+    match node.signer {
+        // `RemoteSigningTransport` signs `eth_sign` of remote signers
+        Signer::Node | Signer::Remote => sign(transport, node.account, data),
+        Signer::Personal => personal_sign(transport, node.account, data, personal_password(node)),
     }
 }
 
@@ -100,9 +910,52 @@ pub fn sign<T: Transport>(transport: T, address: Address, data: Bytes) -> ApiCal
 pub struct LogStreamInit {
     pub after: u64,
     pub filter: FilterBuilder,
+    /// name of the chain of the node, for timeouts
+    pub chain: &'static str,
     pub request_timeout: Duration,
     pub poll_interval: Duration,
+    /// random delay after every poll interval and its source. `None` to poll on the interval
+    pub poll_jitter: Option<(JitterRange, Jitter)>,
     pub confirmations: usize,
+    pub sanity_check: Option<LogsSanityCheck>,
+    /// how far the head may go backwards between polls
+    pub head_regression: HeadRegression,
+    /// only blocks the rollup posted to l1 are considered. `None` for confirmations only
+    pub finality: Option<FinalityRpc>,
+    /// messages of limit errors besides the known ones, see `is_log_limit_error`
+    pub log_limit_errors: Vec<String>,
+    /// logs with more bytes of data are dropped before they are decoded
+    pub max_log_data: usize,
+}
+
+/// A log dropped for its size. its data isn't kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OversizedLog {
+    pub address: Address,
+    pub transaction_hash: Option<H256>,
+    pub block: Option<u64>,
+    pub log_index: Option<U256>,
+    /// bytes of data
+    pub size: usize,
+}
+
+/// removes the logs with more than `max_log_data` bytes of data from `logs`.
+fn remove_oversized_logs(logs: &mut Vec<Log>, max_log_data: usize) -> Vec<OversizedLog> {
+    let mut oversized = Vec::new();
+    logs.retain(|log| {
+        if log.data.0.len() <= max_log_data {
+            return true;
+        }
+        oversized.push(OversizedLog {
+            address: log.address,
+            transaction_hash: log.transaction_hash,
+            block: log.block_number.map(|block| block.low_u64()),
+            log_index: log.log_index,
+            size: log.data.0.len(),
+        });
+        false
+    });
+    oversized
 }
 
 /// Contains all logs matching `LogStream` filter in inclusive range `[from, to]`.
@@ -117,29 +970,109 @@ pub struct LogStreamItem {
 enum LogStreamState<T: Transport> {
     /// Log Stream is waiting for timer to poll.
     Wait,
+    /// Waiting a random delay after the poll interval.
+    Jitter(Sleep),
     /// Fetching best block number.
     FetchBlockNumber(Timeout<ApiCall<U256, T::Out>>),
+    /// Fetching the last block posted to l1, which limits the confirmed blocks.
+    FetchFinalBlock {
+        head: u64,
+        future: Timeout<ApiCall<Value, T::Out>>,
+    },
     /// Fetching logs for new best block.
+    /// a range the node refuses to return all logs of is split in two, see `is_log_limit_error`.
     FetchLogs {
         from: u64,
         to: u64,
+        /// range of `future`
+        chunk: (u64, u64),
+        /// ranges left to fetch after `chunk`, the next one last
+        remaining: Vec<(u64, u64)>,
+        /// logs of the ranges fetched before `chunk`
+        fetched: Vec<Log>,
+        future: Timeout<ApiCall<Vec<Log>, T::Out>>,
+        /// span of the range, the calls fetching it are nested in it
+        span: Span,
+    },
+    /// Re-fetching a log which is known to exist before yielding `item`.
+    SanityCheck {
+        item: Option<LogStreamItem>,
+        known: KnownLog,
         future: Timeout<ApiCall<Vec<Log>, T::Out>>,
     },
     /// All logs has been fetched.
     NextItem(Option<LogStreamItem>),
 }
 
+/// A log previously returned by the node.
+/// Used by the sanity check to detect nodes that silently stopped returning logs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KnownLog {
+    block: u64,
+    transaction_hash: H256,
+}
+
+/// Returns the most recent mined log in `logs`.
+fn last_known_log(logs: &[Log]) -> Option<KnownLog> {
+    logs.iter()
+        .rev()
+        .filter_map(|log| match (log.block_number, log.transaction_hash) {
+            (Some(block), Some(transaction_hash)) => Some(KnownLog {
+                block: block.low_u64(),
+                transaction_hash,
+            }),
+            _ => None,
+        })
+        .next()
+}
+
+/// `true` for logs of pending transactions, which lack the block, transaction or index.
+/// some nodes return them among the logs of mined blocks.
+fn is_pending_log(log: &Log) -> bool {
+    log.block_number.is_none() || log.transaction_hash.is_none() || log.log_index.is_none()
+}
+
+/// position of `log` in the chain.
+fn log_position(log: &Log) -> (Option<U256>, Option<U256>, Option<U256>) {
+    (log.block_number, log.transaction_index, log.log_index)
+}
+
+/// sorts `logs` by block, transaction index and log index.
+/// some load balanced nodes return logs in a different order.
+/// returns `true` if the order changed.
+fn sort_logs(logs: &mut Vec<Log>) -> bool {
+    let sorted = logs.windows(2)
+        .all(|pair| log_position(&pair[0]) <= log_position(&pair[1]));
+    if !sorted {
+        logs.sort_by_key(log_position);
+    }
+    !sorted
+}
+
 /// Creates new `LogStream`.
 pub fn log_stream<T: Transport>(transport: T, timer: Timer, init: LogStreamInit) -> LogStream<T> {
     LogStream {
         transport,
         interval: timer.interval(init.poll_interval),
+        jitter: init.poll_jitter,
         timer,
         state: LogStreamState::Wait,
         after: init.after,
         filter: init.filter,
         confirmations: init.confirmations,
+        chain: init.chain,
         request_timeout: init.request_timeout,
+        sanity_check: init.sanity_check,
+        known_log: None,
+        blocks_since_check: 0,
+        head: HeadTracker::new(init.head_regression),
+        finality: init.finality,
+        final_block: None,
+        finalized: None,
+        pending_logs: 0,
+        log_limit_errors: init.log_limit_errors,
+        max_log_data: init.max_log_data,
+        oversized: Vec::new(),
     }
 }
 
@@ -148,11 +1081,174 @@ pub struct LogStream<T: Transport> {
     transport: T,
     timer: Timer,
     interval: Interval,
+    jitter: Option<(JitterRange, Jitter)>,
     state: LogStreamState<T>,
     after: u64,
     filter: FilterBuilder,
     confirmations: usize,
+    chain: &'static str,
+    request_timeout: Duration,
+    sanity_check: Option<LogsSanityCheck>,
+    known_log: Option<KnownLog>,
+    blocks_since_check: u64,
+    head: HeadTracker,
+    finality: Option<FinalityRpc>,
+    /// last block posted to l1 and when it was fetched
+    final_block: Option<(u64, Instant)>,
+    /// finalized block limiting the confirmed blocks. `None` for confirmations only
+    finalized: Option<SharedFinality>,
+    /// pending logs dropped since the last `take_pending_logs`
+    pending_logs: u64,
+    log_limit_errors: Vec<String>,
+    max_log_data: usize,
+    /// oversized logs dropped since the last `take_oversized_logs`, the latest
+    /// `KEPT_OVERSIZED_LOGS` of them
+    oversized: Vec<OversizedLog>,
+}
+
+impl<T: Transport> LogStream<T> {
+    /// returns the number of pending logs dropped since the last call.
+    pub fn take_pending_logs(&mut self) -> u64 {
+        mem::replace(&mut self.pending_logs, 0)
+    }
+
+    /// returns the logs dropped for their size since the last call.
+    pub fn take_oversized_logs(&mut self) -> Vec<OversizedLog> {
+        mem::replace(&mut self.oversized, Vec::new())
+    }
+
+    /// only yields blocks at or below the finalized block of `finalized` from the next poll on.
+    pub fn watch_finalized(&mut self, finalized: SharedFinality) {
+        self.finalized = Some(finalized);
+    }
+}
+
+/// Highest head a log stream has seen, which keeps it from following a node
+/// whose `eth_blockNumber` goes backwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadTracker {
+    config: HeadRegression,
+    highest: u64,
+    /// consecutive polls whose head was more than `config.tolerance` below `highest`
+    regressed_polls: u32,
+}
+
+impl HeadTracker {
+    pub fn new(config: HeadRegression) -> Self {
+        HeadTracker {
+            config,
+            highest: 0,
+            regressed_polls: 0,
+        }
+    }
+
+    /// the head to compute the next range from. `None` if `head` is below the highest head
+    /// and the poll must be skipped. fails if `head` stays more than the tolerance below
+    /// the highest head for `config.polls` polls.
+    pub fn observe(&mut self, head: u64) -> Result<Option<u64>, Error> {
+        if head >= self.highest {
+            self.highest = head;
+            self.regressed_polls = 0;
+            return Ok(Some(head));
+        }
+        let delta = self.highest - head;
+        if delta <= self.config.tolerance {
+            self.regressed_polls = 0;
+            debug!(
+                target: "bridge::log_stream",
+                "node head {} is {} blocks below the highest head {}. skipping the poll",
+                head,
+                delta,
+                self.highest
+            );
+            return Ok(None);
+        }
+        self.regressed_polls += 1;
+        if self.regressed_polls >= self.config.polls {
+            return Err(ErrorKind::HeadRegression(self.highest, head, self.regressed_polls).into());
+        }
+        warn!(
+            target: "bridge::log_stream",
+            "node head {} is {} blocks below the highest head {} ({} of {} polls). skipping the poll",
+            head,
+            delta,
+            self.highest,
+            self.regressed_polls,
+            self.config.polls
+        );
+        Ok(None)
+    }
+}
+
+/// inclusive range of the blocks after the checked block `after` with at least `confirmations`
+/// confirmations at `head`, capped at the last block posted to l1 `final_block`.
+/// the head has 0 confirmations, so with 0 confirmations it's included.
+/// `None` if there are no new confirmed blocks, e.g. while `head < confirmations`
+/// or if the node is behind `after`.
+pub fn confirmed_range(
+    after: u64,
+    head: u64,
+    confirmations: u64,
+    final_block: Option<u64>,
+) -> Option<(u64, u64)> {
+    let last_confirmed = head.checked_sub(confirmations)?;
+    let to = match final_block {
+        Some(final_block) => cmp::min(last_confirmed, final_block),
+        None => last_confirmed,
+    };
+    if to > after {
+        Some((after + 1, to))
+    } else {
+        None
+    }
+}
+
+/// `eth_getLogs` of the logs matching `filter` in the inclusive range `[from, to]`.
+fn fetch_chunk<T: Transport>(
+    transport: &T,
+    timer: &Timer,
+    chain: &'static str,
+    request_timeout: Duration,
+    filter: &FilterBuilder,
+    (from, to): (u64, u64),
+) -> Timeout<ApiCall<Vec<Log>, T::Out>> {
+    let filter = filter
+        .clone()
+        .from_block(from.into())
+        .to_block(to.into())
+        .build();
+    timeout(timer, logs(transport, &filter), chain, request_timeout)
+}
+
+/// fetches logs matching `filter` in the inclusive `range`, if there are any new blocks.
+fn fetch_logs<T: Transport>(
+    transport: &T,
+    timer: &Timer,
+    chain: &'static str,
     request_timeout: Duration,
+    filter: &FilterBuilder,
+    range: Option<(u64, u64)>,
+) -> LogStreamState<T> {
+    match range {
+        Some((from, to)) => {
+            debug!(
+                target: "bridge::log_stream",
+                "fetching logs in blocks {}..={}",
+                from,
+                to
+            );
+            LogStreamState::FetchLogs {
+                from,
+                to,
+                chunk: (from, to),
+                remaining: Vec::new(),
+                fetched: Vec::new(),
+                future: fetch_chunk(transport, timer, chain, request_timeout, filter, (from, to)),
+                span: debug_span!(target: "bridge::log_stream", "fetch_logs", chain, from, to),
+            }
+        }
+        None => LogStreamState::Wait,
+    }
 }
 
 impl<T: Transport> Stream for LogStream<T> {
@@ -164,41 +1260,275 @@ impl<T: Transport> Stream for LogStream<T> {
             let next_state = match self.state {
                 LogStreamState::Wait => {
                     let _ = try_stream!(self.interval.poll());
-                    LogStreamState::FetchBlockNumber(
-                        self.timer
-                            .timeout(block_number(&self.transport), self.request_timeout),
-                    )
+                    match self.jitter {
+                        Some((ref range, ref mut jitter)) => {
+                            LogStreamState::Jitter(self.timer.sleep(jitter.delay(range)))
+                        }
+                        None => LogStreamState::FetchBlockNumber(timeout(
+                            &self.timer,
+                            block_number(&self.transport),
+                            self.chain,
+                            self.request_timeout,
+                        )),
+                    }
+                }
+                LogStreamState::Jitter(ref mut future) => {
+                    try_ready!(future.poll());
+                    LogStreamState::FetchBlockNumber(timeout(
+                        &self.timer,
+                        block_number(&self.transport),
+                        self.chain,
+                        self.request_timeout,
+                    ))
                 }
                 LogStreamState::FetchBlockNumber(ref mut future) => {
-                    let last_block = try_ready!(future.poll()).low_u64();
-                    let last_confirmed_block = last_block.saturating_sub(self.confirmations as u64);
-                    if last_confirmed_block > self.after {
-                        let from = self.after + 1;
-                        let filter = self.filter
-                            .clone()
-                            .from_block(from.into())
-                            .to_block(last_confirmed_block.into())
-                            .build();
-                        LogStreamState::FetchLogs {
-                            from: from,
-                            to: last_confirmed_block,
-                            future: self.timer
-                                .timeout(logs(&self.transport, &filter), self.request_timeout),
+                    let head = try_ready!(future.poll()).low_u64();
+                    let confirmations = self.confirmations as u64;
+                    match (self.head.observe(head)?, self.finality.as_ref(), self.final_block) {
+                        // the node went backwards. wait for it to catch up
+                        (None, _, _) => LogStreamState::Wait,
+                        (Some(head), _, _) if self.finalized.is_some() => {
+                            let finalized = self.finalized
+                                .as_ref()
+                                .map(|finalized| finalized.get())
+                                .expect("checked by the guard; qed");
+                            debug!(
+                                target: "bridge::log_stream",
+                                "finalized block: {:?}",
+                                finalized
+                            );
+                            fetch_logs(
+                                &self.transport,
+                                &self.timer,
+                                self.chain,
+                                self.request_timeout,
+                                &self.filter,
+                                finalized.final_range(self.after, head, confirmations),
+                            )
                         }
-                    } else {
-                        LogStreamState::Wait
+                        (Some(head), None, _) => fetch_logs(
+                            &self.transport,
+                            &self.timer,
+                            self.chain,
+                            self.request_timeout,
+                            &self.filter,
+                            confirmed_range(self.after, head, confirmations, None),
+                        ),
+                        (Some(head), Some(rpc), Some((final_block, fetched)))
+                            if fetched.elapsed() < rpc.poll_interval =>
+                        {
+                            fetch_logs(
+                                &self.transport,
+                                &self.timer,
+                                self.chain,
+                                self.request_timeout,
+                                &self.filter,
+                                confirmed_range(self.after, head, confirmations, Some(final_block)),
+                            )
+                        }
+                        (Some(head), Some(rpc), _) => LogStreamState::FetchFinalBlock {
+                            head,
+                            future: timeout(
+                                &self.timer,
+                                finality_rpc(&self.transport, rpc),
+                                self.chain,
+                                self.request_timeout,
+                            ),
+                        },
                     }
                 }
+                LogStreamState::FetchFinalBlock {
+                    head,
+                    ref mut future,
+                } => {
+                    let result = try_ready!(future.poll());
+                    let final_block = {
+                        let rpc = self.finality
+                            .as_ref()
+                            .expect("FetchFinalBlock state is only entered if finality rpc is configured; qed");
+                        block_at_path(&result, &rpc.result_path)?
+                    };
+                    debug!(
+                        target: "bridge::log_stream",
+                        "last block posted to l1: {}",
+                        final_block
+                    );
+                    self.final_block = Some((final_block, Instant::now()));
+                    fetch_logs(
+                        &self.transport,
+                        &self.timer,
+                        self.chain,
+                        self.request_timeout,
+                        &self.filter,
+                        confirmed_range(
+                            self.after,
+                            head,
+                            self.confirmations as u64,
+                            Some(final_block),
+                        ),
+                    )
+                }
                 LogStreamState::FetchLogs {
                     ref mut future,
                     from,
                     to,
+                    ref mut chunk,
+                    ref mut remaining,
+                    ref mut fetched,
+                    ref span,
+                } => {
+                    let _entered = span.enter();
+                    let next_chunk = match future.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(mut logs)) => {
+                            // before the logs are sorted, checked or decoded
+                            for log in remove_oversized_logs(&mut logs, self.max_log_data) {
+                                OVERSIZED_LOGS.fetch_add(1, Ordering::Relaxed);
+                                warn!(
+                                    target: "bridge::log_stream",
+                                    "dropped a log of {:?} with {} bytes of data in transaction {:?}. the limit is {}",
+                                    log.address,
+                                    log.size,
+                                    log.transaction_hash,
+                                    self.max_log_data
+                                );
+                                if self.oversized.len() == KEPT_OVERSIZED_LOGS {
+                                    self.oversized.remove(0);
+                                }
+                                self.oversized.push(log);
+                            }
+                            fetched.extend(logs);
+                            remaining.pop()
+                        }
+                        // a single block is never split. its limit error is a real error
+                        Err(ref err)
+                            if chunk.0 < chunk.1
+                                && is_log_limit_error(err, &self.log_limit_errors) =>
+                        {
+                            let (chunk_from, chunk_to) = *chunk;
+                            let middle = chunk_from + (chunk_to - chunk_from) / 2;
+                            LOG_BISECTIONS.fetch_add(1, Ordering::Relaxed);
+                            debug!(
+                                target: "bridge::log_stream",
+                                "node refused the logs in blocks {}..={}: {}. fetching {}..={} and {}..={}",
+                                chunk_from,
+                                chunk_to,
+                                err,
+                                chunk_from,
+                                middle,
+                                middle + 1,
+                                chunk_to
+                            );
+                            remaining.push((middle + 1, chunk_to));
+                            Some((chunk_from, middle))
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    if let Some(next_chunk) = next_chunk {
+                        *chunk = next_chunk;
+                        *future = fetch_chunk(
+                            &self.transport,
+                            &self.timer,
+                            self.chain,
+                            self.request_timeout,
+                            &self.filter,
+                            next_chunk,
+                        );
+                        continue;
+                    }
+                    let mut logs = mem::replace(fetched, Vec::new());
+                    let fetched = logs.len();
+                    logs.retain(|log| !is_pending_log(log));
+                    if logs.len() < fetched {
+                        debug!(
+                            target: "bridge::log_stream",
+                            "dropped {} pending logs in blocks {}..={}",
+                            fetched - logs.len(),
+                            from,
+                            to
+                        );
+                        self.pending_logs += (fetched - logs.len()) as u64;
+                    }
+                    let mut item = LogStreamItem { from, to, logs };
+                    if sort_logs(&mut item.logs) {
+                        warn!(
+                            target: "bridge::log_stream",
+                            "node returned logs in blocks {}..={} out of order. sorted them by block, transaction index and log index",
+                            from,
+                            to
+                        );
+                    }
+                    debug!(
+                        target: "bridge::log_stream",
+                        "fetched {} logs in blocks {}..={}",
+                        item.logs.len(),
+                        from,
+                        to
+                    );
+                    self.blocks_since_check += to - from + 1;
+
+                    match (self.sanity_check.as_ref(), self.known_log) {
+                        (Some(check), Some(known)) if self.blocks_since_check >= check.interval => {
+                            let filter = self.filter
+                                .clone()
+                                .from_block(known.block.into())
+                                .to_block(known.block.into())
+                                .build();
+                            LogStreamState::SanityCheck {
+                                item: Some(item),
+                                known,
+                                future: timeout(
+                                    &self.timer,
+                                    logs(&self.transport, &filter),
+                                    self.chain,
+                                    self.request_timeout,
+                                ),
+                            }
+                        }
+                        _ => {
+                            self.after = to;
+                            if let Some(known) = last_known_log(&item.logs) {
+                                self.known_log = Some(known);
+                            }
+                            LogStreamState::NextItem(Some(item))
+                        }
+                    }
+                }
+                LogStreamState::SanityCheck {
+                    ref mut future,
+                    ref mut item,
+                    known,
                 } => {
-                    let logs = try_ready!(future.poll());
-                    let item = LogStreamItem { from, to, logs };
+                    let found = try_ready!(future.poll())
+                        .iter()
+                        .any(|log| log.transaction_hash == Some(known.transaction_hash));
+                    let halt = self.sanity_check
+                        .as_ref()
+                        .map(|check| check.halt)
+                        .expect("SanityCheck state is only entered if sanity check is enabled; qed");
 
-                    self.after = to;
-                    LogStreamState::NextItem(Some(item))
+                    if !found && halt {
+                        error!(
+                            "node did not return log of transaction {} in block {} which is known to exist. not advancing past block {} until the node returns it again",
+                            known.transaction_hash, known.block, self.after
+                        );
+                        LogStreamState::Wait
+                    } else {
+                        if !found {
+                            warn!(
+                                "node did not return log of transaction {} in block {} which is known to exist. logs returned by the node may be incomplete",
+                                known.transaction_hash, known.block
+                            );
+                        }
+                        let item = item.take()
+                            .expect("item is only taken when leaving SanityCheck state; qed");
+                        self.blocks_since_check = 0;
+                        self.after = item.to;
+                        if let Some(known) = last_known_log(&item.logs) {
+                            self.known_log = Some(known);
+                        }
+                        LogStreamState::NextItem(Some(item))
+                    }
                 }
                 LogStreamState::NextItem(ref mut item) => match item.take() {
                     None => LogStreamState::Wait,
@@ -210,3 +1540,369 @@ impl<T: Transport> Stream for LogStream<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use futures::{future, Future};
+    use serde_json::{self, Value};
+    use timer::Timer;
+    use config::{HeadRegression, TxpoolClient};
+    use error::{Error, ErrorKind};
+    use web3;
+    use web3::types::{Bytes, H256, Log, TransactionRequest, U256};
+    use remote_signer::REMOTE_SIGNER_ERROR;
+    use rpc;
+    use super::{confirmed_range, decode_result, is_log_limit_error, is_method_not_found,
+                is_missing_state, node_failure, pending_count, pending_transaction, timeouts,
+                HeadTracker, NodeFailure, ReceiptWithStatus, Timeout, MAX_INVALID_RESULT_SIZE};
+
+    fn timeout<F>(timer: &Timer, future: F) -> Timeout<F> {
+        Timeout {
+            future,
+            sleep: timer.sleep(Duration::from_millis(10)),
+            operation: "eth_getTransactionReceipt",
+            chain: "home",
+            subject: None,
+            after: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_timeout_names_what_it_waited_for() {
+        let (timer, clock) = Timer::manual();
+        assert_eq!(1, timeout(&timer, future::ok::<u64, Error>(1)).wait().unwrap());
+
+        let waiting = timeout(&timer, future::empty::<(), Error>()).about(H256::from(1));
+        clock.advance(Duration::from_millis(10));
+        let err = waiting.wait().unwrap_err();
+        match *err.kind() {
+            ErrorKind::Timeout(operation, chain, ref subject, after) => {
+                assert_eq!("eth_getTransactionReceipt", operation);
+                assert_eq!("home", chain);
+                assert_eq!(Some(format!("{:?}", H256::from(1))), *subject);
+                assert_eq!(Duration::from_secs(5), after);
+            }
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        let message = err.to_string();
+        assert!(message.starts_with("eth_getTransactionReceipt on home for 0x"));
+        assert!(message.ends_with(" timed out after 5s"));
+        assert_eq!(Some(&1), timeouts().get("eth_getTransactionReceipt"));
+
+        let waiting = timeout(&timer, future::empty::<(), Error>());
+        clock.advance(Duration::from_millis(10));
+        let err = waiting.wait().unwrap_err();
+        assert_eq!("eth_getTransactionReceipt on home timed out after 5s", err.to_string());
+        assert_eq!(Some(&2), timeouts().get("eth_getTransactionReceipt"));
+    }
+
+    #[test]
+    fn test_confirmed_range_from_genesis() {
+        // expected ranges after the checked block 0, by head (rows) and confirmations (columns)
+        let expected = [
+            [None, None, None, None],
+            [Some((1, 1)), None, None, None],
+            [Some((1, 2)), Some((1, 1)), None, None],
+            [Some((1, 3)), Some((1, 2)), Some((1, 1)), None],
+            [Some((1, 4)), Some((1, 3)), Some((1, 2)), Some((1, 1))],
+            [Some((1, 5)), Some((1, 4)), Some((1, 3)), Some((1, 2))],
+        ];
+        for head in 0..6 {
+            for confirmations in 0..4 {
+                assert_eq!(
+                    expected[head as usize][confirmations as usize],
+                    confirmed_range(0, head, confirmations, None),
+                    "head {}, confirmations {}",
+                    head,
+                    confirmations
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_head_tracker_ranges_never_go_backwards() {
+        let config = HeadRegression {
+            tolerance: 5,
+            polls: 3,
+        };
+        // replicas at different heights behind a load balancer
+        let heads = [10, 12, 11, 12, 9, 15, 14, 14, 20, 17, 21, 16, 22, 22, 25];
+        let mut tracker = HeadTracker::new(config);
+        let mut after = 0;
+        let mut ranges = Vec::new();
+        for &head in &heads {
+            if let Some(head) = tracker.observe(head).unwrap() {
+                if let Some((from, to)) = confirmed_range(after, head, 2, None) {
+                    ranges.push((from, to));
+                    after = to;
+                }
+            }
+        }
+        assert_eq!(
+            vec![(1, 8), (9, 10), (11, 13), (14, 18), (19, 19), (20, 20), (21, 23)],
+            ranges
+        );
+    }
+
+    #[test]
+    fn test_head_tracker_fails_on_persistent_regression() {
+        let config = HeadRegression {
+            tolerance: 5,
+            polls: 3,
+        };
+        let mut tracker = HeadTracker::new(config);
+        assert_eq!(Some(100), tracker.observe(100).unwrap());
+        assert_eq!(None, tracker.observe(90).unwrap());
+        assert_eq!(None, tracker.observe(90).unwrap());
+        // a head within the tolerance resets the count
+        assert_eq!(None, tracker.observe(97).unwrap());
+        assert_eq!(None, tracker.observe(90).unwrap());
+        assert_eq!(None, tracker.observe(90).unwrap());
+        match *tracker.observe(80).unwrap_err().kind() {
+            ErrorKind::HeadRegression(100, 80, 3) => {}
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_confirmed_range_covers_every_block_once() {
+        for confirmations in 0..4 {
+            for start in 0..6 {
+                // the head grows by one block per poll
+                let mut after = start;
+                let mut fetched = Vec::new();
+                for head in 0..6 {
+                    if let Some((from, to)) = confirmed_range(after, head, confirmations, None) {
+                        assert_eq!(after + 1, from);
+                        assert!(from <= to && to + confirmations <= head);
+                        fetched.extend(from..to + 1);
+                        after = to;
+                    }
+                }
+                let last_confirmed = 5u64.saturating_sub(confirmations);
+                let expected = (start + 1..last_confirmed + 1).collect::<Vec<_>>();
+                assert_eq!(
+                    expected, fetched,
+                    "start {}, confirmations {}",
+                    start, confirmations
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_confirmed_range_boundaries() {
+        // node behind the checked block
+        assert_eq!(None, confirmed_range(10, 5, 0, None));
+        assert_eq!(None, confirmed_range(5, 5, 0, None));
+        assert_eq!(Some((6, 6)), confirmed_range(5, 6, 0, None));
+        // head below the confirmations never underflows
+        assert_eq!(None, confirmed_range(0, 2, 3, None));
+        assert_eq!(None, confirmed_range(0, 0, u64::max_value(), None));
+        // capped at the last block posted to l1
+        assert_eq!(Some((1, 3)), confirmed_range(0, 10, 2, Some(3)));
+        assert_eq!(Some((1, 8)), confirmed_range(0, 10, 2, Some(20)));
+        assert_eq!(None, confirmed_range(3, 10, 2, Some(3)));
+        assert_eq!(None, confirmed_range(0, 10, 2, Some(0)));
+    }
+
+    #[test]
+    fn test_is_missing_state() {
+        let pruned = Error::from("Rpc error: missing trie node 1a2b (path )");
+        assert!(is_missing_state(&pruned));
+        let chained = Error::with_chain(Error::from("Header not found"), "eth_call failed");
+        assert!(is_missing_state(&chained));
+        assert!(!is_missing_state(&Error::from("execution reverted")));
+    }
+
+    #[test]
+    fn test_is_log_limit_error() {
+        let infura = Error::from("Rpc error: query returned more than 10000 results");
+        assert!(is_log_limit_error(&infura, &[]));
+        let alchemy = Error::with_chain(
+            Error::from("Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range"),
+            "eth_getLogs failed",
+        );
+        assert!(is_log_limit_error(&alchemy, &[]));
+        assert!(is_log_limit_error(&Error::from("exceed maximum block range: 5000"), &[]));
+
+        let custom = Error::from("Response Too Large");
+        assert!(!is_log_limit_error(&custom, &[]));
+        assert!(is_log_limit_error(&custom, &["response too large".into()]));
+        assert!(!is_log_limit_error(&Error::from("request timed out"), &["too large".into()]));
+    }
+
+    #[test]
+    fn test_is_method_not_found() {
+        let geth = Error::from("Rpc error: the method eth_feeHistory does not exist/is not available");
+        assert!(is_method_not_found(&geth));
+        let parity = Error::with_chain(Error::from("Method not found"), "eth_feeHistory failed");
+        assert!(is_method_not_found(&parity));
+        assert!(!is_method_not_found(&Error::from("request timed out")));
+    }
+
+    #[test]
+    fn test_decode_result_normalizes_quantities() {
+        let head: U256 = decode_result("eth_blockNumber", "0x0001b4".into()).unwrap();
+        assert_eq!(U256::from(0x1b4), head);
+
+        // a receipt of a provider padding its quantities
+        let receipt: Value = serde_json::from_str(&format!(
+            r#"{{
+                "blockHash": null,
+                "blockNumber": "0x0000001b4",
+                "contractAddress": null,
+                "cumulativeGasUsed": "0x05208",
+                "gasUsed": "0x05208",
+                "logs": [],
+                "logsBloom": "0x{}",
+                "status": "0x00",
+                "transactionHash": "0x{:064x}",
+                "transactionIndex": "0x00"
+            }}"#,
+            "0".repeat(512),
+            0x11
+        )).unwrap();
+        let receipt: ReceiptWithStatus =
+            decode_result("eth_getTransactionReceipt", receipt).unwrap();
+        assert_eq!(Some(0x1b4.into()), receipt.receipt.block_number);
+        assert_eq!(U256::from(21000), receipt.receipt.gas_used);
+        assert!(receipt.reverted());
+
+        // data isn't a quantity, its leading zeros are kept
+        let output: Vec<u8> = decode_result::<::web3::types::Bytes>("eth_call", "0x0010".into())
+            .unwrap()
+            .0;
+        assert_eq!(vec![0, 0x10], output);
+    }
+
+    #[test]
+    fn test_decode_result_errors_name_the_method_and_result() {
+        let err = decode_result::<U256>("eth_gasPrice", "0x1g".into()).unwrap_err();
+        assert_eq!(
+            "invalid eth_gasPrice result \"0x1g\": quantity \"0x1g\" is not a hex number",
+            err.to_string()
+        );
+        match *err.kind() {
+            ErrorKind::InvalidRpcResponse("eth_gasPrice", ..) => {}
+            ref kind => panic!("expected invalid response error, got {:?}", kind),
+        }
+
+        // data of odd length is rejected by the decoder
+        let err = decode_result::<::web3::types::Bytes>("eth_call", "0x123".into()).unwrap_err();
+        assert!(
+            err.to_string().starts_with("invalid eth_call result \"0x123\": "),
+            "{}",
+            err
+        );
+
+        // large results are truncated
+        let logs = Value::Array(vec![Value::from("not a log"); 1000]);
+        let err = decode_result::<Vec<Log>>("eth_getLogs", logs).unwrap_err();
+        match *err.kind() {
+            ErrorKind::InvalidRpcResponse(_, ref result, _) => {
+                assert!(result.len() < MAX_INVALID_RESULT_SIZE + 32, "{}", result)
+            }
+            ref kind => panic!("expected invalid response error, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_pending_count() {
+        let parity: Value = serde_json::from_str(
+            r#"[{"from":"0x0000000000000000000000000000000000000001","nonce":"0x1"},
+                {"from":"0x0000000000000000000000000000000000000002","nonce":"0x0"},
+                {"from":"0x0000000000000000000000000000000000000001","nonce":"0x2"}]"#,
+        ).unwrap();
+        assert_eq!(2, pending_count(TxpoolClient::Parity, &parity, 1.into()).unwrap());
+        assert_eq!(0, pending_count(TxpoolClient::Parity, &parity, 3.into()).unwrap());
+
+        let geth: Value = serde_json::from_str(r#"{"pending":"0x1a","queued":"0x2"}"#).unwrap();
+        assert_eq!(26, pending_count(TxpoolClient::Geth, &geth, 1.into()).unwrap());
+        assert!(pending_count(TxpoolClient::Geth, &parity, 1.into()).is_err());
+        assert!(pending_count(TxpoolClient::Parity, &geth, 1.into()).is_err());
+    }
+
+    #[test]
+    fn test_node_failure() {
+        let rejection = rpc::Error {
+            code: rpc::ErrorCode::ServerError(-32015),
+            message: "VM execution error.".into(),
+            data: Some("Reverted 0x".into()),
+        };
+        let rejected = Some(NodeFailure::Rejected {
+            code: -32015,
+            message: "VM execution error.".into(),
+            data: Some("Reverted 0x".into()),
+        });
+        let err = Error::from(ErrorKind::Web3(web3::Error::Rpc(rejection.clone())));
+        assert_eq!(rejected, node_failure(&err, false));
+        assert_eq!(rejected, node_failure(&err, true));
+        let personal = Error::with_chain(
+            err,
+            ErrorKind::PersonalApi("personal_sendTransaction", rejection),
+        );
+        assert_eq!(rejected, node_failure(&personal, true));
+
+        let refused = web3::Error::Transport("connection refused".into());
+        let refused = Error::from(ErrorKind::Web3(refused));
+        assert_eq!(
+            Some(NodeFailure::Unreachable {
+                source: "Transport(\"connection refused\")".into(),
+            }),
+            node_failure(&refused, false)
+        );
+        let ambiguous = Some(NodeFailure::AmbiguousSend { maybe_sent: true });
+        assert_eq!(ambiguous, node_failure(&refused, true));
+        let timeout = Error::from(ErrorKind::Timeout(
+            "eth_sendTransaction",
+            "foreign",
+            None,
+            Duration::from_secs(5),
+        ));
+        assert_eq!(ambiguous, node_failure(&timeout, true));
+        let invalid = decode_result::<H256>("eth_sendTransaction", "0x1".into()).unwrap_err();
+        assert_eq!(ambiguous, node_failure(&invalid, true));
+        assert_eq!(None, node_failure(&invalid, false));
+
+        // a remote signer refusing to sign never sends the transaction
+        let message = format!("{}sending to 0x00f0 is not allowed", REMOTE_SIGNER_ERROR);
+        let refusal = Error::from(ErrorKind::Web3(web3::Error::Transport(message)));
+        assert_eq!(
+            Some(NodeFailure::AmbiguousSend { maybe_sent: false }),
+            node_failure(&refusal, true)
+        );
+        assert_eq!(None, node_failure(&Error::from("invalid call data"), true));
+    }
+
+    #[test]
+    fn test_pending_transaction() {
+        let pending: Value = serde_json::from_str(&format!(
+            r#"[{{"hash":"0x{:064x}","from":"0x0000000000000000000000000000000000000001",
+                 "to":"0x00000000000000000000000000000000000000f0","input":"0x01"}},
+                {{"hash":"0x{:064x}","from":"0x0000000000000000000000000000000000000001",
+                 "to":"0x00000000000000000000000000000000000000f0","input":"0x02"}}]"#,
+            1, 2
+        )).unwrap();
+        let tx = |data: u8| TransactionRequest {
+            from: 1.into(),
+            to: Some(0xf0.into()),
+            gas: None,
+            gas_price: None,
+            value: None,
+            data: Some(Bytes(vec![data])),
+            nonce: None,
+            condition: None,
+        };
+        assert_eq!(Some(H256::from(2)), pending_transaction(&pending, &tx(2)));
+        assert_eq!(None, pending_transaction(&pending, &tx(3)));
+        let other_sender = TransactionRequest {
+            from: 2.into(),
+            ..tx(1)
+        };
+        assert_eq!(None, pending_transaction(&pending, &other_sender));
+        assert_eq!(None, pending_transaction(&Value::Null, &tx(1)));
+    }
+}
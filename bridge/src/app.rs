@@ -1,21 +1,43 @@
 use std::path::{Path, PathBuf};
+use futures::{Future, Poll};
 use tokio_core::reactor::Handle;
-use tokio_timer::Timer;
-use web3::Transport;
+use timer::Timer;
+use web3::{self, RequestId, Transport};
 use web3::transports::ipc::Ipc;
+use web3::types::U256;
+use rpc;
+use bridge::{node_endpoint, ChainClocks, NodeVersions, RelayNode, SharedGasPrice};
 use error::{Error, ErrorKind, ResultExt};
-use config::Config;
+use config::{Config, Node, TransactionConfig};
 use contracts::{foreign, home};
+use http_transport::HttpTransport;
+use runtime_abi::RuntimeAbi;
+use tunables::SharedTunables;
 
 pub struct App<T>
 where
     T: Transport,
 {
     pub config: Config,
+    /// settings of `config` that can be reloaded while the bridge runs.
+    /// read these instead of the ones in `config`
+    pub tunables: SharedTunables,
     pub database_path: PathBuf,
     pub connections: Connections<T>,
     pub home_bridge: home::HomeBridge,
     pub foreign_bridge: foreign::ForeignBridge,
+    /// ABI of the contracts if loaded at runtime.
+    /// `None` if the compiled bindings are used
+    pub abi: Option<RuntimeAbi>,
+    /// gas price of foreign derived by `NodeGasPriceMonitor`.
+    /// never set if `node_gas_price` isn't configured
+    pub node_gas_price: SharedGasPrice,
+    /// recent headers of both chains fetched by `ChainClockMonitor`.
+    /// never updated if `chain_clock` isn't configured
+    pub chain_clocks: ChainClocks,
+    /// client versions of both nodes fetched by `NodeVersionMonitor`
+    pub node_versions: NodeVersions,
+    /// clock of the timeouts, delays and intervals. tests inject a `ManualClock`
     pub timer: Timer,
 }
 
@@ -43,6 +65,17 @@ impl Connections<Ipc> {
     }
 }
 
+impl Connections<NodeTransport> {
+    /// connects to the `ipc` or the `rpc_url` of each node of `config`.
+    pub fn new(handle: &Handle, config: &Config) -> Result<Self, Error> {
+        let result = Connections {
+            home: NodeTransport::new(handle, "home", &config.home)?,
+            foreign: NodeTransport::new(handle, "foreign", &config.foreign)?,
+        };
+        Ok(result)
+    }
+}
+
 impl<T: Transport> Connections<T> {
     pub fn as_ref(&self) -> Connections<&T> {
         Connections {
@@ -52,6 +85,75 @@ impl<T: Transport> Connections<T> {
     }
 }
 
+/// Connection to a node over its `ipc` or its `rpc_url`.
+#[derive(Debug, Clone)]
+pub enum NodeTransport {
+    Ipc(Ipc),
+    Http(HttpTransport),
+}
+
+impl NodeTransport {
+    /// connects to `node` of `chain`, over http if `rpc_url` is configured.
+    pub fn new(handle: &Handle, chain: &'static str, node: &Node) -> Result<Self, Error> {
+        if node.rpc_url.is_some() {
+            return Ok(NodeTransport::Http(HttpTransport::new(chain, node)?));
+        }
+        let ipc = Ipc::with_event_loop(&node.ipc, handle)
+            .map_err(ErrorKind::Web3)
+            .map_err(Error::from)
+            .chain_err(|| format!("Cannot connect to {} node ipc", chain))?;
+        Ok(NodeTransport::Ipc(ipc))
+    }
+}
+
+impl Transport for NodeTransport {
+    type Out = NodeResponse;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        match *self {
+            NodeTransport::Ipc(ref ipc) => ipc.prepare(method, params),
+            NodeTransport::Http(ref http) => http.prepare(method, params),
+        }
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        match *self {
+            NodeTransport::Ipc(ref ipc) => NodeResponse::Ipc(ipc.send(id, request)),
+            NodeTransport::Http(ref http) => NodeResponse::Http(http.send(id, request)),
+        }
+    }
+}
+
+/// Response of a `NodeTransport`.
+pub enum NodeResponse {
+    Ipc(<Ipc as Transport>::Out),
+    Http(<HttpTransport as Transport>::Out),
+}
+
+impl Future for NodeResponse {
+    type Item = rpc::Value;
+    type Error = web3::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match *self {
+            NodeResponse::Ipc(ref mut response) => response.poll(),
+            NodeResponse::Http(ref mut response) => response.poll(),
+        }
+    }
+}
+
+impl App<NodeTransport> {
+    /// app connected to the `ipc` or the `rpc_url` of each node of `config`.
+    pub fn new<P: AsRef<Path>>(
+        config: Config,
+        database_path: P,
+        handle: &Handle,
+    ) -> Result<Self, Error> {
+        let connections = Connections::new(handle, &config)?;
+        App::with_connections(config, database_path, connections)
+    }
+}
+
 impl App<Ipc> {
     pub fn new_ipc<P: AsRef<Path>>(
         config: Config,
@@ -59,27 +161,93 @@ impl App<Ipc> {
         handle: &Handle,
     ) -> Result<Self, Error> {
         let connections = Connections::new_ipc(handle, &config.home.ipc, &config.foreign.ipc)?;
+        App::with_connections(config, database_path, connections)
+    }
+}
+
+impl<T: Transport> App<T> {
+    fn with_connections<P: AsRef<Path>>(
+        config: Config,
+        database_path: P,
+        connections: Connections<T>,
+    ) -> Result<Self, Error> {
+        let abi = RuntimeAbi::load(&config)?;
         let result = App {
+            tunables: SharedTunables::of(&config),
             config,
             database_path: database_path.as_ref().to_path_buf(),
             connections,
             home_bridge: home::HomeBridge::default(),
             foreign_bridge: foreign::ForeignBridge::default(),
+            abi,
+            node_gas_price: SharedGasPrice::default(),
+            chain_clocks: ChainClocks::default(),
+            node_versions: NodeVersions::default(),
             timer: Timer::default(),
         };
         Ok(result)
     }
-}
 
-impl<T: Transport> App<T> {
+    /// wraps the connections in transport middleware.
+    /// `wrap` is called with the transport and the name of its chain.
+    pub fn with_transports<U, F>(self, wrap: F) -> App<U>
+    where
+        U: Transport,
+        F: Fn(T, &'static str) -> U,
+    {
+        App {
+            config: self.config,
+            tunables: self.tunables,
+            database_path: self.database_path,
+            connections: Connections {
+                home: wrap(self.connections.home, "home"),
+                foreign: wrap(self.connections.foreign, "foreign"),
+            },
+            home_bridge: self.home_bridge,
+            foreign_bridge: self.foreign_bridge,
+            abi: self.abi,
+            node_gas_price: self.node_gas_price,
+            chain_clocks: self.chain_clocks,
+            node_versions: self.node_versions,
+            timer: self.timer,
+        }
+    }
+
     pub fn as_ref(&self) -> App<&T> {
         App {
             config: self.config.clone(),
+            tunables: self.tunables.clone(),
             connections: self.connections.as_ref(),
             database_path: self.database_path.clone(),
             home_bridge: home::HomeBridge::default(),
             foreign_bridge: foreign::ForeignBridge::default(),
+            abi: self.abi.clone(),
+            node_gas_price: self.node_gas_price.clone(),
+            chain_clocks: self.chain_clocks.clone(),
+            node_versions: self.node_versions.clone(),
             timer: self.timer.clone(),
         }
     }
+
+    /// gas price of transactions to foreign sent with `tx`: the one derived from the node
+    /// if `node_gas_price` is configured and it was derived already, `tx.gas_price` otherwise.
+    pub fn foreign_gas_price(&self, tx: &TransactionConfig) -> U256 {
+        self.node_gas_price.get().unwrap_or(tx.gas_price).into()
+    }
+
+    /// the home node with its client version, if fetched.
+    pub fn home_node(&self) -> RelayNode {
+        RelayNode {
+            endpoint: node_endpoint(&self.config.home),
+            client_version: self.node_versions.home(),
+        }
+    }
+
+    /// the foreign node with its client version, if fetched.
+    pub fn foreign_node(&self) -> RelayNode {
+        RelayNode {
+            endpoint: node_endpoint(&self.config.foreign),
+            client_version: self.node_versions.foreign(),
+        }
+    }
 }
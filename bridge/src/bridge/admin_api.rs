@@ -0,0 +1,738 @@
+use futures::{future, Future};
+use jsonrpc_http_server::{RequestMiddlewareAction, Response, Server, ServerBuilder};
+use jsonrpc_http_server::hyper::{header, server, StatusCode};
+use log::LogLevelFilter;
+use rpc::{self, IoHandler, Params, Value};
+use serde_json;
+use tokio_core::reactor::Remote;
+use web3::types::{H256, U256};
+use config::{AdminConfig, SignedCommandsConfig};
+use error::{Error, ResultExt};
+use log_level::{Directive, LogLevels};
+use util::is_bearer;
+use super::command::{BridgeCommand, CommandSender};
+use super::signed_command::{command_signers, severity, CommandAuthorization, SignedParams};
+use super::{Component, SpendKind};
+
+/// parses the `[level]` or `[level, module]` params of `set-log-level`.
+fn parse_set_log_level(params: Params) -> Result<Directive, rpc::Error> {
+    let params: Vec<String> = params.parse()?;
+    let module = match params.len() {
+        1 => None,
+        2 if params[1].is_empty() => {
+            return Err(rpc::Error::invalid_params("module must not be empty"))
+        }
+        2 => Some(params[1].clone()),
+        _ => return Err(rpc::Error::invalid_params("expected [level] or [level, module]")),
+    };
+    let level: LogLevelFilter = params[0].parse().map_err(|_| {
+        rpc::Error::invalid_params(format!(
+            "level must be off, error, warn, info, debug or trace, got {:?}",
+            params[0]
+        ))
+    })?;
+    Ok(Directive { module, level })
+}
+
+/// accepts `[]` or no params of the methods without arguments.
+fn parse_no_params(params: Params) -> Result<(), rpc::Error> {
+    match params {
+        Params::None => Ok(()),
+        Params::Array(ref params) if params.is_empty() => Ok(()),
+        _ => Err(rpc::Error::invalid_params("expected no params")),
+    }
+}
+
+/// parses the `[component]` params of the circuit methods.
+fn parse_component(params: Params) -> Result<Component, rpc::Error> {
+    let params: Vec<String> = params.parse()?;
+    match params.first().map(String::as_str) {
+        Some("deposit_relay") if params.len() == 1 => Ok(Component::DepositRelay),
+        Some("withdraw_relay") if params.len() == 1 => Ok(Component::WithdrawRelay),
+        Some("withdraw_confirm") if params.len() == 1 => Ok(Component::WithdrawConfirm),
+        _ => Err(rpc::Error::invalid_params(
+            "expected [\"deposit_relay\"], [\"withdraw_relay\"] or [\"withdraw_confirm\"]",
+        )),
+    }
+}
+
+/// parses the `[kind]` or `[kind, argument]` params of the spend limit methods.
+fn parse_spend_params(params: Params, argument: bool) -> Result<(SpendKind, String), rpc::Error> {
+    let params: Vec<String> = params.parse()?;
+    let expected = if argument { 2 } else { 1 };
+    if params.len() != expected {
+        return Err(rpc::Error::invalid_params(if argument {
+            "expected [kind, argument]"
+        } else {
+            "expected [kind]"
+        }));
+    }
+    let kind = match params[0].as_str() {
+        "deposits" => SpendKind::Deposits,
+        "withdraws" => SpendKind::Withdraws,
+        other => {
+            return Err(rpc::Error::invalid_params(format!(
+                "kind must be deposits or withdraws, got {:?}",
+                other
+            )))
+        }
+    };
+    Ok((kind, params.last().cloned().unwrap_or_default()))
+}
+
+fn parse_set_spend_limit(params: Params) -> Result<BridgeCommand, rpc::Error> {
+    let (kind, limit) = parse_spend_params(params, true)?;
+    let limit = U256::from_dec_str(&limit).map_err(|_| {
+        rpc::Error::invalid_params(format!("limit must be a decimal integer, got {:?}", limit))
+    })?;
+    Ok(BridgeCommand::SetSpendLimit(kind, limit))
+}
+
+/// parses a relay key, see `relay_key`.
+fn parse_key(key: &str) -> Result<H256, rpc::Error> {
+    key.trim_left_matches("0x").parse().map_err(|_| {
+        rpc::Error::invalid_params(format!("key must be 32 bytes of hex, got {:?}", key))
+    })
+}
+
+fn parse_release_deferred(params: Params) -> Result<BridgeCommand, rpc::Error> {
+    let (kind, key) = parse_spend_params(params, true)?;
+    Ok(BridgeCommand::ReleaseDeferred(kind, parse_key(&key)?))
+}
+
+fn parse_release_held_deposit(params: Params) -> Result<BridgeCommand, rpc::Error> {
+    let params: Vec<String> = params.parse()?;
+    if params.len() != 1 {
+        return Err(rpc::Error::invalid_params("expected [key]"));
+    }
+    Ok(BridgeCommand::ReleaseHeldDeposit(parse_key(&params[0])?))
+}
+
+fn unauthorized(message: String) -> rpc::Error {
+    rpc::Error {
+        code: rpc::ErrorCode::ServerError(-32001),
+        message,
+        data: None,
+    }
+}
+
+/// the params of `method` and, if `signed` makes it need signatures, the authorization of
+/// the operators who signed it. the params of a signed call are `SignedParams`.
+fn authorize(
+    signed: Option<&SignedCommandsConfig>,
+    method: &str,
+    params: Params,
+) -> Result<(Params, Option<CommandAuthorization>), rpc::Error> {
+    let (config, required) = match signed {
+        Some(config) => {
+            let severity = severity(method).expect("every admin method has a severity; qed");
+            (config, config.required_signatures(severity))
+        }
+        None => return Ok((params, None)),
+    };
+    if required == 0 {
+        return Ok((params, None));
+    }
+    let signed: SignedParams = match params {
+        Params::Map(_) => params.parse()?,
+        _ => {
+            return Err(unauthorized(format!(
+                "{} needs {} operator signatures, see --admin-sign",
+                method, required
+            )))
+        }
+    };
+    let signers = command_signers(config, method, &signed).map_err(|err| {
+        warn!(target: "bridge::admin", "refused {} with nonce {}: {}", method, signed.nonce, err);
+        unauthorized(err.to_string())
+    })?;
+    if signers.len() < required {
+        warn!(
+            target: "bridge::admin",
+            "refused {} with nonce {}: signed by {} of {} operators",
+            method,
+            signed.nonce,
+            signers.len(),
+            required
+        );
+        return Err(unauthorized(format!(
+            "{} needs {} operator signatures, got {}",
+            method,
+            required,
+            signers.len()
+        )));
+    }
+    let authorization = CommandAuthorization {
+        method: method.to_owned(),
+        params: signed.params.clone(),
+        nonce: signed.nonce,
+        signers,
+    };
+    Ok((Params::Array(signed.params), Some(authorization)))
+}
+
+/// sends `command` to the bridge and returns its reply.
+/// a command the bridge rejects fails with its error message.
+fn send(commands: &CommandSender, command: BridgeCommand) -> rpc::BoxFuture<Value> {
+    Box::new(commands.send(command).map_err(|message| rpc::Error {
+        code: rpc::ErrorCode::ServerError(-32000),
+        message,
+        data: None,
+    }))
+}
+
+/// sends the command parsed from the params to the bridge and returns its reply.
+/// a signed command is only sent once the bridge accepted the nonce of its `authorization`,
+/// its outcome is logged with the signers to the `bridge::admin` target.
+fn execute(
+    commands: &CommandSender,
+    authorization: Option<CommandAuthorization>,
+    command: Result<BridgeCommand, rpc::Error>,
+) -> rpc::BoxFuture<Value> {
+    let command = match command {
+        Ok(command) => command,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    info!(target: "bridge", "admin api sends {:?} to the bridge", command);
+    let authorization = match authorization {
+        Some(authorization) => authorization,
+        None => return send(commands, command),
+    };
+    let commands = commands.clone();
+    let executed = send(&commands, BridgeCommand::Authorize(authorization.clone()))
+        .and_then(move |_| send(&commands, command))
+        .then(move |result| {
+            match result {
+                Ok(_) => info!(target: "bridge::admin", "executed {}", authorization),
+                Err(ref err) => warn!(
+                    target: "bridge::admin",
+                    "cannot execute {}: {}",
+                    authorization,
+                    err.message
+                ),
+            }
+            result
+        });
+    Box::new(executed)
+}
+
+/// adds `method`, sending the command `parse` makes of its params to the bridge.
+fn add_command<P>(
+    io: &mut IoHandler,
+    method: &'static str,
+    signed: &Option<SignedCommandsConfig>,
+    commands: &CommandSender,
+    parse: P,
+) where
+    P: Fn(Params) -> Result<BridgeCommand, rpc::Error> + Send + Sync + 'static,
+{
+    let signed = signed.clone();
+    let commands = commands.clone();
+    io.add_method(method, move |params: Params| {
+        match authorize(signed.as_ref(), method, params) {
+            Ok((params, authorization)) => execute(&commands, authorization, parse(params)),
+            Err(err) => Box::new(future::err(err)),
+        }
+    });
+}
+
+fn status(levels: &LogLevels) -> Value {
+    serde_json::to_value(levels.status()).expect("LevelsStatus always serializes; qed")
+}
+
+/// methods of the admin api:
+///
+/// - `set-log-level` with `[level]` or `[level, module]` overrides the level of all modules
+///   or of `module` for `log_level_duration`. the timer reverting it runs on `remote`
+/// - `get-log-level` returns the effective filter and the seconds until each override reverts
+///
+/// both return the `LevelsStatus` after the call.
+///
+/// the other methods are executed by the bridge polling the receiver of `commands`:
+///
+/// - `force-open-circuit` with `[component]` stops `deposit_relay`, `withdraw_relay` or
+///   `withdraw_confirm` until `force-close-circuit` with the same params closes its circuit
+/// - `get-spend-limit` with `[kind]`, `deposits` or `withdraws`, returns the limit, the spend
+///   within the window and the keys of the deferred relays
+/// - `set-spend-limit` with `[kind, limit]` changes the limit until the bridge restarts
+/// - `release-deferred` with `[kind, key]` relays the deferred relay regardless of the limit
+///
+/// the spend limit methods return the state of the limit after the call.
+///
+/// - `release-held-deposit` with `[key]` relays the deposit with the relay key held for its
+///   `manual` recipient of `recipient_policies`. returns the deposits still held by recipient
+/// - `drain` drains the bridge like `SIGUSR2` and returns whether it drains, the timeout and the
+///   components drained so far. a drain in progress isn't restarted
+/// - `acknowledge-deep-reorg` acknowledges the home reorg that reached
+///   `reorg_monitor.alarm_depth` and resumes deposit relay if the reorg halted it
+/// - `get-errors` returns the latest failures of every component that failed, see
+///   `Session::recent_errors`
+///
+/// with `signed_commands`, the methods of its severity and above need `SignedParams` signed
+/// by operators, see `severity`. the bridge accepts each nonce once, before the method runs.
+pub fn admin_handler(
+    levels: LogLevels,
+    config: &AdminConfig,
+    remote: Remote,
+    commands: CommandSender,
+) -> IoHandler {
+    let mut io = IoHandler::new();
+    let duration = config.log_level_duration;
+    let signed = config.signed_commands.clone();
+    let set_levels = levels.clone();
+    let set_signed = signed.clone();
+    let set_commands = commands.clone();
+    io.add_method("set-log-level", move |params: Params| -> rpc::BoxFuture<Value> {
+        let parsed = authorize(set_signed.as_ref(), "set-log-level", params).and_then(
+            |(params, authorization)| Ok((parse_set_log_level(params)?, authorization)),
+        );
+        let (directive, authorization) = match parsed {
+            Ok(parsed) => parsed,
+            Err(err) => return Box::new(future::err(err)),
+        };
+        let levels = set_levels.clone();
+        let remote = remote.clone();
+        let set = move || -> Result<Value, rpc::Error> {
+            levels.set_level(
+                directive.level,
+                directive.module.as_ref().map(String::as_str),
+                duration,
+            );
+            info!(
+                target: "bridge",
+                "log level {} set by the admin api, reverts in {}s",
+                directive,
+                duration.as_secs()
+            );
+            let revert = levels
+                .revert_after(duration)
+                .map_err(|err| error!(target: "bridge", "Cannot revert the log level: {}", err));
+            remote.spawn(move |_| revert);
+            Ok(status(&levels))
+        };
+        match authorization {
+            None => Box::new(future::result(set())),
+            Some(authorization) => {
+                // the nonce is accepted by the bridge, which records it
+                let accepted = send(&set_commands, BridgeCommand::Authorize(authorization.clone()));
+                Box::new(accepted.and_then(move |_| {
+                    info!(target: "bridge::admin", "executed {}", authorization);
+                    set()
+                }))
+            }
+        }
+    });
+    io.add_method("get-log-level", move |_params: Params| Ok(status(&levels)));
+    add_command(&mut io, "force-open-circuit", &signed, &commands, |params| {
+        parse_component(params).map(BridgeCommand::ForceOpenCircuit)
+    });
+    add_command(&mut io, "force-close-circuit", &signed, &commands, |params| {
+        parse_component(params).map(BridgeCommand::ForceCloseCircuit)
+    });
+    add_command(&mut io, "get-spend-limit", &signed, &commands, |params| {
+        let kind = parse_spend_params(params, false).map(|(kind, _)| kind);
+        kind.map(BridgeCommand::SpendLimit)
+    });
+    add_command(&mut io, "set-spend-limit", &signed, &commands, parse_set_spend_limit);
+    add_command(&mut io, "release-deferred", &signed, &commands, parse_release_deferred);
+    add_command(
+        &mut io,
+        "release-held-deposit",
+        &signed,
+        &commands,
+        parse_release_held_deposit,
+    );
+    add_command(&mut io, "drain", &signed, &commands, |params| {
+        parse_no_params(params).map(|()| BridgeCommand::Drain)
+    });
+    add_command(&mut io, "acknowledge-deep-reorg", &signed, &commands, |params| {
+        parse_no_params(params).map(|()| BridgeCommand::AcknowledgeDeepReorg)
+    });
+    add_command(&mut io, "get-errors", &signed, &commands, |params| {
+        parse_no_params(params).map(|()| BridgeCommand::RecentErrors)
+    });
+    io
+}
+
+/// serves the admin api on `config.listen` until the returned server is dropped.
+/// requests need the bearer token of `config.token` if it's set,
+/// without it the api is only served on a loopback address.
+pub fn serve_admin(
+    levels: LogLevels,
+    config: &AdminConfig,
+    remote: Remote,
+    commands: CommandSender,
+) -> Result<Server, Error> {
+    let token = config.token.clone();
+    let listen = config.listen;
+    if token.is_none() && !listen.ip().is_loopback() {
+        bail!("Cannot serve the admin api on {} without a token", listen);
+    }
+    ServerBuilder::new(admin_handler(levels, config, remote, commands))
+        .request_middleware(move |request: server::Request| -> RequestMiddlewareAction {
+            let authorized = token.as_ref().map_or(true, |token| {
+                request
+                    .headers()
+                    .get_raw("Authorization")
+                    .and_then(|value| value.one())
+                    .map_or(false, |value| is_bearer(value, token.as_str()))
+            });
+            if authorized {
+                return request.into();
+            }
+            let mut response: server::Response = Response {
+                code: StatusCode::Unauthorized,
+                content_type: header::ContentType::json(),
+                content: json!({ "error": "missing or invalid bearer token" }).to_string(),
+            }.into();
+            response.headers_mut().set_raw("WWW-Authenticate", "Bearer");
+            response.into()
+        })
+        .start_http(&listen)
+        .chain_err(|| format!("Cannot serve the admin api on {}", listen))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread;
+    use std::time::Duration;
+    use futures::Stream;
+    use log::{LogLevel, LogLevelFilter};
+    use reqwest;
+    use rpc::{self, Params};
+    use serde_json;
+    use tokio_core::reactor::Core;
+    use config::{AdminConfig, CommandSeverity, Password, SignedCommandsConfig};
+    use log_level::{Directive, LogLevels};
+    use timer::Timer;
+    use super::super::command::{command_channel, CommandReceiver, CommandSender};
+    use super::super::signed_command::sign_command;
+    use super::{admin_handler, parse_set_log_level, serve_admin};
+
+    /// secret keys of the operators 0x7e5f4552091a69125d5dfcb7b8c2659029395bdf
+    /// and 0x2b5ad5c4795c026514f8317c7a215e218dccd6cf
+    const ONE: &str = "0x0000000000000000000000000000000000000000000000000000000000000001";
+    const TWO: &str = "0x0000000000000000000000000000000000000000000000000000000000000002";
+
+    fn config(token: Option<&str>) -> AdminConfig {
+        AdminConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            token_file: None,
+            token: token.map(Password::new),
+            log_level_duration: Duration::from_secs(600),
+            signed_commands: None,
+        }
+    }
+
+    /// sender of commands nobody receives.
+    fn no_bridge() -> CommandSender {
+        command_channel().0
+    }
+
+    /// replies to every command with its debug output, as the bridge would with the result.
+    fn bridge(receiver: CommandReceiver) {
+        thread::spawn(move || {
+            for request in receiver.wait() {
+                let request = request.unwrap();
+                let reply = format!("{:?}", request.command);
+                request.reply(Ok(json!(reply)));
+            }
+        });
+    }
+
+    fn call(io: &rpc::IoHandler, method: &str, params: &str) -> serde_json::Value {
+        let request = format!(
+            r#"{{"jsonrpc":"2.0","method":"{}","params":{},"id":1}}"#,
+            method, params
+        );
+        serde_json::from_str(&io.handle_request_sync(&request).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_set_log_level() {
+        let params = |json: &str| Params::Array(serde_json::from_str(json).unwrap());
+        assert_eq!(
+            Directive {
+                module: None,
+                level: LogLevelFilter::Debug,
+            },
+            parse_set_log_level(params(r#"["debug"]"#)).unwrap()
+        );
+        assert_eq!(
+            Directive {
+                module: Some("bridge::deposit_relay".into()),
+                level: LogLevelFilter::Trace,
+            },
+            parse_set_log_level(params(r#"["TRACE", "bridge::deposit_relay"]"#)).unwrap()
+        );
+        for invalid in &[r#"[]"#, r#"["loud"]"#, r#"["debug", ""]"#, r#"["debug", "a", "b"]"#] {
+            assert!(parse_set_log_level(params(invalid)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_set_log_level_reverts_after_the_duration() {
+        let mut core = Core::new().unwrap();
+        let (timer, clock) = Timer::manual();
+        let levels = LogLevels::with_timer("info", timer.clone());
+        let io = admin_handler(levels.clone(), &config(None), core.remote(), no_bridge());
+
+        let response = call(&io, "set-log-level", r#"["debug", "bridge::deposit_relay"]"#);
+        assert_eq!(
+            json!({
+                "filter": "info,bridge::deposit_relay=debug",
+                "overrides": [{ "directive": "bridge::deposit_relay=debug", "reverts_in": 600 }],
+            }),
+            response["result"]
+        );
+        assert!(levels.enabled("bridge::deposit_relay", LogLevel::Debug));
+        // the revert timer is spawned on the event loop
+        core.turn(Some(Duration::from_millis(10)));
+
+        clock.advance(Duration::from_secs(599));
+        core.turn(Some(Duration::from_millis(10)));
+        assert_eq!(
+            json!({
+                "filter": "info,bridge::deposit_relay=debug",
+                "overrides": [{ "directive": "bridge::deposit_relay=debug", "reverts_in": 1 }],
+            }),
+            call(&io, "get-log-level", "[]")["result"]
+        );
+
+        clock.advance(Duration::from_secs(1));
+        core.turn(Some(Duration::from_millis(10)));
+        assert!(!levels.enabled("bridge::deposit_relay", LogLevel::Debug));
+        // reverted by the timer already
+        assert!(levels.revert_expired_at(timer.now()).is_empty());
+        assert_eq!(
+            json!({ "filter": "info", "overrides": [] }),
+            call(&io, "get-log-level", "[]")["result"]
+        );
+    }
+
+    #[test]
+    fn test_get_errors_sends_a_command_to_the_bridge() {
+        let core = Core::new().unwrap();
+        let (commands, receiver) = command_channel();
+        bridge(receiver);
+        let io = admin_handler(LogLevels::new("info"), &config(None), core.remote(), commands);
+        assert_eq!(json!("RecentErrors"), call(&io, "get-errors", "[]")["result"]);
+        assert_eq!(-32602, call(&io, "get-errors", r#"["DepositRelay"]"#)["error"]["code"]);
+    }
+
+    #[test]
+    fn test_set_log_level_rejects_invalid_params() {
+        let core = Core::new().unwrap();
+        let levels = LogLevels::new("info");
+        let io = admin_handler(levels.clone(), &config(None), core.remote(), no_bridge());
+        let response = call(&io, "set-log-level", r#"["loud"]"#);
+        assert_eq!(-32602, response["error"]["code"]);
+        assert_eq!("info", levels.describe());
+    }
+
+    #[test]
+    fn test_serve_admin() {
+        let core = Core::new().unwrap();
+        let levels = LogLevels::new("warn");
+        let open = AdminConfig {
+            listen: "0.0.0.0:0".parse().unwrap(),
+            ..config(None)
+        };
+        assert!(serve_admin(levels.clone(), &open, core.remote(), no_bridge()).is_err());
+        let config = config(Some("secret"));
+        let server = serve_admin(levels, &config, core.remote(), no_bridge()).unwrap();
+        let url = format!("http://{}", server.address());
+        let client = reqwest::Client::new();
+        let body = r#"{"jsonrpc":"2.0","method":"get-log-level","params":[],"id":1}"#;
+
+        let response = client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .unwrap();
+        assert_eq!(401, response.status().as_u16());
+        assert_eq!("Bearer", response.headers()["WWW-Authenticate"]);
+
+        let mut response = client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header("Authorization", "Bearer secret")
+            .body(body)
+            .send()
+            .unwrap();
+        assert_eq!(200, response.status().as_u16());
+        let mut content = String::new();
+        response.read_to_string(&mut content).unwrap();
+        let response: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json!({ "filter": "warn", "overrides": [] }), response["result"]);
+    }
+
+    #[test]
+    fn test_circuit_methods_send_commands_to_the_bridge() {
+        let core = Core::new().unwrap();
+        let (commands, receiver) = command_channel();
+        bridge(receiver);
+        let io = admin_handler(LogLevels::new("info"), &config(None), core.remote(), commands);
+
+        assert_eq!(
+            json!("ForceOpenCircuit(WithdrawConfirm)"),
+            call(&io, "force-open-circuit", r#"["withdraw_confirm"]"#)["result"]
+        );
+        assert_eq!(
+            json!("ForceCloseCircuit(DepositRelay)"),
+            call(&io, "force-close-circuit", r#"["deposit_relay"]"#)["result"]
+        );
+        for invalid in &[r#"[]"#, r#"["relay"]"#, r#"["deposit_relay", "withdraw_relay"]"#] {
+            assert_eq!(-32602, call(&io, "force-open-circuit", invalid)["error"]["code"]);
+        }
+    }
+
+    #[test]
+    fn test_spend_limit_methods_send_commands_to_the_bridge() {
+        let core = Core::new().unwrap();
+        let (commands, receiver) = command_channel();
+        bridge(receiver);
+        let io = admin_handler(LogLevels::new("info"), &config(None), core.remote(), commands);
+
+        assert_eq!(
+            json!("SpendLimit(Withdraws)"),
+            call(&io, "get-spend-limit", r#"["withdraws"]"#)["result"]
+        );
+        assert_eq!(
+            json!("SetSpendLimit(Deposits, 1000000000000000000)"),
+            call(&io, "set-spend-limit", r#"["deposits", "1000000000000000000"]"#)["result"]
+        );
+        let key = "0x000000000000000000000000000000000000000000000000000000000000002a";
+        assert_eq!(
+            json!(format!("ReleaseDeferred(Deposits, {})", &key[2..])),
+            call(&io, "release-deferred", &format!(r#"["deposits", "{}"]"#, key))["result"]
+        );
+        let invalid = [
+            ("get-spend-limit", r#"["deposit"]"#),
+            ("get-spend-limit", r#"["deposits", "1"]"#),
+            ("set-spend-limit", r#"["deposits"]"#),
+            ("set-spend-limit", r#"["deposits", "1 ether"]"#),
+            ("release-deferred", r#"["withdraws", "0x2a"]"#),
+        ];
+        for &(method, params) in &invalid {
+            assert_eq!(-32602, call(&io, method, params)["error"]["code"]);
+        }
+    }
+
+    #[test]
+    fn test_release_held_deposit_sends_a_command_to_the_bridge() {
+        let core = Core::new().unwrap();
+        let (commands, receiver) = command_channel();
+        bridge(receiver);
+        let io = admin_handler(LogLevels::new("info"), &config(None), core.remote(), commands);
+        let key = "0x000000000000000000000000000000000000000000000000000000000000002a";
+        assert_eq!(
+            json!(format!("ReleaseHeldDeposit({})", &key[2..])),
+            call(&io, "release-held-deposit", &format!(r#"["{}"]"#, key))["result"]
+        );
+        let two_keys = format!(r#"["{}", "{}"]"#, key, key);
+        for invalid in &[r#"[]"#, r#"["0x2a"]"#, two_keys.as_str()] {
+            assert_eq!(-32602, call(&io, "release-held-deposit", invalid)["error"]["code"]);
+        }
+    }
+
+    #[test]
+    fn test_drain_sends_a_command_to_the_bridge() {
+        let core = Core::new().unwrap();
+        let (commands, receiver) = command_channel();
+        bridge(receiver);
+        let io = admin_handler(LogLevels::new("info"), &config(None), core.remote(), commands);
+        assert_eq!(json!("Drain"), call(&io, "drain", "[]")["result"]);
+        assert_eq!(-32602, call(&io, "drain", r#"["now"]"#)["error"]["code"]);
+    }
+
+    #[test]
+    fn test_acknowledge_deep_reorg_sends_a_command_to_the_bridge() {
+        let core = Core::new().unwrap();
+        let (commands, receiver) = command_channel();
+        bridge(receiver);
+        let io = admin_handler(LogLevels::new("info"), &config(None), core.remote(), commands);
+        assert_eq!(
+            json!("AcknowledgeDeepReorg"),
+            call(&io, "acknowledge-deep-reorg", "[]")["result"]
+        );
+        assert_eq!(-32602, call(&io, "acknowledge-deep-reorg", "[1]")["error"]["code"]);
+    }
+
+    /// `SignedParams` of `method` with `params` signed by the `secrets`.
+    fn signed(method: &str, params: serde_json::Value, nonce: u64, secrets: &[&str]) -> String {
+        let params = params.as_array().unwrap().clone();
+        let signatures = secrets
+            .iter()
+            .map(|secret| sign_command(secret, method, &params, nonce).unwrap())
+            .collect::<Vec<_>>();
+        json!({ "params": params, "nonce": nonce, "signatures": signatures }).to_string()
+    }
+
+    #[test]
+    fn test_signed_commands_need_the_signatures_of_operators() {
+        let core = Core::new().unwrap();
+        let (commands, receiver) = command_channel();
+        bridge(receiver);
+        let config = AdminConfig {
+            signed_commands: Some(SignedCommandsConfig {
+                operators: vec![
+                    "7e5f4552091a69125d5dfcb7b8c2659029395bdf".parse().unwrap(),
+                    "2b5ad5c4795c026514f8317c7a215e218dccd6cf".parse().unwrap(),
+                ],
+                severity: CommandSeverity::Control,
+                critical_signatures: 2,
+            }),
+            ..config(None)
+        };
+        let io = admin_handler(LogLevels::new("info"), &config, core.remote(), commands);
+
+        // reading needs no signature
+        assert_eq!(json!("RecentErrors"), call(&io, "get-errors", "[]")["result"]);
+        let response = call(&io, "drain", "[]");
+        assert_eq!(-32001, response["error"]["code"]);
+        assert_eq!(
+            "drain needs 1 operator signatures, see --admin-sign",
+            response["error"]["message"]
+        );
+        assert_eq!(
+            json!("Drain"),
+            call(&io, "drain", &signed("drain", json!([]), 1, &[ONE]))["result"]
+        );
+
+        let close = json!(["deposit_relay"]);
+        let once = signed("force-close-circuit", close.clone(), 2, &[ONE, ONE]);
+        let response = call(&io, "force-close-circuit", &once);
+        assert_eq!(
+            "force-close-circuit needs 2 operator signatures, got 1",
+            response["error"]["message"]
+        );
+        let twice = signed("force-close-circuit", close.clone(), 3, &[ONE, TWO]);
+        assert_eq!(
+            json!("ForceCloseCircuit(DepositRelay)"),
+            call(&io, "force-close-circuit", &twice)["result"]
+        );
+        // signed for another method
+        let response = call(&io, "force-open-circuit", &signed("drain", close, 4, &[ONE]));
+        assert_eq!(-32001, response["error"]["code"]);
+
+        let level = json!(["debug", "bridge::deposit_relay"]);
+        assert_eq!(-32001, call(&io, "set-log-level", &level.to_string())["error"]["code"]);
+        let response = call(&io, "set-log-level", &signed("set-log-level", level, 5, &[TWO]));
+        assert_eq!(
+            json!("info,bridge::deposit_relay=debug"),
+            response["result"]["filter"]
+        );
+    }
+
+    #[test]
+    fn test_commands_fail_without_a_bridge() {
+        let core = Core::new().unwrap();
+        let io = admin_handler(LogLevels::new("info"), &config(None), core.remote(), no_bridge());
+        let response = call(&io, "force-close-circuit", r#"["withdraw_relay"]"#);
+        assert_eq!(-32000, response["error"]["code"]);
+        assert_eq!("the bridge stopped", response["error"]["message"]);
+    }
+}
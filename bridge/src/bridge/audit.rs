@@ -0,0 +1,538 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use serde_json;
+use web3::Transport;
+use web3::types::{Address, H256, U256};
+use app::App;
+use config::Config;
+use contracts::{foreign, home};
+use database::Database;
+use error::{Error, Result};
+use message_to_mainnet::MessageToMainnet;
+use units::{checked_add, format_ether};
+use util::{log_id, log_transaction_hash, raw_log};
+use super::rebuild::{scan_logs, ScanLogs, ScannedLogs};
+
+/// A deposit to the audited address on home and its execution on foreign.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditedDeposit {
+    /// home transaction that emitted `HomeBridge.Deposit`
+    pub home_transaction: H256,
+    /// value on home
+    pub value: U256,
+    /// value `ForeignBridge.deposit` must mint. `None` if the bridge doesn't relay the deposit
+    pub expected_value: Option<U256>,
+    /// foreign transaction that emitted `ForeignBridge.Deposit`. `None` while pending relay
+    pub foreign_transaction: Option<H256>,
+    /// value minted on foreign
+    pub minted: Option<U256>,
+}
+
+/// A withdraw to the audited address on foreign and its execution on home.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditedWithdraw {
+    /// foreign transaction that emitted `ForeignBridge.Withdraw`
+    pub foreign_transaction: H256,
+    /// value on foreign
+    pub value: U256,
+    /// value on home before the relay cost. `None` if `value_scale` can't convert it
+    pub expected_value: Option<U256>,
+    /// home transaction that emitted `HomeBridge.Withdraw`. `None` while pending relay
+    pub home_transaction: Option<H256>,
+    /// value paid out on home, less the relay cost
+    pub paid_out: Option<U256>,
+}
+
+/// Reconciliation of the deposits and withdraws of one address on both chains.
+/// values are in units of the chain they were emitted on.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Audit {
+    pub recipient: Address,
+    /// last scanned home block
+    pub home_block: u64,
+    /// last scanned foreign block
+    pub foreign_block: u64,
+    /// sum of `HomeBridge.Deposit` values
+    pub deposited: U256,
+    /// sum of `ForeignBridge.Deposit` values
+    pub minted: U256,
+    /// sum of `ForeignBridge.Withdraw` values
+    pub withdrawn: U256,
+    /// sum of `HomeBridge.Withdraw` values
+    pub paid_out: U256,
+    /// relay costs subtracted from executed withdraws on home
+    pub relay_costs: U256,
+    /// `deposited - paid_out - relay_costs` on home, signed
+    pub net_position: String,
+    pub deposits: Vec<AuditedDeposit>,
+    pub withdraws: Vec<AuditedWithdraw>,
+    /// differences the relay cost and `value_scale` don't explain
+    pub discrepancies: Vec<String>,
+}
+
+impl Audit {
+    /// deposits the bridge relays that aren't executed on foreign yet.
+    pub fn pending_deposits(&self) -> Vec<&AuditedDeposit> {
+        self.deposits
+            .iter()
+            .filter(|deposit| {
+                deposit.expected_value.is_some() && deposit.foreign_transaction.is_none()
+            })
+            .collect()
+    }
+
+    /// withdraws that aren't executed on home yet.
+    pub fn pending_withdraws(&self) -> Vec<&AuditedWithdraw> {
+        self.withdraws
+            .iter()
+            .filter(|withdraw| {
+                withdraw.expected_value.is_some() && withdraw.home_transaction.is_none()
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("audit serializes to json; qed")
+    }
+}
+
+impl fmt::Display for Audit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "audit of {:?} up to home block {} and foreign block {}",
+            self.recipient, self.home_block, self.foreign_block
+        )?;
+        writeln!(f, "deposited on home: {}", format_ether(self.deposited, 0))?;
+        writeln!(f, "minted on foreign: {}", self.minted)?;
+        writeln!(f, "withdrawn on foreign: {}", self.withdrawn)?;
+        writeln!(f, "paid out on home: {}", format_ether(self.paid_out, 0))?;
+        writeln!(f, "relay costs: {}", format_ether(self.relay_costs, 0))?;
+        writeln!(f, "net position on home: {}", signed_ether(&self.net_position))?;
+        for deposit in self.pending_deposits() {
+            writeln!(
+                f,
+                "deposit of {} in home transaction {:?} pending relay",
+                format_ether(deposit.value, 0),
+                deposit.home_transaction
+            )?;
+        }
+        for withdraw in self.pending_withdraws() {
+            writeln!(
+                f,
+                "withdraw of {} in foreign transaction {:?} pending relay",
+                withdraw.value, withdraw.foreign_transaction
+            )?;
+        }
+        if self.discrepancies.is_empty() {
+            write!(f, "no discrepancies")
+        } else {
+            write!(f, "discrepancies:\n{}", self.discrepancies.join("\n"))
+        }
+    }
+}
+
+/// `a - b` as a signed decimal.
+fn signed_difference(a: U256, b: U256) -> String {
+    if a >= b {
+        (a - b).to_string()
+    } else {
+        format!("-{}", b - a)
+    }
+}
+
+/// signed decimal of `signed_difference` in ether.
+fn signed_ether(signed: &str) -> String {
+    let (sign, digits) = if signed.starts_with('-') {
+        ("-", &signed[1..])
+    } else {
+        ("", signed)
+    };
+    match U256::from_dec_str(digits) {
+        Ok(wei) => format!("{}{}", sign, format_ether(wei, 0)),
+        Err(_) => signed.to_owned(),
+    }
+}
+
+/// `total + value` of the sum of the values `what`. fails if it overflows.
+fn sum(total: U256, value: U256, what: &str) -> Result<U256> {
+    match checked_add(total, value) {
+        Some(total) => Ok(total),
+        None => bail!("sum of the values {} overflows", what),
+    }
+}
+
+/// reconciles the deposits and withdraws of `recipient` in `logs`.
+///
+/// deposits are matched to `ForeignBridge.Deposit` by their id, withdraws to `HomeBridge.Withdraw`
+/// by the id of the withdraw, see `util::log_id`.
+/// an executed deposit must mint exactly its value converted by `value_scale`.
+/// an executed withdraw pays out its converted value less the relay cost, so it may pay out less.
+/// `home_block` and `foreign_block` are the last scanned blocks.
+/// fails if a sum doesn't fit `U256`.
+pub fn audit(
+    config: &Config,
+    home: &home::HomeBridge,
+    foreign: &foreign::ForeignBridge,
+    recipient: Address,
+    logs: &ScannedLogs,
+    home_block: u64,
+    foreign_block: u64,
+) -> Result<Audit> {
+    let unique_ids = config.unique_log_ids;
+    let scale = config.value_scale.as_ref();
+    let mut discrepancies = Vec::new();
+
+    // id of the deposit -> (foreign transaction, minted value)
+    let mut executed_deposits = HashMap::new();
+    let mut minted = U256::zero();
+    for log in &logs.foreign_deposits {
+        let event = foreign.events().deposit().parse_log(raw_log(log))?;
+        if event.recipient != recipient {
+            continue;
+        }
+        minted = sum(minted, event.value, "minted on foreign")?;
+        executed_deposits.insert(
+            H256::from(event.transaction_hash),
+            (log_transaction_hash(log)?, event.value),
+        );
+    }
+
+    let mut deposited = U256::zero();
+    let mut matched = HashSet::new();
+    let mut deposits = Vec::new();
+    for log in &logs.home_deposits {
+        let event = home.events().deposit().parse_log(raw_log(log))?;
+        if event.recipient != recipient {
+            continue;
+        }
+        deposited = sum(deposited, event.value, "deposited on home")?;
+        let id = log_id(log, unique_ids)?;
+        let relayed = !event.value.is_zero() || config.relay_zero_value;
+        let expected_value = match scale {
+            _ if !relayed => None,
+            Some(scale) => scale.deposit(event.value).ok().map(|scaled| scaled.value),
+            None => Some(event.value),
+        };
+        let executed = executed_deposits.get(&id).cloned();
+        if let Some((foreign_transaction, value)) = executed {
+            matched.insert(id);
+            if expected_value != Some(value) {
+                discrepancies.push(format!(
+                    "deposit of {} in home transaction {:?} minted {} in foreign transaction {:?}, expected {}",
+                    event.value,
+                    log_transaction_hash(log)?,
+                    value,
+                    foreign_transaction,
+                    expected_value.map_or("none".to_owned(), |value| value.to_string())
+                ));
+            }
+        }
+        deposits.push(AuditedDeposit {
+            home_transaction: log_transaction_hash(log)?,
+            value: event.value,
+            expected_value,
+            foreign_transaction: executed.map(|(transaction, _)| transaction),
+            minted: executed.map(|(_, value)| value),
+        });
+    }
+    for (id, &(foreign_transaction, value)) in &executed_deposits {
+        if !matched.contains(id) {
+            discrepancies.push(format!(
+                "{} minted in foreign transaction {:?} without a deposit on home. deposit id {:?}",
+                value, foreign_transaction, id
+            ));
+        }
+    }
+
+    // id of the withdraw -> (home transaction, paid out value)
+    let mut executed_withdraws = HashMap::new();
+    let mut paid_out = U256::zero();
+    for log in &logs.home_withdraws {
+        let event = home.events().withdraw().parse_log(raw_log(log))?;
+        if event.recipient != recipient {
+            continue;
+        }
+        paid_out = sum(paid_out, event.value, "paid out on home")?;
+        executed_withdraws.insert(
+            H256::from(event.transaction_hash),
+            (log_transaction_hash(log)?, event.value),
+        );
+    }
+
+    let mut withdrawn = U256::zero();
+    let mut relay_costs = U256::zero();
+    let mut matched = HashSet::new();
+    let mut withdraws = Vec::new();
+    for log in &logs.foreign_withdraws {
+        let message = MessageToMainnet::from_log(log.clone(), unique_ids)?;
+        if message.recipient != recipient {
+            continue;
+        }
+        withdrawn = sum(withdrawn, message.value, "withdrawn on foreign")?;
+        let id = message.sidenet_transaction_hash;
+        let expected_value = match scale {
+            Some(scale) => scale.withdraw(message.value).ok().map(|scaled| scaled.value),
+            None => Some(message.value),
+        };
+        let executed = executed_withdraws.get(&id).cloned();
+        if let Some((home_transaction, value)) = executed {
+            matched.insert(id);
+            match expected_value {
+                Some(expected) if value <= expected => {
+                    relay_costs = sum(relay_costs, expected - value, "relay costs")?
+                }
+                _ => discrepancies.push(format!(
+                    "withdraw of {} in foreign transaction {:?} paid out {} in home transaction {:?}, expected at most {}",
+                    message.value,
+                    log_transaction_hash(log)?,
+                    value,
+                    home_transaction,
+                    expected_value.map_or("none".to_owned(), |value| value.to_string())
+                )),
+            }
+        }
+        withdraws.push(AuditedWithdraw {
+            foreign_transaction: log_transaction_hash(log)?,
+            value: message.value,
+            expected_value,
+            home_transaction: executed.map(|(transaction, _)| transaction),
+            paid_out: executed.map(|(_, value)| value),
+        });
+    }
+    for (id, &(home_transaction, value)) in &executed_withdraws {
+        if !matched.contains(id) {
+            discrepancies.push(format!(
+                "{} paid out in home transaction {:?} without a withdraw on foreign. withdraw id {:?}",
+                value, home_transaction, id
+            ));
+        }
+    }
+    // hash map order
+    discrepancies.sort();
+    let spent = sum(paid_out, relay_costs, "paid out and relay costs")?;
+
+    Ok(Audit {
+        recipient,
+        home_block,
+        foreign_block,
+        deposited,
+        minted,
+        withdrawn,
+        paid_out,
+        relay_costs,
+        net_position: signed_difference(deposited, spent),
+        deposits,
+        withdraws,
+        discrepancies,
+    })
+}
+
+/// audits the deposits and withdraws of `recipient` in the logs of both chains
+/// since the deployment of the contracts in `init`.
+pub fn create_audit<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+    recipient: Address,
+) -> AuditRecipient<T> {
+    AuditRecipient {
+        scan: scan_logs(app.clone(), init),
+        app,
+        recipient,
+    }
+}
+
+/// Future resolving to the audit of an address.
+pub struct AuditRecipient<T: Transport> {
+    app: Arc<App<T>>,
+    scan: ScanLogs<T>,
+    recipient: Address,
+}
+
+impl<T: Transport> Future for AuditRecipient<T> {
+    type Item = Audit;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (logs, home_block, foreign_block) = try_ready!(self.scan.poll());
+        let audit = audit(
+            &self.app.config,
+            &self.app.home_bridge,
+            &self.app.foreign_bridge,
+            self.recipient,
+            &logs,
+            home_block,
+            foreign_block,
+        )?;
+        Ok(Async::Ready(audit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, Value};
+    use web3::types::{Address, H256, Log, U256};
+    use config::{Authorities, Config, Node};
+    use contracts::{foreign, home};
+    use super::audit;
+    use super::super::ScannedLogs;
+
+    const HOME_DEPOSIT: &str = "e1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c";
+    const HOME_WITHDRAW: &str = "4d911754a3efbbc2e0463de4f6bff32ed24421d1c89c11dce59a4935f327afff";
+    const FOREIGN_DEPOSIT: &str =
+        "1a771fe656018364a9369da21954bb3081cb08b0196c27e43ca59c7cae872737";
+    const FOREIGN_WITHDRAW: &str =
+        "f279e6a1f5e320cca91135676d9cb6e44ca8a08c0b88342bcdb1144f6511b568";
+
+    fn address_word(address: Address) -> H256 {
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(&address.0);
+        word.into()
+    }
+
+    fn value_word(value: u64) -> H256 {
+        let mut word = [0u8; 32];
+        U256::from(value).to_big_endian(&mut word);
+        word.into()
+    }
+
+    fn log(topic: &str, words: Vec<H256>, block: u64, transaction: u64) -> Log {
+        Log {
+            topics: vec![topic.parse().unwrap()],
+            data: words
+                .iter()
+                .flat_map(|word| word.0.to_vec())
+                .collect::<Vec<_>>()
+                .into(),
+            block_number: Some(block.into()),
+            transaction_hash: Some(transaction.into()),
+            log_index: Some(0.into()),
+            ..Default::default()
+        }
+    }
+
+    fn config() -> Config {
+        let authority: Address = 0xaa.into();
+        Config::builder()
+            .home(Node::new(authority, "/home.ipc".into()))
+            .foreign(Node::new(authority, "/foreign.ipc".into()))
+            .authorities(Authorities {
+                accounts: vec![authority],
+                required_signatures: 1,
+            })
+            .estimated_gas_cost_of_withdraw(100000u64.into())
+            .max_total_home_contract_balance(0u64.into())
+            .max_single_deposit_value(0u64.into())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_audit() {
+        let config = config();
+        let recipient = address_word(1.into());
+        let other = address_word(2.into());
+
+        let logs = ScannedLogs {
+            // the first deposit is executed, the second is pending, the third is another address
+            home_deposits: vec![
+                log(HOME_DEPOSIT, vec![recipient, value_word(100)], 11, 1),
+                log(HOME_DEPOSIT, vec![recipient, value_word(50)], 15, 2),
+                log(HOME_DEPOSIT, vec![other, value_word(70)], 16, 3),
+            ],
+            foreign_deposits: vec![
+                log(FOREIGN_DEPOSIT, vec![recipient, value_word(100), 1.into()], 21, 101),
+                log(FOREIGN_DEPOSIT, vec![other, value_word(70), 3.into()], 22, 102),
+            ],
+            // the first withdraw is executed less the relay cost, the second is pending
+            foreign_withdraws: vec![
+                log(FOREIGN_WITHDRAW, vec![recipient, value_word(60), value_word(10)], 23, 4),
+                log(FOREIGN_WITHDRAW, vec![recipient, value_word(20), value_word(10)], 24, 5),
+            ],
+            home_withdraws: vec![
+                log(HOME_WITHDRAW, vec![recipient, value_word(55), 4.into()], 17, 103),
+            ],
+            collected_signatures: Vec::new(),
+        };
+
+        let home = home::HomeBridge::default();
+        let foreign = foreign::ForeignBridge::default();
+        let audit = audit(&config, &home, &foreign, 1.into(), &logs, 50, 60).unwrap();
+        assert_eq!(U256::from(150), audit.deposited);
+        assert_eq!(U256::from(100), audit.minted);
+        assert_eq!(U256::from(80), audit.withdrawn);
+        assert_eq!(U256::from(55), audit.paid_out);
+        assert_eq!(U256::from(5), audit.relay_costs);
+        assert_eq!("90", audit.net_position);
+        assert_eq!(
+            vec![H256::from(2)],
+            audit
+                .pending_deposits()
+                .iter()
+                .map(|deposit| deposit.home_transaction)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![H256::from(5)],
+            audit
+                .pending_withdraws()
+                .iter()
+                .map(|withdraw| withdraw.foreign_transaction)
+                .collect::<Vec<_>>()
+        );
+        assert!(audit.discrepancies.is_empty(), "{:?}", audit.discrepancies);
+
+        // a mint without deposit, a deposit minting the wrong value
+        // and a withdraw paying out more than withdrawn
+        let logs = ScannedLogs {
+            foreign_deposits: vec![
+                log(FOREIGN_DEPOSIT, vec![recipient, value_word(90), 1.into()], 21, 101),
+                log(FOREIGN_DEPOSIT, vec![recipient, value_word(10), 9.into()], 22, 104),
+            ],
+            home_withdraws: vec![
+                log(HOME_WITHDRAW, vec![recipient, value_word(61), 4.into()], 17, 103),
+            ],
+            ..logs
+        };
+        let audit = audit(&config, &home, &foreign, 1.into(), &logs, 50, 60).unwrap();
+        assert_eq!(3, audit.discrepancies.len(), "{:?}", audit.discrepancies);
+        assert_eq!("89", audit.net_position);
+        assert_eq!("-20", super::signed_difference(10.into(), 30.into()));
+        assert_eq!("-0.00000000000000002 ETH", super::signed_ether("-20"));
+        assert_eq!("0.000000000000000089 ETH", super::signed_ether("89"));
+    }
+
+    #[test]
+    fn test_audit_of_max_values() {
+        let config = config();
+        let recipient = address_word(1.into());
+        let max = H256::from([0xff; 32]);
+        let mut logs = ScannedLogs {
+            home_deposits: vec![log(HOME_DEPOSIT, vec![recipient, max], 11, 1)],
+            foreign_deposits: Vec::new(),
+            foreign_withdraws: Vec::new(),
+            home_withdraws: Vec::new(),
+            collected_signatures: Vec::new(),
+        };
+        let home = home::HomeBridge::default();
+        let foreign = foreign::ForeignBridge::default();
+
+        // the json keeps every digit of the value
+        let audit = audit(&config, &home, &foreign, 1.into(), &logs, 50, 60).unwrap();
+        assert_eq!(U256::max_value(), audit.deposited);
+        let json: Value = serde_json::from_str(&audit.to_json()).unwrap();
+        assert_eq!(json!(format!("0x{}", "f".repeat(64))), json["deposited"]);
+        assert_eq!(
+            U256::max_value(),
+            serde_json::from_value::<U256>(json["deposited"].clone()).unwrap()
+        );
+
+        // sums that don't fit are an error instead of a panic
+        logs.home_deposits
+            .push(log(HOME_DEPOSIT, vec![recipient, value_word(1)], 12, 2));
+        let err = audit(&config, &home, &foreign, 1.into(), &logs, 50, 60).unwrap_err();
+        assert_eq!("sum of the values deposited on home overflows", err.to_string());
+    }
+}
@@ -0,0 +1,600 @@
+use std::mem;
+use std::sync::Arc;
+use futures::{Async, Future, Poll, Stream};
+use futures::future::{join_all, Join, JoinAll};
+use timer::Interval;
+use web3;
+use web3::Transport;
+use web3::types::{Address, Bytes, U256};
+use api::{self, ApiCall, Settle, Timeout};
+use app::App;
+use database::Database;
+use error::{Error, ErrorKind};
+use super::Component;
+use super::revert::{revert_reason_in_error, RevertKind};
+
+/// number of `authorities(i)` getters called at once.
+const AUTHORITIES_PAGE_SIZE: u64 = 16;
+
+/// contracts listing more authorities than this are considered broken.
+const MAX_AUTHORITIES: u64 = 1024;
+
+/// Authorities and signature threshold of a bridge contract.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthoritySet {
+    /// in the order of the contract
+    pub authorities: Vec<Address>,
+    pub required_signatures: U256,
+}
+
+/// Differences between the authorities of `HomeBridge` and `ForeignBridge`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct AuthorityDiff {
+    /// authorities of `HomeBridge` missing from `ForeignBridge`
+    pub home_only: Vec<Address>,
+    /// authorities of `ForeignBridge` missing from `HomeBridge`
+    pub foreign_only: Vec<Address>,
+    /// `requiredSignatures` of home and foreign if they differ
+    pub required_signatures: Option<(U256, U256)>,
+}
+
+impl AuthorityDiff {
+    pub fn new(home: &AuthoritySet, foreign: &AuthoritySet) -> Self {
+        let missing = |from: &AuthoritySet, of: &AuthoritySet| -> Vec<Address> {
+            of.authorities
+                .iter()
+                .filter(|authority| !from.authorities.contains(authority))
+                .cloned()
+                .collect()
+        };
+        AuthorityDiff {
+            home_only: missing(foreign, home),
+            foreign_only: missing(home, foreign),
+            required_signatures: if home.required_signatures == foreign.required_signatures {
+                None
+            } else {
+                Some((home.required_signatures, foreign.required_signatures))
+            },
+        }
+    }
+
+    /// `true` if both contracts have the same authorities and `requiredSignatures`.
+    pub fn is_consistent(&self) -> bool {
+        self.home_only.is_empty() && self.foreign_only.is_empty()
+            && self.required_signatures.is_none()
+    }
+
+    pub fn description(&self) -> String {
+        let mut differences = Vec::new();
+        if !self.home_only.is_empty() {
+            differences.push(format!("only HomeBridge lists {:?}", self.home_only));
+        }
+        if !self.foreign_only.is_empty() {
+            differences.push(format!("only ForeignBridge lists {:?}", self.foreign_only));
+        }
+        if let Some((home, foreign)) = self.required_signatures {
+            differences.push(format!(
+                "HomeBridge requires {} signatures, ForeignBridge requires {}",
+                home, foreign
+            ));
+        }
+        differences.join(". ")
+    }
+}
+
+/// `true` if `err` is a failure of a transaction to foreign because the account
+/// isn't an authority of `ForeignBridge`: a relay that reverted for it, see
+/// `record_event_checks`, or a call the node refused with such a revert reason.
+pub fn is_not_an_authority(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::NotAnAuthority(_) => true,
+        _ => RevertKind::of(revert_reason_in_error(err).as_ref().map(String::as_str))
+            == RevertKind::NotAuthority,
+    }
+}
+
+/// How it was found that the account isn't an authority.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Detection {
+    /// a transaction of a component reverted
+    Revert,
+    /// the authorities of `ForeignBridge` don't list it
+    AuthorityList,
+}
+
+/// The account of the bridge isn't an authority of `ForeignBridge`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NotAnAuthority {
+    pub account: Address,
+    pub detection: Detection,
+    /// error or check it was detected by
+    pub reason: String,
+}
+
+impl NotAnAuthority {
+    pub fn description(&self) -> String {
+        format!(
+            "{:?} is not an authority of ForeignBridge ({:?}: {})",
+            self.account, self.detection, self.reason
+        )
+    }
+}
+
+/// Whether the account of the bridge may send transactions to `ForeignBridge`.
+/// while it isn't an authority the components sending to foreign are suspended,
+/// until the authorities of the contract list it again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Authorization {
+    account: Address,
+    /// `None` as long as nothing says the account isn't an authority
+    revoked: Option<NotAnAuthority>,
+    /// `false` until `revoked` is taken by `take_unreported`
+    reported: bool,
+    /// components that failed because the account isn't an authority.
+    /// they are rebuilt once it's an authority again
+    failed: Vec<Component>,
+}
+
+impl Authorization {
+    pub fn new(account: Address) -> Self {
+        Authorization {
+            account,
+            revoked: None,
+            reported: false,
+            failed: Vec::new(),
+        }
+    }
+
+    pub fn revoked(&self) -> Option<&NotAnAuthority> {
+        self.revoked.as_ref()
+    }
+
+    /// `true` while the account isn't an authority and `component` sends to foreign.
+    pub fn suspends(&self, component: Component) -> bool {
+        self.revoked.is_some() && component != Component::WithdrawRelay
+    }
+
+    fn revoke(&mut self, detection: Detection, reason: String) {
+        if self.revoked.is_none() {
+            self.revoked = Some(NotAnAuthority {
+                account: self.account,
+                detection,
+                reason,
+            });
+            self.reported = false;
+        }
+    }
+
+    /// records the failure of `component` with `err` if it sends to foreign and
+    /// `is_not_an_authority(err)`. returns `false` for any other failure.
+    pub fn record_failure(&mut self, component: Component, err: &Error) -> bool {
+        if component == Component::WithdrawRelay || !is_not_an_authority(err) {
+            return false;
+        }
+        if !self.failed.contains(&component) {
+            self.failed.push(component);
+        }
+        self.revoke(Detection::Revert, err.to_string());
+        true
+    }
+
+    /// checks the account against the authorities of `ForeignBridge`.
+    /// returns the components to rebuild if it's an authority again.
+    pub fn check(&mut self, foreign: &AuthoritySet) -> Option<Vec<Component>> {
+        if !foreign.authorities.contains(&self.account) {
+            self.revoke(
+                Detection::AuthorityList,
+                format!("{} authorities don't list it", foreign.authorities.len()),
+            );
+            return None;
+        }
+        self.revoked.take()?;
+        Some(mem::replace(&mut self.failed, Vec::new()))
+    }
+
+    /// the revocation once, to report it.
+    pub fn take_unreported(&mut self) -> Option<&NotAnAuthority> {
+        if self.reported {
+            return None;
+        }
+        self.reported = true;
+        self.revoked.as_ref()
+    }
+}
+
+/// `true` if `err` is the node failing the `authorities(i)` call
+/// because `i` is past the end of the array.
+fn is_out_of_range(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::Web3(web3::Error::Rpc(_)) => true,
+        _ => false,
+    }
+}
+
+type PageFuture<T> = JoinAll<Vec<Settle<Timeout<ApiCall<Bytes, <T as Transport>::Out>>>>>;
+
+/// Reads all authorities and `requiredSignatures` of a bridge contract.
+///
+/// the contracts only have the `authorities(i)` getter of `address[] public authorities`.
+/// it's called for pages of indexes until a call fails or returns nothing,
+/// which is what the getter does past the end of the array.
+pub struct FetchAuthoritySet<T: Transport> {
+    app: Arc<App<T>>,
+    on_home: bool,
+    contract: Address,
+    required_signatures: Option<U256>,
+    fetch_required_signatures: Timeout<ApiCall<Bytes, T::Out>>,
+    authorities: Vec<Address>,
+    page: PageFuture<T>,
+}
+
+pub fn fetch_authority_set<T: Transport>(
+    app: Arc<App<T>>,
+    on_home: bool,
+    contract: Address,
+) -> FetchAuthoritySet<T> {
+    let payload = if on_home {
+        app.home_bridge.functions().required_signatures().input()
+    } else {
+        app.foreign_bridge
+            .functions()
+            .required_signatures()
+            .input()
+    };
+    let fetch_required_signatures = call(&app, on_home, contract, payload.into());
+    let page = request_page(&app, on_home, contract, 0);
+    FetchAuthoritySet {
+        app,
+        on_home,
+        contract,
+        required_signatures: None,
+        fetch_required_signatures,
+        authorities: Vec::new(),
+        page,
+    }
+}
+
+fn call<T: Transport>(
+    app: &App<T>,
+    on_home: bool,
+    contract: Address,
+    payload: Bytes,
+) -> Timeout<ApiCall<Bytes, T::Out>> {
+    if on_home {
+        api::timeout(
+            &app.timer,
+            api::call(&app.connections.home, contract, payload),
+            "home",
+            app.config.home.request_timeout,
+        )
+    } else {
+        api::timeout(
+            &app.timer,
+            api::call(&app.connections.foreign, contract, payload),
+            "foreign",
+            app.config.foreign.request_timeout,
+        )
+    }
+}
+
+/// calls `authorities(i)` for the page starting at index `start`.
+fn request_page<T: Transport>(
+    app: &App<T>,
+    on_home: bool,
+    contract: Address,
+    start: u64,
+) -> PageFuture<T> {
+    let calls = (start..start + AUTHORITIES_PAGE_SIZE)
+        .map(|index| {
+            let payload = if on_home {
+                app.home_bridge
+                    .functions()
+                    .authorities()
+                    .input(U256::from(index))
+            } else {
+                app.foreign_bridge
+                    .functions()
+                    .authorities()
+                    .input(U256::from(index))
+            };
+            api::settle(call(app, on_home, contract, payload.into()))
+        })
+        .collect();
+    join_all(calls)
+}
+
+impl<T: Transport> FetchAuthoritySet<T> {
+    fn decode_required_signatures(&self, output: &[u8]) -> Result<U256, Error> {
+        let decoded = if self.on_home {
+            self.app
+                .home_bridge
+                .functions()
+                .required_signatures()
+                .output(output)
+        } else {
+            self.app
+                .foreign_bridge
+                .functions()
+                .required_signatures()
+                .output(output)
+        };
+        decoded.map_err(Error::from)
+    }
+
+    fn decode_authority(&self, output: &[u8]) -> Result<Address, Error> {
+        let decoded = if self.on_home {
+            self.app.home_bridge.functions().authorities().output(output)
+        } else {
+            self.app
+                .foreign_bridge
+                .functions()
+                .authorities()
+                .output(output)
+        };
+        decoded.map_err(Error::from)
+    }
+
+    fn take_set(&mut self) -> AuthoritySet {
+        AuthoritySet {
+            authorities: mem::replace(&mut self.authorities, Vec::new()),
+            required_signatures: self.required_signatures
+                .expect("required signatures are fetched before the authorities; qed"),
+        }
+    }
+}
+
+impl<T: Transport> Future for FetchAuthoritySet<T> {
+    type Item = AuthoritySet;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.required_signatures.is_none() {
+            let output = try_ready!(self.fetch_required_signatures.poll());
+            self.required_signatures = Some(self.decode_required_signatures(&output.0)?);
+        }
+        loop {
+            let results = try_ready!(self.page.poll());
+            for result in results {
+                match result {
+                    Ok(ref output) if !output.0.is_empty() => {
+                        let authority = self.decode_authority(&output.0)?;
+                        self.authorities.push(authority);
+                    }
+                    Ok(_) => return Ok(Async::Ready(self.take_set())),
+                    Err(ref err) if is_out_of_range(err) => {
+                        return Ok(Async::Ready(self.take_set()))
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            let start = self.authorities.len() as u64;
+            if start >= MAX_AUTHORITIES {
+                bail!(
+                    "contract {:?} lists more than {} authorities",
+                    self.contract,
+                    MAX_AUTHORITIES
+                );
+            }
+            self.page = request_page(&self.app, self.on_home, self.contract, start);
+        }
+    }
+}
+
+enum AuthorityCheckState<T: Transport> {
+    /// Waiting for the next check.
+    Wait,
+    /// Fetching the authorities of both contracts.
+    Fetch(Join<FetchAuthoritySet<T>, FetchAuthoritySet<T>>),
+    /// Contracts have been compared.
+    Yield(Option<AuthorityDiff>),
+}
+
+/// Compares the authorities and `requiredSignatures` of `HomeBridge` and `ForeignBridge`
+/// right away and every time `interval` elapses.
+/// differences are logged as `CRITICAL` errors whenever they change.
+pub fn create_authority_monitor<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+    interval: Interval,
+) -> AuthorityMonitor<T> {
+    let mut monitor = AuthorityMonitor {
+        app,
+        home_contract: init.home_contract_address,
+        foreign_contract: init.foreign_contract_address,
+        interval,
+        reported: None,
+        foreign: None,
+        state: AuthorityCheckState::Wait,
+    };
+    monitor.state = AuthorityCheckState::Fetch(monitor.fetch());
+    monitor
+}
+
+pub struct AuthorityMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    home_contract: Address,
+    foreign_contract: Address,
+    interval: Interval,
+    /// differences of the last check
+    reported: Option<AuthorityDiff>,
+    /// authorities of `ForeignBridge` as of the last check
+    foreign: Option<AuthoritySet>,
+    state: AuthorityCheckState<T>,
+}
+
+impl<T: Transport> AuthorityMonitor<T> {
+    /// authorities of `ForeignBridge` as of the last check. `None` until checked.
+    pub fn foreign_authorities(&self) -> Option<&AuthoritySet> {
+        self.foreign.as_ref()
+    }
+
+    fn fetch(&self) -> Join<FetchAuthoritySet<T>, FetchAuthoritySet<T>> {
+        fetch_authority_set(self.app.clone(), true, self.home_contract).join(
+            fetch_authority_set(self.app.clone(), false, self.foreign_contract),
+        )
+    }
+}
+
+/// logs `diff` if it differs from the `reported` one and replaces that.
+fn report(
+    reported: &mut Option<AuthorityDiff>,
+    contracts: (Address, Address),
+    home: &AuthoritySet,
+    diff: &AuthorityDiff,
+) {
+    if diff.is_consistent() {
+        match *reported {
+            None => info!(
+                "HomeBridge and ForeignBridge have the same {} authorities and require {} signatures",
+                home.authorities.len(),
+                home.required_signatures
+            ),
+            Some(ref reported) if !reported.is_consistent() => {
+                info!("authorities of HomeBridge and ForeignBridge are consistent again")
+            }
+            Some(_) => {}
+        }
+    } else if reported.as_ref() != Some(diff) {
+        error!(
+            "CRITICAL: authorities of HomeBridge {:?} and ForeignBridge {:?} are inconsistent: {}",
+            contracts.0,
+            contracts.1,
+            diff.description()
+        );
+    }
+    *reported = Some(diff.clone());
+}
+
+impl<T: Transport> Stream for AuthorityMonitor<T> {
+    type Item = AuthorityDiff;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                AuthorityCheckState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    AuthorityCheckState::Fetch(self.fetch())
+                }
+                AuthorityCheckState::Fetch(ref mut future) => match future.poll() {
+                    Ok(Async::Ready((home, foreign))) => {
+                        let diff = AuthorityDiff::new(&home, &foreign);
+                        report(
+                            &mut self.reported,
+                            (self.home_contract, self.foreign_contract),
+                            &home,
+                            &diff,
+                        );
+                        self.foreign = Some(foreign);
+                        AuthorityCheckState::Yield(Some(diff))
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        warn!(
+                            "cannot compare the authorities of HomeBridge and ForeignBridge: {}",
+                            err
+                        );
+                        AuthorityCheckState::Wait
+                    }
+                },
+                AuthorityCheckState::Yield(ref mut diff) => match diff.take() {
+                    None => AuthorityCheckState::Wait,
+                    some => return Ok(some.into()),
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{Address, H256};
+    use bridge::Component;
+    use error::{Error, ErrorKind};
+    use super::{is_not_an_authority, Authorization, AuthorityDiff, AuthoritySet, Detection};
+
+    fn set(authorities: &[u64], required_signatures: u64) -> AuthoritySet {
+        AuthoritySet {
+            authorities: authorities
+                .iter()
+                .map(|authority| Address::from(*authority))
+                .collect(),
+            required_signatures: required_signatures.into(),
+        }
+    }
+
+    #[test]
+    fn test_authority_diff_consistent() {
+        // the order of the authorities doesn't matter
+        let diff = AuthorityDiff::new(&set(&[1, 2, 3], 2), &set(&[3, 1, 2], 2));
+        assert!(diff.is_consistent());
+        assert_eq!(AuthorityDiff::default(), diff);
+        assert_eq!("", diff.description());
+    }
+
+    #[test]
+    fn test_authority_diff_asymmetric() {
+        let diff = AuthorityDiff::new(&set(&[1, 2, 3], 2), &set(&[1, 2, 4, 5], 3));
+        assert!(!diff.is_consistent());
+        assert_eq!(vec![Address::from(3)], diff.home_only);
+        assert_eq!(vec![Address::from(4), Address::from(5)], diff.foreign_only);
+        assert_eq!(Some((2.into(), 3.into())), diff.required_signatures);
+        let description = diff.description();
+        assert!(description.starts_with("only HomeBridge lists ["));
+        assert!(description.contains(". only ForeignBridge lists ["));
+        assert!(description.ends_with(". HomeBridge requires 2 signatures, ForeignBridge requires 3"));
+
+        // a threshold change alone is inconsistent too
+        let diff = AuthorityDiff::new(&set(&[1, 2], 1), &set(&[1, 2], 2));
+        assert!(!diff.is_consistent());
+        assert!(diff.home_only.is_empty() && diff.foreign_only.is_empty());
+    }
+
+    #[test]
+    fn test_is_not_an_authority() {
+        assert!(is_not_an_authority(&ErrorKind::NotAnAuthority(1.into()).into()));
+        // geth refusing the call in eth_estimateGas
+        let refused: Error = "execution reverted: Only authorities can sign".into();
+        assert!(is_not_an_authority(&refused));
+        let reverted: Error =
+            ErrorKind::RelayReverted(H256::zero(), "insufficient_funds", "".into()).into();
+        assert!(!is_not_an_authority(&reverted));
+        assert!(!is_not_an_authority(&"execution reverted: already signed".into()));
+    }
+
+    #[test]
+    fn test_authorization_suspends_until_listed_again() {
+        let mut authorization = Authorization::new(1.into());
+        let err: Error = ErrorKind::NotAnAuthority(1.into()).into();
+        assert!(!authorization.suspends(Component::DepositRelay));
+        // withdraw relay sends to home, where the account needn't be an authority
+        assert!(!authorization.record_failure(Component::WithdrawRelay, &err));
+        assert!(!authorization.record_failure(Component::DepositRelay, &"timeout".into()));
+        assert_eq!(None, authorization.revoked());
+
+        assert!(authorization.record_failure(Component::WithdrawConfirm, &err));
+        assert!(authorization.suspends(Component::DepositRelay));
+        assert!(authorization.suspends(Component::WithdrawConfirm));
+        assert!(!authorization.suspends(Component::WithdrawRelay));
+        assert_eq!(Detection::Revert, authorization.take_unreported().unwrap().detection);
+        assert_eq!(None, authorization.take_unreported());
+
+        assert_eq!(None, authorization.check(&set(&[2, 3], 1)));
+        // the first detection is kept
+        assert_eq!(Detection::Revert, authorization.revoked().unwrap().detection);
+        assert_eq!(
+            Some(vec![Component::WithdrawConfirm]),
+            authorization.check(&set(&[1, 2, 3], 1))
+        );
+        assert_eq!(None, authorization.revoked());
+        assert_eq!(None, authorization.check(&set(&[1, 2, 3], 1)));
+
+        // removed without a failed relay
+        assert_eq!(None, authorization.check(&set(&[2], 1)));
+        let revoked = authorization.take_unreported().unwrap();
+        assert_eq!(Detection::AuthorityList, revoked.detection);
+        assert_eq!("1 authorities don't list it", revoked.reason);
+    }
+}
@@ -0,0 +1,1004 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use futures::future::Join;
+use rustc_hex::FromHex;
+use tiny_keccak::keccak256;
+use timer::Sleep;
+use toml;
+use web3::Transport;
+use web3::types::{Address, Bytes, H256, TransactionReceipt, TransactionRequest, U256};
+use address::{checksummed, parse_address};
+use api::{self, ApiCall, ReceiptWithStatus, Timeout};
+use app::App;
+use config::{Authorities, Config, Node, Password, Signer};
+use database::Database;
+use error::{Error, Result, ResultExt};
+use units::{format_ether, format_wei, parse_wei};
+use super::authority_check::{fetch_authority_set, AuthoritySet, FetchAuthoritySet};
+use super::deploy_block::chain_name;
+
+/// confirmations of the deployments and fundings unless configured
+const DEFAULT_CONFIRMATIONS: usize = 12;
+
+/// Everything needed to bring up a new bridge, read from a toml plan file.
+///
+/// the deployer deploys both contracts and funds the authorities. the written config is the
+/// one of `account`, the other operators change its accounts to theirs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootstrapPlan {
+    /// authority the config is written for
+    pub account: Address,
+    /// account sending the deployments and fundings on both chains, unlocked or signed
+    /// with `password_file` by the nodes
+    pub deployer: Address,
+    pub password_file: Option<PathBuf>,
+    /// password read from `password_file`
+    pub password: Option<Password>,
+    pub authorities: Vec<Address>,
+    pub required_signatures: u32,
+    pub estimated_gas_cost_of_withdraw: U256,
+    pub max_total_home_contract_balance: U256,
+    pub max_single_deposit_value: U256,
+    /// gas price of the deployments and fundings. `None` lets the nodes price them
+    pub gas_price: Option<U256>,
+    /// wei sent to every authority on foreign to pay for its transactions there
+    pub fund_authorities: Option<U256>,
+    pub home: BootstrapChain,
+    pub foreign: BootstrapChain,
+    /// keccak256 of the plan file. a bootstrap is only continued with the plan it started with
+    pub digest: H256,
+}
+
+/// Node and contract of a chain of a `BootstrapPlan`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootstrapChain {
+    pub ipc: PathBuf,
+    /// compiled contract, written to the config as `contract.bin`
+    pub contract_bin: PathBuf,
+    /// the contract read from `contract_bin`
+    pub bin: Bytes,
+    pub required_confirmations: usize,
+}
+
+impl BootstrapChain {
+    fn from_load_struct(chain: load::BootstrapChain) -> Result<Self> {
+        let mut read = String::new();
+        fs::File::open(&chain.contract_bin)
+            .chain_err(|| format!("Cannot open compiled contract file {:?}", chain.contract_bin))?
+            .read_to_string(&mut read)?;
+        Ok(BootstrapChain {
+            bin: Bytes(read.trim().from_hex()?),
+            required_confirmations: chain
+                .required_confirmations
+                .unwrap_or(DEFAULT_CONFIRMATIONS),
+            ipc: chain.ipc,
+            contract_bin: chain.contract_bin,
+        })
+    }
+
+    fn node(&self, account: Address) -> Node {
+        let mut node = Node {
+            required_confirmations: self.required_confirmations,
+            ..Node::new(account, self.ipc.clone())
+        };
+        node.contract.bin = self.bin.clone();
+        node
+    }
+}
+
+impl BootstrapPlan {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut read = String::new();
+        fs::File::open(path)
+            .chain_err(|| format!("Cannot open bootstrap plan {:?}", path))?
+            .read_to_string(&mut read)?;
+        Self::load_from_str(&read).chain_err(|| format!("Invalid bootstrap plan {:?}", path))
+    }
+
+    pub fn load_from_str(s: &str) -> Result<Self> {
+        let plan: load::BootstrapPlan = toml::from_str(s)?;
+        let u256 = |key: &str, value: &str| {
+            U256::from_dec_str(value).map_err(|_| format!("{} must be a decimal number", key))
+        };
+        let password = match plan.password_file {
+            Some(ref path) => Some(Password::from_file(path)?),
+            None => None,
+        };
+        let authorities = plan.authorities
+            .iter()
+            .map(|authority| parse_address("authorities", authority))
+            .collect::<Result<Vec<_>>>()?;
+        let result = BootstrapPlan {
+            account: parse_address("account", &plan.account)?,
+            deployer: parse_address("deployer", &plan.deployer)?,
+            password_file: plan.password_file,
+            password,
+            authorities,
+            required_signatures: plan.required_signatures,
+            estimated_gas_cost_of_withdraw: u256(
+                "estimated_gas_cost_of_withdraw",
+                &plan.estimated_gas_cost_of_withdraw,
+            )?,
+            max_total_home_contract_balance: u256(
+                "max_total_home_contract_balance",
+                &plan.max_total_home_contract_balance,
+            )?,
+            max_single_deposit_value: u256(
+                "max_single_deposit_value",
+                &plan.max_single_deposit_value,
+            )?,
+            gas_price: match plan.gas_price {
+                Some(ref gas_price) => {
+                    Some(parse_wei(gas_price).chain_err(|| "Invalid gas_price")?)
+                }
+                None => None,
+            },
+            fund_authorities: match plan.fund_authorities {
+                Some(ref value) => {
+                    Some(parse_wei(value).chain_err(|| "Invalid fund_authorities")?)
+                }
+                None => None,
+            },
+            home: BootstrapChain::from_load_struct(plan.home)?,
+            foreign: BootstrapChain::from_load_struct(plan.foreign)?,
+            digest: keccak256(s.as_bytes()).into(),
+        };
+        result.validate()?;
+        Ok(result)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.authorities.is_empty() {
+            bail!("authorities must not be empty");
+        }
+        let unique = self.authorities.iter().collect::<HashSet<_>>();
+        if unique.len() != self.authorities.len() {
+            bail!("authorities must not contain an account twice");
+        }
+        if !self.authorities.contains(&self.account) {
+            bail!("account {:?} must be one of the authorities", self.account);
+        }
+        if self.required_signatures == 0 {
+            bail!("required_signatures must be greater than 0");
+        }
+        if self.required_signatures as usize > self.authorities.len() {
+            bail!(
+                "required_signatures {} is more than the {} authorities",
+                self.required_signatures,
+                self.authorities.len()
+            );
+        }
+        if self.fund_authorities.map_or(false, |value| value.is_zero()) {
+            bail!("fund_authorities must be greater than 0");
+        }
+        if let Some(gas_price) = self.gas_price {
+            if gas_price > U256::from(u64::max_value()) {
+                bail!("gas_price {} is too large", format_wei(gas_price));
+            }
+        }
+        Ok(())
+    }
+
+    /// config of the deployer, which the bootstrap runs with.
+    pub fn deploy_config(&self) -> Result<Config> {
+        let node = |chain: &BootstrapChain| {
+            let mut node = chain.node(self.deployer);
+            if self.password.is_some() {
+                node.signer = Signer::Personal;
+                node.password_file = self.password_file.clone();
+                node.password = self.password.clone();
+            }
+            node
+        };
+        Config::builder()
+            .home(node(&self.home))
+            .foreign(node(&self.foreign))
+            .authorities(Authorities {
+                accounts: self.authorities.clone(),
+                required_signatures: self.required_signatures,
+            })
+            .estimated_gas_cost_of_withdraw(self.estimated_gas_cost_of_withdraw)
+            .max_total_home_contract_balance(self.max_total_home_contract_balance)
+            .max_single_deposit_value(self.max_single_deposit_value)
+            .build()
+    }
+
+    /// the config file of `account`. the nodes estimate the gas of its transactions.
+    pub fn config_file(&self) -> String {
+        let node = |chain: &BootstrapChain| ConfigNode {
+            account: checksummed(&self.account),
+            ipc: chain.ipc.clone(),
+            required_confirmations: chain.required_confirmations,
+            contract: ConfigContract {
+                bin: chain.contract_bin.clone(),
+            },
+        };
+        let file = ConfigFile {
+            estimated_gas_cost_of_withdraw: self.estimated_gas_cost_of_withdraw.to_string(),
+            max_total_home_contract_balance: self.max_total_home_contract_balance.to_string(),
+            max_single_deposit_value: self.max_single_deposit_value.to_string(),
+            home: node(&self.home),
+            foreign: node(&self.foreign),
+            authorities: ConfigAuthorities {
+                accounts: self.authorities.iter().map(checksummed).collect(),
+                required_signatures: self.required_signatures,
+            },
+            transactions: ConfigTransactions {
+                gas_price: self.gas_price.map(format_wei),
+                estimate_gas: true,
+            },
+        };
+        toml::to_string(&file).expect("the config file always serializes; qed")
+    }
+}
+
+/// Config file written by a bootstrap, see `load::Config`. values come before the tables.
+#[derive(Serialize)]
+struct ConfigFile {
+    estimated_gas_cost_of_withdraw: String,
+    max_total_home_contract_balance: String,
+    max_single_deposit_value: String,
+    home: ConfigNode,
+    foreign: ConfigNode,
+    authorities: ConfigAuthorities,
+    transactions: ConfigTransactions,
+}
+
+#[derive(Serialize)]
+struct ConfigNode {
+    account: String,
+    ipc: PathBuf,
+    required_confirmations: usize,
+    contract: ConfigContract,
+}
+
+#[derive(Serialize)]
+struct ConfigContract {
+    bin: PathBuf,
+}
+
+#[derive(Serialize)]
+struct ConfigAuthorities {
+    accounts: Vec<String>,
+    required_signatures: u32,
+}
+
+#[derive(Serialize)]
+struct ConfigTransactions {
+    gas_price: Option<String>,
+    estimate_gas: bool,
+}
+
+/// default bootstrap state file of the plan at `plan`: `<plan>.state`.
+pub fn bootstrap_state_path(plan: &Path) -> PathBuf {
+    let mut path = plan.as_os_str().to_owned();
+    path.push(".state");
+    path.into()
+}
+
+/// Deployment of a contract by a bootstrap.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DeployStep {
+    /// saved once sent, so an interrupted bootstrap waits for it instead of deploying again
+    pub transaction: Option<H256>,
+    /// saved once the transaction has its confirmations
+    pub contract: Option<Address>,
+    pub block: Option<u64>,
+}
+
+/// Funding of an authority on foreign by a bootstrap.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FundingStep {
+    pub authority: Address,
+    pub transaction: H256,
+    /// the transaction has its confirmations
+    pub mined: bool,
+}
+
+/// Steps of a bootstrap done so far, saved to the bootstrap state file after every step.
+///
+/// a bootstrap interrupted between sending a transaction and saving its hash sends it again.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct BootstrapProgress {
+    /// `BootstrapPlan::digest` of the plan
+    pub plan: H256,
+    /// both contracts list the authorities and required signatures of the plan
+    #[serde(default)]
+    pub verified: bool,
+    /// the config and the database were written
+    #[serde(default)]
+    pub written: bool,
+    #[serde(default)]
+    pub home: DeployStep,
+    #[serde(default)]
+    pub foreign: DeployStep,
+    #[serde(default)]
+    pub funded: Vec<FundingStep>,
+}
+
+impl BootstrapProgress {
+    /// the progress saved at `path`, or none if there is no file yet.
+    /// fails if it was saved for another plan.
+    pub fn load_or_new<P: AsRef<Path>>(path: P, plan: &BootstrapPlan) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(BootstrapProgress {
+                plan: plan.digest,
+                ..BootstrapProgress::default()
+            });
+        }
+        let mut read = String::new();
+        fs::File::open(path)
+            .chain_err(|| format!("Cannot open bootstrap state {:?}", path))?
+            .read_to_string(&mut read)?;
+        let progress: BootstrapProgress =
+            toml::from_str(&read).chain_err(|| format!("Invalid bootstrap state {:?}", path))?;
+        if progress.plan != plan.digest {
+            bail!(
+                "bootstrap state {:?} belongs to another plan. continue with the plan it was started with or remove it to start over",
+                path
+            );
+        }
+        Ok(progress)
+    }
+
+    /// replaces the file at `path` with the progress.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let contents = toml::to_string(self).expect("bootstrap progress always serializes; qed");
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        fs::File::create(&tmp)?.write_all(contents.as_bytes())?;
+        fs::rename(&tmp, path).chain_err(|| format!("Cannot save bootstrap state {:?}", path))?;
+        Ok(())
+    }
+
+    /// the database of the deployed contracts, checked from their deployment blocks.
+    pub fn database(&self) -> Result<Database> {
+        let (home, home_deploy) = match (self.home.contract, self.home.block) {
+            (Some(contract), Some(block)) => (contract, block),
+            _ => bail!("HomeBridge is not deployed yet"),
+        };
+        let (foreign, foreign_deploy) = match (self.foreign.contract, self.foreign.block) {
+            (Some(contract), Some(block)) => (contract, block),
+            _ => bail!("ForeignBridge is not deployed yet"),
+        };
+        Ok(Database {
+            home_contract_address: home,
+            foreign_contract_address: foreign,
+            home_deploy,
+            foreign_deploy,
+            checked_deposit_relay: home_deploy,
+            checked_withdraw_relay: foreign_deploy,
+            checked_withdraw_confirm: foreign_deploy,
+            ..Database::default()
+        })
+    }
+
+    /// what the operators need to know about the bootstrapped bridge.
+    pub fn summary(&self, plan: &BootstrapPlan, config: &Path, database: &Path) -> String {
+        let deployed = |name: &str, step: &DeployStep| {
+            format!(
+                "{}: {} deployed in block {} by {:?}",
+                name,
+                step.contract.as_ref().map_or("-".into(), checksummed),
+                step.block.unwrap_or_default(),
+                step.transaction.unwrap_or_default()
+            )
+        };
+        let mut lines = vec![
+            format!(
+                "bootstrapped a bridge of {} authorities requiring {} signatures",
+                plan.authorities.len(),
+                plan.required_signatures
+            ),
+            deployed("HomeBridge", &self.home),
+            deployed("ForeignBridge", &self.foreign),
+        ];
+        if let Some(value) = plan.fund_authorities {
+            lines.push(format!("funded on foreign with {} each:", format_ether(value, 0)));
+            for funding in &self.funded {
+                lines.push(format!(
+                    "  {} in {:?}",
+                    checksummed(&funding.authority),
+                    funding.transaction
+                ));
+            }
+        }
+        lines.push(format!("config of {}: {:?}", checksummed(&plan.account), config));
+        lines.push(format!("database: {:?}", database));
+        lines.push(
+            "every authority runs the bridge with a copy of the database and of the config \
+             with home.account and foreign.account set to its own account, \
+             unlocked on both nodes"
+                .into(),
+        );
+        lines.join("\n")
+    }
+}
+
+/// writes the config of `plan.account` to `config_path` and the database of the contracts of
+/// `progress` to `database_path`. both are written next to their paths and loaded like the
+/// bridge loads them before they replace any file there.
+pub fn write_bootstrap_files(
+    plan: &BootstrapPlan,
+    progress: &BootstrapProgress,
+    config_path: &Path,
+    database_path: &Path,
+) -> Result<()> {
+    let database = progress.database()?;
+    let with_tmp = |path: &Path| {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        PathBuf::from(tmp)
+    };
+    let (config_tmp, database_tmp) = (with_tmp(config_path), with_tmp(database_path));
+    fs::File::create(&config_tmp)
+        .and_then(|mut file| file.write_all(plan.config_file().as_bytes()))
+        .chain_err(|| format!("Cannot write config {:?}", config_tmp))?;
+    database.save(fs::File::create(&database_tmp)
+        .chain_err(|| format!("Cannot write database {:?}", database_tmp))?)?;
+
+    let config = Config::load(&config_tmp).chain_err(|| "The written config doesn't load")?;
+    if config.authorities.accounts != plan.authorities {
+        bail!(
+            "The written config lists the authorities {:?} instead of {:?}",
+            config.authorities.accounts,
+            plan.authorities
+        );
+    }
+    if Database::load(&database_tmp).chain_err(|| "The written database doesn't load")? != database
+    {
+        bail!("The written database {:?} differs from the bootstrapped one", database_tmp);
+    }
+    fs::rename(&database_tmp, database_path)
+        .chain_err(|| format!("Cannot write database {:?}", database_path))?;
+    fs::rename(&config_tmp, config_path)
+        .chain_err(|| format!("Cannot write config {:?}", config_path))?;
+    Ok(())
+}
+
+type SendFuture<T> = Timeout<ApiCall<H256, <T as Transport>::Out>>;
+type ReceiptFuture<T> = Timeout<ApiCall<Option<ReceiptWithStatus>, <T as Transport>::Out>>;
+type BlockNumberFuture<T> = Timeout<ApiCall<U256, <T as Transport>::Out>>;
+type CodeFuture<T> = Timeout<ApiCall<Bytes, <T as Transport>::Out>>;
+
+/// Transaction of a bootstrap waited for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Awaited {
+    /// deployment on home or foreign
+    Deploy(bool),
+    /// funding of `BootstrapProgress::funded[i]`
+    Funding(usize),
+}
+
+impl Awaited {
+    fn on_home(&self) -> bool {
+        match *self {
+            Awaited::Deploy(on_home) => on_home,
+            Awaited::Funding(_) => false,
+        }
+    }
+}
+
+enum BootstrapState<T: Transport> {
+    /// Sending the deployment of the contract on home or foreign.
+    Deploy(bool, SendFuture<T>),
+    /// Sending foreign gas money to an authority.
+    Fund(Address, SendFuture<T>),
+    FetchReceipt(Awaited, H256, ReceiptFuture<T>),
+    /// Fetching the head until the mined transaction has its confirmations.
+    FetchHead(Awaited, TransactionReceipt, BlockNumberFuture<T>),
+    /// Waiting to fetch the receipt again.
+    Wait(Awaited, H256, Sleep),
+    FetchCode(Join<CodeFuture<T>, CodeFuture<T>>),
+    FetchAuthorities(Join<FetchAuthoritySet<T>, FetchAuthoritySet<T>>),
+    Done,
+}
+
+/// deploys the contracts, verifies them and funds the authorities of `plan`, continuing
+/// from `progress`, which is saved to `state_path` after every step.
+pub fn create_bootstrap<T: Transport>(
+    app: Arc<App<T>>,
+    plan: BootstrapPlan,
+    progress: BootstrapProgress,
+    state_path: PathBuf,
+) -> Bootstrap<T> {
+    let run = Run {
+        app,
+        plan,
+        progress,
+        state_path,
+    };
+    let state = run.next_state();
+    Bootstrap { run, state }
+}
+
+/// A bootstrap but its state.
+struct Run<T: Transport> {
+    app: Arc<App<T>>,
+    plan: BootstrapPlan,
+    progress: BootstrapProgress,
+    state_path: PathBuf,
+}
+
+impl<T: Transport> Run<T> {
+    fn node(&self, on_home: bool) -> (&T, &Node) {
+        if on_home {
+            (&self.app.connections.home, &self.app.config.home)
+        } else {
+            (&self.app.connections.foreign, &self.app.config.foreign)
+        }
+    }
+
+    fn deploy_step(&mut self, on_home: bool) -> &mut DeployStep {
+        if on_home {
+            &mut self.progress.home
+        } else {
+            &mut self.progress.foreign
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        self.progress.save(&self.state_path)
+    }
+
+    /// the first step not done yet.
+    fn next_state(&self) -> BootstrapState<T> {
+        for &(on_home, step) in &[(true, &self.progress.home), (false, &self.progress.foreign)] {
+            match (step.transaction, step.contract) {
+                (None, _) => return self.deploy(on_home),
+                (Some(hash), None) => return self.receipt(Awaited::Deploy(on_home), hash),
+                (Some(_), Some(_)) => (),
+            }
+        }
+        if !self.progress.verified {
+            let (home, foreign) = self.contracts();
+            let fetch = |on_home: bool, contract: Address| {
+                let (transport, node) = self.node(on_home);
+                api::timeout(
+                    &self.app.timer,
+                    api::latest_code(transport, contract),
+                    chain_name(on_home),
+                    node.request_timeout,
+                )
+            };
+            return BootstrapState::FetchCode(fetch(true, home).join(fetch(false, foreign)));
+        }
+        let value = match self.plan.fund_authorities {
+            Some(value) => value,
+            None => return BootstrapState::Done,
+        };
+        for authority in &self.plan.authorities {
+            let funded = self.progress
+                .funded
+                .iter()
+                .position(|funding| funding.authority == *authority);
+            match funded {
+                None => {
+                    let future = self.send(false, Some(*authority), Some(value), None);
+                    return BootstrapState::Fund(*authority, future);
+                }
+                Some(index) if !self.progress.funded[index].mined => {
+                    let hash = self.progress.funded[index].transaction;
+                    return self.receipt(Awaited::Funding(index), hash);
+                }
+                Some(_) => (),
+            }
+        }
+        BootstrapState::Done
+    }
+
+    fn contracts(&self) -> (Address, Address) {
+        (
+            self.progress
+                .home
+                .contract
+                .expect("contracts are deployed before they are verified; qed"),
+            self.progress
+                .foreign
+                .contract
+                .expect("contracts are deployed before they are verified; qed"),
+        )
+    }
+
+    fn deploy(&self, on_home: bool) -> BootstrapState<T> {
+        let plan = &self.plan;
+        let data = if on_home {
+            self.app.home_bridge.constructor(
+                plan.home.bin.0.clone(),
+                plan.required_signatures,
+                plan.authorities.clone(),
+                plan.estimated_gas_cost_of_withdraw,
+                plan.max_total_home_contract_balance,
+                plan.max_single_deposit_value,
+                // 0 disables `HomeBridge.withdrawToPayout`
+                Address::zero(),
+            )
+        } else {
+            self.app.foreign_bridge.constructor(
+                plan.foreign.bin.0.clone(),
+                plan.required_signatures,
+                plan.authorities.clone(),
+                plan.estimated_gas_cost_of_withdraw,
+            )
+        };
+        info!(target: "bridge::bootstrap", "deploying the contract on {}", chain_name(on_home));
+        BootstrapState::Deploy(on_home, self.send(on_home, None, None, Some(data.into())))
+    }
+
+    /// sends a transaction from the deployer. the nodes estimate its gas.
+    fn send(
+        &self,
+        on_home: bool,
+        to: Option<Address>,
+        value: Option<U256>,
+        data: Option<Bytes>,
+    ) -> SendFuture<T> {
+        let (transport, node) = self.node(on_home);
+        let tx = TransactionRequest {
+            from: self.plan.deployer,
+            to,
+            gas: None,
+            gas_price: self.plan.gas_price,
+            value,
+            data,
+            nonce: None,
+            condition: None,
+        };
+        api::timeout(
+            &self.app.timer,
+            api::node_send_transaction(transport, node, tx),
+            chain_name(on_home),
+            node.request_timeout,
+        )
+    }
+
+    fn receipt(&self, awaited: Awaited, hash: H256) -> BootstrapState<T> {
+        let (transport, node) = self.node(awaited.on_home());
+        let future = api::timeout(
+            &self.app.timer,
+            api::transaction_receipt_with_status(transport, hash),
+            chain_name(awaited.on_home()),
+            node.request_timeout,
+        ).about(hash);
+        BootstrapState::FetchReceipt(awaited, hash, future)
+    }
+
+    fn wait(&self, awaited: Awaited, hash: H256) -> BootstrapState<T> {
+        let (_, node) = self.node(awaited.on_home());
+        BootstrapState::Wait(awaited, hash, self.app.timer.sleep(node.poll_interval))
+    }
+
+    /// saves that the transaction `awaited` was mined in `receipt` and confirmed.
+    fn mined(&mut self, awaited: Awaited, receipt: TransactionReceipt) -> Result<()> {
+        match awaited {
+            Awaited::Deploy(on_home) => {
+                let contract = match receipt.contract_address {
+                    Some(contract) => contract,
+                    None => bail!(
+                        "deployment {:?} on {} created no contract",
+                        receipt.transaction_hash,
+                        chain_name(on_home)
+                    ),
+                };
+                info!(target: "bridge::bootstrap", "deployed the contract on {} to {:?}", chain_name(on_home), contract);
+                let step = self.deploy_step(on_home);
+                step.contract = Some(contract);
+                step.block = Some(receipt.block_number.low_u64());
+            }
+            Awaited::Funding(index) => {
+                let funding = &mut self.progress.funded[index];
+                info!(target: "bridge::bootstrap", "funded authority {:?} on foreign", funding.authority);
+                funding.mined = true;
+            }
+        }
+        self.save()
+    }
+
+    /// fails unless both contracts list the authorities and required signatures of the plan.
+    fn verify(&self, home: &AuthoritySet, foreign: &AuthoritySet) -> Result<()> {
+        let required = U256::from(self.plan.required_signatures);
+        for &(on_home, set) in &[(true, home), (false, foreign)] {
+            if set.authorities != self.plan.authorities || set.required_signatures != required {
+                bail!(
+                    "the contract on {} lists the authorities {:?} requiring {} signatures instead of {:?} requiring {}",
+                    chain_name(on_home),
+                    set.authorities,
+                    set.required_signatures,
+                    self.plan.authorities,
+                    required
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Future bootstrapping a new bridge, see `create_bootstrap`.
+/// resolves to the progress once every step of the plan is done.
+pub struct Bootstrap<T: Transport> {
+    run: Run<T>,
+    state: BootstrapState<T>,
+}
+
+impl<T: Transport> Future for Bootstrap<T> {
+    type Item = BootstrapProgress;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let run = &mut self.run;
+            let next_state = match self.state {
+                BootstrapState::Deploy(on_home, ref mut future) => {
+                    let hash = try_ready!(future.poll());
+                    run.deploy_step(on_home).transaction = Some(hash);
+                    run.save()?;
+                    run.receipt(Awaited::Deploy(on_home), hash)
+                }
+                BootstrapState::Fund(authority, ref mut future) => {
+                    let hash = try_ready!(future.poll());
+                    run.progress.funded.push(FundingStep {
+                        authority,
+                        transaction: hash,
+                        mined: false,
+                    });
+                    run.save()?;
+                    run.receipt(Awaited::Funding(run.progress.funded.len() - 1), hash)
+                }
+                BootstrapState::FetchReceipt(awaited, hash, ref mut future) => {
+                    match try_ready!(future.poll()) {
+                        None => run.wait(awaited, hash),
+                        Some(ref receipt) if receipt.reverted() => bail!(
+                            "bootstrap transaction {:?} reverted on {}",
+                            hash,
+                            chain_name(awaited.on_home())
+                        ),
+                        Some(receipt) => {
+                            let (transport, node) = run.node(awaited.on_home());
+                            let future = api::timeout(
+                                &run.app.timer,
+                                api::block_number(transport),
+                                chain_name(awaited.on_home()),
+                                node.request_timeout,
+                            );
+                            BootstrapState::FetchHead(awaited, receipt.receipt, future)
+                        }
+                    }
+                }
+                BootstrapState::FetchHead(awaited, ref receipt, ref mut future) => {
+                    let head = try_ready!(future.poll()).low_u64();
+                    let confirmations = run.node(awaited.on_home()).1.required_confirmations;
+                    if head >= receipt.block_number.low_u64() + confirmations as u64 {
+                        run.mined(awaited, receipt.clone())?;
+                        run.next_state()
+                    } else {
+                        run.wait(awaited, receipt.transaction_hash)
+                    }
+                }
+                BootstrapState::Wait(awaited, hash, ref mut sleep) => {
+                    try_ready!(sleep.poll());
+                    // fetched again in case the transaction was reorged out
+                    run.receipt(awaited, hash)
+                }
+                BootstrapState::FetchCode(ref mut future) => {
+                    let (home, foreign) = try_ready!(future.poll());
+                    let (home_contract, foreign_contract) = run.contracts();
+                    for &(on_home, contract, ref code) in
+                        &[(true, home_contract, home), (false, foreign_contract, foreign)]
+                    {
+                        if code.0.is_empty() {
+                            bail!("there is no contract at {:?} on {}", contract, chain_name(on_home));
+                        }
+                    }
+                    BootstrapState::FetchAuthorities(
+                        fetch_authority_set(run.app.clone(), true, home_contract)
+                            .join(fetch_authority_set(run.app.clone(), false, foreign_contract)),
+                    )
+                }
+                BootstrapState::FetchAuthorities(ref mut future) => {
+                    let (home, foreign) = try_ready!(future.poll());
+                    run.verify(&home, &foreign)?;
+                    info!(target: "bridge::bootstrap", "verified the authorities of both contracts");
+                    run.progress.verified = true;
+                    run.save()?;
+                    run.next_state()
+                }
+                BootstrapState::Done => return Ok(Async::Ready(run.progress.clone())),
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+mod load {
+    use std::path::PathBuf;
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct BootstrapPlan {
+        pub account: String,
+        pub deployer: String,
+        pub password_file: Option<PathBuf>,
+        pub authorities: Vec<String>,
+        pub required_signatures: u32,
+        pub estimated_gas_cost_of_withdraw: String,
+        pub max_total_home_contract_balance: String,
+        pub max_single_deposit_value: String,
+        pub gas_price: Option<String>,
+        pub fund_authorities: Option<String>,
+        pub home: BootstrapChain,
+        pub foreign: BootstrapChain,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct BootstrapChain {
+        pub ipc: PathBuf,
+        pub contract_bin: PathBuf,
+        pub required_confirmations: Option<usize>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use self::tempdir::TempDir;
+    use std::fs;
+    use web3::types::{Address, H256};
+    use config::Config;
+    use database::Database;
+    use super::{bootstrap_state_path, write_bootstrap_files, BootstrapPlan, BootstrapProgress,
+                DeployStep, FundingStep};
+
+    const PLAN: &str = r#"
+account = "0x0000000000000000000000000000000000000002"
+deployer = "0x00000000000000000000000000000000000000d0"
+authorities = [
+    "0x0000000000000000000000000000000000000001",
+    "0x0000000000000000000000000000000000000002",
+    "0x0000000000000000000000000000000000000003",
+]
+required_signatures = 2
+estimated_gas_cost_of_withdraw = "100000"
+max_total_home_contract_balance = "10000000000000000000"
+max_single_deposit_value = "1000000000000000000"
+gas_price = "20 gwei"
+fund_authorities = "0.5 ether"
+
+[home]
+ipc = "/home.ipc"
+contract_bin = "../compiled_contracts/HomeBridge.bin"
+
+[foreign]
+ipc = "/foreign.ipc"
+contract_bin = "../compiled_contracts/ForeignBridge.bin"
+required_confirmations = 2
+"#;
+
+    fn deployed() -> BootstrapProgress {
+        BootstrapProgress {
+            plan: BootstrapPlan::load_from_str(PLAN).unwrap().digest,
+            verified: true,
+            written: false,
+            home: DeployStep {
+                transaction: Some(H256::from(1)),
+                contract: Some(Address::from(0x10)),
+                block: Some(100),
+            },
+            foreign: DeployStep {
+                transaction: Some(H256::from(2)),
+                contract: Some(Address::from(0x20)),
+                block: Some(7),
+            },
+            funded: vec![FundingStep {
+                authority: Address::from(1),
+                transaction: H256::from(3),
+                mined: true,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_load_bootstrap_plan() {
+        let plan = BootstrapPlan::load_from_str(PLAN).unwrap();
+        assert_eq!(Address::from(2), plan.account);
+        assert_eq!(3, plan.authorities.len());
+        assert_eq!(Some(20_000_000_000u64.into()), plan.gas_price);
+        assert_eq!(Some(500_000_000_000_000_000u64.into()), plan.fund_authorities);
+        assert_eq!(12, plan.home.required_confirmations);
+        assert_eq!(2, plan.foreign.required_confirmations);
+        assert!(!plan.home.bin.0.is_empty());
+
+        let config = plan.deploy_config().unwrap();
+        assert_eq!(plan.deployer, config.home.account);
+        assert_eq!(plan.deployer, config.foreign.account);
+        assert_eq!(2, config.authorities.required_signatures);
+    }
+
+    #[test]
+    fn test_invalid_bootstrap_plans() {
+        let invalid = |from: &str, to: &str| {
+            let plan = PLAN.replace(from, to);
+            BootstrapPlan::load_from_str(&plan).unwrap_err().to_string()
+        };
+        assert!(invalid(
+            "account = \"0x0000000000000000000000000000000000000002\"",
+            "account = \"0x0000000000000000000000000000000000000004\""
+        ).contains("must be one of the authorities"));
+        assert_eq!(
+            "required_signatures 4 is more than the 3 authorities",
+            invalid("required_signatures = 2", "required_signatures = 4")
+        );
+        assert_eq!(
+            "authorities must not contain an account twice",
+            invalid(
+                "0x0000000000000000000000000000000000000003",
+                "0x0000000000000000000000000000000000000001"
+            )
+        );
+        assert_eq!(
+            "fund_authorities must be greater than 0",
+            invalid("0.5 ether", "0 ether")
+        );
+        assert!(invalid("gas_price", "gas_prize").contains("unknown field `gas_prize`"));
+    }
+
+    #[test]
+    fn test_bootstrap_progress_is_resumed_for_its_plan_only() {
+        let tempdir = TempDir::new("test_bootstrap_progress").unwrap();
+        let path = bootstrap_state_path(&tempdir.path().join("plan.toml"));
+        assert_eq!(tempdir.path().join("plan.toml.state"), path);
+        let plan = BootstrapPlan::load_from_str(PLAN).unwrap();
+
+        let fresh = BootstrapProgress::load_or_new(&path, &plan).unwrap();
+        assert_eq!(plan.digest, fresh.plan);
+        assert_eq!(None, fresh.home.transaction);
+
+        deployed().save(&path).unwrap();
+        assert_eq!(deployed(), BootstrapProgress::load_or_new(&path, &plan).unwrap());
+
+        let other = BootstrapPlan::load_from_str(&PLAN.replace("20 gwei", "30 gwei")).unwrap();
+        let err = BootstrapProgress::load_or_new(&path, &other).unwrap_err();
+        assert!(err.to_string().contains("belongs to another plan"));
+    }
+
+    #[test]
+    fn test_write_bootstrap_files() {
+        let tempdir = TempDir::new("test_write_bootstrap_files").unwrap();
+        let config_path = tempdir.path().join("config.toml");
+        let database_path = tempdir.path().join("db.toml");
+        let plan = BootstrapPlan::load_from_str(PLAN).unwrap();
+
+        let mut undeployed = deployed();
+        undeployed.foreign = DeployStep::default();
+        assert!(write_bootstrap_files(&plan, &undeployed, &config_path, &database_path).is_err());
+        assert!(!config_path.exists());
+
+        write_bootstrap_files(&plan, &deployed(), &config_path, &database_path).unwrap();
+        let config = Config::load(&config_path).unwrap();
+        assert_eq!(plan.account, config.home.account);
+        assert_eq!(plan.account, config.foreign.account);
+        assert_eq!(plan.authorities, config.authorities.accounts);
+        assert_eq!(2, config.foreign.required_confirmations);
+        assert_eq!(plan.home.bin, config.home.contract.bin);
+        assert_eq!(20_000_000_000, config.txs.deposit_relay.gas_price);
+        assert!(config.txs.deposit_relay.estimate_gas);
+
+        let database = Database::load(&database_path).unwrap();
+        assert_eq!(Address::from(0x10), database.home_contract_address);
+        assert_eq!(Address::from(0x20), database.foreign_contract_address);
+        assert_eq!(100, database.home_deploy);
+        assert_eq!(100, database.checked_deposit_relay);
+        assert_eq!(7, database.checked_withdraw_relay);
+        assert_eq!(7, database.checked_withdraw_confirm);
+        // no temporary files are left behind
+        assert_eq!(2, fs::read_dir(tempdir.path()).unwrap().count());
+    }
+}
@@ -0,0 +1,271 @@
+use ethabi::{self, ParamType, Token};
+use tiny_keccak::keccak256;
+use web3::Transport;
+use web3::types::{Address, H256, U256};
+use app::App;
+use error::{ErrorKind, Result};
+use signature::Signature;
+use super::RelayCounts;
+
+/// Function a transaction calls and the arguments it was encoded from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectedCall {
+    /// canonical signature, e.g. `deposit(address,uint256,bytes32)`
+    pub signature: String,
+    pub params: Vec<ParamType>,
+    pub args: Vec<Token>,
+}
+
+impl ExpectedCall {
+    /// `ForeignBridge.deposit`
+    pub fn deposit(recipient: Address, value: U256, transaction_hash: H256) -> Self {
+        ExpectedCall {
+            signature: "deposit(address,uint256,bytes32)".into(),
+            params: vec![
+                ParamType::Address,
+                ParamType::Uint(256),
+                ParamType::FixedBytes(32),
+            ],
+            args: vec![
+                Token::Address(recipient),
+                Token::Uint(value),
+                Token::FixedBytes(transaction_hash.to_vec()),
+            ],
+        }
+    }
+
+    /// `ForeignBridge.submitSignature`
+    pub fn submit_signature(signature: Vec<u8>, message: Vec<u8>) -> Self {
+        ExpectedCall {
+            signature: "submitSignature(bytes,bytes)".into(),
+            params: vec![ParamType::Bytes, ParamType::Bytes],
+            args: vec![Token::Bytes(signature), Token::Bytes(message)],
+        }
+    }
+
+    /// `HomeBridge.withdraw`
+    pub fn withdraw(signatures: &[Signature], message: Vec<u8>) -> Self {
+        ExpectedCall {
+            signature: "withdraw(uint8[],bytes32[],bytes32[],bytes)".into(),
+            params: vec![
+                ParamType::Array(Box::new(ParamType::Uint(8))),
+                ParamType::Array(Box::new(ParamType::FixedBytes(32))),
+                ParamType::Array(Box::new(ParamType::FixedBytes(32))),
+                ParamType::Bytes,
+            ],
+            args: vec![
+                Token::Array(
+                    signatures
+                        .iter()
+                        .map(|signature| Token::Uint(signature.v.into()))
+                        .collect(),
+                ),
+                Token::Array(
+                    signatures
+                        .iter()
+                        .map(|signature| Token::FixedBytes(signature.r.to_vec()))
+                        .collect(),
+                ),
+                Token::Array(
+                    signatures
+                        .iter()
+                        .map(|signature| Token::FixedBytes(signature.s.to_vec()))
+                        .collect(),
+                ),
+                Token::Bytes(message),
+            ],
+        }
+    }
+
+    /// `HomeBridge.withdrawToPayout`
+    pub fn withdraw_to_payout(signatures: &[Signature], message: Vec<u8>, payout: Address) -> Self {
+        let mut call = ExpectedCall::withdraw(signatures, message);
+        call.signature = "withdrawToPayout(uint8[],bytes32[],bytes32[],bytes,address)".into();
+        call.params.push(ParamType::Address);
+        call.args.push(Token::Address(payout));
+        call
+    }
+}
+
+/// checks that `payload` calls `expected.signature` with `expected.args`:
+/// the function selector matches and the arguments decode back to the same tokens.
+/// fails with `ErrorKind::CallDataMismatch` otherwise.
+pub fn verify_call_data(expected: &ExpectedCall, payload: &[u8]) -> Result<()> {
+    let mismatch = |details: String| -> Result<()> {
+        Err(ErrorKind::CallDataMismatch(expected.signature.clone(), details).into())
+    };
+    let selector = &keccak256(expected.signature.as_bytes())[..4];
+    if payload.len() < 4 || &payload[..4] != selector {
+        return mismatch(format!(
+            "selector {:?} instead of {:?}",
+            &payload[..payload.len().min(4)],
+            selector
+        ));
+    }
+    let decoded = match ethabi::decode(&expected.params, &payload[4..]) {
+        Ok(decoded) => decoded,
+        Err(err) => return mismatch(format!("arguments don't decode: {}", err)),
+    };
+    for (index, (decoded, arg)) in decoded.iter().zip(expected.args.iter()).enumerate() {
+        if decoded != arg {
+            return mismatch(format!(
+                "argument {} decodes to {:?} instead of {:?}",
+                index, decoded, arg
+            ));
+        }
+    }
+    // the encoding of the decoded arguments must be the whole call data
+    if ethabi::encode(&decoded).len() != payload.len() - 4 {
+        return mismatch("trailing bytes after the arguments".into());
+    }
+    Ok(())
+}
+
+/// `verify_call_data` unless `verify_call_data` is disabled in the config.
+/// mismatches are counted in `counts`.
+pub fn check_call_data<T: Transport>(
+    app: &App<T>,
+    counts: &mut RelayCounts,
+    expected: &ExpectedCall,
+    payload: &[u8],
+) -> Result<()> {
+    if !app.config.verify_call_data {
+        return Ok(());
+    }
+    let result = verify_call_data(expected, payload);
+    if result.is_err() {
+        counts.call_data_mismatches += 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::{self, Token};
+    use tiny_keccak::keccak256;
+    use web3::types::{Address, H256, U256};
+    use contracts::{foreign, home};
+    use error::ErrorKind;
+    use signature::Signature;
+    use super::{verify_call_data, ExpectedCall};
+
+    #[test]
+    fn test_verify_deposit_call_data() {
+        let foreign = foreign::ForeignBridge::default();
+        let recipient: Address = 1.into();
+        let value: U256 = 2.into();
+        let id: H256 = 3.into();
+        let expected = ExpectedCall::deposit(recipient, value, id);
+
+        let payload = foreign.functions().deposit().input(recipient, value, id.0);
+        verify_call_data(&expected, &payload).unwrap();
+
+        // an encoder that swapped recipient and value
+        let mut swapped = keccak256(b"deposit(address,uint256,bytes32)")[..4].to_vec();
+        swapped.extend(ethabi::encode(&[
+            Token::Address(value.low_u64().into()),
+            Token::Uint(U256::from(1)),
+            Token::FixedBytes(id.to_vec()),
+        ]));
+        match *verify_call_data(&expected, &swapped).unwrap_err().kind() {
+            ErrorKind::CallDataMismatch(ref function, ref details) => {
+                assert_eq!("deposit(address,uint256,bytes32)", function.as_str());
+                assert!(details.starts_with("argument 0"), "{}", details);
+            }
+            ref kind => panic!("expected call data mismatch, got {:?}", kind),
+        }
+
+        // an encoder that used the wrong function
+        let mut wrong_selector = payload.clone();
+        wrong_selector[0] ^= 1;
+        assert!(verify_call_data(&expected, &wrong_selector).is_err());
+
+        let mut trailing = payload.clone();
+        trailing.extend_from_slice(&[0u8; 32]);
+        assert!(verify_call_data(&expected, &trailing).is_err());
+        assert!(verify_call_data(&expected, &payload[..3]).is_err());
+    }
+
+    #[test]
+    fn test_verify_submit_signature_and_withdraw_call_data() {
+        let foreign = foreign::ForeignBridge::default();
+        let home = home::HomeBridge::default();
+        let signature = vec![1u8; 65];
+        let message = vec![2u8; 116];
+
+        let payload = foreign
+            .functions()
+            .submit_signature()
+            .input(signature.clone(), message.clone());
+        let expected = ExpectedCall::submit_signature(signature.clone(), message.clone());
+        verify_call_data(&expected, &payload).unwrap();
+        // signature and message swapped
+        let swapped = ExpectedCall::submit_signature(message.clone(), signature.clone());
+        assert!(verify_call_data(&swapped, &payload).is_err());
+
+        let signatures = vec![
+            Signature {
+                v: 27,
+                r: 3.into(),
+                s: 4.into(),
+            },
+        ];
+        let payload = home.functions().withdraw().input(
+            signatures.iter().map(|x| x.v),
+            signatures.iter().map(|x| x.r),
+            signatures.iter().map(|x| x.s),
+            message.clone(),
+        );
+        let expected = ExpectedCall::withdraw(&signatures, message.clone());
+        verify_call_data(&expected, &payload).unwrap();
+        // an encoder that swapped r and s
+        let swapped = vec![
+            Signature {
+                v: 27,
+                r: 4.into(),
+                s: 3.into(),
+            },
+        ];
+        assert!(verify_call_data(&ExpectedCall::withdraw(&swapped, message), &payload).is_err());
+    }
+
+    #[test]
+    fn test_verify_withdraw_to_payout_call_data() {
+        let home = home::HomeBridge::default();
+        let message = vec![2u8; 116];
+        let signatures = vec![
+            Signature {
+                v: 27,
+                r: 3.into(),
+                s: 4.into(),
+            },
+        ];
+        let payout: Address = 5.into();
+        let payload = home.functions().withdraw_to_payout().input(
+            signatures.iter().map(|x| x.v),
+            signatures.iter().map(|x| x.r),
+            signatures.iter().map(|x| x.s),
+            message.clone(),
+            payout,
+        );
+        let expected = ExpectedCall::withdraw_to_payout(&signatures, message.clone(), payout);
+        verify_call_data(&expected, &payload).unwrap();
+
+        // an encoder that paid another address
+        let other = ExpectedCall::withdraw_to_payout(&signatures, message.clone(), 6.into());
+        match *verify_call_data(&other, &payload).unwrap_err().kind() {
+            ErrorKind::CallDataMismatch(_, ref details) => {
+                assert!(details.starts_with("argument 4"), "{}", details);
+            }
+            ref kind => panic!("expected call data mismatch, got {:?}", kind),
+        }
+        // a plain withdraw isn't a withdraw to the payout address
+        let withdraw = home.functions().withdraw().input(
+            signatures.iter().map(|x| x.v),
+            signatures.iter().map(|x| x.r),
+            signatures.iter().map(|x| x.s),
+            message,
+        );
+        assert!(verify_call_data(&expected, &withdraw).is_err());
+    }
+}
@@ -0,0 +1,436 @@
+use std::cmp;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::{Async, Future, Poll};
+use futures::future::{join_all, Join, JoinAll};
+use web3::Transport;
+use web3::types::{Log, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use database::Database;
+use error::Error;
+use util::web3_filter;
+
+/// blocks at the start of a backlog the events are counted in.
+const SAMPLE_BLOCKS: u64 = 1_000;
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000 + u64::from(duration.subsec_nanos()) / 1_000_000
+}
+
+/// Blocks of a chain the bridge hasn't processed yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ChainBacklog {
+    /// first unprocessed block
+    pub from: u64,
+    /// last confirmed block. below `from` if there is no backlog
+    pub to: u64,
+    /// blocks at the start of the backlog the events were counted in
+    pub sampled_blocks: u64,
+    /// events found in the sampled blocks
+    pub sampled_events: u64,
+    /// `sampled_events` extrapolated to the whole backlog
+    pub estimated_events: u64,
+}
+
+impl ChainBacklog {
+    /// backlog of the blocks after the checked block `checked` up to the confirmed block `to`.
+    fn new(checked: u64, to: u64) -> Self {
+        let from = checked + 1;
+        ChainBacklog {
+            from,
+            to,
+            sampled_blocks: if to < from {
+                0
+            } else {
+                cmp::min(SAMPLE_BLOCKS, to - from + 1)
+            },
+            ..ChainBacklog::default()
+        }
+    }
+
+    pub fn blocks(&self) -> u64 {
+        (self.to + 1).saturating_sub(self.from)
+    }
+
+    /// last block of the sample. only meaningful if `sampled_blocks` isn't 0
+    fn sample_to(&self) -> u64 {
+        self.from + self.sampled_blocks - 1
+    }
+
+    /// records `events` found in the sampled blocks and extrapolates them to the backlog.
+    fn sampled(&mut self, events: u64) {
+        self.sampled_events = events;
+        self.estimated_events = match self.sampled_blocks {
+            0 => 0,
+            sampled => events * self.blocks() / sampled,
+        };
+    }
+
+    /// unprocessed blocks left once the bridge checked `checked`.
+    pub fn left(&self, checked: u64) -> u64 {
+        (self.to + 1).saturating_sub(cmp::max(self.from, checked + 1))
+    }
+}
+
+/// Expected work of the bridge before it relays live events, estimated at startup.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CatchUpEstimate {
+    /// blocks deposit relay hasn't processed. events are `HomeBridge.Deposit`
+    pub home: ChainBacklog,
+    /// blocks the slower of withdraw relay and withdraw confirm hasn't processed.
+    /// events are `ForeignBridge.Withdraw` and `ForeignBridge.CollectedSignatures`
+    pub foreign: ChainBacklog,
+    /// blocks per second the sample was fetched at
+    pub blocks_per_sec: u64,
+    /// transactions relayed at once, `relay_scheduling.max_in_flight`.
+    /// `None` if all transactions of a batch are sent at once
+    pub concurrency: Option<usize>,
+    /// seconds until the backlog is expected to be processed
+    pub projected_secs: u64,
+}
+
+impl CatchUpEstimate {
+    /// unprocessed blocks of the chain that is further behind.
+    pub fn max_blocks(&self) -> u64 {
+        cmp::max(self.home.blocks(), self.foreign.blocks())
+    }
+
+    pub fn is_caught_up(&self) -> bool {
+        self.home.blocks() == 0 && self.foreign.blocks() == 0
+    }
+
+    /// progress of catching up once `checked` is processed, `elapsed` after the estimate.
+    /// the remaining time is extrapolated from the blocks processed so far,
+    /// taken from the estimate until a block was processed.
+    pub fn progress(&self, checked: &Database, elapsed: Duration) -> CatchUpProgress {
+        let home_blocks_left = self.home.left(checked.checked_deposit_relay);
+        let foreign_blocks_left = self.foreign.left(cmp::min(
+            checked.checked_withdraw_relay,
+            checked.checked_withdraw_confirm,
+        ));
+        let total = self.home.blocks() + self.foreign.blocks();
+        let left = home_blocks_left + foreign_blocks_left;
+        let done = total - left;
+        let eta_secs = if done == 0 {
+            self.projected_secs
+        } else {
+            millis(elapsed) * left / done / 1_000
+        };
+        CatchUpProgress {
+            home_blocks_left,
+            foreign_blocks_left,
+            eta_secs,
+        }
+    }
+}
+
+/// seconds to process `home` and `foreign` if `requests` requests fetched the samples
+/// in `elapsed`: the blocks are fetched at the rate of the sample and every round of
+/// `concurrency` relays takes a request.
+fn project(
+    home: &ChainBacklog,
+    foreign: &ChainBacklog,
+    elapsed: Duration,
+    requests: u64,
+    concurrency: Option<usize>,
+) -> u64 {
+    let sampled = home.sampled_blocks + foreign.sampled_blocks;
+    if sampled == 0 || requests == 0 {
+        return 0;
+    }
+    let elapsed = millis(elapsed);
+    let fetch = (home.blocks() + foreign.blocks()) * elapsed / sampled;
+    let events = home.estimated_events + foreign.estimated_events;
+    let rounds = match concurrency {
+        Some(concurrency) => (events + concurrency as u64 - 1) / concurrency as u64,
+        None => cmp::min(events, 1),
+    };
+    (fetch + rounds * elapsed / requests) / 1_000
+}
+
+impl fmt::Display for CatchUpEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_caught_up() {
+            return write!(f, "catch-up estimate: no unprocessed blocks");
+        }
+        writeln!(f, "catch-up estimate:")?;
+        for &(chain, ref backlog, events) in &[
+            ("home", self.home, "deposits"),
+            ("foreign", self.foreign, "withdraws and signatures"),
+        ] {
+            writeln!(
+                f,
+                "  {}: {} unprocessed blocks {}..={}, about {} {} ({} in the first {} blocks)",
+                chain,
+                backlog.blocks(),
+                backlog.from,
+                backlog.to,
+                backlog.estimated_events,
+                events,
+                backlog.sampled_events,
+                backlog.sampled_blocks
+            )?;
+        }
+        let concurrency = match self.concurrency {
+            Some(concurrency) => format!("{} transactions in flight", concurrency),
+            None => "all transactions of a batch sent at once".into(),
+        };
+        write!(
+            f,
+            "  projected: {}s at {} blocks/s with {}",
+            self.projected_secs, self.blocks_per_sec, concurrency
+        )
+    }
+}
+
+/// Blocks left to catch up and the expected time until then, part of the digest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct CatchUpProgress {
+    pub home_blocks_left: u64,
+    pub foreign_blocks_left: u64,
+    pub eta_secs: u64,
+}
+
+enum EstimateState<T: Transport> {
+    /// Fetching the block numbers of both chains.
+    FetchBlockNumbers(Join<Timeout<ApiCall<U256, T::Out>>, Timeout<ApiCall<U256, T::Out>>>),
+    /// Fetching the logs of the sampled blocks.
+    FetchSamples {
+        future: JoinAll<Vec<Timeout<ApiCall<Vec<Log>, T::Out>>>>,
+        /// `true` for every request of `future` to home
+        on_home: Vec<bool>,
+        started: Instant,
+    },
+}
+
+/// estimates the backlog of the checked blocks of `init` up to `required_confirmations`
+/// below the heads. the events are counted in the first `SAMPLE_BLOCKS` blocks.
+pub fn estimate_catch_up<T: Transport>(app: Arc<App<T>>, init: &Database) -> EstimateCatchUp<T> {
+    let home = api::timeout(
+        &app.timer,
+        api::block_number(&app.connections.home),
+        "home",
+        app.config.home.request_timeout,
+    );
+    let foreign = api::timeout(
+        &app.timer,
+        api::block_number(&app.connections.foreign),
+        "foreign",
+        app.config.foreign.request_timeout,
+    );
+    EstimateCatchUp {
+        app,
+        init: init.clone(),
+        home: ChainBacklog::default(),
+        foreign: ChainBacklog::default(),
+        state: EstimateState::FetchBlockNumbers(home.join(foreign)),
+    }
+}
+
+/// Future resolving to the `CatchUpEstimate` of a database.
+pub struct EstimateCatchUp<T: Transport> {
+    app: Arc<App<T>>,
+    init: Database,
+    home: ChainBacklog,
+    foreign: ChainBacklog,
+    state: EstimateState<T>,
+}
+
+impl<T: Transport> EstimateCatchUp<T> {
+    /// requests for the logs of the events in the sampled blocks.
+    fn sample_requests(&self) -> (Vec<Timeout<ApiCall<Vec<Log>, T::Out>>>, Vec<bool>) {
+        let app = &self.app;
+        let home_events = app.home_bridge.events();
+        let foreign_events = app.foreign_bridge.events();
+        let home_address = self.init.home_contract_address;
+        let foreign_address = self.init.foreign_contract_address;
+        let filters = vec![
+            (true, web3_filter(home_events.deposit().create_filter(), home_address)),
+            (
+                false,
+                web3_filter(foreign_events.withdraw().create_filter(), foreign_address),
+            ),
+            (
+                false,
+                web3_filter(
+                    foreign_events.collected_signatures().create_filter(),
+                    foreign_address,
+                ),
+            ),
+        ];
+        let mut requests = Vec::new();
+        let mut on_home = Vec::new();
+        for (home, filter) in filters {
+            let (transport, chain, node, backlog) = if home {
+                (&app.connections.home, "home", &app.config.home, &self.home)
+            } else {
+                (&app.connections.foreign, "foreign", &app.config.foreign, &self.foreign)
+            };
+            if backlog.sampled_blocks == 0 {
+                continue;
+            }
+            let filter = filter
+                .from_block(backlog.from.into())
+                .to_block(backlog.sample_to().into())
+                .build();
+            requests.push(api::timeout(
+                &app.timer,
+                api::logs(transport, &filter),
+                chain,
+                node.request_timeout,
+            ));
+            on_home.push(home);
+        }
+        (requests, on_home)
+    }
+}
+
+impl<T: Transport> Future for EstimateCatchUp<T> {
+    type Item = CatchUpEstimate;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                EstimateState::FetchBlockNumbers(ref mut future) => {
+                    let (home, foreign) = try_ready!(future.poll());
+                    let home_head = home.low_u64()
+                        .saturating_sub(self.app.config.home.required_confirmations as u64);
+                    let foreign_head = foreign
+                        .low_u64()
+                        .saturating_sub(self.app.config.foreign.required_confirmations as u64);
+                    self.home = ChainBacklog::new(self.init.checked_deposit_relay, home_head);
+                    self.foreign = ChainBacklog::new(
+                        cmp::min(
+                            self.init.checked_withdraw_relay,
+                            self.init.checked_withdraw_confirm,
+                        ),
+                        foreign_head,
+                    );
+                    let (requests, on_home) = self.sample_requests();
+                    EstimateState::FetchSamples {
+                        future: join_all(requests),
+                        on_home,
+                        started: Instant::now(),
+                    }
+                }
+                EstimateState::FetchSamples {
+                    ref mut future,
+                    ref on_home,
+                    started,
+                } => {
+                    let samples = try_ready!(future.poll());
+                    let elapsed = started.elapsed();
+                    let events = |home: bool| {
+                        samples
+                            .iter()
+                            .zip(on_home.iter())
+                            .filter(|&(_, on_home)| *on_home == home)
+                            .map(|(logs, _)| logs.len() as u64)
+                            .sum::<u64>()
+                    };
+                    self.home.sampled(events(true));
+                    self.foreign.sampled(events(false));
+                    let concurrency = self.app
+                        .config
+                        .relay_scheduling
+                        .as_ref()
+                        .map(|scheduling| scheduling.max_in_flight);
+                    let sampled = self.home.sampled_blocks + self.foreign.sampled_blocks;
+                    return Ok(Async::Ready(CatchUpEstimate {
+                        home: self.home,
+                        foreign: self.foreign,
+                        blocks_per_sec: sampled * 1_000 / cmp::max(millis(elapsed), 1),
+                        concurrency,
+                        projected_secs: project(
+                            &self.home,
+                            &self.foreign,
+                            elapsed,
+                            samples.len() as u64,
+                            concurrency,
+                        ),
+                    }));
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use database::Database;
+    use super::{project, CatchUpEstimate, ChainBacklog};
+
+    fn backlog(checked: u64, to: u64, events: u64) -> ChainBacklog {
+        let mut backlog = ChainBacklog::new(checked, to);
+        backlog.sampled(events);
+        backlog
+    }
+
+    #[test]
+    fn test_backlog_extrapolates_sampled_events() {
+        let long = backlog(999, 10_999, 3);
+        assert_eq!(1_000, long.from);
+        assert_eq!(10_000, long.blocks());
+        assert_eq!(1_000, long.sampled_blocks);
+        assert_eq!(30, long.estimated_events);
+
+        // shorter than a sample
+        let short = backlog(10, 19, 4);
+        assert_eq!(9, short.blocks());
+        assert_eq!(9, short.sampled_blocks);
+        assert_eq!(4, short.estimated_events);
+
+        // checked beyond the confirmed block
+        let empty = backlog(20, 19, 0);
+        assert_eq!(0, empty.blocks());
+        assert_eq!(0, empty.sampled_blocks);
+        assert_eq!(0, empty.estimated_events);
+        assert_eq!(0, empty.left(0));
+    }
+
+    #[test]
+    fn test_project() {
+        let home = backlog(0, 10_000, 10);
+        let foreign = backlog(0, 0, 0);
+        let elapsed = Duration::from_secs(2);
+        // 10_000 blocks at 500 blocks/s and 100 relays in rounds of 10, 1s each
+        assert_eq!(30, project(&home, &foreign, elapsed, 2, Some(10)));
+        // all relays at once
+        assert_eq!(21, project(&home, &foreign, elapsed, 2, None));
+        assert_eq!(0, project(&foreign, &foreign, elapsed, 0, None));
+    }
+
+    #[test]
+    fn test_progress() {
+        let estimate = CatchUpEstimate {
+            home: backlog(100, 1_100, 0),
+            foreign: backlog(200, 1_200, 0),
+            blocks_per_sec: 100,
+            concurrency: None,
+            projected_secs: 40,
+        };
+        assert_eq!(1_000, estimate.max_blocks());
+        let mut checked = Database {
+            checked_deposit_relay: 100,
+            checked_withdraw_relay: 200,
+            checked_withdraw_confirm: 200,
+            ..Database::default()
+        };
+        let progress = estimate.progress(&checked, Duration::from_secs(5));
+        assert_eq!(1_000, progress.home_blocks_left);
+        assert_eq!(40, progress.eta_secs);
+
+        checked.checked_deposit_relay = 1_100;
+        checked.checked_withdraw_relay = 700;
+        checked.checked_withdraw_confirm = 1_000;
+        let progress = estimate.progress(&checked, Duration::from_secs(30));
+        assert_eq!(0, progress.home_blocks_left);
+        assert_eq!(500, progress.foreign_blocks_left);
+        assert_eq!(10, progress.eta_secs);
+    }
+}
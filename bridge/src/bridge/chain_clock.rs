@@ -0,0 +1,484 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use futures::{Async, Future, Poll, Stream};
+use futures::future::{join_all, Join, JoinAll};
+use timer::Interval;
+use web3::Transport;
+use web3::types::{Block, H256, U256};
+use api::{self, ApiCall, Settle, Timeout};
+use app::App;
+use config::ChainClockConfig;
+use error::Error;
+use super::clock_drift::unix_now;
+
+/// number of headers a `ChainClock` keeps.
+/// blocks older than the kept headers are timed by their own timestamp.
+pub const KEPT_HEADERS: usize = 1024;
+
+/// median of `values`, the mean of the middle two if their number is even.
+/// `None` if there are no values.
+fn median(values: &mut Vec<i64>) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort();
+    let middle = values.len() / 2;
+    if values.len() % 2 == 1 {
+        Some(values[middle])
+    } else {
+        Some(values[middle - 1] + (values[middle] - values[middle - 1]) / 2)
+    }
+}
+
+/// Timestamps of the recent headers of a chain, see `ChainClockConfig`.
+///
+/// a block is timed by the headers of the `blocks` blocks up to it. the block interval is
+/// the median of the intervals between every two of the headers. every header is projected
+/// to the block by that interval and the median of the projections is the time of the block.
+/// validators skewing the timestamps of less than a quarter of the headers move neither
+/// median, and a regular chain is timed by its own timestamps.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChainClock {
+    /// timestamps of the most recent `KEPT_HEADERS` headers by block number
+    headers: BTreeMap<u64, u64>,
+    /// local unix time the headers were last fetched. `None` until they are
+    updated: Option<u64>,
+}
+
+impl ChainClock {
+    /// records the `timestamp` of header `block`. replaces the timestamp of a reorged block.
+    pub fn record(&mut self, block: u64, timestamp: u64) {
+        self.headers.insert(block, timestamp);
+        while self.headers.len() > KEPT_HEADERS {
+            let oldest = *self.headers
+                .keys()
+                .next()
+                .expect("more than KEPT_HEADERS headers are kept; qed");
+            self.headers.remove(&oldest);
+        }
+    }
+
+    /// the headers were fetched up to `head` at local unix time `now`.
+    /// headers above `head` were reorged away and are dropped.
+    pub fn update(&mut self, head: u64, now: u64) {
+        let reorged = self.headers
+            .range(head + 1..)
+            .map(|(block, _)| *block)
+            .collect::<Vec<_>>();
+        for block in reorged {
+            self.headers.remove(&block);
+        }
+        self.updated = Some(now);
+    }
+
+    /// the most recent header kept.
+    pub fn latest(&self) -> Option<u64> {
+        self.headers.keys().next_back().cloned()
+    }
+
+    /// time of `block` by the headers of the `blocks` blocks up to it.
+    /// `None` unless more than half of them are kept.
+    pub fn median_time(&self, block: u64, blocks: u64) -> Option<u64> {
+        let first = (block + 1).saturating_sub(blocks);
+        let window = self.headers
+            .range(first..block + 1)
+            .map(|(number, timestamp)| (*number as i64, *timestamp as i64))
+            .collect::<Vec<_>>();
+        if window.len() as u64 * 2 <= blocks {
+            return None;
+        }
+        // block interval in milliseconds, the median slope of all pairs of headers
+        let mut intervals = Vec::new();
+        for (index, &(number, timestamp)) in window.iter().enumerate() {
+            for &(later_number, later_timestamp) in &window[index + 1..] {
+                intervals.push((later_timestamp - timestamp) * 1000 / (later_number - number));
+            }
+        }
+        let interval = median(&mut intervals).unwrap_or(0).max(0);
+        let mut projections = window
+            .iter()
+            .map(|&(number, timestamp)| timestamp * 1000 + (block as i64 - number) * interval)
+            .collect::<Vec<_>>();
+        median(&mut projections).map(|time| time.max(0) as u64 / 1000)
+    }
+
+    /// time of `block` with `timestamp` at local unix time `now`. the `timestamp` of the block
+    /// unless the headers were fetched within `max_staleness` and enough of them are kept.
+    pub fn time_of(&self, config: &ChainClockConfig, block: u64, timestamp: u64, now: u64) -> u64 {
+        match self.updated {
+            Some(updated) if now.saturating_sub(updated) <= config.max_staleness.as_secs() => {
+                self.median_time(block, config.blocks).unwrap_or(timestamp)
+            }
+            _ => timestamp,
+        }
+    }
+}
+
+/// `ChainClock` of a chain shared by the features timing its blocks.
+#[derive(Debug, Default, Clone)]
+pub struct SharedChainClock {
+    inner: Arc<RwLock<ChainClock>>,
+}
+
+impl SharedChainClock {
+    pub fn get(&self) -> ChainClock {
+        self.inner
+            .read()
+            .expect("no code panics while holding the lock; qed")
+            .clone()
+    }
+
+    fn modify<F: FnOnce(&mut ChainClock)>(&self, modify: F) {
+        modify(&mut *self.inner
+            .write()
+            .expect("no code panics while holding the lock; qed"));
+    }
+
+    /// time of `block` with `timestamp`, see `ChainClock::time_of`.
+    /// the `timestamp` of the block if `chain_clock` isn't configured.
+    pub fn time_of(&self, config: Option<&ChainClockConfig>, block: u64, timestamp: u64) -> u64 {
+        match config {
+            Some(config) => self.inner
+                .read()
+                .expect("no code panics while holding the lock; qed")
+                .time_of(config, block, timestamp, unix_now()),
+            None => timestamp,
+        }
+    }
+}
+
+/// Chain clocks of both chains.
+#[derive(Debug, Default, Clone)]
+pub struct ChainClocks {
+    pub home: SharedChainClock,
+    pub foreign: SharedChainClock,
+}
+
+/// blocks after `latest` up to `head` whose headers are fetched.
+/// at most the `blocks` blocks up to `head`, older blocks aren't in the window of the head.
+fn missing_blocks(latest: Option<u64>, head: u64, blocks: u64) -> Vec<u64> {
+    let from = (head + 1).saturating_sub(blocks);
+    let from = latest.map_or(from, |latest| from.max(latest + 1));
+    (from..head + 1).collect()
+}
+
+type HeadFuture<T> = Settle<Timeout<ApiCall<U256, <T as Transport>::Out>>>;
+
+type HeadersFuture<T> = JoinAll<Vec<Settle<Timeout<ApiCall<Block<H256>, <T as Transport>::Out>>>>>;
+
+enum ChainClockState<T: Transport> {
+    /// Waiting for the next update.
+    Wait,
+    /// Fetching the heads of both chains.
+    FetchHeads(Join<HeadFuture<T>, HeadFuture<T>>),
+    /// Fetching the headers after the kept ones up to the heads.
+    FetchHeaders {
+        future: Join<HeadersFuture<T>, HeadersFuture<T>>,
+        /// head and fetched blocks of home and foreign. `None` if its head failed
+        home: Option<(u64, Vec<u64>)>,
+        foreign: Option<(u64, Vec<u64>)>,
+    },
+}
+
+pub fn create_chain_clock_monitor<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    interval: Interval,
+    config: ChainClockConfig,
+) -> ChainClockMonitor<T> {
+    ChainClockMonitor {
+        app,
+        interval,
+        config,
+        state: ChainClockState::Wait,
+    }
+}
+
+/// Periodically fetches the new headers of both chains into the `ChainClocks` of the app.
+/// yields once the clocks are updated.
+///
+/// a chain whose requests fail keeps its headers, once they are older than `max_staleness`
+/// its blocks are timed by their own timestamps.
+pub struct ChainClockMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    interval: Interval,
+    config: ChainClockConfig,
+    state: ChainClockState<T>,
+}
+
+fn fetch_head<T: Transport>(
+    app: &App<T>,
+    transport: &T,
+    chain: &'static str,
+    timeout: Duration,
+) -> HeadFuture<T> {
+    api::settle(api::timeout(&app.timer, api::block_number(transport), chain, timeout))
+}
+
+fn fetch_headers<T: Transport>(
+    app: &App<T>,
+    transport: &T,
+    chain: &'static str,
+    timeout: Duration,
+    blocks: &[u64],
+) -> HeadersFuture<T> {
+    let requests = blocks
+        .iter()
+        .map(|block| {
+            api::settle(api::timeout(
+                &app.timer,
+                api::block_by_number(transport, *block),
+                chain,
+                timeout,
+            ))
+        })
+        .collect();
+    join_all(requests)
+}
+
+/// the head of `chain` and the blocks whose headers `clock` is missing.
+/// `None` with a warning if the head failed.
+fn missing(
+    clock: &SharedChainClock,
+    chain: &str,
+    head: Result<U256, Error>,
+    blocks: u64,
+) -> Option<(u64, Vec<u64>)> {
+    match head {
+        Ok(head) => {
+            let head = head.low_u64();
+            Some((head, missing_blocks(clock.get().latest(), head, blocks)))
+        }
+        Err(err) => {
+            warn!(
+                target: "bridge::chain_clock",
+                "cannot fetch the head of {}, its chain clock isn't updated: {}",
+                chain,
+                err
+            );
+            None
+        }
+    }
+}
+
+/// records the fetched headers in `clock`. the clock is updated unless a header failed.
+fn record_headers(
+    clock: &SharedChainClock,
+    chain: &str,
+    (head, blocks): (u64, Vec<u64>),
+    headers: Vec<Result<Block<H256>, Error>>,
+    now: u64,
+) {
+    clock.modify(|clock| {
+        for (block, header) in blocks.iter().zip(headers) {
+            match header {
+                Ok(header) => clock.record(*block, header.timestamp.low_u64()),
+                Err(err) => {
+                    warn!(
+                        target: "bridge::chain_clock",
+                        "cannot fetch {} block {}, its chain clock isn't updated: {}",
+                        chain,
+                        block,
+                        err
+                    );
+                    return;
+                }
+            }
+        }
+        clock.update(head, now);
+    });
+}
+
+impl<T: Transport> Stream for ChainClockMonitor<T> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let mut updated = false;
+            let next_state = match self.state {
+                ChainClockState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    let app = &self.app;
+                    let home = fetch_head(
+                        app,
+                        &app.connections.home,
+                        "home",
+                        app.config.home.request_timeout,
+                    );
+                    let foreign = fetch_head(
+                        app,
+                        &app.connections.foreign,
+                        "foreign",
+                        app.config.foreign.request_timeout,
+                    );
+                    ChainClockState::FetchHeads(home.join(foreign))
+                }
+                ChainClockState::FetchHeads(ref mut future) => {
+                    let (home, foreign) = try_ready!(future.poll());
+                    let app = &self.app;
+                    let home = missing(&app.chain_clocks.home, "home", home, self.config.blocks);
+                    let foreign = missing(
+                        &app.chain_clocks.foreign,
+                        "foreign",
+                        foreign,
+                        self.config.blocks,
+                    );
+                    let home_headers = fetch_headers(
+                        app,
+                        &app.connections.home,
+                        "home",
+                        app.config.home.request_timeout,
+                        home.as_ref().map_or(&[][..], |&(_, ref blocks)| &blocks[..]),
+                    );
+                    let foreign_headers = fetch_headers(
+                        app,
+                        &app.connections.foreign,
+                        "foreign",
+                        app.config.foreign.request_timeout,
+                        foreign.as_ref().map_or(&[][..], |&(_, ref blocks)| &blocks[..]),
+                    );
+                    ChainClockState::FetchHeaders {
+                        future: home_headers.join(foreign_headers),
+                        home,
+                        foreign,
+                    }
+                }
+                ChainClockState::FetchHeaders {
+                    ref mut future,
+                    ref mut home,
+                    ref mut foreign,
+                } => {
+                    let (home_headers, foreign_headers) = try_ready!(future.poll());
+                    let now = unix_now();
+                    let clocks = &self.app.chain_clocks;
+                    if let Some(home) = home.take() {
+                        record_headers(&clocks.home, "home", home, home_headers, now);
+                    }
+                    if let Some(foreign) = foreign.take() {
+                        record_headers(&clocks.foreign, "foreign", foreign, foreign_headers, now);
+                    }
+                    updated = true;
+                    ChainClockState::Wait
+                }
+            };
+            self.state = next_state;
+            if updated {
+                return Ok(Async::Ready(Some(())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use config::ChainClockConfig;
+    use super::{missing_blocks, ChainClock, KEPT_HEADERS};
+
+    fn config(blocks: u64) -> ChainClockConfig {
+        ChainClockConfig {
+            blocks,
+            check_interval: Duration::from_secs(15),
+            max_staleness: Duration::from_secs(300),
+        }
+    }
+
+    /// clock of blocks `first..` with `timestamps`.
+    fn clock(first: u64, timestamps: &[u64]) -> ChainClock {
+        let mut clock = ChainClock::default();
+        for (index, timestamp) in timestamps.iter().enumerate() {
+            clock.record(first + index as u64, *timestamp);
+        }
+        clock.update(first + timestamps.len() as u64 - 1, 10_000);
+        clock
+    }
+
+    #[test]
+    fn test_chain_clock_times_a_regular_chain_by_its_timestamps() {
+        let timestamps = (0..20).map(|index| 5000 + 5 * index).collect::<Vec<_>>();
+        let clock = clock(100, &timestamps);
+        for block in 110..120 {
+            assert_eq!(Some(5000 + 5 * (block - 100)), clock.median_time(block, 11));
+        }
+        // irregular block times are evened out
+        let clock = clock(100, &[5000, 5004, 5010, 5016, 5020]);
+        assert_eq!(Some(5020), clock.median_time(104, 5));
+    }
+
+    #[test]
+    fn test_chain_clock_ignores_a_skewed_minority() {
+        let mut timestamps = (0..11).map(|index| 5000 + 5 * index).collect::<Vec<_>>();
+        // a validator stamps its blocks an hour ahead, one back at the epoch
+        timestamps[3] += 3600;
+        timestamps[7] += 3600;
+        timestamps[9] = 0;
+        let clock = clock(100, &timestamps);
+        assert_eq!(Some(5050), clock.median_time(110, 11));
+
+        // the timed block itself is skewed
+        let mut timestamps = (0..11).map(|index| 5000 + 5 * index).collect::<Vec<_>>();
+        timestamps[10] = 1_000_000;
+        let clock = clock(100, &timestamps);
+        assert_eq!(Some(5050), clock.median_time(110, 11));
+
+        // out of order timestamps
+        let clock = clock(100, &[5000, 5015, 5010, 5005, 5020, 5025, 5030]);
+        assert_eq!(Some(5030), clock.median_time(106, 7));
+    }
+
+    #[test]
+    fn test_chain_clock_never_goes_backwards_by_interval() {
+        // timestamps running backwards don't project blocks into the past
+        let clock = clock(100, &[5040, 5030, 5020, 5010, 5000]);
+        assert_eq!(Some(5020), clock.median_time(104, 5));
+        let clock = clock(100, &[0, 0, 0]);
+        assert_eq!(Some(0), clock.median_time(102, 3));
+    }
+
+    #[test]
+    fn test_chain_clock_falls_back_to_the_block_timestamp() {
+        let timestamps = (0..11).map(|index| 5000 + 5 * index).collect::<Vec<_>>();
+        let clock = clock(100, &timestamps);
+        let config = config(11);
+        assert_eq!(5050, clock.time_of(&config, 110, 9999, 10_000));
+        // the window of block 94 has too few headers
+        assert_eq!(9999, clock.time_of(&config, 94, 9999, 10_000));
+        // a block past the kept headers is projected while most of its window is kept
+        assert_eq!(5055, clock.time_of(&config, 111, 9999, 10_000));
+        assert_eq!(9999, clock.time_of(&config, 116, 9999, 10_000));
+        // the headers are stale
+        assert_eq!(5050, clock.time_of(&config, 110, 9999, 10_300));
+        assert_eq!(9999, clock.time_of(&config, 110, 9999, 10_301));
+        // nothing fetched yet
+        assert_eq!(9999, ChainClock::default().time_of(&config, 110, 9999, 10_000));
+    }
+
+    #[test]
+    fn test_chain_clock_drops_reorged_and_old_headers() {
+        let mut clock = clock(100, &[5000, 5005, 5010, 5015, 5020]);
+        // the chain reorged to a lower head
+        clock.update(102, 10_000);
+        assert_eq!(Some(102), clock.latest());
+        clock.record(103, 5016);
+        assert_eq!(Some(5016), clock.median_time(103, 1));
+
+        let mut clock = ChainClock::default();
+        for block in 0..KEPT_HEADERS as u64 + 10 {
+            clock.record(block, block);
+        }
+        assert_eq!(None, clock.median_time(9, 1));
+        assert_eq!(Some(10), clock.median_time(10, 1));
+    }
+
+    #[test]
+    fn test_missing_blocks() {
+        assert_eq!((90..101).collect::<Vec<_>>(), missing_blocks(None, 100, 11));
+        assert_eq!(vec![98, 99, 100], missing_blocks(Some(97), 100, 11));
+        // blocks out of the window of the head aren't fetched
+        assert_eq!((90..101).collect::<Vec<_>>(), missing_blocks(Some(10), 100, 11));
+        assert!(missing_blocks(Some(100), 100, 11).is_empty());
+        assert!(missing_blocks(Some(105), 100, 11).is_empty());
+        assert_eq!(vec![0, 1, 2], missing_blocks(None, 2, 11));
+    }
+}
@@ -0,0 +1,234 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::{Future, Poll, Stream};
+use futures::future::Join;
+use timer::Interval;
+use web3::Transport;
+use web3::types::U256;
+use api::{self, ApiCall, Timeout};
+use app::App;
+use error::Error;
+
+/// Time since the block number of a chain last increased.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockAge {
+    pub age: Duration,
+    /// `true` if `age` exceeds the stall timeout
+    pub stalled: bool,
+}
+
+impl Default for BlockAge {
+    fn default() -> Self {
+        BlockAge {
+            age: Duration::from_secs(0),
+            stalled: false,
+        }
+    }
+}
+
+/// Last measured block age of both chains.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BlockAges {
+    pub home: BlockAge,
+    pub foreign: BlockAge,
+}
+
+/// Change of the stall condition of a chain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StallChange {
+    /// no block for the given time
+    Stalled(Duration),
+    /// a new block arrived after the given time
+    Recovered(Duration),
+}
+
+/// Tracks when the block number of a chain last increased.
+#[derive(Debug)]
+pub struct BlockProgress {
+    block: u64,
+    since: Instant,
+    stalled: bool,
+}
+
+impl BlockProgress {
+    pub fn new(now: Instant) -> Self {
+        BlockProgress {
+            block: 0,
+            since: now,
+            stalled: false,
+        }
+    }
+
+    /// records that the chain is at `block` at `now`.
+    /// returns the change of the stall condition, if any.
+    pub fn record(&mut self, block: u64, now: Instant, timeout: Duration) -> Option<StallChange> {
+        let age = now.duration_since(self.since);
+        if block > self.block {
+            self.block = block;
+            self.since = now;
+            if self.stalled {
+                self.stalled = false;
+                return Some(StallChange::Recovered(age));
+            }
+        } else if !self.stalled && age > timeout {
+            self.stalled = true;
+            return Some(StallChange::Stalled(age));
+        }
+        None
+    }
+
+    pub fn age(&self, now: Instant) -> BlockAge {
+        BlockAge {
+            age: now.duration_since(self.since),
+            stalled: self.stalled,
+        }
+    }
+}
+
+/// logs `change` of the stall condition of `chain`.
+fn log_stall_change(chain: &str, block: u64, change: StallChange) {
+    match change {
+        StallChange::Stalled(age) => error!(
+            "{} chain stalled: no new block for {} seconds. last block: {}",
+            chain,
+            age.as_secs(),
+            block
+        ),
+        StallChange::Recovered(age) => info!(
+            "{} chain recovered: block {} after {} seconds without a new block",
+            chain,
+            block,
+            age.as_secs()
+        ),
+    }
+}
+
+enum ChainStallState<T: Transport> {
+    /// Waiting for the next check.
+    Wait,
+    /// Fetching block numbers of both chains.
+    FetchBlockNumbers(Join<Timeout<ApiCall<U256, T::Out>>, Timeout<ApiCall<U256, T::Out>>>),
+    /// Block ages have been measured.
+    Yield(Option<BlockAges>),
+}
+
+pub fn create_chain_stall_monitor<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    interval: Interval,
+    stall_timeout: Duration,
+) -> ChainStallMonitor<T> {
+    let now = Instant::now();
+    ChainStallMonitor {
+        app,
+        interval,
+        stall_timeout,
+        home: BlockProgress::new(now),
+        foreign: BlockProgress::new(now),
+        state: ChainStallState::Wait,
+    }
+}
+
+/// Periodically checks the block numbers of both chains and logs an error
+/// if either hasn't produced a block for `stall_timeout`.
+/// recovery is logged as well.
+pub struct ChainStallMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    interval: Interval,
+    stall_timeout: Duration,
+    home: BlockProgress,
+    foreign: BlockProgress,
+    state: ChainStallState<T>,
+}
+
+impl<T: Transport> Stream for ChainStallMonitor<T> {
+    type Item = BlockAges;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                ChainStallState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    let home = api::timeout(
+                        &self.app.timer,
+                        api::block_number(&self.app.connections.home),
+                        "home",
+                        self.app.config.home.request_timeout,
+                    );
+                    let foreign = api::timeout(
+                        &self.app.timer,
+                        api::block_number(&self.app.connections.foreign),
+                        "foreign",
+                        self.app.config.foreign.request_timeout,
+                    );
+                    ChainStallState::FetchBlockNumbers(home.join(foreign))
+                }
+                ChainStallState::FetchBlockNumbers(ref mut future) => {
+                    let (home, foreign) = try_ready!(future.poll());
+                    let (home, foreign) = (home.low_u64(), foreign.low_u64());
+                    let now = Instant::now();
+                    if let Some(change) = self.home.record(home, now, self.stall_timeout) {
+                        log_stall_change("home", home, change);
+                    }
+                    if let Some(change) = self.foreign.record(foreign, now, self.stall_timeout) {
+                        log_stall_change("foreign", foreign, change);
+                    }
+                    let ages = BlockAges {
+                        home: self.home.age(now),
+                        foreign: self.foreign.age(now),
+                    };
+                    debug!("block ages: {:?}", ages);
+                    ChainStallState::Yield(Some(ages))
+                }
+                ChainStallState::Yield(ref mut ages) => match ages.take() {
+                    None => ChainStallState::Wait,
+                    some => return Ok(some.into()),
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{BlockProgress, StallChange};
+
+    #[test]
+    fn test_block_progress_stall_and_recovery() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let timeout = Duration::from_secs(60);
+        let mut progress = BlockProgress::new(start);
+
+        assert_eq!(None, progress.record(100, at(0), timeout));
+        assert_eq!(None, progress.record(100, at(60), timeout));
+        assert_eq!(
+            Some(StallChange::Stalled(Duration::from_secs(61))),
+            progress.record(100, at(61), timeout)
+        );
+        // reported once
+        assert_eq!(None, progress.record(100, at(120), timeout));
+        assert!(progress.age(at(120)).stalled);
+        assert_eq!(Duration::from_secs(120), progress.age(at(120)).age);
+
+        assert_eq!(
+            Some(StallChange::Recovered(Duration::from_secs(130))),
+            progress.record(101, at(130), timeout)
+        );
+        assert!(!progress.age(at(130)).stalled);
+        assert_eq!(Duration::from_secs(5), progress.age(at(135)).age);
+    }
+
+    #[test]
+    fn test_block_progress_ignores_lower_blocks() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let mut progress = BlockProgress::new(start);
+        progress.record(100, start, timeout);
+        // a node behind a load balancer returned an older block
+        assert_eq!(None, progress.record(99, start + Duration::from_secs(10), timeout));
+        assert_eq!(Duration::from_secs(10), progress.age(start + Duration::from_secs(10)).age);
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+use futures::{Async, Stream};
+use timer::Interval;
+use config::CircuitBreakerConfig;
+use error::Error;
+use super::Component;
+
+/// State of a `CircuitBreaker`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CircuitState {
+    /// component runs normally.
+    Closed,
+    /// component has failed too often and is halted until `until`.
+    Open { until: Instant },
+    /// cool down elapsed. component runs until `probes_left` more relays succeed.
+    /// a single failure opens the circuit again.
+    HalfOpen { probes_left: u32 },
+    /// opened by the operator. only closed by the operator.
+    ForcedOpen,
+}
+
+/// Halts a component when it fails too often.
+///
+/// tracks the outcomes of the last `config.window` relays.
+/// the circuit opens once `config.max_failures` of them failed.
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    /// `true` for every failed relay
+    outcomes: VecDeque<bool>,
+    state: CircuitState,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        CircuitBreaker {
+            outcomes: VecDeque::with_capacity(config.window),
+            config,
+            state: CircuitState::Closed,
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state
+    }
+
+    fn failures(&self) -> usize {
+        self.outcomes.iter().filter(|failed| **failed).count()
+    }
+
+    fn push(&mut self, failed: bool) {
+        if self.outcomes.len() == self.config.window {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(failed);
+    }
+
+    /// returns `true` if the component may run at `now`.
+    /// moves an open circuit to half open once the cool down elapsed.
+    pub fn allows(&mut self, now: Instant) -> bool {
+        match self.state {
+            CircuitState::Closed | CircuitState::HalfOpen { .. } => true,
+            CircuitState::ForcedOpen => false,
+            CircuitState::Open { until } if now >= until => {
+                self.state = CircuitState::HalfOpen {
+                    probes_left: self.config.probes,
+                };
+                true
+            }
+            CircuitState::Open { .. } => false,
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.push(false);
+        if let CircuitState::HalfOpen { probes_left } = self.state {
+            if probes_left <= 1 {
+                self.outcomes.clear();
+                self.state = CircuitState::Closed;
+            } else {
+                self.state = CircuitState::HalfOpen {
+                    probes_left: probes_left - 1,
+                };
+            }
+        }
+    }
+
+    pub fn record_failure(&mut self, now: Instant) {
+        self.push(true);
+        let open = match self.state {
+            CircuitState::Closed => self.failures() >= self.config.max_failures,
+            CircuitState::HalfOpen { .. } => true,
+            CircuitState::Open { .. } | CircuitState::ForcedOpen => false,
+        };
+        if open {
+            self.state = CircuitState::Open {
+                until: now + self.config.cool_down,
+            };
+        }
+    }
+
+    pub fn force_open(&mut self) {
+        self.state = CircuitState::ForcedOpen;
+    }
+
+    pub fn force_close(&mut self) {
+        self.outcomes.clear();
+        self.state = CircuitState::Closed;
+    }
+}
+
+/// Circuit breakers of all bridge components.
+pub struct CircuitBreakers {
+    /// wakes up the bridge to check if open circuits cooled down
+    interval: Interval,
+    breakers: Vec<(Component, CircuitBreaker)>,
+    /// components which failed and need to be rebuilt before they can run again
+    failed: Vec<Component>,
+}
+
+impl CircuitBreakers {
+    pub fn new(interval: Interval, components: &[Component], config: &CircuitBreakerConfig) -> Self {
+        CircuitBreakers {
+            interval,
+            breakers: components
+                .iter()
+                .map(|c| (*c, CircuitBreaker::new(config.clone())))
+                .collect(),
+            failed: Vec::new(),
+        }
+    }
+
+    fn breaker_mut(&mut self, component: Component) -> &mut CircuitBreaker {
+        &mut self.breakers
+            .iter_mut()
+            .find(|entry| entry.0 == component)
+            .expect("circuit breakers are created for all components; qed")
+            .1
+    }
+
+    pub fn state(&self, component: Component) -> Option<CircuitState> {
+        self.breakers
+            .iter()
+            .find(|entry| entry.0 == component)
+            .map(|entry| entry.1.state())
+    }
+
+    /// returns `true` if `component` may be polled at `now`.
+    pub fn allows(&mut self, component: Component, now: Instant) -> bool {
+        !self.failed.contains(&component) && self.breaker_mut(component).allows(now)
+    }
+
+    pub fn record_success(&mut self, component: Component) {
+        let breaker = self.breaker_mut(component);
+        let was_closed = breaker.state() == CircuitState::Closed;
+        breaker.record_success();
+        if !was_closed && breaker.state() == CircuitState::Closed {
+            info!("circuit of {:?} closed", component);
+        }
+    }
+
+    pub fn record_failure(&mut self, component: Component, now: Instant) {
+        if !self.failed.contains(&component) {
+            self.failed.push(component);
+        }
+        let breaker = self.breaker_mut(component);
+        breaker.record_failure(now);
+        if let CircuitState::Open { .. } = breaker.state() {
+            error!(
+                "circuit of {:?} opened. no transactions are sent by it until the cool down elapsed",
+                component
+            );
+        }
+    }
+
+    pub fn force_open(&mut self, component: Component) {
+        warn!("circuit of {:?} forced open", component);
+        self.breaker_mut(component).force_open();
+    }
+
+    pub fn force_close(&mut self, component: Component) {
+        warn!("circuit of {:?} forced closed", component);
+        self.breaker_mut(component).force_close();
+    }
+
+    /// returns failed components which may run again and have to be rebuilt.
+    pub fn poll_rebuild(&mut self, now: Instant) -> Result<Vec<Component>, Error> {
+        while let Async::Ready(Some(())) = self.interval.poll()? {}
+
+        let failed = ::std::mem::replace(&mut self.failed, Vec::new());
+        let (rebuild, failed): (Vec<_>, Vec<_>) = failed
+            .into_iter()
+            .partition(|component| self.breaker_mut(*component).allows(now));
+        self.failed = failed;
+        Ok(rebuild)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use config::CircuitBreakerConfig;
+    use super::{CircuitBreaker, CircuitState};
+
+    fn config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            window: 4,
+            max_failures: 2,
+            cool_down: Duration::from_secs(10),
+            probes: 2,
+        }
+    }
+
+    #[test]
+    fn test_circuit_opens_after_max_failures_in_window() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::new(config());
+
+        breaker.record_failure(start);
+        breaker.record_success();
+        breaker.record_success();
+        breaker.record_success();
+        // first failure left the window
+        breaker.record_failure(start);
+        assert_eq!(CircuitState::Closed, breaker.state());
+        assert!(breaker.allows(start));
+
+        breaker.record_failure(start);
+        assert_eq!(
+            CircuitState::Open {
+                until: start + Duration::from_secs(10)
+            },
+            breaker.state()
+        );
+        assert!(!breaker.allows(start + Duration::from_secs(9)));
+    }
+
+    #[test]
+    fn test_circuit_closes_after_probes_succeed() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::new(config());
+        breaker.record_failure(start);
+        breaker.record_failure(start);
+
+        assert!(breaker.allows(start + Duration::from_secs(10)));
+        assert_eq!(CircuitState::HalfOpen { probes_left: 2 }, breaker.state());
+        breaker.record_success();
+        assert_eq!(CircuitState::HalfOpen { probes_left: 1 }, breaker.state());
+        breaker.record_success();
+        assert_eq!(CircuitState::Closed, breaker.state());
+
+        // window was cleared
+        breaker.record_failure(start);
+        assert_eq!(CircuitState::Closed, breaker.state());
+    }
+
+    #[test]
+    fn test_failed_probe_opens_circuit_again() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::new(config());
+        breaker.record_failure(start);
+        breaker.record_failure(start);
+
+        let probe = start + Duration::from_secs(10);
+        assert!(breaker.allows(probe));
+        breaker.record_failure(probe);
+        assert_eq!(
+            CircuitState::Open {
+                until: probe + Duration::from_secs(10)
+            },
+            breaker.state()
+        );
+    }
+
+    #[test]
+    fn test_forced_open_circuit_stays_open_until_forced_closed() {
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::new(config());
+
+        breaker.force_open();
+        assert!(!breaker.allows(start + Duration::from_secs(1000)));
+        breaker.record_failure(start);
+        assert_eq!(CircuitState::ForcedOpen, breaker.state());
+
+        breaker.force_close();
+        assert_eq!(CircuitState::Closed, breaker.state());
+        assert!(breaker.allows(start));
+    }
+}
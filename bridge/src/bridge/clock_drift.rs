@@ -0,0 +1,201 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures::{Future, Poll, Stream};
+use futures::future::Join;
+use timer::Interval;
+use web3::Transport;
+use web3::types::{Block, H256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::ChainClockConfig;
+use error::Error;
+use super::chain_clock::SharedChainClock;
+
+/// Difference in seconds between the timestamp of the latest block of a chain
+/// and the local clock. positive if the chain is ahead of the local clock.
+///
+/// the latest block is up to one block time old when it's fetched,
+/// so a healthy chain drifts slightly behind.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Drift(pub i64);
+
+impl Drift {
+    pub fn measure(block_timestamp: u64, now: u64) -> Self {
+        Drift(block_timestamp as i64 - now as i64)
+    }
+
+    /// `true` if the drift is larger than `max` in either direction.
+    pub fn exceeds(&self, max: Duration) -> bool {
+        self.0.abs() as u64 > max.as_secs()
+    }
+
+    /// age at local time `now` of a block with `block_timestamp`
+    /// from a chain with this drift. never negative.
+    pub fn corrected_age(&self, block_timestamp: u64, now: u64) -> Duration {
+        let local_timestamp = block_timestamp as i64 - self.0;
+        let age = now as i64 - local_timestamp;
+        Duration::from_secs(if age < 0 { 0 } else { age as u64 })
+    }
+}
+
+/// Last measured drift of both chains.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ClockDrift {
+    pub home: Drift,
+    pub foreign: Drift,
+}
+
+/// time of the latest `block` by `clock`, see `ChainClock`.
+fn block_time(
+    clock: &SharedChainClock,
+    config: Option<&ChainClockConfig>,
+    block: &Block<H256>,
+) -> u64 {
+    let timestamp = block.timestamp.low_u64();
+    match block.number {
+        Some(number) => clock.time_of(config, number.low_u64(), timestamp),
+        None => timestamp,
+    }
+}
+
+/// seconds since unix epoch on the local clock.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+enum ClockDriftState<T: Transport> {
+    /// Waiting for the next measurement.
+    Wait,
+    /// Fetching latest blocks of both chains.
+    FetchBlocks(
+        Join<Timeout<ApiCall<Block<H256>, T::Out>>, Timeout<ApiCall<Block<H256>, T::Out>>>,
+    ),
+    /// Drift has been measured.
+    Yield(Option<ClockDrift>),
+}
+
+pub fn create_clock_drift_monitor<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    interval: Interval,
+    max_drift: Duration,
+) -> ClockDriftMonitor<T> {
+    ClockDriftMonitor {
+        app,
+        interval,
+        max_drift,
+        state: ClockDriftState::Wait,
+    }
+}
+
+/// Periodically compares timestamps of the latest blocks of both chains
+/// to the local clock and warns if either drifts more than `max_drift`.
+pub struct ClockDriftMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    interval: Interval,
+    max_drift: Duration,
+    state: ClockDriftState<T>,
+}
+
+impl<T: Transport> Stream for ClockDriftMonitor<T> {
+    type Item = ClockDrift;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                ClockDriftState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    let home = api::timeout(
+                        &self.app.timer,
+                        api::latest_block(&self.app.connections.home),
+                        "home",
+                        self.app.config.home.request_timeout,
+                    );
+                    let foreign = api::timeout(
+                        &self.app.timer,
+                        api::latest_block(&self.app.connections.foreign),
+                        "foreign",
+                        self.app.config.foreign.request_timeout,
+                    );
+                    ClockDriftState::FetchBlocks(home.join(foreign))
+                }
+                ClockDriftState::FetchBlocks(ref mut future) => {
+                    let (home, foreign) = try_ready!(future.poll());
+                    let now = unix_now();
+                    let config = self.app.config.chain_clock.as_ref();
+                    let clocks = &self.app.chain_clocks;
+                    let drift = ClockDrift {
+                        home: Drift::measure(block_time(&clocks.home, config, &home), now),
+                        foreign: Drift::measure(block_time(&clocks.foreign, config, &foreign), now),
+                    };
+                    debug!("clock drift: {:?}", drift);
+                    if drift.home.exceeds(self.max_drift) {
+                        warn!(
+                            "home chain clock drifts {} seconds from local clock",
+                            drift.home.0
+                        );
+                    }
+                    if drift.foreign.exceeds(self.max_drift) {
+                        warn!(
+                            "foreign chain clock drifts {} seconds from local clock",
+                            drift.foreign.0
+                        );
+                    }
+                    ClockDriftState::Yield(Some(drift))
+                }
+                ClockDriftState::Yield(ref mut drift) => match drift.take() {
+                    None => ClockDriftState::Wait,
+                    some => return Ok(some.into()),
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::Drift;
+
+    #[test]
+    fn test_drift_measure() {
+        assert_eq!(Drift(0), Drift::measure(1000, 1000));
+        assert_eq!(Drift(-15), Drift::measure(985, 1000));
+        assert_eq!(Drift(120), Drift::measure(1120, 1000));
+    }
+
+    #[test]
+    fn test_drift_exceeds() {
+        let max = Duration::from_secs(30);
+        assert!(!Drift(30).exceeds(max));
+        assert!(!Drift(-30).exceeds(max));
+        assert!(Drift(31).exceeds(max));
+        assert!(Drift(-31).exceeds(max));
+    }
+
+    #[test]
+    fn test_corrected_age_with_chain_ahead() {
+        // chain clock is 120 seconds ahead. a block stamped 1100 was created at local time 980.
+        let drift = Drift(120);
+        assert_eq!(Duration::from_secs(20), drift.corrected_age(1100, 1000));
+        // without correction the block would appear to be from the future
+        assert_eq!(Duration::from_secs(0), Drift(0).corrected_age(1100, 1000));
+    }
+
+    #[test]
+    fn test_corrected_age_with_chain_behind() {
+        // chain clock is 300 seconds behind. a block stamped 690 was created at local time 990.
+        let drift = Drift(-300);
+        assert_eq!(Duration::from_secs(10), drift.corrected_age(690, 1000));
+        assert_eq!(Duration::from_secs(310), Drift(0).corrected_age(690, 1000));
+    }
+
+    #[test]
+    fn test_corrected_age_is_never_negative() {
+        assert_eq!(Duration::from_secs(0), Drift(10).corrected_age(2000, 1000));
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use futures::{future, Future};
+use futures::sync::{mpsc, oneshot};
+use serde_json::Value;
+use web3::types::{H256, U256};
+use config::Config;
+use super::signed_command::CommandAuthorization;
+use super::{Component, SpendKind};
+
+/// Change of a running bridge requested from outside its stream, e.g. by a `SIGHUP` reload.
+/// executed by `Bridge::execute` the next time the bridge is polled.
+#[derive(Debug, Clone)]
+pub enum BridgeCommand {
+    /// `Bridge::rotate_authority` to the credentials of the config
+    RotateAuthority(Box<Config>),
+    /// `Bridge::force_open_circuit`
+    ForceOpenCircuit(Component),
+    /// `Bridge::force_close_circuit`
+    ForceCloseCircuit(Component),
+    /// limit, spend and deferred relays of `Bridge::spend_limiter`
+    SpendLimit(SpendKind),
+    /// `Bridge::set_spend_limit`
+    SetSpendLimit(SpendKind, U256),
+    /// `Bridge::release_deferred` of the relay with the key
+    ReleaseDeferred(SpendKind, H256),
+    /// `Bridge::release_held_deposit` with the relay key
+    ReleaseHeldDeposit(H256),
+    /// drains the bridge like `SIGUSR2`, see `Bridge::drain_on`
+    Drain,
+    /// `Bridge::acknowledge_deep_reorg`
+    AcknowledgeDeepReorg,
+    /// latest failures of every component, see `Session::recent_errors`
+    RecentErrors,
+    /// `Bridge::authorize` accepts the nonce of a signed command before it's sent
+    Authorize(CommandAuthorization),
+}
+
+/// json result of an executed command, or why it failed.
+pub type CommandReply = Result<Value, String>;
+
+/// Command waiting for the bridge, with the channel its reply is sent to.
+pub struct CommandRequest {
+    pub command: BridgeCommand,
+    reply: oneshot::Sender<CommandReply>,
+}
+
+impl CommandRequest {
+    pub fn reply(self, reply: CommandReply) {
+        // the sender stopped waiting for the reply
+        let _ = self.reply.send(reply);
+    }
+}
+
+impl fmt::Debug for CommandRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CommandRequest")
+            .field("command", &self.command)
+            .finish()
+    }
+}
+
+/// Sends commands to the bridge polling its `CommandReceiver`, see `Bridge::commands_from`.
+#[derive(Debug, Clone)]
+pub struct CommandSender {
+    sender: mpsc::UnboundedSender<CommandRequest>,
+}
+
+impl CommandSender {
+    /// resolves to the reply of the bridge once it executed `command`.
+    pub fn send(
+        &self,
+        command: BridgeCommand,
+    ) -> Box<Future<Item = Value, Error = String> + Send> {
+        let (reply, receiver) = oneshot::channel();
+        let request = CommandRequest { command, reply };
+        if self.sender.unbounded_send(request).is_err() {
+            return Box::new(future::err("the bridge stopped".to_owned()));
+        }
+        Box::new(
+            receiver
+                .map_err(|_| "the bridge stopped before executing the command".to_owned())
+                .and_then(|reply| reply),
+        )
+    }
+}
+
+pub type CommandReceiver = mpsc::UnboundedReceiver<CommandRequest>;
+
+/// channel of the commands to a bridge. commands are queued until the bridge polls them.
+pub fn command_channel() -> (CommandSender, CommandReceiver) {
+    let (sender, receiver) = mpsc::unbounded();
+    (CommandSender { sender }, receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future, Stream};
+    use config::{Authorities, Config, Node};
+    use super::{command_channel, BridgeCommand};
+
+    fn rotate() -> BridgeCommand {
+        let config = Config::builder()
+            .home(Node::new(1.into(), "/home.ipc".into()))
+            .foreign(Node::new(1.into(), "/foreign.ipc".into()))
+            .authorities(Authorities {
+                accounts: vec![1.into()],
+                required_signatures: 1,
+            })
+            .estimated_gas_cost_of_withdraw(0u64.into())
+            .max_total_home_contract_balance(0u64.into())
+            .max_single_deposit_value(0u64.into())
+            .build()
+            .unwrap();
+        BridgeCommand::RotateAuthority(Box::new(config))
+    }
+
+    #[test]
+    fn test_command_resolves_to_the_reply() {
+        let (sender, receiver) = command_channel();
+        let reply = sender.send(rotate());
+        let (request, _receiver) = receiver.into_future().wait().map_err(|_| ()).unwrap();
+        request.unwrap().reply(Ok(json!({ "rotating": true })));
+        assert_eq!(json!({ "rotating": true }), reply.wait().unwrap());
+    }
+
+    #[test]
+    fn test_command_fails_once_the_bridge_stopped() {
+        let (sender, receiver) = command_channel();
+        let pending = sender.send(rotate());
+        // drops the queued command
+        drop(receiver);
+        assert_eq!(
+            Err("the bridge stopped before executing the command".to_owned()),
+            pending.wait()
+        );
+        assert_eq!(
+            Err("the bridge stopped".to_owned()),
+            sender.send(rotate()).wait()
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use web3;
+use web3::types::{Address, Bytes};
+use config::Config;
+use database::Database;
+use error::{ErrorKind, Result};
+use super::revert::{decode_revert_reason, REVERT_SELECTOR};
+use super::self_test::Probe;
+
+/// number of recipients whose kind `RecipientKinds` keeps.
+pub const KEPT_RECIPIENTS: usize = 4096;
+
+/// Kind of the account receiving a deposit on foreign.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecipientKind {
+    /// account without code. relayed with the gas of `transactions.deposit_relay`
+    Account,
+    /// contract. relayed with the gas of `contract_recipients` once a simulation succeeds
+    Contract,
+}
+
+impl RecipientKind {
+    /// kind of the account whose code on foreign is `code`.
+    pub fn of_code(code: &Bytes) -> Self {
+        if code.0.is_empty() {
+            RecipientKind::Account
+        } else {
+            RecipientKind::Contract
+        }
+    }
+}
+
+/// Kinds of the recipients of relayed deposits, fetched with `eth_getCode` on foreign.
+///
+/// recipients rarely change kind, so at most `KEPT_RECIPIENTS` of them are kept
+/// and all are fetched again once that many are.
+#[derive(Debug, Default)]
+pub struct RecipientKinds {
+    kinds: HashMap<Address, RecipientKind>,
+}
+
+impl RecipientKinds {
+    pub fn get(&self, recipient: &Address) -> Option<RecipientKind> {
+        self.kinds.get(recipient).cloned()
+    }
+
+    pub fn insert(&mut self, recipient: Address, kind: RecipientKind) {
+        if self.kinds.len() >= KEPT_RECIPIENTS && !self.kinds.contains_key(&recipient) {
+            self.kinds.clear();
+        }
+        self.kinds.insert(recipient, kind);
+    }
+}
+
+/// rpc method `--self-test` probes for `contract_recipients` on foreign,
+/// fetching the code of the bridge contract of `init`.
+pub fn self_test_probes(config: &Config, init: &Database) -> Vec<Probe> {
+    if config.contract_recipients.is_none() {
+        return Vec::new();
+    }
+    let params = vec![json!(init.foreign_contract_address), "latest".into()];
+    vec![
+        Probe::call("foreign", "eth_getCode", params, "contract recipients")
+            .enabled_by("contract_recipients"),
+    ]
+}
+
+/// the reason the simulation of a deposit to a contract recipient predicts that it reverts,
+/// `None` if it predicts that it succeeds.
+///
+/// parity fails the call with an rpc error if it reverts, geth returns the abi encoded revert
+/// reason. other failures of the call, e.g. timeouts, predict nothing and are returned.
+pub fn simulation_revert(result: Result<Bytes>) -> Result<Option<String>> {
+    match result {
+        Ok(ref output) if output.0.starts_with(&REVERT_SELECTOR) => Ok(Some(
+            decode_revert_reason(&output.0).unwrap_or_else(|| "reverted without reason".into()),
+        )),
+        Ok(_) => Ok(None),
+        Err(err) => {
+            let reason = match *err.kind() {
+                ErrorKind::Web3(web3::Error::Rpc(ref rpc)) => Some(rpc.message.clone()),
+                _ => None,
+            };
+            match reason {
+                Some(reason) => Ok(Some(reason)),
+                None => Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use ethabi::{self, Token};
+    use rpc;
+    use web3;
+    use web3::types::Bytes;
+    use error::{Error, ErrorKind};
+    use super::{simulation_revert, RecipientKind, RecipientKinds, KEPT_RECIPIENTS};
+    use super::super::revert::REVERT_SELECTOR;
+
+    #[test]
+    fn test_recipient_kinds() {
+        assert_eq!(RecipientKind::Account, RecipientKind::of_code(&Bytes(vec![])));
+        assert_eq!(RecipientKind::Contract, RecipientKind::of_code(&Bytes(vec![0x60])));
+
+        let mut kinds = RecipientKinds::default();
+        kinds.insert(1.into(), RecipientKind::Contract);
+        assert_eq!(Some(RecipientKind::Contract), kinds.get(&1.into()));
+        assert_eq!(None, kinds.get(&2.into()));
+        for recipient in 2..KEPT_RECIPIENTS as u64 + 1 {
+            kinds.insert(recipient.into(), RecipientKind::Account);
+        }
+        // full: the next recipient starts over
+        assert_eq!(Some(RecipientKind::Contract), kinds.get(&1.into()));
+        kinds.insert(0.into(), RecipientKind::Account);
+        assert_eq!(None, kinds.get(&1.into()));
+        assert_eq!(Some(RecipientKind::Account), kinds.get(&0.into()));
+    }
+
+    #[test]
+    fn test_simulation_revert() {
+        assert_eq!(None, simulation_revert(Ok(Bytes(vec![]))).unwrap());
+
+        let mut revert = REVERT_SELECTOR.to_vec();
+        revert.extend(ethabi::encode(&[Token::String("no fallback".into())]));
+        assert_eq!(
+            Some("no fallback".to_owned()),
+            simulation_revert(Ok(Bytes(revert))).unwrap()
+        );
+
+        let rpc_error = Error::from(ErrorKind::Web3(web3::Error::Rpc(rpc::Error {
+            code: rpc::ErrorCode::ServerError(-32015),
+            message: "Reverted".into(),
+            data: None,
+        })));
+        assert_eq!(Some("Reverted".to_owned()), simulation_revert(Err(rpc_error)).unwrap());
+
+        let timeout = ErrorKind::Timeout("eth_call", "foreign", None, Duration::from_secs(5));
+        let timeout = Error::from(timeout);
+        assert!(simulation_revert(Err(timeout)).is_err());
+    }
+}
@@ -2,12 +2,12 @@ use std::sync::Arc;
 use futures::{Future, Poll};
 use web3::Transport;
 use web3::confirm::SendTransactionWithConfirmation;
-use web3::types::{TransactionReceipt, TransactionRequest};
+use web3::types::{Address, H256, TransactionReceipt, TransactionRequest};
 use app::App;
 use std::path::Path;
 use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use error::{Error, ErrorKind};
 use rustc_hex::ToHex;
 use api;
@@ -53,12 +53,19 @@ impl<T: Transport + Clone> Future for DeployHome<T> {
                         self.app.config.estimated_gas_cost_of_withdraw,
                         self.app.config.max_total_home_contract_balance,
                         self.app.config.max_single_deposit_value,
+                        // 0 disables `HomeBridge.withdrawToPayout`
+                        self.app
+                            .config
+                            .payout_registry
+                            .as_ref()
+                            .map(|registry| registry.address)
+                            .unwrap_or_default(),
                     );
 
                     let tx_request = TransactionRequest {
                         from: self.app.config.home.account,
                         to: None,
-                        gas: Some(self.app.config.txs.home_deploy.gas.into()),
+                        gas: self.app.config.txs.home_deploy.request_gas(),
                         gas_price: Some(self.app.config.txs.home_deploy.gas_price.into()),
                         value: None,
                         data: Some(data.clone().into()),
@@ -141,7 +148,7 @@ impl<T: Transport + Clone> Future for DeployForeign<T> {
                     let tx_request = TransactionRequest {
                         from: self.app.config.foreign.account,
                         to: None,
-                        gas: Some(self.app.config.txs.foreign_deploy.gas.into()),
+                        gas: self.app.config.txs.foreign_deploy.request_gas(),
                         gas_price: Some(self.app.config.txs.foreign_deploy.gas_price.into()),
                         value: None,
                         data: Some(data.clone().into()),
@@ -292,3 +299,25 @@ impl DeployedContract {
         Ok(())
     }
 }
+
+/// directory `DeployedContract::dump_info` is called with by `parity-bridge-deploy`
+/// for the contract deployed to `address` on `chain` (`home` or `foreign`).
+pub fn deploy_record_dir(chain: &str, address: &Address) -> String {
+    format!("deployment-{}-{}", chain, address.to_hex())
+}
+
+/// hash of the deployment transaction from the deploy record in `dir`.
+/// `None` if there is no record.
+pub fn deploy_record_transaction<P: AsRef<Path>>(dir: P) -> Result<Option<H256>, Error> {
+    let path = dir.as_ref().join("transaction_hash");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut hash = String::new();
+    File::open(&path)?.read_to_string(&mut hash)?;
+    let hash = hash.trim()
+        .trim_left_matches("0x")
+        .parse()
+        .map_err(|_| format!("invalid transaction hash in {:?}", path))?;
+    Ok(Some(hash))
+}
@@ -0,0 +1,354 @@
+use std::cmp;
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use futures::future::Join;
+use web3::Transport;
+use web3::types::{Address, Bytes, H256, TransactionReceipt, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::Node;
+use database::Database;
+use error::{Error, ErrorKind};
+
+/// Binary search for the block a contract was deployed at:
+/// the first block at which the contract has code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeployBlockSearch {
+    /// first block the deployment can be at
+    low: u64,
+    /// block the contract has code at once `deployed` is set
+    high: u64,
+    /// `true` once the contract has code at `high`
+    deployed: bool,
+}
+
+impl DeployBlockSearch {
+    /// search of the blocks up to `head`
+    pub fn new(head: u64) -> Self {
+        DeployBlockSearch {
+            low: 0,
+            high: head,
+            deployed: false,
+        }
+    }
+
+    /// block to fetch the code at next. `None` once the deployment block is found.
+    /// the head is checked first.
+    pub fn next_block(&self) -> Option<u64> {
+        if !self.deployed {
+            Some(self.high)
+        } else if self.low < self.high {
+            Some(self.low + (self.high - self.low) / 2)
+        } else {
+            None
+        }
+    }
+
+    /// the deployment block, once found
+    pub fn found(&self) -> Option<u64> {
+        if self.deployed && self.low == self.high {
+            Some(self.low)
+        } else {
+            None
+        }
+    }
+
+    /// records whether the contract has code at `block`, which must be `next_block`.
+    /// returns `false` if it has no code at the head, so it isn't deployed.
+    pub fn record(&mut self, block: u64, has_code: bool) -> bool {
+        if !self.deployed {
+            self.deployed = has_code;
+        } else if has_code {
+            self.high = block;
+        } else {
+            self.low = block + 1;
+        }
+        self.deployed
+    }
+}
+
+enum FindDeployBlockState<T: Transport> {
+    /// The deployment block is known.
+    Known(u64),
+    /// Fetching the block number the search starts at.
+    FetchHead(Timeout<ApiCall<U256, T::Out>>),
+    /// Fetching the code of the contract at the next block of the search.
+    FetchCode {
+        future: Timeout<ApiCall<Bytes, T::Out>>,
+        search: DeployBlockSearch,
+        block: u64,
+    },
+    /// Fetching the receipt of the deployment transaction.
+    /// used if the node doesn't have the state of old blocks.
+    FetchReceipt(Timeout<ApiCall<Option<TransactionReceipt>, T::Out>>),
+}
+
+/// transport and node config of home if `on_home`, of foreign otherwise.
+pub fn chain<T: Transport>(app: &App<T>, on_home: bool) -> (&T, &Node) {
+    if on_home {
+        (&app.connections.home, &app.config.home)
+    } else {
+        (&app.connections.foreign, &app.config.foreign)
+    }
+}
+
+/// name of home if `on_home`, of foreign otherwise.
+pub fn chain_name(on_home: bool) -> &'static str {
+    if on_home {
+        "home"
+    } else {
+        "foreign"
+    }
+}
+
+fn fetch_code<T: Transport>(
+    app: &App<T>,
+    on_home: bool,
+    address: Address,
+    block: u64,
+) -> Timeout<ApiCall<Bytes, T::Out>> {
+    let (transport, node) = chain(app, on_home);
+    api::timeout(
+        &app.timer,
+        api::code(transport, address, block),
+        chain_name(on_home),
+        node.request_timeout,
+    )
+}
+
+fn not_found(address: Address, reason: String) -> Error {
+    ErrorKind::DeployBlockNotFound(address, reason).into()
+}
+
+/// Future resolving to the block the contract at `address` was deployed at.
+///
+/// binary searches the blocks up to the head for the first block the contract has code at.
+/// if the node doesn't have the state of a block of the search (pruned nodes)
+/// the block of the receipt of `creation_transaction` is used instead.
+pub struct FindDeployBlock<T: Transport> {
+    app: Arc<App<T>>,
+    on_home: bool,
+    address: Address,
+    creation_transaction: Option<H256>,
+    state: FindDeployBlockState<T>,
+}
+
+impl<T: Transport> FindDeployBlock<T> {
+    /// resolves to `known` right away unless it's `0`, which means the deployment block is unknown.
+    fn new(
+        app: Arc<App<T>>,
+        on_home: bool,
+        address: Address,
+        known: u64,
+        creation_transaction: Option<H256>,
+    ) -> Self {
+        let state = if known != 0 {
+            FindDeployBlockState::Known(known)
+        } else {
+            let (transport, node) = chain(&app, on_home);
+            FindDeployBlockState::FetchHead(api::timeout(
+                &app.timer,
+                api::block_number(transport),
+                chain_name(on_home),
+                node.request_timeout,
+            ))
+        };
+        FindDeployBlock {
+            app,
+            on_home,
+            address,
+            creation_transaction,
+            state,
+        }
+    }
+}
+
+impl<T: Transport> Future for FindDeployBlock<T> {
+    type Item = u64;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                FindDeployBlockState::Known(block) => return Ok(Async::Ready(block)),
+                FindDeployBlockState::FetchHead(ref mut future) => {
+                    let head = try_ready!(future.poll()).low_u64();
+                    FindDeployBlockState::FetchCode {
+                        future: fetch_code(&self.app, self.on_home, self.address, head),
+                        search: DeployBlockSearch::new(head),
+                        block: head,
+                    }
+                }
+                FindDeployBlockState::FetchCode {
+                    ref mut future,
+                    ref mut search,
+                    block,
+                } => match future.poll().map_err(Error::from) {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(code)) => {
+                        if !search.record(block, !code.0.is_empty()) {
+                            return Err(not_found(
+                                self.address,
+                                format!("no contract at block {}", block),
+                            ));
+                        }
+                        match search.next_block() {
+                            Some(next) => FindDeployBlockState::FetchCode {
+                                future: fetch_code(&self.app, self.on_home, self.address, next),
+                                search: search.clone(),
+                                block: next,
+                            },
+                            None => FindDeployBlockState::Known(
+                                search
+                                    .found()
+                                    .expect("search without next block is done; qed"),
+                            ),
+                        }
+                    }
+                    Err(ref err) if api::is_missing_state(err) => {
+                        let hash = match self.creation_transaction {
+                            Some(hash) => hash,
+                            None => {
+                                return Err(not_found(
+                                    self.address,
+                                    format!(
+                                        "the node has no state of block {} and the deployment \
+                                         transaction is unknown. set the deployment block \
+                                         in the database",
+                                        block
+                                    ),
+                                ))
+                            }
+                        };
+                        info!(
+                            target: "bridge::deploy_block",
+                            "node has no state of block {}, using the receipt of \
+                             deployment transaction {:?}",
+                            block,
+                            hash
+                        );
+                        let (transport, node) = chain(&self.app, self.on_home);
+                        FindDeployBlockState::FetchReceipt(api::timeout(
+                            &self.app.timer,
+                            api::transaction_receipt(transport, hash),
+                            chain_name(self.on_home),
+                            node.request_timeout,
+                        ).about(hash))
+                    }
+                    Err(err) => return Err(err),
+                },
+                FindDeployBlockState::FetchReceipt(ref mut future) => {
+                    let receipt = match try_ready!(future.poll()) {
+                        Some(receipt) => receipt,
+                        None => {
+                            return Err(not_found(
+                                self.address,
+                                format!(
+                                    "deployment transaction {:?} not found",
+                                    self.creation_transaction
+                                ),
+                            ))
+                        }
+                    };
+                    if receipt.contract_address != Some(self.address) {
+                        return Err(not_found(
+                            self.address,
+                            format!(
+                                "transaction {:?} deployed {:?}",
+                                receipt.transaction_hash, receipt.contract_address
+                            ),
+                        ));
+                    }
+                    FindDeployBlockState::Known(receipt.block_number.low_u64())
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+/// looks up the deployment blocks `database` doesn't know (`0`) of both contracts.
+/// `home_creation` and `foreign_creation` are the deployment transactions, if known.
+pub fn find_deploy_blocks<T: Transport>(
+    app: Arc<App<T>>,
+    database: &Database,
+    home_creation: Option<H256>,
+    foreign_creation: Option<H256>,
+) -> FindDeployBlocks<T> {
+    let home = FindDeployBlock::new(
+        app.clone(),
+        true,
+        database.home_contract_address,
+        database.home_deploy,
+        home_creation,
+    );
+    let foreign = FindDeployBlock::new(
+        app,
+        false,
+        database.foreign_contract_address,
+        database.foreign_deploy,
+        foreign_creation,
+    );
+    FindDeployBlocks {
+        database: database.clone(),
+        future: home.join(foreign),
+    }
+}
+
+/// Future resolving to the database with the deployment blocks of both contracts.
+/// the checked blocks are moved forward to the deployment blocks, since there is
+/// nothing to scan before them.
+pub struct FindDeployBlocks<T: Transport> {
+    database: Database,
+    future: Join<FindDeployBlock<T>, FindDeployBlock<T>>,
+}
+
+impl<T: Transport> Future for FindDeployBlocks<T> {
+    type Item = Database;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (home, foreign) = try_ready!(self.future.poll());
+        let database = &self.database;
+        Ok(Async::Ready(Database {
+            home_deploy: home,
+            foreign_deploy: foreign,
+            checked_deposit_relay: cmp::max(home, database.checked_deposit_relay),
+            checked_withdraw_relay: cmp::max(foreign, database.checked_withdraw_relay),
+            checked_withdraw_confirm: cmp::max(foreign, database.checked_withdraw_confirm),
+            ..database.clone()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeployBlockSearch;
+
+    /// block the search finds for a contract deployed at `deployed` with the chain at `head`,
+    /// and the number of blocks it checked.
+    fn search(head: u64, deployed: Option<u64>) -> (Option<u64>, usize) {
+        let mut search = DeployBlockSearch::new(head);
+        let mut checked = 0;
+        while let Some(block) = search.next_block() {
+            checked += 1;
+            let has_code = deployed.map_or(false, |deployed| block >= deployed);
+            if !search.record(block, has_code) {
+                return (None, checked);
+            }
+        }
+        (search.found(), checked)
+    }
+
+    #[test]
+    fn test_deploy_block_search() {
+        assert_eq!((Some(0), 1), search(0, Some(0)));
+        assert_eq!((Some(1000), 1), search(1000, Some(1000)));
+        assert_eq!((None, 1), search(1000, None));
+        for deployed in 0..65 {
+            assert_eq!(Some(deployed), search(64, Some(deployed)).0);
+        }
+        // one block for the head and one per halving of the range
+        assert_eq!((Some(123_456), 25), search(10_000_000, Some(123_456)));
+    }
+}
@@ -0,0 +1,296 @@
+use std::mem;
+use std::time::Instant;
+use web3::types::{Bytes, H256, U256};
+use call_template::{CallArgs, CallTemplate};
+use config::DepositCallbackConfig;
+use database::{Database, DepositCallback};
+use error::Error;
+
+/// number of callbacks `DepositCallbacks` keeps.
+pub const KEPT_CALLBACKS: usize = 1024;
+
+/// Callbacks of the relayed deposits, see `DepositCallbackConfig`. most recent `KEPT_CALLBACKS`
+/// callbacks, oldest first.
+///
+/// a callback is queued once the relay of its deposit emitted its event. a callback that fails
+/// to send is sent again after `retry_delay`. once it was attempted `attempts` times it's given
+/// up. a failed callback never fails the deposit relay.
+#[derive(Debug, Clone, Default)]
+pub struct DepositCallbacks {
+    /// `None` if `deposit_callback` isn't configured
+    config: Option<(DepositCallbackConfig, CallTemplate)>,
+    callbacks: Vec<DepositCallback>,
+    /// callbacks that failed, by their relay transaction, and when they are sent again
+    retry_at: Vec<(H256, Instant)>,
+    /// relay transactions of the callbacks in flight, in the order they are sent
+    in_flight: Vec<H256>,
+    /// callbacks given up since the last `take_given_up`
+    given_up: Vec<DepositCallback>,
+}
+
+impl DepositCallbacks {
+    /// callbacks continuing from those of `init`. callbacks without transaction that weren't
+    /// given up are sent again.
+    pub fn new(config: Option<&DepositCallbackConfig>, init: &Database) -> Self {
+        let config = match config {
+            Some(config) => config,
+            None => return DepositCallbacks::default(),
+        };
+        DepositCallbacks {
+            config: Some((config.clone(), config.template())),
+            callbacks: init.deposit_callbacks.clone(),
+            ..DepositCallbacks::default()
+        }
+    }
+
+    fn cap(&self) -> u32 {
+        self.config.as_ref().map_or(0, |&(ref config, _)| config.attempts)
+    }
+
+    /// `true` if `callback` is waiting to be sent.
+    fn pending(&self, callback: &DepositCallback) -> bool {
+        callback.transaction.is_none() && callback.attempts < self.cap()
+    }
+
+    /// queues the callback of the deposit in home transaction `args.main_tx` relayed in
+    /// foreign transaction `relay_transaction`. ignored if callbacks aren't configured.
+    pub fn queue(&mut self, args: CallArgs, log_index: Option<U256>, relay_transaction: H256) {
+        if self.config.is_none()
+            || self.callbacks
+                .iter()
+                .any(|callback| callback.relay_transaction == relay_transaction)
+        {
+            return;
+        }
+        if self.callbacks.len() >= KEPT_CALLBACKS {
+            self.callbacks.remove(0);
+        }
+        self.callbacks.push(DepositCallback {
+            home_transaction: args.main_tx,
+            log_index,
+            relay_transaction,
+            recipient: args.recipient,
+            value: args.value,
+            attempts: 0,
+            transaction: None,
+            error: None,
+        });
+    }
+
+    /// `true` if a callback can be sent at `now`.
+    pub fn has_due(&self, now: Instant) -> bool {
+        self.in_flight.is_empty()
+            && self.callbacks
+                .iter()
+                .any(|callback| self.pending(callback) && self.is_due(callback, now))
+    }
+
+    fn is_due(&self, callback: &DepositCallback, now: Instant) -> bool {
+        self.retry_at
+            .iter()
+            .find(|&&(relay, _)| relay == callback.relay_transaction)
+            .map_or(true, |&(_, at)| at <= now)
+    }
+
+    /// starts an attempt of every callback that can be sent at `now`.
+    /// returns the call data of each of them.
+    pub fn start_due(&mut self, now: Instant) -> Vec<Bytes> {
+        let due = self.callbacks
+            .iter()
+            .filter(|callback| self.pending(callback) && self.is_due(callback, now))
+            .map(|callback| callback.relay_transaction)
+            .collect::<Vec<_>>();
+        let template = match self.config {
+            Some((_, ref template)) => template.clone(),
+            None => return Vec::new(),
+        };
+        let mut calls = Vec::new();
+        for relay in due {
+            if let Some(callback) = self.callbacks
+                .iter_mut()
+                .find(|callback| callback.relay_transaction == relay)
+            {
+                callback.attempts += 1;
+                calls.push(template.expand(&CallArgs {
+                    recipient: callback.recipient,
+                    value: callback.value,
+                    main_tx: callback.home_transaction,
+                }));
+            }
+            self.in_flight.push(relay);
+        }
+        calls
+    }
+
+    fn callback_mut(&mut self, relay_transaction: H256) -> Option<&mut DepositCallback> {
+        self.callbacks
+            .iter_mut()
+            .find(|callback| callback.relay_transaction == relay_transaction)
+    }
+
+    /// the callbacks in flight were sent in `transactions`, in the order they were started.
+    pub fn sent(&mut self, transactions: &[H256]) {
+        for (relay, transaction) in mem::replace(&mut self.in_flight, Vec::new())
+            .into_iter()
+            .zip(transactions)
+        {
+            self.retry_at.retain(|&(retried, _)| retried != relay);
+            if let Some(callback) = self.callback_mut(relay) {
+                callback.transaction = Some(*transaction);
+                callback.error = None;
+            }
+        }
+    }
+
+    /// the callbacks in flight failed with `err` at `now`. they are sent again after
+    /// `retry_delay` or given up if they were attempted `attempts` times.
+    pub fn fail(&mut self, err: &Error, now: Instant) {
+        let (cap, retry_delay) = match self.config {
+            Some((ref config, _)) => (config.attempts, config.retry_delay),
+            None => return,
+        };
+        let error = err.to_string();
+        for relay in mem::replace(&mut self.in_flight, Vec::new()) {
+            self.retry_at.retain(|&(retried, _)| retried != relay);
+            let given_up = match self.callback_mut(relay) {
+                Some(callback) => {
+                    callback.error = Some(error.clone());
+                    if callback.attempts >= cap {
+                        Some(callback.clone())
+                    } else {
+                        None
+                    }
+                }
+                None => continue,
+            };
+            match given_up {
+                Some(callback) => self.given_up.push(callback),
+                None => self.retry_at.push((relay, now + retry_delay)),
+            }
+        }
+    }
+
+    /// kept callbacks, oldest first.
+    pub fn callbacks(&self) -> &[DepositCallback] {
+        &self.callbacks
+    }
+
+    /// returns the callbacks given up since the last call.
+    pub fn take_given_up(&mut self) -> Vec<DepositCallback> {
+        mem::replace(&mut self.given_up, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use web3::types::H256;
+    use call_template::{CallArgs, CallTemplate};
+    use config::DepositCallbackConfig;
+    use database::Database;
+    use error::Error;
+    use super::DepositCallbacks;
+
+    fn config() -> DepositCallbackConfig {
+        DepositCallbackConfig {
+            contract: 12.into(),
+            call: "0x6a627842{recipient}{value}{main_tx}".into(),
+            attempts: 2,
+            retry_delay: Duration::from_secs(60),
+        }
+    }
+
+    fn args(main_tx: u64) -> CallArgs {
+        CallArgs {
+            recipient: 0xaa.into(),
+            value: 0x1234.into(),
+            main_tx: main_tx.into(),
+        }
+    }
+
+    #[test]
+    fn test_deposit_callbacks_are_linked_to_their_relays() {
+        let mut callbacks = DepositCallbacks::new(Some(&config()), &Database::default());
+        let now = Instant::now();
+        assert!(!callbacks.has_due(now));
+        callbacks.queue(args(1), Some(3.into()), 10.into());
+        callbacks.queue(args(2), None, 20.into());
+        // the same relay is only called back once
+        callbacks.queue(args(1), Some(3.into()), 10.into());
+        assert!(callbacks.has_due(now));
+
+        let calls = callbacks.start_due(now);
+        let template = CallTemplate::parse(&config().call).unwrap();
+        assert_eq!(vec![template.expand(&args(1)), template.expand(&args(2))], calls);
+        // nothing else is started while callbacks are in flight
+        assert!(!callbacks.has_due(now));
+        callbacks.sent(&[100.into(), 200.into()]);
+
+        let kept = callbacks.callbacks();
+        assert_eq!(2, kept.len());
+        assert_eq!(H256::from(1), kept[0].home_transaction);
+        assert_eq!(Some(3.into()), kept[0].log_index);
+        assert_eq!(H256::from(10), kept[0].relay_transaction);
+        assert_eq!(Some(H256::from(100)), kept[0].transaction);
+        assert_eq!(H256::from(20), kept[1].relay_transaction);
+        assert_eq!(Some(H256::from(200)), kept[1].transaction);
+        assert_eq!(1, kept[1].attempts);
+        assert!(!callbacks.has_due(now));
+    }
+
+    #[test]
+    fn test_deposit_callbacks_retry_and_give_up() {
+        let mut callbacks = DepositCallbacks::new(Some(&config()), &Database::default());
+        let now = Instant::now();
+        callbacks.queue(args(1), None, 10.into());
+        assert_eq!(1, callbacks.start_due(now).len());
+        callbacks.fail(&Error::from("nonce too low"), now);
+        assert_eq!(
+            Some("nonce too low".to_owned()),
+            callbacks.callbacks()[0].error
+        );
+        // deferred by the retry delay
+        assert!(!callbacks.has_due(now));
+        assert!(callbacks.start_due(now).is_empty());
+        let later = now + Duration::from_secs(60);
+        assert!(callbacks.has_due(later));
+        assert_eq!(1, callbacks.start_due(later).len());
+        callbacks.fail(&Error::from("timeout"), later);
+
+        let given_up = callbacks.take_given_up();
+        assert_eq!(1, given_up.len());
+        assert_eq!(2, given_up[0].attempts);
+        assert_eq!(None, given_up[0].transaction);
+        assert_eq!(Some("timeout".to_owned()), given_up[0].error);
+        assert!(!callbacks.has_due(later + Duration::from_secs(3600)));
+        assert!(callbacks.take_given_up().is_empty());
+    }
+
+    #[test]
+    fn test_deposit_callbacks_continue_from_the_database() {
+        let mut callbacks = DepositCallbacks::new(Some(&config()), &Database::default());
+        let now = Instant::now();
+        callbacks.queue(args(1), None, 10.into());
+        callbacks.queue(args(2), None, 20.into());
+        callbacks.start_due(now);
+        callbacks.fail(&Error::from("timeout"), now);
+        callbacks.queue(args(3), None, 30.into());
+        let database = Database {
+            deposit_callbacks: callbacks.callbacks().to_vec(),
+            ..Database::default()
+        };
+
+        // failed callbacks aren't deferred after a restart, their attempts count
+        let mut callbacks = DepositCallbacks::new(Some(&config()), &database);
+        assert!(callbacks.has_due(now));
+        assert_eq!(3, callbacks.start_due(now).len());
+        callbacks.fail(&Error::from("timeout"), now);
+        assert_eq!(2, callbacks.take_given_up().len());
+
+        // without config nothing is queued
+        let mut callbacks = DepositCallbacks::new(None, &Database::default());
+        callbacks.queue(args(1), None, 10.into());
+        assert!(callbacks.callbacks().is_empty());
+        assert!(!callbacks.has_due(now));
+    }
+}
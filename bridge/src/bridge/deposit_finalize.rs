@@ -0,0 +1,472 @@
+use std::mem;
+use std::sync::Arc;
+use ethabi::{self, ParamType, Token};
+use futures::{Future, Poll, Stream};
+use futures::future::{join_all, Join, JoinAll};
+use tiny_keccak::keccak256;
+use web3::Transport;
+use web3::types::{Address, Bytes, FilterBuilder, H256, Log, TransactionRequest, U256};
+use api::{self, ApiCall, LogStream, Timeout};
+use app::App;
+use database::Database;
+use error::{Error, Result};
+use jitter;
+use util::log_transaction_hash;
+use super::Component;
+use super::schedule::{foreign_quota, send_foreign_transactions, SendTransactions};
+use super::signature_stagger::authority_slot;
+
+/// event two-phase `ForeignBridge` contracts emit for every deposit signature.
+/// no parameter is indexed.
+pub const SIGNED_DEPOSIT_EVENT: &str = "SignedDeposit(address,bytes32)";
+/// number of authorities that signed the deposit of a home transaction
+pub const NUM_DEPOSIT_SIGNATURES_FUNCTION: &str = "numDepositSignatures(bytes32)";
+/// `true` once the deposit of a home transaction is credited
+pub const FINALIZED_FUNCTION: &str = "finalized(bytes32)";
+/// credits the deposit of a home transaction signed by `requiredSignatures` authorities
+pub const FINALIZE_DEPOSIT_FUNCTION: &str = "finalizeDeposit(bytes32)";
+
+pub fn signed_deposit_topic() -> H256 {
+    keccak256(SIGNED_DEPOSIT_EVENT.as_bytes()).into()
+}
+
+/// call data of `function` taking the hash of the home transaction of a deposit.
+pub fn deposit_hash_payload(function: &str, main_tx_hash: H256) -> Bytes {
+    let mut payload = keccak256(function.as_bytes())[..4].to_vec();
+    payload.extend(ethabi::encode(&[Token::FixedBytes(main_tx_hash.to_vec())]));
+    payload.into()
+}
+
+/// A `SignedDeposit` event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedDeposit {
+    /// authority that signed
+    pub authority: Address,
+    /// transaction on home (main) of the deposit
+    pub main_tx_hash: H256,
+}
+
+impl SignedDeposit {
+    pub fn from_log(log: &Log) -> Result<Self> {
+        let tokens = ethabi::decode(&[ParamType::Address, ParamType::FixedBytes(32)], &log.data.0)?;
+        match (&tokens[0], &tokens[1]) {
+            (&Token::Address(authority), &Token::FixedBytes(ref hash)) => Ok(SignedDeposit {
+                authority,
+                main_tx_hash: H256::from_slice(hash),
+            }),
+            _ => Err("SignedDeposit event decodes to unexpected tokens".into()),
+        }
+    }
+}
+
+/// Deposit signed by this authority that isn't known to be finalized.
+#[derive(Debug, Clone, PartialEq)]
+struct PendingDeposit {
+    main_tx_hash: H256,
+    /// slot of this authority, see `authority_slot`. `None` if it isn't one of the authorities
+    slot: Option<u64>,
+    /// foreign block the signatures were first seen to reach `requiredSignatures` at
+    threshold_block: Option<u64>,
+    /// foreign block this authority last sent `finalizeDeposit` at
+    sent_block: Option<u64>,
+}
+
+impl PendingDeposit {
+    /// `true` if this authority should send `finalizeDeposit` at `block`:
+    /// the threshold is met, it's its turn and it didn't send it within its turn already.
+    ///
+    /// the authority in slot `n` gets its turn `n * turn_blocks` blocks after the threshold
+    /// was met, so the next one finalizes if the previous ones didn't.
+    fn should_finalize(&self, block: u64, turn_blocks: u64) -> bool {
+        let turn = match (self.threshold_block, self.slot) {
+            (Some(threshold_block), Some(slot)) => threshold_block + slot * turn_blocks,
+            _ => return false,
+        };
+        block >= turn && self.sent_block.map_or(true, |sent| block >= sent + turn_blocks)
+    }
+}
+
+/// `finalizeDeposit` sent by this authority.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finalization {
+    /// transaction on home (main) of the deposit
+    pub main_tx_hash: H256,
+    /// foreign transaction calling `finalizeDeposit`
+    pub transaction: H256,
+}
+
+type CallFuture<T> = Timeout<ApiCall<Bytes, <T as Transport>::Out>>;
+
+enum DepositFinalizeState<T: Transport> {
+    /// Waiting for the next range of `SignedDeposit` events.
+    Wait,
+    /// Fetching `requiredSignatures` and the signatures of due deposits
+    /// and whether they are finalized.
+    FetchStatus {
+        block: u64,
+        due: Vec<H256>,
+        future: Join<CallFuture<T>, JoinAll<Vec<Join<CallFuture<T>, CallFuture<T>>>>>,
+    },
+    /// Sending `finalizeDeposit` for deposits at their turn.
+    Finalize {
+        block: u64,
+        deposits: Vec<H256>,
+        future: SendTransactions<T>,
+    },
+    /// Deposits have been finalized.
+    Yield(Option<Vec<Finalization>>),
+}
+
+/// watches the foreign blocks after the last checked withdraw confirm.
+pub fn create_deposit_finalize<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+    turn_blocks: u64,
+) -> DepositFinalize<T> {
+    let logs_init = api::LogStreamInit {
+        after: init.checked_withdraw_confirm,
+        chain: "foreign",
+        request_timeout: app.config.foreign.request_timeout,
+        poll_interval: app.tunables.get().foreign_poll_interval,
+        poll_jitter: jitter::poll_jitter(app.config.poll_jitter, &app.config.foreign.account),
+        confirmations: app.config.foreign.required_confirmations,
+        sanity_check: app.config.foreign.logs_sanity_check.clone(),
+        head_regression: app.config.foreign.head_regression,
+        log_limit_errors: app.config.foreign.log_limit_errors.clone(),
+        max_log_data: app.config.foreign.max_log_data,
+        finality: app.config.foreign.l1_posted_rpc(),
+        filter: FilterBuilder::default()
+            .address(vec![init.foreign_contract_address])
+            .topics(Some(vec![signed_deposit_topic()]), None, None, None),
+    };
+    DepositFinalize {
+        logs: api::log_stream(
+            app.connections.foreign.clone(),
+            app.timer.clone(),
+            logs_init,
+        ),
+        foreign_contract: init.foreign_contract_address,
+        app,
+        turn_blocks,
+        pending: Vec::new(),
+        state: DepositFinalizeState::Wait,
+    }
+}
+
+/// Finalizes deposits on two-phase `ForeignBridge` contracts.
+///
+/// a deposit is tracked once the `SignedDeposit` event of this authority is confirmed.
+/// its signatures are checked then and on every `SignedDeposit` of another authority.
+/// once `requiredSignatures` signed it, authorities take turns to call `finalizeDeposit`
+/// until `finalized` is `true`. yields the finalizations this authority sent.
+pub struct DepositFinalize<T: Transport> {
+    app: Arc<App<T>>,
+    logs: LogStream<T>,
+    foreign_contract: Address,
+    turn_blocks: u64,
+    pending: Vec<PendingDeposit>,
+    state: DepositFinalizeState<T>,
+}
+
+impl<T: Transport> DepositFinalize<T> {
+    /// home transactions of the deposits signed by this authority that aren't finalized yet.
+    pub fn pending(&self) -> Vec<H256> {
+        self.pending
+            .iter()
+            .map(|deposit| deposit.main_tx_hash)
+            .collect()
+    }
+
+    /// deposits to check at `block` after the `SignedDeposit` logs of `logs`.
+    fn due(&mut self, logs: &[Log], block: u64) -> Vec<H256> {
+        let mut due = Vec::new();
+        for log in logs {
+            let signed = match SignedDeposit::from_log(log) {
+                Ok(signed) => signed,
+                Err(err) => {
+                    warn!(
+                        target: "bridge::deposit_finalize",
+                        "cannot decode SignedDeposit event in foreign transaction {:?}: {}",
+                        log.transaction_hash,
+                        err
+                    );
+                    continue;
+                }
+            };
+            let tracked = self.pending
+                .iter()
+                .any(|deposit| deposit.main_tx_hash == signed.main_tx_hash);
+            if !tracked && signed.authority == self.app.config.foreign.account {
+                debug!(
+                    target: "bridge::deposit_finalize",
+                    "signature of deposit in home transaction {} confirmed in foreign transaction {:?}",
+                    signed.main_tx_hash,
+                    log_transaction_hash(log).ok()
+                );
+                self.pending.push(PendingDeposit {
+                    main_tx_hash: signed.main_tx_hash,
+                    slot: authority_slot(
+                        &self.app.config.authorities.accounts,
+                        &self.app.config.foreign.account,
+                        &signed.main_tx_hash,
+                    ),
+                    threshold_block: None,
+                    sent_block: None,
+                });
+            } else if !tracked {
+                continue;
+            }
+            if !due.contains(&signed.main_tx_hash) {
+                due.push(signed.main_tx_hash);
+            }
+        }
+        for deposit in &self.pending {
+            if !due.contains(&deposit.main_tx_hash)
+                && deposit.should_finalize(block, self.turn_blocks)
+            {
+                due.push(deposit.main_tx_hash);
+            }
+        }
+        due
+    }
+
+    fn call(&self, payload: Bytes) -> CallFuture<T> {
+        api::timeout(
+            &self.app.timer,
+            api::call(&self.app.connections.foreign, self.foreign_contract, payload),
+            "foreign",
+            self.app.config.foreign.request_timeout,
+        )
+    }
+}
+
+impl<T: Transport> Stream for DepositFinalize<T> {
+    type Item = Vec<Finalization>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                DepositFinalizeState::Wait => {
+                    let item = try_stream!(self.logs.poll());
+                    let due = self.due(&item.logs, item.to);
+                    if due.is_empty() {
+                        DepositFinalizeState::Wait
+                    } else {
+                        let required_signatures = self.call(
+                            self.app
+                                .foreign_bridge
+                                .functions()
+                                .required_signatures()
+                                .input()
+                                .into(),
+                        );
+                        let statuses = due.iter()
+                            .map(|hash| {
+                                self.call(deposit_hash_payload(
+                                    NUM_DEPOSIT_SIGNATURES_FUNCTION,
+                                    *hash,
+                                )).join(self.call(deposit_hash_payload(FINALIZED_FUNCTION, *hash)))
+                            })
+                            .collect::<Vec<_>>();
+                        DepositFinalizeState::FetchStatus {
+                            block: item.to,
+                            due,
+                            future: required_signatures.join(join_all(statuses)),
+                        }
+                    }
+                }
+                DepositFinalizeState::FetchStatus {
+                    ref mut future,
+                    ref due,
+                    block,
+                } => {
+                    let (required_signatures, statuses) = try_ready!(future.poll());
+                    let required_signatures = self.app
+                        .foreign_bridge
+                        .functions()
+                        .required_signatures()
+                        .output(&required_signatures.0)?;
+                    let mut finalize = Vec::new();
+                    for (hash, status) in due.iter().zip(statuses.iter()) {
+                        let (ref signatures, ref finalized) = *status;
+                        let signatures = decode_uint(&signatures.0)?;
+                        let finalized = decode_bool(&finalized.0)?;
+                        let index = match self.pending
+                            .iter()
+                            .position(|deposit| deposit.main_tx_hash == *hash)
+                        {
+                            Some(index) => index,
+                            None => continue,
+                        };
+                        if finalized {
+                            debug!(
+                                target: "bridge::deposit_finalize",
+                                "deposit in home transaction {} is finalized",
+                                hash
+                            );
+                            self.pending.remove(index);
+                            continue;
+                        }
+                        if signatures < required_signatures {
+                            continue;
+                        }
+                        let deposit = &mut self.pending[index];
+                        if deposit.threshold_block.is_none() {
+                            deposit.threshold_block = Some(block);
+                        }
+                        if deposit.should_finalize(block, self.turn_blocks) {
+                            finalize.push(*hash);
+                        }
+                    }
+                    if finalize.is_empty() {
+                        DepositFinalizeState::Wait
+                    } else {
+                        let tx_config = self.app.tunables.get().txs.deposit_finalize;
+                        let requests = finalize
+                            .iter()
+                            .map(|hash| TransactionRequest {
+                                from: self.app.config.foreign.account,
+                                to: Some(self.foreign_contract),
+                                gas: tx_config.request_gas(),
+                                gas_price: Some(self.app.foreign_gas_price(&tx_config)),
+                                value: None,
+                                data: Some(deposit_hash_payload(FINALIZE_DEPOSIT_FUNCTION, *hash)),
+                                nonce: None,
+                                condition: None,
+                            })
+                            .collect::<Vec<_>>();
+                        info!(
+                            target: "bridge::deposit_finalize",
+                            "finalizing {} deposits",
+                            requests.len()
+                        );
+                        let quota = foreign_quota(&self.app, Component::DepositRelay);
+                        DepositFinalizeState::Finalize {
+                            block,
+                            deposits: finalize,
+                            future: send_foreign_transactions(self.app.clone(), requests, quota),
+                        }
+                    }
+                }
+                DepositFinalizeState::Finalize {
+                    ref mut future,
+                    ref mut deposits,
+                    block,
+                } => {
+                    let transactions = try_ready!(future.poll());
+                    let mut finalizations = Vec::new();
+                    for (hash, transaction) in mem::replace(deposits, Vec::new())
+                        .into_iter()
+                        .zip(transactions)
+                    {
+                        info!(
+                            target: "bridge::deposit_finalize",
+                            "deposit in home transaction {} finalized in foreign transaction {}",
+                            hash,
+                            transaction
+                        );
+                        if let Some(deposit) = self.pending
+                            .iter_mut()
+                            .find(|deposit| deposit.main_tx_hash == hash)
+                        {
+                            deposit.sent_block = Some(block);
+                        }
+                        finalizations.push(Finalization {
+                            main_tx_hash: hash,
+                            transaction,
+                        });
+                    }
+                    DepositFinalizeState::Yield(Some(finalizations))
+                }
+                DepositFinalizeState::Yield(ref mut finalizations) => match finalizations.take() {
+                    None => DepositFinalizeState::Wait,
+                    some => return Ok(some.into()),
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+fn decode_uint(output: &[u8]) -> Result<U256> {
+    match ethabi::decode(&[ParamType::Uint(256)], output)?.pop() {
+        Some(Token::Uint(value)) => Ok(value),
+        _ => Err("numDepositSignatures returned an unexpected output".into()),
+    }
+}
+
+fn decode_bool(output: &[u8]) -> Result<bool> {
+    match ethabi::decode(&[ParamType::Bool], output)?.pop() {
+        Some(Token::Bool(value)) => Ok(value),
+        _ => Err("finalized returned an unexpected output".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::{self, Token};
+    use tiny_keccak::keccak256;
+    use web3::types::{H256, Log};
+    use super::{deposit_hash_payload, signed_deposit_topic, PendingDeposit, SignedDeposit,
+                FINALIZE_DEPOSIT_FUNCTION};
+
+    fn pending(slot: u64, threshold_block: Option<u64>, sent_block: Option<u64>) -> PendingDeposit {
+        PendingDeposit {
+            main_tx_hash: 1.into(),
+            slot: Some(slot),
+            threshold_block,
+            sent_block,
+        }
+    }
+
+    #[test]
+    fn test_signed_deposit_topic() {
+        assert_eq!(
+            signed_deposit_topic(),
+            keccak256(b"SignedDeposit(address,bytes32)").into()
+        );
+    }
+
+    #[test]
+    fn test_signed_deposit_from_log() {
+        let log = Log {
+            topics: vec![signed_deposit_topic()],
+            data: ethabi::encode(&[Token::Address(0xaa.into()), Token::FixedBytes(vec![0x11; 32])])
+                .into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            SignedDeposit {
+                authority: 0xaa.into(),
+                main_tx_hash: [0x11u8; 32].into(),
+            },
+            SignedDeposit::from_log(&log).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_deposit_hash_payload() {
+        let payload = deposit_hash_payload(FINALIZE_DEPOSIT_FUNCTION, H256::from(5));
+        assert_eq!(&keccak256(b"finalizeDeposit(bytes32)")[..4], &payload.0[..4]);
+        assert_eq!(&H256::from(5).0[..], &payload.0[4..]);
+    }
+
+    #[test]
+    fn test_should_finalize() {
+        // below the threshold
+        assert!(!pending(0, None, None).should_finalize(100, 20));
+        // the first slot finalizes right away
+        assert!(pending(0, Some(100), None).should_finalize(100, 20));
+        // the next slot waits a turn
+        assert!(!pending(1, Some(100), None).should_finalize(119, 20));
+        assert!(pending(1, Some(100), None).should_finalize(120, 20));
+        // a finalization is sent again if it didn't take effect within a turn
+        assert!(!pending(0, Some(100), Some(100)).should_finalize(119, 20));
+        assert!(pending(0, Some(100), Some(100)).should_finalize(120, 20));
+        // not an authority
+        let mut deposit = pending(0, Some(100), None);
+        deposit.slot = None;
+        assert!(!deposit.should_finalize(1000, 20));
+    }
+}
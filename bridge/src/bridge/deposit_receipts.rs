@@ -0,0 +1,322 @@
+use std::mem;
+use std::time::Instant;
+use web3::types::H256;
+use config::ReceiptRegistryConfig;
+use database::{Database, DepositReceipt};
+use error::Error;
+use super::relay_events::EventCheck;
+
+/// number of receipts `DepositReceipts` keeps.
+pub const KEPT_RECEIPTS: usize = 1024;
+
+/// event of the relay that completes a deposit on foreign, the last required confirmation.
+/// the relays of the other authorities emit `DepositConfirmation`.
+pub const COMPLETED_DEPOSIT_EVENT: &str = "Deposit(address,uint256,bytes32)";
+
+/// Receipts of the deposits this authority completed, see `ReceiptRegistryConfig`.
+/// most recent `KEPT_RECEIPTS` receipts, oldest first.
+///
+/// only the authority whose relay emitted `Deposit` records the receipt of a deposit,
+/// so every deposit is recorded once. like `CollectedSignatures` makes the last signer of a
+/// withdraw responsible for its relay. a receipt the registry has already is not recorded
+/// again. a receipt that fails to record is recorded again after `retry_delay`. once it was
+/// attempted `attempts` times it's given up. a failed receipt never fails the deposit relay.
+#[derive(Debug, Clone, Default)]
+pub struct DepositReceipts {
+    /// `None` if `receipt_registry` isn't configured
+    config: Option<ReceiptRegistryConfig>,
+    receipts: Vec<DepositReceipt>,
+    /// receipts that failed, by their home transaction, and when they are recorded again
+    retry_at: Vec<(H256, Instant)>,
+    /// home transactions of the receipts in flight
+    in_flight: Vec<H256>,
+    /// receipts given up since the last `take_given_up`
+    given_up: Vec<DepositReceipt>,
+}
+
+impl DepositReceipts {
+    /// receipts continuing from those of `init`. receipts without transaction that weren't
+    /// given up are recorded again.
+    pub fn new(config: Option<&ReceiptRegistryConfig>, init: &Database) -> Self {
+        let config = match config {
+            Some(config) => config,
+            None => return DepositReceipts::default(),
+        };
+        DepositReceipts {
+            config: Some(config.clone()),
+            receipts: init.deposit_receipts.clone(),
+            ..DepositReceipts::default()
+        }
+    }
+
+    fn cap(&self) -> u32 {
+        self.config.as_ref().map_or(0, |config| config.attempts)
+    }
+
+    /// `true` if `receipt` is waiting to be recorded.
+    fn pending(&self, receipt: &DepositReceipt) -> bool {
+        receipt.transaction.is_none() && !receipt.already_recorded && receipt.attempts < self.cap()
+    }
+
+    /// queues the receipt of the deposit in home transaction `home_transaction` completed in
+    /// foreign transaction `relay_transaction`. ignored if the registry isn't configured.
+    pub fn queue(&mut self, home_transaction: H256, relay_transaction: H256) {
+        if self.config.is_none()
+            || self.receipts
+                .iter()
+                .any(|receipt| receipt.home_transaction == home_transaction)
+        {
+            return;
+        }
+        if self.receipts.len() >= KEPT_RECEIPTS {
+            self.receipts.remove(0);
+        }
+        self.receipts.push(DepositReceipt {
+            home_transaction,
+            relay_transaction,
+            attempts: 0,
+            transaction: None,
+            already_recorded: false,
+            error: None,
+        });
+    }
+
+    /// `true` if a receipt can be recorded at `now`.
+    pub fn has_due(&self, now: Instant) -> bool {
+        self.in_flight.is_empty()
+            && self.receipts
+                .iter()
+                .any(|receipt| self.pending(receipt) && self.is_due(receipt, now))
+    }
+
+    fn is_due(&self, receipt: &DepositReceipt, now: Instant) -> bool {
+        self.retry_at
+            .iter()
+            .find(|&&(home, _)| home == receipt.home_transaction)
+            .map_or(true, |&(_, at)| at <= now)
+    }
+
+    /// starts an attempt of every receipt that can be recorded at `now`.
+    /// returns the home and relay transaction of each of them. every receipt started
+    /// must end with `already_recorded`, `sent` or `fail`.
+    pub fn start_due(&mut self, now: Instant) -> Vec<(H256, H256)> {
+        let due = self.receipts
+            .iter()
+            .filter(|receipt| self.pending(receipt) && self.is_due(receipt, now))
+            .map(|receipt| receipt.home_transaction)
+            .collect::<Vec<_>>();
+        let mut started = Vec::new();
+        for home in due {
+            if let Some(receipt) = self.receipt_mut(home) {
+                receipt.attempts += 1;
+                started.push((home, receipt.relay_transaction));
+            }
+            self.in_flight.push(home);
+        }
+        started
+    }
+
+    fn receipt_mut(&mut self, home_transaction: H256) -> Option<&mut DepositReceipt> {
+        self.receipts
+            .iter_mut()
+            .find(|receipt| receipt.home_transaction == home_transaction)
+    }
+
+    fn end(&mut self, home_transaction: H256) {
+        self.in_flight.retain(|&home| home != home_transaction);
+        self.retry_at.retain(|&(home, _)| home != home_transaction);
+    }
+
+    /// the registry has a receipt of `home_transaction` already. it isn't recorded again.
+    pub fn already_recorded(&mut self, home_transaction: H256) {
+        self.end(home_transaction);
+        if let Some(receipt) = self.receipt_mut(home_transaction) {
+            receipt.already_recorded = true;
+            receipt.error = None;
+        }
+    }
+
+    /// the receipt of `home_transaction` was recorded in `transaction`.
+    pub fn sent(&mut self, home_transaction: H256, transaction: H256) {
+        self.end(home_transaction);
+        if let Some(receipt) = self.receipt_mut(home_transaction) {
+            receipt.transaction = Some(transaction);
+            receipt.error = None;
+        }
+    }
+
+    /// the receipt of `home_transaction` failed with `err` at `now`. it's recorded again after
+    /// `retry_delay` or given up if it was attempted `attempts` times.
+    pub fn fail(&mut self, home_transaction: H256, err: &Error, now: Instant) {
+        let (cap, retry_delay) = match self.config {
+            Some(ref config) => (config.attempts, config.retry_delay),
+            None => return,
+        };
+        self.end(home_transaction);
+        let given_up = match self.receipt_mut(home_transaction) {
+            Some(receipt) => {
+                receipt.error = Some(err.to_string());
+                if receipt.attempts >= cap {
+                    Some(receipt.clone())
+                } else {
+                    None
+                }
+            }
+            None => return,
+        };
+        match given_up {
+            Some(receipt) => self.given_up.push(receipt),
+            None => self.retry_at.push((home_transaction, now + retry_delay)),
+        }
+    }
+
+    /// every receipt in flight failed with `err` at `now`, see `fail`.
+    pub fn fail_in_flight(&mut self, err: &Error, now: Instant) {
+        for home_transaction in self.in_flight.clone() {
+            self.fail(home_transaction, err, now);
+        }
+    }
+
+    /// kept receipts, oldest first.
+    pub fn receipts(&self) -> &[DepositReceipt] {
+        &self.receipts
+    }
+
+    /// returns the receipts given up since the last call.
+    pub fn take_given_up(&mut self) -> Vec<DepositReceipt> {
+        mem::replace(&mut self.given_up, Vec::new())
+    }
+}
+
+/// queues the receipts of the deposits of `relays`, home and relay transactions, whose relay
+/// completed them. relays that only confirmed a deposit are recorded by the authority that
+/// completes it.
+pub fn queue_receipts(
+    receipts: &mut DepositReceipts,
+    checks: &[EventCheck],
+    relays: &[(H256, H256)],
+) {
+    for check in checks {
+        if check.event != Some(COMPLETED_DEPOSIT_EVENT) {
+            continue;
+        }
+        let deposit = relays
+            .iter()
+            .find(|&&(_, relay_hash)| relay_hash == check.transaction);
+        if let Some(&(deposit_hash, _)) = deposit {
+            let relay = check.retry.map_or(check.transaction, |retry| retry.transaction);
+            receipts.queue(deposit_hash, relay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use web3::types::{Address, H256};
+    use config::ReceiptRegistryConfig;
+    use database::Database;
+    use error::Error;
+    use super::super::gas_usage::RelayCall;
+    use super::super::relay_events::{EventCheck, EventOutcome, ExpectedEvent};
+    use super::{queue_receipts, DepositReceipts, COMPLETED_DEPOSIT_EVENT};
+
+    fn config() -> ReceiptRegistryConfig {
+        ReceiptRegistryConfig {
+            address: 13.into(),
+            attempts: 2,
+            retry_delay: Duration::from_secs(60),
+        }
+    }
+
+    fn check(transaction: u64, event: Option<&'static str>) -> EventCheck {
+        EventCheck {
+            transaction: transaction.into(),
+            expected: ExpectedEvent {
+                contract: Address::from(9),
+                alternatives: Vec::new(),
+                call: RelayCall::Deposit,
+                gas: None,
+                resend: None,
+            },
+            outcome: if event.is_some() {
+                EventOutcome::Emitted
+            } else {
+                EventOutcome::Missing
+            },
+            gas_used: Some(50_000.into()),
+            retry: None,
+            event,
+        }
+    }
+
+    #[test]
+    fn test_deposit_receipts_are_queued_by_the_authority_completing_the_deposit() {
+        let mut receipts = DepositReceipts::new(Some(&config()), &Database::default());
+        let checks = [
+            check(10, Some("DepositConfirmation(address,uint256,bytes32)")),
+            check(20, Some(COMPLETED_DEPOSIT_EVENT)),
+            check(30, None),
+        ];
+        let relays = [(1.into(), 10.into()), (2.into(), 20.into()), (3.into(), 30.into())];
+        queue_receipts(&mut receipts, &checks, &relays);
+        // the same deposit is only recorded once
+        queue_receipts(&mut receipts, &checks, &relays);
+
+        let kept = receipts.receipts();
+        assert_eq!(1, kept.len());
+        assert_eq!(H256::from(2), kept[0].home_transaction);
+        assert_eq!(H256::from(20), kept[0].relay_transaction);
+
+        let now = Instant::now();
+        assert!(receipts.has_due(now));
+        assert_eq!(vec![(H256::from(2), H256::from(20))], receipts.start_due(now));
+        // nothing else is started while receipts are in flight
+        assert!(!receipts.has_due(now));
+        receipts.sent(2.into(), 100.into());
+        assert_eq!(Some(H256::from(100)), receipts.receipts()[0].transaction);
+        assert!(!receipts.has_due(now));
+
+        // without config nothing is queued
+        let mut receipts = DepositReceipts::new(None, &Database::default());
+        queue_receipts(&mut receipts, &checks, &relays);
+        assert!(receipts.receipts().is_empty());
+    }
+
+    #[test]
+    fn test_deposit_receipts_retry_give_up_and_skip_recorded() {
+        let mut receipts = DepositReceipts::new(Some(&config()), &Database::default());
+        let now = Instant::now();
+        receipts.queue(1.into(), 10.into());
+        receipts.queue(2.into(), 20.into());
+        assert_eq!(2, receipts.start_due(now).len());
+        receipts.already_recorded(1.into());
+        receipts.fail(2.into(), &Error::from("nonce too low"), now);
+        assert!(receipts.receipts()[0].already_recorded);
+        assert_eq!(
+            Some("nonce too low".to_owned()),
+            receipts.receipts()[1].error
+        );
+        // deferred by the retry delay
+        assert!(!receipts.has_due(now));
+        let later = now + Duration::from_secs(60);
+        assert_eq!(vec![(H256::from(2), H256::from(20))], receipts.start_due(later));
+        receipts.fail(2.into(), &Error::from("timeout"), later);
+
+        let given_up = receipts.take_given_up();
+        assert_eq!(1, given_up.len());
+        assert_eq!(2, given_up[0].attempts);
+        assert_eq!(Some("timeout".to_owned()), given_up[0].error);
+        assert!(!receipts.has_due(later + Duration::from_secs(3600)));
+        assert!(receipts.take_given_up().is_empty());
+
+        // receipts continue from the database, failed ones aren't deferred after a restart
+        receipts.queue(3.into(), 30.into());
+        let database = Database {
+            deposit_receipts: receipts.receipts().to_vec(),
+            ..Database::default()
+        };
+        let mut receipts = DepositReceipts::new(Some(&config()), &database);
+        assert_eq!(vec![(H256::from(3), H256::from(30))], receipts.start_due(now));
+    }
+}
@@ -1,16 +1,52 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::mem;
 use std::sync::Arc;
-use futures::{Future, Poll, Stream};
+use std::time::{Duration, Instant};
+use futures::{Async, Future, Poll, Stream};
 use futures::future::{join_all, JoinAll};
-use tokio_timer::Timeout;
+use timer::{Sleep, Timer};
+use tracing::Span;
 use web3::Transport;
-use web3::types::{Address, Bytes, FilterBuilder, H256, Log, TransactionRequest};
-use ethabi::RawLog;
-use api::{self, ApiCall, LogStream};
-use error::{Error, Result};
-use database::Database;
-use contracts::{foreign, home};
-use util::web3_filter;
+use web3::types::{Address, Bytes, FilterBuilder, H256, H520, Log, Transaction,
+                  TransactionReceipt, TransactionRequest, U256};
+use ethabi;
+use acknowledgement::Acknowledgement;
+use api::{self, ApiCall, LogStream, OversizedLog, Settle, Timeout};
+use error::{Error, ErrorKind, Result, ResultExt};
+use jitter;
+use database::{Database, QuarantinedDeposit, WatchedExecution};
+use finality::SharedFinality;
+use contracts::{foreign, home, receipt_registry};
+use util::{check_log_data, log_id, log_transaction_hash, raw_log, shared_transaction_hashes,
+           web3_filter};
 use app::App;
+use call_template::CallArgs;
+use config::{Acknowledgements, DerivedRecipientsConfig, RecipientPolicy, Rounding, ValueScale};
+use units::format_wei;
+use runtime_abi::RuntimeAbi;
+use super::{relay_key, relay_span, Component, Direction, MainPosition, RelayCounts, RelayRecord,
+            RelayedLog};
+use super::call_data::{check_call_data, ExpectedCall};
+use super::contract_recipients::{simulation_revert, RecipientKind, RecipientKinds};
+use super::deposit_callback::DepositCallbacks;
+use super::deposit_receipts::{queue_receipts, DepositReceipts};
+use super::deposit_responsibility::{fallback_delay, responsible_authorities};
+use super::derived_recipient::{derive_recipient, foreign_recipient, DerivedRecipient};
+use super::memo::extract_memos;
+use super::observed_traffic::Observation;
+use super::same_chain::sent_by_authority;
+use super::gas_price_tiers::{deposit_gas_price, TieredGasPrice};
+use super::gas_usage::{gas_samples, GasSample};
+use super::held_deposits::{HeldDeposit, HeldDeposits, HeldValue, UNTIL_RELEASED};
+use super::relay_events::{expected_events, record_event_checks, verify_relay_events, EventCheck,
+                          EventOutcome, ExpectedEvent, VerifyRelayEvents};
+use super::schedule::{foreign_quota, send_foreign_transactions, send_ordered_foreign_transactions,
+                      QueueStatus, SendTransactions};
+use super::pending_age::{PendingAges, PendingRelay};
+use super::relay_outcome::{DepositOutcome, RelayOutcome, Skip, SkipReason};
+use super::spend_limit::{unix_time, SpendKind, SpendLimiter, DEPOSIT_SPEND_WINDOW};
+use super::step_timing::{RelayStep, StepTimer};
 
 fn deposits_filter(home: &home::HomeBridge, address: Address) -> FilterBuilder {
     let filter = home.events().deposit().create_filter();
@@ -21,50 +57,490 @@ fn deposit_relay_payload(
     home: &home::HomeBridge,
     foreign: &foreign::ForeignBridge,
     log: Log,
+    unique_ids: bool,
+    scale: Option<&ValueScale>,
+    derived: Option<&DerivedRecipientsConfig>,
 ) -> Result<Bytes> {
-    let raw_log = RawLog {
-        topics: log.topics,
-        data: log.data.0,
-    };
-    let deposit_log = home.events().deposit().parse_log(raw_log)?;
-    let hash = log.transaction_hash
-        .expect("log to be mined and contain `transaction_hash`");
+    parse_deposit_relay_payload(home, foreign, &log, unique_ids, scale, derived)
+        .chain_err(|| ErrorKind::InvalidLog(log))
+}
+
+fn parse_deposit_relay_payload(
+    home: &home::HomeBridge,
+    foreign: &foreign::ForeignBridge,
+    log: &Log,
+    unique_ids: bool,
+    scale: Option<&ValueScale>,
+    derived: Option<&DerivedRecipientsConfig>,
+) -> Result<Bytes> {
+    let deposit_log = home.events().deposit().parse_log(raw_log(log))?;
+    let hash = log_transaction_hash(log)?;
+    let id = log_id(log, unique_ids)?;
+    info!(
+        target: "bridge::deposit_relay",
+        "deposit is ready for relay. tx hash: {}",
+        hash
+    );
     let payload = foreign.functions().deposit().input(
-        deposit_log.recipient,
-        deposit_log.value,
-        hash.0,
+        foreign_recipient(derived, deposit_log.recipient),
+        foreign_value(scale, deposit_log.value)?,
+        id.0,
+    );
+    Ok(payload.into())
+}
+
+/// `ForeignBridge.deposit` call the payload of the deposit in `log` encodes.
+/// derived from `log` independently of `parse_deposit_relay_payload`.
+fn deposit_relay_call(
+    home: &home::HomeBridge,
+    log: &Log,
+    unique_ids: bool,
+    scale: Option<&ValueScale>,
+    derived: Option<&DerivedRecipientsConfig>,
+) -> Result<ExpectedCall> {
+    let deposit_log = home.events().deposit().parse_log(raw_log(log))?;
+    Ok(ExpectedCall::deposit(
+        foreign_recipient(derived, deposit_log.recipient),
+        foreign_value(scale, deposit_log.value)?,
+        log_id(log, unique_ids)?,
+    ))
+}
+
+/// value of the deposit in `log` on home.
+fn deposit_value(home: &home::HomeBridge, log: &Log) -> Result<U256> {
+    let deposit_log = home.events().deposit().parse_log(raw_log(log))?;
+    Ok(deposit_log.value)
+}
+
+/// recipient of the deposit in `log` on home, decoded with the runtime ABI of `app` if loaded.
+fn deposit_recipient<T: Transport>(app: &App<T>, log: &Log) -> Result<Address> {
+    match app.abi {
+        Some(ref abi) => Ok(abi.parse_deposit(log)?.recipient),
+        None => Ok(app.home_bridge
+            .events()
+            .deposit()
+            .parse_log(raw_log(log))?
+            .recipient),
+    }
+}
+
+/// converts the value of a deposit to the value minted on foreign.
+fn foreign_value(scale: Option<&ValueScale>, value: U256) -> Result<U256> {
+    match scale {
+        Some(scale) => Ok(scale.deposit(value)?.value),
+        None => Ok(value),
+    }
+}
+
+/// skips zero value deposits unless `relay_zero_value` is enabled and deposits whose value
+/// `value_scale` can't convert.
+fn deposit_outcome<T: Transport>(
+    app: &App<T>,
+    counts: &mut RelayCounts,
+    log: &Log,
+) -> Result<RelayOutcome> {
+    let value = app_deposit_value(app, log).chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+    if value.is_zero() && !app.config.relay_zero_value {
+        debug!(
+            target: "bridge::deposit_relay",
+            "skipping zero value deposit in home transaction {:?}",
+            log.transaction_hash
+        );
+        counts.skipped_zero_value += 1;
+        return Ok(RelayOutcome::Skipped(Skip::new(
+            SkipReason::ZeroValue,
+            "zero value deposits are only relayed with relay_zero_value",
+        )));
+    }
+    if let Some(ref scale) = app.config.value_scale {
+        match scale.deposit(value) {
+            Ok(scaled) => {
+                if !scaled.dust.is_zero() && scale.rounding == Rounding::FloorWithDust {
+                    info!(
+                        target: "bridge::deposit_relay",
+                        "deposit of {} in home transaction {:?} rounded down to {}. dust: {}",
+                        value,
+                        log.transaction_hash,
+                        scaled.value,
+                        scaled.dust
+                    );
+                    counts.dust = counts.dust + scaled.dust;
+                }
+            }
+            Err(err) => {
+                error!(
+                    target: "bridge::deposit_relay",
+                    "not relaying deposit in home transaction {:?}: {}",
+                    log.transaction_hash,
+                    err
+                );
+                counts.rejected_values += 1;
+                let skip = Skip::new(SkipReason::RejectedValue, err.to_string()).with("value", value);
+                return Ok(RelayOutcome::Skipped(skip));
+            }
+        }
+    }
+    Ok(RelayOutcome::Relayed)
+}
+
+/// `outcome` of the deposit in `log`.
+fn log_outcome(log: &Log, outcome: RelayOutcome) -> DepositOutcome {
+    DepositOutcome {
+        home_transaction: log.transaction_hash.unwrap_or_default(),
+        log_index: log.log_index,
+        block: log.block_number.map(|block| block.low_u64()),
+        outcome,
+    }
+}
+
+/// payload for `ForeignBridge.isDepositExecuted` of the deposit in `log`.
+fn deposit_executed_payload(
+    home: &home::HomeBridge,
+    foreign: &foreign::ForeignBridge,
+    log: &Log,
+    unique_ids: bool,
+    scale: Option<&ValueScale>,
+    derived: Option<&DerivedRecipientsConfig>,
+) -> Result<Bytes> {
+    let deposit_log = home.events().deposit().parse_log(raw_log(log))?;
+    let id = log_id(log, unique_ids)?;
+    let payload = foreign.functions().is_deposit_executed().input(
+        foreign_recipient(derived, deposit_log.recipient),
+        foreign_value(scale, deposit_log.value)?,
+        id.0,
     );
     Ok(payload.into())
 }
 
+/// value of the deposit in `log` on home, decoded with the runtime ABI of `app` if loaded.
+pub fn app_deposit_value<T: Transport>(app: &App<T>, log: &Log) -> Result<U256> {
+    check_log_data(log)?;
+    match app.abi {
+        Some(ref abi) => Ok(abi.parse_deposit(log)?.value),
+        None => deposit_value(&app.home_bridge, log),
+    }
+}
+
+/// `ForeignBridge.deposit` call and its payload relaying the deposit in `log` with `abi`.
+fn runtime_deposit_relay(
+    abi: &RuntimeAbi,
+    log: &Log,
+    unique_ids: bool,
+    scale: Option<&ValueScale>,
+    derived: Option<&DerivedRecipientsConfig>,
+) -> Result<(ExpectedCall, Bytes)> {
+    let mut deposit = abi.parse_deposit(log)?;
+    deposit.recipient = foreign_recipient(derived, deposit.recipient);
+    let hash = log_transaction_hash(log)?;
+    let id = log_id(log, unique_ids)?;
+    let value = foreign_value(scale, deposit.value)?;
+    info!(
+        target: "bridge::deposit_relay",
+        "deposit is ready for relay. tx hash: {}",
+        hash
+    );
+    let call = abi.deposit_call(&deposit, value, id)?;
+    let payload = abi.deposit_payload(&deposit, value, id)?;
+    Ok((call, payload))
+}
+
+/// `ForeignBridge.isDepositExecuted` payload of the deposit in `log` with `abi`.
+fn runtime_deposit_executed_payload(
+    abi: &RuntimeAbi,
+    log: &Log,
+    unique_ids: bool,
+    scale: Option<&ValueScale>,
+    derived: Option<&DerivedRecipientsConfig>,
+) -> Result<Bytes> {
+    let mut deposit = abi.parse_deposit(log)?;
+    deposit.recipient = foreign_recipient(derived, deposit.recipient);
+    let id = log_id(log, unique_ids)?;
+    abi.deposit_executed_payload(&deposit, foreign_value(scale, deposit.value)?, id)
+}
+
+/// `ForeignBridge.isDepositExecuted` payload of the deposit in `log`,
+/// encoded with the runtime ABI of `app` if loaded.
+pub fn app_deposit_executed_payload<T: Transport>(app: &App<T>, log: &Log) -> Result<Bytes> {
+    let unique_ids = app.config.unique_log_ids;
+    let scale = app.config.value_scale.as_ref();
+    let derived = app.config.derived_recipients.as_ref();
+    match app.abi {
+        Some(ref abi) => runtime_deposit_executed_payload(abi, log, unique_ids, scale, derived),
+        None => deposit_executed_payload(
+            &app.home_bridge,
+            &app.foreign_bridge,
+            log,
+            unique_ids,
+            scale,
+            derived,
+        ),
+    }
+}
+
+/// A deposit ready to be relayed.
+struct PendingDeposit {
+    /// hash of the home transaction that emitted the deposit
+    hash: H256,
+    /// index of the deposit log in its block
+    log_index: Option<U256>,
+    /// position of the deposit log on home, if the node returned all of it
+    position: Option<MainPosition>,
+    /// payload of `ForeignBridge.deposit`
+    payload: Bytes,
+    /// value of the deposit on home. counted by the spend limit
+    value: U256,
+    /// sender of the home transaction. only fetched if `log_deposit_senders` is enabled
+    sender: Option<Address>,
+    /// memo decoded from the call data of the home transaction if `deposit_memo` is configured
+    memo: Option<H256>,
+    /// recipient of the deposit on foreign
+    recipient: Address,
+    /// recipient on home and its contract wallet `recipient` if `derived_recipients` is configured
+    derived: Option<DerivedRecipient>,
+    /// kind of `recipient`. only fetched if `contract_recipients` is configured
+    recipient_kind: Option<RecipientKind>,
+}
+
+impl PendingDeposit {
+    /// key of the relay, see `relay_key`.
+    fn key(&self) -> H256 {
+        relay_key(Direction::HomeToForeign, &self.hash, self.log_index)
+    }
+
+    /// `outcome` of the deposit.
+    fn outcome(&self, outcome: RelayOutcome) -> DepositOutcome {
+        DepositOutcome {
+            home_transaction: self.hash,
+            log_index: self.log_index,
+            block: self.position.map(|position| position.block_number),
+            outcome,
+        }
+    }
+}
+
+/// A deposit whose relay is being sent.
+struct RelayingDeposit {
+    deposit: PendingDeposit,
+    /// gas price of the relay if `gas_price_tiers` is configured
+    gas_price: Option<TieredGasPrice>,
+    /// event the relay must emit. `None` unless `relay_events` is configured
+    expected: Option<ExpectedEvent>,
+    /// arguments of the callback of the deposit. `None` unless `deposit_callback` is configured
+    callback: Option<CallArgs>,
+}
+
+/// A deposit relayed in `relay_transaction`.
+struct SentRelay {
+    /// hash of the home transaction that emitted the deposit
+    hash: H256,
+    log_index: Option<U256>,
+    relay_transaction: H256,
+    callback: Option<CallArgs>,
+}
+
 /// State of deposits relay.
 enum DepositRelayState<T: Transport> {
     /// Deposit relay is waiting for logs.
     Wait,
+    /// Fetching the home receipts of deposits whose execution a foreign reorg invalidated.
+    FetchRequeued {
+        future: JoinAll<Vec<Timeout<ApiCall<Option<TransactionReceipt>, T::Out>>>>,
+    },
+    /// Fetching the home receipts of held deposits deep enough to relay, see `HeldDeposits`.
+    FetchReleased {
+        future: JoinAll<Vec<Timeout<ApiCall<Option<TransactionReceipt>, T::Out>>>>,
+        deposits: Vec<HeldDeposit<u64>>,
+    },
+    /// Checking which deposits have already been executed by other authorities.
+    CheckExecuted {
+        future: JoinAll<Vec<Timeout<ApiCall<Bytes, T::Out>>>>,
+        deposits: Vec<PendingDeposit>,
+        relayed_logs: Vec<RelayedLog>,
+        /// `true` if the deposits waited for the authorities responsible for them
+        awaited: bool,
+        block: u64,
+    },
+    /// Fetching the code of the recipients of deposits to find which are contracts.
+    FetchRecipients {
+        future: JoinAll<Vec<Timeout<ApiCall<Bytes, T::Out>>>>,
+        /// recipients whose code is fetched
+        recipients: Vec<Address>,
+        deposits: Vec<PendingDeposit>,
+        relayed_logs: Vec<RelayedLog>,
+        block: u64,
+    },
+    /// Simulating the deposits to contracts to find those their recipient rejects.
+    SimulateContractDeposits {
+        future: JoinAll<Vec<Settle<Timeout<ApiCall<Bytes, T::Out>>>>>,
+        /// indexes of the simulated deposits in `deposits`
+        simulated: Vec<usize>,
+        deposits: Vec<PendingDeposit>,
+        relayed_logs: Vec<RelayedLog>,
+        block: u64,
+    },
+    /// Fetching the home transactions of deposits to find their senders and memos.
+    FetchSenders {
+        future: JoinAll<Vec<Timeout<ApiCall<Option<Transaction>, T::Out>>>>,
+        deposits: Vec<PendingDeposit>,
+        /// deposits beyond the spend limit
+        deferred: Vec<PendingDeposit>,
+        relayed_logs: Vec<RelayedLog>,
+        block: u64,
+    },
     /// Relaying deposits in progress.
     RelayDeposits {
-        future: JoinAll<Vec<Timeout<ApiCall<H256, T::Out>>>>,
+        future: SendTransactions<T>,
+        /// relayed deposits in the order of their relay transactions
+        deposits: Vec<RelayingDeposit>,
+        /// deposits beyond the spend limit. relayed once the limit allows them
+        deferred: Vec<PendingDeposit>,
+        /// relayed logs to report once all deposits are relayed. empty if not tracked
+        relayed_logs: Vec<RelayedLog>,
+        block: u64,
+    },
+    /// Checking the receipts of the relays for the expected events.
+    VerifyEvents {
+        future: VerifyRelayEvents<T>,
+        relays: Vec<SentRelay>,
+        deferred: Vec<PendingDeposit>,
+        relayed_logs: Vec<RelayedLog>,
+        block: u64,
+    },
+    /// Signing the acknowledgements of the relayed deposits.
+    SignAcknowledgements {
+        future: JoinAll<Vec<Settle<Timeout<ApiCall<H520, T::Out>>>>>,
+        acknowledgements: Vec<Acknowledgement>,
+        deferred: Vec<PendingDeposit>,
+        relayed_logs: Vec<RelayedLog>,
         block: u64,
     },
+    /// Publishing the signed acknowledgements to the registry contract.
+    PublishAcknowledgements {
+        future: JoinAll<Vec<Settle<Timeout<ApiCall<H256, T::Out>>>>>,
+        deferred: Vec<PendingDeposit>,
+        relayed_logs: Vec<RelayedLog>,
+        block: u64,
+    },
+    /// Deposits beyond the spend limit. Checking the limit again after a delay.
+    DeferDeposits {
+        future: Sleep,
+        deposits: Vec<PendingDeposit>,
+        relayed_logs: Vec<RelayedLog>,
+        block: u64,
+    },
+    /// Sending the callbacks of relayed deposits, see `DepositCallbacks`.
+    SendCallbacks { future: SendTransactions<T> },
+    /// Looking up the receipts of completed deposits in the registry, see `DepositReceipts`.
+    CheckReceipts {
+        future: JoinAll<Vec<Settle<Timeout<ApiCall<Bytes, T::Out>>>>>,
+        /// home and relay transactions of the receipts
+        receipts: Vec<(H256, H256)>,
+    },
+    /// Recording the receipts the registry doesn't have yet.
+    RecordReceipts {
+        future: JoinAll<Vec<Settle<Timeout<ApiCall<H256, T::Out>>>>>,
+        /// home transactions of the receipts with the cost of recording them, if known
+        receipts: Vec<(H256, Option<U256>)>,
+    },
     /// All deposits till given block has been relayed.
     Yield(Option<u64>),
 }
 
-pub fn create_deposit_relay<T: Transport + Clone>(
-    app: Arc<App<T>>,
-    init: &Database,
-) -> DepositRelay<T> {
+impl<T: Transport> DepositRelayState<T> {
+    /// timed step of the relays in this state. `None` if not timed.
+    fn step(&self) -> Option<RelayStep> {
+        match *self {
+            DepositRelayState::CheckExecuted { .. }
+            | DepositRelayState::FetchRecipients { .. }
+            | DepositRelayState::SimulateContractDeposits { .. } => Some(RelayStep::Check),
+            DepositRelayState::RelayDeposits { .. } => Some(RelayStep::Submit),
+            DepositRelayState::VerifyEvents { .. } => Some(RelayStep::Confirm),
+            _ => None,
+        }
+    }
+}
+
+/// deposit logs on home after the checked block of `init`.
+pub fn deposit_log_stream<T: Transport + Clone>(app: &App<T>, init: &Database) -> LogStream<T> {
     let logs_init = api::LogStreamInit {
         after: init.checked_deposit_relay,
+        chain: "home",
         request_timeout: app.config.home.request_timeout,
-        poll_interval: app.config.home.poll_interval,
+        poll_interval: app.tunables.get().home_poll_interval,
+        poll_jitter: jitter::poll_jitter(app.config.poll_jitter, &app.config.home.account),
         confirmations: app.config.home.required_confirmations,
-        filter: deposits_filter(&app.home_bridge, init.home_contract_address),
+        sanity_check: app.config.home.logs_sanity_check.clone(),
+        head_regression: app.config.home.head_regression,
+        log_limit_errors: app.config.home.log_limit_errors.clone(),
+        max_log_data: app.config.home.max_log_data,
+        finality: app.config.home.l1_posted_rpc(),
+        filter: match app.abi {
+            Some(ref abi) => abi.deposits_filter(init.home_contract_address),
+            None => deposits_filter(&app.home_bridge, init.home_contract_address),
+        },
     };
+    api::log_stream(app.connections.home.clone(), app.timer.clone(), logs_init)
+}
+
+pub fn create_deposit_relay<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    init: &Database,
+) -> DepositRelay<T> {
     DepositRelay {
-        logs: api::log_stream(app.connections.home.clone(), app.timer.clone(), logs_init),
+        logs: deposit_log_stream(&app, init),
+        home_contract: init.home_contract_address,
         foreign_contract: init.foreign_contract_address,
+        checked: init.checked_deposit_relay,
         state: DepositRelayState::Wait,
+        relayed: if app.config.reorg_monitor.is_some() {
+            Some(Vec::new())
+        } else {
+            None
+        },
+        history: if app.config.relay_history.is_some() || app.config.webhooks.is_some() {
+            Some(Vec::new())
+        } else {
+            None
+        },
+        executions: if app.config.execution_watch.is_some() {
+            Some(Vec::new())
+        } else {
+            None
+        },
+        requeued: Vec::new(),
+        relaying_requeued: Vec::new(),
+        requeued_logs: None,
+        awaiting: HeldDeposits::default(),
+        deadline: None,
+        awaited_logs: None,
+        held: HeldDeposits::default(),
+        confirmed: init.checked_deposit_relay,
+        released_logs: None,
+        counts: RelayCounts::default(),
+        gas_samples: Vec::new(),
+        spend_limiter: app.config
+            .spend_limits
+            .as_ref()
+            .and_then(|config| config.deposit_value)
+            .map(|limit| {
+                SpendLimiter::new(
+                    SpendKind::Deposits,
+                    limit,
+                    DEPOSIT_SPEND_WINDOW,
+                    &init.spend.deposits,
+                    init.checked_deposit_relay,
+                )
+            }),
+        pending: PendingAges::new(app.clone(), Direction::HomeToForeign),
+        steps: StepTimer::default(),
+        recipients: RecipientKinds::default(),
+        quarantined: Vec::new(),
+        outcomes: Vec::new(),
+        observations: Vec::new(),
+        callbacks: DepositCallbacks::new(app.config.deposit_callback.as_ref(), init),
+        receipts: DepositReceipts::new(app.config.receipt_registry.as_ref(), init),
+        draining: false,
+        blocks: Span::none(),
         app,
     }
 }
@@ -73,98 +549,2206 @@ pub struct DepositRelay<T: Transport> {
     app: Arc<App<T>>,
     logs: LogStream<T>,
     state: DepositRelayState<T>,
+    home_contract: Address,
     foreign_contract: Address,
+    /// last yielded block
+    checked: u64,
+    /// relayed logs not yet taken by `take_relayed`. `None` if not tracked
+    relayed: Option<Vec<RelayedLog>>,
+    /// relays not yet taken by `take_history`. `None` if not tracked
+    history: Option<Vec<RelayRecord>>,
+    /// executions not yet taken by `take_executions`. `None` if not tracked
+    executions: Option<Vec<WatchedExecution>>,
+    /// invalidated executions whose deposits are relayed again with the next batch
+    requeued: Vec<WatchedExecution>,
+    /// invalidated executions whose deposits are relayed again with the current batch
+    relaying_requeued: Vec<WatchedExecution>,
+    /// deposits of `relaying_requeued` to relay before polling for new logs
+    requeued_logs: Option<Vec<Log>>,
+    /// deposits other authorities are responsible for, until the deadline of the fallback of
+    /// this authority. empty unless `cooperative_deposits` is configured
+    awaiting: HeldDeposits<Instant>,
+    /// wakes the relay at the earliest deadline of `awaiting` while it waits for logs
+    deadline: Option<(Instant, Sleep)>,
+    /// deposits to check for execution after their deadline before polling for new logs
+    awaited_logs: Option<Vec<Log>>,
+    /// deposits to recipients with `extra_confirmations` or `manual` of `recipient_policies`.
+    /// `manual` ones are held until `UNTIL_RELEASED` unless released by `release_held`
+    held: HeldDeposits<u64>,
+    /// last block confirmed on home, i.e. the end of the last range of new logs
+    confirmed: u64,
+    /// held deposits deep enough to relay before polling for new logs
+    released_logs: Option<Vec<Log>>,
+    counts: RelayCounts,
+    /// gas used by the checked relays not yet taken by `take_gas_samples`
+    gas_samples: Vec<GasSample>,
+    /// `None` if `spend_limits.deposit_value` isn't configured
+    spend_limiter: Option<SpendLimiter>,
+    /// deposits observed but not yet relayed
+    pending: PendingAges<T>,
+    /// steps of the batch of deposits in flight
+    steps: StepTimer,
+    /// kinds of the recipients of deposits. only fetched if `contract_recipients` is configured
+    recipients: RecipientKinds,
+    /// quarantined deposits not yet taken by `take_quarantined`
+    quarantined: Vec<QuarantinedDeposit>,
+    /// outcomes of deposits not yet taken by `take_outcomes`
+    outcomes: Vec<DepositOutcome>,
+    /// deposits observed but not yet taken by `take_observations`
+    observations: Vec<Observation>,
+    /// callbacks of the relayed deposits. empty unless `deposit_callback` is configured
+    callbacks: DepositCallbacks,
+    /// receipts of the deposits completed. empty unless `receipt_registry` is configured
+    receipts: DepositReceipts,
+    /// `true` once drained. the relay ends instead of taking new logs or sending callbacks
+    draining: bool,
+    /// span of the blocks of the batch in flight
+    blocks: Span,
+}
+
+impl<T: Transport> DepositRelay<T> {
+    /// limit of the value of relayed deposits. `None` if not configured.
+    pub fn spend_limiter(&self) -> Option<&SpendLimiter> {
+        self.spend_limiter.as_ref()
+    }
+
+    pub fn spend_limiter_mut(&mut self) -> Option<&mut SpendLimiter> {
+        self.spend_limiter.as_mut()
+    }
+
+    /// replaces the spend limiter, e.g. with the one of the relay this relay is rebuilt from.
+    pub fn set_spend_limiter(&mut self, spend_limiter: Option<SpendLimiter>) {
+        self.spend_limiter = spend_limiter;
+    }
+
+    /// relays deposits only once `finality` reports their block finalized.
+    pub fn watch_home_finality(&mut self, finality: SharedFinality) {
+        self.logs.watch_finalized(finality);
+    }
+
+    /// returns the logs relayed since the last call.
+    /// only tracked if the reorg monitor is enabled.
+    pub fn take_relayed(&mut self) -> Vec<RelayedLog> {
+        match self.relayed {
+            Some(ref mut relayed) => relayed.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// returns the deposits relayed since the last call.
+    /// only tracked if the relay history or webhooks are enabled.
+    pub fn take_history(&mut self) -> Vec<RelayRecord> {
+        match self.history {
+            Some(ref mut history) => history.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// returns the foreign executions of the deposits relayed since the last call.
+    /// only tracked if the execution watch is enabled.
+    pub fn take_executions(&mut self) -> Vec<WatchedExecution> {
+        match self.executions {
+            Some(ref mut executions) => executions.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// returns the deposits quarantined since the last call since their recipient contract
+    /// rejects the transfer. only simulated if `contract_recipients` is configured.
+    pub fn take_quarantined(&mut self) -> Vec<QuarantinedDeposit> {
+        self.quarantined.drain(..).collect()
+    }
+
+    /// returns the deposits skipped and relayed since the last call.
+    /// a deposit deferred by the spend limit is skipped again every time it's checked.
+    pub fn take_outcomes(&mut self) -> Vec<DepositOutcome> {
+        mem::replace(&mut self.outcomes, Vec::new())
+    }
+
+    /// returns the deposits the log stream yielded since the last call, before deciding
+    /// whether to relay them. deposits of a rebuilt relay are observed again.
+    pub fn take_observations(&mut self) -> Vec<Observation> {
+        mem::replace(&mut self.observations, Vec::new())
+    }
+
+    /// callbacks of the relayed deposits.
+    pub fn callbacks(&self) -> &DepositCallbacks {
+        &self.callbacks
+    }
+
+    pub fn callbacks_mut(&mut self) -> &mut DepositCallbacks {
+        &mut self.callbacks
+    }
+
+    /// replaces the callbacks, e.g. with those of the relay this relay is rebuilt from.
+    pub fn set_callbacks(&mut self, callbacks: DepositCallbacks) {
+        self.callbacks = callbacks;
+    }
+
+    /// receipts of the deposits this authority completed.
+    pub fn receipts(&self) -> &DepositReceipts {
+        &self.receipts
+    }
+
+    pub fn receipts_mut(&mut self) -> &mut DepositReceipts {
+        &mut self.receipts
+    }
+
+    /// replaces the receipts, e.g. with those of the relay this relay is rebuilt from.
+    pub fn set_receipts(&mut self, receipts: DepositReceipts) {
+        self.receipts = receipts;
+    }
+
+    /// relays the deposits of `executions` again through the execution check.
+    /// used for executions a foreign reorg invalidated.
+    pub fn requeue<I: IntoIterator<Item = WatchedExecution>>(&mut self, executions: I) {
+        self.requeued.extend(executions);
+    }
+
+    /// returns the requeued executions whose deposits aren't relayed again yet,
+    /// e.g. to requeue them in the relay this relay is rebuilt as.
+    pub fn take_requeued(&mut self) -> Vec<WatchedExecution> {
+        let mut requeued = self.relaying_requeued.drain(..).collect::<Vec<_>>();
+        requeued.extend(self.requeued.drain(..));
+        requeued
+    }
+
+    /// stops taking new logs. the batch in flight is finished, then the relay ends.
+    pub fn drain(&mut self) {
+        self.draining = true;
+    }
+
+    /// returns the relay outcomes since the last call.
+    pub fn take_counts(&mut self) -> RelayCounts {
+        let mut counts = mem::replace(&mut self.counts, RelayCounts::default());
+        counts.pending_logs += self.logs.take_pending_logs();
+        counts
+    }
+
+    /// returns the logs the log stream dropped for their size since the last call.
+    pub fn take_oversized_logs(&mut self) -> Vec<OversizedLog> {
+        self.logs.take_oversized_logs()
+    }
+
+    /// returns the gas used by the relays checked since the last call.
+    /// empty unless `gas_usage` is configured.
+    pub fn take_gas_samples(&mut self) -> Vec<GasSample> {
+        mem::replace(&mut self.gas_samples, Vec::new())
+    }
+
+    /// the deposit observed the longest ago that isn't relayed yet.
+    /// `None` if all deposits up to the checked block are relayed.
+    pub fn oldest_pending(&mut self) -> Option<PendingRelay> {
+        self.pending.oldest()
+    }
+
+    /// deposits observed but not relayed yet, the oldest first.
+    pub fn pending_relays(&self) -> Vec<PendingRelay> {
+        self.pending.all()
+    }
+
+    /// value of the deposits held by `recipient_policies`, by recipient.
+    pub fn held_values(&self) -> Vec<HeldValue> {
+        let mut values = BTreeMap::new();
+        for deposit in self.held.iter() {
+            let recipient = deposit_recipient(&self.app, &deposit.log);
+            let value = app_deposit_value(&self.app, &deposit.log);
+            let (recipient, value) = match (recipient, value) {
+                (Ok(recipient), Ok(value)) => (recipient, value),
+                // the logs were parsed before they were held
+                _ => continue,
+            };
+            let policy = self.app
+                .config
+                .recipient_policies
+                .as_ref()
+                .map_or(RecipientPolicy::Normal, |policies| policies.policy(&recipient));
+            let held = values.entry(recipient).or_insert_with(|| HeldValue {
+                recipient,
+                policy,
+                value: U256::zero(),
+                keys: Vec::new(),
+            });
+            held.value = held.value + value;
+            held.keys.push(deposit.key());
+        }
+        values.into_iter().map(|(_, held)| held).collect()
+    }
+
+    /// relays the deposit with relay `key` held for its `manual` recipient of
+    /// `recipient_policies` once the relay is polled, like a confirmed deposit held for
+    /// `extra_confirmations`. a restart before it's relayed holds it again.
+    pub fn release_held(&mut self, key: &H256) -> Result<()> {
+        let manual = self.held
+            .get(key)
+            .map(|deposit| deposit.release == UNTIL_RELEASED);
+        match manual {
+            Some(true) => {}
+            Some(false) => bail!(
+                "deposit with key {:?} is held for extra confirmations, not for release",
+                key
+            ),
+            None => bail!("no deposit with key {:?} is held for release", key),
+        }
+        let confirmed = self.confirmed;
+        self.held.release(key, confirmed);
+        info!(
+            target: "bridge::deposit_relay",
+            "released held deposit with key {:?}",
+            key
+        );
+        Ok(())
+    }
+
+    /// deposits waiting to be sent. `None` if not relaying.
+    pub fn queue_status(&self) -> Option<QueueStatus> {
+        match self.state {
+            DepositRelayState::RelayDeposits { ref future, .. } => {
+                Some(future.queue_status(self.app.timer.now()))
+            }
+            _ => None,
+        }
+    }
+
+    /// describes the current state. used for diagnostics.
+    pub fn state_description(&self) -> String {
+        match self.state {
+            DepositRelayState::Wait if self.awaiting.is_empty() && self.held.is_empty() => {
+                "waiting for logs".into()
+            }
+            DepositRelayState::Wait => format!(
+                "waiting for logs. {} deposits left to other authorities, {} held by recipient_policies",
+                self.awaiting.len(),
+                self.held.len()
+            ),
+            DepositRelayState::FetchRequeued { .. } => format!(
+                "fetching {} deposits to relay again after a foreign reorg",
+                self.relaying_requeued.len()
+            ),
+            DepositRelayState::FetchReleased { ref deposits, .. } => format!(
+                "fetching {} held deposits to relay at block {}",
+                deposits.len(),
+                self.confirmed
+            ),
+            DepositRelayState::CheckExecuted { block, .. } => {
+                format!("checking for executed deposits up to block {}", block)
+            }
+            DepositRelayState::FetchRecipients {
+                ref recipients,
+                block,
+                ..
+            } => format!(
+                "fetching the code of {} recipients of deposits up to block {}",
+                recipients.len(),
+                block
+            ),
+            DepositRelayState::SimulateContractDeposits {
+                ref simulated,
+                block,
+                ..
+            } => format!(
+                "simulating {} deposits to contracts up to block {}",
+                simulated.len(),
+                block
+            ),
+            DepositRelayState::FetchSenders { block, .. } => {
+                format!("fetching senders of deposits up to block {}", block)
+            }
+            DepositRelayState::RelayDeposits { ref future, block, .. } => {
+                let queue = future.queue_status(self.app.timer.now());
+                format!(
+                    "relaying deposits up to block {}. {} queued for {}s",
+                    block,
+                    queue.depth,
+                    queue.age.as_secs()
+                )
+            }
+            DepositRelayState::VerifyEvents { block, .. } => {
+                format!("checking events of deposit relays up to block {}", block)
+            }
+            DepositRelayState::SignAcknowledgements { block, .. } => format!(
+                "signing acknowledgements of deposits up to block {}",
+                block
+            ),
+            DepositRelayState::PublishAcknowledgements { block, .. } => format!(
+                "publishing acknowledgements of deposits up to block {}",
+                block
+            ),
+            DepositRelayState::DeferDeposits {
+                ref deposits,
+                block,
+                ..
+            } => format!(
+                "deferring {} deposits up to block {} by the spend limit",
+                deposits.len(),
+                block
+            ),
+            DepositRelayState::SendCallbacks { .. } => "sending deposit callbacks".into(),
+            DepositRelayState::CheckReceipts { ref receipts, .. } => {
+                format!("checking {} deposit receipts", receipts.len())
+            }
+            DepositRelayState::RecordReceipts { ref receipts, .. } => {
+                format!("recording {} deposit receipts", receipts.len())
+            }
+            DepositRelayState::Yield(_) => "yielding checked block".into(),
+        }
+    }
 }
 
-impl<T: Transport> Stream for DepositRelay<T> {
+impl<T: Transport + Clone> Stream for DepositRelay<T> {
     type Item = u64;
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         loop {
+            // the calls of the batch in flight are nested in the span of its blocks
+            let blocks = self.blocks.clone();
+            let _entered = blocks.enter();
             let next_state = match self.state {
+                DepositRelayState::Wait if self.draining => return Ok(None.into()),
+                DepositRelayState::Wait
+                    if self.requeued_logs.is_none() && self.awaited_logs.is_none()
+                        && self.released_logs.is_none() && !self.requeued.is_empty() =>
+                {
+                    self.relaying_requeued.extend(self.requeued.drain(..));
+                    let app = &self.app;
+                    let receipts = self.relaying_requeued
+                        .iter()
+                        .map(|execution| {
+                            api::timeout(
+                                &app.timer,
+                                api::transaction_receipt(
+                                    &app.connections.home,
+                                    execution.home_transaction,
+                                ),
+                                "home",
+                                app.config.home.request_timeout,
+                            ).about(execution.home_transaction)
+                        })
+                        .collect::<Vec<_>>();
+                    DepositRelayState::FetchRequeued {
+                        future: join_all(receipts),
+                    }
+                }
+                DepositRelayState::FetchRequeued { ref mut future } => {
+                    let receipts = try_ready!(future.poll());
+                    let mut logs = Vec::new();
+                    for (execution, receipt) in self.relaying_requeued.iter().zip(receipts) {
+                        let home_contract = self.home_contract;
+                        let log = receipt.and_then(|receipt| {
+                            receipt.logs.into_iter().find(|log| {
+                                log.address == home_contract && log.log_index == execution.log_index
+                            })
+                        });
+                        match log {
+                            Some(log) => logs.push(log),
+                            None => error!(
+                                target: "bridge::deposit_relay",
+                                "cannot relay deposit in home transaction {} again: its log is not on home anymore",
+                                execution.home_transaction
+                            ),
+                        }
+                    }
+                    self.requeued_logs = Some(logs);
+                    DepositRelayState::Wait
+                }
+                DepositRelayState::Wait
+                    if self.requeued_logs.is_none() && self.awaited_logs.is_none()
+                        && self.released_logs.is_none() && self.held.has_due(self.confirmed) =>
+                {
+                    // held deposits are fetched again, their blocks may be gone by now
+                    let deposits = self.held.take_due(self.confirmed);
+                    let app = &self.app;
+                    let receipts = deposits
+                        .iter()
+                        .map(|deposit| {
+                            let hash = deposit.log
+                                .transaction_hash
+                                .expect("only deposits with a transaction hash are held; qed");
+                            api::timeout(
+                                &app.timer,
+                                api::transaction_receipt(&app.connections.home, hash),
+                                "home",
+                                app.config.home.request_timeout,
+                            ).about(hash)
+                        })
+                        .collect::<Vec<_>>();
+                    DepositRelayState::FetchReleased {
+                        future: join_all(receipts),
+                        deposits,
+                    }
+                }
+                DepositRelayState::FetchReleased {
+                    ref mut future,
+                    ref mut deposits,
+                } => {
+                    let receipts = try_ready!(future.poll());
+                    let mut logs = Vec::new();
+                    let deposits = mem::replace(deposits, Vec::new());
+                    for (held, receipt) in deposits.into_iter().zip(receipts) {
+                        let hash = held.log
+                            .transaction_hash
+                            .expect("only deposits with a transaction hash are held; qed");
+                        let log = receipt.and_then(|receipt| {
+                            receipt.logs.into_iter().find(|log| {
+                                log.address == held.log.address && log.topics == held.log.topics
+                                    && log.data == held.log.data
+                            })
+                        });
+                        match log {
+                            Some(ref log) if log.block_number == held.log.block_number => {
+                                logs.push(log.clone())
+                            }
+                            Some(log) => {
+                                // the transaction was mined again in another block
+                                let block = log.block_number.map_or(0, |block| block.low_u64());
+                                let release_block = block + (held.release - held.block);
+                                info!(
+                                    target: "bridge::deposit_relay",
+                                    "held deposit in home transaction {} moved to block {}. holding it until block {} is confirmed",
+                                    hash,
+                                    block,
+                                    release_block
+                                );
+                                self.pending.finish(&hash, held.log.log_index);
+                                self.pending.observe(&[log.clone()]);
+                                self.held.hold(log, release_block);
+                            }
+                            None => {
+                                error!(
+                                    target: "bridge::deposit_relay",
+                                    "cannot relay held deposit in home transaction {}: its log is not on home anymore",
+                                    hash
+                                );
+                                self.pending.finish(&hash, held.log.log_index);
+                            }
+                        }
+                    }
+                    self.released_logs = Some(logs);
+                    DepositRelayState::Wait
+                }
+                DepositRelayState::Wait
+                    if self.requeued_logs.is_none() && self.awaited_logs.is_none()
+                        && self.released_logs.is_none()
+                        && self.awaiting.has_due(self.app.timer.now()) =>
+                {
+                    let due = self.awaiting.take_due(self.app.timer.now());
+                    self.awaited_logs = Some(due.into_iter().map(|deposit| deposit.log).collect());
+                    DepositRelayState::Wait
+                }
+                DepositRelayState::Wait if self.callbacks.has_due(self.app.timer.now()) => {
+                    let app = &self.app;
+                    let contract = app.config
+                        .deposit_callback
+                        .as_ref()
+                        .expect("callbacks are only queued if deposit_callback is configured; qed")
+                        .contract;
+                    let requests = self.callbacks
+                        .start_due(self.app.timer.now())
+                        .into_iter()
+                        .map(|data| callback_request(app, contract, data))
+                        .collect::<Vec<_>>();
+                    info!(
+                        target: "bridge::deposit_relay",
+                        "sending {} deposit callbacks to {:?}",
+                        requests.len(),
+                        contract
+                    );
+                    let quota = foreign_quota(app, Component::DepositRelay);
+                    DepositRelayState::SendCallbacks {
+                        future: send_foreign_transactions(app.clone(), requests, quota),
+                    }
+                }
+                DepositRelayState::SendCallbacks { ref mut future } => {
+                    // failed callbacks are deferred, they never fail the relay
+                    match future.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(transactions)) => {
+                            for transaction in &transactions {
+                                info!(
+                                    target: "bridge::deposit_relay",
+                                    "deposit callback sent in foreign transaction {}",
+                                    transaction
+                                );
+                            }
+                            self.callbacks.sent(&transactions);
+                        }
+                        Err(err) => {
+                            warn!(
+                                target: "bridge::deposit_relay",
+                                "cannot send deposit callbacks: {}",
+                                err
+                            );
+                            self.callbacks.fail(&err, self.app.timer.now());
+                        }
+                    }
+                    DepositRelayState::Wait
+                }
+                DepositRelayState::Wait if self.receipts.has_due(self.app.timer.now()) => {
+                    let app = &self.app;
+                    let registry = app.config
+                        .receipt_registry
+                        .as_ref()
+                        .expect("receipts are only queued if receipt_registry is configured; qed")
+                        .address;
+                    let receipts = self.receipts.start_due(self.app.timer.now());
+                    let functions = receipt_registry::ReceiptRegistry::default().functions();
+                    let lookups = receipts
+                        .iter()
+                        .map(|&(home_transaction, _)| {
+                            let payload = functions.receipt_of().input(home_transaction.0);
+                            api::settle(api::timeout(
+                                &app.timer,
+                                api::call(&app.connections.home, registry, payload.into()),
+                                "home",
+                                app.config.home.request_timeout,
+                            ).about(home_transaction))
+                        })
+                        .collect::<Vec<_>>();
+                    info!(
+                        target: "bridge::deposit_relay",
+                        "checking {} deposit receipts in {:?}",
+                        receipts.len(),
+                        registry
+                    );
+                    DepositRelayState::CheckReceipts {
+                        future: join_all(lookups),
+                        receipts,
+                    }
+                }
+                DepositRelayState::CheckReceipts {
+                    ref mut future,
+                    ref receipts,
+                } => {
+                    // failed receipts are deferred, they never fail the relay
+                    let lookups = try_ready!(future.poll());
+                    let app = &self.app;
+                    let registry = app.config
+                        .receipt_registry
+                        .as_ref()
+                        .expect("receipts are only queued if receipt_registry is configured; qed")
+                        .address;
+                    let functions = receipt_registry::ReceiptRegistry::default().functions();
+                    let mut records = Vec::new();
+                    let mut recorded = Vec::new();
+                    for (&(home_transaction, relay_transaction), lookup) in
+                        receipts.iter().zip(lookups)
+                    {
+                        let receipt = lookup.and_then(|output| {
+                            functions
+                                .receipt_of()
+                                .output(output.0.as_slice())
+                                .map_err(Error::from)
+                        });
+                        match receipt {
+                            Ok(ref receipt) if receipt.iter().any(|byte| *byte != 0) => {
+                                info!(
+                                    target: "bridge::deposit_relay",
+                                    "receipt of the deposit in home transaction {} is recorded already",
+                                    home_transaction
+                                );
+                                self.receipts.already_recorded(home_transaction);
+                                self.counts.receipts_already_recorded += 1;
+                            }
+                            Ok(_) => {
+                                let request = receipt_request(
+                                    app,
+                                    registry,
+                                    home_transaction,
+                                    relay_transaction,
+                                );
+                                let cost = request
+                                    .gas
+                                    .and_then(|gas| request.gas_price.map(|price| gas * price));
+                                records.push(api::settle(api::timeout(
+                                    &app.timer,
+                                    api::node_send_transaction(
+                                        &app.connections.home,
+                                        &app.config.home,
+                                        request,
+                                    ),
+                                    "home",
+                                    app.config.home.request_timeout,
+                                )));
+                                recorded.push((home_transaction, cost));
+                            }
+                            Err(err) => {
+                                warn!(
+                                    target: "bridge::deposit_relay",
+                                    "cannot look up the receipt of the deposit in home transaction {}: {}",
+                                    home_transaction,
+                                    err
+                                );
+                                self.receipts.fail(home_transaction, &err, self.app.timer.now());
+                            }
+                        }
+                    }
+                    if records.is_empty() {
+                        DepositRelayState::Wait
+                    } else {
+                        DepositRelayState::RecordReceipts {
+                            future: join_all(records),
+                            receipts: recorded,
+                        }
+                    }
+                }
+                DepositRelayState::RecordReceipts {
+                    ref mut future,
+                    ref receipts,
+                } => {
+                    for (&(home_transaction, cost), sent) in
+                        receipts.iter().zip(try_ready!(future.poll()))
+                    {
+                        match sent {
+                            Ok(transaction) => {
+                                info!(
+                                    target: "bridge::deposit_relay",
+                                    "receipt of the deposit in home transaction {} recorded in home transaction {}",
+                                    home_transaction,
+                                    transaction
+                                );
+                                self.receipts.sent(home_transaction, transaction);
+                                self.counts.receipts_recorded += 1;
+                                if let Some(cost) = cost {
+                                    self.counts.receipt_costs = self.counts.receipt_costs + cost;
+                                }
+                            }
+                            Err(err) => {
+                                warn!(
+                                    target: "bridge::deposit_relay",
+                                    "cannot record the receipt of the deposit in home transaction {}: {}",
+                                    home_transaction,
+                                    err
+                                );
+                                self.receipts.fail(home_transaction, &err, self.app.timer.now());
+                            }
+                        }
+                    }
+                    DepositRelayState::Wait
+                }
                 DepositRelayState::Wait => {
-                    let item = try_stream!(self.logs.poll());
-                    info!("got {} new deposits to relay", item.logs.len());
+                    let awaited = self.awaited_logs.is_some();
+                    let requeued = self.requeued_logs.is_some();
+                    let released = self.released_logs.is_some();
+                    let taken = (
+                        self.awaited_logs.take(),
+                        self.requeued_logs.take(),
+                        self.released_logs.take(),
+                    );
+                    let mut item = match taken {
+                        (None, Some(logs), _) => api::LogStreamItem {
+                            from: self.checked,
+                            to: self.checked,
+                            logs,
+                        },
+                        // every new log up to the confirmed block is checked already
+                        (Some(logs), _, _) | (None, None, Some(logs)) => api::LogStreamItem {
+                            from: self.checked,
+                            to: self.confirmed,
+                            logs,
+                        },
+                        (None, None, None) => {
+                            if poll_deadline(&self.app.timer, &self.awaiting, &mut self.deadline)? {
+                                // the guard above takes the deposits due by now
+                                continue;
+                            }
+                            let item = try_stream!(self.logs.poll());
+                            self.confirmed = item.to;
+                            item
+                        }
+                    };
+                    if awaited {
+                        info!(
+                            target: "bridge::deposit_relay",
+                            "checking {} deposits other authorities are responsible for after their deadline",
+                            item.logs.len()
+                        );
+                    } else if requeued {
+                        info!(
+                            target: "bridge::deposit_relay",
+                            "relaying {} deposits again whose execution a foreign reorg invalidated",
+                            item.logs.len()
+                        );
+                    } else if released {
+                        info!(
+                            target: "bridge::deposit_relay",
+                            "relaying {} held deposits confirmed by block {} (recipient_policies)",
+                            item.logs.len(),
+                            item.to
+                        );
+                    } else {
+                        info!(
+                            target: "bridge::deposit_relay",
+                            "got {} new deposits to relay in blocks {}..={}",
+                            item.logs.len(),
+                            item.from,
+                            item.to
+                        );
+                    }
+                    self.blocks = info_span!(
+                        target: "bridge::deposit_relay",
+                        parent: None,
+                        "deposits",
+                        from = item.from,
+                        to = item.to
+                    );
+                    let batch = self.blocks.clone();
+                    let _batch = batch.enter();
+                    let logs = mem::replace(&mut item.logs, Vec::new());
+                    for log in logs {
+                        if awaited || released {
+                            // observed with the batch they were left to other authorities
+                            // or held back in
+                            item.logs.push(log);
+                            continue;
+                        }
+                        if !requeued {
+                            // invalid logs fail `deposit_outcome`
+                            let observation = app_deposit_value(&self.app, &log)
+                                .ok()
+                                .and_then(|value| {
+                                    Observation::from_log(Direction::HomeToForeign, &log, value)
+                                });
+                            self.observations.extend(observation);
+                        }
+                        match deposit_outcome(&self.app, &mut self.counts, &log)? {
+                            RelayOutcome::Relayed => item.logs.push(log),
+                            skipped => self.outcomes.push(log_outcome(&log, skipped)),
+                        }
+                    }
+                    if !awaited && !released {
+                        self.pending.observe(&item.logs);
+                    }
+                    let policies = match self.app.config.recipient_policies {
+                        Some(ref policies) if !requeued && !awaited && !released => Some(policies),
+                        _ => None,
+                    };
+                    if let Some(policies) = policies {
+                        // held deposits go through the checks below once they are released
+                        for log in mem::replace(&mut item.logs, Vec::new()) {
+                            let recipient = deposit_recipient(&self.app, &log)
+                                .chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+                            let extra = policies.extra_confirmations(&recipient);
+                            let block = log.block_number.map_or(0, |block| block.low_u64());
+                            let hash = log.transaction_hash;
+                            match hash {
+                                Some(hash) if policies.is_manual(&recipient) => {
+                                    info!(
+                                        target: "bridge::deposit_relay",
+                                        "holding deposit in home transaction {} to {:?} until released with release-held-deposit (recipient_policies)",
+                                        hash,
+                                        recipient
+                                    );
+                                    self.counts.held_deposits += 1;
+                                    self.held.hold(log, UNTIL_RELEASED);
+                                }
+                                Some(hash) if extra > 0 && block + extra > item.to => {
+                                    info!(
+                                        target: "bridge::deposit_relay",
+                                        "holding deposit in home transaction {} to {:?} until block {} is confirmed (recipient_policies)",
+                                        hash,
+                                        recipient,
+                                        block + extra
+                                    );
+                                    self.counts.held_deposits += 1;
+                                    self.held.hold(log, block + extra);
+                                }
+                                _ => item.logs.push(log),
+                            }
+                        }
+                    }
+                    let relayed_logs = if self.relayed.is_some() && !awaited {
+                        item.logs.iter().filter_map(RelayedLog::from_log).collect()
+                    } else {
+                        Vec::new()
+                    };
+                    let app = &self.app;
+                    let unique_ids = app.config.unique_log_ids;
+                    if !unique_ids && !awaited {
+                        for hash in shared_transaction_hashes(&item.logs) {
+                            error!(
+                                target: "bridge::deposit_relay",
+                                "home transaction {} emitted several deposits. deposits of it with the same recipient and value are relayed only once. enable `unique_log_ids` once the contracts of all authorities support it",
+                                hash
+                            );
+                        }
+                    }
+                    let cooperative = match app.config.cooperative_deposits {
+                        Some(ref cooperative) if !requeued && !awaited => Some(cooperative),
+                        _ => None,
+                    };
+                    if let Some(cooperative) = cooperative {
+                        // the deadline counts from when the deposit is observed. the batch
+                        // goes on without it and new logs are polled meanwhile
+                        let authorities = &app.config.authorities;
+                        let account = &app.config.foreign.account;
+                        let now = app.timer.now();
+                        for log in mem::replace(&mut item.logs, Vec::new()) {
+                            let hash = match log.transaction_hash {
+                                Some(hash) => hash,
+                                None => {
+                                    item.logs.push(log);
+                                    continue;
+                                }
+                            };
+                            let delay = fallback_delay(cooperative, authorities, account, &hash);
+                            if delay == Duration::from_secs(0) {
+                                item.logs.push(log);
+                                continue;
+                            }
+                            info!(
+                                target: "bridge::deposit_relay",
+                                "deposit in home transaction {} is relayed by {:?} (cooperative_deposits). relaying it in {}s unless executed by then",
+                                hash,
+                                responsible_authorities(authorities, &hash),
+                                delay.as_secs()
+                            );
+                            self.counts.cooperative_waits += 1;
+                            self.awaiting.hold(log, now + delay);
+                        }
+                    }
+                    // deposits predating this authority were usually relayed long ago.
+                    // deposits relayed again may have been executed by another authority.
+                    // deposits other authorities are responsible for or held back may have been
+                    // as well
+                    let check_executed = requeued || awaited || released
+                        || app.config.skip_executed
+                        || app.config.is_catching_up(item.from);
+                    let executed_calls = if check_executed {
+                        let calls = item.logs
+                            .iter()
+                            .map(|log| {
+                                let payload = app_deposit_executed_payload(app, log)
+                                    .chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+                                Ok(api::timeout(
+                                    &app.timer,
+                                    api::call(
+                                        &app.connections.foreign,
+                                        self.foreign_contract,
+                                        payload,
+                                    ),
+                                    "foreign",
+                                    app.config.foreign.request_timeout,
+                                ))
+                            })
+                            .collect::<Result<Vec<_>>>()?;
+                        Some(calls)
+                    } else {
+                        None
+                    };
+                    let counts = &mut self.counts;
                     let deposits = item.logs
                         .into_iter()
                         .map(|log| {
-                            deposit_relay_payload(
-                                &self.app.home_bridge,
-                                &self.app.foreign_bridge,
-                                log,
-                            )
+                            let hash = log.transaction_hash;
+                            let log_index = log.log_index;
+                            let position = MainPosition::of_log(&log);
+                            let value = app_deposit_value(app, &log)
+                                .chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+                            let scale = app.config.value_scale.as_ref();
+                            let derived = app.config.derived_recipients.as_ref();
+                            let recipient = deposit_recipient(app, &log)
+                                .chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+                            let derived_recipient = derive_recipient(derived, recipient);
+                            let (expected, payload) = match app.abi {
+                                Some(ref abi) => {
+                                    runtime_deposit_relay(abi, &log, unique_ids, scale, derived)
+                                        .chain_err(|| ErrorKind::InvalidLog(log.clone()))?
+                                }
+                                None => {
+                                    let expected = deposit_relay_call(
+                                        &app.home_bridge,
+                                        &log,
+                                        unique_ids,
+                                        scale,
+                                        derived,
+                                    ).chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+                                    let payload = deposit_relay_payload(
+                                        &app.home_bridge,
+                                        &app.foreign_bridge,
+                                        log,
+                                        unique_ids,
+                                        scale,
+                                        derived,
+                                    )?;
+                                    (expected, payload)
+                                }
+                            };
+                            check_call_data(app, counts, &expected, &payload.0)?;
+                            // deposit_relay_payload already fails for logs without transaction_hash
+                            let hash = hash.ok_or(ErrorKind::UnminedLog)?;
+                            Ok(PendingDeposit {
+                                hash,
+                                log_index,
+                                position,
+                                payload,
+                                value,
+                                sender: None,
+                                memo: None,
+                                recipient: foreign_recipient(derived, recipient),
+                                derived: derived_recipient,
+                                recipient_kind: None,
+                            })
                         })
-                        .collect::<Result<Vec<_>>>()?
+                        .collect::<Result<Vec<_>>>()?;
+
+                    match executed_calls {
+                        Some(calls) => {
+                            info!(
+                                target: "bridge::deposit_relay",
+                                "checking {} deposits for execution",
+                                calls.len()
+                            );
+                            DepositRelayState::CheckExecuted {
+                                future: join_all(calls),
+                                deposits,
+                                relayed_logs,
+                                awaited,
+                                block: item.to,
+                            }
+                        }
+                        None => check_recipients(
+                            app,
+                            counts,
+                            &mut self.spend_limiter,
+                            &self.recipients,
+                            self.foreign_contract,
+                            deposits,
+                            relayed_logs,
+                            item.to,
+                        ),
+                    }
+                }
+                DepositRelayState::CheckExecuted {
+                    ref mut future,
+                    ref mut deposits,
+                    ref mut relayed_logs,
+                    awaited,
+                    block,
+                } => {
+                    let outputs = try_ready!(future.poll());
+                    let app = &self.app;
+                    let pending = &mut self.pending;
+                    let counts = &mut self.counts;
+                    let outcomes = &mut self.outcomes;
+                    let executed = outputs
+                        .iter()
+                        .map(|output| {
+                            app.foreign_bridge
+                                .functions()
+                                .is_deposit_executed()
+                                .output(output.0.as_slice())
+                        })
+                        .collect::<ethabi::Result<Vec<_>>>()
+                        .map_err(Error::from)?;
+                    let deposits = mem::replace(deposits, Vec::new())
+                        .into_iter()
+                        .zip(executed.into_iter())
+                        .filter_map(|(deposit, executed)| {
+                            if executed {
+                                info!(
+                                    target: "bridge::deposit_relay",
+                                    "deposit in home transaction {} already executed. skipping",
+                                    deposit.hash
+                                );
+                                pending.finish(&deposit.hash, deposit.log_index);
+                                counts.skipped_executed += 1;
+                                let skip = Skip::new(
+                                    SkipReason::AlreadyExecuted,
+                                    "ForeignBridge.isDepositExecuted returned true",
+                                );
+                                outcomes.push(deposit.outcome(RelayOutcome::Skipped(skip)));
+                                None
+                            } else {
+                                if awaited {
+                                    warn!(
+                                        target: "bridge::deposit_relay",
+                                        "deposit in home transaction {} not executed by its deadline. relaying it in place of the authorities responsible for it",
+                                        deposit.hash
+                                    );
+                                    counts.cooperative_fallbacks += 1;
+                                }
+                                Some(deposit)
+                            }
+                        })
+                        .collect();
+                    check_recipients(
+                        app,
+                        counts,
+                        &mut self.spend_limiter,
+                        &self.recipients,
+                        self.foreign_contract,
+                        deposits,
+                        mem::replace(relayed_logs, Vec::new()),
+                        block,
+                    )
+                }
+                DepositRelayState::FetchRecipients {
+                    ref mut future,
+                    ref recipients,
+                    ref mut deposits,
+                    ref mut relayed_logs,
+                    block,
+                } => {
+                    let codes = try_ready!(future.poll());
+                    for (recipient, code) in recipients.iter().zip(codes.iter()) {
+                        self.recipients
+                            .insert(*recipient, RecipientKind::of_code(code));
+                    }
+                    let mut deposits = mem::replace(deposits, Vec::new());
+                    for deposit in &mut deposits {
+                        deposit.recipient_kind = self.recipients.get(&deposit.recipient);
+                    }
+                    simulate_contract_deposits(
+                        &self.app,
+                        &mut self.counts,
+                        &mut self.spend_limiter,
+                        self.foreign_contract,
+                        deposits,
+                        mem::replace(relayed_logs, Vec::new()),
+                        block,
+                    )
+                }
+                DepositRelayState::SimulateContractDeposits {
+                    ref mut future,
+                    ref simulated,
+                    ref mut deposits,
+                    ref mut relayed_logs,
+                    block,
+                } => {
+                    let results = try_ready!(future.poll());
+                    let mut reasons = vec![None; deposits.len()];
+                    for (index, result) in simulated.iter().zip(results) {
+                        reasons[*index] = simulation_revert(result)?;
+                    }
+                    let mut kept = Vec::new();
+                    let deposits = mem::replace(deposits, Vec::new());
+                    for (deposit, reason) in deposits.into_iter().zip(reasons) {
+                        let reason = match reason {
+                            Some(reason) => reason,
+                            None => {
+                                kept.push(deposit);
+                                continue;
+                            }
+                        };
+                        let err = Error::from(ErrorKind::RecipientRejectsTransfer(
+                            deposit.recipient,
+                            deposit.hash,
+                            reason.clone(),
+                        ));
+                        error!(
+                            target: "bridge::deposit_relay",
+                            "CRITICAL: {}. the deposit is quarantined and not relayed. recover it through the recovery path of the contracts",
+                            err
+                        );
+                        self.pending.finish(&deposit.hash, deposit.log_index);
+                        self.counts.quarantined_deposits += 1;
+                        let skip = Skip::new(SkipReason::Quarantined, err.to_string())
+                            .with("recipient", format!("{:?}", deposit.recipient))
+                            .with("revert", &reason);
+                        self.outcomes
+                            .push(deposit.outcome(RelayOutcome::Skipped(skip)));
+                        self.quarantined.push(QuarantinedDeposit {
+                            home_transaction: deposit.hash,
+                            log_index: deposit.log_index,
+                            recipient: deposit.recipient,
+                            original_recipient: deposit.derived.map(|derived| derived.original),
+                            value: deposit.value,
+                            reason,
+                            // attached from the observed raw logs once the bridge collects it
+                            raw_log: None,
+                        });
+                    }
+                    relay_deposits(
+                        &self.app,
+                        &mut self.counts,
+                        &mut self.spend_limiter,
+                        self.foreign_contract,
+                        kept,
+                        mem::replace(relayed_logs, Vec::new()),
+                        block,
+                    )
+                }
+                DepositRelayState::FetchSenders {
+                    ref mut future,
+                    ref mut deposits,
+                    ref mut deferred,
+                    ref mut relayed_logs,
+                    block,
+                } => {
+                    let transactions = try_ready!(future.poll());
+                    let mut deposits = mem::replace(deposits, Vec::new());
+                    if let Some(ref config) = self.app.config.deposit_memo {
+                        let decoded = transactions
+                            .iter()
+                            .map(|transaction| match *transaction {
+                                Some(ref transaction) => extract_memos(
+                                    &transaction.input.0,
+                                    &config.selector.0,
+                                    config.argument,
+                                ),
+                                None => Vec::new(),
+                            })
+                            .collect::<Vec<_>>();
+                        let hashes = deposits
+                            .iter()
+                            .map(|deposit| deposit.hash)
+                            .collect::<Vec<_>>();
+                        let memos = deposit_memos(&hashes, &decoded);
+                        for (deposit, memo) in deposits.iter_mut().zip(memos.into_iter()) {
+                            deposit.memo = memo;
+                        }
+                    }
+                    let log_senders = self.app.config.log_deposit_senders;
+                    let mut kept = Vec::new();
+                    for (mut deposit, transaction) in deposits.into_iter().zip(transactions) {
+                        match transaction {
+                            Some(ref transaction)
+                                if self.app.config.allow_same_chain
+                                    && sent_by_authority(&self.app.config, transaction.from) =>
+                            {
+                                error!(
+                                    target: "bridge::deposit_relay",
+                                    "deposit in home transaction {} was sent by this authority ({:?}). it's not relayed since home and foreign may be the same chain (allow_same_chain)",
+                                    deposit.hash,
+                                    transaction.from
+                                );
+                                self.pending.finish(&deposit.hash, deposit.log_index);
+                                self.counts.looped_deposits += 1;
+                                let skip = Skip::new(
+                                    SkipReason::SameChain,
+                                    "sent by this authority while home and foreign may be the same chain (allow_same_chain)",
+                                ).with("sender", format!("{:?}", transaction.from));
+                                self.outcomes
+                                    .push(deposit.outcome(RelayOutcome::Skipped(skip)));
+                                continue;
+                            }
+                            Some(transaction) => if log_senders {
+                                deposit.sender = Some(transaction.from);
+                            },
+                            None => warn!(
+                                target: "bridge::deposit_relay",
+                                "home transaction {} not found. sender and memo of its deposit are unknown",
+                                deposit.hash
+                            ),
+                        }
+                        kept.push(deposit);
+                    }
+                    let deposits = kept;
+                    send_deposits(
+                        &self.app,
+                        self.foreign_contract,
+                        deposits,
+                        mem::replace(deferred, Vec::new()),
+                        mem::replace(relayed_logs, Vec::new()),
+                        block,
+                    )
+                }
+                DepositRelayState::RelayDeposits {
+                    ref mut future,
+                    ref mut deposits,
+                    ref mut deferred,
+                    ref mut relayed_logs,
+                    block,
+                } => {
+                    let relay_hashes = try_ready!(future.poll());
+                    self.counts.relayed += relay_hashes.len() as u64;
+                    // relay transactions are returned in the order of the deposits
+                    let relays = mem::replace(deposits, Vec::new())
                         .into_iter()
-                        .map(|payload| TransactionRequest {
-                            from: self.app.config.foreign.account,
-                            to: Some(self.foreign_contract.clone()),
-                            gas: Some(self.app.config.txs.deposit_relay.gas.into()),
-                            gas_price: Some(self.app.config.txs.deposit_relay.gas_price.into()),
+                        .zip(relay_hashes)
+                        .collect::<Vec<_>>();
+                    for &(ref relaying, _) in &relays {
+                        let gas_price = match relaying.gas_price {
+                            Some(gas_price) => gas_price,
+                            None => continue,
+                        };
+                        if gas_price.raised() {
+                            self.counts.tiered_relays += 1;
+                            info!(
+                                target: "bridge::deposit_relay",
+                                "deposit in home transaction {} relayed at gas price {} instead of {} (gas_price_tiers)",
+                                relaying.deposit.hash,
+                                format_wei(gas_price.effective),
+                                format_wei(gas_price.base)
+                            );
+                        }
+                        if gas_price.capped {
+                            self.counts.capped_gas_prices += 1;
+                            warn!(
+                                target: "bridge::deposit_relay",
+                                "gas price of the relay of the deposit in home transaction {} was capped at {} (gas_price_tiers)",
+                                relaying.deposit.hash,
+                                format_wei(gas_price.effective)
+                            );
+                        }
+                    }
+                    let relayed = relays
+                        .iter()
+                        .map(|&(ref relaying, _)| relaying.deposit.outcome(RelayOutcome::Relayed));
+                    self.outcomes.extend(relayed);
+                    let timings = self.steps.sent(relays.len(), self.app.timer.now());
+                    // deferred deposits are reported together with the others once relayed
+                    if deferred.is_empty() {
+                        if let Some(ref mut relayed) = self.relayed {
+                            relayed.extend(relayed_logs.drain(..));
+                        }
+                    }
+                    let observed_on = self.app.home_node();
+                    let submitted_to = self.app.foreign_node();
+                    if let Some(ref mut history) = self.history {
+                        let records = relays.iter().map(|&(ref relaying, relay_hash)| {
+                            let deposit = &relaying.deposit;
+                            RelayRecord {
+                                direction: Direction::HomeToForeign,
+                                key: deposit.key(),
+                                source_transaction: deposit.hash,
+                                relay_transaction: relay_hash,
+                                block,
+                                receiver: deposit.recipient,
+                                value: deposit.value,
+                                memo: deposit.memo,
+                                recipient: deposit.recipient_kind,
+                                derived_recipient: deposit.derived,
+                                main_position: deposit.position,
+                                gas_price: relaying.gas_price,
+                                timings: Some(timings),
+                                observed_on: Some(observed_on.clone()),
+                                submitted_to: Some(submitted_to.clone()),
+                            }
+                        });
+                        history.extend(records);
+                    }
+                    if let Some(ref mut executions) = self.executions {
+                        let watched = relays
+                            .iter()
+                            .map(|&(ref relaying, relay_hash)| WatchedExecution {
+                                home_transaction: relaying.deposit.hash,
+                                log_index: relaying.deposit.log_index,
+                                foreign_transaction: relay_hash,
+                                since_block: None,
+                                block_number: None,
+                                block_hash: None,
+                                timings: Some(timings),
+                            });
+                        executions.extend(watched);
+                    }
+                    for &(ref relaying, relay_hash) in &relays {
+                        let deposit = &relaying.deposit;
+                        if let Some(memo) = deposit.memo {
+                            info!(
+                                target: "bridge::deposit_relay",
+                                "deposit in home transaction {} has memo {:?}",
+                                deposit.hash,
+                                memo
+                            );
+                        }
+                        match deposit.sender {
+                            Some(sender) => info!(
+                                target: "bridge::deposit_relay",
+                                "deposit in home transaction {} sent by {} relayed in foreign transaction {}",
+                                deposit.hash,
+                                sender,
+                                relay_hash
+                            ),
+                            None => info!(
+                                target: "bridge::deposit_relay",
+                                "deposit in home transaction {} relayed in foreign transaction {}",
+                                deposit.hash,
+                                relay_hash
+                            ),
+                        }
+                    }
+                    let deferred = mem::replace(deferred, Vec::new());
+                    let relayed_logs = mem::replace(relayed_logs, Vec::new());
+                    let mut expected = Vec::new();
+                    let mut sent = Vec::new();
+                    for (relaying, relay_hash) in relays {
+                        expected.push(relaying.expected);
+                        sent.push(SentRelay {
+                            hash: relaying.deposit.hash,
+                            log_index: relaying.deposit.log_index,
+                            relay_transaction: relay_hash,
+                            callback: relaying.callback,
+                        });
+                    }
+                    let relay_hashes = sent.iter()
+                        .map(|relay| relay.relay_transaction)
+                        .collect::<Vec<_>>();
+                    match verify_relay_events(&self.app, false, &relay_hashes, expected) {
+                        Some(future) => DepositRelayState::VerifyEvents {
+                            future,
+                            relays: sent,
+                            deferred,
+                            relayed_logs,
+                            block,
+                        },
+                        None => deposits_relayed(&self.app, &sent, deferred, relayed_logs, block),
+                    }
+                }
+                DepositRelayState::VerifyEvents {
+                    ref mut future,
+                    ref relays,
+                    ref mut deferred,
+                    ref mut relayed_logs,
+                    block,
+                } => {
+                    let checks = try_ready!(future.poll());
+                    self.gas_samples.extend(gas_samples(&self.app, &checks));
+                    // confirmed relays are called back even if others fail the batch
+                    queue_callbacks(&mut self.callbacks, &checks, relays);
+                    let receipts = relays
+                        .iter()
+                        .map(|relay| (relay.hash, relay.relay_transaction))
+                        .collect::<Vec<_>>();
+                    queue_receipts(&mut self.receipts, &checks, &receipts);
+                    record_event_checks(&self.app, false, &mut self.counts, &checks)?;
+                    deposits_relayed(
+                        &self.app,
+                        relays,
+                        mem::replace(deferred, Vec::new()),
+                        mem::replace(relayed_logs, Vec::new()),
+                        block,
+                    )
+                }
+                DepositRelayState::SignAcknowledgements {
+                    ref mut future,
+                    ref acknowledgements,
+                    ref mut deferred,
+                    ref mut relayed_logs,
+                    block,
+                } => {
+                    let signatures = try_ready!(future.poll());
+                    let app = &self.app;
+                    let registry = app.config.acknowledgement_registry.expect(
+                        "acknowledgement_registry is required for contract acknowledgements in Config::validate; qed",
+                    );
+                    let tx_config = app.tunables.get().txs.acknowledgement;
+                    let mut publishes = Vec::new();
+                    for (acknowledgement, signature) in acknowledgements.iter().zip(signatures) {
+                        let signature = match signature {
+                            Ok(signature) => signature,
+                            Err(err) => {
+                                warn!(
+                                    target: "bridge::deposit_relay",
+                                    "cannot sign acknowledgement of deposit in home transaction {}: {}",
+                                    acknowledgement.home_transaction_hash,
+                                    err
+                                );
+                                self.counts.acknowledgement_failures += 1;
+                                continue;
+                            }
+                        };
+                        let tx = TransactionRequest {
+                            from: app.config.foreign.account,
+                            to: Some(registry),
+                            gas: tx_config.request_gas(),
+                            gas_price: Some(app.foreign_gas_price(&tx_config)),
                             value: None,
-                            data: Some(payload),
+                            data: Some(acknowledgement.to_registry_payload(&signature).into()),
                             nonce: None,
                             condition: None,
-                        })
-                        .map(|request| {
-                            self.app.timer.timeout(
-                                api::send_transaction(&self.app.connections.foreign, request),
-                                self.app.config.foreign.request_timeout,
-                            )
-                        })
-                        .collect::<Vec<_>>();
-
-                    info!("relaying {} deposits", deposits.len());
-                    DepositRelayState::RelayDeposits {
-                        future: join_all(deposits),
-                        block: item.to,
+                        };
+                        publishes.push(api::settle(api::timeout(
+                            &app.timer,
+                            api::node_send_transaction(
+                                &app.connections.foreign,
+                                &app.config.foreign,
+                                tx,
+                            ),
+                            "foreign",
+                            app.config.foreign.request_timeout,
+                        )));
+                    }
+                    info!(
+                        target: "bridge::deposit_relay",
+                        "publishing {} acknowledgements to {:?}",
+                        publishes.len(),
+                        registry
+                    );
+                    DepositRelayState::PublishAcknowledgements {
+                        future: join_all(publishes),
+                        deferred: mem::replace(deferred, Vec::new()),
+                        relayed_logs: mem::replace(relayed_logs, Vec::new()),
+                        block,
                     }
                 }
-                DepositRelayState::RelayDeposits {
+                DepositRelayState::PublishAcknowledgements {
+                    ref mut future,
+                    ref mut deferred,
+                    ref mut relayed_logs,
+                    block,
+                } => {
+                    for result in try_ready!(future.poll()) {
+                        match result {
+                            Ok(hash) => {
+                                debug!(
+                                    target: "bridge::deposit_relay",
+                                    "acknowledgement published in foreign transaction {}",
+                                    hash
+                                );
+                                self.counts.acknowledged += 1;
+                            }
+                            Err(err) => {
+                                warn!(
+                                    target: "bridge::deposit_relay",
+                                    "cannot publish acknowledgement: {}",
+                                    err
+                                );
+                                self.counts.acknowledgement_failures += 1;
+                            }
+                        }
+                    }
+                    relay_completed(
+                        &self.app,
+                        mem::replace(deferred, Vec::new()),
+                        mem::replace(relayed_logs, Vec::new()),
+                        block,
+                    )
+                }
+                DepositRelayState::DeferDeposits {
                     ref mut future,
+                    ref mut deposits,
+                    ref mut relayed_logs,
                     block,
                 } => {
-                    let _ = try_ready!(future.poll());
-                    info!("deposit relay completed");
-                    DepositRelayState::Yield(Some(block))
+                    try_ready!(future.poll());
+                    check_recipients(
+                        &self.app,
+                        &mut self.counts,
+                        &mut self.spend_limiter,
+                        &self.recipients,
+                        self.foreign_contract,
+                        mem::replace(deposits, Vec::new()),
+                        mem::replace(relayed_logs, Vec::new()),
+                        block,
+                    )
                 }
                 DepositRelayState::Yield(ref mut block) => match block.take() {
                     None => DepositRelayState::Wait,
-                    some => return Ok(some.into()),
+                    Some(block) => {
+                        // every deposit up to the block is relayed or skipped but those held
+                        // or left to other authorities. a restarted relay reads their logs again
+                        let mut kept = self.held.logs();
+                        kept.extend(self.awaiting.logs());
+                        self.pending.finish_all_but(&kept);
+                        let unfinished =
+                            cmp::min(self.held.checked(block), self.awaiting.checked(block));
+                        let block = cmp::max(self.checked, unfinished);
+                        self.checked = block;
+                        self.relaying_requeued.clear();
+                        return Ok(Some(block).into());
+                    }
                 },
             };
+            let now = self.app.timer.now();
+            match next_state {
+                DepositRelayState::Yield(Some(_)) => {
+                    match self.steps.finish(now) {
+                        Some(timings) => {
+                            self.counts.step_durations.record(&timings);
+                            info!(
+                                target: "bridge::deposit_relay",
+                                "deposit relay completed. step durations: {}",
+                                timings
+                            );
+                        }
+                        None => info!(target: "bridge::deposit_relay", "deposit relay completed"),
+                    }
+                }
+                DepositRelayState::DeferDeposits { ref deposits, .. } => {
+                    self.steps.enter(None, now);
+                    if let Some(ref mut limiter) = self.spend_limiter {
+                        let limit = limiter.limit();
+                        let spent = limiter.spent(unix_time());
+                        let deferred = deposits.iter().map(|deposit| {
+                            let skip = Skip::new(
+                                SkipReason::SpendLimit,
+                                "spend limit of deposits per 24 hours reached",
+                            ).with("limit", limit)
+                                .with("spent", spent);
+                            deposit.outcome(RelayOutcome::Skipped(skip))
+                        });
+                        self.outcomes.extend(deferred);
+                    }
+                }
+                ref state => self.steps.enter(state.step(), now),
+            }
             self.state = next_state;
         }
     }
 }
 
+/// polls the timer of `deadline` at the earliest deadline of `awaiting`. `true` once it passed.
+fn poll_deadline(
+    timer: &Timer,
+    awaiting: &HeldDeposits<Instant>,
+    deadline: &mut Option<(Instant, Sleep)>,
+) -> Result<bool> {
+    let next = match awaiting.next_release() {
+        Some(next) => next,
+        None => {
+            *deadline = None;
+            return Ok(false);
+        }
+    };
+    let stale = deadline
+        .as_ref()
+        .map_or(true, |&(at, _)| at != next);
+    if stale {
+        let now = timer.now();
+        let delay = if next > now {
+            next - now
+        } else {
+            Duration::from_secs(0)
+        };
+        *deadline = Some((next, timer.sleep(delay)));
+    }
+    match *deadline {
+        Some((_, ref mut sleep)) => Ok(sleep.poll()?.is_ready()),
+        None => Ok(false),
+    }
+}
+
+/// splits `deposits` into those `spend_limiter` allows now and those it defers.
+fn limit_deposits(
+    counts: &mut RelayCounts,
+    spend_limiter: &mut Option<SpendLimiter>,
+    mut deposits: Vec<PendingDeposit>,
+    block: u64,
+) -> (Vec<PendingDeposit>, Vec<PendingDeposit>) {
+    let limiter = match *spend_limiter {
+        Some(ref mut limiter) => limiter,
+        None => return (deposits, Vec::new()),
+    };
+    let was_deferring = !limiter.deferred().is_empty();
+    let relays = deposits
+        .iter()
+        .map(|deposit| (deposit.key(), deposit.value))
+        .collect::<Vec<_>>();
+    let now = unix_time();
+    let allowed = limiter.allow(now, block, &relays);
+    let deferred = deposits.split_off(allowed);
+
+    if deferred.is_empty() {
+        if was_deferring {
+            info!(
+                target: "bridge::deposit_relay",
+                "spend limit allows the deferred deposits up to block {}",
+                block
+            );
+        }
+    } else if was_deferring {
+        debug!(
+            target: "bridge::deposit_relay",
+            "spend limit still defers {} deposits up to block {}",
+            deferred.len(),
+            block
+        );
+    } else {
+        error!(
+            target: "bridge::deposit_relay",
+            "CRITICAL: spend limit of {} per 24 hours reached with {} relayed. deferring {} deposits up to block {}. raise the limit or release deposits to relay them earlier",
+            limiter.limit(),
+            limiter.spent(now),
+            deferred.len(),
+            block
+        );
+        counts.deferred += deferred.len() as u64;
+    }
+    (deposits, deferred)
+}
+
+/// yields `block` once all deposits up to it are relayed, waits for the spend limit otherwise.
+fn relay_completed<T: Transport>(
+    app: &Arc<App<T>>,
+    deferred: Vec<PendingDeposit>,
+    relayed_logs: Vec<RelayedLog>,
+    block: u64,
+) -> DepositRelayState<T> {
+    if deferred.is_empty() {
+        DepositRelayState::Yield(Some(block))
+    } else {
+        info!(
+            target: "bridge::deposit_relay",
+            "relayed deposits within the spend limit. {} deposits up to block {} deferred",
+            deferred.len(),
+            block
+        );
+        defer_deposits(app, deferred, relayed_logs, block)
+    }
+}
+
+/// acknowledges the deposits of `relays` if enabled, continues with `relay_completed` otherwise.
+fn deposits_relayed<T: Transport>(
+    app: &Arc<App<T>>,
+    relays: &[SentRelay],
+    deferred: Vec<PendingDeposit>,
+    relayed_logs: Vec<RelayedLog>,
+    block: u64,
+) -> DepositRelayState<T> {
+    match app.config.acknowledgements {
+        Acknowledgements::Contract if !relays.is_empty() => {
+            sign_acknowledgements(app, relays, deferred, relayed_logs, block)
+        }
+        _ => relay_completed(app, deferred, relayed_logs, block),
+    }
+}
+
+/// signs an acknowledgement of the relay of every deposit of `relays` with the foreign account.
+fn sign_acknowledgements<T: Transport>(
+    app: &Arc<App<T>>,
+    relays: &[SentRelay],
+    deferred: Vec<PendingDeposit>,
+    relayed_logs: Vec<RelayedLog>,
+    block: u64,
+) -> DepositRelayState<T> {
+    let timestamp = unix_time();
+    let acknowledgements = relays
+        .iter()
+        .map(|relay| Acknowledgement {
+            home_transaction_hash: relay.hash,
+            foreign_transaction_hash: relay.relay_transaction,
+            authority: app.config.foreign.account,
+            timestamp,
+        })
+        .collect::<Vec<_>>();
+    let signatures = acknowledgements
+        .iter()
+        .map(|acknowledgement| {
+            api::settle(api::timeout(
+                &app.timer,
+                api::node_sign(
+                    &app.connections.foreign,
+                    &app.config.foreign,
+                    acknowledgement.to_bytes().into(),
+                ),
+                "foreign",
+                app.config.foreign.request_timeout,
+            ))
+        })
+        .collect();
+    DepositRelayState::SignAcknowledgements {
+        future: join_all(signatures),
+        acknowledgements,
+        deferred,
+        relayed_logs,
+        block,
+    }
+}
+
+/// waits for the spend limit to allow `deposits`.
+fn defer_deposits<T: Transport>(
+    app: &Arc<App<T>>,
+    deposits: Vec<PendingDeposit>,
+    relayed_logs: Vec<RelayedLog>,
+    block: u64,
+) -> DepositRelayState<T> {
+    let check_interval = app.config
+        .spend_limits
+        .as_ref()
+        .expect("deposits are only deferred by configured spend limits; qed")
+        .check_interval;
+    DepositRelayState::DeferDeposits {
+        future: app.timer.sleep(check_interval),
+        deposits,
+        relayed_logs,
+        block,
+    }
+}
+
+/// memo of every deposit in the home transaction in `hashes` at the same index,
+/// given the memos `decoded` from the call data of that transaction.
+/// deposits of a transaction get its memos in order. if their numbers differ
+/// it's unknown which memo belongs to which deposit and none gets a memo.
+fn deposit_memos(hashes: &[H256], decoded: &[Vec<H256>]) -> Vec<Option<H256>> {
+    hashes
+        .iter()
+        .enumerate()
+        .map(|(index, hash)| {
+            let deposits = hashes.iter().filter(|other| *other == hash).count();
+            let ordinal = hashes[..index].iter().filter(|other| *other == hash).count();
+            let memos = &decoded[index];
+            if memos.len() == deposits {
+                Some(memos[ordinal])
+            } else {
+                if !memos.is_empty() {
+                    debug!(
+                        target: "bridge::deposit_relay",
+                        "home transaction {} has {} memos for {} deposits. memos are ignored",
+                        hash,
+                        memos.len(),
+                        deposits
+                    );
+                }
+                None
+            }
+        })
+        .collect()
+}
+
+/// fetches the kinds of the recipients of `deposits` not in `recipients` and simulates
+/// the deposits to contracts if `contract_recipients` is configured. relays them afterwards.
+fn check_recipients<T: Transport>(
+    app: &Arc<App<T>>,
+    counts: &mut RelayCounts,
+    spend_limiter: &mut Option<SpendLimiter>,
+    recipients: &RecipientKinds,
+    foreign_contract: Address,
+    mut deposits: Vec<PendingDeposit>,
+    relayed_logs: Vec<RelayedLog>,
+    block: u64,
+) -> DepositRelayState<T> {
+    if app.config.contract_recipients.is_none() {
+        return relay_deposits(
+            app,
+            counts,
+            spend_limiter,
+            foreign_contract,
+            deposits,
+            relayed_logs,
+            block,
+        );
+    }
+    for deposit in &mut deposits {
+        deposit.recipient_kind = recipients.get(&deposit.recipient);
+    }
+    let mut unknown = deposits
+        .iter()
+        .filter(|deposit| deposit.recipient_kind.is_none())
+        .map(|deposit| deposit.recipient)
+        .collect::<Vec<_>>();
+    unknown.sort();
+    unknown.dedup();
+    if unknown.is_empty() {
+        return simulate_contract_deposits(
+            app,
+            counts,
+            spend_limiter,
+            foreign_contract,
+            deposits,
+            relayed_logs,
+            block,
+        );
+    }
+    let codes = unknown
+        .iter()
+        .map(|recipient| {
+            api::timeout(
+                &app.timer,
+                api::latest_code(&app.connections.foreign, *recipient),
+                "foreign",
+                app.config.foreign.request_timeout,
+            )
+        })
+        .collect::<Vec<_>>();
+    DepositRelayState::FetchRecipients {
+        future: join_all(codes),
+        recipients: unknown,
+        deposits,
+        relayed_logs,
+        block,
+    }
+}
+
+/// simulates the `deposits` to contracts with the gas they are relayed with.
+/// relays `deposits` right away if none is to a contract.
+fn simulate_contract_deposits<T: Transport>(
+    app: &Arc<App<T>>,
+    counts: &mut RelayCounts,
+    spend_limiter: &mut Option<SpendLimiter>,
+    foreign_contract: Address,
+    deposits: Vec<PendingDeposit>,
+    relayed_logs: Vec<RelayedLog>,
+    block: u64,
+) -> DepositRelayState<T> {
+    let simulated = deposits
+        .iter()
+        .enumerate()
+        .filter(|&(_, deposit)| deposit.recipient_kind == Some(RecipientKind::Contract))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if simulated.is_empty() {
+        return relay_deposits(
+            app,
+            counts,
+            spend_limiter,
+            foreign_contract,
+            deposits,
+            relayed_logs,
+            block,
+        );
+    }
+    info!(
+        target: "bridge::deposit_relay",
+        "simulating {} deposits to contracts",
+        simulated.len()
+    );
+    let simulations = simulated
+        .iter()
+        .map(|index| {
+            let (request, _) = relay_request(app, foreign_contract, &deposits[*index]);
+            api::settle(api::timeout(
+                &app.timer,
+                api::simulate_transaction(&app.connections.foreign, &request),
+                "foreign",
+                app.config.foreign.request_timeout,
+            ))
+        })
+        .collect::<Vec<_>>();
+    DepositRelayState::SimulateContractDeposits {
+        future: join_all(simulations),
+        simulated,
+        deposits,
+        relayed_logs,
+        block,
+    }
+}
+
+/// defers `deposits` beyond the spend limit, fetches the home transactions of the others
+/// if `log_deposit_senders` or `allow_same_chain` is enabled or `deposit_memo` is configured
+/// and sends them to `ForeignBridge`.
+fn relay_deposits<T: Transport>(
+    app: &Arc<App<T>>,
+    counts: &mut RelayCounts,
+    spend_limiter: &mut Option<SpendLimiter>,
+    foreign_contract: Address,
+    deposits: Vec<PendingDeposit>,
+    relayed_logs: Vec<RelayedLog>,
+    block: u64,
+) -> DepositRelayState<T> {
+    let (deposits, deferred) = limit_deposits(counts, spend_limiter, deposits, block);
+    if deposits.is_empty() && !deferred.is_empty() {
+        return defer_deposits(app, deferred, relayed_logs, block);
+    }
+    let fetch_transactions = app.config.log_deposit_senders || app.config.deposit_memo.is_some()
+        || app.config.allow_same_chain;
+    if !fetch_transactions || deposits.is_empty() {
+        return send_deposits(app, foreign_contract, deposits, deferred, relayed_logs, block);
+    }
+    let transactions = deposits
+        .iter()
+        .map(|deposit| {
+            api::timeout(
+                &app.timer,
+                api::transaction(&app.connections.home, deposit.hash),
+                "home",
+                app.config.home.request_timeout,
+            ).about(deposit.hash)
+        })
+        .collect::<Vec<_>>();
+    DepositRelayState::FetchSenders {
+        future: join_all(transactions),
+        deposits,
+        deferred,
+        relayed_logs,
+        block,
+    }
+}
+
+/// `ForeignBridge.deposit` transaction relaying `deposit` and its gas price of
+/// `gas_price_tiers`, if configured.
+/// deposits to contracts get the gas of `contract_recipients`.
+fn relay_request<T: Transport>(
+    app: &App<T>,
+    foreign_contract: Address,
+    deposit: &PendingDeposit,
+) -> (TransactionRequest, Option<TieredGasPrice>) {
+    let tx_config = app.tunables.get().txs.deposit_relay;
+    let gas = match (deposit.recipient_kind, app.config.contract_recipients.as_ref()) {
+        (Some(RecipientKind::Contract), Some(config)) => config.gas.map(U256::from),
+        _ => tx_config.request_gas(),
+    };
+    let tiered = deposit_gas_price(app, &tx_config, deposit.value);
+    let gas_price = match tiered {
+        Some(ref tiered) => tiered.effective,
+        None => app.foreign_gas_price(&tx_config),
+    };
+    let request = TransactionRequest {
+        from: app.config.foreign.account,
+        to: Some(foreign_contract),
+        gas,
+        gas_price: Some(gas_price),
+        value: None,
+        data: Some(deposit.payload.clone()),
+        nonce: None,
+        condition: None,
+    };
+    (request, tiered)
+}
+
+/// sends all `deposits` to `ForeignBridge`. `deferred` are relayed afterwards.
+fn send_deposits<T: Transport>(
+    app: &Arc<App<T>>,
+    foreign_contract: Address,
+    deposits: Vec<PendingDeposit>,
+    deferred: Vec<PendingDeposit>,
+    relayed_logs: Vec<RelayedLog>,
+    block: u64,
+) -> DepositRelayState<T> {
+    let (relays, gas_prices): (Vec<_>, Vec<_>) = deposits
+        .iter()
+        .map(|deposit| relay_request(app, foreign_contract, deposit))
+        .unzip();
+    // empty unless `relay_events` is configured
+    let mut expected = expected_events(app, &relays).into_iter();
+
+    // relays to an ordered recipient are held back until its previous relay is mined
+    let keys = deposits
+        .iter()
+        .map(|deposit| match app.config.ordered_relays {
+            Some(ref ordered) if ordered.orders(&deposit.recipient) => Some(deposit.recipient),
+            _ => None,
+        })
+        .collect();
+
+    let spans = deposits
+        .iter()
+        .map(|deposit| relay_span(Direction::HomeToForeign, &deposit.hash))
+        .collect();
+
+    let deposits = deposits
+        .into_iter()
+        .zip(gas_prices)
+        .map(|(deposit, gas_price)| {
+            let callback = app.config.deposit_callback.as_ref().map(|_| CallArgs {
+                recipient: deposit.recipient,
+                value: foreign_value(app.config.value_scale.as_ref(), deposit.value)
+                    .expect("values of deposits are converted by their relay payloads; qed"),
+                main_tx: deposit.hash,
+            });
+            RelayingDeposit {
+                deposit,
+                gas_price,
+                expected: expected.next().and_then(|expected| expected),
+                callback,
+            }
+        })
+        .collect();
+
+    info!(target: "bridge::deposit_relay", "relaying {} deposits", relays.len());
+    let quota = foreign_quota(app, Component::DepositRelay);
+    DepositRelayState::RelayDeposits {
+        future: send_ordered_foreign_transactions(app.clone(), relays, keys, quota).in_spans(spans),
+        deposits,
+        deferred,
+        relayed_logs,
+        block,
+    }
+}
+
+/// transaction calling `contract` back with `data`.
+fn callback_request<T: Transport>(
+    app: &App<T>,
+    contract: Address,
+    data: Bytes,
+) -> TransactionRequest {
+    let tx_config = app.tunables.get().txs.deposit_callback;
+    TransactionRequest {
+        from: app.config.foreign.account,
+        to: Some(contract),
+        gas: tx_config.request_gas(),
+        gas_price: Some(app.foreign_gas_price(&tx_config)),
+        value: None,
+        data: Some(data),
+        nonce: None,
+        condition: None,
+    }
+}
+
+/// transaction recording in `registry` that the deposit of `home_transaction` was completed in
+/// `relay_transaction`.
+fn receipt_request<T: Transport>(
+    app: &App<T>,
+    registry: Address,
+    home_transaction: H256,
+    relay_transaction: H256,
+) -> TransactionRequest {
+    let tx_config = app.tunables.get().txs.receipt_registry;
+    let payload = receipt_registry::ReceiptRegistry::default()
+        .functions()
+        .record_receipt()
+        .input(home_transaction.0, relay_transaction.0);
+    TransactionRequest {
+        from: app.config.home.sender(),
+        to: Some(registry),
+        gas: tx_config.request_gas(),
+        gas_price: Some(tx_config.gas_price.into()),
+        value: None,
+        data: Some(payload.into()),
+        nonce: None,
+        condition: None,
+    }
+}
+
+/// queues the callbacks of the `relays` whose check emitted the expected event.
+/// relays only have callbacks if `deposit_callback` is configured.
+fn queue_callbacks(callbacks: &mut DepositCallbacks, checks: &[EventCheck], relays: &[SentRelay]) {
+    for check in checks {
+        if check.outcome != EventOutcome::Emitted {
+            continue;
+        }
+        let sent = relays
+            .iter()
+            .find(|relay| relay.relay_transaction == check.transaction);
+        if let Some(&SentRelay {
+            log_index,
+            callback: Some(args),
+            ..
+        }) = sent
+        {
+            let relay = check.retry.map_or(check.transaction, |retry| retry.transaction);
+            callbacks.queue(args, log_index, relay);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use rustc_hex::FromHex;
-    use web3::types::{Bytes, Log};
+    use web3::types::{Address, Bytes, H256, Log, U256};
+    use config::{DerivedRecipientsConfig, WalletSalt};
     use contracts::{foreign, home};
-    use super::deposit_relay_payload;
+    use error::{Error, ErrorKind};
+    use test_helpers::{collected_signatures_topic, DepositLogBuilder};
+    use super::super::derived_recipient::wallet_address;
+    use super::{deposit_memos, deposit_relay_payload, deposit_value, parse_deposit_relay_payload};
+
+    fn deposit_log() -> Log {
+        DepositLogBuilder::new().build()
+    }
+
+    fn assert_invalid_log(log: Log, error: Error) {
+        match *error.kind() {
+            ErrorKind::InvalidLog(ref invalid) => assert_eq!(&log, invalid),
+            ref kind => panic!("expected invalid log error, got {:?}", kind),
+        }
+    }
 
     #[test]
     fn test_deposit_relay_payload() {
         let home = home::HomeBridge::default();
         let foreign = foreign::ForeignBridge::default();
 
-        let data = "000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0".from_hex().unwrap();
-        let log = Log {
-            data: data.into(),
-            topics: vec![
-                "e1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c".into(),
-            ],
-            transaction_hash: Some(
-                "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364".into(),
-            ),
-            ..Default::default()
-        };
-
-        let payload = deposit_relay_payload(&home, &foreign, log).unwrap();
+        let payload =
+            deposit_relay_payload(&home, &foreign, deposit_log(), false, None, None).unwrap();
         let expected: Bytes = "26b3293f000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364".from_hex().unwrap().into();
         assert_eq!(expected, payload);
     }
+
+    #[test]
+    fn test_deposit_relay_payload_derived_recipient() {
+        let home = home::HomeBridge::default();
+        let foreign = foreign::ForeignBridge::default();
+        let config = DerivedRecipientsConfig {
+            factory: 0xfac.into(),
+            init_code_hash: 0xc0de.into(),
+            salt: WalletSalt::Address,
+        };
+        let recipient: Address = "aff3454fce5edbc8cca8697c15331677e6ebcccc".parse().unwrap();
+        let wallet = wallet_address(&config, &recipient);
+
+        let payload =
+            deposit_relay_payload(&home, &foreign, deposit_log(), false, None, Some(&config))
+                .unwrap();
+        let plain =
+            deposit_relay_payload(&home, &foreign, deposit_log(), false, None, None).unwrap();
+        // only the recipient differs
+        assert_eq!(&wallet.0[..], &payload.0[16..36]);
+        assert_eq!(&plain.0[..16], &payload.0[..16]);
+        assert_eq!(&plain.0[36..], &payload.0[36..]);
+    }
+
+    #[test]
+    fn test_deposit_relay_payload_unique_ids() {
+        let home = home::HomeBridge::default();
+        let foreign = foreign::ForeignBridge::default();
+        let first = DepositLogBuilder::new().log_index(0).build();
+        let second = DepositLogBuilder::new().log_index(1).build();
+
+        let by_hash =
+            deposit_relay_payload(&home, &foreign, first.clone(), false, None, None).unwrap();
+        let first = deposit_relay_payload(&home, &foreign, first, true, None, None).unwrap();
+        let second = deposit_relay_payload(&home, &foreign, second, true, None, None).unwrap();
+        // only the id differs
+        assert_eq!(&by_hash.0[..68], &first.0[..68]);
+        assert!(by_hash.0[68..] != first.0[68..]);
+        assert!(first != second);
+
+        // unique ids require the log index
+        assert!(deposit_relay_payload(&home, &foreign, deposit_log(), true, None, None).is_err());
+    }
+
+    #[test]
+    fn test_deposit_value() {
+        let home = home::HomeBridge::default();
+        assert_eq!(U256::from(0xf0), deposit_value(&home, &deposit_log()).unwrap());
+        let log = DepositLogBuilder::new().value(0).build();
+        assert!(deposit_value(&home, &log).unwrap().is_zero());
+    }
+
+    #[test]
+    fn test_deposit_relay_payload_unmined_log() {
+        let home = home::HomeBridge::default();
+        let foreign = foreign::ForeignBridge::default();
+        let log = DepositLogBuilder::new().unmined().build();
+
+        let error =
+            parse_deposit_relay_payload(&home, &foreign, &log, false, None, None).unwrap_err();
+        match *error.kind() {
+            ErrorKind::UnminedLog => (),
+            ref kind => panic!("expected unmined log error, got {:?}", kind),
+        }
+        let error =
+            deposit_relay_payload(&home, &foreign, log.clone(), false, None, None).unwrap_err();
+        assert_invalid_log(log, error);
+    }
+
+    #[test]
+    fn test_deposit_relay_payload_wrong_topic() {
+        let home = home::HomeBridge::default();
+        let foreign = foreign::ForeignBridge::default();
+        let log = DepositLogBuilder::new()
+            .topic(collected_signatures_topic())
+            .build();
+
+        let error =
+            deposit_relay_payload(&home, &foreign, log.clone(), false, None, None).unwrap_err();
+        assert_invalid_log(log, error);
+    }
+
+    #[test]
+    fn test_deposit_relay_payload_short_data() {
+        let home = home::HomeBridge::default();
+        let foreign = foreign::ForeignBridge::default();
+        let log = DepositLogBuilder::new().truncated(32).build();
+
+        let error =
+            deposit_relay_payload(&home, &foreign, log.clone(), false, None, None).unwrap_err();
+        assert_invalid_log(log, error);
+    }
+
+    #[test]
+    fn test_deposit_memos() {
+        let hashes = vec![1.into(), 2.into(), 2.into(), 3.into(), 4.into()];
+        let decoded = vec![
+            vec![10.into()],
+            vec![20.into(), 21.into()],
+            vec![20.into(), 21.into()],
+            // plain deposit
+            vec![],
+            // more memos than deposits
+            vec![40.into(), 41.into()],
+        ];
+        assert_eq!(
+            vec![
+                Some(H256::from(10)),
+                Some(H256::from(20)),
+                Some(H256::from(21)),
+                None,
+                None,
+            ],
+            deposit_memos(&hashes, &decoded)
+        );
+    }
 }
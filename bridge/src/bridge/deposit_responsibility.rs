@@ -0,0 +1,112 @@
+use std::time::Duration;
+use web3::types::{Address, H256};
+use config::{Authorities, CooperativeDepositsConfig};
+use super::signature_stagger::authority_slot;
+
+/// authorities responsible for relaying the deposit of the home transaction `main_tx_hash`
+/// with `cooperative_deposits`: those in the first `required_signatures` slots of it,
+/// see `authority_slot`.
+pub fn responsible_authorities(authorities: &Authorities, main_tx_hash: &H256) -> Vec<Address> {
+    authorities
+        .accounts
+        .iter()
+        .filter(|account| is_responsible(authorities, account, main_tx_hash))
+        .cloned()
+        .collect()
+}
+
+/// `true` if `account` relays the deposit of the home transaction `main_tx_hash` right away
+/// with `cooperative_deposits`. an account that isn't one of the authorities relays every deposit.
+pub fn is_responsible(authorities: &Authorities, account: &Address, main_tx_hash: &H256) -> bool {
+    match authority_slot(&authorities.accounts, account, main_tx_hash) {
+        Some(slot) => slot < u64::from(authorities.required_signatures),
+        None => true,
+    }
+}
+
+/// time `account` waits for the authorities responsible for the deposit of the home transaction
+/// `main_tx_hash` before relaying it in their place. zero if it's responsible.
+/// the other authorities fall back in the order of their slots: the first after the grace
+/// period, every later one `fallback_stagger` after the one before it.
+pub fn fallback_delay(
+    config: &CooperativeDepositsConfig,
+    authorities: &Authorities,
+    account: &Address,
+    main_tx_hash: &H256,
+) -> Duration {
+    let responsible = u64::from(authorities.required_signatures);
+    match authority_slot(&authorities.accounts, account, main_tx_hash) {
+        Some(slot) if slot >= responsible => {
+            config.grace_period + config.fallback_stagger * (slot - responsible) as u32
+        }
+        _ => Duration::from_secs(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use web3::types::{Address, H256};
+    use config::{Authorities, CooperativeDepositsConfig};
+    use super::{fallback_delay, is_responsible, responsible_authorities};
+
+    fn authorities(required_signatures: u32) -> Authorities {
+        Authorities {
+            accounts: vec![1.into(), 2.into(), 3.into()],
+            required_signatures,
+        }
+    }
+
+    #[test]
+    fn test_responsible_authorities_rotate_with_the_transaction_hash() {
+        let authorities = authorities(1);
+        let responsible = |hash: u64| responsible_authorities(&authorities, &hash.into());
+        assert_eq!(vec![Address::from(1)], responsible(0));
+        assert_eq!(vec![Address::from(3)], responsible(1));
+        assert_eq!(vec![Address::from(2)], responsible(2));
+        assert_eq!(vec![Address::from(1)], responsible(3));
+    }
+
+    #[test]
+    fn test_every_deposit_has_required_signatures_responsible_authorities() {
+        for required_signatures in 1..4 {
+            let authorities = authorities(required_signatures);
+            for hash in 0..20 {
+                let hash = H256::from(hash);
+                let responsible = responsible_authorities(&authorities, &hash);
+                assert_eq!(required_signatures as usize, responsible.len());
+                for account in &authorities.accounts {
+                    assert_eq!(
+                        responsible.contains(account),
+                        is_responsible(&authorities, account, &hash)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_accounts_that_are_not_authorities_are_responsible() {
+        assert!(is_responsible(&authorities(1), &4.into(), &0.into()));
+    }
+
+    #[test]
+    fn test_fallbacks_are_staggered_by_slot() {
+        let config = CooperativeDepositsConfig {
+            grace_period: Duration::from_secs(60),
+            fallback_stagger: Duration::from_secs(20),
+        };
+        let delay = |account: u64, hash: u64| {
+            fallback_delay(&config, &authorities(1), &account.into(), &hash.into())
+        };
+        // the slots of the deposit of transaction 0 are the indexes of the authorities
+        assert_eq!(Duration::from_secs(0), delay(1, 0));
+        assert_eq!(Duration::from_secs(60), delay(2, 0));
+        assert_eq!(Duration::from_secs(80), delay(3, 0));
+        // and shift with the transaction hash
+        assert_eq!(Duration::from_secs(60), delay(1, 1));
+        assert_eq!(Duration::from_secs(80), delay(2, 1));
+        assert_eq!(Duration::from_secs(0), delay(3, 1));
+        assert_eq!(Duration::from_secs(0), delay(4, 0));
+    }
+}
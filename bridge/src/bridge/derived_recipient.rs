@@ -0,0 +1,201 @@
+use futures::{Async, Future, Poll};
+use tiny_keccak::keccak256;
+use web3::Transport;
+use web3::types::{Address, Bytes, H256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::{DerivedRecipientsConfig, WalletSalt};
+use error::{Error, ErrorKind};
+
+/// address of the contract `factory` deploys with CREATE2, `salt` and the init code hashing
+/// to `init_code_hash`: the last 20 bytes of
+/// `keccak256(0xff ++ factory ++ salt ++ init_code_hash)`, see EIP-1014.
+pub fn create2_address(factory: &Address, salt: &H256, init_code_hash: &H256) -> Address {
+    let mut preimage = Vec::with_capacity(85);
+    preimage.push(0xff);
+    preimage.extend_from_slice(&factory.0);
+    preimage.extend_from_slice(&salt.0);
+    preimage.extend_from_slice(&init_code_hash.0);
+    let hash = keccak256(&preimage);
+    Address::from(&hash[12..])
+}
+
+/// salt of the contract wallet of `recipient`.
+pub fn wallet_salt(salt: WalletSalt, recipient: &Address) -> H256 {
+    match salt {
+        WalletSalt::Address => {
+            let mut padded = [0u8; 32];
+            padded[12..].copy_from_slice(&recipient.0);
+            padded.into()
+        }
+        WalletSalt::Keccak => keccak256(&recipient.0).into(),
+    }
+}
+
+/// contract wallet of `recipient` on foreign.
+pub fn wallet_address(config: &DerivedRecipientsConfig, recipient: &Address) -> Address {
+    let salt = wallet_salt(config.salt, recipient);
+    create2_address(&config.factory, &salt, &config.init_code_hash)
+}
+
+/// Recipient of a deposit on home and the contract wallet on foreign it's relayed to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DerivedRecipient {
+    /// recipient of the deposit on home
+    pub original: Address,
+    /// contract wallet of `original` the deposit is relayed to
+    pub derived: Address,
+}
+
+/// `recipient` with its contract wallet if `derived_recipients` is configured.
+pub fn derive_recipient(
+    config: Option<&DerivedRecipientsConfig>,
+    recipient: Address,
+) -> Option<DerivedRecipient> {
+    config.map(|config| DerivedRecipient {
+        original: recipient,
+        derived: wallet_address(config, &recipient),
+    })
+}
+
+/// the recipient on foreign of deposits to `recipient`: its contract wallet if
+/// `derived_recipients` is configured, `recipient` itself otherwise.
+pub fn foreign_recipient(config: Option<&DerivedRecipientsConfig>, recipient: Address) -> Address {
+    derive_recipient(config, recipient).map_or(recipient, |derived| derived.derived)
+}
+
+pub fn check_wallet_factory<T: Transport>(app: &App<T>) -> CheckWalletFactory<T> {
+    let future = app.config.derived_recipients.as_ref().map(|config| {
+        let code = api::latest_code(&app.connections.foreign, config.factory);
+        (
+            config.factory,
+            api::timeout(&app.timer, code, "foreign", app.config.foreign.request_timeout),
+        )
+    });
+    CheckWalletFactory { future }
+}
+
+/// Checks that the factory of `derived_recipients` is a contract on foreign.
+/// deposits relayed to the wallets of a wrong factory can't be recovered by their recipients.
+/// resolves immediately if `derived_recipients` isn't configured.
+pub struct CheckWalletFactory<T: Transport> {
+    future: Option<(Address, Timeout<ApiCall<Bytes, T::Out>>)>,
+}
+
+impl<T: Transport> Future for CheckWalletFactory<T> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (factory, code) = match self.future {
+            Some((ref factory, ref mut future)) => (*factory, try_ready!(future.poll())),
+            None => return Ok(Async::Ready(())),
+        };
+        if code.0.is_empty() {
+            return Err(ErrorKind::MissingWalletFactory(factory).into());
+        }
+        info!(
+            target: "bridge",
+            "deposits are relayed to the contract wallets of factory {:?}",
+            factory
+        );
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hex::FromHex;
+    use tiny_keccak::keccak256;
+    use web3::types::{Address, H256};
+    use address::checksummed;
+    use config::{DerivedRecipientsConfig, WalletSalt};
+    use super::{create2_address, derive_recipient, foreign_recipient, wallet_salt};
+
+    fn init_code_hash(init_code: &str) -> H256 {
+        let init_code: Vec<u8> = init_code.from_hex().unwrap();
+        keccak256(&init_code).into()
+    }
+
+    #[test]
+    fn test_create2_address() {
+        // examples of EIP-1014
+        let examples = [
+            (
+                "0000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "00",
+                "0x4D1A2e2bB4F88F0250f26Ffff098B0b30B26BF38",
+            ),
+            (
+                "deadbeef00000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "00",
+                "0xB928f69Bb1D91Cd65274e3c79d8986362984fDA3",
+            ),
+            (
+                "deadbeef00000000000000000000000000000000",
+                "000000000000000000000000feed000000000000000000000000000000000000",
+                "00",
+                "0xD04116cDd17beBE565EB2422F2497E06cC1C9833",
+            ),
+            (
+                "0000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "deadbeef",
+                "0x70f2b2914A2a4b783FaEFb75f459A580616Fcb5e",
+            ),
+            (
+                "0000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "",
+                "0xE33C0C7F7df4809055C3ebA6c09CFe4BaF1BD9e0",
+            ),
+        ];
+        for &(factory, salt, init_code, expected) in &examples {
+            let factory: Address = factory.parse().unwrap();
+            let salt: H256 = salt.parse().unwrap();
+            let address = create2_address(&factory, &salt, &init_code_hash(init_code));
+            assert_eq!(expected, checksummed(&address));
+        }
+    }
+
+    #[test]
+    fn test_wallet_salt() {
+        let recipient: Address = "feed000000000000000000000000000000000001".parse().unwrap();
+        let padded: H256 = "000000000000000000000000feed000000000000000000000000000000000001"
+            .parse()
+            .unwrap();
+        assert_eq!(padded, wallet_salt(WalletSalt::Address, &recipient));
+        let hashed: H256 = keccak256(&recipient.0).into();
+        assert_eq!(hashed, wallet_salt(WalletSalt::Keccak, &recipient));
+    }
+
+    #[test]
+    fn test_derive_recipient() {
+        let recipient: Address = 0xfeed.into();
+        assert_eq!(None, derive_recipient(None, recipient));
+        assert_eq!(recipient, foreign_recipient(None, recipient));
+
+        let config = DerivedRecipientsConfig {
+            factory: "deadbeef00000000000000000000000000000000".parse().unwrap(),
+            init_code_hash: init_code_hash("00"),
+            salt: WalletSalt::Address,
+        };
+        let derived = derive_recipient(Some(&config), recipient).unwrap();
+        assert_eq!(recipient, derived.original);
+        let salt = wallet_salt(WalletSalt::Address, &recipient);
+        assert_eq!(
+            create2_address(&config.factory, &salt, &config.init_code_hash),
+            derived.derived
+        );
+        assert_eq!(derived.derived, foreign_recipient(Some(&config), recipient));
+
+        // other salts derive other wallets
+        let config = DerivedRecipientsConfig {
+            salt: WalletSalt::Keccak,
+            ..config
+        };
+        assert!(derived.derived != foreign_recipient(Some(&config), recipient));
+    }
+}
@@ -0,0 +1,399 @@
+use std::fmt;
+use std::time::Instant;
+use serde_json;
+use units::format_wei;
+use super::{CatchUpProgress, LiabilityGauge, RelayCounts};
+
+/// Why a digest was emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestReason {
+    /// `digest_interval` elapsed
+    Interval,
+    /// the bridge is stopping
+    Shutdown,
+    /// open circuits closed or stalled chains produce blocks again
+    Recovered,
+}
+
+/// Cumulative counters of the bridge a digest is the difference of.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DigestCounters {
+    /// checked block of deposit relay
+    pub home_block: u64,
+    /// checked block of the slower of withdraw relay and withdraw confirm
+    pub foreign_block: u64,
+    pub deposit_relay: RelayCounts,
+    pub withdraw_relay: RelayCounts,
+    pub withdraw_confirm: RelayCounts,
+    /// failed json-rpc requests, see `api::rpc_errors`
+    pub rpc_errors: u64,
+    /// requests that timed out, see `api::timeouts`
+    pub timeouts: u64,
+    /// log ranges split in two, see `api::log_bisections`
+    pub log_bisections: u64,
+    /// logs dropped for their size, see `api::oversized_logs`
+    pub oversized_logs: u64,
+    /// deposit events counted by `ObservedTraffic`, relayed or not
+    pub deposits_observed: u64,
+    /// withdraw events counted by `ObservedTraffic`, relayed or not
+    pub withdraws_observed: u64,
+}
+
+/// State of the bridge when a digest is emitted that isn't a counter.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DigestGauges {
+    /// seconds the oldest deposit not yet relayed is pending
+    pub deposit_lag_secs: Option<u64>,
+    /// seconds the oldest withdraw not yet relayed is pending
+    pub withdraw_lag_secs: Option<u64>,
+    /// deposit relays waiting to be sent
+    pub queued_deposits: usize,
+    /// withdraw signatures waiting to be sent
+    pub queued_signatures: usize,
+    /// blocks left to catch up. `None` once caught up with the backlog
+    /// of the catch-up estimate or without one
+    pub catch_up: Option<CatchUpProgress>,
+    /// gas price of foreign in wei derived by `NodeGasPriceMonitor`.
+    /// `None` until derived or without `node_gas_price`
+    pub foreign_gas_price: Option<u64>,
+    /// depth of the latest home reorg since the bridge started, see `ReorgDepths`
+    pub last_reorg_depth: Option<u64>,
+    /// depth of the deepest home reorg ever observed
+    pub max_reorg_depth: Option<u64>,
+    /// deposits not yet executed on foreign as of the last scan, see `Liability`.
+    /// `None` until scanned or without `liability`
+    pub unminted: Option<LiabilityGauge>,
+}
+
+/// Summary of the activity of the bridge since the previous digest.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Digest {
+    pub reason: DigestReason,
+    /// seconds since the previous digest
+    pub period_secs: u64,
+    pub home_blocks: u64,
+    pub foreign_blocks: u64,
+    pub deposits_observed: u64,
+    pub withdraws_observed: u64,
+    pub deposits_relayed: u64,
+    pub deposits_failed: u64,
+    pub withdraws_relayed: u64,
+    pub withdraws_failed: u64,
+    pub signatures_submitted: u64,
+    pub signatures_failed: u64,
+    pub deposit_lag_secs: Option<u64>,
+    pub withdraw_lag_secs: Option<u64>,
+    pub queued_deposits: usize,
+    pub queued_signatures: usize,
+    pub rpc_errors: u64,
+    pub timeouts: u64,
+    pub log_bisections: u64,
+    pub oversized_logs: u64,
+    pub catch_up: Option<CatchUpProgress>,
+    pub foreign_gas_price: Option<u64>,
+    pub last_reorg_depth: Option<u64>,
+    pub max_reorg_depth: Option<u64>,
+    pub unminted: Option<LiabilityGauge>,
+}
+
+impl Digest {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Digest always serializes; qed")
+    }
+}
+
+fn lag(lag: Option<u64>) -> String {
+    match lag {
+        Some(secs) => format!("{}s", secs),
+        None => "none".into(),
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "digest ({:?}) of the last {}s: blocks home {} foreign {}. \
+             observed deposits {} withdraws {}. \
+             deposits {} relayed {} failed, withdraws {} relayed {} failed, \
+             signatures {} submitted {} failed. lag deposits {} withdraws {}. \
+             queued deposits {} signatures {}. rpc errors {}, timeouts {}, log bisections {}, \
+             oversized logs {}",
+            self.reason,
+            self.period_secs,
+            self.home_blocks,
+            self.foreign_blocks,
+            self.deposits_observed,
+            self.withdraws_observed,
+            self.deposits_relayed,
+            self.deposits_failed,
+            self.withdraws_relayed,
+            self.withdraws_failed,
+            self.signatures_submitted,
+            self.signatures_failed,
+            lag(self.deposit_lag_secs),
+            lag(self.withdraw_lag_secs),
+            self.queued_deposits,
+            self.queued_signatures,
+            self.rpc_errors,
+            self.timeouts,
+            self.log_bisections,
+            self.oversized_logs
+        )?;
+        if let Some(catch_up) = self.catch_up {
+            write!(
+                f,
+                ". catching up: blocks left home {} foreign {}, eta {}s",
+                catch_up.home_blocks_left, catch_up.foreign_blocks_left, catch_up.eta_secs
+            )?;
+        }
+        if let Some(gas_price) = self.foreign_gas_price {
+            write!(f, ". foreign gas price {}", format_wei(gas_price.into()))?;
+        }
+        if let Some(max) = self.max_reorg_depth {
+            let last = self.last_reorg_depth
+                .map_or("none".to_owned(), |depth| depth.to_string());
+            write!(f, ". home reorg depth last {} max {}", last, max)?;
+        }
+        if let Some(unminted) = self.unminted {
+            write!(f, ". {}", unminted)?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes digests as the difference of the counters to those of the previous digest.
+#[derive(Debug)]
+pub struct DigestLog {
+    previous: DigestCounters,
+    since: Instant,
+}
+
+impl DigestLog {
+    /// starts counting from `counters` at `now`.
+    pub fn new(counters: DigestCounters, now: Instant) -> Self {
+        DigestLog {
+            previous: counters,
+            since: now,
+        }
+    }
+
+    /// digest of the activity between the previous call and `now`.
+    /// counters that went backwards, e.g. checked blocks after a database reload, count as zero.
+    pub fn digest(
+        &mut self,
+        reason: DigestReason,
+        counters: DigestCounters,
+        gauges: DigestGauges,
+        now: Instant,
+    ) -> Digest {
+        let previous = self.previous;
+        let digest = Digest {
+            reason,
+            period_secs: now.duration_since(self.since).as_secs(),
+            home_blocks: counters.home_block.saturating_sub(previous.home_block),
+            foreign_blocks: counters.foreign_block.saturating_sub(previous.foreign_block),
+            deposits_observed: counters
+                .deposits_observed
+                .saturating_sub(previous.deposits_observed),
+            withdraws_observed: counters
+                .withdraws_observed
+                .saturating_sub(previous.withdraws_observed),
+            deposits_relayed: counters
+                .deposit_relay
+                .relayed
+                .saturating_sub(previous.deposit_relay.relayed),
+            deposits_failed: counters
+                .deposit_relay
+                .failed
+                .saturating_sub(previous.deposit_relay.failed),
+            withdraws_relayed: counters
+                .withdraw_relay
+                .relayed
+                .saturating_sub(previous.withdraw_relay.relayed),
+            withdraws_failed: counters
+                .withdraw_relay
+                .failed
+                .saturating_sub(previous.withdraw_relay.failed),
+            signatures_submitted: counters
+                .withdraw_confirm
+                .relayed
+                .saturating_sub(previous.withdraw_confirm.relayed),
+            signatures_failed: counters
+                .withdraw_confirm
+                .failed
+                .saturating_sub(previous.withdraw_confirm.failed),
+            deposit_lag_secs: gauges.deposit_lag_secs,
+            withdraw_lag_secs: gauges.withdraw_lag_secs,
+            queued_deposits: gauges.queued_deposits,
+            queued_signatures: gauges.queued_signatures,
+            rpc_errors: counters.rpc_errors.saturating_sub(previous.rpc_errors),
+            timeouts: counters.timeouts.saturating_sub(previous.timeouts),
+            log_bisections: counters
+                .log_bisections
+                .saturating_sub(previous.log_bisections),
+            oversized_logs: counters
+                .oversized_logs
+                .saturating_sub(previous.oversized_logs),
+            catch_up: gauges.catch_up,
+            foreign_gas_price: gauges.foreign_gas_price,
+            last_reorg_depth: gauges.last_reorg_depth,
+            max_reorg_depth: gauges.max_reorg_depth,
+            unminted: gauges.unminted,
+        };
+        self.previous = counters;
+        self.since = now;
+        digest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use bridge::{CatchUpProgress, LiabilityGauge, LiabilityTotal};
+    use super::{DigestCounters, DigestGauges, DigestLog, DigestReason};
+
+    #[test]
+    fn test_digest_is_the_difference_to_the_previous_digest() {
+        let start = Instant::now();
+        let mut counters = DigestCounters {
+            home_block: 100,
+            foreign_block: 200,
+            rpc_errors: 3,
+            ..DigestCounters::default()
+        };
+        counters.deposit_relay.relayed = 5;
+        let mut log = DigestLog::new(counters, start);
+
+        counters.home_block = 150;
+        counters.foreign_block = 260;
+        counters.deposit_relay.relayed = 7;
+        counters.deposit_relay.failed = 1;
+        counters.withdraw_relay.relayed = 2;
+        counters.withdraw_confirm.relayed = 4;
+        counters.rpc_errors = 5;
+        counters.timeouts = 1;
+        counters.log_bisections = 3;
+        counters.oversized_logs = 1;
+        counters.deposits_observed = 9;
+        counters.withdraws_observed = 3;
+        let gauges = DigestGauges {
+            deposit_lag_secs: Some(30),
+            queued_signatures: 2,
+            ..DigestGauges::default()
+        };
+        let digest = log.digest(
+            DigestReason::Interval,
+            counters,
+            gauges,
+            start + Duration::from_secs(600),
+        );
+        assert_eq!(600, digest.period_secs);
+        assert_eq!(50, digest.home_blocks);
+        assert_eq!(60, digest.foreign_blocks);
+        assert_eq!(9, digest.deposits_observed);
+        assert_eq!(2, digest.deposits_relayed);
+        assert_eq!(1, digest.deposits_failed);
+        assert_eq!(2, digest.withdraws_relayed);
+        assert_eq!(4, digest.signatures_submitted);
+        assert_eq!(2, digest.rpc_errors);
+        assert_eq!(1, digest.timeouts);
+        assert_eq!(3, digest.log_bisections);
+        assert_eq!(1, digest.oversized_logs);
+        assert_eq!(Some(30), digest.deposit_lag_secs);
+        assert_eq!(
+            "digest (Interval) of the last 600s: blocks home 50 foreign 60. \
+             observed deposits 9 withdraws 3. \
+             deposits 2 relayed 1 failed, withdraws 2 relayed 0 failed, \
+             signatures 4 submitted 0 failed. lag deposits 30s withdraws none. \
+             queued deposits 0 signatures 2. rpc errors 2, timeouts 1, log bisections 3, \
+             oversized logs 1",
+            digest.to_string()
+        );
+        assert!(digest.to_json().contains("\"reason\":\"interval\""));
+
+        // a database reload moved the checked blocks back
+        counters.home_block = 120;
+        let digest = log.digest(
+            DigestReason::Shutdown,
+            counters,
+            DigestGauges::default(),
+            start + Duration::from_secs(660),
+        );
+        assert_eq!(60, digest.period_secs);
+        assert_eq!(0, digest.home_blocks);
+        assert_eq!(0, digest.deposits_relayed);
+        assert_eq!(0, digest.rpc_errors);
+
+        let gauges = DigestGauges {
+            catch_up: Some(CatchUpProgress {
+                home_blocks_left: 10,
+                foreign_blocks_left: 0,
+                eta_secs: 5,
+            }),
+            ..DigestGauges::default()
+        };
+        let digest = log.digest(
+            DigestReason::Interval,
+            counters,
+            gauges,
+            start + Duration::from_secs(720),
+        );
+        assert!(digest
+            .to_string()
+            .ends_with("oversized logs 0. catching up: blocks left home 10 foreign 0, eta 5s"));
+
+        let gauges = DigestGauges {
+            foreign_gas_price: Some(1_500_000_000),
+            ..DigestGauges::default()
+        };
+        let digest = log.digest(
+            DigestReason::Interval,
+            counters,
+            gauges,
+            start + Duration::from_secs(780),
+        );
+        assert!(digest.to_string().ends_with(". foreign gas price 1.5 gwei"));
+
+        // the deepest reorg may be from before a restart
+        let gauges = DigestGauges {
+            max_reorg_depth: Some(14),
+            admin_nonce: None,
+            ..DigestGauges::default()
+        };
+        let digest = log.digest(
+            DigestReason::Interval,
+            counters,
+            gauges,
+            start + Duration::from_secs(840),
+        );
+        assert!(digest.to_string().ends_with(". home reorg depth last none max 14"));
+
+        let awaiting = LiabilityTotal {
+            deposits: 1,
+            value: 1_500_000_000_000_000_000u64.into(),
+        };
+        let gauges = DigestGauges {
+            unminted: Some(LiabilityGauge {
+                total: awaiting,
+                awaiting_signatures: awaiting,
+                since_block: 100,
+                home_block: 200,
+                ..LiabilityGauge::default()
+            }),
+            ..DigestGauges::default()
+        };
+        let digest = log.digest(
+            DigestReason::Interval,
+            counters,
+            gauges,
+            start + Duration::from_secs(900),
+        );
+        assert!(digest.to_string().ends_with(
+            ". unminted 1.5 ETH in 1 deposits in home blocks 100..=200: \
+             awaiting confirmations 0 ETH in 0, quarantined 0 ETH in 0, deferred 0 ETH in 0, \
+             awaiting signatures 1.5 ETH in 1, in flight 0 ETH in 0"
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::{Duration, Instant};
+use futures::{Async, Future};
+use serde_json;
+use timer::{Sleep, Timer};
+use error::Error;
+use super::Component;
+
+/// exit code of the bridge after a drain, to tell a drained bridge from a failed one.
+pub const DRAINED_EXIT_CODE: i32 = 3;
+
+/// seconds a drain waits for the components if `--drain-timeout` isn't given.
+pub const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 300;
+
+/// Progress of a drain, see `Bridge::drain_on`.
+///
+/// once a drain is acknowledged no component takes new logs. every component finishes the batch
+/// in flight, including the transactions still queued by its submitter, and ends.
+/// components that didn't end within the timeout are left pending at their checked block.
+pub struct Drain {
+    timer: Timer,
+    acknowledged: Instant,
+    timeout: Duration,
+    /// wakes the bridge once the timeout elapsed
+    deadline: Sleep,
+    timed_out: bool,
+    /// components that finished their batch in flight, in the order they ended
+    drained: Vec<Component>,
+}
+
+impl Drain {
+    /// drain acknowledged now, timing out after `timeout`.
+    pub fn new(timer: &Timer, timeout: Duration) -> Self {
+        Drain {
+            timer: timer.clone(),
+            acknowledged: timer.now(),
+            timeout,
+            deadline: timer.sleep(timeout),
+            timed_out: false,
+            drained: Vec::new(),
+        }
+    }
+
+    /// `component` finished its batch in flight and won't send anything anymore.
+    pub fn finish(&mut self, component: Component) {
+        if !self.is_drained(component) {
+            info!("{:?} drained", component);
+            self.drained.push(component);
+        }
+    }
+
+    pub fn is_drained(&self, component: Component) -> bool {
+        self.drained.contains(&component)
+    }
+
+    /// components that finished their batch in flight so far, in the order they ended.
+    pub fn drained(&self) -> &[Component] {
+        &self.drained
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// `true` once the drain timeout elapsed.
+    pub fn poll_timed_out(&mut self) -> Result<bool, Error> {
+        if !self.timed_out {
+            if let Async::Ready(()) = self.deadline.poll()? {
+                self.timed_out = true;
+            }
+        }
+        Ok(self.timed_out)
+    }
+
+    /// report of the drain. `pending` are the components that didn't finish.
+    pub fn report(&self, pending: Vec<DrainPending>) -> DrainReport {
+        DrainReport {
+            duration_secs: self.timer.now().duration_since(self.acknowledged).as_secs(),
+            timed_out: self.timed_out,
+            drained: self.drained
+                .iter()
+                .map(|component| format!("{:?}", component))
+                .collect(),
+            pending,
+        }
+    }
+}
+
+/// A component that didn't finish its batch in flight before the bridge stopped.
+/// the logs after its checked block are relayed again by the next instance.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DrainPending {
+    pub component: String,
+    /// last block saved to the database
+    pub checked_block: u64,
+    /// state of the component when the bridge stopped, see `Bridge::state_description`
+    pub state: String,
+    /// transactions its submitter hadn't sent yet
+    pub queued: usize,
+}
+
+/// Summary of a drain written when a drained bridge stops.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DrainReport {
+    /// from the acknowledgement of the drain until the bridge stopped
+    pub duration_secs: u64,
+    /// `true` if the bridge stopped after the drain timeout with components still busy
+    pub timed_out: bool,
+    /// components that finished their batch in flight
+    pub drained: Vec<String>,
+    pub pending: Vec<DrainPending>,
+}
+
+impl DrainReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("DrainReport always serializes; qed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use timer::Timer;
+    use bridge::Component;
+    use super::{Drain, DrainPending};
+
+    #[test]
+    fn test_drain_report() {
+        let (timer, clock) = Timer::manual();
+        let mut drain = Drain::new(&timer, Duration::from_secs(3600));
+        assert!(!drain.is_drained(Component::DepositRelay));
+        drain.finish(Component::WithdrawConfirm);
+        drain.finish(Component::DepositRelay);
+        drain.finish(Component::WithdrawConfirm);
+        assert!(drain.is_drained(Component::DepositRelay));
+
+        let pending = DrainPending {
+            component: "WithdrawRelay".into(),
+            checked_block: 12,
+            state: "relaying withdraws up to block 20".into(),
+            queued: 0,
+        };
+        let report = drain.report(vec![pending.clone()]);
+        assert!(!report.timed_out);
+        assert_eq!(
+            vec!["WithdrawConfirm".to_owned(), "DepositRelay".to_owned()],
+            report.drained
+        );
+        assert_eq!(vec![pending.clone()], report.pending);
+        assert!(report.to_json().contains("\"checked_block\":12"));
+
+        clock.advance(Duration::from_secs(3600));
+        assert!(drain.poll_timed_out().unwrap());
+        let report = drain.report(vec![pending]);
+        assert!(report.timed_out);
+        assert_eq!(3600, report.duration_secs);
+    }
+}
@@ -0,0 +1,341 @@
+use std::sync::Arc;
+use futures::{Future, Poll, Stream};
+use futures::future::{join_all, Join, JoinAll};
+use timer::Interval;
+use web3::Transport;
+use web3::types::{Block, H256, TransactionReceipt, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use database::WatchedExecution;
+use error::Error;
+
+/// Outcome of checking a watched execution.
+#[derive(Debug, PartialEq)]
+enum ExecutionStatus {
+    /// not final yet. keeps being watched
+    Pending(WatchedExecution),
+    /// `depth` blocks below the head in a canonical block
+    Final,
+    /// not part of the canonical foreign chain anymore
+    Invalidated(WatchedExecution),
+}
+
+/// `true` if `execution` must be checked at `latest_block`:
+/// its receipt wasn't found yet or its block is `depth` blocks deep.
+fn is_due(execution: &WatchedExecution, latest_block: u64, depth: u64) -> bool {
+    match execution.block_number {
+        None => true,
+        Some(number) => latest_block >= number + depth,
+    }
+}
+
+/// checks a due `execution` against the `canonical` hash of its block, if it has one,
+/// and its current `receipt`.
+fn check_execution(
+    mut execution: WatchedExecution,
+    latest_block: u64,
+    depth: u64,
+    canonical: Option<H256>,
+    receipt: Option<&TransactionReceipt>,
+) -> ExecutionStatus {
+    if execution.block_hash.is_some() && execution.block_hash == canonical {
+        return ExecutionStatus::Final;
+    }
+    match receipt {
+        // mined for the first time or included again in another block after a reorg
+        Some(receipt) => {
+            execution.block_number = Some(receipt.block_number.low_u64());
+            execution.block_hash = Some(receipt.block_hash);
+            ExecutionStatus::Pending(execution)
+        }
+        None if execution.block_hash.is_some() => ExecutionStatus::Invalidated(execution),
+        // never mined. the relay was dropped if it's still missing `depth` blocks later
+        None => {
+            let since = execution.since_block.unwrap_or(latest_block);
+            if latest_block >= since + depth {
+                ExecutionStatus::Invalidated(execution)
+            } else {
+                ExecutionStatus::Pending(execution)
+            }
+        }
+    }
+}
+
+enum ExecutionWatchState<T: Transport> {
+    /// Waiting for the next check.
+    Wait,
+    /// Fetching the latest foreign block number.
+    FetchBlockNumber(Timeout<ApiCall<U256, T::Out>>),
+    /// Fetching the receipts of due executions and the blocks of those with a block.
+    FetchExecutions {
+        latest_block: u64,
+        due: Vec<WatchedExecution>,
+        future: Join<
+            JoinAll<Vec<Timeout<ApiCall<Option<TransactionReceipt>, T::Out>>>>,
+            JoinAll<Vec<Timeout<ApiCall<Block<H256>, T::Out>>>>,
+        >,
+    },
+    /// Executions have been invalidated.
+    Yield(Option<Vec<WatchedExecution>>),
+}
+
+pub fn create_execution_watch<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    interval: Interval,
+    depth: u64,
+    watched: Vec<WatchedExecution>,
+) -> ExecutionWatch<T> {
+    ExecutionWatch {
+        app,
+        interval,
+        depth,
+        watched,
+        state: ExecutionWatchState::Wait,
+    }
+}
+
+/// Watches the foreign executions of relayed deposits until they are `depth` blocks deep.
+/// yields the executions a foreign reorg invalidated. their deposits must be relayed again.
+pub struct ExecutionWatch<T: Transport> {
+    app: Arc<App<T>>,
+    interval: Interval,
+    depth: u64,
+    watched: Vec<WatchedExecution>,
+    state: ExecutionWatchState<T>,
+}
+
+impl<T: Transport> ExecutionWatch<T> {
+    pub fn track<I: IntoIterator<Item = WatchedExecution>>(&mut self, executions: I) {
+        self.watched.extend(executions);
+    }
+
+    /// executions not final yet. saved to the database to keep watching them after a restart.
+    pub fn watched(&self) -> &[WatchedExecution] {
+        &self.watched
+    }
+
+    /// replaces the watched executions, e.g. by those saved by another instance.
+    pub fn reset(&mut self, watched: Vec<WatchedExecution>) {
+        self.watched = watched;
+        self.state = ExecutionWatchState::Wait;
+    }
+}
+
+impl<T: Transport> Stream for ExecutionWatch<T> {
+    type Item = Vec<WatchedExecution>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                ExecutionWatchState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    ExecutionWatchState::FetchBlockNumber(api::timeout(
+                        &self.app.timer,
+                        api::block_number(&self.app.connections.foreign),
+                        "foreign",
+                        self.app.config.foreign.request_timeout,
+                    ))
+                }
+                ExecutionWatchState::FetchBlockNumber(ref mut future) => {
+                    let latest_block = try_ready!(future.poll()).low_u64();
+                    for execution in &mut self.watched {
+                        if execution.since_block.is_none() {
+                            execution.since_block = Some(latest_block);
+                        }
+                    }
+                    let depth = self.depth;
+                    let due = self.watched
+                        .iter()
+                        .filter(|execution| is_due(execution, latest_block, depth))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    if due.is_empty() {
+                        ExecutionWatchState::Wait
+                    } else {
+                        let app = &self.app;
+                        let receipts = due.iter()
+                            .map(|execution| {
+                                api::timeout(
+                                    &app.timer,
+                                    api::transaction_receipt(
+                                        &app.connections.foreign,
+                                        execution.foreign_transaction,
+                                    ),
+                                    "foreign",
+                                    app.config.foreign.request_timeout,
+                                ).about(execution.foreign_transaction)
+                            })
+                            .collect::<Vec<_>>();
+                        let blocks = due.iter()
+                            .filter_map(|execution| execution.block_number)
+                            .map(|number| {
+                                api::timeout(
+                                    &app.timer,
+                                    api::block_by_number(&app.connections.foreign, number),
+                                    "foreign",
+                                    app.config.foreign.request_timeout,
+                                )
+                            })
+                            .collect::<Vec<_>>();
+                        ExecutionWatchState::FetchExecutions {
+                            latest_block,
+                            due,
+                            future: join_all(receipts).join(join_all(blocks)),
+                        }
+                    }
+                }
+                ExecutionWatchState::FetchExecutions {
+                    ref mut future,
+                    ref due,
+                    latest_block,
+                } => {
+                    let (receipts, blocks) = try_ready!(future.poll());
+                    let mut blocks = blocks.into_iter();
+                    let mut invalidated = Vec::new();
+                    for (execution, receipt) in due.iter().zip(receipts.iter()) {
+                        let canonical = match execution.block_number {
+                            Some(_) => blocks.next().and_then(|block| block.hash),
+                            None => None,
+                        };
+                        let index = match self.watched
+                            .iter()
+                            .position(|watched| watched == execution)
+                        {
+                            Some(index) => index,
+                            None => continue,
+                        };
+                        let status = check_execution(
+                            execution.clone(),
+                            latest_block,
+                            self.depth,
+                            canonical,
+                            receipt.as_ref(),
+                        );
+                        match status {
+                            ExecutionStatus::Pending(execution) => {
+                                self.watched[index] = execution;
+                            }
+                            ExecutionStatus::Final => {
+                                debug!(
+                                    target: "bridge::execution_watch",
+                                    "execution of deposit in home transaction {} is final",
+                                    execution.home_transaction
+                                );
+                                self.watched.remove(index);
+                            }
+                            ExecutionStatus::Invalidated(execution) => {
+                                warn!(
+                                    target: "bridge::execution_watch",
+                                    "foreign transaction {} executing the deposit in home transaction {} is not part of the canonical foreign chain anymore. relaying the deposit again",
+                                    execution.foreign_transaction,
+                                    execution.home_transaction
+                                );
+                                self.watched.remove(index);
+                                invalidated.push(execution);
+                            }
+                        }
+                    }
+                    if invalidated.is_empty() {
+                        ExecutionWatchState::Wait
+                    } else {
+                        ExecutionWatchState::Yield(Some(invalidated))
+                    }
+                }
+                ExecutionWatchState::Yield(ref mut invalidated) => match invalidated.take() {
+                    None => ExecutionWatchState::Wait,
+                    some => return Ok(some.into()),
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use web3::types::{H256, TransactionReceipt, U256};
+    use database::WatchedExecution;
+    use super::{check_execution, is_due, ExecutionStatus};
+
+    fn execution(block: Option<(u64, u64)>) -> WatchedExecution {
+        WatchedExecution {
+            home_transaction: 1.into(),
+            log_index: Some(0.into()),
+            foreign_transaction: 2.into(),
+            since_block: Some(100),
+            block_number: block.map(|(number, _)| number),
+            block_hash: block.map(|(_, hash)| hash.into()),
+            timings: None,
+        }
+    }
+
+    fn receipt(block_number: u64, block_hash: u64) -> TransactionReceipt {
+        serde_json::from_value(json!({
+            "transactionHash": H256::from(2),
+            "transactionIndex": "0x0",
+            "blockHash": H256::from(block_hash),
+            "blockNumber": U256::from(block_number),
+            "cumulativeGasUsed": "0x1",
+            "gasUsed": "0x1",
+            "contractAddress": null,
+            "logs": [],
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "status": "0x1"
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_is_due() {
+        assert!(is_due(&execution(None), 100, 12));
+        assert!(!is_due(&execution(Some((101, 5))), 112, 12));
+        assert!(is_due(&execution(Some((101, 5))), 113, 12));
+    }
+
+    #[test]
+    fn test_check_execution() {
+        // mined
+        assert_eq!(
+            ExecutionStatus::Pending(execution(Some((101, 5)))),
+            check_execution(execution(None), 102, 12, None, Some(&receipt(101, 5)))
+        );
+        // not mined yet
+        assert_eq!(
+            ExecutionStatus::Pending(execution(None)),
+            check_execution(execution(None), 111, 12, None, None)
+        );
+        // dropped
+        assert_eq!(
+            ExecutionStatus::Invalidated(execution(None)),
+            check_execution(execution(None), 112, 12, None, None)
+        );
+        // deep enough in a canonical block
+        assert_eq!(
+            ExecutionStatus::Final,
+            check_execution(
+                execution(Some((101, 5))),
+                113,
+                12,
+                Some(5.into()),
+                Some(&receipt(101, 5))
+            )
+        );
+        // included again after a reorg
+        assert_eq!(
+            ExecutionStatus::Pending(execution(Some((103, 7)))),
+            check_execution(
+                execution(Some((101, 5))),
+                113,
+                12,
+                Some(6.into()),
+                Some(&receipt(103, 7))
+            )
+        );
+        // orphaned by a reorg
+        assert_eq!(
+            ExecutionStatus::Invalidated(execution(Some((101, 5)))),
+            check_execution(execution(Some((101, 5))), 113, 12, Some(H256::from(6)), None)
+        );
+    }
+}
@@ -0,0 +1,527 @@
+use std::time::{Duration, Instant};
+use futures::{Async, Stream};
+use timer::Interval;
+use web3;
+use alert::Severity;
+use config::{FailureAction, FailureRule, DEFAULT_RETRY_BACKOFF, DEFAULT_RETRY_MAX};
+use error::{Error, ErrorKind};
+use remote_signer::is_remote_signer_error;
+use super::Component;
+
+/// backoffs of consecutive retries stop doubling after this many failures
+const MAX_BACKOFF_DOUBLINGS: u32 = 10;
+
+/// seconds components whose send may have been taken by the node wait before they're rebuilt
+pub const AMBIGUOUS_SEND_DELAY: u64 = 60;
+
+/// kind of `err` the rules of `failure_policy` match, one of `config::FAILURE_KINDS`.
+/// reverts are `relay_reverted.<revert kind>`.
+pub fn failure_kind(err: &Error) -> String {
+    let kind = match *err.kind() {
+        ErrorKind::Timeout(..) => "timeout",
+        ErrorKind::Web3(web3::Error::Rpc(_)) => "rpc",
+        ErrorKind::Web3(web3::Error::Transport(ref message)) if is_remote_signer_error(message) => {
+            "remote_signer"
+        }
+        ErrorKind::Web3(web3::Error::Transport(_)) => "transport",
+        ErrorKind::Web3(_) => "web3",
+        ErrorKind::InvalidRpcResponse(..) => "invalid_response",
+        ErrorKind::InvalidLog(_) => "invalid_log",
+        ErrorKind::UnminedLog => "unmined_log",
+        ErrorKind::CallDataMismatch(..) => "call_data_mismatch",
+        ErrorKind::InvalidMessage(_) => "invalid_message",
+        ErrorKind::PersonalApi(..) => "personal_api",
+        ErrorKind::LossyValue(_) | ErrorKind::ValueOverflow(_) => "value_scale",
+        ErrorKind::MissingRelayEvent(..) => "missing_relay_event",
+        ErrorKind::RelayReverted(_, kind, _) => return format!("relay_reverted.{}", kind),
+        ErrorKind::Io(_) => "io",
+        ErrorKind::HeadRegression(..) => "head_regression",
+        ErrorKind::AmbiguousSend(..) => "ambiguous_send",
+        _ => "other",
+    };
+    kind.into()
+}
+
+/// `rules` followed by the default rule for timeouts unless one of them is for timeouts.
+/// timeouts are retried with the defaults of `retry` rules, also without `failure_policy`.
+pub fn with_default_rules(mut rules: Vec<FailureRule>) -> Vec<FailureRule> {
+    if !rules.iter().any(|rule| rule.kind == "timeout") {
+        rules.push(FailureRule {
+            kind: "timeout".into(),
+            action: FailureAction::Retry {
+                max: DEFAULT_RETRY_MAX,
+                backoff: Duration::from_secs(DEFAULT_RETRY_BACKOFF),
+            },
+            severity: None,
+        });
+    }
+    rules
+}
+
+/// `rules` followed by a rule deferring refused and failed signing requests of remote signers
+/// by `delay` unless one of them is for `remote_signer`.
+pub fn with_remote_signer_rule(mut rules: Vec<FailureRule>, delay: Duration) -> Vec<FailureRule> {
+    if !rules.iter().any(|rule| rule.kind == "remote_signer") {
+        rules.push(FailureRule {
+            kind: "remote_signer".into(),
+            action: FailureAction::Defer { delay },
+            severity: None,
+        });
+    }
+    rules
+}
+
+/// `rules` followed by a rule deferring sends that may have been taken by the node by
+/// `AMBIGUOUS_SEND_DELAY` unless one of them is for `ambiguous_send`. until the transaction is
+/// mined the rebuilt component can't tell it was sent and would send it again.
+pub fn with_ambiguous_send_rule(mut rules: Vec<FailureRule>) -> Vec<FailureRule> {
+    if !rules.iter().any(|rule| rule.kind == "ambiguous_send") {
+        rules.push(FailureRule {
+            kind: "ambiguous_send".into(),
+            action: FailureAction::Defer {
+                delay: Duration::from_secs(AMBIGUOUS_SEND_DELAY),
+            },
+            severity: None,
+        });
+    }
+    rules
+}
+
+/// how specific the rule for `rule_kind` is for failures of `kind`. `None` if it doesn't match.
+fn specificity(rule_kind: &str, kind: &str) -> Option<usize> {
+    if rule_kind == "*" {
+        return Some(0);
+    }
+    let matches = rule_kind == kind
+        || (kind.starts_with(rule_kind) && kind[rule_kind.len()..].starts_with('.'));
+    if matches {
+        Some(rule_kind.len())
+    } else {
+        None
+    }
+}
+
+/// What happens to a component after it failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    /// no rule decided: the circuit breaker handles the failure if it's configured,
+    /// otherwise the bridge stops
+    Builtin,
+    /// the bridge stops
+    Fail,
+    /// the component is rebuilt from its checked block at `at`
+    Rebuild { at: Instant },
+    /// the component stops until it's released
+    Quarantine,
+}
+
+/// Outcome of a failure under the policy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub decision: Decision,
+    /// the rule that fired. `"builtin"` if none matched
+    pub rule: String,
+    /// severity of the alert to send, if any
+    pub severity: Option<Severity>,
+}
+
+/// Times a rule fired.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleCount {
+    pub rule: String,
+    pub fired: u64,
+}
+
+/// backoff of the `failures`th consecutive failure under a retry rule with `backoff`.
+fn backoff_of(backoff: Duration, failures: u32) -> Duration {
+    backoff * 2u32.pow(::std::cmp::min(failures - 1, MAX_BACKOFF_DOUBLINGS))
+}
+
+fn describe(rule: &FailureRule) -> String {
+    format!("{} => {:?}", rule.kind, rule.action)
+}
+
+/// Decides by the rules of `failure_policy` what happens to a component that failed.
+/// all failures reported by the components to the bridge are decided here.
+pub struct FailurePolicy {
+    rules: Vec<FailureRule>,
+    /// times every rule fired followed by the times no rule matched
+    fired: Vec<u64>,
+    /// consecutive failures of components retried by a rule
+    retries: Vec<(Component, u32)>,
+    /// components waiting to be rebuilt and when they are due
+    waiting: Vec<(Component, Instant)>,
+    quarantined: Vec<Component>,
+    /// wakes the bridge to rebuild waiting components. `None` without rules
+    interval: Option<Interval>,
+}
+
+impl FailurePolicy {
+    pub fn new(rules: Vec<FailureRule>, interval: Option<Interval>) -> Self {
+        FailurePolicy {
+            fired: vec![0; rules.len() + 1],
+            rules,
+            retries: Vec::new(),
+            waiting: Vec::new(),
+            quarantined: Vec::new(),
+            interval,
+        }
+    }
+
+    /// index of the rule for failures of `kind`: the most specific, the first of equally
+    /// specific rules. `None` if no rule matches.
+    fn rule_for(&self, kind: &str) -> Option<usize> {
+        let mut best: Option<(usize, usize)> = None;
+        for (index, rule) in self.rules.iter().enumerate() {
+            if let Some(specificity) = specificity(&rule.kind, kind) {
+                if best.map_or(true, |(_, best)| specificity > best) {
+                    best = Some((index, specificity));
+                }
+            }
+        }
+        best.map(|(index, _)| index)
+    }
+
+    /// `false` while `component` waits to be rebuilt or is quarantined.
+    pub fn allows(&self, component: Component) -> bool {
+        !self.quarantined.contains(&component)
+            && !self.waiting.iter().any(|waiting| waiting.0 == component)
+    }
+
+    pub fn is_quarantined(&self, component: Component) -> bool {
+        self.quarantined.contains(&component)
+    }
+
+    pub fn quarantined(&self) -> &[Component] {
+        &self.quarantined
+    }
+
+    /// resets the consecutive failures of `component`.
+    pub fn record_success(&mut self, component: Component) {
+        self.retries.retain(|retry| retry.0 != component);
+    }
+
+    /// decides what happens to `component` which failed with `err` at `now`
+    /// and logs the rule that fired.
+    pub fn decide(&mut self, component: Component, err: &Error, now: Instant) -> Verdict {
+        let kind = failure_kind(err);
+        let index = match self.rule_for(&kind) {
+            Some(index) => index,
+            None => {
+                *self.fired.last_mut().expect("fired has an entry for builtin; qed") += 1;
+                return Verdict {
+                    decision: Decision::Builtin,
+                    rule: "builtin".into(),
+                    severity: None,
+                };
+            }
+        };
+        self.fired[index] += 1;
+        let rule = self.rules[index].clone();
+        let mut severity = rule.severity;
+        let decision = match rule.action {
+            FailureAction::Fail => Decision::Fail,
+            FailureAction::Alert => Decision::Builtin,
+            FailureAction::Quarantine => {
+                if !self.quarantined.contains(&component) {
+                    self.quarantined.push(component);
+                }
+                severity = severity.or(Some(Severity::Critical));
+                Decision::Quarantine
+            }
+            FailureAction::Defer { delay } => self.wait(component, now + delay),
+            FailureAction::Retry { max, backoff } => {
+                let failures = self.retry(component);
+                if failures > max {
+                    self.record_success(component);
+                    error!(
+                        "{:?} failed {} times in a row. retries of rule {} exhausted",
+                        component, failures, describe(&rule)
+                    );
+                    Decision::Fail
+                } else {
+                    self.wait(component, now + backoff_of(backoff, failures))
+                }
+            }
+        };
+        warn!(
+            "{:?} failed with {}: {}. rule {} of failure_policy fired: {:?}",
+            component,
+            kind,
+            err,
+            describe(&rule),
+            decision
+        );
+        Verdict {
+            decision,
+            rule: describe(&rule),
+            severity,
+        }
+    }
+
+    /// counts a consecutive failure of `component`. returns the failures so far.
+    fn retry(&mut self, component: Component) -> u32 {
+        if let Some(retry) = self.retries.iter_mut().find(|retry| retry.0 == component) {
+            retry.1 += 1;
+            return retry.1;
+        }
+        self.retries.push((component, 1));
+        1
+    }
+
+    fn wait(&mut self, component: Component, at: Instant) -> Decision {
+        self.waiting.retain(|waiting| waiting.0 != component);
+        self.waiting.push((component, at));
+        Decision::Rebuild { at }
+    }
+
+    /// rebuilds `component` at the next check if it's quarantined. returns `false` if it's not.
+    pub fn release(&mut self, component: Component, now: Instant) -> bool {
+        if !self.quarantined.contains(&component) {
+            return false;
+        }
+        self.quarantined.retain(|quarantined| *quarantined != component);
+        self.wait(component, now);
+        true
+    }
+
+    /// returns the waiting components due at `now`, which have to be rebuilt.
+    pub fn poll_rebuild(&mut self, now: Instant) -> Result<Vec<Component>, Error> {
+        if let Some(ref mut interval) = self.interval {
+            while let Async::Ready(Some(())) = interval.poll()? {}
+        }
+        let (due, waiting): (Vec<_>, Vec<_>) =
+            self.waiting.drain(..).partition(|waiting| waiting.1 <= now);
+        self.waiting = waiting;
+        Ok(due.into_iter().map(|due| due.0).collect())
+    }
+
+    /// times every rule fired, in the order of the rules, followed by the failures
+    /// no rule matched as `"builtin"`.
+    pub fn counts(&self) -> Vec<RuleCount> {
+        self.rules
+            .iter()
+            .map(describe)
+            .chain(Some("builtin".to_owned()))
+            .zip(self.fired.iter())
+            .map(|(rule, fired)| RuleCount {
+                rule,
+                fired: *fired,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use web3;
+    use web3::types::H256;
+    use alert::Severity;
+    use config::{FailureAction, FailureRule};
+    use error::{Error, ErrorKind};
+    use bridge::Component;
+    use super::{backoff_of, failure_kind, with_ambiguous_send_rule, with_default_rules,
+                with_remote_signer_rule, Decision, FailurePolicy, AMBIGUOUS_SEND_DELAY};
+
+    fn rule(kind: &str, action: FailureAction) -> FailureRule {
+        FailureRule {
+            kind: kind.into(),
+            action,
+            severity: None,
+        }
+    }
+
+    fn reverted(kind: &'static str) -> Error {
+        ErrorKind::RelayReverted(H256::zero(), kind, "reverted".into()).into()
+    }
+
+    fn timeout() -> Error {
+        ErrorKind::Timeout("eth_call", "home", None, Duration::from_secs(10)).into()
+    }
+
+    #[test]
+    fn test_failure_kind() {
+        assert_eq!("timeout", failure_kind(&timeout()));
+        assert_eq!("unmined_log", failure_kind(&ErrorKind::UnminedLog.into()));
+        let invalid = ErrorKind::InvalidRpcResponse("eth_getLogs", "[]".into(), "".into());
+        assert_eq!("invalid_response", failure_kind(&invalid.into()));
+        assert_eq!(
+            "relay_reverted.insufficient_funds",
+            failure_kind(&reverted("insufficient_funds"))
+        );
+        assert_eq!(
+            "head_regression",
+            failure_kind(&ErrorKind::HeadRegression(100, 80, 3).into())
+        );
+        assert_eq!("other", failure_kind(&"something".into()));
+        let transport = |message: &str| -> Error {
+            ErrorKind::Web3(web3::Error::Transport(message.into())).into()
+        };
+        assert_eq!(
+            "remote_signer",
+            failure_kind(&transport("remote signer: remote signer responded with status 503"))
+        );
+        assert_eq!("transport", failure_kind(&transport("connection refused")));
+    }
+
+    #[test]
+    fn test_most_specific_rule_fires() {
+        let mut policy = FailurePolicy::new(
+            vec![
+                rule("*", FailureAction::Fail),
+                rule("relay_reverted", FailureAction::Quarantine),
+                rule("relay_reverted.insufficient_funds", FailureAction::Alert),
+                // shadowed by the rule before
+                rule("relay_reverted.insufficient_funds", FailureAction::Fail),
+            ],
+            None,
+        );
+        let now = Instant::now();
+        let component = Component::WithdrawRelay;
+        let verdict = policy.decide(component, &reverted("insufficient_funds"), now);
+        assert_eq!(Decision::Builtin, verdict.decision);
+        assert_eq!("relay_reverted.insufficient_funds => Alert", verdict.rule);
+        let verdict = policy.decide(component, &reverted("other"), now);
+        assert_eq!(Decision::Quarantine, verdict.decision);
+        // quarantine alerts even without severity
+        assert_eq!(Some(Severity::Critical), verdict.severity);
+        assert_eq!(Decision::Fail, policy.decide(component, &timeout(), now).decision);
+
+        let fired = policy
+            .counts()
+            .into_iter()
+            .map(|count| count.fired)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![1, 1, 1, 0, 0], fired);
+    }
+
+    #[test]
+    fn test_unmatched_failures_are_builtin() {
+        let mut policy = FailurePolicy::new(vec![rule("rpc", FailureAction::Fail)], None);
+        let verdict = policy.decide(Component::DepositRelay, &timeout(), Instant::now());
+        assert_eq!(Decision::Builtin, verdict.decision);
+        assert_eq!("builtin", verdict.rule);
+        assert_eq!(1, policy.counts()[1].fired);
+        // rules don't match kinds they are only a string prefix of
+        let mut policy = FailurePolicy::new(vec![rule("relay", FailureAction::Fail)], None);
+        let verdict = policy.decide(Component::DepositRelay, &reverted("other"), Instant::now());
+        assert_eq!(Decision::Builtin, verdict.decision);
+    }
+
+    #[test]
+    fn test_retry_backs_off_until_max() {
+        let backoff = Duration::from_secs(10);
+        let mut policy = FailurePolicy::new(
+            vec![rule("timeout", FailureAction::Retry { max: 2, backoff })],
+            None,
+        );
+        let now = Instant::now();
+        let component = Component::DepositRelay;
+
+        assert_eq!(
+            Decision::Rebuild { at: now + backoff },
+            policy.decide(component, &timeout(), now).decision
+        );
+        assert!(!policy.allows(component));
+        assert!(policy.poll_rebuild(now).unwrap().is_empty());
+        assert_eq!(
+            vec![component],
+            policy.poll_rebuild(now + backoff).unwrap()
+        );
+        assert!(policy.allows(component));
+
+        assert_eq!(
+            Decision::Rebuild {
+                at: now + backoff_of(backoff, 2),
+            },
+            policy.decide(component, &timeout(), now).decision
+        );
+        assert_eq!(Duration::from_secs(20), backoff_of(backoff, 2));
+        assert_eq!(Decision::Fail, policy.decide(component, &timeout(), now).decision);
+
+        // a success starts counting again
+        policy.poll_rebuild(now + backoff * 4).unwrap();
+        policy.decide(component, &timeout(), now);
+        policy.record_success(component);
+        assert_eq!(
+            Decision::Rebuild { at: now + backoff },
+            policy.decide(component, &timeout(), now).decision
+        );
+    }
+
+    #[test]
+    fn test_quarantine_until_released() {
+        let mut policy = FailurePolicy::new(
+            vec![rule("invalid_log", FailureAction::Quarantine)],
+            None,
+        );
+        let now = Instant::now();
+        let component = Component::WithdrawConfirm;
+        let err = ErrorKind::InvalidLog(Default::default()).into();
+        assert_eq!(Decision::Quarantine, policy.decide(component, &err, now).decision);
+        assert!(policy.is_quarantined(component));
+        assert!(policy.poll_rebuild(now + Duration::from_secs(3600)).unwrap().is_empty());
+        assert!(policy.allows(Component::DepositRelay));
+
+        assert!(!policy.release(Component::DepositRelay, now));
+        assert!(policy.release(component, now));
+        assert_eq!(vec![component], policy.poll_rebuild(now).unwrap());
+        assert!(policy.allows(component));
+    }
+
+    #[test]
+    fn test_timeouts_are_retried_by_default() {
+        let mut policy = FailurePolicy::new(
+            with_default_rules(vec![rule("*", FailureAction::Fail)]),
+            None,
+        );
+        let now = Instant::now();
+        assert_eq!(
+            Decision::Rebuild {
+                at: now + Duration::from_secs(10),
+            },
+            policy.decide(Component::DepositRelay, &timeout(), now).decision
+        );
+        let verdict = policy.decide(Component::WithdrawRelay, &reverted("other"), now);
+        assert_eq!(Decision::Fail, verdict.decision);
+
+        // a rule for timeouts replaces the default
+        let rules = with_default_rules(vec![rule("timeout", FailureAction::Fail)]);
+        assert_eq!(vec![rule("timeout", FailureAction::Fail)], rules);
+    }
+
+    #[test]
+    fn test_ambiguous_sends_are_deferred() {
+        let err = Error::with_chain(
+            Error::from(ErrorKind::Web3(web3::Error::Transport("connection reset".into()))),
+            ErrorKind::AmbiguousSend("eth_sendTransaction", None),
+        );
+        assert_eq!("ambiguous_send", failure_kind(&err));
+
+        let rules = with_ambiguous_send_rule(with_default_rules(Vec::new()));
+        let mut policy = FailurePolicy::new(rules, None);
+        let now = Instant::now();
+        assert_eq!(
+            Decision::Rebuild {
+                at: now + Duration::from_secs(AMBIGUOUS_SEND_DELAY),
+            },
+            policy.decide(Component::DepositRelay, &err, now).decision
+        );
+        // a rule for ambiguous sends replaces the default
+        let alert = rule("ambiguous_send", FailureAction::Alert);
+        assert_eq!(vec![alert.clone()], with_ambiguous_send_rule(vec![alert]));
+    }
+
+    #[test]
+    fn test_remote_signer_failures_are_deferred() {
+        let delay = Duration::from_secs(30);
+        let rules = with_remote_signer_rule(vec![rule("*", FailureAction::Fail)], delay);
+        assert_eq!(rule("remote_signer", FailureAction::Defer { delay }), rules[1]);
+        // a rule for remote signers replaces the default
+        let alert = rule("remote_signer", FailureAction::Alert);
+        let rules = with_remote_signer_rule(vec![alert.clone()], delay);
+        assert_eq!(vec![alert], rules);
+    }
+
+}
@@ -0,0 +1,693 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use serde_json;
+use tiny_keccak::keccak256;
+use web3::Transport;
+use web3::types::{Address, Log, Transaction, H256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::Config;
+use contracts::{foreign, home};
+use database::Database;
+use error::{Error, Result};
+use fetch::{fetch_bounded, FetchBounded, Requests};
+use message_to_mainnet::MessageToMainnet;
+use util::{log_transaction_hash, raw_log};
+use super::rebuild::{log_block, scan_logs, ScanLogs, ScannedLogs};
+
+/// foreign blocks an authority has to relay a withdraw it is responsible for
+/// before the assignment counts as failed.
+pub const DEFAULT_DEADLINE_BLOCKS: u64 = 100;
+
+/// upper 5% quantile of the standard normal distribution
+const Z_95: f64 = 1.644_853_6;
+
+/// authority responsible for relaying the withdraw of the `CollectedSignatures` `log`.
+pub fn responsible_authority(foreign: &foreign::ForeignBridge, log: &Log) -> Result<Address> {
+    let event = foreign
+        .events()
+        .collected_signatures()
+        .parse_log(raw_log(log))?;
+    Ok(event.authority_responsible_for_relay.into())
+}
+
+/// `CollectedSignatures` in foreign blocks `from_block..=to_block` and the time an authority
+/// has to relay the withdraw before its assignment counts as failed.
+/// a missing block means the first or last scanned block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FairnessRange {
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+    pub deadline_blocks: u64,
+}
+
+/// `CollectedSignatures` assigning the relay of a withdraw to an authority.
+#[derive(Debug, Clone, PartialEq)]
+struct Assignment {
+    authority: Address,
+    /// foreign transaction that emitted `CollectedSignatures`
+    foreign_transaction: H256,
+    block: u64,
+    /// withdraw id, see `util::log_id`. `None` if the message doesn't match a scanned withdraw
+    withdraw: Option<H256>,
+    /// home transaction that executed the withdraw. `None` while not executed
+    relay: Option<H256>,
+}
+
+/// assignments of `logs` in the foreign blocks `from..=to`, matched to their executions on home.
+fn assignments(
+    config: &Config,
+    home: &home::HomeBridge,
+    foreign: &foreign::ForeignBridge,
+    logs: &ScannedLogs,
+    from: u64,
+    to: u64,
+) -> Result<Vec<Assignment>> {
+    let unique_ids = config.unique_log_ids;
+    // hash of the message of every withdraw -> id of the withdraw
+    let mut withdraw_ids = HashMap::new();
+    for log in &logs.foreign_withdraws {
+        let mut message = MessageToMainnet::from_log(log.clone(), unique_ids)?;
+        if let Some(ref scale) = config.value_scale {
+            match scale.withdraw(message.value) {
+                Ok(scaled) => message.value = scaled.value,
+                Err(_) => continue,
+            }
+        }
+        withdraw_ids.insert(
+            H256::from(keccak256(&message.to_bytes())),
+            message.sidenet_transaction_hash,
+        );
+    }
+    // id of the withdraw -> home transaction that executed it
+    let mut relays = HashMap::new();
+    for log in &logs.home_withdraws {
+        let withdraw = home.events().withdraw().parse_log(raw_log(log))?;
+        relays.insert(H256::from(withdraw.transaction_hash), log_transaction_hash(log)?);
+    }
+
+    let mut assignments = Vec::new();
+    for log in &logs.collected_signatures {
+        let block = log_block(log)?;
+        if block < from || block > to {
+            continue;
+        }
+        let event = foreign
+            .events()
+            .collected_signatures()
+            .parse_log(raw_log(log))?;
+        let withdraw = withdraw_ids.get(&H256::from(event.message_hash)).cloned();
+        assignments.push(Assignment {
+            authority: event.authority_responsible_for_relay.into(),
+            foreign_transaction: log_transaction_hash(log)?,
+            block,
+            withdraw,
+            relay: withdraw.and_then(|withdraw| relays.get(&withdraw).cloned()),
+        });
+    }
+    Ok(assignments)
+}
+
+/// Relays assigned to one authority.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuthorityAssignments {
+    pub authority: Address,
+    pub assigned: u64,
+    /// withdraws the authority relayed itself
+    pub performed: u64,
+    /// withdraws not relayed by the authority that are still within the deadline
+    pub pending: u64,
+    /// withdraws not relayed by the authority within the deadline
+    pub failed: u64,
+}
+
+/// An assignment the responsible authority didn't perform within the deadline.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailedAssignment {
+    pub authority: Address,
+    /// foreign transaction that emitted `CollectedSignatures`
+    pub foreign_transaction: H256,
+    pub block: u64,
+    /// home transaction that executed the withdraw anyway. `None` if not executed
+    pub home_transaction: Option<H256>,
+    /// sender of `home_transaction`
+    pub relayed_by: Option<Address>,
+}
+
+/// Chi-square test of the assignment counts against a uniform distribution.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Uniformity {
+    pub chi_square: f64,
+    pub degrees_of_freedom: u64,
+    /// chi-square above which the counts are unlikely to be uniform, at 5% significance
+    pub critical_value: f64,
+    pub uniform: bool,
+}
+
+/// Distribution of the withdraw relays over the authorities.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Fairness {
+    /// first and last foreign block of the analyzed `CollectedSignatures`
+    pub from_block: u64,
+    pub to_block: u64,
+    pub deadline_blocks: u64,
+    pub assigned: u64,
+    /// configured authorities and any other authority with assignments, by address
+    pub authorities: Vec<AuthorityAssignments>,
+    /// `None` with fewer than 2 authorities or without assignments
+    pub uniformity: Option<Uniformity>,
+    pub failures: Vec<FailedAssignment>,
+}
+
+impl Fairness {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("fairness serializes to json; qed")
+    }
+}
+
+impl fmt::Display for Fairness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} withdraw relays assigned in foreign blocks {}..={}",
+            self.assigned, self.from_block, self.to_block
+        )?;
+        for authority in &self.authorities {
+            let share = if self.assigned == 0 {
+                0.0
+            } else {
+                100.0 * authority.assigned as f64 / self.assigned as f64
+            };
+            writeln!(
+                f,
+                "{:?}: assigned {} ({:.1}%), performed {}, pending {}, failed {}",
+                authority.authority,
+                authority.assigned,
+                share,
+                authority.performed,
+                authority.pending,
+                authority.failed
+            )?;
+        }
+        match self.uniformity {
+            Some(ref uniformity) => writeln!(
+                f,
+                "chi-square {:.2} with {} degrees of freedom, {:.2} at 5% significance: {}",
+                uniformity.chi_square,
+                uniformity.degrees_of_freedom,
+                uniformity.critical_value,
+                if uniformity.uniform {
+                    "consistent with a uniform selection"
+                } else {
+                    "not uniform"
+                }
+            )?,
+            None => writeln!(f, "too few authorities or assignments to test uniformity")?,
+        }
+        if self.failures.is_empty() {
+            write!(
+                f,
+                "no assignments failed within {} blocks",
+                self.deadline_blocks
+            )
+        } else {
+            writeln!(
+                f,
+                "assignments not performed within {} blocks:",
+                self.deadline_blocks
+            )?;
+            let failures = self.failures
+                .iter()
+                .map(|failure| {
+                    let relayed = match (failure.home_transaction, failure.relayed_by) {
+                        (Some(transaction), Some(sender)) => format!(
+                            "relayed by {:?} in home transaction {:?}",
+                            sender, transaction
+                        ),
+                        (Some(transaction), None) => {
+                            format!("relayed in home transaction {:?}", transaction)
+                        }
+                        (None, _) => "not relayed".to_owned(),
+                    };
+                    format!(
+                        "{:?} in foreign transaction {:?} at block {}: {}",
+                        failure.authority, failure.foreign_transaction, failure.block, relayed
+                    )
+                })
+                .collect::<Vec<_>>();
+            write!(f, "{}", failures.join("\n"))
+        }
+    }
+}
+
+/// chi-square critical value at 5% significance, by the Wilson-Hilferty approximation.
+fn critical_value(degrees_of_freedom: u64) -> f64 {
+    let k = degrees_of_freedom as f64;
+    let a = 2.0 / (9.0 * k);
+    k * (1.0 - a + Z_95 * a.sqrt()).powi(3)
+}
+
+/// chi-square test of `counts` against their mean.
+fn uniformity(counts: &[u64]) -> Option<Uniformity> {
+    let total = counts.iter().sum::<u64>();
+    if counts.len() < 2 || total == 0 {
+        return None;
+    }
+    let expected = total as f64 / counts.len() as f64;
+    let chi_square = counts
+        .iter()
+        .map(|count| (*count as f64 - expected).powi(2) / expected)
+        .sum::<f64>();
+    let degrees_of_freedom = counts.len() as u64 - 1;
+    let critical_value = critical_value(degrees_of_freedom);
+    Some(Uniformity {
+        chi_square,
+        degrees_of_freedom,
+        critical_value,
+        uniform: chi_square <= critical_value,
+    })
+}
+
+/// distribution of `assignments` over the configured authorities.
+/// `senders` maps home transactions to their senders.
+/// an assignment not relayed by its authority fails once
+/// `foreign_head` is more than `deadline_blocks` past it.
+fn fairness(
+    config: &Config,
+    assignments: &[Assignment],
+    senders: &HashMap<H256, Address>,
+    from_block: u64,
+    to_block: u64,
+    deadline_blocks: u64,
+    foreign_head: u64,
+) -> Fairness {
+    let mut authorities = config
+        .authorities
+        .accounts
+        .iter()
+        .map(|authority| {
+            (
+                *authority,
+                AuthorityAssignments {
+                    authority: *authority,
+                    assigned: 0,
+                    performed: 0,
+                    pending: 0,
+                    failed: 0,
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    let mut failures = Vec::new();
+    for assignment in assignments {
+        let authority = assignment.authority;
+        let counts = authorities
+            .entry(authority)
+            .or_insert_with(|| AuthorityAssignments {
+                authority,
+                assigned: 0,
+                performed: 0,
+                pending: 0,
+                failed: 0,
+            });
+        counts.assigned += 1;
+        let relayed_by = assignment
+            .relay
+            .and_then(|relay| senders.get(&relay).cloned());
+        if relayed_by == Some(authority) {
+            counts.performed += 1;
+        } else if foreign_head.saturating_sub(assignment.block) <= deadline_blocks {
+            counts.pending += 1;
+        } else {
+            counts.failed += 1;
+            failures.push(FailedAssignment {
+                authority,
+                foreign_transaction: assignment.foreign_transaction,
+                block: assignment.block,
+                home_transaction: assignment.relay,
+                relayed_by,
+            });
+        }
+    }
+    let authorities = authorities
+        .into_iter()
+        .map(|(_, counts)| counts)
+        .collect::<Vec<_>>();
+    let counts = authorities
+        .iter()
+        .map(|authority| authority.assigned)
+        .collect::<Vec<_>>();
+    Fairness {
+        from_block,
+        to_block,
+        deadline_blocks,
+        assigned: assignments.len() as u64,
+        uniformity: uniformity(&counts),
+        authorities,
+        failures,
+    }
+}
+
+enum FairnessState<T: Transport> {
+    /// Scanning the logs of both chains.
+    Scan(ScanLogs<T>),
+    /// Fetching the senders of the home transactions that executed the assigned withdraws.
+    FetchSenders {
+        assignments: Vec<Assignment>,
+        relays: Vec<H256>,
+        from_block: u64,
+        to_block: u64,
+        foreign_head: u64,
+        future: FetchBounded<TransactionRequests<T>>,
+    },
+}
+
+/// `eth_getTransactionByHash` requests for home transactions.
+struct TransactionRequests<T: Transport> {
+    app: Arc<App<T>>,
+    transactions: Vec<H256>,
+}
+
+impl<T: Transport> Requests for TransactionRequests<T> {
+    type Item = Option<Transaction>;
+    type Future = Timeout<ApiCall<Option<Transaction>, T::Out>>;
+
+    fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    fn start(&self, index: usize) -> Self::Future {
+        api::timeout(
+            &self.app.timer,
+            api::transaction(&self.app.connections.home, self.transactions[index]),
+            "home",
+            self.app.config.home.request_timeout,
+        ).about(self.transactions[index])
+    }
+}
+
+/// distribution of the withdraw relays of `range` over the authorities,
+/// from the logs of both chains since the deployment of the contracts in `init`.
+pub fn create_fairness<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+    range: FairnessRange,
+) -> CheckFairness<T> {
+    CheckFairness {
+        state: FairnessState::Scan(scan_logs(app.clone(), init)),
+        foreign_deploy: init.foreign_deploy,
+        app,
+        range,
+    }
+}
+
+/// Future resolving to the `Fairness` of the withdraw relays.
+pub struct CheckFairness<T: Transport> {
+    app: Arc<App<T>>,
+    range: FairnessRange,
+    foreign_deploy: u64,
+    state: FairnessState<T>,
+}
+
+impl<T: Transport> Future for CheckFairness<T> {
+    type Item = Fairness;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                FairnessState::Scan(ref mut scan) => {
+                    let (logs, _, foreign_head) = try_ready!(scan.poll());
+                    let from_block = self.range.from_block.unwrap_or(self.foreign_deploy);
+                    let to_block = self.range.to_block.unwrap_or(foreign_head);
+                    let assignments = assignments(
+                        &self.app.config,
+                        &self.app.home_bridge,
+                        &self.app.foreign_bridge,
+                        &logs,
+                        from_block,
+                        to_block,
+                    )?;
+                    let mut relays = assignments
+                        .iter()
+                        .filter_map(|assignment| assignment.relay)
+                        .collect::<Vec<_>>();
+                    relays.sort();
+                    relays.dedup();
+                    let requests = TransactionRequests {
+                        app: self.app.clone(),
+                        transactions: relays.clone(),
+                    };
+                    let config = &self.app.config;
+                    FairnessState::FetchSenders {
+                        assignments,
+                        relays,
+                        from_block,
+                        to_block,
+                        foreign_head,
+                        future: fetch_bounded(
+                            requests,
+                            config.scan_concurrency,
+                            config.scan_retries,
+                        ),
+                    }
+                }
+                FairnessState::FetchSenders {
+                    ref assignments,
+                    ref relays,
+                    from_block,
+                    to_block,
+                    foreign_head,
+                    ref mut future,
+                } => {
+                    let transactions = try_ready!(future.poll());
+                    let senders = relays
+                        .iter()
+                        .zip(transactions)
+                        .filter_map(|(relay, transaction)| {
+                            transaction.map(|transaction| (*relay, transaction.from))
+                        })
+                        .collect();
+                    return Ok(Async::Ready(fairness(
+                        &self.app.config,
+                        assignments,
+                        &senders,
+                        from_block,
+                        to_block,
+                        self.range.deadline_blocks,
+                        foreign_head,
+                    )));
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use tiny_keccak::keccak256;
+    use web3::types::{Address, H256, Log, U256};
+    use config::{Authorities, Config, Node};
+    use contracts::{foreign, home};
+    use message_to_mainnet::MessageToMainnet;
+    use super::{assignments, critical_value, fairness, responsible_authority, uniformity};
+    use super::super::ScannedLogs;
+
+    const HOME_WITHDRAW: &str = "4d911754a3efbbc2e0463de4f6bff32ed24421d1c89c11dce59a4935f327afff";
+    const FOREIGN_WITHDRAW: &str =
+        "f279e6a1f5e320cca91135676d9cb6e44ca8a08c0b88342bcdb1144f6511b568";
+    const COLLECTED_SIGNATURES: &str =
+        "eb043d149eedb81369bec43d4c3a3a53087debc88d2525f13bfaa3eecda28b5c";
+
+    fn address_word(address: Address) -> H256 {
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(&address.0);
+        word.into()
+    }
+
+    fn value_word(value: u64) -> H256 {
+        let mut word = [0u8; 32];
+        U256::from(value).to_big_endian(&mut word);
+        word.into()
+    }
+
+    fn log(topic: &str, words: Vec<H256>, block: u64, transaction: u64) -> Log {
+        Log {
+            topics: vec![topic.parse().unwrap()],
+            data: words
+                .iter()
+                .flat_map(|word| word.0.to_vec())
+                .collect::<Vec<_>>()
+                .into(),
+            block_number: Some(block.into()),
+            transaction_hash: Some(transaction.into()),
+            log_index: Some(0.into()),
+            ..Default::default()
+        }
+    }
+
+    fn config(authorities: Vec<Address>) -> Config {
+        Config::builder()
+            .home(Node::new(authorities[0], "/home.ipc".into()))
+            .foreign(Node::new(authorities[0], "/foreign.ipc".into()))
+            .authorities(Authorities {
+                accounts: authorities,
+                required_signatures: 1,
+            })
+            .estimated_gas_cost_of_withdraw(100000u64.into())
+            .max_total_home_contract_balance(0u64.into())
+            .max_single_deposit_value(0u64.into())
+            .build()
+            .unwrap()
+    }
+
+    /// a withdraw in foreign transaction `transaction` and its `CollectedSignatures`
+    /// assigning it to `authority`.
+    fn withdraw(authority: Address, block: u64, transaction: u64) -> (Log, Log) {
+        let withdraw = log(
+            FOREIGN_WITHDRAW,
+            vec![address_word(1.into()), value_word(10), value_word(1)],
+            block,
+            transaction,
+        );
+        let message = MessageToMainnet::from_log(withdraw.clone(), false).unwrap();
+        let hash = H256::from(keccak256(&message.to_bytes()));
+        let collected = log(
+            COLLECTED_SIGNATURES,
+            vec![address_word(authority), hash],
+            block,
+            1000 + transaction,
+        );
+        (withdraw, collected)
+    }
+
+    fn executed(withdraw: u64, transaction: u64) -> Log {
+        log(
+            HOME_WITHDRAW,
+            vec![address_word(1.into()), value_word(10), withdraw.into()],
+            50,
+            transaction,
+        )
+    }
+
+    #[test]
+    fn test_responsible_authority() {
+        let (_, collected) = withdraw(0xaa.into(), 10, 1);
+        assert_eq!(
+            Address::from(0xaa),
+            responsible_authority(&foreign::ForeignBridge::default(), &collected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fairness() {
+        let (a, b, c): (Address, Address, Address) = (0xaa.into(), 0xbb.into(), 0xcc.into());
+        let config = config(vec![a, b, c]);
+        let mut logs = ScannedLogs::default();
+        // a performs both of its assignments, b performs one and misses one,
+        // which c relays, c misses a recent one, d isn't configured, e is out of range
+        let assigned = [(a, 10, 1), (a, 11, 2), (b, 12, 3), (b, 13, 4), (c, 95, 5),
+                        (0xdd.into(), 14, 6), (0xee.into(), 200, 7)];
+        for &(authority, block, transaction) in &assigned {
+            let (withdraw, collected) = withdraw(authority, block, transaction);
+            logs.foreign_withdraws.push(withdraw);
+            logs.collected_signatures.push(collected);
+        }
+        logs.home_withdraws = vec![executed(1, 101), executed(2, 102), executed(3, 103),
+                                   executed(4, 104)];
+        let mut senders = HashMap::new();
+        senders.insert(101.into(), a);
+        senders.insert(102.into(), a);
+        senders.insert(103.into(), b);
+        senders.insert(104.into(), c);
+
+        let assignments = assignments(
+            &config,
+            &home::HomeBridge::default(),
+            &foreign::ForeignBridge::default(),
+            &logs,
+            0,
+            100,
+        ).unwrap();
+        assert_eq!(6, assignments.len());
+        assert_eq!(Some(H256::from(1)), assignments[0].withdraw);
+        assert_eq!(Some(H256::from(101)), assignments[0].relay);
+        assert_eq!(None, assignments[4].relay);
+
+        let fairness = fairness(&config, &assignments, &senders, 0, 100, 10, 100);
+        assert_eq!(6, fairness.assigned);
+        let counts = fairness
+            .authorities
+            .iter()
+            .map(|counts| {
+                (counts.authority, counts.assigned, counts.performed, counts.pending, counts.failed)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (a, 2, 2, 0, 0),
+                (b, 2, 1, 0, 1),
+                (c, 1, 0, 1, 0),
+                (0xdd.into(), 1, 0, 0, 1),
+            ],
+            counts
+        );
+        assert_eq!(2, fairness.failures.len());
+        assert_eq!(b, fairness.failures[0].authority);
+        assert_eq!(Some(H256::from(104)), fairness.failures[0].home_transaction);
+        assert_eq!(Some(c), fairness.failures[0].relayed_by);
+        assert_eq!(None, fairness.failures[1].home_transaction);
+
+        let uniformity = fairness.uniformity.unwrap();
+        assert_eq!(3, uniformity.degrees_of_freedom);
+        // expected 1.5 each: (0.25 + 0.25 + 0.25 + 0.25) / 1.5
+        assert!((uniformity.chi_square - 2.0 / 3.0).abs() < 1e-9);
+        assert!(uniformity.uniform);
+
+        let text = fairness.to_string();
+        assert!(text.contains("6 withdraw relays assigned in foreign blocks 0..=100"));
+        assert!(text.contains("assigned 2 (33.3%), performed 2, pending 0, failed 0"));
+        assert!(text.contains("consistent with a uniform selection"));
+        assert!(text.contains(&format!("relayed by {:?} in home transaction", c)));
+        assert!(text.contains("not relayed"));
+    }
+
+    #[test]
+    fn test_uniformity() {
+        assert_eq!(None, uniformity(&[]));
+        assert_eq!(None, uniformity(&[10]));
+        assert_eq!(None, uniformity(&[0, 0]));
+
+        let even = uniformity(&[100, 100, 100, 100]).unwrap();
+        assert_eq!(0.0, even.chi_square);
+        assert!(even.uniform);
+
+        let skewed = uniformity(&[250, 50, 50, 50]).unwrap();
+        // expected 100 each: (22500 + 2500 * 3) / 100
+        assert!((skewed.chi_square - 300.0).abs() < 1e-9);
+        assert!(!skewed.uniform);
+
+        // a single authority that never gets assignments
+        assert!(!uniformity(&[40, 40, 40, 0]).unwrap().uniform);
+    }
+
+    #[test]
+    fn test_critical_value() {
+        // tabulated chi-square quantiles at 95%
+        let table = [(1, 3.841), (2, 5.991), (3, 7.815), (5, 11.070), (10, 18.307), (30, 43.773)];
+        for &(degrees_of_freedom, quantile) in &table {
+            let approximated = critical_value(degrees_of_freedom);
+            assert!(
+                (approximated - quantile).abs() / quantile < 0.03,
+                "{} degrees of freedom: {} instead of {}",
+                degrees_of_freedom,
+                approximated,
+                quantile
+            );
+        }
+    }
+}
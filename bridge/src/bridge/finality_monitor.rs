@@ -0,0 +1,169 @@
+use std::sync::Arc;
+use futures::{Async, Future, Poll, Stream};
+use serde_json::Value;
+use timer::Interval;
+use web3;
+use web3::Transport;
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::{Config, Finality, FinalitySource};
+use error::{Error, ErrorKind};
+use finality::{FinalizedBlock, SharedFinality};
+use super::self_test::Probe;
+
+/// `true` if `err` is the node rejecting the request, e.g. for an unknown tag or method.
+fn is_rejected(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::Web3(web3::Error::Rpc(_)) => true,
+        _ => false,
+    }
+}
+
+/// shares `finalized`, logging when the finality source becomes unavailable or recovers.
+fn record<T: Transport>(app: &App<T>, finality: &SharedFinality, finalized: FinalizedBlock) {
+    let source = &app.config.home.finality_source;
+    match (finality.get(), finalized) {
+        (FinalizedBlock::Unavailable, FinalizedBlock::Unavailable) => (),
+        (_, FinalizedBlock::Unavailable) => warn!(
+            "home finality source {} is unavailable. deposits are relayed after {} confirmations only",
+            source, app.config.home.required_confirmations
+        ),
+        (FinalizedBlock::Unavailable, FinalizedBlock::At(block)) => info!(
+            "home finality source {} recovered: finalized block {}",
+            source, block
+        ),
+        _ => (),
+    }
+    finality.set(finalized);
+}
+
+enum FinalityMonitorState<T: Transport> {
+    /// Waiting for the next check.
+    Wait,
+    /// Fetching the finalized block of home.
+    FetchFinalized(Timeout<ApiCall<Value, T::Out>>),
+    /// The finalized block has been fetched.
+    Yield(Option<FinalizedBlock>),
+}
+
+/// rpc methods `--self-test` probes for the `finality` of home and foreign.
+pub fn self_test_probes(config: &Config) -> Vec<Probe> {
+    let mut probes = Vec::new();
+    // deposits fall back to confirmations while the finality source is unavailable
+    if config.home.finality == Finality::Finalized && config.enable_deposits {
+        let probe = match config.home.finality_source {
+            FinalitySource::Tag(ref tag) => Probe::call(
+                "home",
+                "eth_getBlockByNumber",
+                vec![tag.as_str().into(), false.into()],
+                "finality",
+            ),
+            FinalitySource::Method(ref method) => {
+                Probe::call("home", method.as_str(), vec![], "finality")
+            }
+        };
+        probes.push(probe.enabled_by("home.finality").fallback());
+    }
+    let nodes = [
+        ("home", "home.finality", &config.home),
+        ("foreign", "foreign.finality", &config.foreign),
+    ];
+    for &(chain, option, node) in &nodes {
+        if let Some(rpc) = node.l1_posted_rpc() {
+            probes.push(Probe::call(chain, rpc.method, vec![], "finality").enabled_by(option));
+        }
+    }
+    probes
+}
+
+pub fn create_finality_monitor<T: Transport>(
+    app: Arc<App<T>>,
+    interval: Interval,
+) -> FinalityMonitor<T> {
+    FinalityMonitor {
+        app,
+        interval,
+        finality: SharedFinality::default(),
+        state: FinalityMonitorState::Wait,
+    }
+}
+
+/// Periodically fetches the finalized block of home from `home.finality_source`
+/// and shares it with the log streams limited to finalized blocks.
+///
+/// a source rejecting the request or answering without a block number is reported
+/// unavailable: deposits are then relayed after `required_confirmations` only.
+/// a timed out request keeps the last finalized block.
+pub struct FinalityMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    interval: Interval,
+    finality: SharedFinality,
+    state: FinalityMonitorState<T>,
+}
+
+impl<T: Transport> FinalityMonitor<T> {
+    /// handle to the finalized block set by this monitor.
+    pub fn shared(&self) -> SharedFinality {
+        self.finality.clone()
+    }
+}
+
+impl<T: Transport> Stream for FinalityMonitor<T> {
+    type Item = FinalizedBlock;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                FinalityMonitorState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    FinalityMonitorState::FetchFinalized(api::timeout(
+                        &self.app.timer,
+                        api::finalized_block(
+                            &self.app.connections.home,
+                            &self.app.config.home.finality_source,
+                        ),
+                        "home",
+                        self.app.config.home.request_timeout,
+                    ))
+                }
+                FinalityMonitorState::FetchFinalized(ref mut future) => {
+                    let source = &self.app.config.home.finality_source;
+                    let finalized = match future.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(result)) => {
+                            match api::finalized_block_number(&result, source) {
+                                Ok(block) => FinalizedBlock::At(block),
+                                Err(err) => {
+                                    warn!("home finality source {}: {}", source, err);
+                                    FinalizedBlock::Unavailable
+                                }
+                            }
+                        }
+                        Err(ref err) if is_rejected(err) => {
+                            warn!("home finality source {} rejected the request: {}", source, err);
+                            FinalizedBlock::Unavailable
+                        }
+                        Err(err) => {
+                            // the node is slow or unreachable, not the source unavailable
+                            warn!(
+                                "fetching the finalized home block failed, keeping {:?}: {}",
+                                self.finality.get(),
+                                err
+                            );
+                            self.finality.get()
+                        }
+                    };
+                    record(&self.app, &self.finality, finalized);
+                    debug!("finalized home block: {:?}", finalized);
+                    FinalityMonitorState::Yield(Some(finalized))
+                }
+                FinalityMonitorState::Yield(ref mut finalized) => match finalized.take() {
+                    None => FinalityMonitorState::Wait,
+                    some => return Ok(some.into()),
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
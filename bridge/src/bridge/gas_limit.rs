@@ -0,0 +1,120 @@
+use futures::{Future, Poll};
+use futures::future::Join;
+use web3::Transport;
+use web3::types::{Block, H256, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::{Acknowledgements, TransactionConfig};
+use error::Error;
+
+/// names of configured transactions whose gas exceeds `gas_limit`.
+/// transactions with estimated gas are never reported.
+fn exceeding(txs: &[(&'static str, &TransactionConfig)], gas_limit: U256) -> Vec<&'static str> {
+    txs.iter()
+        .filter(|&&(_, tx)| {
+            tx.request_gas()
+                .map(|gas| gas > gas_limit)
+                .unwrap_or(false)
+        })
+        .map(|&(name, _)| name)
+        .collect()
+}
+
+pub fn check_gas_limits<T: Transport>(app: &App<T>) -> CheckGasLimits<T> {
+    let home = api::timeout(
+        &app.timer,
+        api::latest_block(&app.connections.home),
+        "home",
+        app.config.home.request_timeout,
+    );
+    let foreign = api::timeout(
+        &app.timer,
+        api::latest_block(&app.connections.foreign),
+        "foreign",
+        app.config.foreign.request_timeout,
+    );
+    let mut foreign_txs = vec![
+        ("deposit_relay", app.config.txs.deposit_relay.clone()),
+        ("withdraw_confirm", app.config.txs.withdraw_confirm.clone()),
+    ];
+    if app.config.acknowledgements == Acknowledgements::Contract {
+        foreign_txs.push(("acknowledgement", app.config.txs.acknowledgement.clone()));
+    }
+    if app.config.deposit_finalize.is_some() {
+        foreign_txs.push(("deposit_finalize", app.config.txs.deposit_finalize.clone()));
+    }
+    if app.config.deposit_callback.is_some() {
+        foreign_txs.push(("deposit_callback", app.config.txs.deposit_callback.clone()));
+    }
+    let mut home_txs = vec![("withdraw_relay", app.config.txs.withdraw_relay.clone())];
+    if app.config.receipt_registry.is_some() {
+        home_txs.push(("receipt_registry", app.config.txs.receipt_registry.clone()));
+    }
+    CheckGasLimits {
+        future: home.join(foreign),
+        home_txs,
+        foreign_txs,
+    }
+}
+
+/// Checks that the gas of every relay transaction fits into the
+/// gas limit of the latest block of its chain.
+/// such transactions could never be mined.
+pub struct CheckGasLimits<T: Transport> {
+    future: Join<Timeout<ApiCall<Block<H256>, T::Out>>, Timeout<ApiCall<Block<H256>, T::Out>>>,
+    home_txs: Vec<(&'static str, TransactionConfig)>,
+    foreign_txs: Vec<(&'static str, TransactionConfig)>,
+}
+
+impl<T: Transport> Future for CheckGasLimits<T> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (home, foreign) = try_ready!(self.future.poll());
+        let chains = [
+            ("home", &self.home_txs, home.gas_limit),
+            ("foreign", &self.foreign_txs, foreign.gas_limit),
+        ];
+        for &(chain, txs, gas_limit) in &chains {
+            let txs = txs.iter().map(|&(name, ref tx)| (name, tx)).collect::<Vec<_>>();
+            let exceeding = exceeding(&txs, gas_limit);
+            if !exceeding.is_empty() {
+                bail!(
+                    "gas of transactions.{} exceeds the block gas limit {} of {}",
+                    exceeding.join(", transactions."),
+                    gas_limit,
+                    chain
+                );
+            }
+        }
+        Ok(().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::TransactionConfig;
+    use super::exceeding;
+
+    #[test]
+    fn test_exceeding_gas_limit() {
+        let small = TransactionConfig {
+            gas: 100,
+            gas_price: 0,
+            estimate_gas: false,
+        };
+        let large = TransactionConfig {
+            gas: 1000,
+            ..small.clone()
+        };
+        let estimated = TransactionConfig {
+            estimate_gas: true,
+            ..large.clone()
+        };
+        let txs = [("small", &small), ("large", &large), ("estimated", &estimated)];
+
+        assert_eq!(vec!["large"], exceeding(&txs, 999.into()));
+        assert!(exceeding(&txs, 1000.into()).is_empty());
+    }
+}
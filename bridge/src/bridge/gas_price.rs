@@ -0,0 +1,167 @@
+use futures::{Future, Poll};
+use futures::future::Join;
+use web3::Transport;
+use web3::types::U256;
+use api::{self, ApiCall, Settle, Timeout};
+use app::App;
+use config::{Acknowledgements, TransactionConfig};
+use error::Error;
+use units::format_wei;
+
+/// a configured gas price this many times below or above the chain's
+/// gas price is most likely a mistake
+const UNUSUAL_GAS_PRICE_FACTOR: u64 = 100;
+
+/// names of configured transactions whose gas price differs from `chain_gas_price`
+/// by more than `UNUSUAL_GAS_PRICE_FACTOR` either way.
+/// nothing is reported on chains without gas price.
+fn unusual(
+    txs: &[(&'static str, &TransactionConfig)],
+    chain_gas_price: U256,
+) -> Vec<&'static str> {
+    if chain_gas_price.is_zero() {
+        return Vec::new();
+    }
+    let factor = U256::from(UNUSUAL_GAS_PRICE_FACTOR);
+    // configured gas prices fit u64 so larger chain gas prices can't be exceeded
+    let can_exceed = chain_gas_price <= U256::from(u64::max_value());
+    txs.iter()
+        .filter(|&&(_, tx)| {
+            let gas_price = U256::from(tx.gas_price);
+            gas_price * factor < chain_gas_price
+                || (can_exceed && gas_price > chain_gas_price * factor)
+        })
+        .map(|&(name, _)| name)
+        .collect()
+}
+
+pub fn check_gas_prices<T: Transport>(app: &App<T>) -> CheckGasPrices<T> {
+    let home = api::settle(api::timeout(
+        &app.timer,
+        api::gas_price(&app.connections.home),
+        "home",
+        app.config.home.request_timeout,
+    ));
+    let foreign = api::settle(api::timeout(
+        &app.timer,
+        api::gas_price(&app.connections.foreign),
+        "foreign",
+        app.config.foreign.request_timeout,
+    ));
+    let mut foreign_txs = vec![
+        ("deposit_relay", app.config.txs.deposit_relay.clone()),
+        ("withdraw_confirm", app.config.txs.withdraw_confirm.clone()),
+    ];
+    if app.config.acknowledgements == Acknowledgements::Contract {
+        foreign_txs.push(("acknowledgement", app.config.txs.acknowledgement.clone()));
+    }
+    if app.config.deposit_finalize.is_some() {
+        foreign_txs.push(("deposit_finalize", app.config.txs.deposit_finalize.clone()));
+    }
+    if app.config.deposit_callback.is_some() {
+        foreign_txs.push(("deposit_callback", app.config.txs.deposit_callback.clone()));
+    }
+    let mut home_txs = vec![("withdraw_relay", app.config.txs.withdraw_relay.clone())];
+    if app.config.receipt_registry.is_some() {
+        home_txs.push(("receipt_registry", app.config.txs.receipt_registry.clone()));
+    }
+    CheckGasPrices {
+        future: home.join(foreign),
+        home_txs,
+        foreign_txs,
+    }
+}
+
+/// Warns about relay transactions whose gas price is far off the current
+/// gas price of their chain, e.g. `20` meant as gwei but configured in wei.
+/// such transactions are never mined or overpay by orders of magnitude.
+/// never fails: a chain whose gas price can't be fetched isn't checked.
+pub struct CheckGasPrices<T: Transport> {
+    future: Join<
+        Settle<Timeout<ApiCall<U256, T::Out>>>,
+        Settle<Timeout<ApiCall<U256, T::Out>>>,
+    >,
+    home_txs: Vec<(&'static str, TransactionConfig)>,
+    foreign_txs: Vec<(&'static str, TransactionConfig)>,
+}
+
+impl<T: Transport> Future for CheckGasPrices<T> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (home, foreign) = try_ready!(self.future.poll());
+        let chains = [
+            ("home", &self.home_txs, home),
+            ("foreign", &self.foreign_txs, foreign),
+        ];
+        for &(chain, txs, ref gas_price) in &chains {
+            let gas_price = match *gas_price {
+                Ok(gas_price) => gas_price,
+                Err(ref err) => {
+                    warn!(
+                        "cannot fetch the gas price of {} to check the configured gas prices: {}",
+                        chain, err
+                    );
+                    continue;
+                }
+            };
+            let txs = txs.iter().map(|&(name, ref tx)| (name, tx)).collect::<Vec<_>>();
+            for name in unusual(&txs, gas_price) {
+                let tx = txs.iter()
+                    .find(|&&(tx_name, _)| tx_name == name)
+                    .map(|&(_, tx)| tx)
+                    .expect("unusual returns names of txs; qed");
+                warn!(
+                    "gas price {} of transactions.{} is more than {}x off the gas price {} of {}",
+                    format_wei(tx.gas_price.into()),
+                    name,
+                    UNUSUAL_GAS_PRICE_FACTOR,
+                    format_wei(gas_price),
+                    chain
+                );
+            }
+        }
+        Ok(().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::TransactionConfig;
+    use super::unusual;
+
+    #[test]
+    fn test_unusual_gas_price() {
+        let tx = |gas_price| TransactionConfig {
+            gas: 100,
+            gas_price,
+            estimate_gas: false,
+        };
+        let wei = tx(20);
+        let low = tx(200_000_000);
+        let usual = tx(20_000_000_000);
+        let high = tx(2_000_000_000_000);
+        let free = tx(0);
+        let txs = [
+            ("wei", &wei),
+            ("low", &low),
+            ("usual", &usual),
+            ("high", &high),
+            ("free", &free),
+        ];
+
+        assert_eq!(vec!["wei", "free"], unusual(&txs, 20_000_000_000u64.into()));
+        assert_eq!(
+            vec!["wei", "low", "free"],
+            unusual(&txs, 20_000_000_001u64.into())
+        );
+        assert_eq!(
+            vec!["wei", "high", "free"],
+            unusual(&txs, 19_999_999_999u64.into())
+        );
+        assert_eq!(vec!["low", "usual", "high", "free"], unusual(&txs, 1.into()));
+        // chains without gas price
+        assert!(unusual(&txs, 0.into()).is_empty());
+    }
+}
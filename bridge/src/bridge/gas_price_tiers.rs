@@ -0,0 +1,169 @@
+use web3::Transport;
+use web3::types::U256;
+use app::App;
+use config::{GasPriceTier, GasPriceTiersConfig, TransactionConfig};
+use webhook::serialize_u256;
+
+/// Gas price of a deposit relay with `gas_price_tiers`. gas prices serialize as decimal strings.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TieredGasPrice {
+    /// gas price the relay would get without `gas_price_tiers`
+    #[serde(serialize_with = "serialize_u256")]
+    pub base: U256,
+    /// index of the tier of the deposit. `None` if it's below the first tier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tier: Option<usize>,
+    /// gas price the relay was sent with
+    #[serde(serialize_with = "serialize_u256")]
+    pub effective: U256,
+    /// whether a ceiling lowered the gas price of the tier
+    pub capped: bool,
+}
+
+impl TieredGasPrice {
+    /// whether the relay was sent above its usual gas price.
+    pub fn raised(&self) -> bool {
+        self.effective > self.base
+    }
+}
+
+/// index of the last of `tiers` whose `min_value` `value` reaches.
+pub fn tier_of(tiers: &[GasPriceTier], value: U256) -> Option<usize> {
+    tiers.iter().rposition(|tier| value >= tier.min_value)
+}
+
+/// gas price of the relay of a deposit of `value` usually priced at `base`.
+///
+/// a tier raising the gas price raises it at most to the lower of `config.ceiling` and
+/// `node_ceiling`, but a ceiling never lowers it below `base`.
+pub fn tiered_gas_price(
+    config: &GasPriceTiersConfig,
+    node_ceiling: Option<u64>,
+    base: U256,
+    value: U256,
+) -> TieredGasPrice {
+    let tier = tier_of(&config.tiers, value);
+    let priced = match tier {
+        Some(index) => base * U256::from(config.tiers[index].percent) / U256::from(100),
+        None => base,
+    };
+    let ceiling = match (config.ceiling, node_ceiling) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    let effective = match ceiling {
+        Some(ceiling) if priced > ceiling.into() => base.max(ceiling.into()),
+        _ => priced,
+    };
+    TieredGasPrice {
+        base,
+        tier,
+        effective,
+        capped: effective < priced,
+    }
+}
+
+/// gas price of the relay of a deposit of `value` sent with `tx`.
+/// `None` unless `gas_price_tiers` is configured.
+pub fn deposit_gas_price<T: Transport>(
+    app: &App<T>,
+    tx: &TransactionConfig,
+    value: U256,
+) -> Option<TieredGasPrice> {
+    app.config.gas_price_tiers.as_ref().map(|config| {
+        let node_ceiling = app.config
+            .node_gas_price
+            .as_ref()
+            .and_then(|node| node.ceiling);
+        tiered_gas_price(config, node_ceiling, app.foreign_gas_price(tx), value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{GasPriceTier, GasPriceTiersConfig};
+    use super::{tier_of, tiered_gas_price, TieredGasPrice};
+
+    fn config(ceiling: Option<u64>) -> GasPriceTiersConfig {
+        GasPriceTiersConfig {
+            tiers: vec![
+                GasPriceTier {
+                    min_value: 0.into(),
+                    percent: 50,
+                },
+                GasPriceTier {
+                    min_value: 100.into(),
+                    percent: 100,
+                },
+                GasPriceTier {
+                    min_value: 1000.into(),
+                    percent: 300,
+                },
+            ],
+            ceiling,
+        }
+    }
+
+    #[test]
+    fn test_tier_of_boundary_values() {
+        let tiers = config(None).tiers;
+        assert_eq!(Some(0), tier_of(&tiers, 0.into()));
+        assert_eq!(Some(0), tier_of(&tiers, 99.into()));
+        assert_eq!(Some(1), tier_of(&tiers, 100.into()));
+        assert_eq!(Some(1), tier_of(&tiers, 999.into()));
+        assert_eq!(Some(2), tier_of(&tiers, 1000.into()));
+        assert_eq!(None, tier_of(&tiers[1..], 99.into()));
+    }
+
+    #[test]
+    fn test_tiered_gas_price() {
+        let config = config(None);
+        assert_eq!(
+            TieredGasPrice {
+                base: 10.into(),
+                tier: Some(0),
+                effective: 5.into(),
+                capped: false,
+            },
+            tiered_gas_price(&config, None, 10.into(), 99.into())
+        );
+        let raised = tiered_gas_price(&config, None, 10.into(), 1000.into());
+        assert_eq!(30, raised.effective.low_u64());
+        assert!(raised.raised());
+        assert!(!tiered_gas_price(&config, None, 10.into(), 100.into()).raised());
+
+        // deposits below the first tier get the usual gas price
+        let config = GasPriceTiersConfig {
+            tiers: config.tiers[1..].to_vec(),
+            ..config
+        };
+        assert_eq!(
+            TieredGasPrice {
+                base: 10.into(),
+                tier: None,
+                effective: 10.into(),
+                capped: false,
+            },
+            tiered_gas_price(&config, None, 10.into(), 99.into())
+        );
+    }
+
+    #[test]
+    fn test_tiered_gas_price_respects_the_ceilings() {
+        // the lower ceiling applies
+        let capped = tiered_gas_price(&config(Some(25)), Some(20), 10.into(), 1000.into());
+        assert_eq!(20, capped.effective.low_u64());
+        assert!(capped.capped);
+        let capped = tiered_gas_price(&config(Some(25)), None, 10.into(), 1000.into());
+        assert_eq!(25, capped.effective.low_u64());
+        // the ceiling doesn't lower the usual gas price
+        let capped = tiered_gas_price(&config(Some(5)), None, 10.into(), 1000.into());
+        assert_eq!(10, capped.effective.low_u64());
+        assert!(capped.capped);
+        assert!(!capped.raised());
+        // lowered gas prices are never capped
+        let lowered = tiered_gas_price(&config(Some(5)), None, 20.into(), 0.into());
+        assert_eq!(10, lowered.effective.low_u64());
+        assert!(!lowered.capped);
+    }
+}
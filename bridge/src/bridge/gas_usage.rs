@@ -0,0 +1,474 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use web3::Transport;
+use web3::types::{H256, U256};
+use alert::Severity;
+use app::App;
+use config::GasUsageConfig;
+use units::checked_mul;
+use super::relay_events::{EventCheck, EventOutcome, GasRetry};
+use super::revert::RevertKind;
+
+/// percentile of the gas used by successful relays compared with their gas limit
+pub const GAS_PERCENTILE: usize = 95;
+
+/// Function a relay transaction calls. gas is tracked for each of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RelayCall {
+    /// `ForeignBridge.deposit`, sent by the deposit relay
+    Deposit,
+    /// `ForeignBridge.submitSignature`, sent by the withdraw confirm
+    SubmitSignature,
+    /// `HomeBridge.withdraw` or `HomeBridge.withdrawToPayout`, sent by the withdraw relay
+    Withdraw,
+}
+
+impl RelayCall {
+    pub fn label(&self) -> &'static str {
+        match *self {
+            RelayCall::Deposit => "deposit",
+            RelayCall::SubmitSignature => "submitSignature",
+            RelayCall::Withdraw => "withdraw",
+        }
+    }
+
+    /// option the gas limit of the call is configured with.
+    /// deposits to contracts get `contract_recipients.gas` instead if configured.
+    pub fn gas_option(&self) -> &'static str {
+        match *self {
+            RelayCall::Deposit => "transactions.deposit_relay.gas",
+            RelayCall::SubmitSignature => "transactions.withdraw_confirm.gas",
+            RelayCall::Withdraw => "transactions.withdraw_relay.gas",
+        }
+    }
+}
+
+/// Gas used by a mined relay transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GasSample {
+    pub call: RelayCall,
+    pub transaction: H256,
+    pub gas_used: U256,
+    /// `None` if the node estimated the gas of the transaction
+    pub gas_limit: Option<U256>,
+    /// `true` if the relay emitted its expected event
+    pub succeeded: bool,
+    /// `true` if the relay failed using all of its gas
+    pub out_of_gas: bool,
+    /// the relay sent again after it ran out of gas. only set if `out_of_gas`
+    pub retry: Option<GasRetry>,
+}
+
+/// gas used by the relays of `checks`. relays that weren't mined have none.
+/// a relay sent again after it ran out of gas has a sample of each attempt.
+pub fn samples_of(checks: &[EventCheck]) -> Vec<GasSample> {
+    let mut samples = Vec::new();
+    for check in checks {
+        let call = check.expected.call;
+        if let Some(retry) = check.retry {
+            samples.push(GasSample {
+                call,
+                transaction: check.transaction,
+                gas_used: retry.gas_limit,
+                gas_limit: Some(retry.gas_limit),
+                succeeded: false,
+                out_of_gas: true,
+                retry: Some(retry),
+            });
+        }
+        let gas_used = match check.gas_used {
+            Some(gas_used) => gas_used,
+            None => continue,
+        };
+        let out_of_gas = match check.outcome {
+            EventOutcome::Reverted(ref revert) => revert.kind == RevertKind::OutOfGas,
+            _ => false,
+        };
+        samples.push(GasSample {
+            call,
+            transaction: check.retry.map_or(check.transaction, |retry| retry.transaction),
+            gas_used,
+            gas_limit: check.expected.gas,
+            succeeded: check.outcome == EventOutcome::Emitted,
+            out_of_gas,
+            retry: None,
+        });
+    }
+    samples
+}
+
+/// gas used by the relays of `checks`. empty unless `gas_usage` is configured.
+pub fn gas_samples<T: Transport>(app: &App<T>, checks: &[EventCheck]) -> Vec<GasSample> {
+    if app.config.gas_usage.is_none() {
+        return Vec::new();
+    }
+    samples_of(checks)
+}
+
+/// `percent` of `gas`, rounded down. saturates instead of overflowing.
+pub fn raised_gas(gas: U256, percent: u32) -> U256 {
+    checked_mul(gas, percent.into()).map_or(U256::max_value(), |gas| gas / 100.into())
+}
+
+/// the `percentile`th percentile of `used` by nearest rank. `None` if `used` is empty.
+fn percentile(used: &VecDeque<U256>, percentile: usize) -> Option<U256> {
+    let mut sorted = used.iter().cloned().collect::<Vec<_>>();
+    sorted.sort();
+    let rank = (sorted.len() * percentile + 99) / 100;
+    sorted.get(rank.saturating_sub(1)).cloned()
+}
+
+/// A warning about the gas limit of relays.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GasWarning {
+    /// the `GAS_PERCENTILE`th percentile of the gas used by successful relays with `gas_limit`
+    /// exceeds `gas_usage.warn_percent` of it
+    NearLimit {
+        call: RelayCall,
+        gas_limit: U256,
+        percentile: U256,
+        /// number of relays the percentile is of
+        samples: usize,
+        suggested: U256,
+    },
+    /// a relay failed using all of its gas
+    OutOfGas {
+        call: RelayCall,
+        transaction: H256,
+        gas_limit: U256,
+        suggested: U256,
+        /// the transaction it was sent again in, if retried
+        retry: Option<GasRetry>,
+    },
+}
+
+impl GasWarning {
+    pub fn severity(&self) -> Severity {
+        match *self {
+            GasWarning::NearLimit { .. } => Severity::Warning,
+            GasWarning::OutOfGas { .. } => Severity::Critical,
+        }
+    }
+
+    /// kind of the alert of the warning.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            GasWarning::NearLimit { .. } => "gas_limit_near",
+            GasWarning::OutOfGas { .. } => "relay_out_of_gas",
+        }
+    }
+
+    /// what the warning is about. alerts with the same key are about the same thing.
+    pub fn key(&self) -> String {
+        match *self {
+            GasWarning::NearLimit {
+                call, gas_limit, ..
+            } => format!("{} {}", call.label(), gas_limit),
+            GasWarning::OutOfGas { transaction, .. } => format!("{:?}", transaction),
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match *self {
+            GasWarning::NearLimit {
+                call,
+                gas_limit,
+                percentile,
+                samples,
+                suggested,
+            } => format!(
+                "{}th percentile of the gas used by the last {} {} relays with gas limit {} is {}. raise {} to {}",
+                GAS_PERCENTILE,
+                samples,
+                call.label(),
+                gas_limit,
+                percentile,
+                call.gas_option(),
+                suggested
+            ),
+            GasWarning::OutOfGas {
+                call,
+                transaction,
+                gas_limit,
+                suggested,
+                retry,
+            } => {
+                let retried = match retry {
+                    Some(retry) => format!(
+                        "it was sent again in transaction {:?} with gas limit {}",
+                        retry.transaction, retry.gas
+                    ),
+                    None => "it is not retried".to_owned(),
+                };
+                format!(
+                    "{} relay transaction {:?} ran out of gas with gas limit {}. raise {} to {}. {}",
+                    call.label(),
+                    transaction,
+                    gas_limit,
+                    call.gas_option(),
+                    suggested,
+                    retried
+                )
+            }
+        }
+    }
+}
+
+/// Gas used by the most recent successful relays by the function they call and their
+/// gas limit, see `GasUsageConfig`.
+#[derive(Debug, Clone)]
+pub struct GasUsage {
+    config: GasUsageConfig,
+    /// gas used by function and gas limit, oldest first
+    used: BTreeMap<(RelayCall, U256), VecDeque<U256>>,
+    /// functions and gas limits whose percentile was warned about. warned again once
+    /// the percentile fell below `warn_percent` in between
+    warned: BTreeSet<(RelayCall, U256)>,
+}
+
+impl GasUsage {
+    pub fn new(config: GasUsageConfig) -> Self {
+        GasUsage {
+            config,
+            used: BTreeMap::new(),
+            warned: BTreeSet::new(),
+        }
+    }
+
+    /// the `GAS_PERCENTILE`th percentile of the gas used by successful relays of `call`
+    /// with `gas_limit`. `None` if there were none.
+    pub fn percentile(&self, call: RelayCall, gas_limit: U256) -> Option<U256> {
+        self.used
+            .get(&(call, gas_limit))
+            .and_then(|used| percentile(used, GAS_PERCENTILE))
+    }
+
+    /// records `samples`. returns the warnings they raise.
+    /// samples without gas limit aren't tracked since their gas was estimated.
+    pub fn record(&mut self, samples: &[GasSample]) -> Vec<GasWarning> {
+        let mut warnings = Vec::new();
+        for sample in samples {
+            let gas_limit = match sample.gas_limit {
+                Some(gas_limit) => gas_limit,
+                None => continue,
+            };
+            if sample.out_of_gas {
+                warnings.push(GasWarning::OutOfGas {
+                    call: sample.call,
+                    transaction: sample.transaction,
+                    gas_limit,
+                    suggested: raised_gas(gas_limit, self.config.raise_percent),
+                    retry: sample.retry,
+                });
+                continue;
+            }
+            if !sample.succeeded {
+                continue;
+            }
+            let key = (sample.call, gas_limit);
+            let count = {
+                let used = self.used.entry(key).or_insert_with(VecDeque::new);
+                if used.len() >= self.config.samples {
+                    used.pop_front();
+                }
+                used.push_back(sample.gas_used);
+                used.len()
+            };
+            if count < self.config.min_samples {
+                continue;
+            }
+            let percentile = self.percentile(sample.call, gas_limit)
+                .expect("a sample was just recorded; qed");
+            let near = match (
+                checked_mul(percentile, 100.into()),
+                checked_mul(gas_limit, self.config.warn_percent.into()),
+            ) {
+                (Some(percentile), Some(threshold)) => percentile > threshold,
+                _ => true,
+            };
+            if !near {
+                self.warned.remove(&key);
+            } else if self.warned.insert(key) {
+                warnings.push(GasWarning::NearLimit {
+                    call: sample.call,
+                    gas_limit,
+                    percentile,
+                    samples: count,
+                    suggested: raised_gas(percentile, self.config.raise_percent),
+                });
+            }
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{Address, H256, TransactionRequest, U256};
+    use alert::Severity;
+    use config::GasUsageConfig;
+    use contracts::foreign;
+    use super::super::relay_events::{EventCheck, EventOutcome, ExpectedEvent, GasRetry};
+    use super::super::revert::Revert;
+    use super::{raised_gas, samples_of, GasSample, GasUsage, GasWarning, RelayCall};
+
+    fn config() -> GasUsageConfig {
+        GasUsageConfig {
+            samples: 20,
+            min_samples: 10,
+            warn_percent: 90,
+            raise_percent: 150,
+            retry_out_of_gas: true,
+        }
+    }
+
+    fn sample(call: RelayCall, gas_used: u64, gas_limit: u64) -> GasSample {
+        GasSample {
+            call,
+            transaction: gas_used.into(),
+            gas_used: gas_used.into(),
+            gas_limit: Some(gas_limit.into()),
+            succeeded: true,
+            out_of_gas: false,
+            retry: None,
+        }
+    }
+
+    #[test]
+    fn test_gas_usage_warns_once_the_percentile_nears_the_limit() {
+        let calls = [
+            RelayCall::Deposit,
+            RelayCall::SubmitSignature,
+            RelayCall::Withdraw,
+        ];
+        for &call in &calls {
+            let mut usage = GasUsage::new(config());
+            // 9 of 10 relays use little gas, too few samples to warn yet
+            let mut samples = (0..9)
+                .map(|i| sample(call, 50_000 + i, 100_000))
+                .collect::<Vec<_>>();
+            assert!(usage.record(&samples).is_empty());
+            samples = vec![sample(call, 95_000, 100_000)];
+            // the 95th percentile of 10 samples is the largest one
+            let warnings = usage.record(&samples);
+            assert_eq!(
+                vec![GasWarning::NearLimit {
+                    call,
+                    gas_limit: 100_000.into(),
+                    percentile: 95_000.into(),
+                    samples: 10,
+                    suggested: 142_500.into(),
+                }],
+                warnings
+            );
+            assert!(warnings[0].description().contains(call.gas_option()));
+            // warned once, and not about the other calls or limits
+            assert!(usage.record(&[sample(call, 94_000, 100_000)]).is_empty());
+            assert!(usage.record(&[sample(call, 95_000, 200_000)]).is_empty());
+            let other = calls.iter().find(|&&other| other != call).cloned().unwrap();
+            assert_eq!(None, usage.percentile(other, 100_000.into()));
+
+            // the percentile falls once the large samples are dropped, and warns when it rises
+            let low = (0..20)
+                .map(|i| sample(call, 50_000 + i, 100_000))
+                .collect::<Vec<_>>();
+            assert!(usage.record(&low).is_empty());
+            assert_eq!(Some(50_018.into()), usage.percentile(call, 100_000.into()));
+            let high = vec![sample(call, 99_000, 100_000), sample(call, 99_500, 100_000)];
+            assert_eq!(1, usage.record(&high).len());
+        }
+    }
+
+    fn deposit(gas: u64) -> ExpectedEvent {
+        let foreign = foreign::ForeignBridge::default();
+        let payload = foreign
+            .functions()
+            .deposit()
+            .input(Address::from(1), U256::from(2), [3u8; 32]);
+        let request = TransactionRequest {
+            from: 1.into(),
+            to: Some(9.into()),
+            gas: Some(gas.into()),
+            gas_price: None,
+            value: None,
+            data: Some(payload.into()),
+            nonce: None,
+            condition: None,
+        };
+        ExpectedEvent::for_relay(&request).unwrap()
+    }
+
+    #[test]
+    fn test_gas_usage_classifies_out_of_gas() {
+        let retry = GasRetry {
+            transaction: 3.into(),
+            gas_limit: 100_000.into(),
+            gas: 150_000.into(),
+        };
+        let checks = [
+            EventCheck {
+                transaction: 1.into(),
+                expected: deposit(100_000),
+                outcome: EventOutcome::Reverted(Revert::out_of_gas()),
+                gas_used: Some(100_000.into()),
+                retry: None,
+                event: None,
+            },
+            // retried with the raised limit, which was enough
+            EventCheck {
+                transaction: 2.into(),
+                expected: deposit(150_000),
+                outcome: EventOutcome::Emitted,
+                gas_used: Some(120_000.into()),
+                retry: Some(retry),
+                event: Some("Deposit(address,uint256,bytes32)"),
+            },
+            EventCheck {
+                transaction: 4.into(),
+                expected: deposit(100_000),
+                outcome: EventOutcome::Unconfirmed,
+                gas_used: None,
+                retry: None,
+                event: None,
+            },
+        ];
+        let samples = samples_of(&checks);
+        assert_eq!(3, samples.len());
+        assert!(samples[0].out_of_gas && samples[1].out_of_gas && !samples[2].out_of_gas);
+        assert_eq!(H256::from(2), samples[1].transaction);
+        assert_eq!(Some(retry), samples[1].retry);
+        // the retry is a successful relay with the raised limit
+        assert_eq!(H256::from(3), samples[2].transaction);
+        assert_eq!(Some(150_000.into()), samples[2].gas_limit);
+        assert!(samples[2].succeeded);
+
+        let mut usage = GasUsage::new(config());
+        let warnings = usage.record(&samples);
+        assert_eq!(2, warnings.len());
+        assert_eq!(Severity::Critical, warnings[0].severity());
+        assert_eq!(
+            GasWarning::OutOfGas {
+                call: RelayCall::Deposit,
+                transaction: 2.into(),
+                gas_limit: 100_000.into(),
+                suggested: 150_000.into(),
+                retry: Some(retry),
+            },
+            warnings[1]
+        );
+        assert!(warnings[0].description().ends_with("it is not retried"));
+        let retried = format!(
+            "sent again in transaction {:?} with gas limit 150000",
+            H256::from(3)
+        );
+        assert!(warnings[1].description().ends_with(&retried));
+        assert_eq!(Some(120_000.into()), usage.percentile(RelayCall::Deposit, 150_000.into()));
+        assert_eq!(None, usage.percentile(RelayCall::Deposit, 100_000.into()));
+    }
+
+    #[test]
+    fn test_raised_gas_saturates() {
+        assert_eq!(U256::from(150_000), raised_gas(100_000.into(), 150));
+        assert_eq!(U256::from(400_000), raised_gas(100_000.into(), 400));
+        assert_eq!(U256::max_value(), raised_gas(U256::max_value(), 150));
+    }
+}
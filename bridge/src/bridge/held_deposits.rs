@@ -0,0 +1,233 @@
+use std::slice;
+use web3::types::{Address, H256, Log, U256};
+use config::RecipientPolicy;
+use webhook::serialize_u256;
+use super::history::{relay_key, Direction};
+
+/// release block of the deposits to `manual` recipients of `recipient_policies`: they are
+/// never confirmed deep enough, only `HeldDeposits::release` moves their release.
+pub const UNTIL_RELEASED: u64 = u64::MAX;
+
+/// A deposit held back until `release`.
+#[derive(Debug, Clone)]
+pub struct HeldDeposit<R> {
+    pub log: Log,
+    /// block of the deposit log
+    pub block: u64,
+    pub release: R,
+}
+
+impl<R> HeldDeposit<R> {
+    /// key of the relay of the deposit, see `relay_key`.
+    pub fn key(&self) -> H256 {
+        relay_key(
+            Direction::HomeToForeign,
+            &self.log.transaction_hash.unwrap_or_default(),
+            self.log.log_index,
+        )
+    }
+}
+
+/// Deposits to a recipient held by `recipient_policies`, served on `GET /pending`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HeldValue {
+    pub recipient: Address,
+    pub policy: RecipientPolicy,
+    /// total value of the held deposits, a decimal string
+    #[serde(serialize_with = "serialize_u256")]
+    pub value: U256,
+    /// relay keys of the held deposits in the order of their logs, see `relay_key`
+    pub keys: Vec<H256>,
+}
+
+/// Deposits held outside the batches of the relay until their release: the block home
+/// confirmed for `extra_confirmations` of `recipient_policies`, the deadline of the fallback
+/// for deposits left to other authorities by `cooperative_deposits`.
+///
+/// they aren't persisted: the relay yields blocks only up to the earliest held deposit,
+/// so a restarted or rebuilt relay reads the logs of held deposits again.
+#[derive(Debug)]
+pub struct HeldDeposits<R> {
+    deposits: Vec<HeldDeposit<R>>,
+}
+
+impl<R> Default for HeldDeposits<R> {
+    fn default() -> Self {
+        HeldDeposits {
+            deposits: Vec::new(),
+        }
+    }
+}
+
+fn block_of(log: &Log) -> u64 {
+    log.block_number.map_or(0, |block| block.low_u64())
+}
+
+impl<R: Copy + Ord> HeldDeposits<R> {
+    /// holds the deposit in `log` until `release`.
+    pub fn hold(&mut self, log: Log, release: R) {
+        self.deposits.push(HeldDeposit {
+            block: block_of(&log),
+            log,
+            release,
+        });
+    }
+
+    /// `true` if a deposit is due at `at`.
+    pub fn has_due(&self, at: R) -> bool {
+        self.deposits.iter().any(|deposit| deposit.release <= at)
+    }
+
+    /// removes the deposits due at `at`, in the order of their logs.
+    pub fn take_due(&mut self, at: R) -> Vec<HeldDeposit<R>> {
+        let (mut due, held): (Vec<_>, Vec<_>) = self.deposits
+            .drain(..)
+            .partition(|deposit| deposit.release <= at);
+        self.deposits = held;
+        due.sort_by_key(|deposit| (deposit.block, deposit.log.log_index));
+        due
+    }
+
+    /// the held deposit with relay `key`, see `HeldDeposit::key`.
+    pub fn get(&self, key: &H256) -> Option<&HeldDeposit<R>> {
+        self.deposits.iter().find(|deposit| deposit.key() == *key)
+    }
+
+    /// moves the release of the deposit with relay `key` to `release`.
+    /// `false` if it isn't held.
+    pub fn release(&mut self, key: &H256, release: R) -> bool {
+        match self.deposits.iter_mut().find(|deposit| deposit.key() == *key) {
+            Some(deposit) => {
+                deposit.release = release;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// the held deposits in the order they were held.
+    pub fn iter(&self) -> slice::Iter<HeldDeposit<R>> {
+        self.deposits.iter()
+    }
+
+    /// earliest release of the held deposits. `None` if none is held.
+    pub fn next_release(&self) -> Option<R> {
+        self.deposits.iter().map(|deposit| deposit.release).min()
+    }
+
+    /// highest block up to `block` without held deposits in or before it.
+    pub fn checked(&self, block: u64) -> u64 {
+        self.deposits
+            .iter()
+            .map(|deposit| deposit.block.saturating_sub(1))
+            .fold(block, |checked, before| checked.min(before))
+    }
+
+    /// home transactions and log indices of the held deposits.
+    pub fn logs(&self) -> Vec<(H256, Option<U256>)> {
+        self.deposits
+            .iter()
+            .filter_map(|deposit| {
+                deposit
+                    .log
+                    .transaction_hash
+                    .map(|hash| (hash, deposit.log.log_index))
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.deposits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deposits.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use web3::types::Log;
+    use bridge::{relay_key, Direction};
+    use super::{HeldDeposits, UNTIL_RELEASED};
+
+    fn log(block: u64, log_index: u64) -> Log {
+        Log {
+            block_number: Some(block.into()),
+            log_index: Some(log_index.into()),
+            transaction_hash: Some(block.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_held_deposits_are_due_at_their_release_block() {
+        let mut held = HeldDeposits::<u64>::default();
+        held.hold(log(20, 1), 50);
+        held.hold(log(10, 3), 40);
+        held.hold(log(10, 2), 40);
+        assert_eq!(3, held.len());
+        assert_eq!(Some(40), held.next_release());
+        assert!(!held.has_due(39));
+        assert!(held.take_due(39).is_empty());
+
+        assert!(held.has_due(45));
+        let due = held.take_due(45);
+        assert_eq!(
+            vec![(10, 2.into()), (10, 3.into())],
+            due.iter()
+                .map(|deposit| (deposit.block, deposit.log.log_index.unwrap()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![(20.into(), Some(1.into()))], held.logs());
+        let due = held.take_due(50);
+        assert_eq!(vec![20], due.iter().map(|deposit| deposit.block).collect::<Vec<_>>());
+        assert!(held.is_empty());
+        assert_eq!(None, held.next_release());
+    }
+
+    #[test]
+    fn test_held_deposits_are_due_at_their_deadline() {
+        let mut held = HeldDeposits::default();
+        let now = Instant::now();
+        held.hold(log(10, 1), now + Duration::from_secs(20));
+        held.hold(log(11, 1), now + Duration::from_secs(10));
+        assert_eq!(Some(now + Duration::from_secs(10)), held.next_release());
+        assert!(!held.has_due(now));
+        let due = held.take_due(now + Duration::from_secs(15));
+        assert_eq!(vec![11], due.iter().map(|deposit| deposit.block).collect::<Vec<_>>());
+        assert_eq!(9, held.checked(20));
+    }
+
+    #[test]
+    fn test_held_deposits_cap_the_checked_block() {
+        let mut held = HeldDeposits::<u64>::default();
+        assert_eq!(30, held.checked(30));
+        held.hold(log(20, 1), 50);
+        held.hold(log(25, 1), 55);
+        assert_eq!(19, held.checked(30));
+        assert_eq!(15, held.checked(15));
+        held.take_due(50);
+        assert_eq!(24, held.checked(30));
+    }
+
+    #[test]
+    fn test_held_deposits_are_held_until_released() {
+        let mut held = HeldDeposits::<u64>::default();
+        held.hold(log(20, 1), UNTIL_RELEASED);
+        held.hold(log(21, 1), 50);
+        assert!(held.take_due(1_000_000).iter().all(|deposit| deposit.block == 21));
+        assert_eq!(19, held.checked(30));
+
+        let key = relay_key(Direction::HomeToForeign, &20.into(), Some(1.into()));
+        assert_eq!(Some(20), held.get(&key).map(|deposit| deposit.block));
+        assert_eq!(key, held.iter().next().unwrap().key());
+        assert!(!held.release(&relay_key(Direction::HomeToForeign, &20.into(), None), 30));
+        assert!(held.release(&key, 30));
+        let due = held.take_due(30);
+        assert_eq!(vec![20], due.iter().map(|deposit| deposit.block).collect::<Vec<_>>());
+        assert_eq!(None, held.get(&key));
+        assert!(held.is_empty());
+    }
+}
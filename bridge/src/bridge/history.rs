@@ -0,0 +1,485 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use serde_json;
+use tiny_keccak::keccak256;
+use tracing::Span;
+use web3::Transport;
+use web3::types::{Address, Log, TransactionReceipt, H256, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use error::Error;
+use webhook::serialize_u256;
+use super::contract_recipients::RecipientKind;
+use super::derived_recipient::DerivedRecipient;
+use super::gas_price_tiers::TieredGasPrice;
+use super::node_version::RelayNode;
+use super::step_timing::StepTimings;
+
+/// Direction of a relay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// deposit relayed from home to foreign
+    HomeToForeign,
+    /// withdraw relayed from foreign to home
+    ForeignToHome,
+}
+
+/// stable key of the relay of the deposit or withdraw emitted by `source_transaction`
+/// at `log_index`: `keccak256(direction, source_transaction, log_index)`.
+///
+/// the same deposit or withdraw always gets the same key, so consumers can
+/// recognize records they have already seen.
+/// withdraws pass no `log_index` since their id already includes it with `unique_log_ids`.
+pub fn relay_key(direction: Direction, source_transaction: &H256, log_index: Option<U256>) -> H256 {
+    let mut bytes = [0u8; 65];
+    bytes[0] = match direction {
+        Direction::HomeToForeign => 0,
+        Direction::ForeignToHome => 1,
+    };
+    bytes[1..33].copy_from_slice(&source_transaction.0);
+    log_index
+        .unwrap_or_default()
+        .to_big_endian(&mut bytes[33..]);
+    keccak256(&bytes).into()
+}
+
+/// span of the relay of `direction` of the deposit or withdraw emitted by `source_transaction`,
+/// nested in the current span. `main_tx_hash` of deposits and `side_tx_hash` of withdraws
+/// is `source_transaction`. the rpc calls of the relay are nested in it.
+pub fn relay_span(direction: Direction, source_transaction: &H256) -> Span {
+    match direction {
+        Direction::HomeToForeign => info_span!(
+            target: "bridge::relay",
+            "relay",
+            direction = "home_to_foreign",
+            main_tx_hash = %source_transaction
+        ),
+        Direction::ForeignToHome => info_span!(
+            target: "bridge::relay",
+            "relay",
+            direction = "foreign_to_home",
+            side_tx_hash = %source_transaction
+        ),
+    }
+}
+
+/// Position of a deposit log on home. orders deposits exactly as they were emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct MainPosition {
+    pub block_number: u64,
+    /// index of the transaction that emitted the deposit in its block
+    pub transaction_index: u64,
+    /// index of the deposit log in its block
+    pub log_index: u64,
+}
+
+impl MainPosition {
+    /// position of a mined `log`. `None` if the node left out a field.
+    pub fn of_log(log: &Log) -> Option<Self> {
+        match (log.block_number, log.transaction_index, log.log_index) {
+            (Some(block_number), Some(transaction_index), Some(log_index)) => Some(MainPosition {
+                block_number: block_number.low_u64(),
+                transaction_index: transaction_index.low_u64(),
+                log_index: log_index.low_u64(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A relayed deposit or withdraw.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RelayRecord {
+    pub direction: Direction,
+    /// stable key of the relay. see `relay_key`
+    pub key: H256,
+    /// transaction that emitted the deposit or withdraw.
+    /// for withdraws with `unique_log_ids` this is the id of the withdraw
+    pub source_transaction: H256,
+    /// transaction that relayed the deposit or withdraw
+    pub relay_transaction: H256,
+    /// last block of the batch the deposit or withdraw was relayed with
+    pub block: u64,
+    /// account paid by the relay: the recipient on foreign of a deposit,
+    /// the recipient or payout address on home of a withdraw
+    pub receiver: Address,
+    /// value of the deposit or withdraw, a decimal string
+    #[serde(serialize_with = "serialize_u256")]
+    pub value: U256,
+    /// position of the deposit on home. `None` for withdraws
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub main_position: Option<MainPosition>,
+    /// memo of the deposit decoded from the call data of its home transaction.
+    /// only decoded if `deposit_memo` is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<H256>,
+    /// kind of the recipient of the deposit on foreign.
+    /// only fetched if `contract_recipients` is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<RecipientKind>,
+    /// recipient of the deposit on home and the contract wallet it was relayed to.
+    /// only derived if `derived_recipients` is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub derived_recipient: Option<DerivedRecipient>,
+    /// gas price of the deposit relay. only set if `gas_price_tiers` is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_price: Option<TieredGasPrice>,
+    /// milliseconds the batch of the relay spent in each step until it was sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<StepTimings>,
+    /// node the deposit or withdraw was observed on: home for deposits, foreign for withdraws
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed_on: Option<RelayNode>,
+    /// node the relay transaction was sent to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submitted_to: Option<RelayNode>,
+}
+
+/// Relay records returned by `RelayHistory::page`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Page {
+    pub records: Vec<RelayRecord>,
+    /// `from` of the next page. `None` if there are no more records
+    pub next: Option<u64>,
+}
+
+impl Page {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Page always serializes; qed")
+    }
+}
+
+/// Deposit records returned by `RelayHistory::page_by_main_position`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PositionPage {
+    pub records: Vec<RelayRecord>,
+    /// `after` of the next page. `None` if there are no more records
+    pub next: Option<MainPosition>,
+}
+
+impl PositionPage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("PositionPage always serializes; qed")
+    }
+}
+
+/// The most recent relays, oldest first.
+///
+/// every record gets a sequence number, so pages stay valid while
+/// the oldest records are dropped.
+#[derive(Debug)]
+pub struct RelayHistory {
+    capacity: usize,
+    /// sequence number of the oldest record
+    first: u64,
+    records: VecDeque<RelayRecord>,
+}
+
+impl RelayHistory {
+    pub fn new(capacity: usize) -> Self {
+        RelayHistory {
+            capacity,
+            first: 0,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn extend<I: IntoIterator<Item = RelayRecord>>(&mut self, records: I) {
+        for record in records {
+            if self.records.len() == self.capacity {
+                self.records.pop_front();
+                self.first += 1;
+            }
+            self.records.push_back(record);
+        }
+    }
+
+    /// up to `limit` records of `direction` (all if `None`), starting at sequence number `from`.
+    pub fn page(&self, direction: Option<Direction>, from: u64, limit: usize) -> Page {
+        let skip = from.saturating_sub(self.first) as usize;
+        let mut records = Vec::new();
+        let mut next = None;
+        for (offset, record) in self.records.iter().enumerate().skip(skip) {
+            if direction.map_or(false, |direction| direction != record.direction) {
+                continue;
+            }
+            if records.len() == limit {
+                next = Some(self.first + offset as u64);
+                break;
+            }
+            records.push(record.clone());
+        }
+        Page { records, next }
+    }
+
+    /// up to `limit` deposit records ordered by their position on home, starting after `after`.
+    ///
+    /// the cursor is a position rather than a sequence number, so a page continues
+    /// where the previous one stopped even if a deposit was relayed late.
+    pub fn page_by_main_position(
+        &self,
+        after: Option<MainPosition>,
+        limit: usize,
+    ) -> PositionPage {
+        let mut records = self.records
+            .iter()
+            .filter(|record| match (record.main_position, after) {
+                (None, _) => false,
+                (Some(position), Some(after)) => position > after,
+                (Some(_), None) => true,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        records.sort_by_key(|record| record.main_position);
+        let next = if records.len() > limit {
+            records.truncate(limit);
+            records.last().and_then(|record| record.main_position)
+        } else {
+            None
+        };
+        PositionPage { records, next }
+    }
+
+    /// the relay with `key`, if it's still in the history.
+    pub fn by_key(&self, key: &H256) -> Option<RelayRecord> {
+        self.records
+            .iter()
+            .find(|record| record.key == *key)
+            .cloned()
+    }
+
+    /// relays of the deposit or withdraw emitted by `hash`.
+    pub fn by_source_transaction(&self, hash: &H256) -> Vec<RelayRecord> {
+        self.records
+            .iter()
+            .filter(|record| record.source_transaction == *hash)
+            .cloned()
+            .collect()
+    }
+}
+
+/// position of the deposit of `record` looked up from the receipt of its home transaction,
+/// for relay records written before positions were recorded.
+pub fn find_main_position<T: Transport>(
+    app: &Arc<App<T>>,
+    record: &RelayRecord,
+) -> FindMainPosition<T> {
+    FindMainPosition {
+        future: api::timeout(
+            &app.timer,
+            api::transaction_receipt(&app.connections.home, record.source_transaction),
+            "home",
+            app.config.home.request_timeout,
+        ).about(record.source_transaction),
+        record: record.clone(),
+    }
+}
+
+/// Future resolving to the `MainPosition` of a deposit record.
+/// `None` for withdraw records and if the transaction or the log is gone, e.g. reorged out.
+pub struct FindMainPosition<T: Transport> {
+    future: Timeout<ApiCall<Option<TransactionReceipt>, T::Out>>,
+    record: RelayRecord,
+}
+
+impl<T: Transport> Future for FindMainPosition<T> {
+    type Item = Option<MainPosition>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let receipt = try_ready!(self.future.poll());
+        if self.record.direction != Direction::HomeToForeign {
+            return Ok(Async::Ready(None));
+        }
+        let record = &self.record;
+        let position = receipt.and_then(|receipt| {
+            receipt
+                .logs
+                .iter()
+                .find(|log| {
+                    relay_key(Direction::HomeToForeign, &record.source_transaction, log.log_index)
+                        == record.key
+                })
+                .and_then(MainPosition::of_log)
+        });
+        Ok(Async::Ready(position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{Log, H256};
+    use super::{relay_key, Direction, MainPosition, RelayHistory, RelayRecord};
+
+    fn record(direction: Direction, source: u64) -> RelayRecord {
+        RelayRecord {
+            direction,
+            key: relay_key(direction, &source.into(), None),
+            source_transaction: source.into(),
+            relay_transaction: (source + 100).into(),
+            block: source,
+            receiver: 0xaa.into(),
+            value: source.into(),
+            memo: None,
+            recipient: None,
+            derived_recipient: None,
+            main_position: None,
+            gas_price: None,
+            timings: None,
+            observed_on: None,
+            submitted_to: None,
+        }
+    }
+
+    fn position(block_number: u64, transaction_index: u64, log_index: u64) -> MainPosition {
+        MainPosition {
+            block_number,
+            transaction_index,
+            log_index,
+        }
+    }
+
+    fn deposit(source: u64, position: MainPosition) -> RelayRecord {
+        RelayRecord {
+            key: relay_key(
+                Direction::HomeToForeign,
+                &source.into(),
+                Some(position.log_index.into()),
+            ),
+            main_position: Some(position),
+            ..record(Direction::HomeToForeign, source)
+        }
+    }
+
+    #[test]
+    fn test_relay_history_pages() {
+        let mut history = RelayHistory::new(4);
+        history.extend(vec![
+            record(Direction::HomeToForeign, 1),
+            record(Direction::ForeignToHome, 2),
+            record(Direction::HomeToForeign, 3),
+        ]);
+
+        let page = history.page(None, 0, 2);
+        assert_eq!(
+            vec![record(Direction::HomeToForeign, 1), record(Direction::ForeignToHome, 2)],
+            page.records
+        );
+        assert_eq!(Some(2), page.next);
+        let page = history.page(None, 2, 2);
+        assert_eq!(vec![record(Direction::HomeToForeign, 3)], page.records);
+        assert_eq!(None, page.next);
+
+        let page = history.page(Some(Direction::HomeToForeign), 0, 1);
+        assert_eq!(vec![record(Direction::HomeToForeign, 1)], page.records);
+        assert_eq!(Some(2), page.next);
+
+        // the oldest records are dropped, later sequence numbers stay valid
+        history.extend(vec![
+            record(Direction::ForeignToHome, 4),
+            record(Direction::HomeToForeign, 5),
+        ]);
+        let page = history.page(None, 0, 10);
+        assert_eq!(4, page.records.len());
+        assert_eq!(record(Direction::ForeignToHome, 2), page.records[0]);
+        let page = history.page(None, 4, 10);
+        assert_eq!(vec![record(Direction::HomeToForeign, 5)], page.records);
+
+        let source: H256 = 3.into();
+        assert_eq!(
+            vec![record(Direction::HomeToForeign, 3)],
+            history.by_source_transaction(&source)
+        );
+        assert!(history.by_source_transaction(&1.into()).is_empty());
+        let key = relay_key(Direction::HomeToForeign, &3.into(), None);
+        assert_eq!(Some(record(Direction::HomeToForeign, 3)), history.by_key(&key));
+        assert_eq!(None, history.by_key(&relay_key(Direction::ForeignToHome, &3.into(), None)));
+        assert_eq!(
+            r#"{"records":[{"direction":"home_to_foreign","key":"0x2ae814e46d56bd6291047972156cfe1ab54edd4b6bd3d7d0fa683b5c133dc6a2","source_transaction":"0x0000000000000000000000000000000000000000000000000000000000000005","relay_transaction":"0x0000000000000000000000000000000000000000000000000000000000000069","block":5}],"next":null}"#,
+            history.page(None, 4, 10).to_json()
+        );
+    }
+
+    #[test]
+    fn test_relay_history_pages_by_main_position() {
+        let mut history = RelayHistory::new(10);
+        // the deposit of block 10 was relayed late, e.g. after the spend limit deferred it
+        history.extend(vec![
+            deposit(1, position(11, 0, 0)),
+            record(Direction::ForeignToHome, 2),
+            deposit(3, position(11, 2, 5)),
+            deposit(4, position(10, 7, 9)),
+            deposit(5, position(11, 2, 4)),
+        ]);
+
+        let page = history.page_by_main_position(None, 2);
+        assert_eq!(
+            vec![deposit(4, position(10, 7, 9)), deposit(1, position(11, 0, 0))],
+            page.records
+        );
+        assert_eq!(Some(position(11, 0, 0)), page.next);
+        let page = history.page_by_main_position(page.next, 2);
+        assert_eq!(
+            vec![deposit(5, position(11, 2, 4)), deposit(3, position(11, 2, 5))],
+            page.records
+        );
+        assert_eq!(None, page.next);
+
+        // a deposit relayed after the first page was read doesn't shift the cursor
+        history.extend(vec![deposit(6, position(10, 0, 0))]);
+        let page = history.page_by_main_position(Some(position(11, 0, 0)), 10);
+        assert_eq!(2, page.records.len());
+        assert_eq!(
+            vec![deposit(6, position(10, 0, 0))],
+            history.page_by_main_position(None, 1).records
+        );
+
+        assert_eq!(
+            r#"{"records":[{"direction":"home_to_foreign","key":"0x536e0f23ddcd7dbef08dc8f2951221510e6c6df5a38eaac1f06ca9f0ca0be55d","source_transaction":"0x0000000000000000000000000000000000000000000000000000000000000005","relay_transaction":"0x0000000000000000000000000000000000000000000000000000000000000069","block":5,"main_position":{"block_number":11,"transaction_index":2,"log_index":3}}],"next":null}"#,
+            {
+                let mut history = RelayHistory::new(1);
+                history.extend(vec![deposit(5, position(11, 2, 3))]);
+                history.page_by_main_position(None, 1).to_json()
+            }
+        );
+    }
+
+    #[test]
+    fn test_main_position_of_log() {
+        let log = Log {
+            block_number: Some(11.into()),
+            transaction_index: Some(2.into()),
+            log_index: Some(5.into()),
+            ..Default::default()
+        };
+        assert_eq!(Some(position(11, 2, 5)), MainPosition::of_log(&log));
+        let pending = Log {
+            block_number: None,
+            ..log
+        };
+        assert_eq!(None, MainPosition::of_log(&pending));
+    }
+
+    #[test]
+    fn test_relay_key() {
+        let hash: H256 = 5.into();
+        assert_eq!(
+            "2ae814e46d56bd6291047972156cfe1ab54edd4b6bd3d7d0fa683b5c133dc6a2"
+                .parse::<H256>()
+                .unwrap(),
+            relay_key(Direction::HomeToForeign, &hash, None)
+        );
+        assert_eq!(
+            "536e0f23ddcd7dbef08dc8f2951221510e6c6df5a38eaac1f06ca9f0ca0be55d"
+                .parse::<H256>()
+                .unwrap(),
+            relay_key(Direction::HomeToForeign, &hash, Some(3.into()))
+        );
+        assert_ne!(
+            relay_key(Direction::HomeToForeign, &hash, None),
+            relay_key(Direction::ForeignToHome, &hash, None)
+        );
+    }
+}
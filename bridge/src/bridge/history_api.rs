@@ -0,0 +1,657 @@
+use jsonrpc_http_server::{RequestMiddlewareAction, Response, Server, ServerBuilder};
+use jsonrpc_http_server::hyper::{header, server, Method, StatusCode};
+use rpc::IoHandler;
+use serde_json;
+use web3::types::H256;
+use build_info::BuildInfo;
+use capture::parse_time;
+use config::HistoryApiConfig;
+use database::SkippedDeposit;
+use error::{Error, ResultExt};
+use util::is_bearer;
+use super::held_deposits::HeldValue;
+use super::history::{Direction, MainPosition, Page};
+use super::pending_age::PendingRelay;
+use super::shared_relays::SharedRelays;
+
+/// Pending relays returned by `GET /pending`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PendingPage {
+    pub relays: Vec<PendingRelay>,
+    /// `from` of the next page. `None` if there are no more relays
+    pub next: Option<u64>,
+    /// total value of the deposits held by `recipient_policies` of every recipient with held
+    /// deposits, on every page. the held deposits are pending relays as well
+    pub held: Vec<HeldValue>,
+}
+
+/// Skipped deposits returned by `GET /skipped`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SkippedPage {
+    pub skipped: Vec<SkippedDeposit>,
+    /// `from` of the next page. `None` if there are no more deposits
+    pub next: Option<u64>,
+}
+
+/// Order of the records of `GET /relays`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Order {
+    /// by sequence number, see `RelayHistory::page`
+    Sequence,
+    /// deposits by their position on home, see `RelayHistory::page_by_main_position`
+    MainPosition,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order::Sequence
+    }
+}
+
+/// Query of a request to the history api.
+#[derive(Debug, Default, PartialEq)]
+struct Query {
+    direction: Option<Direction>,
+    from: u64,
+    limit: usize,
+    order: Order,
+    /// cursor of `Order::MainPosition`
+    after: Option<MainPosition>,
+    /// unix time `GET /skipped` starts at
+    since: u64,
+}
+
+/// parses a `<block_number>.<transaction_index>.<log_index>` position.
+fn parse_position(value: &str) -> Option<MainPosition> {
+    let parts = value
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    if parts.len() != 3 {
+        return None;
+    }
+    Some(MainPosition {
+        block_number: parts[0],
+        transaction_index: parts[1],
+        log_index: parts[2],
+    })
+}
+
+impl Query {
+    /// parses `direction`, `from`, `limit`, `order`, `after` and `since` of `query`.
+    /// `limit` is at most `page_size`.
+    fn parse(query: &str, page_size: usize) -> Result<Self, String> {
+        let mut parsed = Query {
+            limit: page_size,
+            ..Query::default()
+        };
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let name = parts.next().unwrap_or_default();
+            let value = parts.next().unwrap_or_default();
+            match name {
+                "direction" => {
+                    parsed.direction = match value {
+                        "home_to_foreign" => Some(Direction::HomeToForeign),
+                        "foreign_to_home" => Some(Direction::ForeignToHome),
+                        _ => {
+                            return Err(format!(
+                                "direction must be home_to_foreign or foreign_to_home, got {:?}",
+                                value
+                            ))
+                        }
+                    }
+                }
+                "from" => {
+                    parsed.from = value
+                        .parse()
+                        .map_err(|_| format!("from must be a number, got {:?}", value))?
+                }
+                "limit" => {
+                    parsed.limit = match value.parse() {
+                        Ok(limit) if limit > 0 && limit <= page_size => limit,
+                        _ => {
+                            return Err(format!(
+                                "limit must be in range 1..={}, got {:?}",
+                                page_size, value
+                            ))
+                        }
+                    }
+                }
+                "order" => {
+                    parsed.order = match value {
+                        "sequence" => Order::Sequence,
+                        "main_position" => Order::MainPosition,
+                        _ => {
+                            return Err(format!(
+                                "order must be sequence or main_position, got {:?}",
+                                value
+                            ))
+                        }
+                    }
+                }
+                "after" => {
+                    parsed.after = Some(parse_position(value).ok_or_else(|| {
+                        format!(
+                            "after must be <block_number>.<transaction_index>.<log_index>, got {:?}",
+                            value
+                        )
+                    })?)
+                }
+                "since" => {
+                    parsed.since = parse_time(value).map_err(|_| {
+                        format!(
+                            "since must be a unix time or YYYY-MM-DD[THH:MM[:SS]], got {:?}",
+                            value
+                        )
+                    })?
+                }
+                _ => return Err(format!("unknown parameter {:?}", name)),
+            }
+        }
+        match parsed.order {
+            Order::Sequence if parsed.after.is_some() => {
+                Err("after needs order=main_position, use from".into())
+            }
+            Order::MainPosition if parsed.from != 0 => {
+                Err("from can't be used with order=main_position, use after".into())
+            }
+            Order::MainPosition if parsed.direction == Some(Direction::ForeignToHome) => {
+                Err("order=main_position only orders deposits".into())
+            }
+            _ => Ok(parsed),
+        }
+    }
+}
+
+/// response of the history api to a `method` request of `path` with `query`.
+/// `authorization` is the value of the `Authorization` header of the request, if any.
+///
+/// - `GET /relays?direction=&from=&limit=` pages through the history, see `RelayHistory::page`
+/// - `GET /relays?order=main_position&after=&limit=` pages through the deposits by their
+///   position on home, see `RelayHistory::page_by_main_position`
+/// - `GET /relays/by-main-tx/<hash>` lists the relays of the deposits of a home transaction
+/// - `GET /pending?from=&limit=` pages through the relays observed but not relayed yet,
+///   with the value held by `recipient_policies` of every recipient
+/// - `GET /skipped?since=&from=&limit=` pages through the deposits skipped at or after `since`
+///   with the reason, see `SkippedDeposits`
+/// - `GET /errors` returns the latest failures of every component that failed, see
+///   `Session::recent_errors`
+/// - `GET /buildinfo` returns what the binary was built from, see `BuildInfo`
+///
+/// requests never reach the nodes.
+pub fn respond(
+    relays: &SharedRelays,
+    config: &HistoryApiConfig,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    authorization: Option<&[u8]>,
+) -> Response {
+    if let Some(ref token) = config.token {
+        if !authorization.map_or(false, |value| is_bearer(value, token.as_str())) {
+            return error(StatusCode::Unauthorized, "missing or invalid bearer token");
+        }
+    }
+    if *method != Method::Get {
+        return error(StatusCode::MethodNotAllowed, "only GET requests are served");
+    }
+    let query = match Query::parse(query.unwrap_or_default(), config.page_size) {
+        Ok(query) => query,
+        Err(err) => return error(StatusCode::BadRequest, &err),
+    };
+    if path == "/relays" {
+        let page = relays.read(|history, _| match query.order {
+            Order::Sequence => history.page(query.direction, query.from, query.limit).to_json(),
+            Order::MainPosition => history
+                .page_by_main_position(query.after, query.limit)
+                .to_json(),
+        });
+        return Response::ok(page);
+    }
+    if path.starts_with("/relays/by-main-tx/") {
+        let hash = &path["/relays/by-main-tx/".len()..];
+        let hash: H256 = match hash.trim_left_matches("0x").parse() {
+            Ok(hash) => hash,
+            Err(_) => return error(StatusCode::BadRequest, "invalid transaction hash"),
+        };
+        let records = relays.read(|history, _| history.by_source_transaction(&hash))
+            .into_iter()
+            .filter(|record| record.direction == Direction::HomeToForeign)
+            .collect();
+        return Response::ok(Page { records, next: None }.to_json());
+    }
+    if path == "/pending" {
+        let held = relays.held();
+        let page = relays.read(|_, pending| {
+            let from = query.from as usize;
+            let relays = pending
+                .iter()
+                .skip(from)
+                .take(query.limit)
+                .cloned()
+                .collect::<Vec<_>>();
+            let next = from + relays.len();
+            PendingPage {
+                next: if next < pending.len() { Some(next as u64) } else { None },
+                relays,
+                held,
+            }
+        });
+        return Response::ok(
+            serde_json::to_string(&page).expect("PendingPage always serializes; qed"),
+        );
+    }
+    if path == "/skipped" {
+        let page = relays.read_skipped(|skipped| {
+            let since = skipped
+                .iter()
+                .filter(|skipped| skipped.time >= query.since)
+                .collect::<Vec<_>>();
+            let from = query.from as usize;
+            let page = since
+                .iter()
+                .skip(from)
+                .take(query.limit)
+                .map(|&skipped| skipped.clone())
+                .collect::<Vec<_>>();
+            let next = from + page.len();
+            SkippedPage {
+                next: if next < since.len() { Some(next as u64) } else { None },
+                skipped: page,
+            }
+        });
+        return Response::ok(
+            serde_json::to_string(&page).expect("SkippedPage always serializes; qed"),
+        );
+    }
+    if path == "/errors" {
+        return Response::ok(
+            serde_json::to_string(&relays.errors()).expect("RecentError always serializes; qed"),
+        );
+    }
+    if path == "/buildinfo" {
+        return Response::ok(
+            serde_json::to_string(&BuildInfo::current()).expect("BuildInfo always serializes; qed"),
+        );
+    }
+    error(StatusCode::NotFound, "not found")
+}
+
+fn error(code: StatusCode, message: &str) -> Response {
+    Response {
+        code,
+        content_type: header::ContentType::json(),
+        content: json!({ "error": message }).to_string(),
+    }
+}
+
+/// serves the history api on `config.listen` until the returned server is dropped.
+pub fn serve_history(relays: SharedRelays, config: &HistoryApiConfig) -> Result<Server, Error> {
+    let config = config.clone();
+    let listen = config.listen;
+    ServerBuilder::new(IoHandler::new())
+        .request_middleware(move |request: server::Request| -> RequestMiddlewareAction {
+            let response = respond(
+                &relays,
+                &config,
+                request.method(),
+                request.path(),
+                request.query(),
+                request
+                    .headers()
+                    .get_raw("Authorization")
+                    .and_then(|value| value.one()),
+            );
+            let unauthorized = response.code == StatusCode::Unauthorized;
+            let mut response: server::Response = response.into();
+            if unauthorized {
+                response.headers_mut().set_raw("WWW-Authenticate", "Bearer");
+            }
+            response.into()
+        })
+        .start_http(&listen)
+        .chain_err(|| format!("Cannot serve the relay history on {}", listen))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io::Read;
+    use reqwest;
+    use serde_json;
+    use jsonrpc_http_server::hyper::{Method, StatusCode};
+    use build_info::BuildInfo;
+    use config::{HistoryApiConfig, Password, RecipientPolicy};
+    use database::SkippedDeposit;
+    use bridge::{relay_key, Direction, HeldValue, MainPosition, PendingRelay, RecentError,
+                 RelayRecord, SharedRelays, SkipReason};
+    use super::{respond, serve_history};
+
+    fn record(direction: Direction, source: u64) -> RelayRecord {
+        RelayRecord {
+            direction,
+            key: relay_key(direction, &source.into(), None),
+            source_transaction: source.into(),
+            relay_transaction: (source + 100).into(),
+            block: source,
+            receiver: 0xaa.into(),
+            value: source.into(),
+            memo: None,
+            recipient: None,
+            derived_recipient: None,
+            main_position: None,
+            gas_price: None,
+            timings: None,
+            observed_on: None,
+            submitted_to: None,
+        }
+    }
+
+    fn pending(source: u64) -> PendingRelay {
+        PendingRelay {
+            direction: Direction::HomeToForeign,
+            source_transaction: source.into(),
+            block: source,
+            since: 1000 + source,
+        }
+    }
+
+    fn relays() -> SharedRelays {
+        let relays = SharedRelays::new(10);
+        relays.extend(vec![
+            record(Direction::HomeToForeign, 1),
+            record(Direction::ForeignToHome, 2),
+            record(Direction::HomeToForeign, 3),
+            record(Direction::ForeignToHome, 3),
+        ]);
+        relays.set_pending(vec![pending(4), pending(5), pending(6)]);
+        relays
+    }
+
+    fn config(token: Option<&str>) -> HistoryApiConfig {
+        HistoryApiConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            token_file: None,
+            token: token.map(Password::new),
+            page_size: 2,
+        }
+    }
+
+    fn get(relays: &SharedRelays, path: &str, query: Option<&str>) -> (StatusCode, String) {
+        let response = respond(relays, &config(None), &Method::Get, path, query, None);
+        (response.code, response.content)
+    }
+
+    #[test]
+    fn test_relays_pages() {
+        let relays = relays();
+        let (code, body) = get(&relays, "/relays", None);
+        assert_eq!(StatusCode::Ok, code);
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(2, page["records"].as_array().unwrap().len());
+        assert_eq!(2, page["next"]);
+
+        let (_, body) = get(&relays, "/relays", Some("from=2"));
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(2, page["records"].as_array().unwrap().len());
+        assert!(page["next"].is_null());
+
+        let (_, body) = get(&relays, "/relays", Some("direction=foreign_to_home&limit=1"));
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!("foreign_to_home", page["records"][0]["direction"]);
+        assert_eq!(3, page["next"]);
+        assert_eq!(
+            relays
+                .read(|history, _| history.page(Some(Direction::ForeignToHome), 3, 1))
+                .to_json(),
+            get(&relays, "/relays", Some("direction=foreign_to_home&from=3&limit=1")).1
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let relays = relays();
+        assert_eq!((StatusCode::Ok, "{}".to_owned()), get(&relays, "/errors", None));
+        let error = RecentError {
+            kind: "timeout".into(),
+            error_chain: vec!["Request timed out".into()],
+            time: 1000,
+            transaction: None,
+        };
+        let mut errors = BTreeMap::new();
+        errors.insert("WithdrawRelay".to_owned(), vec![error]);
+        relays.set_errors(errors);
+        let (code, body) = get(&relays, "/errors", None);
+        assert_eq!(StatusCode::Ok, code);
+        assert_eq!(
+            json!({
+                "WithdrawRelay": [{
+                    "kind": "timeout",
+                    "error_chain": ["Request timed out"],
+                    "time": 1000,
+                }],
+            }),
+            serde_json::from_str::<serde_json::Value>(&body).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_relays_by_main_position() {
+        let relays = SharedRelays::new(10);
+        let deposit = |source: u64, block_number: u64, log_index: u64| RelayRecord {
+            main_position: Some(MainPosition {
+                block_number,
+                transaction_index: 0,
+                log_index,
+            }),
+            ..record(Direction::HomeToForeign, source)
+        };
+        // relayed out of order, e.g. the second deposit was deferred
+        relays.extend(vec![
+            deposit(1, 10, 0),
+            deposit(3, 12, 1),
+            record(Direction::ForeignToHome, 4),
+            deposit(2, 11, 5),
+        ]);
+
+        let (code, body) = get(&relays, "/relays", Some("order=main_position"));
+        assert_eq!(StatusCode::Ok, code);
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let blocks = |page: &serde_json::Value| {
+            page["records"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|record| record["block"].as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![1, 2], blocks(&page));
+        assert_eq!(
+            json!({ "block_number": 11, "transaction_index": 0, "log_index": 5 }),
+            page["next"]
+        );
+
+        let (_, body) = get(&relays, "/relays", Some("order=main_position&after=11.0.5"));
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(vec![3], blocks(&page));
+        assert!(page["next"].is_null());
+
+        let invalid = [
+            "order=position",
+            "order=main_position&after=11.0",
+            "order=main_position&after=11.0.x",
+            "order=main_position&from=1",
+            "order=main_position&direction=foreign_to_home",
+            "after=11.0.5",
+        ];
+        for query in &invalid {
+            assert_eq!(StatusCode::BadRequest, get(&relays, "/relays", Some(query)).0);
+        }
+    }
+
+    #[test]
+    fn test_relays_by_main_tx() {
+        let relays = relays();
+        let hash = "0x0000000000000000000000000000000000000000000000000000000000000003";
+        let (code, body) = get(&relays, &format!("/relays/by-main-tx/{}", hash), None);
+        assert_eq!(StatusCode::Ok, code);
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        // the withdraw with the same hash isn't a deposit of the home transaction
+        assert_eq!(1, page["records"].as_array().unwrap().len());
+        assert_eq!("home_to_foreign", page["records"][0]["direction"]);
+        assert_eq!(hash, page["records"][0]["source_transaction"]);
+
+        let (_, body) = get(&relays, &format!("/relays/by-main-tx/{}", &hash[2..]), None);
+        assert_eq!(page, serde_json::from_str::<serde_json::Value>(&body).unwrap());
+        let unknown = "0x0000000000000000000000000000000000000000000000000000000000000009";
+        let (_, body) = get(&relays, &format!("/relays/by-main-tx/{}", unknown), None);
+        assert_eq!(r#"{"records":[],"next":null}"#, body);
+        let (code, _) = get(&relays, "/relays/by-main-tx/0x03", None);
+        assert_eq!(StatusCode::BadRequest, code);
+    }
+
+    #[test]
+    fn test_pending_pages() {
+        let relays = relays();
+        let (code, body) = get(&relays, "/pending", None);
+        assert_eq!(StatusCode::Ok, code);
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(2, page["relays"].as_array().unwrap().len());
+        assert_eq!(1004, page["relays"][0]["since"]);
+        assert_eq!(2, page["next"]);
+        let (_, body) = get(&relays, "/pending", Some("from=2"));
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(1006, page["relays"][0]["since"]);
+        assert!(page["next"].is_null());
+        assert_eq!(json!([]), page["held"]);
+
+        relays.set_held(vec![HeldValue {
+            recipient: 0xc0.into(),
+            policy: RecipientPolicy::Manual,
+            value: 3_000_000_000_000_000_000u64.into(),
+            keys: vec![0x2a.into()],
+        }]);
+        let (_, body) = get(&relays, "/pending", Some("from=2"));
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            json!([{
+                "recipient": "0x00000000000000000000000000000000000000c0",
+                "policy": "manual",
+                "value": "3000000000000000000",
+                "keys": ["0x000000000000000000000000000000000000000000000000000000000000002a"],
+            }]),
+            page["held"]
+        );
+    }
+
+    #[test]
+    fn test_skipped_since() {
+        let relays = relays();
+        let skipped = |home_transaction: u64, time: u64, reason: SkipReason| SkippedDeposit {
+            home_transaction: home_transaction.into(),
+            log_index: Some(0.into()),
+            block: Some(home_transaction),
+            time,
+            reason,
+            message: format!("{:?}", reason),
+            parameters: Default::default(),
+        };
+        relays.set_skipped(vec![
+            skipped(1, 100, SkipReason::ZeroValue),
+            skipped(2, 200, SkipReason::SpendLimit),
+            skipped(3, 300, SkipReason::AlreadyExecuted),
+            skipped(4, 400, SkipReason::Quarantined),
+        ]);
+        let reasons = |body: &str| {
+            let page: serde_json::Value = serde_json::from_str(body).unwrap();
+            let reasons = page["skipped"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|skipped| skipped["reason"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            (reasons, page["next"].clone())
+        };
+
+        let (code, body) = get(&relays, "/skipped", None);
+        assert_eq!(StatusCode::Ok, code);
+        assert_eq!(
+            (vec!["zero_value".to_owned(), "spend_limit".to_owned()], json!(2)),
+            reasons(&body)
+        );
+        let (_, body) = get(&relays, "/skipped", Some("since=200&from=1"));
+        assert_eq!(
+            (vec!["already_executed".to_owned(), "quarantined".to_owned()], json!(null)),
+            reasons(&body)
+        );
+        let (_, body) = get(&relays, "/skipped", Some("since=400"));
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!("Quarantined", page["skipped"][0]["message"]);
+        assert_eq!(
+            "0x0000000000000000000000000000000000000000000000000000000000000004",
+            page["skipped"][0]["home_transaction"]
+        );
+        assert_eq!(
+            StatusCode::BadRequest,
+            get(&relays, "/skipped", Some("since=yesterday")).0
+        );
+    }
+
+    #[test]
+    fn test_buildinfo() {
+        let (code, body) = get(&relays(), "/buildinfo", None);
+        assert_eq!(StatusCode::Ok, code);
+        let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(serde_json::to_value(BuildInfo::current()).unwrap(), info);
+        assert_eq!(env!("CARGO_PKG_VERSION"), info["version"]);
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let relays = relays();
+        for query in &["limit=3", "limit=0", "from=x", "direction=main_to_side", "page=1"] {
+            assert_eq!(StatusCode::BadRequest, get(&relays, "/relays", Some(query)).0);
+        }
+        assert_eq!(StatusCode::NotFound, get(&relays, "/relays/1", None).0);
+        let response = respond(&relays, &config(None), &Method::Post, "/relays", None, None);
+        assert_eq!(StatusCode::MethodNotAllowed, response.code);
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let relays = relays();
+        let config = config(Some("secret"));
+        let request = |authorization: Option<&[u8]>| {
+            respond(&relays, &config, &Method::Get, "/pending", None, authorization).code
+        };
+        assert_eq!(StatusCode::Unauthorized, request(None));
+        assert_eq!(StatusCode::Unauthorized, request(Some(b"Bearer secre")));
+        assert_eq!(StatusCode::Unauthorized, request(Some(b"Bearer secrets")));
+        assert_eq!(StatusCode::Unauthorized, request(Some(b"Basic secret")));
+        assert_eq!(StatusCode::Ok, request(Some(b"Bearer secret")));
+    }
+
+    #[test]
+    fn test_serve_history() {
+        let server = serve_history(relays(), &config(Some("secret"))).unwrap();
+        let url = format!("http://{}/relays?direction=home_to_foreign", server.address());
+        let client = reqwest::Client::new();
+
+        let response = client.get(&url).send().unwrap();
+        assert_eq!(401, response.status().as_u16());
+        assert_eq!("Bearer", response.headers()["WWW-Authenticate"]);
+
+        let mut response = client
+            .get(&url)
+            .header("Authorization", "Bearer secret")
+            .send()
+            .unwrap();
+        assert_eq!(200, response.status().as_u16());
+        let mut body = String::new();
+        response.read_to_string(&mut body).unwrap();
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(2, page["records"].as_array().unwrap().len());
+        assert!(page["next"].is_null());
+    }
+}
@@ -0,0 +1,664 @@
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use serde_json::{self, Value};
+use tiny_keccak::keccak256;
+use web3::Transport;
+use web3::types::{Address, Bytes, H256, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use error::{Error, Result, ResultExt};
+use fetch::{fetch_bounded, FetchBounded, Requests};
+use rlp;
+use trie;
+
+/// Proof that a transaction was included in a block with its receipt,
+/// which only needs to trust the hash of the block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    /// the transaction the proof was made for.
+    /// the proof covers its receipt, not that the receipt belongs to this hash
+    pub transaction_hash: H256,
+    pub block_hash: H256,
+    pub block_number: u64,
+    pub transaction_index: u64,
+    /// rlp of the header of the block. hashes to `block_hash`
+    pub header: Bytes,
+    /// consensus encoding of the receipt
+    pub receipt: Bytes,
+    /// nodes of the receipts trie from `header`'s receipts root to `receipt`, root first
+    pub proof: Vec<Bytes>,
+}
+
+impl InclusionProof {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).chain_err(|| "Invalid inclusion proof")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialization can't fail; qed")
+    }
+}
+
+/// A log of a receipt proven by an `InclusionProof`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProvenLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: Bytes,
+}
+
+/// A receipt proven to be part of the block `block_hash`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProvenReceipt {
+    pub block_hash: H256,
+    pub block_number: u64,
+    pub transaction_index: u64,
+    /// whether the transaction succeeded. `None` on chains before byzantium
+    pub status: Option<bool>,
+    pub cumulative_gas_used: U256,
+    pub logs: Vec<ProvenLog>,
+}
+
+impl ProvenReceipt {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialization can't fail; qed")
+    }
+}
+
+/// `None` if `field` of `json` is missing or `null`.
+fn optional<T: ::serde::de::DeserializeOwned>(json: &Value, field: &str) -> Result<Option<T>> {
+    match json.get(field) {
+        None | Some(&Value::Null) => Ok(None),
+        Some(value) => Ok(Some(serde_json::from_value(value.clone())
+            .chain_err(|| format!("Invalid {} {}", field, value))?)),
+    }
+}
+
+fn required<T: ::serde::de::DeserializeOwned>(json: &Value, field: &str) -> Result<T> {
+    optional(json, field)?.ok_or_else(|| format!("{} is missing", field).into())
+}
+
+fn data(json: &Value, field: &str) -> Result<Vec<u8>> {
+    Ok(required::<Bytes>(json, field)?.0)
+}
+
+fn quantity(json: &Value, field: &str) -> Result<Vec<u8>> {
+    Ok(rlp::encode_u256(required(json, field)?))
+}
+
+/// rlp of the header of the json `block` as returned by parity or geth.
+/// parity returns the seal as already encoded `sealFields`, geth as `mixHash` and `nonce`.
+pub fn header_rlp(block: &Value) -> Result<Vec<u8>> {
+    let mut fields = vec![
+        rlp::encode_bytes(&data(block, "parentHash")?),
+        rlp::encode_bytes(&data(block, "sha3Uncles")?),
+        rlp::encode_bytes(&data(block, "miner")?),
+        rlp::encode_bytes(&data(block, "stateRoot")?),
+        rlp::encode_bytes(&data(block, "transactionsRoot")?),
+        rlp::encode_bytes(&data(block, "receiptsRoot")?),
+        rlp::encode_bytes(&data(block, "logsBloom")?),
+        quantity(block, "difficulty")?,
+        quantity(block, "number")?,
+        quantity(block, "gasLimit")?,
+        quantity(block, "gasUsed")?,
+        quantity(block, "timestamp")?,
+        rlp::encode_bytes(&data(block, "extraData")?),
+    ];
+    let seal_fields = optional::<Vec<Bytes>>(block, "sealFields")?.unwrap_or_default();
+    if seal_fields.is_empty() {
+        fields.push(rlp::encode_bytes(&data(block, "mixHash")?));
+        fields.push(rlp::encode_bytes(&data(block, "nonce")?));
+    } else {
+        fields.extend(seal_fields.into_iter().map(|field| field.0));
+    }
+    if let Some(base_fee) = optional::<U256>(block, "baseFeePerGas")? {
+        fields.push(rlp::encode_u256(base_fee));
+    }
+    Ok(rlp::encode_list(&fields))
+}
+
+/// consensus encoding of the json `receipt`, the value of the receipt in the receipts trie.
+pub fn receipt_rlp(receipt: &Value) -> Result<Vec<u8>> {
+    // byzantium replaced the state root after the transaction by its status
+    let outcome = match optional::<U256>(receipt, "status")? {
+        Some(status) => rlp::encode_u256(status),
+        None => rlp::encode_bytes(&data(receipt, "root")?),
+    };
+    let logs = required::<Vec<Value>>(receipt, "logs")?
+        .iter()
+        .map(|log| {
+            let topics = required::<Vec<H256>>(log, "topics")?
+                .iter()
+                .map(|topic| rlp::encode_bytes(&topic.0))
+                .collect::<Vec<_>>();
+            Ok(rlp::encode_list(&[
+                rlp::encode_bytes(&data(log, "address")?),
+                rlp::encode_list(&topics),
+                rlp::encode_bytes(&data(log, "data")?),
+            ]))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let encoded = rlp::encode_list(&[
+        outcome,
+        quantity(receipt, "cumulativeGasUsed")?,
+        rlp::encode_bytes(&data(receipt, "logsBloom")?),
+        rlp::encode_list(&logs),
+    ]);
+    // receipts of typed transactions are prefixed with the type
+    match optional::<U256>(receipt, "type")? {
+        Some(kind) if !kind.is_zero() => {
+            let mut typed = vec![kind.low_u64() as u8];
+            typed.extend(encoded);
+            Ok(typed)
+        }
+        _ => Ok(encoded),
+    }
+}
+
+/// proves the inclusion of `transaction` in the json `block` with all its `receipts` in order.
+pub fn inclusion_proof(
+    block: &Value,
+    receipts: &[Value],
+    transaction: H256,
+) -> Result<InclusionProof> {
+    let header = header_rlp(block)?;
+    let block_hash: H256 = required(block, "hash")?;
+    if H256::from(keccak256(&header)) != block_hash {
+        bail!(
+            "the header of block {:?} doesn't hash to its hash, the block format isn't supported",
+            block_hash
+        );
+    }
+    let mut index = None;
+    let mut encoded = Vec::with_capacity(receipts.len());
+    for (position, receipt) in receipts.iter().enumerate() {
+        let transaction_index = required::<U256>(receipt, "transactionIndex")?;
+        if transaction_index != position.into() {
+            bail!(
+                "receipt {} of block {:?} has transaction index {}",
+                position,
+                block_hash,
+                transaction_index
+            );
+        }
+        if required::<H256>(receipt, "transactionHash")? == transaction {
+            index = Some(position);
+        }
+        encoded.push(receipt_rlp(receipt)?);
+    }
+    let index =
+        index.ok_or_else(|| format!("block {:?} has no receipt of {:?}", block_hash, transaction))?;
+    let items = trie::ordered_items(encoded);
+    let receipts_root: H256 = required(block, "receiptsRoot")?;
+    if trie::root(&items) != receipts_root {
+        bail!(
+            "the receipts of block {:?} don't match its receipts root {:?}",
+            block_hash,
+            receipts_root
+        );
+    }
+    let key = rlp::encode_u64(index as u64);
+    Ok(InclusionProof {
+        transaction_hash: transaction,
+        block_hash,
+        block_number: required::<U256>(block, "number")?.low_u64(),
+        transaction_index: index as u64,
+        header: header.into(),
+        receipt: items[index].1.clone().into(),
+        proof: trie::prove(&items, &key)
+            .into_iter()
+            .map(Bytes::from)
+            .collect(),
+    })
+}
+
+fn decode_receipt(encoded: &[u8]) -> Result<(Option<bool>, U256, Vec<ProvenLog>)> {
+    // a typed receipt starts with its type instead of a list
+    let encoded = match encoded.first() {
+        Some(&kind) if kind < 0x80 => &encoded[1..],
+        _ => encoded,
+    };
+    let fields = rlp::decode_list(encoded)?;
+    if fields.len() != 4 {
+        bail!("receipt has {} fields", fields.len());
+    }
+    let outcome = rlp::decode_bytes(fields[0])?;
+    let status = if outcome.len() == 32 {
+        None
+    } else {
+        Some(!rlp::decode_u256(fields[0])?.is_zero())
+    };
+    let cumulative_gas_used = rlp::decode_u256(fields[1])?;
+    let logs = rlp::decode_list(fields[3])?
+        .into_iter()
+        .map(|log| {
+            let log = rlp::decode_list(log)?;
+            if log.len() != 3 {
+                bail!("log has {} fields", log.len());
+            }
+            let address = rlp::decode_bytes(log[0])?;
+            if address.len() != 20 {
+                bail!("log address has {} bytes", address.len());
+            }
+            let topics = rlp::decode_list(log[1])?
+                .into_iter()
+                .map(|topic| {
+                    let topic = rlp::decode_bytes(topic)?;
+                    if topic.len() != 32 {
+                        bail!("log topic has {} bytes", topic.len());
+                    }
+                    Ok(H256::from_slice(topic))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(ProvenLog {
+                address: Address::from_slice(address),
+                topics,
+                data: rlp::decode_bytes(log[2])?.to_vec().into(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((status, cumulative_gas_used, logs))
+}
+
+/// checks `proof` against its block hash and returns the proven receipt.
+/// fails if any part of the proof was modified.
+pub fn verify_inclusion_proof(proof: &InclusionProof) -> Result<ProvenReceipt> {
+    if H256::from(keccak256(&proof.header.0)) != proof.block_hash {
+        bail!("the header doesn't hash to block {:?}", proof.block_hash);
+    }
+    let header = rlp::decode_list(&proof.header.0).chain_err(|| "Invalid header")?;
+    if header.len() < 15 {
+        bail!("the header has {} fields", header.len());
+    }
+    let receipts_root = rlp::decode_bytes(header[5])?;
+    if receipts_root.len() != 32 {
+        bail!("the receipts root has {} bytes", receipts_root.len());
+    }
+    let number = rlp::decode_u256(header[8])?;
+    if number != proof.block_number.into() {
+        bail!(
+            "the header is of block {}, not {}",
+            number,
+            proof.block_number
+        );
+    }
+    let nodes = proof.proof.iter().map(|node| node.0.clone()).collect::<Vec<_>>();
+    let key = rlp::encode_u64(proof.transaction_index);
+    let receipt = trie::verify_proof(H256::from_slice(receipts_root), &key, &nodes)
+        .chain_err(|| "Invalid receipt proof")?
+        .ok_or_else(|| {
+            format!(
+                "block {:?} has no transaction {}",
+                proof.block_hash, proof.transaction_index
+            )
+        })?;
+    if receipt != proof.receipt.0 {
+        bail!(
+            "the receipt of transaction {} differs from the proven receipt",
+            proof.transaction_index
+        );
+    }
+    let (status, cumulative_gas_used, logs) =
+        decode_receipt(&receipt).chain_err(|| "Invalid receipt")?;
+    Ok(ProvenReceipt {
+        block_hash: proof.block_hash,
+        block_number: proof.block_number,
+        transaction_index: proof.transaction_index,
+        status,
+        cumulative_gas_used,
+        logs,
+    })
+}
+
+type JsonFuture<T> = Timeout<ApiCall<Option<Value>, <T as Transport>::Out>>;
+
+enum ProveInclusionState<T: Transport> {
+    /// Fetching the receipt of the transaction for its block.
+    FetchReceipt(JsonFuture<T>),
+    /// Fetching the block with the hashes of its transactions.
+    FetchBlock(JsonFuture<T>),
+    /// Fetching the receipts of all transactions of the block.
+    FetchReceipts {
+        block: Value,
+        future: FetchBounded<ReceiptRequests<T>>,
+    },
+}
+
+/// `eth_getTransactionReceipt` requests for home transactions, answered as json.
+struct ReceiptRequests<T: Transport> {
+    app: Arc<App<T>>,
+    transactions: Vec<H256>,
+}
+
+impl<T: Transport> Requests for ReceiptRequests<T> {
+    type Item = Option<Value>;
+    type Future = JsonFuture<T>;
+
+    fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    fn start(&self, index: usize) -> Self::Future {
+        api::timeout(
+            &self.app.timer,
+            api::raw_transaction_receipt(&self.app.connections.home, self.transactions[index]),
+            "home",
+            self.app.config.home.request_timeout,
+        ).about(self.transactions[index])
+    }
+}
+
+/// proves the inclusion of the home transaction `transaction`, e.g. a withdraw relay.
+pub fn create_prove_inclusion<T: Transport>(
+    app: Arc<App<T>>,
+    transaction: H256,
+) -> ProveInclusion<T> {
+    let state = ProveInclusionState::FetchReceipt(api::timeout(
+        &app.timer,
+        api::raw_transaction_receipt(&app.connections.home, transaction),
+        "home",
+        app.config.home.request_timeout,
+    ).about(transaction));
+    ProveInclusion {
+        app,
+        transaction,
+        state,
+    }
+}
+
+/// Future resolving to the `InclusionProof` of a home transaction,
+/// built from its block and the receipts of all transactions in the block.
+pub struct ProveInclusion<T: Transport> {
+    app: Arc<App<T>>,
+    transaction: H256,
+    state: ProveInclusionState<T>,
+}
+
+impl<T: Transport> Future for ProveInclusion<T> {
+    type Item = InclusionProof;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                ProveInclusionState::FetchReceipt(ref mut future) => {
+                    let receipt = try_ready!(future.poll())
+                        .ok_or_else(|| format!("transaction {:?} isn't mined", self.transaction))?;
+                    let block_hash: H256 = required(&receipt, "blockHash")?;
+                    ProveInclusionState::FetchBlock(api::timeout(
+                        &self.app.timer,
+                        api::raw_block_by_hash(&self.app.connections.home, block_hash),
+                        "home",
+                        self.app.config.home.request_timeout,
+                    ).about(block_hash))
+                }
+                ProveInclusionState::FetchBlock(ref mut future) => {
+                    let block = try_ready!(future.poll()).ok_or_else(|| {
+                        format!("the block of transaction {:?} is unknown", self.transaction)
+                    })?;
+                    let receipts = ReceiptRequests {
+                        app: self.app.clone(),
+                        transactions: required(&block, "transactions")?,
+                    };
+                    let config = &self.app.config;
+                    ProveInclusionState::FetchReceipts {
+                        block,
+                        future: fetch_bounded(
+                            receipts,
+                            config.scan_concurrency,
+                            config.scan_retries,
+                        ),
+                    }
+                }
+                ProveInclusionState::FetchReceipts {
+                    ref block,
+                    ref mut future,
+                } => {
+                    let receipts = try_ready!(future.poll())
+                        .into_iter()
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or("a transaction of the block has no receipt, the block was reorged")?;
+                    return Ok(Async::Ready(inclusion_proof(
+                        block,
+                        &receipts,
+                        self.transaction,
+                    )?));
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tiny_keccak::keccak256;
+    use web3::types::{Bytes, H256};
+    use rlp;
+    use trie;
+    use super::{header_rlp, inclusion_proof, receipt_rlp, verify_inclusion_proof as verify,
+                InclusionProof};
+
+    fn hash(s: &str) -> H256 {
+        s.parse().unwrap()
+    }
+
+    /// the genesis block of the ethereum mainnet as returned by geth
+    fn mainnet_genesis() -> Value {
+        let empty_root = trie::root(&[]);
+        json!({
+            "hash": "0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3",
+            "parentHash": H256::zero(),
+            "sha3Uncles": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "miner": "0x0000000000000000000000000000000000000000",
+            "stateRoot": "0xd7f8974fb5ac78d9ac099b9ad5018bedc2ce0a72dad1827a1709da30580f0544",
+            "transactionsRoot": empty_root,
+            "receiptsRoot": empty_root,
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "difficulty": "0x400000000",
+            "number": "0x0",
+            "gasLimit": "0x1388",
+            "gasUsed": "0x0",
+            "timestamp": "0x0",
+            "extraData": "0x11bbe8db4e347b4e8c937c1c8370e4b5ed33adb3db69cbdb7a38e1e50b1b82fa",
+            "mixHash": H256::zero(),
+            "nonce": "0x0000000000000042",
+            "transactions": []
+        })
+    }
+
+    fn receipt(index: u64, status: u64) -> Value {
+        json!({
+            "transactionHash": H256::from(100 + index),
+            "transactionIndex": format!("0x{:x}", index),
+            "blockHash": H256::zero(),
+            "cumulativeGasUsed": format!("0x{:x}", 21000 * (index + 1)),
+            "gasUsed": "0x5208",
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "status": format!("0x{:x}", status),
+            "logs": [{
+                "address": "0x00000000000000000000000000000000000000aa",
+                "topics": [H256::from(index), H256::from(7)],
+                "data": format!("0x{:064x}", index)
+            }]
+        })
+    }
+
+    /// a parity aura block with `receipts`, hashing to its `hash`.
+    fn parity_block(receipts: &[Value]) -> Value {
+        let encoded = receipts.iter().map(|r| receipt_rlp(r).unwrap()).collect();
+        let mut block = json!({
+            "parentHash": H256::from(1),
+            "sha3Uncles": "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+            "miner": "0x00000000000000000000000000000000000000bb",
+            "author": "0x00000000000000000000000000000000000000bb",
+            "stateRoot": H256::from(2),
+            "transactionsRoot": H256::from(3),
+            "receiptsRoot": trie::root(&trie::ordered_items(encoded)),
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "difficulty": "0xfffffffffffffffffffffffffffffffe",
+            "number": "0x1e240",
+            "gasLimit": "0x7a1200",
+            "gasUsed": format!("0x{:x}", 21000 * receipts.len()),
+            "timestamp": "0x5a0b1bfa",
+            "extraData": "0xd583010a008650617269747986312e32322e31826c69",
+            "sealFields": [
+                "0x8412f5e3c8",
+                format!("0xb841{}", "ab".repeat(65))
+            ],
+            "transactions": receipts
+                .iter()
+                .map(|r| r["transactionHash"].clone())
+                .collect::<Vec<_>>()
+        });
+        let hash = H256::from(keccak256(&header_rlp(&block).unwrap()));
+        block["hash"] = json!(hash);
+        block
+    }
+
+    #[test]
+    fn test_header_rlp_of_mainnet_genesis() {
+        let genesis = mainnet_genesis();
+        let header = header_rlp(&genesis).unwrap();
+        assert_eq!(
+            hash("d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"),
+            H256::from(keccak256(&header))
+        );
+        assert_eq!(15, rlp::decode_list(&header).unwrap().len());
+
+        let mut missing = genesis.clone();
+        missing.as_object_mut().unwrap().remove("stateRoot");
+        assert!(header_rlp(&missing).is_err());
+    }
+
+    #[test]
+    fn test_header_rlp_with_seal_fields() {
+        let block = parity_block(&[receipt(0, 1)]);
+        let header = header_rlp(&block).unwrap();
+        let fields = rlp::decode_list(&header).unwrap();
+        // the seal fields are already encoded
+        assert_eq!(15, fields.len());
+        assert_eq!(&[0x84, 0x12, 0xf5, 0xe3, 0xc8][..], fields[13]);
+        assert_eq!(67, fields[14].len());
+    }
+
+    #[test]
+    fn test_receipt_rlp() {
+        let encoded = receipt_rlp(&receipt(1, 1)).unwrap();
+        let fields = rlp::decode_list(&encoded).unwrap();
+        assert_eq!(4, fields.len());
+        assert_eq!(&[0x01][..], fields[0]);
+        assert_eq!(42000u64, rlp::decode_u256(fields[1]).unwrap().low_u64());
+        assert_eq!(1, rlp::decode_list(fields[3]).unwrap().len());
+
+        // a reverted transaction has an empty status
+        let reverted = receipt_rlp(&receipt(1, 0)).unwrap();
+        assert_eq!(&[0x80][..], rlp::decode_list(&reverted).unwrap()[0]);
+
+        // before byzantium receipts have the state root after the transaction
+        let mut before = receipt(1, 0);
+        before.as_object_mut().unwrap().remove("status");
+        before["root"] = json!(H256::from(9));
+        let fields = rlp::decode_list(&receipt_rlp(&before).unwrap()).unwrap();
+        assert_eq!(33, fields[0].len());
+
+        let mut typed = receipt(1, 1);
+        typed["type"] = json!("0x2");
+        let encoded_typed = receipt_rlp(&typed).unwrap();
+        assert_eq!(0x02, encoded_typed[0]);
+        assert_eq!(encoded, encoded_typed[1..].to_vec());
+    }
+
+    #[test]
+    fn test_prove_and_verify() {
+        let receipts = (0..20).map(|index| receipt(index, index % 2)).collect::<Vec<_>>();
+        let block = parity_block(&receipts);
+        for index in &[0u64, 1, 7, 19] {
+            let proof = inclusion_proof(&block, &receipts, H256::from(100 + index)).unwrap();
+            assert_eq!(*index, proof.transaction_index);
+            assert_eq!(123456, proof.block_number);
+
+            // the proof survives json
+            let proof = InclusionProof::from_json(&proof.to_json()).unwrap();
+            let proven = verify(&proof).unwrap();
+            assert_eq!(proof.block_hash, proven.block_hash);
+            assert_eq!(Some(index % 2 == 1), proven.status);
+            assert_eq!((21000 * (index + 1)), proven.cumulative_gas_used.low_u64());
+            assert_eq!(1, proven.logs.len());
+            assert_eq!(vec![H256::from(*index), H256::from(7)], proven.logs[0].topics);
+            assert_eq!(32, proven.logs[0].data.0.len());
+        }
+
+        let genesis = mainnet_genesis();
+        assert!(inclusion_proof(&genesis, &[], H256::from(100)).is_err());
+    }
+
+    #[test]
+    fn test_prove_rejects_inconsistent_blocks() {
+        let receipts = (0..3).map(|index| receipt(index, 1)).collect::<Vec<_>>();
+        let block = parity_block(&receipts);
+        // not in the block
+        assert!(inclusion_proof(&block, &receipts, H256::from(99)).is_err());
+        // a receipt the block doesn't commit to
+        let mut other = receipts.clone();
+        other[1]["status"] = json!("0x0");
+        assert!(inclusion_proof(&block, &other, H256::from(100)).is_err());
+        // receipts out of order
+        let mut swapped = receipts.clone();
+        swapped.swap(0, 1);
+        assert!(inclusion_proof(&block, &swapped, H256::from(100)).is_err());
+        // a block format whose header can't be reproduced
+        let mut unknown = block.clone();
+        unknown["extraData"] = json!("0x00");
+        assert!(inclusion_proof(&unknown, &receipts, H256::from(100)).is_err());
+    }
+
+    #[test]
+    fn test_verify_rejects_tampering() {
+        let receipts = (0..20).map(|index| receipt(index, 1)).collect::<Vec<_>>();
+        let block = parity_block(&receipts);
+        let proof = inclusion_proof(&block, &receipts, H256::from(105)).unwrap();
+        assert!(verify(&proof).is_ok());
+
+        let mut tampered = proof.clone();
+        tampered.block_hash = H256::from(1);
+        assert!(verify(&tampered).is_err());
+
+        let mut tampered = proof.clone();
+        tampered.block_number += 1;
+        assert!(verify(&tampered).is_err());
+
+        let mut tampered = proof.clone();
+        tampered.transaction_index = 6;
+        assert!(verify(&tampered).is_err());
+
+        // another receipt of the block
+        let mut tampered = proof.clone();
+        tampered.receipt = receipt_rlp(&receipts[6]).unwrap().into();
+        assert!(verify(&tampered).is_err());
+
+        let mut tampered = proof.clone();
+        let last = tampered.receipt.0.len() - 1;
+        tampered.receipt.0[last] ^= 1;
+        assert!(verify(&tampered).is_err());
+
+        let mut tampered = proof.clone();
+        let last = tampered.header.0.len() - 1;
+        tampered.header.0[last] ^= 1;
+        assert!(verify(&tampered).is_err());
+
+        for node in 0..proof.proof.len() {
+            let mut tampered = proof.clone();
+            tampered.proof[node].0[3] ^= 1;
+            assert!(verify(&tampered).is_err(), "node {}", node);
+        }
+        let mut truncated = proof.clone();
+        truncated.proof.pop();
+        assert!(verify(&truncated).is_err());
+
+        let mut empty = proof.clone();
+        empty.proof = vec![Bytes::default()];
+        assert!(verify(&empty).is_err());
+    }
+}
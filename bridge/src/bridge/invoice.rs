@@ -0,0 +1,560 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json;
+use web3::types::{H256, U256};
+use database::ObservedDay;
+use error::{Result, ResultExt};
+use units::{checked_add, checked_mul, format_decimal, format_ether, parse_decimal};
+use super::history::Direction;
+
+/// seconds in a day
+const DAY: u64 = 86_400;
+
+/// decimals of prices and amounts in the reference currency
+pub const PRICE_DECIMALS: usize = 6;
+
+/// Chain a transaction was sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Chain {
+    Home,
+    Foreign,
+}
+
+/// Gas spent by a transaction of the bridge. gas accounting records are one json object per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GasRecord {
+    pub direction: Direction,
+    pub chain: Chain,
+    pub transaction: H256,
+    /// unix time the transaction was sent, in seconds
+    pub time: u64,
+    /// `None` if not recorded, e.g. for relays sent before gas accounting
+    #[serde(default)]
+    pub gas_used: Option<U256>,
+    /// in wei. `None` if not recorded
+    #[serde(default)]
+    pub gas_price: Option<U256>,
+}
+
+/// parses gas accounting records, one per line.
+pub fn read_gas_records(records: &str) -> Result<Vec<GasRecord>> {
+    records
+        .lines()
+        .enumerate()
+        .filter(|&(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).chain_err(|| format!("Invalid gas record in line {}", i + 1))
+        })
+        .collect()
+}
+
+/// days since 1970-01-01 of the date `year`-`month`-`day` of the gregorian calendar.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// date of the gregorian calendar `days` after 1970-01-01.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// A day in utc, as days since 1970-01-01.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Day(pub u64);
+
+impl Day {
+    /// parses `YYYY-MM-DD`.
+    pub fn parse(date: &str) -> Result<Self> {
+        let parts = date.trim()
+            .split('-')
+            .map(|part| part.parse::<u64>())
+            .collect::<::std::result::Result<Vec<_>, _>>()
+            .chain_err(|| format!("Invalid date {}, expected YYYY-MM-DD", date))?;
+        if parts.len() != 3 || parts[0] < 1970 || parts[1] == 0 || parts[2] == 0 {
+            bail!("Invalid date {}, expected YYYY-MM-DD", date);
+        }
+        let day = Day(days_from_civil(parts[0], parts[1], parts[2]));
+        // rejects days like 2024-02-30
+        if civil_from_days(day.0) != (parts[0], parts[1], parts[2]) {
+            bail!("Invalid date {}", date);
+        }
+        Ok(day)
+    }
+
+    /// day of the unix time `time` in seconds.
+    pub fn of(time: u64) -> Self {
+        Day(time / DAY)
+    }
+}
+
+impl fmt::Display for Day {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day) = civil_from_days(self.0);
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+impl Serialize for Day {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Day {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        let date = String::deserialize(deserializer)?;
+        Day::parse(&date).map_err(|err| ::serde::de::Error::custom(err.to_string()))
+    }
+}
+
+/// parses a decimal price, e.g. `2301.55`, into units of `10^-PRICE_DECIMALS`.
+fn parse_price(price: &str) -> Result<U256> {
+    let price = price.trim();
+    match parse_decimal(price, PRICE_DECIMALS) {
+        Some(price) => Ok(price),
+        None => bail!(
+            "Invalid price {}, expected a decimal with at most {} decimals",
+            price,
+            PRICE_DECIMALS
+        ),
+    }
+}
+
+/// Reference price of a whole coin, `10^18` wei, of each chain by day.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Prices {
+    /// day -> (home price, foreign price) in units of `10^-PRICE_DECIMALS`
+    prices: BTreeMap<Day, (U256, U256)>,
+}
+
+impl Prices {
+    /// parses lines of `date,home price,foreign price`, e.g. `2024-01-01,2301.55,0.98`.
+    /// the foreign price defaults to the home price if omitted.
+    /// a header line and lines starting with `#` are skipped.
+    pub fn from_csv(csv: &str) -> Result<Self> {
+        let mut prices = BTreeMap::new();
+        for (i, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || (i == 0 && line.starts_with("date")) {
+                continue;
+            }
+            let columns = line.split(',').collect::<Vec<_>>();
+            let parse = || -> Result<(Day, (U256, U256))> {
+                if columns.len() < 2 || columns.len() > 3 {
+                    bail!("expected date,home price[,foreign price]");
+                }
+                let home = parse_price(columns[1])?;
+                let foreign = match columns.get(2) {
+                    Some(price) => parse_price(price)?,
+                    None => home,
+                };
+                Ok((Day::parse(columns[0])?, (home, foreign)))
+            };
+            let (day, price) = parse().chain_err(|| format!("Invalid price in line {}", i + 1))?;
+            prices.insert(day, price);
+        }
+        Ok(Prices { prices })
+    }
+
+    /// price of a coin of `chain` on `day`.
+    pub fn price(&self, day: Day, chain: Chain) -> Option<U256> {
+        self.prices.get(&day).map(|&(home, foreign)| match chain {
+            Chain::Home => home,
+            Chain::Foreign => foreign,
+        })
+    }
+}
+
+/// Gas spent by the transactions of one direction on one chain in one day.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InvoiceLine {
+    pub date: String,
+    pub direction: Direction,
+    pub chain: Chain,
+    /// transactions with gas records
+    pub transactions: u64,
+    pub gas_used: U256,
+    /// `gas_used` times the gas price of every transaction
+    pub wei: U256,
+    /// price of a coin of `chain` that day. `None` if the price file has none
+    pub price: Option<String>,
+    /// `wei` at `price`. `None` without price
+    pub amount: Option<String>,
+}
+
+/// Totals of a direction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InvoiceTotal {
+    pub direction: Direction,
+    /// all transactions, also those without gas records
+    pub transactions: u64,
+    pub home_wei: U256,
+    pub foreign_wei: U256,
+    /// amount of the lines with a price
+    pub amount: String,
+}
+
+/// Deposit or withdraw events observed in a billing period, relayed by this authority or not.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InvoiceTraffic {
+    pub direction: Direction,
+    pub events: u64,
+    /// value of the events, in wei of the chain that emitted them
+    pub value: U256,
+}
+
+/// Relay costs of a billing period in wei and in the reference currency.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Invoice {
+    /// first day of the period
+    pub from: String,
+    /// first day after the period
+    pub to: String,
+    pub lines: Vec<InvoiceLine>,
+    pub totals: Vec<InvoiceTotal>,
+    /// amount of all lines with a price
+    pub amount: String,
+    /// transactions of the period whose gas wasn't recorded. not billed
+    pub missing_gas_records: Vec<GasRecord>,
+    /// days with gas spent but without price. their lines have no amount
+    pub missing_prices: Vec<String>,
+    /// traffic of the bridge in the period, from the observed traffic of the database.
+    /// empty without database
+    pub observed: Vec<InvoiceTraffic>,
+}
+
+impl Invoice {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("invoice serializes to json; qed")
+    }
+}
+
+impl fmt::Display for Invoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "invoice from {} until {}", self.from, self.to)?;
+        for total in &self.totals {
+            writeln!(
+                f,
+                "{:?}: {} transactions, {} on home, {} wei on foreign, amount {}",
+                total.direction,
+                total.transactions,
+                format_ether(total.home_wei, 0),
+                total.foreign_wei,
+                total.amount
+            )?;
+        }
+        write!(f, "total amount: {}", self.amount)?;
+        for record in &self.missing_gas_records {
+            write!(
+                f,
+                "\nno gas recorded for {:?} transaction {:?} on {}",
+                record.chain,
+                record.transaction,
+                Day::of(record.time)
+            )?;
+        }
+        for date in &self.missing_prices {
+            write!(f, "\nno price on {}, its gas is not billed", date)?;
+        }
+        for traffic in &self.observed {
+            write!(
+                f,
+                "\nobserved {:?}: {} events, {} wei",
+                traffic.direction, traffic.events, traffic.value
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// bills the gas of `records` sent from day `from` until before day `to` at `prices`
+/// and sums the `observed` traffic of the period.
+/// records outside the period are ignored, records without gas used or gas price are listed.
+/// fails if a sum or product of the records doesn't fit `U256`, e.g. for a bogus gas price.
+pub fn invoice(
+    records: &[GasRecord],
+    prices: &Prices,
+    observed: &[ObservedDay],
+    from: Day,
+    to: Day,
+) -> Result<Invoice> {
+    if from >= to {
+        bail!("the billing period from {} until {} is empty", from, to);
+    }
+
+    // (day, direction, chain) -> (transactions, gas used, wei)
+    let mut spent = BTreeMap::new();
+    let mut transactions = BTreeMap::new();
+    let mut missing_gas_records = Vec::new();
+    for record in records {
+        let day = Day::of(record.time);
+        if day < from || day >= to {
+            continue;
+        }
+        *transactions.entry(record.direction).or_insert(0u64) += 1;
+        let (gas_used, gas_price) = match (record.gas_used, record.gas_price) {
+            (Some(gas_used), Some(gas_price)) => (gas_used, gas_price),
+            _ => {
+                missing_gas_records.push(record.clone());
+                continue;
+            }
+        };
+        let overflow = || {
+            format!(
+                "gas of {:?} transaction {:?} on {} overflows",
+                record.chain, record.transaction, day
+            )
+        };
+        let wei = checked_mul(gas_used, gas_price).ok_or_else(&overflow)?;
+        let entry = spent
+            .entry((day, record.direction, record.chain))
+            .or_insert((0u64, U256::zero(), U256::zero()));
+        entry.0 += 1;
+        entry.1 = checked_add(entry.1, gas_used).ok_or_else(&overflow)?;
+        entry.2 = checked_add(entry.2, wei).ok_or_else(&overflow)?;
+    }
+
+    let wei_per_coin = U256::from(1_000_000_000_000_000_000u64);
+    let mut lines = Vec::new();
+    let mut missing_prices = Vec::new();
+    let mut totals = BTreeMap::new();
+    let mut amount = U256::zero();
+    for ((day, direction, chain), (count, gas_used, wei)) in spent {
+        let price = prices.price(day, chain);
+        let line_amount = match price {
+            Some(price) => Some(
+                checked_mul(wei, price)
+                    .ok_or_else(|| format!("amount of the gas spent on {} overflows", day))?
+                    / wei_per_coin,
+            ),
+            None => None,
+        };
+        if price.is_none() && !missing_prices.contains(&day.to_string()) {
+            missing_prices.push(day.to_string());
+        }
+        let total = totals
+            .entry(direction)
+            .or_insert((U256::zero(), U256::zero(), U256::zero()));
+        let overflow = || format!("total of {:?} overflows", direction);
+        match chain {
+            Chain::Home => total.0 = checked_add(total.0, wei).ok_or_else(&overflow)?,
+            Chain::Foreign => total.1 = checked_add(total.1, wei).ok_or_else(&overflow)?,
+        }
+        if let Some(line_amount) = line_amount {
+            total.2 = checked_add(total.2, line_amount).ok_or_else(&overflow)?;
+            amount = checked_add(amount, line_amount).ok_or_else(&overflow)?;
+        }
+        lines.push(InvoiceLine {
+            date: day.to_string(),
+            direction,
+            chain,
+            transactions: count,
+            gas_used,
+            wei,
+            price: price.map(|price| format_decimal(price, PRICE_DECIMALS)),
+            amount: line_amount.map(|amount| format_decimal(amount, PRICE_DECIMALS)),
+        });
+    }
+
+    let totals = transactions
+        .into_iter()
+        .map(|(direction, transactions)| {
+            let (home_wei, foreign_wei, amount) = totals
+                .get(&direction)
+                .cloned()
+                .unwrap_or_default();
+            InvoiceTotal {
+                direction,
+                transactions,
+                home_wei,
+                foreign_wei,
+                amount: format_decimal(amount, PRICE_DECIMALS),
+            }
+        })
+        .collect();
+
+    let mut traffic = BTreeMap::new();
+    for day in observed {
+        if day.day < from || day.day >= to {
+            continue;
+        }
+        let entry = traffic
+            .entry(day.direction)
+            .or_insert((0u64, U256::zero()));
+        entry.0 += day.events;
+        entry.1 = checked_add(entry.1, day.value)
+            .ok_or_else(|| format!("observed value of {:?} overflows", day.direction))?;
+    }
+    let observed = traffic
+        .into_iter()
+        .map(|(direction, (events, value))| InvoiceTraffic {
+            direction,
+            events,
+            value,
+        })
+        .collect();
+
+    Ok(Invoice {
+        from: from.to_string(),
+        to: to.to_string(),
+        lines,
+        totals,
+        amount: format_decimal(amount, PRICE_DECIMALS),
+        missing_gas_records,
+        missing_prices,
+        observed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, Value};
+    use web3::types::{H256, U256};
+    use bridge::Direction;
+    use database::ObservedDay;
+    use super::{format_decimal, invoice, parse_price, read_gas_records, Chain, Day, Prices};
+
+    #[test]
+    fn test_day() {
+        assert_eq!(Day(0), Day::parse("1970-01-01").unwrap());
+        assert_eq!(Day(19_723), Day::parse("2024-01-01").unwrap());
+        assert_eq!("2024-02-29", Day::parse("2024-02-29").unwrap().to_string());
+        assert_eq!("2024-02-29", Day::of(1_709_251_199).to_string());
+        assert!(Day::parse("2023-02-29").is_err());
+        assert!(Day::parse("2024-13-01").is_err());
+        assert!(Day::parse("2024-01-00").is_err());
+        assert!(Day::parse("01/02/2024").is_err());
+    }
+
+    #[test]
+    fn test_price() {
+        assert_eq!(U256::from(2_301_550_000u64), parse_price("2301.55").unwrap());
+        assert_eq!(U256::from(3_000_000), parse_price("3").unwrap());
+        assert!(parse_price("1.0000001").is_err());
+        assert!(parse_price("-1").is_err());
+        assert!(parse_price(".5").is_err());
+        assert_eq!("0.000042", format_decimal(42.into(), 6));
+        assert_eq!("2301.550000", format_decimal(2_301_550_000u64.into(), 6));
+    }
+
+    #[test]
+    fn test_invoice() {
+        let records = read_gas_records(
+            r#"
+{"direction":"home_to_foreign","chain":"foreign","transaction":"0x0000000000000000000000000000000000000000000000000000000000000001","time":1704067200,"gas_used":"0x5208","gas_price":"0x3b9aca00"}
+{"direction":"home_to_foreign","chain":"foreign","transaction":"0x0000000000000000000000000000000000000000000000000000000000000002","time":1704153599,"gas_used":"0x5208","gas_price":"0x3b9aca00"}
+{"direction":"foreign_to_home","chain":"home","transaction":"0x0000000000000000000000000000000000000000000000000000000000000003","time":1704153600,"gas_used":"0x186a0","gas_price":"0x4a817c800"}
+{"direction":"foreign_to_home","chain":"home","transaction":"0x0000000000000000000000000000000000000000000000000000000000000004","time":1704153600}
+{"direction":"foreign_to_home","chain":"home","transaction":"0x0000000000000000000000000000000000000000000000000000000000000005","time":1706745600,"gas_used":"0x5208","gas_price":"0x1"}
+"#,
+        ).unwrap();
+        let prices = Prices::from_csv("date,home,foreign\n2024-01-01,2000,0.5\n").unwrap();
+        let observed_day = |day: &str, direction, events, value: u64| ObservedDay {
+            day: Day::parse(day).unwrap(),
+            direction,
+            events,
+            value: value.into(),
+            counted: Vec::new(),
+        };
+        let observed = vec![
+            observed_day("2024-01-01", Direction::HomeToForeign, 3, 300),
+            observed_day("2024-01-02", Direction::HomeToForeign, 2, 20),
+            observed_day("2024-01-02", Direction::ForeignToHome, 1, 5),
+            observed_day("2024-02-01", Direction::HomeToForeign, 4, 1),
+        ];
+        let invoice = invoice(
+            &records,
+            &prices,
+            &observed,
+            Day::parse("2024-01-01").unwrap(),
+            Day::parse("2024-02-01").unwrap(),
+        ).unwrap();
+
+        assert_eq!(2, invoice.lines.len());
+        // 2 * 21000 gas at 1 gwei on foreign at 0.5
+        assert_eq!(2, invoice.lines[0].transactions);
+        assert_eq!(Chain::Foreign, invoice.lines[0].chain);
+        assert_eq!(U256::from(42_000_000_000_000u64), invoice.lines[0].wei);
+        assert_eq!(Some("0.000021".to_owned()), invoice.lines[0].amount);
+        // 100000 gas at 20 gwei on home without price
+        assert_eq!("2024-01-02", invoice.lines[1].date);
+        assert_eq!(None, invoice.lines[1].amount);
+        assert_eq!(vec!["2024-01-02".to_owned()], invoice.missing_prices);
+
+        assert_eq!(Direction::HomeToForeign, invoice.totals[0].direction);
+        assert_eq!(Direction::ForeignToHome, invoice.totals[1].direction);
+        assert_eq!(2, invoice.totals[1].transactions);
+        assert_eq!(U256::from(2_000_000_000_000_000u64), invoice.totals[1].home_wei);
+        assert!(invoice
+            .to_string()
+            .contains("\nForeignToHome: 2 transactions, 0.002 ETH on home, "));
+        assert_eq!("0.000021", invoice.amount);
+        assert_eq!(vec![records[3].clone()], invoice.missing_gas_records);
+
+        // observed traffic of the period, also of deposits relayed by other authorities
+        assert_eq!(2, invoice.observed.len());
+        assert_eq!(Direction::HomeToForeign, invoice.observed[0].direction);
+        assert_eq!(5, invoice.observed[0].events);
+        assert_eq!(U256::from(320), invoice.observed[0].value);
+        assert_eq!(1, invoice.observed[1].events);
+        assert!(invoice
+            .to_string()
+            .ends_with("observed HomeToForeign: 5 events, 320 wei\nobserved ForeignToHome: 1 events, 5 wei"));
+    }
+
+    #[test]
+    fn test_invoice_of_max_values() {
+        let max = format!("0x{}", "f".repeat(64));
+        let record = |transaction: u64, gas_used: &str| {
+            format!(
+                r#"{{"direction":"home_to_foreign","chain":"foreign","transaction":"{:?}","time":1704067200,"gas_used":"{}","gas_price":"0x1"}}"#,
+                H256::from(transaction),
+                gas_used
+            )
+        };
+        let prices = Prices::default();
+        let from = Day::parse("2024-01-01").unwrap();
+        let to = Day::parse("2024-01-02").unwrap();
+
+        // the json keeps every digit of the gas spent
+        let records = read_gas_records(&record(1, &max)).unwrap();
+        let invoice = invoice(&records, &prices, &[], from, to).unwrap();
+        assert_eq!(U256::max_value(), invoice.lines[0].wei);
+        let json: Value = serde_json::from_str(&invoice.to_json()).unwrap();
+        assert_eq!(json!(max), json["lines"][0]["wei"]);
+        assert_eq!(json!(max), json["totals"][0]["foreign_wei"]);
+
+        // gas that doesn't fit is an error instead of a panic
+        let records = read_gas_records(&format!("{}\n{}", record(1, &max), record(2, "0x1")));
+        let err = invoice(&records.unwrap(), &prices, &[], from, to).unwrap_err();
+        assert_eq!(
+            format!(
+                "gas of Foreign transaction {:?} on 2024-01-01 overflows",
+                H256::from(2)
+            ),
+            err.to_string()
+        );
+    }
+}
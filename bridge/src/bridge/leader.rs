@@ -0,0 +1,510 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures::{Async, Future, Stream};
+use futures::future::{join_all, JoinAll};
+use serde_json;
+use timer::Interval;
+use web3::Transport;
+use web3::types::U256;
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::LeaderElectionConfig;
+use error::{Result, ResultExt};
+
+/// milliseconds since the unix epoch. lease expiry is kept in milliseconds.
+fn unix_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(millis)
+        .unwrap_or(0)
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos()) / 1_000_000
+}
+
+/// transactions sent but not mined yet. nodes behind a load balancer may answer
+/// the counts from different blocks, so a pending count below the latest counts as none.
+fn pending_count(latest: U256, pending: U256) -> U256 {
+    if pending > latest {
+        pending - latest
+    } else {
+        U256::zero()
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// default lease file of the instances sharing the database at `database`.
+pub fn lease_path(database: &Path) -> PathBuf {
+    with_suffix(database, ".lease")
+}
+
+/// Leadership lease written by the leader and read by the standbys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lease {
+    /// `instance_id` of the leader
+    pub holder: String,
+    /// incremented whenever another instance acquires the lease.
+    /// a leader whose epoch was superseded must not send transactions anymore
+    pub epoch: u64,
+    /// unix time in milliseconds after which other instances may acquire the lease
+    pub expires: u64,
+}
+
+/// Outcome of acquiring or renewing a lease.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Acquired {
+    /// the instance holds the lease
+    Held(Lease),
+    /// another instance holds the lease which hasn't expired yet
+    HeldBy(Lease),
+}
+
+/// removes the lock file when dropped
+struct LeaseLock {
+    path: PathBuf,
+}
+
+impl Drop for LeaseLock {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!("cannot remove lock file {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Lease stored in a file shared by the instances.
+///
+/// reads and writes of the lease are serialized by a lock file created next to it.
+/// a lock file older than the lease duration was left behind by a crashed instance
+/// and is removed.
+#[derive(Debug, Clone)]
+pub struct LeaseFile {
+    path: PathBuf,
+}
+
+impl LeaseFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        LeaseFile { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// the current lease. `None` if no instance ever acquired it.
+    pub fn read(&self) -> Result<Option<Lease>> {
+        let mut contents = String::new();
+        match fs::File::open(&self.path) {
+            Ok(mut file) => file.read_to_string(&mut contents)?,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        serde_json::from_str(&contents)
+            .map(Some)
+            .chain_err(|| format!("cannot parse lease file {}", self.path.display()))
+    }
+
+    /// acquires or renews the lease for `holder` until `now + duration`.
+    /// fails if another instance holds the lease and it hasn't expired at `now`.
+    pub fn acquire(&self, holder: &str, now: u64, duration: Duration) -> Result<Acquired> {
+        let _lock = self.lock(duration)?;
+        let lease = match self.read()? {
+            Some(ref lease) if lease.holder != holder && lease.expires > now => {
+                return Ok(Acquired::HeldBy(lease.clone()))
+            }
+            Some(lease) => Lease {
+                holder: holder.into(),
+                epoch: if lease.holder == holder {
+                    lease.epoch
+                } else {
+                    lease.epoch + 1
+                },
+                expires: now + millis(duration),
+            },
+            None => Lease {
+                holder: holder.into(),
+                epoch: 1,
+                expires: now + millis(duration),
+            },
+        };
+        self.write(&lease)?;
+        Ok(Acquired::Held(lease))
+    }
+
+    /// lets the lease of `holder` expire right away. other instances don't have to wait
+    /// for the lease duration to take over. does nothing if another instance holds the lease.
+    pub fn release(&self, holder: &str) -> Result<()> {
+        let _lock = self.lock(Duration::from_secs(0))?;
+        match self.read()? {
+            Some(ref lease) if lease.holder == holder => self.write(&Lease {
+                expires: 0,
+                ..lease.clone()
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn write(&self, lease: &Lease) -> Result<()> {
+        let contents = serde_json::to_string(lease).chain_err(|| "cannot serialize lease")?;
+        let tmp = with_suffix(&self.path, ".tmp");
+        fs::File::create(&tmp)?.write_all(contents.as_bytes())?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// takes the lock file. a lock older than `stale_after` is removed, unless it's zero.
+    fn lock(&self, stale_after: Duration) -> Result<LeaseLock> {
+        let path = with_suffix(&self.path, ".lock");
+        for _ in 0..2 {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(LeaseLock { path: path.clone() }),
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    let age = fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok());
+                    match age {
+                        Some(age) if age > stale_after && stale_after > Duration::from_secs(0) => {
+                            warn!("removing stale lock file {}", path.display());
+                            fs::remove_file(&path)?;
+                        }
+                        _ => break,
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        bail!(
+            "lease file {} is locked by another instance",
+            self.path.display()
+        )
+    }
+}
+
+/// Role of an instance in the leader election.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// another instance holds the lease. the components aren't polled
+    Standby,
+    /// the instance holds the lease and waits until the transactions sent by the
+    /// previous leader are mined
+    Promoting,
+    /// the instance holds the lease and relays
+    Leader,
+}
+
+type TransactionCounts<T> = JoinAll<Vec<Timeout<ApiCall<U256, <T as Transport>::Out>>>>;
+
+/// Leader election between instances running for the same authority.
+///
+/// only the leader sends transactions. the lease is renewed every `renew_interval`.
+/// a standby acquires the lease once it expired and is promoted after the transaction
+/// counts of the sending accounts show no pending transactions on home and foreign,
+/// so it doesn't relay again what the previous leader has in flight.
+/// a leader steps down as soon as another instance holds the lease or its own lease
+/// expired without being renewed.
+pub struct LeaderElection<T: Transport> {
+    app: Arc<App<T>>,
+    file: LeaseFile,
+    instance_id: String,
+    lease_duration: Duration,
+    interval: Interval,
+    /// the lease is checked right away on the first poll
+    checked: bool,
+    role: Role,
+    /// lease held while promoting or leading
+    lease: Option<Lease>,
+    /// latest and pending transaction counts of the home and foreign sender
+    transaction_counts: Option<TransactionCounts<T>>,
+}
+
+impl<T: Transport> LeaderElection<T> {
+    pub fn new(app: Arc<App<T>>, config: &LeaderElectionConfig) -> Self {
+        let path = config
+            .lease_file
+            .clone()
+            .unwrap_or_else(|| lease_path(&app.database_path));
+        info!(
+            "{} starts as standby. leadership lease {}",
+            config.instance_id,
+            path.display()
+        );
+        LeaderElection {
+            file: LeaseFile::new(path),
+            instance_id: config.instance_id.clone(),
+            lease_duration: config.lease_duration,
+            interval: app.timer.interval(config.renew_interval),
+            checked: false,
+            role: Role::Standby,
+            lease: None,
+            transaction_counts: None,
+            app,
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// the lease held while promoting or leading.
+    pub fn lease(&self) -> Option<&Lease> {
+        self.lease.as_ref()
+    }
+
+    /// renews or acquires the lease once the renew interval elapsed.
+    /// `true` once when the instance is promoted to leader.
+    pub fn poll_promoted(&mut self) -> Result<bool> {
+        let mut due = !self.checked;
+        self.checked = true;
+        while let Async::Ready(Some(())) = self.interval.poll()? {
+            due = true;
+        }
+
+        let now = unix_time_millis();
+        if due {
+            self.check_lease(now);
+        }
+        let expired = self.lease
+            .as_ref()
+            .map_or(false, |lease| now >= lease.expires);
+        if expired {
+            error!(
+                "CRITICAL: leadership lease of {} expired without being renewed. stepping down",
+                self.instance_id
+            );
+            self.step_down();
+        }
+        if self.role == Role::Promoting {
+            self.poll_transaction_counts()
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// lets the lease expire right away, e.g. on shutdown. the instance is a standby afterwards.
+    pub fn release(&mut self) -> Result<()> {
+        if self.lease.is_some() {
+            self.file.release(&self.instance_id)?;
+            info!("{} released the leadership lease", self.instance_id);
+            self.step_down();
+        }
+        Ok(())
+    }
+
+    fn step_down(&mut self) {
+        self.role = Role::Standby;
+        self.lease = None;
+        self.transaction_counts = None;
+    }
+
+    fn check_lease(&mut self, now: u64) {
+        match self.file.acquire(&self.instance_id, now, self.lease_duration) {
+            Err(err) => warn!(
+                "cannot acquire leadership lease {}: {}",
+                self.file.path().display(),
+                err
+            ),
+            Ok(Acquired::HeldBy(lease)) => {
+                if self.role == Role::Standby {
+                    debug!(
+                        "{} holds the leadership lease (epoch {})",
+                        lease.holder, lease.epoch
+                    );
+                } else {
+                    error!(
+                        "CRITICAL: {} acquired the leadership lease (epoch {}) held by {}. stepping down",
+                        lease.holder, lease.epoch, self.instance_id
+                    );
+                    self.step_down();
+                }
+            }
+            Ok(Acquired::Held(lease)) => {
+                if self.role == Role::Standby {
+                    info!(
+                        "{} acquired the leadership lease (epoch {}). waiting for transactions of the previous leader to be mined",
+                        self.instance_id, lease.epoch
+                    );
+                    self.role = Role::Promoting;
+                }
+                self.lease = Some(lease);
+                if self.role == Role::Promoting && self.transaction_counts.is_none() {
+                    self.transaction_counts = Some(self.request_transaction_counts());
+                }
+            }
+        }
+    }
+
+    fn request_transaction_counts(&self) -> TransactionCounts<T> {
+        let app = &self.app;
+        let accounts = [
+            (
+                &app.connections.home,
+                "home",
+                app.config.home.sender(),
+                app.config.home.request_timeout,
+            ),
+            (
+                &app.connections.foreign,
+                "foreign",
+                app.config.foreign.account,
+                app.config.foreign.request_timeout,
+            ),
+        ];
+        let mut requests = Vec::new();
+        for &(transport, chain, account, request_timeout) in &accounts {
+            for &pending in &[false, true] {
+                requests.push(api::timeout(
+                    &app.timer,
+                    api::transaction_count(transport, account, pending),
+                    chain,
+                    request_timeout,
+                ));
+            }
+        }
+        join_all(requests)
+    }
+
+    fn poll_transaction_counts(&mut self) -> Result<bool> {
+        let counts = match self.transaction_counts {
+            Some(ref mut future) => match future.poll() {
+                Ok(Async::Ready(counts)) => Ok(counts),
+                Ok(Async::NotReady) => return Ok(false),
+                Err(err) => Err(err),
+            },
+            None => return Ok(false),
+        };
+        // counts are requested again on the next renewal unless promoted
+        self.transaction_counts = None;
+        let counts = match counts {
+            Ok(counts) => counts,
+            Err(err) => {
+                warn!(
+                    "cannot check for pending transactions of the previous leader: {}",
+                    err
+                );
+                return Ok(false);
+            }
+        };
+        let (home, foreign) = (
+            pending_count(counts[0], counts[1]),
+            pending_count(counts[2], counts[3]),
+        );
+        if home.is_zero() && foreign.is_zero() {
+            info!(
+                "{} promoted to leader (epoch {})",
+                self.instance_id,
+                self.lease.as_ref().map_or(0, |lease| lease.epoch)
+            );
+            self.role = Role::Leader;
+            Ok(true)
+        } else {
+            info!(
+                "{} transactions on home and {} on foreign of the previous leader are pending. promoting once they are mined",
+                home, foreign
+            );
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use self::tempdir::TempDir;
+    use std::fs;
+    use std::time::Duration;
+    use super::{lease_path, Acquired, Lease, LeaseFile};
+
+    fn lease(holder: &str, epoch: u64, expires: u64) -> Lease {
+        Lease {
+            holder: holder.into(),
+            epoch,
+            expires,
+        }
+    }
+
+    #[test]
+    fn test_lease_file_acquire() {
+        let tempdir = TempDir::new("test_lease_file_acquire").unwrap();
+        let file = LeaseFile::new(lease_path(&tempdir.path().join("db.toml")));
+        let duration = Duration::from_secs(30);
+        assert_eq!(None, file.read().unwrap());
+
+        assert_eq!(
+            Acquired::Held(lease("a", 1, 31_000)),
+            file.acquire("a", 1000, duration).unwrap()
+        );
+        // standbys can't acquire the lease before it expires
+        assert_eq!(
+            Acquired::HeldBy(lease("a", 1, 31_000)),
+            file.acquire("b", 30_999, duration).unwrap()
+        );
+        // renewing keeps the epoch
+        assert_eq!(
+            Acquired::Held(lease("a", 1, 40_000)),
+            file.acquire("a", 10_000, duration).unwrap()
+        );
+        assert_eq!(Some(lease("a", 1, 40_000)), file.read().unwrap());
+        assert!(tempdir.path().join("db.toml.lease").exists());
+        assert!(!tempdir.path().join("db.toml.lease.lock").exists());
+    }
+
+    #[test]
+    fn test_lease_file_takeover_fences_previous_leader() {
+        let tempdir = TempDir::new("test_lease_file_takeover").unwrap();
+        let file = LeaseFile::new(tempdir.path().join("lease"));
+        let duration = Duration::from_secs(30);
+        file.acquire("a", 0, duration).unwrap();
+
+        // a didn't renew in time
+        assert_eq!(
+            Acquired::Held(lease("b", 2, 60_000)),
+            file.acquire("b", 30_000, duration).unwrap()
+        );
+        // and doesn't get the lease back while b renews it
+        assert_eq!(
+            Acquired::HeldBy(lease("b", 2, 60_000)),
+            file.acquire("a", 30_001, duration).unwrap()
+        );
+
+        // a released lease can be acquired right away
+        file.release("a").unwrap();
+        assert_eq!(Some(lease("b", 2, 60_000)), file.read().unwrap());
+        file.release("b").unwrap();
+        assert_eq!(
+            Acquired::Held(lease("a", 3, 70_000)),
+            file.acquire("a", 40_000, duration).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_lease_file_lock() {
+        let tempdir = TempDir::new("test_lease_file_lock").unwrap();
+        let file = LeaseFile::new(tempdir.path().join("lease"));
+        fs::File::create(tempdir.path().join("lease.lock")).unwrap();
+
+        // another instance is writing the lease
+        assert!(file.acquire("a", 0, Duration::from_secs(30)).is_err());
+        assert_eq!(None, file.read().unwrap());
+
+        // the lock of a crashed instance is removed once it's older than the lease duration
+        ::std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            Acquired::Held(lease("a", 1, 10)),
+            file.acquire("a", 0, Duration::from_millis(10)).unwrap()
+        );
+    }
+}
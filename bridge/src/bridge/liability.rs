@@ -0,0 +1,991 @@
+use std::cmp;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use futures::{Async, Future, Poll, Stream};
+use futures::future::Join;
+use serde_json;
+use timer::Interval;
+use web3::Transport;
+use web3::types::{Address, Bytes, Filter, H256, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::Config;
+use database::{Database, QuarantinedDeposit, SkippedDeposit};
+use error::{Error, ErrorKind, Result, ResultExt};
+use fetch::{fetch_bounded, FetchBounded, Requests};
+use units::format_ether;
+use util::{event_id, log_id, log_transaction_hash, raw_log, web3_filter};
+use super::deposit_relay::{app_deposit_executed_payload, app_deposit_value};
+use super::rebuild::{chunks, log_block, LogRequests, CHUNK_BLOCKS};
+use super::relay_outcome::SkipReason;
+
+/// Why a deposit observed on home isn't executed on foreign yet.
+/// a deposit has exactly one, the first of these that applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiabilityReason {
+    /// fewer than `home.required_confirmations` blocks on top of it
+    AwaitingConfirmations,
+    /// the recipient contract rejects the transfer, see `QuarantinedDeposit`
+    Quarantined,
+    /// beyond the spend limit. relayed once the limit allows it
+    Deferred,
+    /// relayed by this authority. the other authorities haven't confirmed it yet
+    AwaitingSignatures,
+    /// confirmed and not yet relayed by this authority: queued, or its relay isn't mined yet
+    InFlight,
+}
+
+/// Number and sum of the home values of unminted deposits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct LiabilityTotal {
+    pub deposits: usize,
+    /// in wei of home
+    pub value: U256,
+}
+
+impl LiabilityTotal {
+    fn add(&mut self, value: U256) {
+        self.deposits += 1;
+        self.value = self.value + value;
+    }
+
+    fn remove(&mut self, value: U256) {
+        self.deposits -= 1;
+        self.value = self.value - value;
+    }
+}
+
+impl fmt::Display for LiabilityTotal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {}", format_ether(self.value, 0), self.deposits)
+    }
+}
+
+/// Unminted liability by reason, the gauge of the digest.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct LiabilityGauge {
+    /// every unminted deposit, the sum of the reasons
+    pub total: LiabilityTotal,
+    pub awaiting_confirmations: LiabilityTotal,
+    pub quarantined: LiabilityTotal,
+    pub deferred: LiabilityTotal,
+    pub awaiting_signatures: LiabilityTotal,
+    pub in_flight: LiabilityTotal,
+    /// first scanned home block. older deposits aren't counted
+    pub since_block: u64,
+    /// last scanned home block
+    pub home_block: u64,
+}
+
+impl fmt::Display for LiabilityGauge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unminted {} deposits in home blocks {}..={}: awaiting confirmations {}, \
+             quarantined {}, deferred {}, awaiting signatures {}, in flight {}",
+            self.total,
+            self.since_block,
+            self.home_block,
+            self.awaiting_confirmations,
+            self.quarantined,
+            self.deferred,
+            self.awaiting_signatures,
+            self.in_flight
+        )
+    }
+}
+
+/// A deposit on home the bridge relays, as scanned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScannedDeposit {
+    /// id of the deposit, see `util::log_id`
+    pub id: H256,
+    pub home_transaction: H256,
+    pub log_index: Option<U256>,
+    pub block: u64,
+    /// value on home
+    pub value: U256,
+}
+
+/// Deposits and executions observed by a scan of both chains, see `scan_liability`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LiabilityScan {
+    /// deposits on home the bridge relays, in block order
+    pub deposits: Vec<ScannedDeposit>,
+    /// ids of the executed deposits
+    pub executed: HashSet<H256>,
+    /// first scanned home block
+    pub since_block: u64,
+    /// last scanned home block, the head of home
+    pub home_block: u64,
+}
+
+/// A deposit observed on home and not yet executed on foreign.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnmintedDeposit {
+    pub home_transaction: H256,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<U256>,
+    pub block: u64,
+    /// value on home
+    pub value: U256,
+    pub reason: LiabilityReason,
+}
+
+/// unminted deposits of `scan` by id, each with the first reason that applies:
+/// awaiting confirmations, in `quarantined`, deferred by the spend limit in `skipped`,
+/// awaiting signatures once relayed up to `checked_deposit_relay`, otherwise in flight.
+pub fn classify(
+    config: &Config,
+    scan: &LiabilityScan,
+    checked_deposit_relay: u64,
+    quarantined: &[QuarantinedDeposit],
+    skipped: &[SkippedDeposit],
+) -> Vec<(H256, UnmintedDeposit)> {
+    let unique_ids = config.unique_log_ids;
+    let quarantined = quarantined
+        .iter()
+        .filter_map(|deposit| event_id(deposit.home_transaction, deposit.log_index, unique_ids).ok())
+        .collect::<HashSet<_>>();
+    let deferred = skipped
+        .iter()
+        .filter(|skipped| skipped.reason == SkipReason::SpendLimit)
+        .filter_map(|skipped| event_id(skipped.home_transaction, skipped.log_index, unique_ids).ok())
+        .collect::<HashSet<_>>();
+    let confirmed = scan.home_block
+        .saturating_sub(config.home.required_confirmations as u64);
+    scan.deposits
+        .iter()
+        .filter(|deposit| !scan.executed.contains(&deposit.id))
+        .map(|deposit| {
+            let reason = if deposit.block > confirmed {
+                LiabilityReason::AwaitingConfirmations
+            } else if quarantined.contains(&deposit.id) {
+                LiabilityReason::Quarantined
+            } else if deferred.contains(&deposit.id) {
+                LiabilityReason::Deferred
+            } else if deposit.block <= checked_deposit_relay {
+                LiabilityReason::AwaitingSignatures
+            } else {
+                LiabilityReason::InFlight
+            };
+            let unminted = UnmintedDeposit {
+                home_transaction: deposit.home_transaction,
+                log_index: deposit.log_index,
+                block: deposit.block,
+                value: deposit.value,
+                reason,
+            };
+            (deposit.id, unminted)
+        })
+        .collect()
+}
+
+/// A change of the state of a deposit in `Liability`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    /// became unminted, usually observed on home for the first time
+    Added(LiabilityReason),
+    /// moved from one reason to another
+    Moved(LiabilityReason, LiabilityReason),
+    /// executed on foreign
+    Minted(LiabilityReason),
+    /// no longer scanned without being executed: orphaned by a reorg of home
+    /// or older than the scanned blocks
+    Dropped(LiabilityReason),
+}
+
+/// Unminted liability: deposits observed on home and not yet executed on foreign, by reason.
+///
+/// every deposit is in exactly one state. the totals are kept by the transitions between
+/// the states instead of summed up per reason, so a deposit is never counted twice.
+/// nothing is persisted: the first scan after a restart rebuilds it from the records
+/// of the database and the chains.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Liability {
+    deposits: BTreeMap<H256, UnmintedDeposit>,
+    totals: BTreeMap<LiabilityReason, LiabilityTotal>,
+    /// first and last scanned home blocks. `None` until the first scan is applied
+    scanned: Option<(u64, u64)>,
+}
+
+impl Liability {
+    fn total_mut(&mut self, reason: LiabilityReason) -> &mut LiabilityTotal {
+        self.totals.entry(reason).or_insert_with(LiabilityTotal::default)
+    }
+
+    /// puts the deposit `id` into the state of `deposit`.
+    /// `None` if it was in that state already.
+    pub fn update(&mut self, id: H256, deposit: UnmintedDeposit) -> Option<Transition> {
+        let (reason, value) = (deposit.reason, deposit.value);
+        let previous = self.deposits.insert(id, deposit);
+        if let Some(ref previous) = previous {
+            self.total_mut(previous.reason).remove(previous.value);
+        }
+        self.total_mut(reason).add(value);
+        match previous {
+            None => Some(Transition::Added(reason)),
+            Some(ref previous) if previous.reason != reason => {
+                Some(Transition::Moved(previous.reason, reason))
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// removes the deposit `id`, `minted` if it was executed on foreign.
+    /// `None` if it isn't unminted.
+    pub fn remove(&mut self, id: &H256, minted: bool) -> Option<Transition> {
+        let deposit = match self.deposits.remove(id) {
+            Some(deposit) => deposit,
+            None => return None,
+        };
+        self.total_mut(deposit.reason).remove(deposit.value);
+        if minted {
+            Some(Transition::Minted(deposit.reason))
+        } else {
+            Some(Transition::Dropped(deposit.reason))
+        }
+    }
+
+    /// moves every deposit to its state in `unminted` of `scan`, see `classify`.
+    /// deposits no longer unminted are minted if `scan` found them executed, or dropped.
+    pub fn apply(
+        &mut self,
+        scan: &LiabilityScan,
+        unminted: Vec<(H256, UnmintedDeposit)>,
+    ) -> Vec<(H256, Transition)> {
+        let current = unminted.iter().map(|&(id, _)| id).collect::<HashSet<_>>();
+        let gone = self.deposits
+            .keys()
+            .filter(|id| !current.contains(id))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut transitions = Vec::new();
+        for id in gone {
+            if let Some(transition) = self.remove(&id, scan.executed.contains(&id)) {
+                transitions.push((id, transition));
+            }
+        }
+        for (id, deposit) in unminted {
+            if let Some(transition) = self.update(id, deposit) {
+                transitions.push((id, transition));
+            }
+        }
+        self.scanned = Some((scan.since_block, scan.home_block));
+        transitions
+    }
+
+    /// total of the deposits unminted for `reason`.
+    pub fn total(&self, reason: LiabilityReason) -> LiabilityTotal {
+        self.totals.get(&reason).cloned().unwrap_or_default()
+    }
+
+    /// gauge of the digest. `None` until the first scan is applied.
+    pub fn gauge(&self) -> Option<LiabilityGauge> {
+        let (since_block, home_block) = match self.scanned {
+            Some(scanned) => scanned,
+            None => return None,
+        };
+        let mut total = LiabilityTotal::default();
+        for reason_total in self.totals.values() {
+            total.deposits += reason_total.deposits;
+            total.value = total.value + reason_total.value;
+        }
+        Some(LiabilityGauge {
+            total,
+            awaiting_confirmations: self.total(LiabilityReason::AwaitingConfirmations),
+            quarantined: self.total(LiabilityReason::Quarantined),
+            deferred: self.total(LiabilityReason::Deferred),
+            awaiting_signatures: self.total(LiabilityReason::AwaitingSignatures),
+            in_flight: self.total(LiabilityReason::InFlight),
+            since_block,
+            home_block,
+        })
+    }
+
+    /// unminted deposits, oldest first. `None` until the first scan is applied.
+    pub fn report(&self) -> Option<LiabilityReport> {
+        let gauge = match self.gauge() {
+            Some(gauge) => gauge,
+            None => return None,
+        };
+        let mut deposits = self.deposits.values().cloned().collect::<Vec<_>>();
+        deposits.sort_by_key(|deposit| (deposit.block, deposit.log_index));
+        Some(LiabilityReport { gauge, deposits })
+    }
+}
+
+/// Unminted deposits and their totals by reason, printed by `--liability`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LiabilityReport {
+    pub gauge: LiabilityGauge,
+    /// oldest first
+    pub deposits: Vec<UnmintedDeposit>,
+}
+
+impl LiabilityReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("LiabilityReport serializes to json; qed")
+    }
+}
+
+impl fmt::Display for LiabilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.gauge)?;
+        for deposit in &self.deposits {
+            write!(
+                f,
+                "\ndeposit of {} in home transaction {:?} at block {}: {:?}",
+                format_ether(deposit.value, 0),
+                deposit.home_transaction,
+                deposit.block,
+                deposit.reason
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `isDepositExecuted` calls of the deposits a scan found no execution of.
+struct ExecutedRequests<T: Transport> {
+    app: Arc<App<T>>,
+    contract: Address,
+    payloads: Vec<Bytes>,
+}
+
+impl<T: Transport> Requests for ExecutedRequests<T> {
+    type Item = Bytes;
+    type Future = Timeout<ApiCall<Bytes, T::Out>>;
+
+    fn len(&self) -> usize {
+        self.payloads.len()
+    }
+
+    fn start(&self, index: usize) -> Self::Future {
+        api::timeout(
+            &self.app.timer,
+            api::call(
+                &self.app.connections.foreign,
+                self.contract,
+                self.payloads[index].clone(),
+            ),
+            "foreign",
+            self.app.config.foreign.request_timeout,
+        )
+    }
+}
+
+enum ScanLiabilityState<T: Transport> {
+    /// Fetching the block numbers of both chains.
+    FetchBlockNumbers(Join<Timeout<ApiCall<U256, T::Out>>, Timeout<ApiCall<U256, T::Out>>>),
+    /// Fetching the deposits of home and the executions of foreign.
+    FetchLogs {
+        future: FetchBounded<LogRequests<T>>,
+        /// whether every request of `future` is for home
+        on_home: Vec<bool>,
+        since_block: u64,
+        home_block: u64,
+    },
+    /// Calling `isDepositExecuted` for the confirmed deposits without an execution in the logs.
+    CheckExecuted {
+        future: FetchBounded<ExecutedRequests<T>>,
+        /// ids of the checked deposits, in the order of the calls
+        ids: Vec<H256>,
+        scan: LiabilityScan,
+    },
+}
+
+/// scans the deposits on home of the last `scan_blocks` blocks, or since the checked block
+/// of the deposit relay of `init` if that's older, up to the head.
+/// a deposit is executed if a `ForeignBridge.Deposit` of the last `scan_blocks` blocks of foreign
+/// has its id, or else if `isDepositExecuted` says so. deposits the bridge doesn't relay
+/// (zero value, rejected by `value_scale`) are left out.
+pub fn scan_liability<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+    scan_blocks: u64,
+) -> ScanLiability<T> {
+    let home = api::timeout(
+        &app.timer,
+        api::block_number(&app.connections.home),
+        "home",
+        app.config.home.request_timeout,
+    );
+    let foreign = api::timeout(
+        &app.timer,
+        api::block_number(&app.connections.foreign),
+        "foreign",
+        app.config.foreign.request_timeout,
+    );
+    ScanLiability {
+        app,
+        init: init.clone(),
+        scan_blocks,
+        state: ScanLiabilityState::FetchBlockNumbers(home.join(foreign)),
+    }
+}
+
+/// Future resolving to the deposits and executions of a scan.
+pub struct ScanLiability<T: Transport> {
+    app: Arc<App<T>>,
+    init: Database,
+    scan_blocks: u64,
+    state: ScanLiabilityState<T>,
+}
+
+/// `eth_getLogs` filters of the deposits on home from `since_block` to `home_block`
+/// and of the executions on foreign from `foreign_from` to `foreign_block`, in chunks.
+fn scan_filters<T: Transport>(
+    app: &App<T>,
+    init: &Database,
+    since_block: u64,
+    home_block: u64,
+    foreign_from: u64,
+    foreign_block: u64,
+) -> Vec<(Filter, bool)> {
+    let home = web3_filter(
+        app.home_bridge.events().deposit().create_filter(),
+        init.home_contract_address,
+    );
+    let foreign = web3_filter(
+        app.foreign_bridge.events().deposit().create_filter(),
+        init.foreign_contract_address,
+    );
+    let ranges = vec![
+        (home, since_block, home_block, true),
+        (foreign, foreign_from, foreign_block, false),
+    ];
+    let mut filters = Vec::new();
+    for (filter, from, to, on_home) in ranges {
+        for (from, to) in chunks(from, to, CHUNK_BLOCKS) {
+            let filter = filter
+                .clone()
+                .from_block(from.into())
+                .to_block(to.into())
+                .build();
+            filters.push((filter, on_home));
+        }
+    }
+    filters
+}
+
+impl<T: Transport> Future for ScanLiability<T> {
+    type Item = LiabilityScan;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                ScanLiabilityState::FetchBlockNumbers(ref mut future) => {
+                    let (home, foreign) = try_ready!(future.poll());
+                    let (home_block, foreign_block) = (home.low_u64(), foreign.low_u64());
+                    // deposits not relayed yet are scanned however old they are
+                    let since_block = cmp::max(
+                        self.init.home_deploy,
+                        cmp::min(
+                            self.init.checked_deposit_relay.saturating_add(1),
+                            home_block.saturating_sub(self.scan_blocks),
+                        ),
+                    );
+                    let foreign_from = cmp::max(
+                        self.init.foreign_deploy,
+                        foreign_block.saturating_sub(self.scan_blocks),
+                    );
+                    debug!(
+                        target: "bridge::liability",
+                        "scanning home blocks {}..={} and foreign blocks {}..={}",
+                        since_block,
+                        home_block,
+                        foreign_from,
+                        foreign_block
+                    );
+                    let filters = scan_filters(
+                        &self.app,
+                        &self.init,
+                        since_block,
+                        home_block,
+                        foreign_from,
+                        foreign_block,
+                    );
+                    let on_home = filters.iter().map(|&(_, on_home)| on_home).collect();
+                    let config = &self.app.config;
+                    ScanLiabilityState::FetchLogs {
+                        future: fetch_bounded(
+                            LogRequests::new(self.app.clone(), filters),
+                            config.scan_concurrency,
+                            config.scan_retries,
+                        ),
+                        on_home,
+                        since_block,
+                        home_block,
+                    }
+                }
+                ScanLiabilityState::FetchLogs {
+                    ref mut future,
+                    ref on_home,
+                    since_block,
+                    home_block,
+                } => {
+                    let chunks = try_ready!(future.poll());
+                    let app = &self.app;
+                    let config = &app.config;
+                    let scale = config.value_scale.as_ref();
+                    let mut scan = LiabilityScan {
+                        since_block,
+                        home_block,
+                        ..LiabilityScan::default()
+                    };
+                    let mut home_logs = Vec::new();
+                    for (&on_home, chunk) in on_home.iter().zip(chunks.into_iter()) {
+                        if on_home {
+                            home_logs.extend(chunk);
+                            continue;
+                        }
+                        for log in chunk {
+                            let deposit = app.foreign_bridge
+                                .events()
+                                .deposit()
+                                .parse_log(raw_log(&log))
+                                .chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+                            scan.executed.insert(H256::from(deposit.transaction_hash));
+                        }
+                    }
+
+                    let confirmed =
+                        home_block.saturating_sub(config.home.required_confirmations as u64);
+                    let (mut ids, mut payloads) = (Vec::new(), Vec::new());
+                    for log in home_logs {
+                        let value = app_deposit_value(app, &log)
+                            .chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+                        let relayed = (!value.is_zero() || config.relay_zero_value)
+                            && scale.map_or(true, |scale| scale.deposit(value).is_ok());
+                        if !relayed {
+                            continue;
+                        }
+                        let deposit = ScannedDeposit {
+                            id: log_id(&log, config.unique_log_ids)?,
+                            home_transaction: log_transaction_hash(&log)?,
+                            log_index: log.log_index,
+                            block: log_block(&log)?,
+                            value,
+                        };
+                        // executions before the scanned foreign blocks are only in the contract
+                        if deposit.block <= confirmed && !scan.executed.contains(&deposit.id) {
+                            let payload = app_deposit_executed_payload(app, &log)
+                                .chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+                            ids.push(deposit.id);
+                            payloads.push(payload);
+                        }
+                        scan.deposits.push(deposit);
+                    }
+                    let requests = ExecutedRequests {
+                        app: app.clone(),
+                        contract: self.init.foreign_contract_address,
+                        payloads,
+                    };
+                    ScanLiabilityState::CheckExecuted {
+                        future: fetch_bounded(
+                            requests,
+                            config.scan_concurrency,
+                            config.scan_retries,
+                        ),
+                        ids,
+                        scan,
+                    }
+                }
+                ScanLiabilityState::CheckExecuted {
+                    ref mut future,
+                    ref ids,
+                    ref mut scan,
+                } => {
+                    let outputs = try_ready!(future.poll());
+                    for (id, output) in ids.iter().zip(outputs.iter()) {
+                        let executed = self.app
+                            .foreign_bridge
+                            .functions()
+                            .is_deposit_executed()
+                            .output(output.0.as_slice())
+                            .map_err(Error::from)?;
+                        if executed {
+                            scan.executed.insert(*id);
+                        }
+                    }
+                    return Ok(Async::Ready(mem::replace(scan, LiabilityScan::default())));
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+/// scans the liability once and resolves to its report, see `scan_liability`.
+/// the reasons are decided from the records of `init`.
+pub fn create_liability_report<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+    scan_blocks: u64,
+) -> LiabilityEstimate<T> {
+    LiabilityEstimate {
+        scan: scan_liability(app.clone(), init, scan_blocks),
+        init: init.clone(),
+        app,
+    }
+}
+
+/// Future resolving to the report of a single scan of the liability.
+pub struct LiabilityEstimate<T: Transport> {
+    app: Arc<App<T>>,
+    init: Database,
+    scan: ScanLiability<T>,
+}
+
+impl<T: Transport> Future for LiabilityEstimate<T> {
+    type Item = LiabilityReport;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let scan = try_ready!(self.scan.poll());
+        let unminted = classify(
+            &self.app.config,
+            &scan,
+            self.init.checked_deposit_relay,
+            &self.init.quarantined_deposits,
+            &self.init.skipped_deposits,
+        );
+        let mut liability = Liability::default();
+        liability.apply(&scan, unminted);
+        Ok(Async::Ready(
+            liability.report().expect("a scan was just applied; qed"),
+        ))
+    }
+}
+
+enum LiabilityMonitorState<T: Transport> {
+    /// Waiting for the next scan.
+    Wait,
+    Scan(ScanLiability<T>),
+}
+
+pub fn create_liability_monitor<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+    interval: Interval,
+    scan_blocks: u64,
+) -> LiabilityMonitor<T> {
+    LiabilityMonitor {
+        app,
+        init: init.clone(),
+        interval,
+        scan_blocks,
+        state: LiabilityMonitorState::Wait,
+    }
+}
+
+/// Scans the liability every `liability.check_interval`, see `scan_liability`.
+/// a failed scan is logged and retried at the next interval.
+pub struct LiabilityMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    /// contract addresses, deployment blocks and the checked block of the deposit relay
+    init: Database,
+    interval: Interval,
+    scan_blocks: u64,
+    state: LiabilityMonitorState<T>,
+}
+
+impl<T: Transport> LiabilityMonitor<T> {
+    /// scans since `block` at least from the next scan on.
+    pub fn set_checked_deposit_relay(&mut self, block: u64) {
+        self.init.checked_deposit_relay = block;
+    }
+}
+
+impl<T: Transport> Stream for LiabilityMonitor<T> {
+    type Item = LiabilityScan;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                LiabilityMonitorState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    LiabilityMonitorState::Scan(scan_liability(
+                        self.app.clone(),
+                        &self.init,
+                        self.scan_blocks,
+                    ))
+                }
+                LiabilityMonitorState::Scan(ref mut future) => match future.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(scan)) => {
+                        self.state = LiabilityMonitorState::Wait;
+                        return Ok(Async::Ready(Some(scan)));
+                    }
+                    Err(err) => {
+                        warn!(target: "bridge::liability", "cannot scan the unminted deposits: {}", err);
+                        LiabilityMonitorState::Wait
+                    }
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use web3::types::{Address, H256};
+    use config::{Authorities, Config, Node};
+    use database::{QuarantinedDeposit, SkippedDeposit};
+    use bridge::SkipReason;
+    use super::{classify, Liability, LiabilityReason, LiabilityScan, LiabilityTotal,
+                ScannedDeposit, Transition, UnmintedDeposit};
+
+    fn config() -> Config {
+        let authority: Address = 0xaa.into();
+        let node = |ipc: &str| Node {
+            required_confirmations: 12,
+            ..Node::new(authority, ipc.into())
+        };
+        Config::builder()
+            .home(node("/home.ipc"))
+            .foreign(node("/foreign.ipc"))
+            .authorities(Authorities {
+                accounts: vec![authority],
+                required_signatures: 1,
+            })
+            .estimated_gas_cost_of_withdraw(100000u64.into())
+            .max_total_home_contract_balance(0u64.into())
+            .max_single_deposit_value(0u64.into())
+            .build()
+            .unwrap()
+    }
+
+    fn scanned(transaction: u64, block: u64, value: u64) -> ScannedDeposit {
+        ScannedDeposit {
+            id: transaction.into(),
+            home_transaction: transaction.into(),
+            log_index: None,
+            block,
+            value: value.into(),
+        }
+    }
+
+    fn unminted(block: u64, value: u64, reason: LiabilityReason) -> UnmintedDeposit {
+        UnmintedDeposit {
+            home_transaction: H256::zero(),
+            log_index: None,
+            block,
+            value: value.into(),
+            reason,
+        }
+    }
+
+    fn total(deposits: usize, value: u64) -> LiabilityTotal {
+        LiabilityTotal {
+            deposits,
+            value: value.into(),
+        }
+    }
+
+    fn skipped(transaction: u64, reason: SkipReason) -> SkippedDeposit {
+        SkippedDeposit {
+            home_transaction: transaction.into(),
+            log_index: None,
+            block: None,
+            time: 0,
+            reason,
+            message: String::new(),
+            parameters: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_classify_decides_the_first_reason_that_applies() {
+        let scan = LiabilityScan {
+            deposits: vec![
+                scanned(1, 100, 10),
+                scanned(2, 100, 20),
+                scanned(3, 100, 30),
+                scanned(4, 100, 40),
+                scanned(5, 150, 50),
+                scanned(6, 195, 60),
+                scanned(7, 110, 70),
+            ],
+            executed: vec![4.into()].into_iter().collect(),
+            since_block: 90,
+            home_block: 200,
+        };
+        let quarantined = vec![QuarantinedDeposit {
+            home_transaction: 2.into(),
+            log_index: None,
+            recipient: 0xc0.into(),
+            original_recipient: None,
+            value: 20.into(),
+            reason: "reverted".into(),
+            raw_log: None,
+        }];
+        let skipped = vec![
+            skipped(3, SkipReason::SpendLimit),
+            skipped(7, SkipReason::ZeroValue),
+        ];
+        let reasons = classify(&config(), &scan, 120, &quarantined, &skipped)
+            .into_iter()
+            .map(|(id, deposit)| (id.low_u64(), deposit.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (1, LiabilityReason::AwaitingSignatures),
+                (2, LiabilityReason::Quarantined),
+                (3, LiabilityReason::Deferred),
+                (5, LiabilityReason::InFlight),
+                (6, LiabilityReason::AwaitingConfirmations),
+                (7, LiabilityReason::AwaitingSignatures),
+            ],
+            reasons
+        );
+
+        // a reorg of home leaves the quarantined deposit unconfirmed again
+        let scan = LiabilityScan {
+            deposits: vec![scanned(2, 195, 20)],
+            ..scan
+        };
+        let reasons = classify(&config(), &scan, 120, &quarantined, &skipped);
+        assert_eq!(LiabilityReason::AwaitingConfirmations, reasons[0].1.reason);
+    }
+
+    #[test]
+    fn test_transitions_move_the_totals_without_double_counting() {
+        let mut liability = Liability::default();
+        assert_eq!(None, liability.gauge());
+
+        let id = H256::from(1);
+        assert_eq!(
+            Some(Transition::Added(LiabilityReason::AwaitingConfirmations)),
+            liability.update(id, unminted(100, 10, LiabilityReason::AwaitingConfirmations))
+        );
+        // the same state again is no transition
+        assert_eq!(
+            None,
+            liability.update(id, unminted(100, 10, LiabilityReason::AwaitingConfirmations))
+        );
+        assert_eq!(
+            Some(Transition::Moved(
+                LiabilityReason::AwaitingConfirmations,
+                LiabilityReason::InFlight
+            )),
+            liability.update(id, unminted(100, 10, LiabilityReason::InFlight))
+        );
+        assert_eq!(
+            Some(Transition::Moved(
+                LiabilityReason::InFlight,
+                LiabilityReason::AwaitingSignatures
+            )),
+            liability.update(id, unminted(100, 10, LiabilityReason::AwaitingSignatures))
+        );
+        assert_eq!(total(0, 0), liability.total(LiabilityReason::AwaitingConfirmations));
+        assert_eq!(total(0, 0), liability.total(LiabilityReason::InFlight));
+        assert_eq!(total(1, 10), liability.total(LiabilityReason::AwaitingSignatures));
+
+        assert_eq!(
+            Some(Transition::Minted(LiabilityReason::AwaitingSignatures)),
+            liability.remove(&id, true)
+        );
+        assert_eq!(None, liability.remove(&id, true));
+        assert_eq!(total(0, 0), liability.total(LiabilityReason::AwaitingSignatures));
+    }
+
+    #[test]
+    fn test_apply_rebuilds_the_same_liability_after_a_restart() {
+        let first = LiabilityScan {
+            deposits: vec![scanned(1, 100, 10), scanned(2, 150, 20), scanned(3, 195, 30)],
+            executed: Default::default(),
+            since_block: 90,
+            home_block: 200,
+        };
+        let mut liability = Liability::default();
+        let transitions = liability.apply(&first, classify(&config(), &first, 120, &[], &[]));
+        assert_eq!(3, transitions.len());
+        let gauge = liability.gauge().unwrap();
+        assert_eq!(total(3, 60), gauge.total);
+        assert_eq!(total(1, 10), gauge.awaiting_signatures);
+        assert_eq!(total(1, 20), gauge.in_flight);
+        assert_eq!(total(1, 30), gauge.awaiting_confirmations);
+
+        // deposit 1 is executed, 2 relayed and 3 confirmed. a new deposit 4 arrived
+        let second = LiabilityScan {
+            deposits: vec![
+                scanned(1, 100, 10),
+                scanned(2, 150, 20),
+                scanned(3, 195, 30),
+                scanned(4, 215, 40),
+            ],
+            executed: vec![1.into()].into_iter().collect(),
+            since_block: 90,
+            home_block: 220,
+        };
+        let unminted = classify(&config(), &second, 160, &[], &[]);
+        let transitions = liability.apply(&second, unminted.clone());
+        assert_eq!(
+            vec![
+                (1.into(), Transition::Minted(LiabilityReason::AwaitingSignatures)),
+                (
+                    2.into(),
+                    Transition::Moved(LiabilityReason::InFlight, LiabilityReason::AwaitingSignatures)
+                ),
+                (
+                    3.into(),
+                    Transition::Moved(LiabilityReason::AwaitingConfirmations, LiabilityReason::InFlight)
+                ),
+                (4.into(), Transition::Added(LiabilityReason::AwaitingConfirmations)),
+            ],
+            transitions
+        );
+        let gauge = liability.gauge().unwrap();
+        assert_eq!(total(3, 90), gauge.total);
+        assert_eq!(total(1, 20), gauge.awaiting_signatures);
+        assert_eq!(total(1, 30), gauge.in_flight);
+        assert_eq!(total(1, 40), gauge.awaiting_confirmations);
+
+        // a restarted bridge rebuilds the same liability from the same records and chains
+        let mut restarted = Liability::default();
+        restarted.apply(&second, unminted);
+        assert_eq!(liability, restarted);
+
+        // a deposit orphaned by a reorg is dropped, not minted
+        let third = LiabilityScan {
+            deposits: vec![scanned(2, 150, 20), scanned(3, 195, 30)],
+            home_block: 221,
+            ..second
+        };
+        let transitions = liability.apply(&third, classify(&config(), &third, 160, &[], &[]));
+        assert_eq!(
+            vec![(4.into(), Transition::Dropped(LiabilityReason::AwaitingConfirmations))],
+            transitions
+        );
+        assert_eq!(total(2, 50), liability.gauge().unwrap().total);
+    }
+
+    #[test]
+    fn test_report_lists_the_unminted_deposits_oldest_first() {
+        let scan = LiabilityScan {
+            deposits: vec![scanned(2, 150, 2000000000000000000), scanned(1, 100, 500)],
+            executed: Default::default(),
+            since_block: 90,
+            home_block: 200,
+        };
+        let mut liability = Liability::default();
+        liability.apply(&scan, classify(&config(), &scan, 120, &[], &[]));
+        let report = liability.report().unwrap();
+        assert_eq!(vec![100, 150], report.deposits.iter().map(|d| d.block).collect::<Vec<_>>());
+        assert!(report.to_string().starts_with(
+            "unminted 2.0000000000000005 ETH in 2 deposits in home blocks 90..=200: \
+             awaiting confirmations 0 ETH in 0, quarantined 0 ETH in 0, deferred 0 ETH in 0, \
+             awaiting signatures 0.0000000000000005 ETH in 1, in flight 2 ETH in 1\n"
+        ));
+        assert!(report.to_json().contains("\"reason\": \"in_flight\""));
+    }
+}
@@ -0,0 +1,264 @@
+use tiny_keccak::keccak256;
+use web3::types::H256;
+
+/// multicall wrappers nest no deeper than this
+const MAX_DEPTH: usize = 4;
+
+/// Wrapper of several calls in one transaction whose inner calls are searched for memos.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Multicall {
+    /// `multicall(bytes[])`
+    Bytes,
+    /// `multicall(uint256,bytes[])`, with a deadline
+    DeadlineBytes,
+    /// `aggregate((address,bytes)[])` of Multicall
+    Aggregate,
+    /// `aggregate3((address,bool,bytes)[])` of Multicall3
+    Aggregate3,
+}
+
+impl Multicall {
+    fn signature(&self) -> &'static str {
+        match *self {
+            Multicall::Bytes => "multicall(bytes[])",
+            Multicall::DeadlineBytes => "multicall(uint256,bytes[])",
+            Multicall::Aggregate => "aggregate((address,bytes)[])",
+            Multicall::Aggregate3 => "aggregate3((address,bool,bytes)[])",
+        }
+    }
+
+    fn from_selector(selector: &[u8]) -> Option<Self> {
+        [
+            Multicall::Bytes,
+            Multicall::DeadlineBytes,
+            Multicall::Aggregate,
+            Multicall::Aggregate3,
+        ].iter()
+            .cloned()
+            .find(|multicall| &keccak256(multicall.signature().as_bytes())[..4] == selector)
+    }
+
+    /// call data of the inner calls of `args`, the arguments of the wrapper.
+    fn calls<'a>(&self, args: &'a [u8]) -> Option<Vec<&'a [u8]>> {
+        // head of the array of calls and word of the call data in a tuple of a call
+        let (head, tuple_word) = match *self {
+            Multicall::Bytes => (0, None),
+            Multicall::DeadlineBytes => (32, None),
+            Multicall::Aggregate => (0, Some(1)),
+            Multicall::Aggregate3 => (0, Some(2)),
+        };
+        elements(args, head)?
+            .into_iter()
+            .map(|call| match tuple_word {
+                Some(word) => bytes(call, offset(call, word * 32)?),
+                None => bytes(call, 0),
+            })
+            .collect()
+    }
+}
+
+/// value of the word at `at` of `data` if it's an offset or length within `data`.
+fn offset(data: &[u8], at: usize) -> Option<usize> {
+    let word = data.get(at..at + 32)?;
+    if word[..24].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+    let value = word[24..]
+        .iter()
+        .fold(0u64, |value, byte| (value << 8) | *byte as u64);
+    if value > data.len() as u64 {
+        None
+    } else {
+        Some(value as usize)
+    }
+}
+
+/// `bytes` encoded at `at` of `data`: a length followed by the content.
+fn bytes(data: &[u8], at: usize) -> Option<&[u8]> {
+    let encoded = data.get(at..)?;
+    let len = offset(encoded, 0)?;
+    encoded.get(32..32 + len)
+}
+
+/// encodings of the elements of the dynamic array whose offset is the word at `head` of `args`.
+fn elements(args: &[u8], head: usize) -> Option<Vec<&[u8]>> {
+    let array = args.get(offset(args, head)?..)?;
+    let count = offset(array, 0)?;
+    let items = &array[32..];
+    (0..count)
+        .map(|index| items.get(offset(items, index * 32)?..))
+        .collect()
+}
+
+fn collect_memos(
+    input: &[u8],
+    selector: &[u8],
+    argument: usize,
+    depth: usize,
+    memos: &mut Vec<H256>,
+) {
+    if input.len() < 4 {
+        return;
+    }
+    let (called, args) = input.split_at(4);
+    if called == selector {
+        if let Some(word) = args.get(argument * 32..argument * 32 + 32) {
+            memos.push(H256::from_slice(word));
+        }
+        return;
+    }
+    if depth == MAX_DEPTH {
+        return;
+    }
+    let calls = match Multicall::from_selector(called).and_then(|wrapper| wrapper.calls(args)) {
+        Some(calls) => calls,
+        None => return,
+    };
+    for call in calls {
+        collect_memos(call, selector, argument, depth + 1, memos);
+    }
+}
+
+/// memos of the calls of the function with `selector` in the call data `input` of a transaction,
+/// in the order of the calls. the memo is the `bytes32` argument at position `argument`.
+///
+/// calls wrapped in `multicall(bytes[])`, `multicall(uint256,bytes[])`,
+/// `aggregate((address,bytes)[])` and `aggregate3((address,bool,bytes)[])` are found too.
+/// malformed call data has no memos.
+pub fn extract_memos(input: &[u8], selector: &[u8], argument: usize) -> Vec<H256> {
+    let mut memos = Vec::new();
+    collect_memos(input, selector, argument, 0, &mut memos);
+    memos
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::{self, Token};
+    use tiny_keccak::keccak256;
+    use web3::types::H256;
+    use super::extract_memos;
+
+    fn selector(signature: &str) -> Vec<u8> {
+        keccak256(signature.as_bytes())[..4].to_vec()
+    }
+
+    fn call(signature: &str, tokens: &[Token]) -> Vec<u8> {
+        let mut call = selector(signature);
+        call.extend(ethabi::encode(tokens));
+        call
+    }
+
+    fn deposit_with_ref(memo: u64) -> Vec<u8> {
+        call(
+            "depositWithRef(bytes32)",
+            &[Token::FixedBytes(H256::from(memo).to_vec())],
+        )
+    }
+
+    fn memos(input: &[u8]) -> Vec<H256> {
+        extract_memos(input, &selector("depositWithRef(bytes32)"), 0)
+    }
+
+    #[test]
+    fn test_extract_memo_of_direct_call() {
+        assert_eq!(vec![H256::from(7)], memos(&deposit_with_ref(7)));
+        // memo as second argument
+        let input = call(
+            "depositFor(address,bytes32)",
+            &[Token::Address(1.into()), Token::FixedBytes(H256::from(8).to_vec())],
+        );
+        assert_eq!(
+            vec![H256::from(8)],
+            extract_memos(&input, &selector("depositFor(address,bytes32)"), 1)
+        );
+    }
+
+    #[test]
+    fn test_extract_no_memo() {
+        // plain deposit
+        assert!(memos(&selector("deposit()")).is_empty());
+        assert!(memos(&[]).is_empty());
+        // truncated
+        assert!(memos(&deposit_with_ref(7)[..20]).is_empty());
+        // argument out of range
+        let selector = selector("depositWithRef(bytes32)");
+        assert!(extract_memos(&deposit_with_ref(7), &selector, 1).is_empty());
+    }
+
+    #[test]
+    fn test_extract_memos_of_multicall() {
+        let input = call(
+            "multicall(bytes[])",
+            &[Token::Array(vec![
+                Token::Bytes(selector("approve()")),
+                Token::Bytes(deposit_with_ref(1)),
+                Token::Bytes(deposit_with_ref(2)),
+            ])],
+        );
+        assert_eq!(vec![H256::from(1), H256::from(2)], memos(&input));
+
+        let input = call(
+            "multicall(uint256,bytes[])",
+            &[
+                Token::Uint(1000.into()),
+                Token::Array(vec![Token::Bytes(deposit_with_ref(3))]),
+            ],
+        );
+        assert_eq!(vec![H256::from(3)], memos(&input));
+
+        // nested
+        let input = call(
+            "multicall(bytes[])",
+            &[Token::Array(vec![Token::Bytes(input)])],
+        );
+        assert_eq!(vec![H256::from(3)], memos(&input));
+    }
+
+    #[test]
+    fn test_extract_memos_of_aggregate() {
+        // tuples are encoded by hand since ethabi 5 has no tuples
+        let tuple = |words: Vec<Token>, data: Vec<u8>| {
+            let mut tokens = words;
+            tokens.push(Token::Bytes(data));
+            ethabi::encode(&tokens)
+        };
+        let array = |tuples: Vec<Vec<u8>>| {
+            let count = tuples.len() as u64;
+            let mut encoded = ethabi::encode(&[Token::Uint(32.into()), Token::Uint(count.into())]);
+            let mut offset = count * 32;
+            for tuple in &tuples {
+                encoded.extend(ethabi::encode(&[Token::Uint(offset.into())]));
+                offset += tuple.len() as u64;
+            }
+            for tuple in tuples {
+                encoded.extend(tuple);
+            }
+            encoded
+        };
+
+        let mut input = selector("aggregate((address,bytes)[])");
+        input.extend(array(vec![
+            tuple(vec![Token::Address(1.into())], deposit_with_ref(4)),
+            tuple(vec![Token::Address(2.into())], selector("approve()")),
+        ]));
+        assert_eq!(vec![H256::from(4)], memos(&input));
+
+        let mut input = selector("aggregate3((address,bool,bytes)[])");
+        input.extend(array(vec![
+            tuple(
+                vec![Token::Address(1.into()), Token::Bool(true)],
+                deposit_with_ref(5),
+            ),
+        ]));
+        assert_eq!(vec![H256::from(5)], memos(&input));
+
+        // offsets pointing past the end
+        let mut input = selector("aggregate((address,bytes)[])");
+        input.extend(ethabi::encode(&[
+            Token::Uint(32.into()),
+            Token::Uint(1.into()),
+            Token::Uint(4096.into()),
+        ]));
+        assert!(memos(&input).is_empty());
+    }
+}
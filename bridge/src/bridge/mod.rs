@@ -1,129 +1,3613 @@
+#[cfg(feature = "admin-api")]
+mod admin_api;
+mod audit;
+mod authority_check;
+mod bootstrap;
+mod call_data;
+mod catch_up;
+mod chain_clock;
+mod chain_stall;
+mod circuit_breaker;
+mod command;
+mod clock_drift;
+mod contract_recipients;
 mod deploy;
+mod deploy_block;
+mod deposit_callback;
+mod deposit_finalize;
+mod deposit_receipts;
 mod deposit_relay;
+mod deposit_responsibility;
+mod derived_recipient;
+mod digest;
+mod drain;
+mod execution_watch;
+mod failure_policy;
+mod fairness;
+mod finality_monitor;
+mod gas_limit;
+mod gas_price;
+mod gas_price_tiers;
+mod gas_usage;
+mod held_deposits;
+mod history;
+#[cfg(feature = "rest-api")]
+mod history_api;
+mod inclusion_proof;
+mod invoice;
+mod leader;
+mod liability;
+mod memo;
+mod node_gas_price;
+mod node_version;
+mod observed_traffic;
+mod own_signatures;
+mod pause;
+mod pending_age;
+mod persistence;
+mod raw_logs;
+mod rebuild;
+mod relay_chain;
+mod relay_events;
+mod relay_notifications;
+mod relay_outcome;
+mod relayed_messages;
+mod recent_errors;
+mod reorg;
+mod report;
+mod revert;
+mod safe_confirmations;
+mod same_chain;
+mod schedule;
+mod self_test;
+mod shared_relays;
+mod show_message;
+mod sign_rejections;
+mod signed_command;
+mod signature_stagger;
+mod signing_latency;
+mod smoke_test;
+mod spend_limit;
+mod state_horizon;
+mod step_timing;
+mod telemetry;
+mod watchdog;
+mod withdraw_attempts;
 mod withdraw_confirm;
+mod withdraw_cost;
+mod withdraw_origins;
 mod withdraw_relay;
 
 use std::fs;
-use std::sync::Arc;
-use std::path::PathBuf;
-use futures::{Async, Poll, Stream};
+use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use futures::{task, Async, Future, Poll, Stream};
+use futures::sync::oneshot;
+use serde_json::{self, Value};
+use timer::Interval;
 use web3::Transport;
-use app::App;
-use database::Database;
-use error::{Error, Result};
+use web3::types::{H256, U256};
+use alert::{Alert, Alerts, Severity};
+use api;
+use app::{App, Connections};
+use config::{Config, Finality, PauseBehavior, Signer};
+use contracts::{foreign, home};
+use database::{Database, DeadWithdraw, DeepReorg, DepositCallback, DepositReceipt, ObservedDay,
+               ObservedWithdraw, OutboxEntry, QuarantinedDeposit, RawLog, SharedDatabase,
+               SigningLatency, SkippedDeposit, SpendLog, WatchedExecution, WithdrawAttempts,
+               WithdrawOrigin};
+use error::{Error, Result, ResultExt};
+use finality::FinalizedBlock;
+use outbox::{Notification, OutboxStore, OutboxWorkers};
+use tunables::Tunables;
+use util::event_id;
+use self::spend_limit::unix_time;
 
-pub use self::deploy::{DeployForeign, DeployHome};
+#[cfg(feature = "admin-api")]
+pub use self::admin_api::{admin_handler, serve_admin};
+pub use self::audit::{audit, create_audit, Audit, AuditRecipient, AuditedDeposit, AuditedWithdraw};
+pub use self::authority_check::{create_authority_monitor, fetch_authority_set,
+                                is_not_an_authority, Authorization, AuthorityDiff,
+                                AuthorityMonitor, AuthoritySet, Detection, FetchAuthoritySet,
+                                NotAnAuthority};
+pub use self::bootstrap::{bootstrap_state_path, create_bootstrap, write_bootstrap_files, Bootstrap,
+                         BootstrapChain, BootstrapPlan, BootstrapProgress, DeployStep,
+                         FundingStep};
+pub use self::call_data::{verify_call_data, ExpectedCall};
+pub use self::catch_up::{estimate_catch_up, CatchUpEstimate, CatchUpProgress, ChainBacklog,
+                          EstimateCatchUp};
+pub use self::chain_clock::{create_chain_clock_monitor, ChainClock, ChainClockMonitor,
+                            ChainClocks, SharedChainClock, KEPT_HEADERS};
+pub use self::chain_stall::{create_chain_stall_monitor, BlockAge, BlockAges, BlockProgress,
+                            ChainStallMonitor, StallChange};
+pub use self::circuit_breaker::{CircuitBreaker, CircuitBreakers, CircuitState};
+pub use self::command::{command_channel, BridgeCommand, CommandReceiver, CommandReply,
+                        CommandRequest, CommandSender};
+pub use self::clock_drift::{create_clock_drift_monitor, ClockDrift, ClockDriftMonitor, Drift};
+pub use self::contract_recipients::{RecipientKind, RecipientKinds, KEPT_RECIPIENTS};
+pub use self::deploy::{deploy_record_dir, deploy_record_transaction, DeployForeign, DeployHome};
+pub use self::deploy_block::{find_deploy_blocks, DeployBlockSearch, FindDeployBlock,
+                             FindDeployBlocks};
+pub use self::deposit_callback::{DepositCallbacks, KEPT_CALLBACKS};
+pub use self::deposit_receipts::{DepositReceipts, COMPLETED_DEPOSIT_EVENT, KEPT_RECEIPTS};
+pub use self::derived_recipient::{check_wallet_factory, create2_address, derive_recipient,
+                                  foreign_recipient, wallet_address, wallet_salt,
+                                  CheckWalletFactory, DerivedRecipient};
+pub use self::deposit_finalize::{create_deposit_finalize, deposit_hash_payload, signed_deposit_topic,
+                                 DepositFinalize, Finalization, SignedDeposit,
+                                 FINALIZED_FUNCTION, FINALIZE_DEPOSIT_FUNCTION,
+                                 NUM_DEPOSIT_SIGNATURES_FUNCTION, SIGNED_DEPOSIT_EVENT};
 pub use self::deposit_relay::{create_deposit_relay, DepositRelay};
+pub use self::deposit_responsibility::{fallback_delay, is_responsible, responsible_authorities};
+pub use self::digest::{Digest, DigestCounters, DigestGauges, DigestLog, DigestReason};
+pub use self::drain::{Drain, DrainPending, DrainReport, DEFAULT_DRAIN_TIMEOUT_SECS,
+                      DRAINED_EXIT_CODE};
+pub use self::execution_watch::{create_execution_watch, ExecutionWatch};
+pub use self::failure_policy::{failure_kind, with_ambiguous_send_rule, with_default_rules,
+                               with_remote_signer_rule, Decision, FailurePolicy, RuleCount, Verdict,
+                               AMBIGUOUS_SEND_DELAY};
+pub use self::fairness::{create_fairness, responsible_authority, AuthorityAssignments,
+                         CheckFairness, FailedAssignment, Fairness, FairnessRange, Uniformity,
+                         DEFAULT_DEADLINE_BLOCKS};
+pub use self::finality_monitor::{create_finality_monitor, FinalityMonitor};
+pub use self::gas_limit::{check_gas_limits, CheckGasLimits};
+pub use self::gas_price::{check_gas_prices, CheckGasPrices};
+pub use self::gas_price_tiers::{deposit_gas_price, tier_of, tiered_gas_price, TieredGasPrice};
+pub use self::gas_usage::{gas_samples, raised_gas, GasSample, GasUsage, GasWarning, RelayCall,
+                          GAS_PERCENTILE};
+pub use self::held_deposits::{HeldDeposit, HeldDeposits, HeldValue, UNTIL_RELEASED};
+pub use self::history::{find_main_position, relay_key, relay_span, Direction, FindMainPosition,
+                        MainPosition, Page, PositionPage, RelayHistory, RelayRecord};
+#[cfg(feature = "rest-api")]
+pub use self::history_api::{serve_history, PendingPage, SkippedPage};
+pub use self::inclusion_proof::{create_prove_inclusion, header_rlp, inclusion_proof, receipt_rlp,
+                                 verify_inclusion_proof, InclusionProof, ProveInclusion, ProvenLog,
+                                 ProvenReceipt};
+pub use self::invoice::{invoice, read_gas_records, Chain, Day, GasRecord, Invoice, InvoiceLine,
+                        InvoiceTotal, InvoiceTraffic, Prices};
+pub use self::leader::{lease_path, Acquired, LeaderElection, Lease, LeaseFile, Role};
+pub use self::liability::{classify, create_liability_monitor, create_liability_report,
+                          scan_liability, Liability, LiabilityEstimate, LiabilityGauge,
+                          LiabilityMonitor, LiabilityReason, LiabilityReport, LiabilityScan,
+                          LiabilityTotal, ScanLiability, ScannedDeposit, Transition,
+                          UnmintedDeposit};
+pub use self::node_gas_price::{clamp_gas_price, create_node_gas_price_monitor,
+                                fee_history_price, parse_fee_history, FeeHistory,
+                                NodeGasPriceMonitor, SharedGasPrice};
+pub use self::node_version::{create_node_version_monitor, node_endpoint, NodeVersionMonitor,
+                             NodeVersions, RelayNode, NODE_VERSION_INTERVAL_SECS};
+pub use self::observed_traffic::{Observation, ObservedCount, ObservedTraffic, KEPT_OBSERVED_DAYS};
+pub use self::own_signatures::{already_signed, check_stored_signatures, verify_own_signatures,
+                               MismatchAction, SignatureMismatch, StoredSignatures,
+                               VerifiedSignatures, VerifyOwnSignatures};
+pub use self::pause::{create_pause_monitor, target_contract, watch_held_relays, ContractPauses,
+                      HeldRelays, PauseMonitor, PauseState, PAUSED_EVENT, PAUSED_FUNCTION,
+                      UNPAUSED_EVENT};
+pub use self::pending_age::{PendingAges, PendingRelay, PendingRelays};
+pub use self::persistence::{DegradedPersistence, PersistenceChange, PersistenceOutage};
+pub use self::raw_logs::{decode_log, lookup_logs, DecodedLog, LoggedEvent, RawLogs, KEPT_RAW_LOGS,
+                         MAX_QUARANTINED_RAW_LOG_SIZE};
+pub use self::rebuild::{create_rebuild, database_diff, reconcile, scan_logs, Rebuild, ScanLogs,
+                        ScannedLogs};
+pub use self::relay_chain::{verify_chain, ChainEntry, ChainHead, ChainVerification, RelayChain};
+pub use self::relay_events::{verify_relay_events, EventCheck, EventMatch, EventOutcome,
+                             ExpectedEvent, GasRetry, VerifyRelayEvents};
+pub use self::relay_notifications::{relayed_notification, skipped_notification};
+pub use self::relay_outcome::{lookup_deposit, summarize_skipped, DepositLookup, DepositOutcome,
+                              ReasonCount, RelayOutcome, Skip, SkipReason, SkippedDeposits,
+                              SkippedSummary, KEPT_SKIPPED_DEPOSITS};
+pub use self::relayed_messages::{MessageStatus, RelayedMessages, KEPT_MESSAGES};
+pub use self::recent_errors::{RecentError, RecentErrors, KEPT_RECENT_ERRORS};
+pub use self::reorg::{create_reorg_monitor, RelayedLog, ReorgDepths, ReorgIncident, ReorgMonitor};
+pub use self::report::{RelayCounts, Session, ShutdownReport};
+pub use self::revert::{decode_revert_reason, revert_reason, Revert, RevertKind, RevertReason};
+pub use self::safe_confirmations::{check_safe_confirmations, detect_engine,
+                                    get_validators_payload, safe_depth, CheckSafeConfirmations,
+                                    SafeConfirmations, GET_VALIDATORS_FUNCTION};
+pub use self::same_chain::{check_same_chain, sent_by_authority, CheckSameChain};
+pub use self::schedule::{send_ordered_foreign_transactions, QueueStatus};
+pub use self::self_test::{create_self_test, probes, Probe, ProbeKind, ProbeResult, SelfTest,
+                          SelfTestReport};
+pub use self::shared_relays::SharedRelays;
+pub use self::show_message::{create_show_message, message_diff, FieldDiff, MessageSignature,
+                             ShowMessage, ShownMessage, ShownMessages};
+pub use self::signed_command::{command_message, command_signers, severity, sign_command,
+                                CommandAuthorization, SignedParams};
+pub use self::sign_rejections::{create_sign_rejection_monitor, sign_rejected_topic, RejectReason,
+                                SignRejection, SignRejectionMonitor, SIGN_REJECTED_EVENT};
+pub use self::signature_stagger::{authority_slot, signature_delay};
+pub use self::signing_latency::{authority_latencies, authority_latency_report,
+                                create_signing_latency_monitor, signature_topics,
+                                AuthorityLatency, LatencyReport, SignatureOutcome,
+                                SigningLatencies, SigningLatencyMonitor, SubmittedSignature,
+                                KEPT_OBSERVED_WITHDRAWS, KEPT_SIGNING_LATENCIES};
+pub use self::smoke_test::{check_chain_ids, create_smoke_test, SmokeTest, SmokeTestMode,
+                           SmokeTestReport, SmokeTestStep};
+pub use self::spend_limit::{SpendKind, SpendLimiter};
+pub use self::state_horizon::{create_state_horizon_monitor, probe_state_horizon,
+                              ProbeStateHorizon, StateHorizon, StateHorizonMonitor, StateHorizons};
+pub use self::step_timing::{DurationHistogram, RelayStep, StepDurations, StepTimer, StepTimings,
+                            STEP_BUCKETS_MS};
+pub use self::telemetry::{summary_headers, HealthSummary, Telemetry, TelemetryReporter,
+                          AUTHORITY_SIGNATURE_HEADER};
+pub use self::withdraw_attempts::{lookup_withdraw, AttemptLog, WithdrawLookup, KEPT_WITHDRAWS};
 pub use self::withdraw_relay::{create_withdraw_relay, WithdrawRelay};
 pub use self::withdraw_confirm::{create_withdraw_confirm, WithdrawConfirm};
+pub use self::withdraw_cost::{create_withdraw_cost_monitor, WithdrawCost, WithdrawCostMonitor};
+pub use self::withdraw_origins::{WithdrawOrigins, KEPT_WITHDRAW_ORIGINS};
+pub use self::watchdog::{Component, Progress, Watchdog};
+
+/// How often (seconds) the watchdog checks components for progress.
+const WATCHDOG_CHECK_INTERVAL: u64 = 1;
+
+/// How often (seconds) open circuits are checked for elapsed cool down.
+const CIRCUIT_BREAKER_CHECK_INTERVAL: u64 = 1;
+
+/// How often (seconds) components waiting for a rebuild by the failure policy are checked.
+const FAILURE_POLICY_CHECK_INTERVAL: u64 = 1;
+
+/// How often (seconds) the oldest pending relays are updated.
+const PENDING_AGE_CHECK_INTERVAL: u64 = 5;
+
+/// Oversized logs the bridge keeps, the latest ones.
+const KEPT_OVERSIZED_LOGS: usize = 256;
+
+const COMPONENTS: [Component; 3] = [
+    Component::DepositRelay,
+    Component::WithdrawRelay,
+    Component::WithdrawConfirm,
+];
+
+/// components enabled by `enable_deposits` and `enable_withdrawals` of `config`.
+pub fn enabled_components(config: &Config) -> Vec<Component> {
+    COMPONENTS
+        .iter()
+        .cloned()
+        .filter(|component| match *component {
+            Component::DepositRelay => config.enable_deposits,
+            Component::WithdrawRelay | Component::WithdrawConfirm => config.enable_withdrawals,
+        })
+        .collect()
+}
+
+/// `true` if a deep home reorg halts deposit relay, see `ReorgMonitorConfig`.
+fn halts_deposits(config: &Config) -> bool {
+    config.enable_deposits
+        && config
+            .reorg_monitor
+            .as_ref()
+            .map_or(false, |reorg_monitor| reorg_monitor.halt_deposits)
+}
 
 /// Last block checked by the bridge components.
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BridgeChecked {
     DepositRelay(u64),
     WithdrawRelay(u64),
     WithdrawConfirm(u64),
 }
 
-pub trait BridgeBackend {
-    fn save(&mut self, checks: Vec<BridgeChecked>) -> Result<()>;
-}
+/// Updates last checked blocks of `database`.
+fn apply_checks(database: &mut Database, checks: &[BridgeChecked]) {
+    for check in checks {
+        match *check {
+            BridgeChecked::DepositRelay(n) => {
+                database.checked_deposit_relay = n;
+            }
+            BridgeChecked::WithdrawRelay(n) => {
+                database.checked_withdraw_relay = n;
+            }
+            BridgeChecked::WithdrawConfirm(n) => {
+                database.checked_withdraw_confirm = n;
+            }
+        }
+    }
+}
+
+pub trait BridgeBackend {
+    /// applies `checks` and writes the database. the changes are applied even if the write
+    /// fails, so the next `save` that succeeds writes them.
+    fn save(&mut self, checks: Vec<BridgeChecked>) -> Result<()>;
+    /// replaces the relays recorded by the spend limits. written by the next `save`.
+    fn record_spend(&mut self, spend: SpendLog);
+    /// replaces the relayed deposits whose execution isn't final yet. written by the next `save`.
+    fn record_watched_executions(&mut self, executions: Vec<WatchedExecution>);
+    /// replaces the messages of the withdraws relayed last. written by the next `save`.
+    fn record_relayed_withdraws(&mut self, messages: Vec<H256>);
+    /// replaces the deposits quarantined since their recipient rejects the transfer.
+    /// written by the next `save`.
+    fn record_quarantined_deposits(&mut self, deposits: Vec<QuarantinedDeposit>);
+    /// replaces the attempts of withdraws and the withdraws given up. written by the next `save`.
+    fn record_withdraw_attempts(
+        &mut self,
+        attempts: Vec<WithdrawAttempts>,
+        dead: Vec<DeadWithdraw>,
+    );
+    /// replaces the callbacks of recent deposit relays. written by the next `save`.
+    fn record_deposit_callbacks(&mut self, callbacks: Vec<DepositCallback>);
+    /// replaces the receipts of recent completed deposits. written by the next `save`.
+    fn record_deposit_receipts(&mut self, receipts: Vec<DepositReceipt>);
+    /// replaces the recently skipped deposits. written by the next `save`.
+    fn record_skipped_deposits(&mut self, skipped: Vec<SkippedDeposit>);
+    /// replaces the observed traffic by day. written by the next `save`.
+    fn record_observed_traffic(&mut self, days: Vec<ObservedDay>);
+    /// replaces the raw logs of the observed deposits and withdraws. written by the next `save`.
+    fn record_raw_logs(&mut self, logs: Vec<RawLog>);
+    /// replaces the observed withdraws and the signatures on them. written by the next `save`.
+    fn record_signing_latencies(
+        &mut self,
+        observed: Vec<ObservedWithdraw>,
+        latencies: Vec<SigningLatency>,
+    );
+    /// replaces the `Withdraw` events of the withdraws relayed last. written by the next `save`.
+    fn record_withdraw_origins(&mut self, origins: Vec<WithdrawOrigin>);
+    /// replaces the depth of the deepest home reorg and the deep reorg not yet acknowledged.
+    /// written by the next `save`.
+    fn record_reorgs(&mut self, max_depth: Option<u64>, deep_reorg: Option<DeepReorg>);
+    /// replaces the nonce of the last signed admin command and writes the database right away,
+    /// so the command can't be replayed after a crash.
+    fn record_admin_nonce(&mut self, nonce: u64) -> Result<()>;
+    /// the database as last saved, possibly by another instance. replaces the database
+    /// the backend writes to.
+    fn reload(&mut self) -> Result<Database>;
+    /// store of the webhook outbox. written by the outbox whenever it changes, independently
+    /// of `save`.
+    fn outbox_store(&self) -> Box<OutboxStore>;
+}
+
+/// writes the current state of `database` to `path`. `lock` is held while writing,
+/// so writers sharing it write one after the other and the last state wins.
+fn write_database(path: &Path, database: &SharedDatabase, lock: &Mutex<()>) -> Result<()> {
+    let _lock = lock
+        .lock()
+        .expect("no code panics while holding the lock; qed");
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    database.get().save(file)
+}
+
+pub struct FileBackend {
+    path: PathBuf,
+    database: SharedDatabase,
+    /// held while the database file is written, by `save` and by the outbox
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl FileBackend {
+    /// backend writing `init` and the changes to it to `path`.
+    pub fn new(path: PathBuf, init: &Database) -> Self {
+        FileBackend {
+            path,
+            database: SharedDatabase::new(init.clone()),
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// handle to the database the backend writes to.
+    /// can be read concurrently by other components.
+    pub fn database(&self) -> SharedDatabase {
+        self.database.clone()
+    }
+}
+
+/// Outbox in the `webhook_outbox` of the database of a `FileBackend`.
+/// every update writes the whole database.
+pub struct FileOutboxStore {
+    path: PathBuf,
+    database: SharedDatabase,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl OutboxStore for FileOutboxStore {
+    fn entries(&self) -> Vec<OutboxEntry> {
+        self.database.get().webhook_outbox
+    }
+
+    fn update(&mut self, update: &mut FnMut(&mut Vec<OutboxEntry>)) -> Result<()> {
+        self.database
+            .update(|database| update(&mut database.webhook_outbox));
+        write_database(&self.path, &self.database, &self.write_lock)
+    }
+}
+
+impl BridgeBackend for FileBackend {
+    fn save(&mut self, checks: Vec<BridgeChecked>) -> Result<()> {
+        self.database
+            .update(|database| apply_checks(database, &checks));
+        write_database(&self.path, &self.database, &self.write_lock)
+    }
+
+    fn record_spend(&mut self, spend: SpendLog) {
+        self.database.update(|database| database.spend = spend);
+    }
+
+    fn record_watched_executions(&mut self, executions: Vec<WatchedExecution>) {
+        self.database
+            .update(|database| database.watched_executions = executions);
+    }
+
+    fn record_relayed_withdraws(&mut self, messages: Vec<H256>) {
+        self.database
+            .update(|database| database.relayed_withdraws = messages);
+    }
+
+    fn record_quarantined_deposits(&mut self, deposits: Vec<QuarantinedDeposit>) {
+        self.database
+            .update(|database| database.quarantined_deposits = deposits);
+    }
+
+    fn record_withdraw_attempts(
+        &mut self,
+        attempts: Vec<WithdrawAttempts>,
+        dead: Vec<DeadWithdraw>,
+    ) {
+        self.database.update(|database| {
+            database.withdraw_attempts = attempts;
+            database.dead_withdraws = dead;
+        });
+    }
+
+    fn record_deposit_callbacks(&mut self, callbacks: Vec<DepositCallback>) {
+        self.database
+            .update(|database| database.deposit_callbacks = callbacks);
+    }
+
+    fn record_deposit_receipts(&mut self, receipts: Vec<DepositReceipt>) {
+        self.database
+            .update(|database| database.deposit_receipts = receipts);
+    }
+
+    fn record_skipped_deposits(&mut self, skipped: Vec<SkippedDeposit>) {
+        self.database
+            .update(|database| database.skipped_deposits = skipped);
+    }
+
+    fn record_observed_traffic(&mut self, days: Vec<ObservedDay>) {
+        self.database
+            .update(|database| database.observed_traffic = days);
+    }
+
+    fn record_raw_logs(&mut self, logs: Vec<RawLog>) {
+        self.database.update(|database| database.raw_logs = logs);
+    }
+
+    fn record_signing_latencies(
+        &mut self,
+        observed: Vec<ObservedWithdraw>,
+        latencies: Vec<SigningLatency>,
+    ) {
+        self.database.update(|database| {
+            database.observed_withdraws = observed;
+            database.signing_latencies = latencies;
+        });
+    }
+
+    fn record_withdraw_origins(&mut self, origins: Vec<WithdrawOrigin>) {
+        self.database
+            .update(|database| database.withdraw_origins = origins);
+    }
+
+    fn record_reorgs(&mut self, max_depth: Option<u64>, deep_reorg: Option<DeepReorg>) {
+        self.database.update(|database| {
+            database.max_reorg_depth = max_depth;
+            database.deep_reorg = deep_reorg;
+        });
+    }
+
+    fn record_admin_nonce(&mut self, nonce: u64) -> Result<()> {
+        self.database
+            .update(|database| database.admin_nonce = Some(nonce));
+        write_database(&self.path, &self.database, &self.write_lock)
+    }
+
+    fn reload(&mut self) -> Result<Database> {
+        let loaded = Database::load(&self.path)?;
+        self.database
+            .update(|database| *database = loaded.clone());
+        Ok(loaded)
+    }
+
+    fn outbox_store(&self) -> Box<OutboxStore> {
+        Box::new(FileOutboxStore {
+            path: self.path.clone(),
+            database: self.database.clone(),
+            write_lock: self.write_lock.clone(),
+        })
+    }
+}
+
+enum BridgeStatus {
+    Wait,
+    NextItem(Option<()>),
+}
+
+/// Creates new bridge.
+pub fn create_bridge<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    init: &Database,
+) -> Bridge<T, FileBackend> {
+    let backend = FileBackend::new(app.database_path.clone(), init);
+    create_bridge_backed_by(app, init, backend)
+}
+
+/// Creates new bridge writing to custom backend.
+pub fn create_bridge_backed_by<T: Transport + Clone, F: BridgeBackend>(
+    app: Arc<App<T>>,
+    init: &Database,
+    backend: F,
+) -> Bridge<T, F> {
+    let components = enabled_components(&app.config);
+    let direction = |enabled: bool| if enabled { "enabled" } else { "disabled" };
+    info!(
+        "deposits (home to foreign) {}, withdrawals (foreign to home) {}",
+        direction(app.config.enable_deposits),
+        direction(app.config.enable_withdrawals)
+    );
+
+    let watchdog = app.config.watchdog.as_ref().map(|config| {
+        Watchdog::new(
+            app.timer
+                .interval(Duration::from_secs(WATCHDOG_CHECK_INTERVAL)),
+            Progress::new(&components, config.stall_timeout, Instant::now()),
+        )
+    });
+
+    let mut circuit_breakers = app.config.circuit_breaker.as_ref().map(|config| {
+        CircuitBreakers::new(
+            app.timer
+                .interval(Duration::from_secs(CIRCUIT_BREAKER_CHECK_INTERVAL)),
+            &components,
+            config,
+        )
+    });
+    // deposit relay stays halted across restarts until the deep reorg is acknowledged
+    if let Some(ref reorg) = init.deep_reorg {
+        if halts_deposits(&app.config) {
+            if let Some(ref mut breakers) = circuit_breakers {
+                error!(
+                    "deposit relay is halted by the home reorg of depth {} at block {}. acknowledge the reorg to resume it",
+                    reorg.depth, reorg.fork_block
+                );
+                breakers.force_open(Component::DepositRelay);
+            }
+        }
+    }
+
+    let rules = match app.config.failure_policy {
+        Some(ref config) => config.rules.clone(),
+        None => Vec::new(),
+    };
+    let remote_signer_delay = [&app.config.home, &app.config.foreign]
+        .iter()
+        .filter(|node| node.signer == Signer::Remote)
+        .filter_map(|node| node.remote_signer.as_ref())
+        .map(|remote| remote.retry_delay)
+        .min();
+    let rules = match remote_signer_delay {
+        Some(delay) => with_remote_signer_rule(rules, delay),
+        None => rules,
+    };
+    let failure_policy = FailurePolicy::new(
+        with_default_rules(with_ambiguous_send_rule(rules)),
+        Some(app.timer
+            .interval(Duration::from_secs(FAILURE_POLICY_CHECK_INTERVAL))),
+    );
+
+    let clock_drift = app.config.clock_drift.as_ref().map(|config| {
+        create_clock_drift_monitor(
+            app.clone(),
+            app.timer.interval(config.check_interval),
+            config.max_drift,
+        )
+    });
+
+    let chain_clock = app.config.chain_clock.as_ref().map(|config| {
+        create_chain_clock_monitor(
+            app.clone(),
+            app.timer.interval(config.check_interval),
+            config.clone(),
+        )
+    });
+
+    let node_versions = create_node_version_monitor(
+        app.clone(),
+        app.timer
+            .interval(Duration::from_secs(NODE_VERSION_INTERVAL_SECS)),
+    );
+
+    let node_gas_price = app.config.node_gas_price.as_ref().map(|config| {
+        create_node_gas_price_monitor(
+            app.clone(),
+            app.timer.interval(app.tunables.get().foreign_poll_interval),
+            config.clone(),
+        )
+    });
+
+    let chain_stall = app.config.chain_stall.as_ref().map(|config| {
+        create_chain_stall_monitor(
+            app.clone(),
+            app.timer.interval(config.check_interval),
+            config.stall_timeout,
+        )
+    });
+
+    let withdraw_cost = app.config.withdraw_cost.as_ref().map(|config| {
+        create_withdraw_cost_monitor(
+            app.clone(),
+            app.timer.interval(config.check_interval),
+            config.log_drift,
+            config.min_fee.unwrap_or_default(),
+        )
+    });
+
+    let liability_monitor = app.config.liability.as_ref().map(|config| {
+        create_liability_monitor(
+            app.clone(),
+            init,
+            app.timer.interval(config.check_interval),
+            config.scan_blocks,
+        )
+    });
+
+    // only deposits are relayed from home, so only they wait for its finalized block
+    let finality_monitor = if app.config.home.finality == Finality::Finalized
+        && app.config.enable_deposits
+    {
+        Some(create_finality_monitor(
+            app.clone(),
+            app.timer.interval(app.tunables.get().home_poll_interval),
+        ))
+    } else {
+        None
+    };
+
+    let state_horizon = app.config.state_horizon.as_ref().map(|config| {
+        create_state_horizon_monitor(
+            app.clone(),
+            app.timer.interval(config.check_interval),
+            config.depth,
+        )
+    });
+
+    let sign_rejection_monitor = if app.config.watch_sign_rejections {
+        Some(create_sign_rejection_monitor(app.clone(), init))
+    } else {
+        None
+    };
+
+    let (signing_latency_monitor, signing_latencies) = if app.config.watch_signing_latency {
+        (
+            Some(create_signing_latency_monitor(app.clone(), init)),
+            Some(SigningLatencies::new(init)),
+        )
+    } else {
+        (None, None)
+    };
+
+    let authority_monitor = app.config.authority_check.as_ref().map(|config| {
+        create_authority_monitor(app.clone(), init, app.timer.interval(config.check_interval))
+    });
+
+    let reorg_monitor = app.config.reorg_monitor.as_ref().map(|config| {
+        create_reorg_monitor(
+            app.clone(),
+            app.timer.interval(config.check_interval),
+            config.depth,
+        )
+    });
+
+    let execution_watch = app.config.execution_watch.as_ref().map(|config| {
+        create_execution_watch(
+            app.clone(),
+            app.timer.interval(config.check_interval),
+            config.depth,
+            init.watched_executions.clone(),
+        )
+    });
+
+    let deposit_finalize = match app.config.deposit_finalize {
+        Some(ref config) if app.config.enable_deposits => {
+            Some(create_deposit_finalize(app.clone(), init, config.turn_blocks))
+        }
+        _ => None,
+    };
+
+    let leader_election = app.config
+        .leader_election
+        .as_ref()
+        .map(|config| LeaderElection::new(app.clone(), config));
+
+    let pause_monitor = app.config
+        .contract_pause
+        .as_ref()
+        .map(|_| create_pause_monitor(app.clone(), init));
+
+    let alerts = match app.config.alerts {
+        Some(ref config) => Alerts::from_config(config).unwrap_or_else(|err| {
+            error!("cannot create alert sinks. alerts are only logged: {}", err);
+            Alerts::default()
+        }),
+        None => Alerts::default(),
+    };
+
+    let mut session = Session::new(Instant::now());
+    session.set_disabled(
+        COMPONENTS
+            .iter()
+            .cloned()
+            .filter(|component| !components.contains(component))
+            .collect(),
+    );
+
+    let observed = ObservedTraffic::new(init);
+    let digest_log = DigestLog::new(
+        digest_counters(init, &session, &observed),
+        Instant::now(),
+    );
+
+    let telemetry = app.config.telemetry.as_ref().and_then(|config| {
+        let counters = digest_counters(init, &session, &observed);
+        Telemetry::new(app.clone(), config, counters)
+            .map_err(|err| {
+                error!("cannot create telemetry reporter. health is not reported: {}", err);
+            })
+            .ok()
+    });
+
+    let webhooks = app.config.webhooks.as_ref().and_then(|config| {
+        OutboxWorkers::from_config(config, backend.outbox_store())
+            .map_err(|err| {
+                error!("cannot create webhook endpoints. relays are not notified: {}", err);
+            })
+            .ok()
+    });
+
+    let enable_withdrawals = app.config.enable_withdrawals;
+    Bridge {
+        deposit_relay: if app.config.enable_deposits {
+            let mut relay = create_deposit_relay(app.clone(), init);
+            if let Some(ref monitor) = finality_monitor {
+                relay.watch_home_finality(monitor.shared());
+            }
+            Some(relay)
+        } else {
+            None
+        },
+        withdraw_relay: if enable_withdrawals {
+            Some(create_withdraw_relay(app.clone(), init))
+        } else {
+            None
+        },
+        withdraw_confirm: if enable_withdrawals {
+            Some(create_withdraw_confirm(app.clone(), init))
+        } else {
+            None
+        },
+        components,
+        state: BridgeStatus::Wait,
+        backend,
+        checked: init.clone(),
+        watchdog,
+        clock_drift,
+        drift: ClockDrift::default(),
+        chain_clock,
+        node_gas_price,
+        node_versions,
+        chain_stall,
+        block_ages: None,
+        withdraw_cost_monitor: withdraw_cost,
+        withdraw_cost: None,
+        liability_monitor,
+        liability: Liability::default(),
+        state_horizon_monitor: state_horizon,
+        state_horizons: None,
+        finality_monitor,
+        circuit_breakers,
+        failure_policy,
+        reorg_monitor,
+        incidents: Vec::new(),
+        reorg_depths: ReorgDepths::new(init.max_reorg_depth),
+        quarantined_deposits: init.quarantined_deposits.clone(),
+        oversized_logs: Vec::new(),
+        signature_mismatches: Vec::new(),
+        skipped_deposits: SkippedDeposits::new(init),
+        observed,
+        raw_logs: RawLogs::new(init),
+        gas_usage: app.config.gas_usage.clone().map(GasUsage::new),
+        dead_withdraws: init.dead_withdraws.clone(),
+        execution_watch,
+        deposit_finalize,
+        finalizations: Vec::new(),
+        sign_rejection_monitor,
+        sign_rejections: Vec::new(),
+        signing_latency_monitor,
+        signing_latencies,
+        rotation: None,
+        poll_intervals: poll_intervals(&app.tunables.get()),
+        poll_interval_reloads: Vec::new(),
+        session,
+        history: app.config
+            .relay_history
+            .as_ref()
+            .map(|config| {
+                let relays = SharedRelays::new(config.capacity);
+                relays.set_skipped(init.skipped_deposits.clone());
+                relays
+            }),
+        published_failures: 0,
+        webhooks,
+        relay_chain: None,
+        unchained: Vec::new(),
+        relay_chain_interval: app.config
+            .relay_history
+            .as_ref()
+            .and_then(|config| config.chain.as_ref())
+            .map(|config| app.timer.interval(config.head_interval)),
+        pending_age_interval: app.timer
+            .interval(Duration::from_secs(PENDING_AGE_CHECK_INTERVAL)),
+        oldest_pending: Vec::new(),
+        slo_breached: false,
+        digest_interval: app.config
+            .digest_interval
+            .map(|interval| app.timer.interval(interval)),
+        digest_log,
+        telemetry,
+        catch_up: None,
+        outage: false,
+        leader_election,
+        authority_monitor,
+        authority_diff: None,
+        authorization: Authorization::new(app.config.foreign.account),
+        pauses: pause_monitor.as_ref().map(|_| ContractPauses::default()),
+        pause_monitor,
+        held_relays: Vec::new(),
+        drain_request: None,
+        drain_timeout: Duration::from_secs(DEFAULT_DRAIN_TIMEOUT_SECS),
+        commands: None,
+        drain: None,
+        drain_report: None,
+        persistence: app.config
+            .degraded_persistence
+            .clone()
+            .map(DegradedPersistence::new),
+        persistence_retry: app.config
+            .degraded_persistence
+            .as_ref()
+            .map(|config| app.timer.interval(config.retry_interval)),
+        alerts,
+        app,
+    }
+}
+
+/// counters of the bridge with `checked` blocks, the relay outcomes of `session`
+/// and the events counted by `observed`.
+fn digest_counters(
+    checked: &Database,
+    session: &Session,
+    observed: &ObservedTraffic,
+) -> DigestCounters {
+    DigestCounters {
+        home_block: checked.checked_deposit_relay,
+        foreign_block: checked
+            .checked_withdraw_relay
+            .min(checked.checked_withdraw_confirm),
+        deposit_relay: session.counts(Component::DepositRelay),
+        withdraw_relay: session.counts(Component::WithdrawRelay),
+        withdraw_confirm: session.counts(Component::WithdrawConfirm),
+        rpc_errors: api::rpc_errors(),
+        timeouts: api::timeouts().values().sum(),
+        log_bisections: api::log_bisections(),
+        oversized_logs: api::oversized_logs(),
+        deposits_observed: observed.count(Direction::HomeToForeign).events,
+        withdraws_observed: observed.count(Direction::ForeignToHome).events,
+    }
+}
+
+/// Switch of the authority accounts of the bridge components.
+fn poll_intervals(tunables: &Tunables) -> (Duration, Duration) {
+    (tunables.home_poll_interval, tunables.foreign_poll_interval)
+}
+
+struct Rotation<T: Transport> {
+    app: Arc<App<T>>,
+    /// components still using the old accounts
+    pending: Vec<Component>,
+}
+
+/// Polls `relay` if it's enabled and neither the failure policy nor its circuit holds it.
+/// a failure because the account isn't an authority suspends `relay`, see `Authorization`.
+/// any other failure of `relay` is decided by `failure_policy`. failures it leaves to the built-in
+/// handling are recorded by the circuit breakers instead of returned if they are enabled.
+/// the failed relay is rebuilt by the bridge before it's polled again.
+/// `now` is the time of the timer of the app.
+fn poll_guarded<S>(
+    relay: &mut Option<S>,
+    authorization: &mut Authorization,
+    failure_policy: &mut FailurePolicy,
+    circuit_breakers: &mut Option<CircuitBreakers>,
+    session: &mut Session,
+    alerts: &Alerts,
+    component: Component,
+    now: Instant,
+) -> Poll<Option<u64>, Error>
+where
+    S: Stream<Item = u64, Error = Error>,
+{
+    let relay = match *relay {
+        Some(ref mut relay) => relay,
+        None => return Ok(Async::NotReady),
+    };
+    if !failure_policy.allows(component) {
+        return Ok(Async::NotReady);
+    }
+    if let Some(ref mut breakers) = *circuit_breakers {
+        if !breakers.allows(component, now) {
+            return Ok(Async::NotReady);
+        }
+    }
+
+    let err = match relay.poll() {
+        Err(err) => err,
+        Ok(Async::Ready(Some(block))) => {
+            failure_policy.record_success(component);
+            if let Some(ref mut breakers) = *circuit_breakers {
+                breakers.record_success(component);
+            }
+            return Ok(Async::Ready(Some(block)));
+        }
+        other => return other,
+    };
+
+    if authorization.record_failure(component, &err) {
+        error!("{:?} failed because the account is not an authority: {}", component, err);
+        session.record_failure(component, &err);
+        // make sure the bridge is polled again to report it
+        task::current().notify();
+        return Ok(Async::NotReady);
+    }
+
+    let verdict = failure_policy.decide(component, &err, now);
+    if let Some(severity) = verdict.severity {
+        alerts.alert(Alert::new(
+            severity,
+            "component_failed",
+            format!("{:?}", component),
+            format!(
+                "{:?} failed with: {}. rule {} of failure_policy fired",
+                component, err, verdict.rule
+            ),
+        ));
+    }
+    match verdict.decision {
+        Decision::Fail => {
+            session.record_fatal(component, &err);
+            return Err(err);
+        }
+        Decision::Rebuild { .. } | Decision::Quarantine => {
+            session.record_failure(component, &err);
+            return Ok(Async::NotReady);
+        }
+        Decision::Builtin => {}
+    }
+
+    let breakers = match *circuit_breakers {
+        Some(ref mut breakers) => breakers,
+        None => {
+            session.record_fatal(component, &err);
+            return Err(err);
+        }
+    };
+    error!("{:?} failed: {}", component, err);
+    session.record_failure(component, &err);
+    breakers.record_failure(component, now);
+    if let Some(CircuitState::Open { .. }) = breakers.state(component) {
+        alerts.alert(Alert::new(
+            Severity::Critical,
+            "circuit_open",
+            format!("{:?}", component),
+            format!(
+                "circuit of {:?} opened after failing with: {}. no transactions are sent by it until the cool down elapsed",
+                component, err
+            ),
+        ));
+    }
+    // make sure the bridge is polled again to rebuild the failed relay
+    task::current().notify();
+    Ok(Async::NotReady)
+}
+
+pub struct Bridge<T: Transport, F> {
+    app: Arc<App<T>>,
+    /// `None` if deposits are disabled
+    deposit_relay: Option<DepositRelay<T>>,
+    /// `None` if withdrawals are disabled
+    withdraw_relay: Option<WithdrawRelay<T>>,
+    /// `None` if withdrawals are disabled
+    withdraw_confirm: Option<WithdrawConfirm<T>>,
+    /// enabled components
+    components: Vec<Component>,
+    state: BridgeStatus,
+    backend: F,
+    /// last checked blocks. stalled components are rebuilt from here.
+    checked: Database,
+    watchdog: Option<Watchdog>,
+    clock_drift: Option<ClockDriftMonitor<T>>,
+    /// last measured clock drift. zero until measured.
+    drift: ClockDrift,
+    /// fetches the headers of both chains into `App::chain_clocks`
+    chain_clock: Option<ChainClockMonitor<T>>,
+    /// derives the gas price of foreign into `App::node_gas_price`
+    node_gas_price: Option<NodeGasPriceMonitor<T>>,
+    /// fetches the client versions of both nodes into `App::node_versions`
+    node_versions: NodeVersionMonitor<T>,
+    chain_stall: Option<ChainStallMonitor<T>>,
+    /// last measured block ages. `None` until measured
+    block_ages: Option<BlockAges>,
+    withdraw_cost_monitor: Option<WithdrawCostMonitor<T>>,
+    /// last calculated withdraw cost. `None` until calculated
+    withdraw_cost: Option<WithdrawCost>,
+    /// `None` unless `liability` is configured
+    liability_monitor: Option<LiabilityMonitor<T>>,
+    /// unminted deposits as of the last scan of `liability_monitor`
+    liability: Liability,
+    state_horizon_monitor: Option<StateHorizonMonitor<T>>,
+    /// last probed state horizons. `None` until probed
+    state_horizons: Option<StateHorizons>,
+    /// `None` unless deposits are relayed from finalized home blocks only
+    finality_monitor: Option<FinalityMonitor<T>>,
+    circuit_breakers: Option<CircuitBreakers>,
+    /// decides what happens to failed components. without `failure_policy` timeouts are retried
+    /// and every other failure is left to the circuit breakers, or stops the bridge without them
+    failure_policy: FailurePolicy,
+    reorg_monitor: Option<ReorgMonitor<T>>,
+    /// reorgs of home which orphaned relayed deposits
+    incidents: Vec<ReorgIncident>,
+    /// depths of the reorgs of `incidents`, and of the deepest one ever observed
+    reorg_depths: ReorgDepths,
+    /// deposits not relayed since their recipient contract rejects the transfer,
+    /// including those recorded in the database
+    quarantined_deposits: Vec<QuarantinedDeposit>,
+    /// logs of the relays dropped for their size, the latest `KEPT_OVERSIZED_LOGS` of them
+    oversized_logs: Vec<api::OversizedLog>,
+    /// withdraws whose stored signatures don't recover to this authority since the bridge started
+    signature_mismatches: Vec<SignatureMismatch>,
+    /// deposits recently not relayed and why, including those recorded in the database
+    skipped_deposits: SkippedDeposits,
+    /// deposits and withdraws observed by day, including those recorded in the database
+    observed: ObservedTraffic,
+    /// logs of the deposits and withdraws observed last, including those recorded in the database
+    raw_logs: RawLogs,
+    /// gas used by the relays. `None` if `gas_usage` isn't configured
+    gas_usage: Option<GasUsage>,
+    /// withdraws given up after `withdraw_attempts`, including those recorded in the database
+    dead_withdraws: Vec<DeadWithdraw>,
+    /// `None` if `execution_watch` isn't configured
+    execution_watch: Option<ExecutionWatch<T>>,
+    /// `None` if `deposit_finalize` isn't configured or deposits are disabled
+    deposit_finalize: Option<DepositFinalize<T>>,
+    /// `finalizeDeposit` calls this authority sent since the bridge started
+    finalizations: Vec<Finalization>,
+    sign_rejection_monitor: Option<SignRejectionMonitor<T>>,
+    /// refused calls of all authorities. empty unless `watch_sign_rejections`
+    sign_rejections: Vec<SignRejection>,
+    signing_latency_monitor: Option<SigningLatencyMonitor<T>>,
+    /// withdraws by when they were observed and the signatures on them, including those
+    /// recorded in the database. `None` unless `watch_signing_latency`
+    signing_latencies: Option<SigningLatencies>,
+    rotation: Option<Rotation<T>>,
+    /// poll intervals of home and foreign the components were built with
+    poll_intervals: (Duration, Duration),
+    /// components to rebuild at their next checked block to apply reloaded poll intervals
+    poll_interval_reloads: Vec<Component>,
+    session: Session,
+    /// recent relays, the pending relays, the skipped deposits and the recent errors.
+    /// `None` if the relay history is disabled
+    history: Option<SharedRelays>,
+    /// failures of the session when its recent errors were last published to `history`
+    published_failures: u64,
+    /// outbox of the relay notifications and the threads posting them.
+    /// `None` if `webhooks` isn't configured
+    webhooks: Option<OutboxWorkers>,
+    /// `None` until the chain file is opened by the first poll
+    /// or if `relay_history.chain` isn't configured
+    relay_chain: Option<RelayChain>,
+    /// relays collected since the relay chain was last extended
+    unchained: Vec<RelayRecord>,
+    /// wakes the bridge to write the head of the relay chain
+    relay_chain_interval: Option<Interval>,
+    /// wakes the bridge to update `oldest_pending`
+    pending_age_interval: Interval,
+    /// oldest deposit and withdraw not yet relayed as of the last check
+    oldest_pending: Vec<PendingRelay>,
+    /// `true` while a relay is pending longer than `relay_slo.max_pending_age`
+    slo_breached: bool,
+    /// wakes the bridge to log a digest. `None` if `digest_interval` is disabled
+    digest_interval: Option<Interval>,
+    digest_log: DigestLog,
+    /// reports health summaries to the coordinator. `None` unless `telemetry` is configured
+    telemetry: Option<Telemetry<T>>,
+    /// backlog estimated at startup and when. `None` once it's processed
+    catch_up: Option<(CatchUpEstimate, Instant)>,
+    /// `true` while a circuit is open or a chain is stalled as of the last check
+    outage: bool,
+    /// `None` if `leader_election` isn't configured. the bridge always leads then
+    leader_election: Option<LeaderElection<T>>,
+    authority_monitor: Option<AuthorityMonitor<T>>,
+    /// differences between the authorities of the contracts as of the last check.
+    /// `None` until checked
+    authority_diff: Option<AuthorityDiff>,
+    /// components sending to foreign are suspended while the account isn't an authority
+    authorization: Authorization,
+    /// `None` if `contract_pause` isn't configured
+    pause_monitor: Option<PauseMonitor<T>>,
+    /// pause flags of the contracts as of the last check. `None` if `contract_pause`
+    /// isn't configured. components sending to a paused contract are suspended
+    pauses: Option<ContractPauses>,
+    /// relays held by a pause of the contract they are sent to.
+    /// empty unless `contract_pause.while_paused` is `observe`
+    held_relays: Vec<HeldRelays<T>>,
+    /// fires once the bridge should drain. `None` unless `drain_on` was called or once the drain
+    /// is acknowledged
+    drain_request: Option<oneshot::Receiver<()>>,
+    /// how long a drain waits for the batches in flight, set by `drain_on`
+    drain_timeout: Duration,
+    /// commands executed when the bridge is polled. `None` unless `commands_from` was called
+    /// or once every `CommandSender` is dropped
+    commands: Option<CommandReceiver>,
+    /// `None` until a drain is acknowledged
+    drain: Option<Drain>,
+    /// `None` until the drain completed
+    drain_report: Option<DrainReport>,
+    /// `None` unless `degraded_persistence` is configured. failed saves stop the bridge then
+    persistence: Option<DegradedPersistence>,
+    /// wakes the bridge to retry failed saves. `None` unless `degraded_persistence` is configured
+    persistence_retry: Option<Interval>,
+    /// empty if `alerts` isn't configured
+    alerts: Alerts,
+}
+
+impl<T: Transport, F> Bridge<T, F> {
+    /// backend the bridge saves the checked blocks to.
+    pub fn backend(&self) -> &F {
+        &self.backend
+    }
+
+    /// `false` if `component` is disabled by `enable_deposits` or `enable_withdrawals`.
+    /// a disabled component never makes progress and is never considered stalled.
+    pub fn is_enabled(&self, component: Component) -> bool {
+        self.components.contains(&component)
+    }
+
+    /// pause flags of the contracts as of the last check.
+    /// `None` if `contract_pause` isn't configured.
+    pub fn contract_pauses(&self) -> Option<ContractPauses> {
+        self.pauses
+    }
+
+    /// `true` while `component` holds its transactions because the contract it sends them to
+    /// is paused or its pause flag hasn't been checked yet,
+    /// because the account isn't an authority of the contract
+    /// or because too many saves of the database failed, see `degraded_persistence`.
+    pub fn is_suspended(&self, component: Component) -> bool {
+        self.authorization.suspends(component)
+            || self.pauses
+                .map(|pauses| pauses.suspends(component))
+                .unwrap_or(false)
+            || self.persistence
+                .as_ref()
+                .map_or(false, |persistence| persistence.halts())
+    }
+
+    /// saves failing since the last one that succeeded. `None` while the database is written
+    /// or if `degraded_persistence` isn't configured.
+    pub fn persistence_outage(&self) -> Option<&PersistenceOutage> {
+        self.persistence
+            .as_ref()
+            .and_then(|persistence| persistence.outage())
+    }
+
+    /// why the account isn't an authority of `ForeignBridge`. `None` unless it was
+    /// found not to be one and the authorities haven't listed it since.
+    pub fn not_an_authority(&self) -> Option<&NotAnAuthority> {
+        self.authorization.revoked()
+    }
+
+    /// relays of `component` observed since it was suspended by a pause.
+    /// `None` unless it's suspended by a pause with `contract_pause.while_paused = "observe"`.
+    pub fn held_relays(&self, component: Component) -> Option<u64> {
+        self.held_relays
+            .iter()
+            .find(|held| held.component == component)
+            .map(|held| held.held())
+    }
+
+    /// current state of `component`. `"disabled"` for disabled components.
+    pub fn state_description(&self, component: Component) -> String {
+        let description = match component {
+            Component::DepositRelay => self.deposit_relay
+                .as_ref()
+                .map(|relay| relay.state_description()),
+            Component::WithdrawRelay => self.withdraw_relay
+                .as_ref()
+                .map(|relay| relay.state_description()),
+            Component::WithdrawConfirm => self.withdraw_confirm
+                .as_ref()
+                .map(|relay| relay.state_description()),
+        };
+        let description = description.unwrap_or_else(|| "disabled".into());
+        let suspends = self.is_enabled(component) && self.authorization.suspends(component);
+        let description = match self.authorization.revoked() {
+            Some(revoked) if suspends => format!(
+                "suspended while {:?} is not an authority: {}",
+                revoked.account, description
+            ),
+            _ => description,
+        };
+        let description = if self.failure_policy.is_quarantined(component) {
+            format!("quarantined: {}", description)
+        } else {
+            description
+        };
+        match self.pauses {
+            Some(pauses) if self.is_enabled(component) && pauses.suspends(component) => format!(
+                "suspended while {} is {:?}: {}",
+                target_contract(component),
+                pauses.of_target(component),
+                description
+            ),
+            _ => description,
+        }
+    }
+
+    /// last measured clock drift of both chains.
+    /// should be used to correct ages computed from block timestamps.
+    pub fn clock_drift(&self) -> ClockDrift {
+        self.drift
+    }
+
+    /// time since the block number of either chain last increased and whether it's stalled.
+    /// `None` if chain stall detection is disabled or the block numbers haven't been checked yet.
+    pub fn block_ages(&self) -> Option<BlockAges> {
+        self.block_ages
+    }
+
+    /// last calculated cost on home of relaying a withdraw.
+    /// `None` if withdraw cost monitoring is disabled or the cost hasn't been calculated yet.
+    pub fn withdraw_cost(&self) -> Option<WithdrawCost> {
+        self.withdraw_cost
+    }
+
+    /// unminted deposits as of the last scan.
+    /// `None` if `liability` isn't configured or nothing was scanned yet.
+    pub fn liability(&self) -> Option<LiabilityReport> {
+        self.liability.report()
+    }
+
+    /// blocks below the head both nodes still have the state of.
+    /// `None` if state horizon probing is disabled or the nodes haven't been probed yet.
+    pub fn state_horizons(&self) -> Option<StateHorizons> {
+        self.state_horizons
+    }
+
+    /// last fetched finalized home block. `None` unless `home.finality` is `finalized`.
+    pub fn home_finality(&self) -> Option<FinalizedBlock> {
+        self.finality_monitor
+            .as_ref()
+            .map(|monitor| monitor.shared().get())
+    }
+
+    /// state of the circuit of `component`. `None` if circuit breakers are disabled.
+    pub fn circuit_state(&self, component: Component) -> Option<CircuitState> {
+        self.circuit_breakers
+            .as_ref()
+            .and_then(|breakers| breakers.state(component))
+    }
+
+    /// stops `component` from running until `force_close_circuit` is called.
+    pub fn force_open_circuit(&mut self, component: Component) -> Result<()> {
+        match self.circuit_breakers {
+            Some(ref mut breakers) => breakers.force_open(component),
+            None => bail!("circuit breaker is not configured"),
+        }
+        Ok(())
+    }
+
+    /// closes the circuit of `component` and clears its failures.
+    /// the circuit of deposit relay isn't closed while it's halted by a deep reorg.
+    pub fn force_close_circuit(&mut self, component: Component) -> Result<()> {
+        if component == Component::DepositRelay && self.halted_by_reorg() {
+            bail!("deposit relay is halted by a deep home reorg, acknowledge it with acknowledge-deep-reorg to resume it");
+        }
+        match self.circuit_breakers {
+            Some(ref mut breakers) => breakers.force_close(component),
+            None => bail!("circuit breaker is not configured"),
+        }
+        Ok(())
+    }
+
+    /// times every rule of `failure_policy` fired, followed by the failures no rule matched.
+    pub fn failure_counts(&self) -> Vec<RuleCount> {
+        self.failure_policy.counts()
+    }
+
+    /// components quarantined by `failure_policy`.
+    pub fn quarantined(&self) -> &[Component] {
+        self.failure_policy.quarantined()
+    }
+
+    /// rebuilds quarantined `component` from its last checked block.
+    pub fn release_quarantine(&mut self, component: Component) -> Result<()> {
+        if !self.failure_policy.release(component, self.app.timer.now()) {
+            bail!("{:?} is not quarantined", component);
+        }
+        Ok(())
+    }
+
+    /// transactions waiting to be sent by the components that send to foreign.
+    pub fn relay_queues(&self) -> Vec<(Component, QueueStatus)> {
+        let queues = [
+            (
+                Component::DepositRelay,
+                self.deposit_relay
+                    .as_ref()
+                    .and_then(|relay| relay.queue_status()),
+            ),
+            (
+                Component::WithdrawConfirm,
+                self.withdraw_confirm
+                    .as_ref()
+                    .and_then(|relay| relay.queue_status()),
+            ),
+        ];
+        queues
+            .iter()
+            .filter_map(|&(component, queue)| queue.map(|queue| (component, queue)))
+            .collect()
+    }
+
+    /// summary of the session so far. `error` is the error that stopped the bridge, if any.
+    pub fn shutdown_report(&mut self, error: Option<&Error>) -> ShutdownReport {
+        self.collect_counts();
+        let mut report = self.session.report(&self.checked, error);
+        report.not_an_authority = self.authorization.revoked().map(NotAnAuthority::description);
+        report
+    }
+
+    /// drains the bridge once `request` fires, e.g. before an upgrade: no component takes new
+    /// logs anymore and the bridge stream ends once every component finished its batch in flight,
+    /// or after `timeout`. see `Drain`.
+    /// a `BridgeCommand::Drain` drains the bridge with the same timeout.
+    pub fn drain_on(&mut self, request: oneshot::Receiver<()>, timeout: Duration) {
+        self.drain_request = Some(request);
+        self.drain_timeout = timeout;
+    }
+
+    /// `true` once a drain is acknowledged.
+    pub fn is_draining(&self) -> bool {
+        self.drain.is_some()
+    }
+
+    /// `true` once `component` finished its batch in flight during a drain.
+    pub fn is_drained(&self, component: Component) -> bool {
+        self.drain
+            .as_ref()
+            .map_or(false, |drain| drain.is_drained(component))
+    }
+
+    /// report of the completed drain. `None` unless the bridge stream ended after a drain.
+    pub fn drain_report(&self) -> Option<&DrainReport> {
+        self.drain_report.as_ref()
+    }
+
+    /// acknowledges a drain unless the bridge is draining already.
+    fn start_drain(&mut self) {
+        if self.drain.is_some() {
+            return;
+        }
+        self.drain_request = None;
+        warn!(
+            "drain acknowledged. no new logs are taken, the batches in flight are finished for up to {}s",
+            self.drain_timeout.as_secs()
+        );
+        self.drain = Some(Drain::new(&self.app.timer, self.drain_timeout));
+    }
+
+    /// whether the bridge drains, the timeout and the components drained so far.
+    fn drain_status(&self) -> Value {
+        match self.drain {
+            Some(ref drain) => json!({
+                "draining": true,
+                "timeout": drain.timeout().as_secs(),
+                "drained": drain
+                    .drained()
+                    .iter()
+                    .map(|component| format!("{:?}", component))
+                    .collect::<Vec<_>>(),
+            }),
+            None => json!({ "draining": false }),
+        }
+    }
+
+    /// acknowledges a requested drain and drains the components, including those rebuilt since.
+    fn check_drain(&mut self) {
+        if self.drain.is_none() {
+            let polled = match self.drain_request {
+                Some(ref mut request) => request.poll(),
+                None => return,
+            };
+            match polled {
+                Ok(Async::Ready(())) => self.start_drain(),
+                Ok(Async::NotReady) => return,
+                // no drain can be requested anymore
+                Err(_) => {
+                    self.drain_request = None;
+                    return;
+                }
+            }
+        }
+        if let Some(ref mut relay) = self.deposit_relay {
+            relay.drain();
+        }
+        if let Some(ref mut relay) = self.withdraw_relay {
+            relay.drain();
+        }
+        if let Some(ref mut relay) = self.withdraw_confirm {
+            relay.drain();
+        }
+    }
+
+    /// a component polled during a drain ends once it finished its batch in flight.
+    fn end_drained(
+        &mut self,
+        component: Component,
+        polled: Poll<Option<u64>, Error>,
+    ) -> Poll<Option<u64>, Error> {
+        match (polled, self.drain.as_mut()) {
+            (Ok(Async::Ready(None)), Some(drain)) => {
+                drain.finish(component);
+                Ok(Async::NotReady)
+            }
+            (polled, _) => polled,
+        }
+    }
+
+    /// `true` while a drain waits for `component`: it's neither drained nor held by a pause,
+    /// the authorization, the failure policy, an open circuit or another leader.
+    fn drain_waits_for(&self, component: Component) -> bool {
+        let exists = match component {
+            Component::DepositRelay => self.deposit_relay.is_some(),
+            Component::WithdrawRelay => self.withdraw_relay.is_some(),
+            Component::WithdrawConfirm => self.withdraw_confirm.is_some(),
+        };
+        let circuit_open = match self.circuit_state(component) {
+            Some(CircuitState::Open { .. }) | Some(CircuitState::ForcedOpen) => true,
+            _ => false,
+        };
+        exists && !self.is_drained(component) && self.role() == Role::Leader
+            && !self.is_suspended(component) && self.failure_policy.allows(component)
+            && !circuit_open
+    }
+
+    /// components not drained, left at their checked block.
+    fn drain_pending(&self) -> Vec<DrainPending> {
+        let queues = self.relay_queues();
+        self.components
+            .iter()
+            .filter(|&&component| !self.is_drained(component))
+            .map(|&component| DrainPending {
+                component: format!("{:?}", component),
+                checked_block: match component {
+                    Component::DepositRelay => self.checked.checked_deposit_relay,
+                    Component::WithdrawRelay => self.checked.checked_withdraw_relay,
+                    Component::WithdrawConfirm => self.checked.checked_withdraw_confirm,
+                },
+                state: self.state_description(component),
+                queued: queues
+                    .iter()
+                    .find(|&&(queued, _)| queued == component)
+                    .map_or(0, |&(_, queue)| queue.depth),
+            })
+            .collect()
+    }
+
+    /// spend limiter of `kind`. `None` if the limit isn't configured or its component is disabled.
+    /// lists the keys (see `relay_key`) of the deferred relays.
+    pub fn spend_limiter(&self, kind: SpendKind) -> Option<&SpendLimiter> {
+        match kind {
+            SpendKind::Deposits => self.deposit_relay
+                .as_ref()
+                .and_then(|relay| relay.spend_limiter()),
+            SpendKind::Withdraws => self.withdraw_relay
+                .as_ref()
+                .and_then(|relay| relay.spend_limiter()),
+        }
+    }
+
+    fn spend_limiter_mut(&mut self, kind: SpendKind) -> Result<&mut SpendLimiter> {
+        let limiter = match kind {
+            SpendKind::Deposits => self.deposit_relay
+                .as_mut()
+                .and_then(|relay| relay.spend_limiter_mut()),
+            SpendKind::Withdraws => self.withdraw_relay
+                .as_mut()
+                .and_then(|relay| relay.spend_limiter_mut()),
+        };
+        match limiter {
+            Some(limiter) => Ok(limiter),
+            None => bail!("spend limit of {:?} is not configured", kind),
+        }
+    }
+
+    /// changes the spend limit of `kind` until the bridge restarts.
+    /// deferred relays are relayed at the next check of the limit if they fit.
+    pub fn set_spend_limit(&mut self, kind: SpendKind, limit: U256) -> Result<()> {
+        let limiter = self.spend_limiter_mut(kind)?;
+        info!(
+            "changing spend limit of {:?} from {} to {}",
+            kind,
+            limiter.limit(),
+            limit
+        );
+        limiter.set_limit(limit);
+        Ok(())
+    }
+
+    /// relays the deferred relay with `key` (see `relay_key`) at the next check of the
+    /// spend limit of `kind` regardless of the limit.
+    pub fn release_deferred(&mut self, kind: SpendKind, key: H256) -> Result<()> {
+        if !self.spend_limiter_mut(kind)?.release(key) {
+            bail!("no relay with key {:?} is deferred by the spend limit of {:?}", key, kind);
+        }
+        info!("released relay with key {:?} from the spend limit of {:?}", key, kind);
+        Ok(())
+    }
+
+    /// relays the deposit with `key` (see `relay_key`) held for its `manual` recipient of
+    /// `recipient_policies`. it goes through every later check, like the spend limit.
+    pub fn release_held_deposit(&mut self, key: H256) -> Result<()> {
+        match self.deposit_relay {
+            Some(ref mut relay) => relay.release_held(&key)?,
+            None => bail!("deposit relay isn't running"),
+        }
+        self.publish_pending();
+        Ok(())
+    }
+
+    /// deposits held by `recipient_policies` by recipient. empty if deposit relay isn't running.
+    pub fn held_values(&self) -> Vec<HeldValue> {
+        self.deposit_relay
+            .as_ref()
+            .map_or_else(Vec::new, |relay| relay.held_values())
+    }
+
+    /// limit of `kind`, the amount spent within its window and the keys of the deferred relays.
+    /// amounts are decimal strings.
+    fn spend_status(&mut self, kind: SpendKind) -> Result<Value> {
+        let now = unix_time();
+        let limiter = self.spend_limiter_mut(kind)?;
+        Ok(json!({
+            "limit": limiter.limit().to_string(),
+            "spent": limiter.spent(now).to_string(),
+            "deferred": limiter.deferred(),
+        }))
+    }
+
+    /// relays recorded by the spend limiters.
+    /// kinds without a limiter keep the relays recorded in the database.
+    fn spend_log(&self) -> SpendLog {
+        let mut spend = self.checked.spend.clone();
+        if let Some(limiter) = self.spend_limiter(SpendKind::Deposits) {
+            spend.deposits = limiter.to_spend();
+        }
+        if let Some(limiter) = self.spend_limiter(SpendKind::Withdraws) {
+            spend.withdraws = limiter.to_spend();
+        }
+        spend
+    }
+
+    /// handle to the recent relays of deposits and withdraws and to the relays not relayed yet,
+    /// as served by `serve_history`. `None` if the relay history is disabled.
+    /// `relay_queues` lists the transactions not yet sent.
+    pub fn relay_history(&self) -> Option<SharedRelays> {
+        self.history.clone()
+    }
+
+    /// moves the relays of deposit relay and withdraw relay into the history
+    /// and writes their notifications to the webhook outbox.
+    fn collect_history(&mut self) {
+        if self.history.is_none() && self.webhooks.is_none() {
+            return;
+        }
+        let mut records = Vec::new();
+        if let Some(ref mut relay) = self.deposit_relay {
+            records.extend(relay.take_history());
+        }
+        if let Some(ref mut relay) = self.withdraw_relay {
+            records.extend(relay.take_history());
+        }
+        if records.is_empty() {
+            return;
+        }
+        let notifications = records.iter().map(relayed_notification).collect::<Vec<_>>();
+        self.notify(&notifications);
+        if let Some(ref history) = self.history {
+            if self.relay_chain_interval.is_some() {
+                self.unchained.extend(records.iter().cloned());
+            }
+            history.extend(records);
+        }
+    }
+
+    /// writes `notifications` to the webhook outbox, if `webhooks` is configured.
+    fn notify(&self, notifications: &[Notification]) {
+        let webhooks = match self.webhooks {
+            Some(ref webhooks) if !notifications.is_empty() => webhooks,
+            _ => return,
+        };
+        if let Err(err) = webhooks.outbox().enqueue(notifications, unix_time()) {
+            error!(
+                "cannot write {} relay notifications to the outbox: {}",
+                notifications.len(),
+                err
+            );
+        }
+    }
+
+    /// deposits not relayed since their recipient contract on foreign rejects the transfer.
+    /// they are kept until removed from the database, e.g. once recovered on home.
+    pub fn quarantined_deposits(&self) -> &[QuarantinedDeposit] {
+        &self.quarantined_deposits
+    }
+
+    /// moves the deposits quarantined by deposit relay into `quarantined_deposits`
+    /// and alerts each of them.
+    fn collect_quarantined(&mut self) {
+        let mut quarantined = match self.deposit_relay {
+            Some(ref mut relay) => relay.take_quarantined(),
+            None => return,
+        };
+        // the logs of the deposits were observed before they were quarantined
+        self.collect_observations();
+        for deposit in &mut quarantined {
+            self.raw_logs.attach(deposit);
+        }
+        for deposit in &quarantined {
+            let recipient = match deposit.original_recipient {
+                Some(original) => {
+                    format!("{:?} (contract wallet of {:?})", deposit.recipient, original)
+                }
+                None => format!("{:?}", deposit.recipient),
+            };
+            self.alerts.alert(Alert::new(
+                Severity::Critical,
+                "recipient_rejects_transfer",
+                format!("{:?}", deposit.home_transaction),
+                format!(
+                    "contract {} receiving the deposit of {} in home transaction {:?} rejects the transfer: {}. the deposit is quarantined and must be recovered through the recovery path of the contracts",
+                    recipient, deposit.value, deposit.home_transaction, deposit.reason
+                ),
+            ));
+        }
+        self.quarantined_deposits.extend(quarantined);
+    }
+
+    /// logs dropped by the log streams of the relays since the bridge started because their
+    /// data exceeded `max_log_data`. they are never decoded, let alone relayed.
+    pub fn oversized_logs(&self) -> &[api::OversizedLog] {
+        &self.oversized_logs
+    }
+
+    /// moves the logs the relays dropped for their size into `oversized_logs`,
+    /// counts and alerts each of them.
+    fn collect_oversized_logs(&mut self) {
+        let oversized = [
+            (
+                Component::DepositRelay,
+                self.deposit_relay
+                    .as_mut()
+                    .map(|relay| relay.take_oversized_logs()),
+            ),
+            (
+                Component::WithdrawRelay,
+                self.withdraw_relay
+                    .as_mut()
+                    .map(|relay| relay.take_oversized_logs()),
+            ),
+            (
+                Component::WithdrawConfirm,
+                self.withdraw_confirm
+                    .as_mut()
+                    .map(|relay| relay.take_oversized_logs()),
+            ),
+        ];
+        for &(component, ref logs) in &oversized {
+            let logs = match *logs {
+                Some(ref logs) => logs,
+                None => continue,
+            };
+            self.session.counts_mut(component).oversized_logs += logs.len() as u64;
+            for log in logs {
+                self.alerts.alert(Alert::new(
+                    Severity::Warning,
+                    "oversized_log",
+                    format!("{:?}", log.address),
+                    format!(
+                        "{:?} dropped a log of {:?} with {} bytes of data in transaction {:?} of block {:?} without decoding it",
+                        component, log.address, log.size, log.transaction_hash, log.block
+                    ),
+                ));
+                if self.oversized_logs.len() == KEPT_OVERSIZED_LOGS {
+                    self.oversized_logs.remove(0);
+                }
+                self.oversized_logs.push(log.clone());
+            }
+        }
+    }
+
+    /// withdraws this authority already signed, none of whose stored signatures recovers to its
+    /// account, found by withdraw confirm since the bridge started. empty unless
+    /// `verify_own_signatures` is enabled.
+    pub fn signature_mismatches(&self) -> &[SignatureMismatch] {
+        &self.signature_mismatches
+    }
+
+    /// moves the signature mismatches found by withdraw confirm into `signature_mismatches`
+    /// and alerts each of them.
+    fn collect_signature_mismatches(&mut self) {
+        let mismatches = match self.withdraw_confirm {
+            Some(ref mut confirm) => confirm.take_signature_mismatches(),
+            None => return,
+        };
+        let account = self.app.config.foreign.account;
+        for mismatch in &mismatches {
+            self.alerts.alert(Alert::new(
+                Severity::Critical,
+                "own_signature_mismatch",
+                format!("{:?}", mismatch.withdraw),
+                mismatch.description(account),
+            ));
+        }
+        self.signature_mismatches.extend(mismatches);
+    }
+
+    /// records the gas used by the relays checked since the last call and alerts the relays
+    /// that near their gas limit or ran out of gas.
+    fn collect_gas_usage(&mut self) {
+        let gas_usage = match self.gas_usage {
+            Some(ref mut gas_usage) => gas_usage,
+            None => return,
+        };
+        let mut samples = Vec::new();
+        if let Some(ref mut relay) = self.deposit_relay {
+            samples.extend(relay.take_gas_samples());
+        }
+        if let Some(ref mut confirm) = self.withdraw_confirm {
+            samples.extend(confirm.take_gas_samples());
+        }
+        if let Some(ref mut relay) = self.withdraw_relay {
+            samples.extend(relay.take_gas_samples());
+        }
+        for warning in gas_usage.record(&samples) {
+            warn!(target: "bridge", "{}", warning.description());
+            self.alerts.alert(Alert::new(
+                warning.severity(),
+                warning.kind(),
+                warning.key(),
+                warning.description(),
+            ));
+        }
+    }
+
+    /// withdraws given up since a phase was attempted as often as `withdraw_attempts` allows.
+    /// they are kept until removed from the database.
+    pub fn dead_withdraws(&self) -> &[DeadWithdraw] {
+        &self.dead_withdraws
+    }
+
+    /// moves the withdraws given up by the withdraw components into `dead_withdraws`,
+    /// alerts each of them and records the attempts if they changed since the last save.
+    /// `true` if they did.
+    fn collect_withdraw_attempts(&mut self) -> bool {
+        if self.app.config.withdraw_attempts.is_none() {
+            return false;
+        }
+        let mut dead = Vec::new();
+        let mut attempts = Vec::new();
+        if let Some(ref mut confirm) = self.withdraw_confirm {
+            dead.extend(confirm.attempt_log_mut().take_dead());
+            attempts.extend(confirm.attempt_log().withdraws().iter().cloned());
+        }
+        if let Some(ref mut relay) = self.withdraw_relay {
+            dead.extend(relay.attempt_log_mut().take_dead());
+            attempts.extend(relay.attempt_log().withdraws().iter().cloned());
+        }
+        for withdraw in &dead {
+            self.alerts.alert(Alert::new(
+                Severity::Critical,
+                "withdraw_attempts_exceeded",
+                format!("{:?}", withdraw.withdraw),
+                format!(
+                    "withdraw in foreign transaction {:?} is given up after {} attempts. its {:?} phase must be completed manually",
+                    withdraw.withdraw,
+                    withdraw.attempts.len(),
+                    withdraw.phase
+                ),
+            ));
+        }
+        self.dead_withdraws.extend(dead);
+        if attempts == self.checked.withdraw_attempts
+            && self.dead_withdraws == self.checked.dead_withdraws
+        {
+            return false;
+        }
+        self.checked.withdraw_attempts = attempts.clone();
+        self.checked.dead_withdraws = self.dead_withdraws.clone();
+        self.backend
+            .record_withdraw_attempts(attempts, self.dead_withdraws.clone());
+        true
+    }
+
+    /// moves the outcomes of the deposits deposit relay decided into `skipped_deposits`.
+    fn collect_outcomes(&mut self) {
+        let outcomes = match self.deposit_relay {
+            Some(ref mut relay) => relay.take_outcomes(),
+            None => return,
+        };
+        if outcomes.is_empty() {
+            return;
+        }
+        let notifications = outcomes.iter().filter_map(skipped_notification).collect::<Vec<_>>();
+        self.notify(&notifications);
+        let now = unix_time();
+        for outcome in outcomes {
+            self.skipped_deposits.record(outcome, now);
+        }
+        self.publish_skipped();
+    }
+
+    /// updates the skipped deposits the history api serves.
+    fn publish_skipped(&self) {
+        if let Some(ref history) = self.history {
+            history.set_skipped(self.skipped_deposits.skipped().to_vec());
+        }
+    }
+
+    /// records the skipped deposits if they changed since the last save.
+    fn record_skipped_deposits(&mut self) {
+        self.collect_outcomes();
+        let skipped = self.skipped_deposits.skipped();
+        if skipped != &self.checked.skipped_deposits[..] {
+            self.checked.skipped_deposits = skipped.to_vec();
+            self.backend.record_skipped_deposits(skipped.to_vec());
+        }
+    }
+
+    /// moves the events deposit relay and withdraw confirm observed into `observed`.
+    fn collect_observations(&mut self) {
+        let mut observations = Vec::new();
+        if let Some(ref mut relay) = self.deposit_relay {
+            observations.extend(relay.take_observations());
+        }
+        if let Some(ref mut confirm) = self.withdraw_confirm {
+            observations.extend(confirm.take_observations());
+        }
+        let now = unix_time();
+        for observation in observations {
+            if observation.direction == Direction::ForeignToHome {
+                if let Some(ref mut relay) = self.withdraw_relay {
+                    let event = &observation.event;
+                    relay.observe_withdraw(event.transaction, event.block, event.log_index);
+                }
+            }
+            if let Some(ref mut latencies) = self.signing_latencies {
+                if observation.direction == Direction::ForeignToHome {
+                    let event = &observation.event;
+                    let unique = self.app.config.unique_log_ids;
+                    if let Ok(id) = event_id(event.transaction, event.log_index, unique) {
+                        latencies.observe(id, now);
+                    }
+                }
+            }
+            let log = observation.log.clone();
+            if self.observed.observe(observation, now) {
+                self.raw_logs.record(log);
+            }
+        }
+    }
+
+    /// records the observed traffic, the raw logs and the signing latencies if they changed
+    /// since the last save.
+    fn record_observed_traffic(&mut self) {
+        self.collect_observations();
+        self.observed.checked(
+            self.checked.checked_deposit_relay,
+            self.checked.checked_withdraw_confirm,
+        );
+        let days = self.observed.days();
+        if days != &self.checked.observed_traffic[..] {
+            self.checked.observed_traffic = days.to_vec();
+            self.backend.record_observed_traffic(days.to_vec());
+        }
+        let logs = self.raw_logs.logs();
+        if logs != &self.checked.raw_logs[..] {
+            self.checked.raw_logs = logs.to_vec();
+            self.backend.record_raw_logs(logs.to_vec());
+        }
+        if let Some(ref latencies) = self.signing_latencies {
+            let changed = latencies.observed_withdraws() != &self.checked.observed_withdraws[..]
+                || latencies.latencies() != &self.checked.signing_latencies[..];
+            if changed {
+                self.checked.observed_withdraws = latencies.observed_withdraws().to_vec();
+                self.checked.signing_latencies = latencies.latencies().to_vec();
+                self.backend.record_signing_latencies(
+                    latencies.observed_withdraws().to_vec(),
+                    latencies.latencies().to_vec(),
+                );
+            }
+        }
+    }
+
+    /// records the `Withdraw` events of the withdraws relayed last if they changed since the last
+    /// save. observed withdraws are collected first.
+    fn record_withdraw_origins(&mut self) {
+        self.collect_observations();
+        let origins = self.withdraw_relay
+            .as_ref()
+            .and_then(|relay| relay.withdraw_origins())
+            .map(|origins| origins.origins().to_vec());
+        if let Some(origins) = origins {
+            if origins != self.checked.withdraw_origins {
+                self.checked.withdraw_origins = origins.clone();
+                self.backend.record_withdraw_origins(origins);
+            }
+        }
+    }
+
+    /// alerts the deposit callbacks given up by deposit relay and records the callbacks
+    /// if they changed since the last save.
+    fn collect_deposit_callbacks(&mut self) {
+        let callbacks = match self.deposit_relay {
+            Some(ref mut relay) => {
+                for callback in relay.callbacks_mut().take_given_up() {
+                    self.alerts.alert(Alert::new(
+                        Severity::Warning,
+                        "deposit_callback_given_up",
+                        format!("{:?}", callback.relay_transaction),
+                        format!(
+                            "callback of the deposit in home transaction {:?} relayed in foreign transaction {:?} is given up after {} attempts: {}",
+                            callback.home_transaction,
+                            callback.relay_transaction,
+                            callback.attempts,
+                            callback.error.as_ref().map_or("", |error| error.as_str())
+                        ),
+                    ));
+                }
+                relay.callbacks().callbacks().to_vec()
+            }
+            None => return,
+        };
+        if self.app.config.deposit_callback.is_some()
+            && callbacks != self.checked.deposit_callbacks
+        {
+            self.checked.deposit_callbacks = callbacks.clone();
+            self.backend.record_deposit_callbacks(callbacks);
+        }
+    }
+
+    /// alerts the deposit receipts given up by deposit relay and records the receipts
+    /// if they changed since the last save.
+    fn collect_deposit_receipts(&mut self) {
+        let receipts = match self.deposit_relay {
+            Some(ref mut relay) => {
+                for receipt in relay.receipts_mut().take_given_up() {
+                    self.alerts.alert(Alert::new(
+                        Severity::Warning,
+                        "deposit_receipt_given_up",
+                        format!("{:?}", receipt.home_transaction),
+                        format!(
+                            "receipt of the deposit in home transaction {:?} completed in foreign transaction {:?} is given up after {} attempts: {}",
+                            receipt.home_transaction,
+                            receipt.relay_transaction,
+                            receipt.attempts,
+                            receipt.error.as_ref().map_or("", |error| error.as_str())
+                        ),
+                    ));
+                }
+                relay.receipts().receipts().to_vec()
+            }
+            None => return,
+        };
+        if self.app.config.receipt_registry.is_some()
+            && receipts != self.checked.deposit_receipts
+        {
+            self.checked.deposit_receipts = receipts.clone();
+            self.backend.record_deposit_receipts(receipts);
+        }
+    }
+
+    /// appends the collected relays to the relay chain
+    /// and writes its head every `relay_history.chain.head_interval`.
+    fn check_relay_chain(&mut self) -> Result<()> {
+        let config = match self.app.config.relay_history {
+            Some(ref history) => match history.chain {
+                Some(ref chain) => chain.clone(),
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        let mut due = false;
+        if self.relay_chain.is_none() {
+            let chain = RelayChain::open(&config.file, &config.head_file)?;
+            info!("continuing relay chain {:?}", config.file);
+            self.relay_chain = Some(chain);
+            due = true;
+        }
+        if let Some(ref mut interval) = self.relay_chain_interval {
+            while let Async::Ready(Some(())) = interval.poll()? {
+                due = true;
+            }
+        }
+        if let Some(ref mut chain) = self.relay_chain {
+            chain.extend(self.unchained.iter());
+            self.unchained.clear();
+            chain.flush()?;
+        }
+        if due {
+            self.write_relay_chain_head()?;
+        }
+        Ok(())
+    }
+
+    /// writes the relays collected so far to the relay chain and appends its head to
+    /// `relay_history.chain.head_file`, sending it as an alert if `publish_head` is enabled.
+    /// call before shutdown so the last head covers all relays.
+    pub fn write_relay_chain_head(&mut self) -> Result<()> {
+        if self.relay_chain.is_none() {
+            return Ok(());
+        }
+        self.collect_history();
+        let head = match self.relay_chain {
+            Some(ref mut chain) => {
+                chain.extend(self.unchained.iter());
+                self.unchained.clear();
+                chain.write_head(unix_time())?
+            }
+            None => None,
+        };
+        let head = match head {
+            Some(head) => head,
+            None => return Ok(()),
+        };
+        info!(
+            target: "bridge::relay_chain",
+            "relay chain head after {} relays is {:?}",
+            head.records,
+            head.hash
+        );
+        let publish = self.app
+            .config
+            .relay_history
+            .as_ref()
+            .and_then(|config| config.chain.as_ref())
+            .map_or(false, |config| config.publish_head);
+        if publish {
+            self.alerts.alert(Alert::new(
+                Severity::Info,
+                "relay_chain_head",
+                "relay_chain",
+                format!(
+                    "relay chain head after {} relays is {:?}",
+                    head.records, head.hash
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// moves the relay outcomes of all components into the session.
+    fn collect_counts(&mut self) {
+        let counts = [
+            (
+                Component::DepositRelay,
+                self.deposit_relay
+                    .as_mut()
+                    .map(|relay| relay.take_counts()),
+            ),
+            (
+                Component::WithdrawRelay,
+                self.withdraw_relay
+                    .as_mut()
+                    .map(|relay| relay.take_counts()),
+            ),
+            (
+                Component::WithdrawConfirm,
+                self.withdraw_confirm
+                    .as_mut()
+                    .map(|relay| relay.take_counts()),
+            ),
+        ];
+        for &(component, counts) in &counts {
+            if let Some(counts) = counts {
+                self.session.counts_mut(component).add(counts);
+            }
+        }
+        if let Some(ref mut relay) = self.withdraw_relay {
+            self.session.record_relay_assignments(relay.take_assignments());
+        }
+    }
+
+    /// oldest deposit and oldest signed withdraw observed but not yet relayed,
+    /// updated every few seconds. empty if everything up to the checked blocks is relayed.
+    /// ages count from the timestamps of their blocks, see `PendingAges`.
+    pub fn oldest_pending_relays(&self) -> &[PendingRelay] {
+        &self.oldest_pending
+    }
+
+    /// reports the progress of catching up with the backlog of `estimate` in the digests
+    /// until it's processed.
+    pub fn track_catch_up(&mut self, estimate: CatchUpEstimate) {
+        if !estimate.is_caught_up() {
+            self.catch_up = Some((estimate, Instant::now()));
+        }
+    }
+
+    /// gauges of a digest at the current time.
+    fn digest_gauges(&self) -> DigestGauges {
+        let now = unix_time();
+        let queues = self.relay_queues();
+        let lag = |direction| {
+            self.oldest_pending
+                .iter()
+                .filter(|relay| relay.direction == direction)
+                .map(|relay| relay.age(now))
+                .max()
+        };
+        let queued = |component| {
+            queues
+                .iter()
+                .find(|&&(queued, _)| queued == component)
+                .map_or(0, |&(_, queue)| queue.depth)
+        };
+        DigestGauges {
+            deposit_lag_secs: lag(Direction::HomeToForeign),
+            withdraw_lag_secs: lag(Direction::ForeignToHome),
+            queued_deposits: queued(Component::DepositRelay),
+            queued_signatures: queued(Component::WithdrawConfirm),
+            catch_up: self.catch_up.map(|(estimate, since)| {
+                estimate.progress(&self.checked, since.elapsed())
+            }),
+            foreign_gas_price: self.app.node_gas_price.get(),
+            last_reorg_depth: self.reorg_depths.last,
+            max_reorg_depth: self.reorg_depths.max,
+            unminted: self.liability.gauge(),
+        }
+    }
+
+    /// logs a digest of the activity since the previous one. `None` if `digest_interval`
+    /// is disabled. call before shutdown so the record ends with the last activity.
+    pub fn digest(&mut self, reason: DigestReason) -> Option<Digest> {
+        if self.digest_interval.is_none() {
+            return None;
+        }
+        self.collect_counts();
+        let gauges = self.digest_gauges();
+        if let Some(CatchUpProgress {
+            home_blocks_left: 0,
+            foreign_blocks_left: 0,
+            ..
+        }) = gauges.catch_up
+        {
+            info!("caught up with the backlog of the catch-up estimate");
+            self.catch_up = None;
+        }
+        self.collect_observations();
+        let counters = digest_counters(&self.checked, &self.session, &self.observed);
+        let digest = self.digest_log
+            .digest(reason, counters, gauges, Instant::now());
+        info!(target: "bridge::digest", "{}", digest);
+        Some(digest)
+    }
+
+    /// `true` while a circuit is open or a chain is stalled.
+    fn is_outage(&self) -> bool {
+        let open = self.components.iter().any(|&component| {
+            match self.circuit_state(component) {
+                None | Some(CircuitState::Closed) => false,
+                Some(_) => true,
+            }
+        });
+        let stalled = self.block_ages
+            .map_or(false, |ages| ages.home.stalled || ages.foreign.stalled);
+        open || stalled
+    }
+
+    /// logs a digest every `digest_interval` and once an outage is over.
+    fn check_digest(&mut self) -> Result<()> {
+        let mut due = false;
+        if let Some(ref mut interval) = self.digest_interval {
+            while let Async::Ready(Some(())) = interval.poll()? {
+                due = true;
+            }
+        }
+        let outage = self.is_outage();
+        let recovered = self.outage && !outage;
+        self.outage = outage;
+        if recovered {
+            self.digest(DigestReason::Recovered);
+        } else if due {
+            self.digest(DigestReason::Interval);
+        }
+        Ok(())
+    }
+
+    /// reports a health summary every `telemetry.interval` once it's signed.
+    fn check_telemetry(&mut self) -> Result<()> {
+        let due = match self.telemetry {
+            Some(ref mut telemetry) => telemetry.is_due()?,
+            None => return Ok(()),
+        };
+        if due {
+            self.collect_counts();
+            self.collect_observations();
+            let counters = digest_counters(&self.checked, &self.session, &self.observed);
+            let gauges = self.digest_gauges();
+            if let Some(ref mut telemetry) = self.telemetry {
+                telemetry.sign(counters, gauges);
+            }
+        }
+        if let Some(ref mut telemetry) = self.telemetry {
+            telemetry.poll();
+        }
+        Ok(())
+    }
+
+    /// `true` while a deposit or withdraw is pending longer than `relay_slo.max_pending_age`.
+    /// always `false` if `relay_slo` isn't configured.
+    pub fn is_degraded(&self) -> bool {
+        self.slo_breached
+    }
+
+    /// role of this instance. always `Role::Leader` if `leader_election` isn't configured.
+    pub fn role(&self) -> Role {
+        self.leader_election
+            .as_ref()
+            .map_or(Role::Leader, |election| election.role())
+    }
+
+    /// lets the leadership lease expire right away so a standby takes over
+    /// without waiting for the lease duration. call on shutdown only:
+    /// the bridge doesn't relay anymore until it acquires the lease again.
+    pub fn release_leadership(&mut self) -> Result<()> {
+        match self.leader_election {
+            Some(ref mut election) => election.release(),
+            None => Ok(()),
+        }
+    }
+
+    /// updates the pending relays the history api serves.
+    fn publish_pending(&self) {
+        if let Some(ref history) = self.history {
+            let mut pending = Vec::new();
+            if let Some(ref relay) = self.deposit_relay {
+                pending.extend(relay.pending_relays());
+            }
+            if let Some(ref relay) = self.withdraw_relay {
+                pending.extend(relay.pending_relays());
+            }
+            pending.sort_by_key(|relay| relay.since);
+            history.set_pending(pending);
+            history.set_held(self.held_values());
+        }
+    }
+
+    /// updates the recent errors the history api serves once a component failed again.
+    fn publish_errors(&mut self) {
+        let failures = self.session.failures();
+        if failures == self.published_failures {
+            return;
+        }
+        if let Some(ref history) = self.history {
+            history.set_errors(self.session.recent_errors());
+        }
+        self.published_failures = failures;
+    }
+
+    fn check_pending_ages(&mut self) -> Result<()> {
+        let mut due = false;
+        while let Async::Ready(Some(())) = self.pending_age_interval.poll()? {
+            due = true;
+        }
+        let mut oldest = Vec::new();
+        if let Some(ref mut relay) = self.deposit_relay {
+            oldest.extend(relay.oldest_pending());
+        }
+        if let Some(ref mut relay) = self.withdraw_relay {
+            oldest.extend(relay.oldest_pending());
+        }
+        self.oldest_pending = oldest;
+        if due {
+            self.publish_pending();
+        }
+
+        let max_age = match self.app.config.relay_slo {
+            Some(ref slo) => slo.max_pending_age.as_secs(),
+            None => return Ok(()),
+        };
+        let now = unix_time();
+        let breach = self.oldest_pending
+            .iter()
+            .filter(|relay| relay.age(now) > max_age)
+            .max_by_key(|relay| relay.age(now));
+        match (breach, self.slo_breached) {
+            (Some(relay), false) => {
+                error!(
+                    "CRITICAL: {:?} relay of transaction {} is pending for {} seconds. relay_slo.max_pending_age is {} seconds",
+                    relay.direction,
+                    relay.source_transaction,
+                    relay.age(now),
+                    max_age
+                );
+                self.alerts.alert(Alert::new(
+                    Severity::Critical,
+                    "relay_slo_breached",
+                    format!("{:?}", relay.direction),
+                    format!(
+                        "{:?} relay of transaction {:?} is pending for {} seconds. relay_slo.max_pending_age is {} seconds",
+                        relay.direction,
+                        relay.source_transaction,
+                        relay.age(now),
+                        max_age
+                    ),
+                ));
+                self.slo_breached = true;
+            }
+            (None, true) => {
+                info!(
+                    "no relay is pending for more than {} seconds anymore",
+                    max_age
+                );
+                self.slo_breached = false;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// differences between the authorities and `requiredSignatures` of the contracts
+    /// as of the last check. `None` until checked or if `authority_check` isn't configured.
+    pub fn authority_diff(&self) -> Option<&AuthorityDiff> {
+        self.authority_diff.as_ref()
+    }
+
+    /// `true` if both contracts had the same authorities and `requiredSignatures`
+    /// at the last check. `None` until checked or if `authority_check` isn't configured.
+    pub fn contracts_consistent(&self) -> Option<bool> {
+        self.authority_diff
+            .as_ref()
+            .map(AuthorityDiff::is_consistent)
+    }
+
+    fn check_authorities(&mut self) -> Result<()> {
+        let mut restored = None;
+        if let Some(ref mut monitor) = self.authority_monitor {
+            while let Async::Ready(Some(diff)) = monitor.poll()? {
+                if !diff.is_consistent() && self.authority_diff.as_ref() != Some(&diff) {
+                    self.alerts.alert(Alert::new(
+                        Severity::Critical,
+                        "authorities_inconsistent",
+                        "contracts",
+                        format!(
+                            "authorities of HomeBridge and ForeignBridge are inconsistent: {}",
+                            diff.description()
+                        ),
+                    ));
+                }
+                self.authority_diff = Some(diff);
+                // a fresh list decides whether the account is an authority
+                if let Some(foreign) = monitor.foreign_authorities() {
+                    restored = self.authorization.check(foreign).or(restored);
+                }
+            }
+        }
+        if let Some(components) = restored {
+            info!(
+                "{:?} is an authority of ForeignBridge again. components sending to foreign resume",
+                self.app.config.foreign.account
+            );
+            self.alerts.alert(Alert::new(
+                Severity::Info,
+                "authority_restored",
+                format!("{:?}", self.app.config.foreign.account),
+                "the account is an authority of ForeignBridge again".to_owned(),
+            ));
+            for component in components {
+                self.rebuild(component);
+            }
+            // the time suspended doesn't count as a stall
+            if let Some(ref mut watchdog) = self.watchdog {
+                watchdog.restart(&self.components);
+            }
+        }
+        Ok(())
+    }
+
+    /// alerts once the account was found not to be an authority.
+    fn check_authorization(&mut self) {
+        let revoked = match self.authorization.take_unreported() {
+            Some(revoked) => revoked.clone(),
+            None => return,
+        };
+        error!(
+            "CRITICAL: {}. deposit relay and withdraw confirm send no transactions until ForeignBridge lists it again",
+            revoked.description()
+        );
+        self.alerts.alert(Alert::new(
+            Severity::Critical,
+            "not_an_authority",
+            format!("{:?}", revoked.account),
+            format!(
+                "{}. no transactions are sent to ForeignBridge until it lists the account again",
+                revoked.description()
+            ),
+        ));
+    }
+
+    /// reorgs of home detected since the bridge started.
+    pub fn reorg_incidents(&self) -> &[ReorgIncident] {
+        &self.incidents
+    }
+
+    /// depths of the latest reorg of home since the bridge started and of the deepest ever.
+    pub fn reorg_depths(&self) -> ReorgDepths {
+        self.reorg_depths
+    }
+
+    /// home reorg at least `reorg_monitor.alarm_depth` deep the operator hasn't acknowledged.
+    pub fn deep_reorg(&self) -> Option<&DeepReorg> {
+        self.checked.deep_reorg.as_ref()
+    }
+
+    /// `true` while deposit relay is halted by a deep reorg not yet acknowledged.
+    fn halted_by_reorg(&self) -> bool {
+        self.checked.deep_reorg.is_some() && halts_deposits(&self.app.config)
+            && self.circuit_breakers.is_some()
+    }
+
+    /// acknowledges the deep reorg after the operator checked the deposits relayed before it.
+    /// resumes deposit relay if the reorg halted it. written by the next save.
+    /// returns the acknowledged reorg.
+    pub fn acknowledge_deep_reorg(&mut self) -> Result<DeepReorg> {
+        let halted = self.halted_by_reorg();
+        let reorg = match self.checked.deep_reorg.take() {
+            Some(reorg) => reorg,
+            None => bail!("no deep home reorg to acknowledge"),
+        };
+        self.backend.record_reorgs(self.checked.max_reorg_depth, None);
+        info!("home reorg of depth {} at block {} acknowledged", reorg.depth, reorg.fork_block);
+        if halted {
+            self.force_close_circuit(Component::DepositRelay)?;
+        }
+        Ok(reorg)
+    }
+
+    /// accepts the nonce of a signed admin command if it's above that of the last one accepted,
+    /// and records it before the command is executed. every accepted command is logged with its
+    /// signers to the `bridge::admin` target.
+    pub fn authorize(&mut self, authorization: &CommandAuthorization) -> Result<()> {
+        if let Some(last) = self.checked.admin_nonce {
+            if authorization.nonce <= last {
+                bail!(
+                    "nonce {} was used, the next signed command needs a nonce above {}",
+                    authorization.nonce,
+                    last
+                );
+            }
+        }
+        self.backend
+            .record_admin_nonce(authorization.nonce)
+            .chain_err(|| "Cannot record the nonce of the signed command")?;
+        self.checked.admin_nonce = Some(authorization.nonce);
+        info!(target: "bridge::admin", "authorized {}", authorization);
+        Ok(())
+    }
+
+    /// relayed deposits whose execution on foreign isn't final yet.
+    /// empty if the execution watch is disabled.
+    pub fn watched_executions(&self) -> Vec<WatchedExecution> {
+        match self.execution_watch {
+            Some(ref watch) => watch.watched().to_vec(),
+            None => Vec::new(),
+        }
+    }
+
+    /// moves the executions of the deposits relayed by deposit relay into the execution watch.
+    fn collect_executions(&mut self) {
+        if let (Some(watch), Some(relay)) =
+            (self.execution_watch.as_mut(), self.deposit_relay.as_mut())
+        {
+            watch.track(relay.take_executions());
+        }
+    }
+
+    /// relays the deposits again whose execution a foreign reorg invalidated.
+    fn check_executions(&mut self) -> Result<()> {
+        self.collect_executions();
+        if let Some(ref mut watch) = self.execution_watch {
+            while let Async::Ready(Some(invalidated)) = watch.poll()? {
+                for execution in &invalidated {
+                    self.alerts.alert(Alert::new(
+                        Severity::Warning,
+                        "invalidated_execution",
+                        format!("{:?}", execution.home_transaction),
+                        format!(
+                            "foreign transaction {:?} executing the deposit in home transaction {:?} was orphaned by a foreign reorg. the deposit is relayed again",
+                            execution.foreign_transaction, execution.home_transaction
+                        ),
+                    ));
+                }
+                match self.deposit_relay {
+                    Some(ref mut relay) => relay.requeue(invalidated),
+                    None => error!(
+                        "cannot relay {} deposits again whose execution was invalidated: deposits are disabled",
+                        invalidated.len()
+                    ),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `finalizeDeposit` calls this authority sent since the bridge started.
+    /// empty unless `deposit_finalize` is configured.
+    pub fn finalizations(&self) -> &[Finalization] {
+        &self.finalizations
+    }
+
+    /// finalizes deposits signed by enough authorities at the turn of this authority.
+    fn check_deposit_finalize(&mut self) -> Result<()> {
+        if let Some(ref mut finalize) = self.deposit_finalize {
+            while let Async::Ready(Some(finalizations)) = finalize.poll()? {
+                self.finalizations.extend(finalizations);
+            }
+        }
+        Ok(())
+    }
 
-pub struct FileBackend {
-    path: PathBuf,
-    database: Database,
-}
+    /// `SignRejected` events of all authorities seen since the bridge started.
+    pub fn sign_rejections(&self) -> &[SignRejection] {
+        &self.sign_rejections
+    }
 
-impl BridgeBackend for FileBackend {
-    fn save(&mut self, checks: Vec<BridgeChecked>) -> Result<()> {
-        for check in checks {
-            match check {
-                BridgeChecked::DepositRelay(n) => {
-                    self.database.checked_deposit_relay = n;
+    fn check_sign_rejections(&mut self) -> Result<()> {
+        if let Some(ref mut monitor) = self.sign_rejection_monitor {
+            while let Async::Ready(Some(rejections)) = monitor.poll()? {
+                for rejection in &rejections {
+                    self.session.record_sign_rejection(rejection.reason.label());
                 }
-                BridgeChecked::WithdrawRelay(n) => {
-                    self.database.checked_withdraw_relay = n;
+                self.sign_rejections.extend(rejections);
+            }
+        }
+        Ok(())
+    }
+
+    /// signing latencies of the kept signatures by authority. empty unless
+    /// `watch_signing_latency`.
+    pub fn signing_latencies(&self) -> Vec<AuthorityLatency> {
+        self.signing_latencies
+            .as_ref()
+            .map_or_else(Vec::new, SigningLatencies::by_authority)
+    }
+
+    fn check_signing_latency(&mut self) -> Result<()> {
+        let mut signatures = Vec::new();
+        if let Some(ref mut monitor) = self.signing_latency_monitor {
+            while let Async::Ready(Some(range)) = monitor.poll()? {
+                signatures.extend(range);
+            }
+        }
+        if signatures.is_empty() {
+            return Ok(());
+        }
+        // the withdraws of the signatures may not be collected yet
+        self.collect_observations();
+        if let Some(ref mut latencies) = self.signing_latencies {
+            for signature in &signatures {
+                match latencies.record(signature) {
+                    SignatureOutcome::Recorded(seconds) => debug!(
+                        target: "bridge::signing_latency",
+                        "authority {} signed withdraw {} {}s after it was observed",
+                        signature.authority,
+                        signature.withdraw,
+                        seconds
+                    ),
+                    SignatureOutcome::Duplicate => {}
+                    SignatureOutcome::Unobserved => debug!(
+                        target: "bridge::signing_latency",
+                        "authority {} signed withdraw {} this bridge didn't observe, \
+                         its latency is excluded",
+                        signature.authority,
+                        signature.withdraw
+                    ),
                 }
-                BridgeChecked::WithdrawConfirm(n) => {
-                    self.database.checked_withdraw_confirm = n;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_reorgs(&mut self) -> Result<()> {
+        let mut incidents = Vec::new();
+        if let Some(ref mut monitor) = self.reorg_monitor {
+            if let Some(ref mut relay) = self.deposit_relay {
+                monitor.track(relay.take_relayed());
+            }
+            while let Async::Ready(Some(incident)) = monitor.poll()? {
+                incidents.push(incident);
+            }
+        }
+        for incident in incidents {
+            for hash in &incident.unbacked {
+                self.alerts.alert(Alert::new(
+                    Severity::Critical,
+                    "unbacked_deposit",
+                    format!("{:?}", hash),
+                    format!(
+                        "deposit in home transaction {:?} was relayed to foreign but is not part of the canonical home chain anymore after a reorg at block {}. tokens minted for it on foreign are unbacked",
+                        hash, incident.fork_block
+                    ),
+                ));
+            }
+            self.record_reorg_depth(&incident);
+            self.incidents.push(incident);
+        }
+        Ok(())
+    }
+
+    /// records the depth of `incident`. a reorg at least `reorg_monitor.alarm_depth` deep
+    /// breaks the finality the deposit relays assumed: it raises a critical alert and with
+    /// `reorg_monitor.halt_deposits` halts deposit relay until it's acknowledged.
+    fn record_reorg_depth(&mut self, incident: &ReorgIncident) {
+        let (alarm_depth, halt) = match self.app.config.reorg_monitor {
+            Some(ref config) => (
+                config.alarm_depth(&self.app.config.home),
+                halts_deposits(&self.app.config),
+            ),
+            None => return,
+        };
+        let deepest = self.reorg_depths.record(incident.depth);
+        let alarm = incident.depth >= alarm_depth;
+        if alarm {
+            let halted = if halt {
+                ". deposit relay is halted until the reorg is acknowledged"
+            } else {
+                ""
+            };
+            self.alerts.alert(Alert::new(
+                Severity::Critical,
+                "deep_reorg",
+                format!("{}", incident.fork_block),
+                format!(
+                    "home reorg of depth {} at block {} reached the alarm depth {}. deposits relayed before it are suspect{}",
+                    incident.depth,
+                    incident.fork_block,
+                    alarm_depth,
+                    halted
+                ),
+            ));
+            let deeper = self.checked
+                .deep_reorg
+                .as_ref()
+                .map_or(true, |reorg| incident.depth > reorg.depth);
+            if deeper {
+                self.checked.deep_reorg = Some(DeepReorg {
+                    fork_block: incident.fork_block,
+                    depth: incident.depth,
+                    alarm_depth,
+                    time: unix_time(),
+                });
+            }
+            if halt {
+                if let Some(ref mut breakers) = self.circuit_breakers {
+                    breakers.force_open(Component::DepositRelay);
                 }
             }
         }
+        if deepest || alarm {
+            self.checked.max_reorg_depth = self.reorg_depths.max;
+            self.backend.record_reorgs(
+                self.checked.max_reorg_depth,
+                self.checked.deep_reorg.clone(),
+            );
+        }
+    }
 
-        let file = fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&self.path)?;
+    fn check_clock_drift(&mut self) -> Result<()> {
+        if let Some(ref mut monitor) = self.clock_drift {
+            while let Async::Ready(Some(drift)) = monitor.poll()? {
+                self.drift = drift;
+            }
+        }
+        Ok(())
+    }
 
-        self.database.save(file)
+    fn check_chain_clock(&mut self) -> Result<()> {
+        if let Some(ref mut monitor) = self.chain_clock {
+            while let Async::Ready(Some(_)) = monitor.poll()? {}
+        }
+        Ok(())
     }
-}
 
-enum BridgeStatus {
-    Wait,
-    NextItem(Option<()>),
-}
+    fn check_node_gas_price(&mut self) -> Result<()> {
+        if let Some(ref mut monitor) = self.node_gas_price {
+            while let Async::Ready(Some(_)) = monitor.poll()? {}
+        }
+        Ok(())
+    }
 
-/// Creates new bridge.
-pub fn create_bridge<T: Transport + Clone>(
-    app: Arc<App<T>>,
-    init: &Database,
-) -> Bridge<T, FileBackend> {
-    let backend = FileBackend {
-        path: app.database_path.clone(),
-        database: init.clone(),
-    };
+    fn check_node_versions(&mut self) -> Result<()> {
+        while let Async::Ready(Some(_)) = self.node_versions.poll()? {}
+        Ok(())
+    }
 
-    create_bridge_backed_by(app, init, backend)
+    fn check_chain_stall(&mut self) -> Result<()> {
+        if let Some(ref mut monitor) = self.chain_stall {
+            while let Async::Ready(Some(ages)) = monitor.poll()? {
+                self.block_ages = Some(ages);
+            }
+        }
+        Ok(())
+    }
+
+    /// retries the failed saves every `degraded_persistence.retry_interval`.
+    fn check_persistence(&mut self) -> Result<()> {
+        let mut due = false;
+        if let Some(ref mut interval) = self.persistence_retry {
+            while let Async::Ready(Some(())) = interval.poll()? {
+                due = true;
+            }
+        }
+        if due && self.persistence_outage().is_some() {
+            let saved = self.backend.save(Vec::new());
+            self.record_save(saved, true)?;
+        }
+        Ok(())
+    }
+
+    /// records the result of a save. fails with its error unless `degraded_persistence`
+    /// is configured, see `DegradedPersistence`.
+    fn record_save(&mut self, saved: Result<()>, retry: bool) -> Result<()> {
+        let path = self.app.database_path.display().to_string();
+        let change = match (saved, self.persistence.as_mut()) {
+            (Ok(()), Some(persistence)) => persistence.record_success(Instant::now()),
+            (Ok(()), None) => return Ok(()),
+            (Err(err), Some(persistence)) => {
+                debug!("saving {} failed: {}", path, err);
+                persistence.record_failure(err.to_string(), retry, Instant::now())
+            }
+            (Err(err), None) => return Err(err),
+        };
+        let outage = self.persistence_outage().cloned();
+        let config = self.app
+            .config
+            .degraded_persistence
+            .clone()
+            .expect("changes are only recorded if degraded_persistence is configured; qed");
+        match change {
+            PersistenceChange::Unchanged => (),
+            PersistenceChange::Degraded => {
+                let error = outage.map(|outage| outage.error).unwrap_or_default();
+                warn!("persistence degraded: saving {} failed: {}", path, error);
+                self.alerts.alert(Alert::new(
+                    Severity::Warning,
+                    "persistence_degraded",
+                    path.clone(),
+                    format!(
+                        "saving {} failed: {}. relaying continues, transactions are held after \
+                         {} failed saves or {} seconds",
+                        path,
+                        error,
+                        config.max_buffered_saves,
+                        config.max_outage.as_secs()
+                    ),
+                ));
+            }
+            PersistenceChange::Halted => {
+                let buffered = outage.map_or(0, |outage| outage.buffered_saves);
+                error!(
+                    "persistence halted: {} saves of {} are unwritten. transactions are held \
+                     until it's written",
+                    buffered, path
+                );
+                self.alerts.alert(Alert::new(
+                    Severity::Critical,
+                    "persistence_halted",
+                    path.clone(),
+                    format!(
+                        "{} saves of {} are unwritten. transactions are held until it's written",
+                        buffered, path
+                    ),
+                ));
+            }
+            PersistenceChange::Recovered {
+                gap,
+                buffered_saves,
+                halted,
+            } => {
+                info!(
+                    "persistence recovered: wrote {} buffered saves to {} after {} seconds{}",
+                    buffered_saves,
+                    path,
+                    gap.as_secs(),
+                    if halted { ". held transactions are sent" } else { "" }
+                );
+                self.alerts.alert(Alert::new(
+                    Severity::Info,
+                    "persistence_recovered",
+                    path.clone(),
+                    format!(
+                        "wrote {} buffered saves to {} after {} seconds",
+                        buffered_saves,
+                        path,
+                        gap.as_secs()
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_finality(&mut self) -> Result<()> {
+        if let Some(ref mut monitor) = self.finality_monitor {
+            // the deposit relay reads the shared finalized block itself
+            while let Async::Ready(Some(_)) = monitor.poll()? {}
+        }
+        Ok(())
+    }
+
+    fn check_state_horizon(&mut self) -> Result<()> {
+        if let Some(ref mut monitor) = self.state_horizon_monitor {
+            while let Async::Ready(Some(horizons)) = monitor.poll()? {
+                self.state_horizons = Some(horizons);
+                if let Some(ref mut relay) = self.withdraw_relay {
+                    relay.set_foreign_state_horizon(Some(horizons.foreign));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_withdraw_cost(&mut self) -> Result<()> {
+        if let Some(ref mut monitor) = self.withdraw_cost_monitor {
+            while let Async::Ready(Some(cost)) = monitor.poll()? {
+                self.withdraw_cost = Some(cost);
+            }
+        }
+        Ok(())
+    }
+
+    /// moves the deposits of every scan of the liability monitor to their reasons,
+    /// decided from the checked block and the quarantined and skipped deposits of the bridge.
+    fn check_liability(&mut self) -> Result<()> {
+        if let Some(ref mut monitor) = self.liability_monitor {
+            monitor.set_checked_deposit_relay(self.checked.checked_deposit_relay);
+            while let Async::Ready(Some(scan)) = monitor.poll()? {
+                let unminted = classify(
+                    &self.app.config,
+                    &scan,
+                    self.checked.checked_deposit_relay,
+                    &self.quarantined_deposits,
+                    self.skipped_deposits.skipped(),
+                );
+                for (id, transition) in self.liability.apply(&scan, unminted) {
+                    debug!(target: "bridge::liability", "deposit {:?}: {:?}", id, transition);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
-/// Creates new bridge writing to custom backend.
-pub fn create_bridge_backed_by<T: Transport + Clone, F: BridgeBackend>(
-    app: Arc<App<T>>,
-    init: &Database,
-    backend: F,
-) -> Bridge<T, F> {
-    Bridge {
-        deposit_relay: create_deposit_relay(app.clone(), init),
-        withdraw_relay: create_withdraw_relay(app.clone(), init),
-        withdraw_confirm: create_withdraw_confirm(app.clone(), init),
-        state: BridgeStatus::Wait,
-        backend,
+impl<T: Transport + Clone, F> Bridge<T, F> {
+    /// switches to the authority accounts and signing credentials of `reloaded`.
+    /// `reloaded` is usually loaded from the config file again after a key rotation.
+    ///
+    /// each component keeps using the old accounts until it yields its next checked block.
+    /// at that point it has no transactions in flight and is rebuilt with the new accounts.
+    pub fn rotate_authority(&mut self, reloaded: &Config) -> Result<()> {
+        let config = self.app.config.with_credentials_of(reloaded)?;
+        info!(
+            "rotating authority accounts to {:?} on home and {:?} on foreign",
+            config.home.account, config.foreign.account
+        );
+        let app = App {
+            config,
+            tunables: self.app.tunables.clone(),
+            database_path: self.app.database_path.clone(),
+            connections: Connections {
+                home: self.app.connections.home.clone(),
+                foreign: self.app.connections.foreign.clone(),
+            },
+            home_bridge: home::HomeBridge::default(),
+            foreign_bridge: foreign::ForeignBridge::default(),
+            abi: self.app.abi.clone(),
+            node_gas_price: self.app.node_gas_price.clone(),
+            chain_clocks: self.app.chain_clocks.clone(),
+            node_versions: self.app.node_versions.clone(),
+            timer: self.app.timer.clone(),
+        };
+        self.rotation = Some(Rotation {
+            app: Arc::new(app),
+            pending: self.components.clone(),
+        });
+        Ok(())
+    }
+
+    /// `true` while some component still uses the accounts from before `rotate_authority`.
+    pub fn is_rotating(&self) -> bool {
+        self.rotation.is_some()
+    }
+
+    /// executes the commands sent through the `CommandSender` of `receiver` when polled.
+    pub fn commands_from(&mut self, receiver: CommandReceiver) {
+        self.commands = Some(receiver);
+    }
+
+    /// executes `command` right away. returns the json replied to its sender.
+    pub fn execute(&mut self, command: &BridgeCommand) -> Result<Value> {
+        match *command {
+            BridgeCommand::RotateAuthority(ref reloaded) => {
+                self.rotate_authority(reloaded)?;
+                Ok(json!({
+                    "rotating": self.is_rotating(),
+                    "home_account": reloaded.home.account,
+                    "foreign_account": reloaded.foreign.account,
+                }))
+            }
+            BridgeCommand::ForceOpenCircuit(component) => {
+                self.force_open_circuit(component)?;
+                info!("circuit of {:?} forced open", component);
+                Ok(json!({ "circuit": "forced_open" }))
+            }
+            BridgeCommand::ForceCloseCircuit(component) => {
+                self.force_close_circuit(component)?;
+                info!("circuit of {:?} forced closed", component);
+                Ok(json!({ "circuit": "closed" }))
+            }
+            BridgeCommand::SpendLimit(kind) => self.spend_status(kind),
+            BridgeCommand::SetSpendLimit(kind, limit) => {
+                self.set_spend_limit(kind, limit)?;
+                self.spend_status(kind)
+            }
+            BridgeCommand::ReleaseDeferred(kind, key) => {
+                self.release_deferred(kind, key)?;
+                self.spend_status(kind)
+            }
+            BridgeCommand::ReleaseHeldDeposit(key) => {
+                self.release_held_deposit(key)?;
+                Ok(json!({ "released": key, "held": self.held_values() }))
+            }
+            BridgeCommand::Drain => {
+                self.start_drain();
+                Ok(self.drain_status())
+            }
+            BridgeCommand::AcknowledgeDeepReorg => {
+                let halted = self.halted_by_reorg();
+                let reorg = self.acknowledge_deep_reorg()?;
+                Ok(json!({ "acknowledged": reorg, "deposit_relay_resumed": halted }))
+            }
+            BridgeCommand::RecentErrors => {
+                let errors = self.session.recent_errors();
+                Ok(serde_json::to_value(errors).expect("RecentError always serializes; qed"))
+            }
+            BridgeCommand::Authorize(ref authorization) => {
+                self.authorize(authorization)?;
+                Ok(json!({ "nonce": authorization.nonce }))
+            }
+        }
+    }
+
+    /// executes the commands received since the last poll and replies to their senders.
+    fn check_commands(&mut self) {
+        loop {
+            let request = match self.commands {
+                Some(ref mut commands) => commands.poll(),
+                None => return,
+            };
+            match request {
+                Ok(Async::Ready(Some(request))) => {
+                    info!("executing {:?}", request.command);
+                    let reply = self.execute(&request.command).map_err(|err| {
+                        warn!("cannot execute {:?}: {}", request.command, err);
+                        err.to_string()
+                    });
+                    request.reply(reply);
+                }
+                Ok(Async::NotReady) => return,
+                // every sender is dropped
+                Ok(Async::Ready(None)) | Err(()) => {
+                    self.commands = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// rebuilds the components that yielded `checks` with the rotated accounts.
+    fn continue_rotation(&mut self, checks: &[BridgeChecked]) {
+        let pending = match self.rotation {
+            Some(ref rotation) => rotation.pending.clone(),
+            None => return,
+        };
+        for check in checks {
+            let component = Component::from(*check);
+            if pending.contains(&component) {
+                info!("{:?} switched to rotated authority accounts", component);
+                self.rebuild(component);
+            }
+        }
+    }
+
+    /// schedules rebuilding every component if a config reload changed the poll intervals.
+    /// gas settings and limits of a reload apply to the next transactions without it,
+    /// but the log streams of a component only pick up poll intervals when they are built.
+    fn check_poll_intervals(&mut self) {
+        let reloaded = poll_intervals(&self.app.tunables.get());
+        if reloaded != self.poll_intervals {
+            self.poll_intervals = reloaded;
+            self.poll_interval_reloads = self.components.clone();
+        }
+    }
+
+    /// rebuilds the components that yielded `checks` with the reloaded poll intervals.
+    fn continue_poll_interval_reload(&mut self, checks: &[BridgeChecked]) {
+        for check in checks {
+            let component = Component::from(*check);
+            if self.poll_interval_reloads.contains(&component) {
+                self.poll_interval_reloads.retain(|c| *c != component);
+                info!("{:?} switched to reloaded poll intervals", component);
+                self.rebuild(component);
+            }
+        }
+    }
+
+    /// app `component` is built with. the rotated app for components pending rotation.
+    fn app_for(&mut self, component: Component) -> Arc<App<T>> {
+        let (app, done) = match self.rotation {
+            None => return self.app.clone(),
+            Some(ref mut rotation) => {
+                rotation.pending.retain(|c| *c != component);
+                (rotation.app.clone(), rotation.pending.is_empty())
+            }
+        };
+        if done {
+            info!("authority rotation complete");
+            self.rotation = None;
+            self.app = app.clone();
+        }
+        app
+    }
+
+    /// follows the pause flags of the contracts and suspends or resumes the components
+    /// sending to them.
+    fn check_contract_pauses(&mut self) -> Result<()> {
+        for held in &mut self.held_relays {
+            held.observe()?;
+        }
+        let mut changes = Vec::new();
+        if let Some(ref mut monitor) = self.pause_monitor {
+            while let Async::Ready(Some(pauses)) = monitor.poll()? {
+                changes.push(pauses);
+            }
+        }
+        for pauses in changes {
+            let previous = self.pauses.unwrap_or_default();
+            self.pauses = Some(pauses);
+            self.alert_pause_changes(previous, pauses);
+            for component in self.components.clone() {
+                self.apply_pause(component, previous.of_target(component), pauses);
+            }
+        }
+        Ok(())
+    }
+
+    fn alert_pause_changes(&self, previous: ContractPauses, pauses: ContractPauses) {
+        let changes = [
+            ("HomeBridge", previous.home, pauses.home),
+            ("ForeignBridge", previous.foreign, pauses.foreign),
+        ];
+        for &(contract, before, now) in changes.iter() {
+            if before == now {
+                continue;
+            }
+            info!("pause flag of {} is {:?}", contract, now);
+            match (before, now) {
+                (_, PauseState::Paused) => self.alerts.alert(Alert::new(
+                    Severity::Warning,
+                    "contract_paused",
+                    contract.to_owned(),
+                    format!("{} is paused. transactions to it are held until it's unpaused", contract),
+                )),
+                (PauseState::Paused, PauseState::Unpaused) => self.alerts.alert(Alert::new(
+                    Severity::Info,
+                    "contract_unpaused",
+                    contract.to_owned(),
+                    format!("{} is unpaused. held transactions are sent", contract),
+                )),
+                _ => {}
+            }
+        }
+    }
+
+    /// suspends or resumes `component` after the pause flag of its target changed
+    /// from `before` to its flag in `pauses`.
+    fn apply_pause(&mut self, component: Component, before: PauseState, pauses: ContractPauses) {
+        let now = pauses.of_target(component);
+        let contract = target_contract(component);
+        if now == PauseState::Paused && before != PauseState::Paused {
+            warn!(
+                "{:?} holds its transactions while {} is paused",
+                component, contract
+            );
+            let behavior = self.app
+                .config
+                .contract_pause
+                .as_ref()
+                .map(|config| config.while_paused);
+            if behavior == Some(PauseBehavior::Observe) {
+                self.held_relays
+                    .retain(|held| held.component != component);
+                self.held_relays
+                    .push(watch_held_relays(&self.app, component, &self.checked));
+            }
+        }
+        if before.holds() && !now.holds() {
+            let index = self.held_relays
+                .iter()
+                .position(|held| held.component == component);
+            let held = index.map(|index| self.held_relays.remove(index).held());
+            // the time suspended doesn't count as a stall
+            if let Some(ref mut watchdog) = self.watchdog {
+                watchdog.restart(&self.components);
+            }
+            match (before, held) {
+                (PauseState::Paused, Some(held)) => info!(
+                    "{:?} resumes after {} was unpaused. {} relays were held",
+                    component, contract, held
+                ),
+                (PauseState::Paused, None) => info!(
+                    "{:?} resumes from checked block {:?} after {} was unpaused",
+                    component, self.checked, contract
+                ),
+                _ => {}
+            }
+        }
+    }
+
+    /// logs diagnostics of stalled components and rebuilds them if configured.
+    fn check_watchdog(&mut self) -> Result<()> {
+        let stalled = match self.watchdog {
+            Some(ref mut watchdog) => watchdog.poll_stalled()?,
+            None => return Ok(()),
+        };
+
+        let config = self.app
+            .config
+            .watchdog
+            .clone()
+            .expect("watchdog is only created if it is configured; qed");
+
+        for component in stalled {
+            // a suspended component makes no progress on purpose,
+            // neither does one held by the failure policy or a drained one
+            if self.is_suspended(component) || !self.failure_policy.allows(component)
+                || self.is_drained(component)
+            {
+                continue;
+            }
+            error!(
+                "{:?} made no progress for {} seconds. deposit relay: {}. withdraw relay: {}. withdraw confirm: {}",
+                component,
+                config.stall_timeout.as_secs(),
+                self.state_description(Component::DepositRelay),
+                self.state_description(Component::WithdrawRelay),
+                self.state_description(Component::WithdrawConfirm)
+            );
+
+            self.alerts.alert(Alert::new(
+                Severity::Warning,
+                "component_stalled",
+                format!("{:?}", component),
+                format!(
+                    "{:?} made no progress for {} seconds",
+                    component,
+                    config.stall_timeout.as_secs()
+                ),
+            ));
+
+            if !config.rebuild_stalled {
+                continue;
+            }
+
+            self.rebuild(component);
+        }
+
+        Ok(())
+    }
+
+    /// rebuilds failed components once their circuit allows it.
+    fn check_circuit_breakers(&mut self) -> Result<()> {
+        let rebuild = match self.circuit_breakers {
+            Some(ref mut breakers) => breakers.poll_rebuild(self.app.timer.now())?,
+            None => return Ok(()),
+        };
+
+        for component in rebuild {
+            self.rebuild(component);
+        }
+
+        Ok(())
+    }
+
+    /// rebuilds components held by the failure policy once they are due.
+    fn check_failure_policy(&mut self) -> Result<()> {
+        for component in self.failure_policy.poll_rebuild(self.app.timer.now())? {
+            self.rebuild(component);
+        }
+        Ok(())
+    }
+
+    /// recreates `component` from the last checked blocks.
+    fn rebuild(&mut self, component: Component) {
+        if !self.is_enabled(component) {
+            return;
+        }
+        warn!(
+            "rebuilding {:?} from last checked blocks {:?}",
+            component, self.checked
+        );
+        self.collect_counts();
+        self.collect_history();
+        self.collect_withdraw_attempts();
+        self.collect_outcomes();
+        self.collect_observations();
+        let app = self.app_for(component);
+        match component {
+            Component::DepositRelay => {
+                // deposits relayed by the old relay are still watched for reorgs
+                if let (Some(monitor), Some(relay)) =
+                    (self.reorg_monitor.as_mut(), self.deposit_relay.as_mut())
+                {
+                    monitor.track(relay.take_relayed());
+                }
+                // sent relays keep being watched and deposits not relayed again yet stay queued
+                self.collect_executions();
+                let requeued = self.deposit_relay
+                    .as_mut()
+                    .map(|relay| relay.take_requeued())
+                    .unwrap_or_default();
+                // the spend limiter keeps a changed limit and released relays.
+                // relays of the batch in flight are recorded again when they are relayed again
+                let limiter = self.deposit_relay
+                    .as_ref()
+                    .and_then(|relay| relay.spend_limiter().cloned());
+                // callbacks not saved yet are kept. those in flight count as failed
+                let callbacks = self.deposit_relay
+                    .as_ref()
+                    .map(|relay| relay.callbacks().clone());
+                let receipts = self.deposit_relay
+                    .as_ref()
+                    .map(|relay| relay.receipts().clone());
+                let mut relay = create_deposit_relay(app, &self.checked);
+                if let Some(mut callbacks) = callbacks {
+                    callbacks.fail(
+                        &"deposit relay failed while sending the callback".into(),
+                        Instant::now(),
+                    );
+                    relay.set_callbacks(callbacks);
+                }
+                if let Some(mut receipts) = receipts {
+                    receipts.fail_in_flight(
+                        &"deposit relay failed while recording the receipt".into(),
+                        Instant::now(),
+                    );
+                    relay.set_receipts(receipts);
+                }
+                if let Some(mut limiter) = limiter {
+                    limiter.forget_after(self.checked.checked_deposit_relay);
+                    relay.set_spend_limiter(Some(limiter));
+                }
+                if let Some(ref monitor) = self.finality_monitor {
+                    relay.watch_home_finality(monitor.shared());
+                }
+                relay.requeue(requeued);
+                self.deposit_relay = Some(relay);
+            }
+            Component::WithdrawRelay => {
+                let limiter = self.withdraw_relay
+                    .as_ref()
+                    .and_then(|relay| relay.spend_limiter().cloned());
+                // withdraws sent by the old relay stay recognized when their logs repeat,
+                // even if not saved yet. those of the batch in flight are relayed again
+                let messages = self.withdraw_relay.as_ref().map(|relay| {
+                    let mut messages = relay.relayed_messages().clone();
+                    messages.end_batch();
+                    messages
+                });
+                let mut relay = create_withdraw_relay(app, &self.checked);
+                if let Some(mut limiter) = limiter {
+                    limiter.forget_after(self.checked.checked_withdraw_relay);
+                    relay.set_spend_limiter(Some(limiter));
+                }
+                if let Some(messages) = messages {
+                    relay.set_relayed_messages(messages);
+                }
+                // attempts of the failed batch count, even if not saved yet
+                if let Some(ref old) = self.withdraw_relay {
+                    relay.set_attempt_log(old.attempt_log().clone());
+                    if let Some(origins) = old.withdraw_origins() {
+                        relay.set_withdraw_origins(origins.clone());
+                    }
+                }
+                relay.set_foreign_state_horizon(
+                    self.state_horizons.map(|horizons| horizons.foreign),
+                );
+                self.withdraw_relay = Some(relay);
+            }
+            Component::WithdrawConfirm => {
+                let mut confirm = create_withdraw_confirm(app, &self.checked);
+                if let Some(ref old) = self.withdraw_confirm {
+                    confirm.set_attempt_log(old.attempt_log().clone());
+                }
+                self.withdraw_confirm = Some(confirm);
+            }
+        }
     }
 }
 
-pub struct Bridge<T: Transport, F> {
-    deposit_relay: DepositRelay<T>,
-    withdraw_relay: WithdrawRelay<T>,
-    withdraw_confirm: WithdrawConfirm<T>,
-    state: BridgeStatus,
-    backend: F,
+impl<T: Transport + Clone, F: BridgeBackend> Bridge<T, F> {
+    /// `false` while another instance leads. once promoted the components are rebuilt
+    /// from the database as last saved by the previous leader.
+    fn check_leadership(&mut self) -> Result<bool> {
+        let promoted = match self.leader_election {
+            Some(ref mut election) => election.poll_promoted()?,
+            None => return Ok(true),
+        };
+        if promoted {
+            self.checked = self.backend.reload()?;
+            if let Some(ref mut watch) = self.execution_watch {
+                watch.reset(self.checked.watched_executions.clone());
+            }
+            self.quarantined_deposits = self.checked.quarantined_deposits.clone();
+            self.skipped_deposits = SkippedDeposits::new(&self.checked);
+            self.publish_skipped();
+            self.observed = ObservedTraffic::new(&self.checked);
+            self.raw_logs = RawLogs::new(&self.checked);
+            if self.signing_latencies.is_some() {
+                self.signing_latencies = Some(SigningLatencies::new(&self.checked));
+            }
+            self.dead_withdraws = self.checked.dead_withdraws.clone();
+            // components built before the promotion carry nothing over,
+            // not even a spend limiter: the previous leader recorded the spend
+            self.deposit_relay = None;
+            self.withdraw_relay = None;
+            self.withdraw_confirm = None;
+            for component in self.components.clone() {
+                self.rebuild(component);
+            }
+            if let Some(ref mut watchdog) = self.watchdog {
+                watchdog.restart(&self.components);
+            }
+        }
+        Ok(self.role() == Role::Leader)
+    }
 }
 
-impl<T: Transport, F: BridgeBackend> Stream for Bridge<T, F> {
-    type Item = ();
-    type Error = Error;
+impl<T: Transport + Clone, F: BridgeBackend> Bridge<T, F> {
+    /// `true` once the acknowledged drain completed: no component is waited for anymore
+    /// or the drain timed out. saves whatever is left to the backend and records the report.
+    fn poll_drained(&mut self) -> Result<bool> {
+        if self.drain_report.is_some() {
+            return Ok(true);
+        }
+        let timed_out = match self.drain {
+            Some(ref mut drain) => drain.poll_timed_out()?,
+            None => return Ok(false),
+        };
+        let waiting = self.components
+            .iter()
+            .filter(|&&component| self.drain_waits_for(component))
+            .count();
+        if waiting > 0 && !timed_out {
+            return Ok(false);
+        }
+        self.collect_history();
+        self.collect_quarantined();
+        self.collect_withdraw_attempts();
+        self.collect_deposit_callbacks();
+        self.collect_deposit_receipts();
+        self.record_skipped_deposits();
+        self.record_observed_traffic();
+        self.record_withdraw_origins();
+        self.backend.save(Vec::new())?;
+        let report = self.drain
+            .as_ref()
+            .expect("drain is acknowledged before it completes; qed")
+            .report(self.drain_pending());
+        if timed_out && waiting > 0 {
+            warn!("drain timed out waiting for {} components: {}", waiting, report.to_json());
+        } else {
+            info!("drain complete: {}", report.to_json());
+        }
+        self.drain_report = Some(report);
+        Ok(true)
+    }
+
+    fn poll_bridge(&mut self) -> Poll<Option<()>, Error> {
+        self.check_relay_chain()?;
+        self.check_contract_pauses()?;
+        let leader = self.check_leadership()?;
+        if leader {
+            self.check_watchdog()?;
+        }
+        self.check_chain_clock()?;
+        self.check_clock_drift()?;
+        self.check_node_gas_price()?;
+        self.check_node_versions()?;
+        self.check_chain_stall()?;
+        self.check_withdraw_cost()?;
+        self.check_liability()?;
+        self.check_state_horizon()?;
+        self.check_finality()?;
+        self.check_persistence()?;
+        self.check_poll_intervals();
+        if leader {
+            self.check_circuit_breakers()?;
+            self.check_failure_policy()?;
+            // neither relays deposits again nor finalizes them once draining
+            if !self.is_draining() {
+                self.check_executions()?;
+                self.check_deposit_finalize()?;
+            }
+        }
+        self.check_reorgs()?;
+        self.check_authorities()?;
+        self.check_authorization();
+        self.check_sign_rejections()?;
+        self.check_signing_latency()?;
+        self.check_pending_ages()?;
+        self.check_digest()?;
+        self.check_telemetry()?;
+        self.publish_errors();
+        self.check_commands();
+        self.check_drain();
+        if self.poll_drained()? {
+            return Ok(Async::Ready(None));
+        }
+        // standbys only monitor. the leader election wakes them up to check the lease
+        if !leader {
+            return Ok(Async::NotReady);
+        }
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         loop {
             let next_state = match self.state {
                 BridgeStatus::Wait => {
-                    let d_relay =
-                        try_bridge!(self.deposit_relay.poll()).map(BridgeChecked::DepositRelay);
-                    let w_relay =
-                        try_bridge!(self.withdraw_relay.poll()).map(BridgeChecked::WithdrawRelay);
-                    let w_confirm = try_bridge!(self.withdraw_confirm.poll())
-                        .map(BridgeChecked::WithdrawConfirm);
+                    let d_relay = if self.is_suspended(Component::DepositRelay)
+                        || self.is_drained(Component::DepositRelay)
+                    {
+                        None
+                    } else {
+                        let polled = poll_guarded(
+                            &mut self.deposit_relay,
+                            &mut self.authorization,
+                            &mut self.failure_policy,
+                            &mut self.circuit_breakers,
+                            &mut self.session,
+                            &self.alerts,
+                            Component::DepositRelay,
+                            self.app.timer.now(),
+                        );
+                        try_bridge!(self.end_drained(Component::DepositRelay, polled))
+                            .map(BridgeChecked::DepositRelay)
+                    };
+                    let w_relay = if self.is_suspended(Component::WithdrawRelay)
+                        || self.is_drained(Component::WithdrawRelay)
+                    {
+                        None
+                    } else {
+                        let polled = poll_guarded(
+                            &mut self.withdraw_relay,
+                            &mut self.authorization,
+                            &mut self.failure_policy,
+                            &mut self.circuit_breakers,
+                            &mut self.session,
+                            &self.alerts,
+                            Component::WithdrawRelay,
+                            self.app.timer.now(),
+                        );
+                        try_bridge!(self.end_drained(Component::WithdrawRelay, polled))
+                            .map(BridgeChecked::WithdrawRelay)
+                    };
+                    let w_confirm = if self.is_suspended(Component::WithdrawConfirm)
+                        || self.is_drained(Component::WithdrawConfirm)
+                    {
+                        None
+                    } else {
+                        let polled = poll_guarded(
+                            &mut self.withdraw_confirm,
+                            &mut self.authorization,
+                            &mut self.failure_policy,
+                            &mut self.circuit_breakers,
+                            &mut self.session,
+                            &self.alerts,
+                            Component::WithdrawConfirm,
+                            self.app.timer.now(),
+                        );
+                        try_bridge!(self.end_drained(Component::WithdrawConfirm, polled))
+                            .map(BridgeChecked::WithdrawConfirm)
+                    };
+                    self.collect_history();
+                    self.collect_quarantined();
+                    self.collect_oversized_logs();
+                    self.collect_signature_mismatches();
+                    self.collect_gas_usage();
+                    self.check_relay_chain()?;
 
                     let result: Vec<_> = [d_relay, w_relay, w_confirm]
                         .into_iter()
                         .filter_map(|c| *c)
                         .collect();
 
+                    if let Some(ref mut watchdog) = self.watchdog {
+                        watchdog.record(&result);
+                    }
+
                     if result.is_empty() {
+                        if self.poll_drained()? {
+                            return Ok(Async::Ready(None));
+                        }
                         return Ok(Async::NotReady);
                     } else {
-                        self.backend.save(result)?;
+                        apply_checks(&mut self.checked, &result);
+                        let spend = self.spend_log();
+                        if spend != self.checked.spend {
+                            self.checked.spend = spend.clone();
+                            self.backend.record_spend(spend);
+                        }
+                        let relayed = self.withdraw_relay
+                            .as_ref()
+                            .map(|relay| relay.relayed_messages().to_vec());
+                        if let Some(relayed) = relayed {
+                            if relayed != self.checked.relayed_withdraws {
+                                self.checked.relayed_withdraws = relayed.clone();
+                                self.backend.record_relayed_withdraws(relayed);
+                            }
+                        }
+                        if self.quarantined_deposits != self.checked.quarantined_deposits {
+                            let quarantined = self.quarantined_deposits.clone();
+                            self.checked.quarantined_deposits = quarantined.clone();
+                            self.backend.record_quarantined_deposits(quarantined);
+                        }
+                        self.collect_executions();
+                        let watched = self.watched_executions();
+                        if self.execution_watch.is_some()
+                            && watched != self.checked.watched_executions
+                        {
+                            self.checked.watched_executions = watched.clone();
+                            self.backend.record_watched_executions(watched);
+                        }
+                        self.continue_rotation(&result);
+                        self.continue_poll_interval_reload(&result);
+                        self.collect_withdraw_attempts();
+                        self.collect_deposit_callbacks();
+                        self.collect_deposit_receipts();
+                        self.record_skipped_deposits();
+                        self.record_observed_traffic();
+                        self.record_withdraw_origins();
+                        let saved = self.backend.save(result);
+                        self.record_save(saved, false)?;
                         BridgeStatus::NextItem(Some(()))
                     }
                 }
@@ -138,11 +3622,27 @@ impl<T: Transport, F: BridgeBackend> Stream for Bridge<T, F> {
     }
 }
 
+impl<T: Transport + Clone, F: BridgeBackend> Stream for Bridge<T, F> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let result = self.poll_bridge();
+        // failed attempts are saved even if the failure stops the bridge
+        if result.is_err() && self.collect_withdraw_attempts() {
+            self.backend.save(Vec::new())?;
+        }
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate tempdir;
+    use std::fs;
     use self::tempdir::TempDir;
-    use database::Database;
+    use std::sync::{Arc, Mutex};
+    use database::{Database, OutboxEntry, SharedDatabase};
     use super::{BridgeBackend, BridgeChecked, FileBackend};
 
     #[test]
@@ -152,13 +3652,15 @@ mod tests {
         path.push("db");
         let mut backend = FileBackend {
             path: path.clone(),
-            database: Database::default(),
+            database: SharedDatabase::default(),
+            write_lock: Arc::new(Mutex::new(())),
         };
+        let database = backend.database();
 
         backend.save(vec![BridgeChecked::DepositRelay(1)]).unwrap();
-        assert_eq!(1, backend.database.checked_deposit_relay);
-        assert_eq!(0, backend.database.checked_withdraw_confirm);
-        assert_eq!(0, backend.database.checked_withdraw_relay);
+        assert_eq!(1, database.get().checked_deposit_relay);
+        assert_eq!(0, database.get().checked_withdraw_confirm);
+        assert_eq!(0, database.get().checked_withdraw_relay);
         backend
             .save(vec![
                 BridgeChecked::DepositRelay(2),
@@ -166,11 +3668,70 @@ mod tests {
                 BridgeChecked::WithdrawRelay(2),
             ])
             .unwrap();
-        assert_eq!(2, backend.database.checked_deposit_relay);
-        assert_eq!(3, backend.database.checked_withdraw_confirm);
-        assert_eq!(2, backend.database.checked_withdraw_relay);
+        assert_eq!(2, database.get().checked_deposit_relay);
+        assert_eq!(3, database.get().checked_withdraw_confirm);
+        assert_eq!(2, database.get().checked_withdraw_relay);
 
         let loaded = Database::load(path).unwrap();
-        assert_eq!(backend.database, loaded);
+        assert_eq!(database.get(), loaded);
+    }
+
+    #[test]
+    fn test_file_backend_writes_failed_saves_later() {
+        let tempdir = TempDir::new("test_file_backend_writes_failed_saves_later").unwrap();
+        let mut path = tempdir.path().to_owned();
+        path.push("unmounted");
+        path.push("db");
+        let mut backend = FileBackend {
+            path: path.clone(),
+            database: SharedDatabase::default(),
+            write_lock: Arc::new(Mutex::new(())),
+        };
+        let database = backend.database();
+
+        // the directory of the database is unavailable
+        assert!(backend.save(vec![BridgeChecked::DepositRelay(1)]).is_err());
+        assert!(backend.save(vec![BridgeChecked::DepositRelay(2)]).is_err());
+        assert!(backend.save(vec![BridgeChecked::WithdrawRelay(3)]).is_err());
+        assert_eq!(2, database.get().checked_deposit_relay);
+        assert!(Database::load(&path).is_err());
+
+        // a retry writes the failed saves
+        fs::create_dir(tempdir.path().join("unmounted")).unwrap();
+        backend.save(Vec::new()).unwrap();
+        let loaded = Database::load(&path).unwrap();
+        assert_eq!(2, loaded.checked_deposit_relay);
+        assert_eq!(3, loaded.checked_withdraw_relay);
+        assert_eq!(database.get(), loaded);
+    }
+
+    #[test]
+    fn test_file_backend_outbox_writes_the_database() {
+        let tempdir = TempDir::new("test_file_backend_outbox_writes_the_database").unwrap();
+        let path = tempdir.path().join("db");
+        let backend = FileBackend::new(path.clone(), &Database::default());
+        let mut store = backend.outbox_store();
+        let entry = OutboxEntry {
+            sequence: 1,
+            endpoint: "webhook 0".into(),
+            key: "0x01/relayed".into(),
+            ordering_key: "0x01".into(),
+            payload: "{}".into(),
+            created_at: 1000,
+            delivered_at: None,
+            batch: None,
+        };
+
+        store
+            .update(&mut |entries: &mut Vec<OutboxEntry>| entries.push(entry.clone()))
+            .unwrap();
+        assert_eq!(vec![entry.clone()], Database::load(&path).unwrap().webhook_outbox);
+        assert_eq!(vec![entry], backend.database().get().webhook_outbox);
+
+        // shrinking the database leaves no trailing bytes of the longer file
+        store
+            .update(&mut |entries: &mut Vec<OutboxEntry>| entries.clear())
+            .unwrap();
+        assert_eq!(Database::default(), Database::load(&path).unwrap());
     }
 }
@@ -0,0 +1,419 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+use futures::{Async, Future, Poll, Stream};
+use serde_json::{self, Value};
+use timer::Interval;
+use web3::Transport;
+use web3::types::U256;
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::{Config, NodeGasPriceConfig};
+use error::Error;
+use quantity::normalize_quantity;
+use units::{checked_add, format_wei};
+use super::self_test::Probe;
+
+/// Gas price of foreign last derived by `NodeGasPriceMonitor`,
+/// shared by the components sending to foreign.
+#[derive(Debug, Clone, Default)]
+pub struct SharedGasPrice {
+    inner: Arc<RwLock<Option<u64>>>,
+}
+
+impl SharedGasPrice {
+    /// gas price in wei. `None` until derived or if `node_gas_price` isn't configured.
+    pub fn get(&self) -> Option<u64> {
+        *self.inner
+            .read()
+            .expect("no code panics while holding the lock; qed")
+    }
+
+    pub fn set(&self, gas_price: u64) {
+        *self.inner
+            .write()
+            .expect("no code panics while holding the lock; qed") = Some(gas_price);
+    }
+}
+
+/// Base fees and priority fees of the blocks returned by `eth_feeHistory`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeHistory {
+    /// base fees of the blocks, the last one is the base fee of the next block
+    pub base_fees: Vec<U256>,
+    /// priority fee at the requested percentile of every block
+    pub rewards: Vec<U256>,
+}
+
+fn parse_quantity(value: &Value) -> Result<U256, String> {
+    match *value {
+        Value::String(ref quantity) => {
+            let quantity = normalize_quantity(quantity)?;
+            serde_json::from_value(Value::String(quantity.clone()))
+                .map_err(|_| format!("quantity {:?} is not a hex number", quantity))
+        }
+        ref other => Err(format!("quantity {} is not a string", other)),
+    }
+}
+
+/// parses the `result` of `eth_feeHistory` requested with a single percentile.
+pub fn parse_fee_history(result: &Value) -> Result<FeeHistory, String> {
+    let base_fees = match result.get("baseFeePerGas") {
+        Some(&Value::Array(ref base_fees)) => base_fees
+            .iter()
+            .map(parse_quantity)
+            .collect::<Result<_, _>>()
+            .map_err(|reason| format!("baseFeePerGas: {}", reason))?,
+        Some(&Value::Null) | None => Vec::new(),
+        Some(other) => return Err(format!("baseFeePerGas {} is not an array", other)),
+    };
+    let rewards = match result.get("reward") {
+        Some(&Value::Array(ref rewards)) => rewards
+            .iter()
+            .map(|reward| match reward.get(0) {
+                Some(reward) => parse_quantity(reward),
+                None => Err(format!("reward {} has no priority fee", reward)),
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|reason| format!("reward: {}", reason))?,
+        Some(&Value::Null) | None => Vec::new(),
+        Some(other) => return Err(format!("reward {} is not an array", other)),
+    };
+    Ok(FeeHistory { base_fees, rewards })
+}
+
+/// `percentile` of `values` by nearest rank. `None` if there are no values.
+pub fn percentile(values: &[U256], percentile: u32) -> Option<U256> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort();
+    let rank = (percentile as usize * sorted.len() + 99) / 100;
+    Some(sorted[rank.max(1) - 1])
+}
+
+/// gas price paying the base fee of the next block and the median priority fee of `history`.
+/// the base fee is raised by the 12.5% it can grow per block if it rose over the history.
+/// `None` on chains without base fee.
+pub fn fee_history_price(history: &FeeHistory) -> Option<U256> {
+    let next = match history.base_fees.last() {
+        Some(next) if !next.is_zero() => *next,
+        _ => return None,
+    };
+    let base_fee = if next > history.base_fees[0] {
+        checked_add(next, next / 8.into()).unwrap_or_else(U256::max_value)
+    } else {
+        next
+    };
+    let tip = percentile(&history.rewards, 50).unwrap_or_else(U256::zero);
+    Some(checked_add(base_fee, tip).unwrap_or_else(U256::max_value))
+}
+
+/// `price` within `floor` and `ceiling`.
+pub fn clamp_gas_price(price: U256, floor: u64, ceiling: Option<u64>) -> u64 {
+    let ceiling = ceiling.unwrap_or(u64::max_value());
+    if price > ceiling.into() {
+        ceiling
+    } else {
+        price.low_u64().max(floor)
+    }
+}
+
+enum NodeGasPriceState<T: Transport> {
+    /// Waiting for the next poll of the head.
+    Wait,
+    /// Fetching the head of foreign.
+    FetchHead(Timeout<ApiCall<U256, T::Out>>),
+    /// Fetching the fee history of the blocks up to `head`.
+    FetchFeeHistory {
+        future: Timeout<ApiCall<Value, T::Out>>,
+        head: u64,
+    },
+    /// Sampling `eth_gasPrice` at `head`.
+    SampleGasPrice {
+        future: Timeout<ApiCall<U256, T::Out>>,
+        head: u64,
+    },
+    /// The price was derived at `head`.
+    Yield { price: U256, head: u64 },
+}
+
+/// rpc methods `--self-test` probes for `node_gas_price` on foreign.
+pub fn self_test_probes(config: &Config) -> Vec<Probe> {
+    let config = match config.node_gas_price {
+        Some(ref config) => config,
+        None => return Vec::new(),
+    };
+    let fee_history = vec![
+        format!("0x{:x}", config.blocks).into(),
+        "latest".into(),
+        vec![Value::from(config.percentile)].into(),
+    ];
+    vec![
+        // the price is sampled from eth_gasPrice without fee history
+        Probe::call("foreign", "eth_feeHistory", fee_history, "node gas price")
+            .enabled_by("node_gas_price")
+            .fallback(),
+        Probe::call("foreign", "eth_gasPrice", vec![], "node gas price")
+            .enabled_by("node_gas_price"),
+    ]
+}
+
+pub fn create_node_gas_price_monitor<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    interval: Interval,
+    config: NodeGasPriceConfig,
+) -> NodeGasPriceMonitor<T> {
+    NodeGasPriceMonitor {
+        app,
+        interval,
+        config,
+        derived_at: None,
+        fee_history: true,
+        samples: VecDeque::new(),
+        state: NodeGasPriceState::Wait,
+    }
+}
+
+/// Derives the gas price of foreign once per new foreign block, see `NodeGasPriceConfig`,
+/// and stores it in the `SharedGasPrice` of the app. yields every derived price.
+///
+/// the price is derived again only once the head moved. a request that fails keeps the
+/// last price until the next block.
+pub struct NodeGasPriceMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    interval: Interval,
+    config: NodeGasPriceConfig,
+    /// head the price was last derived at
+    derived_at: Option<u64>,
+    /// `false` once the node turned out not to provide a fee history
+    fee_history: bool,
+    /// latest `eth_gasPrice` samples, oldest first. only taken without fee history
+    samples: VecDeque<U256>,
+    state: NodeGasPriceState<T>,
+}
+
+impl<T: Transport> NodeGasPriceMonitor<T> {
+    /// samples `eth_gasPrice` from now on.
+    fn fall_back(&mut self, head: u64, reason: &str) -> NodeGasPriceState<T> {
+        warn!(
+            target: "bridge",
+            "deriving the foreign gas price from eth_gasPrice samples: {}",
+            reason
+        );
+        self.fee_history = false;
+        self.sample_gas_price(head)
+    }
+
+    fn sample_gas_price(&self, head: u64) -> NodeGasPriceState<T> {
+        let future = api::timeout(
+            &self.app.timer,
+            api::gas_price(&self.app.connections.foreign),
+            "foreign",
+            self.app.config.foreign.request_timeout,
+        );
+        NodeGasPriceState::SampleGasPrice { future, head }
+    }
+}
+
+impl<T: Transport> Stream for NodeGasPriceMonitor<T> {
+    type Item = u64;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                NodeGasPriceState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    NodeGasPriceState::FetchHead(api::timeout(
+                        &self.app.timer,
+                        api::block_number(&self.app.connections.foreign),
+                        "foreign",
+                        self.app.config.foreign.request_timeout,
+                    ))
+                }
+                NodeGasPriceState::FetchHead(ref mut future) => {
+                    let head = match future.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(head)) => head.low_u64(),
+                        Err(err) => {
+                            warn!(target: "bridge", "cannot fetch the head of foreign: {}", err);
+                            self.state = NodeGasPriceState::Wait;
+                            continue;
+                        }
+                    };
+                    if self.derived_at == Some(head) {
+                        NodeGasPriceState::Wait
+                    } else if self.fee_history {
+                        let future = api::timeout(
+                            &self.app.timer,
+                            api::fee_history(
+                                &self.app.connections.foreign,
+                                self.config.blocks,
+                                self.config.percentile,
+                            ),
+                            "foreign",
+                            self.app.config.foreign.request_timeout,
+                        );
+                        NodeGasPriceState::FetchFeeHistory { future, head }
+                    } else {
+                        self.sample_gas_price(head)
+                    }
+                }
+                NodeGasPriceState::FetchFeeHistory {
+                    ref mut future,
+                    head,
+                } => match future.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(result)) => match parse_fee_history(&result) {
+                        Ok(history) => match fee_history_price(&history) {
+                            Some(price) => NodeGasPriceState::Yield { price, head },
+                            None => self.fall_back(head, "the chain has no base fee"),
+                        },
+                        Err(reason) => {
+                            warn!(
+                                target: "bridge",
+                                "invalid eth_feeHistory of foreign: {}",
+                                reason
+                            );
+                            NodeGasPriceState::Wait
+                        }
+                    },
+                    Err(ref err) if api::is_method_not_found(err) => {
+                        self.fall_back(head, "the node has no eth_feeHistory")
+                    }
+                    Err(err) => {
+                        warn!(target: "bridge", "cannot fetch the fee history of foreign: {}", err);
+                        NodeGasPriceState::Wait
+                    }
+                },
+                NodeGasPriceState::SampleGasPrice {
+                    ref mut future,
+                    head,
+                } => match future.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(sample)) => {
+                        if self.samples.len() as u64 >= self.config.blocks {
+                            self.samples.pop_front();
+                        }
+                        self.samples.push_back(sample);
+                        let samples: Vec<_> = self.samples.iter().cloned().collect();
+                        let price = percentile(&samples, self.config.percentile)
+                            .expect("a sample was just added; qed");
+                        NodeGasPriceState::Yield { price, head }
+                    }
+                    Err(err) => {
+                        warn!(target: "bridge", "cannot fetch the gas price of foreign: {}", err);
+                        NodeGasPriceState::Wait
+                    }
+                },
+                NodeGasPriceState::Yield { price, head } => {
+                    self.state = NodeGasPriceState::Wait;
+                    self.derived_at = Some(head);
+                    let price = clamp_gas_price(price, self.config.floor, self.config.ceiling);
+                    debug!(
+                        target: "bridge",
+                        "foreign gas price at block {}: {}",
+                        head,
+                        format_wei(price.into())
+                    );
+                    self.app.node_gas_price.set(price);
+                    return Ok(Async::Ready(Some(price)));
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use web3::types::U256;
+    use super::{clamp_gas_price, fee_history_price, parse_fee_history, percentile, FeeHistory};
+
+    fn wei(values: &[u64]) -> Vec<U256> {
+        values.iter().map(|value| (*value).into()).collect()
+    }
+
+    #[test]
+    fn test_parse_fee_history() {
+        let result = serde_json::from_str(
+            r#"{"oldestBlock": "0x10", "baseFeePerGas": ["0x064", "0x6e", "0x78"],
+                "gasUsedRatio": [0.5, 0.9], "reward": [["0xa"], ["0x014"]]}"#,
+        ).unwrap();
+        assert_eq!(
+            FeeHistory {
+                base_fees: wei(&[100, 110, 120]),
+                rewards: wei(&[10, 20]),
+            },
+            parse_fee_history(&result).unwrap()
+        );
+
+        // chains without base fee
+        let result = serde_json::from_str(r#"{"oldestBlock": "0x10", "reward": [["0x1"]]}"#)
+            .unwrap();
+        assert!(parse_fee_history(&result).unwrap().base_fees.is_empty());
+
+        let result = serde_json::from_str(r#"{"baseFeePerGas": ["0x1"], "reward": [[]]}"#)
+            .unwrap();
+        assert_eq!(
+            Err("reward: reward [] has no priority fee".to_owned()),
+            parse_fee_history(&result)
+        );
+        let result = serde_json::from_str(r#"{"baseFeePerGas": ["1"]}"#).unwrap();
+        assert_eq!(
+            Err("baseFeePerGas: quantity \"1\" must start with 0x".to_owned()),
+            parse_fee_history(&result)
+        );
+    }
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(None, percentile(&[], 50));
+        let values = wei(&[30, 10, 20, 50, 40]);
+        assert_eq!(Some(10.into()), percentile(&values, 0));
+        assert_eq!(Some(10.into()), percentile(&values, 20));
+        assert_eq!(Some(30.into()), percentile(&values, 50));
+        assert_eq!(Some(50.into()), percentile(&values, 90));
+        assert_eq!(Some(50.into()), percentile(&values, 100));
+    }
+
+    #[test]
+    fn test_fee_history_price() {
+        // rising base fee: raised by 12.5%
+        let rising = FeeHistory {
+            base_fees: wei(&[100, 110, 120]),
+            rewards: wei(&[10, 30, 20]),
+        };
+        assert_eq!(Some((120 + 15 + 20).into()), fee_history_price(&rising));
+
+        let falling = FeeHistory {
+            base_fees: wei(&[120, 110, 100]),
+            rewards: wei(&[5]),
+        };
+        assert_eq!(Some(105.into()), fee_history_price(&falling));
+
+        let no_base_fee = FeeHistory {
+            base_fees: wei(&[0, 0]),
+            rewards: wei(&[5]),
+        };
+        assert_eq!(None, fee_history_price(&no_base_fee));
+        assert_eq!(
+            None,
+            fee_history_price(&FeeHistory {
+                base_fees: Vec::new(),
+                rewards: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_clamp_gas_price() {
+        assert_eq!(50, clamp_gas_price(50.into(), 10, Some(100)));
+        assert_eq!(10, clamp_gas_price(5.into(), 10, Some(100)));
+        assert_eq!(100, clamp_gas_price(500.into(), 10, Some(100)));
+        let huge = U256::from(u64::max_value()) * 2.into();
+        assert_eq!(u64::max_value(), clamp_gas_price(huge, 0, None));
+    }
+}
@@ -0,0 +1,281 @@
+use web3::types::{Log, U256};
+use database::{Database, ObservedDay, ObservedEvent, RawLog};
+use units::checked_add;
+use super::history::Direction;
+use super::invoice::Day;
+
+/// days of observed traffic `ObservedTraffic` keeps of each direction.
+pub const KEPT_OBSERVED_DAYS: usize = 400;
+
+/// Deposit or withdraw event as soon as its log stream yielded it,
+/// before its component decides whether to relay it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub direction: Direction,
+    pub event: ObservedEvent,
+    /// value of the event, in wei of the chain that emitted it
+    pub value: U256,
+    /// log of the event, kept in `RawLogs` if the event is counted
+    pub log: RawLog,
+}
+
+impl Observation {
+    /// observation of the event in `log`. `None` for logs without transaction hash or block
+    /// number, which the components reject anyway.
+    pub fn from_log(direction: Direction, log: &Log, value: U256) -> Option<Self> {
+        match (log.transaction_hash, log.block_number) {
+            (Some(transaction), Some(block)) => Some(Observation {
+                direction,
+                event: ObservedEvent {
+                    transaction,
+                    log_index: log.log_index,
+                    block: block.low_u64(),
+                },
+                value,
+                log: RawLog::from_log(log),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Number and value of the events of a direction.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ObservedCount {
+    pub events: u64,
+    pub value: U256,
+}
+
+/// Deposits and withdraws observed by day, also those relayed by other authorities
+/// or found already relayed.
+///
+/// an event is counted once: the events of a day are kept with it until their component
+/// checked their block, and an event observed again is ignored. the log streams restart
+/// after the checked blocks, so events up to them aren't observed again.
+/// the most recent `KEPT_OBSERVED_DAYS` of each direction are kept, oldest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObservedTraffic {
+    days: Vec<ObservedDay>,
+    /// events counted since the start, by direction
+    deposits: ObservedCount,
+    withdraws: ObservedCount,
+}
+
+impl ObservedTraffic {
+    /// observed traffic continuing from that of `init`.
+    pub fn new(init: &Database) -> Self {
+        ObservedTraffic {
+            days: init.observed_traffic.clone(),
+            ..ObservedTraffic::default()
+        }
+    }
+
+    /// counts `observation` on the day of unix time `now`.
+    /// `false` if the event was counted already.
+    pub fn observe(&mut self, observation: Observation, now: u64) -> bool {
+        let Observation {
+            direction,
+            event,
+            value,
+            ..
+        } = observation;
+        let counted = self.days
+            .iter()
+            .filter(|day| day.direction == direction)
+            .any(|day| {
+                day.counted.iter().any(|counted| {
+                    counted.transaction == event.transaction && counted.log_index == event.log_index
+                })
+            });
+        if counted {
+            return false;
+        }
+        let today = Day::of(now);
+        let position = self.days
+            .iter()
+            .position(|day| day.day == today && day.direction == direction);
+        let position = match position {
+            Some(position) => position,
+            None => {
+                let kept = self.days
+                    .iter()
+                    .filter(|day| day.direction == direction)
+                    .count();
+                if kept >= KEPT_OBSERVED_DAYS {
+                    let oldest = self.days
+                        .iter()
+                        .position(|day| day.direction == direction)
+                        .expect("at least KEPT_OBSERVED_DAYS days of direction are kept; qed");
+                    self.days.remove(oldest);
+                }
+                self.days.push(ObservedDay {
+                    day: today,
+                    direction,
+                    events: 0,
+                    value: U256::zero(),
+                    counted: Vec::new(),
+                });
+                self.days.len() - 1
+            }
+        };
+        let day = &mut self.days[position];
+        day.events += 1;
+        // wei sums of a day beyond `U256` are bogus values, not traffic
+        day.value = checked_add(day.value, value).unwrap_or_else(U256::max_value);
+        day.counted.push(event);
+        let count = match direction {
+            Direction::HomeToForeign => &mut self.deposits,
+            Direction::ForeignToHome => &mut self.withdraws,
+        };
+        count.events += 1;
+        count.value = checked_add(count.value, value).unwrap_or_else(U256::max_value);
+        true
+    }
+
+    /// forgets the events the components checked: deposits up to home block `deposits`
+    /// and withdraws up to foreign block `withdraws`.
+    pub fn checked(&mut self, deposits: u64, withdraws: u64) {
+        for day in &mut self.days {
+            let checked = match day.direction {
+                Direction::HomeToForeign => deposits,
+                Direction::ForeignToHome => withdraws,
+            };
+            day.counted.retain(|event| event.block > checked);
+        }
+    }
+
+    /// kept days, oldest first.
+    pub fn days(&self) -> &[ObservedDay] {
+        &self.days
+    }
+
+    /// events of `direction` counted since the start.
+    pub fn count(&self, direction: Direction) -> ObservedCount {
+        match direction {
+            Direction::HomeToForeign => self.deposits,
+            Direction::ForeignToHome => self.withdraws,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::Log;
+    use database::{Database, ObservedEvent, RawLog};
+    use bridge::{Day, Direction};
+    use super::{Observation, ObservedCount, ObservedTraffic, KEPT_OBSERVED_DAYS};
+
+    /// 2024-01-01 00:00 utc
+    const JAN_1: u64 = 1_704_067_200;
+    const DAY: u64 = 86_400;
+
+    fn observation(direction: Direction, transaction: u64, block: u64, value: u64) -> Observation {
+        Observation {
+            direction,
+            event: ObservedEvent {
+                transaction: transaction.into(),
+                log_index: Some(0.into()),
+                block,
+            },
+            value: value.into(),
+            log: RawLog::from_log(&Log::default()),
+        }
+    }
+
+    #[test]
+    fn test_observation_from_log() {
+        let log = Log {
+            transaction_hash: Some(1.into()),
+            block_number: Some(10.into()),
+            log_index: Some(2.into()),
+            ..Log::default()
+        };
+        let observed = Observation::from_log(Direction::HomeToForeign, &log, 5.into()).unwrap();
+        assert_eq!(10, observed.event.block);
+        assert_eq!(Some(2.into()), observed.event.log_index);
+        assert_eq!(RawLog::from_log(&log), observed.log);
+        let unmined = Log {
+            block_number: None,
+            ..log
+        };
+        assert_eq!(
+            None,
+            Observation::from_log(Direction::HomeToForeign, &unmined, 5.into())
+        );
+    }
+
+    #[test]
+    fn test_observed_traffic_counts_events_once() {
+        let mut traffic = ObservedTraffic::new(&Database::default());
+        assert!(traffic.observe(observation(Direction::HomeToForeign, 1, 10, 100), JAN_1));
+        assert!(traffic.observe(observation(Direction::HomeToForeign, 2, 11, 50), JAN_1 + 60));
+        assert!(traffic.observe(observation(Direction::ForeignToHome, 1, 20, 7), JAN_1 + 60));
+        // observed again by a rebuilt component, on the next day
+        assert!(!traffic.observe(observation(Direction::HomeToForeign, 2, 11, 50), JAN_1 + DAY));
+        assert!(traffic.observe(observation(Direction::HomeToForeign, 3, 12, 1), JAN_1 + DAY));
+
+        let days = traffic.days();
+        assert_eq!(3, days.len());
+        assert_eq!(Day::parse("2024-01-01").unwrap(), days[0].day);
+        assert_eq!(Direction::HomeToForeign, days[0].direction);
+        assert_eq!(2, days[0].events);
+        assert_eq!(150.into(), days[0].value);
+        assert_eq!(Direction::ForeignToHome, days[1].direction);
+        assert_eq!(1, days[1].events);
+        assert_eq!(Day::parse("2024-01-02").unwrap(), days[2].day);
+        assert_eq!(1, days[2].events);
+        assert_eq!(
+            ObservedCount {
+                events: 3,
+                value: 151.into(),
+            },
+            traffic.count(Direction::HomeToForeign)
+        );
+
+        // restarted from the saved database
+        let database = Database {
+            observed_traffic: traffic.days().to_vec(),
+            ..Database::default()
+        };
+        let mut restarted = ObservedTraffic::new(&database);
+        assert!(!restarted.observe(observation(Direction::HomeToForeign, 1, 10, 100), JAN_1 + DAY));
+        assert_eq!(0, restarted.count(Direction::HomeToForeign).events);
+        assert_eq!(database.observed_traffic, restarted.days());
+    }
+
+    #[test]
+    fn test_observed_traffic_forgets_checked_events() {
+        let mut traffic = ObservedTraffic::new(&Database::default());
+        traffic.observe(observation(Direction::HomeToForeign, 1, 10, 1), JAN_1);
+        traffic.observe(observation(Direction::HomeToForeign, 2, 12, 1), JAN_1);
+        traffic.observe(observation(Direction::ForeignToHome, 3, 10, 1), JAN_1);
+        traffic.checked(10, 9);
+        assert_eq!(1, traffic.days()[0].counted.len());
+        assert_eq!(12, traffic.days()[0].counted[0].block);
+        assert_eq!(1, traffic.days()[1].counted.len());
+        // the day keeps its counts
+        assert_eq!(2, traffic.days()[0].events);
+
+        traffic.checked(12, 10);
+        assert!(traffic.days().iter().all(|day| day.counted.is_empty()));
+    }
+
+    #[test]
+    fn test_observed_traffic_keeps_the_latest_days() {
+        let mut traffic = ObservedTraffic::new(&Database::default());
+        traffic.observe(observation(Direction::ForeignToHome, 1, 1, 1), JAN_1);
+        for day in 0..KEPT_OBSERVED_DAYS as u64 + 1 {
+            let event = observation(Direction::HomeToForeign, 100 + day, day, 1);
+            traffic.observe(event, JAN_1 + day * DAY);
+        }
+        let deposits = traffic
+            .days()
+            .iter()
+            .filter(|day| day.direction == Direction::HomeToForeign)
+            .collect::<Vec<_>>();
+        assert_eq!(KEPT_OBSERVED_DAYS, deposits.len());
+        assert_eq!(Day::parse("2024-01-02").unwrap(), deposits[0].day);
+        // other directions keep their days
+        assert_eq!(Direction::ForeignToHome, traffic.days()[0].direction);
+    }
+}
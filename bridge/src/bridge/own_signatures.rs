@@ -0,0 +1,460 @@
+use std::mem;
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use futures::future::{join_all, JoinAll};
+use web3::Transport;
+use web3::types::{Address, Bytes, H256, H520};
+use api::{self, ApiCall, Settle, Timeout};
+use app::App;
+use error::{Error, Result, ResultExt};
+use message_to_mainnet::MessageToMainnet;
+use signature::Signature;
+use super::relay_events::{EventCheck, EventOutcome};
+use super::revert::{revert_reason_in_error, Revert, RevertKind};
+use super::withdraw_confirm::{submission_request, withdraw_submit_signature_payload};
+
+/// the messages of the `submitted` signatures whose submissions `checks` found reverted
+/// since this authority already signed them. `submitted` are the submission transactions
+/// with the messages of their signatures.
+pub fn already_signed(
+    checks: &[EventCheck],
+    submitted: &[(H256, MessageToMainnet)],
+) -> Vec<MessageToMainnet> {
+    checks
+        .iter()
+        .filter(|check| match check.outcome {
+            EventOutcome::Reverted(ref revert) => revert.kind == RevertKind::AlreadySigned,
+            _ => false,
+        })
+        .filter_map(|check| {
+            submitted
+                .iter()
+                .find(|&&(transaction, _)| transaction == check.transaction)
+                .map(|&(_, ref message)| message.clone())
+        })
+        .collect()
+}
+
+/// What the signatures `ForeignBridge` keeps of a message recover to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoredSignatures {
+    /// one of them recovers to the account of this authority
+    Verified,
+    /// none does. the accounts they recover to, invalid signatures left out
+    Mismatch(Vec<Address>),
+}
+
+/// checks that one of the `stored` signatures of `message` recovers to `account`.
+pub fn check_stored_signatures(
+    account: Address,
+    message: &[u8],
+    stored: &[Bytes],
+) -> StoredSignatures {
+    let mut recovered = Vec::new();
+    for signature in stored {
+        let signer =
+            Signature::from_bytes(&signature.0).and_then(|signature| signature.recover(message));
+        match signer {
+            Ok(signer) if signer == account => return StoredSignatures::Verified,
+            Ok(signer) => recovered.push(signer),
+            Err(err) => debug!(
+                target: "bridge::own_signatures",
+                "stored signature {:?} recovers to no account: {}",
+                signature,
+                err
+            ),
+        }
+    }
+    StoredSignatures::Mismatch(recovered)
+}
+
+/// What this authority did about a withdraw whose stored signatures aren't its own.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MismatchAction {
+    /// signed the withdraw again since `ForeignBridge` accepts another signature
+    Resigned,
+    /// left the withdraw alone since `ForeignBridge` refuses another signature, for this reason
+    Quarantined(String),
+}
+
+/// A withdraw this authority already signed according to `ForeignBridge`, none of whose
+/// stored signatures recovers to its account, e.g. after its key was rotated
+/// or a bug produced a corrupt signature. such a signature never verifies on home.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureMismatch {
+    /// hash of the foreign transaction that emitted the withdraw
+    pub withdraw: H256,
+    /// hash `ForeignBridge` keeps the message and its signatures by
+    pub message_hash: H256,
+    /// accounts the stored signatures recover to
+    pub recovered: Vec<Address>,
+    pub action: MismatchAction,
+}
+
+impl SignatureMismatch {
+    /// used in logs and alerts.
+    pub fn description(&self, account: Address) -> String {
+        let mismatch = format!(
+            "none of the stored signatures of message {:?} of the withdraw in foreign transaction {:?} recovers to authority {:?}, they recover to {:?}",
+            self.message_hash, self.withdraw, account, self.recovered
+        );
+        match self.action {
+            MismatchAction::Resigned => format!("{}. the withdraw is signed again", mismatch),
+            MismatchAction::Quarantined(ref reason) => format!(
+                "{}. the withdraw is quarantined since ForeignBridge refuses another signature: {}. it won't be withdrawn on home unless enough other authorities sign it",
+                mismatch, reason
+            ),
+        }
+    }
+}
+
+/// Outcome of `verify_own_signatures`.
+#[derive(Debug, Default)]
+pub struct VerifiedSignatures {
+    /// withdraws whose stored signatures aren't this authority's
+    pub mismatches: Vec<SignatureMismatch>,
+    /// the new signatures of the withdraws signed again, to be submitted
+    pub resubmissions: Vec<(MessageToMainnet, H520)>,
+}
+
+type CallFuture<T> = Settle<Timeout<ApiCall<Bytes, <T as Transport>::Out>>>;
+
+enum VerifyOwnSignaturesState<T: Transport> {
+    /// Fetching the signatures `ForeignBridge` keeps of every message.
+    FetchSignatures(JoinAll<Vec<JoinAll<Vec<CallFuture<T>>>>>),
+    /// Signing the messages whose stored signatures aren't ours again.
+    SignAgain {
+        future: JoinAll<Vec<Timeout<ApiCall<H520, T::Out>>>>,
+        /// the messages and the accounts their stored signatures recover to
+        mismatches: Vec<(MessageToMainnet, Vec<Address>)>,
+    },
+    /// Simulating the submissions of the new signatures, to see whether the contract
+    /// accepts another signature of this authority.
+    SimulateSubmissions {
+        future: JoinAll<Vec<CallFuture<T>>>,
+        mismatches: Vec<(MessageToMainnet, Vec<Address>)>,
+        signatures: Vec<H520>,
+    },
+}
+
+/// Future verifying that the signatures `ForeignBridge` keeps of `messages`, which this
+/// authority already signed, include one recovering to its account, see
+/// `verify_own_signatures`. messages without one are signed again if the contract accepts
+/// another signature and quarantined otherwise.
+pub struct VerifyOwnSignatures<T: Transport> {
+    app: Arc<App<T>>,
+    foreign_contract: Address,
+    messages: Vec<MessageToMainnet>,
+    state: VerifyOwnSignaturesState<T>,
+}
+
+fn call<T: Transport>(app: &App<T>, foreign_contract: Address, payload: Vec<u8>) -> CallFuture<T> {
+    api::settle(api::timeout(
+        &app.timer,
+        api::call(&app.connections.foreign, foreign_contract, payload.into()),
+        "foreign",
+        app.config.foreign.request_timeout,
+    ))
+}
+
+/// verifies the stored signatures of `messages`, which this authority already signed.
+pub fn verify_own_signatures<T: Transport>(
+    app: &Arc<App<T>>,
+    foreign_contract: Address,
+    messages: Vec<MessageToMainnet>,
+) -> VerifyOwnSignatures<T> {
+    let functions = app.foreign_bridge.functions();
+    // there are at most as many signatures as authorities
+    let calls = messages
+        .iter()
+        .map(|message| {
+            let hash = message.hash();
+            let calls = (0..app.config.authorities.accounts.len() as u32)
+                .map(|index| {
+                    call(
+                        app,
+                        foreign_contract,
+                        functions.signature().input(hash.0, index),
+                    )
+                })
+                .collect::<Vec<_>>();
+            join_all(calls)
+        })
+        .collect::<Vec<_>>();
+    VerifyOwnSignatures {
+        app: app.clone(),
+        foreign_contract,
+        messages,
+        state: VerifyOwnSignaturesState::FetchSignatures(join_all(calls)),
+    }
+}
+
+/// the stored signatures of `message` in the `outputs` of the calls of `signature(hash, index)`.
+/// calls for indexes beyond the last signature fail. fails if all of them did, since
+/// `ForeignBridge` keeps at least the signature of this authority.
+fn stored_signatures<T: Transport>(
+    app: &App<T>,
+    message: &MessageToMainnet,
+    outputs: Vec<Result<Bytes>>,
+) -> Result<Vec<Bytes>> {
+    let mut stored = Vec::new();
+    let mut failure = None;
+    for output in outputs {
+        match output {
+            Ok(output) => {
+                let signature = app.foreign_bridge
+                    .functions()
+                    .signature()
+                    .output(&output.0)
+                    .map_err(Error::from)?;
+                stored.push(Bytes(signature));
+            }
+            Err(err) => failure = failure.or(Some(err)),
+        }
+    }
+    match failure {
+        Some(err) if stored.is_empty() => Err(err).chain_err(|| {
+            format!(
+                "Cannot fetch the stored signatures of the withdraw in foreign transaction {:?}",
+                message.sidenet_transaction_hash
+            )
+        }),
+        _ => Ok(stored),
+    }
+}
+
+impl<T: Transport> Future for VerifyOwnSignatures<T> {
+    type Item = VerifiedSignatures;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                VerifyOwnSignaturesState::FetchSignatures(ref mut future) => {
+                    let outputs = try_ready!(future.poll());
+                    let account = self.app.config.foreign.account;
+                    let mut mismatches = Vec::new();
+                    for (message, outputs) in self.messages.iter().zip(outputs.into_iter()) {
+                        let stored = stored_signatures(&self.app, message, outputs)?;
+                        match check_stored_signatures(account, &message.to_bytes(), &stored) {
+                            StoredSignatures::Verified => debug!(
+                                target: "bridge::own_signatures",
+                                "stored signature of the withdraw in foreign transaction {} recovers to this authority",
+                                message.sidenet_transaction_hash
+                            ),
+                            StoredSignatures::Mismatch(recovered) => {
+                                mismatches.push((message.clone(), recovered))
+                            }
+                        }
+                    }
+                    if mismatches.is_empty() {
+                        return Ok(Async::Ready(VerifiedSignatures::default()));
+                    }
+                    let app = &self.app;
+                    let requests = mismatches
+                        .iter()
+                        .map(|&(ref message, _)| {
+                            api::timeout(
+                                &app.timer,
+                                api::node_sign(
+                                    &app.connections.foreign,
+                                    &app.config.foreign,
+                                    Bytes(message.to_bytes()),
+                                ),
+                                "foreign",
+                                app.config.foreign.request_timeout,
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    VerifyOwnSignaturesState::SignAgain {
+                        future: join_all(requests),
+                        mismatches,
+                    }
+                }
+                VerifyOwnSignaturesState::SignAgain {
+                    ref mut future,
+                    ref mut mismatches,
+                } => {
+                    let signatures = try_ready!(future.poll());
+                    let app = &self.app;
+                    let foreign_contract = self.foreign_contract;
+                    let simulations = mismatches
+                        .iter()
+                        .zip(signatures.iter())
+                        .map(|(&(ref message, _), signature)| {
+                            let payload = withdraw_submit_signature_payload(
+                                &app.foreign_bridge,
+                                message,
+                                *signature,
+                            );
+                            let request = submission_request(app, foreign_contract, payload);
+                            api::settle(api::timeout(
+                                &app.timer,
+                                api::simulate_transaction(&app.connections.foreign, &request),
+                                "foreign",
+                                app.config.foreign.request_timeout,
+                            ))
+                        })
+                        .collect::<Vec<_>>();
+                    VerifyOwnSignaturesState::SimulateSubmissions {
+                        future: join_all(simulations),
+                        mismatches: mem::replace(mismatches, Vec::new()),
+                        signatures,
+                    }
+                }
+                VerifyOwnSignaturesState::SimulateSubmissions {
+                    ref mut future,
+                    ref mut mismatches,
+                    ref mut signatures,
+                } => {
+                    let simulations = try_ready!(future.poll());
+                    let account = self.app.config.foreign.account;
+                    let mut verified = VerifiedSignatures::default();
+                    let mismatches = mismatches
+                        .drain(..)
+                        .zip(signatures.drain(..))
+                        .zip(simulations.into_iter());
+                    for (((message, recovered), signature), simulation) in mismatches {
+                        let action = match simulation {
+                            Ok(_) => MismatchAction::Resigned,
+                            Err(err) => MismatchAction::Quarantined(
+                                Revert::new(revert_reason_in_error(&err)).description(),
+                            ),
+                        };
+                        let mismatch = SignatureMismatch {
+                            withdraw: message.sidenet_transaction_hash,
+                            message_hash: message.hash(),
+                            recovered,
+                            action,
+                        };
+                        error!(
+                            target: "bridge::own_signatures",
+                            "CRITICAL: {}",
+                            mismatch.description(account)
+                        );
+                        if mismatch.action == MismatchAction::Resigned {
+                            verified.resubmissions.push((message, signature));
+                        }
+                        verified.mismatches.push(mismatch);
+                    }
+                    return Ok(Async::Ready(verified));
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hex::FromHex;
+    use web3::types::{Address, Bytes, H256, U256};
+    use message_to_mainnet::MessageToMainnet;
+    use super::super::relay_events::{EventCheck, EventOutcome, ExpectedEvent};
+    use super::super::revert::Revert;
+    use super::super::RelayCall;
+    use super::{already_signed, check_stored_signatures, StoredSignatures};
+
+    /// `eth_sign` of the message of `withdraw()` by 0x2c7536e3605d9c16a7a3d7b1898e529396a65c23
+    const SIGNATURE: &str = "ab919755bd2d15f06535730e3270151c678930427095d0f376d8b48bbf734ac4\
+                             34b3bb45bd91db1419d1e0d28b33111206527ee7d2b0af2ec9deadd9b0e0006d1b";
+    /// `eth_sign` of the same message by the rotated key 0x7e5f4552091a69125d5dfcb7b8c2659029395bdf
+    const ROTATED: &str = "f973a0b87062c389d125d8199e803b832b6ac6bf7867a4f6cd87506060fc4c58\
+                           5cbc86a30e8da0184ca1b07a338f07882de8bf0d6f899225fdd4dd73e075d4fc1c";
+
+    fn withdraw() -> MessageToMainnet {
+        MessageToMainnet {
+            recipient: "aff3454fce5edbc8cca8697c15331677e6ebcccc".parse().unwrap(),
+            value: U256::from(0xf0),
+            sidenet_transaction_hash:
+                "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
+                    .parse()
+                    .unwrap(),
+            mainnet_gas_price: U256::from(1_000_000_000),
+        }
+    }
+
+    fn authority() -> Address {
+        "2c7536e3605d9c16a7a3d7b1898e529396a65c23".parse().unwrap()
+    }
+
+    fn signature(hex: &str) -> Bytes {
+        Bytes(hex.from_hex().unwrap())
+    }
+
+    #[test]
+    fn test_stored_signature_of_the_authority_is_verified() {
+        let message = withdraw().to_bytes();
+        let stored = vec![signature(ROTATED), signature(SIGNATURE)];
+        assert_eq!(
+            StoredSignatures::Verified,
+            check_stored_signatures(authority(), &message, &stored)
+        );
+    }
+
+    #[test]
+    fn test_wrong_stored_signatures_are_a_mismatch() {
+        let message = withdraw().to_bytes();
+        // signed with a rotated key
+        let other: Address = "7e5f4552091a69125d5dfcb7b8c2659029395bdf".parse().unwrap();
+        assert_eq!(
+            StoredSignatures::Mismatch(vec![other]),
+            check_stored_signatures(authority(), &message, &[signature(ROTATED)])
+        );
+
+        // corrupted. the signature of another message recovers to another account if any
+        let mut corrupt = signature(SIGNATURE);
+        corrupt.0[10] ^= 0xff;
+        match check_stored_signatures(authority(), &message, &[corrupt]) {
+            StoredSignatures::Mismatch(recovered) => assert!(!recovered.contains(&authority())),
+            verified => panic!("corrupt signature is {:?}", verified),
+        }
+
+        // a signature over a different message doesn't verify either
+        let mut different = withdraw();
+        different.value = U256::from(0xf1);
+        match check_stored_signatures(authority(), &different.to_bytes(), &[signature(SIGNATURE)]) {
+            StoredSignatures::Mismatch(recovered) => assert!(!recovered.contains(&authority())),
+            verified => panic!("signature of another message is {:?}", verified),
+        }
+
+        // too short to be a signature
+        assert_eq!(
+            StoredSignatures::Mismatch(vec![]),
+            check_stored_signatures(authority(), &message, &[Bytes(vec![1, 2, 3])])
+        );
+    }
+
+    #[test]
+    fn test_already_signed_are_the_submissions_reverted_for_it() {
+        let check = |transaction: u64, outcome: EventOutcome| EventCheck {
+            transaction: transaction.into(),
+            expected: ExpectedEvent {
+                contract: Address::zero(),
+                alternatives: vec![],
+                call: RelayCall::SubmitSignature,
+                gas: None,
+                resend: None,
+            },
+            outcome,
+            gas_used: None,
+            retry: None,
+            event: None,
+        };
+        let checks = vec![
+            check(1, EventOutcome::Emitted),
+            check(2, EventOutcome::Reverted(Revert::new(Some("already signed".into())))),
+            check(3, EventOutcome::Reverted(Revert::new(Some("not an authority".into())))),
+            check(4, EventOutcome::Reverted(Revert::new(None))),
+        ];
+        let mut second = withdraw();
+        second.sidenet_transaction_hash = H256::from(2);
+        let submitted = vec![
+            (H256::from(1), withdraw()),
+            (H256::from(2), second.clone()),
+            (H256::from(3), withdraw()),
+            (H256::from(4), withdraw()),
+        ];
+        assert_eq!(vec![second], already_signed(&checks, &submitted));
+    }
+}
@@ -0,0 +1,495 @@
+use std::sync::Arc;
+use futures::{Async, Future, Poll, Stream};
+use tiny_keccak::keccak256;
+use timer::Sleep;
+use web3;
+use web3::Transport;
+use web3::types::{Address, Bytes, FilterBuilder, H256, Log, U256};
+use api::{self, ApiCall, LogStream, Timeout};
+use app::App;
+use database::Database;
+use error::{Error, ErrorKind};
+use super::deploy_block::{chain, chain_name};
+use super::deposit_relay::deposit_log_stream;
+use super::withdraw_confirm::withdraw_log_stream;
+use super::withdraw_relay::collected_signatures_log_stream;
+use super::Component;
+
+/// getter of the emergency pause flag of the contracts
+pub const PAUSED_FUNCTION: &str = "paused()";
+/// events the contracts emit when governance sets or clears the pause flag.
+/// the account is not indexed.
+pub const PAUSED_EVENT: &str = "Paused(address)";
+pub const UNPAUSED_EVENT: &str = "Unpaused(address)";
+
+fn topic(event: &str) -> H256 {
+    keccak256(event.as_bytes()).into()
+}
+
+/// Pause flag of a bridge contract.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseState {
+    /// not checked yet
+    Unknown,
+    /// the contract has no `paused()` getter
+    Unsupported,
+    Unpaused,
+    Paused,
+}
+
+impl PauseState {
+    /// `true` if transactions to the contract are held: while it's paused
+    /// and until it's checked.
+    pub fn holds(&self) -> bool {
+        match *self {
+            PauseState::Unknown | PauseState::Paused => true,
+            PauseState::Unsupported | PauseState::Unpaused => false,
+        }
+    }
+}
+
+/// Pause flags of `HomeBridge` and `ForeignBridge`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ContractPauses {
+    pub home: PauseState,
+    pub foreign: PauseState,
+}
+
+impl Default for ContractPauses {
+    fn default() -> Self {
+        ContractPauses {
+            home: PauseState::Unknown,
+            foreign: PauseState::Unknown,
+        }
+    }
+}
+
+impl ContractPauses {
+    /// pause flag of the contract `component` sends its transactions to.
+    pub fn of_target(&self, component: Component) -> PauseState {
+        match component {
+            Component::DepositRelay | Component::WithdrawConfirm => self.foreign,
+            Component::WithdrawRelay => self.home,
+        }
+    }
+
+    /// `true` if `component` must not send transactions.
+    pub fn suspends(&self, component: Component) -> bool {
+        self.of_target(component).holds()
+    }
+}
+
+/// name of the contract `component` sends its transactions to. used in logs.
+pub fn target_contract(component: Component) -> &'static str {
+    match component {
+        Component::DepositRelay | Component::WithdrawConfirm => "ForeignBridge",
+        Component::WithdrawRelay => "HomeBridge",
+    }
+}
+
+/// the flag returned by `paused()`. `None` if `output` isn't a `bool`,
+/// e.g. because the call hit a fallback function.
+fn decode_paused(output: &[u8]) -> Option<bool> {
+    if output.len() != 32 || output[..31].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+    match output[31] {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+/// pause flag set by the last `Paused` or `Unpaused` event of `logs`, if any.
+fn last_event(logs: &[Log]) -> Option<PauseState> {
+    let (paused, unpaused) = (topic(PAUSED_EVENT), topic(UNPAUSED_EVENT));
+    for log in logs.iter().rev() {
+        match log.topics.first() {
+            Some(topic) if *topic == paused => return Some(PauseState::Paused),
+            Some(topic) if *topic == unpaused => return Some(PauseState::Unpaused),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `true` if `err` is the node failing the `paused()` call,
+/// which is what contracts without the getter and fallback function do.
+fn is_reverted(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::Web3(web3::Error::Rpc(_)) => true,
+        _ => false,
+    }
+}
+
+enum ChainPauseState<T: Transport> {
+    /// Fetching the block the flag is read at.
+    FetchHead(Timeout<ApiCall<U256, T::Out>>),
+    /// Reading the flag with `paused()`. the events are watched after `block`.
+    Detect {
+        future: Timeout<ApiCall<Bytes, T::Out>>,
+        block: u64,
+    },
+    /// Waiting to read the flag again after a failed request.
+    Retry(Sleep),
+    /// Following the `Paused` and `Unpaused` events.
+    Watch,
+    /// The contract has no pause flag.
+    Unsupported,
+}
+
+/// Follows the pause flag of the bridge contract on one chain.
+/// yields the flag once it's read and whenever an event changes it.
+struct ChainPause<T: Transport> {
+    app: Arc<App<T>>,
+    on_home: bool,
+    contract: Address,
+    /// last yielded flag
+    pause: PauseState,
+    /// `None` until the flag is read
+    events: Option<LogStream<T>>,
+    state: ChainPauseState<T>,
+}
+
+impl<T: Transport + Clone> ChainPause<T> {
+    fn new(app: Arc<App<T>>, on_home: bool, contract: Address) -> Self {
+        let state = fetch_head(&app, on_home);
+        ChainPause {
+            app,
+            on_home,
+            contract,
+            pause: PauseState::Unknown,
+            events: None,
+            state,
+        }
+    }
+}
+
+fn contract_name(on_home: bool) -> &'static str {
+    if on_home {
+        "HomeBridge"
+    } else {
+        "ForeignBridge"
+    }
+}
+
+fn fetch_head<T: Transport>(app: &App<T>, on_home: bool) -> ChainPauseState<T> {
+    let (transport, node) = chain(app, on_home);
+    ChainPauseState::FetchHead(api::timeout(
+        &app.timer,
+        api::block_number(transport),
+        chain_name(on_home),
+        node.request_timeout,
+    ))
+}
+
+fn read_flag<T: Transport>(
+    app: &App<T>,
+    on_home: bool,
+    contract: Address,
+    block: u64,
+) -> ChainPauseState<T> {
+    let (transport, node) = chain(app, on_home);
+    let payload = keccak256(PAUSED_FUNCTION.as_bytes())[..4].to_vec();
+    ChainPauseState::Detect {
+        future: api::timeout(
+            &app.timer,
+            api::call_at(transport, contract, payload.into(), Some(block)),
+            chain_name(on_home),
+            node.request_timeout,
+        ),
+        block,
+    }
+}
+
+fn retry<T: Transport>(
+    app: &App<T>,
+    on_home: bool,
+    contract: Address,
+    err: &Error,
+) -> ChainPauseState<T> {
+    let poll_interval = app.tunables.get().poll_interval(on_home);
+    warn!(
+        target: "bridge::pause",
+        "cannot read the pause flag of {} {:?}: {}. reading it again in {}s",
+        contract_name(on_home),
+        contract,
+        err,
+        poll_interval.as_secs()
+    );
+    ChainPauseState::Retry(app.timer.sleep(poll_interval))
+}
+
+/// events of `contract` after `block`, when its flag was read.
+fn event_stream<T: Transport + Clone>(
+    app: &App<T>,
+    on_home: bool,
+    contract: Address,
+    block: u64,
+) -> LogStream<T> {
+    let (transport, node) = chain(app, on_home);
+    let init = api::LogStreamInit {
+        after: block,
+        filter: FilterBuilder::default()
+            .address(vec![contract])
+            .topics(
+                Some(vec![topic(PAUSED_EVENT), topic(UNPAUSED_EVENT)]),
+                None,
+                None,
+                None,
+            ),
+        chain: chain_name(on_home),
+        request_timeout: node.request_timeout,
+        poll_interval: app.tunables.get().poll_interval(on_home),
+        poll_jitter: None,
+        // a pause takes effect right away, waiting for confirmations only produces reverts
+        confirmations: 0,
+        sanity_check: None,
+        head_regression: node.head_regression,
+        log_limit_errors: node.log_limit_errors.clone(),
+        max_log_data: node.max_log_data,
+        finality: None,
+    };
+    api::log_stream(transport.clone(), app.timer.clone(), init)
+}
+
+impl<T: Transport + Clone> Stream for ChainPause<T> {
+    type Item = PauseState;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let mut pause = None;
+            let next_state = match self.state {
+                ChainPauseState::FetchHead(ref mut future) => match future.poll() {
+                    Ok(Async::Ready(head)) => {
+                        read_flag(&self.app, self.on_home, self.contract, head.low_u64())
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => retry(&self.app, self.on_home, self.contract, &err.into()),
+                },
+                ChainPauseState::Detect {
+                    ref mut future,
+                    block,
+                } => match future.poll().map_err(Error::from) {
+                    Ok(Async::Ready(output)) => match decode_paused(&output.0) {
+                        Some(paused) => {
+                            let state = if paused {
+                                PauseState::Paused
+                            } else {
+                                PauseState::Unpaused
+                            };
+                            info!(
+                                target: "bridge::pause",
+                                "{} {:?} is {:?} at block {}. following its pause events",
+                                contract_name(self.on_home),
+                                self.contract,
+                                state,
+                                block
+                            );
+                            pause = Some(state);
+                            self.events =
+                                Some(event_stream(&self.app, self.on_home, self.contract, block));
+                            ChainPauseState::Watch
+                        }
+                        None => {
+                            warn!(
+                                target: "bridge::pause",
+                                "{} {:?} returned {:?} for paused(). it has no pause flag, pause awareness is disabled for it",
+                                contract_name(self.on_home),
+                                self.contract,
+                                output
+                            );
+                            pause = Some(PauseState::Unsupported);
+                            ChainPauseState::Unsupported
+                        }
+                    },
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(ref err) if is_reverted(err) => {
+                        warn!(
+                            target: "bridge::pause",
+                            "{} {:?} failed paused(): {}. it has no pause flag, pause awareness is disabled for it",
+                            contract_name(self.on_home),
+                            self.contract,
+                            err
+                        );
+                        pause = Some(PauseState::Unsupported);
+                        ChainPauseState::Unsupported
+                    }
+                    Err(err) => retry(&self.app, self.on_home, self.contract, &err),
+                },
+                ChainPauseState::Retry(ref mut sleep) => {
+                    try_ready!(sleep.poll());
+                    fetch_head(&self.app, self.on_home)
+                }
+                ChainPauseState::Watch => {
+                    let item = match self.events {
+                        Some(ref mut events) => try_stream!(events.poll()),
+                        None => return Ok(Async::NotReady),
+                    };
+                    pause = last_event(&item.logs);
+                    ChainPauseState::Watch
+                }
+                // a contract without pause flag is never paused
+                ChainPauseState::Unsupported => return Ok(Async::NotReady),
+            };
+            self.state = next_state;
+            match pause {
+                Some(pause) if pause != self.pause => {
+                    self.pause = pause;
+                    return Ok(Async::Ready(Some(pause)));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Follows the pause flags of `HomeBridge` and `ForeignBridge`: reads them with `paused()`
+/// and then follows their `Paused` and `Unpaused` events.
+/// yields the flags whenever one of them changes, starting with the first reads.
+/// a contract without `paused()` is `PauseState::Unsupported` and never paused.
+pub struct PauseMonitor<T: Transport> {
+    home: ChainPause<T>,
+    foreign: ChainPause<T>,
+    pauses: ContractPauses,
+}
+
+pub fn create_pause_monitor<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    init: &Database,
+) -> PauseMonitor<T> {
+    PauseMonitor {
+        home: ChainPause::new(app.clone(), true, init.home_contract_address),
+        foreign: ChainPause::new(app, false, init.foreign_contract_address),
+        pauses: ContractPauses::default(),
+    }
+}
+
+impl<T: Transport + Clone> Stream for PauseMonitor<T> {
+    type Item = ContractPauses;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut changed = false;
+        while let Async::Ready(Some(pause)) = self.home.poll()? {
+            self.pauses.home = pause;
+            changed = true;
+        }
+        while let Async::Ready(Some(pause)) = self.foreign.poll()? {
+            self.pauses.foreign = pause;
+            changed = true;
+        }
+        if changed {
+            Ok(Async::Ready(Some(self.pauses)))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Counts the relays of a component suspended by a pause,
+/// by following its logs from its checked block.
+pub struct HeldRelays<T: Transport> {
+    pub component: Component,
+    logs: LogStream<T>,
+    held: u64,
+}
+
+/// follows the logs `component` relays after its checked block in `init`.
+pub fn watch_held_relays<T: Transport + Clone>(
+    app: &App<T>,
+    component: Component,
+    init: &Database,
+) -> HeldRelays<T> {
+    let logs = match component {
+        Component::DepositRelay => deposit_log_stream(app, init),
+        Component::WithdrawConfirm => withdraw_log_stream(app, init),
+        Component::WithdrawRelay => collected_signatures_log_stream(app, init),
+    };
+    HeldRelays {
+        component,
+        logs,
+        held: 0,
+    }
+}
+
+impl<T: Transport> HeldRelays<T> {
+    /// relays observed so far.
+    pub fn held(&self) -> u64 {
+        self.held
+    }
+
+    /// counts the relays observed since the last call.
+    pub fn observe(&mut self) -> Result<u64, Error> {
+        while let Async::Ready(Some(item)) = self.logs.poll()? {
+            self.held += item.logs.len() as u64;
+        }
+        Ok(self.held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{H256, Log};
+    use bridge::Component;
+    use super::{decode_paused, last_event, topic, ContractPauses, PauseState, PAUSED_EVENT,
+                UNPAUSED_EVENT};
+
+    #[test]
+    fn test_decode_paused() {
+        let mut output = vec![0u8; 32];
+        assert_eq!(Some(false), decode_paused(&output));
+        output[31] = 1;
+        assert_eq!(Some(true), decode_paused(&output));
+        output[31] = 2;
+        assert_eq!(None, decode_paused(&output));
+        output[31] = 1;
+        output[0] = 1;
+        assert_eq!(None, decode_paused(&output));
+        // fallback function without output
+        assert_eq!(None, decode_paused(&[]));
+    }
+
+    #[test]
+    fn test_last_event_sets_the_flag() {
+        let log = |event: &str| Log {
+            topics: vec![topic(event)],
+            ..Default::default()
+        };
+        let other = Log {
+            topics: vec![H256::from(1)],
+            ..Default::default()
+        };
+        assert_eq!(None, last_event(&[other.clone()]));
+        assert_eq!(
+            Some(PauseState::Unpaused),
+            last_event(&[log(PAUSED_EVENT), log(UNPAUSED_EVENT), other.clone()])
+        );
+        assert_eq!(
+            Some(PauseState::Paused),
+            last_event(&[log(UNPAUSED_EVENT), log(PAUSED_EVENT), other])
+        );
+    }
+
+    #[test]
+    fn test_pauses_suspend_the_components_sending_to_the_paused_contract() {
+        let pauses = ContractPauses {
+            home: PauseState::Unpaused,
+            foreign: PauseState::Paused,
+        };
+        assert!(pauses.suspends(Component::DepositRelay));
+        assert!(pauses.suspends(Component::WithdrawConfirm));
+        assert!(!pauses.suspends(Component::WithdrawRelay));
+
+        // contracts are held until checked
+        assert!(ContractPauses::default().suspends(Component::WithdrawRelay));
+        let pauses = ContractPauses {
+            home: PauseState::Unsupported,
+            foreign: PauseState::Unpaused,
+        };
+        assert!(!pauses.suspends(Component::DepositRelay));
+        assert!(!pauses.suspends(Component::WithdrawRelay));
+    }
+}
@@ -0,0 +1,337 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
+use std::sync::Arc;
+use futures::{Async, Future};
+use futures::future::{join_all, JoinAll};
+use web3::Transport;
+use web3::types::{Block, H256, Log, U256};
+use api::{self, ApiCall, Settle, Timeout};
+use app::App;
+use super::{relay_key, Direction};
+use super::spend_limit::unix_time;
+
+/// A deposit or withdraw observed by its relay but not yet relayed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PendingRelay {
+    pub direction: Direction,
+    /// transaction that emitted the log.
+    /// for withdraws the transaction that collected their signatures
+    pub source_transaction: H256,
+    pub block: u64,
+    /// unix time of `block`. the time the log was observed until the block is fetched
+    pub since: u64,
+}
+
+impl PendingRelay {
+    /// seconds the relay has been pending at unix time `now`.
+    pub fn age(&self, now: u64) -> u64 {
+        now.saturating_sub(self.since)
+    }
+}
+
+/// Pending relays ordered by `since`.
+///
+/// a min-heap of `(since, key)` with the current relay of each key in a map.
+/// removed and updated relays leave stale heap entries that `oldest` drops.
+#[derive(Debug, Default)]
+pub struct PendingRelays {
+    heap: BinaryHeap<Reverse<(u64, H256)>>,
+    relays: HashMap<H256, PendingRelay>,
+}
+
+impl PendingRelays {
+    /// adds the relay with `key`. a relay already pending keeps the older `since`.
+    pub fn insert(&mut self, key: H256, relay: PendingRelay) {
+        if let Some(pending) = self.relays.get(&key) {
+            if pending.since <= relay.since {
+                return;
+            }
+        }
+        self.heap.push(Reverse((relay.since, key)));
+        self.relays.insert(key, relay);
+    }
+
+    /// moves the relays in `block` back to `since` if that's older.
+    pub fn set_block_time(&mut self, block: u64, since: u64) {
+        let updated: Vec<_> = self.relays
+            .iter()
+            .filter(|&(_, relay)| relay.block == block && relay.since > since)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in updated {
+            let relay = self.relays
+                .get_mut(&key)
+                .expect("keys are taken from relays; qed");
+            relay.since = since;
+            self.heap.push(Reverse((since, key)));
+        }
+    }
+
+    pub fn remove(&mut self, key: &H256) -> Option<PendingRelay> {
+        self.relays.remove(key)
+    }
+
+    pub fn clear(&mut self) {
+        self.heap.clear();
+        self.relays.clear();
+    }
+
+    /// removes the relays whose key isn't in `keys`.
+    pub fn retain(&mut self, keys: &[H256]) {
+        self.relays.retain(|key, _| keys.contains(key));
+    }
+
+    pub fn len(&self) -> usize {
+        self.relays.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.relays.is_empty()
+    }
+
+    /// all pending relays, the oldest first.
+    pub fn all(&self) -> Vec<PendingRelay> {
+        let mut relays: Vec<_> = self.relays.values().cloned().collect();
+        relays.sort_by_key(|relay| (relay.since, relay.block, relay.source_transaction));
+        relays
+    }
+
+    /// the relay pending the longest. `None` if none is pending.
+    pub fn oldest(&mut self) -> Option<PendingRelay> {
+        while let Some(&Reverse((since, key))) = self.heap.peek() {
+            match self.relays.get(&key) {
+                Some(relay) if relay.since == since => return Some(*relay),
+                _ => {
+                    self.heap.pop();
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Tracks the deposits or withdraws a relay observed but hasn't relayed yet.
+///
+/// relays start out at the time their log was observed and move back to the
+/// timestamp of their block once it's fetched. fetching never holds up the relay.
+/// the tracker isn't persisted: a restarted or rebuilt relay observes the logs
+/// after its checked block again and fetches their blocks again.
+pub struct PendingAges<T: Transport> {
+    app: Arc<App<T>>,
+    direction: Direction,
+    relays: PendingRelays,
+    /// blocks of pending relays not fetched yet
+    unfetched: BTreeSet<u64>,
+    /// blocks being fetched
+    fetching: Option<(
+        Vec<u64>,
+        JoinAll<Vec<Settle<Timeout<ApiCall<Block<H256>, T::Out>>>>>,
+    )>,
+}
+
+impl<T: Transport> PendingAges<T> {
+    /// the logs of `direction` are on home for deposits and on foreign for withdraws.
+    pub fn new(app: Arc<App<T>>, direction: Direction) -> Self {
+        PendingAges {
+            app,
+            direction,
+            relays: PendingRelays::default(),
+            unfetched: BTreeSet::new(),
+            fetching: None,
+        }
+    }
+
+    /// starts tracking the relays of `logs`. logs without a transaction hash are ignored.
+    pub fn observe(&mut self, logs: &[Log]) {
+        let now = unix_time();
+        for log in logs {
+            let transaction = match log.transaction_hash {
+                Some(transaction) => transaction,
+                None => continue,
+            };
+            let block = log.block_number.map_or(0, |block| block.low_u64());
+            self.relays.insert(
+                relay_key(self.direction, &transaction, log.log_index),
+                PendingRelay {
+                    direction: self.direction,
+                    source_transaction: transaction,
+                    block,
+                    since: now,
+                },
+            );
+            self.unfetched.insert(block);
+        }
+    }
+
+    /// stops tracking the relay of the log emitted by `transaction` at `log_index`.
+    pub fn finish(&mut self, transaction: &H256, log_index: Option<U256>) {
+        self.relays
+            .remove(&relay_key(self.direction, transaction, log_index));
+    }
+
+    /// stops tracking all relays, e.g. once all logs up to a checked block are relayed or skipped.
+    pub fn finish_all(&mut self) {
+        self.relays.clear();
+        self.unfetched.clear();
+    }
+
+    /// stops tracking all relays but those of the logs emitted by the transactions at the
+    /// log indices of `logs`, e.g. deposits held back past a checked block.
+    pub fn finish_all_but(&mut self, logs: &[(H256, Option<U256>)]) {
+        if logs.is_empty() {
+            return self.finish_all();
+        }
+        let direction = self.direction;
+        let keys: Vec<_> = logs.iter()
+            .map(|&(transaction, log_index)| relay_key(direction, &transaction, log_index))
+            .collect();
+        self.relays.retain(&keys);
+    }
+
+    pub fn len(&self) -> usize {
+        self.relays.len()
+    }
+
+    /// all pending relays, the oldest first.
+    pub fn all(&self) -> Vec<PendingRelay> {
+        self.relays.all()
+    }
+
+    /// the relay pending the longest. `None` if none is pending.
+    /// must be called from a task: also fetches the timestamps of the blocks of new relays.
+    pub fn oldest(&mut self) -> Option<PendingRelay> {
+        self.poll_block_times();
+        self.relays.oldest()
+    }
+
+    fn poll_block_times(&mut self) {
+        if self.fetching.is_none() {
+            if self.unfetched.is_empty() {
+                return;
+            }
+            let blocks: Vec<_> = self.unfetched.iter().cloned().collect();
+            self.unfetched.clear();
+            let app = &self.app;
+            let on_home = self.direction == Direction::HomeToForeign;
+            let (transport, chain, request_timeout) = if on_home {
+                (&app.connections.home, "home", app.config.home.request_timeout)
+            } else {
+                (&app.connections.foreign, "foreign", app.config.foreign.request_timeout)
+            };
+            let requests = blocks
+                .iter()
+                .map(|block| {
+                    api::settle(api::timeout(
+                        &app.timer,
+                        api::block_by_number(transport, *block),
+                        chain,
+                        request_timeout,
+                    ))
+                })
+                .collect();
+            self.fetching = Some((blocks, join_all(requests)));
+        }
+        let results = match self.fetching {
+            Some((_, ref mut future)) => match future.poll() {
+                Ok(Async::Ready(results)) => results,
+                Ok(Async::NotReady) => return,
+                Err(err) => {
+                    warn!("cannot fetch blocks of pending relays: {}", err);
+                    Vec::new()
+                }
+            },
+            None => return,
+        };
+        let (blocks, _) = self.fetching
+            .take()
+            .expect("fetching was polled above; qed");
+        let config = self.app.config.chain_clock.as_ref();
+        let clock = if self.direction == Direction::HomeToForeign {
+            &self.app.chain_clocks.home
+        } else {
+            &self.app.chain_clocks.foreign
+        };
+        for (block, result) in blocks.into_iter().zip(results) {
+            match result {
+                Ok(fetched) => {
+                    let time = clock.time_of(config, block, fetched.timestamp.low_u64());
+                    self.relays.set_block_time(block, time)
+                }
+                Err(err) => debug!(
+                    "cannot fetch block {} of pending relays. their age counts from when they were observed: {}",
+                    block,
+                    err
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bridge::Direction;
+    use super::{PendingRelay, PendingRelays};
+
+    fn relay(block: u64, since: u64) -> PendingRelay {
+        PendingRelay {
+            direction: Direction::HomeToForeign,
+            source_transaction: block.into(),
+            block,
+            since,
+        }
+    }
+
+    #[test]
+    fn test_pending_relays_oldest() {
+        let mut relays = PendingRelays::default();
+        assert_eq!(None, relays.oldest());
+        relays.insert(1.into(), relay(10, 300));
+        relays.insert(2.into(), relay(11, 200));
+        relays.insert(3.into(), relay(12, 400));
+        assert_eq!(Some(relay(11, 200)), relays.oldest());
+
+        // a relay observed again keeps the older time
+        relays.insert(2.into(), relay(11, 500));
+        assert_eq!(Some(relay(11, 200)), relays.oldest());
+
+        // relayed, skipped and deferred relays leave once finished
+        assert_eq!(Some(relay(11, 200)), relays.remove(&2.into()));
+        assert_eq!(Some(relay(10, 300)), relays.oldest());
+        assert_eq!(2, relays.len());
+        assert_eq!(vec![relay(10, 300), relay(12, 400)], relays.all());
+        relays.remove(&1.into());
+        relays.remove(&3.into());
+        assert_eq!(None, relays.oldest());
+        assert!(relays.all().is_empty());
+        assert!(relays.is_empty());
+    }
+
+    #[test]
+    fn test_pending_relays_block_time() {
+        let mut relays = PendingRelays::default();
+        relays.insert(1.into(), relay(10, 300));
+        relays.insert(2.into(), relay(11, 250));
+        relays.insert(3.into(), relay(11, 260));
+
+        // block 10 was mined before the relays of block 11 were observed
+        relays.set_block_time(10, 100);
+        assert_eq!(Some(relay(10, 100)), relays.oldest());
+        // block times never make relays younger
+        relays.set_block_time(11, 270);
+        relays.remove(&1.into());
+        assert_eq!(Some(relay(11, 250)), relays.oldest());
+        relays.set_block_time(11, 90);
+        assert_eq!(Some(relay(11, 90)), relays.oldest());
+        assert_eq!(90, relays.oldest().unwrap().since);
+
+        relays.insert(4.into(), relay(12, 80));
+        relays.retain(&[2.into()]);
+        assert_eq!(vec![relay(11, 90)], relays.all());
+        assert_eq!(Some(relay(11, 90)), relays.oldest());
+
+        relays.clear();
+        assert_eq!(None, relays.oldest());
+        assert_eq!(0, relay(1, 100).age(50));
+        assert_eq!(20, relay(1, 100).age(120));
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::{Duration, Instant};
+use config::DegradedPersistenceConfig;
+
+/// Saves failing since `since`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PersistenceOutage {
+    pub since: Instant,
+    /// saves of the bridge that failed since `since`, not counting the retries.
+    /// their changes are kept by the backend and written by the next save that succeeds
+    pub buffered_saves: usize,
+    /// error of the last failed save
+    pub error: String,
+}
+
+/// How a save changed the persistence of the bridge.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PersistenceChange {
+    Unchanged,
+    /// the first save failed. the bridge keeps relaying
+    Degraded,
+    /// the outage reached a limit of `degraded_persistence`. transactions are held
+    Halted,
+    /// a save succeeded after an outage of `gap`, writing its buffered saves
+    Recovered {
+        gap: Duration,
+        buffered_saves: usize,
+        /// whether transactions were held
+        halted: bool,
+    },
+}
+
+/// Persistence of the bridge with `degraded_persistence`.
+///
+/// every save rewrites the whole database and the backend applies the changes of a save
+/// to its database before writing it, so a failed save is written by the next one that
+/// succeeds, in order with the saves before and after it. only the number of unwritten saves
+/// is tracked here. a crash loses them and the restarted bridge checks their blocks again,
+/// which the limits bound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DegradedPersistence {
+    config: DegradedPersistenceConfig,
+    outage: Option<PersistenceOutage>,
+    halted: bool,
+}
+
+impl DegradedPersistence {
+    pub fn new(config: DegradedPersistenceConfig) -> Self {
+        DegradedPersistence {
+            config,
+            outage: None,
+            halted: false,
+        }
+    }
+
+    /// `None` unless the last save failed.
+    pub fn outage(&self) -> Option<&PersistenceOutage> {
+        self.outage.as_ref()
+    }
+
+    /// `true` while transactions are held, from the outage reaching a limit until a save
+    /// succeeds.
+    pub fn halts(&self) -> bool {
+        self.halted
+    }
+
+    /// records a save that failed with `error` at `now`. retries of the buffered saves
+    /// don't add to them.
+    pub fn record_failure(
+        &mut self,
+        error: String,
+        retry: bool,
+        now: Instant,
+    ) -> PersistenceChange {
+        let degraded = self.outage.is_none();
+        let outage = self.outage.get_or_insert(PersistenceOutage {
+            since: now,
+            buffered_saves: 0,
+            error: String::new(),
+        });
+        outage.error = error;
+        if !retry {
+            outage.buffered_saves += 1;
+        }
+        let halt = outage.buffered_saves >= self.config.max_buffered_saves
+            || now.duration_since(outage.since) >= self.config.max_outage;
+        if halt && !self.halted {
+            self.halted = true;
+            PersistenceChange::Halted
+        } else if degraded {
+            PersistenceChange::Degraded
+        } else {
+            PersistenceChange::Unchanged
+        }
+    }
+
+    /// records a save that succeeded at `now`.
+    pub fn record_success(&mut self, now: Instant) -> PersistenceChange {
+        let halted = self.halted;
+        self.halted = false;
+        match self.outage.take() {
+            Some(outage) => PersistenceChange::Recovered {
+                gap: now.duration_since(outage.since),
+                buffered_saves: outage.buffered_saves,
+                halted,
+            },
+            None => PersistenceChange::Unchanged,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use config::DegradedPersistenceConfig;
+    use super::{DegradedPersistence, PersistenceChange};
+
+    fn config() -> DegradedPersistenceConfig {
+        DegradedPersistenceConfig {
+            max_buffered_saves: 3,
+            max_outage: Duration::from_secs(60),
+            retry_interval: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_persistence_halts_once_the_buffer_fills() {
+        let start = Instant::now();
+        let mut persistence = DegradedPersistence::new(config());
+        assert_eq!(PersistenceChange::Unchanged, persistence.record_success(start));
+
+        assert_eq!(
+            PersistenceChange::Degraded,
+            persistence.record_failure("mount gone".into(), false, start)
+        );
+        assert!(!persistence.halts());
+        // retries don't fill the buffer
+        for _ in 0..10 {
+            assert_eq!(
+                PersistenceChange::Unchanged,
+                persistence.record_failure("mount gone".into(), true, start)
+            );
+        }
+        assert_eq!(
+            PersistenceChange::Unchanged,
+            persistence.record_failure("mount gone".into(), false, start)
+        );
+        assert_eq!(
+            PersistenceChange::Halted,
+            persistence.record_failure("still gone".into(), false, start)
+        );
+        assert!(persistence.halts());
+        assert_eq!(3, persistence.outage().unwrap().buffered_saves);
+        assert_eq!("still gone", persistence.outage().unwrap().error);
+        // halts once per outage
+        assert_eq!(
+            PersistenceChange::Unchanged,
+            persistence.record_failure("still gone".into(), true, start)
+        );
+
+        assert_eq!(
+            PersistenceChange::Recovered {
+                gap: Duration::from_secs(10),
+                buffered_saves: 3,
+                halted: true,
+            },
+            persistence.record_success(start + Duration::from_secs(10))
+        );
+        assert!(!persistence.halts());
+        assert_eq!(None, persistence.outage());
+    }
+
+    #[test]
+    fn test_persistence_halts_once_the_outage_lasts_too_long() {
+        let start = Instant::now();
+        let mut persistence = DegradedPersistence::new(config());
+        persistence.record_failure("mount gone".into(), false, start);
+        assert_eq!(
+            PersistenceChange::Unchanged,
+            persistence.record_failure("mount gone".into(), true, start + Duration::from_secs(59))
+        );
+        assert!(!persistence.halts());
+        assert_eq!(
+            PersistenceChange::Halted,
+            persistence.record_failure("mount gone".into(), true, start + Duration::from_secs(60))
+        );
+        assert!(persistence.halts());
+
+        // the next outage starts over
+        persistence.record_success(start + Duration::from_secs(65));
+        assert_eq!(
+            PersistenceChange::Degraded,
+            persistence.record_failure("mount gone".into(), false, start + Duration::from_secs(70))
+        );
+        assert!(!persistence.halts());
+        assert_eq!(1, persistence.outage().unwrap().buffered_saves);
+    }
+}
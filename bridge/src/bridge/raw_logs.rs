@@ -0,0 +1,233 @@
+use ethabi;
+use web3::types::{Address, H256, U256};
+use contracts::foreign::events::Withdraw;
+use contracts::home::events::Deposit;
+use database::{Database, QuarantinedDeposit, RawLog};
+use super::history::Direction;
+
+/// number of raw logs `RawLogs` keeps.
+pub const KEPT_RAW_LOGS: usize = 1024;
+/// largest raw log, in bytes of topics and data, kept with a quarantined deposit.
+/// a deposit log is 96 bytes, larger ones are kept out of `quarantined_deposits`, which
+/// isn't bounded.
+pub const MAX_QUARANTINED_RAW_LOG_SIZE: usize = 1024;
+
+/// Logs of the deposits and withdraws observed last, the most recent `KEPT_RAW_LOGS` of them,
+/// oldest first.
+///
+/// logs are recorded as their log stream yielded them, before their component decides
+/// whether to relay them, so they are kept also for deposits and withdraws that failed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RawLogs {
+    logs: Vec<RawLog>,
+}
+
+impl RawLogs {
+    /// raw logs continuing from those of `init`.
+    pub fn new(init: &Database) -> Self {
+        RawLogs {
+            logs: init.raw_logs.clone(),
+        }
+    }
+
+    pub fn record(&mut self, log: RawLog) {
+        if self.logs.len() >= KEPT_RAW_LOGS {
+            self.logs.remove(0);
+        }
+        self.logs.push(log);
+    }
+
+    /// the kept log of transaction `transaction` at `log_index`.
+    pub fn find(&self, transaction: H256, log_index: Option<U256>) -> Option<&RawLog> {
+        self.logs.iter().rev().find(|log| {
+            log.transaction_hash == Some(transaction) && log.log_index == log_index
+        })
+    }
+
+    /// attaches its kept log to `deposit` unless it's larger than `MAX_QUARANTINED_RAW_LOG_SIZE`.
+    pub fn attach(&self, deposit: &mut QuarantinedDeposit) {
+        deposit.raw_log = self.find(deposit.home_transaction, deposit.log_index)
+            .into_iter()
+            .find(|log| log.size() <= MAX_QUARANTINED_RAW_LOG_SIZE)
+            .cloned();
+    }
+
+    /// kept logs, oldest first.
+    pub fn logs(&self) -> &[RawLog] {
+        &self.logs
+    }
+}
+
+/// Fields of a deposit or withdraw decoded from its log with the compiled contracts.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodedLog {
+    pub recipient: Address,
+    /// value in wei of the chain that emitted the log
+    pub value: U256,
+    /// gas price the withdraw asks to be relayed with. `None` for deposits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub home_gas_price: Option<U256>,
+}
+
+/// `log` decoded as the event of `direction`. `None` if it isn't one,
+/// e.g. if the contracts emit an event of a custom `abi`.
+pub fn decode_log(direction: Direction, log: &RawLog) -> Option<DecodedLog> {
+    let raw = ethabi::RawLog {
+        topics: log.topics.clone(),
+        data: log.data.0.clone(),
+    };
+    match direction {
+        Direction::HomeToForeign => {
+            Deposit::default()
+                .parse_log(raw)
+                .ok()
+                .map(|deposit| DecodedLog {
+                    recipient: deposit.recipient,
+                    value: deposit.value,
+                    home_gas_price: None,
+                })
+        }
+        Direction::ForeignToHome => {
+            Withdraw::default()
+                .parse_log(raw)
+                .ok()
+                .map(|withdraw| DecodedLog {
+                    recipient: withdraw.recipient,
+                    value: withdraw.value,
+                    home_gas_price: Some(withdraw.home_gas_price),
+                })
+        }
+    }
+}
+
+/// A kept log with its decoded fields, printed by `--lookup` and `--lookup-deposit`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoggedEvent {
+    pub raw: RawLog,
+    pub decoded: Option<DecodedLog>,
+}
+
+/// the logs `database` keeps of the events of `direction` in transaction `transaction`.
+pub fn lookup_logs(
+    database: &Database,
+    direction: Direction,
+    transaction: H256,
+) -> Vec<LoggedEvent> {
+    database
+        .raw_logs
+        .iter()
+        .filter(|log| log.transaction_hash == Some(transaction))
+        .map(|log| LoggedEvent {
+            raw: log.clone(),
+            decoded: decode_log(direction, log),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{H256, U256};
+    use database::{Database, QuarantinedDeposit, RawLog};
+    use test_helpers::{DepositLogBuilder, WithdrawLogBuilder, RECIPIENT};
+    use bridge::Direction;
+    use super::{decode_log, lookup_logs, DecodedLog, RawLogs, KEPT_RAW_LOGS,
+                MAX_QUARANTINED_RAW_LOG_SIZE};
+
+    fn deposit_log(transaction: u64) -> RawLog {
+        let log = DepositLogBuilder::new()
+            .value(100)
+            .tx_hash(transaction)
+            .log_index(0)
+            .block_number(10)
+            .build();
+        RawLog::from_log(&log)
+    }
+
+    #[test]
+    fn test_raw_logs_keep_the_latest() {
+        let mut logs = RawLogs::new(&Database::default());
+        for transaction in 0..KEPT_RAW_LOGS as u64 + 1 {
+            logs.record(deposit_log(transaction));
+        }
+        assert_eq!(KEPT_RAW_LOGS, logs.logs().len());
+        assert_eq!(Some(1.into()), logs.logs()[0].transaction_hash);
+        assert!(logs.find(0.into(), Some(0.into())).is_none());
+        assert_eq!(Some(&deposit_log(1)), logs.find(1.into(), Some(0.into())));
+        assert!(logs.find(1.into(), Some(1.into())).is_none());
+
+        let database = Database {
+            raw_logs: logs.logs().to_vec(),
+            ..Database::default()
+        };
+        assert_eq!(logs, RawLogs::new(&database));
+    }
+
+    #[test]
+    fn test_raw_logs_attach_small_logs_to_quarantined_deposits() {
+        let mut logs = RawLogs::new(&Database::default());
+        logs.record(deposit_log(1));
+        logs.record(RawLog {
+            data: vec![0u8; MAX_QUARANTINED_RAW_LOG_SIZE].into(),
+            ..deposit_log(2)
+        });
+        let mut deposit = QuarantinedDeposit {
+            home_transaction: 1.into(),
+            log_index: Some(0.into()),
+            recipient: RECIPIENT.into(),
+            original_recipient: None,
+            value: 100.into(),
+            reason: "no fallback".into(),
+            raw_log: None,
+        };
+        logs.attach(&mut deposit);
+        assert_eq!(Some(deposit_log(1)), deposit.raw_log);
+
+        let mut large = QuarantinedDeposit {
+            home_transaction: 2.into(),
+            ..deposit.clone()
+        };
+        logs.attach(&mut large);
+        assert_eq!(None, large.raw_log);
+    }
+
+    #[test]
+    fn test_decode_raw_logs() {
+        assert_eq!(
+            Some(DecodedLog {
+                recipient: RECIPIENT.into(),
+                value: 100.into(),
+                home_gas_price: None,
+            }),
+            decode_log(Direction::HomeToForeign, &deposit_log(1))
+        );
+        assert_eq!(None, decode_log(Direction::ForeignToHome, &deposit_log(1)));
+
+        let withdraw = WithdrawLogBuilder::new()
+            .value(100)
+            .home_gas_price(20)
+            .build();
+        assert_eq!(
+            Some(DecodedLog {
+                recipient: RECIPIENT.into(),
+                value: 100.into(),
+                home_gas_price: Some(20.into()),
+            }),
+            decode_log(Direction::ForeignToHome, &RawLog::from_log(&withdraw))
+        );
+        let truncated = DepositLogBuilder::new().truncated(40).build();
+        assert_eq!(None, decode_log(Direction::HomeToForeign, &RawLog::from_log(&truncated)));
+    }
+
+    #[test]
+    fn test_lookup_logs_prints_raw_and_decoded_fields() {
+        let database = Database {
+            raw_logs: vec![deposit_log(1), deposit_log(2)],
+            ..Database::default()
+        };
+        let logs = lookup_logs(&database, Direction::HomeToForeign, 2.into());
+        assert_eq!(1, logs.len());
+        assert_eq!(deposit_log(2), logs[0].raw);
+        assert_eq!(Some(U256::from(100)), logs[0].decoded.as_ref().map(|log| log.value));
+        assert!(lookup_logs(&database, Direction::HomeToForeign, H256::from(3)).is_empty());
+    }
+}
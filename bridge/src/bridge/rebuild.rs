@@ -0,0 +1,588 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use futures::future::Join;
+use tiny_keccak::keccak256;
+use web3::Transport;
+use web3::types::{Filter, H256, Log, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::Config;
+use contracts::{foreign, home};
+use database::Database;
+use error::{Error, ErrorKind, Result};
+use fetch::{fetch_bounded, FetchBounded, Requests};
+use message_to_mainnet::MessageToMainnet;
+use util::{log_id, raw_log, web3_filter};
+
+/// blocks covered by a single `eth_getLogs` request of the rebuild.
+pub const CHUNK_BLOCKS: u64 = 10_000;
+
+/// Logs the checked blocks are rebuilt from, in block order.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ScannedLogs {
+    /// `HomeBridge.Deposit`
+    pub home_deposits: Vec<Log>,
+    /// `HomeBridge.Withdraw`, emitted by executed withdraws
+    pub home_withdraws: Vec<Log>,
+    /// `ForeignBridge.Deposit`, emitted by executed deposits
+    pub foreign_deposits: Vec<Log>,
+    /// `ForeignBridge.Withdraw`
+    pub foreign_withdraws: Vec<Log>,
+    /// `ForeignBridge.CollectedSignatures`
+    pub collected_signatures: Vec<Log>,
+}
+
+/// Kinds of logs the rebuild scans for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scan {
+    HomeDeposits,
+    HomeWithdraws,
+    ForeignDeposits,
+    ForeignWithdraws,
+    CollectedSignatures,
+}
+
+impl ScannedLogs {
+    fn logs_mut(&mut self, scan: Scan) -> &mut Vec<Log> {
+        match scan {
+            Scan::HomeDeposits => &mut self.home_deposits,
+            Scan::HomeWithdraws => &mut self.home_withdraws,
+            Scan::ForeignDeposits => &mut self.foreign_deposits,
+            Scan::ForeignWithdraws => &mut self.foreign_withdraws,
+            Scan::CollectedSignatures => &mut self.collected_signatures,
+        }
+    }
+}
+
+/// inclusive block ranges of at most `size` blocks covering `from..=to`.
+pub fn chunks(from: u64, to: u64, size: u64) -> Vec<(u64, u64)> {
+    let mut chunks = Vec::new();
+    let mut start = from;
+    while start <= to {
+        let end = cmp::min(to, start.saturating_add(size - 1));
+        chunks.push((start, end));
+        if end == u64::max_value() {
+            break;
+        }
+        start = end + 1;
+    }
+    chunks
+}
+
+pub fn log_block(log: &Log) -> Result<u64> {
+    log.block_number
+        .map(|block| block.low_u64())
+        .ok_or_else(|| ErrorKind::UnminedLog.into())
+}
+
+/// rebuilds the checked blocks of `init` from `logs` of the blocks up to `home_head` and `foreign_head`.
+///
+/// the checked block of a component is the block before the first log it still has to process,
+/// or the head if there is none:
+/// deposits without `ForeignBridge.Deposit`, withdraws without `CollectedSignatures`
+/// and `CollectedSignatures` this authority is responsible for without `HomeBridge.Withdraw`.
+/// deposits and withdraws the bridge doesn't relay (zero value, rejected by `value_scale`) are ignored.
+pub fn reconcile(
+    config: &Config,
+    home: &home::HomeBridge,
+    foreign: &foreign::ForeignBridge,
+    init: &Database,
+    logs: &ScannedLogs,
+    home_head: u64,
+    foreign_head: u64,
+) -> Result<Database> {
+    let unique_ids = config.unique_log_ids;
+    let scale = config.value_scale.as_ref();
+
+    let executed_deposits = logs.foreign_deposits
+        .iter()
+        .map(|log| {
+            let deposit = foreign.events().deposit().parse_log(raw_log(log))?;
+            Ok(H256::from(deposit.transaction_hash))
+        })
+        .collect::<Result<HashSet<_>>>()?;
+    let mut checked_deposit_relay = home_head;
+    for log in &logs.home_deposits {
+        let value = home.events().deposit().parse_log(raw_log(log))?.value;
+        let skipped = (value.is_zero() && !config.relay_zero_value)
+            || scale.map_or(false, |scale| scale.deposit(value).is_err());
+        if skipped || executed_deposits.contains(&log_id(log, unique_ids)?) {
+            continue;
+        }
+        checked_deposit_relay = log_block(log)?.saturating_sub(1);
+        break;
+    }
+
+    let collected = logs.collected_signatures
+        .iter()
+        .map(|log| {
+            let event = foreign
+                .events()
+                .collected_signatures()
+                .parse_log(raw_log(log))?;
+            Ok((log, event))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let collected_hashes = collected
+        .iter()
+        .map(|&(_, ref event)| H256::from(event.message_hash))
+        .collect::<HashSet<_>>();
+    // hash of the message of every withdraw -> id of the withdraw
+    let mut withdraw_ids = HashMap::new();
+    let mut checked_withdraw_confirm = None;
+    for log in &logs.foreign_withdraws {
+        let mut message = MessageToMainnet::from_log(log.clone(), unique_ids)?;
+        if let Some(scale) = scale {
+            match scale.withdraw(message.value) {
+                Ok(scaled) => message.value = scaled.value,
+                Err(_) => continue,
+            }
+        }
+        let hash = H256::from(keccak256(&message.to_bytes()));
+        withdraw_ids.insert(hash, message.sidenet_transaction_hash);
+        if checked_withdraw_confirm.is_none() && !collected_hashes.contains(&hash) {
+            checked_withdraw_confirm = Some(log_block(log)?.saturating_sub(1));
+        }
+    }
+
+    let executed_withdraws = logs.home_withdraws
+        .iter()
+        .map(|log| {
+            let withdraw = home.events().withdraw().parse_log(raw_log(log))?;
+            Ok(H256::from(withdraw.transaction_hash))
+        })
+        .collect::<Result<HashSet<_>>>()?;
+    let mut checked_withdraw_relay = foreign_head;
+    for &(log, ref event) in &collected {
+        if event.authority_responsible_for_relay != config.foreign.account.0.into() {
+            continue;
+        }
+        let executed = withdraw_ids
+            .get(&H256::from(event.message_hash))
+            .map_or(false, |id| executed_withdraws.contains(id));
+        if !executed {
+            checked_withdraw_relay = log_block(log)?.saturating_sub(1);
+            break;
+        }
+    }
+
+    Ok(Database {
+        checked_deposit_relay: cmp::max(init.home_deploy, checked_deposit_relay),
+        checked_withdraw_relay: cmp::max(init.foreign_deploy, checked_withdraw_relay),
+        checked_withdraw_confirm: cmp::max(
+            init.foreign_deploy,
+            checked_withdraw_confirm.unwrap_or(foreign_head),
+        ),
+        ..init.clone()
+    })
+}
+
+/// lines describing the checked blocks that differ between `old` and `new`.
+pub fn database_diff(old: &Database, new: &Database) -> Vec<String> {
+    let fields = [
+        (
+            "checked_deposit_relay",
+            old.checked_deposit_relay,
+            new.checked_deposit_relay,
+        ),
+        (
+            "checked_withdraw_relay",
+            old.checked_withdraw_relay,
+            new.checked_withdraw_relay,
+        ),
+        (
+            "checked_withdraw_confirm",
+            old.checked_withdraw_confirm,
+            new.checked_withdraw_confirm,
+        ),
+    ];
+    fields
+        .iter()
+        .filter(|&&(_, old, new)| old != new)
+        .map(|&(name, old, new)| format!("{}: {} -> {}", name, old, new))
+        .collect()
+}
+
+enum ScanState<T: Transport> {
+    /// Fetching the block numbers of both chains.
+    FetchBlockNumbers(Join<Timeout<ApiCall<U256, T::Out>>, Timeout<ApiCall<U256, T::Out>>>),
+    /// Fetching the logs of all chunks of both chains.
+    FetchLogs {
+        future: FetchBounded<LogRequests<T>>,
+        /// kind of the logs of every request of `future`
+        scans: Vec<Scan>,
+        home_head: u64,
+        foreign_head: u64,
+    },
+}
+
+/// scans the logs of both chains since the deployment of the contracts in `init`
+/// up to `required_confirmations` below the heads.
+pub fn scan_logs<T: Transport>(app: Arc<App<T>>, init: &Database) -> ScanLogs<T> {
+    let home = api::timeout(
+        &app.timer,
+        api::block_number(&app.connections.home),
+        "home",
+        app.config.home.request_timeout,
+    );
+    let foreign = api::timeout(
+        &app.timer,
+        api::block_number(&app.connections.foreign),
+        "foreign",
+        app.config.foreign.request_timeout,
+    );
+    ScanLogs {
+        app,
+        init: init.clone(),
+        state: ScanState::FetchBlockNumbers(home.join(foreign)),
+    }
+}
+
+/// Future resolving to the scanned logs and the last scanned home and foreign blocks.
+/// the logs are fetched in chunks of `CHUNK_BLOCKS` blocks, `scan_concurrency` chunks at once.
+pub struct ScanLogs<T: Transport> {
+    app: Arc<App<T>>,
+    init: Database,
+    state: ScanState<T>,
+}
+
+/// rebuilds the checked blocks of `init` from the logs of both chains
+/// since the deployment of the contracts.
+/// `init` provides the contract addresses and deployment blocks. its checked blocks are ignored.
+pub fn create_rebuild<T: Transport>(app: Arc<App<T>>, init: &Database) -> Rebuild<T> {
+    Rebuild {
+        scan: scan_logs(app, init),
+    }
+}
+
+/// Future resolving to the rebuilt database.
+pub struct Rebuild<T: Transport> {
+    scan: ScanLogs<T>,
+}
+
+/// `eth_getLogs` requests of a scan, each for a chunk of blocks of one chain.
+pub struct LogRequests<T: Transport> {
+    app: Arc<App<T>>,
+    /// filter of every request and whether it is for home
+    filters: Vec<(Filter, bool)>,
+}
+
+impl<T: Transport> LogRequests<T> {
+    /// requests for `filters`, each with whether it is for home.
+    pub fn new(app: Arc<App<T>>, filters: Vec<(Filter, bool)>) -> Self {
+        LogRequests { app, filters }
+    }
+}
+
+impl<T: Transport> Requests for LogRequests<T> {
+    type Item = Vec<Log>;
+    type Future = Timeout<ApiCall<Vec<Log>, T::Out>>;
+
+    fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    fn start(&self, index: usize) -> Self::Future {
+        let (ref filter, on_home) = self.filters[index];
+        let (transport, chain, node) = if on_home {
+            (&self.app.connections.home, "home", &self.app.config.home)
+        } else {
+            (&self.app.connections.foreign, "foreign", &self.app.config.foreign)
+        };
+        api::timeout(&self.app.timer, api::logs(transport, filter), chain, node.request_timeout)
+    }
+}
+
+/// requests for the logs of `scans` in chunks, together with the kind of each request.
+fn scan_requests<T: Transport>(
+    app: &Arc<App<T>>,
+    init: &Database,
+    home_head: u64,
+    foreign_head: u64,
+) -> (LogRequests<T>, Vec<Scan>) {
+    let home_events = app.home_bridge.events();
+    let foreign_events = app.foreign_bridge.events();
+    let home_address = init.home_contract_address;
+    let foreign_address = init.foreign_contract_address;
+    let scans = vec![
+        (
+            Scan::HomeDeposits,
+            web3_filter(home_events.deposit().create_filter(), home_address),
+        ),
+        (
+            Scan::HomeWithdraws,
+            web3_filter(home_events.withdraw().create_filter(), home_address),
+        ),
+        (
+            Scan::ForeignDeposits,
+            web3_filter(foreign_events.deposit().create_filter(), foreign_address),
+        ),
+        (
+            Scan::ForeignWithdraws,
+            web3_filter(foreign_events.withdraw().create_filter(), foreign_address),
+        ),
+        (
+            Scan::CollectedSignatures,
+            web3_filter(
+                foreign_events.collected_signatures().create_filter(),
+                foreign_address,
+            ),
+        ),
+    ];
+
+    let mut filters = Vec::new();
+    let mut kinds = Vec::new();
+    for (scan, filter) in scans {
+        let on_home = scan == Scan::HomeDeposits || scan == Scan::HomeWithdraws;
+        let (from, to) = if on_home {
+            (init.home_deploy, home_head)
+        } else {
+            (init.foreign_deploy, foreign_head)
+        };
+        for (from, to) in chunks(from, to, CHUNK_BLOCKS) {
+            let filter = filter
+                .clone()
+                .from_block(from.into())
+                .to_block(to.into())
+                .build();
+            filters.push((filter, on_home));
+            kinds.push(scan);
+        }
+    }
+    let requests = LogRequests {
+        app: app.clone(),
+        filters,
+    };
+    (requests, kinds)
+}
+
+impl<T: Transport> Future for ScanLogs<T> {
+    type Item = (ScannedLogs, u64, u64);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                ScanState::FetchBlockNumbers(ref mut future) => {
+                    let (home, foreign) = try_ready!(future.poll());
+                    let home_head = home.low_u64()
+                        .saturating_sub(self.app.config.home.required_confirmations as u64);
+                    let foreign_head = foreign
+                        .low_u64()
+                        .saturating_sub(self.app.config.foreign.required_confirmations as u64);
+                    info!(
+                        target: "bridge::rebuild",
+                        "scanning home blocks {}..={} and foreign blocks {}..={}",
+                        self.init.home_deploy,
+                        home_head,
+                        self.init.foreign_deploy,
+                        foreign_head
+                    );
+                    let (requests, scans) =
+                        scan_requests(&self.app, &self.init, home_head, foreign_head);
+                    let config = &self.app.config;
+                    ScanState::FetchLogs {
+                        future: fetch_bounded(
+                            requests,
+                            config.scan_concurrency,
+                            config.scan_retries,
+                        ),
+                        scans,
+                        home_head,
+                        foreign_head,
+                    }
+                }
+                ScanState::FetchLogs {
+                    ref mut future,
+                    ref scans,
+                    home_head,
+                    foreign_head,
+                } => {
+                    let chunks = try_ready!(future.poll());
+                    let mut logs = ScannedLogs::default();
+                    for (scan, chunk) in scans.iter().zip(chunks.into_iter()) {
+                        logs.logs_mut(*scan).extend(chunk);
+                    }
+                    info!(
+                        target: "bridge::rebuild",
+                        "found {} deposits and {} withdraws",
+                        logs.home_deposits.len(),
+                        logs.foreign_withdraws.len()
+                    );
+                    return Ok(Async::Ready((logs, home_head, foreign_head)));
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+impl<T: Transport> Future for Rebuild<T> {
+    type Item = Database;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (logs, home_head, foreign_head) = try_ready!(self.scan.poll());
+        let app = &self.scan.app;
+        let database = reconcile(
+            &app.config,
+            &app.home_bridge,
+            &app.foreign_bridge,
+            &self.scan.init,
+            &logs,
+            home_head,
+            foreign_head,
+        )?;
+        Ok(Async::Ready(database))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_keccak::keccak256;
+    use web3::types::{Address, H256, Log, U256};
+    use config::{Authorities, Config, Node};
+    use contracts::{foreign, home};
+    use database::Database;
+    use message_to_mainnet::MessageToMainnet;
+    use super::{chunks, database_diff, reconcile, ScannedLogs};
+
+    const HOME_DEPOSIT: &str = "e1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c";
+    const HOME_WITHDRAW: &str = "4d911754a3efbbc2e0463de4f6bff32ed24421d1c89c11dce59a4935f327afff";
+    const FOREIGN_DEPOSIT: &str =
+        "1a771fe656018364a9369da21954bb3081cb08b0196c27e43ca59c7cae872737";
+    const FOREIGN_WITHDRAW: &str =
+        "f279e6a1f5e320cca91135676d9cb6e44ca8a08c0b88342bcdb1144f6511b568";
+    const COLLECTED_SIGNATURES: &str =
+        "eb043d149eedb81369bec43d4c3a3a53087debc88d2525f13bfaa3eecda28b5c";
+
+    fn address_word(address: Address) -> H256 {
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(&address.0);
+        word.into()
+    }
+
+    fn value_word(value: u64) -> H256 {
+        let mut word = [0u8; 32];
+        U256::from(value).to_big_endian(&mut word);
+        word.into()
+    }
+
+    fn log(topic: &str, words: Vec<H256>, block: u64, transaction: u64) -> Log {
+        Log {
+            topics: vec![topic.parse().unwrap()],
+            data: words
+                .iter()
+                .flat_map(|word| word.0.to_vec())
+                .collect::<Vec<_>>()
+                .into(),
+            block_number: Some(block.into()),
+            transaction_hash: Some(transaction.into()),
+            log_index: Some(0.into()),
+            ..Default::default()
+        }
+    }
+
+    fn withdraw_message_hash(transaction: u64, value: u64) -> H256 {
+        let message = MessageToMainnet {
+            recipient: 1.into(),
+            value: value.into(),
+            sidenet_transaction_hash: transaction.into(),
+            mainnet_gas_price: 10.into(),
+        };
+        keccak256(&message.to_bytes()).into()
+    }
+
+    #[test]
+    fn test_chunks() {
+        assert_eq!(vec![(5, 9), (10, 14), (15, 16)], chunks(5, 16, 5));
+        assert_eq!(vec![(5, 5)], chunks(5, 5, 5));
+        assert!(chunks(6, 5, 5).is_empty());
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let authority: Address = 0xaa.into();
+        let config = Config::builder()
+            .home(Node::new(authority, "/home.ipc".into()))
+            .foreign(Node::new(authority, "/foreign.ipc".into()))
+            .authorities(Authorities {
+                accounts: vec![authority],
+                required_signatures: 1,
+            })
+            .estimated_gas_cost_of_withdraw(100000u64.into())
+            .max_total_home_contract_balance(0u64.into())
+            .max_single_deposit_value(0u64.into())
+            .build()
+            .unwrap();
+        let init = Database {
+            home_deploy: 10,
+            foreign_deploy: 20,
+            checked_deposit_relay: 1000,
+            checked_withdraw_relay: 1000,
+            checked_withdraw_confirm: 1000,
+            ..Database::default()
+        };
+        let recipient = address_word(1.into());
+
+        let logs = ScannedLogs {
+            // the first deposit is executed, the second isn't
+            home_deposits: vec![
+                log(HOME_DEPOSIT, vec![recipient, value_word(100)], 11, 1),
+                log(HOME_DEPOSIT, vec![recipient, value_word(100)], 15, 2),
+            ],
+            foreign_deposits: vec![
+                log(FOREIGN_DEPOSIT, vec![recipient, value_word(100), 1.into()], 21, 101),
+            ],
+            // both withdraws collected signatures, only the first is executed
+            foreign_withdraws: vec![
+                log(FOREIGN_WITHDRAW, vec![recipient, value_word(200), value_word(10)], 22, 3),
+                log(FOREIGN_WITHDRAW, vec![recipient, value_word(300), value_word(10)], 24, 4),
+            ],
+            collected_signatures: vec![
+                log(
+                    COLLECTED_SIGNATURES,
+                    vec![address_word(authority), withdraw_message_hash(3, 200)],
+                    23,
+                    102,
+                ),
+                log(
+                    COLLECTED_SIGNATURES,
+                    vec![address_word(authority), withdraw_message_hash(4, 300)],
+                    25,
+                    103,
+                ),
+            ],
+            home_withdraws: vec![
+                log(HOME_WITHDRAW, vec![recipient, value_word(200), 3.into()], 12, 104),
+            ],
+        };
+
+        let home = home::HomeBridge::default();
+        let foreign = foreign::ForeignBridge::default();
+        let rebuilt = reconcile(&config, &home, &foreign, &init, &logs, 50, 60).unwrap();
+        assert_eq!(14, rebuilt.checked_deposit_relay);
+        assert_eq!(24, rebuilt.checked_withdraw_relay);
+        // all withdraws collected signatures
+        assert_eq!(60, rebuilt.checked_withdraw_confirm);
+
+        let logs = ScannedLogs {
+            collected_signatures: Vec::new(),
+            ..logs
+        };
+        let rebuilt = reconcile(&config, &home, &foreign, &init, &logs, 50, 60).unwrap();
+        assert_eq!(21, rebuilt.checked_withdraw_confirm);
+        assert_eq!(60, rebuilt.checked_withdraw_relay);
+        assert_eq!(
+            vec![
+                "checked_deposit_relay: 1000 -> 14".to_owned(),
+                "checked_withdraw_relay: 1000 -> 60".to_owned(),
+                "checked_withdraw_confirm: 1000 -> 21".to_owned(),
+            ],
+            database_diff(&init, &rebuilt)
+        );
+        assert!(database_diff(&rebuilt, &rebuilt).is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, VecDeque};
+use web3::types::H256;
+use capture::unix_time;
+use error::{Error, ErrorKind};
+use super::Component;
+use super::failure_policy::failure_kind;
+
+/// number of errors `RecentErrors` keeps of every component.
+pub const KEPT_RECENT_ERRORS: usize = 16;
+
+/// Failure of a bridge component, as recorded by `RecentErrors`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecentError {
+    /// failure kind `failure_policy` rules match, see `failure_kind`
+    pub kind: String,
+    /// the error followed by its causes
+    pub error_chain: Vec<String>,
+    /// unix time of the failure in seconds
+    pub time: u64,
+    /// relay transaction of the error, if it names one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<H256>,
+}
+
+impl RecentError {
+    pub fn new(err: &Error, time: u64) -> Self {
+        let transaction = match *err.kind() {
+            ErrorKind::MissingRelayEvent(transaction, _)
+            | ErrorKind::RelayReverted(transaction, _, _)
+            | ErrorKind::RecipientRejectsTransfer(_, transaction, _) => Some(transaction),
+            _ => None,
+        };
+        RecentError {
+            kind: failure_kind(err),
+            error_chain: err.iter().map(|cause| cause.to_string()).collect(),
+            time,
+            transaction,
+        }
+    }
+}
+
+/// The latest `KEPT_RECENT_ERRORS` failures of every component, oldest first.
+///
+/// recorded with the `failed` count of the component, so both always agree on what failed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecentErrors {
+    errors: BTreeMap<String, VecDeque<RecentError>>,
+}
+
+impl RecentErrors {
+    /// records a failure of `component` with `err`.
+    pub fn record(&mut self, component: Component, err: &Error) {
+        self.push(component, RecentError::new(err, unix_time()));
+    }
+
+    fn push(&mut self, component: Component, error: RecentError) {
+        let errors = self.errors
+            .entry(format!("{:?}", component))
+            .or_insert_with(VecDeque::new);
+        if errors.len() >= KEPT_RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(error);
+    }
+
+    /// kept errors by component, oldest first. components that never failed are missing.
+    pub fn by_component(&self) -> BTreeMap<String, Vec<RecentError>> {
+        self.errors
+            .iter()
+            .map(|(component, errors)| (component.clone(), errors.iter().cloned().collect()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::H256;
+    use error::{Error, ErrorKind, ResultExt};
+    use bridge::Component;
+    use super::{RecentError, RecentErrors, KEPT_RECENT_ERRORS};
+
+    #[test]
+    fn test_recent_error_of_a_revert() {
+        let err: Error =
+            ErrorKind::RelayReverted(H256::from(7), "other", "out of gas".into()).into();
+        let error = RecentError::new(&err, 100);
+        assert_eq!("relay_reverted.other", error.kind);
+        assert_eq!(Some(H256::from(7)), error.transaction);
+        assert_eq!(100, error.time);
+
+        let err = Err::<(), Error>("connection lost".into())
+            .chain_err(|| "cannot fetch logs")
+            .unwrap_err();
+        let error = RecentError::new(&err, 100);
+        assert_eq!("other", error.kind);
+        assert_eq!(
+            vec!["cannot fetch logs".to_owned(), "connection lost".to_owned()],
+            error.error_chain
+        );
+        assert_eq!(None, error.transaction);
+    }
+
+    #[test]
+    fn test_recent_errors_keep_the_latest_of_every_component() {
+        let mut errors = RecentErrors::default();
+        for time in 0..KEPT_RECENT_ERRORS as u64 + 1 {
+            errors.push(
+                Component::DepositRelay,
+                RecentError::new(&"failed".into(), time),
+            );
+        }
+        errors.record(Component::WithdrawConfirm, &"failed".into());
+
+        let by_component = errors.by_component();
+        assert_eq!(2, by_component.len());
+        let deposit_relay = &by_component["DepositRelay"];
+        assert_eq!(KEPT_RECENT_ERRORS, deposit_relay.len());
+        assert_eq!(1, deposit_relay[0].time);
+        assert_eq!(KEPT_RECENT_ERRORS as u64, deposit_relay[KEPT_RECENT_ERRORS - 1].time);
+        assert_eq!(1, by_component["WithdrawConfirm"].len());
+        assert!(!by_component.contains_key("WithdrawRelay"));
+    }
+}
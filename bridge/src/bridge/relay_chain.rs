@@ -0,0 +1,357 @@
+//! Tamper evidence of the relay history.
+//!
+//! every relay record is appended to the chain file as an entry holding the hash of the
+//! entry before it, so editing, removing or reordering any entry breaks the hashes of all
+//! entries after it. the head of the chain is appended to a separate head file from time to
+//! time: a chain rewritten from an edited entry on doesn't match the heads written before.
+//! records are never updated in place, a relay is only ever appended.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use serde_json::{self, Value};
+use tiny_keccak::keccak256;
+use web3::types::H256;
+use error::{Result, ResultExt};
+use super::RelayRecord;
+
+/// A relay record in the chain file, one json object per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainEntry {
+    /// position in the chain, starting at 0
+    pub seq: u64,
+    /// `hash` of the entry before. zero for the first entry
+    pub prev: H256,
+    /// `entry_hash` of `seq`, `prev` and `record`
+    pub hash: H256,
+    pub record: Value,
+}
+
+/// hash of the entry at `seq` after the entry with hash `prev`.
+/// `record` is hashed as compact json with sorted keys, so its hash doesn't depend on
+/// the formatting of the line it was read from.
+fn entry_hash(seq: u64, prev: &H256, record: &Value) -> H256 {
+    let mut bytes = prev.to_vec();
+    bytes.extend((0..8).map(|index| (seq >> (56 - 8 * index)) as u8));
+    bytes.extend(record.to_string().into_bytes());
+    keccak256(&bytes).into()
+}
+
+/// Head of the chain, one json object per line of the head file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChainHead {
+    /// number of entries
+    pub records: u64,
+    /// hash of the last entry. zero if there is none
+    pub hash: H256,
+    /// unix time in seconds the head was written
+    pub time: u64,
+}
+
+/// Result of walking a chain file with `verify_chain`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainVerification {
+    /// entries before the first divergence
+    pub records: u64,
+    /// hash of the last valid entry. zero if there is none
+    pub head: H256,
+    /// the first divergence from a valid chain. `None` if the chain is intact
+    pub divergence: Option<String>,
+}
+
+/// the entry on `line` if it continues a chain of `records` entries ending with `head`.
+fn check_entry(
+    line: &str,
+    records: u64,
+    head: &H256,
+) -> ::std::result::Result<ChainEntry, String> {
+    let entry: ChainEntry =
+        serde_json::from_str(line).map_err(|err| format!("not an entry: {}", err))?;
+    if entry.seq != records {
+        return Err(format!("entry {} where entry {} was expected", entry.seq, records));
+    }
+    if entry.prev != *head {
+        return Err(format!(
+            "entry {} follows {:?} but the entry before is {:?}",
+            entry.seq, entry.prev, head
+        ));
+    }
+    let hash = entry_hash(entry.seq, &entry.prev, &entry.record);
+    if entry.hash != hash {
+        return Err(format!(
+            "entry {} has hash {:?} but its content hashes to {:?}",
+            entry.seq, entry.hash, hash
+        ));
+    }
+    Ok(entry)
+}
+
+/// walks `chain` and compares it with the heads in `heads`, if any.
+/// reports the first line of `chain` that doesn't continue the chain,
+/// or else the first head that doesn't match it.
+pub fn verify_chain<C: BufRead, H: BufRead>(
+    chain: C,
+    heads: Option<H>,
+) -> Result<ChainVerification> {
+    let mut hashes = Vec::new();
+    let mut head = H256::zero();
+    let mut divergence = None;
+    for (index, line) in chain.lines().enumerate() {
+        match check_entry(&line?, hashes.len() as u64, &head) {
+            Ok(entry) => {
+                head = entry.hash;
+                hashes.push(entry.hash);
+            }
+            Err(reason) => {
+                divergence = Some(format!("line {} of the chain: {}", index + 1, reason));
+                break;
+            }
+        }
+    }
+    if let (None, Some(heads)) = (divergence.as_ref(), heads) {
+        for (index, line) in heads.lines().enumerate() {
+            let line = line?;
+            let written: ChainHead = match serde_json::from_str(&line) {
+                Ok(written) => written,
+                Err(err) => {
+                    divergence =
+                        Some(format!("line {} of the heads: not a head: {}", index + 1, err));
+                    break;
+                }
+            };
+            let hash = match written.records {
+                0 => Some(H256::zero()),
+                records => hashes.get(records as usize - 1).cloned(),
+            };
+            if hash != Some(written.hash) {
+                divergence = Some(format!(
+                    "line {} of the heads: head {:?} of {} entries written at {} is not in the chain",
+                    index + 1,
+                    written.hash,
+                    written.records,
+                    written.time
+                ));
+                break;
+            }
+        }
+    }
+    Ok(ChainVerification {
+        records: hashes.len() as u64,
+        head,
+        divergence,
+    })
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .chain_err(|| format!("Cannot open {:?}", path))
+}
+
+/// Appends relay records to the chain file and heads to the head file.
+pub struct RelayChain {
+    path: PathBuf,
+    file: File,
+    head_file: File,
+    records: u64,
+    head: H256,
+    /// lines of entries not written to the chain file yet
+    unwritten: String,
+    /// head last written to the head file
+    written_head: Option<(u64, H256)>,
+}
+
+impl RelayChain {
+    /// continues the chain in the file at `path`. fails if it isn't a valid chain:
+    /// appending to it would hide where it diverged.
+    pub fn open(path: &Path, head_path: &Path) -> Result<Self> {
+        let file = open_append(path)?;
+        let existing = File::open(path).chain_err(|| format!("Cannot open {:?}", path))?;
+        let verification = verify_chain(BufReader::new(existing), None::<&[u8]>)?;
+        if let Some(divergence) = verification.divergence {
+            bail!(
+                "relay chain {:?} is not valid at {}. move it away to start a new chain",
+                path,
+                divergence
+            );
+        }
+        Ok(RelayChain {
+            path: path.to_owned(),
+            file,
+            head_file: open_append(head_path)?,
+            records: verification.records,
+            head: verification.head,
+            unwritten: String::new(),
+            written_head: None,
+        })
+    }
+
+    /// chains `records` after the head. they are written by `flush`.
+    pub fn extend<'a, I: IntoIterator<Item = &'a RelayRecord>>(&mut self, records: I) {
+        for record in records {
+            let record = serde_json::to_value(record).expect("RelayRecord always serializes; qed");
+            let entry = ChainEntry {
+                seq: self.records,
+                prev: self.head,
+                hash: entry_hash(self.records, &self.head, &record),
+                record,
+            };
+            self.unwritten.push_str(&serde_json::to_string(&entry)
+                .expect("ChainEntry always serializes; qed"));
+            self.unwritten.push('\n');
+            self.records += 1;
+            self.head = entry.hash;
+        }
+    }
+
+    /// writes the chained records to the chain file.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.unwritten.is_empty() {
+            return Ok(());
+        }
+        self.file
+            .write_all(self.unwritten.as_bytes())
+            .and_then(|_| self.file.flush())
+            .chain_err(|| format!("Cannot write relay chain {:?}", self.path))?;
+        self.unwritten.clear();
+        Ok(())
+    }
+
+    /// flushes the chain and appends its head at `time` to the head file,
+    /// unless the head didn't change since it was last written.
+    /// returns the head if it was written.
+    pub fn write_head(&mut self, time: u64) -> Result<Option<ChainHead>> {
+        self.flush()?;
+        if self.written_head == Some((self.records, self.head)) {
+            return Ok(None);
+        }
+        let head = ChainHead {
+            records: self.records,
+            hash: self.head,
+            time,
+        };
+        let mut line = serde_json::to_string(&head).expect("ChainHead always serializes; qed");
+        line.push('\n');
+        self.head_file
+            .write_all(line.as_bytes())
+            .and_then(|_| self.head_file.flush())
+            .chain_err(|| "Cannot write relay chain head")?;
+        self.written_head = Some((self.records, self.head));
+        Ok(Some(head))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use self::tempdir::TempDir;
+    use std::fs;
+    use std::io::{BufReader, Read, Write};
+    use std::path::Path;
+    use super::super::{relay_key, Direction, RelayRecord};
+    use super::{verify_chain, RelayChain};
+
+    fn record(source: u64) -> RelayRecord {
+        RelayRecord {
+            direction: Direction::HomeToForeign,
+            key: relay_key(Direction::HomeToForeign, &source.into(), None),
+            source_transaction: source.into(),
+            relay_transaction: (source + 100).into(),
+            block: source,
+            receiver: 0xaa.into(),
+            value: source.into(),
+            memo: None,
+            recipient: None,
+            derived_recipient: None,
+            main_position: None,
+            gas_price: None,
+            timings: None,
+            observed_on: None,
+            submitted_to: None,
+        }
+    }
+
+    fn read(path: &Path) -> String {
+        let mut content = String::new();
+        fs::File::open(path)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    fn verify(chain: &str, heads: Option<&str>) -> (u64, Option<String>) {
+        let verification = verify_chain(chain.as_bytes(), heads.map(|heads| heads.as_bytes()))
+            .unwrap();
+        (verification.records, verification.divergence)
+    }
+
+    #[test]
+    fn test_relay_chain() {
+        let tempdir = TempDir::new("test_relay_chain").unwrap();
+        let path = tempdir.path().join("chain");
+        let head_path = tempdir.path().join("heads");
+
+        let mut chain = RelayChain::open(&path, &head_path).unwrap();
+        assert_eq!(0, chain.write_head(10).unwrap().unwrap().records);
+        chain.extend(&[record(1), record(2)]);
+        assert_eq!(2, chain.write_head(20).unwrap().unwrap().records);
+        // unchanged head isn't written again
+        assert_eq!(None, chain.write_head(30).unwrap());
+
+        // continued after a restart
+        let mut chain = RelayChain::open(&path, &head_path).unwrap();
+        chain.extend(&[record(3)]);
+        let head = chain.write_head(40).unwrap().unwrap();
+        assert_eq!(3, head.records);
+
+        let verification = verify_chain(
+            BufReader::new(fs::File::open(&path).unwrap()),
+            Some(BufReader::new(fs::File::open(&head_path).unwrap())),
+        ).unwrap();
+        assert_eq!(None, verification.divergence);
+        assert_eq!(3, verification.records);
+        assert_eq!(head.hash, verification.head);
+    }
+
+    #[test]
+    fn test_verify_chain_finds_first_divergence() {
+        let tempdir = TempDir::new("test_verify_chain").unwrap();
+        let path = tempdir.path().join("chain");
+        let head_path = tempdir.path().join("heads");
+        let mut chain = RelayChain::open(&path, &head_path).unwrap();
+        chain.extend(&[record(1), record(2), record(3)]);
+        chain.write_head(10).unwrap();
+        let content = read(&path);
+        let heads = read(&head_path);
+        let lines = content.lines().collect::<Vec<_>>();
+
+        assert_eq!((3, None), verify(&content, Some(&heads)));
+
+        // edited record
+        let edited = content.replacen("\"block\":2", "\"block\":5", 1);
+        let (records, divergence) = verify(&edited, None);
+        assert_eq!(1, records);
+        assert!(divergence.unwrap().starts_with("line 2 of the chain: entry 1 has hash"));
+
+        // removed record
+        let removed = format!("{}\n{}\n", lines[0], lines[2]);
+        let (records, divergence) = verify(&removed, None);
+        assert_eq!(1, records);
+        assert!(divergence.unwrap().contains("entry 2 where entry 1 was expected"));
+
+        // truncated chain or chain rewritten after the head was written
+        let (records, divergence) = verify(&format!("{}\n", lines[0]), Some(&heads));
+        assert_eq!(1, records);
+        assert!(divergence.unwrap().starts_with("line 1 of the heads"));
+
+        // a valid chain can't be continued after an edit
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(edited.as_bytes())
+            .unwrap();
+        assert!(RelayChain::open(&path, &head_path).is_err());
+    }
+}
@@ -0,0 +1,1121 @@
+use std::mem;
+use std::sync::Arc;
+use std::time::Instant;
+use ethabi::{self, ParamType, Token};
+use futures::{Async, Future, Poll, Stream};
+use futures::future::{join_all, JoinAll};
+use tiny_keccak::keccak256;
+use timer::Sleep;
+use web3::Transport;
+use web3::types::{Address, FilterBuilder, H256, Log, TransactionRequest, U256};
+use api::{self, settle, ApiCall, LogStream, ReceiptWithStatus, Settle, Timeout};
+use app::App;
+use config::{Confirmation, MissingEventAction};
+use error::{Error, ErrorKind, Result};
+use message_to_mainnet::MessageToMainnet;
+use super::RelayCounts;
+use super::deploy_block::{chain, chain_name};
+use super::gas_usage::{raised_gas, RelayCall};
+use super::revert::{revert_reason, Revert, RevertKind, RevertReason};
+
+/// An event with the arguments it must be emitted with. `None` matches any value.
+/// the bridge events have no indexed parameters, all arguments are in the log data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventMatch {
+    /// canonical signature, e.g. `Deposit(address,uint256,bytes32)`
+    pub signature: &'static str,
+    pub params: Vec<ParamType>,
+    pub args: Vec<Option<Token>>,
+}
+
+impl EventMatch {
+    /// `true` if `log` is this event with the expected arguments.
+    pub fn matches(&self, log: &Log) -> bool {
+        let topic = H256::from(keccak256(self.signature.as_bytes()));
+        if log.topics.first() != Some(&topic) {
+            return false;
+        }
+        match ethabi::decode(&self.params, &log.data.0) {
+            Ok(decoded) => decoded
+                .iter()
+                .zip(self.args.iter())
+                .all(|(decoded, arg)| arg.as_ref().map_or(true, |arg| arg == decoded)),
+            Err(_) => false,
+        }
+    }
+}
+
+/// relays are checked for their events from this many blocks before the head
+/// when they are confirmed by their events, in case they were mined before the check started.
+const EVENT_LOOKBACK: u64 = 16;
+
+/// Events a relay transaction must emit one of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectedEvent {
+    /// contract the event must be emitted by
+    pub contract: Address,
+    pub alternatives: Vec<EventMatch>,
+    /// function the relay calls
+    pub call: RelayCall,
+    /// gas limit of the relay. `None` if the node estimated it
+    pub gas: Option<U256>,
+    /// the relay, sent again with a raised gas limit if it runs out of gas.
+    /// `None` unless `gas_usage.retry_out_of_gas` is enabled
+    pub resend: Option<TransactionRequest>,
+}
+
+/// arguments of the call of `signature` in `data`. `None` if `data` calls another function.
+fn decode_call(signature: &str, params: &[ParamType], data: &[u8]) -> Option<Vec<Token>> {
+    if data.len() < 4 || data[..4] != keccak256(signature.as_bytes())[..4] {
+        return None;
+    }
+    ethabi::decode(params, &data[4..]).ok()
+}
+
+impl ExpectedEvent {
+    /// events the relay transaction `request` must emit, by the function it calls:
+    ///
+    /// - `ForeignBridge.deposit`: `DepositConfirmation` of the deposit, or `Deposit`
+    ///   if it is the last required confirmation
+    /// - `ForeignBridge.submitSignature`: `WithdrawSignatureSubmitted` of the message hash,
+    ///   or `CollectedSignatures` if it is the last required signature
+    /// - `HomeBridge.withdraw` and `HomeBridge.withdrawToPayout`: `Withdraw` to the recipient
+    ///   of the withdraw in the message. the value isn't checked since the relay cost is
+    ///   subtracted from it
+    ///
+    /// `None` for transactions calling other functions.
+    pub fn for_relay(request: &TransactionRequest) -> Option<Self> {
+        let contract = request.to?;
+        let data = &request.data.as_ref()?.0;
+
+        let deposit_params = vec![
+            ParamType::Address,
+            ParamType::Uint(256),
+            ParamType::FixedBytes(32),
+        ];
+        if let Some(args) = decode_call("deposit(address,uint256,bytes32)", &deposit_params, data) {
+            let args = args.into_iter().map(Some).collect::<Vec<_>>();
+            return Some(ExpectedEvent {
+                contract,
+                call: RelayCall::Deposit,
+                gas: request.gas,
+                resend: None,
+                alternatives: vec![
+                    EventMatch {
+                        signature: "DepositConfirmation(address,uint256,bytes32)",
+                        params: deposit_params.clone(),
+                        args: args.clone(),
+                    },
+                    EventMatch {
+                        signature: "Deposit(address,uint256,bytes32)",
+                        params: deposit_params,
+                        args,
+                    },
+                ],
+            });
+        }
+
+        let submit_params = vec![ParamType::Bytes, ParamType::Bytes];
+        if let Some(mut args) = decode_call("submitSignature(bytes,bytes)", &submit_params, data) {
+            let message = match args.pop() {
+                Some(Token::Bytes(message)) => message,
+                _ => return None,
+            };
+            let message_hash = Token::FixedBytes(keccak256(&message).to_vec());
+            return Some(ExpectedEvent {
+                contract,
+                call: RelayCall::SubmitSignature,
+                gas: request.gas,
+                resend: None,
+                alternatives: vec![
+                    EventMatch {
+                        signature: "WithdrawSignatureSubmitted(bytes32)",
+                        params: vec![ParamType::FixedBytes(32)],
+                        args: vec![Some(message_hash.clone())],
+                    },
+                    EventMatch {
+                        signature: "CollectedSignatures(address,bytes32)",
+                        params: vec![ParamType::Address, ParamType::FixedBytes(32)],
+                        args: vec![None, Some(message_hash)],
+                    },
+                ],
+            });
+        }
+
+        let withdraw_params = vec![
+            ParamType::Array(Box::new(ParamType::Uint(8))),
+            ParamType::Array(Box::new(ParamType::FixedBytes(32))),
+            ParamType::Array(Box::new(ParamType::FixedBytes(32))),
+            ParamType::Bytes,
+        ];
+        let withdraw_signature = "withdraw(uint8[],bytes32[],bytes32[],bytes)";
+        let mut payout_params = withdraw_params.clone();
+        payout_params.push(ParamType::Address);
+        let payout_signature = "withdrawToPayout(uint8[],bytes32[],bytes32[],bytes,address)";
+        // `withdrawToPayout` emits `Withdraw` to the recipient too, followed by `PayoutSubstituted`
+        let withdraw_args = decode_call(withdraw_signature, &withdraw_params, data).or_else(|| {
+            decode_call(payout_signature, &payout_params, data).map(|mut args| {
+                args.pop();
+                args
+            })
+        });
+        if let Some(mut args) = withdraw_args {
+            let message = match args.pop() {
+                Some(Token::Bytes(message)) => MessageToMainnet::parse(&message).ok()?,
+                _ => return None,
+            };
+            return Some(ExpectedEvent {
+                contract,
+                call: RelayCall::Withdraw,
+                gas: request.gas,
+                resend: None,
+                alternatives: vec![
+                    EventMatch {
+                        signature: "Withdraw(address,uint256,bytes32)",
+                        params: vec![
+                            ParamType::Address,
+                            ParamType::Uint(256),
+                            ParamType::FixedBytes(32),
+                        ],
+                        args: vec![
+                            Some(Token::Address(message.recipient)),
+                            None,
+                            Some(Token::FixedBytes(message.sidenet_transaction_hash.to_vec())),
+                        ],
+                    },
+                ],
+            });
+        }
+        None
+    }
+
+    /// the expected events, e.g. `Deposit(address,uint256,bytes32)`. used in logs and errors.
+    pub fn description(&self) -> String {
+        self.alternatives
+            .iter()
+            .map(|event| event.signature)
+            .collect::<Vec<_>>()
+            .join(" or ")
+    }
+
+    /// `true` if one of `logs` is one of the expected events emitted by the contract.
+    pub fn emitted_in(&self, logs: &[Log]) -> bool {
+        self.emitted_event(logs).is_some()
+    }
+
+    /// signature of the first of the expected events emitted by the contract in `logs`.
+    pub fn emitted_event(&self, logs: &[Log]) -> Option<&'static str> {
+        logs.iter()
+            .filter(|log| log.address == self.contract)
+            .filter_map(|log| {
+                self.alternatives
+                    .iter()
+                    .find(|event| event.matches(log))
+                    .map(|event| event.signature)
+            })
+            .next()
+    }
+}
+
+/// events the relay transactions `requests` must emit, at the same index.
+/// empty unless `relay_events` is configured.
+pub fn expected_events<T: Transport>(
+    app: &App<T>,
+    requests: &[TransactionRequest],
+) -> Vec<Option<ExpectedEvent>> {
+    if app.config.relay_events.is_none() {
+        return Vec::new();
+    }
+    let retry = app.config
+        .gas_usage
+        .as_ref()
+        .map_or(false, |config| config.retry_out_of_gas);
+    requests
+        .iter()
+        .map(|request| {
+            ExpectedEvent::for_relay(request).map(|expected| ExpectedEvent {
+                resend: if retry { Some(request.clone()) } else { None },
+                ..expected
+            })
+        })
+        .collect()
+}
+
+/// `true` if the relay of `expected` that used `gas_used` and didn't emit its event
+/// ran out of gas, which it did if it used all of its gas.
+fn ran_out_of_gas(expected: &ExpectedEvent, gas_used: U256) -> bool {
+    expected.gas == Some(gas_used)
+}
+
+/// the relay of `expected` that ran out of gas with its gas limit raised to `percent`.
+/// `None` unless it may be sent again, which it is only once.
+fn out_of_gas_retry(expected: &ExpectedEvent, percent: u32) -> Option<(TransactionRequest, U256)> {
+    let request = expected.resend.as_ref()?;
+    let gas = raised_gas(expected.gas?, percent);
+    let request = TransactionRequest {
+        gas: Some(gas),
+        // the nonce was used by the transaction that ran out of gas
+        nonce: None,
+        ..request.clone()
+    };
+    Some((request, gas))
+}
+
+/// A relay sent again with a raised gas limit after it ran out of gas,
+/// see `gas_usage.retry_out_of_gas`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GasRetry {
+    pub transaction: H256,
+    /// gas limit the relay ran out of
+    pub gas_limit: U256,
+    /// raised gas limit of `transaction`
+    pub gas: U256,
+}
+
+/// Outcome of the check of the events of a relay transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventOutcome {
+    /// the transaction emitted an expected event
+    Emitted,
+    /// the transaction was mined without emitting an expected event
+    Missing,
+    /// the transaction reverted
+    Reverted(Revert),
+    /// the transaction wasn't mined within `relay_events.timeout`
+    Unconfirmed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventCheck {
+    pub transaction: H256,
+    pub expected: ExpectedEvent,
+    /// outcome of `retry` if the relay was sent again
+    pub outcome: EventOutcome,
+    /// gas used by the relay. `None` unless it was mined
+    pub gas_used: Option<U256>,
+    /// the relay sent again after `transaction` ran out of gas
+    pub retry: Option<GasRetry>,
+    /// signature of the expected event the relay emitted. `None` unless `outcome` is `Emitted`
+    pub event: Option<&'static str>,
+}
+
+enum VerifyRelayEventsState<T: Transport> {
+    /// Fetching the receipts of the `fetched` relays.
+    FetchReceipts {
+        future: JoinAll<Vec<Timeout<ApiCall<Option<ReceiptWithStatus>, T::Out>>>>,
+        fetched: Vec<(H256, ExpectedEvent)>,
+    },
+    /// Sending the `retried` relays that ran out of gas again with a raised gas limit.
+    RetryOutOfGas {
+        future: JoinAll<Vec<Settle<Timeout<ApiCall<H256, T::Out>>>>>,
+        /// relays with their gas used and raised gas limit
+        retried: Vec<(H256, ExpectedEvent, U256, U256)>,
+        /// relays replayed by `reasons` afterwards
+        reverted: Vec<(H256, ExpectedEvent, U256)>,
+        reasons: Vec<RevertReason<T>>,
+    },
+    /// Replaying the `reverted` relays for their revert reasons.
+    FetchRevertReasons {
+        future: JoinAll<Vec<RevertReason<T>>>,
+        /// relays with their gas used
+        reverted: Vec<(H256, ExpectedEvent, U256)>,
+    },
+    /// Waiting for the relays not yet mined.
+    Wait(Sleep),
+    /// The receipts of relays were checked.
+    Checked,
+    /// Fetching the head the expected events are watched from.
+    FetchHead(Timeout<ApiCall<U256, T::Out>>),
+    /// Watching the logs of the relay contracts for the expected events.
+    WatchEvents,
+}
+
+fn fetch_receipts<T: Transport>(
+    app: &App<T>,
+    on_home: bool,
+    fetched: Vec<(H256, ExpectedEvent)>,
+) -> VerifyRelayEventsState<T> {
+    let (transport, node) = chain(app, on_home);
+    let receipts = fetched
+        .iter()
+        .map(|&(hash, _)| {
+            api::timeout(
+                &app.timer,
+                api::transaction_receipt_with_status(transport, hash),
+                chain_name(on_home),
+                node.request_timeout,
+            ).about(hash)
+        })
+        .collect::<Vec<_>>();
+    VerifyRelayEventsState::FetchReceipts {
+        future: join_all(receipts),
+        fetched,
+    }
+}
+
+/// replays the `reverted` relays with `reasons` if there are any.
+fn fetch_revert_reasons<T: Transport>(
+    reasons: Vec<RevertReason<T>>,
+    reverted: Vec<(H256, ExpectedEvent, U256)>,
+) -> VerifyRelayEventsState<T> {
+    if reverted.is_empty() {
+        VerifyRelayEventsState::Checked
+    } else {
+        VerifyRelayEventsState::FetchRevertReasons {
+            future: join_all(reasons),
+            reverted,
+        }
+    }
+}
+
+/// stream of the logs of the contracts of the `pending` relays with their expected events,
+/// starting `EVENT_LOOKBACK` blocks before `head`.
+fn event_stream<T: Transport + Clone>(
+    app: &App<T>,
+    on_home: bool,
+    head: u64,
+    pending: &[(H256, ExpectedEvent)],
+) -> LogStream<T> {
+    let (transport, node) = chain(app, on_home);
+    let mut contracts = Vec::new();
+    let mut topics = Vec::new();
+    for &(_, ref expected) in pending {
+        if !contracts.contains(&expected.contract) {
+            contracts.push(expected.contract);
+        }
+        for event in &expected.alternatives {
+            let topic = H256::from(keccak256(event.signature.as_bytes()));
+            if !topics.contains(&topic) {
+                topics.push(topic);
+            }
+        }
+    }
+    let init = api::LogStreamInit {
+        after: head.saturating_sub(EVENT_LOOKBACK),
+        filter: FilterBuilder::default()
+            .address(contracts)
+            .topics(Some(topics), None, None, None),
+        chain: chain_name(on_home),
+        request_timeout: node.request_timeout,
+        poll_interval: app.tunables.get().poll_interval(on_home),
+        poll_jitter: None,
+        confirmations: 0,
+        sanity_check: None,
+        head_regression: node.head_regression,
+        log_limit_errors: node.log_limit_errors.clone(),
+        max_log_data: node.max_log_data,
+        finality: None,
+    };
+    api::log_stream(transport.clone(), app.timer.clone(), init)
+}
+
+/// waits for the `pending` relays unless there are none or the `deadline` passed,
+/// in which case they are unconfirmed. `None` once all relays are checked.
+fn wait_or_finish<T: Transport>(
+    app: &App<T>,
+    on_home: bool,
+    deadline: Instant,
+    pending: &mut Vec<(H256, ExpectedEvent)>,
+    checks: &mut Vec<EventCheck>,
+) -> Option<VerifyRelayEventsState<T>> {
+    if !pending.is_empty() && Instant::now() >= deadline {
+        for (transaction, expected) in pending.drain(..) {
+            checks.push(EventCheck {
+                transaction,
+                expected,
+                outcome: EventOutcome::Unconfirmed,
+                gas_used: None,
+                retry: None,
+                event: None,
+            });
+        }
+    }
+    if pending.is_empty() {
+        return None;
+    }
+    let poll_interval = app.tunables.get().poll_interval(on_home);
+    Some(VerifyRelayEventsState::Wait(app.timer.sleep(poll_interval)))
+}
+
+/// Future waiting for relay transactions to be mined and checking their receipts
+/// for the expected events. resolves to the outcome of every relay.
+pub struct VerifyRelayEvents<T: Transport> {
+    app: Arc<App<T>>,
+    on_home: bool,
+    /// relays not yet mined
+    pending: Vec<(H256, ExpectedEvent)>,
+    checks: Vec<EventCheck>,
+    /// relays not mined by then are unconfirmed
+    deadline: Instant,
+    /// logs with the expected events. `None` unless the chain's `confirmation` is `event`
+    events: Option<LogStream<T>>,
+    /// wakes the event watch at the `deadline`
+    expiry: Option<Sleep>,
+    /// relays sent again after they ran out of gas, by their first transaction
+    retries: Vec<(H256, GasRetry)>,
+    state: VerifyRelayEventsState<T>,
+}
+
+/// checks the receipts of the relay `transactions` on home if `on_home`, foreign otherwise
+/// for the `expected` events at the same index. relays without expected events aren't checked.
+/// `None` if there is nothing to check, e.g. because `relay_events` isn't configured.
+///
+/// with `confirmation = "event"` the receipt of a relay is only fetched once its expected event
+/// is seen in the logs. the receipts of the relays whose events weren't seen by the deadline
+/// are fetched once, to tell relays that reverted or missed their events from unmined ones.
+pub fn verify_relay_events<T: Transport + Clone>(
+    app: &Arc<App<T>>,
+    on_home: bool,
+    transactions: &[H256],
+    expected: Vec<Option<ExpectedEvent>>,
+) -> Option<VerifyRelayEvents<T>> {
+    let config = app.config.relay_events.as_ref()?;
+    let mut pending = Vec::new();
+    for (transaction, expected) in transactions.iter().zip(expected.into_iter()) {
+        match expected {
+            Some(expected) => pending.push((*transaction, expected)),
+            None => warn!(
+                target: "bridge::relay_events",
+                "relay transaction {} calls no function with known events. its events are not checked",
+                transaction
+            ),
+        }
+    }
+    if pending.is_empty() {
+        return None;
+    }
+    info!(
+        target: "bridge::relay_events",
+        "waiting for {} relay transactions to check their events",
+        pending.len()
+    );
+    let (transport, node) = chain(app, on_home);
+    let (state, pending, expiry) = match node.confirmation {
+        Confirmation::Receipt => (fetch_receipts(app, on_home, pending), Vec::new(), None),
+        Confirmation::Event => {
+            let head = api::block_number(transport);
+            (
+                VerifyRelayEventsState::FetchHead(api::timeout(
+                    &app.timer,
+                    head,
+                    chain_name(on_home),
+                    node.request_timeout,
+                )),
+                pending,
+                Some(app.timer.sleep(config.timeout)),
+            )
+        }
+    };
+    Some(VerifyRelayEvents {
+        state,
+        app: app.clone(),
+        on_home,
+        pending,
+        checks: Vec::new(),
+        deadline: Instant::now() + config.timeout,
+        events: None,
+        expiry,
+        retries: Vec::new(),
+    })
+}
+
+impl<T: Transport> VerifyRelayEvents<T> {
+    /// the outcomes of the relays. relays sent again after they ran out of gas
+    /// are checked by their first transaction.
+    fn take_checks(&mut self) -> Vec<EventCheck> {
+        let mut checks = mem::replace(&mut self.checks, Vec::new());
+        for check in &mut checks {
+            let retry = self.retries
+                .iter()
+                .find(|&&(_, retry)| retry.transaction == check.transaction)
+                .cloned();
+            if let Some((first, retry)) = retry {
+                check.transaction = first;
+                check.retry = Some(retry);
+            }
+        }
+        checks
+    }
+
+    /// state after the receipts of relays were checked. `None` once all relays are checked.
+    fn next_state(&mut self) -> Option<VerifyRelayEventsState<T>> {
+        if self.expiry.is_none() {
+            return wait_or_finish(
+                &self.app,
+                self.on_home,
+                self.deadline,
+                &mut self.pending,
+                &mut self.checks,
+            );
+        }
+        if self.pending.is_empty() {
+            return None;
+        }
+        if Instant::now() >= self.deadline {
+            // the receipts of the relays whose events weren't seen were fetched at the deadline
+            for (transaction, expected) in self.pending.drain(..) {
+                self.checks.push(EventCheck {
+                    transaction,
+                    expected,
+                    outcome: EventOutcome::Unconfirmed,
+                    gas_used: None,
+                    retry: None,
+                    event: None,
+                });
+            }
+            return None;
+        }
+        Some(VerifyRelayEventsState::WatchEvents)
+    }
+}
+
+impl<T: Transport + Clone> Future for VerifyRelayEvents<T> {
+    type Item = Vec<EventCheck>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                VerifyRelayEventsState::FetchReceipts {
+                    ref mut future,
+                    ref mut fetched,
+                } => {
+                    let receipts = try_ready!(future.poll());
+                    let raise_percent = self.app
+                        .config
+                        .gas_usage
+                        .as_ref()
+                        .map_or(100, |config| config.raise_percent);
+                    let mut retried = Vec::new();
+                    let mut retries = Vec::new();
+                    let mut reverted = Vec::new();
+                    let mut reasons = Vec::new();
+                    for ((transaction, expected), receipt) in fetched.drain(..).zip(receipts) {
+                        let receipt = match receipt {
+                            Some(receipt) => receipt,
+                            None => {
+                                self.pending.push((transaction, expected));
+                                continue;
+                            }
+                        };
+                        let gas_used = receipt.receipt.gas_used;
+                        let event = if receipt.reverted() {
+                            None
+                        } else {
+                            expected.emitted_event(&receipt.receipt.logs)
+                        };
+                        let emitted = event.is_some();
+                        if !emitted && ran_out_of_gas(&expected, gas_used) {
+                            match out_of_gas_retry(&expected, raise_percent) {
+                                Some((request, gas)) => {
+                                    let (transport, node) = chain(&self.app, self.on_home);
+                                    retries.push(settle(api::timeout(
+                                        &self.app.timer,
+                                        api::node_send_transaction(transport, node, request),
+                                        chain_name(self.on_home),
+                                        node.request_timeout,
+                                    )));
+                                    retried.push((transaction, expected, gas_used, gas));
+                                }
+                                None => self.checks.push(EventCheck {
+                                    transaction,
+                                    expected,
+                                    outcome: EventOutcome::Reverted(Revert::out_of_gas()),
+                                    gas_used: Some(gas_used),
+                                    retry: None,
+                                    event: None,
+                                }),
+                            }
+                        } else if receipt.reverted() {
+                            reasons.push(revert_reason(
+                                &self.app,
+                                self.on_home,
+                                transaction,
+                                receipt.receipt.block_number.low_u64(),
+                            ));
+                            reverted.push((transaction, expected, gas_used));
+                        } else {
+                            self.checks.push(EventCheck {
+                                transaction,
+                                expected,
+                                outcome: if emitted {
+                                    EventOutcome::Emitted
+                                } else {
+                                    EventOutcome::Missing
+                                },
+                                gas_used: Some(gas_used),
+                                retry: None,
+                                event,
+                            });
+                        }
+                    }
+                    if !retried.is_empty() {
+                        VerifyRelayEventsState::RetryOutOfGas {
+                            future: join_all(retries),
+                            retried,
+                            reverted,
+                            reasons,
+                        }
+                    } else {
+                        fetch_revert_reasons(reasons, reverted)
+                    }
+                }
+                VerifyRelayEventsState::RetryOutOfGas {
+                    ref mut future,
+                    ref mut retried,
+                    ref mut reverted,
+                    ref mut reasons,
+                } => {
+                    let sent = try_ready!(future.poll());
+                    for ((transaction, expected, gas_used, gas), sent) in
+                        retried.drain(..).zip(sent)
+                    {
+                        match sent {
+                            Ok(retry) => {
+                                warn!(
+                                    target: "bridge::relay_events",
+                                    "relay transaction {} ran out of gas with gas limit {}. sent it again as {} with gas limit {}",
+                                    transaction,
+                                    gas_used,
+                                    retry,
+                                    gas
+                                );
+                                self.retries.push((
+                                    transaction,
+                                    GasRetry {
+                                        transaction: retry,
+                                        gas_limit: gas_used,
+                                        gas,
+                                    },
+                                ));
+                                self.pending.push((
+                                    retry,
+                                    ExpectedEvent {
+                                        gas: Some(gas),
+                                        resend: None,
+                                        ..expected
+                                    },
+                                ));
+                            }
+                            Err(err) => {
+                                error!(
+                                    target: "bridge::relay_events",
+                                    "can't send relay transaction {} again after it ran out of gas: {}",
+                                    transaction,
+                                    err
+                                );
+                                self.checks.push(EventCheck {
+                                    transaction,
+                                    expected,
+                                    outcome: EventOutcome::Reverted(Revert::out_of_gas()),
+                                    gas_used: Some(gas_used),
+                                    retry: None,
+                                    event: None,
+                                });
+                            }
+                        }
+                    }
+                    fetch_revert_reasons(
+                        mem::replace(reasons, Vec::new()),
+                        mem::replace(reverted, Vec::new()),
+                    )
+                }
+                VerifyRelayEventsState::FetchRevertReasons {
+                    ref mut future,
+                    ref mut reverted,
+                } => {
+                    let reasons = try_ready!(future.poll());
+                    for ((transaction, expected, gas_used), reason) in
+                        reverted.drain(..).zip(reasons)
+                    {
+                        self.checks.push(EventCheck {
+                            transaction,
+                            expected,
+                            outcome: EventOutcome::Reverted(Revert::new(reason)),
+                            gas_used: Some(gas_used),
+                            retry: None,
+                            event: None,
+                        });
+                    }
+                    VerifyRelayEventsState::Checked
+                }
+                VerifyRelayEventsState::Checked => match self.next_state() {
+                    Some(state) => state,
+                    None => return Ok(Async::Ready(self.take_checks())),
+                },
+                VerifyRelayEventsState::Wait(ref mut future) => {
+                    try_ready!(future.poll());
+                    let pending = mem::replace(&mut self.pending, Vec::new());
+                    fetch_receipts(&self.app, self.on_home, pending)
+                }
+                VerifyRelayEventsState::FetchHead(ref mut future) => {
+                    let head = try_ready!(future.poll()).low_u64();
+                    self.events = Some(event_stream(&self.app, self.on_home, head, &self.pending));
+                    VerifyRelayEventsState::WatchEvents
+                }
+                VerifyRelayEventsState::WatchEvents => {
+                    let expired = match self.expiry {
+                        Some(ref mut expiry) => expiry.poll()?.is_ready(),
+                        None => true,
+                    };
+                    let item = match self.events {
+                        Some(ref mut events) if !expired => try_ready!(events.poll()),
+                        _ => None,
+                    };
+                    let pending = mem::replace(&mut self.pending, Vec::new());
+                    match item {
+                        Some(item) => {
+                            let (seen, pending) = pending.into_iter().partition::<Vec<_>, _>(
+                                |&(transaction, ref expected)| {
+                                    item.logs.iter().any(|log| {
+                                        log.transaction_hash == Some(transaction)
+                                            && expected.emitted_in(&[log.clone()])
+                                    })
+                                },
+                            );
+                            self.pending = pending;
+                            if seen.is_empty() {
+                                VerifyRelayEventsState::WatchEvents
+                            } else {
+                                fetch_receipts(&self.app, self.on_home, seen)
+                            }
+                        }
+                        // the deadline passed. the relays whose receipts are still missing
+                        // after this last fetch are unconfirmed
+                        None => {
+                            self.deadline = Instant::now();
+                            fetch_receipts(&self.app, self.on_home, pending)
+                        }
+                    }
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+/// logs the outcomes of `checks` and counts missing events and reverts in `counts`.
+/// fails with `ErrorKind::NotAnAuthority` if a relay to foreign (`!on_home`) reverted because
+/// the account isn't an authority, whatever `relay_events.reverted` is.
+/// fails with `ErrorKind::RelayReverted` if a relay reverted for a reason other than
+/// having already taken effect and `relay_events.reverted` is `fail`.
+/// fails with `ErrorKind::MissingRelayEvent` if an event is missing
+/// and `relay_events.missing` is `fail`.
+pub fn record_event_checks<T: Transport>(
+    app: &App<T>,
+    on_home: bool,
+    counts: &mut RelayCounts,
+    checks: &[EventCheck],
+) -> Result<()> {
+    let mut missing = None;
+    let mut reverted = None;
+    for check in checks {
+        match check.outcome {
+            EventOutcome::Emitted => debug!(
+                target: "bridge::relay_events",
+                "relay transaction {} emitted {}",
+                check.transaction,
+                check.expected.description()
+            ),
+            EventOutcome::Unconfirmed => warn!(
+                target: "bridge::relay_events",
+                "relay transaction {} wasn't mined within relay_events.timeout. its events are not checked",
+                check.transaction
+            ),
+            EventOutcome::Missing => {
+                error!(
+                    target: "bridge::relay_events",
+                    "CRITICAL: relay transaction {} was mined without emitting {}. the relay may not have taken effect",
+                    check.transaction,
+                    check.expected.description()
+                );
+                counts.missing_events += 1;
+                missing = missing.or(Some(check));
+            }
+            EventOutcome::Reverted(ref revert) if revert.kind.already_done() => {
+                info!(
+                    target: "bridge::relay_events",
+                    "relay transaction {} reverted because the relay already took effect: {}",
+                    check.transaction,
+                    revert.description()
+                );
+                counts.already_relayed += 1;
+            }
+            EventOutcome::Reverted(ref revert) => {
+                error!(
+                    target: "bridge::relay_events",
+                    "CRITICAL: relay transaction {} reverted: {}",
+                    check.transaction,
+                    revert.description()
+                );
+                counts.reverted += 1;
+                reverted = reverted.or(Some((check.transaction, revert)));
+            }
+        }
+    }
+    let not_an_authority = checks.iter().any(|check| match check.outcome {
+        EventOutcome::Reverted(ref revert) => revert.kind == RevertKind::NotAuthority,
+        _ => false,
+    });
+    if not_an_authority && !on_home {
+        return Err(ErrorKind::NotAnAuthority(app.config.foreign.account).into());
+    }
+    let config = app.config.relay_events.as_ref();
+    if let Some((transaction, revert)) = reverted {
+        if config.map_or(false, |config| config.reverted == MissingEventAction::Fail) {
+            return Err(ErrorKind::RelayReverted(
+                transaction,
+                revert.kind.label(),
+                revert.description(),
+            ).into());
+        }
+    }
+    let fail = config.map_or(false, |config| config.missing == MissingEventAction::Fail);
+    match missing {
+        Some(check) if fail => Err(ErrorKind::MissingRelayEvent(
+            check.transaction,
+            check.expected.description(),
+        ).into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::{self, Token};
+    use tiny_keccak::keccak256;
+    use web3::types::{Address, H256, Log, TransactionRequest, U256};
+    use config::{GasPriceTier, GasPriceTiersConfig};
+    use contracts::{foreign, home};
+    use message_to_mainnet::MessageToMainnet;
+    use super::super::tiered_gas_price;
+    use super::{out_of_gas_retry, ran_out_of_gas, ExpectedEvent};
+
+    fn request(contract: Address, data: Vec<u8>) -> TransactionRequest {
+        TransactionRequest {
+            from: 1.into(),
+            to: Some(contract),
+            gas: None,
+            gas_price: None,
+            value: None,
+            data: Some(data.into()),
+            nonce: None,
+            condition: None,
+        }
+    }
+
+    fn log(contract: Address, signature: &str, args: &[Token]) -> Log {
+        Log {
+            address: contract,
+            topics: vec![keccak256(signature.as_bytes()).into()],
+            data: ethabi::encode(args).into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_deposit_relay_expects_deposit_confirmation_or_deposit() {
+        let foreign = foreign::ForeignBridge::default();
+        let contract: Address = 9.into();
+        let recipient: Address = 1.into();
+        let value: U256 = 2.into();
+        let id: H256 = 3.into();
+        let payload = foreign.functions().deposit().input(recipient, value, id.0);
+        let expected = ExpectedEvent::for_relay(&request(contract, payload)).unwrap();
+        assert_eq!(
+            "DepositConfirmation(address,uint256,bytes32) or Deposit(address,uint256,bytes32)",
+            expected.description()
+        );
+
+        let args = [
+            Token::Address(recipient),
+            Token::Uint(value),
+            Token::FixedBytes(id.to_vec()),
+        ];
+        let confirmation = log(contract, "DepositConfirmation(address,uint256,bytes32)", &args);
+        let deposit = log(contract, "Deposit(address,uint256,bytes32)", &args);
+        assert!(expected.emitted_in(&[confirmation.clone()]));
+        assert!(expected.emitted_in(&[deposit.clone()]));
+        assert_eq!(
+            Some("DepositConfirmation(address,uint256,bytes32)"),
+            expected.emitted_event(&[confirmation.clone()])
+        );
+        assert_eq!(
+            Some("Deposit(address,uint256,bytes32)"),
+            expected.emitted_event(&[deposit])
+        );
+
+        // another deposit, another contract, another event
+        let other_value = [
+            Token::Address(recipient),
+            Token::Uint(3.into()),
+            Token::FixedBytes(id.to_vec()),
+        ];
+        assert!(!expected.emitted_in(&[
+            log(contract, "Deposit(address,uint256,bytes32)", &other_value),
+        ]));
+        assert!(!expected.emitted_in(&[Log {
+            address: 8.into(),
+            ..confirmation
+        }]));
+        assert!(!expected.emitted_in(&[log(contract, "Transfer(address,address,uint256)", &args)]));
+        assert!(!expected.emitted_in(&[]));
+    }
+
+    #[test]
+    fn test_withdraw_confirm_expects_submitted_or_collected_signatures() {
+        let foreign = foreign::ForeignBridge::default();
+        let contract: Address = 9.into();
+        let message = vec![2u8; 116];
+        let payload = foreign
+            .functions()
+            .submit_signature()
+            .input(vec![1u8; 65], message.clone());
+        let expected = ExpectedEvent::for_relay(&request(contract, payload)).unwrap();
+
+        let hash = Token::FixedBytes(keccak256(&message).to_vec());
+        assert!(expected.emitted_in(&[
+            log(contract, "WithdrawSignatureSubmitted(bytes32)", &[hash.clone()]),
+        ]));
+        // any authority may be responsible for the relay
+        assert!(expected.emitted_in(&[log(
+            contract,
+            "CollectedSignatures(address,bytes32)",
+            &[Token::Address(5.into()), hash],
+        )]));
+        assert!(!expected.emitted_in(&[log(
+            contract,
+            "WithdrawSignatureSubmitted(bytes32)",
+            &[Token::FixedBytes(vec![0u8; 32])],
+        )]));
+    }
+
+    #[test]
+    fn test_withdraw_relay_expects_withdraw() {
+        let home = home::HomeBridge::default();
+        let contract: Address = 9.into();
+        let message = MessageToMainnet {
+            recipient: 1.into(),
+            value: 1000.into(),
+            sidenet_transaction_hash: 2.into(),
+            mainnet_gas_price: 3.into(),
+        };
+        let payload = home.functions().withdraw().input(
+            vec![27u8],
+            vec![H256::from(4)],
+            vec![H256::from(5)],
+            message.to_bytes(),
+        );
+        let expected = ExpectedEvent::for_relay(&request(contract, payload)).unwrap();
+
+        // the relay cost is subtracted from the value
+        let withdraw = |hash: H256| {
+            log(
+                contract,
+                "Withdraw(address,uint256,bytes32)",
+                &[
+                    Token::Address(message.recipient),
+                    Token::Uint(900.into()),
+                    Token::FixedBytes(hash.to_vec()),
+                ],
+            )
+        };
+        assert!(expected.emitted_in(&[withdraw(2.into())]));
+        assert!(!expected.emitted_in(&[withdraw(3.into())]));
+
+        // paid to the payout address, but the event names the recipient
+        let payload = home.functions().withdraw_to_payout().input(
+            vec![27u8],
+            vec![H256::from(4)],
+            vec![H256::from(5)],
+            message.to_bytes(),
+            Address::from(6),
+        );
+        let to_payout = ExpectedEvent::for_relay(&request(contract, payload)).unwrap();
+        assert_eq!(expected, to_payout);
+    }
+
+    #[test]
+    fn test_other_calls_expect_no_events() {
+        let contract: Address = 9.into();
+        let payload = keccak256(b"acknowledge(bytes,bytes)")[..4].to_vec();
+        assert_eq!(None, ExpectedEvent::for_relay(&request(contract, payload)));
+        assert_eq!(
+            None,
+            ExpectedEvent::for_relay(&TransactionRequest {
+                to: None,
+                ..request(contract, Vec::new())
+            })
+        );
+    }
+
+    #[test]
+    fn test_out_of_gas_relay_is_sent_again_once() {
+        let foreign = foreign::ForeignBridge::default();
+        let payload = foreign
+            .functions()
+            .deposit()
+            .input(Address::from(1), U256::from(2), [3u8; 32]);
+        let relay = TransactionRequest {
+            gas: Some(100_000.into()),
+            nonce: Some(7.into()),
+            ..request(9.into(), payload)
+        };
+        let expected = ExpectedEvent::for_relay(&relay).unwrap();
+        assert!(ran_out_of_gas(&expected, 100_000.into()));
+        assert!(!ran_out_of_gas(&expected, 99_999.into()));
+        // not sent again unless `retry_out_of_gas` is enabled
+        assert_eq!(None, out_of_gas_retry(&expected, 150));
+
+        let expected = ExpectedEvent {
+            resend: Some(relay.clone()),
+            ..expected
+        };
+        let (retry, gas) = out_of_gas_retry(&expected, 150).unwrap();
+        assert_eq!(U256::from(150_000), gas);
+        assert_eq!(
+            TransactionRequest {
+                gas: Some(gas),
+                nonce: None,
+                ..relay
+            },
+            retry
+        );
+
+        // a relay with an estimated gas limit never runs out of the configured one
+        let estimated = ExpectedEvent {
+            gas: None,
+            ..expected
+        };
+        assert!(!ran_out_of_gas(&estimated, 100_000.into()));
+        assert_eq!(None, out_of_gas_retry(&estimated, 150));
+    }
+    #[test]
+    fn test_out_of_gas_retry_keeps_the_tiered_gas_price() {
+        let config = GasPriceTiersConfig {
+            tiers: vec![GasPriceTier {
+                min_value: 1000.into(),
+                percent: 300,
+            }],
+            ceiling: Some(25),
+        };
+        let gas_price = tiered_gas_price(&config, None, 10.into(), 1000.into());
+        let foreign = foreign::ForeignBridge::default();
+        let payload = foreign
+            .functions()
+            .deposit()
+            .input(Address::from(1), U256::from(1000), [3u8; 32]);
+        let relay = TransactionRequest {
+            gas: Some(100_000.into()),
+            gas_price: Some(gas_price.effective),
+            ..request(9.into(), payload)
+        };
+        let expected = ExpectedEvent {
+            resend: Some(relay.clone()),
+            ..ExpectedEvent::for_relay(&relay).unwrap()
+        };
+        // the tier isn't applied again and the ceiling still holds
+        let (retry, _) = out_of_gas_retry(&expected, 150).unwrap();
+        assert_eq!(Some(U256::from(25)), retry.gas_price);
+    }
+}
@@ -0,0 +1,187 @@
+use serde_json;
+use web3::types::H256;
+use outbox::Notification;
+use webhook::{u256, PAYLOAD_VERSION};
+use super::history::{relay_key, Direction, RelayRecord};
+use super::relay_outcome::{DepositOutcome, RelayOutcome};
+
+fn hex(hash: &H256) -> String {
+    format!("0x{:x}", hash)
+}
+
+/// notification that `record` was relayed, with the `recipient` paid and the `value`.
+/// its idempotency key is `<relay key>/relayed/<relay transaction>`,
+/// so a relay sent again after a reorg is notified again.
+pub fn relayed_notification(record: &RelayRecord) -> Notification {
+    let ordering_key = hex(&record.key);
+    let key = format!("{}/relayed/{}", ordering_key, hex(&record.relay_transaction));
+    let payload = json!({
+        "payload_version": PAYLOAD_VERSION,
+        "idempotency_key": key,
+        "relay_key": ordering_key,
+        "direction": record.direction,
+        "status": "relayed",
+        "recipient": record.receiver,
+        "value": u256(record.value),
+        "relay": record,
+    });
+    Notification {
+        key,
+        ordering_key,
+        payload,
+    }
+}
+
+/// notification that the deposit of `outcome` wasn't relayed, `None` if it was.
+/// its idempotency key is `<relay key>/skipped/<reason>`: a deposit deferred by the spend limit
+/// on every check is notified once.
+pub fn skipped_notification(outcome: &DepositOutcome) -> Option<Notification> {
+    let skip = match outcome.outcome {
+        RelayOutcome::Skipped(ref skip) => skip,
+        RelayOutcome::Relayed => return None,
+    };
+    let ordering_key = hex(&relay_key(
+        Direction::HomeToForeign,
+        &outcome.home_transaction,
+        outcome.log_index,
+    ));
+    let reason = serde_json::to_value(skip.reason).expect("serialization can't fail; qed");
+    let key = format!(
+        "{}/skipped/{}",
+        ordering_key,
+        reason.as_str().expect("reasons serialize to their names; qed")
+    );
+    let payload = json!({
+        "payload_version": PAYLOAD_VERSION,
+        "idempotency_key": key,
+        "relay_key": ordering_key,
+        "direction": Direction::HomeToForeign,
+        "status": "skipped",
+        "home_transaction": outcome.home_transaction,
+        "log_index": outcome.log_index.map(u256),
+        "block": outcome.block,
+        "reason": reason,
+        "message": skip.message,
+        "parameters": skip.parameters,
+    });
+    Some(Notification {
+        key,
+        ordering_key,
+        payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::H256;
+    use webhook::canonical_json;
+    use super::super::gas_price_tiers::TieredGasPrice;
+    use super::super::history::{relay_key, Direction, MainPosition, RelayRecord};
+    use super::super::relay_outcome::{DepositOutcome, RelayOutcome, Skip, SkipReason};
+    use super::super::step_timing::StepTimings;
+    use super::{relayed_notification, skipped_notification};
+
+    const RELAYED: &str = include_str!("../../golden/webhook_relayed.json");
+
+    fn outcome(outcome: RelayOutcome) -> DepositOutcome {
+        DepositOutcome {
+            home_transaction: 1.into(),
+            log_index: Some(2.into()),
+            block: Some(120),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_relayed_notification() {
+        let key = relay_key(Direction::HomeToForeign, &1.into(), Some(2.into()));
+        let record = RelayRecord {
+            direction: Direction::HomeToForeign,
+            key,
+            source_transaction: 1.into(),
+            relay_transaction: 3.into(),
+            block: 130,
+            receiver: 0xaa.into(),
+            value: 0xf0.into(),
+            main_position: None,
+            memo: None,
+            recipient: None,
+            derived_recipient: None,
+            gas_price: None,
+            timings: None,
+            observed_on: None,
+            submitted_to: None,
+        };
+        let notification = relayed_notification(&record);
+        let relay = format!("0x{:x}", key);
+        assert_eq!(relay, notification.ordering_key);
+        assert_eq!(
+            format!("{}/relayed/0x{:x}", relay, H256::from(3)),
+            notification.key
+        );
+        assert_eq!("relayed", notification.payload["status"]);
+        assert_eq!("home_to_foreign", notification.payload["direction"]);
+        assert_eq!(notification.key, notification.payload["idempotency_key"]);
+        assert_eq!(130, notification.payload["relay"]["block"]);
+        assert_eq!("240", notification.payload["value"]);
+        assert_eq!(
+            "0x00000000000000000000000000000000000000aa",
+            notification.payload["recipient"]
+        );
+    }
+
+    #[test]
+    fn test_relayed_payload_is_pinned() {
+        let record = RelayRecord {
+            direction: Direction::HomeToForeign,
+            key: 0x10.into(),
+            source_transaction: 1.into(),
+            relay_transaction: 3.into(),
+            block: 130,
+            receiver: 0xaa.into(),
+            value: 1_000_000_000_000_000_000u64.into(),
+            main_position: Some(MainPosition {
+                block_number: 120,
+                transaction_index: 0,
+                log_index: 2,
+            }),
+            memo: None,
+            recipient: None,
+            derived_recipient: None,
+            gas_price: Some(TieredGasPrice {
+                base: 20_000_000_000u64.into(),
+                tier: Some(1),
+                effective: 30_000_000_000u64.into(),
+                capped: false,
+            }),
+            timings: Some(StepTimings {
+                check: Some(5),
+                signatures: None,
+                submit: Some(20),
+                confirm: None,
+            }),
+            observed_on: None,
+            submitted_to: None,
+        };
+        let payload = canonical_json(&relayed_notification(&record).payload).unwrap();
+        assert_eq!(RELAYED.trim_right(), payload);
+    }
+
+    #[test]
+    fn test_skipped_notification() {
+        assert_eq!(None, skipped_notification(&outcome(RelayOutcome::Relayed)));
+
+        let skip = Skip::new(SkipReason::SpendLimit, "deferred by the spend limit").with("limit", 10);
+        let notification = skipped_notification(&outcome(RelayOutcome::Skipped(skip))).unwrap();
+        let relay = format!(
+            "0x{:x}",
+            relay_key(Direction::HomeToForeign, &1.into(), Some(2.into()))
+        );
+        assert_eq!(relay, notification.ordering_key);
+        assert_eq!(format!("{}/skipped/spend_limit", relay), notification.key);
+        assert_eq!("skipped", notification.payload["status"]);
+        assert_eq!("spend_limit", notification.payload["reason"]);
+        assert_eq!("2", notification.payload["log_index"]);
+        assert_eq!("10", notification.payload["parameters"]["limit"]);
+    }
+}
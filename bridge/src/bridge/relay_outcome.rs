@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde_json;
+use web3::types::{H256, U256};
+use database::{Database, SkippedDeposit};
+use super::history::Direction;
+use super::raw_logs::{lookup_logs, LoggedEvent};
+
+/// number of skipped deposits `SkippedDeposits` keeps.
+pub const KEPT_SKIPPED_DEPOSITS: usize = 1024;
+
+/// Why a deposit wasn't relayed, or not yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// zero value deposit and `relay_zero_value` is disabled
+    ZeroValue,
+    /// `value_scale` can't convert the value of the deposit
+    RejectedValue,
+    /// the deposit was already executed on foreign
+    AlreadyExecuted,
+    /// home transaction sent by this authority while `allow_same_chain` is enabled
+    SameChain,
+    /// the recipient contract rejects the transfer, see `QuarantinedDeposit`
+    Quarantined,
+    /// beyond the spend limit. relayed once the limit allows it
+    SpendLimit,
+}
+
+/// Why a deposit wasn't relayed with what the decision depended on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skip {
+    pub reason: SkipReason,
+    pub message: String,
+    /// values the decision depended on, e.g. the spend limit
+    pub parameters: BTreeMap<String, String>,
+}
+
+impl Skip {
+    pub fn new<S: Into<String>>(reason: SkipReason, message: S) -> Self {
+        Skip {
+            reason,
+            message: message.into(),
+            parameters: BTreeMap::new(),
+        }
+    }
+
+    /// `self` with the parameter `name` set to `value`.
+    pub fn with<V: fmt::Display>(mut self, name: &str, value: V) -> Self {
+        self.parameters.insert(name.into(), value.to_string());
+        self
+    }
+}
+
+/// What a step of the deposit relay decided for a deposit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelayOutcome {
+    /// the deposit is relayed, unless a later step skips it
+    Relayed,
+    Skipped(Skip),
+}
+
+/// Outcome of the deposit emitted by `home_transaction` at `log_index`,
+/// see `DepositRelay::take_outcomes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepositOutcome {
+    pub home_transaction: H256,
+    pub log_index: Option<U256>,
+    /// home block of the deposit. `None` if the node left it out
+    pub block: Option<u64>,
+    pub outcome: RelayOutcome,
+}
+
+/// Deposits recently skipped, the most recent `KEPT_SKIPPED_DEPOSITS` of them, oldest first.
+///
+/// a deposit skipped again only keeps its latest skip. a deposit relayed after it was
+/// deferred is forgotten.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SkippedDeposits {
+    skipped: Vec<SkippedDeposit>,
+}
+
+impl SkippedDeposits {
+    /// skipped deposits continuing from those of `init`.
+    pub fn new(init: &Database) -> Self {
+        SkippedDeposits {
+            skipped: init.skipped_deposits.clone(),
+        }
+    }
+
+    /// records `outcome` at unix time `now`.
+    pub fn record(&mut self, outcome: DepositOutcome, now: u64) {
+        let DepositOutcome {
+            home_transaction,
+            log_index,
+            block,
+            outcome,
+        } = outcome;
+        self.skipped.retain(|skipped| {
+            skipped.home_transaction != home_transaction || skipped.log_index != log_index
+        });
+        let skip = match outcome {
+            RelayOutcome::Relayed => return,
+            RelayOutcome::Skipped(skip) => skip,
+        };
+        if self.skipped.len() >= KEPT_SKIPPED_DEPOSITS {
+            self.skipped.remove(0);
+        }
+        self.skipped.push(SkippedDeposit {
+            home_transaction,
+            log_index,
+            block,
+            time: now,
+            reason: skip.reason,
+            message: skip.message,
+            parameters: skip.parameters,
+        });
+    }
+
+    /// kept deposits, oldest first.
+    pub fn skipped(&self) -> &[SkippedDeposit] {
+        &self.skipped
+    }
+}
+
+/// What the database records of the deposits of a home transaction, printed by
+/// `--lookup-deposit`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DepositLookup {
+    pub home_transaction: H256,
+    /// why its deposits weren't relayed. empty if none was skipped recently
+    pub skipped: Vec<SkippedDeposit>,
+    /// logs of its deposits, raw and decoded. empty if none was observed recently
+    pub logs: Vec<LoggedEvent>,
+}
+
+impl DepositLookup {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialization can't fail; qed")
+    }
+}
+
+/// the skips and logs `database` records of the deposits in home transaction
+/// `home_transaction`.
+pub fn lookup_deposit(database: &Database, home_transaction: H256) -> DepositLookup {
+    DepositLookup {
+        home_transaction,
+        skipped: database
+            .skipped_deposits
+            .iter()
+            .filter(|skipped| skipped.home_transaction == home_transaction)
+            .cloned()
+            .collect(),
+        logs: lookup_logs(database, Direction::HomeToForeign, home_transaction),
+    }
+}
+
+/// Deposits skipped since a time by reason, printed by `--skipped`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SkippedSummary {
+    /// unix time the deposits were skipped at or after
+    pub since: u64,
+    pub total: usize,
+    /// reasons any deposit was skipped for, in the order of `SkipReason`
+    pub reasons: Vec<ReasonCount>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ReasonCount {
+    pub reason: SkipReason,
+    pub deposits: usize,
+}
+
+impl SkippedSummary {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialization can't fail; qed")
+    }
+}
+
+/// counts the deposits `database` records as skipped at or after unix time `since` by reason.
+pub fn summarize_skipped(database: &Database, since: u64) -> SkippedSummary {
+    let mut reasons = BTreeMap::new();
+    let mut total = 0;
+    for skipped in database
+        .skipped_deposits
+        .iter()
+        .filter(|skipped| skipped.time >= since)
+    {
+        *reasons.entry(skipped.reason).or_insert(0) += 1;
+        total += 1;
+    }
+    SkippedSummary {
+        since,
+        total,
+        reasons: reasons
+            .into_iter()
+            .map(|(reason, deposits)| ReasonCount { reason, deposits })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use super::{lookup_deposit, summarize_skipped, DepositOutcome, ReasonCount, RelayOutcome,
+                Skip, SkipReason, SkippedDeposits, KEPT_SKIPPED_DEPOSITS};
+
+    fn outcome(home_transaction: u64, outcome: RelayOutcome) -> DepositOutcome {
+        DepositOutcome {
+            home_transaction: home_transaction.into(),
+            log_index: Some(0.into()),
+            block: Some(10),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_skipped_deposits_keep_the_latest_skip() {
+        let mut skipped = SkippedDeposits::new(&Database::default());
+        let deferred = Skip::new(SkipReason::SpendLimit, "spend limit reached")
+            .with("limit", 100)
+            .with("spent", 90);
+        skipped.record(outcome(1, RelayOutcome::Skipped(deferred.clone())), 1000);
+        skipped.record(outcome(2, RelayOutcome::Skipped(deferred.clone())), 1000);
+        let zero = Skip::new(SkipReason::ZeroValue, "zero value");
+        skipped.record(outcome(3, RelayOutcome::Skipped(zero)), 1010);
+        // deferred again, then relayed
+        skipped.record(outcome(1, RelayOutcome::Skipped(deferred)), 1020);
+        skipped.record(outcome(2, RelayOutcome::Relayed), 1030);
+
+        let kept = skipped.skipped();
+        assert_eq!(2, kept.len());
+        assert_eq!(SkipReason::ZeroValue, kept[0].reason);
+        assert_eq!(1.into(), kept[1].home_transaction);
+        assert_eq!(1020, kept[1].time);
+        assert_eq!(Some(10), kept[1].block);
+        assert_eq!("spend limit reached", kept[1].message);
+        assert_eq!(Some(&"100".to_owned()), kept[1].parameters.get("limit"));
+
+        for home_transaction in 0..KEPT_SKIPPED_DEPOSITS as u64 {
+            let skip = Skip::new(SkipReason::AlreadyExecuted, "already executed");
+            skipped.record(outcome(100 + home_transaction, RelayOutcome::Skipped(skip)), 2000);
+        }
+        assert_eq!(KEPT_SKIPPED_DEPOSITS, skipped.skipped().len());
+        assert_eq!(100.into(), skipped.skipped()[0].home_transaction);
+    }
+
+    #[test]
+    fn test_lookup_and_summarize_skipped_deposits() {
+        let mut skipped = SkippedDeposits::new(&Database::default());
+        let executed = Skip::new(SkipReason::AlreadyExecuted, "already executed");
+        skipped.record(outcome(1, RelayOutcome::Skipped(executed.clone())), 1000);
+        skipped.record(outcome(2, RelayOutcome::Skipped(executed)), 2000);
+        let quarantined = Skip::new(SkipReason::Quarantined, "recipient rejects the transfer");
+        skipped.record(outcome(3, RelayOutcome::Skipped(quarantined)), 3000);
+        let database = Database {
+            skipped_deposits: skipped.skipped().to_vec(),
+            ..Database::default()
+        };
+
+        let lookup = lookup_deposit(&database, 3.into());
+        assert_eq!(1, lookup.skipped.len());
+        assert_eq!(SkipReason::Quarantined, lookup.skipped[0].reason);
+        assert!(lookup.to_json().contains("\"reason\": \"quarantined\""));
+        assert!(lookup_deposit(&database, 4.into()).skipped.is_empty());
+
+        let summary = summarize_skipped(&database, 2000);
+        assert_eq!(2, summary.total);
+        assert_eq!(
+            vec![
+                ReasonCount {
+                    reason: SkipReason::AlreadyExecuted,
+                    deposits: 1,
+                },
+                ReasonCount {
+                    reason: SkipReason::Quarantined,
+                    deposits: 1,
+                },
+            ],
+            summary.reasons
+        );
+        assert!(summary.to_json().contains("\"reason\": \"already_executed\""));
+        assert_eq!(3, summarize_skipped(&database, 0).total);
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{HashSet, VecDeque};
+use web3::types::H256;
+
+/// number of messages `RelayedMessages` keeps of the withdraws relayed by this authority,
+/// and of those assigned to other authorities.
+pub const KEPT_MESSAGES: usize = 1024;
+
+/// What the withdraw relay knows about the message of a `CollectedSignatures` log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageStatus {
+    /// not relayed by this authority yet
+    New,
+    /// in the batch of withdraws in flight
+    InFlight,
+    /// relayed by this authority
+    Relayed,
+}
+
+/// Hashes of messages, the most recent `KEPT_MESSAGES` of them, oldest first.
+#[derive(Debug, Clone, Default)]
+struct RecentHashes {
+    order: VecDeque<H256>,
+    hashes: HashSet<H256>,
+}
+
+impl RecentHashes {
+    fn contains(&self, hash: &H256) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    fn insert(&mut self, hash: H256) {
+        if !self.hashes.insert(hash) {
+            return;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > KEPT_MESSAGES {
+            let oldest = self.order
+                .pop_front()
+                .expect("more than KEPT_MESSAGES are kept; qed");
+            self.hashes.remove(&oldest);
+        }
+    }
+}
+
+/// Messages of the withdraws the withdraw relay relayed or sends, by hash.
+///
+/// `ForeignBridge` may emit `CollectedSignatures` again for a message, e.g. replaying
+/// its pending state after an upgrade. relaying the repeated log reverts, so it's resolved
+/// by this record before its signatures are fetched. messages assigned to other authorities
+/// are kept as well: a repeated log assigning one of them to this authority is checked
+/// with `HomeBridge.isWithdrawExecuted` instead.
+#[derive(Debug, Clone, Default)]
+pub struct RelayedMessages {
+    relayed: RecentHashes,
+    in_flight: HashSet<H256>,
+    assigned_elsewhere: RecentHashes,
+}
+
+impl RelayedMessages {
+    /// record of the messages `relayed`, oldest first, e.g. as saved in the database.
+    pub fn new(relayed: &[H256]) -> Self {
+        let mut messages = RelayedMessages::default();
+        for hash in relayed {
+            messages.relayed.insert(*hash);
+        }
+        messages
+    }
+
+    pub fn status(&self, hash: &H256) -> MessageStatus {
+        if self.in_flight.contains(hash) {
+            MessageStatus::InFlight
+        } else if self.relayed.contains(hash) {
+            MessageStatus::Relayed
+        } else {
+            MessageStatus::New
+        }
+    }
+
+    /// `true` if a log assigned the message to another authority.
+    pub fn is_assigned_elsewhere(&self, hash: &H256) -> bool {
+        self.assigned_elsewhere.contains(hash)
+    }
+
+    pub fn assign_elsewhere(&mut self, hash: H256) {
+        self.assigned_elsewhere.insert(hash);
+    }
+
+    /// adds the message to the batch in flight.
+    pub fn start(&mut self, hash: H256) {
+        self.in_flight.insert(hash);
+    }
+
+    /// records that the withdraw of the message was sent.
+    pub fn relayed(&mut self, hash: H256) {
+        self.in_flight.remove(&hash);
+        self.relayed.insert(hash);
+    }
+
+    /// forgets the messages of the batch in flight that weren't relayed, e.g. executed already
+    /// or of a batch that failed. a repeated log of one of them is relayed again.
+    pub fn end_batch(&mut self) {
+        self.in_flight.clear();
+    }
+
+    /// messages relayed, oldest first.
+    pub fn to_vec(&self) -> Vec<H256> {
+        self.relayed.order.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::H256;
+    use super::{MessageStatus, RelayedMessages, KEPT_MESSAGES};
+
+    #[test]
+    fn test_relayed_messages() {
+        let mut messages = RelayedMessages::new(&[1.into()]);
+        assert_eq!(MessageStatus::Relayed, messages.status(&1.into()));
+        assert_eq!(MessageStatus::New, messages.status(&2.into()));
+
+        messages.start(2.into());
+        messages.start(3.into());
+        assert_eq!(MessageStatus::InFlight, messages.status(&2.into()));
+        messages.relayed(2.into());
+        messages.end_batch();
+        assert_eq!(MessageStatus::Relayed, messages.status(&2.into()));
+        // not relayed by the batch, e.g. executed already
+        assert_eq!(MessageStatus::New, messages.status(&3.into()));
+        assert_eq!(vec![H256::from(1), H256::from(2)], messages.to_vec());
+
+        messages.assign_elsewhere(4.into());
+        assert!(messages.is_assigned_elsewhere(&4.into()));
+        assert_eq!(MessageStatus::New, messages.status(&4.into()));
+    }
+
+    #[test]
+    fn test_relayed_messages_keeps_the_most_recent() {
+        let mut messages = RelayedMessages::default();
+        for hash in 0..KEPT_MESSAGES as u64 + 2 {
+            messages.relayed(hash.into());
+        }
+        // relaying a message again doesn't make it more recent
+        messages.relayed(2.into());
+        assert_eq!(MessageStatus::New, messages.status(&1.into()));
+        assert_eq!(MessageStatus::Relayed, messages.status(&2.into()));
+        assert_eq!(KEPT_MESSAGES, messages.to_vec().len());
+        assert_eq!(H256::from(2), messages.to_vec()[0]);
+    }
+}
@@ -0,0 +1,360 @@
+use std::mem;
+use std::sync::Arc;
+use futures::{Future, Poll, Stream};
+use futures::future::{join_all, JoinAll};
+use timer::Interval;
+use web3::Transport;
+use web3::types::{Block, H256, Log, TransactionReceipt, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use error::Error;
+
+/// A deposit log that has been relayed to foreign.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RelayedLog {
+    pub block_number: u64,
+    pub block_hash: H256,
+    pub transaction_hash: H256,
+}
+
+impl RelayedLog {
+    /// returns `None` for logs which have not been mined.
+    pub fn from_log(log: &Log) -> Option<Self> {
+        match (log.block_number, log.block_hash, log.transaction_hash) {
+            (Some(block_number), Some(block_hash), Some(transaction_hash)) => Some(RelayedLog {
+                block_number: block_number.low_u64(),
+                block_hash,
+                transaction_hash,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A reorg of home that orphaned blocks of relayed deposits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReorgIncident {
+    /// lowest orphaned block
+    pub fork_block: u64,
+    /// number of blocks between `fork_block` and the latest block, inclusive
+    pub depth: u64,
+    /// `(orphaned hash, canonical hash)` of every orphaned block
+    pub block_hashes: Vec<(H256, H256)>,
+    /// transactions of relayed deposits whose blocks were orphaned
+    pub orphaned: Vec<H256>,
+    /// orphaned deposits that are not part of the canonical chain anymore.
+    /// the tokens minted on foreign for them are unbacked
+    pub unbacked: Vec<H256>,
+}
+
+/// Depths of the home reorgs the reorg monitor observed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReorgDepths {
+    /// depth of the latest reorg since the bridge started
+    pub last: Option<u64>,
+    /// depth of the deepest reorg ever observed, also before a restart
+    pub max: Option<u64>,
+}
+
+impl ReorgDepths {
+    /// depths continuing from the deepest reorg `max` observed before.
+    pub fn new(max: Option<u64>) -> Self {
+        ReorgDepths { last: None, max }
+    }
+
+    /// records a reorg of `depth`. returns `true` if it's the deepest yet.
+    pub fn record(&mut self, depth: u64) -> bool {
+        self.last = Some(depth);
+        if self.max.map_or(true, |max| depth > max) {
+            self.max = Some(depth);
+            return true;
+        }
+        false
+    }
+}
+
+/// Relayed deposits which are watched for reorgs.
+#[derive(Debug, Default)]
+pub struct RelayedLogs {
+    logs: Vec<RelayedLog>,
+}
+
+impl RelayedLogs {
+    pub fn track<I: IntoIterator<Item = RelayedLog>>(&mut self, logs: I) {
+        self.logs.extend(logs);
+    }
+
+    /// stops watching logs from blocks older than `depth` blocks before `latest_block`.
+    pub fn prune(&mut self, latest_block: u64, depth: u64) {
+        let oldest = latest_block.saturating_sub(depth);
+        self.logs.retain(|log| log.block_number >= oldest);
+    }
+
+    /// distinct numbers of blocks containing watched logs, ascending.
+    pub fn block_numbers(&self) -> Vec<u64> {
+        let mut numbers = self.logs
+            .iter()
+            .map(|log| log.block_number)
+            .collect::<Vec<_>>();
+        numbers.sort();
+        numbers.dedup();
+        numbers
+    }
+
+    /// returns `(orphaned hash, canonical hash)` of blocks whose hash changed
+    /// and removes their logs. `canonical` contains `(number, hash)` of blocks.
+    pub fn take_orphaned(
+        &mut self,
+        canonical: &[(u64, H256)],
+    ) -> (Vec<(H256, H256)>, Vec<RelayedLog>) {
+        let mut block_hashes = Vec::new();
+        let logs = mem::replace(&mut self.logs, Vec::new());
+        let (orphaned, kept): (Vec<_>, Vec<_>) = logs.into_iter().partition(|log| {
+            canonical
+                .iter()
+                .any(|&(number, hash)| number == log.block_number && hash != log.block_hash)
+        });
+        for log in &orphaned {
+            let canonical_hash = canonical
+                .iter()
+                .find(|&&(number, _)| number == log.block_number)
+                .map(|&(_, hash)| hash)
+                .expect("orphaned logs are in canonical blocks; qed");
+            if !block_hashes.contains(&(log.block_hash, canonical_hash)) {
+                block_hashes.push((log.block_hash, canonical_hash));
+            }
+        }
+        self.logs = kept;
+        (block_hashes, orphaned)
+    }
+}
+
+enum ReorgMonitorState<T: Transport> {
+    /// Waiting for the next check.
+    Wait,
+    /// Fetching the latest block number.
+    FetchBlockNumber(Timeout<ApiCall<U256, T::Out>>),
+    /// Fetching blocks of watched logs.
+    FetchBlocks {
+        numbers: Vec<u64>,
+        latest_block: u64,
+        future: JoinAll<Vec<Timeout<ApiCall<Block<H256>, T::Out>>>>,
+    },
+    /// Checking whether orphaned deposits are part of the canonical chain.
+    FetchReceipts {
+        incident: Option<ReorgIncident>,
+        future: JoinAll<Vec<Timeout<ApiCall<Option<TransactionReceipt>, T::Out>>>>,
+    },
+    /// Reorg has been detected.
+    Yield(Option<ReorgIncident>),
+}
+
+pub fn create_reorg_monitor<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    interval: Interval,
+    depth: u64,
+) -> ReorgMonitor<T> {
+    ReorgMonitor {
+        app,
+        interval,
+        depth,
+        relayed: RelayedLogs::default(),
+        state: ReorgMonitorState::Wait,
+    }
+}
+
+/// Detects reorgs of home which orphaned blocks of relayed deposits.
+/// relayed deposits are watched for `depth` blocks.
+pub struct ReorgMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    interval: Interval,
+    depth: u64,
+    relayed: RelayedLogs,
+    state: ReorgMonitorState<T>,
+}
+
+impl<T: Transport> ReorgMonitor<T> {
+    pub fn track<I: IntoIterator<Item = RelayedLog>>(&mut self, logs: I) {
+        self.relayed.track(logs);
+    }
+}
+
+impl<T: Transport> Stream for ReorgMonitor<T> {
+    type Item = ReorgIncident;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                ReorgMonitorState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    ReorgMonitorState::FetchBlockNumber(api::timeout(
+                        &self.app.timer,
+                        api::block_number(&self.app.connections.home),
+                        "home",
+                        self.app.config.home.request_timeout,
+                    ))
+                }
+                ReorgMonitorState::FetchBlockNumber(ref mut future) => {
+                    let latest_block = try_ready!(future.poll()).low_u64();
+                    self.relayed.prune(latest_block, self.depth);
+                    let numbers = self.relayed.block_numbers();
+                    let app = &self.app;
+                    let blocks = numbers
+                        .iter()
+                        .map(|number| {
+                            api::timeout(
+                                &app.timer,
+                                api::block_by_number(&app.connections.home, *number),
+                                "home",
+                                app.config.home.request_timeout,
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    ReorgMonitorState::FetchBlocks {
+                        numbers,
+                        latest_block,
+                        future: join_all(blocks),
+                    }
+                }
+                ReorgMonitorState::FetchBlocks {
+                    ref mut future,
+                    ref numbers,
+                    latest_block,
+                } => {
+                    let blocks = try_ready!(future.poll());
+                    let canonical = numbers
+                        .iter()
+                        .zip(blocks.iter())
+                        .filter_map(|(number, block)| block.hash.map(|hash| (*number, hash)))
+                        .collect::<Vec<_>>();
+                    let (block_hashes, orphaned) = self.relayed.take_orphaned(&canonical);
+
+                    match orphaned.iter().map(|log| log.block_number).min() {
+                        None => ReorgMonitorState::Wait,
+                        Some(fork_block) => {
+                            let incident = ReorgIncident {
+                                fork_block,
+                                depth: latest_block.saturating_sub(fork_block) + 1,
+                                block_hashes,
+                                orphaned: orphaned.iter().map(|log| log.transaction_hash).collect(),
+                                unbacked: Vec::new(),
+                            };
+                            warn!(
+                                "home reorg of depth {} orphaned blocks of {} relayed deposits: {:?}",
+                                incident.depth,
+                                incident.orphaned.len(),
+                                incident
+                            );
+                            let app = &self.app;
+                            let receipts = incident
+                                .orphaned
+                                .iter()
+                                .map(|hash| {
+                                    api::timeout(
+                                        &app.timer,
+                                        api::transaction_receipt(&app.connections.home, *hash),
+                                        "home",
+                                        app.config.home.request_timeout,
+                                    ).about(hash)
+                                })
+                                .collect::<Vec<_>>();
+                            ReorgMonitorState::FetchReceipts {
+                                incident: Some(incident),
+                                future: join_all(receipts),
+                            }
+                        }
+                    }
+                }
+                ReorgMonitorState::FetchReceipts {
+                    ref mut future,
+                    ref mut incident,
+                } => {
+                    let receipts = try_ready!(future.poll());
+                    let mut incident = incident
+                        .take()
+                        .expect("incident is only taken when leaving FetchReceipts state; qed");
+
+                    let mut still_canonical = Vec::new();
+                    for (hash, receipt) in incident.orphaned.iter().zip(receipts.into_iter()) {
+                        match receipt {
+                            Some(receipt) => still_canonical.push(RelayedLog {
+                                block_number: receipt.block_number.low_u64(),
+                                block_hash: receipt.block_hash,
+                                transaction_hash: *hash,
+                            }),
+                            None => incident.unbacked.push(*hash),
+                        }
+                    }
+                    // deposits that were included again keep being watched
+                    self.relayed.track(still_canonical);
+
+                    for hash in &incident.unbacked {
+                        error!(
+                            "CRITICAL: deposit in home transaction {} was relayed to foreign but is not part of the canonical home chain anymore. tokens minted for it on foreign are unbacked",
+                            hash
+                        );
+                    }
+                    ReorgMonitorState::Yield(Some(incident))
+                }
+                ReorgMonitorState::Yield(ref mut incident) => match incident.take() {
+                    None => ReorgMonitorState::Wait,
+                    some => return Ok(some.into()),
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RelayedLog, RelayedLogs, ReorgDepths};
+
+    fn relayed(block_number: u64, block_hash: u64, transaction_hash: u64) -> RelayedLog {
+        RelayedLog {
+            block_number,
+            block_hash: block_hash.into(),
+            transaction_hash: transaction_hash.into(),
+        }
+    }
+
+    #[test]
+    fn test_relayed_logs_prune() {
+        let mut logs = RelayedLogs::default();
+        logs.track(vec![relayed(10, 1, 1), relayed(20, 2, 2), relayed(20, 2, 3)]);
+
+        logs.prune(25, 10);
+        assert_eq!(vec![20], logs.block_numbers());
+    }
+
+    #[test]
+    fn test_reorg_depths_keep_the_last_and_the_deepest() {
+        let mut depths = ReorgDepths::new(Some(20));
+        assert!(!depths.record(14));
+        assert_eq!((Some(14), Some(20)), (depths.last, depths.max));
+        assert!(depths.record(21));
+        assert!(!depths.record(3));
+        assert_eq!((Some(3), Some(21)), (depths.last, depths.max));
+
+        let mut depths = ReorgDepths::default();
+        assert!(depths.record(13));
+        assert_eq!(Some(13), depths.max);
+    }
+
+    #[test]
+    fn test_relayed_logs_take_orphaned() {
+        let mut logs = RelayedLogs::default();
+        logs.track(vec![relayed(10, 1, 1), relayed(11, 2, 2), relayed(11, 2, 3)]);
+
+        let (block_hashes, orphaned) = logs.take_orphaned(&[(10, 1.into()), (11, 5.into())]);
+        assert_eq!(vec![(2.into(), 5.into())], block_hashes);
+        assert_eq!(vec![relayed(11, 2, 2), relayed(11, 2, 3)], orphaned);
+        assert_eq!(vec![10], logs.block_numbers());
+
+        // nothing orphaned twice
+        let (block_hashes, orphaned) = logs.take_orphaned(&[(10, 1.into()), (11, 5.into())]);
+        assert!(block_hashes.is_empty());
+        assert!(orphaned.is_empty());
+    }
+}
@@ -0,0 +1,359 @@
+use std::fs;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Instant;
+use serde_json;
+use web3::types::{Address, U256};
+use api;
+use database::Database;
+use error::{Error, Result, ResultExt};
+use super::Component;
+use super::recent_errors::{RecentError, RecentErrors};
+use super::step_timing::StepDurations;
+
+/// Outcomes of the relays of a bridge component.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct RelayCounts {
+    /// transactions sent
+    pub relayed: u64,
+    /// errors of the component
+    pub failed: u64,
+    /// transactions held back because they were predicted to fail or exceeded a spend limit
+    pub deferred: u64,
+    /// changes of `HomeBridge.requiredSignatures` noticed by the withdraw relay
+    pub required_signatures_changes: u64,
+    /// zero value deposits that weren't relayed
+    pub skipped_zero_value: u64,
+    /// deposits or withdraws skipped since they were already executed
+    pub skipped_executed: u64,
+    /// deposits or withdraws not relayed since `value_scale` can't convert their value
+    pub rejected_values: u64,
+    /// transactions whose encoded call data didn't decode back to their arguments
+    pub call_data_mismatches: u64,
+    /// acknowledgements of relayed deposits published
+    pub acknowledged: u64,
+    /// acknowledgements of relayed deposits that couldn't be signed or published
+    pub acknowledgement_failures: u64,
+    /// relay transactions mined without emitting the expected event
+    pub missing_events: u64,
+    /// relay transactions that reverted, other than `already_relayed`
+    pub reverted: u64,
+    /// relay transactions that reverted because the relay already took effect
+    pub already_relayed: u64,
+    /// withdraws already signed by this authority none of whose stored signatures recovers
+    /// to its account, see `verify_own_signatures`
+    pub signature_mismatches: u64,
+    /// repeated `CollectedSignatures` of withdraws relayed or in flight, or found executed. dropped
+    pub duplicates: u64,
+    /// deposits quarantined since their recipient contract rejects the transfer
+    pub quarantined_deposits: u64,
+    /// deposits sent by this authority on a chain that is home and foreign. not relayed
+    pub looped_deposits: u64,
+    /// deposits left to the authorities responsible for them with `cooperative_deposits`
+    pub cooperative_waits: u64,
+    /// deposits not executed after their grace period of `cooperative_deposits`. relayed
+    pub cooperative_fallbacks: u64,
+    /// deposits held back by `extra_confirmations` of `recipient_policies`
+    pub held_deposits: u64,
+    /// withdraws relayed to the payout address their recipient registered
+    pub payout_withdraws: u64,
+    /// logs of pending transactions returned by the node among the logs of mined blocks. dropped
+    pub pending_logs: u64,
+    /// logs with more data than `max_log_data`. dropped before they are decoded
+    pub oversized_logs: u64,
+    /// collected signatures whose foreign transaction emitted no matching `Withdraw`. not relayed
+    pub orphaned_withdraws: u64,
+    /// withdraws held back since the `HomeBridge` balance didn't cover them. counted at every check
+    pub unfunded_withdraws: u64,
+    /// deposits relayed above their usual gas price by `gas_price_tiers`
+    pub tiered_relays: u64,
+    /// deposits whose gas price of `gas_price_tiers` a ceiling lowered
+    pub capped_gas_prices: u64,
+    /// receipts of completed deposits recorded in `receipt_registry`
+    pub receipts_recorded: u64,
+    /// receipts of completed deposits not recorded since the registry had them already
+    pub receipts_already_recorded: u64,
+    /// gas limit times gas price of the recorded receipts in wei of home, what they cost at most.
+    /// receipts whose gas the node estimates aren't included
+    pub receipt_costs: U256,
+    /// value lost to rounding down with `value_scale`, in units of the source chain
+    pub dust: U256,
+    /// durations of the steps of the relayed batches
+    pub step_durations: StepDurations,
+}
+
+impl RelayCounts {
+    pub fn add(&mut self, other: RelayCounts) {
+        self.relayed += other.relayed;
+        self.failed += other.failed;
+        self.deferred += other.deferred;
+        self.required_signatures_changes += other.required_signatures_changes;
+        self.skipped_zero_value += other.skipped_zero_value;
+        self.skipped_executed += other.skipped_executed;
+        self.rejected_values += other.rejected_values;
+        self.call_data_mismatches += other.call_data_mismatches;
+        self.acknowledged += other.acknowledged;
+        self.acknowledgement_failures += other.acknowledgement_failures;
+        self.missing_events += other.missing_events;
+        self.reverted += other.reverted;
+        self.already_relayed += other.already_relayed;
+        self.signature_mismatches += other.signature_mismatches;
+        self.duplicates += other.duplicates;
+        self.quarantined_deposits += other.quarantined_deposits;
+        self.looped_deposits += other.looped_deposits;
+        self.cooperative_waits += other.cooperative_waits;
+        self.cooperative_fallbacks += other.cooperative_fallbacks;
+        self.held_deposits += other.held_deposits;
+        self.payout_withdraws += other.payout_withdraws;
+        self.pending_logs += other.pending_logs;
+        self.oversized_logs += other.oversized_logs;
+        self.orphaned_withdraws += other.orphaned_withdraws;
+        self.unfunded_withdraws += other.unfunded_withdraws;
+        self.tiered_relays += other.tiered_relays;
+        self.capped_gas_prices += other.capped_gas_prices;
+        self.receipts_recorded += other.receipts_recorded;
+        self.receipts_already_recorded += other.receipts_already_recorded;
+        self.receipt_costs = self.receipt_costs + other.receipt_costs;
+        self.dust = self.dust + other.dust;
+        self.step_durations.add(&other.step_durations);
+    }
+}
+
+/// Relay outcomes since the bridge started.
+#[derive(Debug)]
+pub struct Session {
+    started: Instant,
+    deposit_relay: RelayCounts,
+    withdraw_relay: RelayCounts,
+    withdraw_confirm: RelayCounts,
+    /// component whose error stopped the bridge
+    failed_component: Option<Component>,
+    /// components disabled by the config
+    disabled: Vec<Component>,
+    /// `SignRejected` events of all authorities by reason
+    sign_rejections: BTreeMap<String, u64>,
+    /// withdraws assigned to every authority by `CollectedSignatures`
+    relay_assignments: BTreeMap<Address, u64>,
+    /// latest failures of every component, recorded with their `failed` counts
+    recent_errors: RecentErrors,
+}
+
+impl Session {
+    pub fn new(started: Instant) -> Self {
+        Session {
+            started,
+            deposit_relay: RelayCounts::default(),
+            withdraw_relay: RelayCounts::default(),
+            withdraw_confirm: RelayCounts::default(),
+            failed_component: None,
+            disabled: Vec::new(),
+            sign_rejections: BTreeMap::new(),
+            relay_assignments: BTreeMap::new(),
+            recent_errors: RecentErrors::default(),
+        }
+    }
+
+    /// counts a `SignRejected` event with `reason`.
+    pub fn record_sign_rejection(&mut self, reason: &str) {
+        *self.sign_rejections.entry(reason.to_owned()).or_insert(0) += 1;
+    }
+
+    /// counts withdraws assigned to authorities, see `WithdrawRelay::take_assignments`.
+    pub fn record_relay_assignments(&mut self, assignments: BTreeMap<Address, u64>) {
+        for (authority, count) in assignments {
+            *self.relay_assignments.entry(authority).or_insert(0) += count;
+        }
+    }
+
+    /// records that `disabled` components don't run in this session.
+    pub fn set_disabled(&mut self, disabled: Vec<Component>) {
+        self.disabled = disabled;
+    }
+
+    pub fn counts(&self, component: Component) -> RelayCounts {
+        match component {
+            Component::DepositRelay => self.deposit_relay,
+            Component::WithdrawRelay => self.withdraw_relay,
+            Component::WithdrawConfirm => self.withdraw_confirm,
+        }
+    }
+
+    pub fn counts_mut(&mut self, component: Component) -> &mut RelayCounts {
+        match component {
+            Component::DepositRelay => &mut self.deposit_relay,
+            Component::WithdrawRelay => &mut self.withdraw_relay,
+            Component::WithdrawConfirm => &mut self.withdraw_confirm,
+        }
+    }
+
+    /// records that `component` failed with `err`.
+    pub fn record_failure(&mut self, component: Component, err: &Error) {
+        self.counts_mut(component).failed += 1;
+        self.recent_errors.record(component, err);
+    }
+
+    /// records that `err` of `component` stopped the bridge.
+    pub fn record_fatal(&mut self, component: Component, err: &Error) {
+        self.record_failure(component, err);
+        self.failed_component = Some(component);
+    }
+
+    /// failures of all components so far.
+    pub fn failures(&self) -> u64 {
+        self.deposit_relay.failed + self.withdraw_relay.failed + self.withdraw_confirm.failed
+    }
+
+    /// latest failures of every component, oldest first.
+    pub fn recent_errors(&self) -> BTreeMap<String, Vec<RecentError>> {
+        self.recent_errors.by_component()
+    }
+
+    /// summary of the session ending with `checked` blocks because of `error`.
+    pub fn report(&self, checked: &Database, error: Option<&Error>) -> ShutdownReport {
+        ShutdownReport {
+            uptime_secs: self.started.elapsed().as_secs(),
+            deposit_relay: self.deposit_relay,
+            withdraw_relay: self.withdraw_relay,
+            withdraw_confirm: self.withdraw_confirm,
+            checked_deposit_relay: checked.checked_deposit_relay,
+            checked_withdraw_relay: checked.checked_withdraw_relay,
+            checked_withdraw_confirm: checked.checked_withdraw_confirm,
+            error_chain: error
+                .map(|error| error.iter().map(|e| e.to_string()).collect())
+                .unwrap_or_default(),
+            failed_component: error
+                .and(self.failed_component)
+                .map(|component| format!("{:?}", component)),
+            disabled_components: self.disabled
+                .iter()
+                .map(|component| format!("{:?}", component))
+                .collect(),
+            sign_rejections: self.sign_rejections.clone(),
+            relay_assignments: self.relay_assignments.clone(),
+            timeouts: api::timeouts()
+                .into_iter()
+                .map(|(operation, count)| (operation.to_owned(), count))
+                .collect(),
+            not_an_authority: None,
+            recent_errors: self.recent_errors(),
+        }
+    }
+}
+
+/// Summary of a bridge session written when the bridge stops.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShutdownReport {
+    pub uptime_secs: u64,
+    pub deposit_relay: RelayCounts,
+    pub withdraw_relay: RelayCounts,
+    pub withdraw_confirm: RelayCounts,
+    pub checked_deposit_relay: u64,
+    pub checked_withdraw_relay: u64,
+    pub checked_withdraw_confirm: u64,
+    /// error that stopped the bridge followed by its causes. empty on normal shutdown
+    pub error_chain: Vec<String>,
+    /// component that caused the error. `None` if the error didn't come from a component
+    pub failed_component: Option<String>,
+    /// components disabled by `enable_deposits` or `enable_withdrawals`. their counts stay zero
+    pub disabled_components: Vec<String>,
+    /// `SignRejected` events of all authorities by reason. empty unless `watch_sign_rejections`
+    pub sign_rejections: BTreeMap<String, u64>,
+    /// withdraws assigned to every authority by `CollectedSignatures` seen by the withdraw relay,
+    /// including those of other authorities. empty if withdrawals are disabled
+    pub relay_assignments: BTreeMap<Address, u64>,
+    /// requests that timed out by json-rpc method, see `api::timeouts`
+    pub timeouts: BTreeMap<String, u64>,
+    /// why the account wasn't an authority of `ForeignBridge` when the bridge stopped.
+    /// `None` if it was one, as far as known
+    pub not_an_authority: Option<String>,
+    /// latest failures of every component that failed, see `RecentErrors`
+    pub recent_errors: BTreeMap<String, Vec<RecentError>>,
+}
+
+impl ShutdownReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("ShutdownReport always serializes; qed")
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = fs::File::create(path)
+            .chain_err(|| format!("Cannot create shutdown report {:?}", path))?;
+        serde_json::to_writer_pretty(file, self)
+            .chain_err(|| format!("Cannot write shutdown report {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use web3::types::Address;
+    use database::Database;
+    use error::{Error, ResultExt};
+    use bridge::Component;
+    use super::Session;
+
+    #[test]
+    fn test_report_relay_assignments() {
+        let mut session = Session::new(Instant::now());
+        let (a, b): (Address, Address) = (1.into(), 2.into());
+        session.record_relay_assignments(vec![(a, 2), (b, 1)].into_iter().collect());
+        session.record_relay_assignments(vec![(a, 1)].into_iter().collect());
+
+        let report = session.report(&Database::default(), None);
+        assert_eq!(Some(&3), report.relay_assignments.get(&a));
+        assert_eq!(Some(&1), report.relay_assignments.get(&b));
+        assert!(report.to_json().contains("relay_assignments"));
+    }
+
+    #[test]
+    fn test_report_of_failed_session() {
+        let mut session = Session::new(Instant::now());
+        session.counts_mut(Component::DepositRelay).relayed += 2;
+        session.counts_mut(Component::WithdrawRelay).deferred += 1;
+        let error: Error = Err::<(), Error>("connection lost".into())
+            .chain_err(|| "withdraw confirm failed")
+            .unwrap_err();
+        session.record_failure(Component::DepositRelay, &"timed out".into());
+        session.record_fatal(Component::WithdrawConfirm, &error);
+        session.record_sign_rejection("duplicate");
+        session.record_sign_rejection("duplicate");
+        session.record_sign_rejection("paused");
+
+        let checked = Database {
+            checked_deposit_relay: 10,
+            checked_withdraw_relay: 20,
+            checked_withdraw_confirm: 30,
+            ..Database::default()
+        };
+        let report = session.report(&checked, Some(&error));
+
+        assert_eq!(2, report.deposit_relay.relayed);
+        assert_eq!(1, report.withdraw_relay.deferred);
+        assert_eq!(1, report.withdraw_confirm.failed);
+        assert_eq!(1, report.deposit_relay.failed);
+        assert_eq!(1, report.recent_errors["DepositRelay"].len());
+        assert_eq!(report.error_chain, report.recent_errors["WithdrawConfirm"][0].error_chain);
+        assert!(!report.recent_errors.contains_key("WithdrawRelay"));
+        assert_eq!(30, report.checked_withdraw_confirm);
+        assert_eq!(
+            vec!["withdraw confirm failed".to_owned(), "connection lost".to_owned()],
+            report.error_chain
+        );
+        assert_eq!(Some("WithdrawConfirm".to_owned()), report.failed_component);
+        assert_eq!(Some(&2), report.sign_rejections.get("duplicate"));
+        assert_eq!(Some(&1), report.sign_rejections.get("paused"));
+        assert!(report.relay_assignments.is_empty());
+
+        let report = session.report(&checked, None);
+        assert!(report.error_chain.is_empty());
+        assert_eq!(None, report.failed_component);
+        assert!(report.disabled_components.is_empty());
+
+        session.set_disabled(vec![Component::WithdrawRelay, Component::WithdrawConfirm]);
+        assert_eq!(
+            vec!["WithdrawRelay".to_owned(), "WithdrawConfirm".to_owned()],
+            session.report(&checked, None).disabled_components
+        );
+    }
+}
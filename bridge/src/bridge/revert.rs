@@ -0,0 +1,298 @@
+use std::sync::Arc;
+use ethabi::{self, ParamType, Token};
+use futures::{Async, Future, Poll};
+use rustc_hex::FromHex;
+use web3::Transport;
+use web3::types::{Bytes, H256, Transaction};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use error::Error;
+use super::deploy_block::{chain, chain_name};
+
+/// selector of `Error(string)`, the abi encoded revert reason
+pub const REVERT_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// the reason of the output of a reverted call, `Error(string)` abi encoded.
+/// `None` for any other output, e.g. of a bare `revert()`.
+pub fn decode_revert_reason(output: &[u8]) -> Option<String> {
+    if !output.starts_with(&REVERT_SELECTOR) {
+        return None;
+    }
+    match ethabi::decode(&[ParamType::String], &output[4..]) {
+        Ok(mut tokens) => match tokens.pop() {
+            Some(Token::String(reason)) => Some(reason),
+            _ => None,
+        },
+        Err(_) => None,
+    }
+}
+
+/// the revert reason in the error of a call that reverted.
+/// geth puts it in the message, `execution reverted: reason`,
+/// parity the abi encoded output in the data, `Reverted 0x08c379a0...`.
+pub fn revert_reason_in_error(err: &Error) -> Option<String> {
+    const GETH_PREFIX: &str = "execution reverted: ";
+    let selector = "08c379a0";
+    err.iter().filter_map(|err| {
+        let message = err.to_string();
+        if let Some(start) = message.find(GETH_PREFIX) {
+            let reason = message[start + GETH_PREFIX.len()..]
+                .split(|c| c == '"' || c == '\n')
+                .next()
+                .unwrap_or("");
+            return Some(reason.trim().to_owned());
+        }
+        let start = message.find(selector)?;
+        let hex = message[start..]
+            .chars()
+            .take_while(|c| c.is_digit(16))
+            .collect::<String>();
+        decode_revert_reason(&hex.from_hex().ok()?)
+    }).next()
+}
+
+/// Reason class of a revert. the number of classes is bounded, unlike reasons,
+/// so they can be counted and matched on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevertKind {
+    /// the authority already signed the message or confirmed the deposit
+    AlreadySigned,
+    /// the deposit or withdraw was already executed
+    AlreadyProcessed,
+    /// the sender or the contract lacks the funds
+    InsufficientFunds,
+    /// the sender isn't an authority
+    NotAuthority,
+    /// the transaction used all of its gas
+    OutOfGas,
+    /// a reason none of the others matches
+    Other,
+    /// the transaction reverted without reason
+    Unknown,
+}
+
+impl RevertKind {
+    /// class of `reason`, matched case insensitively.
+    pub fn of(reason: Option<&str>) -> Self {
+        const KNOWN: &[(&str, RevertKind)] = &[
+            ("already signed", RevertKind::AlreadySigned),
+            ("already confirmed", RevertKind::AlreadySigned),
+            ("already submitted", RevertKind::AlreadySigned),
+            ("already processed", RevertKind::AlreadyProcessed),
+            ("already executed", RevertKind::AlreadyProcessed),
+            ("already relayed", RevertKind::AlreadyProcessed),
+            ("already withdrawn", RevertKind::AlreadyProcessed),
+            ("insufficient", RevertKind::InsufficientFunds),
+            ("not an authority", RevertKind::NotAuthority),
+            ("not authority", RevertKind::NotAuthority),
+            ("only authorit", RevertKind::NotAuthority),
+            ("out of gas", RevertKind::OutOfGas),
+        ];
+        let reason = match reason {
+            Some(reason) if !reason.is_empty() => reason.to_lowercase(),
+            _ => return RevertKind::Unknown,
+        };
+        KNOWN
+            .iter()
+            .find(|&&(known, _)| reason.contains(known))
+            .map_or(RevertKind::Other, |&(_, kind)| kind)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match *self {
+            RevertKind::AlreadySigned => "already_signed",
+            RevertKind::AlreadyProcessed => "already_processed",
+            RevertKind::InsufficientFunds => "insufficient_funds",
+            RevertKind::NotAuthority => "not_authority",
+            RevertKind::OutOfGas => "out_of_gas",
+            RevertKind::Other => "other",
+            RevertKind::Unknown => "unknown",
+        }
+    }
+
+    /// `true` if the relay reverted because it already took effect, e.g. by an earlier attempt.
+    pub fn already_done(&self) -> bool {
+        match *self {
+            RevertKind::AlreadySigned | RevertKind::AlreadyProcessed => true,
+            _ => false,
+        }
+    }
+}
+
+/// A reverted relay transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Revert {
+    /// `None` if the transaction reverted without reason or it couldn't be replayed
+    pub reason: Option<String>,
+    pub kind: RevertKind,
+}
+
+impl Revert {
+    pub fn new(reason: Option<String>) -> Self {
+        Revert {
+            kind: RevertKind::of(reason.as_ref().map(String::as_str)),
+            reason,
+        }
+    }
+
+    /// a relay that failed using all of its gas. it reverts without reason,
+    /// the gas used tells it apart.
+    pub fn out_of_gas() -> Self {
+        Revert {
+            reason: Some("out of gas".into()),
+            kind: RevertKind::OutOfGas,
+        }
+    }
+
+    /// the reason, or that there is none. used in logs and errors.
+    pub fn description(&self) -> String {
+        match self.reason {
+            Some(ref reason) => format!("{} ({})", reason, self.kind.label()),
+            None => format!("no reason ({})", self.kind.label()),
+        }
+    }
+}
+
+enum RevertReasonState<T: Transport> {
+    /// Fetching the reverted transaction.
+    FetchTransaction(Timeout<ApiCall<Option<Transaction>, T::Out>>),
+    /// Replaying it with `eth_call` at its block.
+    Replay(Timeout<ApiCall<Bytes, T::Out>>),
+}
+
+/// Future resolving to the revert reason of a transaction, by replaying it at its block.
+/// resolves to `None` if the transaction has no reason or can't be replayed.
+pub struct RevertReason<T: Transport> {
+    app: Arc<App<T>>,
+    on_home: bool,
+    transaction: H256,
+    block: u64,
+    state: RevertReasonState<T>,
+}
+
+/// replays `transaction` reverted in `block` on home if `on_home`, foreign otherwise.
+pub fn revert_reason<T: Transport>(
+    app: &Arc<App<T>>,
+    on_home: bool,
+    transaction: H256,
+    block: u64,
+) -> RevertReason<T> {
+    let state = {
+        let (transport, node) = chain(app, on_home);
+        RevertReasonState::FetchTransaction(api::timeout(
+            &app.timer,
+            api::transaction(transport, transaction),
+            chain_name(on_home),
+            node.request_timeout,
+        ).about(transaction))
+    };
+    RevertReason {
+        app: app.clone(),
+        on_home,
+        transaction,
+        block,
+        state,
+    }
+}
+
+impl<T: Transport> Future for RevertReason<T> {
+    type Item = Option<String>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                RevertReasonState::FetchTransaction(ref mut future) => {
+                    let transaction = match future.poll().map_err(Error::from) {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(Some(transaction))) => transaction,
+                        Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                        Err(err) => {
+                            warn!(
+                                target: "bridge::relay_events",
+                                "can't fetch reverted transaction {} to replay it: {}",
+                                self.transaction,
+                                err
+                            );
+                            return Ok(Async::Ready(None));
+                        }
+                    };
+                    let (transport, node) = chain(&self.app, self.on_home);
+                    RevertReasonState::Replay(api::timeout(
+                        &self.app.timer,
+                        api::replay_transaction(transport, &transaction, self.block),
+                        chain_name(self.on_home),
+                        node.request_timeout,
+                    ))
+                }
+                RevertReasonState::Replay(ref mut future) => {
+                    let reason = match future.poll().map_err(Error::from) {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(output)) => decode_revert_reason(&output.0),
+                        Err(err) => revert_reason_in_error(&err),
+                    };
+                    return Ok(Async::Ready(reason));
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::{self, Token};
+    use error::Error;
+    use super::{decode_revert_reason, revert_reason_in_error, Revert, RevertKind, REVERT_SELECTOR};
+
+    fn revert_output(reason: &str) -> Vec<u8> {
+        let mut output = REVERT_SELECTOR.to_vec();
+        output.extend(ethabi::encode(&[Token::String(reason.into())]));
+        output
+    }
+
+    #[test]
+    fn test_decode_revert_reason() {
+        assert_eq!(
+            Some("already signed".to_owned()),
+            decode_revert_reason(&revert_output("already signed"))
+        );
+        assert_eq!(None, decode_revert_reason(&[]));
+        assert_eq!(None, decode_revert_reason(&REVERT_SELECTOR));
+        assert_eq!(None, decode_revert_reason(&[0u8; 36]));
+    }
+
+    #[test]
+    fn test_revert_reason_in_error() {
+        let geth = Error::from("Rpc error: execution reverted: already processed");
+        assert_eq!(Some("already processed".to_owned()), revert_reason_in_error(&geth));
+
+        let parity = Error::with_chain(
+            Error::from(
+                "Rpc error: Error { code: ServerError(-32015), message: \"VM execution error.\", \
+                 data: Some(String(\"Reverted 0x08c379a0000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000066661696c65640000000000000000000000000000000000000000000000000000\")) }",
+            ),
+            "eth_call failed",
+        );
+        assert_eq!(Some("failed".to_owned()), revert_reason_in_error(&parity));
+
+        assert_eq!(None, revert_reason_in_error(&Error::from("Request timeout")));
+    }
+
+    #[test]
+    fn test_revert_kind() {
+        assert_eq!(RevertKind::AlreadySigned, RevertKind::of(Some("Already signed by authority")));
+        assert_eq!(RevertKind::AlreadyProcessed, RevertKind::of(Some("deposit already processed")));
+        assert_eq!(RevertKind::InsufficientFunds, RevertKind::of(Some("insufficient balance")));
+        assert_eq!(RevertKind::Other, RevertKind::of(Some("paused")));
+        assert_eq!(RevertKind::Unknown, RevertKind::of(Some("")));
+        assert_eq!(RevertKind::Unknown, RevertKind::of(None));
+
+        assert!(Revert::new(Some("already signed".into())).kind.already_done());
+        assert!(!Revert::new(Some("paused".into())).kind.already_done());
+        assert_eq!("paused (other)", Revert::new(Some("paused".into())).description());
+        assert_eq!("no reason (unknown)", Revert::new(None).description());
+        assert_eq!(Revert::out_of_gas(), Revert::new(Some("out of gas".into())));
+        assert!(!Revert::out_of_gas().kind.already_done());
+    }
+}
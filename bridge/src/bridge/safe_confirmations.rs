@@ -0,0 +1,380 @@
+use std::time::Duration;
+use ethabi::{self, ParamType, Token};
+use futures::{Async, Future, Poll};
+use futures::future::Join;
+use serde_json::{self, Value};
+use tiny_keccak::keccak256;
+use timer::Timer;
+use web3::Transport;
+use web3::types::{Address, Bytes, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::{ConsensusEngine, ForeignConsensusConfig};
+use error::Error;
+
+/// validators of the validator set contract of aura
+pub const GET_VALIDATORS_FUNCTION: &str = "getValidators()";
+
+/// bytes of the vanity prefix and the seal signature in the `extraData` of a clique block.
+const CLIQUE_EXTRA_DATA: usize = 32 + 65;
+
+/// bytes of an rlp encoded signature, the second seal field of an aura block.
+const AURA_SIGNATURE_SEAL: usize = 2 + 65;
+
+/// blocks of an aura or clique chain with `validators` that can still be reverted:
+/// until more than half of the validators built on a block.
+pub fn safe_depth(validators: usize) -> usize {
+    validators / 2 + 1
+}
+
+/// engine of a chain whose node runs `client_version` and whose latest `block` is given as json.
+/// aura blocks are sealed with a step and a signature, which only parity and openethereum
+/// return as `sealFields`. clique blocks have a difficulty of 1 or 2 and the signature of
+/// their signer in `extraData`. `None` if the block looks like neither.
+pub fn detect_engine(client_version: &str, block: &Value) -> Option<ConsensusEngine> {
+    let client = client_version.to_lowercase();
+    let field = |name: &str| block.get(name).cloned().unwrap_or(Value::Null);
+    let seal_fields = serde_json::from_value::<Vec<Bytes>>(field("sealFields")).unwrap_or_default();
+    if (client.starts_with("parity") || client.starts_with("openethereum"))
+        && seal_fields.len() == 2
+        && seal_fields[1].0.len() == AURA_SIGNATURE_SEAL
+    {
+        return Some(ConsensusEngine::Aura);
+    }
+    let difficulty = serde_json::from_value::<U256>(field("difficulty")).ok();
+    let extra_data = serde_json::from_value::<Bytes>(field("extraData")).unwrap_or_default();
+    let clique_difficulty = difficulty == Some(1.into()) || difficulty == Some(2.into());
+    if clique_difficulty && extra_data.0.len() >= CLIQUE_EXTRA_DATA {
+        return Some(ConsensusEngine::Clique);
+    }
+    None
+}
+
+/// payload of `GET_VALIDATORS_FUNCTION`.
+pub fn get_validators_payload() -> Bytes {
+    keccak256(GET_VALIDATORS_FUNCTION.as_bytes())[..4].to_vec().into()
+}
+
+/// validators in the `output` of `getValidators()`.
+fn decode_validators(output: &[u8]) -> Result<Vec<Address>, Error> {
+    let mut tokens = ethabi::decode(&[ParamType::Array(Box::new(ParamType::Address))], output)?;
+    match tokens.pop() {
+        Some(Token::Array(validators)) => Ok(validators
+            .into_iter()
+            .filter_map(|validator| validator.to_address())
+            .collect()),
+        _ => bail!("getValidators() of the validator set returned no addresses"),
+    }
+}
+
+/// Confirmations of foreign compared with the depth its consensus can still revert.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafeConfirmations {
+    pub engine: ConsensusEngine,
+    pub validators: usize,
+    /// `validators / 2 + 1`
+    pub safe: usize,
+    /// `foreign.required_confirmations`
+    pub configured: usize,
+    /// confirmations to wait for. `safe` if `enforce_safe_confirmations` raised `configured`
+    pub effective: usize,
+}
+
+impl SafeConfirmations {
+    pub fn new(
+        engine: ConsensusEngine,
+        validators: usize,
+        configured: usize,
+        enforce: bool,
+    ) -> Self {
+        let safe = safe_depth(validators);
+        SafeConfirmations {
+            engine,
+            validators,
+            safe,
+            configured,
+            effective: if enforce && configured < safe {
+                safe
+            } else {
+                configured
+            },
+        }
+    }
+}
+
+pub fn check_safe_confirmations<T: Transport + Clone>(app: &App<T>) -> CheckSafeConfirmations<T> {
+    let foreign = app.connections.foreign.clone();
+    let timeout = app.config.foreign.request_timeout;
+    let consensus = app.config.foreign_consensus.as_ref();
+    let state = match consensus {
+        Some(&ForeignConsensusConfig {
+            engine: Some(engine),
+            validator_set,
+            ..
+        }) => read_validators(&foreign, &app.timer, timeout, engine, validator_set),
+        Some(_) => {
+            let version = api::timeout(
+                &app.timer,
+                api::client_version(foreign.clone()),
+                "foreign",
+                timeout,
+            );
+            let block = api::timeout(
+                &app.timer,
+                api::raw_latest_block(foreign.clone()),
+                "foreign",
+                timeout,
+            );
+            CheckState::Detect {
+                future: version.join(block),
+            }
+        }
+        None => CheckState::Unconfigured,
+    };
+    CheckSafeConfirmations {
+        state,
+        foreign,
+        timer: app.timer.clone(),
+        timeout,
+        validator_set: consensus.and_then(|consensus| consensus.validator_set),
+        enforce: consensus.map_or(false, |consensus| consensus.enforce_safe_confirmations),
+        configured: app.config.foreign.required_confirmations,
+    }
+}
+
+/// reads the validators of `engine`, of the contract `validator_set` with aura.
+fn read_validators<T: Transport + Clone>(
+    foreign: &T,
+    timer: &Timer,
+    timeout: Duration,
+    engine: ConsensusEngine,
+    validator_set: Option<Address>,
+) -> CheckState<T> {
+    match (engine, validator_set) {
+        (ConsensusEngine::Aura, Some(validator_set)) => CheckState::AuraValidators {
+            future: api::timeout(
+                timer,
+                api::call(foreign.clone(), validator_set, get_validators_payload()),
+                "foreign",
+                timeout,
+            ),
+        },
+        (ConsensusEngine::Aura, None) => CheckState::NoValidatorSet,
+        (ConsensusEngine::Clique, _) => CheckState::CliqueSigners {
+            future: api::timeout(
+                timer,
+                api::clique_signers(foreign.clone()),
+                "foreign",
+                timeout,
+            ),
+        },
+    }
+}
+
+enum CheckState<T: Transport> {
+    /// `foreign_consensus` isn't configured
+    Unconfigured,
+    /// detecting the engine from the client and the latest block of foreign
+    Detect {
+        future: Join<Timeout<ApiCall<String, T::Out>>, Timeout<ApiCall<Option<Value>, T::Out>>>,
+    },
+    /// detected aura without a validator set to read the validators of
+    NoValidatorSet,
+    /// reading `getValidators()` of the validator set contract
+    AuraValidators {
+        future: Timeout<ApiCall<Bytes, T::Out>>,
+    },
+    /// reading the signers of clique
+    CliqueSigners {
+        future: Timeout<ApiCall<Vec<Address>, T::Out>>,
+    },
+}
+
+/// Checks `foreign.required_confirmations` against the safe depth of the aura or clique
+/// consensus of foreign, see `ForeignConsensusConfig`. warns if they're below it and raises them
+/// with `enforce_safe_confirmations`. resolves to the confirmations, `None` if
+/// `foreign_consensus` isn't configured or foreign runs neither engine.
+pub struct CheckSafeConfirmations<T: Transport> {
+    state: CheckState<T>,
+    foreign: T,
+    timer: Timer,
+    timeout: Duration,
+    validator_set: Option<Address>,
+    enforce: bool,
+    configured: usize,
+}
+
+/// logs how the configured `confirmations` compare with the safe depth.
+fn log_confirmations(confirmations: &SafeConfirmations) {
+    let (engine, validators) = (confirmations.engine, confirmations.validators);
+    if confirmations.configured >= confirmations.safe {
+        info!(
+            target: "bridge",
+            "foreign.required_confirmations {} cover the safe depth {} of {:?} with {} validators",
+            confirmations.configured,
+            confirmations.safe,
+            engine,
+            validators
+        );
+    } else if confirmations.effective > confirmations.configured {
+        warn!(
+            target: "bridge",
+            "foreign.required_confirmations {} are below the safe depth {} of {:?} with {} validators. waiting for {} confirmations (enforce_safe_confirmations)",
+            confirmations.configured,
+            confirmations.safe,
+            engine,
+            validators,
+            confirmations.effective
+        );
+    } else {
+        warn!(
+            target: "bridge",
+            "foreign.required_confirmations {} are below the safe depth {} of {:?} with {} validators. withdraws are signed on blocks that can still be reverted. raise them or set foreign_consensus.enforce_safe_confirmations",
+            confirmations.configured,
+            confirmations.safe,
+            engine,
+            validators
+        );
+    }
+}
+
+impl<T: Transport + Clone> Future for CheckSafeConfirmations<T> {
+    type Item = Option<SafeConfirmations>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                CheckState::Unconfigured => return Ok(Async::Ready(None)),
+                CheckState::Detect { ref mut future } => {
+                    let (version, block) = try_ready!(future.poll());
+                    let engine = match block {
+                        Some(ref block) => detect_engine(&version, block),
+                        None => None,
+                    };
+                    match engine {
+                        Some(engine) => {
+                            info!(target: "bridge", "foreign ({}) runs {:?}", version, engine);
+                            read_validators(
+                                &self.foreign,
+                                &self.timer,
+                                self.timeout,
+                                engine,
+                                self.validator_set,
+                            )
+                        }
+                        None => {
+                            info!(
+                                target: "bridge",
+                                "foreign ({}) runs neither aura nor clique. not checking its confirmations",
+                                version
+                            );
+                            return Ok(Async::Ready(None));
+                        }
+                    }
+                }
+                CheckState::NoValidatorSet => bail!(
+                    "foreign runs aura. foreign_consensus.validator_set is required to read its validators"
+                ),
+                CheckState::AuraValidators { ref mut future } => {
+                    let output = try_ready!(future.poll());
+                    let validators = decode_validators(&output.0)?;
+                    if validators.is_empty() {
+                        bail!("validator set of foreign has no validators");
+                    }
+                    let confirmations = SafeConfirmations::new(
+                        ConsensusEngine::Aura,
+                        validators.len(),
+                        self.configured,
+                        self.enforce,
+                    );
+                    log_confirmations(&confirmations);
+                    return Ok(Async::Ready(Some(confirmations)));
+                }
+                CheckState::CliqueSigners { ref mut future } => {
+                    let signers = try_ready!(future.poll());
+                    if signers.is_empty() {
+                        bail!("clique of foreign has no signers");
+                    }
+                    let confirmations = SafeConfirmations::new(
+                        ConsensusEngine::Clique,
+                        signers.len(),
+                        self.configured,
+                        self.enforce,
+                    );
+                    log_confirmations(&confirmations);
+                    return Ok(Async::Ready(Some(confirmations)));
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::{self, Token};
+    use config::ConsensusEngine;
+    use super::{decode_validators, detect_engine, safe_depth, SafeConfirmations};
+
+    #[test]
+    fn test_safe_depth() {
+        assert_eq!(1, safe_depth(1));
+        assert_eq!(2, safe_depth(2));
+        assert_eq!(3, safe_depth(5));
+        assert_eq!(16, safe_depth(31));
+    }
+
+    #[test]
+    fn test_safe_confirmations() {
+        let warned = SafeConfirmations::new(ConsensusEngine::Aura, 31, 12, false);
+        assert_eq!(16, warned.safe);
+        assert_eq!(12, warned.effective);
+        let enforced = SafeConfirmations::new(ConsensusEngine::Aura, 31, 12, true);
+        assert_eq!(16, enforced.effective);
+        // confirmations above the safe depth are never lowered
+        let deeper = SafeConfirmations::new(ConsensusEngine::Clique, 3, 12, true);
+        assert_eq!(2, deeper.safe);
+        assert_eq!(12, deeper.effective);
+    }
+
+    #[test]
+    fn test_detect_engine() {
+        let signature = format!("0xb841{}", "11".repeat(65));
+        let aura = json!({
+            "sealFields": ["0x84175a8a51", signature],
+            "difficulty": "0xfffffffffffffffffffffffffffffffe",
+            "extraData": "0xd5830108048650617269747986312e32322e31826c69",
+        });
+        assert_eq!(Some(ConsensusEngine::Aura), detect_engine("Parity//v1.11.8", &aura));
+        assert_eq!(
+            Some(ConsensusEngine::Aura),
+            detect_engine("OpenEthereum//v3.3.5-stable", &aura)
+        );
+        // ethash blocks of parity are sealed with a mix hash and an 8 byte nonce
+        let ethash = json!({
+            "sealFields": [format!("0xa0{}", "22".repeat(32)), "0x880102030405060708"],
+            "difficulty": "0x8d1a2b3c4d",
+            "extraData": "0x",
+        });
+        assert_eq!(None, detect_engine("Parity-Ethereum//v2.7.2", &ethash));
+
+        let clique = json!({
+            "difficulty": "0x2",
+            "extraData": format!("0x{}", "00".repeat(32 + 65)),
+        });
+        assert_eq!(Some(ConsensusEngine::Clique), detect_engine("Geth/v1.10.26", &clique));
+        let short = json!({ "difficulty": "0x1", "extraData": "0x00" });
+        assert_eq!(None, detect_engine("Geth/v1.10.26", &short));
+    }
+
+    #[test]
+    fn test_decode_validators() {
+        let output = ethabi::encode(&[Token::Array(vec![
+            Token::Address(1.into()),
+            Token::Address(2.into()),
+        ])]);
+        assert_eq!(vec![1.into(), 2.into()], decode_validators(&output).unwrap());
+        assert!(decode_validators(&[]).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use futures::{Future, Poll};
+use futures::future::Join;
+use web3::Transport;
+use web3::types::{Address, Block, H256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::Config;
+use error::{Error, ErrorKind};
+
+pub fn check_same_chain<T: Transport>(app: &App<T>) -> CheckSameChain<T> {
+    let home = api::timeout(
+        &app.timer,
+        api::block_by_number(&app.connections.home, 0),
+        "home",
+        app.config.home.request_timeout,
+    );
+    let foreign = api::timeout(
+        &app.timer,
+        api::block_by_number(&app.connections.foreign, 0),
+        "foreign",
+        app.config.foreign.request_timeout,
+    );
+    CheckSameChain {
+        future: home.join(foreign),
+        allow_same_chain: app.config.allow_same_chain,
+    }
+}
+
+/// Checks that home and foreign are different chains by comparing their genesis blocks.
+/// relaying deposits back into the chain they came from can loop until gas runs out,
+/// so the same chain is refused unless `allow_same_chain` is set.
+/// resolves to `true` if both are the same chain.
+pub struct CheckSameChain<T: Transport> {
+    future: Join<Timeout<ApiCall<Block<H256>, T::Out>>, Timeout<ApiCall<Block<H256>, T::Out>>>,
+    allow_same_chain: bool,
+}
+
+impl<T: Transport> Future for CheckSameChain<T> {
+    type Item = bool;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (home, foreign) = try_ready!(self.future.poll());
+        let same = same_genesis(home.hash, foreign.hash, self.allow_same_chain)?;
+        if same {
+            warn!(
+                target: "bridge",
+                "home and foreign are the same chain (allow_same_chain). deposits sent by this authority are not relayed"
+            );
+        }
+        Ok(same.into())
+    }
+}
+
+/// `true` if the genesis blocks `home` and `foreign` are the same block,
+/// an error if they are and `allow_same_chain` isn't set.
+fn same_genesis(
+    home: Option<H256>,
+    foreign: Option<H256>,
+    allow_same_chain: bool,
+) -> Result<bool, Error> {
+    match (home, foreign) {
+        (Some(home), Some(foreign)) if home == foreign => if allow_same_chain {
+            Ok(true)
+        } else {
+            Err(ErrorKind::SameChain(home).into())
+        },
+        _ => Ok(false),
+    }
+}
+
+/// `true` if the home transaction of a deposit was sent by `sender`, an account of this
+/// authority. relaying it could be the bridge relaying its own relays.
+pub fn sent_by_authority(config: &Config, sender: Address) -> bool {
+    sender == config.home.account || sender == config.foreign.account
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::H256;
+    use error::ErrorKind;
+    use super::same_genesis;
+
+    #[test]
+    fn test_same_genesis() {
+        let (a, b) = (Some(H256::from(1)), Some(H256::from(2)));
+        assert!(!same_genesis(a, b, false).unwrap());
+        // pending genesis blocks have no hash
+        assert!(!same_genesis(None, None, false).unwrap());
+        assert!(same_genesis(a, a, true).unwrap());
+        match *same_genesis(a, a, false).unwrap_err().kind() {
+            ErrorKind::SameChain(genesis) => assert_eq!(H256::from(1), genesis),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::{Async, Future, Poll, Stream};
+use futures::stream::FuturesOrdered;
+use serde_json::Value;
+use timer::Sleep;
+use tracing::Span;
+use tracing_futures::{Instrument, Instrumented};
+use web3::Transport;
+use web3::types::{Address, H256, TransactionReceipt, TransactionRequest};
+use api::{self, ApiCall, NodeFailure, Timeout};
+use app::App;
+use config::{Config, Signer, TxpoolClient};
+use error::{Error, ErrorKind};
+use super::Component;
+use super::self_test::Probe;
+
+/// Transactions of a component waiting to be sent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueueStatus {
+    /// transactions not yet sent
+    pub depth: usize,
+    /// how long the oldest transaction has been waiting
+    pub age: Duration,
+}
+
+/// number of transactions `component` may have in flight on foreign.
+/// unlimited if relay scheduling is disabled.
+pub fn foreign_quota<T: Transport>(app: &App<T>, component: Component) -> usize {
+    match app.tunables.get().relay_scheduling {
+        None => usize::max_value(),
+        Some(ref scheduling) => match component {
+            Component::DepositRelay => scheduling.deposit_relay_quota(),
+            Component::WithdrawConfirm => scheduling.withdraw_confirm_quota(),
+            Component::WithdrawRelay => usize::max_value(),
+        },
+    }
+}
+
+enum PoolLimiterState<T: Transport> {
+    /// `txpool` isn't configured.
+    Unlimited,
+    /// Fetching the pending transactions of foreign.
+    FetchPool(Timeout<ApiCall<Value, T::Out>>),
+    /// Waiting for the full pool to drain.
+    Wait(Sleep),
+    /// `allowance` transactions may be sent before the pool is checked again.
+    Ready(usize),
+}
+
+/// rpc method `--self-test` probes for `txpool` on foreign.
+pub fn self_test_probes(config: &Config) -> Vec<Probe> {
+    config
+        .txpool
+        .iter()
+        .map(|txpool| {
+            Probe::call("foreign", txpool.client.method(), vec![], "txpool limit")
+                .enabled_by("txpool")
+        })
+        .collect()
+}
+
+fn fetch_pool<T: Transport>(app: &App<T>) -> PoolLimiterState<T> {
+    match app.config.txpool {
+        None => PoolLimiterState::Unlimited,
+        Some(ref txpool) => PoolLimiterState::FetchPool(api::timeout(
+            &app.timer,
+            api::txpool(&app.connections.foreign, txpool.client),
+            "foreign",
+            app.config.foreign.request_timeout,
+        )),
+    }
+}
+
+/// Limits the transactions sent to foreign to the free `txpool.capacity()`
+/// of its transaction pool.
+pub struct PoolLimiter<T: Transport> {
+    app: Arc<App<T>>,
+    state: PoolLimiterState<T>,
+}
+
+impl<T: Transport> PoolLimiter<T> {
+    pub fn new(app: Arc<App<T>>) -> Self {
+        let state = fetch_pool(&app);
+        PoolLimiter { app, state }
+    }
+
+    /// number of transactions that may be sent now.
+    /// not ready while the pool is checked or full.
+    pub fn poll_allowance(&mut self) -> Poll<usize, Error> {
+        loop {
+            let next_state = match self.state {
+                PoolLimiterState::Unlimited => return Ok(Async::Ready(usize::max_value())),
+                PoolLimiterState::Ready(0) => fetch_pool(&self.app),
+                PoolLimiterState::Ready(allowance) => return Ok(Async::Ready(allowance)),
+                PoolLimiterState::FetchPool(ref mut future) => {
+                    let result = try_ready!(future.poll());
+                    let txpool = self.app
+                        .config
+                        .txpool
+                        .as_ref()
+                        .expect("the pool is only fetched with txpool; qed");
+                    let pending =
+                        api::pending_count(txpool.client, &result, self.app.config.foreign.account)?;
+                    let capacity = txpool.capacity();
+                    if pending >= capacity {
+                        info!(
+                            "foreign transaction pool is full ({} of {} pending), waiting {} seconds",
+                            pending,
+                            capacity,
+                            txpool.check_interval.as_secs()
+                        );
+                        PoolLimiterState::Wait(self.app.timer.sleep(txpool.check_interval))
+                    } else {
+                        debug!("foreign transaction pool: {} of {} pending", pending, capacity);
+                        PoolLimiterState::Ready(capacity - pending)
+                    }
+                }
+                PoolLimiterState::Wait(ref mut future) => {
+                    try_ready!(future.poll());
+                    fetch_pool(&self.app)
+                }
+            };
+            self.state = next_state;
+        }
+    }
+
+    /// records that `count` transactions of the allowance were sent.
+    pub fn sent(&mut self, count: usize) {
+        if let PoolLimiterState::Ready(ref mut allowance) = self.state {
+            *allowance = allowance.saturating_sub(count);
+        }
+    }
+}
+
+pub fn send_foreign_transactions<T: Transport>(
+    app: Arc<App<T>>,
+    requests: Vec<TransactionRequest>,
+    quota: usize,
+) -> SendTransactions<T> {
+    let keys = vec![None; requests.len()];
+    send_ordered_foreign_transactions(app, requests, keys, quota)
+}
+
+/// like `send_foreign_transactions`, but a request with an ordering key in `keys`, at the same
+/// index, is only sent once the transaction of the previous request with the same key is mined.
+/// requests with other keys or none are sent meanwhile, see `OrderedRelaysConfig`.
+pub fn send_ordered_foreign_transactions<T: Transport>(
+    app: Arc<App<T>>,
+    requests: Vec<TransactionRequest>,
+    keys: Vec<Option<Address>>,
+    quota: usize,
+) -> SendTransactions<T> {
+    assert_eq!(requests.len(), keys.len(), "every request has a key; qed");
+    SendTransactions {
+        pool: PoolLimiter::new(app.clone()),
+        queued_since: app.timer.now(),
+        app,
+        hashes: vec![None; requests.len()],
+        queued: requests
+            .into_iter()
+            .zip(keys)
+            .enumerate()
+            .map(|(index, (request, key))| QueuedTransaction {
+                index,
+                request,
+                key,
+                span: Span::none(),
+            })
+            .collect(),
+        in_flight: FuturesOrdered::new(),
+        in_flight_keys: VecDeque::new(),
+        quota,
+        mining: Vec::new(),
+    }
+}
+
+enum SendState<T: Transport> {
+    Send(Timeout<ApiCall<H256, T::Out>>),
+    /// the send failed with `err` without telling whether foreign took the transaction.
+    /// looking for it in the pending transactions
+    Lookup {
+        future: Timeout<ApiCall<Value, T::Out>>,
+        err: Option<Error>,
+    },
+}
+
+/// Transaction sent to foreign. resolves to its hash.
+///
+/// a send failing with `api::NodeFailure::AmbiguousSend` that may have reached the node is
+/// looked up in the pending transactions of foreign first. it resolves to the hash found there,
+/// otherwise fails with `ErrorKind::AmbiguousSend` instead of failing like a send that can be
+/// repeated. a transaction mined before it's looked up isn't found.
+struct SendTransaction<T: Transport> {
+    app: Arc<App<T>>,
+    request: TransactionRequest,
+    state: SendState<T>,
+}
+
+impl<T: Transport> SendTransaction<T> {
+    fn new(app: Arc<App<T>>, request: TransactionRequest) -> Self {
+        let future = api::timeout(
+            &app.timer,
+            api::node_send_transaction(
+                &app.connections.foreign,
+                &app.config.foreign,
+                request.clone(),
+            ),
+            "foreign",
+            app.config.foreign.request_timeout,
+        );
+        SendTransaction {
+            app,
+            request,
+            state: SendState::Send(future),
+        }
+    }
+}
+
+/// the send of `request` failed with `err` although foreign may have taken the transaction
+fn ambiguous_send<T: Transport>(app: &App<T>, request: &TransactionRequest, err: Error) -> Error {
+    Error::with_chain(err, ErrorKind::AmbiguousSend(send_method(app), request.to))
+}
+
+/// fetches the pending transactions of foreign. only parity lists them
+fn lookup_pending<T: Transport>(app: &App<T>) -> Timeout<ApiCall<Value, T::Out>> {
+    api::timeout(
+        &app.timer,
+        api::txpool(&app.connections.foreign, TxpoolClient::Parity),
+        "foreign",
+        app.config.foreign.request_timeout,
+    )
+}
+
+/// json-rpc method transactions are sent to foreign with
+fn send_method<T: Transport>(app: &App<T>) -> &'static str {
+    match app.config.foreign.signer {
+        Signer::Personal => "personal_sendTransaction",
+        Signer::Node | Signer::Remote => "eth_sendTransaction",
+    }
+}
+
+impl<T: Transport> Future for SendTransaction<T> {
+    type Item = H256;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                SendState::Send(ref mut future) => match future.poll() {
+                    Ok(result) => return Ok(result),
+                    Err(err) => match api::node_failure(&err, true) {
+                        Some(NodeFailure::AmbiguousSend { maybe_sent: true }) => {
+                            warn!(
+                                "sending transaction to {:?} failed without telling whether \
+                                 foreign took it: {}. looking for it in the pending transactions",
+                                self.request.to, err
+                            );
+                            SendState::Lookup {
+                                future: lookup_pending(&self.app),
+                                err: Some(err),
+                            }
+                        }
+                        _ => return Err(err),
+                    },
+                },
+                SendState::Lookup {
+                    ref mut future,
+                    ref mut err,
+                } => {
+                    let result = match future.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(result)) => Some(result),
+                        Err(lookup_err) => {
+                            debug!(
+                                "cannot look up the pending transactions of foreign: {}",
+                                lookup_err
+                            );
+                            None
+                        }
+                    };
+                    let hash = result
+                        .as_ref()
+                        .and_then(|result| api::pending_transaction(result, &self.request));
+                    let err = err.take().expect("the error is only taken once; qed");
+                    return match hash {
+                        Some(hash) => {
+                            info!(
+                                "foreign took transaction {:?} to {:?} despite the failed send",
+                                hash, self.request.to
+                            );
+                            Ok(Async::Ready(hash))
+                        }
+                        None => Err(ambiguous_send(&self.app, &self.request, err)),
+                    };
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+struct QueuedTransaction {
+    /// index of the request
+    index: usize,
+    request: TransactionRequest,
+    /// ordering key of the request
+    key: Option<Address>,
+    /// span the send of the request is nested in
+    span: Span,
+}
+
+enum MinedState<T: Transport> {
+    FetchReceipt(Timeout<ApiCall<Option<TransactionReceipt>, T::Out>>),
+    /// no receipt yet. fetching it again after `ordered_relays.receipt_interval`
+    Wait(Sleep),
+}
+
+/// Sent transaction holding back the later requests with its ordering key until it's mined.
+struct MinedTransaction<T: Transport> {
+    key: Address,
+    hash: H256,
+    state: MinedState<T>,
+}
+
+fn fetch_receipt<T: Transport>(app: &App<T>, hash: H256) -> MinedState<T> {
+    MinedState::FetchReceipt(api::timeout(
+        &app.timer,
+        api::transaction_receipt(&app.connections.foreign, hash),
+        "foreign",
+        app.config.foreign.request_timeout,
+    ))
+}
+
+impl<T: Transport> MinedTransaction<T> {
+    /// ready once the transaction is mined.
+    fn poll(&mut self, app: &App<T>) -> Poll<(), Error> {
+        loop {
+            let next_state = match self.state {
+                MinedState::FetchReceipt(ref mut future) => match try_ready!(future.poll()) {
+                    Some(_) => return Ok(Async::Ready(())),
+                    None => {
+                        let interval = app.config
+                            .ordered_relays
+                            .as_ref()
+                            .map_or(Duration::from_secs(1), |ordered| ordered.receipt_interval);
+                        MinedState::Wait(app.timer.sleep(interval))
+                    }
+                },
+                MinedState::Wait(ref mut future) => {
+                    try_ready!(future.poll());
+                    fetch_receipt(app, self.hash)
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+/// Sends transactions to foreign with at most `quota` of them in flight
+/// and no more than the transaction pool of foreign takes.
+/// resolves to the transaction hashes in the order of the requests.
+///
+/// a request whose ordering key is held back by an earlier transaction waits in the queue
+/// without taking a place of `quota`, so only the sends of the other requests take them and
+/// the queue always drains. resolves once the transactions with ordering keys are mined, so the
+/// requests sent next can't overtake them either.
+pub struct SendTransactions<T: Transport> {
+    app: Arc<App<T>>,
+    pool: PoolLimiter<T>,
+    queued: VecDeque<QueuedTransaction>,
+    queued_since: Instant,
+    in_flight: FuturesOrdered<Instrumented<SendTransaction<T>>>,
+    /// indexes and ordering keys of the requests in flight, in the order they were sent
+    in_flight_keys: VecDeque<(usize, Option<Address>)>,
+    quota: usize,
+    /// sent transactions with ordering keys that aren't mined yet
+    mining: Vec<MinedTransaction<T>>,
+    /// hashes of the sent transactions by the index of their request
+    hashes: Vec<Option<H256>>,
+}
+
+impl<T: Transport> SendTransactions<T> {
+    /// nests the send of every request in the span at its index in `spans`, e.g. of its relay.
+    pub fn in_spans(mut self, spans: Vec<Span>) -> Self {
+        assert_eq!(self.hashes.len(), spans.len(), "every request has a span; qed");
+        for (queued, span) in self.queued.iter_mut().zip(spans) {
+            queued.span = span;
+        }
+        self
+    }
+
+    pub fn queue_status(&self, now: Instant) -> QueueStatus {
+        QueueStatus {
+            depth: self.queued.len(),
+            age: if self.queued.is_empty() {
+                Duration::from_secs(0)
+            } else {
+                now.duration_since(self.queued_since)
+            },
+        }
+    }
+}
+
+impl<T: Transport> SendTransactions<T> {
+    /// polls the transactions holding back ordering keys. `true` if any of them was mined.
+    fn poll_mining(&mut self) -> Result<bool, Error> {
+        let mut mined = false;
+        let mut index = 0;
+        while index < self.mining.len() {
+            if self.mining[index].poll(&self.app)?.is_ready() {
+                let transaction = self.mining.remove(index);
+                debug!(
+                    "transaction {} to {:?} mined, releasing the next one",
+                    transaction.hash, transaction.key
+                );
+                mined = true;
+            } else {
+                index += 1;
+            }
+        }
+        Ok(mined)
+    }
+
+    /// sends up to `allowance` queued requests whose ordering key isn't held back,
+    /// in the order of the queue. returns how many were sent.
+    fn send_released(&mut self, allowance: usize) -> usize {
+        let mut held = self.in_flight_keys
+            .iter()
+            .filter_map(|&(_, key)| key)
+            .chain(self.mining.iter().map(|transaction| transaction.key))
+            .collect::<Vec<_>>();
+        let mut sent = 0;
+        let mut position = 0;
+        while self.in_flight_keys.len() < self.quota && sent < allowance
+            && position < self.queued.len()
+        {
+            if let Some(key) = self.queued[position].key {
+                if held.contains(&key) {
+                    position += 1;
+                    continue;
+                }
+                held.push(key);
+            }
+            let queued = self.queued
+                .remove(position)
+                .expect("position is in the queue; qed");
+            let send = SendTransaction::new(self.app.clone(), queued.request);
+            self.in_flight.push(send.instrument(queued.span));
+            self.in_flight_keys.push_back((queued.index, queued.key));
+            sent += 1;
+        }
+        sent
+    }
+}
+
+impl<T: Transport> Future for SendTransactions<T> {
+    type Item = Vec<H256>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let mined = self.poll_mining()?;
+            let allowance = if self.queued.is_empty() {
+                0
+            } else {
+                match self.pool.poll_allowance()? {
+                    Async::Ready(allowance) => allowance,
+                    Async::NotReady => 0,
+                }
+            };
+            let sent = self.send_released(allowance);
+            self.pool.sent(sent);
+
+            match try_ready!(self.in_flight.poll()) {
+                Some(hash) => {
+                    let (index, key) = self.in_flight_keys
+                        .pop_front()
+                        .expect("every transaction in flight has a key; qed");
+                    self.hashes[index] = Some(hash);
+                    if let Some(key) = key {
+                        self.mining.push(MinedTransaction {
+                            key,
+                            hash,
+                            state: fetch_receipt(&self.app, hash),
+                        });
+                    }
+                }
+                // a mined transaction released the next request of its key
+                None if mined => {}
+                // waiting for the pool or for held back requests
+                None if !self.queued.is_empty() || !self.mining.is_empty() => {
+                    return Ok(Async::NotReady)
+                }
+                None => {
+                    let hashes = mem::replace(&mut self.hashes, Vec::new())
+                        .into_iter()
+                        .map(|hash| hash.expect("every request was sent; qed"))
+                        .collect();
+                    return Ok(Async::Ready(hashes));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,472 @@
+//! Probes of the rpc methods the configured features call, run by `--self-test`.
+//!
+//! every feature defines the probes of the methods it calls next to its code, see
+//! `self_test_probes` of its module. `probes` collects those of the enabled features.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::{Async, Future, Poll};
+use futures::future::{join_all, JoinAll};
+use serde_json::{self, Value};
+use tiny_keccak::keccak256;
+use web3;
+use web3::Transport;
+use web3::types::{Address, Bytes, H256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use config::{Config, Node, Signer};
+use database::Database;
+use error::{Error, ErrorKind};
+use super::contract_recipients;
+use super::finality_monitor;
+use super::node_gas_price;
+use super::schedule;
+
+/// How the answer to a probe is judged.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeKind {
+    /// the call must succeed
+    Call,
+    /// the call has no params, e.g. of a method sending a transaction. any rpc error but
+    /// the method not being found passes
+    Exists,
+}
+
+/// Rpc method a feature calls, probed with harmless params.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probe {
+    pub chain: &'static str,
+    pub method: String,
+    pub params: Vec<Value>,
+    pub kind: ProbeKind,
+    /// feature calling the method
+    pub feature: &'static str,
+    /// config option enabling the feature. `None` if the bridge always calls the method
+    pub option: Option<&'static str>,
+    /// `false` if the feature falls back to something else without the method
+    pub required: bool,
+}
+
+impl Probe {
+    /// probe of `method` with `params` on `chain`, which must succeed.
+    pub fn call<S: Into<String>>(
+        chain: &'static str,
+        method: S,
+        params: Vec<Value>,
+        feature: &'static str,
+    ) -> Self {
+        Probe {
+            chain,
+            method: method.into(),
+            params,
+            kind: ProbeKind::Call,
+            feature,
+            option: None,
+            required: true,
+        }
+    }
+
+    /// probe of whether `chain` knows `method`, called without params.
+    pub fn exists(chain: &'static str, method: &str, feature: &'static str) -> Self {
+        Probe {
+            kind: ProbeKind::Exists,
+            ..Probe::call(chain, method, Vec::new(), feature)
+        }
+    }
+
+    /// the probe of a feature enabled by config option `option`.
+    pub fn enabled_by(mut self, option: &'static str) -> Self {
+        self.option = Some(option);
+        self
+    }
+
+    /// the probe of a method the feature can do without.
+    pub fn fallback(mut self) -> Self {
+        self.required = false;
+        self
+    }
+
+    /// why `result` fails the probe. `None` if it passes.
+    fn failure(&self, result: &Result<Value, Error>) -> Option<String> {
+        let err = match *result {
+            Ok(_) => return None,
+            Err(ref err) => err,
+        };
+        let rejected = match *err.kind() {
+            ErrorKind::Web3(web3::Error::Rpc(_)) => true,
+            _ => false,
+        };
+        if self.kind == ProbeKind::Exists && rejected && !api::is_method_not_found(err) {
+            return None;
+        }
+        Some(err.to_string())
+    }
+}
+
+/// Outcome of a probe.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProbeResult {
+    pub chain: &'static str,
+    pub method: String,
+    pub feature: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub option: Option<&'static str>,
+    pub required: bool,
+    pub passed: bool,
+    pub latency_ms: u64,
+    /// error the probe failed with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ProbeResult {
+    fn of(probe: &Probe, result: &Result<Value, Error>, latency: Duration) -> Self {
+        let error = probe.failure(result);
+        ProbeResult {
+            chain: probe.chain,
+            method: probe.method.clone(),
+            feature: probe.feature,
+            option: probe.option,
+            required: probe.required,
+            passed: error.is_none(),
+            latency_ms: latency.as_secs() * 1000 + u64::from(latency.subsec_nanos() / 1_000_000),
+            error,
+        }
+    }
+}
+
+/// Capability matrix of both nodes, one result per probe.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SelfTestReport {
+    pub probes: Vec<ProbeResult>,
+}
+
+impl SelfTestReport {
+    /// `true` unless a required method failed.
+    pub fn passed(&self) -> bool {
+        self.probes
+            .iter()
+            .all(|probe| probe.passed || !probe.required)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("self test report serializes to json; qed")
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "self test of {} rpc methods", self.probes.len())?;
+        for probe in &self.probes {
+            let outcome = match (probe.passed, probe.required) {
+                (true, _) => "pass",
+                (false, true) => "FAIL",
+                (false, false) => "warn",
+            };
+            write!(
+                f,
+                "{:<8} {:<28} {} {:>6}ms  {}",
+                probe.chain, probe.method, outcome, probe.latency_ms, probe.feature
+            )?;
+            if let Some(ref error) = probe.error {
+                write!(f, ": {}", error)?;
+            }
+            writeln!(f)?;
+        }
+        let missing = self.probes
+            .iter()
+            .filter(|probe| !probe.passed && probe.required)
+            .collect::<Vec<_>>();
+        for probe in &missing {
+            write!(
+                f,
+                "{} lacks {}, required by {}. ",
+                probe.chain, probe.method, probe.feature
+            )?;
+            match probe.option {
+                Some(option) => writeln!(f, "disable `{}` to run without it", option)?,
+                None => writeln!(f, "the bridge can't run without it")?,
+            }
+        }
+        if missing.is_empty() {
+            write!(f, "passed")
+        } else {
+            write!(f, "failed")
+        }
+    }
+}
+
+/// `eth_call` input of `requiredSignatures()`, which both contracts have.
+fn required_signatures_input() -> Bytes {
+    keccak256(b"requiredSignatures()")[..4].to_vec().into()
+}
+
+/// methods the bridge calls on `chain` whatever the config, of the contract at `contract`
+/// deployed at block `deploy`.
+fn bridge_probes(chain: &'static str, node: &Node, contract: Address, deploy: u64) -> Vec<Probe> {
+    let latest_logs = json!({
+        "address": contract,
+        "fromBlock": "latest",
+        "toBlock": "latest",
+    });
+    // no log has a zero topic, the node still has to search every block since the deployment
+    let history_logs = json!({
+        "address": contract,
+        "fromBlock": format!("0x{:x}", deploy),
+        "toBlock": "latest",
+        "topics": [H256::zero()],
+    });
+    let call = json!({
+        "to": contract,
+        "data": required_signatures_input(),
+    });
+    let (send, sign) = match node.signer {
+        Signer::Node => ("eth_sendTransaction", "eth_sign"),
+        Signer::Personal => ("personal_sendTransaction", "personal_sign"),
+        // the remote signer signs, the node only gets the signed transactions
+        Signer::Remote => ("eth_sendRawTransaction", "eth_chainId"),
+    };
+    vec![
+        Probe::call(chain, "eth_blockNumber", vec![], "bridge"),
+        Probe::call(chain, "eth_getBlockByNumber", vec!["latest".into(), false.into()], "bridge"),
+        Probe::call(chain, "eth_getLogs", vec![latest_logs], "bridge"),
+        // ranges with too many logs are bisected
+        Probe::call(chain, "eth_getLogs", vec![history_logs], "log scans").fallback(),
+        Probe::call(chain, "eth_call", vec![call, "latest".into()], "bridge"),
+        Probe::call(
+            chain,
+            "eth_getTransactionReceipt",
+            vec![json!(H256::zero())],
+            "bridge",
+        ),
+        Probe::call(
+            chain,
+            "eth_getTransactionCount",
+            vec![json!(node.sender()), "pending".into()],
+            "bridge",
+        ),
+        Probe::exists(chain, send, "signer"),
+        Probe::exists(chain, sign, "signer"),
+    ]
+}
+
+/// probes of the methods `config` makes the bridge call, for the contracts of `init`.
+pub fn probes(config: &Config, init: &Database) -> Vec<Probe> {
+    let mut probes = bridge_probes(
+        "home",
+        &config.home,
+        init.home_contract_address,
+        init.home_deploy,
+    );
+    probes.extend(bridge_probes(
+        "foreign",
+        &config.foreign,
+        init.foreign_contract_address,
+        init.foreign_deploy,
+    ));
+    probes.extend(finality_monitor::self_test_probes(config));
+    probes.extend(schedule::self_test_probes(config));
+    probes.extend(node_gas_price::self_test_probes(config));
+    probes.extend(contract_recipients::self_test_probes(config, init));
+    probes
+}
+
+/// A probe in flight.
+struct RunProbe<T: Transport> {
+    probe: Probe,
+    future: Timeout<ApiCall<Value, T::Out>>,
+    started: Instant,
+}
+
+impl<T: Transport> Future for RunProbe<T> {
+    type Item = ProbeResult;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.future.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(value)) => Ok(value),
+            Err(err) => Err(err),
+        };
+        Ok(Async::Ready(ProbeResult::of(
+            &self.probe,
+            &result,
+            self.started.elapsed(),
+        )))
+    }
+}
+
+/// probes every rpc method the configured features call on both nodes at once.
+pub fn create_self_test<T: Transport>(app: Arc<App<T>>, init: &Database) -> SelfTest<T> {
+    let runs = probes(&app.config, init)
+        .into_iter()
+        .map(|probe| {
+            let (transport, timeout) = if probe.chain == "home" {
+                (&app.connections.home, app.config.home.request_timeout)
+            } else {
+                (&app.connections.foreign, app.config.foreign.request_timeout)
+            };
+            let call = api::probe(transport, &probe.method, probe.params.clone());
+            RunProbe {
+                future: api::timeout(&app.timer, call, probe.chain, timeout),
+                started: Instant::now(),
+                probe,
+            }
+        })
+        .collect::<Vec<_>>();
+    SelfTest {
+        future: join_all(runs),
+    }
+}
+
+/// Future resolving to the capability matrix of both nodes. failed probes don't fail it.
+pub struct SelfTest<T: Transport> {
+    future: JoinAll<Vec<RunProbe<T>>>,
+}
+
+impl<T: Transport> Future for SelfTest<T> {
+    type Item = SelfTestReport;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let probes = try_ready!(self.future.poll());
+        Ok(Async::Ready(SelfTestReport { probes }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use rpc;
+    use web3;
+    use web3::types::Address;
+    use config::{Authorities, Config, Node, NodeGasPriceConfig, Signer};
+    use database::Database;
+    use error::{Error, ErrorKind};
+    use super::{probes, Probe, ProbeResult, SelfTestReport};
+
+    fn rpc_error(message: &str) -> Error {
+        ErrorKind::Web3(web3::Error::Rpc(rpc::Error {
+            code: rpc::ErrorCode::ServerError(-32601),
+            message: message.into(),
+            data: None,
+        })).into()
+    }
+
+    fn config() -> Config {
+        let authority: Address = 0xaa.into();
+        Config::builder()
+            .home(Node::new(authority, "/home.ipc".into()))
+            .foreign(Node::new(authority, "/foreign.ipc".into()))
+            .authorities(Authorities {
+                accounts: vec![authority],
+                required_signatures: 1,
+            })
+            .estimated_gas_cost_of_withdraw(100000u64.into())
+            .max_total_home_contract_balance(0u64.into())
+            .max_single_deposit_value(0u64.into())
+            .build()
+            .unwrap()
+    }
+
+    /// `<chain> <method>` of the probes of `config`.
+    fn methods(config: &Config) -> Vec<String> {
+        probes(config, &Database::default())
+            .into_iter()
+            .map(|probe| format!("{} {}", probe.chain, probe.method))
+            .collect()
+    }
+
+    #[test]
+    fn test_probe_results() {
+        let call = Probe::call("home", "eth_blockNumber", vec![], "bridge");
+        let passed = ProbeResult::of(&call, &Ok(json!("0x1")), Duration::from_millis(12));
+        assert!(passed.passed);
+        assert_eq!(12, passed.latency_ms);
+        let rejected = Err(rpc_error("invalid params"));
+        let failed = ProbeResult::of(&call, &rejected, Duration::from_millis(0));
+        assert!(!failed.passed);
+        assert!(failed.error.unwrap().contains("invalid params"));
+
+        let exists = Probe::exists("home", "personal_sign", "signer");
+        let invalid = Err(rpc_error("Invalid params: invalid length 0, expected a tuple"));
+        assert!(ProbeResult::of(&exists, &invalid, Duration::from_millis(0)).passed);
+        let missing = Err(rpc_error("the method personal_sign does not exist/is not available"));
+        assert!(!ProbeResult::of(&exists, &missing, Duration::from_millis(0)).passed);
+        // a node that doesn't answer doesn't prove the method exists
+        let timeout = Err(Error::from("request timed out"));
+        assert!(!ProbeResult::of(&exists, &timeout, Duration::from_millis(0)).passed);
+    }
+
+    #[test]
+    fn test_probes_follow_the_config() {
+        let mut config = config();
+        let plain = methods(&config);
+        assert!(plain.contains(&"home eth_getLogs".to_owned()));
+        assert!(plain.contains(&"foreign eth_sendTransaction".to_owned()));
+        assert!(!plain.iter().any(|method| method.contains("personal_")));
+        assert!(!plain.iter().any(|method| method.contains("eth_feeHistory")));
+
+        config.foreign.signer = Signer::Personal;
+        config.node_gas_price = Some(NodeGasPriceConfig {
+            blocks: 20,
+            percentile: 60,
+            floor: 1,
+            ceiling: None,
+        });
+        let configured = methods(&config);
+        assert!(configured.contains(&"foreign personal_sendTransaction".to_owned()));
+        assert!(configured.contains(&"home eth_sendTransaction".to_owned()));
+
+        config.home.signer = Signer::Remote;
+        let remote = methods(&config);
+        assert!(remote.contains(&"home eth_sendRawTransaction".to_owned()));
+        assert!(remote.contains(&"home eth_chainId".to_owned()));
+        assert!(!remote.contains(&"home eth_sign".to_owned()));
+        let fee_history = probes(&config, &Database::default())
+            .into_iter()
+            .find(|probe| probe.method == "eth_feeHistory")
+            .unwrap();
+        assert_eq!("foreign", fee_history.chain);
+        assert_eq!(Some("node_gas_price"), fee_history.option);
+        // eth_gasPrice is the fallback
+        assert!(!fee_history.required);
+    }
+
+    #[test]
+    fn test_self_test_report_names_the_option_of_a_missing_method() {
+        let probe = |method: &str, option, required, passed| ProbeResult {
+            chain: "foreign",
+            method: method.into(),
+            feature: "feature",
+            option,
+            required,
+            passed,
+            latency_ms: 3,
+            error: if passed { None } else { Some("method not found".into()) },
+        };
+        let mut report = SelfTestReport {
+            probes: vec![
+                probe("eth_blockNumber", None, true, true),
+                probe("eth_feeHistory", Some("node_gas_price"), false, false),
+            ],
+        };
+        assert!(report.passed());
+        let shown = report.to_string();
+        assert!(shown.contains("warn"), "{}", shown);
+        assert!(shown.ends_with("passed"), "{}", shown);
+
+        report.probes.push(probe("txpool_status", Some("txpool"), true, false));
+        assert!(!report.passed());
+        let shown = report.to_string();
+        assert!(
+            shown.contains("foreign lacks txpool_status, required by feature. disable `txpool`"),
+            "{}",
+            shown
+        );
+        assert!(shown.ends_with("failed"), "{}", shown);
+        assert!(report.to_json().contains("\"option\": \"txpool\""));
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use database::SkippedDeposit;
+use super::held_deposits::HeldValue;
+use super::history::{RelayHistory, RelayRecord};
+use super::pending_age::PendingRelay;
+use super::recent_errors::RecentError;
+
+/// Cheaply cloneable handle to the relay history, the pending relays, the skipped deposits
+/// and the recent errors of a bridge.
+///
+/// the bridge updates it while the history api reads it from its own thread.
+#[derive(Debug, Clone)]
+pub struct SharedRelays {
+    inner: Arc<RwLock<Relays>>,
+}
+
+#[derive(Debug)]
+struct Relays {
+    history: RelayHistory,
+    /// pending relays, the oldest first
+    pending: Vec<PendingRelay>,
+    /// deposits held by `recipient_policies` by recipient, see `DepositRelay::held_values`
+    held: Vec<HeldValue>,
+    /// recently skipped deposits, the oldest first, see `SkippedDeposits`
+    skipped: Vec<SkippedDeposit>,
+    /// latest failures of every component, see `Session::recent_errors`
+    errors: BTreeMap<String, Vec<RecentError>>,
+}
+
+impl SharedRelays {
+    pub fn new(capacity: usize) -> Self {
+        SharedRelays {
+            inner: Arc::new(RwLock::new(Relays {
+                history: RelayHistory::new(capacity),
+                pending: Vec::new(),
+                held: Vec::new(),
+                skipped: Vec::new(),
+                errors: BTreeMap::new(),
+            })),
+        }
+    }
+
+    pub fn extend<I: IntoIterator<Item = RelayRecord>>(&self, records: I) {
+        self.inner
+            .write()
+            .expect("no code panics while holding the lock; qed")
+            .history
+            .extend(records);
+    }
+
+    /// replaces the pending relays, which must be ordered oldest first.
+    pub fn set_pending(&self, pending: Vec<PendingRelay>) {
+        self.inner
+            .write()
+            .expect("no code panics while holding the lock; qed")
+            .pending = pending;
+    }
+
+    /// replaces the deposits held by `recipient_policies`.
+    pub fn set_held(&self, held: Vec<HeldValue>) {
+        self.inner
+            .write()
+            .expect("no code panics while holding the lock; qed")
+            .held = held;
+    }
+
+    /// the deposits held by `recipient_policies` by recipient.
+    pub fn held(&self) -> Vec<HeldValue> {
+        self.inner
+            .read()
+            .expect("no code panics while holding the lock; qed")
+            .held
+            .clone()
+    }
+
+    /// replaces the skipped deposits, which must be ordered oldest first.
+    pub fn set_skipped(&self, skipped: Vec<SkippedDeposit>) {
+        self.inner
+            .write()
+            .expect("no code panics while holding the lock; qed")
+            .skipped = skipped;
+    }
+
+    /// applies `read` to the skipped deposits while no writer has access.
+    pub fn read_skipped<R, F: FnOnce(&[SkippedDeposit]) -> R>(&self, read: F) -> R {
+        let relays = self.inner
+            .read()
+            .expect("no code panics while holding the lock; qed");
+        read(&relays.skipped)
+    }
+
+    pub fn set_errors(&self, errors: BTreeMap<String, Vec<RecentError>>) {
+        self.inner
+            .write()
+            .expect("no code panics while holding the lock; qed")
+            .errors = errors;
+    }
+
+    /// the recent errors of every component.
+    pub fn errors(&self) -> BTreeMap<String, Vec<RecentError>> {
+        self.inner
+            .read()
+            .expect("no code panics while holding the lock; qed")
+            .errors
+            .clone()
+    }
+
+    /// applies `read` to the history and the pending relays while no writer has access.
+    pub fn read<R, F: FnOnce(&RelayHistory, &[PendingRelay]) -> R>(&self, read: F) -> R {
+        let relays = self.inner
+            .read()
+            .expect("no code panics while holding the lock; qed");
+        read(&relays.history, &relays.pending)
+    }
+}
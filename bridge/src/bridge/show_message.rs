@@ -0,0 +1,501 @@
+use std::fmt;
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use futures::future::{join_all, Join, JoinAll};
+use rustc_hex::ToHex;
+use serde_json;
+use tiny_keccak::keccak256;
+use web3::Transport;
+use web3::types::{Address, Bytes, H256, TransactionReceipt, U256};
+use api::{self, ApiCall, Settle, Timeout};
+use app::App;
+use database::Database;
+use error::{Error, Result};
+use message_to_mainnet::{MessageToMainnet, MESSAGE_LENGTH};
+use units::{format_ether, format_wei};
+
+/// A field of a message that differs from the message stored in `ForeignBridge`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldDiff {
+    pub field: &'static str,
+    /// value in the message reconstructed from the `Withdraw` event
+    pub expected: String,
+    pub on_chain: String,
+}
+
+/// the fields of `expected` that differ from the message `on_chain`.
+/// the length is the only field compared if `on_chain` isn't a message.
+pub fn message_diff(expected: &MessageToMainnet, on_chain: &[u8]) -> Vec<FieldDiff> {
+    let on_chain = match MessageToMainnet::parse(on_chain) {
+        Ok(message) => message,
+        Err(_) => {
+            return vec![FieldDiff {
+                field: "length",
+                expected: MESSAGE_LENGTH.to_string(),
+                on_chain: on_chain.len().to_string(),
+            }]
+        }
+    };
+    let mut diff = Vec::new();
+    if expected.recipient != on_chain.recipient {
+        diff.push(FieldDiff {
+            field: "recipient",
+            expected: format!("{:?}", expected.recipient),
+            on_chain: format!("{:?}", on_chain.recipient),
+        });
+    }
+    if expected.value != on_chain.value {
+        diff.push(FieldDiff {
+            field: "value",
+            expected: expected.value.to_string(),
+            on_chain: on_chain.value.to_string(),
+        });
+    }
+    if expected.sidenet_transaction_hash != on_chain.sidenet_transaction_hash {
+        diff.push(FieldDiff {
+            field: "sidenet_transaction_hash",
+            expected: format!("{:?}", expected.sidenet_transaction_hash),
+            on_chain: format!("{:?}", on_chain.sidenet_transaction_hash),
+        });
+    }
+    if expected.mainnet_gas_price != on_chain.mainnet_gas_price {
+        diff.push(FieldDiff {
+            field: "mainnet_gas_price",
+            expected: expected.mainnet_gas_price.to_string(),
+            on_chain: on_chain.mainnet_gas_price.to_string(),
+        });
+    }
+    diff
+}
+
+/// A signature of a message submitted to `ForeignBridge`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MessageSignature {
+    pub signature: Bytes,
+    /// account the signature recovers to. `None` if it can't be recovered
+    pub signer: Option<Address>,
+}
+
+/// The message the bridge signs for a `ForeignBridge.Withdraw` event
+/// and the signatures submitted for it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShownMessage {
+    /// foreign transaction that emitted the event
+    pub transaction: H256,
+    pub recipient: Address,
+    /// value on home, converted by `value_scale`
+    pub value: U256,
+    /// id of the withdraw, see `util::log_id`
+    pub sidenet_transaction_hash: H256,
+    pub mainnet_gas_price: U256,
+    /// the signed bytes, `MessageToMainnet.to_bytes()`
+    pub bytes: Bytes,
+    /// keccak hash of `bytes`, the key of the message in `ForeignBridge`
+    pub hash: H256,
+    /// message stored in `ForeignBridge`. `None` until an authority submits a signature
+    pub on_chain: Option<Bytes>,
+    /// fields the stored message differs in
+    pub diff: Vec<FieldDiff>,
+    pub signatures: Vec<MessageSignature>,
+    /// account of this authority on foreign
+    pub authority: Address,
+}
+
+impl ShownMessage {
+    fn new(transaction: H256, message: &MessageToMainnet, authority: Address) -> Self {
+        let bytes = message.to_bytes();
+        ShownMessage {
+            transaction,
+            recipient: message.recipient,
+            value: message.value,
+            sidenet_transaction_hash: message.sidenet_transaction_hash,
+            mainnet_gas_price: message.mainnet_gas_price,
+            hash: keccak256(&bytes).into(),
+            bytes: Bytes(bytes),
+            on_chain: None,
+            diff: Vec::new(),
+            signatures: Vec::new(),
+            authority,
+        }
+    }
+
+    /// `true` if a submitted signature recovers to this authority.
+    pub fn signed_by_authority(&self) -> bool {
+        self.signatures
+            .iter()
+            .any(|signature| signature.signer == Some(self.authority))
+    }
+}
+
+impl fmt::Display for ShownMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "withdraw in foreign transaction {:?}", self.transaction)?;
+        writeln!(f, "recipient: {:?}", self.recipient)?;
+        writeln!(f, "value: {}", format_ether(self.value, 0))?;
+        writeln!(f, "sidenet transaction hash: {:?}", self.sidenet_transaction_hash)?;
+        writeln!(f, "mainnet gas price: {}", format_wei(self.mainnet_gas_price))?;
+        writeln!(f, "message: 0x{}", self.bytes.0.to_hex())?;
+        writeln!(f, "message hash: {:?}", self.hash)?;
+        match self.on_chain {
+            None => writeln!(f, "on-chain message: not submitted")?,
+            Some(_) if self.diff.is_empty() => writeln!(f, "on-chain message: matches")?,
+            Some(ref on_chain) => {
+                writeln!(f, "on-chain message: 0x{} differs", on_chain.0.to_hex())?;
+                for diff in &self.diff {
+                    writeln!(
+                        f,
+                        "  {}: expected {}, on-chain {}",
+                        diff.field, diff.expected, diff.on_chain
+                    )?;
+                }
+            }
+        }
+        for (index, signature) in self.signatures.iter().enumerate() {
+            let signer = match signature.signer {
+                Some(signer) if signer == self.authority => format!("{:?} (this authority)", signer),
+                Some(signer) => format!("{:?}", signer),
+                None => "unknown signer".to_owned(),
+            };
+            writeln!(
+                f,
+                "signature {}: 0x{} recovers to {}",
+                index,
+                signature.signature.0.to_hex(),
+                signer
+            )?;
+        }
+        if self.signed_by_authority() {
+            write!(f, "signed by this authority {:?}", self.authority)
+        } else {
+            write!(f, "not signed by this authority {:?}", self.authority)
+        }
+    }
+}
+
+/// The messages of all `Withdraw` events of a foreign transaction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShownMessages {
+    pub messages: Vec<ShownMessage>,
+}
+
+impl ShownMessages {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("messages serialize to json; qed")
+    }
+}
+
+impl fmt::Display for ShownMessages {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let messages = self.messages
+            .iter()
+            .map(ShownMessage::to_string)
+            .collect::<Vec<_>>();
+        write!(f, "{}", messages.join("\n\n"))
+    }
+}
+
+type CallFuture<T> = Settle<Timeout<ApiCall<Bytes, <T as Transport>::Out>>>;
+
+type OnChainFuture<T> =
+    Join<JoinAll<Vec<CallFuture<T>>>, JoinAll<Vec<JoinAll<Vec<CallFuture<T>>>>>>;
+
+type RecoverFuture<T> =
+    JoinAll<Vec<JoinAll<Vec<Settle<Timeout<ApiCall<Address, <T as Transport>::Out>>>>>>>;
+
+enum ShowMessageState<T: Transport> {
+    /// Fetching the receipt of the foreign transaction.
+    FetchReceipt(Timeout<ApiCall<Option<TransactionReceipt>, T::Out>>),
+    /// Reading the stored messages and their signatures from `ForeignBridge`.
+    FetchOnChain {
+        future: OnChainFuture<T>,
+        shown: Vec<ShownMessage>,
+    },
+    /// Recovering the signers of the signatures.
+    Recover {
+        future: RecoverFuture<T>,
+        shown: Vec<ShownMessage>,
+    },
+}
+
+/// reconstructs the messages the bridge signs for the `Withdraw` events
+/// `ForeignBridge` at `init.foreign_contract_address` emitted in `transaction`.
+pub fn create_show_message<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+    transaction: H256,
+) -> ShowMessage<T> {
+    let state = ShowMessageState::FetchReceipt(api::timeout(
+        &app.timer,
+        api::transaction_receipt(&app.connections.foreign, transaction),
+        "foreign",
+        app.config.foreign.request_timeout,
+    ).about(transaction));
+    ShowMessage {
+        app,
+        foreign_contract: init.foreign_contract_address,
+        transaction,
+        state,
+    }
+}
+
+/// Future resolving to the messages of the `Withdraw` events of a foreign transaction,
+/// compared to the messages and signatures stored in `ForeignBridge`.
+pub struct ShowMessage<T: Transport> {
+    app: Arc<App<T>>,
+    foreign_contract: Address,
+    transaction: H256,
+    state: ShowMessageState<T>,
+}
+
+/// the messages the bridge signs for the `Withdraw` events of `foreign_contract` in `receipt`,
+/// converted by `value_scale` the same as by `WithdrawConfirm`.
+fn withdraw_messages<T: Transport>(
+    app: &App<T>,
+    foreign_contract: Address,
+    receipt: TransactionReceipt,
+) -> Result<Vec<MessageToMainnet>> {
+    let mut messages = Vec::new();
+    for log in receipt.logs {
+        if log.address != foreign_contract {
+            continue;
+        }
+        let mut message = match MessageToMainnet::from_log(log, app.config.unique_log_ids) {
+            Ok(message) => message,
+            // another event of the contract
+            Err(_) => continue,
+        };
+        if let Some(ref scale) = app.config.value_scale {
+            message.value = scale
+                .withdraw(message.value)
+                .map_err(|err| format!("the bridge doesn't sign this withdraw: {}", err))?
+                .value;
+        }
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+fn call<T: Transport>(app: &App<T>, foreign_contract: Address, payload: Vec<u8>) -> CallFuture<T> {
+    api::settle(api::timeout(
+        &app.timer,
+        api::call(&app.connections.foreign, foreign_contract, payload.into()),
+        "foreign",
+        app.config.foreign.request_timeout,
+    ))
+}
+
+/// reads the stored message and a signature of every authority for each of `shown`.
+fn fetch_on_chain<T: Transport>(
+    app: &App<T>,
+    foreign_contract: Address,
+    shown: &[ShownMessage],
+) -> OnChainFuture<T> {
+    let functions = app.foreign_bridge.functions();
+    let messages = shown
+        .iter()
+        .map(|shown| call(app, foreign_contract, functions.message().input(shown.hash.0)))
+        .collect::<Vec<_>>();
+    let signatures = shown
+        .iter()
+        .map(|shown| {
+            let calls = (0..app.config.authorities.accounts.len() as u32)
+                .map(|index| {
+                    call(
+                        app,
+                        foreign_contract,
+                        functions.signature().input(shown.hash.0, index),
+                    )
+                })
+                .collect::<Vec<_>>();
+            join_all(calls)
+        })
+        .collect::<Vec<_>>();
+    join_all(messages).join(join_all(signatures))
+}
+
+/// recovers the signers of the signatures of `shown` with the foreign node.
+fn recover<T: Transport>(app: &App<T>, shown: &[ShownMessage]) -> RecoverFuture<T> {
+    let calls = shown
+        .iter()
+        .map(|shown| {
+            let calls = shown
+                .signatures
+                .iter()
+                .map(|signature| {
+                    api::settle(api::timeout(
+                        &app.timer,
+                        api::ec_recover(
+                            &app.connections.foreign,
+                            shown.bytes.clone(),
+                            signature.signature.clone(),
+                        ),
+                        "foreign",
+                        app.config.foreign.request_timeout,
+                    ))
+                })
+                .collect::<Vec<_>>();
+            join_all(calls)
+        })
+        .collect::<Vec<_>>();
+    join_all(calls)
+}
+
+impl<T: Transport> Future for ShowMessage<T> {
+    type Item = ShownMessages;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                ShowMessageState::FetchReceipt(ref mut future) => {
+                    let transaction = self.transaction;
+                    let receipt = try_ready!(future.poll()).ok_or_else(|| {
+                        format!("foreign transaction {:?} isn't mined", transaction)
+                    })?;
+                    let authority = self.app.config.foreign.account;
+                    let shown = withdraw_messages(&self.app, self.foreign_contract, receipt)?
+                        .iter()
+                        .map(|message| ShownMessage::new(transaction, message, authority))
+                        .collect::<Vec<_>>();
+                    if shown.is_empty() {
+                        return Err(format!(
+                            "foreign transaction {:?} emitted no withdraw of contract {:?}",
+                            self.transaction, self.foreign_contract
+                        ).into());
+                    }
+                    ShowMessageState::FetchOnChain {
+                        future: fetch_on_chain(&self.app, self.foreign_contract, &shown),
+                        shown,
+                    }
+                }
+                ShowMessageState::FetchOnChain {
+                    ref mut future,
+                    ref mut shown,
+                } => {
+                    let (messages, signatures) = try_ready!(future.poll());
+                    let functions = self.app.foreign_bridge.functions();
+                    for ((shown, message), signatures) in shown
+                        .iter_mut()
+                        .zip(messages.into_iter())
+                        .zip(signatures.into_iter())
+                    {
+                        let on_chain = submitted(
+                            message
+                                .ok()
+                                .and_then(|output| functions.message().output(&output.0).ok()),
+                        );
+                        if let Some(ref on_chain) = on_chain {
+                            let expected = MessageToMainnet::parse(&shown.bytes.0)
+                                .expect("bytes of a message parse; qed");
+                            shown.diff = message_diff(&expected, on_chain);
+                        }
+                        shown.on_chain = on_chain.map(Bytes);
+                        // calls for signatures not yet submitted fail
+                        shown.signatures = signatures
+                            .into_iter()
+                            .filter_map(|signature| {
+                                submitted(signature.ok().and_then(|output| {
+                                    functions.signature().output(&output.0).ok()
+                                }))
+                            })
+                            .map(|signature| MessageSignature {
+                                signature: Bytes(signature),
+                                signer: None,
+                            })
+                            .collect();
+                    }
+                    let shown = shown.drain(..).collect::<Vec<_>>();
+                    ShowMessageState::Recover {
+                        future: recover(&self.app, &shown),
+                        shown,
+                    }
+                }
+                ShowMessageState::Recover {
+                    ref mut future,
+                    ref mut shown,
+                } => {
+                    let signers = try_ready!(future.poll());
+                    for (shown, signers) in shown.iter_mut().zip(signers.into_iter()) {
+                        for (signature, signer) in shown.signatures.iter_mut().zip(signers) {
+                            signature.signer = match signer {
+                                Ok(signer) => Some(signer),
+                                Err(err) => {
+                                    warn!(
+                                        target: "bridge::show_message",
+                                        "cannot recover the signer of signature 0x{}: {}",
+                                        signature.signature.0.to_hex(),
+                                        err
+                                    );
+                                    None
+                                }
+                            };
+                        }
+                    }
+                    return Ok(Async::Ready(ShownMessages {
+                        messages: shown.drain(..).collect(),
+                    }));
+                }
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+/// `ForeignBridge` returns empty bytes for messages and signatures not yet submitted.
+fn submitted(output: Option<Vec<u8>>) -> Option<Vec<u8>> {
+    match output {
+        Some(ref bytes) if bytes.is_empty() => None,
+        output => output,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use message_to_mainnet::MessageToMainnet;
+    use super::{message_diff, FieldDiff};
+
+    fn message() -> MessageToMainnet {
+        MessageToMainnet {
+            recipient: 0xaa.into(),
+            value: 1000.into(),
+            sidenet_transaction_hash: 0xcc.into(),
+            mainnet_gas_price: 20.into(),
+        }
+    }
+
+    #[test]
+    fn test_message_diff() {
+        let expected = message();
+        assert!(message_diff(&expected, &expected.to_bytes()).is_empty());
+
+        let on_chain = MessageToMainnet {
+            value: 999.into(),
+            mainnet_gas_price: 10.into(),
+            ..message()
+        };
+        assert_eq!(
+            vec![
+                FieldDiff {
+                    field: "value",
+                    expected: "1000".into(),
+                    on_chain: "999".into(),
+                },
+                FieldDiff {
+                    field: "mainnet_gas_price",
+                    expected: "20".into(),
+                    on_chain: "10".into(),
+                },
+            ],
+            message_diff(&expected, &on_chain.to_bytes())
+        );
+
+        assert_eq!(
+            vec![FieldDiff {
+                field: "length",
+                expected: "116".into(),
+                on_chain: "84".into(),
+            }],
+            message_diff(&expected, &expected.to_bytes()[..84])
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use std::sync::Arc;
+use ethabi::{self, ParamType, Token};
+use futures::{Async, Poll, Stream};
+use tiny_keccak::keccak256;
+use web3::Transport;
+use web3::types::{Address, FilterBuilder, H256, Log};
+use api::{self, LogStream};
+use app::App;
+use database::Database;
+use error::{Error, Result};
+use jitter;
+use util::log_transaction_hash;
+
+/// event upgraded `ForeignBridge` contracts emit when they refuse a signature
+/// or deposit confirmation instead of reverting. no parameter is indexed.
+pub const SIGN_REJECTED_EVENT: &str = "SignRejected(address,bytes32,uint8)";
+
+pub fn sign_rejected_topic() -> H256 {
+    keccak256(SIGN_REJECTED_EVENT.as_bytes()).into()
+}
+
+/// Reason code of a `SignRejected` event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RejectReason {
+    /// the authority already signed or confirmed
+    Duplicate,
+    /// the sender isn't an authority
+    NotAuthority,
+    /// the contract is paused
+    Paused,
+    /// a code this version of the bridge doesn't know
+    Unknown(u8),
+}
+
+impl RejectReason {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            1 => RejectReason::Duplicate,
+            2 => RejectReason::NotAuthority,
+            3 => RejectReason::Paused,
+            code => RejectReason::Unknown(code),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match *self {
+            RejectReason::Duplicate => "duplicate",
+            RejectReason::NotAuthority => "not_authority",
+            RejectReason::Paused => "paused",
+            RejectReason::Unknown(_) => "unknown",
+        }
+    }
+}
+
+/// A refused signature or deposit confirmation of an authority.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignRejection {
+    /// authority whose call was refused
+    pub authority: Address,
+    /// transaction on home (main) of the deposit or withdraw message hash
+    pub main_tx_hash: H256,
+    pub reason: RejectReason,
+    /// foreign transaction that emitted the event
+    pub transaction: H256,
+    pub block: u64,
+}
+
+impl SignRejection {
+    pub fn from_log(log: &Log) -> Result<Self> {
+        let tokens = ethabi::decode(
+            &[ParamType::Address, ParamType::FixedBytes(32), ParamType::Uint(8)],
+            &log.data.0,
+        )?;
+        match (&tokens[0], &tokens[1], &tokens[2]) {
+            (&Token::Address(authority), &Token::FixedBytes(ref hash), &Token::Uint(code)) => {
+                Ok(SignRejection {
+                    authority,
+                    main_tx_hash: H256::from_slice(hash),
+                    reason: RejectReason::from_code(code.low_u64() as u8),
+                    transaction: log_transaction_hash(log)?,
+                    block: log.block_number.map_or(0, |block| block.low_u64()),
+                })
+            }
+            _ => Err("SignRejected event decodes to unexpected tokens".into()),
+        }
+    }
+}
+
+/// Watches the `SignRejected` events of `ForeignBridge` for refused calls of all authorities.
+/// yields the rejections of every confirmed range of blocks.
+pub struct SignRejectionMonitor<T: Transport> {
+    logs: LogStream<T>,
+    /// account of this authority on foreign
+    account: Address,
+}
+
+/// watches the blocks after the last checked withdraw confirm.
+pub fn create_sign_rejection_monitor<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+) -> SignRejectionMonitor<T> {
+    let logs_init = api::LogStreamInit {
+        after: init.checked_withdraw_confirm,
+        chain: "foreign",
+        request_timeout: app.config.foreign.request_timeout,
+        poll_interval: app.tunables.get().foreign_poll_interval,
+        poll_jitter: jitter::poll_jitter(app.config.poll_jitter, &app.config.foreign.account),
+        confirmations: app.config.foreign.required_confirmations,
+        sanity_check: app.config.foreign.logs_sanity_check.clone(),
+        head_regression: app.config.foreign.head_regression,
+        log_limit_errors: app.config.foreign.log_limit_errors.clone(),
+        max_log_data: app.config.foreign.max_log_data,
+        finality: app.config.foreign.l1_posted_rpc(),
+        filter: FilterBuilder::default()
+            .address(vec![init.foreign_contract_address])
+            .topics(Some(vec![sign_rejected_topic()]), None, None, None),
+    };
+    SignRejectionMonitor {
+        logs: api::log_stream(
+            app.connections.foreign.clone(),
+            app.timer.clone(),
+            logs_init,
+        ),
+        account: app.config.foreign.account,
+    }
+}
+
+impl<T: Transport> Stream for SignRejectionMonitor<T> {
+    type Item = Vec<SignRejection>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let item = try_stream!(self.logs.poll());
+        let mut rejections = Vec::new();
+        for log in &item.logs {
+            let rejection = match SignRejection::from_log(log) {
+                Ok(rejection) => rejection,
+                Err(err) => {
+                    warn!(
+                        target: "bridge::sign_rejections",
+                        "cannot decode SignRejected event in foreign transaction {:?}: {}",
+                        log.transaction_hash,
+                        err
+                    );
+                    continue;
+                }
+            };
+            if rejection.authority == self.account {
+                warn!(
+                    target: "bridge::sign_rejections",
+                    "ForeignBridge rejected the call of this authority in transaction {} for {}: {}",
+                    rejection.transaction,
+                    rejection.main_tx_hash,
+                    rejection.reason.label()
+                );
+            } else {
+                info!(
+                    target: "bridge::sign_rejections",
+                    "ForeignBridge rejected the call of authority {} in transaction {} for {}: {}",
+                    rejection.authority,
+                    rejection.transaction,
+                    rejection.main_tx_hash,
+                    rejection.reason.label()
+                );
+            }
+            rejections.push(rejection);
+        }
+        Ok(Async::Ready(Some(rejections)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::{self, Token};
+    use tiny_keccak::keccak256;
+    use web3::types::{Log, U256};
+    use super::{sign_rejected_topic, RejectReason, SignRejection};
+
+    #[test]
+    fn test_sign_rejected_topic() {
+        assert_eq!(
+            sign_rejected_topic(),
+            keccak256(b"SignRejected(address,bytes32,uint8)").into()
+        );
+    }
+
+    #[test]
+    fn test_sign_rejection_from_log() {
+        let data = ethabi::encode(&[
+            Token::Address(0xaa.into()),
+            Token::FixedBytes(vec![0x11; 32]),
+            Token::Uint(2.into()),
+        ]);
+        let log = Log {
+            address: 0xff.into(),
+            topics: vec![sign_rejected_topic()],
+            data: data.into(),
+            block_number: Some(U256::from(7)),
+            transaction_hash: Some(0xcc.into()),
+            ..Default::default()
+        };
+        let rejection = SignRejection::from_log(&log).unwrap();
+        assert_eq!(rejection.authority, 0xaa.into());
+        assert_eq!(rejection.main_tx_hash, [0x11u8; 32].into());
+        assert_eq!(rejection.reason, RejectReason::NotAuthority);
+        assert_eq!(rejection.transaction, 0xcc.into());
+        assert_eq!(rejection.block, 7);
+
+        assert_eq!(RejectReason::Unknown(9), RejectReason::from_code(9));
+        assert_eq!("unknown", RejectReason::from_code(9).label());
+
+        let truncated = Log {
+            data: vec![0u8; 32].into(),
+            ..log
+        };
+        assert!(SignRejection::from_log(&truncated).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+use web3::types::{Address, H256};
+
+fn duration_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+/// slot of `account` among `authorities` for the message hashing to `message_hash`:
+/// its index in `authorities` shifted by the message hash, so no authority is always first.
+/// `None` if `account` is not one of `authorities`.
+pub fn authority_slot(
+    authorities: &[Address],
+    account: &Address,
+    message_hash: &H256,
+) -> Option<u64> {
+    let index = authorities.iter().position(|authority| authority == account)? as u64;
+    let slots = authorities.len() as u64;
+    let shift = message_hash.0[24..]
+        .iter()
+        .fold(0u64, |shift, byte| (shift << 8) | *byte as u64) % slots;
+    Some((index + shift) % slots)
+}
+
+/// delay of the signature submission of `account` for the withdraw message hashing to `message_hash`.
+///
+/// `max_delay` is split into one slot per authority. every authority gets a different slot,
+/// see `authority_slot`. zero if `account` is not one of `authorities`.
+pub fn signature_delay(
+    authorities: &[Address],
+    account: &Address,
+    message_hash: &H256,
+    max_delay: Duration,
+) -> Duration {
+    let slot = match authority_slot(authorities, account, message_hash) {
+        Some(slot) => slot,
+        None => return Duration::from_secs(0),
+    };
+    let slots = authorities.len() as u64;
+    Duration::from_millis(duration_millis(max_delay) / slots * slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use web3::types::{Address, H256};
+    use super::signature_delay;
+
+    #[test]
+    fn test_signature_delay() {
+        let authorities: Vec<Address> = vec![1.into(), 2.into(), 3.into()];
+        let max_delay = Duration::from_secs(9);
+        let delays = |hash: H256| {
+            authorities
+                .iter()
+                .map(|account| signature_delay(&authorities, account, &hash, max_delay))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![
+                Duration::from_secs(0),
+                Duration::from_secs(3),
+                Duration::from_secs(6),
+            ],
+            delays(0.into())
+        );
+        // the slots rotate with the message hash
+        assert_eq!(
+            vec![
+                Duration::from_secs(3),
+                Duration::from_secs(6),
+                Duration::from_secs(0),
+            ],
+            delays(4.into())
+        );
+        for hash in 0..20 {
+            let mut delays = delays(hash.into());
+            assert!(delays.iter().all(|delay| *delay < max_delay));
+            delays.sort();
+            delays.dedup();
+            assert_eq!(authorities.len(), delays.len());
+        }
+
+        assert_eq!(
+            Duration::from_secs(0),
+            signature_delay(&authorities, &4.into(), &1.into(), max_delay)
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use std::fmt;
+use rustc_hex::{FromHex, ToHex};
+use secp256k1;
+use serde_json::{self, Value};
+use web3::types::Address;
+use config::{CommandSeverity, SignedCommandsConfig};
+use error::{Error, ResultExt};
+use remote_signer::message_hash;
+use signature::Signature;
+use webhook::canonical_json;
+
+/// how much `method` of the admin api changes the bridge. `None` for unknown methods.
+pub fn severity(method: &str) -> Option<CommandSeverity> {
+    match method {
+        "get-log-level" | "get-spend-limit" | "get-errors" => Some(CommandSeverity::Read),
+        "set-log-level" | "force-open-circuit" | "set-spend-limit" | "drain" => {
+            Some(CommandSeverity::Control)
+        }
+        "force-close-circuit"
+        | "release-deferred"
+        | "release-held-deposit"
+        | "acknowledge-deep-reorg" => Some(CommandSeverity::Critical),
+        _ => None,
+    }
+}
+
+/// Params of a signed call of an admin method: the params of the method, the nonce and the
+/// signatures of `command_message` by the operators.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SignedParams {
+    pub params: Vec<Value>,
+    /// above the nonce of the last signed command the bridge executed
+    pub nonce: u64,
+    /// `0x` hex of the 65 bytes of each signature, as `eth_sign` returns it
+    pub signatures: Vec<String>,
+}
+
+impl SignedParams {
+    /// unsigned params of a call with the json array `params` and `nonce`.
+    pub fn new(params: &str, nonce: u64) -> Result<Self, Error> {
+        let params = serde_json::from_str(params).chain_err(|| "params must be a json array")?;
+        Ok(SignedParams {
+            params,
+            nonce,
+            signatures: Vec::new(),
+        })
+    }
+
+    /// params as printed by `to_json`, e.g. signed by another operator.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json).chain_err(|| "Cannot parse the signed params")
+    }
+
+    /// adds the signature of `method` with these params by the account of the hex `secret`.
+    pub fn sign(&mut self, secret: &str, method: &str) -> Result<(), Error> {
+        let signature = sign_command(secret, method, &self.params, self.nonce)?;
+        self.signatures.push(signature);
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("SignedParams always serializes; qed")
+    }
+}
+
+/// the canonical json the operators sign for `method` with `params` and `nonce`,
+/// e.g. `{"method":"drain","nonce":7,"params":[]}`.
+pub fn command_message(method: &str, params: &[Value], nonce: u64) -> Result<Vec<u8>, Error> {
+    let command = json!({ "method": method, "params": params, "nonce": nonce });
+    Ok(canonical_json(&command)?.into_bytes())
+}
+
+/// EIP-191 signature of the command by the account of the hex `secret` key, as
+/// `eth_sign` of the account would sign it. used by `--admin-sign`.
+pub fn sign_command(
+    secret: &str,
+    method: &str,
+    params: &[Value],
+    nonce: u64,
+) -> Result<String, Error> {
+    if severity(method).is_none() {
+        bail!("{} is no method of the admin api", method);
+    }
+    let bytes: Vec<u8> = secret
+        .trim()
+        .trim_left_matches("0x")
+        .from_hex()
+        .map_err(|_| "the secret key must be hex")?;
+    let secret = secp256k1::SecretKey::parse_slice(&bytes)
+        .map_err(|err| format!("invalid secret key: {:?}", err))?;
+    let hash = message_hash(&command_message(method, params, nonce)?);
+    let (signature, recovery_id) = secp256k1::sign(&secp256k1::Message::parse(&hash.0), &secret);
+    let mut bytes = signature.serialize().to_vec();
+    bytes.push(recovery_id.serialize() + 27);
+    Ok(format!("0x{}", bytes.to_hex()))
+}
+
+/// distinct operators of `config` whose signatures of the command are in `signed`.
+/// fails if a signature is invalid or of an account that's no operator.
+pub fn command_signers(
+    config: &SignedCommandsConfig,
+    method: &str,
+    signed: &SignedParams,
+) -> Result<Vec<Address>, Error> {
+    let message = command_message(method, &signed.params, signed.nonce)?;
+    let mut signers = Vec::new();
+    for (index, signature) in signed.signatures.iter().enumerate() {
+        let bytes: Vec<u8> = signature
+            .trim_left_matches("0x")
+            .from_hex()
+            .map_err(|_| format!("signatures[{}] must be hex", index))?;
+        let signer = Signature::from_bytes(&bytes)
+            .and_then(|signature| signature.recover(&message))
+            .chain_err(|| format!("invalid signatures[{}]", index))?;
+        if !config.operators.contains(&signer) {
+            bail!("signatures[{}] is of {:?}, no operator", index, signer);
+        }
+        if !signers.contains(&signer) {
+            signers.push(signer);
+        }
+    }
+    Ok(signers)
+}
+
+/// Admin command signed by enough operators, executed once the bridge accepted its nonce,
+/// see `Bridge::authorize`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandAuthorization {
+    pub method: String,
+    pub params: Vec<Value>,
+    pub nonce: u64,
+    pub signers: Vec<Address>,
+}
+
+impl fmt::Display for CommandAuthorization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let signers = self.signers
+            .iter()
+            .map(|signer| format!("{:?}", signer))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(
+            f,
+            "{} {} with nonce {} signed by {}",
+            self.method,
+            Value::Array(self.params.clone()),
+            self.nonce,
+            signers
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use config::{CommandSeverity, SignedCommandsConfig};
+    use super::{command_message, command_signers, severity, sign_command, SignedParams};
+
+    /// secret keys of the accounts 0x7e5f4552091a69125d5dfcb7b8c2659029395bdf
+    /// and 0x2b5ad5c4795c026514f8317c7a215e218dccd6cf
+    const ONE: &str = "0x0000000000000000000000000000000000000000000000000000000000000001";
+    const TWO: &str = "0x0000000000000000000000000000000000000000000000000000000000000002";
+
+    fn config() -> SignedCommandsConfig {
+        SignedCommandsConfig {
+            operators: vec![
+                "7e5f4552091a69125d5dfcb7b8c2659029395bdf".parse().unwrap(),
+                "2b5ad5c4795c026514f8317c7a215e218dccd6cf".parse().unwrap(),
+            ],
+            severity: CommandSeverity::Control,
+            critical_signatures: 2,
+        }
+    }
+
+    fn signed(params: Vec<Value>, nonce: u64, secrets: &[&str]) -> SignedParams {
+        let signatures = secrets
+            .iter()
+            .map(|secret| sign_command(secret, "drain", &params, nonce).unwrap())
+            .collect();
+        SignedParams {
+            params,
+            nonce,
+            signatures,
+        }
+    }
+
+    #[test]
+    fn test_command_message_is_canonical_json() {
+        assert_eq!(
+            br#"{"method":"release-deferred","nonce":7,"params":["deposits","0x2a"]}"#.to_vec(),
+            command_message("release-deferred", &[json!("deposits"), json!("0x2a")], 7).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_severity() {
+        assert_eq!(Some(CommandSeverity::Read), severity("get-errors"));
+        assert_eq!(Some(CommandSeverity::Control), severity("force-open-circuit"));
+        assert_eq!(Some(CommandSeverity::Critical), severity("force-close-circuit"));
+        assert_eq!(None, severity("steal-lock"));
+    }
+
+    #[test]
+    fn test_command_signers_are_the_distinct_operators() {
+        let config = config();
+        let both = signed(Vec::new(), 3, &[ONE, TWO, ONE]);
+        assert_eq!(
+            config.operators,
+            command_signers(&config, "drain", &both).unwrap()
+        );
+
+        // the signatures are of another nonce, method or params
+        let mut replayed = both.clone();
+        replayed.nonce = 4;
+        assert!(command_signers(&config, "drain", &replayed).is_err());
+        assert!(command_signers(&config, "force-open-circuit", &both).is_err());
+        let mut changed = both.clone();
+        changed.params.push(json!("now"));
+        assert!(command_signers(&config, "drain", &changed).is_err());
+
+        let other = SignedCommandsConfig {
+            operators: vec![config.operators[0]],
+            ..config.clone()
+        };
+        assert_eq!(
+            "signatures[1] is of 0x2b5ad5c4795c026514f8317c7a215e218dccd6cf, no operator",
+            command_signers(&other, "drain", &both).unwrap_err().to_string()
+        );
+        let mut invalid = both.clone();
+        invalid.signatures[0] = "0x00".into();
+        assert!(command_signers(&config, "drain", &invalid).is_err());
+    }
+
+    #[test]
+    fn test_signed_params_collect_the_signatures_of_the_operators() {
+        let mut first = SignedParams::new(r#"["deposit_relay"]"#, 9).unwrap();
+        first.sign(ONE, "force-close-circuit").unwrap();
+        let mut second = SignedParams::from_json(&first.to_json()).unwrap();
+        second.sign(TWO, "force-close-circuit").unwrap();
+        assert_eq!(
+            config().operators,
+            command_signers(&config(), "force-close-circuit", &second).unwrap()
+        );
+        assert!(SignedParams::new("deposit_relay", 9).is_err());
+        assert!(SignedParams::from_json(r#"{"params":[],"nonce":1}"#).is_err());
+    }
+
+    #[test]
+    fn test_sign_command_rejects_unknown_methods_and_invalid_keys() {
+        assert!(sign_command(ONE, "steal-lock", &[], 1).is_err());
+        assert!(sign_command("0xzz", "drain", &[], 1).is_err());
+        assert!(sign_command("0x01", "drain", &[], 1).is_err());
+    }
+}
@@ -0,0 +1,595 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use ethabi::{self, ParamType, Token};
+use futures::{Async, Future, Poll, Stream};
+use futures::future::{join_all, Join, JoinAll};
+use serde_json;
+use tiny_keccak::keccak256;
+use web3::Transport;
+use web3::types::{Address, Block, FilterBuilder, H256, Log, Transaction};
+use api::{self, ApiCall, LogStream, Settle, Timeout};
+use app::App;
+use database::{Database, ObservedWithdraw, SigningLatency};
+use error::{Error, Result};
+use jitter;
+use message_to_mainnet::MessageToMainnet;
+use util::log_transaction_hash;
+
+/// number of withdraws `SigningLatencies` keeps the observation time of.
+pub const KEPT_OBSERVED_WITHDRAWS: usize = 1024;
+/// number of signatures `SigningLatencies` keeps.
+pub const KEPT_SIGNING_LATENCIES: usize = 4096;
+/// events of `ForeignBridge.submitSignature`. the signature collecting the required
+/// signatures emits `CollectedSignatures` instead of `WithdrawSignatureSubmitted`.
+pub const SIGNATURE_EVENTS: [&str; 2] = [
+    "WithdrawSignatureSubmitted(bytes32)",
+    "CollectedSignatures(address,bytes32)",
+];
+
+const SUBMIT_SIGNATURE: &str = "submitSignature(bytes,bytes)";
+
+pub fn signature_topics() -> Vec<H256> {
+    SIGNATURE_EVENTS
+        .iter()
+        .map(|event| keccak256(event.as_bytes()).into())
+        .collect()
+}
+
+/// A signature of an authority submitted to `ForeignBridge`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubmittedSignature {
+    /// id of the signed withdraw, see `util::log_id`
+    pub withdraw: H256,
+    /// sender of the transaction
+    pub authority: Address,
+    pub transaction: H256,
+    pub block: u64,
+    /// timestamp of `block`
+    pub signed: u64,
+}
+
+impl SubmittedSignature {
+    /// the signature `transaction` submitted with the event in `log`, in a block of `signed`.
+    /// fails unless `transaction` calls `submitSignature` with the message of the event,
+    /// e.g. if an authority submits through a contract.
+    pub fn from_transaction(log: &Log, transaction: &Transaction, signed: u64) -> Result<Self> {
+        let input = &transaction.input.0;
+        let selector = &keccak256(SUBMIT_SIGNATURE.as_bytes())[..4];
+        if input.len() < 4 || &input[..4] != selector {
+            bail!("transaction {:?} doesn't call {}", transaction.hash, SUBMIT_SIGNATURE);
+        }
+        let tokens = ethabi::decode(&[ParamType::Bytes, ParamType::Bytes], &input[4..])?;
+        let message = match tokens.get(1) {
+            Some(&Token::Bytes(ref message)) => message,
+            _ => bail!("{} decodes to unexpected tokens", SUBMIT_SIGNATURE),
+        };
+        // the message hash is the last parameter of both events
+        let data = &log.data.0;
+        if data.len() < 32 || data[data.len() - 32..] != keccak256(message)[..] {
+            bail!(
+                "transaction {:?} doesn't submit the message of its event",
+                transaction.hash
+            );
+        }
+        Ok(SubmittedSignature {
+            withdraw: MessageToMainnet::parse(message)?.sidenet_transaction_hash,
+            authority: transaction.from,
+            transaction: log_transaction_hash(log)?,
+            block: log.block_number.map_or(0, |block| block.low_u64()),
+            signed,
+        })
+    }
+}
+
+/// How `SigningLatencies::record` took a signature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureOutcome {
+    /// the latency of the signature, in seconds
+    Recorded(i64),
+    /// the signature of the authority on the withdraw was recorded before,
+    /// e.g. its block was checked again after a restart
+    Duplicate,
+    /// the withdraw wasn't observed by this bridge, e.g. it was emitted before the
+    /// latencies were tracked. excluded rather than counted as a latency of zero
+    Unobserved,
+}
+
+/// Withdraws by when this bridge first observed them and the signatures of all authorities
+/// on them, the most recent `KEPT_OBSERVED_WITHDRAWS` and `KEPT_SIGNING_LATENCIES`, oldest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SigningLatencies {
+    observed: Vec<ObservedWithdraw>,
+    latencies: Vec<SigningLatency>,
+    /// signatures on withdraws this bridge didn't observe since it started
+    unobserved: u64,
+}
+
+impl SigningLatencies {
+    /// latencies continuing from those of `init`.
+    pub fn new(init: &Database) -> Self {
+        SigningLatencies {
+            observed: init.observed_withdraws.clone(),
+            latencies: init.signing_latencies.clone(),
+            unobserved: 0,
+        }
+    }
+
+    /// records that `withdraw` was observed at unix time `now` unless it was before.
+    pub fn observe(&mut self, withdraw: H256, now: u64) {
+        if self.observed.iter().any(|observed| observed.withdraw == withdraw) {
+            return;
+        }
+        if self.observed.len() >= KEPT_OBSERVED_WITHDRAWS {
+            self.observed.remove(0);
+        }
+        self.observed.push(ObservedWithdraw {
+            withdraw,
+            observed: now,
+        });
+    }
+
+    pub fn record(&mut self, signature: &SubmittedSignature) -> SignatureOutcome {
+        let observed = match self.observed
+            .iter()
+            .find(|observed| observed.withdraw == signature.withdraw)
+        {
+            Some(observed) => observed.observed,
+            None => {
+                self.unobserved += 1;
+                return SignatureOutcome::Unobserved;
+            }
+        };
+        let recorded = self.latencies.iter().any(|latency| {
+            latency.withdraw == signature.withdraw && latency.authority == signature.authority
+        });
+        if recorded {
+            return SignatureOutcome::Duplicate;
+        }
+        if self.latencies.len() >= KEPT_SIGNING_LATENCIES {
+            self.latencies.remove(0);
+        }
+        let latency = SigningLatency {
+            withdraw: signature.withdraw,
+            authority: signature.authority,
+            transaction: signature.transaction,
+            observed,
+            signed: signature.signed,
+        };
+        let seconds = latency.latency();
+        self.latencies.push(latency);
+        SignatureOutcome::Recorded(seconds)
+    }
+
+    pub fn observed_withdraws(&self) -> &[ObservedWithdraw] {
+        &self.observed
+    }
+
+    /// kept signatures, oldest first.
+    pub fn latencies(&self) -> &[SigningLatency] {
+        &self.latencies
+    }
+
+    /// signatures on withdraws this bridge didn't observe since it started.
+    pub fn unobserved(&self) -> u64 {
+        self.unobserved
+    }
+
+    /// percentiles of the kept latencies by authority.
+    pub fn by_authority(&self) -> Vec<AuthorityLatency> {
+        authority_latencies(&self.latencies)
+    }
+}
+
+/// Percentiles of the signing latencies of an authority in seconds, by nearest rank.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuthorityLatency {
+    pub authority: Address,
+    pub signatures: usize,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub max: i64,
+}
+
+fn percentile(sorted: &[i64], percentile: usize) -> i64 {
+    let rank = (sorted.len() * percentile + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+/// percentiles of `latencies` by authority, ordered by authority.
+pub fn authority_latencies(latencies: &[SigningLatency]) -> Vec<AuthorityLatency> {
+    let mut by_authority = BTreeMap::new();
+    for latency in latencies {
+        by_authority
+            .entry(latency.authority)
+            .or_insert_with(Vec::new)
+            .push(latency.latency());
+    }
+    by_authority
+        .into_iter()
+        .map(|(authority, mut seconds)| {
+            seconds.sort();
+            AuthorityLatency {
+                authority,
+                signatures: seconds.len(),
+                p50: percentile(&seconds, 50),
+                p90: percentile(&seconds, 90),
+                p99: percentile(&seconds, 99),
+                max: seconds[seconds.len() - 1],
+            }
+        })
+        .collect()
+}
+
+/// Signing latencies by authority recorded in a database, printed by `--authority-latency`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LatencyReport {
+    /// unix time the counted signatures were submitted at or after
+    pub since: u64,
+    pub signatures: usize,
+    pub authorities: Vec<AuthorityLatency>,
+}
+
+impl LatencyReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialization can't fail; qed")
+    }
+}
+
+/// the latencies of the signatures `database` records as submitted at or after unix time
+/// `since`, by authority.
+pub fn authority_latency_report(database: &Database, since: u64) -> LatencyReport {
+    let latencies: Vec<_> = database
+        .signing_latencies
+        .iter()
+        .filter(|latency| latency.signed >= since)
+        .cloned()
+        .collect();
+    LatencyReport {
+        since,
+        signatures: latencies.len(),
+        authorities: authority_latencies(&latencies),
+    }
+}
+
+enum SigningLatencyState<T: Transport> {
+    /// waiting for the next confirmed range of signature events
+    Wait,
+    /// fetching the transactions and blocks of the events
+    Fetch {
+        logs: Vec<Log>,
+        blocks: Vec<u64>,
+        future: Join<
+            JoinAll<Vec<Settle<Timeout<ApiCall<Option<Transaction>, T::Out>>>>>,
+            JoinAll<Vec<Settle<Timeout<ApiCall<Block<H256>, T::Out>>>>>,
+        >,
+    },
+}
+
+/// Watches the signatures all authorities submit to `ForeignBridge`.
+/// yields the signatures of every confirmed range of blocks.
+/// costs a transaction and a block request per range and event.
+pub struct SigningLatencyMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    logs: LogStream<T>,
+    state: SigningLatencyState<T>,
+}
+
+/// watches the blocks after the last checked withdraw confirm.
+pub fn create_signing_latency_monitor<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+) -> SigningLatencyMonitor<T> {
+    let logs_init = api::LogStreamInit {
+        after: init.checked_withdraw_confirm,
+        chain: "foreign",
+        request_timeout: app.config.foreign.request_timeout,
+        poll_interval: app.tunables.get().foreign_poll_interval,
+        poll_jitter: jitter::poll_jitter(app.config.poll_jitter, &app.config.foreign.account),
+        confirmations: app.config.foreign.required_confirmations,
+        sanity_check: app.config.foreign.logs_sanity_check.clone(),
+        head_regression: app.config.foreign.head_regression,
+        log_limit_errors: app.config.foreign.log_limit_errors.clone(),
+        max_log_data: app.config.foreign.max_log_data,
+        finality: app.config.foreign.l1_posted_rpc(),
+        filter: FilterBuilder::default()
+            .address(vec![init.foreign_contract_address])
+            .topics(Some(signature_topics()), None, None, None),
+    };
+    SigningLatencyMonitor {
+        logs: api::log_stream(
+            app.connections.foreign.clone(),
+            app.timer.clone(),
+            logs_init,
+        ),
+        app,
+        state: SigningLatencyState::Wait,
+    }
+}
+
+impl<T: Transport> Stream for SigningLatencyMonitor<T> {
+    type Item = Vec<SubmittedSignature>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let (next_state, signatures) = match self.state {
+                SigningLatencyState::Wait => {
+                    let item = try_stream!(self.logs.poll());
+                    if item.logs.is_empty() {
+                        return Ok(Async::Ready(Some(Vec::new())));
+                    }
+                    let mut blocks: Vec<u64> = item.logs
+                        .iter()
+                        .filter_map(|log| log.block_number.map(|block| block.low_u64()))
+                        .collect();
+                    blocks.sort();
+                    blocks.dedup();
+                    let app = &self.app;
+                    let timeout = app.config.foreign.request_timeout;
+                    let transactions = item.logs
+                        .iter()
+                        .map(|log| {
+                            let hash = log.transaction_hash.unwrap_or_default();
+                            api::settle(api::timeout(
+                                &app.timer,
+                                api::transaction(&app.connections.foreign, hash),
+                                "foreign",
+                                timeout,
+                            ).about(hash))
+                        })
+                        .collect();
+                    let block_requests = blocks
+                        .iter()
+                        .map(|block| {
+                            api::settle(api::timeout(
+                                &app.timer,
+                                api::block_by_number(&app.connections.foreign, *block),
+                                "foreign",
+                                timeout,
+                            ))
+                        })
+                        .collect();
+                    let state = SigningLatencyState::Fetch {
+                        logs: item.logs,
+                        blocks,
+                        future: join_all(transactions).join(join_all(block_requests)),
+                    };
+                    (state, None)
+                }
+                SigningLatencyState::Fetch {
+                    ref logs,
+                    ref blocks,
+                    ref mut future,
+                } => {
+                    let (transactions, fetched_blocks) = try_ready!(future.poll());
+                    let mut times = BTreeMap::new();
+                    for (block, fetched) in blocks.iter().zip(fetched_blocks) {
+                        match fetched {
+                            Ok(fetched) => {
+                                let time = self.app.chain_clocks.foreign.time_of(
+                                    self.app.config.chain_clock.as_ref(),
+                                    *block,
+                                    fetched.timestamp.low_u64(),
+                                );
+                                times.insert(*block, time);
+                            }
+                            Err(err) => warn!(
+                                target: "bridge::signing_latency",
+                                "cannot fetch foreign block {}, its signatures aren't timed: {}",
+                                block,
+                                err
+                            ),
+                        }
+                    }
+                    let mut signatures = Vec::new();
+                    for (log, transaction) in logs.iter().zip(transactions) {
+                        let block = log.block_number.map_or(0, |block| block.low_u64());
+                        let signature = match (transaction, times.get(&block)) {
+                            (Ok(Some(transaction)), Some(signed)) => {
+                                SubmittedSignature::from_transaction(log, &transaction, *signed)
+                            }
+                            (Ok(Some(_)), None) => continue,
+                            (Ok(None), _) => Err("the node doesn't know it".into()),
+                            (Err(err), _) => Err(err),
+                        };
+                        match signature {
+                            Ok(signature) => signatures.push(signature),
+                            Err(err) => debug!(
+                                target: "bridge::signing_latency",
+                                "signature in foreign transaction {:?} isn't timed: {}",
+                                log.transaction_hash,
+                                err
+                            ),
+                        }
+                    }
+                    (SigningLatencyState::Wait, Some(signatures))
+                }
+            };
+            self.state = next_state;
+            if let Some(signatures) = signatures {
+                return Ok(Async::Ready(Some(signatures)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::{self, Token};
+    use tiny_keccak::keccak256;
+    use web3::types::{Log, Transaction, U256};
+    use database::{Database, SigningLatency};
+    use message_to_mainnet::MessageToMainnet;
+    use super::{authority_latency_report, signature_topics, AuthorityLatency, SignatureOutcome,
+                SigningLatencies, SubmittedSignature, KEPT_OBSERVED_WITHDRAWS};
+
+    fn signature(withdraw: u64, authority: u64, signed: u64) -> SubmittedSignature {
+        SubmittedSignature {
+            withdraw: withdraw.into(),
+            authority: authority.into(),
+            transaction: (withdraw * 100 + authority).into(),
+            block: 10,
+            signed,
+        }
+    }
+
+    fn message() -> MessageToMainnet {
+        MessageToMainnet {
+            recipient: 0xaa.into(),
+            value: 100.into(),
+            sidenet_transaction_hash: 0x11.into(),
+            mainnet_gas_price: 20.into(),
+        }
+    }
+
+    fn submission(message: &[u8]) -> Transaction {
+        let mut input = keccak256(b"submitSignature(bytes,bytes)")[..4].to_vec();
+        input.extend(ethabi::encode(&[
+            Token::Bytes(vec![0x5; 65]),
+            Token::Bytes(message.to_vec()),
+        ]));
+        Transaction {
+            from: 0xbb.into(),
+            input: input.into(),
+            ..Transaction::default()
+        }
+    }
+
+    fn event(data: Vec<u8>) -> Log {
+        Log {
+            topics: vec![signature_topics()[0]],
+            data: data.into(),
+            block_number: Some(U256::from(7)),
+            transaction_hash: Some(0xcc.into()),
+            ..Log::default()
+        }
+    }
+
+    #[test]
+    fn test_submitted_signature_from_transaction() {
+        let bytes = message().to_bytes();
+        let log = event(message().hash().to_vec());
+        assert_eq!(
+            SubmittedSignature {
+                withdraw: 0x11.into(),
+                authority: 0xbb.into(),
+                transaction: 0xcc.into(),
+                block: 7,
+                signed: 1000,
+            },
+            SubmittedSignature::from_transaction(&log, &submission(&bytes), 1000).unwrap()
+        );
+        // `CollectedSignatures` has the message hash after the authority
+        let mut data = vec![0u8; 32];
+        data.extend(message().hash().to_vec());
+        let collected = Log {
+            topics: vec![signature_topics()[1]],
+            ..event(data)
+        };
+        assert!(SubmittedSignature::from_transaction(&collected, &submission(&bytes), 0).is_ok());
+
+        // another message than the one of the event
+        let other = MessageToMainnet {
+            value: 200.into(),
+            ..message()
+        };
+        let submitted = submission(&other.to_bytes());
+        assert!(SubmittedSignature::from_transaction(&log, &submitted, 0).is_err());
+        // a call through a contract
+        let proxied = Transaction {
+            input: vec![0x12, 0x34, 0x56, 0x78].into(),
+            ..submission(&bytes)
+        };
+        assert!(SubmittedSignature::from_transaction(&log, &proxied, 0).is_err());
+    }
+
+    #[test]
+    fn test_signing_latencies_exclude_unobserved_withdraws() {
+        let mut latencies = SigningLatencies::new(&Database::default());
+        latencies.observe(1.into(), 1000);
+        // observed again after a restart, keeps the first time
+        latencies.observe(1.into(), 1050);
+
+        assert_eq!(SignatureOutcome::Recorded(30), latencies.record(&signature(1, 0xa, 1030)));
+        assert_eq!(SignatureOutcome::Duplicate, latencies.record(&signature(1, 0xa, 1040)));
+        // signed before this bridge observed the withdraw
+        assert_eq!(SignatureOutcome::Recorded(-5), latencies.record(&signature(1, 0xb, 995)));
+        assert_eq!(SignatureOutcome::Unobserved, latencies.record(&signature(2, 0xa, 1030)));
+        assert_eq!(1, latencies.unobserved());
+        assert_eq!(2, latencies.latencies().len());
+
+        let database = Database {
+            observed_withdraws: latencies.observed_withdraws().to_vec(),
+            signing_latencies: latencies.latencies().to_vec(),
+            ..Database::default()
+        };
+        let restarted = SigningLatencies::new(&database);
+        assert_eq!(latencies.latencies(), restarted.latencies());
+        assert_eq!(0, restarted.unobserved());
+    }
+
+    #[test]
+    fn test_signing_latencies_keep_the_latest_observations() {
+        let mut latencies = SigningLatencies::new(&Database::default());
+        for withdraw in 0..KEPT_OBSERVED_WITHDRAWS as u64 + 1 {
+            latencies.observe(withdraw.into(), 1000 + withdraw);
+        }
+        assert_eq!(KEPT_OBSERVED_WITHDRAWS, latencies.observed_withdraws().len());
+        assert_eq!(SignatureOutcome::Unobserved, latencies.record(&signature(0, 0xa, 2000)));
+        assert_eq!(SignatureOutcome::Recorded(999), latencies.record(&signature(1, 0xa, 2000)));
+    }
+
+    #[test]
+    fn test_latency_percentiles_by_authority() {
+        let mut latencies = SigningLatencies::new(&Database::default());
+        for withdraw in 1..101 {
+            latencies.observe(withdraw.into(), 1000);
+            latencies.record(&signature(withdraw, 0xa, 1000 + withdraw));
+        }
+        latencies.record(&signature(1, 0xb, 1200));
+        assert_eq!(
+            vec![
+                AuthorityLatency {
+                    authority: 0xa.into(),
+                    signatures: 100,
+                    p50: 50,
+                    p90: 90,
+                    p99: 99,
+                    max: 100,
+                },
+                AuthorityLatency {
+                    authority: 0xb.into(),
+                    signatures: 1,
+                    p50: 200,
+                    p90: 200,
+                    p99: 200,
+                    max: 200,
+                },
+            ],
+            latencies.by_authority()
+        );
+    }
+
+    #[test]
+    fn test_authority_latency_report() {
+        let latency = |authority: u64, signed: u64| SigningLatency {
+            withdraw: 1.into(),
+            authority: authority.into(),
+            transaction: 2.into(),
+            observed: 1000,
+            signed,
+        };
+        let database = Database {
+            signing_latencies: vec![latency(0xa, 1010), latency(0xb, 1990), latency(0xa, 2030)],
+            ..Database::default()
+        };
+        let report = authority_latency_report(&database, 1500);
+        assert_eq!(2, report.signatures);
+        assert_eq!(2, report.authorities.len());
+        assert_eq!(1030, report.authorities[0].max);
+        assert_eq!(990, report.authorities[1].p50);
+        assert!(report.to_json().contains("\"p99\": 1030"), "{}", report.to_json());
+        assert!(authority_latency_report(&database, 3000).authorities.is_empty());
+    }
+}
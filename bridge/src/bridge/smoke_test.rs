@@ -0,0 +1,633 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+use futures::{Async, Future, Poll};
+use futures::future::Join;
+use serde_json;
+use timer::Sleep;
+use web3::Transport;
+use web3::types::{Address, Bytes, H256, Log, TransactionRequest, U256};
+use api::{self, ApiCall, ReceiptWithStatus, Timeout};
+use app::App;
+use config::SmokeTestConfig;
+use database::Database;
+use error::{Error, Result};
+use message_to_mainnet::MessageToMainnet;
+use units::format_ether;
+use super::deploy_block::chain_name;
+
+/// Transfers a smoke test sends through the bridge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmokeTestMode {
+    /// a deposit, then a withdraw of it back to home
+    DepositAndWithdraw,
+    DepositOnly,
+    /// a withdraw of tokens the test account already holds on foreign
+    WithdrawOnly,
+}
+
+impl SmokeTestMode {
+    fn deposits(&self) -> bool {
+        *self != SmokeTestMode::WithdrawOnly
+    }
+
+    fn withdraws(&self) -> bool {
+        *self != SmokeTestMode::DepositOnly
+    }
+}
+
+/// fails if the chain id of `home` or `foreign` is one of the `production` chain ids.
+pub fn check_chain_ids(home: u64, foreign: u64, production: &[u64]) -> Result<()> {
+    for &(chain, id) in &[("home", home), ("foreign", foreign)] {
+        if production.contains(&id) {
+            bail!(
+                "refusing to smoke test a production chain: {} has chain id {}, \
+                 listed in smoke_test.production_chain_ids",
+                chain,
+                id
+            );
+        }
+    }
+    Ok(())
+}
+
+/// A completed step of a smoke test.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SmokeTestStep {
+    pub step: String,
+    /// transaction the step sent or saw mined
+    pub transaction: Option<H256>,
+    /// seconds the step took
+    pub secs: u64,
+}
+
+/// Outcome of a smoke test, step by step.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SmokeTestReport {
+    pub home_chain_id: u64,
+    pub foreign_chain_id: u64,
+    /// test account sending the transfers
+    pub account: Address,
+    /// account receiving the withdraw on home
+    pub recipient: Address,
+    /// value of the transfers on home, in wei
+    pub value: U256,
+    pub steps: Vec<SmokeTestStep>,
+    /// why the smoke test stopped early. `None` if every step completed
+    pub failure: Option<String>,
+}
+
+impl SmokeTestReport {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("smoke test report serializes to json; qed")
+    }
+}
+
+impl fmt::Display for SmokeTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "smoke test of home chain {} and foreign chain {}: {} from {:?} to {:?}",
+            self.home_chain_id,
+            self.foreign_chain_id,
+            format_ether(self.value, 0),
+            self.account,
+            self.recipient
+        )?;
+        for step in &self.steps {
+            write!(f, "{:>6}s {}", step.secs, step.step)?;
+            if let Some(transaction) = step.transaction {
+                write!(f, " {:?}", transaction)?;
+            }
+            writeln!(f)?;
+        }
+        match self.failure {
+            None => write!(f, "passed"),
+            Some(ref failure) => write!(f, "failed: {}", failure),
+        }
+    }
+}
+
+/// Check a smoke test repeats until it succeeds or times out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Check {
+    DepositMined,
+    DepositRelayed,
+    WithdrawMined,
+    WithdrawRelayed,
+}
+
+impl Check {
+    /// `true` if the check asks home.
+    fn on_home(&self) -> bool {
+        match *self {
+            Check::DepositMined | Check::WithdrawRelayed => true,
+            Check::DepositRelayed | Check::WithdrawMined => false,
+        }
+    }
+}
+
+type CallFuture<T> = Timeout<ApiCall<Bytes, <T as Transport>::Out>>;
+type SendFuture<T> = Timeout<ApiCall<H256, <T as Transport>::Out>>;
+type ReceiptFuture<T> = Timeout<ApiCall<Option<ReceiptWithStatus>, <T as Transport>::Out>>;
+type ChainIdFuture<T> = Timeout<ApiCall<U256, <T as Transport>::Out>>;
+
+enum SmokeTestState<T: Transport> {
+    /// Fetching the chain ids of both nodes.
+    FetchChainIds(Join<ChainIdFuture<T>, ChainIdFuture<T>>),
+    /// Reading the foreign balance of the test account before the deposit.
+    FetchBalance(CallFuture<T>),
+    /// Sending the deposit to `HomeBridge`.
+    SendDeposit(SendFuture<T>),
+    FetchDepositReceipt(ReceiptFuture<T>),
+    /// Reading the foreign balance of the test account until the deposit raised it.
+    FetchDepositBalance(CallFuture<T>),
+    /// Sending `ForeignBridge.transferHomeViaRelay`.
+    SendWithdraw(SendFuture<T>),
+    FetchWithdrawReceipt(ReceiptFuture<T>),
+    /// Asking `HomeBridge.isWithdrawExecuted` until the withdraw was relayed.
+    FetchWithdrawExecuted(CallFuture<T>),
+    /// Waiting to repeat a check.
+    Wait(Sleep, Check),
+    Done,
+}
+
+/// sends a transaction from the test account to `to` on home or foreign.
+fn send<T: Transport>(
+    app: &App<T>,
+    config: &SmokeTestConfig,
+    on_home: bool,
+    to: Address,
+    value: Option<U256>,
+    data: Option<Bytes>,
+) -> SendFuture<T> {
+    let (transport, node) = if on_home {
+        (&app.connections.home, &app.config.home)
+    } else {
+        (&app.connections.foreign, &app.config.foreign)
+    };
+    // the nodes estimate the gas and price the transactions of the test account
+    let tx = TransactionRequest {
+        from: config.account,
+        to: Some(to),
+        gas: None,
+        gas_price: None,
+        value,
+        data,
+        nonce: None,
+        condition: None,
+    };
+    let call = match config.password {
+        Some(ref password) => api::personal_send_transaction(transport, tx, password),
+        None => api::send_transaction(transport, tx),
+    };
+    api::timeout(&app.timer, call, chain_name(on_home), node.request_timeout)
+}
+
+fn receipt<T: Transport>(app: &App<T>, on_home: bool, hash: H256) -> ReceiptFuture<T> {
+    let (transport, node) = if on_home {
+        (&app.connections.home, &app.config.home)
+    } else {
+        (&app.connections.foreign, &app.config.foreign)
+    };
+    api::timeout(
+        &app.timer,
+        api::transaction_receipt_with_status(transport, hash),
+        chain_name(on_home),
+        node.request_timeout,
+    ).about(hash)
+}
+
+fn foreign_balance<T: Transport>(
+    app: &App<T>,
+    foreign_contract: Address,
+    account: Address,
+) -> CallFuture<T> {
+    let payload = app.foreign_bridge.functions().balance_of().input(account);
+    api::timeout(
+        &app.timer,
+        api::call(&app.connections.foreign, foreign_contract, payload.into()),
+        "foreign",
+        app.config.foreign.request_timeout,
+    )
+}
+
+fn withdraw_executed<T: Transport>(
+    app: &App<T>,
+    home_contract: Address,
+    id: H256,
+) -> CallFuture<T> {
+    let payload = app.home_bridge
+        .functions()
+        .is_withdraw_executed()
+        .input(id.0);
+    api::timeout(
+        &app.timer,
+        api::call(&app.connections.home, home_contract, payload.into()),
+        "home",
+        app.config.home.request_timeout,
+    )
+}
+
+/// id of the first `Withdraw` of `foreign_contract` in `logs`, see `util::log_id`.
+fn withdraw_id<T: Transport>(
+    app: &App<T>,
+    foreign_contract: Address,
+    logs: Vec<Log>,
+) -> Option<H256> {
+    let unique_ids = app.config.unique_log_ids;
+    logs.into_iter()
+        .filter(|log| log.address == foreign_contract)
+        .filter_map(|log| {
+            // other events of the contract don't parse
+            let message = match app.abi {
+                Some(ref abi) => abi.withdraw_message(log, unique_ids).ok(),
+                None => MessageToMainnet::from_log(log, unique_ids).ok(),
+            };
+            message.map(|message| message.sidenet_transaction_hash)
+        })
+        .next()
+}
+
+/// smoke tests the bridge between the contracts of `init` with transfers of `value` wei
+/// from the test account of `smoke_test`.
+pub fn create_smoke_test<T: Transport>(
+    app: Arc<App<T>>,
+    init: &Database,
+    mode: SmokeTestMode,
+    recipient: Address,
+    value: U256,
+) -> Result<SmokeTest<T>> {
+    let config = match app.config.smoke_test {
+        Some(ref config) => config.clone(),
+        None => bail!("--smoke-test requires the [smoke_test] section with the test account"),
+    };
+    if mode == SmokeTestMode::DepositAndWithdraw && app.config.derived_recipients.is_some() {
+        bail!(
+            "the deposit can't be withdrawn: derived_recipients credits it to the contract wallet \
+             of the test account. use --deposit-only"
+        );
+    }
+    let foreign_value = match app.config.value_scale {
+        Some(ref scale) => scale.deposit(value)?.value,
+        None => value,
+    };
+    let home = api::timeout(
+        &app.timer,
+        api::chain_id(&app.connections.home),
+        "home",
+        app.config.home.request_timeout,
+    );
+    let foreign = api::timeout(
+        &app.timer,
+        api::chain_id(&app.connections.foreign),
+        "foreign",
+        app.config.foreign.request_timeout,
+    );
+    let state = SmokeTestState::FetchChainIds(home.join(foreign));
+    let now = Instant::now();
+    let run = Run {
+        report: SmokeTestReport {
+            home_chain_id: 0,
+            foreign_chain_id: 0,
+            account: config.account,
+            recipient,
+            value,
+            steps: Vec::new(),
+            failure: None,
+        },
+        home_contract: init.home_contract_address,
+        foreign_contract: init.foreign_contract_address,
+        mode,
+        foreign_value,
+        balance: U256::zero(),
+        deposit: None,
+        withdraw: None,
+        withdraw_id: None,
+        step_started: now,
+        deadline: now + config.timeout,
+        config,
+        app,
+    };
+    Ok(SmokeTest { run, state })
+}
+
+/// A smoke test but its state.
+struct Run<T: Transport> {
+    app: Arc<App<T>>,
+    config: SmokeTestConfig,
+    home_contract: Address,
+    foreign_contract: Address,
+    mode: SmokeTestMode,
+    /// value of the transfers on foreign, converted by `value_scale`
+    foreign_value: U256,
+    /// foreign balance of the test account before the deposit
+    balance: U256,
+    deposit: Option<H256>,
+    withdraw: Option<H256>,
+    withdraw_id: Option<H256>,
+    report: SmokeTestReport,
+    /// when the current step started
+    step_started: Instant,
+    /// when the transfer waited for times out
+    deadline: Instant,
+}
+
+impl<T: Transport> Run<T> {
+    fn step(&mut self, step: &str, transaction: Option<H256>) {
+        let now = Instant::now();
+        info!(target: "bridge::smoke_test", "{}", step);
+        self.report.steps.push(SmokeTestStep {
+            step: step.into(),
+            transaction,
+            secs: now.duration_since(self.step_started).as_secs(),
+        });
+        self.step_started = now;
+    }
+
+    /// starts waiting up to `smoke_test.timeout` for the transfer sent last.
+    fn sent(&mut self) {
+        self.deadline = Instant::now() + self.config.timeout;
+    }
+
+    fn fetch_balance(&self) -> CallFuture<T> {
+        foreign_balance(&self.app, self.foreign_contract, self.config.account)
+    }
+
+    fn balance_of(&self, output: Bytes) -> Result<U256> {
+        Ok(self.app
+            .foreign_bridge
+            .functions()
+            .balance_of()
+            .output(&output.0)?)
+    }
+
+    /// `ForeignBridge.transferHomeViaRelay` of `foreign_value` to the recipient.
+    fn send_withdraw(&self) -> SmokeTestState<T> {
+        let home_gas_price = self.app.tunables.get().txs.withdraw_relay.gas_price;
+        let payload = self.app.foreign_bridge.functions().transfer_home_via_relay().input(
+            self.report.recipient,
+            self.foreign_value,
+            U256::from(home_gas_price),
+        );
+        SmokeTestState::SendWithdraw(send(
+            &self.app,
+            &self.config,
+            false,
+            self.foreign_contract,
+            None,
+            Some(payload.into()),
+        ))
+    }
+
+    /// repeats `check` after the poll interval of its chain, unless the deadline passed,
+    /// in which case the smoke test fails with `failure`.
+    fn wait_or_fail(&mut self, check: Check, failure: String) -> SmokeTestState<T> {
+        if Instant::now() >= self.deadline {
+            self.report.failure = Some(failure);
+            return SmokeTestState::Done;
+        }
+        let poll_interval = self.app.tunables.get().poll_interval(check.on_home());
+        SmokeTestState::Wait(self.app.timer.sleep(poll_interval), check)
+    }
+
+    /// the state repeating `check`.
+    fn repeat(&self, check: Check) -> SmokeTestState<T> {
+        match check {
+            Check::DepositMined => SmokeTestState::FetchDepositReceipt(receipt(
+                &self.app,
+                true,
+                self.deposit.expect("the deposit was sent before it's checked; qed"),
+            )),
+            Check::DepositRelayed => SmokeTestState::FetchDepositBalance(self.fetch_balance()),
+            Check::WithdrawMined => SmokeTestState::FetchWithdrawReceipt(receipt(
+                &self.app,
+                false,
+                self.withdraw.expect("the withdraw was sent before it's checked; qed"),
+            )),
+            Check::WithdrawRelayed => SmokeTestState::FetchWithdrawExecuted(withdraw_executed(
+                &self.app,
+                self.home_contract,
+                self.withdraw_id
+                    .expect("the withdraw was mined before it's checked; qed"),
+            )),
+        }
+    }
+}
+
+/// Future sending a deposit and a withdraw from a test account through the bridge and
+/// waiting for the bridge to relay them. resolves to the report of the steps.
+/// transfers that aren't relayed within `smoke_test.timeout` fail the report.
+pub struct SmokeTest<T: Transport> {
+    run: Run<T>,
+    state: SmokeTestState<T>,
+}
+
+impl<T: Transport> Future for SmokeTest<T> {
+    type Item = SmokeTestReport;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let run = &mut self.run;
+            let next_state = match self.state {
+                SmokeTestState::FetchChainIds(ref mut future) => {
+                    let (home, foreign) = try_ready!(future.poll());
+                    let (home, foreign) = (home.low_u64(), foreign.low_u64());
+                    check_chain_ids(home, foreign, &run.config.production_chain_ids)?;
+                    run.report.home_chain_id = home;
+                    run.report.foreign_chain_id = foreign;
+                    run.step("checked the chain ids", None);
+                    if run.mode.deposits() {
+                        SmokeTestState::FetchBalance(run.fetch_balance())
+                    } else {
+                        run.send_withdraw()
+                    }
+                }
+                SmokeTestState::FetchBalance(ref mut future) => {
+                    let output = try_ready!(future.poll());
+                    run.balance = run.balance_of(output)?;
+                    SmokeTestState::SendDeposit(send(
+                        &run.app,
+                        &run.config,
+                        true,
+                        run.home_contract,
+                        Some(run.report.value),
+                        None,
+                    ))
+                }
+                SmokeTestState::SendDeposit(ref mut future) => {
+                    let hash = try_ready!(future.poll());
+                    run.deposit = Some(hash);
+                    run.step("sent the deposit to HomeBridge", Some(hash));
+                    run.sent();
+                    SmokeTestState::FetchDepositReceipt(receipt(&run.app, true, hash))
+                }
+                SmokeTestState::FetchDepositReceipt(ref mut future) => {
+                    match try_ready!(future.poll()) {
+                        None => {
+                            let failure = format!(
+                                "the deposit wasn't mined within {}s",
+                                run.config.timeout.as_secs()
+                            );
+                            run.wait_or_fail(Check::DepositMined, failure)
+                        }
+                        Some(ref receipt) if receipt.reverted() => {
+                            run.report.failure = Some("the deposit reverted on home".into());
+                            SmokeTestState::Done
+                        }
+                        Some(_) => {
+                            let deposit = run.deposit;
+                            run.step("the deposit was mined on home", deposit);
+                            SmokeTestState::FetchDepositBalance(run.fetch_balance())
+                        }
+                    }
+                }
+                SmokeTestState::FetchDepositBalance(ref mut future) => {
+                    let output = try_ready!(future.poll());
+                    let balance = run.balance_of(output)?;
+                    // the tokens on foreign are backed by ether on home, they don't overflow
+                    if balance >= run.balance + run.foreign_value {
+                        run.step("the deposit was relayed to foreign", None);
+                        if run.mode.withdraws() {
+                            run.send_withdraw()
+                        } else {
+                            SmokeTestState::Done
+                        }
+                    } else {
+                        let failure = format!(
+                            "the deposit wasn't relayed within {}s, see --lookup-deposit {:?}",
+                            run.config.timeout.as_secs(),
+                            run.deposit.expect("the deposit was sent; qed")
+                        );
+                        run.wait_or_fail(Check::DepositRelayed, failure)
+                    }
+                }
+                SmokeTestState::SendWithdraw(ref mut future) => {
+                    let hash = try_ready!(future.poll());
+                    run.withdraw = Some(hash);
+                    run.step("sent the withdraw to ForeignBridge", Some(hash));
+                    run.sent();
+                    SmokeTestState::FetchWithdrawReceipt(receipt(&run.app, false, hash))
+                }
+                SmokeTestState::FetchWithdrawReceipt(ref mut future) => {
+                    match try_ready!(future.poll()) {
+                        None => {
+                            let failure = format!(
+                                "the withdraw wasn't mined within {}s",
+                                run.config.timeout.as_secs()
+                            );
+                            run.wait_or_fail(Check::WithdrawMined, failure)
+                        }
+                        Some(ref receipt) if receipt.reverted() => {
+                            run.report.failure = Some(
+                                "the withdraw reverted on foreign: the test account holds less \
+                                 than the value or the value doesn't cover the gas of the relay"
+                                    .into(),
+                            );
+                            SmokeTestState::Done
+                        }
+                        Some(receipt) => {
+                            let logs = receipt.receipt.logs;
+                            let id = withdraw_id(&run.app, run.foreign_contract, logs)
+                                .ok_or_else(|| "the withdraw emitted no ForeignBridge.Withdraw")?;
+                            run.withdraw_id = Some(id);
+                            let withdraw = run.withdraw;
+                            run.step("the withdraw was mined on foreign", withdraw);
+                            SmokeTestState::FetchWithdrawExecuted(withdraw_executed(
+                                &run.app,
+                                run.home_contract,
+                                id,
+                            ))
+                        }
+                    }
+                }
+                SmokeTestState::FetchWithdrawExecuted(ref mut future) => {
+                    let output = try_ready!(future.poll());
+                    let executed = run.app
+                        .home_bridge
+                        .functions()
+                        .is_withdraw_executed()
+                        .output(&output.0)?;
+                    if executed {
+                        run.step("the withdraw was relayed to home", None);
+                        SmokeTestState::Done
+                    } else {
+                        let failure = format!(
+                            "the withdraw wasn't relayed within {}s, see --show-message {:?}",
+                            run.config.timeout.as_secs(),
+                            run.withdraw.expect("the withdraw was sent; qed")
+                        );
+                        run.wait_or_fail(Check::WithdrawRelayed, failure)
+                    }
+                }
+                SmokeTestState::Wait(ref mut future, check) => {
+                    try_ready!(future.poll());
+                    run.repeat(check)
+                }
+                SmokeTestState::Done => return Ok(Async::Ready(run.report.clone())),
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_chain_ids, SmokeTestReport, SmokeTestStep};
+
+    #[test]
+    fn test_check_chain_ids() {
+        assert!(check_chain_ids(5, 77, &[1]).is_ok());
+        assert!(check_chain_ids(5, 77, &[]).is_ok());
+        let err = check_chain_ids(1, 77, &[1, 100]).unwrap_err();
+        assert!(err.to_string().contains("home has chain id 1"), "{}", err);
+        let err = check_chain_ids(5, 100, &[1, 100]).unwrap_err();
+        assert!(err.to_string().contains("foreign has chain id 100"), "{}", err);
+    }
+
+    #[test]
+    fn test_smoke_test_report() {
+        let mut report = SmokeTestReport {
+            home_chain_id: 5,
+            foreign_chain_id: 77,
+            account: 0xaa.into(),
+            recipient: 0xbb.into(),
+            value: 10_000_000_000_000_000u64.into(),
+            steps: vec![
+                SmokeTestStep {
+                    step: "checked the chain ids".into(),
+                    transaction: None,
+                    secs: 0,
+                },
+                SmokeTestStep {
+                    step: "sent the deposit to HomeBridge".into(),
+                    transaction: Some(1.into()),
+                    secs: 1,
+                },
+            ],
+            failure: None,
+        };
+        assert!(report.passed());
+        let shown = report.to_string();
+        assert!(shown.starts_with(
+            "smoke test of home chain 5 and foreign chain 77: 0.01 ETH from"
+        ));
+        assert!(shown.contains(&format!(
+            "\n     1s sent the deposit to HomeBridge {:?}\n",
+            ::web3::types::H256::from(1)
+        )));
+        assert!(shown.ends_with("\npassed"));
+
+        report.failure = Some("the deposit wasn't relayed within 60s".into());
+        assert!(!report.passed());
+        assert!(report
+            .to_string()
+            .ends_with("\nfailed: the deposit wasn't relayed within 60s"));
+        assert!(report.to_json().contains("\"failure\""));
+    }
+}
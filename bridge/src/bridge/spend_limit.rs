@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+use web3::types::{H256, U256};
+use database::Spend;
+
+/// Window (seconds) of `spend_limits.deposit_value`.
+pub const DEPOSIT_SPEND_WINDOW: u64 = 24 * 60 * 60;
+
+/// Window (seconds) of `spend_limits.withdraws`.
+pub const WITHDRAW_SPEND_WINDOW: u64 = 60 * 60;
+
+/// seconds since the unix epoch. spend is recorded in wall clock time so it survives restarts.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Relays a spend limit applies to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpendKind {
+    /// value of the deposits relayed to foreign
+    Deposits,
+    /// number of the withdraws relayed to home
+    Withdraws,
+}
+
+/// Limit of the amount relayed within a rolling window.
+///
+/// relays are allowed in order. once one doesn't fit into the limit it and all
+/// relays after it are deferred until the window allows them, the limit is raised
+/// or they are released.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpendLimiter {
+    kind: SpendKind,
+    limit: U256,
+    /// seconds
+    window: u64,
+    /// relays within the window, oldest first
+    spent: VecDeque<Spend>,
+    /// keys of deferred relays allowed regardless of the limit
+    released: Vec<H256>,
+    /// keys of the relays deferred by the last `allow`
+    deferred: Vec<H256>,
+}
+
+impl SpendLimiter {
+    /// limiter restored from the recorded `spent`.
+    /// spend of batches after `checked_block` is dropped since these are relayed again.
+    pub fn new(
+        kind: SpendKind,
+        limit: U256,
+        window: u64,
+        spent: &[Spend],
+        checked_block: u64,
+    ) -> Self {
+        let mut limiter = SpendLimiter {
+            kind,
+            limit,
+            window,
+            spent: spent.iter().cloned().collect(),
+            released: Vec::new(),
+            deferred: Vec::new(),
+        };
+        limiter.forget_after(checked_block);
+        limiter
+    }
+
+    pub fn kind(&self) -> SpendKind {
+        self.kind
+    }
+
+    pub fn limit(&self) -> U256 {
+        self.limit
+    }
+
+    /// changes the limit. deferred relays are allowed again at the next check if they fit.
+    pub fn set_limit(&mut self, limit: U256) {
+        self.limit = limit;
+    }
+
+    /// amount relayed within the window ending at `now`.
+    pub fn spent(&mut self, now: u64) -> U256 {
+        self.prune(now);
+        self.spent
+            .iter()
+            .fold(U256::zero(), |total, spend| total + spend.amount)
+    }
+
+    /// keys of the relays deferred by the last check.
+    pub fn deferred(&self) -> &[H256] {
+        &self.deferred
+    }
+
+    /// allows the deferred relay with `key` at the next check regardless of the limit.
+    /// it isn't counted against the limit. returns `false` if no relay with `key` is deferred.
+    pub fn release(&mut self, key: H256) -> bool {
+        if !self.deferred.contains(&key) {
+            return false;
+        }
+        if !self.released.contains(&key) {
+            self.released.push(key);
+        }
+        true
+    }
+
+    /// number of `relays` (key and amount, in order) that can be relayed at `now`.
+    /// these are recorded as spent for the batch up to `block`, the others are deferred.
+    pub fn allow(&mut self, now: u64, block: u64, relays: &[(H256, U256)]) -> usize {
+        let mut spent = self.spent(now);
+        let mut allowed = 0;
+        for &(key, amount) in relays {
+            if let Some(index) = self.released.iter().position(|released| *released == key) {
+                self.released.remove(index);
+            } else if spent <= self.limit && amount <= self.limit - spent {
+                spent = spent + amount;
+                self.spent.push_back(Spend {
+                    time: now,
+                    amount,
+                    block,
+                });
+            } else {
+                break;
+            }
+            allowed += 1;
+        }
+        self.deferred = relays[allowed..].iter().map(|&(key, _)| key).collect();
+        allowed
+    }
+
+    /// drops the spend of batches after `block`.
+    pub fn forget_after(&mut self, block: u64) {
+        self.spent.retain(|spend| spend.block <= block);
+    }
+
+    /// relays within the window. persisted in the database.
+    pub fn to_spend(&self) -> Vec<Spend> {
+        self.spent.iter().cloned().collect()
+    }
+
+    fn prune(&mut self, now: u64) {
+        let window = self.window;
+        self.spent.retain(|spend| spend.time + window > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::{H256, U256};
+    use database::Spend;
+    use super::{SpendKind, SpendLimiter};
+
+    fn relays(amounts: &[u64]) -> Vec<(H256, U256)> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(index, amount)| ((index as u64 + 1).into(), (*amount).into()))
+            .collect()
+    }
+
+    #[test]
+    fn test_spend_limiter_defers_relays_beyond_the_limit() {
+        let mut limiter = SpendLimiter::new(SpendKind::Deposits, 10.into(), 100, &[], 0);
+
+        assert_eq!(2, limiter.allow(1000, 5, &relays(&[4, 5, 2, 1])));
+        assert_eq!(U256::from(9), limiter.spent(1000));
+        // the relay of 1 fits but comes after the deferred one
+        let deferred: Vec<H256> = vec![3.into(), 4.into()];
+        assert_eq!(deferred, limiter.deferred());
+
+        // nothing expired yet
+        assert_eq!(0, limiter.allow(1099, 5, &relays(&[2, 1])));
+        // the window moved past the first relays
+        assert_eq!(2, limiter.allow(1100, 6, &relays(&[2, 1])));
+        assert!(limiter.deferred().is_empty());
+        assert_eq!(U256::from(3), limiter.spent(1100));
+
+        // a single relay above the limit waits for a higher limit
+        assert_eq!(0, limiter.allow(1300, 7, &relays(&[11])));
+        limiter.set_limit(20.into());
+        assert_eq!(1, limiter.allow(1300, 7, &relays(&[11])));
+    }
+
+    #[test]
+    fn test_spend_limiter_release() {
+        let mut limiter = SpendLimiter::new(SpendKind::Withdraws, 1.into(), 100, &[], 0);
+        assert_eq!(1, limiter.allow(1000, 5, &relays(&[1, 1, 1])));
+        assert!(!limiter.release(1.into()));
+        assert!(limiter.release(2.into()));
+
+        // the released relay passes without being counted
+        assert_eq!(1, limiter.allow(1000, 5, &relays(&[1, 1, 1])[1..]));
+        assert_eq!(U256::from(1), limiter.spent(1000));
+        let deferred: Vec<H256> = vec![3.into()];
+        assert_eq!(deferred, limiter.deferred());
+    }
+
+    #[test]
+    fn test_spend_limiter_restores_checked_spend() {
+        let spend = |time, block| Spend {
+            time,
+            amount: 1.into(),
+            block,
+        };
+        let recorded = vec![spend(100, 5), spend(200, 6), spend(300, 7)];
+        // the batch of block 7 wasn't checked and is relayed again
+        let mut limiter = SpendLimiter::new(SpendKind::Withdraws, 5.into(), 1000, &recorded, 6);
+        assert_eq!(vec![spend(100, 5), spend(200, 6)], limiter.to_spend());
+        assert_eq!(U256::from(2), limiter.spent(1000));
+        assert_eq!(U256::from(1), limiter.spent(1100));
+        assert_eq!(vec![spend(200, 6)], limiter.to_spend());
+    }
+}
@@ -0,0 +1,282 @@
+use std::cmp;
+use std::sync::Arc;
+use futures::{Async, Future, Poll, Stream};
+use futures::future::Join;
+use timer::Interval;
+use web3::Transport;
+use web3::types::{Bytes, U256};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use error::Error;
+use super::deploy_block::{chain, chain_name};
+
+/// Blocks below its head a node still has the state of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateHorizon {
+    /// head of the chain when probed
+    pub head: u64,
+    /// the node has the state of `head - depth`
+    pub depth: u64,
+    /// `true` if the node lacks the state of older blocks within the probed depth,
+    /// e.g. because it prunes old state
+    pub pruned: bool,
+}
+
+impl StateHorizon {
+    /// `true` unless the node is known to lack the state of `block`.
+    pub fn has_state(&self, block: u64) -> bool {
+        !self.pruned || block >= self.head.saturating_sub(self.depth)
+    }
+}
+
+/// Last probed state horizon of both chains.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateHorizons {
+    pub home: StateHorizon,
+    pub foreign: StateHorizon,
+}
+
+enum ProbeStateHorizonState<T: Transport> {
+    /// Fetching the head of the chain.
+    FetchHead(Timeout<ApiCall<U256, T::Out>>),
+    /// Reading state at `head - depth`.
+    FetchCode {
+        future: Timeout<ApiCall<Bytes, T::Out>>,
+        head: u64,
+        depth: u64,
+        /// `true` once a deeper read lacked state
+        pruned: bool,
+    },
+}
+
+fn fetch_code<T: Transport>(
+    app: &App<T>,
+    on_home: bool,
+    block: u64,
+) -> Timeout<ApiCall<Bytes, T::Out>> {
+    let (transport, node) = chain(app, on_home);
+    // any account will do, reading it needs the state of the block
+    api::timeout(
+        &app.timer,
+        api::code(transport, node.account, block),
+        chain_name(on_home),
+        node.request_timeout,
+    )
+}
+
+/// Future resolving to the state horizon of the node of home if `on_home`, foreign otherwise.
+/// reads state `max_depth` blocks below the head and halves the depth until the node has it.
+pub struct ProbeStateHorizon<T: Transport> {
+    app: Arc<App<T>>,
+    on_home: bool,
+    max_depth: u64,
+    state: ProbeStateHorizonState<T>,
+}
+
+pub fn probe_state_horizon<T: Transport>(
+    app: Arc<App<T>>,
+    on_home: bool,
+    max_depth: u64,
+) -> ProbeStateHorizon<T> {
+    let state = {
+        let (transport, node) = chain(&app, on_home);
+        ProbeStateHorizonState::FetchHead(api::timeout(
+            &app.timer,
+            api::block_number(transport),
+            chain_name(on_home),
+            node.request_timeout,
+        ))
+    };
+    ProbeStateHorizon {
+        app,
+        on_home,
+        max_depth,
+        state,
+    }
+}
+
+impl<T: Transport> Future for ProbeStateHorizon<T> {
+    type Item = StateHorizon;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                ProbeStateHorizonState::FetchHead(ref mut future) => {
+                    let head = try_ready!(future.poll()).low_u64();
+                    let depth = cmp::min(self.max_depth, head);
+                    if depth == 0 {
+                        return Ok(Async::Ready(StateHorizon {
+                            head,
+                            depth,
+                            pruned: false,
+                        }));
+                    }
+                    ProbeStateHorizonState::FetchCode {
+                        future: fetch_code(&self.app, self.on_home, head - depth),
+                        head,
+                        depth,
+                        pruned: false,
+                    }
+                }
+                ProbeStateHorizonState::FetchCode {
+                    ref mut future,
+                    head,
+                    depth,
+                    pruned,
+                } => match future.poll().map_err(Error::from) {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(_)) => {
+                        return Ok(Async::Ready(StateHorizon {
+                            head,
+                            depth,
+                            pruned,
+                        }))
+                    }
+                    Err(ref err) if api::is_missing_state(err) => {
+                        if depth == 1 {
+                            return Ok(Async::Ready(StateHorizon {
+                                head,
+                                depth: 0,
+                                pruned: true,
+                            }));
+                        }
+                        ProbeStateHorizonState::FetchCode {
+                            future: fetch_code(&self.app, self.on_home, head - depth / 2),
+                            head,
+                            depth: depth / 2,
+                            pruned: true,
+                        }
+                    }
+                    Err(err) => return Err(err),
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+/// logs `horizon` of `chain` unless it's the same as `previous`.
+fn log_state_horizon(chain: &str, horizon: &StateHorizon, previous: Option<&StateHorizon>) {
+    let changed = previous.map_or(true, |previous| {
+        previous.pruned != horizon.pruned || previous.depth != horizon.depth
+    });
+    if !changed {
+        return;
+    }
+    if horizon.pruned {
+        warn!(
+            "{} node only has the state of the last {} blocks. older state is read at the latest block instead, which may be less accurate",
+            chain, horizon.depth
+        );
+    } else {
+        info!(
+            "{} node has the state of the last {} blocks at least",
+            chain, horizon.depth
+        );
+    }
+}
+
+enum StateHorizonState<T: Transport> {
+    /// Waiting for the next probe.
+    Wait,
+    /// Probing the nodes of both chains.
+    Probe(Join<ProbeStateHorizon<T>, ProbeStateHorizon<T>>),
+    /// Horizons have been probed.
+    Yield(Option<StateHorizons>),
+}
+
+fn probe<T: Transport>(app: &Arc<App<T>>, depth: u64) -> StateHorizonState<T> {
+    StateHorizonState::Probe(
+        probe_state_horizon(app.clone(), true, depth)
+            .join(probe_state_horizon(app.clone(), false, depth)),
+    )
+}
+
+/// probes the nodes right away, then every `interval`.
+pub fn create_state_horizon_monitor<T: Transport>(
+    app: Arc<App<T>>,
+    interval: Interval,
+    depth: u64,
+) -> StateHorizonMonitor<T> {
+    StateHorizonMonitor {
+        state: probe(&app, depth),
+        app,
+        interval,
+        depth,
+        last: None,
+    }
+}
+
+/// Periodically probes up to `depth` blocks below the head of both chains
+/// for the oldest block whose state their nodes still have.
+/// logs a warning if a node prunes state within `depth`.
+pub struct StateHorizonMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    interval: Interval,
+    depth: u64,
+    last: Option<StateHorizons>,
+    state: StateHorizonState<T>,
+}
+
+impl<T: Transport> Stream for StateHorizonMonitor<T> {
+    type Item = StateHorizons;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                StateHorizonState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    probe(&self.app, self.depth)
+                }
+                StateHorizonState::Probe(ref mut future) => {
+                    let (home, foreign) = try_ready!(future.poll());
+                    let last = self.last.as_ref();
+                    log_state_horizon("home", &home, last.map(|last| &last.home));
+                    log_state_horizon("foreign", &foreign, last.map(|last| &last.foreign));
+                    let horizons = StateHorizons { home, foreign };
+                    self.last = Some(horizons);
+                    StateHorizonState::Yield(Some(horizons))
+                }
+                StateHorizonState::Yield(ref mut horizons) => match horizons.take() {
+                    None => StateHorizonState::Wait,
+                    some => return Ok(some.into()),
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StateHorizon;
+
+    #[test]
+    fn test_has_state() {
+        let archive = StateHorizon {
+            head: 10_000,
+            depth: 4096,
+            pruned: false,
+        };
+        assert!(archive.has_state(0));
+
+        let pruned = StateHorizon {
+            head: 10_000,
+            depth: 128,
+            pruned: true,
+        };
+        assert!(pruned.has_state(9872));
+        assert!(pruned.has_state(10_000));
+        assert!(!pruned.has_state(9871));
+
+        let latest_only = StateHorizon {
+            head: 10_000,
+            depth: 0,
+            pruned: true,
+        };
+        assert!(latest_only.has_state(10_000));
+        assert!(!latest_only.has_state(9999));
+    }
+}
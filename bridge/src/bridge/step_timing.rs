@@ -0,0 +1,311 @@
+use std::fmt;
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// upper bounds in milliseconds of the buckets of a `DurationHistogram`.
+/// the last bucket counts the longer steps.
+pub const STEP_BUCKETS_MS: [u64; 7] = [100, 500, 1_000, 5_000, 15_000, 60_000, 300_000];
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000 + u64::from(duration.subsec_nanos()) / 1_000_000
+}
+
+/// Timed step of a relay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayStep {
+    /// checking whether the relays are still needed, e.g. not executed yet and predicted to succeed
+    Check,
+    /// fetching the collected signatures of withdraws or signing them
+    Signatures,
+    /// sending the relay transactions until the node accepted them
+    Submit,
+    /// waiting for the receipts of the relay transactions
+    Confirm,
+}
+
+impl RelayStep {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RelayStep::Check => "check",
+            RelayStep::Signatures => "signatures",
+            RelayStep::Submit => "submit",
+            RelayStep::Confirm => "confirm",
+        }
+    }
+}
+
+/// Milliseconds a batch of relays spent in each step. `None` for steps it didn't take.
+///
+/// a step taken several times, e.g. checks repeated after a deferral, counts with its total.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct StepTimings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signatures: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submit: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<u64>,
+}
+
+impl StepTimings {
+    pub fn get(&self, step: RelayStep) -> Option<u64> {
+        match step {
+            RelayStep::Check => self.check,
+            RelayStep::Signatures => self.signatures,
+            RelayStep::Submit => self.submit,
+            RelayStep::Confirm => self.confirm,
+        }
+    }
+
+    /// adds `duration` to `step`.
+    pub fn add(&mut self, step: RelayStep, duration: Duration) {
+        let millis = millis(duration);
+        let timing = match step {
+            RelayStep::Check => &mut self.check,
+            RelayStep::Signatures => &mut self.signatures,
+            RelayStep::Submit => &mut self.submit,
+            RelayStep::Confirm => &mut self.confirm,
+        };
+        *timing = Some(timing.unwrap_or(0) + millis);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == StepTimings::default()
+    }
+}
+
+const STEPS: [RelayStep; 4] = [
+    RelayStep::Check,
+    RelayStep::Signatures,
+    RelayStep::Submit,
+    RelayStep::Confirm,
+];
+
+/// `check 12ms, submit 340ms, confirm 2100ms` with the steps taken only.
+impl fmt::Display for StepTimings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for step in STEPS.iter() {
+            if let Some(millis) = self.get(*step) {
+                if !first {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{} {}ms", step.name(), millis)?;
+                first = false;
+            }
+        }
+        if first {
+            f.write_str("no timed steps")?;
+        }
+        Ok(())
+    }
+}
+
+/// Measures the steps of the batch of relays in flight.
+///
+/// the relays pass the time of every state transition, so the durations are exactly
+/// those of the passed times.
+#[derive(Debug, Default)]
+pub struct StepTimer {
+    /// step in progress and when it started
+    current: Option<(RelayStep, Instant)>,
+    /// durations of the steps ended since the last `finish`
+    timings: StepTimings,
+    /// relay transactions sent since the last `finish`
+    sent: usize,
+}
+
+impl StepTimer {
+    /// starts `step` at `now`, ending the step in progress.
+    /// `None` only ends the step in progress. entering the step in progress does nothing.
+    pub fn enter(&mut self, step: Option<RelayStep>, now: Instant) {
+        match self.current {
+            Some((current, _)) if Some(current) == step => return,
+            Some((current, since)) => self.timings.add(current, now.duration_since(since)),
+            None => {}
+        }
+        self.current = step.map(|step| (step, now));
+    }
+
+    /// records that `count` relay transactions were sent at `now`, ending the step in progress.
+    /// returns the durations of the steps so far.
+    pub fn sent(&mut self, count: usize, now: Instant) -> StepTimings {
+        self.enter(None, now);
+        self.sent += count;
+        self.timings
+    }
+
+    /// ends the step in progress at `now` and returns the durations of all steps of the batch.
+    /// `None` if the batch sent no relay transaction. the next step starts a new batch.
+    pub fn finish(&mut self, now: Instant) -> Option<StepTimings> {
+        self.enter(None, now);
+        let timings = mem::replace(&mut self.timings, StepTimings::default());
+        match mem::replace(&mut self.sent, 0) {
+            0 => None,
+            _ => Some(timings),
+        }
+    }
+}
+
+/// Number of steps by duration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct DurationHistogram {
+    /// steps per bucket of `STEP_BUCKETS_MS`. the last bucket counts the longer steps
+    pub buckets: [u64; 8],
+    pub count: u64,
+    pub sum_millis: u64,
+}
+
+impl DurationHistogram {
+    pub fn record(&mut self, millis: u64) {
+        let bucket = STEP_BUCKETS_MS
+            .iter()
+            .position(|bound| millis <= *bound)
+            .unwrap_or(STEP_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_millis += millis;
+    }
+
+    pub fn add(&mut self, other: &DurationHistogram) {
+        for (bucket, other) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += *other;
+        }
+        self.count += other.count;
+        self.sum_millis += other.sum_millis;
+    }
+}
+
+/// Duration histograms of every step of the batches of a relay component.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct StepDurations {
+    pub check: DurationHistogram,
+    pub signatures: DurationHistogram,
+    pub submit: DurationHistogram,
+    pub confirm: DurationHistogram,
+}
+
+impl StepDurations {
+    /// counts the steps the batch with `timings` took.
+    pub fn record(&mut self, timings: &StepTimings) {
+        for step in STEPS.iter() {
+            if let Some(millis) = timings.get(*step) {
+                self.histogram_mut(*step).record(millis);
+            }
+        }
+    }
+
+    pub fn add(&mut self, other: &StepDurations) {
+        for step in STEPS.iter() {
+            let other = *other.histogram(*step);
+            self.histogram_mut(*step).add(&other);
+        }
+    }
+
+    pub fn histogram(&self, step: RelayStep) -> &DurationHistogram {
+        match step {
+            RelayStep::Check => &self.check,
+            RelayStep::Signatures => &self.signatures,
+            RelayStep::Submit => &self.submit,
+            RelayStep::Confirm => &self.confirm,
+        }
+    }
+
+    fn histogram_mut(&mut self, step: RelayStep) -> &mut DurationHistogram {
+        match step {
+            RelayStep::Check => &mut self.check,
+            RelayStep::Signatures => &mut self.signatures,
+            RelayStep::Submit => &mut self.submit,
+            RelayStep::Confirm => &mut self.confirm,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{RelayStep, StepDurations, StepTimer, StepTimings};
+
+    #[test]
+    fn test_step_timer_measures_the_passed_times() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut timer = StepTimer::default();
+
+        timer.enter(Some(RelayStep::Check), at(0));
+        // staying in a step keeps its start
+        timer.enter(Some(RelayStep::Check), at(5));
+        timer.enter(None, at(12));
+        timer.enter(Some(RelayStep::Submit), at(20));
+        let sent = timer.sent(2, at(360));
+        assert_eq!(Some(12), sent.check);
+        assert_eq!(Some(340), sent.submit);
+        assert_eq!(None, sent.confirm);
+        timer.enter(Some(RelayStep::Confirm), at(360));
+
+        let timings = timer.finish(at(2460)).unwrap();
+        assert_eq!(
+            StepTimings {
+                check: Some(12),
+                signatures: None,
+                submit: Some(340),
+                confirm: Some(2100),
+            },
+            timings
+        );
+        assert_eq!("check 12ms, submit 340ms, confirm 2100ms", timings.to_string());
+
+        // the next batch starts over
+        timer.enter(Some(RelayStep::Check), at(3000));
+        timer.enter(Some(RelayStep::Submit), at(3001));
+        timer.enter(Some(RelayStep::Check), at(3003));
+        assert_eq!(Some(1), timer.sent(1, at(3003)).check);
+        timer.enter(Some(RelayStep::Check), at(3003));
+        assert_eq!(Some(3), timer.finish(at(3005)).unwrap().check);
+    }
+
+    #[test]
+    fn test_step_timer_ignores_batches_without_relays() {
+        let start = Instant::now();
+        let mut timer = StepTimer::default();
+        timer.enter(Some(RelayStep::Submit), start);
+        timer.sent(0, start + Duration::from_millis(3));
+        assert_eq!(None, timer.finish(start + Duration::from_millis(5)));
+        // the durations of the empty batch don't carry over
+        timer.enter(Some(RelayStep::Submit), start + Duration::from_millis(5));
+        timer.sent(1, start + Duration::from_millis(6));
+        assert_eq!(Some(1), timer.finish(start + Duration::from_millis(7)).unwrap().submit);
+    }
+
+    #[test]
+    fn test_step_timings_without_steps() {
+        assert_eq!("no timed steps", StepTimings::default().to_string());
+    }
+
+    #[test]
+    fn test_step_durations_histograms() {
+        let mut durations = StepDurations::default();
+        durations.record(&StepTimings {
+            check: Some(100),
+            submit: Some(101),
+            ..Default::default()
+        });
+        durations.record(&StepTimings {
+            check: Some(400_000),
+            ..Default::default()
+        });
+        assert_eq!([1, 0, 0, 0, 0, 0, 0, 1], durations.check.buckets);
+        assert_eq!(2, durations.check.count);
+        assert_eq!(400_100, durations.check.sum_millis);
+        assert_eq!([0, 1, 0, 0, 0, 0, 0, 0], durations.submit.buckets);
+        assert_eq!(0, durations.confirm.count);
+
+        let mut total = StepDurations::default();
+        total.add(&durations);
+        total.add(&durations);
+        assert_eq!([2, 0, 0, 0, 0, 0, 0, 2], total.check.buckets);
+        assert_eq!(2, total.submit.count);
+    }
+}
@@ -0,0 +1,324 @@
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use futures::{Async, Future, Stream};
+use reqwest;
+use reqwest::header::HeaderMap;
+use rustc_hex::ToHex;
+use serde_json;
+use timer::Interval;
+use web3::Transport;
+use web3::types::{Address, H520};
+use alert::{http_client, post, set_header};
+use api::{self, ApiCall, Timeout};
+use app::App;
+use build_info::BuildInfo;
+use config::TelemetryConfig;
+use error::Result;
+use webhook::{canonical_json, PAYLOAD_VERSION};
+use super::{Digest, DigestCounters, DigestGauges, DigestLog, DigestReason};
+
+/// header of the signature of a health summary: `0x` followed by the hex of the `eth_sign`
+/// signature of the body by the authority.
+pub const AUTHORITY_SIGNATURE_HEADER: &str = "X-Bridge-Authority-Signature";
+
+/// Health of an authority reported to the coordinator of its federation.
+///
+/// only what's in here is reported: counters, checkpoints and the version. never recipients,
+/// values or transactions. a field added gets a new `PAYLOAD_VERSION`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthSummary {
+    pub payload_version: u64,
+    /// foreign account of the authority, which signs the summary
+    pub authority: Address,
+    /// version of the `bridge` crate
+    pub version: String,
+    /// seconds since the previous summary
+    pub period_secs: u64,
+    /// checked block of deposit relay
+    pub home_block: u64,
+    /// checked block of the slower of withdraw relay and withdraw confirm
+    pub foreign_block: u64,
+    pub deposits_relayed: u64,
+    pub deposits_failed: u64,
+    pub withdraws_relayed: u64,
+    pub withdraws_failed: u64,
+    pub signatures_submitted: u64,
+    pub signatures_failed: u64,
+    pub deposit_lag_secs: Option<u64>,
+    pub withdraw_lag_secs: Option<u64>,
+    pub queued_deposits: usize,
+    pub queued_signatures: usize,
+    pub rpc_errors: u64,
+    pub timeouts: u64,
+}
+
+impl HealthSummary {
+    /// summary of `authority` with the checkpoints of `counters` and the activity of `digest`.
+    pub fn new(authority: Address, counters: &DigestCounters, digest: &Digest) -> Self {
+        HealthSummary {
+            payload_version: PAYLOAD_VERSION,
+            authority,
+            version: BuildInfo::current().version,
+            period_secs: digest.period_secs,
+            home_block: counters.home_block,
+            foreign_block: counters.foreign_block,
+            deposits_relayed: digest.deposits_relayed,
+            deposits_failed: digest.deposits_failed,
+            withdraws_relayed: digest.withdraws_relayed,
+            withdraws_failed: digest.withdraws_failed,
+            signatures_submitted: digest.signatures_submitted,
+            signatures_failed: digest.signatures_failed,
+            deposit_lag_secs: digest.deposit_lag_secs,
+            withdraw_lag_secs: digest.withdraw_lag_secs,
+            queued_deposits: digest.queued_deposits,
+            queued_signatures: digest.queued_signatures,
+            rpc_errors: digest.rpc_errors,
+            timeouts: digest.timeouts,
+        }
+    }
+
+    /// the posted body, the canonical json of the summary, see `webhook`.
+    pub fn to_body(&self) -> Result<String> {
+        let value = serde_json::to_value(self).expect("HealthSummary always serializes; qed");
+        canonical_json(&value)
+    }
+}
+
+/// headers of a summary signed with `signature`.
+pub fn summary_headers(signature: &H520) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    set_header(&mut headers, AUTHORITY_SIGNATURE_HEADER, &format!("0x{}", signature.0.to_hex()))
+        .expect("hex is a valid header value; qed");
+    headers
+}
+
+/// Posts signed summaries to the coordinator on a thread of its own.
+/// failed deliveries are logged at debug level and not retried.
+pub struct TelemetryReporter {
+    sender: mpsc::Sender<(String, H520)>,
+}
+
+impl TelemetryReporter {
+    pub fn new(url: String, timeout: Duration) -> Result<Self> {
+        let client = http_client(timeout)?;
+        let (sender, receiver) = mpsc::channel::<(String, H520)>();
+        thread::Builder::new()
+            .name("telemetry".into())
+            .spawn(move || {
+                for (body, signature) in receiver {
+                    if let Err(err) = post_summary(&client, &url, body, &signature) {
+                        debug!(target: "bridge::telemetry", "cannot report health summary: {}", err);
+                    }
+                }
+            })?;
+        Ok(TelemetryReporter { sender })
+    }
+
+    /// queues the summary `body` signed with `signature` for delivery. never blocks.
+    pub fn report(&self, body: String, signature: H520) {
+        if self.sender.send((body, signature)).is_err() {
+            debug!(target: "bridge::telemetry", "telemetry reporter stopped. dropping health summary");
+        }
+    }
+}
+
+fn post_summary(client: &reqwest::Client, url: &str, body: String, signature: &H520) -> Result<()> {
+    post(client, url, summary_headers(signature), body, "telemetry coordinator")
+}
+
+/// Reports a `HealthSummary` every `telemetry.interval`, see `TelemetryConfig`.
+///
+/// summaries are signed with `eth_sign` by the foreign account, so the coordinator recovers
+/// the authority from the signature of the body. a summary that fails to sign is dropped.
+pub struct Telemetry<T: Transport> {
+    app: Arc<App<T>>,
+    interval: Interval,
+    log: DigestLog,
+    reporter: TelemetryReporter,
+    /// body of the summary being signed and its signature
+    signing: Option<(String, Timeout<ApiCall<H520, T::Out>>)>,
+}
+
+impl<T: Transport> Telemetry<T> {
+    /// reports the activity since `counters`. fails if the http client can't be created.
+    pub fn new(
+        app: Arc<App<T>>,
+        config: &TelemetryConfig,
+        counters: DigestCounters,
+    ) -> Result<Self> {
+        Ok(Telemetry {
+            interval: app.timer.interval(config.interval),
+            log: DigestLog::new(counters, Instant::now()),
+            reporter: TelemetryReporter::new(config.url.clone(), config.timeout)?,
+            signing: None,
+            app,
+        })
+    }
+
+    /// `true` if a summary is due. never while the previous summary is signed.
+    pub fn is_due(&mut self) -> Result<bool> {
+        let mut due = false;
+        while let Async::Ready(Some(())) = self.interval.poll()? {
+            due = true;
+        }
+        Ok(due && self.signing.is_none())
+    }
+
+    /// signs the summary of the activity up to `counters`. it's reported by `poll` once signed.
+    pub fn sign(&mut self, counters: DigestCounters, gauges: DigestGauges) {
+        let digest = self.log
+            .digest(DigestReason::Interval, counters, gauges, Instant::now());
+        let summary = HealthSummary::new(self.app.config.foreign.account, &counters, &digest);
+        let body = match summary.to_body() {
+            Ok(body) => body,
+            Err(err) => {
+                debug!(target: "bridge::telemetry", "cannot encode health summary: {}", err);
+                return;
+            }
+        };
+        let future = api::timeout(
+            &self.app.timer,
+            api::node_sign(
+                &self.app.connections.foreign,
+                &self.app.config.foreign,
+                body.as_bytes().to_vec().into(),
+            ),
+            "foreign",
+            self.app.config.foreign.request_timeout,
+        );
+        self.signing = Some((body, future));
+    }
+
+    /// queues the summary for delivery once it's signed.
+    pub fn poll(&mut self) {
+        let signed = match self.signing {
+            Some((_, ref mut future)) => match future.poll() {
+                Ok(Async::Ready(signature)) => Some(Ok(signature)),
+                Ok(Async::NotReady) => None,
+                Err(err) => Some(Err(err)),
+            },
+            None => None,
+        };
+        let signed = match signed {
+            Some(signed) => signed,
+            None => return,
+        };
+        let (body, _) = self.signing.take().expect("the summary was signed; qed");
+        match signed {
+            Ok(signature) => self.reporter.report(body, signature),
+            Err(err) => debug!(target: "bridge::telemetry", "cannot sign health summary: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use serde_json::{self, Value};
+    use web3::types::H520;
+    use super::super::{DigestCounters, DigestGauges, DigestLog, DigestReason, RelayCounts};
+    use super::{HealthSummary, TelemetryReporter};
+
+    fn summary() -> HealthSummary {
+        let start = Instant::now();
+        let mut log = DigestLog::new(DigestCounters::default(), start);
+        let counters = DigestCounters {
+            home_block: 120,
+            foreign_block: 80,
+            deposit_relay: RelayCounts {
+                relayed: 3,
+                failed: 1,
+                ..RelayCounts::default()
+            },
+            rpc_errors: 2,
+            ..DigestCounters::default()
+        };
+        let gauges = DigestGauges {
+            deposit_lag_secs: Some(45),
+            queued_deposits: 2,
+            ..DigestGauges::default()
+        };
+        let digest = log.digest(
+            DigestReason::Interval,
+            counters,
+            gauges,
+            start + Duration::from_secs(900),
+        );
+        HealthSummary::new(7.into(), &counters, &digest)
+    }
+
+    #[test]
+    fn test_health_summary_only_holds_counters_and_checkpoints() {
+        let summary = summary();
+        assert_eq!(900, summary.period_secs);
+        assert_eq!(120, summary.home_block);
+        assert_eq!(3, summary.deposits_relayed);
+        assert_eq!(1, summary.deposits_failed);
+        assert_eq!(Some(45), summary.deposit_lag_secs);
+
+        let body = summary.to_body().unwrap();
+        let value: Value = serde_json::from_str(&body).unwrap();
+        let keys = value.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "authority", "deposit_lag_secs", "deposits_failed", "deposits_relayed",
+                "foreign_block", "home_block", "payload_version", "period_secs",
+                "queued_deposits", "queued_signatures", "rpc_errors", "signatures_failed",
+                "signatures_submitted", "timeouts", "version", "withdraw_lag_secs",
+                "withdraws_failed", "withdraws_relayed",
+            ],
+            keys
+        );
+        assert_eq!(
+            Value::String("0x0000000000000000000000000000000000000007".into()),
+            value["authority"]
+        );
+        // canonical: sorted keys without whitespace
+        assert!(body.starts_with("{\"authority\":"));
+        assert!(!body.contains(' '));
+    }
+
+    #[test]
+    fn test_reporter_posts_the_summary_with_its_signature() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            let mut signature = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                let lowercase = line.trim_right().to_lowercase();
+                if lowercase.starts_with("content-length:") {
+                    length = lowercase[15..].trim().parse().unwrap();
+                }
+                if lowercase.starts_with("x-bridge-authority-signature:") {
+                    signature = Some(lowercase[29..].trim().to_owned());
+                }
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+            let mut stream = stream;
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            (signature, body)
+        });
+
+        let reporter = TelemetryReporter::new(url, Duration::from_secs(5)).unwrap();
+        let body = summary().to_body().unwrap();
+        reporter.report(body.clone(), H520::from(0xab));
+        let (signature, posted) = server.join().unwrap();
+        assert_eq!(body.as_bytes(), &posted[..]);
+        assert_eq!(Some(format!("0x{}ab", "00".repeat(64))), signature);
+    }
+}
@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+use futures::{Async, Stream};
+use timer::Interval;
+use error::Error;
+use super::BridgeChecked;
+
+/// Bridge components observed by the `Watchdog`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Component {
+    DepositRelay,
+    WithdrawRelay,
+    WithdrawConfirm,
+}
+
+impl From<BridgeChecked> for Component {
+    fn from(check: BridgeChecked) -> Self {
+        match check {
+            BridgeChecked::DepositRelay(_) => Component::DepositRelay,
+            BridgeChecked::WithdrawRelay(_) => Component::WithdrawRelay,
+            BridgeChecked::WithdrawConfirm(_) => Component::WithdrawConfirm,
+        }
+    }
+}
+
+/// Time of last progress of each component.
+/// a component makes progress whenever it yields a checked block.
+#[derive(Debug)]
+pub struct Progress {
+    stall_timeout: Duration,
+    last_progress: Vec<(Component, Instant)>,
+}
+
+impl Progress {
+    pub fn new(components: &[Component], stall_timeout: Duration, now: Instant) -> Self {
+        Progress {
+            stall_timeout,
+            last_progress: components.iter().map(|c| (*c, now)).collect(),
+        }
+    }
+
+    /// records progress of the components that yielded `checks`.
+    pub fn record(&mut self, checks: &[BridgeChecked], now: Instant) {
+        for check in checks {
+            let component = Component::from(*check);
+            for entry in self.last_progress.iter_mut() {
+                if entry.0 == component {
+                    entry.1 = now;
+                }
+            }
+        }
+    }
+
+    /// returns components which made no progress for longer than `stall_timeout`.
+    /// progress of returned components is reset to `now`,
+    /// so a component that stays stalled is reported once per `stall_timeout`.
+    pub fn stalled(&mut self, now: Instant) -> Vec<Component> {
+        let stall_timeout = self.stall_timeout;
+        self.last_progress
+            .iter_mut()
+            .filter(|entry| now.duration_since(entry.1) > stall_timeout)
+            .map(|entry| {
+                entry.1 = now;
+                entry.0
+            })
+            .collect()
+    }
+}
+
+/// Periodically checks `Progress` of the bridge components.
+/// polling the watchdog makes sure the bridge is woken up
+/// even if none of its components is ever ready again.
+pub struct Watchdog {
+    interval: Interval,
+    progress: Progress,
+}
+
+impl Watchdog {
+    pub fn new(interval: Interval, progress: Progress) -> Self {
+        Watchdog { interval, progress }
+    }
+
+    pub fn record(&mut self, checks: &[BridgeChecked]) {
+        self.progress.record(checks, Instant::now());
+    }
+
+    /// forgets the progress so far. `components` count as making progress `now`.
+    pub fn restart(&mut self, components: &[Component]) {
+        self.progress = Progress::new(components, self.progress.stall_timeout, Instant::now());
+    }
+
+    /// returns stalled components once the check interval elapsed.
+    pub fn poll_stalled(&mut self) -> Result<Vec<Component>, Error> {
+        let mut elapsed = false;
+        while let Async::Ready(Some(())) = self.interval.poll()? {
+            elapsed = true;
+        }
+
+        if elapsed {
+            Ok(self.progress.stalled(Instant::now()))
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use bridge::BridgeChecked;
+    use super::{Component, Progress};
+
+    #[test]
+    fn test_progress_detects_stalled_components() {
+        let start = Instant::now();
+        let mut progress = Progress::new(
+            &[Component::DepositRelay, Component::WithdrawConfirm],
+            Duration::from_secs(10),
+            start,
+        );
+
+        assert!(progress.stalled(start + Duration::from_secs(10)).is_empty());
+
+        progress.record(
+            &[BridgeChecked::DepositRelay(5)],
+            start + Duration::from_secs(5),
+        );
+        assert_eq!(
+            vec![Component::WithdrawConfirm],
+            progress.stalled(start + Duration::from_secs(11))
+        );
+
+        // reported once per stall timeout
+        assert!(progress.stalled(start + Duration::from_secs(12)).is_empty());
+        assert_eq!(
+            vec![Component::DepositRelay],
+            progress.stalled(start + Duration::from_secs(16))
+        );
+    }
+
+    #[test]
+    fn test_progress_ignores_unobserved_components() {
+        let start = Instant::now();
+        let mut progress = Progress::new(&[Component::DepositRelay], Duration::from_secs(1), start);
+
+        progress.record(
+            &[BridgeChecked::WithdrawRelay(1)],
+            start + Duration::from_secs(2),
+        );
+        assert_eq!(
+            vec![Component::DepositRelay],
+            progress.stalled(start + Duration::from_secs(2))
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use std::mem;
+use serde_json;
+use web3::types::H256;
+use config::WithdrawAttemptsConfig;
+use database::{Database, DeadWithdraw, WithdrawAttempt, WithdrawAttempts, WithdrawPhase};
+use error::Error;
+use super::history::Direction;
+use super::raw_logs::{lookup_logs, LoggedEvent};
+
+/// number of withdraws whose attempts `AttemptLog` keeps.
+pub const KEPT_WITHDRAWS: usize = 1024;
+
+/// attempts of `phase` a withdraw gets.
+fn cap(config: &WithdrawAttemptsConfig, phase: WithdrawPhase) -> usize {
+    let cap = match phase {
+        WithdrawPhase::Sign => config.sign,
+        WithdrawPhase::SubmitSignature => config.submit_signature,
+        WithdrawPhase::Finalize => config.finalize,
+    };
+    cap as usize
+}
+
+/// Attempts of the phases of withdraws a component performs, most recent `KEPT_WITHDRAWS`
+/// withdraws, oldest first.
+///
+/// a component that fails is rebuilt and attempts the withdraws of the failed batch again.
+/// a withdraw whose phase was attempted as often as `withdraw_attempts` allows is given up:
+/// it's moved to the dead withdraws and not attempted anymore.
+#[derive(Debug, Clone, Default)]
+pub struct AttemptLog {
+    /// `None` if attempts aren't tracked
+    config: Option<WithdrawAttemptsConfig>,
+    withdraws: Vec<WithdrawAttempts>,
+    /// withdraws given up, also those saved in the database
+    given_up: Vec<H256>,
+    /// withdraws and phases of the attempts in flight
+    in_flight: Vec<(H256, WithdrawPhase)>,
+    /// withdraws given up since the last `take_dead`
+    dead: Vec<DeadWithdraw>,
+}
+
+impl AttemptLog {
+    /// log of the attempts of `phases` saved in `init`.
+    pub fn new(
+        config: Option<WithdrawAttemptsConfig>,
+        phases: &[WithdrawPhase],
+        init: &Database,
+    ) -> Self {
+        let mut log = AttemptLog {
+            config,
+            ..AttemptLog::default()
+        };
+        if config.is_none() {
+            return log;
+        }
+        for withdraw in &init.withdraw_attempts {
+            let attempts = withdraw
+                .attempts
+                .iter()
+                .filter(|attempt| phases.contains(&attempt.phase))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !attempts.is_empty() {
+                log.withdraws.push(WithdrawAttempts {
+                    withdraw: withdraw.withdraw,
+                    attempts,
+                });
+            }
+        }
+        log.given_up = init.dead_withdraws
+            .iter()
+            .filter(|dead| phases.contains(&dead.phase))
+            .map(|dead| dead.withdraw)
+            .collect();
+        log
+    }
+
+    /// starts an attempt of `phase` of `withdraw`. `false` if `withdraw` is given up,
+    /// now or earlier, and must not be attempted.
+    pub fn start(&mut self, withdraw: H256, phase: WithdrawPhase) -> bool {
+        let cap = match self.config {
+            Some(ref config) => cap(config, phase),
+            None => return true,
+        };
+        if self.given_up.contains(&withdraw) {
+            return false;
+        }
+        let index = match self.withdraws
+            .iter()
+            .position(|attempts| attempts.withdraw == withdraw)
+        {
+            Some(index) => index,
+            None => {
+                if self.withdraws.len() >= KEPT_WITHDRAWS {
+                    self.withdraws.remove(0);
+                }
+                self.withdraws.push(WithdrawAttempts {
+                    withdraw,
+                    attempts: Vec::new(),
+                });
+                self.withdraws.len() - 1
+            }
+        };
+        if self.withdraws[index].count(phase) >= cap {
+            let attempts = self.withdraws.remove(index).attempts;
+            self.given_up.push(withdraw);
+            self.dead.push(DeadWithdraw {
+                withdraw,
+                phase,
+                attempts,
+            });
+            return false;
+        }
+        self.withdraws[index].attempts.push(WithdrawAttempt {
+            phase,
+            transaction: None,
+            error: None,
+        });
+        self.in_flight.push((withdraw, phase));
+        true
+    }
+
+    fn last_attempt(
+        &mut self,
+        withdraw: H256,
+        phase: WithdrawPhase,
+    ) -> Option<&mut WithdrawAttempt> {
+        self.withdraws
+            .iter_mut()
+            .find(|attempts| attempts.withdraw == withdraw)?
+            .attempts
+            .iter_mut()
+            .rev()
+            .find(|attempt| attempt.phase == phase)
+    }
+
+    /// records `transaction` sent by the attempt of `phase` of `withdraw` in flight.
+    pub fn sent(&mut self, withdraw: H256, phase: WithdrawPhase, transaction: H256) {
+        if let Some(attempt) = self.last_attempt(withdraw, phase) {
+            attempt.transaction = Some(transaction);
+        }
+    }
+
+    /// the attempts in flight succeeded.
+    pub fn succeed(&mut self) {
+        self.in_flight.clear();
+    }
+
+    /// the attempts in flight failed with `err`.
+    pub fn fail(&mut self, err: &Error) {
+        let error = err.to_string();
+        for (withdraw, phase) in mem::replace(&mut self.in_flight, Vec::new()) {
+            if let Some(attempt) = self.last_attempt(withdraw, phase) {
+                attempt.error = Some(error.clone());
+            }
+        }
+    }
+
+    /// attempts of the withdraws kept, oldest first.
+    pub fn withdraws(&self) -> &[WithdrawAttempts] {
+        &self.withdraws
+    }
+
+    /// returns the withdraws given up since the last call.
+    pub fn take_dead(&mut self) -> Vec<DeadWithdraw> {
+        mem::replace(&mut self.dead, Vec::new())
+    }
+}
+
+/// What the database records of the attempts of a withdraw, printed by `--lookup`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WithdrawLookup {
+    /// hash of the foreign transaction that emitted the withdraw
+    pub withdraw: H256,
+    /// attempts of every phase, oldest first. each component records the phases it performs
+    pub attempts: Vec<WithdrawAttempt>,
+    /// the phase the withdraw was given up at. `None` if it wasn't
+    pub given_up: Option<WithdrawPhase>,
+    /// logs of the withdraw, raw and decoded. empty if it wasn't observed recently
+    pub logs: Vec<LoggedEvent>,
+}
+
+impl WithdrawLookup {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialization can't fail; qed")
+    }
+}
+
+/// the attempts and logs `database` records of the withdraw in foreign transaction `withdraw`.
+pub fn lookup_withdraw(database: &Database, withdraw: H256) -> WithdrawLookup {
+    let mut attempts = database
+        .withdraw_attempts
+        .iter()
+        .filter(|attempts| attempts.withdraw == withdraw)
+        .flat_map(|attempts| attempts.attempts.iter().cloned())
+        .collect::<Vec<_>>();
+    let dead = database
+        .dead_withdraws
+        .iter()
+        .find(|dead| dead.withdraw == withdraw);
+    if let Some(dead) = dead {
+        attempts.extend(dead.attempts.iter().cloned());
+    }
+    WithdrawLookup {
+        withdraw,
+        attempts,
+        given_up: dead.map(|dead| dead.phase),
+        logs: lookup_logs(database, Direction::ForeignToHome, withdraw),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::H256;
+    use config::WithdrawAttemptsConfig;
+    use database::{Database, WithdrawPhase};
+    use error::Error;
+    use super::{lookup_withdraw, AttemptLog};
+
+    const CONFIG: WithdrawAttemptsConfig = WithdrawAttemptsConfig {
+        sign: 2,
+        submit_signature: 2,
+        finalize: 1,
+    };
+
+    #[test]
+    fn test_attempt_log_gives_up_at_the_cap() {
+        let phases = [WithdrawPhase::Sign, WithdrawPhase::SubmitSignature];
+        let mut log = AttemptLog::new(Some(CONFIG), &phases, &Database::default());
+        let withdraw = H256::from(1);
+
+        assert!(log.start(withdraw, WithdrawPhase::Sign));
+        log.succeed();
+        assert!(log.start(withdraw, WithdrawPhase::SubmitSignature));
+        log.sent(withdraw, WithdrawPhase::SubmitSignature, 10.into());
+        log.fail(&Error::from("relay transaction reverted"));
+        // retried by the rebuilt component
+        assert!(log.start(withdraw, WithdrawPhase::Sign));
+        log.succeed();
+        assert!(log.start(withdraw, WithdrawPhase::SubmitSignature));
+        log.fail(&Error::from("timeout"));
+        assert!(log.take_dead().is_empty());
+
+        assert!(!log.start(withdraw, WithdrawPhase::Sign));
+        let dead = log.take_dead();
+        assert_eq!(1, dead.len());
+        assert_eq!(WithdrawPhase::Sign, dead[0].phase);
+        assert_eq!(4, dead[0].attempts.len());
+        assert_eq!(Some(H256::from(10)), dead[0].attempts[1].transaction);
+        assert_eq!(
+            Some("relay transaction reverted".to_owned()),
+            dead[0].attempts[1].error
+        );
+        assert_eq!(None, dead[0].attempts[2].error);
+        // given up for good
+        assert!(!log.start(withdraw, WithdrawPhase::SubmitSignature));
+        assert!(log.take_dead().is_empty());
+        assert!(log.withdraws().is_empty());
+    }
+
+    #[test]
+    fn test_attempt_log_continues_from_the_database() {
+        let finalize = [WithdrawPhase::Finalize];
+        let mut log = AttemptLog::new(Some(CONFIG), &finalize, &Database::default());
+        assert!(log.start(1.into(), WithdrawPhase::Finalize));
+        log.fail(&Error::from("reverted"));
+        let database = Database {
+            withdraw_attempts: log.withdraws().to_vec(),
+            ..Database::default()
+        };
+
+        let mut log = AttemptLog::new(Some(CONFIG), &finalize, &database);
+        assert!(!log.start(1.into(), WithdrawPhase::Finalize));
+        let database = Database {
+            dead_withdraws: log.take_dead(),
+            ..database
+        };
+        let lookup = lookup_withdraw(&database, 1.into());
+        assert_eq!(Some(WithdrawPhase::Finalize), lookup.given_up);
+        assert_eq!(1, lookup.attempts.len());
+        assert_eq!(Some("reverted".to_owned()), lookup.attempts[0].error);
+
+        // dead withdraws of the database stay given up
+        let mut log = AttemptLog::new(Some(CONFIG), &finalize, &database);
+        assert!(!log.start(1.into(), WithdrawPhase::Finalize));
+        assert!(log.take_dead().is_empty());
+        // other phases are tracked by other components
+        let mut log = AttemptLog::new(Some(CONFIG), &[WithdrawPhase::Sign], &database);
+        assert!(log.start(1.into(), WithdrawPhase::Sign));
+
+        // without config nothing is tracked
+        let mut log = AttemptLog::new(None, &finalize, &database);
+        assert!(log.start(1.into(), WithdrawPhase::Finalize));
+        assert!(log.withdraws().is_empty());
+    }
+}
@@ -1,80 +1,234 @@
+use std::mem;
 use std::sync::Arc;
 use std::ops;
+use std::time::Duration;
+use ethabi;
 use futures::{Future, Poll, Stream};
 use futures::future::{join_all, JoinAll};
-use tokio_timer::Timeout;
+use tiny_keccak::keccak256;
+use timer::Sleep;
+use tracing::Span;
+use tracing_futures::{Instrument, Instrumented};
 use web3::Transport;
-use web3::types::{Address, Bytes, FilterBuilder, H256, H520, TransactionRequest};
-use api::{self, ApiCall, LogStream};
+use web3::types::{Address, Bytes, FilterBuilder, H256, H520, TransactionRequest, U256};
+use api::{self, ApiCall, LogStream, OversizedLog, Timeout};
 use app::App;
+use config::Rounding;
 use contracts::foreign;
-use util::web3_filter;
-use database::Database;
-use error::Error;
-use message_to_mainnet::{MessageToMainnet, MESSAGE_LENGTH};
+use util::{shared_transaction_hashes, web3_filter};
+use database::{Database, WithdrawPhase};
+use error::{Error, ErrorKind, Result};
+use jitter;
+use message_to_mainnet::MessageToMainnet;
+use super::{relay_span, Component, Direction, RelayCounts};
+use super::call_data::{check_call_data, ExpectedCall};
+use super::gas_usage::{gas_samples, GasSample};
+use super::observed_traffic::Observation;
+use super::own_signatures::{already_signed, verify_own_signatures, SignatureMismatch,
+                            VerifyOwnSignatures};
+use super::relay_events::{expected_events, record_event_checks, verify_relay_events, ExpectedEvent,
+                          VerifyRelayEvents};
+use super::schedule::{foreign_quota, send_foreign_transactions, QueueStatus, SendTransactions};
+use super::signature_stagger::signature_delay;
+use super::step_timing::{RelayStep, StepTimer};
+use super::withdraw_attempts::AttemptLog;
 
 fn withdraws_filter(foreign: &foreign::ForeignBridge, address: Address) -> FilterBuilder {
     let filter = foreign.events().withdraw().create_filter();
     web3_filter(filter, address)
 }
 
-fn withdraw_submit_signature_payload(
+pub fn withdraw_submit_signature_payload(
     foreign: &foreign::ForeignBridge,
-    withdraw_message: Vec<u8>,
+    withdraw_message: &MessageToMainnet,
     signature: H520,
 ) -> Bytes {
-    assert_eq!(
-        withdraw_message.len(),
-        MESSAGE_LENGTH,
-        "ForeignBridge never accepts messages with len != {} bytes; qed",
-        MESSAGE_LENGTH
-    );
     foreign
         .functions()
         .submit_signature()
-        .input(signature.0.to_vec(), withdraw_message)
+        .input(signature.0.to_vec(), withdraw_message.to_bytes())
         .into()
 }
 
+/// transaction calling `ForeignBridge` at `foreign_contract` with the `submitSignature` `payload`.
+pub fn submission_request<T: Transport>(
+    app: &App<T>,
+    foreign_contract: Address,
+    payload: Bytes,
+) -> TransactionRequest {
+    let tx_config = app.tunables.get().txs.withdraw_confirm;
+    TransactionRequest {
+        from: app.config.foreign.account,
+        to: Some(foreign_contract),
+        gas: tx_config.request_gas(),
+        gas_price: Some(app.foreign_gas_price(&tx_config)),
+        value: None,
+        data: Some(payload),
+        nonce: None,
+        condition: None,
+    }
+}
+
+/// the `submission_request` of the `signature` of `message`, once its call data decodes back
+/// to them.
+fn checked_submission<T: Transport>(
+    app: &App<T>,
+    counts: &mut RelayCounts,
+    foreign_contract: Address,
+    message: &MessageToMainnet,
+    signature: H520,
+) -> Result<TransactionRequest> {
+    let payload = withdraw_submit_signature_payload(&app.foreign_bridge, message, signature);
+    let expected = ExpectedCall::submit_signature(signature.0.to_vec(), message.to_bytes());
+    check_call_data(app, counts, &expected, &payload.0)?;
+    Ok(submission_request(app, foreign_contract, payload))
+}
+
+/// delay of the submission of the signatures of `messages` by the `signature_stagger` config.
+/// the delay of the first message applies to the whole batch.
+/// `None` without delay or if the oldest withdraw (at `oldest_block`) is `skip_after_blocks`
+/// older than `block` and shouldn't wait any longer.
+fn stagger_delay<T: Transport>(
+    app: &App<T>,
+    messages: &[MessageToMainnet],
+    oldest_block: u64,
+    block: u64,
+) -> Option<Duration> {
+    let stagger = app.config.signature_stagger.as_ref()?;
+    if block.saturating_sub(oldest_block) >= stagger.skip_after_blocks {
+        info!(
+            target: "bridge::withdraw_confirm",
+            "withdraw in block {} is {} blocks old. submitting signatures without delay",
+            oldest_block,
+            block - oldest_block
+        );
+        return None;
+    }
+    let message_hash = H256::from(keccak256(&messages.first()?.to_bytes()));
+    let delay = signature_delay(
+        &app.config.authorities.accounts,
+        &app.config.foreign.account,
+        &message_hash,
+        stagger.max_delay,
+    );
+    if delay == Duration::from_secs(0) {
+        None
+    } else {
+        Some(delay)
+    }
+}
+
 /// State of withdraw confirmation.
 enum WithdrawConfirmState<T: Transport> {
     /// Withdraw confirm is waiting for logs.
     Wait,
+    /// Checking withdraws of blocks before `authority_since_block` for execution on home.
+    /// executed withdraws aren't signed.
+    CheckExecuted {
+        future: JoinAll<Vec<Timeout<ApiCall<Bytes, T::Out>>>>,
+        messages: Vec<MessageToMainnet>,
+        /// block of the oldest withdraw
+        oldest_block: u64,
+        block: u64,
+    },
     /// Signing withdraws.
     SignWithdraws {
-        messages: Vec<Vec<u8>>,
-        future: JoinAll<Vec<Timeout<ApiCall<H520, T::Out>>>>,
+        messages: Vec<MessageToMainnet>,
+        future: JoinAll<Vec<Instrumented<Timeout<ApiCall<H520, T::Out>>>>>,
+        /// block of the oldest withdraw
+        oldest_block: u64,
+        block: u64,
+    },
+    /// Waiting for the stagger delay before submitting signatures.
+    StaggerSignatures {
+        future: Sleep,
+        confirmations: Vec<TransactionRequest>,
+        messages: Vec<MessageToMainnet>,
         block: u64,
     },
     /// Confirming withdraws.
     ConfirmWithdraws {
-        future: JoinAll<Vec<Timeout<ApiCall<H256, T::Out>>>>,
+        future: SendTransactions<T>,
+        /// the signed withdraws
+        messages: Vec<MessageToMainnet>,
+        /// events the submissions must emit. empty unless `relay_events` is configured
+        expected: Vec<Option<ExpectedEvent>>,
+        block: u64,
+    },
+    /// Checking the receipts of the submissions for the expected events.
+    VerifyEvents {
+        future: VerifyRelayEvents<T>,
+        /// the submissions and the withdraws they sign
+        submitted: Vec<(H256, MessageToMainnet)>,
+        block: u64,
+    },
+    /// Verifying the stored signatures of the withdraws this authority already signed,
+    /// see `verify_own_signatures`.
+    VerifyOwnSignatures {
+        future: VerifyOwnSignatures<T>,
         block: u64,
     },
     /// All withdraws till given block has been confirmed.
     Yield(Option<u64>),
 }
 
-pub fn create_withdraw_confirm<T: Transport + Clone>(
-    app: Arc<App<T>>,
-    init: &Database,
-) -> WithdrawConfirm<T> {
+impl<T: Transport> WithdrawConfirmState<T> {
+    /// timed step of the signatures in this state. `None` if not timed.
+    fn step(&self) -> Option<RelayStep> {
+        match *self {
+            WithdrawConfirmState::CheckExecuted { .. } => Some(RelayStep::Check),
+            WithdrawConfirmState::SignWithdraws { .. } => Some(RelayStep::Signatures),
+            WithdrawConfirmState::ConfirmWithdraws { .. } => Some(RelayStep::Submit),
+            WithdrawConfirmState::VerifyEvents { .. }
+            | WithdrawConfirmState::VerifyOwnSignatures { .. } => Some(RelayStep::Confirm),
+            _ => None,
+        }
+    }
+}
+
+/// withdraw logs on foreign after the checked block of `init`.
+pub fn withdraw_log_stream<T: Transport + Clone>(app: &App<T>, init: &Database) -> LogStream<T> {
     let logs_init = api::LogStreamInit {
         after: init.checked_withdraw_confirm,
+        chain: "foreign",
         request_timeout: app.config.foreign.request_timeout,
-        poll_interval: app.config.foreign.poll_interval,
+        poll_interval: app.tunables.get().foreign_poll_interval,
+        poll_jitter: jitter::poll_jitter(app.config.poll_jitter, &app.config.foreign.account),
         confirmations: app.config.foreign.required_confirmations,
-        filter: withdraws_filter(&app.foreign_bridge, init.foreign_contract_address.clone()),
+        sanity_check: app.config.foreign.logs_sanity_check.clone(),
+        head_regression: app.config.foreign.head_regression,
+        log_limit_errors: app.config.foreign.log_limit_errors.clone(),
+        max_log_data: app.config.foreign.max_log_data,
+        finality: app.config.foreign.l1_posted_rpc(),
+        filter: match app.abi {
+            Some(ref abi) => abi.withdraws_filter(init.foreign_contract_address),
+            None => withdraws_filter(&app.foreign_bridge, init.foreign_contract_address.clone()),
+        },
     };
+    api::log_stream(app.connections.foreign.clone(), app.timer.clone(), logs_init)
+}
 
+pub fn create_withdraw_confirm<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    init: &Database,
+) -> WithdrawConfirm<T> {
     WithdrawConfirm {
-        logs: api::log_stream(
-            app.connections.foreign.clone(),
-            app.timer.clone(),
-            logs_init,
-        ),
+        logs: withdraw_log_stream(&app, init),
+        home_contract: init.home_contract_address,
         foreign_contract: init.foreign_contract_address,
         state: WithdrawConfirmState::Wait,
+        counts: RelayCounts::default(),
+        gas_samples: Vec::new(),
+        observations: Vec::new(),
+        signature_mismatches: Vec::new(),
+        steps: StepTimer::default(),
+        attempts: AttemptLog::new(
+            app.config.withdraw_attempts,
+            &[WithdrawPhase::Sign, WithdrawPhase::SubmitSignature],
+            init,
+        ),
+        draining: false,
+        blocks: Span::none(),
         app,
     }
 }
@@ -83,114 +237,607 @@ pub struct WithdrawConfirm<T: Transport> {
     app: Arc<App<T>>,
     logs: LogStream<T>,
     state: WithdrawConfirmState<T>,
+    home_contract: Address,
     foreign_contract: Address,
+    counts: RelayCounts,
+    /// gas used by the checked relays not yet taken by `take_gas_samples`
+    gas_samples: Vec<GasSample>,
+    /// withdraws observed but not yet taken by `take_observations`
+    observations: Vec<Observation>,
+    /// withdraws whose stored signatures aren't this authority's,
+    /// not yet taken by `take_signature_mismatches`
+    signature_mismatches: Vec<SignatureMismatch>,
+    /// steps of the batch of signatures in flight
+    steps: StepTimer,
+    /// attempts to sign withdraws and to submit their signatures
+    attempts: AttemptLog,
+    /// `true` once drained. the component ends instead of taking new logs
+    draining: bool,
+    /// span of the blocks of the batch in flight
+    blocks: Span,
 }
 
-impl<T: Transport> Stream for WithdrawConfirm<T> {
-    type Item = u64;
-    type Error = Error;
+impl<T: Transport> WithdrawConfirm<T> {
+    /// stops taking new logs. the batch in flight is finished, then it ends.
+    pub fn drain(&mut self) {
+        self.draining = true;
+    }
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+    /// returns the relay outcomes since the last call.
+    pub fn take_counts(&mut self) -> RelayCounts {
+        let mut counts = mem::replace(&mut self.counts, RelayCounts::default());
+        counts.pending_logs += self.logs.take_pending_logs();
+        counts
+    }
+
+    /// returns the logs the log stream dropped for their size since the last call.
+    pub fn take_oversized_logs(&mut self) -> Vec<OversizedLog> {
+        self.logs.take_oversized_logs()
+    }
+
+    /// returns the gas used by the relays checked since the last call.
+    /// empty unless `gas_usage` is configured.
+    pub fn take_gas_samples(&mut self) -> Vec<GasSample> {
+        mem::replace(&mut self.gas_samples, Vec::new())
+    }
+
+    /// returns the withdraws the log stream yielded since the last call, before deciding
+    /// whether to sign them. withdraws of a rebuilt component are observed again.
+    pub fn take_observations(&mut self) -> Vec<Observation> {
+        mem::replace(&mut self.observations, Vec::new())
+    }
+
+    /// returns the withdraws found since the last call whose stored signatures don't recover
+    /// to this authority. empty unless `verify_own_signatures` is enabled.
+    pub fn take_signature_mismatches(&mut self) -> Vec<SignatureMismatch> {
+        mem::replace(&mut self.signature_mismatches, Vec::new())
+    }
+
+    /// attempts to sign withdraws and to submit their signatures.
+    pub fn attempt_log(&self) -> &AttemptLog {
+        &self.attempts
+    }
+
+    pub fn attempt_log_mut(&mut self) -> &mut AttemptLog {
+        &mut self.attempts
+    }
+
+    /// replaces the attempt log, e.g. with the one of the component this one is rebuilt from.
+    pub fn set_attempt_log(&mut self, attempts: AttemptLog) {
+        self.attempts = attempts;
+    }
+
+    /// signatures waiting to be submitted. `None` if not submitting.
+    pub fn queue_status(&self) -> Option<QueueStatus> {
+        match self.state {
+            WithdrawConfirmState::ConfirmWithdraws { ref future, .. } => {
+                Some(future.queue_status(self.app.timer.now()))
+            }
+            _ => None,
+        }
+    }
+
+    /// describes the current state. used for diagnostics.
+    pub fn state_description(&self) -> String {
+        match self.state {
+            WithdrawConfirmState::Wait => "waiting for logs".into(),
+            WithdrawConfirmState::CheckExecuted {
+                ref messages,
+                block,
+                ..
+            } => format!(
+                "checking {} withdraws up to block {} for execution",
+                messages.len(),
+                block
+            ),
+            WithdrawConfirmState::SignWithdraws { block, .. } => {
+                format!("signing withdraws up to block {}", block)
+            }
+            WithdrawConfirmState::StaggerSignatures {
+                ref confirmations,
+                block,
+                ..
+            } => format!(
+                "delaying the submission of {} signatures up to block {}",
+                confirmations.len(),
+                block
+            ),
+            WithdrawConfirmState::ConfirmWithdraws { ref future, block, .. } => {
+                let queue = future.queue_status(self.app.timer.now());
+                format!(
+                    "submitting signatures up to block {}. {} queued for {}s",
+                    block,
+                    queue.depth,
+                    queue.age.as_secs()
+                )
+            }
+            WithdrawConfirmState::VerifyEvents { block, .. } => {
+                format!("checking events of signatures up to block {}", block)
+            }
+            WithdrawConfirmState::VerifyOwnSignatures { block, .. } => format!(
+                "verifying the stored signatures of already signed withdraws up to block {}",
+                block
+            ),
+            WithdrawConfirmState::Yield(_) => "yielding checked block".into(),
+        }
+    }
+}
+
+impl<T: Transport + Clone> WithdrawConfirm<T> {
+    fn poll_withdraws(&mut self) -> Poll<Option<u64>, Error> {
         loop {
+            // the calls of the batch in flight are nested in the span of its blocks
+            let blocks = self.blocks.clone();
+            let _entered = blocks.enter();
             let next_state = match self.state {
+                WithdrawConfirmState::Wait if self.draining => return Ok(None.into()),
                 WithdrawConfirmState::Wait => {
                     let item = try_stream!(self.logs.poll());
-                    info!("got {} new withdraws to sign", item.logs.len());
-                    let withdraw_messages = item.logs
-                        .into_iter()
-                        .map(|log| {
-                            info!(
-                                "withdraw is ready for signature submission. tx hash {}",
-                                log.transaction_hash.unwrap()
+                    info!(
+                        target: "bridge::withdraw_confirm",
+                        "got {} new withdraws to sign in blocks {}..={}",
+                        item.logs.len(),
+                        item.from,
+                        item.to
+                    );
+                    self.blocks = info_span!(
+                        target: "bridge::withdraw_confirm",
+                        parent: None,
+                        "withdraws",
+                        from = item.from,
+                        to = item.to
+                    );
+                    let batch = self.blocks.clone();
+                    let _batch = batch.enter();
+                    let unique_ids = self.app.config.unique_log_ids;
+                    if !unique_ids {
+                        for hash in shared_transaction_hashes(&item.logs) {
+                            error!(
+                                target: "bridge::withdraw_confirm",
+                                "foreign transaction {} emitted several withdraws. only one of them can ever be withdrawn on home. enable `unique_log_ids` once the contracts of all authorities support it",
+                                hash
                             );
-                            Ok(MessageToMainnet::from_log(log)?.to_bytes())
-                        })
-                        .collect::<Result<Vec<_>, Error>>()?;
+                        }
+                    }
+                    let mut withdraw_messages = Vec::new();
+                    let mut oldest_block = item.to;
+                    for log in item.logs {
+                        let hash = log.transaction_hash;
+                        let log_block = log.block_number.map(|number| number.low_u64());
+                        let observation =
+                            Observation::from_log(Direction::ForeignToHome, &log, U256::zero());
+                        let mut message = match self.app.abi {
+                            Some(ref abi) => abi.withdraw_message(log, unique_ids)?,
+                            None => MessageToMainnet::from_log(log, unique_ids)?,
+                        };
+                        self.observations.extend(observation.map(|observation| Observation {
+                            value: message.value,
+                            ..observation
+                        }));
+                        // MessageToMainnet::from_log already fails for logs without transaction_hash
+                        let hash = hash.ok_or(ErrorKind::UnminedLog)?;
+                        if let Some(ref scale) = self.app.config.value_scale {
+                            match scale.withdraw(message.value) {
+                                Ok(scaled) => {
+                                    if !scaled.dust.is_zero()
+                                        && scale.rounding == Rounding::FloorWithDust
+                                    {
+                                        info!(
+                                            target: "bridge::withdraw_confirm",
+                                            "withdraw of {} in foreign transaction {} rounded down to {}. dust: {}",
+                                            message.value,
+                                            hash,
+                                            scaled.value,
+                                            scaled.dust
+                                        );
+                                        self.counts.dust = self.counts.dust + scaled.dust;
+                                    }
+                                    message.value = scaled.value;
+                                }
+                                Err(err) => {
+                                    error!(
+                                        target: "bridge::withdraw_confirm",
+                                        "not signing withdraw in foreign transaction {}: {}",
+                                        hash,
+                                        err
+                                    );
+                                    self.counts.rejected_values += 1;
+                                    continue;
+                                }
+                            }
+                        }
+                        info!(
+                            target: "bridge::withdraw_confirm",
+                            "withdraw is ready for signature submission. tx hash {}",
+                            hash
+                        );
+                        if let Some(log_block) = log_block {
+                            oldest_block = oldest_block.min(log_block);
+                        }
+                        withdraw_messages.push(message);
+                    }
 
-                    let requests = withdraw_messages
-                        .clone()
-                        .into_iter()
-                        .map(|message| {
-                            self.app.timer.timeout(
-                                api::sign(
-                                    &self.app.connections.foreign,
-                                    self.app.config.foreign.account,
-                                    Bytes(message),
-                                ),
-                                self.app.config.foreign.request_timeout,
-                            )
+                    if self.app.config.is_catching_up(item.from) && !withdraw_messages.is_empty() {
+                        info!(
+                            target: "bridge::withdraw_confirm",
+                            "checking {} withdraws of blocks before authority_since_block for execution",
+                            withdraw_messages.len()
+                        );
+                        WithdrawConfirmState::CheckExecuted {
+                            future: check_executed(&self.app, self.home_contract, &withdraw_messages),
+                            messages: withdraw_messages,
+                            oldest_block,
+                            block: item.to,
+                        }
+                    } else {
+                        sign_withdraws(
+                            &self.app,
+                            &mut self.attempts,
+                            withdraw_messages,
+                            oldest_block,
+                            item.to,
+                        )
+                    }
+                }
+                WithdrawConfirmState::CheckExecuted {
+                    ref mut future,
+                    ref mut messages,
+                    oldest_block,
+                    block,
+                } => {
+                    let outputs = try_ready!(future.poll());
+                    let app = &self.app;
+                    let executed = outputs
+                        .iter()
+                        .map(|output| {
+                            app.home_bridge
+                                .functions()
+                                .is_withdraw_executed()
+                                .output(output.0.as_slice())
                         })
-                        .collect::<Vec<_>>();
-
-                    info!("signing");
-                    WithdrawConfirmState::SignWithdraws {
-                        future: join_all(requests),
-                        messages: withdraw_messages,
-                        block: item.to,
+                        .collect::<ethabi::Result<Vec<_>>>()
+                        .map_err(Error::from)?;
+                    let mut unexecuted = Vec::new();
+                    for (message, executed) in messages.drain(ops::RangeFull).zip(executed) {
+                        if executed {
+                            debug!(
+                                target: "bridge::withdraw_confirm",
+                                "withdraw {} already executed on home. not signing",
+                                message.sidenet_transaction_hash
+                            );
+                            self.counts.skipped_executed += 1;
+                        } else {
+                            unexecuted.push(message);
+                        }
                     }
+                    info!(
+                        target: "bridge::withdraw_confirm",
+                        "{} withdraws up to block {} not executed yet",
+                        unexecuted.len(),
+                        block
+                    );
+                    sign_withdraws(app, &mut self.attempts, unexecuted, oldest_block, block)
                 }
                 WithdrawConfirmState::SignWithdraws {
                     ref mut future,
                     ref mut messages,
+                    oldest_block,
                     block,
                 } => {
                     let signatures = try_ready!(future.poll());
-                    info!("signing complete");
+                    info!(target: "bridge::withdraw_confirm", "signing complete");
+                    self.attempts.succeed();
                     // borrow checker...
                     let app = &self.app;
                     let foreign_contract = &self.foreign_contract;
-                    let confirmations = messages
+                    let counts = &mut self.counts;
+                    let attempts = &mut self.attempts;
+                    let delay = stagger_delay(app, messages, oldest_block, block);
+                    let signed = messages
                         .drain(ops::RangeFull)
                         .zip(signatures.into_iter())
-                        .map(|(withdraw_message, signature)| {
-                            withdraw_submit_signature_payload(
-                                &app.foreign_bridge,
-                                withdraw_message,
-                                signature,
-                            )
-                        })
-                        .map(|payload| TransactionRequest {
-                            from: app.config.foreign.account,
-                            to: Some(foreign_contract.clone()),
-                            gas: Some(app.config.txs.withdraw_confirm.gas.into()),
-                            gas_price: Some(app.config.txs.withdraw_confirm.gas_price.into()),
-                            value: None,
-                            data: Some(payload),
-                            nonce: None,
-                            condition: None,
+                        .filter(|&(ref message, _)| {
+                            let hash = message.sidenet_transaction_hash;
+                            let started = attempts.start(hash, WithdrawPhase::SubmitSignature);
+                            if !started {
+                                error!(
+                                    target: "bridge::withdraw_confirm",
+                                    "withdraw in foreign transaction {} is given up after too many attempts. not submitting its signature",
+                                    hash
+                                );
+                            }
+                            started
                         })
-                        .map(|request| {
-                            info!("submitting signature");
-                            app.timer.timeout(
-                                api::send_transaction(&app.connections.foreign, request),
-                                app.config.foreign.request_timeout,
-                            )
+                        .collect::<Vec<_>>();
+                    let confirmations = signed
+                        .iter()
+                        .map(|&(ref message, signature)| {
+                            checked_submission(app, counts, *foreign_contract, message, signature)
                         })
+                        .collect::<Result<Vec<_>>>()?;
+                    let messages = signed
+                        .into_iter()
+                        .map(|(message, _)| message)
                         .collect::<Vec<_>>();
 
-                    info!("submitting {} signatures", confirmations.len());
-                    WithdrawConfirmState::ConfirmWithdraws {
-                        future: join_all(confirmations),
-                        block,
+                    match delay {
+                        Some(delay) => {
+                            info!(
+                                target: "bridge::withdraw_confirm",
+                                "delaying the submission of {} signatures by {:?}",
+                                confirmations.len(),
+                                delay
+                            );
+                            WithdrawConfirmState::StaggerSignatures {
+                                future: app.timer.sleep(delay),
+                                confirmations,
+                                messages,
+                                block,
+                            }
+                        }
+                        None => submit_signatures(app, confirmations, messages, block),
                     }
                 }
+                WithdrawConfirmState::StaggerSignatures {
+                    ref mut future,
+                    ref mut confirmations,
+                    ref mut messages,
+                    block,
+                } => {
+                    try_ready!(future.poll());
+                    submit_signatures(
+                        &self.app,
+                        mem::replace(confirmations, Vec::new()),
+                        mem::replace(messages, Vec::new()),
+                        block,
+                    )
+                }
                 WithdrawConfirmState::ConfirmWithdraws {
                     ref mut future,
+                    ref mut messages,
+                    ref mut expected,
                     block,
                 } => {
-                    let _ = try_ready!(future.poll());
-                    info!("submitting signatures complete");
-                    WithdrawConfirmState::Yield(Some(block))
+                    let confirm_hashes = try_ready!(future.poll());
+                    self.counts.relayed += confirm_hashes.len() as u64;
+                    self.steps.sent(confirm_hashes.len(), self.app.timer.now());
+                    for (message, confirm_hash) in messages.iter().zip(confirm_hashes.iter()) {
+                        let withdraw_hash = message.sidenet_transaction_hash;
+                        self.attempts.sent(
+                            withdraw_hash,
+                            WithdrawPhase::SubmitSignature,
+                            *confirm_hash,
+                        );
+                        info!(
+                            target: "bridge::withdraw_confirm",
+                            "signature for withdraw in foreign transaction {} submitted in foreign transaction {}",
+                            withdraw_hash,
+                            confirm_hash
+                        );
+                    }
+                    info!(target: "bridge::withdraw_confirm", "submitting signatures complete");
+                    let expected = mem::replace(expected, Vec::new());
+                    match verify_relay_events(&self.app, false, &confirm_hashes, expected) {
+                        Some(future) => WithdrawConfirmState::VerifyEvents {
+                            future,
+                            submitted: confirm_hashes
+                                .into_iter()
+                                .zip(messages.drain(ops::RangeFull))
+                                .collect(),
+                            block,
+                        },
+                        None => WithdrawConfirmState::Yield(Some(block)),
+                    }
+                }
+                WithdrawConfirmState::VerifyEvents {
+                    ref mut future,
+                    ref submitted,
+                    block,
+                } => {
+                    let checks = try_ready!(future.poll());
+                    self.gas_samples.extend(gas_samples(&self.app, &checks));
+                    record_event_checks(&self.app, false, &mut self.counts, &checks)?;
+                    let signed = already_signed(&checks, submitted);
+                    if self.app.config.verify_own_signatures && !signed.is_empty() {
+                        info!(
+                            target: "bridge::withdraw_confirm",
+                            "verifying the stored signatures of {} withdraws this authority already signed",
+                            signed.len()
+                        );
+                        WithdrawConfirmState::VerifyOwnSignatures {
+                            future: verify_own_signatures(&self.app, self.foreign_contract, signed),
+                            block,
+                        }
+                    } else {
+                        WithdrawConfirmState::Yield(Some(block))
+                    }
+                }
+                WithdrawConfirmState::VerifyOwnSignatures {
+                    ref mut future,
+                    block,
+                } => {
+                    let verified = try_ready!(future.poll());
+                    self.counts.signature_mismatches += verified.mismatches.len() as u64;
+                    self.signature_mismatches.extend(verified.mismatches);
+                    resubmit_signatures(
+                        &self.app,
+                        &mut self.counts,
+                        &mut self.attempts,
+                        self.foreign_contract,
+                        verified.resubmissions,
+                        block,
+                    )?
                 }
                 WithdrawConfirmState::Yield(ref mut block) => match block.take() {
                     None => {
-                        info!("waiting for new withdraws that should get signed");
+                        info!(
+                            target: "bridge::withdraw_confirm",
+                            "waiting for new withdraws that should get signed"
+                        );
                         WithdrawConfirmState::Wait
                     }
                     some => return Ok(some.into()),
                 },
             };
+            let now = self.app.timer.now();
+            match next_state {
+                WithdrawConfirmState::Yield(Some(block)) => {
+                    self.attempts.succeed();
+                    if let Some(timings) = self.steps.finish(now) {
+                        self.counts.step_durations.record(&timings);
+                        info!(
+                            target: "bridge::withdraw_confirm",
+                            "signatures of withdraws up to block {} submitted. step durations: {}",
+                            block,
+                            timings
+                        );
+                    }
+                }
+                ref state => self.steps.enter(state.step(), now),
+            }
             self.state = next_state;
         }
     }
 }
+
+impl<T: Transport + Clone> Stream for WithdrawConfirm<T> {
+    type Item = u64;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let result = self.poll_withdraws();
+        if let Err(ref err) = result {
+            self.attempts.fail(err);
+        }
+        result
+    }
+}
+
+/// asks `HomeBridge.isWithdrawExecuted` for all `messages` at once.
+fn check_executed<T: Transport>(
+    app: &App<T>,
+    home_contract: Address,
+    messages: &[MessageToMainnet],
+) -> JoinAll<Vec<Timeout<ApiCall<Bytes, T::Out>>>> {
+    let calls = messages
+        .iter()
+        .map(|message| {
+            let payload = app.home_bridge
+                .functions()
+                .is_withdraw_executed()
+                .input(message.sidenet_transaction_hash.0);
+            api::timeout(
+                &app.timer,
+                api::call(&app.connections.home, home_contract, payload.into()),
+                "home",
+                app.config.home.request_timeout,
+            )
+        })
+        .collect::<Vec<_>>();
+    join_all(calls)
+}
+
+/// signs `messages` except those given up after too many attempts.
+fn sign_withdraws<T: Transport>(
+    app: &App<T>,
+    attempts: &mut AttemptLog,
+    messages: Vec<MessageToMainnet>,
+    oldest_block: u64,
+    block: u64,
+) -> WithdrawConfirmState<T> {
+    let messages = messages
+        .into_iter()
+        .filter(|message| {
+            let hash = message.sidenet_transaction_hash;
+            let started = attempts.start(hash, WithdrawPhase::Sign);
+            if !started {
+                error!(
+                    target: "bridge::withdraw_confirm",
+                    "withdraw in foreign transaction {} is given up after too many attempts. not signing it",
+                    hash
+                );
+            }
+            started
+        })
+        .collect::<Vec<_>>();
+    let requests = messages
+        .iter()
+        .map(|message| {
+            api::timeout(
+                &app.timer,
+                api::node_sign(
+                    &app.connections.foreign,
+                    &app.config.foreign,
+                    Bytes(message.to_bytes()),
+                ),
+                "foreign",
+                app.config.foreign.request_timeout,
+            ).instrument(relay_span(Direction::ForeignToHome, &message.sidenet_transaction_hash))
+        })
+        .collect::<Vec<_>>();
+
+    info!(target: "bridge::withdraw_confirm", "signing");
+    WithdrawConfirmState::SignWithdraws {
+        future: join_all(requests),
+        messages,
+        oldest_block,
+        block,
+    }
+}
+
+fn submit_signatures<T: Transport>(
+    app: &Arc<App<T>>,
+    confirmations: Vec<TransactionRequest>,
+    messages: Vec<MessageToMainnet>,
+    block: u64,
+) -> WithdrawConfirmState<T> {
+    info!(
+        target: "bridge::withdraw_confirm",
+        "submitting {} signatures",
+        confirmations.len()
+    );
+    let expected = expected_events(app, &confirmations);
+    let quota = foreign_quota(app, Component::WithdrawConfirm);
+    let spans = messages
+        .iter()
+        .map(|message| relay_span(Direction::ForeignToHome, &message.sidenet_transaction_hash))
+        .collect();
+    WithdrawConfirmState::ConfirmWithdraws {
+        future: send_foreign_transactions(app.clone(), confirmations, quota).in_spans(spans),
+        messages,
+        expected,
+        block,
+    }
+}
+
+/// submits the new signatures of the withdraws `verify_own_signatures` signed again,
+/// except those given up after too many attempts.
+fn resubmit_signatures<T: Transport>(
+    app: &Arc<App<T>>,
+    counts: &mut RelayCounts,
+    attempts: &mut AttemptLog,
+    foreign_contract: Address,
+    resubmissions: Vec<(MessageToMainnet, H520)>,
+    block: u64,
+) -> Result<WithdrawConfirmState<T>> {
+    let resubmissions = resubmissions
+        .into_iter()
+        .filter(|&(ref message, _)| {
+            attempts.start(message.sidenet_transaction_hash, WithdrawPhase::SubmitSignature)
+        })
+        .collect::<Vec<_>>();
+    if resubmissions.is_empty() {
+        return Ok(WithdrawConfirmState::Yield(Some(block)));
+    }
+    let confirmations = resubmissions
+        .iter()
+        .map(|&(ref message, signature)| {
+            checked_submission(app, counts, foreign_contract, message, signature)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let messages = resubmissions
+        .into_iter()
+        .map(|(message, _)| message)
+        .collect();
+    Ok(submit_signatures(app, confirmations, messages, block))
+}
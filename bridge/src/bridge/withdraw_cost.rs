@@ -0,0 +1,179 @@
+use std::cmp;
+use std::sync::Arc;
+use futures::{Future, Poll, Stream};
+use timer::Interval;
+use web3::Transport;
+use web3::types::U256;
+use api::{self, ApiCall, Timeout};
+use app::App;
+use error::Error;
+use units::{format_ether, format_wei};
+
+/// Expected cost on home of relaying a withdraw.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct WithdrawCost {
+    /// `estimated_gas_cost_of_withdraw`
+    pub gas: U256,
+    /// current gas price of home
+    pub gas_price: U256,
+    /// `gas * gas_price` in wei
+    pub cost: U256,
+    /// fee suggested for a withdraw in wei: `cost`, but at least `withdraw_cost.min_fee`
+    pub fee: U256,
+}
+
+impl WithdrawCost {
+    /// cost of `gas` at `gas_price`, with a fee of at least `min_fee`.
+    pub fn new(gas: U256, gas_price: U256, min_fee: U256) -> Self {
+        let cost = gas * gas_price;
+        WithdrawCost {
+            gas,
+            gas_price,
+            cost,
+            fee: cmp::max(cost, min_fee),
+        }
+    }
+
+    /// `true` if `self.cost` differs from `previous.cost` by more than `percent` percent.
+    pub fn drifted_from(&self, previous: &WithdrawCost, percent: u64) -> bool {
+        let difference = if self.cost > previous.cost {
+            self.cost - previous.cost
+        } else {
+            previous.cost - self.cost
+        };
+        difference * U256::from(100) > previous.cost * U256::from(percent)
+    }
+}
+
+enum WithdrawCostState<T: Transport> {
+    /// Waiting for the next calculation.
+    Wait,
+    /// Fetching the gas price of home.
+    FetchGasPrice(Timeout<ApiCall<U256, T::Out>>),
+    /// Cost has been calculated.
+    Yield(Option<WithdrawCost>),
+}
+
+pub fn create_withdraw_cost_monitor<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    interval: Interval,
+    log_drift: u64,
+    min_fee: U256,
+) -> WithdrawCostMonitor<T> {
+    WithdrawCostMonitor {
+        app,
+        interval,
+        log_drift,
+        min_fee,
+        logged: None,
+        state: WithdrawCostState::Wait,
+    }
+}
+
+/// Periodically calculates the expected cost of relaying a withdraw
+/// from the gas price of home. logs it when it drifts more than
+/// `log_drift` percent from the last logged cost.
+pub struct WithdrawCostMonitor<T: Transport> {
+    app: Arc<App<T>>,
+    interval: Interval,
+    log_drift: u64,
+    /// floor of the suggested fee
+    min_fee: U256,
+    /// last logged cost
+    logged: Option<WithdrawCost>,
+    state: WithdrawCostState<T>,
+}
+
+impl<T: Transport> Stream for WithdrawCostMonitor<T> {
+    type Item = WithdrawCost;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let next_state = match self.state {
+                WithdrawCostState::Wait => {
+                    let _ = try_stream!(self.interval.poll());
+                    WithdrawCostState::FetchGasPrice(api::timeout(
+                        &self.app.timer,
+                        api::gas_price(&self.app.connections.home),
+                        "home",
+                        self.app.config.home.request_timeout,
+                    ))
+                }
+                WithdrawCostState::FetchGasPrice(ref mut future) => {
+                    let gas_price = try_ready!(future.poll());
+                    let cost = WithdrawCost::new(
+                        self.app.config.estimated_gas_cost_of_withdraw,
+                        gas_price,
+                        self.min_fee,
+                    );
+                    debug!("withdraw cost: {:?}", cost);
+                    match self.logged {
+                        None => {
+                            info!(
+                                "expected cost of relaying a withdraw is {} ({} gas at {}), suggested fee {}",
+                                format_ether(cost.cost, 0),
+                                cost.gas,
+                                format_wei(cost.gas_price),
+                                format_ether(cost.fee, 0)
+                            );
+                            self.logged = Some(cost);
+                        }
+                        Some(logged) if cost.drifted_from(&logged, self.log_drift) => {
+                            info!(
+                                "expected cost of relaying a withdraw changed from {} ({} gas price) to {} ({} gas at {}), suggested fee from {} to {}",
+                                format_ether(logged.cost, 0),
+                                format_wei(logged.gas_price),
+                                format_ether(cost.cost, 0),
+                                cost.gas,
+                                format_wei(cost.gas_price),
+                                format_ether(logged.fee, 0),
+                                format_ether(cost.fee, 0)
+                            );
+                            self.logged = Some(cost);
+                        }
+                        Some(_) => {}
+                    }
+                    WithdrawCostState::Yield(Some(cost))
+                }
+                WithdrawCostState::Yield(ref mut cost) => match cost.take() {
+                    None => WithdrawCostState::Wait,
+                    some => return Ok(some.into()),
+                },
+            };
+            self.state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::U256;
+    use super::WithdrawCost;
+
+    #[test]
+    fn test_withdraw_cost() {
+        let cost = WithdrawCost::new(100000.into(), 20.into(), 0.into());
+        assert_eq!(U256::from(2000000), cost.cost);
+        assert_eq!(U256::from(2000000), cost.fee);
+    }
+
+    #[test]
+    fn test_withdraw_fee_floor() {
+        let cost = WithdrawCost::new(100000.into(), 20.into(), 3000000.into());
+        assert_eq!(U256::from(2000000), cost.cost);
+        assert_eq!(U256::from(3000000), cost.fee);
+        let cost = WithdrawCost::new(100000.into(), 40.into(), 3000000.into());
+        assert_eq!(U256::from(4000000), cost.fee);
+    }
+
+    #[test]
+    fn test_withdraw_cost_drift() {
+        let previous = WithdrawCost::new(100.into(), 100.into(), 0.into());
+        assert!(!WithdrawCost::new(100.into(), 110.into(), 0.into()).drifted_from(&previous, 10));
+        assert!(!WithdrawCost::new(100.into(), 90.into(), 0.into()).drifted_from(&previous, 10));
+        assert!(WithdrawCost::new(100.into(), 111.into(), 0.into()).drifted_from(&previous, 10));
+        assert!(WithdrawCost::new(100.into(), 89.into(), 0.into()).drifted_from(&previous, 10));
+        assert!(WithdrawCost::new(100.into(), 1.into(), 0.into()).drifted_from(&previous, 0));
+    }
+}
@@ -0,0 +1,175 @@
+use web3::Transport;
+use web3::types::{Address, H256, TransactionReceipt, U256};
+use app::App;
+use database::{Database, WithdrawOrigin};
+use message_to_mainnet::MessageToMainnet;
+
+/// number of withdraws `WithdrawOrigins` keeps.
+pub const KEPT_WITHDRAW_ORIGINS: usize = 4096;
+
+/// `Withdraw` events of the withdraws withdraw relay may relay, the most recent
+/// `KEPT_WITHDRAW_ORIGINS` of them, oldest first. kept with `check_withdraw_origins`.
+///
+/// withdraw confirm and withdraw relay follow `Withdraw` and `CollectedSignatures` with log
+/// streams of their own, so the signatures of a withdraw may be collected before withdraw
+/// confirm observed it, e.g. if other authorities sign after fewer confirmations. withdraw relay
+/// only relays a message once its `Withdraw` is recorded here, fetching it from foreign if it's
+/// missing, and links the message to it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WithdrawOrigins {
+    origins: Vec<WithdrawOrigin>,
+}
+
+impl WithdrawOrigins {
+    /// origins continuing from those of `init`.
+    pub fn new(init: &Database) -> Self {
+        WithdrawOrigins {
+            origins: init.withdraw_origins.clone(),
+        }
+    }
+
+    fn push(&mut self, origin: WithdrawOrigin) {
+        if self.origins.len() >= KEPT_WITHDRAW_ORIGINS {
+            self.origins.remove(0);
+        }
+        self.origins.push(origin);
+    }
+
+    /// records the withdraw of foreign transaction `transaction` observed by withdraw confirm.
+    /// a withdraw is only recorded once.
+    pub fn observe(&mut self, transaction: H256, block: u64, log_index: Option<U256>) {
+        if self.find(transaction).is_some() {
+            return;
+        }
+        self.push(WithdrawOrigin {
+            transaction,
+            block,
+            log_index,
+            message_hash: None,
+            fetched: false,
+        });
+    }
+
+    /// records `origin` fetched from foreign unless its withdraw is recorded already.
+    pub fn record_fetched(&mut self, origin: WithdrawOrigin) {
+        if self.find(origin.transaction).is_none() {
+            self.push(origin);
+        }
+    }
+
+    /// the recorded withdraw of foreign transaction `transaction`.
+    pub fn find(&self, transaction: H256) -> Option<&WithdrawOrigin> {
+        self.origins
+            .iter()
+            .rev()
+            .find(|origin| origin.transaction == transaction)
+    }
+
+    /// links the withdraw of `transaction` to the message `message_hash` relaying it.
+    /// `false` if the withdraw isn't recorded.
+    pub fn link(&mut self, transaction: H256, message_hash: H256) -> bool {
+        match self.origins
+            .iter_mut()
+            .rev()
+            .find(|origin| origin.transaction == transaction)
+        {
+            Some(origin) => {
+                origin.message_hash = Some(message_hash);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// kept origins, oldest first.
+    pub fn origins(&self) -> &[WithdrawOrigin] {
+        &self.origins
+    }
+}
+
+/// the `Withdraw` of `foreign_contract` in `receipt` `message` was signed for, converted by
+/// `value_scale` the same as by withdraw confirm. `None` if the transaction emitted none.
+pub fn withdraw_origin<T: Transport>(
+    app: &App<T>,
+    foreign_contract: Address,
+    message: &MessageToMainnet,
+    receipt: &TransactionReceipt,
+) -> Option<WithdrawOrigin> {
+    receipt
+        .logs
+        .iter()
+        .filter(|log| log.address == foreign_contract)
+        .find(|log| {
+            // unique log ids are rejected with `check_withdraw_origins`
+            let mut signed = match MessageToMainnet::from_log((*log).clone(), false) {
+                Ok(signed) => signed,
+                // another event of the contract
+                Err(_) => return false,
+            };
+            if let Some(ref scale) = app.config.value_scale {
+                signed.value = match scale.withdraw(signed.value) {
+                    Ok(scaled) => scaled.value,
+                    Err(_) => return false,
+                };
+            }
+            signed == *message
+        })
+        .map(|log| WithdrawOrigin {
+            transaction: receipt.transaction_hash,
+            block: receipt.block_number.low_u64(),
+            log_index: log.log_index,
+            message_hash: Some(message.hash()),
+            fetched: true,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use database::{Database, WithdrawOrigin};
+    use super::{WithdrawOrigins, KEPT_WITHDRAW_ORIGINS};
+
+    #[test]
+    fn test_withdraw_origins_link_recorded_withdraws() {
+        let mut origins = WithdrawOrigins::new(&Database::default());
+        origins.observe(1.into(), 10, Some(0.into()));
+        // observed again, e.g. after a restart
+        origins.observe(1.into(), 10, Some(0.into()));
+        assert_eq!(1, origins.origins().len());
+
+        assert!(origins.link(1.into(), 100.into()));
+        assert!(!origins.link(2.into(), 200.into()));
+        assert_eq!(Some(100.into()), origins.find(1.into()).unwrap().message_hash);
+        assert!(!origins.find(1.into()).unwrap().fetched);
+
+        let fetched = WithdrawOrigin {
+            transaction: 2.into(),
+            block: 11,
+            log_index: Some(1.into()),
+            message_hash: Some(200.into()),
+            fetched: true,
+        };
+        origins.record_fetched(fetched.clone());
+        origins.record_fetched(WithdrawOrigin {
+            block: 12,
+            ..fetched.clone()
+        });
+        assert_eq!(Some(&fetched), origins.find(2.into()));
+
+        let database = Database {
+            withdraw_origins: origins.origins().to_vec(),
+            ..Database::default()
+        };
+        assert_eq!(origins, WithdrawOrigins::new(&database));
+    }
+
+    #[test]
+    fn test_withdraw_origins_keep_the_latest() {
+        let mut origins = WithdrawOrigins::new(&Database::default());
+        for transaction in 0..KEPT_WITHDRAW_ORIGINS as u64 + 1 {
+            origins.observe(transaction.into(), 10, None);
+        }
+        assert_eq!(KEPT_WITHDRAW_ORIGINS, origins.origins().len());
+        assert!(origins.find(0.into()).is_none());
+        assert!(origins.find(1.into()).is_some());
+    }
+}
@@ -1,18 +1,40 @@
+use std::collections::BTreeMap;
+use std::mem;
 use std::sync::Arc;
 use futures::{Future, Poll, Stream};
 use futures::future::{join_all, Join, JoinAll};
-use tokio_timer::Timeout;
+use timer::Sleep;
+use tracing::Span;
+use tracing_futures::{Instrument, Instrumented};
 use web3::Transport;
-use web3::types::{Address, Bytes, FilterBuilder, H256, Log, TransactionRequest};
-use ethabi::{self, RawLog};
+use web3::types::{Address, Bytes, FilterBuilder, H256, Log, TransactionReceipt, TransactionRequest,
+                  U256};
+use ethabi;
 use app::App;
-use api::{self, ApiCall, LogStream};
-use contracts::foreign;
-use util::web3_filter;
-use database::Database;
-use error::{self, Error};
+use api::{self, ApiCall, LogStream, OversizedLog, Settle, Timeout};
+use super::{relay_key, relay_span, Direction, RelayCounts, RelayRecord};
+use super::call_data::{check_call_data, ExpectedCall};
+use super::fairness::responsible_authority;
+use super::gas_usage::{gas_samples, GasSample};
+use super::relay_events::{expected_events, record_event_checks, verify_relay_events, ExpectedEvent,
+                          VerifyRelayEvents};
+use super::pending_age::{PendingAges, PendingRelay};
+use super::relayed_messages::{MessageStatus, RelayedMessages};
+use super::spend_limit::{unix_time, SpendKind, SpendLimiter, WITHDRAW_SPEND_WINDOW};
+use super::revert::REVERT_SELECTOR;
+use super::state_horizon::StateHorizon;
+use super::step_timing::{RelayStep, StepTimer};
+use super::withdraw_attempts::AttemptLog;
+use super::withdraw_origins::{withdraw_origin, WithdrawOrigins};
+use contracts::{foreign, payout_registry};
+use util::{check_log_data, log_transaction_hash, raw_log, web3_filter};
+use config::BalancePolicy;
+use database::{Database, WithdrawPhase};
+use error::{self, Error, ErrorKind, ResultExt};
+use jitter;
 use message_to_mainnet::MessageToMainnet;
 use signature::Signature;
+use units::format_ether;
 
 /// returns a filter for `ForeignBridge.CollectedSignatures` events
 fn collected_signatures_filter(
@@ -39,22 +61,48 @@ fn signatures_payload(
     my_address: Address,
     log: Log,
 ) -> error::Result<Option<RelayAssignment>> {
-    // convert web3::Log to ethabi::RawLog since ethabi events can
-    // only be parsed from the latter
-    let raw_log = RawLog {
-        topics: log.topics.into_iter().map(|t| t.0.into()).collect(),
-        data: log.data.0,
-    };
-    let collected_signatures = foreign.events().collected_signatures().parse_log(raw_log)?;
+    parse_signatures_payload(foreign, required_signatures, my_address, &log)
+        .chain_err(|| ErrorKind::InvalidLog(log))
+}
+
+/// hash of the message whose signatures the `CollectedSignatures` `log` announces.
+fn collected_message_hash(foreign: &foreign::ForeignBridge, log: &Log) -> error::Result<H256> {
+    check_log_data(log).chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+    let collected_signatures = foreign
+        .events()
+        .collected_signatures()
+        .parse_log(raw_log(log))
+        .chain_err(|| ErrorKind::InvalidLog(log.clone()))?;
+    Ok(collected_signatures.message_hash.into())
+}
+
+fn parse_signatures_payload(
+    foreign: &foreign::ForeignBridge,
+    required_signatures: u32,
+    my_address: Address,
+    log: &Log,
+) -> error::Result<Option<RelayAssignment>> {
+    check_log_data(log)?;
+    let collected_signatures = foreign
+        .events()
+        .collected_signatures()
+        .parse_log(raw_log(log))?;
+    let hash = log_transaction_hash(log)?;
     if collected_signatures.authority_responsible_for_relay != my_address.0.into() {
         info!(
+            target: "bridge::withdraw_relay",
             "bridge not responsible for relaying transaction to home. tx hash: {}",
-            log.transaction_hash.unwrap()
+            hash
         );
         // this authority is not responsible for relaying this transaction.
         // someone else will relay this transaction to home.
         return Ok(None);
     }
+    info!(
+        target: "bridge::withdraw_relay",
+        "collected signature is ready for relay: tx hash: {}",
+        hash
+    );
     let signature_payloads = (0..required_signatures)
         .into_iter()
         .map(|index| {
@@ -77,44 +125,621 @@ fn signatures_payload(
     }))
 }
 
+/// returns `true` if the `eth_call` simulation of a withdraw predicts that it fails.
+///
+/// parity fails the call with an rpc error if it reverts.
+/// geth returns the abi encoded revert reason or `0x`, which can't be told apart from
+/// the output of a successful `HomeBridge.withdraw`. insufficient funds in the contract
+/// are detected separately by comparing its balance to the withdrawn value.
+fn simulation_failed(result: &error::Result<Bytes>) -> bool {
+    match *result {
+        Err(_) => true,
+        Ok(ref output) => output.0.starts_with(&REVERT_SELECTOR),
+    }
+}
+
+/// withdraws that are ready to be sent to `HomeBridge`
+#[derive(Default)]
+pub struct PendingWithdraws {
+    requests: Vec<TransactionRequest>,
+    /// hashes of the foreign transactions that emitted the withdraws
+    withdraw_hashes: Vec<H256>,
+    /// withdrawn value of each withdraw
+    values: Vec<U256>,
+    total_value: U256,
+    messages: Vec<MessageToMainnet>,
+    /// signatures of each message, in the order they were submitted to `ForeignBridge`
+    signatures: Vec<Vec<Signature>>,
+    /// payout address each withdraw is paid to instead of its recipient.
+    /// `None` if paid to the recipient
+    payouts: Vec<Option<Address>>,
+}
+
+impl PendingWithdraws {
+    /// removes the withdraws for which `executed` is `true`.
+    fn remove_executed(&mut self, executed: &[bool]) {
+        for (hash, executed) in self.withdraw_hashes.iter().zip(executed) {
+            if *executed {
+                info!(
+                    target: "bridge::withdraw_relay",
+                    "withdraw in foreign transaction {} already executed. skipping",
+                    hash
+                );
+            }
+        }
+        self.remove(executed);
+    }
+
+    /// removes the withdraws for which `removed` is `true`.
+    fn remove(&mut self, removed: &[bool]) {
+        self.take(removed);
+    }
+
+    /// removes the withdraws for which `taken` is `true` and returns them.
+    fn take(&mut self, taken: &[bool]) -> PendingWithdraws {
+        let pending = mem::replace(self, PendingWithdraws::default());
+        let mut taken_withdraws = PendingWithdraws::default();
+        let withdraws = pending
+            .requests
+            .into_iter()
+            .zip(pending.withdraw_hashes.into_iter())
+            .zip(pending.values.into_iter())
+            .zip(pending.messages.into_iter())
+            .zip(pending.signatures.into_iter())
+            .zip(pending.payouts.into_iter())
+            .zip(taken.iter());
+        for ((((((request, hash), value), message), signatures), payout), taken) in withdraws {
+            let withdraws = if *taken {
+                &mut taken_withdraws
+            } else {
+                &mut *self
+            };
+            withdraws.requests.push(request);
+            withdraws.withdraw_hashes.push(hash);
+            withdraws.values.push(value);
+            withdraws.total_value = withdraws.total_value + value;
+            withdraws.messages.push(message);
+            withdraws.signatures.push(signatures);
+            withdraws.payouts.push(payout);
+        }
+        taken_withdraws
+    }
+
+    /// appends the withdraws of `other`.
+    fn append(&mut self, other: PendingWithdraws) {
+        self.requests.extend(other.requests);
+        self.withdraw_hashes.extend(other.withdraw_hashes);
+        self.values.extend(other.values);
+        self.total_value = self.total_value + other.total_value;
+        self.messages.extend(other.messages);
+        self.signatures.extend(other.signatures);
+        self.payouts.extend(other.payouts);
+    }
+
+    /// removes the withdraws from index `at` on and returns them.
+    fn split_off(&mut self, at: usize) -> PendingWithdraws {
+        let values = self.values.split_off(at);
+        let total_value = values
+            .iter()
+            .fold(U256::zero(), |sum, value| sum + *value);
+        self.total_value = self.total_value - total_value;
+        PendingWithdraws {
+            requests: self.requests.split_off(at),
+            withdraw_hashes: self.withdraw_hashes.split_off(at),
+            values,
+            total_value,
+            messages: self.messages.split_off(at),
+            signatures: self.signatures.split_off(at),
+            payouts: self.payouts.split_off(at),
+        }
+    }
+}
+
+/// which of the withdraws with `values` `balance` covers by `policy`, see `BalancePolicy`.
+fn funded_withdraws(values: &[U256], balance: U256, policy: BalancePolicy) -> Vec<bool> {
+    let mut remaining = balance;
+    let mut held_back = false;
+    values
+        .iter()
+        .map(|value| {
+            let funded = !held_back && *value <= remaining;
+            if funded {
+                remaining = remaining - *value;
+            } else if policy == BalancePolicy::Fifo {
+                held_back = true;
+            }
+            funded
+        })
+        .collect()
+}
+
+/// splits off the `pending` withdraws `spend_limiter` defers and returns them.
+fn limit_withdraws(
+    counts: &mut RelayCounts,
+    spend_limiter: &mut Option<SpendLimiter>,
+    pending: &mut PendingWithdraws,
+    block: u64,
+) -> PendingWithdraws {
+    let limiter = match *spend_limiter {
+        Some(ref mut limiter) => limiter,
+        None => return PendingWithdraws::default(),
+    };
+    let was_deferring = !limiter.deferred().is_empty();
+    let relays = pending
+        .withdraw_hashes
+        .iter()
+        .map(|hash| (relay_key(Direction::ForeignToHome, hash, None), U256::from(1)))
+        .collect::<Vec<_>>();
+    let now = unix_time();
+    let allowed = limiter.allow(now, block, &relays);
+    let deferred = pending.split_off(allowed);
+
+    if deferred.requests.is_empty() {
+        if was_deferring {
+            info!(
+                target: "bridge::withdraw_relay",
+                "spend limit allows the deferred withdraws up to block {}",
+                block
+            );
+        }
+    } else if was_deferring {
+        debug!(
+            target: "bridge::withdraw_relay",
+            "spend limit still defers {} withdraws up to block {}",
+            deferred.requests.len(),
+            block
+        );
+    } else {
+        error!(
+            target: "bridge::withdraw_relay",
+            "CRITICAL: spend limit of {} withdraws per hour reached with {} relayed. deferring {} withdraws up to block {}. raise the limit or release withdraws to relay them earlier",
+            limiter.limit(),
+            limiter.spent(now),
+            deferred.requests.len(),
+            block
+        );
+        counts.deferred += deferred.requests.len() as u64;
+    }
+    deferred
+}
+
+/// parses the `messages` returned by `ForeignBridge.message`.
+/// withdraws with invalid messages are skipped together with their `signatures`:
+/// `HomeBridge` rejects them, so relaying them would only fail.
+fn parse_messages(
+    messages: Vec<Bytes>,
+    signatures: Vec<Vec<Signature>>,
+) -> (Vec<MessageToMainnet>, Vec<Vec<Signature>>) {
+    let mut parsed = (Vec::new(), Vec::new());
+    for (message, signatures) in messages.into_iter().zip(signatures.into_iter()) {
+        match MessageToMainnet::parse(&message.0) {
+            Ok(message) => {
+                parsed.0.push(message);
+                parsed.1.push(signatures);
+            }
+            Err(err) => error!(
+                target: "bridge::withdraw_relay",
+                "skipping withdraw with invalid message {:?}: {}",
+                message,
+                err
+            ),
+        }
+    }
+    parsed
+}
+
+/// `HomeBridge.withdraw` transaction relaying `message` with `signatures`,
+/// `HomeBridge.withdrawToPayout` paying it to `payout` if that's `Some`.
+/// fails if the payload doesn't decode back to its arguments. see `check_call_data`.
+fn withdraw_request<T: Transport>(
+    app: &App<T>,
+    counts: &mut RelayCounts,
+    home_contract: Address,
+    message: &MessageToMainnet,
+    signatures: &[Signature],
+    payout: Option<Address>,
+) -> error::Result<TransactionRequest> {
+    let (payload, expected): (Bytes, _) = match payout {
+        None => (
+            app.home_bridge
+                .functions()
+                .withdraw()
+                .input(
+                    signatures.iter().map(|x| x.v),
+                    signatures.iter().map(|x| x.r),
+                    signatures.iter().map(|x| x.s),
+                    message.to_bytes(),
+                )
+                .into(),
+            ExpectedCall::withdraw(signatures, message.to_bytes()),
+        ),
+        Some(payout) => (
+            app.home_bridge
+                .functions()
+                .withdraw_to_payout()
+                .input(
+                    signatures.iter().map(|x| x.v),
+                    signatures.iter().map(|x| x.r),
+                    signatures.iter().map(|x| x.s),
+                    message.to_bytes(),
+                    payout,
+                )
+                .into(),
+            ExpectedCall::withdraw_to_payout(signatures, message.to_bytes(), payout),
+        ),
+    };
+    check_call_data(app, counts, &expected, &payload.0)?;
+    Ok(TransactionRequest {
+        from: app.config.home.sender(),
+        to: Some(home_contract),
+        gas: app.tunables.get().txs.withdraw_relay.request_gas(),
+        gas_price: Some(message.mainnet_gas_price),
+        value: None,
+        data: Some(payload),
+        nonce: None,
+        condition: None,
+    })
+}
+
+/// calls `PayoutRegistry.payoutAddressOf` with the recipient of every `pending` withdraw.
+fn fetch_payouts<T: Transport>(
+    app: &App<T>,
+    registry: Address,
+    pending: &PendingWithdraws,
+) -> JoinAll<Vec<Timeout<ApiCall<Bytes, T::Out>>>> {
+    let functions = payout_registry::PayoutRegistry::default().functions();
+    let calls = pending
+        .messages
+        .iter()
+        .map(|message| {
+            let payload = functions.payout_address_of().input(message.recipient);
+            api::timeout(
+                &app.timer,
+                api::call(&app.connections.home, registry, payload.into()),
+                "home",
+                app.config.home.request_timeout,
+            )
+        })
+        .collect::<Vec<_>>();
+    join_all(calls)
+}
+
+/// the payout address `recipient` registered, `None` if it registered none or itself.
+fn payout_of(recipient: Address, registered: Address) -> Option<Address> {
+    if registered.is_zero() || registered == recipient {
+        None
+    } else {
+        Some(registered)
+    }
+}
+
+/// reads `HomeBridge.requiredSignatures`, which may differ from the configured
+/// `authorities.required_signatures` after the threshold changed.
+fn fetch_required_signatures<T: Transport>(
+    app: &App<T>,
+    home_contract: Address,
+) -> Timeout<ApiCall<Bytes, T::Out>> {
+    let payload = app.home_bridge.functions().required_signatures().input();
+    api::timeout(
+        &app.timer,
+        api::call(&app.connections.home, home_contract, payload.into()),
+        "home",
+        app.config.home.request_timeout,
+    )
+}
+
+type MessagesSignaturesFuture<T> = Join<
+    JoinAll<Vec<Timeout<ApiCall<Bytes, <T as Transport>::Out>>>>,
+    JoinAll<Vec<JoinAll<Vec<Timeout<ApiCall<Bytes, <T as Transport>::Out>>>>>>,
+>;
+
+/// calls `ForeignBridge.message` and `ForeignBridge.signature` with `messages` and `signatures`
+/// at `block`, or at the latest block if `None`.
+fn fetch_messages_signatures<T: Transport>(
+    app: &App<T>,
+    foreign_contract: Address,
+    messages: &[Bytes],
+    signatures: &[Vec<Bytes>],
+    block: Option<u64>,
+) -> MessagesSignaturesFuture<T> {
+    let call = |payload: &Bytes| {
+        api::timeout(
+            &app.timer,
+            api::call_at(&app.connections.foreign, foreign_contract, payload.clone(), block),
+            "foreign",
+            app.config.foreign.request_timeout,
+        )
+    };
+    let message_calls = messages.iter().map(&call).collect::<Vec<_>>();
+    let signature_calls = signatures
+        .iter()
+        .map(|payloads| join_all(payloads.iter().map(&call).collect::<Vec<_>>()))
+        .collect::<Vec<_>>();
+    join_all(message_calls).join(join_all(signature_calls))
+}
+
+type MissingSignaturesFuture<T> =
+    JoinAll<Vec<JoinAll<Vec<Settle<Timeout<ApiCall<Bytes, <T as Transport>::Out>>>>>>>;
+
+/// asks `ForeignBridge.signature` for the signatures each `pending` withdraw lacks
+/// to reach `required`. calls for signatures not yet submitted fail.
+fn fetch_missing_signatures<T: Transport>(
+    app: &App<T>,
+    foreign_contract: Address,
+    pending: &PendingWithdraws,
+    required: u32,
+) -> MissingSignaturesFuture<T> {
+    let calls = pending
+        .messages
+        .iter()
+        .zip(pending.signatures.iter())
+        .map(|(message, signatures)| {
+            let hash = message.hash();
+            let calls = (signatures.len() as u32..required)
+                .map(|index| {
+                    let payload = app.foreign_bridge.functions().signature().input(hash.0, index);
+                    api::settle(api::timeout(
+                        &app.timer,
+                        api::call(&app.connections.foreign, foreign_contract, payload.into()),
+                        "foreign",
+                        app.config.foreign.request_timeout,
+                    ))
+                })
+                .collect::<Vec<_>>();
+            join_all(calls)
+        })
+        .collect::<Vec<_>>();
+    join_all(calls)
+}
+
+/// fetches the receipts of the foreign transactions of the `pending` withdraws at `indexes`.
+fn fetch_origin_receipts<T: Transport>(
+    app: &App<T>,
+    pending: &PendingWithdraws,
+    indexes: &[usize],
+) -> JoinAll<Vec<Timeout<ApiCall<Option<TransactionReceipt>, T::Out>>>> {
+    let calls = indexes
+        .iter()
+        .map(|index| {
+            let hash = pending.withdraw_hashes[*index];
+            api::timeout(
+                &app.timer,
+                api::transaction_receipt(&app.connections.foreign, hash),
+                "foreign",
+                app.config.foreign.request_timeout,
+            ).about(hash)
+        })
+        .collect::<Vec<_>>();
+    join_all(calls)
+}
+
+/// asks `HomeBridge.isWithdrawExecuted` for the `pending` withdraws at `indexes`.
+fn check_executed<T: Transport>(
+    app: &App<T>,
+    home_contract: Address,
+    pending: &PendingWithdraws,
+    indexes: &[usize],
+) -> JoinAll<Vec<Timeout<ApiCall<Bytes, T::Out>>>> {
+    let calls = indexes
+        .iter()
+        .map(|index| {
+            let payload = app.home_bridge
+                .functions()
+                .is_withdraw_executed()
+                .input(pending.withdraw_hashes[*index].0);
+            api::timeout(
+                &app.timer,
+                api::call(&app.connections.home, home_contract, payload.into()),
+                "home",
+                app.config.home.request_timeout,
+            )
+        })
+        .collect::<Vec<_>>();
+    join_all(calls)
+}
+
+type PrecheckFuture<T> = Join<
+    JoinAll<Vec<Settle<Timeout<ApiCall<Bytes, <T as Transport>::Out>>>>>,
+    Timeout<ApiCall<U256, <T as Transport>::Out>>,
+>;
+
+/// simulates all `pending` withdraws and fetches the balance of `home_contract`.
+fn precheck<T: Transport>(
+    app: &App<T>,
+    home_contract: Address,
+    pending: &PendingWithdraws,
+) -> PrecheckFuture<T> {
+    let simulations = pending
+        .requests
+        .iter()
+        .map(|request| {
+            api::settle(api::timeout(
+                &app.timer,
+                api::simulate_transaction(&app.connections.home, request),
+                "home",
+                app.config.home.request_timeout,
+            ))
+        })
+        .collect::<Vec<_>>();
+    let balance = api::timeout(
+        &app.timer,
+        api::balance(&app.connections.home, home_contract),
+        "home",
+        app.config.home.request_timeout,
+    );
+    join_all(simulations).join(balance)
+}
+
 /// state of the withdraw relay state machine
 pub enum WithdrawRelayState<T: Transport> {
     Wait,
     FetchMessagesSignatures {
-        future: Join<
-            JoinAll<Vec<Timeout<ApiCall<Bytes, T::Out>>>>,
-            JoinAll<Vec<JoinAll<Vec<Timeout<ApiCall<Bytes, T::Out>>>>>>,
-        >,
+        future: MessagesSignaturesFuture<T>,
+        /// payloads to call again at the latest block if the node lacks the state of `block`.
+        /// `None` unless the calls are made at `block`
+        retry: Option<(Vec<Bytes>, Vec<Vec<Bytes>>)>,
+        block: u64,
+    },
+    /// reading the number of signatures `HomeBridge` currently requires
+    FetchRequiredSignatures {
+        future: Timeout<ApiCall<Bytes, T::Out>>,
+        pending: PendingWithdraws,
+        block: u64,
+    },
+    /// fetching the signatures submitted since the threshold was raised
+    FetchMissingSignatures {
+        future: MissingSignaturesFuture<T>,
+        pending: PendingWithdraws,
+        block: u64,
+    },
+    /// fetching the `Withdraw` events withdraw confirm hasn't observed, see `WithdrawOrigins`
+    FetchOrigins {
+        future: JoinAll<Vec<Timeout<ApiCall<Option<TransactionReceipt>, T::Out>>>>,
+        pending: PendingWithdraws,
+        /// indexes of the withdraws in `pending` whose transactions are fetched
+        fetched: Vec<usize>,
+        block: u64,
+    },
+    /// checking which withdraws have already been executed by other authorities
+    CheckExecuted {
+        future: JoinAll<Vec<Timeout<ApiCall<Bytes, T::Out>>>>,
+        pending: PendingWithdraws,
+        /// indexes of the checked withdraws in `pending`
+        checked: Vec<usize>,
+        block: u64,
+    },
+    /// looking up the payout addresses the recipients registered in `payout_registry`
+    FetchPayouts {
+        future: JoinAll<Vec<Timeout<ApiCall<Bytes, T::Out>>>>,
+        pending: PendingWithdraws,
+        block: u64,
+    },
+    /// predicting whether the withdraws would succeed before sending them
+    PrecheckWithdraws {
+        future: PrecheckFuture<T>,
+        pending: PendingWithdraws,
+        block: u64,
+    },
+    /// some withdraws were predicted to fail, lack signatures, exceed the `HomeBridge` balance
+    /// or the spend limit. checking again after a delay
+    DeferWithdraws {
+        future: Sleep,
+        pending: PendingWithdraws,
+        block: u64,
+    },
+    /// withdraws passed all checks. sending those within the spend limit
+    LimitWithdraws {
+        pending: PendingWithdraws,
+        /// withdraws the `HomeBridge` balance doesn't cover. relayed once it does
+        unfunded: PendingWithdraws,
         block: u64,
     },
     RelayWithdraws {
-        future: JoinAll<Vec<Timeout<ApiCall<H256, T::Out>>>>,
+        future: JoinAll<Vec<Instrumented<Timeout<ApiCall<H256, T::Out>>>>>,
+        /// hashes of the foreign transactions that emitted the relayed withdraws
+        withdraw_hashes: Vec<H256>,
+        /// hashes of the messages of the relayed withdraws
+        message_hashes: Vec<H256>,
+        /// account each withdraw is paid to and its value
+        receivers: Vec<(Address, U256)>,
+        /// events the relays must emit. empty unless `relay_events` is configured
+        expected: Vec<Option<ExpectedEvent>>,
+        /// withdraws beyond the spend limit. relayed once the limit allows them
+        deferred: PendingWithdraws,
+        block: u64,
+    },
+    /// checking the receipts of the relays for the expected events
+    VerifyEvents {
+        future: VerifyRelayEvents<T>,
+        deferred: PendingWithdraws,
         block: u64,
     },
     Yield(Option<u64>),
 }
 
-pub fn create_withdraw_relay<T: Transport + Clone>(
-    app: Arc<App<T>>,
+impl<T: Transport> WithdrawRelayState<T> {
+    /// timed step of the relays in this state. `None` if not timed.
+    fn step(&self) -> Option<RelayStep> {
+        match *self {
+            WithdrawRelayState::FetchMessagesSignatures { .. }
+            | WithdrawRelayState::FetchRequiredSignatures { .. }
+            | WithdrawRelayState::FetchMissingSignatures { .. } => Some(RelayStep::Signatures),
+            WithdrawRelayState::FetchOrigins { .. }
+            | WithdrawRelayState::CheckExecuted { .. }
+            | WithdrawRelayState::FetchPayouts { .. }
+            | WithdrawRelayState::PrecheckWithdraws { .. } => Some(RelayStep::Check),
+            WithdrawRelayState::RelayWithdraws { .. } => Some(RelayStep::Submit),
+            WithdrawRelayState::VerifyEvents { .. } => Some(RelayStep::Confirm),
+            _ => None,
+        }
+    }
+}
+
+/// `CollectedSignatures` logs on foreign after the checked block of `init`.
+pub fn collected_signatures_log_stream<T: Transport + Clone>(
+    app: &App<T>,
     init: &Database,
-) -> WithdrawRelay<T> {
+) -> LogStream<T> {
     let logs_init = api::LogStreamInit {
         after: init.checked_withdraw_relay,
+        chain: "foreign",
         request_timeout: app.config.foreign.request_timeout,
-        poll_interval: app.config.foreign.poll_interval,
+        poll_interval: app.tunables.get().foreign_poll_interval,
+        poll_jitter: jitter::poll_jitter(app.config.poll_jitter, &app.config.foreign.account),
         confirmations: app.config.foreign.required_confirmations,
+        sanity_check: app.config.foreign.logs_sanity_check.clone(),
+        head_regression: app.config.foreign.head_regression,
+        log_limit_errors: app.config.foreign.log_limit_errors.clone(),
+        max_log_data: app.config.foreign.max_log_data,
+        finality: app.config.foreign.l1_posted_rpc(),
         filter: collected_signatures_filter(&app.foreign_bridge, init.foreign_contract_address),
     };
+    api::log_stream(app.connections.foreign.clone(), app.timer.clone(), logs_init)
+}
 
+pub fn create_withdraw_relay<T: Transport + Clone>(
+    app: Arc<App<T>>,
+    init: &Database,
+) -> WithdrawRelay<T> {
     WithdrawRelay {
-        logs: api::log_stream(
-            app.connections.foreign.clone(),
-            app.timer.clone(),
-            logs_init,
-        ),
+        logs: collected_signatures_log_stream(&app, init),
         home_contract: init.home_contract_address,
         foreign_contract: init.foreign_contract_address,
         state: WithdrawRelayState::Wait,
+        history: if app.config.relay_history.is_some() || app.config.webhooks.is_some() {
+            Some(Vec::new())
+        } else {
+            None
+        },
+        counts: RelayCounts::default(),
+        gas_samples: Vec::new(),
+        assignments: BTreeMap::new(),
+        required_signatures: app.config.authorities.required_signatures,
+        spend_limiter: app.config
+            .spend_limits
+            .as_ref()
+            .and_then(|config| config.withdraws)
+            .map(|limit| {
+                SpendLimiter::new(
+                    SpendKind::Withdraws,
+                    limit.into(),
+                    WITHDRAW_SPEND_WINDOW,
+                    &init.spend.withdraws,
+                    init.checked_withdraw_relay,
+                )
+            }),
+        foreign_state_horizon: None,
+        pending: PendingAges::new(app.clone(), Direction::ForeignToHome),
+        steps: StepTimer::default(),
+        messages: RelayedMessages::new(&init.relayed_withdraws),
+        attempts: AttemptLog::new(app.config.withdraw_attempts, &[WithdrawPhase::Finalize], init),
+        origins: if app.config.check_withdraw_origins {
+            Some(WithdrawOrigins::new(init))
+        } else {
+            None
+        },
+        draining: false,
+        blocks: Span::none(),
         app,
     }
 }
@@ -125,31 +750,267 @@ pub struct WithdrawRelay<T: Transport> {
     state: WithdrawRelayState<T>,
     foreign_contract: Address,
     home_contract: Address,
+    /// relays not yet taken by `take_history`. `None` if not tracked
+    history: Option<Vec<RelayRecord>>,
+    counts: RelayCounts,
+    /// gas used by the checked relays not yet taken by `take_gas_samples`
+    gas_samples: Vec<GasSample>,
+    /// responsible authorities of the `CollectedSignatures` since the last `take_assignments`
+    assignments: BTreeMap<Address, u64>,
+    /// last known `HomeBridge.requiredSignatures`
+    required_signatures: u32,
+    /// `None` if `spend_limits.withdraws` isn't configured
+    spend_limiter: Option<SpendLimiter>,
+    /// last probed state horizon of the foreign node. `None` if unknown
+    foreign_state_horizon: Option<StateHorizon>,
+    /// signed withdraws this authority relays, observed but not yet relayed
+    pending: PendingAges<T>,
+    /// steps of the batch of withdraws in flight
+    steps: StepTimer,
+    /// messages relayed or in flight, to drop repeated `CollectedSignatures`
+    messages: RelayedMessages,
+    /// attempts to relay withdraws to `HomeBridge`
+    attempts: AttemptLog,
+    /// `Withdraw` events of the messages to relay. `None` without `check_withdraw_origins`
+    origins: Option<WithdrawOrigins>,
+    /// `true` once drained. the relay ends instead of taking new logs
+    draining: bool,
+    /// span of the blocks of the batch in flight
+    blocks: Span,
 }
 
-impl<T: Transport> Stream for WithdrawRelay<T> {
-    type Item = u64;
-    type Error = Error;
+impl<T: Transport> WithdrawRelay<T> {
+    /// limit of the number of relayed withdraws. `None` if not configured.
+    pub fn spend_limiter(&self) -> Option<&SpendLimiter> {
+        self.spend_limiter.as_ref()
+    }
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+    pub fn spend_limiter_mut(&mut self) -> Option<&mut SpendLimiter> {
+        self.spend_limiter.as_mut()
+    }
+
+    /// replaces the spend limiter, e.g. with the one of the relay this relay is rebuilt from.
+    pub fn set_spend_limiter(&mut self, spend_limiter: Option<SpendLimiter>) {
+        self.spend_limiter = spend_limiter;
+    }
+
+    /// messages of the withdraws relayed and of the batch in flight.
+    pub fn relayed_messages(&self) -> &RelayedMessages {
+        &self.messages
+    }
+
+    /// replaces the relayed messages, e.g. with those of the relay this relay is rebuilt from.
+    pub fn set_relayed_messages(&mut self, messages: RelayedMessages) {
+        self.messages = messages;
+    }
+
+    /// attempts to relay withdraws to `HomeBridge`.
+    pub fn attempt_log(&self) -> &AttemptLog {
+        &self.attempts
+    }
+
+    pub fn attempt_log_mut(&mut self) -> &mut AttemptLog {
+        &mut self.attempts
+    }
+
+    /// replaces the attempt log, e.g. with the one of the relay this relay is rebuilt from.
+    pub fn set_attempt_log(&mut self, attempts: AttemptLog) {
+        self.attempts = attempts;
+    }
+
+    /// with `consistent_reads` messages and signatures of blocks outside `horizon`
+    /// are read at the latest block instead.
+    pub fn set_foreign_state_horizon(&mut self, horizon: Option<StateHorizon>) {
+        self.foreign_state_horizon = horizon;
+    }
+
+    /// the signed withdraw observed the longest ago that isn't relayed yet.
+    /// `None` if all withdraws up to the checked block are relayed.
+    pub fn oldest_pending(&mut self) -> Option<PendingRelay> {
+        self.pending.oldest()
+    }
+
+    /// signed withdraws observed but not relayed yet, the oldest first.
+    pub fn pending_relays(&self) -> Vec<PendingRelay> {
+        self.pending.all()
+    }
+
+    /// `Withdraw` events of the messages relayed last. `None` without `check_withdraw_origins`.
+    pub fn withdraw_origins(&self) -> Option<&WithdrawOrigins> {
+        self.origins.as_ref()
+    }
+
+    /// replaces the recorded `Withdraw` events, e.g. with those of the relay this relay replaces.
+    /// ignored without `check_withdraw_origins`.
+    pub fn set_withdraw_origins(&mut self, origins: WithdrawOrigins) {
+        if self.origins.is_some() {
+            self.origins = Some(origins);
+        }
+    }
+
+    /// records a `Withdraw` of foreign transaction `transaction` observed by withdraw confirm.
+    /// ignored without `check_withdraw_origins`.
+    pub fn observe_withdraw(&mut self, transaction: H256, block: u64, log_index: Option<U256>) {
+        if let Some(ref mut origins) = self.origins {
+            origins.observe(transaction, block, log_index);
+        }
+    }
+
+    /// stops taking new logs. the batch in flight is finished, then the relay ends.
+    pub fn drain(&mut self) {
+        self.draining = true;
+    }
+
+    /// returns the relay outcomes since the last call.
+    pub fn take_counts(&mut self) -> RelayCounts {
+        let mut counts = mem::replace(&mut self.counts, RelayCounts::default());
+        counts.pending_logs += self.logs.take_pending_logs();
+        counts
+    }
+
+    /// returns the logs the log stream dropped for their size since the last call.
+    pub fn take_oversized_logs(&mut self) -> Vec<OversizedLog> {
+        self.logs.take_oversized_logs()
+    }
+
+    /// returns the gas used by the relays checked since the last call.
+    /// empty unless `gas_usage` is configured.
+    pub fn take_gas_samples(&mut self) -> Vec<GasSample> {
+        mem::replace(&mut self.gas_samples, Vec::new())
+    }
+
+    /// returns the number of withdraws assigned to every authority since the last call.
+    pub fn take_assignments(&mut self) -> BTreeMap<Address, u64> {
+        mem::replace(&mut self.assignments, BTreeMap::new())
+    }
+
+    /// returns the withdraws relayed since the last call.
+    /// only tracked if the relay history or webhooks are enabled.
+    pub fn take_history(&mut self) -> Vec<RelayRecord> {
+        match self.history {
+            Some(ref mut history) => history.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// describes the current state. used for diagnostics.
+    pub fn state_description(&self) -> String {
+        match self.state {
+            WithdrawRelayState::Wait => "waiting for logs".into(),
+            WithdrawRelayState::FetchMessagesSignatures { block, .. } => {
+                format!("fetching messages and signatures up to block {}", block)
+            }
+            WithdrawRelayState::FetchRequiredSignatures { block, .. } => {
+                format!("fetching required signatures for withdraws up to block {}", block)
+            }
+            WithdrawRelayState::FetchMissingSignatures { block, .. } => {
+                format!("fetching missing signatures for withdraws up to block {}", block)
+            }
+            WithdrawRelayState::FetchOrigins { block, .. } => {
+                format!("fetching unobserved withdraws up to block {}", block)
+            }
+            WithdrawRelayState::CheckExecuted { block, .. } => {
+                format!("checking for executed withdraws up to block {}", block)
+            }
+            WithdrawRelayState::FetchPayouts { block, .. } => {
+                format!("looking up payout addresses of withdraws up to block {}", block)
+            }
+            WithdrawRelayState::PrecheckWithdraws { block, .. } => {
+                format!("prechecking withdraws up to block {}", block)
+            }
+            WithdrawRelayState::DeferWithdraws { block, .. } => {
+                format!("deferring withdraws up to block {}", block)
+            }
+            WithdrawRelayState::LimitWithdraws { block, .. } => {
+                format!("checking the spend limit of withdraws up to block {}", block)
+            }
+            WithdrawRelayState::RelayWithdraws { block, .. } => {
+                format!("relaying withdraws up to block {}", block)
+            }
+            WithdrawRelayState::VerifyEvents { block, .. } => {
+                format!("checking events of withdraw relays up to block {}", block)
+            }
+            WithdrawRelayState::Yield(_) => "yielding checked block".into(),
+        }
+    }
+}
+
+impl<T: Transport + Clone> WithdrawRelay<T> {
+    fn poll_withdraws(&mut self) -> Poll<Option<u64>, Error> {
         loop {
+            // the calls of the batch in flight are nested in the span of its blocks
+            let blocks = self.blocks.clone();
+            let _entered = blocks.enter();
             let next_state = match self.state {
+                WithdrawRelayState::Wait if self.draining => return Ok(None.into()),
                 WithdrawRelayState::Wait => {
                     let item = try_stream!(self.logs.poll());
-                    info!("got {} new signed withdraws to relay", item.logs.len());
+                    info!(
+                        target: "bridge::withdraw_relay",
+                        "got {} new signed withdraws to relay in blocks {}..={}",
+                        item.logs.len(),
+                        item.from,
+                        item.to
+                    );
+                    self.blocks = info_span!(
+                        target: "bridge::withdraw_relay",
+                        parent: None,
+                        "withdraws",
+                        from = item.from,
+                        to = item.to
+                    );
+                    let batch = self.blocks.clone();
+                    let _batch = batch.enter();
+                    self.pending.observe(&item.logs);
+                    for log in &item.logs {
+                        let authority = responsible_authority(&self.app.foreign_bridge, log)?;
+                        *self.assignments.entry(authority).or_insert(0) += 1;
+                    }
+                    let app = &self.app;
+                    let pending_ages = &mut self.pending;
+                    let counts = &mut self.counts;
+                    let relayed_messages = &mut self.messages;
                     let assignments = item.logs
                         .into_iter()
-                        .map(|log| {
-                            info!(
-                                "collected signature is ready for relay: tx hash: {}",
-                                log.transaction_hash.unwrap()
-                            );
-                            signatures_payload(
-                                &self.app.foreign_bridge,
-                                self.app.config.authorities.required_signatures,
-                                self.app.config.foreign.account,
+                        .map(|log| -> error::Result<_> {
+                            let (hash, log_index) = (log.transaction_hash, log.log_index);
+                            // repeated logs are resolved before their signatures are fetched
+                            let message_hash = collected_message_hash(&app.foreign_bridge, &log)?;
+                            let status = match relayed_messages.status(&message_hash) {
+                                MessageStatus::New => None,
+                                MessageStatus::InFlight => Some("in flight"),
+                                MessageStatus::Relayed => Some("relayed already"),
+                            };
+                            if let Some(status) = status {
+                                info!(
+                                    target: "bridge::withdraw_relay",
+                                    "CollectedSignatures of message {} repeated in foreign transaction {:?}. the withdraw is {}. skipping",
+                                    message_hash,
+                                    hash,
+                                    status
+                                );
+                                counts.duplicates += 1;
+                                if let Some(hash) = hash {
+                                    pending_ages.finish(&hash, log_index);
+                                }
+                                return Ok(None);
+                            }
+                            let assignment = signatures_payload(
+                                &app.foreign_bridge,
+                                app.config.authorities.required_signatures,
+                                app.config.foreign.account,
                                 log,
-                            )
+                            )?;
+                            if assignment.is_some() {
+                                relayed_messages.start(message_hash);
+                            } else {
+                                // another authority relays the withdraw
+                                relayed_messages.assign_elsewhere(message_hash);
+                                if let Some(hash) = hash {
+                                    pending_ages.finish(&hash, log_index);
+                                }
+                            }
+                            Ok(assignment)
                         })
                         .collect::<error::Result<Vec<_>>>()?;
 
@@ -161,52 +1022,71 @@ impl<T: Transport> Stream for WithdrawRelay<T> {
                         })
                         .unzip();
 
-                    let message_calls = messages
-                        .into_iter()
-                        .map(|payload| {
-                            self.app.timer.timeout(
-                                api::call(
-                                    &self.app.connections.foreign,
-                                    self.foreign_contract.clone(),
-                                    payload,
-                                ),
-                                self.app.config.foreign.request_timeout,
-                            )
-                        })
-                        .collect::<Vec<_>>();
-
-                    let signature_calls = signatures
-                        .into_iter()
-                        .map(|payloads| {
-                            payloads
-                                .into_iter()
-                                .map(|payload| {
-                                    self.app.timer.timeout(
-                                        api::call(
-                                            &self.app.connections.foreign,
-                                            self.foreign_contract.clone(),
-                                            payload,
-                                        ),
-                                        self.app.config.foreign.request_timeout,
-                                    )
-                                })
-                                .collect::<Vec<_>>()
-                        })
-                        .map(|calls| join_all(calls))
-                        .collect::<Vec<_>>();
+                    // all signatures of the withdraws are collected at the last block of the batch
+                    let at = match self.foreign_state_horizon {
+                        _ if !self.app.config.consistent_reads => None,
+                        Some(ref horizon) if !horizon.has_state(item.to) => {
+                            warn!(
+                                target: "bridge::withdraw_relay",
+                                "foreign node only has the state of the last {} blocks. fetching messages and signatures of block {} at the latest block instead",
+                                horizon.depth,
+                                item.to
+                            );
+                            None
+                        }
+                        _ => Some(item.to),
+                    };
+                    let future = fetch_messages_signatures(
+                        &self.app,
+                        self.foreign_contract,
+                        &messages,
+                        &signatures,
+                        at,
+                    );
 
-                    info!("fetching messages and signatures");
+                    info!(target: "bridge::withdraw_relay", "fetching messages and signatures");
                     WithdrawRelayState::FetchMessagesSignatures {
-                        future: join_all(message_calls).join(join_all(signature_calls)),
+                        future,
+                        retry: at.map(|_| (messages, signatures)),
                         block: item.to,
                     }
                 }
                 WithdrawRelayState::FetchMessagesSignatures {
                     ref mut future,
+                    ref mut retry,
                     block,
                 } => {
-                    let (messages_raw, signatures_raw) = try_ready!(future.poll());
-                    info!("fetching messages and signatures complete");
+                    let fetched = match future.poll() {
+                        Err(ref err) if retry.is_some() && api::is_missing_state(err) => {
+                            warn!(
+                                target: "bridge::withdraw_relay",
+                                "foreign node lacks the state of block {}. fetching messages and signatures at the latest block instead: {}",
+                                block,
+                                err
+                            );
+                            None
+                        }
+                        result => Some(try_ready!(result)),
+                    };
+                    let (messages_raw, signatures_raw) = match fetched {
+                        Some(fetched) => fetched,
+                        None => {
+                            let (messages, signatures) =
+                                retry.take().expect("retry is Some, checked above; qed");
+                            *future = fetch_messages_signatures(
+                                &self.app,
+                                self.foreign_contract,
+                                &messages,
+                                &signatures,
+                                None,
+                            );
+                            continue;
+                        }
+                    };
+                    info!(
+                        target: "bridge::withdraw_relay",
+                        "fetching messages and signatures complete"
+                    );
                     assert_eq!(messages_raw.len(), signatures_raw.len());
 
                     let app = &self.app;
@@ -223,7 +1103,7 @@ impl<T: Transport> Stream for WithdrawRelay<T> {
                         })
                         .collect::<ethabi::Result<Vec<_>>>()
                         .map_err(error::Error::from)?;
-                    info!("messages decoded");
+                    info!(target: "bridge::withdraw_relay", "messages decoded");
 
                     let signatures = signatures_raw
                         .iter()
@@ -243,93 +1123,945 @@ impl<T: Transport> Stream for WithdrawRelay<T> {
                                 .map_err(error::Error::from)
                         })
                         .collect::<error::Result<Vec<_>>>()?;
-                    info!("signatures decoded");
+                    info!(target: "bridge::withdraw_relay", "signatures decoded");
 
-                    let relays = messages
-                        .into_iter()
-                        .zip(signatures.into_iter())
+                    let (messages, signatures) = parse_messages(messages, signatures);
+                    let withdraw_hashes = messages
+                        .iter()
+                        .map(|message| message.sidenet_transaction_hash)
+                        .collect::<Vec<_>>();
+                    let values = messages
+                        .iter()
+                        .map(|message| message.value)
+                        .collect::<Vec<_>>();
+                    let total_value = values
+                        .iter()
+                        .fold(U256::zero(), |sum, value| sum + *value);
+
+                    let counts = &mut self.counts;
+                    let requests = messages
+                        .iter()
+                        .zip(signatures.iter())
                         .map(|(message, signatures)| {
-                            let payload: Bytes = app.home_bridge
-                                .functions()
-                                .withdraw()
-                                .input(
-                                    signatures.iter().map(|x| x.v),
-                                    signatures.iter().map(|x| x.r),
-                                    signatures.iter().map(|x| x.s),
-                                    message.clone().0,
-                                )
-                                .into();
-                            let request = TransactionRequest {
-                                from: app.config.home.account,
-                                to: Some(home_contract.clone()),
-                                gas: Some(app.config.txs.withdraw_relay.gas.into()),
-                                gas_price: Some(
-                                    MessageToMainnet::from_bytes(message.0.as_slice())
-                                        .mainnet_gas_price,
-                                ),
-                                value: None,
-                                data: Some(payload),
-                                nonce: None,
-                                condition: None,
-                            };
-                            app.timer.timeout(
-                                api::send_transaction(&app.connections.home, request),
-                                app.config.home.request_timeout,
-                            )
+                            withdraw_request(app, counts, *home_contract, message, signatures, None)
                         })
+                        .collect::<error::Result<Vec<_>>>()?;
+                    let payouts = vec![None; messages.len()];
+
+                    let pending = PendingWithdraws {
+                        requests,
+                        withdraw_hashes,
+                        values,
+                        total_value,
+                        messages,
+                        signatures,
+                        payouts,
+                    };
+
+                    // withdraws whose `Withdraw` isn't recorded are fetched before they're relayed
+                    let missing = match self.origins {
+                        Some(ref mut origins) => pending
+                            .withdraw_hashes
+                            .iter()
+                            .zip(pending.messages.iter())
+                            .enumerate()
+                            .filter(|&(_, (hash, message))| !origins.link(*hash, message.hash()))
+                            .map(|(index, _)| index)
+                            .collect::<Vec<_>>(),
+                        None => Vec::new(),
+                    };
+                    if missing.is_empty() {
+                        check_executed_or_signatures(
+                            app,
+                            &self.messages,
+                            *home_contract,
+                            pending,
+                            block,
+                        )
+                    } else {
+                        info!(
+                            target: "bridge::withdraw_relay",
+                            "fetching {} withdraws not observed by withdraw confirm yet",
+                            missing.len()
+                        );
+                        WithdrawRelayState::FetchOrigins {
+                            future: fetch_origin_receipts(app, &pending, &missing),
+                            pending,
+                            fetched: missing,
+                            block,
+                        }
+                    }
+                }
+                WithdrawRelayState::FetchOrigins {
+                    ref mut future,
+                    ref mut pending,
+                    ref fetched,
+                    block,
+                } => {
+                    let receipts = try_ready!(future.poll());
+                    let app = &self.app;
+                    let origins = self.origins
+                        .as_mut()
+                        .expect("withdraws are only fetched with check_withdraw_origins; qed");
+                    let mut orphaned = vec![false; pending.requests.len()];
+                    for (index, receipt) in fetched.iter().zip(receipts.into_iter()) {
+                        let hash = pending.withdraw_hashes[*index];
+                        let message = &pending.messages[*index];
+                        let receipt = match receipt {
+                            Some(receipt) => receipt,
+                            None => bail!(
+                                "foreign node has no receipt of transaction {:?} of the withdraw of message {:?}",
+                                hash,
+                                message.hash()
+                            ),
+                        };
+                        match withdraw_origin(app, self.foreign_contract, message, &receipt) {
+                            Some(origin) => {
+                                info!(
+                                    target: "bridge::withdraw_relay",
+                                    "fetched the withdraw of message {:?} in foreign transaction {:?} at block {}",
+                                    message.hash(),
+                                    hash,
+                                    origin.block
+                                );
+                                origins.record_fetched(origin);
+                            }
+                            None => {
+                                error!(
+                                    target: "bridge::withdraw_relay",
+                                    "foreign transaction {:?} emitted no Withdraw matching message {:?} whose signatures were collected. not relaying it",
+                                    hash,
+                                    message.hash()
+                                );
+                                self.counts.orphaned_withdraws += 1;
+                                orphaned[*index] = true;
+                            }
+                        }
+                    }
+                    let mut pending = mem::replace(pending, PendingWithdraws::default());
+                    pending.remove(&orphaned);
+                    check_executed_or_signatures(
+                        app,
+                        &self.messages,
+                        self.home_contract,
+                        pending,
+                        block,
+                    )
+                }
+                WithdrawRelayState::CheckExecuted {
+                    ref mut future,
+                    ref mut pending,
+                    ref checked,
+                    block,
+                } => {
+                    let outputs = try_ready!(future.poll());
+                    let app = &self.app;
+                    let mut executed = vec![false; pending.requests.len()];
+                    for (index, output) in checked.iter().zip(outputs.iter()) {
+                        executed[*index] = app.home_bridge
+                            .functions()
+                            .is_withdraw_executed()
+                            .output(output.0.as_slice())
+                            .map_err(error::Error::from)?;
+                        if !executed[*index] {
+                            continue;
+                        }
+                        let hash = pending.messages[*index].hash();
+                        if self.messages.is_assigned_elsewhere(&hash) {
+                            // a repeated log of a withdraw another authority relayed
+                            self.counts.duplicates += 1;
+                        } else {
+                            self.counts.skipped_executed += 1;
+                        }
+                    }
+                    let mut pending = mem::replace(pending, PendingWithdraws::default());
+                    pending.remove_executed(&executed);
+                    check_signatures_or_precheck(app, self.home_contract, pending, block)
+                }
+                WithdrawRelayState::FetchRequiredSignatures {
+                    ref mut future,
+                    ref mut pending,
+                    block,
+                } => {
+                    let output = try_ready!(future.poll());
+                    let app = &self.app;
+                    let required = app.home_bridge
+                        .functions()
+                        .required_signatures()
+                        .output(output.0.as_slice())
+                        .map_err(error::Error::from)?
+                        .low_u64() as u32;
+                    if required != self.required_signatures {
+                        warn!(
+                            target: "bridge::withdraw_relay",
+                            "HomeBridge.requiredSignatures changed from {} to {}. withdraws lacking signatures are deferred until enough authorities signed them",
+                            self.required_signatures,
+                            required
+                        );
+                        self.counts.required_signatures_changes += 1;
+                        self.required_signatures = required;
+                    }
+
+                    let pending = mem::replace(pending, PendingWithdraws::default());
+                    let lacking = pending
+                        .signatures
+                        .iter()
+                        .filter(|signatures| (signatures.len() as u32) < required)
+                        .count();
+                    if lacking == 0 {
+                        precheck_or_relay(app, self.home_contract, pending, block)
+                    } else {
+                        info!(
+                            target: "bridge::withdraw_relay",
+                            "fetching missing signatures of {} withdraws",
+                            lacking
+                        );
+                        WithdrawRelayState::FetchMissingSignatures {
+                            future: fetch_missing_signatures(
+                                app,
+                                self.foreign_contract,
+                                &pending,
+                                required,
+                            ),
+                            pending,
+                            block,
+                        }
+                    }
+                }
+                WithdrawRelayState::FetchMissingSignatures {
+                    ref mut future,
+                    ref mut pending,
+                    block,
+                } => {
+                    let fetched = try_ready!(future.poll());
+                    let app = &self.app;
+                    let mut pending = mem::replace(pending, PendingWithdraws::default());
+
+                    let mut lacking = Vec::new();
+                    for (index, outputs) in fetched.into_iter().enumerate() {
+                        if outputs.is_empty() {
+                            continue;
+                        }
+                        if outputs.iter().any(Result::is_err) {
+                            lacking.push(index);
+                            continue;
+                        }
+                        for output in outputs {
+                            let signature = Signature::from_bytes(
+                                app.foreign_bridge
+                                    .functions()
+                                    .signature()
+                                    .output(output?.0.as_slice())?
+                                    .as_slice(),
+                            )?;
+                            pending.signatures[index].push(signature);
+                        }
+                        pending.requests[index] = withdraw_request(
+                            app,
+                            &mut self.counts,
+                            self.home_contract,
+                            &pending.messages[index],
+                            &pending.signatures[index],
+                            pending.payouts[index],
+                        )?;
+                    }
+
+                    if lacking.is_empty() {
+                        precheck_or_relay(app, self.home_contract, pending, block)
+                    } else {
+                        for index in lacking {
+                            warn!(
+                                target: "bridge::withdraw_relay",
+                                "withdraw in foreign transaction {} has {} of {} required signatures. deferring withdraws up to block {}",
+                                pending.withdraw_hashes[index],
+                                pending.signatures[index].len(),
+                                self.required_signatures,
+                                block
+                            );
+                        }
+                        self.counts.deferred += pending.requests.len() as u64;
+                        WithdrawRelayState::DeferWithdraws {
+                            future: app.timer.sleep(app.tunables.get().home_poll_interval),
+                            pending,
+                            block,
+                        }
+                    }
+                }
+                WithdrawRelayState::FetchPayouts {
+                    ref mut future,
+                    ref mut pending,
+                    block,
+                } => {
+                    let outputs = try_ready!(future.poll());
+                    let app = &self.app;
+                    let functions = payout_registry::PayoutRegistry::default().functions();
+                    let mut pending = mem::replace(pending, PendingWithdraws::default());
+                    for (index, output) in outputs.iter().enumerate() {
+                        let recipient = pending.messages[index].recipient;
+                        let registered = functions
+                            .payout_address_of()
+                            .output(output.0.as_slice())
+                            .map_err(error::Error::from)?;
+                        let payout = payout_of(recipient, registered);
+                        if payout == pending.payouts[index] {
+                            continue;
+                        }
+                        match payout {
+                            Some(payout) => info!(
+                                target: "bridge::withdraw_relay",
+                                "withdraw in foreign transaction {} to {:?} is paid to the payout address {:?} it registered",
+                                pending.withdraw_hashes[index],
+                                recipient,
+                                payout
+                            ),
+                            None => info!(
+                                target: "bridge::withdraw_relay",
+                                "recipient {:?} of the withdraw in foreign transaction {} removed its payout address. paying it to the recipient",
+                                recipient,
+                                pending.withdraw_hashes[index]
+                            ),
+                        }
+                        pending.payouts[index] = payout;
+                        pending.requests[index] = withdraw_request(
+                            app,
+                            &mut self.counts,
+                            self.home_contract,
+                            &pending.messages[index],
+                            &pending.signatures[index],
+                            payout,
+                        )?;
+                    }
+                    precheck_or_limit(app, self.home_contract, pending, block)
+                }
+                WithdrawRelayState::PrecheckWithdraws {
+                    ref mut future,
+                    ref mut pending,
+                    block,
+                } => {
+                    let (simulations, balance) = try_ready!(future.poll());
+                    let app = &self.app;
+
+                    let mut pending = mem::replace(pending, PendingWithdraws::default());
+                    let policy = app.config.withdraw_balance_policy;
+                    let funded = if balance < pending.total_value {
+                        funded_withdraws(&pending.values, balance, policy)
+                    } else {
+                        vec![true; pending.values.len()]
+                    };
+                    // withdraws the balance doesn't cover are expected to fail their simulation
+                    let failed = simulations
+                        .iter()
+                        .zip(pending.withdraw_hashes.iter())
+                        .zip(funded.iter())
+                        .filter(|&((result, _), funded)| *funded && simulation_failed(result))
+                        .map(|((_, hash), _)| *hash)
                         .collect::<Vec<_>>();
 
-                    info!("relaying {} withdraws", relays.len());
-                    WithdrawRelayState::RelayWithdraws {
-                        future: join_all(relays),
+                    let unfunded = funded.iter().filter(|funded| !**funded).count();
+                    if unfunded > 0 {
+                        error!(
+                            target: "bridge::withdraw_relay",
+                            "HomeBridge balance {} is lower than the value {} of withdraws up to block {}. deferring {} of {} withdraws until the balance covers them",
+                            format_ether(balance, 0),
+                            format_ether(pending.total_value, 0),
+                            block,
+                            unfunded,
+                            funded.len()
+                        );
+                        self.counts.unfunded_withdraws += unfunded as u64;
+                    }
+                    for hash in &failed {
+                        error!(
+                            target: "bridge::withdraw_relay",
+                            "withdraw in foreign transaction {} is predicted to fail. deferring withdraws up to block {}",
+                            hash,
+                            block
+                        );
+                    }
+
+                    if !failed.is_empty() || (unfunded > 0 && unfunded == funded.len()) {
+                        self.counts.deferred += pending.requests.len() as u64;
+                        WithdrawRelayState::DeferWithdraws {
+                            future: app.timer.sleep(app.tunables.get().home_poll_interval),
+                            pending,
+                            block,
+                        }
+                    } else {
+                        self.counts.deferred += unfunded as u64;
+                        let unfunded_withdraws =
+                            funded.iter().map(|funded| !*funded).collect::<Vec<_>>();
+                        let unfunded = pending.take(&unfunded_withdraws);
+                        WithdrawRelayState::LimitWithdraws {
+                            pending,
+                            unfunded,
+                            block,
+                        }
+                    }
+                }
+                WithdrawRelayState::DeferWithdraws {
+                    ref mut future,
+                    ref mut pending,
+                    block,
+                } => {
+                    try_ready!(future.poll());
+                    let pending = mem::replace(pending, PendingWithdraws::default());
+                    check_signatures_or_precheck(&self.app, self.home_contract, pending, block)
+                }
+                WithdrawRelayState::LimitWithdraws {
+                    ref mut pending,
+                    ref mut unfunded,
+                    block,
+                } => {
+                    let mut pending = mem::replace(pending, PendingWithdraws::default());
+                    let mut deferred = limit_withdraws(
+                        &mut self.counts,
+                        &mut self.spend_limiter,
+                        &mut pending,
                         block,
+                    );
+                    // deferred in order, the withdraws beyond the spend limit precede the
+                    // unfunded ones with a fifo balance policy
+                    deferred.append(mem::replace(unfunded, PendingWithdraws::default()));
+                    let attempts = &mut self.attempts;
+                    let given_up = pending
+                        .withdraw_hashes
+                        .iter()
+                        .map(|hash| {
+                            let started = attempts.start(*hash, WithdrawPhase::Finalize);
+                            if !started {
+                                error!(
+                                    target: "bridge::withdraw_relay",
+                                    "withdraw in foreign transaction {} is given up after too many attempts. not relaying it",
+                                    hash
+                                );
+                            }
+                            !started
+                        })
+                        .collect::<Vec<_>>();
+                    pending.remove(&given_up);
+                    self.counts.payout_withdraws +=
+                        pending.payouts.iter().filter(|payout| payout.is_some()).count() as u64;
+                    if pending.requests.is_empty() && !deferred.requests.is_empty() {
+                        defer_withdraws(&self.app, deferred, block)
+                    } else {
+                        relay_withdraws(&self.app, pending, deferred, block)
                     }
                 }
                 WithdrawRelayState::RelayWithdraws {
                     ref mut future,
+                    ref withdraw_hashes,
+                    ref message_hashes,
+                    ref receivers,
+                    ref mut expected,
+                    ref mut deferred,
                     block,
                 } => {
-                    let _ = try_ready!(future.poll());
-                    info!("relaying withdraws complete");
-                    WithdrawRelayState::Yield(Some(block))
+                    let relay_hashes = try_ready!(future.poll());
+                    self.counts.relayed += relay_hashes.len() as u64;
+                    for hash in message_hashes {
+                        self.messages.relayed(*hash);
+                    }
+                    let timings = self.steps.sent(relay_hashes.len(), self.app.timer.now());
+                    let observed_on = self.app.foreign_node();
+                    let submitted_to = self.app.home_node();
+                    if let Some(ref mut history) = self.history {
+                        let relays = withdraw_hashes.iter().zip(relay_hashes.iter()).zip(receivers);
+                        let records = relays.map(
+                            |((withdraw_hash, relay_hash), &(receiver, value))| RelayRecord {
+                                direction: Direction::ForeignToHome,
+                                key: relay_key(Direction::ForeignToHome, withdraw_hash, None),
+                                source_transaction: *withdraw_hash,
+                                relay_transaction: *relay_hash,
+                                block,
+                                receiver,
+                                value,
+                                memo: None,
+                                recipient: None,
+                                derived_recipient: None,
+                                main_position: None,
+                                gas_price: None,
+                                timings: Some(timings),
+                                observed_on: Some(observed_on.clone()),
+                                submitted_to: Some(submitted_to.clone()),
+                            },
+                        );
+                        history.extend(records);
+                    }
+                    let hashes = withdraw_hashes.iter().zip(relay_hashes.iter());
+                    for (withdraw_hash, relay_hash) in hashes {
+                        self.attempts
+                            .sent(*withdraw_hash, WithdrawPhase::Finalize, *relay_hash);
+                        info!(
+                            target: "bridge::withdraw_relay",
+                            "withdraw in foreign transaction {} relayed in home transaction {}",
+                            withdraw_hash,
+                            relay_hash
+                        );
+                    }
+                    let deferred = mem::replace(deferred, PendingWithdraws::default());
+                    let expected = mem::replace(expected, Vec::new());
+                    match verify_relay_events(&self.app, true, &relay_hashes, expected) {
+                        Some(future) => WithdrawRelayState::VerifyEvents {
+                            future,
+                            deferred,
+                            block,
+                        },
+                        None => relay_completed(&self.app, deferred, block),
+                    }
+                }
+                WithdrawRelayState::VerifyEvents {
+                    ref mut future,
+                    ref mut deferred,
+                    block,
+                } => {
+                    let checks = try_ready!(future.poll());
+                    self.gas_samples.extend(gas_samples(&self.app, &checks));
+                    record_event_checks(&self.app, true, &mut self.counts, &checks)?;
+                    let deferred = mem::replace(deferred, PendingWithdraws::default());
+                    relay_completed(&self.app, deferred, block)
                 }
                 WithdrawRelayState::Yield(ref mut block) => match block.take() {
                     None => {
-                        info!("waiting for signed withdraws to relay");
+                        info!(
+                            target: "bridge::withdraw_relay",
+                            "waiting for signed withdraws to relay"
+                        );
                         WithdrawRelayState::Wait
                     }
-                    some => return Ok(some.into()),
+                    some => {
+                        // every withdraw up to the block is relayed or skipped
+                        self.pending.finish_all();
+                        return Ok(some.into());
+                    }
                 },
             };
+            let now = self.app.timer.now();
+            match next_state {
+                WithdrawRelayState::Yield(Some(_)) => {
+                    self.messages.end_batch();
+                    self.attempts.succeed();
+                    match self.steps.finish(now) {
+                        Some(timings) => {
+                            self.counts.step_durations.record(&timings);
+                            info!(
+                                target: "bridge::withdraw_relay",
+                                "relaying withdraws complete. step durations: {}",
+                                timings
+                            );
+                        }
+                        None => info!(target: "bridge::withdraw_relay", "relaying withdraws complete"),
+                    }
+                }
+                ref state => self.steps.enter(state.step(), now),
+            }
             self.state = next_state;
         }
     }
 }
 
+impl<T: Transport + Clone> Stream for WithdrawRelay<T> {
+    type Item = u64;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let result = self.poll_withdraws();
+        if let Err(ref err) = result {
+            self.attempts.fail(err);
+        }
+        result
+    }
+}
+
+/// checks which `pending` withdraws were executed already, all of them with `skip_executed`
+/// and those assigned to another authority before, which may have relayed them.
+/// checks their signatures otherwise.
+fn check_executed_or_signatures<T: Transport>(
+    app: &App<T>,
+    messages: &RelayedMessages,
+    home_contract: Address,
+    pending: PendingWithdraws,
+    block: u64,
+) -> WithdrawRelayState<T> {
+    let checked = (0..pending.requests.len())
+        .filter(|index| {
+            app.config.skip_executed
+                || messages.is_assigned_elsewhere(&pending.messages[*index].hash())
+        })
+        .collect::<Vec<_>>();
+    if checked.is_empty() {
+        check_signatures_or_precheck(app, home_contract, pending, block)
+    } else {
+        info!(
+            target: "bridge::withdraw_relay",
+            "checking {} withdraws for execution",
+            checked.len()
+        );
+        WithdrawRelayState::CheckExecuted {
+            future: check_executed(app, home_contract, &pending, &checked),
+            pending,
+            checked,
+            block,
+        }
+    }
+}
+
+/// checks that `pending` withdraws have as many signatures as `HomeBridge`
+/// requires if configured. prechecks or sends them otherwise.
+fn check_signatures_or_precheck<T: Transport>(
+    app: &App<T>,
+    home_contract: Address,
+    pending: PendingWithdraws,
+    block: u64,
+) -> WithdrawRelayState<T> {
+    if app.config.check_required_signatures {
+        info!(
+            target: "bridge::withdraw_relay",
+            "checking signatures of {} withdraws against HomeBridge.requiredSignatures",
+            pending.requests.len()
+        );
+        WithdrawRelayState::FetchRequiredSignatures {
+            future: fetch_required_signatures(app, home_contract),
+            pending,
+            block,
+        }
+    } else {
+        precheck_or_relay(app, home_contract, pending, block)
+    }
+}
+
+/// looks up the payout addresses of `pending` withdraws if `payout_registry` is configured.
+/// prechecks or sends them otherwise.
+fn precheck_or_relay<T: Transport>(
+    app: &App<T>,
+    home_contract: Address,
+    pending: PendingWithdraws,
+    block: u64,
+) -> WithdrawRelayState<T> {
+    match app.config.payout_registry {
+        Some(ref registry) => {
+            debug!(
+                target: "bridge::withdraw_relay",
+                "looking up the payout addresses of {} withdraws",
+                pending.requests.len()
+            );
+            WithdrawRelayState::FetchPayouts {
+                future: fetch_payouts(app, registry.address, &pending),
+                pending,
+                block,
+            }
+        }
+        None => precheck_or_limit(app, home_contract, pending, block),
+    }
+}
+
+/// prechecks `pending` withdraws if configured. sends them otherwise.
+fn precheck_or_limit<T: Transport>(
+    app: &App<T>,
+    home_contract: Address,
+    pending: PendingWithdraws,
+    block: u64,
+) -> WithdrawRelayState<T> {
+    if app.config.withdraw_relay_precheck {
+        info!(
+            target: "bridge::withdraw_relay",
+            "prechecking {} withdraws",
+            pending.requests.len()
+        );
+        WithdrawRelayState::PrecheckWithdraws {
+            future: precheck(app, home_contract, &pending),
+            pending,
+            block,
+        }
+    } else {
+        WithdrawRelayState::LimitWithdraws {
+            pending,
+            unfunded: PendingWithdraws::default(),
+            block,
+        }
+    }
+}
+
+/// checks `pending` withdraws deferred by the spend limit or the `HomeBridge` balance again
+/// after `spend_limits.check_interval`, or `home.poll_interval` without spend limits.
+fn defer_withdraws<T: Transport>(
+    app: &App<T>,
+    pending: PendingWithdraws,
+    block: u64,
+) -> WithdrawRelayState<T> {
+    let check_interval = app.config
+        .spend_limits
+        .as_ref()
+        .map_or(app.tunables.get().home_poll_interval, |limits| limits.check_interval);
+    WithdrawRelayState::DeferWithdraws {
+        future: app.timer.sleep(check_interval),
+        pending,
+        block,
+    }
+}
+
+/// yields `block` once all withdraws up to it are relayed,
+/// waits for the spend limit or the `HomeBridge` balance otherwise.
+fn relay_completed<T: Transport>(
+    app: &App<T>,
+    deferred: PendingWithdraws,
+    block: u64,
+) -> WithdrawRelayState<T> {
+    if deferred.requests.is_empty() {
+        WithdrawRelayState::Yield(Some(block))
+    } else {
+        info!(
+            target: "bridge::withdraw_relay",
+            "relayed withdraws within the spend limit and the HomeBridge balance. {} withdraws up to block {} deferred",
+            deferred.requests.len(),
+            block
+        );
+        defer_withdraws(app, deferred, block)
+    }
+}
+
+/// sends all `pending` withdraws to `HomeBridge`. `deferred` are relayed afterwards.
+fn relay_withdraws<T: Transport>(
+    app: &App<T>,
+    pending: PendingWithdraws,
+    deferred: PendingWithdraws,
+    block: u64,
+) -> WithdrawRelayState<T> {
+    let expected = expected_events(app, &pending.requests);
+    let message_hashes = pending.messages.iter().map(MessageToMainnet::hash).collect();
+    let receivers = pending
+        .messages
+        .iter()
+        .zip(&pending.payouts)
+        .map(|(message, payout)| (payout.unwrap_or(message.recipient), message.value))
+        .collect();
+    let relays = pending
+        .requests
+        .into_iter()
+        .zip(&pending.withdraw_hashes)
+        .map(|(request, withdraw_hash)| {
+            api::timeout(
+                &app.timer,
+                api::node_send_transaction(&app.connections.home, &app.config.home, request),
+                "home",
+                app.config.home.request_timeout,
+            ).instrument(relay_span(Direction::ForeignToHome, withdraw_hash))
+        })
+        .collect::<Vec<_>>();
+
+    info!(target: "bridge::withdraw_relay", "relaying {} withdraws", relays.len());
+    WithdrawRelayState::RelayWithdraws {
+        future: join_all(relays),
+        withdraw_hashes: pending.withdraw_hashes,
+        message_hashes,
+        receivers,
+        expected,
+        deferred,
+        block,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use rustc_hex::FromHex;
-    use web3::types::{Bytes, Log};
+    use web3::types::{Address, Bytes, H256, TransactionRequest, U256};
     use contracts::foreign;
-    use super::signatures_payload;
+    use error::{ErrorKind, Result};
+    use message_to_mainnet::MessageToMainnet;
+    use signature::Signature;
+    use test_helpers::CollectedSignaturesLogBuilder;
+    use config::BalancePolicy;
+    use super::{funded_withdraws, parse_messages, payout_of, signatures_payload, simulation_failed,
+                PendingWithdraws};
+
+    fn message(value: u64) -> MessageToMainnet {
+        MessageToMainnet {
+            recipient: 1.into(),
+            value: value.into(),
+            sidenet_transaction_hash: value.into(),
+            mainnet_gas_price: 10.into(),
+        }
+    }
 
     #[test]
-    fn test_signatures_payload() {
-        let foreign = foreign::ForeignBridge::default();
-        let my_address = "aff3454fce5edbc8cca8697c15331677e6ebcccc".into();
+    fn test_simulation_failed() {
+        let success: Result<Bytes> = Ok(Bytes(vec![]));
+        assert!(!simulation_failed(&success));
 
-        let data = "000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0".from_hex().unwrap();
+        // parity
+        let rpc_error: Result<Bytes> = Err("VM execution error.".into());
+        assert!(simulation_failed(&rpc_error));
 
-        let log = Log {
-            data: data.into(),
-            topics: vec![
-                "eb043d149eedb81369bec43d4c3a3a53087debc88d2525f13bfaa3eecda28b5c".into(),
-            ],
-            transaction_hash: Some(
-                "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364".into(),
-            ),
-            ..Default::default()
+        // geth with revert reason `Error("failed")`
+        let revert: Result<Bytes> = Ok("08c379a0000000000000000000000000000000000000000000000000000000000000002000000000000000000000000000000000000000000000000000000000000000066661696c65640000000000000000000000000000000000000000000000000000"
+            .from_hex()
+            .unwrap()
+            .into());
+        assert!(simulation_failed(&revert));
+    }
+
+    #[test]
+    fn test_remove_executed_withdraws() {
+        let request = |nonce: u64| TransactionRequest {
+            from: 1.into(),
+            to: Some(2.into()),
+            gas: None,
+            gas_price: None,
+            value: None,
+            data: None,
+            nonce: Some(nonce.into()),
+            condition: None,
+        };
+        let mut pending = PendingWithdraws {
+            requests: vec![request(0), request(1), request(2)],
+            withdraw_hashes: vec![10.into(), 11.into(), 12.into()],
+            values: vec![100.into(), 200.into(), 300.into()],
+            total_value: 600.into(),
+            messages: vec![message(100), message(200), message(300)],
+            signatures: vec![vec![], vec![], vec![]],
+            payouts: vec![None, None, Some(3.into())],
+        };
+
+        pending.remove_executed(&[false, true, false]);
+        assert_eq!(vec![request(0), request(2)], pending.requests);
+        let hashes: Vec<H256> = vec![10.into(), 12.into()];
+        assert_eq!(hashes, pending.withdraw_hashes);
+        let values: Vec<U256> = vec![100.into(), 300.into()];
+        assert_eq!(values, pending.values);
+        assert_eq!(U256::from(400), pending.total_value);
+        assert_eq!(vec![message(100), message(300)], pending.messages);
+        assert_eq!(2, pending.signatures.len());
+        assert_eq!(vec![None, Some(3.into())], pending.payouts);
+
+        // withdraws deferred by the spend limit
+        let deferred = pending.split_off(1);
+        assert_eq!(vec![request(0)], pending.requests);
+        assert_eq!(U256::from(100), pending.total_value);
+        assert_eq!(vec![request(2)], deferred.requests);
+        let hashes: Vec<H256> = vec![12.into()];
+        assert_eq!(hashes, deferred.withdraw_hashes);
+        assert_eq!(U256::from(300), deferred.total_value);
+        assert_eq!(vec![message(300)], deferred.messages);
+        assert_eq!(1, deferred.signatures.len());
+        assert_eq!(vec![Some(Address::from(3))], deferred.payouts);
+
+        // withdraws the balance doesn't cover are deferred after those beyond the spend limit
+        pending.append(deferred);
+        assert_eq!(vec![request(0), request(2)], pending.requests);
+        assert_eq!(U256::from(400), pending.total_value);
+        let mut unfunded = pending.take(&[true, false]);
+        assert_eq!(vec![request(2)], pending.requests);
+        assert_eq!(U256::from(300), pending.total_value);
+        assert_eq!(vec![request(0)], unfunded.requests);
+        assert_eq!(U256::from(100), unfunded.total_value);
+        assert_eq!(vec![message(100)], unfunded.messages);
+        assert_eq!(vec![None], unfunded.payouts);
+        unfunded.append(pending);
+        assert_eq!(vec![request(0), request(2)], unfunded.requests);
+    }
+
+    /// relays the withdraws of `pending` `balance` covers by `policy` and returns their values.
+    fn release(pending: &mut Vec<u64>, balance: &mut u64, policy: BalancePolicy) -> Vec<u64> {
+        let values = pending.iter().map(|value| U256::from(*value)).collect::<Vec<_>>();
+        let funded = funded_withdraws(&values, (*balance).into(), policy);
+        let mut relayed = Vec::new();
+        let mut deferred = Vec::new();
+        for (value, funded) in pending.iter().zip(funded) {
+            if funded {
+                *balance -= *value;
+                relayed.push(*value);
+            } else {
+                deferred.push(*value);
+            }
+        }
+        *pending = deferred;
+        relayed
+    }
+
+    #[test]
+    fn test_funded_withdraws() {
+        let values = [5.into(), 1.into(), 1.into(), 3.into()];
+        assert_eq!(vec![true; 4], funded_withdraws(&values, 10.into(), BalancePolicy::Fifo));
+        assert_eq!(vec![true; 4], funded_withdraws(&values, 10.into(), BalancePolicy::FirstFit));
+        assert_eq!(
+            vec![true, true, false, false],
+            funded_withdraws(&values, 6.into(), BalancePolicy::Fifo)
+        );
+        assert_eq!(
+            vec![false, true, true, false],
+            funded_withdraws(&values, 2.into(), BalancePolicy::FirstFit)
+        );
+        assert_eq!(vec![false; 4], funded_withdraws(&values, 2.into(), BalancePolicy::Fifo));
+        assert_eq!(vec![false; 4], funded_withdraws(&values, 0.into(), BalancePolicy::FirstFit));
+        assert!(funded_withdraws(&[], 0.into(), BalancePolicy::Fifo).is_empty());
+    }
+
+    #[test]
+    fn test_unfunded_withdraws_are_released_in_order_as_deposits_arrive() {
+        // deposits are in flight, the balance covers none of the large first withdraw
+        let mut pending = vec![5, 1, 1, 3];
+        let mut balance = 2;
+        assert!(release(&mut pending, &mut balance, BalancePolicy::Fifo).is_empty());
+        balance += 4;
+        assert_eq!(vec![5, 1], release(&mut pending, &mut balance, BalancePolicy::Fifo));
+        // a new withdraw queues behind the deferred ones
+        pending.push(2);
+        balance += 3;
+        assert_eq!(vec![1], release(&mut pending, &mut balance, BalancePolicy::Fifo));
+        assert_eq!(vec![3, 2], pending);
+        balance += 1;
+        assert_eq!(vec![3], release(&mut pending, &mut balance, BalancePolicy::Fifo));
+        balance += 2;
+        assert_eq!(vec![2], release(&mut pending, &mut balance, BalancePolicy::Fifo));
+        assert!(pending.is_empty());
+        assert_eq!(0, balance);
+
+        // first fit relays the small withdraws first
+        let mut pending = vec![5, 1, 1, 3];
+        let mut balance = 2;
+        assert_eq!(vec![1, 1], release(&mut pending, &mut balance, BalancePolicy::FirstFit));
+        balance += 4;
+        assert_eq!(vec![3], release(&mut pending, &mut balance, BalancePolicy::FirstFit));
+        pending.push(1);
+        balance += 4;
+        assert_eq!(vec![5], release(&mut pending, &mut balance, BalancePolicy::FirstFit));
+        assert_eq!(vec![1], pending);
+        assert_eq!(0, balance);
+    }
+
+    #[test]
+    fn test_payout_of() {
+        let recipient: Address = 1.into();
+        assert_eq!(None, payout_of(recipient, Address::zero()));
+        assert_eq!(None, payout_of(recipient, recipient));
+        assert_eq!(Some(2.into()), payout_of(recipient, 2.into()));
+    }
+
+    #[test]
+    fn test_parse_messages_skips_invalid() {
+        let signature = || Signature {
+            v: 27,
+            r: 1.into(),
+            s: 2.into(),
         };
+        let mut truncated = message(200).to_bytes();
+        truncated.pop();
+        let messages: Vec<Bytes> = vec![
+            message(100).to_bytes().into(),
+            truncated.into(),
+            message(300).to_bytes().into(),
+        ];
+        let signatures = vec![vec![signature()], vec![], vec![signature()]];
+
+        let (messages, signatures) = parse_messages(messages, signatures);
+        assert_eq!(vec![message(100), message(300)], messages);
+        assert_eq!(vec![vec![signature()], vec![signature()]], signatures);
+    }
+
+    #[test]
+    fn test_signatures_payload() {
+        let foreign = foreign::ForeignBridge::default();
+        let my_address = "aff3454fce5edbc8cca8697c15331677e6ebcccc".into();
+        let log = CollectedSignaturesLogBuilder::new().build();
 
         let assignment = signatures_payload(&foreign, 2, my_address, log)
             .unwrap()
@@ -351,21 +2083,26 @@ mod tests {
     fn test_signatures_payload_not_ours() {
         let foreign = foreign::ForeignBridge::default();
         let my_address = "aff3454fce5edbc8cca8697c15331677e6ebcccd".into();
-
-        let data = "000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0".from_hex().unwrap();
-
-        let log = Log {
-            data: data.into(),
-            topics: vec![
-                "eb043d149eedb81369bec43d4c3a3a53087debc88d2525f13bfaa3eecda28b5c".into(),
-            ],
-            transaction_hash: Some(
-                "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364".into(),
-            ),
-            ..Default::default()
-        };
+        let log = CollectedSignaturesLogBuilder::new().build();
 
         let assignment = signatures_payload(&foreign, 2, my_address, log).unwrap();
         assert_eq!(None, assignment);
     }
+
+    #[test]
+    fn test_signatures_payload_invalid_log() {
+        let foreign = foreign::ForeignBridge::default();
+        let my_address = "aff3454fce5edbc8cca8697c15331677e6ebcccc".into();
+
+        let log = CollectedSignaturesLogBuilder::new()
+            .truncated(32)
+            .unmined()
+            .build();
+
+        let error = signatures_payload(&foreign, 2, my_address, log.clone()).unwrap_err();
+        match *error.kind() {
+            ErrorKind::InvalidLog(ref invalid) => assert_eq!(&log, invalid),
+            ref kind => panic!("expected invalid log error, got {:?}", kind),
+        }
+    }
 }
@@ -0,0 +1,68 @@
+//! Routing of the webhook notifications published to a message broker.
+//!
+//! a broker is an endpoint of the outbox like a webhook, see `amqp::AmqpSink`.
+
+use serde_json::Value;
+use error::Error;
+
+/// routing key of the notifications if `routing_key` isn't configured.
+pub const DEFAULT_ROUTING_KEY: &str = "bridge.{direction}.{status}";
+
+/// fields of the payloads a routing key template may contain as `{field}`.
+const ROUTING_KEY_FIELDS: [&str; 2] = ["direction", "status"];
+
+/// routing key of a notification: `template` with every `{field}` replaced by the field of
+/// `payload`, e.g. `bridge.home_to_foreign.relayed`. fails on other placeholders.
+pub fn routing_key(template: &str, payload: &Value) -> Result<String, Error> {
+    let mut key = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        key.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => bail!("routing key {:?} has an unclosed {{", template),
+        };
+        let field = &rest[start + 1..end];
+        if !ROUTING_KEY_FIELDS.contains(&field) {
+            bail!(
+                "routing key {:?} has the unknown field {{{}}}, expected direction or status",
+                template,
+                field
+            );
+        }
+        match payload[field].as_str() {
+            Some(value) => key.push_str(value),
+            None => bail!("notification has no {} for the routing key", field),
+        }
+        rest = &rest[end + 1..];
+    }
+    key.push_str(rest);
+    Ok(key)
+}
+
+/// fails if `template` has placeholders other than `{direction}` and `{status}`.
+pub fn check_routing_key(template: &str) -> Result<(), Error> {
+    let payload = json!({ "direction": "home_to_foreign", "status": "relayed" });
+    routing_key(template, &payload).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_routing_key, routing_key, DEFAULT_ROUTING_KEY};
+
+    #[test]
+    fn test_routing_key() {
+        let payload = json!({ "direction": "home_to_foreign", "status": "relayed" });
+        assert_eq!(
+            "bridge.home_to_foreign.relayed",
+            routing_key(DEFAULT_ROUTING_KEY, &payload).unwrap()
+        );
+        assert_eq!("relayed", routing_key("{status}", &payload).unwrap());
+        assert_eq!("relays", routing_key("relays", &payload).unwrap());
+        assert!(routing_key("bridge.{relay_key}", &payload).is_err());
+        assert!(routing_key("bridge.{status", &payload).is_err());
+        assert!(routing_key("{status}", &json!({ "direction": "home_to_foreign" })).is_err());
+        assert!(check_routing_key(DEFAULT_ROUTING_KEY).is_ok());
+        assert!(check_routing_key("bridge.{relay_key}").is_err());
+    }
+}
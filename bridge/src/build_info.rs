@@ -0,0 +1,102 @@
+use std::fmt;
+use serde_json;
+use web3::types::H256;
+use runtime_abi::{abi_hash, COMPILED_FOREIGN_ABI, COMPILED_HOME_ABI};
+
+/// What a bridge binary was built from, embedded at compile time by `build.rs`.
+///
+/// printed by `--version --verbose` and logged at startup, which tells apart the builds
+/// a fleet of authorities runs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuildInfo {
+    /// version of the `bridge` crate
+    pub version: String,
+    /// git commit the binary was built from
+    pub commit: String,
+    /// unix time of the build
+    pub build_timestamp: u64,
+    /// `cargo --version` of the build
+    pub cargo_version: String,
+    /// `solc --version` the contracts were compiled with
+    pub solc_version: String,
+    /// keccak256 of the compiled-in `HomeBridge` ABI, see `runtime_abi::abi_hash`
+    pub home_abi_hash: H256,
+    /// keccak256 of the compiled-in `ForeignBridge` ABI, see `runtime_abi::abi_hash`
+    pub foreign_abi_hash: H256,
+}
+
+impl BuildInfo {
+    /// the build of this binary.
+    pub fn current() -> Self {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION").into(),
+            commit: env!("GIT_HASH").trim().into(),
+            build_timestamp: env!("BUILD_TIMESTAMP")
+                .parse()
+                .expect("build.rs writes a unix time; qed"),
+            cargo_version: env!("CARGO_VERSION").into(),
+            solc_version: env!("SOLC_VERSION").into(),
+            home_abi_hash: abi_hash(COMPILED_HOME_ABI),
+            foreign_abi_hash: abi_hash(COMPILED_FOREIGN_ABI),
+        }
+    }
+
+    /// one line for the startup log.
+    pub fn summary(&self) -> String {
+        format!(
+            "version {} commit {} home abi {:?} foreign abi {:?}",
+            self.version, self.commit, self.home_abi_hash, self.foreign_abi_hash
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialization can't fail; qed")
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version: {}", self.version)?;
+        writeln!(f, "commit: {}", self.commit)?;
+        writeln!(f, "build timestamp: {}", self.build_timestamp)?;
+        writeln!(f, "cargo: {}", self.cargo_version)?;
+        writeln!(f, "solc: {}", self.solc_version)?;
+        writeln!(f, "home abi hash: {:?}", self.home_abi_hash)?;
+        write!(f, "foreign abi hash: {:?}", self.foreign_abi_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, Value};
+    use tiny_keccak::keccak256;
+    use web3::types::H256;
+    use runtime_abi::{COMPILED_FOREIGN_ABI, COMPILED_HOME_ABI};
+    use super::BuildInfo;
+
+    #[test]
+    fn test_build_info_hashes_the_compiled_abis() {
+        let info = BuildInfo::current();
+        assert_eq!(H256::from(keccak256(COMPILED_HOME_ABI.as_bytes())), info.home_abi_hash);
+        assert_eq!(
+            H256::from(keccak256(COMPILED_FOREIGN_ABI.as_bytes())),
+            info.foreign_abi_hash
+        );
+        assert!(info.home_abi_hash != info.foreign_abi_hash);
+        assert!(!info.commit.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_build_info_json() {
+        let info = BuildInfo::current();
+        let json: Value = serde_json::from_str(&info.to_json()).unwrap();
+        assert_eq!(Value::String(info.commit.clone()), json["commit"]);
+        assert_eq!(json!(info.build_timestamp), json["build_timestamp"]);
+        assert_eq!(
+            Value::String(format!("{:?}", info.home_abi_hash)),
+            json["home_abi_hash"]
+        );
+        assert!(info.to_string().contains(&info.commit));
+        assert!(info.summary().contains(&format!("{:?}", info.foreign_abi_hash)));
+    }
+}
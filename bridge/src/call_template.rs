@@ -0,0 +1,167 @@
+use ethabi::{self, Token};
+use rustc_hex::FromHex;
+use web3::types::{Address, Bytes, H256, U256};
+use error::{ErrorKind, Result};
+
+/// Arguments a `CallTemplate` is expanded with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CallArgs {
+    /// recipient of the deposit on foreign
+    pub recipient: Address,
+    /// value of the deposit on foreign, after `value_scale`
+    pub value: U256,
+    /// hash of the home transaction that emitted the deposit
+    pub main_tx: H256,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Bytes(Vec<u8>),
+    Recipient,
+    Value,
+    MainTx,
+}
+
+/// ABI encoded call data with placeholders, e.g.
+/// `0x6a627842{recipient}{value}{main_tx}`.
+///
+/// the template is hex with or without `0x` prefix. every `{recipient}`, `{value}` and
+/// `{main_tx}` is replaced by the argument as 32 byte word, the way the ABI encodes
+/// `address`, `uint256` and `bytes32`. the call data must start with a 4 byte selector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallTemplate {
+    parts: Vec<Part>,
+}
+
+impl CallTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let invalid = |reason: String| ErrorKind::InvalidCallTemplate(template.into(), reason);
+        let mut rest = if template.starts_with("0x") || template.starts_with("0X") {
+            &template[2..]
+        } else {
+            template
+        };
+        let mut parts = Vec::new();
+        while !rest.is_empty() {
+            let hex_len = rest.find('{').unwrap_or_else(|| rest.len());
+            if hex_len > 0 {
+                let hex = &rest[..hex_len];
+                if hex.len() % 2 != 0 {
+                    bail!(invalid(format!("odd number of hex digits in {:?}", hex)));
+                }
+                let bytes: Vec<u8> = hex.from_hex()
+                    .map_err(|_| invalid(format!("{:?} is not a hex string", hex)))?;
+                parts.push(Part::Bytes(bytes));
+                rest = &rest[hex_len..];
+                continue;
+            }
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => bail!(invalid("unclosed placeholder".into())),
+            };
+            let part = match &rest[1..end] {
+                "recipient" => Part::Recipient,
+                "value" => Part::Value,
+                "main_tx" => Part::MainTx,
+                other => bail!(invalid(format!(
+                    "unknown placeholder {{{}}}. expected {{recipient}}, {{value}} or {{main_tx}}",
+                    other
+                ))),
+            };
+            parts.push(part);
+            rest = &rest[end + 1..];
+        }
+        match parts.first() {
+            Some(&Part::Bytes(ref selector)) if selector.len() >= 4 => {}
+            _ => bail!(invalid("must start with a 4 byte function selector".into())),
+        }
+        Ok(CallTemplate { parts })
+    }
+
+    /// call data of the template with the placeholders replaced by `args`.
+    pub fn expand(&self, args: &CallArgs) -> Bytes {
+        let mut data = Vec::new();
+        for part in &self.parts {
+            let word = match *part {
+                Part::Bytes(ref bytes) => {
+                    data.extend_from_slice(bytes);
+                    continue;
+                }
+                Part::Recipient => Token::Address(args.recipient),
+                Part::Value => Token::Uint(args.value),
+                Part::MainTx => Token::FixedBytes(args.main_tx.to_vec()),
+            };
+            data.extend(ethabi::encode(&[word]));
+        }
+        data.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hex::FromHex;
+    use web3::types::Bytes;
+    use super::{CallArgs, CallTemplate};
+
+    fn args() -> CallArgs {
+        CallArgs {
+            recipient: "00000000000000000000000000000000000000aa".parse().unwrap(),
+            value: 0x1234.into(),
+            main_tx: "00000000000000000000000000000000000000000000000000000000000000ff"
+                .parse()
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_call_template_expand() {
+        let template = CallTemplate::parse("0x6a627842{recipient}{value}{main_tx}").unwrap();
+        let expected: Vec<u8> = concat!(
+            "6a627842",
+            "00000000000000000000000000000000000000000000000000000000000000aa",
+            "0000000000000000000000000000000000000000000000000000000000001234",
+            "00000000000000000000000000000000000000000000000000000000000000ff"
+        ).from_hex()
+            .unwrap();
+        assert_eq!(Bytes(expected), template.expand(&args()));
+
+        // placeholders in any order and repeated, fixed words in between
+        let template = CallTemplate::parse(concat!(
+            "6a627842{main_tx}",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "{value}{value}"
+        )).unwrap();
+        let data = template.expand(&args()).0;
+        assert_eq!(4 + 4 * 32, data.len());
+        assert_eq!(0xff, data[35]);
+        assert_eq!(1, data[67]);
+        assert_eq!(&data[68..100], &data[100..132]);
+
+        // a call without arguments
+        let template = CallTemplate::parse("6a627842").unwrap();
+        assert_eq!(Bytes(vec![0x6a, 0x62, 0x78, 0x42]), template.expand(&args()));
+    }
+
+    #[test]
+    fn test_call_template_parse_errors() {
+        let rejected = [
+            ("", "must start with a 4 byte function selector"),
+            ("0x6a62{recipient}", "must start with a 4 byte function selector"),
+            ("{recipient}6a627842", "must start with a 4 byte function selector"),
+            ("0x6a62784{value}", "odd number of hex digits in \"6a62784\""),
+            ("0x6a627842{value", "unclosed placeholder"),
+            ("0x6a62784g", "\"6a62784g\" is not a hex string"),
+            (
+                "0x6a627842{sender}",
+                "unknown placeholder {sender}. expected {recipient}, {value} or {main_tx}",
+            ),
+        ];
+        for &(template, reason) in &rejected {
+            let err = CallTemplate::parse(template).unwrap_err();
+            assert_eq!(
+                format!("invalid call template {:?}: {}", template, reason),
+                err.to_string()
+            );
+        }
+    }
+}
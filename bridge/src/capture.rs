@@ -0,0 +1,518 @@
+//! Writing, rotation and lookup of rpc capture files.
+//!
+//! entries are written on a dedicated thread, so a slow disk never delays a request.
+//! rotated files are compressed with gzip and listed with the time range of their entries
+//! in an index next to the capture file, `<capture file>.index`.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde_json;
+use bridge::Day;
+use config::CaptureConfig;
+use error::{Result, ResultExt};
+use transport::CaptureEntry;
+
+/// suffix of the index of a capture file
+pub const INDEX_SUFFIX: &str = ".index";
+
+/// entries waiting to be written if the capture file isn't rotated
+pub const DEFAULT_QUEUE_SIZE: usize = 10000;
+
+/// how often an idle writer checks whether the capture file is due for rotation by age
+const IDLE_CHECK_INTERVAL: u64 = 1;
+
+/// current unix time in seconds.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+/// parses a unix time in seconds, `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM[:SS]`, in utc.
+pub fn parse_time(time: &str) -> Result<u64> {
+    let time = time.trim().trim_right_matches('Z');
+    if !time.is_empty() && time.chars().all(|c| c.is_digit(10)) {
+        return time.parse::<u64>()
+            .chain_err(|| format!("Invalid unix time {}", time));
+    }
+    let mut parts = time.splitn(2, |c| c == 'T' || c == ' ');
+    let day = Day::parse(parts.next().unwrap_or(""))?;
+    let secs = match parts.next() {
+        None => 0,
+        Some(clock) => {
+            let clock = clock
+                .split(':')
+                .map(|part| part.parse::<u64>())
+                .collect::<::std::result::Result<Vec<_>, _>>()
+                .chain_err(|| format!("Invalid time {}, expected HH:MM[:SS]", clock))?;
+            let valid = (clock.len() == 2 || clock.len() == 3) && clock[0] < 24
+                && clock[1] < 60 && clock.get(2).map_or(true, |secs| *secs < 60);
+            if !valid {
+                bail!("Invalid time {}, expected HH:MM[:SS]", time);
+            }
+            clock[0] * 3600 + clock[1] * 60 + clock.get(2).cloned().unwrap_or(0)
+        }
+    };
+    Ok(day.0 * 86_400 + secs)
+}
+
+/// A rotated capture file listed in an index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// name of the compressed file, in the directory of the index
+    pub file: String,
+    /// unix time in seconds of the first entry
+    pub from: u64,
+    /// unix time in seconds of the last entry
+    pub to: u64,
+}
+
+impl IndexEntry {
+    /// `true` if some entries of the file may be between `from` and `to`.
+    pub fn overlaps(&self, from: u64, to: u64) -> bool {
+        self.from <= to && self.to >= from
+    }
+}
+
+/// path of the index of `capture_file`.
+pub fn index_path(capture_file: &Path) -> PathBuf {
+    let mut name = capture_file
+        .file_name()
+        .map_or_else(Default::default, |name| name.to_os_string());
+    name.push(INDEX_SUFFIX);
+    capture_file.with_file_name(name)
+}
+
+/// the entries of the index at `path`, oldest first. empty if there is no index yet.
+/// one json object per line.
+pub fn read_index(path: &Path) -> Result<Vec<IndexEntry>> {
+    let mut content = String::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_string(&mut content)?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(err).chain_err(|| format!("Cannot open capture index {:?}", path))
+        }
+    };
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .chain_err(|| format!("Invalid entry in line {} of capture index {:?}", i + 1, path))
+        })
+        .collect()
+}
+
+/// replaces the index at `path` with `entries`.
+fn write_index(path: &Path, entries: &[IndexEntry]) -> Result<()> {
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&serde_json::to_string(entry).chain_err(|| "Cannot serialize capture index")?);
+        content.push('\n');
+    }
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    File::create(&tmp)?.write_all(content.as_bytes())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// the rotated files listed in the indexes in `dir` that may hold entries
+/// between `from` and `to`, oldest first.
+pub fn select_capture_files(dir: &Path, from: u64, to: u64) -> Result<Vec<PathBuf>> {
+    let mut selected = Vec::new();
+    for dir_entry in fs::read_dir(dir).chain_err(|| format!("Cannot read capture directory {:?}", dir))? {
+        let path = dir_entry?.path();
+        let is_index = path.file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.ends_with(INDEX_SUFFIX));
+        if !is_index {
+            continue;
+        }
+        for entry in read_index(&path)? {
+            if entry.overlaps(from, to) {
+                selected.push((entry.from, dir.join(&entry.file)));
+            }
+        }
+    }
+    selected.sort();
+    Ok(selected.into_iter().map(|(_, path)| path).collect())
+}
+
+/// the lines of the capture file at `path`, decompressed if it's a `.gz`.
+fn read_capture_file(path: &Path) -> Result<String> {
+    let file = File::open(path).chain_err(|| format!("Cannot open capture file {:?}", path))?;
+    let mut content = String::new();
+    let read = if path.extension().map_or(false, |extension| extension == "gz") {
+        GzDecoder::new(file).read_to_string(&mut content)
+    } else {
+        BufReader::new(file).read_to_string(&mut content)
+    };
+    read.chain_err(|| format!("Cannot read capture file {:?}", path))?;
+    Ok(content)
+}
+
+/// the entries between `from` and `to` of the rotated capture files in `dir`,
+/// one per line in captured order, as read by `simulation::read_capture`.
+/// entries without time are kept if their file overlaps the period.
+pub fn read_captures(dir: &Path, from: u64, to: u64) -> Result<String> {
+    let files = select_capture_files(dir, from, to)?;
+    if files.is_empty() {
+        bail!(
+            "No rotated capture file in {:?} holds entries between {} and {}",
+            dir,
+            from,
+            to
+        );
+    }
+    let mut capture = String::new();
+    for path in files {
+        info!(target: "bridge::rpc", "Reading capture file {:?}", path);
+        for (i, line) in read_capture_file(&path)?.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: CaptureEntry = serde_json::from_str(line)
+                .chain_err(|| format!("Invalid capture entry in line {} of {:?}", i + 1, path))?;
+            if entry.time.map_or(true, |time| time >= from && time <= to) {
+                capture.push_str(line);
+                capture.push('\n');
+            }
+        }
+    }
+    Ok(capture)
+}
+
+/// time of the first entry of the capture file at `path`. `None` if it's empty or has no time.
+fn first_time(path: &Path) -> Option<u64> {
+    let file = File::open(path).ok()?;
+    let line = BufReader::new(file).lines().next()?.ok()?;
+    serde_json::from_str::<CaptureEntry>(&line).ok()?.time
+}
+
+/// `path` with `suffix` appended, numbered if that file already exists.
+fn unused_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut n = 0;
+    loop {
+        let mut name = path.as_os_str().to_os_string();
+        if n == 0 {
+            name.push(format!(".{}", suffix));
+        } else {
+            name.push(format!(".{}.{}", n, suffix));
+        }
+        let candidate = PathBuf::from(name);
+        if !candidate.exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// compresses the file at `path` to `<path>.gz` and removes it.
+fn compress(path: &Path) -> Result<PathBuf> {
+    let compressed = unused_path(path, "gz");
+    {
+        let mut input = File::open(path)?;
+        let mut encoder = GzEncoder::new(File::create(&compressed)?, Compression::default());
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?;
+    }
+    fs::remove_file(path)?;
+    Ok(compressed)
+}
+
+/// The capture file currently written.
+struct CaptureFile {
+    path: PathBuf,
+    file: Option<File>,
+    rotation: Option<CaptureConfig>,
+    size: u64,
+    /// unix time the file was opened
+    opened: u64,
+    /// unix time of the first and last entry
+    first: Option<u64>,
+    last: u64,
+}
+
+impl CaptureFile {
+    fn open(path: PathBuf, rotation: Option<CaptureConfig>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .chain_err(|| format!("Cannot open rpc capture file {:?}", path))?;
+        let size = file.metadata()?.len();
+        let now = unix_time();
+        Ok(CaptureFile {
+            first: if size == 0 { None } else { first_time(&path).or(Some(now)) },
+            last: now,
+            path,
+            file: Some(file),
+            rotation,
+            size,
+            opened: now,
+        })
+    }
+
+    fn write(&mut self, line: &str, time: u64) {
+        if self.file.is_none() {
+            self.reopen();
+        }
+        let result = match self.file {
+            Some(ref mut file) => writeln!(file, "{}", line),
+            None => return,
+        };
+        match result {
+            Ok(()) => {
+                self.size += line.len() as u64 + 1;
+                if self.first.is_none() {
+                    self.first = Some(time);
+                }
+                self.last = time;
+            }
+            Err(err) => warn!(target: "bridge::rpc", "cannot write rpc capture file: {}", err),
+        }
+    }
+
+    fn reopen(&mut self) {
+        match CaptureFile::open(self.path.clone(), self.rotation.clone()) {
+            Ok(reopened) => *self = reopened,
+            Err(err) => warn!(target: "bridge::rpc", "cannot reopen rpc capture file: {}", err),
+        }
+    }
+
+    /// `true` if the file has entries and reached `max_file_size` or `max_file_age`.
+    fn due(&self, now: u64) -> bool {
+        let rotation = match self.rotation {
+            Some(ref rotation) if self.first.is_some() => rotation,
+            _ => return false,
+        };
+        self.size >= rotation.max_file_size || rotation.max_file_age.map_or(false, |age| {
+            now.saturating_sub(self.opened) >= age.as_secs()
+        })
+    }
+
+    /// moves the file aside, starts a new one, compresses the old one and adds it to the index.
+    fn rotate(&mut self) -> Result<()> {
+        let from = self.first.unwrap_or(self.opened);
+        let to = self.last;
+        // closes the file
+        self.file = None;
+        let rotated = unused_path(&self.path, &from.to_string());
+        fs::rename(&self.path, &rotated)
+            .chain_err(|| format!("Cannot move rpc capture file {:?} aside", self.path))?;
+        self.reopen();
+
+        let compressed = compress(&rotated)?;
+        let index = index_path(&self.path);
+        let mut entries = read_index(&index)?;
+        entries.push(IndexEntry {
+            file: compressed
+                .file_name()
+                .and_then(|name| name.to_str())
+                .expect("named after the capture file; qed")
+                .to_owned(),
+            from,
+            to,
+        });
+        let max_files = self.rotation.as_ref().and_then(|rotation| rotation.max_files);
+        if let Some(max_files) = max_files {
+            while entries.len() > max_files {
+                let oldest = entries.remove(0);
+                let path = self.path.with_file_name(&oldest.file);
+                if let Err(err) = fs::remove_file(&path) {
+                    warn!(target: "bridge::rpc", "cannot delete rotated rpc capture file {:?}: {}", path, err);
+                }
+            }
+        }
+        write_index(&index, &entries)?;
+        info!(
+            target: "bridge::rpc",
+            "rotated rpc capture file to {:?} with entries from {} to {}",
+            compressed,
+            from,
+            to
+        );
+        Ok(())
+    }
+
+    fn rotate_if_due(&mut self, now: u64) {
+        if self.due(now) {
+            if let Err(err) = self.rotate() {
+                warn!(target: "bridge::rpc", "cannot rotate rpc capture file: {}", err);
+            }
+        }
+    }
+}
+
+enum Message {
+    Entry { line: String, time: u64 },
+    /// acknowledged once all earlier entries are written
+    Flush(SyncSender<()>),
+}
+
+/// writes the entries of `receiver` until every sender is dropped.
+fn write_captures(mut file: CaptureFile, receiver: Receiver<Message>) {
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(IDLE_CHECK_INTERVAL)) {
+            Ok(Message::Entry { line, time }) => {
+                file.write(&line, time);
+                file.rotate_if_due(time);
+            }
+            Ok(Message::Flush(done)) => {
+                let _ = done.send(());
+            }
+            Err(RecvTimeoutError::Timeout) => file.rotate_if_due(unix_time()),
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Writes capture entries to a file on a dedicated thread.
+///
+/// entries are queued without blocking. entries that don't fit into the full queue
+/// are dropped and counted.
+#[derive(Debug)]
+pub struct CaptureWriter {
+    /// `SyncSender` isn't `Sync`
+    sender: Mutex<SyncSender<Message>>,
+    dropped: AtomicUsize,
+}
+
+impl CaptureWriter {
+    /// appends to the file at `path`, rotated by `rotation` if set.
+    pub fn create<P: AsRef<Path>>(path: P, rotation: Option<CaptureConfig>) -> Result<Self> {
+        let queue_size = rotation
+            .as_ref()
+            .map_or(DEFAULT_QUEUE_SIZE, |rotation| rotation.queue_size);
+        let file = CaptureFile::open(path.as_ref().to_path_buf(), rotation)?;
+        let (sender, receiver) = sync_channel(queue_size);
+        thread::Builder::new()
+            .name("rpc-capture".into())
+            .spawn(move || write_captures(file, receiver))
+            .chain_err(|| "Cannot start rpc capture writer")?;
+        Ok(CaptureWriter {
+            sender: Mutex::new(sender),
+            dropped: AtomicUsize::new(0),
+        })
+    }
+
+    /// queues `line` captured at unix time `time`.
+    pub fn write(&self, line: String, time: u64) {
+        let result = match self.sender.lock() {
+            Ok(sender) => sender.try_send(Message::Entry { line, time }),
+            Err(_) => return,
+        };
+        match result {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                // 1, 2, 4, ... so a backlog doesn't flood the log
+                if dropped.is_power_of_two() {
+                    warn!(
+                        target: "bridge::rpc",
+                        "rpc capture queue is full. {} entries dropped so far",
+                        dropped
+                    );
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                warn!(target: "bridge::rpc", "rpc capture writer stopped");
+            }
+        }
+    }
+
+    /// number of entries dropped because the queue was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// blocks until every queued entry is written.
+    pub fn flush(&self) {
+        let (done, wait) = sync_channel(1);
+        let sent = match self.sender.lock() {
+            Ok(sender) => sender.send(Message::Flush(done)).is_ok(),
+            Err(_) => false,
+        };
+        if sent {
+            let _ = wait.recv();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use self::tempdir::TempDir;
+    use std::fs;
+    use config::CaptureConfig;
+    use super::{index_path, parse_time, read_captures, read_index, select_capture_files,
+                CaptureWriter};
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(1704067200, parse_time("1704067200").unwrap());
+        assert_eq!(1704067200, parse_time("2024-01-01").unwrap());
+        assert_eq!(1704067200 + 3600 + 120 + 3, parse_time("2024-01-01T01:02:03Z").unwrap());
+        assert_eq!(1704067200 + 3600 + 120, parse_time("2024-01-01 01:02").unwrap());
+        assert!(parse_time("2024-01-01T24:00").is_err());
+        assert!(parse_time("2024-01-01T01").is_err());
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    fn line(time: u64) -> String {
+        format!(
+            r#"{{"chain":"home","method":"eth_blockNumber","params":[],"response":"0x1","time":{}}}"#,
+            time
+        )
+    }
+
+    #[test]
+    fn test_rotation_and_replay_lookup() {
+        let tempdir = TempDir::new("test_capture_rotation").unwrap();
+        let path = tempdir.path().join("rpc.jsonl");
+        let rotation = CaptureConfig {
+            // every entry is longer
+            max_file_size: 10,
+            max_file_age: None,
+            max_files: Some(2),
+            queue_size: 16,
+        };
+        let writer = CaptureWriter::create(&path, Some(rotation)).unwrap();
+        for time in &[100, 200, 300] {
+            writer.write(line(*time), *time);
+        }
+        writer.flush();
+        assert_eq!(0, writer.dropped());
+
+        // the oldest rotated file was deleted
+        let index = read_index(&index_path(&path)).unwrap();
+        assert_eq!(
+            vec![(200, 200), (300, 300)],
+            index.iter().map(|entry| (entry.from, entry.to)).collect::<Vec<_>>()
+        );
+        assert!(index.iter().all(|entry| entry.file.ends_with(".gz")));
+        assert_eq!(0, fs::metadata(&path).unwrap().len());
+
+        let files = select_capture_files(tempdir.path(), 250, 1000).unwrap();
+        assert_eq!(vec![tempdir.path().join(&index[1].file)], files);
+        assert_eq!(
+            format!("{}\n{}\n", line(200), line(300)),
+            read_captures(tempdir.path(), 0, 1000).unwrap()
+        );
+        assert!(read_captures(tempdir.path(), 400, 1000).is_err());
+    }
+}
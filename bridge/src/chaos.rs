@@ -0,0 +1,420 @@
+//! Fault injection for chaos testing a staging bridge, see `ChaosConfig`.
+//!
+//! only compiled with the `chaos` feature. `ChaosTransport` drops responses, malforms logs
+//! and delays receipts of a node, `ChaosBackend` fails a window of database saves.
+//! every injector draws from a `Jitter` seeded by `chaos.seed` and its name, so the same seed
+//! and the same traffic inject the same faults. `Chaos::counts` tells which were injected.
+
+use std::fmt;
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock};
+use futures::{Async, Future, Poll};
+use rpc;
+use serde_json;
+use tiny_keccak::keccak256;
+use web3::{self, RequestId, Transport};
+use web3::types::{H256, U256};
+use bridge::{BridgeBackend, BridgeChecked};
+use config::ChaosConfig;
+use database::{Database, DeadWithdraw, DeepReorg, DepositCallback, DepositReceipt, ObservedDay,
+               ObservedWithdraw, QuarantinedDeposit, RawLog, SigningLatency, SkippedDeposit,
+               SpendLog, WatchedExecution, WithdrawAttempts, WithdrawOrigin};
+use error::Result;
+use jitter::Jitter;
+use outbox::OutboxStore;
+use transport::method_call;
+
+/// Faults injected by the injectors of a `Chaos`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ChaosCounts {
+    pub dropped_responses: u64,
+    pub malformed_logs: u64,
+    pub delayed_receipts: u64,
+    pub failed_saves: u64,
+}
+
+impl fmt::Display for ChaosCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "dropped responses {}, malformed logs {}, delayed receipts {}, failed saves {}",
+            self.dropped_responses, self.malformed_logs, self.delayed_receipts, self.failed_saves
+        )
+    }
+}
+
+/// Configured faults and the ones injected, shared by the injectors of a run.
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    config: ChaosConfig,
+    counts: Arc<RwLock<ChaosCounts>>,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        Chaos {
+            config,
+            counts: Default::default(),
+        }
+    }
+
+    /// faults injected so far.
+    pub fn counts(&self) -> ChaosCounts {
+        *self.counts
+            .read()
+            .expect("no code panics while holding the lock; qed")
+    }
+
+    fn record<F: FnOnce(&mut ChaosCounts)>(&self, record: F) {
+        record(&mut self.counts
+            .write()
+            .expect("no code panics while holding the lock; qed"));
+    }
+
+    /// source of the injector `name`, seeded by `chaos.seed`.
+    fn jitter(&self, name: &str) -> Jitter {
+        let hash = keccak256(name.as_bytes());
+        let seed = hash[..8]
+            .iter()
+            .fold(0u64, |seed, byte| (seed << 8) | u64::from(*byte));
+        Jitter::from_seed(self.config.seed ^ seed)
+    }
+
+    /// `inner` with the faults of the json-rpc traffic with the node of `chain`.
+    pub fn transport<T>(&self, inner: T, chain: &'static str) -> ChaosTransport<T> {
+        ChaosTransport {
+            inner,
+            chain,
+            jitter: Arc::new(Mutex::new(self.jitter(chain))),
+            chaos: self.clone(),
+        }
+    }
+
+    /// `inner` failing the saves of `chaos.persistence_outage`.
+    pub fn backend<B>(&self, inner: B) -> ChaosBackend<B> {
+        ChaosBackend {
+            inner,
+            chaos: self.clone(),
+            saves: 0,
+            unsaved: Vec::new(),
+        }
+    }
+}
+
+/// `logs` with a byte appended to the data of the first log, so it isn't a multiple of
+/// 32 bytes anymore. `None` if there is no log.
+fn malform_logs(logs: &rpc::Value) -> Option<rpc::Value> {
+    let mut logs = logs.clone();
+    let data = match logs.get_mut(0).and_then(|log| log.get_mut("data")) {
+        Some(data) => data,
+        None => return None,
+    };
+    let malformed = format!("{}00", data.as_str().unwrap_or("0x"));
+    *data = rpc::Value::String(malformed);
+    Some(logs)
+}
+
+fn quantity(value: &rpc::Value) -> Option<u64> {
+    serde_json::from_value::<U256>(value.clone())
+        .ok()
+        .map(|quantity| quantity.low_u64())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fault {
+    None,
+    DropResponse,
+    MalformLogs,
+    DelayReceipt,
+}
+
+/// Injects faults into the json-rpc traffic with a node, see `ChaosConfig`.
+///
+/// requests always reach the node. a dropped response fails with a transport error after
+/// the node answered, like a connection lost in between.
+#[derive(Debug, Clone)]
+pub struct ChaosTransport<T> {
+    inner: T,
+    chain: &'static str,
+    jitter: Arc<Mutex<Jitter>>,
+    chaos: Chaos,
+}
+
+impl<T: Transport + Clone> Transport for ChaosTransport<T> {
+    type Out = Chaotic<T>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let (method, _) = method_call(&request);
+        let config = &self.chaos.config;
+        let fault = {
+            let mut jitter = self.jitter
+                .lock()
+                .expect("no code panics while holding the lock; qed");
+            if jitter.chance(config.drop_responses) {
+                Fault::DropResponse
+            } else if method == "eth_getLogs" && jitter.chance(config.malformed_logs) {
+                Fault::MalformLogs
+            } else if method == "eth_getTransactionReceipt" && config.receipt_delay > 0 {
+                Fault::DelayReceipt
+            } else {
+                Fault::None
+            }
+        };
+        Chaotic {
+            response: self.inner.send(id, request),
+            head: None,
+            transport: self.inner.clone(),
+            chain: self.chain,
+            method,
+            fault,
+            chaos: self.chaos.clone(),
+        }
+    }
+}
+
+/// Response of a `ChaosTransport`.
+pub struct Chaotic<T: Transport> {
+    response: T::Out,
+    /// receipt and its block waiting for the head, see `ChaosConfig::receipt_delay`
+    head: Option<(rpc::Value, u64, T::Out)>,
+    transport: T,
+    chain: &'static str,
+    method: String,
+    fault: Fault,
+    chaos: Chaos,
+}
+
+impl<T: Transport> Future for Chaotic<T> {
+    type Item = rpc::Value;
+    type Error = web3::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.head.is_some() {
+            let head = match self.head {
+                Some((_, _, ref mut head)) => try_ready!(head.poll()),
+                None => unreachable!("checked above; qed"),
+            };
+            let (receipt, block, _) = self.head.take().expect("checked above; qed");
+            let delay = self.chaos.config.receipt_delay;
+            if quantity(&head).map_or(true, |head| head < block + delay) {
+                self.chaos.record(|counts| counts.delayed_receipts += 1);
+                return Ok(Async::Ready(rpc::Value::Null));
+            }
+            return Ok(Async::Ready(receipt));
+        }
+
+        let response = try_ready!(self.response.poll());
+        match self.fault {
+            Fault::None => Ok(Async::Ready(response)),
+            Fault::DropResponse => {
+                self.chaos.record(|counts| counts.dropped_responses += 1);
+                Err(web3::Error::Transport(format!(
+                    "chaos dropped the response to {} on {}",
+                    self.method, self.chain
+                )))
+            }
+            Fault::MalformLogs => match malform_logs(&response) {
+                Some(malformed) => {
+                    self.chaos.record(|counts| counts.malformed_logs += 1);
+                    Ok(Async::Ready(malformed))
+                }
+                None => Ok(Async::Ready(response)),
+            },
+            Fault::DelayReceipt => {
+                let block = quantity(&response["blockNumber"]);
+                match block {
+                    Some(block) => {
+                        let head = self.transport.execute("eth_blockNumber", Vec::new());
+                        self.head = Some((response, block, head));
+                        self.poll()
+                    }
+                    None => Ok(Async::Ready(response)),
+                }
+            }
+        }
+    }
+}
+
+/// Fails the saves of `chaos.persistence_outage`, like an unavailable database mount.
+///
+/// the checks of the failed saves are written by the next save that gets through,
+/// every other change is recorded by `inner` right away.
+pub struct ChaosBackend<B> {
+    inner: B,
+    chaos: Chaos,
+    /// saves so far
+    saves: u64,
+    /// checks of the failed saves
+    unsaved: Vec<BridgeChecked>,
+}
+
+impl<B> ChaosBackend<B> {
+    /// the backend the saves get through to.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+}
+
+impl<B: BridgeBackend> BridgeBackend for ChaosBackend<B> {
+    fn save(&mut self, checks: Vec<BridgeChecked>) -> Result<()> {
+        self.saves += 1;
+        let saves = self.saves;
+        let failed = self.chaos
+            .config
+            .persistence_outage
+            .as_ref()
+            .map_or(false, |outage| {
+                saves > outage.after_saves && saves <= outage.after_saves + outage.saves
+            });
+        self.unsaved.extend(checks);
+        if failed {
+            self.chaos.record(|counts| counts.failed_saves += 1);
+            let err = io::Error::new(
+                io::ErrorKind::Other,
+                format!("chaos failed save {} of the database", saves),
+            );
+            return Err(err.into());
+        }
+        let checks = mem::replace(&mut self.unsaved, Vec::new());
+        self.inner.save(checks)
+    }
+
+    fn record_spend(&mut self, spend: SpendLog) {
+        self.inner.record_spend(spend)
+    }
+
+    fn record_watched_executions(&mut self, executions: Vec<WatchedExecution>) {
+        self.inner.record_watched_executions(executions)
+    }
+
+    fn record_relayed_withdraws(&mut self, messages: Vec<H256>) {
+        self.inner.record_relayed_withdraws(messages)
+    }
+
+    fn record_quarantined_deposits(&mut self, deposits: Vec<QuarantinedDeposit>) {
+        self.inner.record_quarantined_deposits(deposits)
+    }
+
+    fn record_withdraw_attempts(
+        &mut self,
+        attempts: Vec<WithdrawAttempts>,
+        dead: Vec<DeadWithdraw>,
+    ) {
+        self.inner.record_withdraw_attempts(attempts, dead)
+    }
+
+    fn record_deposit_callbacks(&mut self, callbacks: Vec<DepositCallback>) {
+        self.inner.record_deposit_callbacks(callbacks)
+    }
+
+    fn record_deposit_receipts(&mut self, receipts: Vec<DepositReceipt>) {
+        self.inner.record_deposit_receipts(receipts)
+    }
+
+    fn record_skipped_deposits(&mut self, skipped: Vec<SkippedDeposit>) {
+        self.inner.record_skipped_deposits(skipped)
+    }
+
+    fn record_observed_traffic(&mut self, days: Vec<ObservedDay>) {
+        self.inner.record_observed_traffic(days)
+    }
+
+    fn record_raw_logs(&mut self, logs: Vec<RawLog>) {
+        self.inner.record_raw_logs(logs)
+    }
+
+    fn record_signing_latencies(
+        &mut self,
+        observed: Vec<ObservedWithdraw>,
+        latencies: Vec<SigningLatency>,
+    ) {
+        self.inner.record_signing_latencies(observed, latencies)
+    }
+
+    fn record_withdraw_origins(&mut self, origins: Vec<WithdrawOrigin>) {
+        self.inner.record_withdraw_origins(origins)
+    }
+
+    fn record_reorgs(&mut self, max_depth: Option<u64>, deep_reorg: Option<DeepReorg>) {
+        self.inner.record_reorgs(max_depth, deep_reorg)
+    }
+
+    fn record_admin_nonce(&mut self, nonce: u64) -> Result<()> {
+        self.inner.record_admin_nonce(nonce)
+    }
+
+    fn reload(&mut self) -> Result<Database> {
+        self.unsaved.clear();
+        self.inner.reload()
+    }
+
+    fn outbox_store(&self) -> Box<OutboxStore> {
+        self.inner.outbox_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+    use bridge::{BridgeBackend, BridgeChecked, FileBackend};
+    use config::{ChaosConfig, PersistenceOutage};
+    use database::Database;
+    use self::tempdir::TempDir;
+    use super::{malform_logs, Chaos};
+
+    #[test]
+    fn test_malform_logs_breaks_the_data_of_the_first_log() {
+        let logs = json!([{"data": "0x00"}, {"data": "0x01"}]);
+        assert_eq!(
+            json!([{"data": "0x0000"}, {"data": "0x01"}]),
+            malform_logs(&logs).unwrap()
+        );
+        assert_eq!(None, malform_logs(&json!([])));
+    }
+
+    #[test]
+    fn test_chaos_backend_fails_the_outage_and_writes_its_checks_later() {
+        let tempdir = TempDir::new("test_chaos_backend").unwrap();
+        let path = tempdir.path().join("db.toml");
+        let chaos = Chaos::new(ChaosConfig {
+            persistence_outage: Some(PersistenceOutage {
+                after_saves: 1,
+                saves: 2,
+            }),
+            ..ChaosConfig::default()
+        });
+        let mut backend = chaos.backend(FileBackend::new(path.clone(), &Database::default()));
+
+        backend.save(vec![BridgeChecked::DepositRelay(1)]).unwrap();
+        assert!(backend.save(vec![BridgeChecked::DepositRelay(2)]).is_err());
+        assert!(backend.save(vec![BridgeChecked::WithdrawRelay(3)]).is_err());
+        assert_eq!(1, Database::load(&path).unwrap().checked_deposit_relay);
+        assert_eq!(2, chaos.counts().failed_saves);
+
+        backend.save(Vec::new()).unwrap();
+        let saved = Database::load(&path).unwrap();
+        assert_eq!(2, saved.checked_deposit_relay);
+        assert_eq!(3, saved.checked_withdraw_relay);
+        assert_eq!(saved, backend.inner().database().get());
+    }
+
+    #[test]
+    fn test_chaos_draws_are_seeded() {
+        let draws = |seed, name: &str| {
+            let chaos = Chaos::new(ChaosConfig {
+                seed,
+                ..ChaosConfig::default()
+            });
+            let mut jitter = chaos.jitter(name);
+            (0..50).map(|_| jitter.chance(50)).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(1, "home"), draws(1, "home"));
+        assert!(draws(1, "home") != draws(1, "foreign"));
+        assert!(draws(1, "home") != draws(2, "home"));
+    }
+}
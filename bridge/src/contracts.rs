@@ -4,3 +4,13 @@ use_contract!(
     "ForeignBridge",
     "../compiled_contracts/ForeignBridge.abi"
 );
+use_contract!(
+    payout_registry,
+    "PayoutRegistry",
+    "../compiled_contracts/PayoutRegistry.abi"
+);
+use_contract!(
+    receipt_registry,
+    "ReceiptRegistry",
+    "../compiled_contracts/ReceiptRegistry.abi"
+);
@@ -1,9 +1,23 @@
+use std::collections::BTreeMap;
 use std::path::Path;
-use std::{fmt, fs, io, str};
+use std::sync::{Arc, RwLock};
+use std::{fmt, fs, io, str, u32};
 use std::io::{Read, Write};
-use web3::types::{Address, TransactionReceipt};
+use web3::types::{Address, Bytes, H256, Log, TransactionReceipt, U256};
+use serde_json;
+use tiny_keccak::keccak256;
 use toml;
 use error::{Error, ErrorKind, ResultExt};
+use bridge::{Day, Direction, SkipReason, StepTimings};
+
+/// schema version of the database files written by this version of the bridge.
+/// files of older versions are upgraded by `Database::migrate`.
+pub const SCHEMA_VERSION: u32 = 8;
+/// oldest schema version `Database::migrate` can upgrade.
+/// files written before schema versions were introduced have no `schema_version` and are version 0.
+pub const OLDEST_SCHEMA_VERSION: u32 = 0;
+/// version of the json snapshots written by `Snapshot::new`.
+pub const SNAPSHOT_VERSION: u32 = 1;
 
 /// Application "database".
 #[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
@@ -22,35 +36,535 @@ pub struct Database {
     pub checked_withdraw_relay: u64,
     /// Number of last block which has been checked for withdraw confirms.
     pub checked_withdraw_confirm: u64,
+    /// Hashes of the messages of the withdraws relayed last, oldest first.
+    /// Omitted if empty. Written before the tables, as toml requires.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relayed_withdraws: Vec<H256>,
+    /// Depth of the deepest home reorg the reorg monitor observed, see `ReorgMonitorConfig`.
+    /// Omitted until one is observed. Written before the tables, as toml requires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_reorg_depth: Option<u64>,
+    /// Nonce of the last signed admin command executed, see `SignedCommandsConfig`.
+    /// Omitted until one is executed. Written before the tables, as toml requires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_nonce: Option<u64>,
+    /// Relays recorded by the spend limits. Omitted if empty.
+    #[serde(default, skip_serializing_if = "SpendLog::is_empty")]
+    pub spend: SpendLog,
+    /// Relayed deposits whose execution on foreign isn't final yet. Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub watched_executions: Vec<WatchedExecution>,
+    /// Deposits not relayed because their recipient contract rejects the transfer.
+    /// Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quarantined_deposits: Vec<QuarantinedDeposit>,
+    /// Attempts of the phases of recent withdraws, see `WithdrawAttemptsConfig`. Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub withdraw_attempts: Vec<WithdrawAttempts>,
+    /// Withdraws given up after too many attempts of a phase. Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dead_withdraws: Vec<DeadWithdraw>,
+    /// Callbacks of recent deposit relays, see `DepositCallbackConfig`. Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deposit_callbacks: Vec<DepositCallback>,
+    /// Recent deposits not relayed, or not yet, and why. Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_deposits: Vec<SkippedDeposit>,
+    /// Deposits and withdraws observed by day, also those relayed by other authorities.
+    /// Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub observed_traffic: Vec<ObservedDay>,
+    /// Deposit and withdraw logs as observed, most recent last, see `RawLogs`.
+    /// Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub raw_logs: Vec<RawLog>,
+    /// Withdraws by when this bridge first observed them, most recent last, see
+    /// `SigningLatencies`. Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub observed_withdraws: Vec<ObservedWithdraw>,
+    /// Signatures of the authorities on observed withdraws, most recent last. Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signing_latencies: Vec<SigningLatency>,
+    /// `Withdraw` events of the withdraws relayed last, most recent last, see `WithdrawOrigins`.
+    /// Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub withdraw_origins: Vec<WithdrawOrigin>,
+    /// Receipts of recent completed deposits, see `ReceiptRegistryConfig`. Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deposit_receipts: Vec<DepositReceipt>,
+    /// Webhook notifications not yet delivered, and those delivered within
+    /// `webhooks.retention`, see `outbox::Outbox`. Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webhook_outbox: Vec<OutboxEntry>,
+    /// Home reorg at least `reorg_monitor.alarm_depth` deep the operator hasn't acknowledged.
+    /// Omitted if none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deep_reorg: Option<DeepReorg>,
+}
+
+/// Deposit relayed to foreign that is watched until its execution is final.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct WatchedExecution {
+    /// Hash of the home transaction that emitted the deposit.
+    pub home_transaction: H256,
+    /// Index of the deposit log in its home block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<U256>,
+    /// Hash of the foreign transaction that executed the deposit.
+    pub foreign_transaction: H256,
+    /// Foreign block the relay was first checked at.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since_block: Option<u64>,
+    /// Number of the foreign block of the execution. `None` until its receipt is found.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<u64>,
+    /// Hash of the foreign block of the execution.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<H256>,
+    /// Milliseconds the batch of the relay spent in each step until it was sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<StepTimings>,
+}
+
+/// Deposit not relayed because the relay to its recipient contract on foreign reverts.
+/// Kept until the operator removes it, e.g. once the deposit is recovered on home.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct QuarantinedDeposit {
+    /// Hash of the home transaction that emitted the deposit.
+    pub home_transaction: H256,
+    /// Index of the deposit log in its home block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<U256>,
+    /// Contract receiving the deposit on foreign.
+    pub recipient: Address,
+    /// Recipient of the deposit on home if `recipient` is its contract wallet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_recipient: Option<Address>,
+    /// Value of the deposit on home.
+    pub value: U256,
+    /// Why the simulated relay reverted.
+    pub reason: String,
+    /// Log of the deposit if it was observed and is at most `MAX_QUARANTINED_RAW_LOG_SIZE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_log: Option<RawLog>,
+}
+
+/// Phase of a withdraw this authority attempts.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WithdrawPhase {
+    /// signing the message of the withdraw
+    Sign,
+    /// submitting the signature to `ForeignBridge`
+    SubmitSignature,
+    /// relaying the collected signatures to `HomeBridge.withdraw`
+    Finalize,
+}
+
+/// One attempt of a phase of a withdraw.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct WithdrawAttempt {
+    pub phase: WithdrawPhase,
+    /// Hash of the transaction sent by the attempt, if it got that far.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<H256>,
+    /// Error the attempt failed with. `None` if it succeeded or is in flight.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Attempts of the phases of a withdraw, oldest first.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct WithdrawAttempts {
+    /// Hash of the foreign transaction that emitted the withdraw.
+    pub withdraw: H256,
+    pub attempts: Vec<WithdrawAttempt>,
+}
+
+impl WithdrawAttempts {
+    /// number of attempts of `phase`.
+    pub fn count(&self, phase: WithdrawPhase) -> usize {
+        self.attempts
+            .iter()
+            .filter(|attempt| attempt.phase == phase)
+            .count()
+    }
+}
+
+/// Withdraw given up after its `phase` was attempted as often as `WithdrawAttemptsConfig` allows.
+/// Kept until the operator removes it, e.g. once the withdraw is completed manually.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct DeadWithdraw {
+    /// Hash of the foreign transaction that emitted the withdraw.
+    pub withdraw: H256,
+    pub phase: WithdrawPhase,
+    /// Every attempt of the withdraw, oldest first.
+    pub attempts: Vec<WithdrawAttempt>,
+}
+
+/// Callback sent to `deposit_callback.contract` after the relay of a deposit.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct DepositCallback {
+    /// Hash of the home transaction that emitted the deposit.
+    pub home_transaction: H256,
+    /// Index of the deposit log in its home block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<U256>,
+    /// Hash of the foreign transaction that relayed the deposit.
+    pub relay_transaction: H256,
+    /// Recipient of the deposit on foreign.
+    pub recipient: Address,
+    /// Value of the deposit on foreign.
+    pub value: U256,
+    /// Number of times the callback was sent.
+    pub attempts: u32,
+    /// Hash of the callback transaction. `None` until it's sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<H256>,
+    /// Error the last attempt failed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Receipt of a deposit completed by this authority, recorded in `receipt_registry.address`.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct DepositReceipt {
+    /// Hash of the home transaction that emitted the deposit.
+    pub home_transaction: H256,
+    /// Hash of the foreign transaction that completed the deposit.
+    pub relay_transaction: H256,
+    /// Number of times the receipt was recorded.
+    pub attempts: u32,
+    /// Hash of the home transaction recording the receipt. `None` until it's sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<H256>,
+    /// Whether the registry had a receipt of the home transaction already. not recorded again.
+    #[serde(default)]
+    pub already_recorded: bool,
+    /// Error the last attempt failed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
+/// Webhook notification in the outbox, see `outbox::Outbox`.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct OutboxEntry {
+    /// Position of the entry in the outbox. Entries of the same `ordering_key` and endpoint
+    /// are delivered by sequence.
+    pub sequence: u64,
+    /// Endpoint the entry is delivered to, e.g. `webhook 0`.
+    pub endpoint: String,
+    /// Idempotency key of the notification, the same on every attempt and replay.
+    pub key: String,
+    /// Key of the relay the notification is about.
+    pub ordering_key: String,
+    /// Canonical json of the notification.
+    pub payload: String,
+    /// Unix time in seconds the entry was written.
+    pub created_at: u64,
+    /// Unix time in seconds the endpoint acknowledged the entry. `None` until delivered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivered_at: Option<u64>,
+    /// Idempotency key of the batch the entry was first posted in, if its endpoint batches.
+    /// The batch is posted again as it was until it's acknowledged as a whole.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<String>,
+}
+
+/// Home reorg deep enough to break the finality the deposit relays assume.
+/// Kept until the operator acknowledges it, see `Bridge::acknowledge_deep_reorg`.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct DeepReorg {
+    /// Lowest orphaned block.
+    pub fork_block: u64,
+    /// Number of blocks between `fork_block` and the latest block, inclusive.
+    pub depth: u64,
+    /// `reorg_monitor.alarm_depth` the reorg reached.
+    pub alarm_depth: u64,
+    /// Unix time in seconds the reorg was detected.
+    pub time: u64,
+}
+
+/// Deposit the deposit relay didn't relay, or not yet, see `SkippedDeposits`.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct SkippedDeposit {
+    /// Hash of the home transaction that emitted the deposit.
+    pub home_transaction: H256,
+    /// Index of the deposit log in its home block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<U256>,
+    /// Home block of the deposit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<u64>,
+    /// Unix time of the skip in seconds.
+    pub time: u64,
+    pub reason: SkipReason,
+    /// Why the deposit was skipped, as logged.
+    pub message: String,
+    /// Values the skip depended on, e.g. the spend limit. Omitted if empty.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub parameters: BTreeMap<String, String>,
+}
+
+/// Deposits or withdraws observed on one day, see `ObservedTraffic`.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ObservedDay {
+    /// Utc day the events were observed on.
+    pub day: Day,
+    pub direction: Direction,
+    /// Number of events observed.
+    pub events: u64,
+    /// Sum of the values of the events, in wei of the chain that emitted them.
+    pub value: U256,
+    /// Events counted that can still be observed again, e.g. by a component rebuilt
+    /// from its checked block. Omitted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub counted: Vec<ObservedEvent>,
+}
+
+/// Identity of a deposit or withdraw event counted in an `ObservedDay`.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ObservedEvent {
+    /// Hash of the transaction that emitted the event.
+    pub transaction: H256,
+    /// Index of the event log in its block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<U256>,
+    /// Block of the event.
+    pub block: u64,
+}
+
+/// Deposit or withdraw log as the node returned it, kept to analyze the event offline.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct RawLog {
+    /// Contract that emitted the log.
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: Bytes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<H256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_hash: Option<H256>,
+    /// Index of the log in its block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<U256>,
+}
+
+impl RawLog {
+    pub fn from_log(log: &Log) -> Self {
+        RawLog {
+            address: log.address,
+            topics: log.topics.clone(),
+            data: log.data.clone(),
+            block_hash: log.block_hash,
+            block_number: log.block_number.map(|number| number.low_u64()),
+            transaction_hash: log.transaction_hash,
+            log_index: log.log_index,
+        }
+    }
+
+    /// bytes of the topics and data of the log.
+    pub fn size(&self) -> usize {
+        32 * self.topics.len() + self.data.0.len()
+    }
+}
+
+/// Withdraw and when this bridge first observed it, in seconds since the unix epoch.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ObservedWithdraw {
+    /// Id of the withdraw, see `util::log_id`.
+    pub withdraw: H256,
+    pub observed: u64,
+}
+
+/// Signature of an authority on a withdraw this bridge observed before.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct SigningLatency {
+    /// Id of the withdraw, see `util::log_id`.
+    pub withdraw: H256,
+    pub authority: Address,
+    /// Hash of the foreign transaction that submitted the signature.
+    pub transaction: H256,
+    /// When this bridge first observed the withdraw, in seconds since the unix epoch.
+    pub observed: u64,
+    /// Timestamp of the foreign block of the signature.
+    pub signed: u64,
+}
+
+impl SigningLatency {
+    /// seconds from the observation of the withdraw to its signature. negative if the
+    /// authority signed before this bridge observed the withdraw, e.g. with fewer confirmations.
+    pub fn latency(&self) -> i64 {
+        self.signed as i64 - self.observed as i64
+    }
+}
+
+/// `Withdraw` event a message relayed by withdraw relay originates from.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct WithdrawOrigin {
+    /// Hash of the foreign transaction that emitted the withdraw, as in its message.
+    pub transaction: H256,
+    /// Number of the foreign block of the withdraw.
+    pub block: u64,
+    /// Index of the withdraw log in its foreign block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_index: Option<U256>,
+    /// Hash of the message relaying the withdraw. `None` until its signatures were collected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_hash: Option<H256>,
+    /// Whether withdraw relay fetched the withdraw since withdraw confirm hadn't observed it.
+    #[serde(default)]
+    pub fetched: bool,
+}
+
+/// Relay recorded by a spend limit.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct Spend {
+    /// Unix time of the relay in seconds.
+    pub time: u64,
+    /// Value of a deposit or 1 for a withdraw.
+    pub amount: U256,
+    /// Last block of the batch the relay belongs to.
+    pub block: u64,
+}
+
+/// Relays recorded by the spend limits within their windows.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct SpendLog {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deposits: Vec<Spend>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub withdraws: Vec<Spend>,
+}
+
+impl SpendLog {
+    pub fn is_empty(&self) -> bool {
+        self.deposits.is_empty() && self.withdraws.is_empty()
+    }
+}
+
+fn parse_table(s: &str) -> Result<toml::value::Table, Error> {
+    toml::from_str(s).chain_err(|| "Cannot parse database")
+}
+
+fn schema_version(table: &toml::value::Table) -> Result<u32, Error> {
+    match table.get("schema_version") {
+        None => Ok(0),
+        Some(&toml::Value::Integer(version)) if 0 <= version && version <= u32::MAX as i64 => {
+            Ok(version as u32)
+        }
+        Some(other) => Err(format!("Invalid database schema version {}", other).into()),
+    }
+}
+
+/// upgrades `table` from schema version `version` to `version + 1`.
+fn upgrade(version: u32, table: &mut toml::value::Table) {
+    match version {
+        // version 1 introduced `schema_version`, the other fields are unchanged
+        0 => {}
+        // version 2 added the optional `timings` of watched executions. older ones have none
+        1 => {}
+        // version 3 added the optional `raw_logs` and `raw_log` of quarantined deposits
+        2 => {}
+        // version 4 added the optional `observed_withdraws` and `signing_latencies`
+        3 => {}
+        // version 5 added the optional `withdraw_origins`
+        4 => {}
+        // version 6 added the optional `deposit_receipts`
+        5 => {}
+        // version 7 added the optional `max_reorg_depth` and `deep_reorg`
+        6 => {}
+        // version 8 added the optional `webhook_outbox`
+        7 => {}
+        _ => unreachable!("only versions below SCHEMA_VERSION are upgraded; qed"),
+    }
+    table.insert(
+        "schema_version".into(),
+        toml::Value::Integer(i64::from(version + 1)),
+    );
+}
+
+fn into_database(table: toml::value::Table) -> Result<Database, Error> {
+    toml::Value::Table(table)
+        .try_into()
+        .chain_err(|| "Cannot parse database")
+}
+
+/// only parses databases of `SCHEMA_VERSION`.
+/// older databases must be upgraded with `Database::migrate` first.
 impl str::FromStr for Database {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        toml::from_str(s).chain_err(|| "Cannot parse database")
+        let table = parse_table(s)?;
+        match schema_version(&table)? {
+            SCHEMA_VERSION => into_database(table),
+            version if version > SCHEMA_VERSION => {
+                Err(ErrorKind::UnsupportedDatabaseVersion(version, SCHEMA_VERSION).into())
+            }
+            version => Err(ErrorKind::DatabaseMigrationRequired(version, SCHEMA_VERSION).into()),
+        }
     }
 }
 
 impl fmt::Display for Database {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "schema_version = {}\n", SCHEMA_VERSION)?;
         f.write_str(&toml::to_string(self).expect("serialization can't fail; qed"))
     }
 }
 
+fn read_file(path: &Path) -> Result<String, Error> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(ErrorKind::MissingFile(format!("{:?}", path)).into())
+        }
+        Err(err) => return Err(err).chain_err(|| "Cannot open database"),
+    };
+
+    let mut buffer = String::new();
+    file.read_to_string(&mut buffer)?;
+    Ok(buffer)
+}
+
 impl Database {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let mut file = match fs::File::open(&path) {
-            Ok(file) => file,
-            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
-                return Err(ErrorKind::MissingFile(format!("{:?}", path.as_ref())).into())
-            }
-            Err(err) => return Err(err).chain_err(|| "Cannot open database"),
-        };
+        read_file(path.as_ref())?.parse()
+    }
+
+    /// parses a database of any schema version since `OLDEST_SCHEMA_VERSION`
+    /// and upgrades it to `SCHEMA_VERSION`.
+    /// returns the schema version it had along with the upgraded database.
+    pub fn migrate_str(s: &str) -> Result<(u32, Self), Error> {
+        let mut table = parse_table(s)?;
+        let found = schema_version(&table)?;
+        if found > SCHEMA_VERSION {
+            return Err(ErrorKind::UnsupportedDatabaseVersion(found, SCHEMA_VERSION).into());
+        }
+        for version in found..SCHEMA_VERSION {
+            upgrade(version, &mut table);
+        }
+        Ok((found, into_database(table)?))
+    }
+
+    /// upgrades the database at `path` to `SCHEMA_VERSION`.
+    /// the original file is kept as `<path>.v<version>.bak` before it's overwritten.
+    /// returns the schema version the database had or `None` if it was up to date.
+    pub fn migrate<P: AsRef<Path>>(path: P) -> Result<Option<u32>, Error> {
+        let path = path.as_ref();
+        let (found, database) = Database::migrate_str(&read_file(path)?)?;
+        if found == SCHEMA_VERSION {
+            return Ok(None);
+        }
 
-        let mut buffer = String::new();
-        file.read_to_string(&mut buffer)?;
-        buffer.parse()
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".v{}.bak", found));
+        fs::copy(path, &backup).chain_err(|| format!("Cannot back up database to {:?}", backup))?;
+        database.save(fs::File::create(path)?)?;
+        Ok(Some(found))
     }
 
     pub fn save<W: Write>(&self, mut write: W) -> Result<(), Error> {
@@ -58,6 +572,23 @@ impl Database {
         Ok(())
     }
 
+    /// writes the database of a `Snapshot` to `path`.
+    /// refuses to overwrite an existing database unless `force`.
+    pub fn import<P: AsRef<Path>>(path: P, snapshot: &str, force: bool) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let database = Snapshot::database_from_json(snapshot)?;
+        if path.exists() && !force {
+            bail!(
+                "Database {:?} already exists, import over it with --force",
+                path
+            );
+        }
+        let file =
+            fs::File::create(path).chain_err(|| format!("Cannot create database {:?}", path))?;
+        database.save(file)?;
+        Ok(database)
+    }
+
     pub fn from_receipts(
         home_receipt: &TransactionReceipt,
         foreign_receipt: &TransactionReceipt,
@@ -74,17 +605,319 @@ impl Database {
             checked_deposit_relay: home_receipt.block_number.low_u64(),
             checked_withdraw_relay: foreign_receipt.block_number.low_u64(),
             checked_withdraw_confirm: foreign_receipt.block_number.low_u64(),
+            relayed_withdraws: Vec::new(),
+            max_reorg_depth: None,
+            admin_nonce: None,
+            spend: SpendLog::default(),
+            watched_executions: Vec::new(),
+            quarantined_deposits: Vec::new(),
+            withdraw_attempts: Vec::new(),
+            dead_withdraws: Vec::new(),
+            deposit_callbacks: Vec::new(),
+            skipped_deposits: Vec::new(),
+            observed_traffic: Vec::new(),
+            raw_logs: Vec::new(),
+            observed_withdraws: Vec::new(),
+            signing_latencies: Vec::new(),
+            withdraw_origins: Vec::new(),
+            deposit_receipts: Vec::new(),
+            webhook_outbox: Vec::new(),
+            deep_reorg: None,
+        }
+    }
+}
+
+/// Json export of a `Database` independent of how the database is stored.
+///
+/// used to move a database between hosts or storage formats.
+/// imports check the record counts and the checksum.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct Snapshot {
+    pub snapshot_version: u32,
+    /// schema version of `database`
+    pub schema_version: u32,
+    pub counts: SnapshotCounts,
+    /// keccak256 of the compact json of `database`
+    pub checksum: H256,
+    pub database: Database,
+}
+
+/// Number of records of each kind in a `Snapshot`.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+pub struct SnapshotCounts {
+    pub spend_deposits: usize,
+    pub spend_withdraws: usize,
+    #[serde(default)]
+    pub watched_executions: usize,
+    #[serde(default)]
+    pub quarantined_deposits: usize,
+    #[serde(default)]
+    pub withdraw_attempts: usize,
+    #[serde(default)]
+    pub dead_withdraws: usize,
+    #[serde(default)]
+    pub deposit_callbacks: usize,
+    #[serde(default)]
+    pub skipped_deposits: usize,
+    #[serde(default)]
+    pub observed_days: usize,
+    #[serde(default)]
+    pub raw_logs: usize,
+    #[serde(default)]
+    pub observed_withdraws: usize,
+    #[serde(default)]
+    pub signing_latencies: usize,
+    #[serde(default)]
+    pub withdraw_origins: usize,
+    #[serde(default)]
+    pub deposit_receipts: usize,
+    #[serde(default)]
+    pub webhook_outbox: usize,
+}
+
+impl SnapshotCounts {
+    fn of(database: &Database) -> Self {
+        SnapshotCounts {
+            spend_deposits: database.spend.deposits.len(),
+            spend_withdraws: database.spend.withdraws.len(),
+            watched_executions: database.watched_executions.len(),
+            quarantined_deposits: database.quarantined_deposits.len(),
+            withdraw_attempts: database.withdraw_attempts.len(),
+            dead_withdraws: database.dead_withdraws.len(),
+            deposit_callbacks: database.deposit_callbacks.len(),
+            skipped_deposits: database.skipped_deposits.len(),
+            observed_days: database.observed_traffic.len(),
+            raw_logs: database.raw_logs.len(),
+            observed_withdraws: database.observed_withdraws.len(),
+            signing_latencies: database.signing_latencies.len(),
+            withdraw_origins: database.withdraw_origins.len(),
+            deposit_receipts: database.deposit_receipts.len(),
+            webhook_outbox: database.webhook_outbox.len(),
         }
     }
 }
 
+fn checksum(database: &Database) -> H256 {
+    let json = serde_json::to_string(database).expect("serialization can't fail; qed");
+    keccak256(json.as_bytes()).into()
+}
+
+impl Snapshot {
+    pub fn new(database: Database) -> Self {
+        Snapshot {
+            snapshot_version: SNAPSHOT_VERSION,
+            schema_version: SCHEMA_VERSION,
+            counts: SnapshotCounts::of(&database),
+            checksum: checksum(&database),
+            database,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialization can't fail; qed")
+    }
+
+    /// the database of a snapshot written by `to_json`, after checking its integrity.
+    pub fn database_from_json(json: &str) -> Result<Database, Error> {
+        let snapshot: Snapshot =
+            serde_json::from_str(json).chain_err(|| "Cannot parse snapshot")?;
+        if snapshot.snapshot_version != SNAPSHOT_VERSION {
+            bail!(
+                "Unsupported snapshot version {}, expected {}",
+                snapshot.snapshot_version,
+                SNAPSHOT_VERSION
+            );
+        }
+        if snapshot.schema_version != SCHEMA_VERSION {
+            bail!(
+                "Snapshot has database schema version {}, expected {}",
+                snapshot.schema_version,
+                SCHEMA_VERSION
+            );
+        }
+        let counts = SnapshotCounts::of(&snapshot.database);
+        if counts != snapshot.counts {
+            bail!(
+                "Snapshot is incomplete: it has {:?} records but lists {:?}",
+                counts,
+                snapshot.counts
+            );
+        }
+        if checksum(&snapshot.database) != snapshot.checksum {
+            bail!("Snapshot checksum mismatch: its database was modified or corrupted");
+        }
+        Ok(snapshot.database)
+    }
+}
+
+/// Cheaply cloneable handle to a `Database` shared by several components.
+///
+/// readers always get a consistent snapshot, also while it's being updated.
+#[derive(Debug, Default, Clone)]
+pub struct SharedDatabase {
+    inner: Arc<RwLock<Database>>,
+}
+
+impl SharedDatabase {
+    pub fn new(database: Database) -> Self {
+        SharedDatabase {
+            inner: Arc::new(RwLock::new(database)),
+        }
+    }
+
+    /// snapshot of the current state.
+    pub fn get(&self) -> Database {
+        self.inner
+            .read()
+            .expect("no code panics while holding the lock; qed")
+            .clone()
+    }
+
+    /// applies `update` while no other reader or writer has access.
+    /// returns a snapshot of the updated state.
+    pub fn update<F: FnOnce(&mut Database)>(&self, update: F) -> Database {
+        let mut database = self.inner
+            .write()
+            .expect("no code panics while holding the lock; qed");
+        update(&mut database);
+        database.clone()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Database;
+    use std::thread;
+    extern crate tempdir;
+    use std::fs;
+    use std::io::{Read, Write};
+    use self::tempdir::TempDir;
+    use web3::types::Log;
+    use error::ErrorKind;
+    use bridge::{Day, Direction, SkipReason, StepTimings};
+    use super::{Database, DeadWithdraw, DeepReorg, DepositCallback, DepositReceipt, ObservedDay,
+                ObservedEvent, ObservedWithdraw, OutboxEntry, QuarantinedDeposit, RawLog,
+                SharedDatabase, SigningLatency, SkippedDeposit, Snapshot, Spend, SpendLog,
+                WatchedExecution, WithdrawAttempt, WithdrawAttempts, WithdrawOrigin,
+                WithdrawPhase, OLDEST_SCHEMA_VERSION, SCHEMA_VERSION};
+
+    /// a database written by every schema version, oldest first
+    const FIXTURES: [&str; 9] = [
+        r#"home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
+foreign_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+home_deploy = 100
+foreign_deploy = 101
+checked_deposit_relay = 120
+checked_withdraw_relay = 121
+checked_withdraw_confirm = 121
+"#,
+        r#"schema_version = 1
+home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
+foreign_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+home_deploy = 100
+foreign_deploy = 101
+checked_deposit_relay = 120
+checked_withdraw_relay = 121
+checked_withdraw_confirm = 121
+"#,
+        r#"schema_version = 2
+home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
+foreign_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+home_deploy = 100
+foreign_deploy = 101
+checked_deposit_relay = 120
+checked_withdraw_relay = 121
+checked_withdraw_confirm = 121
+"#,
+        r#"schema_version = 3
+home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
+foreign_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+home_deploy = 100
+foreign_deploy = 101
+checked_deposit_relay = 120
+checked_withdraw_relay = 121
+checked_withdraw_confirm = 121
+"#,
+        r#"schema_version = 4
+home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
+foreign_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+home_deploy = 100
+foreign_deploy = 101
+checked_deposit_relay = 120
+checked_withdraw_relay = 121
+checked_withdraw_confirm = 121
+"#,
+        r#"schema_version = 5
+home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
+foreign_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+home_deploy = 100
+foreign_deploy = 101
+checked_deposit_relay = 120
+checked_withdraw_relay = 121
+checked_withdraw_confirm = 121
+"#,
+        r#"schema_version = 6
+home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
+foreign_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+home_deploy = 100
+foreign_deploy = 101
+checked_deposit_relay = 120
+checked_withdraw_relay = 121
+checked_withdraw_confirm = 121
+"#,
+        r#"schema_version = 7
+home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
+foreign_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+home_deploy = 100
+foreign_deploy = 101
+checked_deposit_relay = 120
+checked_withdraw_relay = 121
+checked_withdraw_confirm = 121
+"#,
+        r#"schema_version = 8
+home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
+foreign_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+home_deploy = 100
+foreign_deploy = 101
+checked_deposit_relay = 120
+checked_withdraw_relay = 121
+checked_withdraw_confirm = 121
+"#,
+    ];
+
+    fn fixture_database() -> Database {
+        Database {
+            home_contract_address: "49edf201c1e139282643d5e7c6fb0c7219ad1db7".into(),
+            foreign_contract_address: "49edf201c1e139282643d5e7c6fb0c7219ad1db8".into(),
+            home_deploy: 100,
+            foreign_deploy: 101,
+            checked_deposit_relay: 120,
+            checked_withdraw_relay: 121,
+            checked_withdraw_confirm: 121,
+            relayed_withdraws: Vec::new(),
+            max_reorg_depth: None,
+            admin_nonce: None,
+            spend: SpendLog::default(),
+            watched_executions: Vec::new(),
+            quarantined_deposits: Vec::new(),
+            withdraw_attempts: Vec::new(),
+            dead_withdraws: Vec::new(),
+            deposit_callbacks: Vec::new(),
+            skipped_deposits: Vec::new(),
+            observed_traffic: Vec::new(),
+            raw_logs: Vec::new(),
+            observed_withdraws: Vec::new(),
+            signing_latencies: Vec::new(),
+            withdraw_origins: Vec::new(),
+            deposit_receipts: Vec::new(),
+            webhook_outbox: Vec::new(),
+            deep_reorg: None,
+        }
+    }
 
     #[test]
     fn database_to_and_from_str() {
-        let toml = r#"home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
+        let toml = r#"schema_version = 8
+home_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db7"
 foreign_contract_address = "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
 home_deploy = 100
 foreign_deploy = 101
@@ -101,6 +934,24 @@ checked_withdraw_confirm = 121
             checked_deposit_relay: 120,
             checked_withdraw_relay: 121,
             checked_withdraw_confirm: 121,
+            relayed_withdraws: Vec::new(),
+            max_reorg_depth: None,
+            admin_nonce: None,
+            spend: SpendLog::default(),
+            watched_executions: Vec::new(),
+            quarantined_deposits: Vec::new(),
+            withdraw_attempts: Vec::new(),
+            dead_withdraws: Vec::new(),
+            deposit_callbacks: Vec::new(),
+            skipped_deposits: Vec::new(),
+            observed_traffic: Vec::new(),
+            raw_logs: Vec::new(),
+            observed_withdraws: Vec::new(),
+            signing_latencies: Vec::new(),
+            withdraw_origins: Vec::new(),
+            deposit_receipts: Vec::new(),
+            webhook_outbox: Vec::new(),
+            deep_reorg: None,
         };
 
         let database = toml.parse().unwrap();
@@ -108,4 +959,645 @@ checked_withdraw_confirm = 121
         let s = database.to_string();
         assert_eq!(s, toml);
     }
+
+    #[test]
+    fn database_with_spend_to_and_from_str() {
+        let database = Database {
+            spend: SpendLog {
+                deposits: vec![
+                    Spend {
+                        time: 1000,
+                        amount: 5.into(),
+                        block: 120,
+                    },
+                ],
+                withdraws: Vec::new(),
+            },
+            ..fixture_database()
+        };
+        let s = database.to_string();
+        assert!(s.starts_with(FIXTURES[FIXTURES.len() - 1]), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+    }
+
+    #[test]
+    fn database_with_watched_executions_to_and_from_str() {
+        let database = Database {
+            watched_executions: vec![
+                WatchedExecution {
+                    home_transaction: 1.into(),
+                    log_index: Some(2.into()),
+                    foreign_transaction: 3.into(),
+                    since_block: Some(130),
+                    block_number: Some(131),
+                    block_hash: Some(4.into()),
+                    timings: Some(StepTimings {
+                        check: Some(12),
+                        submit: Some(340),
+                        ..Default::default()
+                    }),
+                },
+                WatchedExecution {
+                    home_transaction: 5.into(),
+                    log_index: None,
+                    foreign_transaction: 6.into(),
+                    since_block: None,
+                    block_number: None,
+                    block_hash: None,
+                    timings: None,
+                },
+            ],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[watched_executions]]"), "{}", s);
+        assert!(s.contains("[watched_executions.timings]"), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let json = Snapshot::new(database.clone()).to_json();
+        assert_eq!(database, Snapshot::database_from_json(&json).unwrap());
+    }
+
+    #[test]
+    fn database_with_quarantined_deposits_to_and_from_str() {
+        let database = Database {
+            quarantined_deposits: vec![
+                QuarantinedDeposit {
+                    home_transaction: 1.into(),
+                    log_index: Some(2.into()),
+                    recipient: 3.into(),
+                    original_recipient: Some(5.into()),
+                    value: 4.into(),
+                    reason: "no fallback".into(),
+                    raw_log: Some(raw_log()),
+                },
+                QuarantinedDeposit {
+                    home_transaction: 6.into(),
+                    log_index: None,
+                    recipient: 3.into(),
+                    original_recipient: None,
+                    value: 4.into(),
+                    reason: "no fallback".into(),
+                    raw_log: None,
+                },
+            ],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[quarantined_deposits]]"), "{}", s);
+        assert!(s.contains("[quarantined_deposits.raw_log]"), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(2, snapshot.counts.quarantined_deposits);
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    #[test]
+    fn database_with_withdraw_attempts_to_and_from_str() {
+        let failed = WithdrawAttempt {
+            phase: WithdrawPhase::Finalize,
+            transaction: Some(2.into()),
+            error: Some("relay transaction reverted".into()),
+        };
+        let database = Database {
+            withdraw_attempts: vec![
+                WithdrawAttempts {
+                    withdraw: 1.into(),
+                    attempts: vec![
+                        WithdrawAttempt {
+                            phase: WithdrawPhase::Sign,
+                            transaction: None,
+                            error: None,
+                        },
+                        failed.clone(),
+                    ],
+                },
+            ],
+            dead_withdraws: vec![
+                DeadWithdraw {
+                    withdraw: 3.into(),
+                    phase: WithdrawPhase::Finalize,
+                    attempts: vec![failed.clone(), failed],
+                },
+            ],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[withdraw_attempts.attempts]]"), "{}", s);
+        assert!(s.contains("phase = \"sign\""), "{}", s);
+        assert!(s.contains("[[dead_withdraws]]"), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(1, snapshot.counts.dead_withdraws);
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    #[test]
+    fn database_with_deposit_callbacks_to_and_from_str() {
+        let database = Database {
+            deposit_callbacks: vec![
+                DepositCallback {
+                    home_transaction: 1.into(),
+                    log_index: Some(2.into()),
+                    relay_transaction: 3.into(),
+                    recipient: 4.into(),
+                    value: 5.into(),
+                    attempts: 1,
+                    transaction: Some(6.into()),
+                    error: None,
+                },
+                DepositCallback {
+                    home_transaction: 7.into(),
+                    log_index: None,
+                    relay_transaction: 8.into(),
+                    recipient: 4.into(),
+                    value: 5.into(),
+                    attempts: 3,
+                    transaction: None,
+                    error: Some("nonce too low".into()),
+                },
+            ],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[deposit_callbacks]]"), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(2, snapshot.counts.deposit_callbacks);
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    #[test]
+    fn database_with_skipped_deposits_to_and_from_str() {
+        let database = Database {
+            skipped_deposits: vec![
+                SkippedDeposit {
+                    home_transaction: 1.into(),
+                    log_index: Some(2.into()),
+                    block: Some(3),
+                    time: 1000,
+                    reason: SkipReason::SpendLimit,
+                    message: "spend limit reached".into(),
+                    parameters: vec![("limit".to_owned(), "100".to_owned())].into_iter().collect(),
+                },
+                SkippedDeposit {
+                    home_transaction: 4.into(),
+                    log_index: None,
+                    block: None,
+                    time: 1010,
+                    reason: SkipReason::ZeroValue,
+                    message: "zero value deposit".into(),
+                    parameters: Default::default(),
+                },
+            ],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[skipped_deposits]]"), "{}", s);
+        assert!(s.contains("reason = \"spend_limit\""), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(2, snapshot.counts.skipped_deposits);
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    #[test]
+    fn database_with_observed_traffic_to_and_from_str() {
+        let database = Database {
+            observed_traffic: vec![
+                ObservedDay {
+                    day: Day::parse("2024-01-01").unwrap(),
+                    direction: Direction::HomeToForeign,
+                    events: 2,
+                    value: 30.into(),
+                    counted: Vec::new(),
+                },
+                ObservedDay {
+                    day: Day::parse("2024-01-02").unwrap(),
+                    direction: Direction::ForeignToHome,
+                    events: 1,
+                    value: 5.into(),
+                    counted: vec![ObservedEvent {
+                        transaction: 1.into(),
+                        log_index: Some(0.into()),
+                        block: 20,
+                    }],
+                },
+            ],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[observed_traffic]]"), "{}", s);
+        assert!(s.contains("day = \"2024-01-02\""), "{}", s);
+        assert!(s.contains("direction = \"foreign_to_home\""), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(2, snapshot.counts.observed_days);
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    fn raw_log() -> RawLog {
+        RawLog {
+            address: 7.into(),
+            topics: vec![8.into(), 9.into()],
+            data: vec![0xde, 0xad, 0xbe, 0xef].into(),
+            block_hash: Some(10.into()),
+            block_number: Some(120),
+            transaction_hash: Some(1.into()),
+            log_index: Some(2.into()),
+        }
+    }
+
+    #[test]
+    fn database_with_raw_logs_to_and_from_str() {
+        let unmined = RawLog {
+            block_hash: None,
+            block_number: None,
+            log_index: None,
+            ..raw_log()
+        };
+        let database = Database {
+            raw_logs: vec![raw_log(), unmined],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[raw_logs]]"), "{}", s);
+        assert!(s.contains("data = \"0xdeadbeef\""), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(2, snapshot.counts.raw_logs);
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    #[test]
+    fn database_with_signing_latencies_to_and_from_str() {
+        let database = Database {
+            observed_withdraws: vec![
+                ObservedWithdraw {
+                    withdraw: 1.into(),
+                    observed: 1000,
+                },
+            ],
+            signing_latencies: vec![
+                SigningLatency {
+                    withdraw: 1.into(),
+                    authority: 2.into(),
+                    transaction: 3.into(),
+                    observed: 1000,
+                    signed: 1012,
+                },
+            ],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[observed_withdraws]]"), "{}", s);
+        assert!(s.contains("[[signing_latencies]]"), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        assert_eq!(12, database.signing_latencies[0].latency());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(1, snapshot.counts.observed_withdraws);
+        assert_eq!(1, snapshot.counts.signing_latencies);
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    #[test]
+    fn database_with_withdraw_origins_to_and_from_str() {
+        let database = Database {
+            withdraw_origins: vec![
+                WithdrawOrigin {
+                    transaction: 1.into(),
+                    block: 110,
+                    log_index: Some(0.into()),
+                    message_hash: Some(2.into()),
+                    fetched: false,
+                },
+                WithdrawOrigin {
+                    transaction: 3.into(),
+                    block: 111,
+                    log_index: None,
+                    message_hash: None,
+                    fetched: true,
+                },
+            ],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[withdraw_origins]]"), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(2, snapshot.counts.withdraw_origins);
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    #[test]
+    fn database_with_deposit_receipts_to_and_from_str() {
+        let database = Database {
+            deposit_receipts: vec![
+                DepositReceipt {
+                    home_transaction: 1.into(),
+                    relay_transaction: 2.into(),
+                    attempts: 1,
+                    transaction: Some(3.into()),
+                    already_recorded: false,
+                    error: None,
+                },
+                DepositReceipt {
+                    home_transaction: 4.into(),
+                    relay_transaction: 5.into(),
+                    attempts: 2,
+                    transaction: None,
+                    already_recorded: false,
+                    error: Some("nonce too low".into()),
+                },
+            ],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[deposit_receipts]]"), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(2, snapshot.counts.deposit_receipts);
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    #[test]
+    fn database_with_deep_reorg_to_and_from_str() {
+        let database = Database {
+            max_reorg_depth: Some(14),
+            admin_nonce: None,
+            deep_reorg: Some(DeepReorg {
+                fork_block: 110,
+                depth: 14,
+                alarm_depth: 12,
+                time: 1_700_000_000,
+            }),
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("\nmax_reorg_depth = 14\n"), "{}", s);
+        assert!(s.contains("[deep_reorg]"), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    #[test]
+    fn database_with_webhook_outbox_to_and_from_str() {
+        let database = Database {
+            webhook_outbox: vec![
+                OutboxEntry {
+                    sequence: 1,
+                    endpoint: "webhook 0".into(),
+                    key: "0x01/relayed/0x02".into(),
+                    ordering_key: "0x01".into(),
+                    payload: r#"{"payload_version":1,"status":"relayed"}"#.into(),
+                    created_at: 1_700_000_000,
+                    delivered_at: Some(1_700_000_001),
+                    batch: None,
+                },
+                OutboxEntry {
+                    sequence: 2,
+                    endpoint: "webhook 0".into(),
+                    key: "0x03/relayed/0x04".into(),
+                    ordering_key: "0x03".into(),
+                    payload: r#"{"payload_version":1,"status":"relayed"}"#.into(),
+                    created_at: 1_700_000_002,
+                    delivered_at: None,
+                    batch: Some("batch/0x05".into()),
+                },
+            ],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("[[webhook_outbox]]"), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+        let snapshot = Snapshot::new(database.clone());
+        assert_eq!(2, snapshot.counts.webhook_outbox);
+        assert_eq!(database, Snapshot::database_from_json(&snapshot.to_json()).unwrap());
+    }
+
+    #[test]
+    fn test_raw_log_of_log() {
+        let log = Log {
+            address: 7.into(),
+            topics: vec![8.into(), 9.into()],
+            data: vec![0xde, 0xad, 0xbe, 0xef].into(),
+            block_hash: Some(10.into()),
+            block_number: Some(120.into()),
+            transaction_hash: Some(1.into()),
+            log_index: Some(2.into()),
+            ..Log::default()
+        };
+        let raw = RawLog::from_log(&log);
+        assert_eq!(raw_log(), raw);
+        assert_eq!(2 * 32 + 4, raw.size());
+    }
+
+    #[test]
+    fn database_with_relayed_withdraws_to_and_from_str() {
+        let database = Database {
+            relayed_withdraws: vec![1.into(), 2.into()],
+            ..database_with_spend()
+        };
+        let s = database.to_string();
+        assert!(s.contains("relayed_withdraws = ["), "{}", s);
+        assert_eq!(database, s.parse().unwrap());
+    }
+
+    #[test]
+    fn shared_database_reads_are_consistent() {
+        let database = SharedDatabase::default();
+        let readers = (0..4)
+            .map(|_| {
+                let database = database.clone();
+                thread::spawn(move || {
+                    for _ in 0..10000 {
+                        let snapshot = database.get();
+                        let checked = snapshot.checked_deposit_relay;
+                        assert_eq!(checked, snapshot.checked_withdraw_relay);
+                        assert_eq!(checked, snapshot.checked_withdraw_confirm);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for n in 1..10001 {
+            database.update(|database| {
+                database.checked_deposit_relay = n;
+                database.checked_withdraw_relay = n;
+                database.checked_withdraw_confirm = n;
+            });
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(10000, database.get().checked_withdraw_confirm);
+    }
+
+    #[test]
+    fn test_migrate_fixture_of_every_version() {
+        assert_eq!(
+            (SCHEMA_VERSION - OLDEST_SCHEMA_VERSION + 1) as usize,
+            FIXTURES.len(),
+            "every schema version needs a fixture"
+        );
+        for (index, fixture) in FIXTURES.iter().enumerate() {
+            let version = OLDEST_SCHEMA_VERSION + index as u32;
+            let (found, database) = Database::migrate_str(fixture).unwrap();
+            assert_eq!(version, found);
+            assert_eq!(fixture_database(), database);
+            assert_eq!(FIXTURES[FIXTURES.len() - 1], database.to_string());
+        }
+    }
+
+    #[test]
+    fn test_migrate_keeps_watched_executions_without_timings() {
+        let v1 = format!(
+            "{}{}",
+            FIXTURES[1],
+            r#"
+[[watched_executions]]
+home_transaction = "0x0000000000000000000000000000000000000000000000000000000000000001"
+foreign_transaction = "0x0000000000000000000000000000000000000000000000000000000000000002"
+"#
+        );
+        assert!(v1.parse::<Database>().is_err());
+        let (found, database) = Database::migrate_str(&v1).unwrap();
+        assert_eq!(1, found);
+        assert_eq!(1, database.watched_executions.len());
+        assert_eq!(None, database.watched_executions[0].timings);
+    }
+
+    #[test]
+    fn test_incompatible_versions_are_refused() {
+        match *FIXTURES[0].parse::<Database>().unwrap_err().kind() {
+            ErrorKind::DatabaseMigrationRequired(0, SCHEMA_VERSION) => {}
+            ref kind => panic!("expected migration required, got {:?}", kind),
+        }
+
+        let newer = FIXTURES[FIXTURES.len() - 1].replace(
+            &format!("schema_version = {}", SCHEMA_VERSION),
+            &format!("schema_version = {}", SCHEMA_VERSION + 1),
+        );
+        let err = newer.parse::<Database>().unwrap_err();
+        match *err.kind() {
+            ErrorKind::UnsupportedDatabaseVersion(found, SCHEMA_VERSION) => {
+                assert_eq!(SCHEMA_VERSION + 1, found)
+            }
+            ref kind => panic!("expected unsupported version, got {:?}", kind),
+        }
+        let message = err.to_string();
+        assert!(message.contains(&format!("schema version {}", SCHEMA_VERSION + 1)));
+        assert!(message.contains(&format!("up to {}", SCHEMA_VERSION)));
+        assert!(Database::migrate_str(&newer).is_err());
+
+        assert!("schema_version = \"1\"".parse::<Database>().is_err());
+        assert!("schema_version = -1".parse::<Database>().is_err());
+    }
+
+    #[test]
+    fn test_migrate_file_keeps_backup() {
+        let tempdir = TempDir::new("test_migrate_file_keeps_backup").unwrap();
+        let path = tempdir.path().join("db.toml");
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(FIXTURES[0].as_bytes())
+            .unwrap();
+        assert!(Database::load(&path).is_err());
+
+        assert_eq!(Some(0), Database::migrate(&path).unwrap());
+        assert_eq!(fixture_database(), Database::load(&path).unwrap());
+        let mut backup = String::new();
+        fs::File::open(tempdir.path().join("db.toml.v0.bak"))
+            .unwrap()
+            .read_to_string(&mut backup)
+            .unwrap();
+        assert_eq!(FIXTURES[0], backup);
+
+        // already up to date
+        assert_eq!(None, Database::migrate(&path).unwrap());
+        assert!(!tempdir.path().join("db.toml.v1.bak").exists());
+    }
+
+    fn database_with_spend() -> Database {
+        Database {
+            spend: SpendLog {
+                deposits: vec![
+                    Spend {
+                        time: 1000,
+                        amount: 5.into(),
+                        block: 120,
+                    },
+                ],
+                withdraws: vec![
+                    Spend {
+                        time: 1001,
+                        amount: 1.into(),
+                        block: 121,
+                    },
+                ],
+            },
+            ..fixture_database()
+        }
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let tempdir = TempDir::new("test_snapshot_roundtrip").unwrap();
+        let database = database_with_spend();
+        let json = Snapshot::new(database.clone()).to_json();
+        assert_eq!(database, Snapshot::database_from_json(&json).unwrap());
+
+        let path = tempdir.path().join("db.toml");
+        assert_eq!(database, Database::import(&path, &json, false).unwrap());
+        assert_eq!(database, Database::load(&path).unwrap());
+        // exporting the imported database gives the same snapshot
+        assert_eq!(json, Snapshot::new(Database::load(&path).unwrap()).to_json());
+    }
+
+    #[test]
+    fn test_snapshot_integrity() {
+        let snapshot = Snapshot::new(database_with_spend());
+        assert!(Snapshot::database_from_json("{}").is_err());
+
+        let mut modified = snapshot.clone();
+        modified.database.checked_deposit_relay += 1;
+        let err = Snapshot::database_from_json(&modified.to_json()).unwrap_err();
+        assert!(err.to_string().contains("checksum"), "{}", err);
+
+        let mut truncated = snapshot.clone();
+        truncated.database.spend.withdraws.clear();
+        let err = Snapshot::database_from_json(&truncated.to_json()).unwrap_err();
+        assert!(err.to_string().contains("incomplete"), "{}", err);
+
+        let mut newer = snapshot.clone();
+        newer.snapshot_version += 1;
+        assert!(Snapshot::database_from_json(&newer.to_json()).is_err());
+
+        let mut older = snapshot.clone();
+        older.schema_version = OLDEST_SCHEMA_VERSION;
+        assert!(Snapshot::database_from_json(&older.to_json()).is_err());
+    }
+
+    #[test]
+    fn test_import_refuses_existing_database() {
+        let tempdir = TempDir::new("test_import_refuses_existing_database").unwrap();
+        let path = tempdir.path().join("db.toml");
+        fixture_database().save(fs::File::create(&path).unwrap()).unwrap();
+        let json = Snapshot::new(database_with_spend()).to_json();
+
+        assert!(Database::import(&path, &json, false).is_err());
+        assert_eq!(fixture_database(), Database::load(&path).unwrap());
+
+        Database::import(&path, &json, true).unwrap();
+        assert_eq!(database_with_spend(), Database::load(&path).unwrap());
+
+        // a corrupted snapshot never replaces the database
+        let corrupted = json.replace(
+            "\"checked_deposit_relay\": 120",
+            "\"checked_deposit_relay\": 1",
+        );
+        assert!(json != corrupted);
+        assert!(Database::import(&path, &corrupted, true).is_err());
+        assert_eq!(database_with_spend(), Database::load(&path).unwrap());
+    }
 }
@@ -1,9 +1,12 @@
 #![allow(unknown_lints)]
 
-use std::io;
-use api::ApiCall;
-use tokio_timer::{TimeoutError, TimerError};
-use {ethabi, rustc_hex, toml, web3};
+use std::{io, num};
+use std::path::PathBuf;
+use std::time::Duration;
+use message_to_mainnet::MESSAGE_LENGTH;
+use tokio_timer::TimerError;
+use web3::types::{Address, H256, Log, U256};
+use {ethabi, rpc, rustc_hex, toml, web3};
 
 error_chain! {
     types {
@@ -16,13 +19,22 @@ error_chain! {
         Ethabi(ethabi::Error);
         Timer(TimerError);
         Hex(rustc_hex::FromHexError);
+        ParseInt(num::ParseIntError);
     }
 
     errors {
-        // api timeout
-        Timeout(request: &'static str) {
-            description("Request timeout"),
-            display("Request {} timed out", request),
+        // request to the node of `chain` that wasn't done within `after`, see `api::Timeout`.
+        // `subject` is what it's about, e.g. the transaction of a receipt
+        Timeout(
+            operation: &'static str,
+            chain: &'static str,
+            subject: Option<String>,
+            after: Duration
+        ) {
+            description("request timed out"),
+            display("{} on {}{} timed out after {}s", operation, chain,
+                    subject.as_ref().map_or(String::new(), |subject| format!(" for {}", subject)),
+                    after.as_secs()),
         }
         // workaround for error_chain not allowing to check internal error kind
         // https://github.com/rust-lang-nursery/error-chain/issues/206
@@ -30,6 +42,142 @@ error_chain! {
             description("File not found"),
             display("File {} not found", filename),
         }
+        // database written by a newer version of the bridge
+        UnsupportedDatabaseVersion(found: u32, supported: u32) {
+            description("unsupported database schema version"),
+            display("database has schema version {}, this version of the bridge \
+                    supports schema versions up to {}", found, supported),
+        }
+        // database written by an older version of the bridge that must be migrated first
+        DatabaseMigrationRequired(found: u32, supported: u32) {
+            description("database must be migrated"),
+            display("database has schema version {}, this version of the bridge \
+                    requires schema version {}. run with --migrate to upgrade it", found, supported),
+        }
+        // deployment block of a contract that can't be looked up
+        DeployBlockNotFound(address: Address, reason: String) {
+            description("deployment block not found"),
+            display("cannot find the deployment block of contract {:?}: {}", address, reason),
+        }
+        // log returned by a node that can't be turned into the event it was filtered for
+        InvalidLog(log: Log) {
+            description("invalid log"),
+            display("invalid log: {:?}", log),
+        }
+        // result of a json-rpc call that doesn't decode, e.g. a quantity that isn't hex.
+        // `result` is the raw result, truncated
+        InvalidRpcResponse(method: &'static str, result: String, reason: String) {
+            description("invalid json-rpc response"),
+            display("invalid {} result {}: {}", method, result, reason),
+        }
+        // log data that isn't whole 32 byte ABI words, which the data of every bridge event is
+        MisalignedLogData(length: usize) {
+            description("log data is not ABI encoded"),
+            display("log data of {} bytes is not a multiple of 32 bytes", length),
+        }
+        // log which has not been mined yet. only mined logs are ever relayed
+        UnminedLog {
+            description("log must be mined and contain `transaction_hash` and `log_index`"),
+            display("log must be mined and contain `transaction_hash` and `log_index`"),
+        }
+        // encoded call data that doesn't decode back to the arguments it was encoded from
+        CallDataMismatch(function: String, details: String) {
+            description("call data doesn't match its arguments"),
+            display("call data of {} doesn't match its arguments: {}", function, details),
+        }
+        // withdraw message that can't be parsed into a `MessageToMainnet`
+        InvalidMessage(length: usize) {
+            description("invalid withdraw message"),
+            display("withdraw message must be {} bytes long, got {} bytes", MESSAGE_LENGTH, length),
+        }
+        // acknowledgement that can't be parsed into an `Acknowledgement`
+        InvalidAcknowledgement(reason: String) {
+            description("invalid acknowledgement"),
+            display("invalid acknowledgement: {}", reason),
+        }
+        // personal api is disabled or the password is wrong, `error` is the rejection of the
+        // node. the password is never part of the error
+        PersonalApi(method: &'static str, error: rpc::Error) {
+            description("personal api call failed"),
+            display("{} failed. make sure the node has the `personal` api enabled \
+                    and the password is correct", method),
+        }
+        // value that `value_scale` can only convert by rounding it down, which is rejected
+        LossyValue(value: U256) {
+            description("value conversion loses precision"),
+            display("converting value {} with `value_scale` loses precision", value),
+        }
+        // value that overflows when converted with `value_scale`
+        ValueOverflow(value: U256) {
+            description("value conversion overflows"),
+            display("converting value {} with `value_scale` overflows", value),
+        }
+        // relay transaction mined without emitting any of the events the relay must emit
+        MissingRelayEvent(transaction: H256, events: String) {
+            description("relay transaction didn't emit the expected event"),
+            display("relay transaction {:?} was mined without emitting {}", transaction, events),
+        }
+        // send of a transaction to `to` that failed without telling whether the node took it,
+        // which isn't in the pending transactions of the node either. see `api::NodeFailure`
+        AmbiguousSend(method: &'static str, to: Option<Address>) {
+            description("transaction may have been sent"),
+            display("{} to {:?} failed, but the node may have taken the transaction. it's not \
+                    pending, so it's either mined already or was never sent. not sending it again", method, to),
+        }
+        RelayReverted(transaction: H256, kind: &'static str, reason: String) {
+            description("relay transaction reverted"),
+            display("relay transaction {:?} reverted: {}", transaction, reason),
+        }
+        RecipientRejectsTransfer(recipient: Address, transaction: H256, reason: String) {
+            description("deposit recipient rejects the transfer"),
+            display("contract {:?} receiving the deposit in home transaction {:?} rejects the transfer: {}", recipient, transaction, reason),
+        }
+        // another instance holds the lock of the database
+        DatabaseLocked(database: PathBuf, holder: String) {
+            description("another instance is running with the database"),
+            display("another instance is running with database {:?}: {}. two instances \
+                    against one database interleave their writes and relay twice, stop it \
+                    first", database, holder),
+        }
+        // the lock of the database was left by an instance that didn't release it
+        StaleDatabaseLock(database: PathBuf, holder: String) {
+            description("stale lock of the database"),
+            display("database {:?} has a stale lock of {}, which didn't shut down cleanly. \
+                    if it crashed and doesn't run anymore, also not on another host sharing \
+                    the file system, start with --steal-lock", database, holder),
+        }
+        // factory of `derived_recipients` without code on foreign
+        MissingWalletFactory(factory: Address) {
+            description("wallet factory is not a contract"),
+            display("derived_recipients.factory {:?} has no code on foreign. deposits relayed \
+                    to its wallets couldn't be recovered", factory),
+        }
+        // home and foreign have the same genesis block and `allow_same_chain` isn't set
+        SameChain(genesis: H256) {
+            description("home and foreign are the same chain"),
+            display("home and foreign are the same chain (genesis block {:?}). relaying deposits \
+                    back into it loops. set allow_same_chain = true if this is intended", genesis),
+        }
+        // node whose head stayed too far below the highest head the log stream has seen
+        HeadRegression(highest: u64, head: u64, polls: u32) {
+            description("node head went backwards"),
+            display("node head {} stayed more than head_regression.tolerance below the highest \
+                    head {} for {} polls. the node may have reorged or be out of sync", head, highest, polls),
+        }
+        // address of a config option or a cli argument that doesn't parse
+        InvalidAddress(key: String, value: String, reason: String) {
+            description("invalid address"),
+            display("invalid {} {:?}: {}", key, value, reason),
+        }
+        // call data template of `deposit_callback.call` that doesn't parse
+        InvalidCallTemplate(template: String, reason: String) {
+            description("invalid call template"),
+            display("invalid call template {:?}: {}", template, reason),
+        }
+        NotAnAuthority(account: Address) {
+            description("account is not an authority of the contract"),
+            display("{:?} is not an authority of ForeignBridge", account),
+        }
         // workaround for lack of web3:Error Display and Error implementations
         Web3(err: web3::Error) {
             description("web3 error"),
@@ -37,13 +185,3 @@ error_chain! {
         }
     }
 }
-
-impl<T, F> From<TimeoutError<ApiCall<T, F>>> for Error {
-    fn from(err: TimeoutError<ApiCall<T, F>>) -> Self {
-        match err {
-            TimeoutError::Timer(call, _) | TimeoutError::TimedOut(call) => {
-                ErrorKind::Timeout(call.message()).into()
-            }
-        }
-    }
-}
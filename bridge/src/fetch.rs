@@ -0,0 +1,254 @@
+//! Fetching many independent requests with a bound on the requests in flight.
+//!
+//! requests complete in any order, but their results are returned in the order of the
+//! requests, so whatever is built from them doesn't depend on the timing of the nodes.
+
+use std::cmp;
+use std::mem;
+use futures::{Async, Future, Poll, Stream};
+use futures::stream::FuturesUnordered;
+use error::Error;
+
+/// Requests fetched by `fetch_bounded`, started by their index.
+pub trait Requests {
+    type Item;
+    type Future: Future<Item = Self::Item, Error = Error>;
+
+    /// number of requests
+    fn len(&self) -> usize;
+
+    /// starts request `index`. called again for every retry of the request.
+    fn start(&self, index: usize) -> Self::Future;
+}
+
+/// Request `index` in its `attempt`th retry, resolving to its outcome instead of failing.
+struct Attempt<F> {
+    index: usize,
+    attempt: u32,
+    future: F,
+}
+
+impl<F: Future> Future for Attempt<F> {
+    type Item = (usize, u32, Result<F::Item, F::Error>);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.future.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(item)) => Ok(item),
+            Err(err) => Err(err),
+        };
+        Ok(Async::Ready((self.index, self.attempt, result)))
+    }
+}
+
+/// fetches `requests` with at most `limit` of them in flight.
+/// a failed request is retried up to `retries` times before the fetch fails with its error.
+pub fn fetch_bounded<R: Requests>(requests: R, limit: usize, retries: u32) -> FetchBounded<R> {
+    let results = (0..requests.len()).map(|_| None).collect();
+    FetchBounded {
+        requests,
+        limit: cmp::max(limit, 1),
+        retries,
+        next: 0,
+        running: FuturesUnordered::new(),
+        results,
+    }
+}
+
+/// Future resolving to the results of all requests, in the order of the requests.
+pub struct FetchBounded<R: Requests> {
+    requests: R,
+    limit: usize,
+    retries: u32,
+    /// index of the next request to start
+    next: usize,
+    running: FuturesUnordered<Attempt<R::Future>>,
+    results: Vec<Option<R::Item>>,
+}
+
+impl<R: Requests> FetchBounded<R> {
+    fn start(&mut self, index: usize, attempt: u32) {
+        self.running.push(Attempt {
+            index,
+            attempt,
+            future: self.requests.start(index),
+        });
+    }
+}
+
+impl<R: Requests> Future for FetchBounded<R> {
+    type Item = Vec<R::Item>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            while self.running.len() < self.limit && self.next < self.results.len() {
+                let index = self.next;
+                self.next += 1;
+                self.start(index, 0);
+            }
+            match try_ready!(self.running.poll()) {
+                None => {
+                    let results = mem::replace(&mut self.results, Vec::new())
+                        .into_iter()
+                        .map(|result| result.expect("no request is left once none runs; qed"))
+                        .collect();
+                    return Ok(Async::Ready(results));
+                }
+                Some((index, _, Ok(item))) => self.results[index] = Some(item),
+                Some((index, attempt, Err(err))) => {
+                    if attempt >= self.retries {
+                        return Err(err);
+                    }
+                    warn!(
+                        target: "bridge::fetch",
+                        "request {} of {} failed, retrying ({} of {}): {}",
+                        index + 1,
+                        self.results.len(),
+                        attempt + 1,
+                        self.retries,
+                        err
+                    );
+                    self.start(index, attempt + 1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use futures::{task, Async, Future, Poll};
+    use futures::future::{self, FutureResult};
+    use error::Error;
+    use super::{fetch_bounded, Requests};
+
+    /// requests resolving to their index, each failing its first `failures[index]` attempts.
+    struct Failing {
+        failures: Vec<u32>,
+        attempts: RefCell<Vec<u32>>,
+    }
+
+    impl Failing {
+        fn new(failures: Vec<u32>) -> Self {
+            let attempts = RefCell::new(vec![0; failures.len()]);
+            Failing { failures, attempts }
+        }
+    }
+
+    impl<'a> Requests for &'a Failing {
+        type Item = usize;
+        type Future = FutureResult<usize, Error>;
+
+        fn len(&self) -> usize {
+            self.failures.len()
+        }
+
+        fn start(&self, index: usize) -> Self::Future {
+            let mut attempts = self.attempts.borrow_mut();
+            attempts[index] += 1;
+            if attempts[index] > self.failures[index] {
+                future::ok(index)
+            } else {
+                future::err(format!("request {} failed", index).into())
+            }
+        }
+    }
+
+    /// request resolving to its index once polled `polls` times, counting the requests in flight.
+    struct Slow {
+        index: usize,
+        polls: usize,
+        running: Rc<Cell<usize>>,
+    }
+
+    impl Future for Slow {
+        type Item = usize;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<usize, Error> {
+            if self.polls == 0 {
+                self.running.set(self.running.get() - 1);
+                return Ok(Async::Ready(self.index));
+            }
+            self.polls -= 1;
+            task::current().notify();
+            Ok(Async::NotReady)
+        }
+    }
+
+    struct SlowRequests {
+        polls: Vec<usize>,
+        running: Rc<Cell<usize>>,
+        max_running: Rc<Cell<usize>>,
+    }
+
+    impl Requests for SlowRequests {
+        type Item = usize;
+        type Future = Slow;
+
+        fn len(&self) -> usize {
+            self.polls.len()
+        }
+
+        fn start(&self, index: usize) -> Slow {
+            self.running.set(self.running.get() + 1);
+            if self.running.get() > self.max_running.get() {
+                self.max_running.set(self.running.get());
+            }
+            Slow {
+                index,
+                polls: self.polls[index],
+                running: self.running.clone(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_fetch_bounded_keeps_the_order_of_the_requests() {
+        let requests = SlowRequests {
+            polls: vec![5, 0, 3, 1, 4, 0, 2],
+            running: Default::default(),
+            max_running: Default::default(),
+        };
+        let max_running = requests.max_running.clone();
+        let results = fetch_bounded(requests, 3, 0).wait().unwrap();
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 6], results);
+        assert_eq!(3, max_running.get());
+    }
+
+    #[test]
+    fn test_fetch_bounded_runs_one_request_at_least() {
+        let requests = SlowRequests {
+            polls: vec![2, 1, 0],
+            running: Default::default(),
+            max_running: Default::default(),
+        };
+        let max_running = requests.max_running.clone();
+        assert_eq!(vec![0, 1, 2], fetch_bounded(requests, 0, 0).wait().unwrap());
+        assert_eq!(1, max_running.get());
+    }
+
+    #[test]
+    fn test_fetch_bounded_retries_failed_requests() {
+        let requests = Failing::new(vec![0, 2, 1]);
+        assert_eq!(vec![0, 1, 2], fetch_bounded(&requests, 2, 2).wait().unwrap());
+        assert_eq!(vec![1, 3, 2], *requests.attempts.borrow());
+    }
+
+    #[test]
+    fn test_fetch_bounded_fails_after_the_last_retry() {
+        let requests = Failing::new(vec![0, 3]);
+        assert!(fetch_bounded(&requests, 2, 2).wait().is_err());
+        assert_eq!(3, requests.attempts.borrow()[1]);
+    }
+
+    #[test]
+    fn test_fetch_bounded_without_requests() {
+        let requests = Failing::new(vec![]);
+        assert_eq!(Vec::<usize>::new(), fetch_bounded(&requests, 4, 0).wait().unwrap());
+    }
+}
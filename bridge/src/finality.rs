@@ -0,0 +1,82 @@
+//! Finalized block of a chain shared by the monitor fetching it and the log streams it limits.
+
+use std::sync::{Arc, RwLock};
+use api::confirmed_range;
+
+/// What is known about the finalized block of a chain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FinalizedBlock {
+    /// not fetched yet. no block is final until it is
+    Unknown,
+    /// blocks up to this one are finalized
+    At(u64),
+    /// the finality source doesn't answer. blocks with enough confirmations are final
+    Unavailable,
+}
+
+impl FinalizedBlock {
+    /// inclusive range of the blocks after the checked block `after` that are final:
+    /// confirmed by `confirmed_range` and at most the finalized block.
+    pub fn final_range(&self, after: u64, head: u64, confirmations: u64) -> Option<(u64, u64)> {
+        match *self {
+            FinalizedBlock::Unknown => None,
+            FinalizedBlock::At(block) => confirmed_range(after, head, confirmations, Some(block)),
+            FinalizedBlock::Unavailable => confirmed_range(after, head, confirmations, None),
+        }
+    }
+}
+
+/// Cheaply cloneable handle to the `FinalizedBlock` of a chain.
+///
+/// the finality monitor sets it once per poll interval and every log stream
+/// watching it reads it, so the finality source is queried once for all of them.
+#[derive(Debug, Clone)]
+pub struct SharedFinality {
+    inner: Arc<RwLock<FinalizedBlock>>,
+}
+
+impl Default for SharedFinality {
+    fn default() -> Self {
+        SharedFinality {
+            inner: Arc::new(RwLock::new(FinalizedBlock::Unknown)),
+        }
+    }
+}
+
+impl SharedFinality {
+    pub fn get(&self) -> FinalizedBlock {
+        *self.inner
+            .read()
+            .expect("no code panics while holding the lock; qed")
+    }
+
+    pub fn set(&self, block: FinalizedBlock) {
+        *self.inner
+            .write()
+            .expect("no code panics while holding the lock; qed") = block;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FinalizedBlock, SharedFinality};
+
+    #[test]
+    fn test_final_range() {
+        // head 100 with 12 confirmations confirms blocks up to 88
+        assert_eq!(None, FinalizedBlock::Unknown.final_range(10, 100, 12));
+        assert_eq!(Some((11, 50)), FinalizedBlock::At(50).final_range(10, 100, 12));
+        assert_eq!(Some((11, 88)), FinalizedBlock::At(95).final_range(10, 100, 12));
+        assert_eq!(None, FinalizedBlock::At(10).final_range(10, 100, 12));
+        assert_eq!(Some((11, 88)), FinalizedBlock::Unavailable.final_range(10, 100, 12));
+    }
+
+    #[test]
+    fn test_shared_finality() {
+        let finality = SharedFinality::default();
+        let watched = finality.clone();
+        assert_eq!(FinalizedBlock::Unknown, watched.get());
+        finality.set(FinalizedBlock::At(7));
+        assert_eq!(FinalizedBlock::At(7), watched.get());
+    }
+}
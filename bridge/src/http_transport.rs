@@ -0,0 +1,485 @@
+//! Json-rpc with a node over http or https, optionally through an http or socks5 proxy.
+//!
+//! a failed request tells which hop failed: the node, the proxy, or the node behind the proxy.
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use futures_cpupool::{CpuFuture, CpuPool};
+use reqwest::{self, StatusCode, Url};
+use reqwest::header::CONTENT_TYPE;
+use rpc;
+use serde_json;
+use web3::{self, RequestId, Transport};
+use web3::helpers::build_request;
+use config::{Node, ProxyConfig};
+use error::{Error, ResultExt};
+
+/// requests to a node in flight at once. further requests wait for one of them
+const MAX_PARALLEL_REQUESTS: usize = 8;
+
+/// time the proxy of a failed request may take to accept a connection before it's reported
+/// unreachable.
+const PROXY_PROBE_TIMEOUT_SECS: u64 = 5;
+
+/// `true` if `no_proxy` excludes `host` from the proxy: an entry matches the host and its
+/// subdomains, with or without a leading `.`. `*` matches every host.
+pub fn is_excluded(no_proxy: &[String], host: &str) -> bool {
+    let host = host.to_lowercase();
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim().to_lowercase();
+        let domain = entry.trim_left_matches('.');
+        entry == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+    })
+}
+
+/// Proxy the requests of a transport go through.
+#[derive(Debug, Clone)]
+struct Proxy {
+    /// without credentials
+    url: Url,
+    /// of the proxy itself, probed when a request fails
+    address: String,
+}
+
+/// Hop of a request through a proxy that failed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FailedHop {
+    /// no connection to the proxy
+    Proxy,
+    /// the proxy rejected the credentials of `proxy.credentials_file`, or asked for some
+    ProxyAuthentication,
+    /// the proxy was reached, but it couldn't connect to the node
+    Node,
+}
+
+/// hop that failed with `err` through a proxy, which accepted connections if `proxy_reachable`.
+pub fn failed_hop(err: &str, proxy_reachable: bool) -> FailedHop {
+    let err = err.to_lowercase();
+    // only a proxy that was reached asks for credentials
+    if err.contains("proxy authentication") || err.contains("authentication failed")
+        || err.contains("no acceptable auth")
+    {
+        FailedHop::ProxyAuthentication
+    } else if !proxy_reachable {
+        FailedHop::Proxy
+    } else {
+        FailedHop::Node
+    }
+}
+
+/// Json-rpc transport to the `rpc_url` of a node, through its `proxy` unless it excludes
+/// the host of `rpc_url`.
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    /// `home` or `foreign`
+    chain: &'static str,
+    url: Url,
+    /// `None` if requests go to `url` directly
+    proxy: Option<Proxy>,
+    client: reqwest::Client,
+    /// sends the requests off the event loop
+    pool: CpuPool,
+    id: Arc<AtomicUsize>,
+}
+
+impl HttpTransport {
+    /// transport to the `rpc_url` of `node` of `chain`.
+    pub fn new(chain: &'static str, node: &Node) -> Result<Self, Error> {
+        let url = node.rpc_url
+            .as_ref()
+            .ok_or_else(|| format!("{}.rpc_url is required for an http transport", chain))?;
+        let url = Url::parse(url).chain_err(|| format!("Invalid {}.rpc_url", chain))?;
+        let mut builder = reqwest::Client::builder().timeout(node.request_timeout);
+        let proxy = match node.proxy {
+            Some(ref config) => {
+                let excluded = url.host_str()
+                    .map_or(false, |host| is_excluded(&config.no_proxy, host));
+                if excluded {
+                    info!(
+                        target: "bridge",
+                        "{} rpc {} is excluded from the proxy by {}.proxy.no_proxy",
+                        chain,
+                        url,
+                        chain
+                    );
+                    None
+                } else {
+                    let (proxy, scheme) = proxy_of(chain, config)?;
+                    builder = builder.proxy(scheme);
+                    Some(proxy)
+                }
+            }
+            None => None,
+        };
+        Ok(HttpTransport {
+            chain,
+            url,
+            proxy,
+            client: builder
+                .build()
+                .chain_err(|| format!("Cannot create the http client of {}", chain))?,
+            pool: CpuPool::new(MAX_PARALLEL_REQUESTS),
+            id: Arc::new(AtomicUsize::new(1)),
+        })
+    }
+
+    /// sends `request` and waits for the response. called on the pool.
+    fn post(&self, request: &rpc::Call) -> Result<rpc::Value, web3::Error> {
+        let body = serde_json::to_string(request).expect("rpc::Call always serializes; qed");
+        let mut response = self.client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .map_err(|err| self.unreachable(&err.to_string()))?;
+        let status = response.status();
+        if status == StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+            if let Some(ref proxy) = self.proxy {
+                return Err(web3::Error::Transport(format!(
+                    "proxy {} rejected the credentials of {} rpc {}: {}",
+                    proxy.url, self.chain, self.url, status
+                )));
+            }
+        }
+        if !status.is_success() {
+            return Err(web3::Error::Transport(format!(
+                "{} rpc {} responded with status {}",
+                self.chain, self.url, status
+            )));
+        }
+        let output: rpc::Output = response.json().map_err(|err| {
+            web3::Error::Transport(format!(
+                "invalid response of {} rpc {}: {}",
+                self.chain, self.url, err
+            ))
+        })?;
+        match output {
+            rpc::Output::Success(success) => Ok(success.result),
+            rpc::Output::Failure(failure) => Err(web3::Error::Rpc(failure.error)),
+        }
+    }
+
+    /// error of a request that got no response with `err`, naming the hop that failed.
+    fn unreachable(&self, err: &str) -> web3::Error {
+        let message = match self.proxy {
+            None => format!("cannot reach {} rpc {}: {}", self.chain, self.url, err),
+            Some(ref proxy) => match failed_hop(err, is_reachable(&proxy.address)) {
+                FailedHop::Proxy => format!(
+                    "cannot reach proxy {} of {} rpc {}: {}",
+                    proxy.url, self.chain, self.url, err
+                ),
+                FailedHop::ProxyAuthentication => format!(
+                    "proxy {} rejected the credentials of {} rpc {}: {}",
+                    proxy.url, self.chain, self.url, err
+                ),
+                FailedHop::Node => format!(
+                    "proxy {} cannot reach {} rpc {}: {}",
+                    proxy.url, self.chain, self.url, err
+                ),
+            },
+        };
+        web3::Error::Transport(message)
+    }
+}
+
+/// `proxy` of `chain` and the scheme the client connects through.
+fn proxy_of(chain: &str, config: &ProxyConfig) -> Result<(Proxy, reqwest::Proxy), Error> {
+    let url = Url::parse(&config.url).chain_err(|| format!("Invalid {}.proxy.url", chain))?;
+    let port = url.port_or_known_default()
+        .or_else(|| if url.scheme().starts_with("socks5") { Some(1080) } else { None })
+        .ok_or_else(|| format!("{}.proxy.url has no port", chain))?;
+    let address = format!("{}:{}", url.host_str().unwrap_or_default(), port);
+    let mut scheme = reqwest::Proxy::all(url.clone())
+        .chain_err(|| format!("Invalid {}.proxy.url", chain))?;
+    if let Some(ref credentials) = config.credentials {
+        let mut parts = credentials.as_str().splitn(2, ':');
+        let user = parts.next().unwrap_or_default();
+        let password = parts.next().unwrap_or_default();
+        scheme = scheme.basic_auth(user, password);
+    }
+    Ok((Proxy { url, address }, scheme))
+}
+
+/// `true` if `address` accepts tcp connections.
+fn is_reachable(address: &str) -> bool {
+    let timeout = Duration::from_secs(PROXY_PROBE_TIMEOUT_SECS);
+    match address.to_socket_addrs() {
+        Ok(addresses) => addresses
+            .into_iter()
+            .any(|address| TcpStream::connect_timeout(&address, timeout).is_ok()),
+        Err(_) => false,
+    }
+}
+
+impl Transport for HttpTransport {
+    type Out = CpuFuture<rpc::Value, web3::Error>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.id.fetch_add(1, Ordering::SeqCst);
+        (id, build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let transport = self.clone();
+        self.pool.spawn_fn(move || transport.post(&request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use futures::Future;
+    use web3::{self, Transport};
+    use config::{Node, Password, ProxyConfig};
+    use super::{failed_hop, is_excluded, FailedHop, HttpTransport};
+
+    fn node(rpc_url: &str, proxy: Option<ProxyConfig>) -> Node {
+        Node {
+            rpc_url: Some(rpc_url.into()),
+            proxy,
+            request_timeout: Duration::from_secs(5),
+            ..Node::new(1.into(), "".into())
+        }
+    }
+
+    fn proxy(url: &str, credentials: Option<&str>, no_proxy: &[&str]) -> ProxyConfig {
+        ProxyConfig {
+            url: url.into(),
+            credentials_file: None,
+            credentials: credentials.map(Password::new),
+            no_proxy: no_proxy.iter().map(|host| host.to_string()).collect(),
+        }
+    }
+
+    /// address of a port nothing listens on
+    fn closed_port() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    /// answers the http request on `stream` with `response`, or a 407 if it's empty.
+    /// returns the head of the request
+    fn answer(stream: TcpStream, response: &str) -> String {
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.to_lowercase().starts_with("content-length:") {
+                length = line[15..].trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).unwrap();
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            if response.is_empty() { "407 Proxy Authentication Required" } else { "200 OK" },
+            response.len(),
+            response
+        ).unwrap();
+        head
+    }
+
+    /// answers one http request with `response` and sends its head on the returned channel
+    fn serve_once(response: &'static str) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            sender.send(answer(stream, response)).unwrap();
+        });
+        (address, receiver)
+    }
+
+    /// socks5 proxy taking the password `user:secret` that answers the first request it
+    /// tunnels itself with `response`. sends the target of the tunnel on the returned channel
+    fn serve_socks5(response: &'static str) -> (String, mpsc::Receiver<(String, u16)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 2];
+            stream.read_exact(&mut greeting).unwrap();
+            let mut methods = vec![0u8; greeting[1] as usize];
+            stream.read_exact(&mut methods).unwrap();
+            assert!(methods.contains(&2), "password authentication is offered");
+            stream.write_all(&[5, 2]).unwrap();
+
+            let mut read_field = |stream: &mut TcpStream| {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).unwrap();
+                let mut field = vec![0u8; len[0] as usize];
+                stream.read_exact(&mut field).unwrap();
+                String::from_utf8(field).unwrap()
+            };
+            let mut version = [0u8; 1];
+            stream.read_exact(&mut version).unwrap();
+            let user = read_field(&mut stream);
+            let password = read_field(&mut stream);
+            if (user.as_str(), password.as_str()) != ("user", "secret") {
+                stream.write_all(&[1, 1]).unwrap();
+                return;
+            }
+            stream.write_all(&[1, 0]).unwrap();
+
+            // a connect to a domain name or an ipv4 address
+            let mut request = [0u8; 4];
+            stream.read_exact(&mut request).unwrap();
+            let host = match request[3] {
+                3 => read_field(&mut stream),
+                _ => {
+                    let mut ip = [0u8; 4];
+                    stream.read_exact(&mut ip).unwrap();
+                    format!("{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3])
+                }
+            };
+            let mut port = [0u8; 2];
+            stream.read_exact(&mut port).unwrap();
+            stream.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+            sender
+                .send((host, u16::from(port[0]) << 8 | u16::from(port[1])))
+                .unwrap();
+            answer(stream, response);
+        });
+        (address, receiver)
+    }
+
+    fn request(transport: &HttpTransport) -> Result<::rpc::Value, web3::Error> {
+        transport.execute("eth_blockNumber", vec![]).wait()
+    }
+
+    fn transport_error(result: Result<::rpc::Value, web3::Error>) -> String {
+        match result {
+            Err(web3::Error::Transport(message)) => message,
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_no_proxy_matches_hosts_and_their_subdomains() {
+        let no_proxy = vec!["localhost".to_owned(), ".internal".to_owned(), "10.0.0.1".into()];
+        assert!(is_excluded(&no_proxy, "localhost"));
+        assert!(is_excluded(&no_proxy, "node.internal"));
+        assert!(is_excluded(&no_proxy, "internal"));
+        assert!(is_excluded(&no_proxy, "NODE.Internal"));
+        assert!(is_excluded(&no_proxy, "10.0.0.1"));
+        assert!(!is_excluded(&no_proxy, "rpc.example.com"));
+        assert!(!is_excluded(&no_proxy, "notinternal"));
+        assert!(is_excluded(&["*".to_owned()], "rpc.example.com"));
+        assert!(!is_excluded(&[], "localhost"));
+    }
+
+    #[test]
+    fn test_failed_hop() {
+        assert_eq!(FailedHop::Proxy, failed_hop("connection refused", false));
+        assert_eq!(
+            FailedHop::ProxyAuthentication,
+            failed_hop("proxy authentication required", true)
+        );
+        assert_eq!(
+            FailedHop::ProxyAuthentication,
+            failed_hop("password authentication failed", true)
+        );
+        assert_eq!(
+            FailedHop::ProxyAuthentication,
+            failed_hop("no acceptable auth methods", true)
+        );
+        assert_eq!(
+            FailedHop::ProxyAuthentication,
+            failed_hop("password authentication failed", false)
+        );
+        assert_eq!(FailedHop::Node, failed_hop("unsuccessful tunnel", true));
+        assert_eq!(FailedHop::Node, failed_hop("host unreachable", true));
+    }
+
+    #[test]
+    fn test_http_transport_posts_to_the_node() {
+        let (address, head) = serve_once(r#"{"jsonrpc":"2.0","result":"0x10","id":1}"#);
+        let transport = HttpTransport::new("home", &node(&format!("http://{}", address), None))
+            .unwrap();
+        assert_eq!(json!("0x10"), request(&transport).unwrap());
+        assert!(head.recv().unwrap().starts_with("POST / HTTP/1.1"));
+    }
+
+    #[test]
+    fn test_http_transport_errors_name_the_failed_hop() {
+        let node_url = format!("http://{}", closed_port());
+        let direct = HttpTransport::new("home", &node(&node_url, None)).unwrap();
+        let message = transport_error(request(&direct));
+        assert!(message.starts_with(&format!("cannot reach home rpc {}/: ", node_url)));
+
+        let proxy_url = format!("http://{}", closed_port());
+        let config = node(&node_url, Some(proxy(&proxy_url, None, &[])));
+        let proxied = HttpTransport::new("foreign", &config).unwrap();
+        let message = transport_error(request(&proxied));
+        assert!(message.starts_with(&format!("cannot reach proxy {}/ of foreign rpc", proxy_url)));
+    }
+
+    #[test]
+    fn test_http_transport_authenticates_to_the_proxy() {
+        let (address, head) = serve_once(r#"{"jsonrpc":"2.0","result":"0x10","id":1}"#);
+        let proxy = proxy(&format!("http://{}", address), Some("user:secret"), &[]);
+        let config = node("http://rpc.example.com:8545", Some(proxy));
+        let transport = HttpTransport::new("home", &config).unwrap();
+        assert_eq!(json!("0x10"), request(&transport).unwrap());
+        let head = head.recv().unwrap();
+        // an http proxy gets the absolute url of the node
+        assert!(head.starts_with("POST http://rpc.example.com:8545/ HTTP/1.1"));
+        assert!(head.contains("proxy-authorization: Basic dXNlcjpzZWNyZXQ="));
+    }
+
+    #[test]
+    fn test_http_transport_reports_rejected_proxy_credentials() {
+        let (address, _head) = serve_once("");
+        let proxy_url = format!("http://{}", address);
+        let proxy = proxy(&proxy_url, Some("user:wrong"), &[]);
+        let transport = HttpTransport::new("home", &node("http://rpc.example.com", Some(proxy)))
+            .unwrap();
+        let message = transport_error(request(&transport));
+        assert!(message.starts_with(&format!("proxy {}/ rejected the credentials", proxy_url)));
+    }
+
+    #[test]
+    fn test_http_transport_tunnels_through_a_socks5_proxy() {
+        let (address, target) = serve_socks5(r#"{"jsonrpc":"2.0","result":"0x10","id":1}"#);
+        let accepted = proxy(&format!("socks5://{}", address), Some("user:secret"), &[]);
+        let transport = HttpTransport::new("home", &node("http://10.0.0.1:8545", Some(accepted)))
+            .unwrap();
+        assert_eq!(json!("0x10"), request(&transport).unwrap());
+        assert_eq!(("10.0.0.1".to_owned(), 8545), target.recv().unwrap());
+
+        let (address, _target) = serve_socks5("");
+        let proxy_url = format!("socks5://{}", address);
+        let rejected = proxy(&proxy_url, Some("user:wrong"), &[]);
+        let transport = HttpTransport::new("home", &node("http://10.0.0.1:8545", Some(rejected)))
+            .unwrap();
+        let message = transport_error(request(&transport));
+        assert!(message.starts_with(&format!("proxy {}/ rejected the credentials", proxy_url)));
+    }
+
+    #[test]
+    fn test_http_transport_goes_direct_to_excluded_hosts() {
+        let (address, head) = serve_once(r#"{"jsonrpc":"2.0","result":"0x10","id":1}"#);
+        let proxy = proxy(&format!("http://{}", closed_port()), None, &["127.0.0.1"]);
+        let config = node(&format!("http://{}", address), Some(proxy));
+        let transport = HttpTransport::new("home", &config).unwrap();
+        assert_eq!(json!("0x10"), request(&transport).unwrap());
+        assert!(head.recv().unwrap().starts_with("POST / HTTP/1.1"));
+    }
+}
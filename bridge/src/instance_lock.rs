@@ -0,0 +1,237 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use libc;
+use serde_json;
+use capture::unix_time;
+use error::{ErrorKind, Result, ResultExt};
+
+/// lock file of the instance running with the database at `database`.
+pub fn lock_path(database: &Path) -> PathBuf {
+    let mut path = database.as_os_str().to_owned();
+    path.push(".lock");
+    path.into()
+}
+
+/// Instance holding the lock of a database, written to the lock file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub hostname: String,
+    /// unix time the instance took the lock at
+    pub started: u64,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        LockHolder {
+            pid: process::id(),
+            hostname: hostname(),
+            started: unix_time(),
+        }
+    }
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pid {} on host {} since unix time {}",
+            self.pid, self.hostname, self.started
+        )
+    }
+}
+
+/// describes the holder recorded in the contents of a lock file.
+/// `None` if the lock was released cleanly, which empties the file.
+fn recorded_holder(contents: &str) -> Option<String> {
+    if contents.trim().is_empty() {
+        return None;
+    }
+    Some(match serde_json::from_str::<LockHolder>(contents) {
+        Ok(holder) => holder.to_string(),
+        Err(_) => "an unknown instance".into(),
+    })
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // gethostname writes at most `buf.len()` bytes to `buf`
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if result != 0 {
+        return "unknown".into();
+    }
+    let len = buf.iter().position(|&byte| byte == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// takes an exclusive advisory lock on `file`. `false` if another open file holds it.
+fn try_flock(file: &File) -> io::Result<bool> {
+    // flock only reads the descriptor, which `file` keeps open
+    let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if result == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+/// Exclusive access of one instance to a database.
+///
+/// a `flock` on `<database>.lock` held as long as the instance runs. the kernel releases it
+/// when the process exits, also after a crash. the file records the holder while it's held
+/// and is emptied on a clean release, so a lock file that still names a holder although the
+/// lock is free was left by an instance that crashed, or runs on another host sharing the file
+/// system without `flock` support. such a lock is only taken with `steal`.
+#[derive(Debug)]
+pub struct InstanceLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl InstanceLock {
+    pub fn acquire(database: &Path, steal: bool) -> Result<Self> {
+        let path = lock_path(database);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .chain_err(|| format!("Cannot open lock file {:?}", path))?;
+        let mut contents = String::new();
+        let locked = try_flock(&file).chain_err(|| format!("Cannot lock {:?}", path))?;
+        file.read_to_string(&mut contents)
+            .chain_err(|| format!("Cannot read lock file {:?}", path))?;
+        let holder = recorded_holder(&contents);
+        if !locked {
+            let holder = holder.unwrap_or_else(|| "an unknown instance".into());
+            return Err(ErrorKind::DatabaseLocked(database.to_path_buf(), holder).into());
+        }
+        if let Some(holder) = holder {
+            if !steal {
+                return Err(ErrorKind::StaleDatabaseLock(database.to_path_buf(), holder).into());
+            }
+            warn!(
+                target: "bridge",
+                "stealing the lock of database {:?} from {}. if that instance still runs, \
+                 both relay the same deposits and withdraws",
+                database,
+                holder
+            );
+        }
+        let holder = LockHolder::current();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(
+            serde_json::to_string(&holder)
+                .expect("LockHolder always serializes; qed")
+                .as_bytes(),
+        )?;
+        file.sync_all()?;
+        info!(target: "bridge", "locked database {:?} for {}", database, holder);
+        Ok(InstanceLock { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InstanceLock {
+    /// empties the lock file to mark a clean release. closing `file` releases the lock.
+    fn drop(&mut self) {
+        if let Err(err) = self.file.set_len(0) {
+            warn!("cannot clear lock file {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::process;
+    use serde_json;
+    use self::tempdir::TempDir;
+    use error::ErrorKind;
+    use super::{lock_path, recorded_holder, InstanceLock, LockHolder};
+
+    fn read_lock_file(database: &Path) -> String {
+        let mut contents = String::new();
+        File::open(lock_path(database))
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_recorded_holder() {
+        assert_eq!(None, recorded_holder(""));
+        assert_eq!(None, recorded_holder("\n"));
+        let holder = r#"{"pid": 42, "hostname": "relay-1", "started": 1500000000}"#;
+        assert_eq!(
+            Some("pid 42 on host relay-1 since unix time 1500000000".to_owned()),
+            recorded_holder(holder)
+        );
+        assert_eq!(Some("an unknown instance".to_owned()), recorded_holder("garbage"));
+    }
+
+    #[test]
+    fn test_instance_lock_excludes_other_instances() {
+        let tempdir = TempDir::new("test_instance_lock_excludes_other_instances").unwrap();
+        let database = tempdir.path().join("db.toml");
+        let lock = InstanceLock::acquire(&database, false).unwrap();
+        assert_eq!(lock_path(&database), lock.path());
+        let holder: LockHolder = serde_json::from_str(&read_lock_file(&database)).unwrap();
+        assert_eq!(process::id(), holder.pid);
+
+        // flock is held per open file, so it excludes this process too. stealing doesn't help
+        match *InstanceLock::acquire(&database, true).unwrap_err().kind() {
+            ErrorKind::DatabaseLocked(ref path, ref holder) => {
+                assert_eq!(&database, path);
+                assert!(holder.starts_with(&format!("pid {} on host", process::id())));
+            }
+            ref other => panic!("unexpected error {:?}", other),
+        }
+
+        // released cleanly
+        drop(lock);
+        assert_eq!("", read_lock_file(&database));
+        InstanceLock::acquire(&database, false).unwrap();
+    }
+
+    #[test]
+    fn test_instance_lock_left_by_a_crash_is_only_stolen() {
+        let tempdir = TempDir::new("test_instance_lock_left_by_a_crash_is_only_stolen").unwrap();
+        let database = tempdir.path().join("db.toml");
+        let crashed = r#"{"pid": 42, "hostname": "relay-1", "started": 1500000000}"#;
+        File::create(lock_path(&database))
+            .unwrap()
+            .write_all(crashed.as_bytes())
+            .unwrap();
+
+        match *InstanceLock::acquire(&database, false).unwrap_err().kind() {
+            ErrorKind::StaleDatabaseLock(_, ref holder) => {
+                assert_eq!("pid 42 on host relay-1 since unix time 1500000000", holder);
+            }
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        // refusing to start keeps the record of the crashed instance
+        assert_eq!(crashed, read_lock_file(&database));
+
+        let _lock = InstanceLock::acquire(&database, true).unwrap();
+        let holder: LockHolder = serde_json::from_str(&read_lock_file(&database)).unwrap();
+        assert_eq!(process::id(), holder.pid);
+    }
+}
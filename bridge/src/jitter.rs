@@ -0,0 +1,248 @@
+//! Random delays that desynchronize authorities restarted at the same time.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tiny_keccak::keccak256;
+use web3::types::Address;
+use error::Result;
+
+fn duration_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000)
+}
+
+/// `duration` in whole seconds if possible, otherwise in milliseconds.
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration_millis(duration);
+    if millis % 1000 == 0 {
+        format!("{}s", millis / 1000)
+    } else {
+        format!("{}ms", millis)
+    }
+}
+
+/// splits `value` like `30s` into its number and its unit, which may be empty.
+fn split_unit(value: &str) -> (&str, &str) {
+    let digits = value
+        .find(|c: char| !c.is_digit(10))
+        .unwrap_or_else(|| value.len());
+    (&value[..digits], &value[digits..])
+}
+
+fn parse_millis(number: &str, unit: &str, range: &str) -> Result<u64> {
+    let factor = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        _ => bail!("invalid unit {:?} in jitter {:?}. expected ms, s, m or h", unit, range),
+    };
+    if number.is_empty() {
+        bail!("invalid jitter {:?}. expected e.g. 0..30s", range);
+    }
+    Ok(number.parse::<u64>()? * factor)
+}
+
+/// Inclusive range of random delays, e.g. `0..30s`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct JitterRange {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl JitterRange {
+    /// parses `min..max` with a unit of `ms`, `s`, `m` or `h` after `max`.
+    /// `min` without unit has the unit of `max`. `max` alone means `0..max`.
+    pub fn parse(range: &str) -> Result<Self> {
+        let (min, max) = match range.find("..") {
+            Some(index) => (&range[..index], &range[index + 2..]),
+            None => ("0", range),
+        };
+        let (max_number, max_unit) = split_unit(max.trim());
+        let (min_number, min_unit) = split_unit(min.trim());
+        let min_unit = if min_unit.is_empty() { max_unit } else { min_unit };
+        let min = parse_millis(min_number, min_unit, range)?;
+        let max = parse_millis(max_number, max_unit, range)?;
+        if min > max {
+            bail!("invalid jitter {:?}. the minimum is above the maximum", range);
+        }
+        Ok(JitterRange {
+            min: Duration::from_millis(min),
+            max: Duration::from_millis(max),
+        })
+    }
+}
+
+impl fmt::Display for JitterRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", format_duration(self.min), format_duration(self.max))
+    }
+}
+
+impl Serialize for JitterRange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for JitterRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        let range = String::deserialize(deserializer)?;
+        JitterRange::parse(&range).map_err(|err| ::serde::de::Error::custom(err.to_string()))
+    }
+}
+
+/// Source of random delays within `JitterRange`s.
+/// not suitable for anything but spreading out requests and injecting faults.
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    state: u64,
+}
+
+impl Jitter {
+    /// seeded from `account` and the current time, so authorities restarted
+    /// at the same time draw different delays.
+    pub fn new(account: &Address) -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        let hash = keccak256(&account.0);
+        let seed = hash[..8]
+            .iter()
+            .fold(0u64, |seed, byte| (seed << 8) | u64::from(*byte));
+        let mut jitter = Self::from_seed(
+            seed ^ duration_millis(since_epoch).rotate_left(20)
+                ^ u64::from(since_epoch.subsec_nanos()),
+        );
+        // mix the time into all bits of the state
+        jitter.next_u64();
+        jitter
+    }
+
+    pub fn from_seed(seed: u64) -> Self {
+        // xorshift gets stuck at zero
+        Jitter {
+            state: if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed },
+        }
+    }
+
+    /// xorshift64*
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// random delay within `range`, with millisecond resolution.
+    pub fn delay(&mut self, range: &JitterRange) -> Duration {
+        let min = duration_millis(range.min);
+        let spread = duration_millis(range.max) - min;
+        let offset = match spread.checked_add(1) {
+            Some(values) => self.next_u64() % values,
+            None => self.next_u64(),
+        };
+        Duration::from_millis(min + offset)
+    }
+
+    /// `true` with a probability of `percent` percent.
+    pub fn chance(&mut self, percent: u32) -> bool {
+        self.next_u64() % 100 < u64::from(percent)
+    }
+}
+
+/// `range` with a source seeded for `account`, as polled by `api::LogStream`.
+pub fn poll_jitter(range: Option<JitterRange>, account: &Address) -> Option<(JitterRange, Jitter)> {
+    range.map(|range| (range, Jitter::new(account)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{Jitter, JitterRange};
+
+    fn range(min: u64, max: u64) -> JitterRange {
+        JitterRange {
+            min: Duration::from_millis(min),
+            max: Duration::from_millis(max),
+        }
+    }
+
+    #[test]
+    fn test_parse_jitter_range() {
+        assert_eq!(range(0, 30_000), JitterRange::parse("0..30s").unwrap());
+        assert_eq!(range(0, 30_000), JitterRange::parse("30s").unwrap());
+        assert_eq!(range(5_000, 30_000), JitterRange::parse("5s..30s").unwrap());
+        assert_eq!(range(500, 2_000), JitterRange::parse("500ms..2s").unwrap());
+        assert_eq!(range(60_000, 120_000), JitterRange::parse("1..2m").unwrap());
+        assert_eq!(range(0, 3_600_000), JitterRange::parse("0 .. 1h").unwrap());
+        assert_eq!(range(0, 0), JitterRange::parse("0s").unwrap());
+
+        assert!(JitterRange::parse("30").is_err());
+        assert!(JitterRange::parse("0..30").is_err());
+        assert!(JitterRange::parse("0..30d").is_err());
+        assert!(JitterRange::parse("..30s").is_err());
+        assert!(JitterRange::parse("s").is_err());
+        assert!(JitterRange::parse("20s..10s").is_err());
+        assert!(JitterRange::parse("-1..10s").is_err());
+    }
+
+    #[test]
+    fn test_display_jitter_range() {
+        assert_eq!("0s..30s", JitterRange::parse("0..30s").unwrap().to_string());
+        assert_eq!("500ms..2s", JitterRange::parse("500ms..2s").unwrap().to_string());
+        let range = JitterRange::parse("1500ms..1m").unwrap();
+        assert_eq!(range, JitterRange::parse(&range.to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_jitter_delay_is_bounded() {
+        let ranges = [range(0, 30_000), range(1_000, 1_010), range(7, 7), range(0, 0)];
+        for seed in 0..50 {
+            let mut jitter = Jitter::from_seed(seed);
+            for range in &ranges {
+                for _ in 0..100 {
+                    let delay = jitter.delay(range);
+                    assert!(range.min <= delay && delay <= range.max, "{:?}", delay);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_jitter_delay_spreads() {
+        let range = range(0, 9);
+        let mut jitter = Jitter::from_seed(42);
+        let mut seen = [0u32; 10];
+        for _ in 0..1000 {
+            seen[super::duration_millis(jitter.delay(&range)) as usize] += 1;
+        }
+        // every delay is drawn, none much more often than the others
+        assert!(seen.iter().all(|count| *count > 50 && *count < 150), "{:?}", seen);
+
+        // authorities with different seeds draw different delays
+        let startup = JitterRange::parse("0..30s").unwrap();
+        let delays = (1..10)
+            .map(|seed| Jitter::from_seed(seed).delay(&startup))
+            .collect::<Vec<_>>();
+        let mut distinct = delays.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() > 1, "{:?}", delays);
+    }
+
+    #[test]
+    fn test_jitter_chance() {
+        let mut jitter = Jitter::from_seed(7);
+        assert!((0..100).all(|_| !jitter.chance(0)));
+        assert!((0..100).all(|_| jitter.chance(100)));
+        let hits = (0..1000).filter(|_| jitter.chance(20)).count();
+        assert!(hits > 150 && hits < 250, "{}", hits);
+        // the same seed draws the same
+        let draws = |seed| {
+            let mut jitter = Jitter::from_seed(seed);
+            (0..50).map(|_| jitter.chance(50)).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(3), draws(3));
+    }
+}
@@ -1,3 +1,8 @@
+#[cfg(feature = "amqp")]
+extern crate amiquip;
+extern crate base64;
+#[cfg(feature = "amqp")]
+extern crate crossbeam_channel;
 #[macro_use]
 extern crate error_chain;
 extern crate ethabi;
@@ -6,8 +11,17 @@ extern crate ethabi_contract;
 #[macro_use]
 extern crate ethabi_derive;
 extern crate ethereum_types;
+extern crate flate2;
 #[macro_use]
 extern crate futures;
+extern crate futures_cpupool;
+extern crate hmac;
+extern crate jsonrpc_core as rpc;
+#[cfg(any(feature = "rest-api", feature = "admin-api"))]
+extern crate jsonrpc_http_server;
+extern crate libc;
+#[cfg(feature = "jaeger")]
+extern crate opentelemetry_jaeger;
 #[macro_use]
 extern crate log;
 #[macro_use]
@@ -15,26 +29,69 @@ extern crate pretty_assertions;
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
+extern crate reqwest;
 extern crate rustc_hex;
+extern crate secp256k1;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
+extern crate sha2;
+extern crate tiny_keccak;
 extern crate tokio_core;
 extern crate tokio_timer;
 extern crate toml;
+#[macro_use(debug_span, info_span)]
+extern crate tracing;
+extern crate tracing_futures;
+extern crate tracing_log;
+#[cfg(feature = "jaeger")]
+extern crate tracing_opentelemetry;
+extern crate tracing_subscriber;
 extern crate web3;
 
 #[macro_use]
 mod macros;
 
+pub mod acknowledgement;
+pub mod address;
+pub mod alert;
+#[cfg(feature = "amqp")]
+pub mod amqp;
 pub mod api;
 pub mod app;
+pub mod capture;
 pub mod config;
 pub mod bridge;
+pub mod broker;
+pub mod build_info;
+pub mod call_template;
+#[cfg(feature = "chaos")]
+pub mod chaos;
 pub mod contracts;
 pub mod database;
 pub mod error;
+pub mod fetch;
+pub mod finality;
+pub mod http_transport;
+pub mod instance_lock;
+pub mod jitter;
+pub mod log_level;
+pub mod outbox;
 pub mod util;
 pub mod message_to_mainnet;
+pub mod quantity;
+pub mod remote_signer;
+pub mod rlp;
+pub mod runtime_abi;
 pub mod signature;
+pub mod simulation;
+#[cfg(test)]
+pub mod test_helpers;
+pub mod timer;
+pub mod transport;
+pub mod trie;
+pub mod tunables;
+pub mod units;
+pub mod webhook;
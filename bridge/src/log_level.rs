@@ -0,0 +1,596 @@
+use std::{env, fmt};
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use futures::{Async, Future, Poll};
+use log::{LogLevel, LogLevelFilter};
+use tokio_timer::TimerError;
+use tracing::{self, Level, Metadata, Subscriber};
+use tracing::subscriber::Interest;
+use tracing_log::LogTracer;
+use tracing_subscriber::{reload, Layer, Registry};
+use tracing_subscriber::layer::{Context, Layered, SubscriberExt};
+#[cfg(feature = "jaeger")]
+use opentelemetry_jaeger;
+#[cfg(feature = "jaeger")]
+use tracing_opentelemetry;
+use config::TracingConfig;
+use error::{Error, ResultExt};
+use timer::{Sleep, Timer};
+
+/// seconds an override lasts unless another duration is given
+pub const DEFAULT_OVERRIDE_SECS: u64 = 600;
+
+/// Level of the logs of a module and its submodules. all modules if `module` is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directive {
+    pub module: Option<String>,
+    pub level: LogLevelFilter,
+}
+
+impl Directive {
+    /// parses `level` or `module=level`.
+    pub fn parse(directive: &str) -> Option<Self> {
+        let mut parts = directive.trim().splitn(2, '=');
+        let first = parts.next()?;
+        match parts.next() {
+            Some(level) => Some(Directive {
+                module: Some(first.to_owned()),
+                level: level.parse().ok()?,
+            }),
+            None => match first.parse() {
+                Ok(level) => Some(Directive {
+                    module: None,
+                    level,
+                }),
+                // a module without level logs everything, like `env_logger`
+                Err(_) => Some(Directive {
+                    module: Some(first.to_owned()),
+                    level: LogLevelFilter::Trace,
+                }),
+            },
+        }
+    }
+
+    fn matches(&self, target: &str) -> bool {
+        self.module
+            .as_ref()
+            .map_or(true, |module| target.starts_with(module.as_str()))
+    }
+
+    fn specificity(&self) -> usize {
+        self.module.as_ref().map_or(0, |module| module.len())
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = self.level.to_string().to_lowercase();
+        match self.module {
+            Some(ref module) => write!(f, "{}={}", module, level),
+            None => f.write_str(&level),
+        }
+    }
+}
+
+/// parses a comma separated list of directives. invalid directives are skipped.
+/// an empty list logs errors only, like `env_logger`.
+pub fn parse_directives(filter: &str) -> Vec<Directive> {
+    let directives = filter
+        .split(',')
+        .filter(|directive| !directive.trim().is_empty())
+        .filter_map(Directive::parse)
+        .collect::<Vec<_>>();
+    if directives.is_empty() {
+        vec![
+            Directive {
+                module: None,
+                level: LogLevelFilter::Error,
+            },
+        ]
+    } else {
+        directives
+    }
+}
+
+/// A directive replacing the one of the same module until it expires.
+#[derive(Debug, Clone, PartialEq)]
+struct Override {
+    directive: Directive,
+    expires: Instant,
+}
+
+struct State {
+    /// directives from `RUST_LOG`
+    base: Vec<Directive>,
+    overrides: Vec<Override>,
+}
+
+impl State {
+    /// directives in effect at `now`
+    fn effective(&self, now: Instant) -> Vec<Directive> {
+        let mut directives = self.base.clone();
+        for o in self.overrides.iter().filter(|o| o.expires > now) {
+            directives.retain(|directive| directive.module != o.directive.module);
+            directives.push(o.directive.clone());
+        }
+        directives
+    }
+}
+
+/// level of the most specific of `directives` matching `target`.
+fn level_of(directives: &[Directive], target: &str) -> LogLevelFilter {
+    directives
+        .iter()
+        .filter(|directive| directive.matches(target))
+        .max_by_key(|directive| directive.specificity())
+        .map_or(LogLevelFilter::Off, |directive| directive.level)
+}
+
+/// the registry filtered by `LogLevels`. the exporter of the spans is added to it
+type Filtered = Layered<LevelsFilter, Registry>;
+
+/// exporter of the spans, `None` until `LogLevels::export_spans`
+type Exporter = Option<Box<Layer<Filtered> + Send + Sync>>;
+
+/// Handle to change the filter of the installed subscriber while the bridge runs.
+///
+/// the filter is read from `RUST_LOG` in the format of `env_logger`,
+/// e.g. `info,bridge::deposit_relay=debug`. it applies to the logs and the spans alike.
+/// `set_level` overrides the level of a module for a while. overrides revert by themselves
+/// once they expire on `timer`, so debug logging can't be left on forever.
+#[derive(Clone)]
+pub struct LogLevels {
+    state: Arc<RwLock<State>>,
+    timer: Timer,
+    /// `None` unless installed as the subscriber
+    exporter: Option<reload::Handle<Exporter, Filtered>>,
+}
+
+impl LogLevels {
+    /// levels filtering by `filter`, e.g. `info,bridge::deposit_relay=debug`. not installed.
+    pub fn new(filter: &str) -> Self {
+        LogLevels::with_timer(filter, Timer::default())
+    }
+
+    /// levels whose overrides expire on the clock of `timer`.
+    pub fn with_timer(filter: &str, timer: Timer) -> Self {
+        LogLevels {
+            state: Arc::new(RwLock::new(State {
+                base: parse_directives(filter),
+                overrides: Vec::new(),
+            })),
+            timer,
+            exporter: None,
+        }
+    }
+
+    /// replaces the filter the levels were created with, e.g. from a reloaded config.
+    /// overrides still apply until they expire.
+    pub fn set_filter(&self, filter: &str) {
+        let mut state = self.state.write().expect("log levels lock poisoned");
+        state.base = parse_directives(filter);
+    }
+
+    /// `true` if a log of `level` from `target` is written at `now`.
+    pub fn enabled_at(&self, target: &str, level: LogLevel, now: Instant) -> bool {
+        let state = self.state.read().expect("log levels lock poisoned");
+        level <= level_of(&state.effective(now), target)
+    }
+
+    /// `true` if a log of `level` from `target` is written now.
+    pub fn enabled(&self, target: &str, level: LogLevel) -> bool {
+        self.enabled_at(target, level, self.timer.now())
+    }
+
+    /// logs `module`, all modules if `None`, at `level` for `duration`.
+    /// replaces any previous override of `module`.
+    pub fn set_level(&self, level: LogLevelFilter, module: Option<&str>, duration: Duration) {
+        self.set_level_at(level, module, duration, self.timer.now())
+    }
+
+    pub fn set_level_at(
+        &self,
+        level: LogLevelFilter,
+        module: Option<&str>,
+        duration: Duration,
+        now: Instant,
+    ) {
+        let mut state = self.state.write().expect("log levels lock poisoned");
+        let directive = Directive {
+            module: module.map(str::to_owned),
+            level,
+        };
+        state.overrides.retain(|o| o.directive.module != directive.module && o.expires > now);
+        state.overrides.push(Override {
+            directive,
+            expires: now + duration,
+        });
+    }
+
+    /// drops the overrides expired at `now` and returns their directives. they no longer
+    /// apply anyway, this only keeps them from piling up.
+    pub fn revert_expired_at(&self, now: Instant) -> Vec<Directive> {
+        let mut state = self.state.write().expect("log levels lock poisoned");
+        let (expired, overrides) = state
+            .overrides
+            .drain(..)
+            .partition::<Vec<_>, _>(|o| o.expires <= now);
+        state.overrides = overrides;
+        expired.into_iter().map(|o| o.directive).collect()
+    }
+
+    /// reverts the overrides expired once `duration` passed, logging each of them.
+    /// later overrides of their modules stay.
+    pub fn revert_after(&self, duration: Duration) -> Revert {
+        Revert {
+            levels: self.clone(),
+            sleep: self.timer.sleep(duration),
+        }
+    }
+
+    /// the effective filter at `now` and the overrides in it.
+    pub fn status_at(&self, now: Instant) -> LevelsStatus {
+        let state = self.state.read().expect("log levels lock poisoned");
+        LevelsStatus {
+            filter: state
+                .effective(now)
+                .iter()
+                .map(|directive| directive.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            overrides: state
+                .overrides
+                .iter()
+                .filter(|o| o.expires > now)
+                .map(|o| OverrideStatus {
+                    directive: o.directive.to_string(),
+                    reverts_in: (o.expires - now).as_secs(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn status(&self) -> LevelsStatus {
+        self.status_at(self.timer.now())
+    }
+
+    /// the effective filter at `now`, e.g. `info,bridge::deposit_relay=trace`,
+    /// followed by the time left of every override.
+    pub fn describe_at(&self, now: Instant) -> String {
+        let status = self.status_at(now);
+        let mut description = status.filter;
+        for o in status.overrides {
+            description.push_str(&format!("\n{} reverts in {}s", o.directive, o.reverts_in));
+        }
+        description
+    }
+
+    pub fn describe(&self) -> String {
+        self.describe_at(self.timer.now())
+    }
+
+    /// sends the spans to the Jaeger agent of `config` from now on, see `TracingConfig`.
+    #[cfg(feature = "jaeger")]
+    pub fn export_spans(&self, config: &TracingConfig) -> Result<(), Error> {
+        let handle = match self.exporter {
+            Some(ref handle) => handle,
+            None => bail!("Cannot export spans: the subscriber isn't installed"),
+        };
+        let tracer = opentelemetry_jaeger::new_pipeline()
+            .with_agent_endpoint(config.jaeger_agent)
+            .with_service_name(config.service_name.clone())
+            .install_simple()
+            .chain_err(|| "Cannot create the Jaeger exporter")?;
+        let exporter: Box<Layer<Filtered> + Send + Sync> =
+            Box::new(tracing_opentelemetry::layer().with_tracer(tracer));
+        handle
+            .reload(Some(exporter))
+            .chain_err(|| "Cannot export spans")
+    }
+
+    #[cfg(not(feature = "jaeger"))]
+    pub fn export_spans(&self, _config: &TracingConfig) -> Result<(), Error> {
+        bail!("tracing.jaeger_agent needs a bridge built with the `jaeger` feature")
+    }
+}
+
+/// Effective filter of `LogLevels`, as returned by `get-log-level` of the admin api.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LevelsStatus {
+    /// e.g. `info,bridge::deposit_relay=trace`
+    pub filter: String,
+    pub overrides: Vec<OverrideStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OverrideStatus {
+    /// e.g. `bridge::deposit_relay=trace`
+    pub directive: String,
+    /// seconds until the filter of the module applies again
+    pub reverts_in: u64,
+}
+
+/// Future of `LogLevels::revert_after`.
+pub struct Revert {
+    levels: LogLevels,
+    sleep: Sleep,
+}
+
+impl Future for Revert {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        try_ready!(self.sleep.poll());
+        for directive in self.levels.revert_expired_at(self.levels.timer.now()) {
+            info!(
+                target: "bridge",
+                "log level {} reverted, the filter is {}",
+                directive,
+                self.levels.status().filter
+            );
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+/// level of the `log` crate of `level`
+fn log_level(level: &Level) -> LogLevel {
+    match *level {
+        Level::ERROR => LogLevel::Error,
+        Level::WARN => LogLevel::Warn,
+        Level::INFO => LogLevel::Info,
+        Level::DEBUG => LogLevel::Debug,
+        _ => LogLevel::Trace,
+    }
+}
+
+/// Filters the spans and events of the subscriber by `LogLevels`.
+struct LevelsFilter {
+    state: Arc<RwLock<State>>,
+    timer: Timer,
+}
+
+impl<S: Subscriber> Layer<S> for LevelsFilter {
+    fn register_callsite(&self, _metadata: &'static Metadata<'static>) -> Interest {
+        // levels change while the bridge runs
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata, _context: Context<S>) -> bool {
+        let state = self.state.read().expect("log levels lock poisoned");
+        let now = self.timer.now();
+        log_level(metadata.level()) <= level_of(&state.effective(now), metadata.target())
+    }
+}
+
+/// installs a compact subscriber writing to stderr and filtering by `RUST_LOG`.
+/// the logs of the `log` crate go to it as events. replaces `env_logger::init`.
+/// returns the handle to change its levels.
+pub fn init() -> Result<LogLevels, Error> {
+    let levels = LogLevels::new(&env::var("RUST_LOG").unwrap_or_default());
+    let (exporter, handle) = reload::Layer::<Exporter, Filtered>::new(None);
+    let subscriber = Registry::default()
+        .with(LevelsFilter {
+            state: levels.state.clone(),
+            timer: levels.timer.clone(),
+        })
+        .with(exporter)
+        .with(tracing_subscriber::fmt::layer().compact().with_writer(io::stderr));
+    tracing::subscriber::set_global_default(subscriber)
+        .chain_err(|| "Cannot install the subscriber")?;
+    LogTracer::init().chain_err(|| "Cannot install the logger")?;
+    Ok(LogLevels {
+        exporter: Some(handle),
+        ..levels
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use log::{LogLevel, LogLevelFilter};
+    use tracing::{self, Subscriber};
+    use tracing::span::{Attributes, Id};
+    use tracing_subscriber::{Layer, Registry};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use futures::{executor, Async};
+    use futures::executor::Notify;
+    use timer::Timer;
+    use super::{parse_directives, Directive, LevelsFilter, LevelsStatus, LogLevels, OverrideStatus};
+
+    #[test]
+    fn test_parse_directives() {
+        assert_eq!(
+            vec![
+                Directive {
+                    module: None,
+                    level: LogLevelFilter::Info,
+                },
+                Directive {
+                    module: Some("bridge::deposit_relay".into()),
+                    level: LogLevelFilter::Trace,
+                },
+                Directive {
+                    module: Some("bridge::api".into()),
+                    level: LogLevelFilter::Trace,
+                },
+            ],
+            parse_directives("info,bridge::deposit_relay=TRACE,bridge::api,bridge=loud")
+        );
+        assert_eq!(
+            vec![
+                Directive {
+                    module: None,
+                    level: LogLevelFilter::Error,
+                },
+            ],
+            parse_directives("")
+        );
+    }
+
+    #[test]
+    fn test_most_specific_directive_applies() {
+        let levels = LogLevels::new("warn,bridge=info,bridge::deposit_relay=debug");
+        let now = Instant::now();
+        assert!(levels.enabled_at("bridge::deposit_relay", LogLevel::Debug, now));
+        assert!(!levels.enabled_at("bridge::withdraw_relay", LogLevel::Debug, now));
+        assert!(levels.enabled_at("bridge::withdraw_relay", LogLevel::Info, now));
+        assert!(!levels.enabled_at("tokio_core", LogLevel::Info, now));
+        assert!(levels.enabled_at("tokio_core", LogLevel::Warn, now));
+    }
+
+    #[test]
+    fn test_override_reverts_after_duration() {
+        let levels = LogLevels::new("info");
+        let now = Instant::now();
+        levels.set_level_at(
+            LogLevelFilter::Trace,
+            Some("bridge::withdraw_confirm"),
+            Duration::from_secs(60),
+            now,
+        );
+        assert!(levels.enabled_at("bridge::withdraw_confirm", LogLevel::Trace, now));
+        assert!(!levels.enabled_at("bridge::withdraw_relay", LogLevel::Debug, now));
+        assert_eq!(
+            "info,bridge::withdraw_confirm=trace\nbridge::withdraw_confirm=trace reverts in 60s",
+            levels.describe_at(now)
+        );
+
+        let later = now + Duration::from_secs(59);
+        assert!(levels.enabled_at("bridge::withdraw_confirm", LogLevel::Trace, later));
+
+        // expired before it is dropped
+        let expired = now + Duration::from_secs(60);
+        assert!(!levels.enabled_at("bridge::withdraw_confirm", LogLevel::Debug, expired));
+        assert_eq!("info", levels.describe_at(expired));
+        levels.revert_expired_at(expired);
+        assert_eq!("info", levels.describe_at(now));
+    }
+
+    #[test]
+    fn test_set_filter_keeps_overrides() {
+        let levels = LogLevels::new("info");
+        let now = Instant::now();
+        levels.set_level_at(LogLevelFilter::Trace, Some("bridge::api"), Duration::from_secs(60), now);
+        levels.set_filter("warn,bridge::deposit_relay=debug");
+        assert!(!levels.enabled_at("bridge::withdraw_relay", LogLevel::Info, now));
+        assert!(levels.enabled_at("bridge::deposit_relay", LogLevel::Debug, now));
+        assert!(levels.enabled_at("bridge::api", LogLevel::Trace, now));
+    }
+
+    #[test]
+    fn test_override_replaces_previous_override_of_module() {
+        let levels = LogLevels::new("info,bridge=warn");
+        let now = Instant::now();
+        levels.set_level_at(LogLevelFilter::Trace, Some("bridge"), Duration::from_secs(60), now);
+        levels.set_level_at(LogLevelFilter::Debug, Some("bridge"), Duration::from_secs(10), now);
+        levels.set_level_at(LogLevelFilter::Error, None, Duration::from_secs(30), now);
+        assert!(levels.enabled_at("bridge::api", LogLevel::Debug, now));
+        assert!(!levels.enabled_at("bridge::api", LogLevel::Trace, now));
+        assert!(!levels.enabled_at("tokio_core", LogLevel::Info, now));
+
+        // the base directive of the module applies again once the override expires
+        let later = now + Duration::from_secs(20);
+        assert!(!levels.enabled_at("bridge::api", LogLevel::Info, later));
+        assert!(!levels.enabled_at("tokio_core", LogLevel::Info, later));
+        let later = now + Duration::from_secs(30);
+        assert!(levels.enabled_at("tokio_core", LogLevel::Info, later));
+    }
+
+    struct Noop;
+
+    impl Notify for Noop {
+        fn notify(&self, _id: usize) {}
+    }
+
+    #[test]
+    fn test_revert_timer_drops_the_override_once_it_expires() {
+        let (timer, clock) = Timer::manual();
+        let levels = LogLevels::with_timer("info", timer);
+        let duration = Duration::from_secs(300);
+        levels.set_level(LogLevelFilter::Debug, Some("bridge::deposit_relay"), duration);
+        let notify = Arc::new(Noop);
+        let mut revert = executor::spawn(levels.revert_after(duration));
+
+        assert!(levels.enabled("bridge::deposit_relay", LogLevel::Debug));
+        assert_eq!(Async::NotReady, revert.poll_future_notify(&notify, 0).unwrap());
+        clock.advance(Duration::from_secs(299));
+        assert_eq!(Async::NotReady, revert.poll_future_notify(&notify, 0).unwrap());
+        assert_eq!(
+            LevelsStatus {
+                filter: "info,bridge::deposit_relay=debug".into(),
+                overrides: vec![
+                    OverrideStatus {
+                        directive: "bridge::deposit_relay=debug".into(),
+                        reverts_in: 1,
+                    },
+                ],
+            },
+            levels.status()
+        );
+
+        clock.advance(Duration::from_secs(1));
+        assert!(!levels.enabled("bridge::deposit_relay", LogLevel::Debug));
+        assert_eq!(Async::Ready(()), revert.poll_future_notify(&notify, 0).unwrap());
+        assert_eq!(
+            LevelsStatus {
+                filter: "info".into(),
+                overrides: Vec::new(),
+            },
+            levels.status()
+        );
+    }
+
+    #[test]
+    fn test_revert_timer_keeps_a_later_override_of_the_module() {
+        let (timer, clock) = Timer::manual();
+        let levels = LogLevels::with_timer("info", timer);
+        let notify = Arc::new(Noop);
+        levels.set_level(LogLevelFilter::Debug, Some("bridge"), Duration::from_secs(60));
+        let mut first = executor::spawn(levels.revert_after(Duration::from_secs(60)));
+        clock.advance(Duration::from_secs(30));
+        levels.set_level(LogLevelFilter::Trace, Some("bridge"), Duration::from_secs(60));
+        let mut second = executor::spawn(levels.revert_after(Duration::from_secs(60)));
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(Async::Ready(()), first.poll_future_notify(&notify, 0).unwrap());
+        assert!(levels.enabled("bridge::api", LogLevel::Trace));
+        assert_eq!("info,bridge=trace\nbridge=trace reverts in 30s", levels.describe());
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(Async::Ready(()), second.poll_future_notify(&notify, 0).unwrap());
+        assert!(!levels.enabled("bridge::api", LogLevel::Debug));
+        assert_eq!("info", levels.describe());
+    }
+
+    /// Names of the spans it sees.
+    struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+
+    impl<S: Subscriber> Layer<S> for SpanNames {
+        fn on_new_span(&self, attributes: &Attributes, _id: &Id, _context: Context<S>) {
+            self.0.lock().unwrap().push(attributes.metadata().name());
+        }
+    }
+
+    #[test]
+    fn test_spans_are_filtered_by_levels() {
+        let levels = LogLevels::new("info,bridge::rpc=warn");
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = Registry::default()
+            .with(LevelsFilter {
+                state: levels.state.clone(),
+                timer: levels.timer.clone(),
+            })
+            .with(SpanNames(names.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let _relay = info_span!(target: "bridge::deposit_relay", "relay");
+            let _rpc = debug_span!(target: "bridge::rpc", "rpc");
+            levels.set_level(LogLevelFilter::Debug, Some("bridge::rpc"), Duration::from_secs(60));
+            let _rpc = debug_span!(target: "bridge::rpc", "rpc_call");
+        });
+        assert_eq!(vec!["relay", "rpc_call"], *names.lock().unwrap());
+    }
+}
@@ -2,15 +2,18 @@ use ethereum_types::{Address, H256, U256};
 use contracts::foreign::events::Withdraw;
 use web3::types::Log;
 use ethabi;
-use error::Error;
+use tiny_keccak::keccak256;
+use error::{Error, ErrorKind, ResultExt};
+use util::{check_log_data, log_id, raw_log};
 
 /// the message that is relayed from side to main.
 /// contains all the information required for the relay.
 /// validators sign off on this message.
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct MessageToMainnet {
     pub recipient: Address,
     pub value: U256,
+    /// id of the withdraw. the transaction hash unless unique log ids are used
     pub sidenet_transaction_hash: H256,
     pub mainnet_gas_price: U256,
 }
@@ -19,32 +22,36 @@ pub struct MessageToMainnet {
 pub const MESSAGE_LENGTH: usize = 116;
 
 impl MessageToMainnet {
-    /// parses message from a byte slice
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        assert_eq!(bytes.len(), MESSAGE_LENGTH);
+    /// parses message from a byte slice.
+    /// fails with `ErrorKind::InvalidMessage` unless `bytes` are exactly `MESSAGE_LENGTH` long.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != MESSAGE_LENGTH {
+            return Err(ErrorKind::InvalidMessage(bytes.len()).into());
+        }
 
-        Self {
+        Ok(Self {
             recipient: bytes[0..20].into(),
             value: U256::from_big_endian(&bytes[20..52]),
             sidenet_transaction_hash: bytes[52..84].into(),
             mainnet_gas_price: U256::from_big_endian(&bytes[84..MESSAGE_LENGTH]),
-        }
+        })
     }
 
     /// construct a message from a `Withdraw` event that was logged on `foreign`
-    pub fn from_log(web3_log: Log) -> Result<Self, Error> {
-        let ethabi_raw_log = ethabi::RawLog {
-            topics: web3_log.topics,
-            data: web3_log.data.0,
-        };
-        let withdraw_log = Withdraw::default().parse_log(ethabi_raw_log)?;
-        let hash = web3_log
-            .transaction_hash
-            .ok_or_else(|| "`log` must be mined and contain `transaction_hash`")?;
+    /// fails with `ErrorKind::InvalidLog` if `web3_log` is not a mined `Withdraw` event.
+    /// see `util::log_id` for `unique_ids`
+    pub fn from_log(web3_log: Log, unique_ids: bool) -> Result<Self, Error> {
+        Self::parse_log(&web3_log, unique_ids).chain_err(|| ErrorKind::InvalidLog(web3_log))
+    }
+
+    fn parse_log(web3_log: &Log, unique_ids: bool) -> Result<Self, Error> {
+        check_log_data(web3_log)?;
+        let withdraw_log = Withdraw::default().parse_log(raw_log(web3_log))?;
+        let id = log_id(web3_log, unique_ids)?;
         Ok(Self {
             recipient: withdraw_log.recipient,
             value: withdraw_log.value,
-            sidenet_transaction_hash: hash,
+            sidenet_transaction_hash: id,
             mainnet_gas_price: withdraw_log.home_gas_price,
         })
     }
@@ -62,6 +69,12 @@ impl MessageToMainnet {
         return result;
     }
 
+    /// hash `ForeignBridge` keeps the message and its signatures by,
+    /// e.g. `messageHash` of `CollectedSignatures`
+    pub fn hash(&self) -> H256 {
+        keccak256(&self.to_bytes()).into()
+    }
+
     /// serializes message to an ethabi payload
     pub fn to_payload(&self) -> Vec<u8> {
         ethabi::encode(&[ethabi::Token::Bytes(self.to_bytes())])
@@ -73,6 +86,7 @@ mod test {
     use quickcheck::TestResult;
     use super::*;
     use rustc_hex::FromHex;
+    use test_helpers::{deposit_topic, WithdrawLogBuilder, TRANSACTION_HASH};
 
     #[test]
     fn test_message_to_mainnet_to_bytes() {
@@ -92,6 +106,54 @@ mod test {
         assert_eq!(message.to_bytes(), "eac4a655451e159313c3641e29824e77d6fcb0ce000000000000000000000000000000000000000000000000000d80147225800075ebc3036b5a5a758be9a8c0e6f6ed8d46c640dda39845de99d9570ba76798e200000000000000000000000000000000000000000000000000000001dcd65000".from_hex().unwrap())
     }
 
+    #[test]
+    fn test_message_to_mainnet_from_log_with_wrong_topic() {
+        let log = WithdrawLogBuilder::new().topic(deposit_topic()).build();
+
+        match *MessageToMainnet::from_log(log.clone(), false).unwrap_err().kind() {
+            ErrorKind::InvalidLog(ref invalid) => assert_eq!(&log, invalid),
+            ref kind => panic!("expected invalid log error, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_message_to_mainnet_from_log_with_misaligned_data() {
+        // a node returned the data of the event without its last byte
+        let log = WithdrawLogBuilder::new().truncated(95).build();
+
+        let err = MessageToMainnet::from_log(log.clone(), false).unwrap_err();
+        match *err.kind() {
+            ErrorKind::InvalidLog(ref invalid) => assert_eq!(&log, invalid),
+            ref kind => panic!("expected invalid log error, got {:?}", kind),
+        }
+        assert_eq!(
+            Some("log data of 95 bytes is not a multiple of 32 bytes".to_owned()),
+            err.iter().nth(1).map(|cause| cause.to_string())
+        );
+    }
+
+    #[test]
+    fn test_message_to_mainnet_from_logs_of_one_transaction() {
+        // three withdraws of a contract called in one foreign transaction
+        let transaction_hash: H256 = TRANSACTION_HASH.into();
+        let logs: Vec<_> = (0..3u64)
+            .map(|log_index| WithdrawLogBuilder::new().log_index(log_index).build())
+            .collect();
+
+        let ids = logs.iter()
+            .cloned()
+            .map(|log| MessageToMainnet::from_log(log, true).unwrap().sidenet_transaction_hash)
+            .collect::<Vec<_>>();
+        assert!(ids.iter().all(|id| *id != transaction_hash));
+        assert!(ids[0] != ids[1] && ids[1] != ids[2] && ids[0] != ids[2]);
+
+        // without unique ids all three messages are the same and only one can be withdrawn
+        for log in logs {
+            let message = MessageToMainnet::from_log(log, false).unwrap();
+            assert_eq!(transaction_hash, message.sidenet_transaction_hash);
+        }
+    }
+
     quickcheck! {
         fn quickcheck_message_to_mainnet_roundtrips_to_bytes(
             recipient_raw: Vec<u8>,
@@ -116,13 +178,26 @@ mod test {
             };
 
             let bytes = message.to_bytes();
-            assert_eq!(message, MessageToMainnet::from_bytes(bytes.as_slice()));
+            assert_eq!(message, MessageToMainnet::parse(bytes.as_slice()).unwrap());
 
             let payload = message.to_payload();
             let mut tokens = ethabi::decode(&[ethabi::ParamType::Bytes], payload.as_slice())
                 .unwrap();
             let decoded = tokens.pop().unwrap().to_bytes().unwrap();
-            assert_eq!(message, MessageToMainnet::from_bytes(decoded.as_slice()));
+            assert_eq!(message, MessageToMainnet::parse(decoded.as_slice()).unwrap());
+
+            TestResult::passed()
+        }
+
+        fn quickcheck_message_to_mainnet_rejects_wrong_length(bytes: Vec<u8>) -> TestResult {
+            if bytes.len() == MESSAGE_LENGTH {
+                return TestResult::discard();
+            }
+
+            match *MessageToMainnet::parse(bytes.as_slice()).unwrap_err().kind() {
+                ErrorKind::InvalidMessage(length) => assert_eq!(bytes.len(), length),
+                ref kind => panic!("expected invalid message error, got {:?}", kind),
+            }
 
             TestResult::passed()
         }
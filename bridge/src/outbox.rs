@@ -0,0 +1,1395 @@
+//! Outbox of the webhook notifications about relays.
+//!
+//! every notification is written to the `webhook_outbox` of the database before it's delivered
+//! and marked delivered once its endpoint acknowledged it with a 2xx response. a delivery thread
+//! per endpoint retries failed entries with exponential backoff, also the ones left undelivered
+//! by an earlier run. entries of the same relay are delivered one after the other: a later
+//! notification of a relay waits until the earlier ones were acknowledged.
+//!
+//! an endpoint with `Batching` gets the entries due as json arrays of up to `max_size` entries
+//! instead, once it has that many or the oldest waited `max_delay`. the key of a batch is written
+//! to its entries before it's posted, so a batch is posted again as it was until it's acknowledged
+//! as a whole, also after a restart.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(feature = "webhooks")]
+use reqwest;
+#[cfg(feature = "webhooks")]
+use reqwest::header::HeaderMap;
+use serde_json::{self, Value};
+use tiny_keccak::keccak256;
+use web3::types::H256;
+#[cfg(feature = "webhooks")]
+use alert::{http_client, post, set_header};
+#[cfg(feature = "amqp")]
+use amqp::AmqpSink;
+use capture::unix_time;
+#[cfg(feature = "webhooks")]
+use config::Password;
+use config::WebhooksConfig;
+use database::OutboxEntry;
+use error::{Error, ResultExt};
+use webhook::canonical_json;
+#[cfg(feature = "webhooks")]
+use webhook::{sign_webhook, SIGNATURE_HEADER};
+
+/// header of the posted notifications with their idempotency key.
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+
+/// Where the entries of an `Outbox` are kept, e.g. the `webhook_outbox` of the database.
+pub trait OutboxStore: Send {
+    /// entries of the outbox by sequence.
+    fn entries(&self) -> Vec<OutboxEntry>;
+
+    /// applies `update` to the entries and writes them. the changes are applied even if the
+    /// write fails, so the next write that succeeds writes them.
+    fn update(&mut self, update: &mut FnMut(&mut Vec<OutboxEntry>)) -> Result<(), Error>;
+}
+
+/// Store keeping the entries in memory only. clones share the entries,
+/// so a new `Outbox` on a clone continues where the last one stopped.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryOutboxStore {
+    entries: Arc<Mutex<Vec<OutboxEntry>>>,
+}
+
+impl OutboxStore for MemoryOutboxStore {
+    fn entries(&self) -> Vec<OutboxEntry> {
+        self.entries
+            .lock()
+            .expect("no code panics while holding the lock; qed")
+            .clone()
+    }
+
+    fn update(&mut self, update: &mut FnMut(&mut Vec<OutboxEntry>)) -> Result<(), Error> {
+        let mut entries = self.entries
+            .lock()
+            .expect("no code panics while holding the lock; qed");
+        update(&mut *entries);
+        Ok(())
+    }
+}
+
+/// A notification about a relay, delivered to every endpoint of the outbox.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    /// idempotency key. enqueueing a notification again is a no-op while its entry is kept
+    pub key: String,
+    /// key of the relay. the notifications of a relay are delivered in the order enqueued
+    pub ordering_key: String,
+    pub payload: Value,
+}
+
+#[derive(Debug, Default)]
+struct Signal {
+    /// incremented whenever entries are added or made undelivered
+    changes: u64,
+    stopped: bool,
+}
+
+/// Handle to the notifications waiting for delivery, shared by the bridge enqueueing them
+/// and the threads delivering them.
+#[derive(Clone)]
+pub struct Outbox {
+    store: Arc<Mutex<Box<OutboxStore>>>,
+    signal: Arc<(Mutex<Signal>, Condvar)>,
+    endpoints: Vec<String>,
+    /// seconds delivered entries are kept
+    retention: u64,
+}
+
+impl Outbox {
+    /// outbox delivering to `endpoints`, continuing with the entries in `store`.
+    pub fn new(store: Box<OutboxStore>, endpoints: Vec<String>, retention: Duration) -> Self {
+        Outbox {
+            store: Arc::new(Mutex::new(store)),
+            signal: Arc::new((Mutex::new(Signal::default()), Condvar::new())),
+            endpoints,
+            retention: retention.as_secs(),
+        }
+    }
+
+    fn update<F: FnMut(&mut Vec<OutboxEntry>)>(&self, mut update: F) -> Result<(), Error> {
+        self.store
+            .lock()
+            .expect("no code panics while holding the lock; qed")
+            .update(&mut update)
+            .chain_err(|| "Cannot write the webhook outbox")
+    }
+
+    /// writes an entry of every notification for every endpoint, unless it has one already,
+    /// and wakes the delivery threads. delivered entries older than the retention are dropped.
+    /// returns the number of entries written.
+    pub fn enqueue(&self, notifications: &[Notification], now: u64) -> Result<usize, Error> {
+        let mut payloads = Vec::with_capacity(notifications.len());
+        for notification in notifications {
+            payloads.push(canonical_json(&notification.payload)?);
+        }
+        let retention = self.retention;
+        let mut added = 0;
+        let result = self.update(|entries| {
+            entries.retain(|entry| {
+                entry
+                    .delivered_at
+                    .map_or(true, |delivered| delivered + retention > now)
+            });
+            let mut sequence = entries.last().map_or(0, |entry| entry.sequence);
+            let known = entries
+                .iter()
+                .map(|entry| (entry.endpoint.clone(), entry.key.clone()))
+                .collect::<HashSet<_>>();
+            for (notification, payload) in notifications.iter().zip(payloads.iter()) {
+                for endpoint in &self.endpoints {
+                    if known.contains(&(endpoint.clone(), notification.key.clone())) {
+                        continue;
+                    }
+                    sequence += 1;
+                    added += 1;
+                    entries.push(OutboxEntry {
+                        sequence,
+                        endpoint: endpoint.clone(),
+                        key: notification.key.clone(),
+                        ordering_key: notification.ordering_key.clone(),
+                        payload: payload.clone(),
+                        created_at: now,
+                        delivered_at: None,
+                        batch: None,
+                    });
+                }
+            }
+        });
+        if added > 0 {
+            self.notify(|signal| signal.changes += 1);
+        }
+        result.map(|_| added)
+    }
+
+    /// undelivered entries of `endpoint` by sequence.
+    pub fn pending(&self, endpoint: &str) -> Vec<OutboxEntry> {
+        self.store
+            .lock()
+            .expect("no code panics while holding the lock; qed")
+            .entries()
+            .into_iter()
+            .filter(|entry| entry.endpoint == endpoint && entry.delivered_at.is_none())
+            .collect()
+    }
+
+    /// marks the entries of `sequences` acknowledged at `now`.
+    pub fn mark_delivered(&self, sequences: &[u64], now: u64) -> Result<(), Error> {
+        self.update(|entries| {
+            for entry in entries.iter_mut() {
+                if sequences.contains(&entry.sequence) {
+                    entry.delivered_at = Some(now);
+                }
+            }
+        })
+    }
+
+    /// writes `batch` as the batch of the entries of `sequences`.
+    pub fn assign_batch(&self, sequences: &[u64], batch: &str) -> Result<(), Error> {
+        self.update(|entries| {
+            for entry in entries.iter_mut() {
+                if sequences.contains(&entry.sequence) {
+                    entry.batch = Some(batch.to_owned());
+                }
+            }
+        })
+    }
+
+    /// undelivered and delivered entries of every endpoint as of `now`.
+    pub fn status(&self, now: u64) -> OutboxStatus {
+        let entries = self.store
+            .lock()
+            .expect("no code panics while holding the lock; qed")
+            .entries();
+        OutboxStatus::of(&entries, now)
+    }
+
+    fn notify<F: FnOnce(&mut Signal)>(&self, change: F) {
+        let &(ref signal, ref condvar) = &*self.signal;
+        {
+            let mut signal = signal
+                .lock()
+                .expect("no code panics while holding the lock; qed");
+            change(&mut *signal);
+        }
+        condvar.notify_all();
+    }
+
+    /// number of changes so far, see `wait`.
+    pub fn changes(&self) -> u64 {
+        self.signal
+            .0
+            .lock()
+            .expect("no code panics while holding the lock; qed")
+            .changes
+    }
+
+    /// blocks until the entries changed since `changes` were returned by `changes`,
+    /// or until `until`. returns the changes then, `None` once the outbox is stopped.
+    fn wait(&self, changes: u64, until: Option<Instant>) -> Option<u64> {
+        let &(ref signal, ref condvar) = &*self.signal;
+        let mut signal = signal
+            .lock()
+            .expect("no code panics while holding the lock; qed");
+        loop {
+            if signal.stopped {
+                return None;
+            }
+            if signal.changes != changes {
+                return Some(signal.changes);
+            }
+            signal = match until {
+                Some(until) => {
+                    let now = Instant::now();
+                    if now >= until {
+                        return Some(signal.changes);
+                    }
+                    condvar
+                        .wait_timeout(signal, until - now)
+                        .expect("no code panics while holding the lock; qed")
+                        .0
+                }
+                None => condvar
+                    .wait(signal)
+                    .expect("no code panics while holding the lock; qed"),
+            };
+        }
+    }
+
+    /// stops the delivery threads after their current delivery.
+    fn stop(&self) {
+        self.notify(|signal| signal.stopped = true);
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.signal
+            .0
+            .lock()
+            .expect("no code panics while holding the lock; qed")
+            .stopped
+    }
+}
+
+/// marks the entries created at or after `since` undelivered, so they are delivered again.
+/// returns the number of entries marked. the undelivered entries are batched anew,
+/// so the replayed ones are posted before the later entries of their relay.
+pub fn replay(entries: &mut [OutboxEntry], since: u64) -> usize {
+    let mut replayed = 0;
+    for entry in entries.iter_mut() {
+        if entry.created_at >= since && entry.delivered_at.is_some() {
+            entry.delivered_at = None;
+            replayed += 1;
+        }
+        if entry.delivered_at.is_none() {
+            entry.batch = None;
+        }
+    }
+    replayed
+}
+
+/// idempotency key of a batch of `entries`, `batch/` and the keccak of their keys.
+pub fn batch_key(entries: &[OutboxEntry]) -> String {
+    let keys = entries
+        .iter()
+        .map(|entry| entry.key.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("batch/0x{:x}", H256::from(keccak256(keys.as_bytes())))
+}
+
+/// Entries of an endpoint in the outbox.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EndpointStatus {
+    pub endpoint: String,
+    pub undelivered: usize,
+    /// seconds since the oldest undelivered entry was written. `None` if all were delivered
+    pub oldest_pending_age: Option<u64>,
+    /// delivered entries still kept for replays
+    pub delivered: usize,
+}
+
+/// Entries of every endpoint in the outbox, see `Outbox::status`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutboxStatus {
+    pub endpoints: Vec<EndpointStatus>,
+}
+
+impl OutboxStatus {
+    /// status of the endpoints of `entries` as of `now`, by endpoint.
+    pub fn of(entries: &[OutboxEntry], now: u64) -> Self {
+        let mut endpoints: Vec<EndpointStatus> = Vec::new();
+        for entry in entries {
+            let index = match endpoints
+                .iter()
+                .position(|status| status.endpoint == entry.endpoint)
+            {
+                Some(index) => index,
+                None => {
+                    endpoints.push(EndpointStatus {
+                        endpoint: entry.endpoint.clone(),
+                        undelivered: 0,
+                        oldest_pending_age: None,
+                        delivered: 0,
+                    });
+                    endpoints.len() - 1
+                }
+            };
+            let status = &mut endpoints[index];
+            if entry.delivered_at.is_some() {
+                status.delivered += 1;
+                continue;
+            }
+            status.undelivered += 1;
+            let age = now.saturating_sub(entry.created_at);
+            if status.oldest_pending_age.map_or(true, |oldest| age > oldest) {
+                status.oldest_pending_age = Some(age);
+            }
+        }
+        endpoints.sort_by(|a, b| a.endpoint.cmp(&b.endpoint));
+        OutboxStatus { endpoints }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serialization can't fail; qed")
+    }
+}
+
+/// Delivers entries of the outbox to an endpoint.
+///
+/// every sink runs on a thread of its own: `deliver` may block until the entry is acknowledged.
+pub trait OutboxSink: Send {
+    /// name of the endpoint of the entries, e.g. `webhook 0`
+    fn name(&self) -> &str;
+
+    /// `Ok` once the endpoint acknowledged `entry`.
+    fn deliver(&mut self, entry: &OutboxEntry) -> Result<(), Error>;
+
+    /// `Ok` once the endpoint acknowledged the batch `batch` of `entries` as a whole.
+    /// sinks that can't batch deliver the entries one after the other.
+    fn deliver_batch(&mut self, batch: &str, entries: &[OutboxEntry]) -> Result<(), Error> {
+        let _ = batch;
+        for entry in entries {
+            self.deliver(entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Posts the payloads of entries to a webhook with their idempotency key in
+/// `IDEMPOTENCY_HEADER`, signed in `SIGNATURE_HEADER` if it has a secret.
+/// batches are posted as a json array of the payloads with the key of the batch.
+#[cfg(feature = "webhooks")]
+pub struct WebhookEndpoint {
+    name: String,
+    url: String,
+    secret: Option<Password>,
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "webhooks")]
+impl WebhookEndpoint {
+    pub fn new<S: Into<String>>(name: S, url: &Password, timeout: Duration) -> Result<Self, Error> {
+        Ok(WebhookEndpoint {
+            name: name.into(),
+            url: url.as_str().into(),
+            secret: None,
+            client: http_client(timeout)?,
+        })
+    }
+
+    /// signs the posted bodies with `secret`.
+    pub fn signed_with(mut self, secret: Password) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+
+    fn post(&self, key: &str, body: String) -> Result<(), Error> {
+        let mut headers = HeaderMap::new();
+        set_header(&mut headers, IDEMPOTENCY_HEADER, key)?;
+        if let Some(ref secret) = self.secret {
+            let signature = sign_webhook(secret.as_str().as_bytes(), body.as_bytes());
+            set_header(&mut headers, SIGNATURE_HEADER, &signature)?;
+        }
+        post(&self.client, &self.url, headers, body, &self.name)
+    }
+}
+
+#[cfg(feature = "webhooks")]
+impl OutboxSink for WebhookEndpoint {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn deliver(&mut self, entry: &OutboxEntry) -> Result<(), Error> {
+        self.post(&entry.key, entry.payload.clone())
+    }
+
+    fn deliver_batch(&mut self, batch: &str, entries: &[OutboxEntry]) -> Result<(), Error> {
+        let payloads = entries
+            .iter()
+            .map(|entry| entry.payload.as_str())
+            .collect::<Vec<_>>();
+        // the payloads are canonical json, so is the array of them
+        self.post(batch, format!("[{}]", payloads.join(",")))
+    }
+}
+
+/// Exponential backoff between the retries of a failed entry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    /// delay after the `failures`th failure in a row: `initial` doubled for every earlier
+    /// failure, at most `max`.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31);
+        let delay = self.initial
+            .checked_mul(1 << doublings)
+            .unwrap_or(self.max);
+        delay.min(self.max)
+    }
+}
+
+/// How an endpoint batches its entries, see `Delivery::batched`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Batching {
+    /// most entries in a batch. a batch this large is posted at once
+    pub max_size: usize,
+    /// longest an entry waits for a batch to fill up
+    pub max_delay: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Retry {
+    failures: u32,
+    at: Instant,
+}
+
+/// Delivers the entries of the endpoint of a sink, see `Outbox`.
+pub struct Delivery<S> {
+    outbox: Outbox,
+    sink: S,
+    backoff: Backoff,
+    /// `None` if the entries are delivered one by one
+    batching: Option<Batching>,
+    /// retries by ordering key of the entries that failed last
+    retries: HashMap<String, Retry>,
+    /// when the entries not yet in a batch were first seen pending, by sequence
+    seen: HashMap<u64, Instant>,
+}
+
+impl<S: OutboxSink> Delivery<S> {
+    pub fn new(outbox: Outbox, sink: S, backoff: Backoff) -> Self {
+        Delivery {
+            outbox,
+            sink,
+            backoff,
+            batching: None,
+            retries: HashMap::new(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// delivers the entries in batches of `batching` instead of one by one.
+    pub fn batched(mut self, batching: Batching) -> Self {
+        self.batching = Some(batching);
+        self
+    }
+
+    /// delivers the entries due at `now` by sequence, marking them delivered at `unix_now`.
+    /// an entry is due once the entries of its ordering key before it were delivered and
+    /// the backoff of its last failure passed.
+    /// returns when the next failed entry is retried or the next batch is posted,
+    /// `None` if there is neither.
+    pub fn deliver_due(&mut self, now: Instant, unix_now: u64) -> Option<Instant> {
+        let pending = self.outbox.pending(self.sink.name());
+        let undelivered = pending
+            .iter()
+            .map(|entry| entry.ordering_key.clone())
+            .collect::<HashSet<_>>();
+        self.retries.retain(|key, _| undelivered.contains(key));
+        let flush = match self.batching {
+            Some(batching) => self.deliver_batches(pending, batching, now, unix_now),
+            None => {
+                self.deliver_entries(pending, now, unix_now);
+                None
+            }
+        };
+        self.retries
+            .values()
+            .map(|retry| retry.at)
+            .chain(flush)
+            .min()
+    }
+
+    /// whether the entries of `ordering_key` may be delivered at `now`.
+    fn due(&self, ordering_key: &str, now: Instant) -> bool {
+        self.retries
+            .get(ordering_key)
+            .map_or(true, |retry| retry.at <= now)
+    }
+
+    fn deliver_entries(&mut self, pending: Vec<OutboxEntry>, now: Instant, unix_now: u64) {
+        // ordering keys with an entry not delivered by this call
+        let mut blocked = HashSet::new();
+        for entry in pending {
+            if self.outbox.is_stopped() {
+                break;
+            }
+            if blocked.contains(&entry.ordering_key) || !self.due(&entry.ordering_key, now) {
+                blocked.insert(entry.ordering_key);
+                continue;
+            }
+            let result = self.sink.deliver(&entry);
+            let what = format!("notification {}", entry.key);
+            let ordering_key = entry.ordering_key.clone();
+            if !self.delivered(result, &[entry], &what, now, unix_now) {
+                blocked.insert(ordering_key);
+            }
+        }
+    }
+
+    /// posts the batches written by earlier attempts, then batches the entries not in one yet.
+    /// returns when the entries waiting for a batch to fill up are posted anyway.
+    fn deliver_batches(
+        &mut self,
+        pending: Vec<OutboxEntry>,
+        batching: Batching,
+        now: Instant,
+        unix_now: u64,
+    ) -> Option<Instant> {
+        self.seen.retain(|sequence, _| {
+            pending
+                .iter()
+                .any(|entry| entry.sequence == *sequence && entry.batch.is_none())
+        });
+        // the entries of a relay in a batch precede those not in one, so the batches go first
+        let mut batches: Vec<(String, Vec<OutboxEntry>)> = Vec::new();
+        let mut unbatched = Vec::new();
+        for entry in pending {
+            match entry.batch.clone() {
+                Some(batch) => {
+                    let index = batches.iter().position(|&(ref key, _)| *key == batch);
+                    match index {
+                        Some(index) => batches[index].1.push(entry),
+                        None => batches.push((batch, vec![entry])),
+                    }
+                }
+                None => {
+                    self.seen.entry(entry.sequence).or_insert(now);
+                    unbatched.push(entry);
+                }
+            }
+        }
+
+        // ordering keys with an entry not delivered by this call
+        let mut blocked = HashSet::new();
+        for (batch, entries) in batches {
+            if self.outbox.is_stopped() {
+                return None;
+            }
+            let due = entries.iter().all(|entry| {
+                !blocked.contains(&entry.ordering_key) && self.due(&entry.ordering_key, now)
+            });
+            if !due || !self.post_batch(&batch, &entries, now, unix_now) {
+                blocked.extend(entries.into_iter().map(|entry| entry.ordering_key));
+            }
+        }
+
+        let mut posted = HashSet::new();
+        loop {
+            if self.outbox.is_stopped() {
+                return None;
+            }
+            let batch = unbatched
+                .iter()
+                .filter(|entry| !posted.contains(&entry.sequence))
+                .filter(|entry| {
+                    !blocked.contains(&entry.ordering_key) && self.due(&entry.ordering_key, now)
+                })
+                .take(batching.max_size)
+                .cloned()
+                .collect::<Vec<_>>();
+            if batch.is_empty() {
+                return None;
+            }
+            if batch.len() < batching.max_size {
+                let oldest = batch
+                    .iter()
+                    .filter_map(|entry| self.seen.get(&entry.sequence))
+                    .min()
+                    .cloned()
+                    .unwrap_or(now);
+                if oldest + batching.max_delay > now {
+                    return Some(oldest + batching.max_delay);
+                }
+            }
+            let sequences = batch.iter().map(|entry| entry.sequence).collect::<Vec<_>>();
+            posted.extend(sequences.iter().cloned());
+            let key = batch_key(&batch);
+            // written first, so the batch is posted as it was until it's acknowledged
+            if let Err(err) = self.outbox.assign_batch(&sequences, &key) {
+                error!(target: "bridge::outbox", "{}", err);
+                return None;
+            }
+            if !self.post_batch(&key, &batch, now, unix_now) {
+                blocked.extend(batch.into_iter().map(|entry| entry.ordering_key));
+            }
+        }
+    }
+
+    fn post_batch(
+        &mut self,
+        batch: &str,
+        entries: &[OutboxEntry],
+        now: Instant,
+        unix_now: u64,
+    ) -> bool {
+        let result = self.sink.deliver_batch(batch, entries);
+        let what = format!("batch {} of {} notifications", batch, entries.len());
+        self.delivered(result, entries, &what, now, unix_now)
+    }
+
+    /// marks `entries` delivered at `unix_now` if `result` is `Ok`, otherwise schedules
+    /// the retry of their ordering keys. returns whether they were delivered.
+    fn delivered(
+        &mut self,
+        result: Result<(), Error>,
+        entries: &[OutboxEntry],
+        what: &str,
+        now: Instant,
+        unix_now: u64,
+    ) -> bool {
+        let err = match result {
+            Ok(()) => {
+                for entry in entries {
+                    self.retries.remove(&entry.ordering_key);
+                }
+                let sequences = entries.iter().map(|entry| entry.sequence).collect::<Vec<_>>();
+                if let Err(err) = self.outbox.mark_delivered(&sequences, unix_now) {
+                    error!(target: "bridge::outbox", "{}", err);
+                }
+                return true;
+            }
+            Err(err) => err,
+        };
+        let failures = entries
+            .iter()
+            .filter_map(|entry| self.retries.get(&entry.ordering_key))
+            .map(|retry| retry.failures)
+            .max()
+            .unwrap_or(0) + 1;
+        let delay = self.backoff.delay(failures);
+        warn!(
+            target: "bridge::outbox",
+            "cannot deliver {} to {}, attempt {}: {}. retrying in {}s",
+            what,
+            self.sink.name(),
+            failures,
+            err,
+            delay.as_secs()
+        );
+        for entry in entries {
+            self.retries.insert(
+                entry.ordering_key.clone(),
+                Retry {
+                    failures,
+                    at: now + delay,
+                },
+            );
+        }
+        false
+    }
+}
+
+/// Delivers the entries of an outbox to its endpoints on threads of their own until dropped.
+///
+/// a slow or failing endpoint holds up neither the bridge nor the other endpoints.
+pub struct OutboxWorkers {
+    outbox: Outbox,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl OutboxWorkers {
+    /// posts the entries of `store` to the endpoints and publishes them to the brokers
+    /// of `config`. fails if a url or secret wasn't read from its file.
+    pub fn from_config(config: &WebhooksConfig, store: Box<OutboxStore>) -> Result<Self, Error> {
+        let names = (0..config.endpoints.len())
+            .map(|index| format!("webhook {}", index))
+            .collect::<Vec<_>>();
+        let broker_names = (0..config.brokers.len())
+            .map(|index| format!("broker {}", index))
+            .collect::<Vec<_>>();
+        let endpoints = names.iter().chain(&broker_names).cloned().collect();
+        let mut workers = OutboxWorkers {
+            outbox: Outbox::new(store, endpoints, config.retention),
+            threads: Vec::new(),
+        };
+        let backoff = Backoff {
+            initial: config.retry_initial,
+            max: config.retry_max,
+        };
+        workers.spawn_endpoints(config, names, backoff)?;
+        workers.spawn_brokers(config, broker_names, backoff)?;
+        Ok(workers)
+    }
+
+    #[cfg(feature = "webhooks")]
+    fn spawn_endpoints(
+        &mut self,
+        config: &WebhooksConfig,
+        names: Vec<String>,
+        backoff: Backoff,
+    ) -> Result<(), Error> {
+        for (index, (endpoint, name)) in config.endpoints.iter().zip(names).enumerate() {
+            let url = match endpoint.url {
+                Some(ref url) => url,
+                None => bail!("url of webhooks.endpoints[{}] was not read from url_file", index),
+            };
+            let mut sink = WebhookEndpoint::new(name, url, config.timeout)?;
+            if endpoint.secret_file.is_some() {
+                match endpoint.secret {
+                    Some(ref secret) => sink = sink.signed_with(secret.clone()),
+                    None => bail!(
+                        "secret of webhooks.endpoints[{}] was not read from secret_file",
+                        index
+                    ),
+                }
+            }
+            // a batch of one is a single notification
+            let batching = if endpoint.batch_max_size > 1 {
+                Some(Batching {
+                    max_size: endpoint.batch_max_size,
+                    max_delay: endpoint.batch_max_delay,
+                })
+            } else {
+                None
+            };
+            self.spawn(sink, backoff, batching)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "webhooks"))]
+    fn spawn_endpoints(
+        &mut self,
+        config: &WebhooksConfig,
+        _names: Vec<String>,
+        _backoff: Backoff,
+    ) -> Result<(), Error> {
+        if !config.endpoints.is_empty() {
+            bail!("webhooks.endpoints needs a bridge built with the `webhooks` feature");
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "amqp")]
+    fn spawn_brokers(
+        &mut self,
+        config: &WebhooksConfig,
+        names: Vec<String>,
+        backoff: Backoff,
+    ) -> Result<(), Error> {
+        for (index, (broker, name)) in config.brokers.iter().zip(names).enumerate() {
+            let url = match broker.url {
+                Some(ref url) => url.clone(),
+                None => bail!("url of webhooks.brokers[{}] was not read from url_file", index),
+            };
+            let sink = AmqpSink::new(name, url, broker, config.timeout, backoff);
+            self.spawn(sink, backoff, None)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "amqp"))]
+    fn spawn_brokers(
+        &mut self,
+        config: &WebhooksConfig,
+        _names: Vec<String>,
+        _backoff: Backoff,
+    ) -> Result<(), Error> {
+        if !config.brokers.is_empty() {
+            bail!("webhooks.brokers needs a bridge built with the `amqp` feature");
+        }
+        Ok(())
+    }
+
+    /// the outbox the workers deliver.
+    pub fn outbox(&self) -> &Outbox {
+        &self.outbox
+    }
+
+    /// delivers the entries of the endpoint of `sink` on a thread of its own,
+    /// in batches if `batching` is set.
+    pub fn spawn<S>(
+        &mut self,
+        sink: S,
+        backoff: Backoff,
+        batching: Option<Batching>,
+    ) -> Result<(), Error>
+    where
+        S: OutboxSink + 'static,
+    {
+        let name = sink.name().to_owned();
+        let mut delivery = Delivery::new(self.outbox.clone(), sink, backoff);
+        if let Some(batching) = batching {
+            delivery = delivery.batched(batching);
+        }
+        let thread = thread::Builder::new()
+            .name(format!("outbox ({})", name))
+            .spawn(move || {
+                let mut changes = delivery.outbox.changes();
+                loop {
+                    let next = delivery.deliver_due(Instant::now(), unix_time());
+                    changes = match delivery.outbox.wait(changes, next) {
+                        Some(changes) => changes,
+                        None => break,
+                    };
+                }
+            })?;
+        self.threads.push(thread);
+        Ok(())
+    }
+}
+
+impl Drop for OutboxWorkers {
+    /// stops the delivery threads after their current delivery.
+    /// undelivered entries stay in the outbox for the next run.
+    fn drop(&mut self) {
+        self.outbox.stop();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "webhooks")]
+    use std::io::{BufRead, BufReader, Read, Write};
+    #[cfg(feature = "webhooks")]
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    #[cfg(feature = "webhooks")]
+    use std::thread;
+    use std::time::{Duration, Instant};
+    #[cfg(feature = "webhooks")]
+    use config::Password;
+    use database::OutboxEntry;
+    use error::Error;
+    use super::{batch_key, replay, Backoff, Batching, Delivery, EndpointStatus, MemoryOutboxStore,
+                Notification, Outbox, OutboxSink, OutboxStatus, OutboxStore};
+    #[cfg(feature = "webhooks")]
+    use super::WebhookEndpoint;
+
+    fn notification(relay: &str, status: &str) -> Notification {
+        Notification {
+            key: format!("{}/{}", relay, status),
+            ordering_key: relay.into(),
+            payload: json!({ "relay_key": relay, "status": status }),
+        }
+    }
+
+    fn outbox(store: &MemoryOutboxStore) -> Outbox {
+        Outbox::new(
+            Box::new(store.clone()),
+            vec!["webhook 0".into()],
+            Duration::from_secs(100),
+        )
+    }
+
+    fn backoff() -> Backoff {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(4),
+        }
+    }
+
+    /// records the keys delivered and the batches. fails the keys of `failing`
+    /// and the batches with one of them
+    #[derive(Clone, Default)]
+    struct Sink {
+        delivered: Arc<Mutex<Vec<String>>>,
+        batches: Arc<Mutex<Vec<(String, Vec<String>)>>>,
+        failing: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Sink {
+        fn delivered(&self) -> Vec<String> {
+            self.delivered.lock().unwrap().clone()
+        }
+
+        /// the keys of every batch posted, successfully or not
+        fn batches(&self) -> Vec<Vec<String>> {
+            let batches = self.batches.lock().unwrap();
+            batches.iter().map(|&(_, ref keys)| keys.clone()).collect()
+        }
+
+        fn batch_keys(&self) -> Vec<String> {
+            let batches = self.batches.lock().unwrap();
+            batches.iter().map(|&(ref batch, _)| batch.clone()).collect()
+        }
+
+        fn fail(&self, keys: &[&str]) {
+            *self.failing.lock().unwrap() = keys.iter().map(|key| key.to_string()).collect();
+        }
+    }
+
+    impl OutboxSink for Sink {
+        fn name(&self) -> &str {
+            "webhook 0"
+        }
+
+        fn deliver(&mut self, entry: &OutboxEntry) -> Result<(), Error> {
+            if self.failing.lock().unwrap().contains(&entry.key) {
+                bail!("webhook 0 responded with status 503 Service Unavailable");
+            }
+            self.delivered.lock().unwrap().push(entry.key.clone());
+            Ok(())
+        }
+
+        fn deliver_batch(&mut self, batch: &str, entries: &[OutboxEntry]) -> Result<(), Error> {
+            let keys = entries.iter().map(|entry| entry.key.clone()).collect::<Vec<_>>();
+            self.batches.lock().unwrap().push((batch.into(), keys.clone()));
+            let failing = self.failing.lock().unwrap();
+            if keys.iter().any(|key| failing.contains(key)) {
+                bail!("webhook 0 responded with status 503 Service Unavailable");
+            }
+            self.delivered.lock().unwrap().extend(keys);
+            Ok(())
+        }
+    }
+
+    fn batching(max_size: usize) -> Batching {
+        Batching {
+            max_size,
+            max_delay: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_enqueue_writes_entries_before_delivery_once() {
+        let store = MemoryOutboxStore::default();
+        let outbox = Outbox::new(
+            Box::new(store.clone()),
+            vec!["webhook 0".into(), "webhook 1".into()],
+            Duration::from_secs(100),
+        );
+        let notifications = [notification("0x01", "relayed"), notification("0x02", "relayed")];
+        assert_eq!(4, outbox.enqueue(&notifications, 1000).unwrap());
+        let entries = store.entries();
+        assert_eq!(vec![1, 2, 3, 4], entries.iter().map(|e| e.sequence).collect::<Vec<_>>());
+        assert_eq!(
+            OutboxEntry {
+                sequence: 2,
+                endpoint: "webhook 1".into(),
+                key: "0x01/relayed".into(),
+                ordering_key: "0x01".into(),
+                payload: r#"{"relay_key":"0x01","status":"relayed"}"#.into(),
+                created_at: 1000,
+                delivered_at: None,
+                batch: None,
+            },
+            entries[1]
+        );
+        // the same notifications are written once
+        assert_eq!(0, outbox.enqueue(&notifications, 1001).unwrap());
+        assert_eq!(4, store.entries().len());
+        assert_eq!(2, outbox.pending("webhook 1").len());
+    }
+
+    #[test]
+    fn test_delivered_entries_are_kept_for_the_retention() {
+        let store = MemoryOutboxStore::default();
+        let outbox = outbox(&store);
+        outbox.enqueue(&[notification("0x01", "relayed")], 1000).unwrap();
+        outbox.mark_delivered(&[1], 1010).unwrap();
+        outbox.enqueue(&[notification("0x02", "relayed")], 1109).unwrap();
+        assert_eq!(2, store.entries().len());
+        outbox.enqueue(&[notification("0x03", "relayed")], 1110).unwrap();
+        let keys = store.entries().into_iter().map(|e| e.key).collect::<Vec<_>>();
+        assert_eq!(vec!["0x02/relayed", "0x03/relayed"], keys);
+        // sequences keep increasing
+        assert_eq!(3, store.entries()[1].sequence);
+    }
+
+    #[test]
+    fn test_delivery_marks_acknowledged_entries_delivered() {
+        let store = MemoryOutboxStore::default();
+        let outbox = outbox(&store);
+        let sink = Sink::default();
+        let mut delivery = Delivery::new(outbox.clone(), sink.clone(), backoff());
+        outbox
+            .enqueue(&[notification("0x01", "relayed"), notification("0x02", "relayed")], 1000)
+            .unwrap();
+
+        assert_eq!(None, delivery.deliver_due(Instant::now(), 1005));
+        assert_eq!(vec!["0x01/relayed", "0x02/relayed"], sink.delivered());
+        assert!(outbox.pending("webhook 0").is_empty());
+        assert!(store.entries().iter().all(|entry| entry.delivered_at == Some(1005)));
+    }
+
+    #[test]
+    fn test_delivery_keeps_the_order_of_a_relay_across_retries() {
+        let store = MemoryOutboxStore::default();
+        let outbox = outbox(&store);
+        let sink = Sink::default();
+        let mut delivery = Delivery::new(outbox.clone(), sink.clone(), backoff());
+        outbox
+            .enqueue(
+                &[
+                    notification("0x01", "skipped"),
+                    notification("0x02", "relayed"),
+                    notification("0x01", "relayed"),
+                ],
+                1000,
+            )
+            .unwrap();
+        sink.fail(&["0x01/skipped"]);
+        let start = Instant::now();
+
+        // the later notification of 0x01 waits for the failed one, 0x02 doesn't
+        assert_eq!(Some(start + Duration::from_secs(1)), delivery.deliver_due(start, 1000));
+        assert_eq!(vec!["0x02/relayed"], sink.delivered());
+        // not retried before the backoff passed
+        assert_eq!(
+            Some(start + Duration::from_secs(1)),
+            delivery.deliver_due(start + Duration::from_millis(999), 1000)
+        );
+        // the backoff doubles
+        let retry = start + Duration::from_secs(1);
+        assert_eq!(Some(retry + Duration::from_secs(2)), delivery.deliver_due(retry, 1001));
+        assert_eq!(vec!["0x02/relayed"], sink.delivered());
+
+        sink.fail(&[]);
+        let retry = retry + Duration::from_secs(2);
+        assert_eq!(None, delivery.deliver_due(retry, 1003));
+        assert_eq!(
+            vec!["0x02/relayed", "0x01/skipped", "0x01/relayed"],
+            sink.delivered()
+        );
+    }
+
+    #[test]
+    fn test_undelivered_entries_are_delivered_after_a_restart() {
+        let store = MemoryOutboxStore::default();
+        let sink = Sink::default();
+        {
+            let outbox = outbox(&store);
+            let mut delivery = Delivery::new(outbox.clone(), sink.clone(), backoff());
+            outbox
+                .enqueue(&[notification("0x01", "skipped"), notification("0x01", "relayed")], 1000)
+                .unwrap();
+            sink.fail(&["0x01/skipped"]);
+            delivery.deliver_due(Instant::now(), 1000);
+            assert!(sink.delivered().is_empty());
+        }
+
+        // a new run continues with the store, in order and without waiting for the backoff
+        sink.fail(&[]);
+        let outbox = outbox(&store);
+        let mut delivery = Delivery::new(outbox.clone(), sink.clone(), backoff());
+        assert_eq!(None, delivery.deliver_due(Instant::now(), 2000));
+        assert_eq!(vec!["0x01/skipped", "0x01/relayed"], sink.delivered());
+        assert!(outbox.pending("webhook 0").is_empty());
+    }
+
+    #[test]
+    fn test_batches_are_posted_once_full() {
+        let store = MemoryOutboxStore::default();
+        let outbox = outbox(&store);
+        let sink = Sink::default();
+        let mut delivery =
+            Delivery::new(outbox.clone(), sink.clone(), backoff()).batched(batching(2));
+        let start = Instant::now();
+        outbox
+            .enqueue(
+                &[
+                    notification("0x01", "skipped"),
+                    notification("0x02", "relayed"),
+                    notification("0x01", "relayed"),
+                ],
+                1000,
+            )
+            .unwrap();
+
+        // the third waits for another notification or the delay
+        assert_eq!(Some(start + Duration::from_secs(5)), delivery.deliver_due(start, 1000));
+        assert_eq!(vec![vec!["0x01/skipped", "0x02/relayed"]], sink.batches());
+        let entries = store.entries();
+        let batch = batch_key(&entries[..2]);
+        assert_eq!(vec![batch.clone()], sink.batch_keys());
+        assert_eq!(Some(batch), entries[0].batch);
+        assert_eq!(
+            vec![Some(1000), Some(1000), None],
+            entries.iter().map(|e| e.delivered_at).collect::<Vec<_>>()
+        );
+
+        let later = start + Duration::from_secs(1);
+        outbox.enqueue(&[notification("0x03", "relayed")], 1001).unwrap();
+        assert_eq!(None, delivery.deliver_due(later, 1001));
+        assert_eq!(
+            vec!["0x01/skipped", "0x02/relayed", "0x01/relayed", "0x03/relayed"],
+            sink.delivered()
+        );
+        assert_eq!(2, sink.batches().len());
+    }
+
+    #[test]
+    fn test_batches_are_posted_after_the_delay() {
+        let store = MemoryOutboxStore::default();
+        let outbox = outbox(&store);
+        let sink = Sink::default();
+        let mut delivery =
+            Delivery::new(outbox.clone(), sink.clone(), backoff()).batched(batching(10));
+        let start = Instant::now();
+        outbox.enqueue(&[notification("0x01", "relayed")], 1000).unwrap();
+        assert_eq!(Some(start + Duration::from_secs(5)), delivery.deliver_due(start, 1000));
+
+        // the delay counts from the oldest notification of the batch
+        let later = start + Duration::from_secs(3);
+        outbox.enqueue(&[notification("0x02", "relayed")], 1003).unwrap();
+        assert_eq!(Some(start + Duration::from_secs(5)), delivery.deliver_due(later, 1003));
+        assert!(sink.batches().is_empty());
+
+        assert_eq!(None, delivery.deliver_due(start + Duration::from_secs(5), 1005));
+        assert_eq!(vec![vec!["0x01/relayed", "0x02/relayed"]], sink.batches());
+        assert!(outbox.pending("webhook 0").is_empty());
+    }
+
+    #[test]
+    fn test_batches_keep_the_order_of_a_relay_across_retries() {
+        let store = MemoryOutboxStore::default();
+        let outbox = outbox(&store);
+        let sink = Sink::default();
+        let mut delivery =
+            Delivery::new(outbox.clone(), sink.clone(), backoff()).batched(batching(2));
+        let start = Instant::now();
+        outbox
+            .enqueue(
+                &[
+                    notification("0x01", "skipped"),
+                    notification("0x02", "relayed"),
+                    notification("0x01", "relayed"),
+                    notification("0x03", "relayed"),
+                    notification("0x04", "relayed"),
+                ],
+                1000,
+            )
+            .unwrap();
+        sink.fail(&["0x02/relayed"]);
+
+        // the failed batch holds back 0x01 and 0x02, the others are batched without them
+        assert_eq!(Some(start + Duration::from_secs(1)), delivery.deliver_due(start, 1000));
+        assert_eq!(
+            vec![
+                vec!["0x01/skipped", "0x02/relayed"],
+                vec!["0x03/relayed", "0x04/relayed"],
+            ],
+            sink.batches()
+        );
+        assert_eq!(vec!["0x03/relayed", "0x04/relayed"], sink.delivered());
+
+        // the failed batch is posted again as it was before the later notification of 0x01
+        sink.fail(&[]);
+        let retry = start + Duration::from_secs(1);
+        assert_eq!(Some(start + Duration::from_secs(5)), delivery.deliver_due(retry, 1001));
+        assert_eq!(vec!["0x01/skipped", "0x02/relayed"], sink.batches()[2]);
+        assert_eq!(sink.batch_keys()[0], sink.batch_keys()[2]);
+        assert_eq!(None, delivery.deliver_due(retry + Duration::from_secs(5), 1006));
+        assert_eq!(vec!["0x01/relayed"], sink.batches()[3]);
+    }
+
+    #[test]
+    fn test_a_batch_is_posted_as_it_was_after_a_restart() {
+        let store = MemoryOutboxStore::default();
+        let sink = Sink::default();
+        let start = Instant::now();
+        {
+            let outbox = outbox(&store);
+            let mut delivery =
+                Delivery::new(outbox.clone(), sink.clone(), backoff()).batched(batching(2));
+            outbox
+                .enqueue(&[notification("0x01", "relayed"), notification("0x02", "relayed")], 1000)
+                .unwrap();
+            sink.fail(&["0x01/relayed"]);
+            delivery.deliver_due(start, 1000);
+            assert!(sink.delivered().is_empty());
+        }
+
+        // a new run with a larger batch size posts the batch of the last run as it was,
+        // not together with the notifications written since
+        sink.fail(&[]);
+        let outbox = outbox(&store);
+        outbox.enqueue(&[notification("0x03", "relayed")], 2000).unwrap();
+        let mut delivery =
+            Delivery::new(outbox.clone(), sink.clone(), backoff()).batched(batching(3));
+        assert_eq!(Some(start + Duration::from_secs(5)), delivery.deliver_due(start, 2000));
+        assert_eq!(
+            vec![
+                vec!["0x01/relayed", "0x02/relayed"],
+                vec!["0x01/relayed", "0x02/relayed"],
+            ],
+            sink.batches()
+        );
+        assert_eq!(sink.batch_keys()[0], sink.batch_keys()[1]);
+        let pending = outbox.pending("webhook 0");
+        assert_eq!(vec!["0x03/relayed"], pending.into_iter().map(|e| e.key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_max() {
+        let delays = (1..6).map(|failures| backoff().delay(failures).as_secs()).collect::<Vec<_>>();
+        assert_eq!(vec![1, 2, 4, 4, 4], delays);
+        assert_eq!(Duration::from_secs(4), backoff().delay(u32::max_value()));
+    }
+
+    fn entry(
+        sequence: u64,
+        endpoint: &str,
+        created_at: u64,
+        delivered_at: Option<u64>,
+    ) -> OutboxEntry {
+        OutboxEntry {
+            sequence,
+            endpoint: endpoint.into(),
+            key: format!("0x{:02x}/relayed", sequence),
+            ordering_key: format!("0x{:02x}", sequence),
+            payload: "{}".into(),
+            created_at,
+            delivered_at,
+            batch: None,
+        }
+    }
+
+    #[test]
+    fn test_status_counts_undelivered_entries_by_endpoint() {
+        let entries = vec![
+            entry(1, "webhook 1", 900, Some(901)),
+            entry(2, "webhook 0", 950, None),
+            entry(3, "webhook 0", 990, None),
+            entry(4, "webhook 1", 990, Some(995)),
+        ];
+        assert_eq!(
+            OutboxStatus {
+                endpoints: vec![
+                    EndpointStatus {
+                        endpoint: "webhook 0".into(),
+                        undelivered: 2,
+                        oldest_pending_age: Some(50),
+                        delivered: 0,
+                    },
+                    EndpointStatus {
+                        endpoint: "webhook 1".into(),
+                        undelivered: 0,
+                        oldest_pending_age: None,
+                        delivered: 2,
+                    },
+                ],
+            },
+            OutboxStatus::of(&entries, 1000)
+        );
+    }
+
+    #[test]
+    fn test_replay_marks_entries_since_undelivered() {
+        let mut entries = vec![
+            entry(1, "webhook 0", 900, Some(901)),
+            entry(2, "webhook 0", 950, Some(951)),
+            entry(3, "webhook 0", 990, None),
+        ];
+        entries[2].batch = Some("batch/0x01".into());
+        assert_eq!(1, replay(&mut entries, 950));
+        assert_eq!(
+            vec![Some(901), None, None],
+            entries.iter().map(|e| e.delivered_at).collect::<Vec<_>>()
+        );
+        // the undelivered entries are batched anew
+        assert_eq!(None, entries[2].batch);
+    }
+
+    #[test]
+    #[cfg(feature = "webhooks")]
+    fn test_webhook_endpoint_posts_the_payload_with_its_idempotency_key() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Password::new(format!("http://{}/relays", listener.local_addr().unwrap()));
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut headers = Vec::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                headers.push(line.trim_right().to_lowercase());
+            }
+            let mut stream = stream;
+            stream
+                .write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            headers
+        });
+        let mut endpoint = WebhookEndpoint::new("webhook 0", &url, Duration::from_secs(5))
+            .unwrap()
+            .signed_with(Password::new("secret"));
+
+        let err = endpoint.deliver(&entry(1, "webhook 0", 900, None)).unwrap_err();
+        assert_eq!("webhook 0 responded with status 500 Internal Server Error", err.to_string());
+        let headers = server.join().unwrap();
+        assert!(headers.contains(&"idempotency-key: 0x01/relayed".to_owned()), "{:?}", headers);
+        assert!(headers.iter().any(|header| header.starts_with("x-bridge-signature: sha256=")));
+    }
+
+    #[test]
+    #[cfg(feature = "webhooks")]
+    fn test_webhook_endpoint_posts_a_batch_as_an_array_with_its_key() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Password::new(format!("http://{}/relays", listener.local_addr().unwrap()));
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut headers = Vec::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                headers.push(line.trim_right().to_lowercase());
+            }
+            let length = headers
+                .iter()
+                .filter_map(|header| header.split("content-length: ").nth(1))
+                .next()
+                .unwrap()
+                .parse::<usize>()
+                .unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let mut stream = stream;
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            (headers, String::from_utf8(body).unwrap())
+        });
+        let mut endpoint = WebhookEndpoint::new("webhook 0", &url, Duration::from_secs(5)).unwrap();
+        let mut entries = vec![entry(1, "webhook 0", 900, None), entry(2, "webhook 0", 900, None)];
+        entries[1].payload = r#"{"status":"relayed"}"#.into();
+        let batch = batch_key(&entries);
+
+        endpoint.deliver_batch(&batch, &entries).unwrap();
+        let (headers, body) = server.join().unwrap();
+        assert!(headers.contains(&format!("idempotency-key: {}", batch)), "{:?}", headers);
+        assert_eq!(r#"[{},{"status":"relayed"}]"#, body);
+    }
+}
@@ -0,0 +1,196 @@
+//! Quantities in json-rpc responses, normalized before they are decoded.
+//!
+//! some nodes return quantities with leading zeros, e.g. `0x00ff`, which some decoders reject.
+//! they are rewritten to the shortest form. strings that aren't quantities at all are rejected
+//! with the raw value, so the failure names what the node returned.
+
+use serde_json::Value;
+
+/// quantity fields of a log, also found in the logs of a receipt
+const LOG_FIELDS: &[&str] = &[
+    "blockNumber",
+    "transactionIndex",
+    "logIndex",
+    "transactionLogIndex",
+];
+
+const RECEIPT_FIELDS: &[&str] = &[
+    "blockNumber",
+    "transactionIndex",
+    "cumulativeGasUsed",
+    "gasUsed",
+    "status",
+    "logIndex",
+    "transactionLogIndex",
+];
+
+const TRANSACTION_FIELDS: &[&str] = &[
+    "blockNumber",
+    "transactionIndex",
+    "nonce",
+    "value",
+    "gas",
+    "gasPrice",
+];
+
+/// `nonce` of a block is data, not a quantity, and is kept as is
+const BLOCK_FIELDS: &[&str] = &[
+    "number",
+    "gasUsed",
+    "gasLimit",
+    "timestamp",
+    "difficulty",
+    "totalDifficulty",
+    "size",
+];
+
+/// Quantities in the result of a json-rpc method.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantities {
+    /// the result is a quantity
+    Result,
+    /// these fields of the result and of the objects nested in it are quantities
+    Fields(&'static [&'static str]),
+    /// the result isn't normalized
+    None,
+}
+
+/// quantities in the result of `method`.
+pub fn quantities(method: &str) -> Quantities {
+    match method {
+        "eth_blockNumber" | "eth_gasPrice" | "eth_getBalance" | "eth_getTransactionCount" => {
+            Quantities::Result
+        }
+        "eth_getLogs" => Quantities::Fields(LOG_FIELDS),
+        "eth_getTransactionReceipt" => Quantities::Fields(RECEIPT_FIELDS),
+        "eth_getTransactionByHash" => Quantities::Fields(TRANSACTION_FIELDS),
+        "eth_getBlockByNumber" => Quantities::Fields(BLOCK_FIELDS),
+        _ => Quantities::None,
+    }
+}
+
+/// `raw` without leading zeros, e.g. `0x1` for `0x0001`.
+/// fails unless `raw` is `0x` followed by up to 256 bits of hex digits.
+pub fn normalize_quantity(raw: &str) -> Result<String, String> {
+    if !raw.starts_with("0x") {
+        return Err(format!("quantity {:?} must start with 0x", raw));
+    }
+    let digits = &raw[2..];
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(16)) {
+        return Err(format!("quantity {:?} is not a hex number", raw));
+    }
+    let digits = digits.trim_left_matches('0');
+    if digits.len() > 64 {
+        return Err(format!("quantity {:?} doesn't fit in 256 bits", raw));
+    }
+    Ok(format!("0x{}", if digits.is_empty() { "0" } else { digits }))
+}
+
+/// normalizes the `quantities` in the `result` of a json-rpc method in place.
+/// fails with a description of the first value that isn't a quantity.
+pub fn normalize_result(result: &mut Value, quantities: Quantities) -> Result<(), String> {
+    match quantities {
+        Quantities::Result => match *result {
+            Value::String(ref mut quantity) => {
+                *quantity = normalize_quantity(quantity)?;
+                Ok(())
+            }
+            ref other => Err(format!("quantity {} is not a string", other)),
+        },
+        Quantities::Fields(fields) => normalize_fields(result, fields),
+        Quantities::None => Ok(()),
+    }
+}
+
+fn normalize_fields(value: &mut Value, fields: &[&str]) -> Result<(), String> {
+    match *value {
+        Value::Array(ref mut values) => {
+            for value in values {
+                normalize_fields(value, fields)?;
+            }
+        }
+        Value::Object(ref mut object) => {
+            for (key, value) in object.iter_mut() {
+                if fields.contains(&key.as_str()) {
+                    if let Value::String(ref mut quantity) = *value {
+                        *quantity = normalize_quantity(quantity)
+                            .map_err(|reason| format!("{}: {}", key, reason))?;
+                        continue;
+                    }
+                }
+                normalize_fields(value, fields)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, Value};
+    use super::{normalize_quantity, normalize_result, quantities, Quantities};
+
+    #[test]
+    fn test_normalize_quantity() {
+        assert_eq!(Ok("0x0".to_owned()), normalize_quantity("0x0"));
+        assert_eq!(Ok("0x0".to_owned()), normalize_quantity("0x0000"));
+        assert_eq!(Ok("0x1b4".to_owned()), normalize_quantity("0x0001b4"));
+        assert_eq!(Ok("0xABC".to_owned()), normalize_quantity("0x0ABC"));
+        let max = format!("0x0000{}", "f".repeat(64));
+        assert_eq!(Ok(format!("0x{}", "f".repeat(64))), normalize_quantity(&max));
+
+        let rejected = [
+            ("", "quantity \"\" must start with 0x"),
+            ("1b4", "quantity \"1b4\" must start with 0x"),
+            ("0x", "quantity \"0x\" is not a hex number"),
+            ("0x1g", "quantity \"0x1g\" is not a hex number"),
+            ("0x-1", "quantity \"0x-1\" is not a hex number"),
+        ];
+        for &(raw, err) in &rejected {
+            assert_eq!(Err(err.to_owned()), normalize_quantity(raw));
+        }
+        let overflow = format!("0x1{}", "0".repeat(64));
+        assert!(normalize_quantity(&overflow).unwrap_err().ends_with("doesn't fit in 256 bits"));
+    }
+
+    #[test]
+    fn test_normalize_result() {
+        let mut head: Value = "0x000000001a".into();
+        normalize_result(&mut head, quantities("eth_blockNumber")).unwrap();
+        assert_eq!(Value::from("0x1a"), head);
+
+        let mut head = Value::from(26);
+        assert_eq!(
+            Err("quantity 26 is not a string".to_owned()),
+            normalize_result(&mut head, Quantities::Result)
+        );
+
+        // quantities of the receipt and of its logs, data is left as is
+        let hash = format!("0x{:064x}", 0xff);
+        let mut receipt: Value = serde_json::from_str(&format!(
+            r#"{{"blockNumber": "0x01b4", "gasUsed": "0x05208", "status": "0x01",
+                "transactionHash": "{}", "logs": [{{"logIndex": "0x00", "data": "0x0000"}}]}}"#,
+            hash
+        )).unwrap();
+        normalize_result(&mut receipt, quantities("eth_getTransactionReceipt")).unwrap();
+        let expected: Value = serde_json::from_str(&format!(
+            r#"{{"blockNumber": "0x1b4", "gasUsed": "0x5208", "status": "0x1",
+                "transactionHash": "{}", "logs": [{{"logIndex": "0x0", "data": "0x0000"}}]}}"#,
+            hash
+        )).unwrap();
+        assert_eq!(expected, receipt);
+
+        let mut logs: Value =
+            serde_json::from_str(r#"[{"logIndex": null}, {"logIndex": "0xzz"}]"#).unwrap();
+        assert_eq!(
+            Err("logIndex: quantity \"0xzz\" is not a hex number".to_owned()),
+            normalize_result(&mut logs, quantities("eth_getLogs"))
+        );
+
+        // results of other methods are kept
+        let mut output: Value = "0x0001".into();
+        normalize_result(&mut output, quantities("eth_call")).unwrap();
+        assert_eq!(Value::from("0x0001"), output);
+    }
+}
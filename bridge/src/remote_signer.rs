@@ -0,0 +1,777 @@
+//! Client of a signing service keeping the keys of an authority, see `RemoteSignerConfig`.
+//!
+//! `RemoteSigningTransport` answers the `eth_sendTransaction` and `eth_sign` requests of the
+//! bridge for a node whose `signer` is `remote`. it fills in what a transaction lacks from the
+//! node, asks the signer for its EIP-155 signature and sends it with `eth_sendRawTransaction`.
+//! messages are signed like by `eth_sign`. all other requests go to the node unchanged.
+//!
+//! every signing request is a json `POST` of a `SigningRequest` to the url of the signer,
+//! with `Authorization: Bearer <token>` if it has a token. the signer answers with
+//! `{"signature": "0x..."}`: `r`, `s` and `v` in 65 bytes, `v` being 0 or 1, or 27 or 28.
+//!
+//! the bridge only asks for signatures its `Allowlist` allows. refused and failed signing
+//! requests fail with an error `failure_policy` defers, see `is_remote_signer_error`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
+use reqwest;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use rpc;
+use serde_json::{self, Value};
+use tiny_keccak::keccak256;
+use web3::{self, RequestId, Transport};
+use web3::helpers;
+use web3::types::{Address, Bytes, H256, H520, U256};
+use alert::set_header;
+use config::{Node, Password, RemoteSignerConfig, Signer};
+use error::{Error, ResultExt};
+use message_to_mainnet::MESSAGE_LENGTH;
+use quantity::normalize_quantity;
+use rlp;
+
+/// prefix of the transport errors of refused and failed signing requests.
+pub const REMOTE_SIGNER_ERROR: &str = "remote signer: ";
+
+/// `true` if the transport error `message` is of a refused or failed signing request.
+pub fn is_remote_signer_error(message: &str) -> bool {
+    message.starts_with(REMOTE_SIGNER_ERROR)
+}
+
+fn signer_error<D: fmt::Display>(reason: D) -> web3::Error {
+    web3::Error::Transport(format!("{}{}", REMOTE_SIGNER_ERROR, reason))
+}
+
+/// `err` and its causes on one line.
+fn describe(err: &Error) -> String {
+    err.iter()
+        .map(|cause| cause.to_string())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+/// hash `eth_sign` signs for `message`.
+pub fn message_hash(message: &[u8]) -> H256 {
+    let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    prefixed.extend_from_slice(message);
+    keccak256(&prefixed).into()
+}
+
+/// Transaction as signed by the remote signer.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnsignedTransaction {
+    pub nonce: U256,
+    pub gas_price: U256,
+    pub gas: U256,
+    pub to: Address,
+    pub value: U256,
+    pub data: Bytes,
+}
+
+impl UnsignedTransaction {
+    fn rlp_fields(&self) -> Vec<Vec<u8>> {
+        vec![
+            rlp::encode_u256(self.nonce),
+            rlp::encode_u256(self.gas_price),
+            rlp::encode_u256(self.gas),
+            rlp::encode_bytes(&self.to.0),
+            rlp::encode_u256(self.value),
+            rlp::encode_bytes(&self.data.0),
+        ]
+    }
+
+    /// hash signed for a chain with `chain_id`, see EIP-155.
+    pub fn signing_hash(&self, chain_id: u64) -> H256 {
+        let mut fields = self.rlp_fields();
+        fields.push(rlp::encode_u64(chain_id));
+        fields.push(rlp::encode_u64(0));
+        fields.push(rlp::encode_u64(0));
+        keccak256(&rlp::encode_list(&fields)).into()
+    }
+
+    /// the transaction with `signature` of its `signing_hash`, as sent with
+    /// `eth_sendRawTransaction`.
+    pub fn signed(&self, chain_id: u64, signature: &RecoverableSignature) -> Bytes {
+        let mut fields = self.rlp_fields();
+        fields.push(rlp::encode_u64(chain_id * 2 + 35 + u64::from(signature.recovery_id)));
+        fields.push(rlp::encode_u256(U256::from(&signature.r.0[..])));
+        fields.push(rlp::encode_u256(U256::from(&signature.s.0[..])));
+        rlp::encode_list(&fields).into()
+    }
+}
+
+/// Signature returned by the remote signer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecoverableSignature {
+    pub r: H256,
+    pub s: H256,
+    /// 0 or 1
+    pub recovery_id: u8,
+}
+
+impl RecoverableSignature {
+    /// `r`, `s` and `v` of 65 bytes. `v` is 0 or 1, or 27 or 28.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != 65 {
+            bail!("signature has {} bytes instead of 65", bytes.len());
+        }
+        let recovery_id = match bytes[64] {
+            v @ 0 | v @ 1 => v,
+            v @ 27 | v @ 28 => v - 27,
+            v => bail!("signature has invalid v {}", v),
+        };
+        Ok(RecoverableSignature {
+            r: H256::from(&bytes[..32]),
+            s: H256::from(&bytes[32..64]),
+            recovery_id,
+        })
+    }
+
+    /// the signature as returned by `eth_sign`, `v` being 27 or 28.
+    pub fn to_eth_sign(&self) -> H520 {
+        let mut bytes = [0u8; 65];
+        bytes[..32].copy_from_slice(&self.r.0);
+        bytes[32..64].copy_from_slice(&self.s.0);
+        bytes[64] = self.recovery_id + 27;
+        H520::from(bytes)
+    }
+}
+
+/// Body of a signing request. `hash` is what the signature is of.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SigningRequest {
+    Transaction {
+        account: Address,
+        chain_id: u64,
+        transaction: UnsignedTransaction,
+        hash: H256,
+    },
+    /// signed like by `eth_sign`
+    Message {
+        account: Address,
+        message: Bytes,
+        hash: H256,
+    },
+}
+
+impl SigningRequest {
+    pub fn transaction(account: Address, chain_id: u64, transaction: UnsignedTransaction) -> Self {
+        SigningRequest::Transaction {
+            account,
+            chain_id,
+            hash: transaction.signing_hash(chain_id),
+            transaction,
+        }
+    }
+
+    pub fn message(account: Address, message: Bytes) -> Self {
+        SigningRequest::Message {
+            account,
+            hash: message_hash(&message.0),
+            message,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SigningResponse {
+    signature: Bytes,
+}
+
+/// What the bridge asks the remote signer to sign: transactions calling the bridge contracts
+/// and withdraw messages. everything else is refused before it reaches the signer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allowlist {
+    contracts: Vec<Address>,
+}
+
+impl Allowlist {
+    pub fn new(contracts: Vec<Address>) -> Self {
+        Allowlist { contracts }
+    }
+
+    /// fails unless `request` may be signed.
+    pub fn check(&self, request: &SigningRequest) -> Result<(), Error> {
+        match *request {
+            SigningRequest::Transaction {
+                ref transaction, ..
+            } => if !self.contracts.contains(&transaction.to) {
+                bail!(
+                    "refused to sign a transaction to {:?}, which is not a bridge contract",
+                    transaction.to
+                );
+            },
+            SigningRequest::Message { ref message, .. } => if message.0.len() != MESSAGE_LENGTH {
+                bail!(
+                    "refused to sign a message of {} bytes, which is not a withdraw message",
+                    message.0.len()
+                );
+            },
+        }
+        Ok(())
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    fs::File::open(path)
+        .chain_err(|| format!("Cannot open {}", path.to_string_lossy()))?
+        .read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Client of the signing service of a node.
+pub struct RemoteSigner {
+    url: String,
+    token: Option<Password>,
+    client: reqwest::Client,
+    allowlist: Allowlist,
+    /// chain id of the node, fetched with the first transaction
+    chain_id: Mutex<Option<u64>>,
+    /// nonces of the next transactions of the accounts
+    nonces: Mutex<BTreeMap<Address, U256>>,
+}
+
+impl fmt::Debug for RemoteSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RemoteSigner")
+            .field("url", &self.url)
+            .field("allowlist", &self.allowlist)
+            .finish()
+    }
+}
+
+impl RemoteSigner {
+    pub fn new(config: &RemoteSignerConfig, allowlist: Allowlist) -> Result<Self, Error> {
+        let mut builder = reqwest::Client::builder().timeout(config.timeout);
+        if let Some(ref path) = config.identity_file {
+            let password = config
+                .identity_password
+                .as_ref()
+                .map_or("", |password| password.as_str());
+            let identity = reqwest::Identity::from_pkcs12_der(&read_file(path)?, password)
+                .chain_err(|| "Invalid remote_signer.identity_file")?;
+            builder = builder.identity(identity);
+        }
+        if let Some(ref path) = config.ca_file {
+            let certificate = reqwest::Certificate::from_der(&read_file(path)?)
+                .chain_err(|| "Invalid remote_signer.ca_file")?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        Ok(RemoteSigner {
+            url: config.url.clone(),
+            token: config.token.clone(),
+            client: builder
+                .build()
+                .chain_err(|| "Cannot create remote signer client")?,
+            allowlist,
+            chain_id: Mutex::new(None),
+            nonces: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// signer of `node` if its `signer` is `remote`, allowed to sign calls to `contract`.
+    pub fn of_node(node: &Node, contract: Address) -> Result<Option<Arc<Self>>, Error> {
+        match (node.signer, node.remote_signer.as_ref()) {
+            (Signer::Remote, Some(config)) => Ok(Some(Arc::new(RemoteSigner::new(
+                config,
+                Allowlist::new(vec![contract]),
+            )?))),
+            _ => Ok(None),
+        }
+    }
+
+    /// the signature of `request` by the signer. blocks until it responds.
+    pub fn sign(&self, request: &SigningRequest) -> Result<RecoverableSignature, Error> {
+        self.allowlist.check(request)?;
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        if let Some(ref token) = self.token {
+            set_header(&mut headers, AUTHORIZATION.as_str(), &format!("Bearer {}", token.as_str()))
+                .chain_err(|| "Invalid remote_signer.token")?;
+        }
+        let body = serde_json::to_string(request).expect("SigningRequest always serializes; qed");
+        let mut response = self.client
+            .post(&self.url)
+            .headers(headers)
+            .body(body)
+            .send()
+            .chain_err(|| "Cannot reach the remote signer")?;
+        if !response.status().is_success() {
+            bail!("remote signer responded with status {}", response.status());
+        }
+        let response: SigningResponse = response
+            .json()
+            .chain_err(|| "Invalid response of the remote signer")?;
+        RecoverableSignature::from_bytes(&response.signature.0)
+    }
+
+    /// nonce of the next transaction of `account`, which has `pending` transactions on the node.
+    /// transactions signed at the same time get consecutive nonces.
+    fn reserve_nonce(&self, account: Address, pending: U256) -> U256 {
+        let mut nonces = self.nonces
+            .lock()
+            .expect("no code panics while holding the lock; qed");
+        let nonce = match nonces.get(&account) {
+            Some(next) if *next > pending => *next,
+            _ => pending,
+        };
+        nonces.insert(account, nonce + 1.into());
+        nonce
+    }
+
+    /// forgets the reserved nonces of `account` after one of its transactions wasn't sent.
+    /// the next transaction gets its nonce from the node again.
+    fn release_nonces(&self, account: Address) {
+        self.nonces
+            .lock()
+            .expect("no code panics while holding the lock; qed")
+            .remove(&account);
+    }
+}
+
+/// `RemoteSigner::sign` on a thread of its own, which doesn't block the event loop.
+fn spawn_sign(
+    signer: &Arc<RemoteSigner>,
+    request: SigningRequest,
+) -> oneshot::Receiver<Result<RecoverableSignature, Error>> {
+    let (sender, receiver) = oneshot::channel();
+    let signer = signer.clone();
+    thread::spawn(move || {
+        let _ = sender.send(signer.sign(&request));
+    });
+    receiver
+}
+
+/// Transport signing with the remote signer of its node. passes every request to the node
+/// unchanged if the node has none.
+#[derive(Debug, Clone)]
+pub struct RemoteSigningTransport<T> {
+    inner: T,
+    signer: Option<Arc<RemoteSigner>>,
+}
+
+impl<T> RemoteSigningTransport<T> {
+    pub fn new(inner: T, signer: Option<Arc<RemoteSigner>>) -> Self {
+        RemoteSigningTransport { inner, signer }
+    }
+}
+
+impl<T: Transport + Clone> Transport for RemoteSigningTransport<T> {
+    type Out = RemoteSigned<T>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let signer = match self.signer {
+            Some(ref signer) => signer.clone(),
+            None => return RemoteSigned::node(self.inner.send(id, request)),
+        };
+        let call = serde_json::to_value(&request).unwrap_or(Value::Null);
+        let params = call["params"].as_array().cloned().unwrap_or_default();
+        match call["method"].as_str() {
+            Some("eth_sendTransaction") => {
+                RemoteSigned::transaction(self.inner.clone(), signer, params)
+            }
+            Some("eth_sign") => RemoteSigned::message(&signer, &params),
+            _ => RemoteSigned::node(self.inner.send(id, request)),
+        }
+    }
+}
+
+/// Params of `eth_sendTransaction`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendParams {
+    from: Address,
+    to: Option<Address>,
+    gas: Option<U256>,
+    gas_price: Option<U256>,
+    value: Option<U256>,
+    data: Option<Bytes>,
+    nonce: Option<U256>,
+}
+
+/// What a transaction lacks and is fetched from the node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    ChainId,
+    Gas,
+    GasPrice,
+    Nonce,
+}
+
+/// quantity in the `result` of `method`.
+fn quantity(method: &str, result: &Value) -> Result<U256, web3::Error> {
+    let invalid = |reason: String| {
+        web3::Error::Transport(format!("invalid {} result {}: {}", method, result, reason))
+    };
+    let raw = result
+        .as_str()
+        .ok_or_else(|| invalid("not a quantity".into()))?;
+    let normalized = normalize_quantity(raw).map_err(&invalid)?;
+    serde_json::from_value(Value::String(normalized)).map_err(|err| invalid(err.to_string()))
+}
+
+/// A transaction of the bridge signed by the remote signer.
+struct PendingTransaction<T> {
+    inner: T,
+    signer: Arc<RemoteSigner>,
+    params: SendParams,
+    /// the address the transaction calls. contract creations are refused
+    to: Address,
+    chain_id: Option<u64>,
+    /// whether the nonce was reserved with `RemoteSigner::reserve_nonce`
+    reserved: bool,
+}
+
+impl<T: Transport> PendingTransaction<T> {
+    /// request fetching the next field the transaction lacks, or the signing request
+    /// once it lacks none.
+    fn next(&self) -> SignState<T> {
+        let from = helpers::serialize(&self.params.from);
+        let (field, method, params) = if self.chain_id.is_none() {
+            (Field::ChainId, "eth_chainId", vec![])
+        } else if self.params.gas.is_none() {
+            let call = json!({
+                "from": self.params.from,
+                "to": self.to,
+                "value": self.params.value,
+                "data": self.params.data,
+            });
+            (Field::Gas, "eth_estimateGas", vec![call])
+        } else if self.params.gas_price.is_none() {
+            (Field::GasPrice, "eth_gasPrice", vec![])
+        } else if self.params.nonce.is_none() {
+            // the nonce is reserved last, right before signing
+            (Field::Nonce, "eth_getTransactionCount", vec![from, "pending".into()])
+        } else {
+            let request = SigningRequest::transaction(
+                self.params.from,
+                self.chain_id.expect("checked above; qed"),
+                self.unsigned(),
+            );
+            return SignState::Sign(spawn_sign(&self.signer, request));
+        };
+        SignState::Fill {
+            future: self.inner.execute(method, params),
+            field,
+        }
+    }
+
+    fn fill(&mut self, field: Field, result: &Value) -> Result<(), web3::Error> {
+        match field {
+            Field::ChainId => {
+                let chain_id = quantity("eth_chainId", result)?.low_u64();
+                *self.signer
+                    .chain_id
+                    .lock()
+                    .expect("no code panics while holding the lock; qed") = Some(chain_id);
+                self.chain_id = Some(chain_id);
+            }
+            Field::Gas => self.params.gas = Some(quantity("eth_estimateGas", result)?),
+            Field::GasPrice => self.params.gas_price = Some(quantity("eth_gasPrice", result)?),
+            Field::Nonce => {
+                let pending = quantity("eth_getTransactionCount", result)?;
+                self.params.nonce = Some(self.signer.reserve_nonce(self.params.from, pending));
+                self.reserved = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn unsigned(&self) -> UnsignedTransaction {
+        let filled = "fields are filled before signing; qed";
+        UnsignedTransaction {
+            nonce: self.params.nonce.expect(filled),
+            gas_price: self.params.gas_price.expect(filled),
+            gas: self.params.gas.expect(filled),
+            to: self.to,
+            value: self.params.value.unwrap_or_default(),
+            data: self.params.data.clone().unwrap_or_else(|| Bytes(Vec::new())),
+        }
+    }
+
+    /// the signed transaction sent to the node.
+    fn send(&self, signature: &RecoverableSignature) -> SignState<T> {
+        let chain_id = self.chain_id.expect("fetched before signing; qed");
+        let raw = self.unsigned().signed(chain_id, signature);
+        SignState::Send(
+            self.inner
+                .execute("eth_sendRawTransaction", vec![helpers::serialize(&raw)]),
+        )
+    }
+
+    fn release(&self) {
+        if self.reserved {
+            self.signer.release_nonces(self.params.from);
+        }
+    }
+}
+
+enum SignState<T: Transport> {
+    /// a request the node answers
+    Node(T::Out),
+    /// refused before anything was requested
+    Refused(Option<web3::Error>),
+    /// fetching `field` of the transaction from the node
+    Fill { future: T::Out, field: Field },
+    /// waiting for the signature of the remote signer
+    Sign(oneshot::Receiver<Result<RecoverableSignature, Error>>),
+    /// sending the signed transaction
+    Send(T::Out),
+}
+
+/// Response of a `RemoteSigningTransport`.
+pub struct RemoteSigned<T: Transport> {
+    state: SignState<T>,
+    /// `None` unless the request is `eth_sendTransaction`
+    transaction: Option<PendingTransaction<T>>,
+}
+
+impl<T: Transport> RemoteSigned<T> {
+    fn node(future: T::Out) -> Self {
+        RemoteSigned {
+            state: SignState::Node(future),
+            transaction: None,
+        }
+    }
+
+    fn refused(err: web3::Error) -> Self {
+        RemoteSigned {
+            state: SignState::Refused(Some(err)),
+            transaction: None,
+        }
+    }
+
+    fn transaction(inner: T, signer: Arc<RemoteSigner>, params: Vec<Value>) -> Self {
+        let param = params.into_iter().next().unwrap_or(Value::Null);
+        let params: SendParams = match serde_json::from_value(param) {
+            Ok(params) => params,
+            Err(err) => return RemoteSigned::refused(signer_error(err)),
+        };
+        let to = match params.to {
+            Some(to) => to,
+            None => {
+                return RemoteSigned::refused(signer_error("refused to sign a contract creation"))
+            }
+        };
+        let chain_id = *signer
+            .chain_id
+            .lock()
+            .expect("no code panics while holding the lock; qed");
+        let transaction = PendingTransaction {
+            inner,
+            signer,
+            params,
+            to,
+            chain_id,
+            reserved: false,
+        };
+        RemoteSigned {
+            state: transaction.next(),
+            transaction: Some(transaction),
+        }
+    }
+
+    fn message(signer: &Arc<RemoteSigner>, params: &[Value]) -> Self {
+        let param = |index: usize| params.get(index).cloned().unwrap_or(Value::Null);
+        let account: Address = match serde_json::from_value(param(0)) {
+            Ok(account) => account,
+            Err(err) => return RemoteSigned::refused(signer_error(err)),
+        };
+        let message: Bytes = match serde_json::from_value(param(1)) {
+            Ok(message) => message,
+            Err(err) => return RemoteSigned::refused(signer_error(err)),
+        };
+        let request = SigningRequest::message(account, message);
+        RemoteSigned {
+            state: SignState::Sign(spawn_sign(signer, request)),
+            transaction: None,
+        }
+    }
+}
+
+impl<T: Transport> Future for RemoteSigned<T> {
+    type Item = Value;
+    type Error = web3::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                SignState::Node(ref mut future) => return future.poll(),
+                SignState::Refused(ref mut err) => {
+                    return Err(err.take().expect("not polled after it failed; qed"))
+                }
+                SignState::Fill {
+                    ref mut future,
+                    field,
+                } => {
+                    let result = try_ready!(future.poll());
+                    let transaction = self.transaction
+                        .as_mut()
+                        .expect("only transactions are filled; qed");
+                    transaction.fill(field, &result)?;
+                    transaction.next()
+                }
+                SignState::Sign(ref mut receiver) => {
+                    let signed = match receiver.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(signed)) => {
+                            signed.map_err(|err| signer_error(describe(&err)))
+                        }
+                        Err(_) => Err(signer_error("signing thread stopped")),
+                    };
+                    match (signed, self.transaction.as_ref()) {
+                        (Ok(signature), None) => {
+                            return Ok(Async::Ready(json!(signature.to_eth_sign())))
+                        }
+                        (Ok(signature), Some(transaction)) => transaction.send(&signature),
+                        (Err(err), transaction) => {
+                            if let Some(transaction) = transaction {
+                                transaction.release();
+                            }
+                            return Err(err);
+                        }
+                    }
+                }
+                SignState::Send(ref mut future) => {
+                    let result = future.poll();
+                    if result.is_err() {
+                        if let Some(ref transaction) = self.transaction {
+                            transaction.release();
+                        }
+                    }
+                    return result;
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hex::FromHex;
+    use web3::types::{Bytes, U256};
+    use message_to_mainnet::MESSAGE_LENGTH;
+    use super::{is_remote_signer_error, signer_error, Allowlist, RecoverableSignature,
+                SigningRequest, UnsignedTransaction};
+
+    /// the example transaction of EIP-155
+    fn eip155_transaction() -> UnsignedTransaction {
+        UnsignedTransaction {
+            nonce: 9.into(),
+            gas_price: 20_000_000_000u64.into(),
+            gas: 21_000.into(),
+            to: "3535353535353535353535353535353535353535".parse().unwrap(),
+            value: U256::from_dec_str("1000000000000000000").unwrap(),
+            data: Bytes(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn test_eip155_signing_hash_and_signed_transaction() {
+        let transaction = eip155_transaction();
+        assert_eq!(
+            "daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53"
+                .parse::<::web3::types::H256>()
+                .unwrap(),
+            transaction.signing_hash(1)
+        );
+        let mut bytes = Vec::new();
+        bytes.extend(
+            "28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276"
+                .from_hex::<Vec<u8>>()
+                .unwrap(),
+        );
+        bytes.extend(
+            "67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+                .from_hex::<Vec<u8>>()
+                .unwrap(),
+        );
+        bytes.push(0);
+        let signature = RecoverableSignature::from_bytes(&bytes).unwrap();
+        let expected: Vec<u8> = concat!(
+            "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000",
+            "8025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f",
+            "761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        ).from_hex()
+            .unwrap();
+        assert_eq!(Bytes(expected), transaction.signed(1, &signature));
+    }
+
+    #[test]
+    fn test_recoverable_signature_from_bytes() {
+        let mut bytes = vec![1u8; 65];
+        bytes[64] = 28;
+        let signature = RecoverableSignature::from_bytes(&bytes).unwrap();
+        assert_eq!(1, signature.recovery_id);
+        assert_eq!(&bytes[..], &signature.to_eth_sign().0[..]);
+        bytes[64] = 0;
+        assert_eq!(0, RecoverableSignature::from_bytes(&bytes).unwrap().recovery_id);
+        bytes[64] = 2;
+        assert!(RecoverableSignature::from_bytes(&bytes).is_err());
+        assert!(RecoverableSignature::from_bytes(&bytes[..64]).is_err());
+    }
+
+    #[test]
+    fn test_allowlist_allows_bridge_calls_and_withdraw_messages() {
+        let contract = "3535353535353535353535353535353535353535".parse().unwrap();
+        let allowlist = Allowlist::new(vec![contract]);
+        let account = 1.into();
+        let transaction = eip155_transaction();
+        assert!(
+            allowlist
+                .check(&SigningRequest::transaction(account, 1, transaction.clone()))
+                .is_ok()
+        );
+        let elsewhere = UnsignedTransaction {
+            to: 2.into(),
+            ..transaction
+        };
+        assert!(
+            allowlist
+                .check(&SigningRequest::transaction(account, 1, elsewhere))
+                .is_err()
+        );
+
+        let message = |len: usize| SigningRequest::message(account, Bytes(vec![0u8; len]));
+        assert!(allowlist.check(&message(MESSAGE_LENGTH)).is_ok());
+        assert!(allowlist.check(&message(MESSAGE_LENGTH - 1)).is_err());
+        assert!(allowlist.check(&message(32)).is_err());
+    }
+
+    #[test]
+    fn test_signing_request_json() {
+        let request = SigningRequest::message(1.into(), Bytes(vec![0x12, 0x34]));
+        let json = ::serde_json::to_value(&request).unwrap();
+        assert_eq!("message", json["type"]);
+        assert_eq!("0x0000000000000000000000000000000000000001", json["account"]);
+        assert_eq!("0x1234", json["message"]);
+
+        let request = SigningRequest::transaction(1.into(), 77, eip155_transaction());
+        let json = ::serde_json::to_value(&request).unwrap();
+        assert_eq!("transaction", json["type"]);
+        assert_eq!(77, json["chain_id"]);
+        assert_eq!("0x9", json["transaction"]["nonce"]);
+    }
+
+    #[test]
+    fn test_remote_signer_errors() {
+        match signer_error("refused") {
+            ::web3::Error::Transport(ref message) => assert!(is_remote_signer_error(message)),
+            _ => unreachable!(),
+        }
+        assert!(!is_remote_signer_error("connection refused"));
+    }
+}
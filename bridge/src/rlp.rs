@@ -0,0 +1,252 @@
+//! Recursive length prefix encoding of block headers, receipts and trie nodes.
+
+use ethereum_types::U256;
+use error::Result;
+
+fn length_prefix(len: usize, short: u8, long: u8) -> Vec<u8> {
+    if len < 56 {
+        return vec![short + len as u8];
+    }
+    let len_bytes = be_bytes(len as u64);
+    let mut prefix = vec![long + len_bytes.len() as u8];
+    prefix.extend_from_slice(&len_bytes);
+    prefix
+}
+
+/// big endian bytes of `value` without leading zeros. empty for `0`.
+fn be_bytes(value: u64) -> Vec<u8> {
+    let bytes = [
+        (value >> 56) as u8,
+        (value >> 48) as u8,
+        (value >> 40) as u8,
+        (value >> 32) as u8,
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ];
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    bytes[zeros..].to_vec()
+}
+
+pub fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+    let mut encoded = length_prefix(bytes.len(), 0x80, 0xb7);
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+/// list of `items`, which are already encoded.
+pub fn encode_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let len = items.iter().map(Vec::len).sum();
+    let mut encoded = length_prefix(len, 0xc0, 0xf7);
+    for item in items {
+        encoded.extend_from_slice(item);
+    }
+    encoded
+}
+
+pub fn encode_u64(value: u64) -> Vec<u8> {
+    encode_bytes(&be_bytes(value))
+}
+
+pub fn encode_u256(value: U256) -> Vec<u8> {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    encode_bytes(&bytes[zeros..])
+}
+
+/// A decoded item. lists hold the encodings of their items.
+#[derive(Debug, PartialEq)]
+pub enum Item<'a> {
+    Bytes(&'a [u8]),
+    List(Vec<&'a [u8]>),
+}
+
+/// whether the first item of `data` is a list, and the offset and length of its payload.
+fn header(data: &[u8]) -> Result<(bool, usize, usize)> {
+    let first = match data.first() {
+        Some(first) => *first,
+        None => bail!("rlp item is empty"),
+    };
+    let (list, offset, len) = match first {
+        0x00...0x7f => (false, 0, 1),
+        0x80...0xb7 => (false, 1, (first - 0x80) as usize),
+        0xc0...0xf7 => (true, 1, (first - 0xc0) as usize),
+        _ => {
+            let (list, len_of_len) = if first < 0xc0 {
+                (false, (first - 0xb7) as usize)
+            } else {
+                (true, (first - 0xf7) as usize)
+            };
+            if data.len() < 1 + len_of_len || len_of_len > 8 {
+                bail!("rlp length of length {} is invalid", len_of_len);
+            }
+            let len = data[1..1 + len_of_len]
+                .iter()
+                .fold(0u64, |len, byte| (len << 8) | u64::from(*byte));
+            if data[1] == 0 || len < 56 {
+                bail!("rlp length {} isn't encoded canonically", len);
+            }
+            (list, 1 + len_of_len, len as usize)
+        }
+    };
+    if data.len() - offset < len {
+        bail!("rlp item of {} bytes is truncated to {}", len, data.len() - offset);
+    }
+    if !list && offset == 1 && len == 1 && data[1] < 0x80 {
+        bail!("rlp single byte {} isn't encoded canonically", data[1]);
+    }
+    Ok((list, offset, len))
+}
+
+/// decodes `data`, which must be exactly one item.
+pub fn decode(data: &[u8]) -> Result<Item> {
+    let (list, offset, len) = header(data)?;
+    if offset + len != data.len() {
+        bail!("rlp item has {} trailing bytes", data.len() - offset - len);
+    }
+    let payload = &data[offset..offset + len];
+    if !list {
+        return Ok(Item::Bytes(payload));
+    }
+    let mut items = Vec::new();
+    let mut rest = payload;
+    while !rest.is_empty() {
+        let (_, offset, len) = header(rest)?;
+        items.push(&rest[..offset + len]);
+        rest = &rest[offset + len..];
+    }
+    Ok(Item::List(items))
+}
+
+pub fn decode_bytes(data: &[u8]) -> Result<&[u8]> {
+    match decode(data)? {
+        Item::Bytes(bytes) => Ok(bytes),
+        Item::List(_) => bail!("expected rlp bytes, got a list"),
+    }
+}
+
+pub fn decode_list(data: &[u8]) -> Result<Vec<&[u8]>> {
+    match decode(data)? {
+        Item::List(items) => Ok(items),
+        Item::Bytes(_) => bail!("expected rlp list, got bytes"),
+    }
+}
+
+pub fn decode_u256(data: &[u8]) -> Result<U256> {
+    let bytes = decode_bytes(data)?;
+    if bytes.len() > 32 || bytes.first() == Some(&0) {
+        bail!("rlp integer {:?} isn't encoded canonically", bytes);
+    }
+    Ok(U256::from_big_endian(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hex::FromHex;
+    use super::{decode, decode_bytes, decode_list, decode_u256, encode_bytes, encode_list,
+                encode_u256, encode_u64, Item};
+
+    fn hex(s: &str) -> Vec<u8> {
+        s.from_hex().unwrap()
+    }
+
+    const LOREM: &str = "Lorem ipsum dolor sit amet, consectetur adipisicing elit";
+
+    #[test]
+    fn test_encode_bytes() {
+        assert_eq!(hex("83646f67"), encode_bytes(b"dog"));
+        assert_eq!(hex("80"), encode_bytes(b""));
+        assert_eq!(hex("00"), encode_bytes(&[0]));
+        assert_eq!(hex("7f"), encode_bytes(&[0x7f]));
+        assert_eq!(hex("8180"), encode_bytes(&[0x80]));
+        let mut lorem = hex("b838");
+        lorem.extend_from_slice(LOREM.as_bytes());
+        assert_eq!(lorem, encode_bytes(LOREM.as_bytes()));
+        let long = vec![0xaa; 1024];
+        assert_eq!(hex("b90400"), encode_bytes(&long)[..3].to_vec());
+    }
+
+    #[test]
+    fn test_encode_integers() {
+        assert_eq!(hex("80"), encode_u64(0));
+        assert_eq!(hex("0f"), encode_u64(15));
+        assert_eq!(hex("820400"), encode_u64(1024));
+        assert_eq!(hex("88ffffffffffffffff"), encode_u64(u64::max_value()));
+        assert_eq!(hex("80"), encode_u256(0.into()));
+        assert_eq!(hex("820400"), encode_u256(1024.into()));
+        assert_eq!(
+            hex("8f102030405060708090a0b0c0d0e0f2"),
+            encode_u256("102030405060708090a0b0c0d0e0f2".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_encode_list() {
+        assert_eq!(hex("c0"), encode_list(&[]));
+        assert_eq!(
+            hex("c88363617483646f67"),
+            encode_list(&[encode_bytes(b"cat"), encode_bytes(b"dog")])
+        );
+        // [ [], [[]], [ [], [[]] ] ]
+        let empty = encode_list(&[]);
+        let nested = encode_list(&[empty.clone()]);
+        let set = encode_list(&[empty.clone(), nested.clone(), encode_list(&[empty, nested])]);
+        assert_eq!(hex("c7c0c1c0c3c0c1c0"), set);
+        let mut lorem = hex("f83ab838");
+        lorem.extend_from_slice(LOREM.as_bytes());
+        assert_eq!(lorem, encode_list(&[encode_bytes(LOREM.as_bytes())]));
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Item::Bytes(b"dog"), decode(&hex("83646f67")).unwrap());
+        assert_eq!(Item::Bytes(&[0x7f]), decode(&hex("7f")).unwrap());
+        assert_eq!(b"", decode_bytes(&hex("80")).unwrap());
+        let list = hex("c88363617483646f67");
+        assert_eq!(vec![&list[1..5], &list[5..]], decode_list(&list).unwrap());
+        let set = hex("c7c0c1c0c3c0c1c0");
+        let items = decode_list(&set).unwrap();
+        assert_eq!(3, items.len());
+        assert_eq!(vec![&hex("c0")[..]], decode_list(items[1]).unwrap());
+
+        let lorem = encode_list(&[encode_bytes(LOREM.as_bytes())]);
+        let items = decode_list(&lorem).unwrap();
+        assert_eq!(LOREM.as_bytes(), decode_bytes(items[0]).unwrap());
+
+        assert_eq!(1024u64, decode_u256(&hex("820400")).unwrap().low_u64());
+        assert!(decode_u256(&hex("820004")).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid() {
+        assert!(decode(&[]).is_err());
+        // truncated
+        assert!(decode(&hex("83646f")).is_err());
+        assert!(decode(&hex("c88363617483646f")).is_err());
+        // trailing bytes
+        assert!(decode(&hex("83646f6700")).is_err());
+        // a single byte below 0x80 must not have a prefix
+        assert!(decode(&hex("8105")).is_err());
+        // lengths below 56 must use the short form
+        assert!(decode(&hex("b803646f67")).is_err());
+        assert!(decode_bytes(&hex("c0")).is_err());
+        assert!(decode_list(&hex("80")).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for len in &[0usize, 1, 55, 56, 255, 256, 70000] {
+            let bytes = vec![0x99; *len];
+            assert_eq!(bytes, decode_bytes(&encode_bytes(&bytes)).unwrap().to_vec());
+            let items = vec![encode_bytes(&bytes), encode_u64(*len as u64)];
+            let list = encode_list(&items);
+            let decoded = decode_list(&list).unwrap();
+            assert_eq!(items, decoded.iter().map(|item| item.to_vec()).collect::<Vec<_>>());
+        }
+    }
+}
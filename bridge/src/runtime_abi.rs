@@ -0,0 +1,666 @@
+use std::fs;
+use std::path::Path;
+use ethabi::{self, Contract, Event, Function, ParamType, Token};
+use tiny_keccak::keccak256;
+use web3::types::{Address, Bytes, FilterBuilder, H256, Log, U256};
+use bridge::ExpectedCall;
+use config::Config;
+use error::{Error, ErrorKind, Result, ResultExt};
+use message_to_mainnet::MessageToMainnet;
+use util::{check_log_data, log_id, raw_log};
+
+/// JSON ABI `contracts::home` is generated from
+pub const COMPILED_HOME_ABI: &str = include_str!("../../compiled_contracts/HomeBridge.abi");
+/// JSON ABI `contracts::foreign` is generated from
+pub const COMPILED_FOREIGN_ABI: &str = include_str!("../../compiled_contracts/ForeignBridge.abi");
+
+/// keccak256 of the JSON `abi`. identifies the ABI a bridge was built or runs with.
+pub fn abi_hash(abi: &str) -> H256 {
+    keccak256(abi.as_bytes()).into()
+}
+
+/// canonical name of `kind` as used in signatures, e.g. `uint256` or `bytes32[]`.
+fn type_name(kind: &ParamType) -> String {
+    match *kind {
+        ParamType::Address => "address".into(),
+        ParamType::Bytes => "bytes".into(),
+        ParamType::Int(size) => format!("int{}", size),
+        ParamType::Uint(size) => format!("uint{}", size),
+        ParamType::Bool => "bool".into(),
+        ParamType::String => "string".into(),
+        ParamType::Array(ref kind) => format!("{}[]", type_name(kind)),
+        ParamType::FixedBytes(size) => format!("bytes{}", size),
+        ParamType::FixedArray(ref kind, size) => format!("{}[{}]", type_name(kind), size),
+    }
+}
+
+/// canonical signature of `name` with parameters of `kinds`, e.g. `deposit(address,uint256)`.
+fn signature<'a, I: Iterator<Item = &'a ParamType>>(name: &str, kinds: I) -> String {
+    let kinds: Vec<_> = kinds.map(type_name).collect();
+    format!("{}({})", name, kinds.join(","))
+}
+
+fn event_signature(event: &Event) -> String {
+    signature(&event.name, event.inputs.iter().map(|input| &input.kind))
+}
+
+fn event_topic(event: &Event) -> H256 {
+    keccak256(event_signature(event).as_bytes()).into()
+}
+
+fn function_signature(function: &Function) -> String {
+    signature(&function.name, function.inputs.iter().map(|input| &input.kind))
+}
+
+/// fails unless `event` has a parameter `name` of `kind`.
+fn require_event_field(contract: &str, event: &Event, name: &str, kind: &ParamType) -> Result<()> {
+    match event.inputs.iter().find(|input| input.name == name) {
+        Some(input) if input.kind == *kind => Ok(()),
+        Some(input) => bail!(
+            "{}.{} field {} is {} instead of {}",
+            contract,
+            event.name,
+            name,
+            type_name(&input.kind),
+            type_name(kind)
+        ),
+        None => bail!(
+            "{}.{} has no {} field of type {}",
+            contract,
+            event.name,
+            name,
+            type_name(kind)
+        ),
+    }
+}
+
+/// Where an argument of a `ForeignBridge` function on deposits comes from.
+#[derive(Debug, Clone, PartialEq)]
+enum DepositArg {
+    Recipient,
+    /// value minted on foreign
+    Value,
+    /// id of the deposit, see `util::log_id`
+    TransactionHash,
+    /// field of the same name of the `HomeBridge.Deposit` event
+    Field(String),
+}
+
+/// A `ForeignBridge` function called with the arguments of a deposit,
+/// e.g. `deposit(address recipient, uint256 value, bytes32 transactionHash)`.
+#[derive(Debug, Clone)]
+struct DepositFunction {
+    function: Function,
+    args: Vec<DepositArg>,
+}
+
+impl DepositFunction {
+    /// resolves the arguments of `function` by name.
+    /// `recipient`, `value` and `transactionHash` are required.
+    /// every other argument is forwarded from the field of the same name of `deposit_event`.
+    fn new(function: &Function, deposit_event: &Event) -> Result<Self> {
+        let mut args = Vec::new();
+        for input in &function.inputs {
+            let (arg, kind) = match input.name.as_str() {
+                "recipient" => (DepositArg::Recipient, ParamType::Address),
+                "value" => (DepositArg::Value, ParamType::Uint(256)),
+                "transactionHash" => (DepositArg::TransactionHash, ParamType::FixedBytes(32)),
+                name => match deposit_event.inputs.iter().find(|field| field.name == name) {
+                    Some(field) => (DepositArg::Field(name.into()), field.kind.clone()),
+                    None => bail!(
+                        "ForeignBridge.{} argument {} is no field of HomeBridge.{}",
+                        function.name,
+                        name,
+                        deposit_event.name
+                    ),
+                },
+            };
+            if input.kind != kind {
+                bail!(
+                    "ForeignBridge.{} argument {} is {} instead of {}",
+                    function.name,
+                    input.name,
+                    type_name(&input.kind),
+                    type_name(&kind)
+                );
+            }
+            args.push(arg);
+        }
+        for required in &[
+            DepositArg::Recipient,
+            DepositArg::Value,
+            DepositArg::TransactionHash,
+        ] {
+            if !args.contains(required) {
+                bail!(
+                    "ForeignBridge.{} has no {:?} argument",
+                    function.name,
+                    required
+                );
+            }
+        }
+        Ok(DepositFunction {
+            function: function.clone(),
+            args,
+        })
+    }
+
+    fn tokens(&self, deposit: &DepositLog, value: U256, id: H256) -> Result<Vec<Token>> {
+        self.args
+            .iter()
+            .map(|arg| match *arg {
+                DepositArg::Recipient => Ok(Token::Address(deposit.recipient)),
+                DepositArg::Value => Ok(Token::Uint(value)),
+                DepositArg::TransactionHash => Ok(Token::FixedBytes(id.to_vec())),
+                DepositArg::Field(ref name) => deposit
+                    .field(name)
+                    .cloned()
+                    .ok_or_else(|| Error::from(format!("deposit has no {} field", name))),
+            })
+            .collect()
+    }
+
+    fn payload(&self, deposit: &DepositLog, value: U256, id: H256) -> Result<Bytes> {
+        let tokens = self.tokens(deposit, value, id)?;
+        Ok(self.function.encode_input(&tokens)?.into())
+    }
+
+    fn call(&self, deposit: &DepositLog, value: U256, id: H256) -> Result<ExpectedCall> {
+        Ok(ExpectedCall {
+            signature: function_signature(&self.function),
+            params: self.function
+                .inputs
+                .iter()
+                .map(|input| input.kind.clone())
+                .collect(),
+            args: self.tokens(deposit, value, id)?,
+        })
+    }
+}
+
+/// A `HomeBridge.Deposit` event decoded with the runtime ABI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepositLog {
+    pub recipient: Address,
+    /// value deposited on home
+    pub value: U256,
+    /// every field of the event by name, including `recipient` and `value`
+    pub fields: Vec<(String, Token)>,
+}
+
+impl DepositLog {
+    pub fn field(&self, name: &str) -> Option<&Token> {
+        self.fields
+            .iter()
+            .find(|field| field.0 == name)
+            .map(|field| &field.1)
+    }
+}
+
+/// ABI of the `HomeBridge` and `ForeignBridge` contracts loaded at runtime.
+///
+/// lets the bridge relay for contracts whose events have extra fields and
+/// whose `ForeignBridge.deposit` takes extra arguments without regenerating
+/// the compiled bindings in `contracts`. events and functions are resolved by
+/// name and validated once, at startup.
+#[derive(Debug, Clone)]
+pub struct RuntimeAbi {
+    deposit_event: Event,
+    withdraw_event: Event,
+    deposit: DepositFunction,
+    is_deposit_executed: DepositFunction,
+}
+
+impl RuntimeAbi {
+    /// loads the ABIs of `home.contract.abi` and `foreign.contract.abi`.
+    /// `None` unless at least one is set, the compiled ABI is used for the other.
+    pub fn load(config: &Config) -> Result<Option<Self>> {
+        if config.home.contract.abi.is_none() && config.foreign.contract.abi.is_none() {
+            return Ok(None);
+        }
+        let home = match config.home.contract.abi {
+            Some(ref path) => load_contract(path)?,
+            None => Contract::load(COMPILED_HOME_ABI.as_bytes())?,
+        };
+        let foreign = match config.foreign.contract.abi {
+            Some(ref path) => load_contract(path)?,
+            None => Contract::load(COMPILED_FOREIGN_ABI.as_bytes())?,
+        };
+        let abi = Self::new(&home, &foreign).chain_err(|| "Invalid runtime contract ABI")?;
+        info!(
+            "using runtime ABI. deposit relay calls ForeignBridge.{}",
+            function_signature(&abi.deposit.function)
+        );
+        Ok(Some(abi))
+    }
+
+    /// resolves and validates the events and functions the bridge uses.
+    pub fn new(home: &Contract, foreign: &Contract) -> Result<Self> {
+        let deposit_event = home.event("Deposit")?.clone();
+        require_event_field("HomeBridge", &deposit_event, "recipient", &ParamType::Address)?;
+        require_event_field("HomeBridge", &deposit_event, "value", &ParamType::Uint(256))?;
+
+        let withdraw = home.function("withdraw")?;
+        let withdraw_params = ExpectedCall::withdraw(&[], Vec::new()).params;
+        let params: Vec<_> = withdraw.inputs.iter().map(|input| input.kind.clone()).collect();
+        if params != withdraw_params {
+            bail!(
+                "HomeBridge.withdraw must be {}, got {}",
+                signature("withdraw", withdraw_params.iter()),
+                function_signature(withdraw)
+            );
+        }
+
+        let withdraw_event = foreign.event("Withdraw")?.clone();
+        require_event_field("ForeignBridge", &withdraw_event, "recipient", &ParamType::Address)?;
+        require_event_field("ForeignBridge", &withdraw_event, "value", &ParamType::Uint(256))?;
+        require_event_field(
+            "ForeignBridge",
+            &withdraw_event,
+            "homeGasPrice",
+            &ParamType::Uint(256),
+        )?;
+
+        let submit_signature = foreign.function("submitSignature")?;
+        if function_signature(submit_signature) != "submitSignature(bytes,bytes)" {
+            bail!(
+                "ForeignBridge.submitSignature must be submitSignature(bytes,bytes), got {}",
+                function_signature(submit_signature)
+            );
+        }
+
+        Ok(RuntimeAbi {
+            deposit: DepositFunction::new(foreign.function("deposit")?, &deposit_event)?,
+            is_deposit_executed: DepositFunction::new(
+                foreign.function("isDepositExecuted")?,
+                &deposit_event,
+            )?,
+            deposit_event,
+            withdraw_event,
+        })
+    }
+
+    pub fn deposits_filter(&self, address: Address) -> FilterBuilder {
+        FilterBuilder::default()
+            .address(vec![address])
+            .topics(Some(vec![event_topic(&self.deposit_event)]), None, None, None)
+    }
+
+    pub fn withdraws_filter(&self, address: Address) -> FilterBuilder {
+        FilterBuilder::default()
+            .address(vec![address])
+            .topics(Some(vec![event_topic(&self.withdraw_event)]), None, None, None)
+    }
+
+    /// decodes the `HomeBridge.Deposit` event in `log`.
+    pub fn parse_deposit(&self, log: &Log) -> Result<DepositLog> {
+        let parsed = self.deposit_event.parse_log(raw_log(log))?;
+        let fields: Vec<_> = parsed
+            .params
+            .into_iter()
+            .map(|param| (param.name, param.value))
+            .collect();
+        let recipient = match fields.iter().find(|field| field.0 == "recipient") {
+            Some(&(_, Token::Address(recipient))) => recipient,
+            _ => bail!("Deposit event has no recipient"),
+        };
+        let value = match fields.iter().find(|field| field.0 == "value") {
+            Some(&(_, Token::Uint(value))) => value,
+            _ => bail!("Deposit event has no value"),
+        };
+        Ok(DepositLog {
+            recipient,
+            value,
+            fields,
+        })
+    }
+
+    /// payload of the `ForeignBridge.deposit` call relaying `deposit` with `value` and `id`.
+    pub fn deposit_payload(&self, deposit: &DepositLog, value: U256, id: H256) -> Result<Bytes> {
+        self.deposit.payload(deposit, value, id)
+    }
+
+    /// `ForeignBridge.deposit` call `deposit_payload` encodes.
+    pub fn deposit_call(&self, deposit: &DepositLog, value: U256, id: H256) -> Result<ExpectedCall> {
+        self.deposit.call(deposit, value, id)
+    }
+
+    /// payload of the `ForeignBridge.isDepositExecuted` call for `deposit` with `value` and `id`.
+    pub fn deposit_executed_payload(
+        &self,
+        deposit: &DepositLog,
+        value: U256,
+        id: H256,
+    ) -> Result<Bytes> {
+        self.is_deposit_executed.payload(deposit, value, id)
+    }
+
+    /// message of the `ForeignBridge.Withdraw` event in `log`, see `MessageToMainnet::from_log`.
+    pub fn withdraw_message(&self, log: Log, unique_ids: bool) -> Result<MessageToMainnet> {
+        self.parse_withdraw(&log, unique_ids)
+            .chain_err(|| ErrorKind::InvalidLog(log))
+    }
+
+    fn parse_withdraw(&self, log: &Log, unique_ids: bool) -> Result<MessageToMainnet> {
+        check_log_data(log)?;
+        let parsed = self.withdraw_event.parse_log(raw_log(log))?;
+        let uint = |name: &str| -> Result<U256> {
+            match parsed.params.iter().find(|param| param.name == name) {
+                Some(param) => match param.value {
+                    Token::Uint(value) => Ok(value),
+                    _ => bail!("Withdraw event field {} is no uint", name),
+                },
+                None => bail!("Withdraw event has no {}", name),
+            }
+        };
+        let recipient = match parsed.params.iter().find(|param| param.name == "recipient") {
+            Some(param) => match param.value {
+                Token::Address(recipient) => recipient,
+                _ => bail!("Withdraw event field recipient is no address"),
+            },
+            None => bail!("Withdraw event has no recipient"),
+        };
+        Ok(MessageToMainnet {
+            recipient,
+            value: uint("value")?,
+            sidenet_transaction_hash: log_id(log, unique_ids)?,
+            mainnet_gas_price: uint("homeGasPrice")?,
+        })
+    }
+}
+
+fn load_contract(path: &Path) -> Result<Contract> {
+    let file = fs::File::open(path)
+        .chain_err(|| format!("Cannot open contract ABI at {}", path.to_string_lossy()))?;
+    Ok(Contract::load(file)
+        .chain_err(|| format!("Invalid contract ABI at {}", path.to_string_lossy()))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::{self, Contract, Token};
+    use serde_json::{self, Value};
+    use tiny_keccak::keccak256;
+    use web3::types::{FilterBuilder, H256, Log, U256};
+    use bridge::verify_call_data;
+    use contracts::{foreign, home};
+    use message_to_mainnet::MessageToMainnet;
+    use util::web3_filter;
+    use super::{RuntimeAbi, COMPILED_FOREIGN_ABI, COMPILED_HOME_ABI};
+
+    fn param(name: &str, kind: &str) -> Value {
+        json!({"name": name, "type": kind})
+    }
+
+    fn event_param(name: &str, kind: &str) -> Value {
+        json!({"name": name, "type": kind, "indexed": false})
+    }
+
+    fn function(name: &str, inputs: Vec<Value>) -> Value {
+        json!({
+            "type": "function",
+            "name": name,
+            "inputs": inputs,
+            "outputs": [],
+            "constant": false,
+            "payable": false,
+        })
+    }
+
+    fn event(name: &str, inputs: Vec<Value>) -> Value {
+        json!({"type": "event", "name": name, "inputs": inputs, "anonymous": false})
+    }
+
+    fn filter_json(filter: FilterBuilder) -> Value {
+        serde_json::to_value(filter.build()).unwrap()
+    }
+
+    fn contract(abi: Vec<Value>) -> Contract {
+        Contract::load(serde_json::to_string(&abi).unwrap().as_bytes()).unwrap()
+    }
+
+    /// `HomeBridge` of a fork whose deposits carry a memo and a fee.
+    fn fork_home() -> Contract {
+        contract(vec![
+            event(
+                "Deposit",
+                vec![
+                    event_param("recipient", "address"),
+                    event_param("value", "uint256"),
+                    event_param("memo", "bytes32"),
+                    event_param("fee", "uint256"),
+                ],
+            ),
+            function(
+                "withdraw",
+                vec![
+                    param("vs", "uint8[]"),
+                    param("rs", "bytes32[]"),
+                    param("ss", "bytes32[]"),
+                    param("message", "bytes"),
+                ],
+            ),
+        ])
+    }
+
+    fn fork_deposit_args() -> Vec<Value> {
+        vec![
+            param("recipient", "address"),
+            param("value", "uint256"),
+            param("memo", "bytes32"),
+            param("transactionHash", "bytes32"),
+        ]
+    }
+
+    /// `ForeignBridge` of the fork. `deposit` forwards the memo, `Withdraw` has a reference.
+    fn fork_foreign(deposit_args: Vec<Value>) -> Contract {
+        contract(vec![
+            function("deposit", deposit_args.clone()),
+            function("isDepositExecuted", deposit_args),
+            function(
+                "submitSignature",
+                vec![param("signature", "bytes"), param("message", "bytes")],
+            ),
+            event(
+                "Withdraw",
+                vec![
+                    event_param("recipient", "address"),
+                    event_param("reference", "bytes32"),
+                    event_param("value", "uint256"),
+                    event_param("homeGasPrice", "uint256"),
+                ],
+            ),
+        ])
+    }
+
+    fn fork_abi() -> RuntimeAbi {
+        RuntimeAbi::new(&fork_home(), &fork_foreign(fork_deposit_args())).unwrap()
+    }
+
+    fn fork_deposit_log() -> Log {
+        let data = ethabi::encode(&[
+            Token::Address(0xaa.into()),
+            Token::Uint(0xf0.into()),
+            Token::FixedBytes(vec![0x11; 32]),
+            Token::Uint(3.into()),
+        ]);
+        Log {
+            topics: vec![keccak256(b"Deposit(address,uint256,bytes32,uint256)").into()],
+            data: data.into(),
+            transaction_hash: Some(0xcc.into()),
+            ..Default::default()
+        }
+    }
+
+    fn compiled_abi() -> RuntimeAbi {
+        let home = Contract::load(COMPILED_HOME_ABI.as_bytes()).unwrap();
+        let foreign = Contract::load(COMPILED_FOREIGN_ABI.as_bytes()).unwrap();
+        RuntimeAbi::new(&home, &foreign).unwrap()
+    }
+
+    #[test]
+    fn test_compiled_abi_matches_bindings() {
+        let abi = compiled_abi();
+        let home = home::HomeBridge::default();
+        let expected = web3_filter(home.events().deposit().create_filter(), 0xff.into());
+        assert_eq!(filter_json(expected), filter_json(abi.deposits_filter(0xff.into())));
+
+        let data = ethabi::encode(&[Token::Address(0xaa.into()), Token::Uint(0xf0.into())]);
+        let log = Log {
+            topics: vec![keccak256(b"Deposit(address,uint256)").into()],
+            data: data.into(),
+            transaction_hash: Some(0xcc.into()),
+            ..Default::default()
+        };
+        let deposit = abi.parse_deposit(&log).unwrap();
+        let payload = abi.deposit_payload(&deposit, deposit.value, 0xcc.into())
+            .unwrap();
+        let compiled = foreign::ForeignBridge::default().functions().deposit().input(
+            deposit.recipient,
+            deposit.value,
+            H256::from(0xcc).0,
+        );
+        assert_eq!(compiled, payload.0);
+    }
+
+    #[test]
+    fn test_fork_deposit() {
+        let abi = fork_abi();
+        let log = fork_deposit_log();
+        let deposit = abi.parse_deposit(&log).unwrap();
+        assert_eq!(deposit.recipient, 0xaa.into());
+        assert_eq!(deposit.value, 0xf0.into());
+        assert_eq!(4, deposit.fields.len());
+        assert_eq!(Some(&Token::Uint(3.into())), deposit.field("fee"));
+
+        let payload = abi.deposit_payload(&deposit, 0xf.into(), 0xcc.into()).unwrap();
+        let selector = &keccak256(b"deposit(address,uint256,bytes32,bytes32)")[..4];
+        assert_eq!(selector, &payload.0[..4]);
+        let expected = ethabi::encode(&[
+            Token::Address(0xaa.into()),
+            Token::Uint(0xf.into()),
+            Token::FixedBytes(vec![0x11; 32]),
+            Token::FixedBytes(H256::from(0xcc).to_vec()),
+        ]);
+        assert_eq!(expected, payload.0[4..].to_vec());
+
+        let call = abi.deposit_call(&deposit, 0xf.into(), 0xcc.into()).unwrap();
+        assert_eq!("deposit(address,uint256,bytes32,bytes32)", call.signature);
+        verify_call_data(&call, &payload.0).unwrap();
+        let other = abi.deposit_call(&deposit, 0xe.into(), 0xcc.into()).unwrap();
+        assert!(verify_call_data(&other, &payload.0).is_err());
+
+        let executed = abi.deposit_executed_payload(&deposit, 0xf.into(), 0xcc.into())
+            .unwrap();
+        let selector = &keccak256(b"isDepositExecuted(address,uint256,bytes32,bytes32)")[..4];
+        assert_eq!(selector, &executed.0[..4]);
+        assert_eq!(payload.0[4..], executed.0[4..]);
+    }
+
+    #[test]
+    fn test_fork_deposit_of_other_event() {
+        let abi = fork_abi();
+        let mut log = fork_deposit_log();
+        log.topics = vec![keccak256(b"Deposit(address,uint256)").into()];
+        assert!(abi.parse_deposit(&log).is_err());
+        let truncated = Log {
+            data: vec![0u8; 64].into(),
+            ..fork_deposit_log()
+        };
+        assert!(abi.parse_deposit(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_fork_withdraw() {
+        let abi = fork_abi();
+        let data = ethabi::encode(&[
+            Token::Address(0xaa.into()),
+            Token::FixedBytes(vec![0x22; 32]),
+            Token::Uint(0xf0.into()),
+            Token::Uint(20.into()),
+        ]);
+        let log = Log {
+            topics: vec![keccak256(b"Withdraw(address,bytes32,uint256,uint256)").into()],
+            data: data.into(),
+            transaction_hash: Some(0xcc.into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            json!([[log.topics[0]]]),
+            filter_json(abi.withdraws_filter(0xff.into()))["topics"]
+        );
+        assert_eq!(
+            MessageToMainnet {
+                recipient: 0xaa.into(),
+                value: U256::from(0xf0),
+                sidenet_transaction_hash: 0xcc.into(),
+                mainnet_gas_price: 20.into(),
+            },
+            abi.withdraw_message(log.clone(), false).unwrap()
+        );
+        let unmined = Log {
+            transaction_hash: None,
+            ..log
+        };
+        assert!(abi.withdraw_message(unmined, false).is_err());
+    }
+
+    #[test]
+    fn test_fork_validation() {
+        // deposit arguments must be fields of the deposit event
+        let mut args = fork_deposit_args();
+        args.push(param("nonce", "uint256"));
+        let error = RuntimeAbi::new(&fork_home(), &fork_foreign(args)).unwrap_err();
+        assert!(error.to_string().contains("nonce"));
+
+        // of the same type
+        let mut args = fork_deposit_args();
+        args[2] = param("memo", "bytes");
+        assert!(RuntimeAbi::new(&fork_home(), &fork_foreign(args)).is_err());
+
+        // the transaction hash is required
+        let mut args = fork_deposit_args();
+        args.pop();
+        let error = RuntimeAbi::new(&fork_home(), &fork_foreign(args)).unwrap_err();
+        assert!(error.to_string().contains("TransactionHash"));
+
+        // the deposit event needs a recipient
+        let home = contract(vec![
+            event(
+                "Deposit",
+                vec![event_param("to", "address"), event_param("value", "uint256")],
+            ),
+            function("withdraw", vec![]),
+        ]);
+        let error = RuntimeAbi::new(&home, &fork_foreign(fork_deposit_args())).unwrap_err();
+        assert!(error.to_string().contains("recipient"));
+
+        // withdraw can't take extra arguments
+        let home = contract(vec![
+            event(
+                "Deposit",
+                vec![
+                    event_param("recipient", "address"),
+                    event_param("value", "uint256"),
+                ],
+            ),
+            function(
+                "withdraw",
+                vec![
+                    param("vs", "uint8[]"),
+                    param("rs", "bytes32[]"),
+                    param("ss", "bytes32[]"),
+                    param("message", "bytes"),
+                    param("memo", "bytes32"),
+                ],
+            ),
+        ]);
+        let args = vec![
+            param("recipient", "address"),
+            param("value", "uint256"),
+            param("transactionHash", "bytes32"),
+        ];
+        let error = RuntimeAbi::new(&home, &fork_foreign(args)).unwrap_err();
+        assert!(error.to_string().contains("HomeBridge.withdraw"));
+    }
+}
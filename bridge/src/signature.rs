@@ -2,10 +2,13 @@
 /// conversion from/to byte vectors.
 /// from/to v, r, s components.
 
-use ethereum_types::H256;
+use ethereum_types::{Address, H256};
 use ethabi;
+use secp256k1;
+use tiny_keccak::keccak256;
 
 use error::Error;
+use remote_signer::message_hash;
 
 pub const SIGNATURE_LENGTH: usize = 65;
 
@@ -41,13 +44,72 @@ impl Signature {
     pub fn to_payload(&self) -> Vec<u8> {
         ethabi::encode(&[ethabi::Token::Bytes(self.to_bytes())])
     }
+
+    /// the account whose `eth_sign` of `message` this is, as `ecrecover` of the contracts
+    /// recovers it. `v` is 27 or 28, or 0 or 1.
+    pub fn recover(&self, message: &[u8]) -> Result<Address, Error> {
+        let recovery_id = match self.v {
+            v @ 0 | v @ 1 => v,
+            v @ 27 | v @ 28 => v - 27,
+            v => bail!("signature has invalid v {}", v),
+        };
+        let mut rs = [0u8; 64];
+        rs[..32].copy_from_slice(&self.r.0);
+        rs[32..].copy_from_slice(&self.s.0);
+        let recovery_id = secp256k1::RecoveryId::parse(recovery_id)
+            .map_err(|err| format!("signature has invalid recovery id: {:?}", err))?;
+        let public = secp256k1::recover(
+            &secp256k1::Message::parse(&message_hash(message).0),
+            &secp256k1::Signature::parse(&rs),
+            &recovery_id,
+        ).map_err(|err| format!("signature recovers to no account: {:?}", err))?;
+        // the account is the last 20 bytes of the hash of the uncompressed key without its prefix
+        Ok(Address::from(&keccak256(&public.serialize()[1..])[12..]))
+    }
 }
 
 #[cfg(test)]
 mod test {
     use quickcheck::TestResult;
+    use rustc_hex::FromHex;
     use super::*;
 
+    /// message of a withdraw of 0xf0 to 0xaff3454fce5edbc8cca8697c15331677e6ebcccc
+    const MESSAGE: &str = "aff3454fce5edbc8cca8697c15331677e6ebcccc\
+                           00000000000000000000000000000000000000000000000000000000000000f0\
+                           884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364\
+                           000000000000000000000000000000000000000000000000000000003b9aca00";
+
+    /// `eth_sign` of `MESSAGE` by 0x2c7536e3605d9c16a7a3d7b1898e529396a65c23
+    const SIGNATURE: &str = "ab919755bd2d15f06535730e3270151c678930427095d0f376d8b48bbf734ac4\
+                             34b3bb45bd91db1419d1e0d28b33111206527ee7d2b0af2ec9deadd9b0e0006d1b";
+
+    fn signature(hex: &str) -> Signature {
+        Signature::from_bytes(&hex.from_hex::<Vec<u8>>().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_recover_the_signer_of_a_message() {
+        let message = MESSAGE.from_hex::<Vec<u8>>().unwrap();
+        let signer: Address = "2c7536e3605d9c16a7a3d7b1898e529396a65c23".parse().unwrap();
+        let mut signature = signature(SIGNATURE);
+        assert_eq!(signer, signature.recover(&message).unwrap());
+
+        signature.v -= 27;
+        assert_eq!(signer, signature.recover(&message).unwrap());
+
+        // the signature of another message recovers to another account
+        let mut other = message.clone();
+        other[115] = 0x01;
+        assert!(signature.recover(&other).ok() != Some(signer));
+
+        signature.v = 29;
+        assert_eq!(
+            "signature has invalid v 29",
+            signature.recover(&message).unwrap_err().to_string()
+        );
+    }
+
     quickcheck! {
         fn quickcheck_signature_roundtrips(v: u8, r_raw: Vec<u8>, s_raw: Vec<u8>) -> TestResult {
             if r_raw.len() != 32 || s_raw.len() != 32 {
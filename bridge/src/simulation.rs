@@ -0,0 +1,446 @@
+//! Replays captured rpc traffic through the bridge components without touching real chains.
+//!
+//! a capture written by `transport::RecordingTransport` is turned into a recording of both chains:
+//! the sequence of latest block numbers, all logs and the responses of every other request.
+//! each component runs against its own replay of the recording until the block numbers run out.
+//! transactions and signatures are fabricated, so nothing is ever sent.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use futures::{future, Stream};
+use serde_json;
+use timer::Timer;
+use web3::{self, RequestId, Transport};
+use web3::helpers::build_request;
+use web3::types::{H256, H520};
+use rpc;
+use app::{App, Connections};
+use bridge::{create_deposit_relay, create_withdraw_confirm, create_withdraw_relay,
+             enabled_components, Component, RelayCounts};
+use config::{Acknowledgements, Config};
+use contracts::{foreign, home};
+use database::Database;
+use runtime_abi::RuntimeAbi;
+use error::{Error, Result, ResultExt};
+use transport::CaptureEntry;
+use tunables::SharedTunables;
+
+/// parses a capture written by `RecordingTransport`. one entry per line.
+pub fn read_capture(capture: &str) -> Result<Vec<CaptureEntry>> {
+    capture
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line).chain_err(|| format!("Invalid capture entry in line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Recorded state of a chain.
+#[derive(Debug, Default, Clone)]
+struct ChainRecording {
+    /// responses of `eth_blockNumber` in captured order
+    block_numbers: Vec<rpc::Value>,
+    /// every log of every captured `eth_getLogs` response
+    logs: Vec<rpc::Value>,
+    /// all other requests
+    entries: Vec<CaptureEntry>,
+}
+
+impl ChainRecording {
+    fn new(entries: &[CaptureEntry], chain: &str) -> Self {
+        let mut recording = ChainRecording::default();
+        for entry in entries.iter().filter(|entry| entry.chain == chain) {
+            match (entry.method.as_str(), &entry.error) {
+                // failed polls of the chain head carry no information
+                ("eth_blockNumber", &Some(_)) => (),
+                ("eth_blockNumber", &None) => recording.block_numbers.push(entry.response.clone()),
+                ("eth_getLogs", &None) => {
+                    let logs = entry.response.as_array().cloned().unwrap_or_default();
+                    for log in logs {
+                        if !recording.logs.contains(&log) {
+                            recording.logs.push(log);
+                        }
+                    }
+                }
+                _ => recording.entries.push(entry.clone()),
+            }
+        }
+        recording
+    }
+}
+
+/// parses a hex encoded quantity.
+fn quantity(value: &rpc::Value) -> Option<u64> {
+    value
+        .as_str()
+        .and_then(|s| u64::from_str_radix(s.trim_left_matches("0x"), 16).ok())
+}
+
+/// returns `true` if `value` matches `filter`, which is either `null`, a value or an array of values.
+fn matches_any(filter: &rpc::Value, value: &rpc::Value) -> bool {
+    match *filter {
+        rpc::Value::Null => true,
+        rpc::Value::Array(ref values) => values.contains(value),
+        ref single => single == value,
+    }
+}
+
+/// returns `true` if `log` is selected by the `eth_getLogs` `filter`.
+pub fn filter_matches(filter: &rpc::Value, log: &rpc::Value) -> bool {
+    let block = quantity(&log["blockNumber"]);
+    let after_from = match (quantity(&filter["fromBlock"]), block) {
+        (Some(from), Some(block)) => block >= from,
+        _ => true,
+    };
+    let before_to = match (quantity(&filter["toBlock"]), block) {
+        (Some(to), Some(block)) => block <= to,
+        _ => true,
+    };
+    let topics = filter["topics"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .all(|(i, topic)| matches_any(topic, &log["topics"][i]));
+    after_from && before_to && topics && matches_any(&filter["address"], &log["address"])
+}
+
+/// Requests answered by a `ReplayTransport`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ReplayStats {
+    /// number of requests per `chain method`
+    pub requests: BTreeMap<String, u64>,
+    /// transactions and signatures answered with fabricated responses
+    pub fabricated: u64,
+    /// request the recording had no response for. the replay fails there
+    pub unanswered: Option<String>,
+    /// set once a chain ran out of block numbers. the replay ends there
+    pub finished: bool,
+}
+
+#[derive(Debug)]
+struct Replay {
+    home: ChainRecording,
+    foreign: ChainRecording,
+    next_id: RequestId,
+    stats: ReplayStats,
+}
+
+impl Replay {
+    fn respond(
+        &mut self,
+        chain: &str,
+        method: &str,
+        params: &[rpc::Value],
+    ) -> ::std::result::Result<rpc::Value, String> {
+        *self.stats
+            .requests
+            .entry(format!("{} {}", chain, method))
+            .or_insert(0) += 1;
+
+        match method {
+            "eth_sendTransaction" | "personal_sendTransaction" => {
+                self.stats.fabricated += 1;
+                return Ok(json!(H256::from(self.stats.fabricated)));
+            }
+            "eth_sign" | "personal_sign" => {
+                self.stats.fabricated += 1;
+                let mut signature = [0u8; 65];
+                signature[..32].copy_from_slice(&H256::from(self.stats.fabricated).0);
+                signature[64] = 27;
+                return Ok(json!(H520::from(signature)));
+            }
+            _ => (),
+        }
+
+        let recording = if chain == "home" {
+            &mut self.home
+        } else {
+            &mut self.foreign
+        };
+        match method {
+            "eth_blockNumber" => {
+                if recording.block_numbers.is_empty() {
+                    self.stats.finished = true;
+                    return Err(format!("{} recording has no more block numbers", chain));
+                }
+                return Ok(recording.block_numbers.remove(0));
+            }
+            "eth_getLogs" => {
+                let filter = params.get(0).cloned().unwrap_or(rpc::Value::Null);
+                let logs = recording
+                    .logs
+                    .iter()
+                    .filter(|log| filter_matches(&filter, log))
+                    .cloned()
+                    .collect();
+                return Ok(rpc::Value::Array(logs));
+            }
+            _ => (),
+        }
+
+        let entry = recording
+            .entries
+            .iter()
+            .find(|entry| entry.method == method && entry.params.as_slice() == params)
+            .cloned();
+        match entry {
+            Some(CaptureEntry {
+                error: Some(error), ..
+            }) => Err(error),
+            Some(entry) => Ok(entry.response),
+            None => {
+                let request = format!("{} {} {}", chain, method, json!(params));
+                if self.stats.unanswered.is_none() {
+                    self.stats.unanswered = Some(request.clone());
+                }
+                Err(format!("recording has no response to {}", request))
+            }
+        }
+    }
+}
+
+/// Answers requests from a recording of both chains.
+/// never forwards anything to a node.
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    chain: &'static str,
+    replay: Arc<Mutex<Replay>>,
+}
+
+impl ReplayTransport {
+    /// home and foreign transports replaying `entries`.
+    pub fn pair(entries: &[CaptureEntry]) -> (Self, Self) {
+        let replay = Arc::new(Mutex::new(Replay {
+            home: ChainRecording::new(entries, "home"),
+            foreign: ChainRecording::new(entries, "foreign"),
+            next_id: 0,
+            stats: ReplayStats::default(),
+        }));
+        let home = ReplayTransport {
+            chain: "home",
+            replay: replay.clone(),
+        };
+        let foreign = ReplayTransport {
+            chain: "foreign",
+            replay,
+        };
+        (home, foreign)
+    }
+
+    /// statistics of both transports of the pair.
+    pub fn stats(&self) -> ReplayStats {
+        self.replay
+            .lock()
+            .expect("replay is never poisoned; qed")
+            .stats
+            .clone()
+    }
+}
+
+impl Transport for ReplayTransport {
+    type Out = web3::Result<rpc::Value>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let mut replay = self.replay.lock().expect("replay is never poisoned; qed");
+        replay.next_id += 1;
+        (replay.next_id, build_request(replay.next_id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let request = serde_json::to_value(&request).unwrap_or(rpc::Value::Null);
+        let method = request["method"].as_str().unwrap_or_default();
+        let params = request["params"].as_array().cloned().unwrap_or_default();
+        let response = self.replay
+            .lock()
+            .expect("replay is never poisoned; qed")
+            .respond(self.chain, method, &params);
+        match response {
+            Ok(response) => Box::new(future::ok(response)),
+            Err(err) => Box::new(future::err(web3::Error::Transport(err))),
+        }
+    }
+}
+
+/// `config` adjusted for replays. nothing waits for the wall clock:
+/// poll intervals are zero and checks that depend on wall clock time are disabled.
+pub fn simulation_config(config: Config) -> Config {
+    let mut config = config;
+    config.home.poll_interval = Duration::from_secs(0);
+    config.foreign.poll_interval = Duration::from_secs(0);
+    config.startup_jitter = None;
+    config.poll_jitter = None;
+    config.watchdog = None;
+    config.clock_drift = None;
+    config.withdraw_cost = None;
+    config.chain_stall = None;
+    config.txpool = None;
+    config.spend_limits = None;
+    config.acknowledgements = Acknowledgements::Off;
+    config.circuit_breaker = None;
+    config.reorg_monitor = None;
+    config.rpc_trace = None;
+    config.shutdown_report = None;
+    config
+}
+
+/// Outcome of replaying a recording through one component.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ComponentReport {
+    pub component: String,
+    pub wall_time_ms: u64,
+    /// last block checked by the component
+    pub checked_block: Option<u64>,
+    pub counts: RelayCounts,
+    /// relayed transactions per second of wall time
+    pub relays_per_sec: f64,
+    pub replay: ReplayStats,
+    /// error that stopped the component before the recording ended
+    pub error_chain: Vec<String>,
+}
+
+/// Outcome of `simulate`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SimulationReport {
+    pub components: Vec<ComponentReport>,
+}
+
+impl SimulationReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("SimulationReport always serializes; qed")
+    }
+}
+
+/// polls `stream` until it ends or fails. returns the last yielded block.
+fn run_component<S>(stream: &mut S) -> (Option<u64>, Option<Error>)
+where
+    S: Stream<Item = u64, Error = Error>,
+{
+    let mut checked = None;
+    for item in stream.wait() {
+        match item {
+            Ok(block) => checked = Some(block),
+            Err(err) => return (checked, Some(err)),
+        }
+    }
+    (checked, None)
+}
+
+fn replay_app(
+    config: &Config,
+    entries: &[CaptureEntry],
+) -> Result<(Arc<App<ReplayTransport>>, ReplayTransport)> {
+    let (home, foreign) = ReplayTransport::pair(entries);
+    let config = simulation_config(config.clone());
+    let app = App {
+        tunables: SharedTunables::of(&config),
+        config,
+        database_path: PathBuf::new(),
+        connections: Connections {
+            home: home.clone(),
+            foreign,
+        },
+        home_bridge: home::HomeBridge::default(),
+        foreign_bridge: foreign::ForeignBridge::default(),
+        abi: RuntimeAbi::load(&config)?,
+        node_gas_price: Default::default(),
+        chain_clocks: Default::default(),
+        node_versions: Default::default(),
+        timer: Timer::default(),
+    };
+    Ok((Arc::new(app), home))
+}
+
+/// replays the recording in `capture` through deposit relay, withdraw relay and withdraw confirm,
+/// starting at the blocks checked in `database`. `database` is never written.
+/// components disabled by the config are skipped.
+pub fn simulate(config: &Config, database: &Database, capture: &str) -> Result<SimulationReport> {
+    let entries = read_capture(capture)?;
+    let mut components = Vec::new();
+
+    for component in enabled_components(config) {
+        let (app, transport) = replay_app(config, &entries)?;
+        let started = Instant::now();
+        let (checked_block, error, counts) = match component {
+            Component::DepositRelay => {
+                let mut relay = create_deposit_relay(app, database);
+                let (checked, error) = run_component(&mut relay);
+                (checked, error, relay.take_counts())
+            }
+            Component::WithdrawRelay => {
+                let mut relay = create_withdraw_relay(app, database);
+                let (checked, error) = run_component(&mut relay);
+                (checked, error, relay.take_counts())
+            }
+            Component::WithdrawConfirm => {
+                let mut confirm = create_withdraw_confirm(app, database);
+                let (checked, error) = run_component(&mut confirm);
+                (checked, error, confirm.take_counts())
+            }
+        };
+        let elapsed = started.elapsed();
+        let replay = transport.stats();
+        let wall_time_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos() / 1_000_000);
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+        components.push(ComponentReport {
+            component: format!("{:?}", component),
+            wall_time_ms,
+            checked_block,
+            counts,
+            relays_per_sec: if secs > 0.0 {
+                counts.relayed as f64 / secs
+            } else {
+                0.0
+            },
+            // running out of block numbers is the regular end of a replay
+            error_chain: match error {
+                Some(ref err) if !replay.finished || replay.unanswered.is_some() => {
+                    err.iter().map(|e| e.to_string()).collect()
+                }
+                _ => Vec::new(),
+            },
+            replay,
+        });
+    }
+
+    Ok(SimulationReport { components })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::filter_matches;
+
+    #[test]
+    fn test_filter_matches() {
+        let log = json!({
+            "address": "0x0000000000000000000000000000000000000001",
+            "blockNumber": "0x10",
+            "topics": ["0xaa", "0xbb"],
+        });
+        let filter = json!({
+            "address": ["0x0000000000000000000000000000000000000001"],
+            "fromBlock": "0xb",
+            "toBlock": "0x10",
+            "topics": [["0xaa"], null],
+        });
+        assert!(filter_matches(&filter, &log));
+
+        let mut later = filter.clone();
+        later["fromBlock"] = json!("0x11");
+        assert!(!filter_matches(&later, &log));
+
+        let mut other_topic = filter.clone();
+        other_topic["topics"] = json!([["0xcc"]]);
+        assert!(!filter_matches(&other_topic, &log));
+
+        let mut other_address = filter.clone();
+        other_address["address"] = json!(["0x0000000000000000000000000000000000000002"]);
+        assert!(!filter_matches(&other_address, &log));
+    }
+}
@@ -0,0 +1,303 @@
+//! Builders of the logs of the bridge contracts for tests.
+//!
+//! topics and data are encoded from the same contract definitions the components decode
+//! them with. every builder starts from a valid mined log and lets a test change one field,
+//! or break the log with `topic` and `truncated`.
+
+use ethabi::{self, Token, TopicFilter};
+use web3::types::{Address, H256, Log, U256};
+use contracts::{foreign, home};
+
+/// recipient, value and transaction hash of the fixtures unless set
+pub const RECIPIENT: &str = "aff3454fce5edbc8cca8697c15331677e6ebcccc";
+pub const TRANSACTION_HASH: &str =
+    "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364";
+pub const VALUE: u64 = 0xf0;
+/// home gas price of withdraw fixtures unless set
+pub const HOME_GAS_PRICE: u64 = 8_000_000_000;
+
+fn first_topic(filter: TopicFilter) -> H256 {
+    let topics: Vec<ethabi::Hash> = filter.topic0.into();
+    topics[0]
+}
+
+/// topic of `HomeBridge.Deposit`.
+pub fn deposit_topic() -> H256 {
+    first_topic(home::HomeBridge::default().events().deposit().create_filter())
+}
+
+/// topic of `ForeignBridge.Withdraw`.
+pub fn withdraw_topic() -> H256 {
+    first_topic(foreign::ForeignBridge::default().events().withdraw().create_filter())
+}
+
+/// topic of `ForeignBridge.CollectedSignatures`.
+pub fn collected_signatures_topic() -> H256 {
+    first_topic(
+        foreign::ForeignBridge::default()
+            .events()
+            .collected_signatures()
+            .create_filter(),
+    )
+}
+
+/// Fields of a fixture log not defined by its event.
+#[derive(Debug, Clone)]
+struct Mined {
+    transaction_hash: Option<H256>,
+    log_index: Option<U256>,
+    block_number: Option<U256>,
+    /// replaces the topic of the event
+    topic: Option<H256>,
+    /// bytes of the data kept
+    truncated: Option<usize>,
+}
+
+impl Default for Mined {
+    fn default() -> Self {
+        Mined {
+            transaction_hash: Some(TRANSACTION_HASH.into()),
+            log_index: None,
+            block_number: None,
+            topic: None,
+            truncated: None,
+        }
+    }
+}
+
+impl Mined {
+    fn log(&self, topic: H256, params: &[Token]) -> Log {
+        let mut data = ethabi::encode(params);
+        if let Some(len) = self.truncated {
+            data.truncate(len);
+        }
+        Log {
+            topics: vec![self.topic.unwrap_or(topic)],
+            data: data.into(),
+            transaction_hash: self.transaction_hash,
+            log_index: self.log_index,
+            block_number: self.block_number,
+            ..Default::default()
+        }
+    }
+}
+
+/// `Default` and the setters of the fields every fixture log has.
+macro_rules! fixture_builder {
+    ($builder: ident) => {
+        impl Default for $builder {
+            fn default() -> Self {
+                $builder::new()
+            }
+        }
+
+        impl $builder {
+            /// hash of the transaction that emitted the log.
+            pub fn tx_hash<H: Into<H256>>(mut self, hash: H) -> Self {
+                self.mined.transaction_hash = Some(hash.into());
+                self
+            }
+
+            /// a log without transaction hash, as of a pending transaction.
+            pub fn unmined(mut self) -> Self {
+                self.mined.transaction_hash = None;
+                self
+            }
+
+            pub fn log_index<I: Into<U256>>(mut self, index: I) -> Self {
+                self.mined.log_index = Some(index.into());
+                self
+            }
+
+            pub fn block_number<N: Into<U256>>(mut self, number: N) -> Self {
+                self.mined.block_number = Some(number.into());
+                self
+            }
+
+            /// a log with `topic` instead of the topic of its event.
+            pub fn topic<H: Into<H256>>(mut self, topic: H) -> Self {
+                self.mined.topic = Some(topic.into());
+                self
+            }
+
+            /// a log with only the first `len` bytes of its data.
+            pub fn truncated(mut self, len: usize) -> Self {
+                self.mined.truncated = Some(len);
+                self
+            }
+        }
+    };
+}
+
+/// Builds `HomeBridge.Deposit(address recipient, uint256 value)` logs.
+#[derive(Debug, Clone)]
+pub struct DepositLogBuilder {
+    recipient: Address,
+    value: U256,
+    mined: Mined,
+}
+
+impl DepositLogBuilder {
+    pub fn new() -> Self {
+        DepositLogBuilder {
+            recipient: RECIPIENT.into(),
+            value: VALUE.into(),
+            mined: Mined::default(),
+        }
+    }
+
+    pub fn recipient<A: Into<Address>>(mut self, recipient: A) -> Self {
+        self.recipient = recipient.into();
+        self
+    }
+
+    pub fn value<V: Into<U256>>(mut self, value: V) -> Self {
+        self.value = value.into();
+        self
+    }
+
+    pub fn build(&self) -> Log {
+        self.mined.log(
+            deposit_topic(),
+            &[Token::Address(self.recipient), Token::Uint(self.value)],
+        )
+    }
+}
+
+fixture_builder!(DepositLogBuilder);
+
+/// Builds `ForeignBridge.Withdraw(address recipient, uint256 value, uint256 homeGasPrice)` logs.
+#[derive(Debug, Clone)]
+pub struct WithdrawLogBuilder {
+    recipient: Address,
+    value: U256,
+    home_gas_price: U256,
+    mined: Mined,
+}
+
+impl WithdrawLogBuilder {
+    pub fn new() -> Self {
+        WithdrawLogBuilder {
+            recipient: RECIPIENT.into(),
+            value: VALUE.into(),
+            home_gas_price: HOME_GAS_PRICE.into(),
+            mined: Mined::default(),
+        }
+    }
+
+    pub fn recipient<A: Into<Address>>(mut self, recipient: A) -> Self {
+        self.recipient = recipient.into();
+        self
+    }
+
+    pub fn value<V: Into<U256>>(mut self, value: V) -> Self {
+        self.value = value.into();
+        self
+    }
+
+    pub fn home_gas_price<V: Into<U256>>(mut self, home_gas_price: V) -> Self {
+        self.home_gas_price = home_gas_price.into();
+        self
+    }
+
+    pub fn build(&self) -> Log {
+        self.mined.log(
+            withdraw_topic(),
+            &[
+                Token::Address(self.recipient),
+                Token::Uint(self.value),
+                Token::Uint(self.home_gas_price),
+            ],
+        )
+    }
+}
+
+fixture_builder!(WithdrawLogBuilder);
+
+/// Builds `ForeignBridge.CollectedSignatures(address authorityResponsibleForRelay,
+/// bytes32 messageHash)` logs.
+#[derive(Debug, Clone)]
+pub struct CollectedSignaturesLogBuilder {
+    authority: Address,
+    message_hash: H256,
+    mined: Mined,
+}
+
+impl CollectedSignaturesLogBuilder {
+    pub fn new() -> Self {
+        CollectedSignaturesLogBuilder {
+            authority: RECIPIENT.into(),
+            message_hash: VALUE.into(),
+            mined: Mined::default(),
+        }
+    }
+
+    /// authority responsible for relaying the message.
+    pub fn authority<A: Into<Address>>(mut self, authority: A) -> Self {
+        self.authority = authority.into();
+        self
+    }
+
+    pub fn message_hash<H: Into<H256>>(mut self, message_hash: H) -> Self {
+        self.message_hash = message_hash.into();
+        self
+    }
+
+    pub fn build(&self) -> Log {
+        self.mined.log(
+            collected_signatures_topic(),
+            &[
+                Token::Address(self.authority),
+                Token::FixedBytes(self.message_hash.to_vec()),
+            ],
+        )
+    }
+}
+
+fixture_builder!(CollectedSignaturesLogBuilder);
+
+#[cfg(test)]
+mod tests {
+    use rustc_hex::FromHex;
+    use web3::types::H256;
+    use super::{collected_signatures_topic, deposit_topic, withdraw_topic,
+                CollectedSignaturesLogBuilder, DepositLogBuilder, WithdrawLogBuilder};
+
+    #[test]
+    fn test_topics_of_the_contract_events() {
+        let topic = |hex: &str| -> H256 { hex.parse().unwrap() };
+        assert_eq!(
+            topic("e1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c"),
+            deposit_topic()
+        );
+        assert_eq!(
+            topic("f279e6a1f5e320cca91135676d9cb6e44ca8a08c0b88342bcdb1144f6511b568"),
+            withdraw_topic()
+        );
+        assert_eq!(
+            topic("eb043d149eedb81369bec43d4c3a3a53087debc88d2525f13bfaa3eecda28b5c"),
+            collected_signatures_topic()
+        );
+    }
+
+    #[test]
+    fn test_log_builders_encode_the_event_data() {
+        let deposit = "000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0".from_hex().unwrap();
+        assert_eq!(deposit, DepositLogBuilder::new().build().data.0);
+        assert_eq!(deposit, CollectedSignaturesLogBuilder::new().build().data.0);
+        let withdraw = "000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f000000000000000000000000000000000000000000000000000000001dcd65000".from_hex().unwrap();
+        assert_eq!(withdraw, WithdrawLogBuilder::new().build().data.0);
+    }
+
+    #[test]
+    fn test_log_builders_break_logs() {
+        let log = DepositLogBuilder::new()
+            .topic(withdraw_topic())
+            .truncated(32)
+            .unmined()
+            .build();
+        assert_eq!(vec![withdraw_topic()], log.topics);
+        assert_eq!(32, log.data.0.len());
+        assert_eq!(None, log.transaction_hash);
+    }
+}
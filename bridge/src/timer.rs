@@ -0,0 +1,292 @@
+//! Clock behind the timeouts, delays and intervals of the bridge.
+//!
+//! every component takes its `Timer` from the `App`. `Timer::default()` reads the time of the
+//! system and sleeps with tokio-timer, tests inject a `ManualClock` and advance it themselves.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use futures::{Async, Future, Poll, Stream};
+use futures::task::{self, Task};
+use tokio_timer::{self, TimerError};
+
+/// Source of the current time and of futures resolving once time passed.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// resolves once `duration` passed from now.
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// Future resolving once its duration passed on the clock it was created with.
+pub struct Sleep(Box<Future<Item = (), Error = TimerError> + Send>);
+
+impl Sleep {
+    pub fn new<F>(future: F) -> Self
+    where
+        F: Future<Item = (), Error = TimerError> + Send + 'static,
+    {
+        Sleep(Box::new(future))
+    }
+}
+
+impl Future for Sleep {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.0.poll()
+    }
+}
+
+impl fmt::Debug for Sleep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sleep").finish()
+    }
+}
+
+/// Handle to the `Clock` shared by the components of an `App`.
+#[derive(Clone)]
+pub struct Timer {
+    clock: Arc<Clock>,
+}
+
+impl Timer {
+    pub fn new<C: Clock + 'static>(clock: C) -> Self {
+        Timer {
+            clock: Arc::new(clock),
+        }
+    }
+
+    /// timer of a new `ManualClock`, returned with it.
+    pub fn manual() -> (Self, ManualClock) {
+        let clock = ManualClock::new();
+        (Timer::new(clock.clone()), clock)
+    }
+
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    pub fn sleep(&self, duration: Duration) -> Sleep {
+        self.clock.sleep(duration)
+    }
+
+    /// yields every `duration`. like the interval of tokio-timer the next period starts
+    /// once a tick is polled, missed ticks aren't caught up.
+    pub fn interval(&self, duration: Duration) -> Interval {
+        Interval {
+            sleep: self.sleep(duration),
+            timer: self.clone(),
+            duration,
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Timer::new(TokioClock::default())
+    }
+}
+
+impl fmt::Debug for Timer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Timer").finish()
+    }
+}
+
+/// Stream yielding every period of `Timer::interval`.
+#[derive(Debug)]
+pub struct Interval {
+    timer: Timer,
+    sleep: Sleep,
+    duration: Duration,
+}
+
+impl Stream for Interval {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        try_ready!(self.sleep.poll());
+        self.sleep = self.timer.sleep(self.duration);
+        Ok(Async::Ready(Some(())))
+    }
+}
+
+/// Time of the system, sleeping on the wheel of tokio-timer.
+#[derive(Clone, Default)]
+pub struct TokioClock {
+    timer: tokio_timer::Timer,
+}
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Sleep::new(self.timer.sleep(duration))
+    }
+}
+
+struct ManualTime {
+    now: Instant,
+    /// tasks of the sleeps that weren't due when last polled
+    waiting: Vec<Task>,
+}
+
+/// Clock that only moves when advanced, for deterministic tests of timeouts, backoffs and
+/// intervals. `advance` wakes the tasks of the sleeps it may have made due.
+#[derive(Clone)]
+pub struct ManualClock {
+    time: Arc<Mutex<ManualTime>>,
+}
+
+impl ManualClock {
+    /// clock starting at the current time of the system.
+    pub fn new() -> Self {
+        ManualClock {
+            time: Arc::new(Mutex::new(ManualTime {
+                now: Instant::now(),
+                waiting: Vec::new(),
+            })),
+        }
+    }
+
+    /// moves the clock `duration` forward.
+    pub fn advance(&self, duration: Duration) {
+        let waiting = {
+            let mut time = self.time
+                .lock()
+                .expect("no code panics while holding the lock; qed");
+            time.now += duration;
+            time.waiting.split_off(0)
+        };
+        for task in waiting {
+            task.notify();
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.time
+            .lock()
+            .expect("no code panics while holding the lock; qed")
+            .now
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Sleep::new(ManualSleep {
+            deadline: self.now() + duration,
+            time: self.time.clone(),
+        })
+    }
+}
+
+struct ManualSleep {
+    time: Arc<Mutex<ManualTime>>,
+    deadline: Instant,
+}
+
+impl Future for ManualSleep {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut time = self.time
+            .lock()
+            .expect("no code panics while holding the lock; qed");
+        if time.now >= self.deadline {
+            return Ok(Async::Ready(()));
+        }
+        if !time.waiting.iter().any(|task| task.will_notify_current()) {
+            time.waiting.push(task::current());
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use futures::{executor, Async, Future};
+    use futures::executor::Notify;
+    use super::Timer;
+
+    /// counts its notifications
+    #[derive(Default)]
+    struct Count(AtomicUsize);
+
+    impl Notify for Count {
+        fn notify(&self, _id: usize) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_manual_sleep_resolves_once_advanced_past_its_duration() {
+        let (timer, clock) = Timer::manual();
+        let start = timer.now();
+        let notify = Arc::new(Count::default());
+        let mut sleep = executor::spawn(timer.sleep(Duration::from_millis(10)));
+
+        assert_eq!(Async::NotReady, sleep.poll_future_notify(&notify, 0).unwrap());
+        clock.advance(Duration::from_millis(9));
+        assert_eq!(1, notify.0.load(Ordering::SeqCst));
+        assert_eq!(Async::NotReady, sleep.poll_future_notify(&notify, 0).unwrap());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(2, notify.0.load(Ordering::SeqCst));
+        assert_eq!(Async::Ready(()), sleep.poll_future_notify(&notify, 0).unwrap());
+        assert_eq!(start + Duration::from_millis(10), timer.now());
+
+        // nothing waits anymore
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(2, notify.0.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_manual_sleep_of_zero_is_ready() {
+        let (timer, _clock) = Timer::manual();
+        assert_eq!((), timer.sleep(Duration::from_secs(0)).wait().unwrap());
+    }
+
+    #[test]
+    fn test_manual_interval_does_not_catch_up_missed_ticks() {
+        let (timer, clock) = Timer::manual();
+        let notify = Arc::new(Count::default());
+        let mut interval = executor::spawn(timer.interval(Duration::from_millis(10)));
+        let mut poll = || interval.poll_stream_notify(&notify, 0).unwrap();
+
+        assert_eq!(Async::NotReady, poll());
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(Async::Ready(Some(())), poll());
+        assert_eq!(Async::NotReady, poll());
+
+        // the next period starts at the tick that was polled
+        clock.advance(Duration::from_millis(35));
+        assert_eq!(Async::Ready(Some(())), poll());
+        assert_eq!(Async::NotReady, poll());
+        clock.advance(Duration::from_millis(9));
+        assert_eq!(Async::NotReady, poll());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(Async::Ready(Some(())), poll());
+    }
+
+    #[test]
+    fn test_default_timer_sleeps_on_the_system_clock() {
+        let timer = Timer::default();
+        let start = timer.now();
+        timer.sleep(Duration::from_millis(1)).wait().unwrap();
+        assert!(timer.now() > start);
+    }
+}
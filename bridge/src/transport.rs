@@ -0,0 +1,318 @@
+//! Transport middleware tracing the json-rpc traffic with a node.
+//!
+//! middleware is only wrapped around a transport if enabled in `RpcTraceConfig`,
+//! so disabled tracing costs nothing.
+
+use std::path::Path;
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use rpc;
+use serde_json;
+use web3::{self, RequestId, Transport};
+use capture::{unix_time, CaptureWriter};
+use config::CaptureConfig;
+use error::Error;
+
+/// Replaces secret params.
+const REDACTED: &str = "<redacted>";
+
+/// Replaces passwords in the `params` of `personal_*` calls.
+pub fn redact(method: &str, params: &mut [rpc::Value]) {
+    let index = match method {
+        "personal_sendTransaction" | "personal_signTransaction" | "personal_unlockAccount" => 1,
+        "personal_sign" => 2,
+        _ => return,
+    };
+    if let Some(param) = params.get_mut(index) {
+        *param = rpc::Value::String(REDACTED.into());
+    }
+}
+
+/// Truncates `text` to at most `max_size` bytes.
+pub fn truncate(mut text: String, max_size: usize) -> String {
+    if text.len() <= max_size {
+        return text;
+    }
+    let len = text.len();
+    let mut end = max_size;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    text.push_str(&format!("... ({} bytes)", len));
+    text
+}
+
+/// Method and redacted params of `request`.
+pub fn method_call(request: &rpc::Call) -> (String, Vec<rpc::Value>) {
+    let request = serde_json::to_value(request).unwrap_or(rpc::Value::Null);
+    let method = request["method"].as_str().unwrap_or_default().to_owned();
+    let mut params = request["params"].as_array().cloned().unwrap_or_default();
+    redact(&method, &mut params);
+    (method, params)
+}
+
+/// Logs every request and response at debug level.
+#[derive(Debug, Clone)]
+pub struct LoggingTransport<T> {
+    inner: T,
+    chain: &'static str,
+    max_size: usize,
+}
+
+impl<T> LoggingTransport<T> {
+    pub fn new(inner: T, chain: &'static str, max_size: usize) -> Self {
+        LoggingTransport {
+            inner,
+            chain,
+            max_size,
+        }
+    }
+}
+
+impl<T: Transport> Transport for LoggingTransport<T> {
+    type Out = Logged<T::Out>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let (method, params) = method_call(&request);
+        let params = serde_json::to_string(&params).unwrap_or_default();
+        debug!(
+            target: "bridge::rpc",
+            "{} request {}: {} {}",
+            self.chain,
+            id,
+            method,
+            truncate(params, self.max_size)
+        );
+        Logged {
+            inner: self.inner.send(id, request),
+            chain: self.chain,
+            id,
+            max_size: self.max_size,
+        }
+    }
+}
+
+/// Response of a `LoggingTransport`.
+pub struct Logged<F> {
+    inner: F,
+    chain: &'static str,
+    id: RequestId,
+    max_size: usize,
+}
+
+impl<F: Future<Item = rpc::Value, Error = web3::Error>> Future for Logged<F> {
+    type Item = rpc::Value;
+    type Error = web3::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = self.inner.poll();
+        match result {
+            Ok(Async::NotReady) => (),
+            Ok(Async::Ready(ref response)) => {
+                debug!(
+                    target: "bridge::rpc",
+                    "{} response {}: {}",
+                    self.chain,
+                    self.id,
+                    truncate(response.to_string(), self.max_size)
+                );
+            }
+            Err(ref err) => {
+                debug!(
+                    target: "bridge::rpc",
+                    "{} request {} failed: {:?}",
+                    self.chain,
+                    self.id,
+                    err
+                );
+            }
+        }
+        result
+    }
+}
+
+/// A request and its response as stored in a capture file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureEntry {
+    /// `home` or `foreign`
+    pub chain: String,
+    pub method: String,
+    /// passwords are redacted
+    pub params: Vec<rpc::Value>,
+    #[serde(default)]
+    pub response: rpc::Value,
+    /// set if the request failed. `response` is `null` then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// unix time in seconds of the response. `None` in captures of older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+}
+
+/// Appends capture entries to a file, one json object per line.
+/// the file is written on a dedicated thread, see `capture::CaptureWriter`.
+#[derive(Debug)]
+pub struct Recorder {
+    writer: CaptureWriter,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Recorder::with_rotation(path, None)
+    }
+
+    /// the file is rotated by `rotation` if set.
+    pub fn with_rotation<P: AsRef<Path>>(
+        path: P,
+        rotation: Option<CaptureConfig>,
+    ) -> Result<Self, Error> {
+        Ok(Recorder {
+            writer: CaptureWriter::create(path, rotation)?,
+        })
+    }
+
+    /// capturing is best effort. failing to write never fails or delays the request.
+    fn record(&self, entry: &CaptureEntry) {
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!(target: "bridge::rpc", "cannot serialize rpc capture entry: {}", err);
+                return;
+            }
+        };
+        self.writer.write(line, entry.time.unwrap_or_else(unix_time));
+    }
+
+    /// number of entries dropped because the writer fell behind.
+    pub fn dropped(&self) -> usize {
+        self.writer.dropped()
+    }
+
+    /// blocks until every recorded entry is written.
+    pub fn flush(&self) {
+        self.writer.flush()
+    }
+}
+
+/// Records every request and response with a `Recorder`.
+#[derive(Debug, Clone)]
+pub struct RecordingTransport<T> {
+    inner: T,
+    chain: &'static str,
+    recorder: Arc<Recorder>,
+}
+
+impl<T> RecordingTransport<T> {
+    pub fn new(inner: T, chain: &'static str, recorder: Arc<Recorder>) -> Self {
+        RecordingTransport {
+            inner,
+            chain,
+            recorder,
+        }
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    type Out = Recorded<T::Out>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let (method, params) = method_call(&request);
+        Recorded {
+            inner: self.inner.send(id, request),
+            entry: Some(CaptureEntry {
+                chain: self.chain.into(),
+                method,
+                params,
+                response: rpc::Value::Null,
+                error: None,
+                time: None,
+            }),
+            recorder: self.recorder.clone(),
+        }
+    }
+}
+
+/// Response of a `RecordingTransport`.
+pub struct Recorded<F> {
+    inner: F,
+    /// taken once the response is recorded
+    entry: Option<CaptureEntry>,
+    recorder: Arc<Recorder>,
+}
+
+impl<F: Future<Item = rpc::Value, Error = web3::Error>> Future for Recorded<F> {
+    type Item = rpc::Value;
+    type Error = web3::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = self.inner.poll();
+        let entry = match result {
+            Ok(Async::NotReady) => None,
+            Ok(Async::Ready(ref response)) => self.entry.take().map(|mut entry| {
+                entry.response = response.clone();
+                entry.time = Some(unix_time());
+                entry
+            }),
+            Err(ref err) => self.entry.take().map(|mut entry| {
+                entry.error = Some(format!("{:?}", err));
+                entry.time = Some(unix_time());
+                entry
+            }),
+        };
+        if let Some(entry) = entry {
+            self.recorder.record(&entry);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use super::{redact, truncate, CaptureEntry};
+
+    #[test]
+    fn test_redact_personal_passwords() {
+        let mut params = vec![json!({"from": "0x01"}), json!("hunter2")];
+        redact("personal_sendTransaction", &mut params);
+        assert_eq!(vec![json!({"from": "0x01"}), json!("<redacted>")], params);
+
+        let mut params = vec![json!("0x1234"), json!("0x01"), json!("hunter2")];
+        redact("personal_sign", &mut params);
+        assert_eq!(json!("<redacted>"), params[2]);
+
+        let mut params = vec![json!("0x01"), json!("latest")];
+        redact("eth_getBalance", &mut params);
+        assert_eq!(vec![json!("0x01"), json!("latest")], params);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!("0x1234", truncate("0x1234".into(), 6));
+        assert_eq!("0x12... (6 bytes)", truncate("0x1234".into(), 4));
+        // never splits a character
+        assert_eq!("a... (3 bytes)", truncate("aä".into(), 2));
+    }
+
+    #[test]
+    fn test_capture_entry_keeps_null_responses() {
+        let entry: CaptureEntry = serde_json::from_str(
+            r#"{"chain":"home","method":"eth_getTransactionReceipt","params":["0x01"],"response":null}"#,
+        ).unwrap();
+        assert_eq!(json!(null), entry.response);
+        assert_eq!(None, entry.error);
+        assert_eq!(
+            r#"{"chain":"home","method":"eth_getTransactionReceipt","params":["0x01"],"response":null}"#,
+            serde_json::to_string(&entry).unwrap()
+        );
+    }
+}
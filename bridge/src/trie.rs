@@ -0,0 +1,402 @@
+//! Merkle patricia tries of block receipts and proofs of their items.
+//!
+//! tries are built at once from all their items, as receipt tries are.
+//! a proof of a key lists the encoded nodes referenced by hash on its path from
+//! the root, root first. nodes shorter than a hash are embedded in their parent.
+
+use tiny_keccak::keccak256;
+use web3::types::H256;
+use error::Result;
+use rlp::{self, Item};
+
+/// nibbles of `bytes`, high nibble first.
+fn nibbles(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .flat_map(|byte| vec![byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+/// hex prefix encoding of the nibbles of a leaf or extension.
+fn hex_prefix(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 };
+    let mut encoded = Vec::with_capacity(nibbles.len() / 2 + 1);
+    let rest = if nibbles.len() % 2 == 1 {
+        encoded.push(((flag + 1) << 4) | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        encoded.push(flag << 4);
+        nibbles
+    };
+    for pair in rest.chunks(2) {
+        encoded.push((pair[0] << 4) | pair[1]);
+    }
+    encoded
+}
+
+/// nibbles of a hex prefix encoded path and whether it belongs to a leaf.
+fn decode_hex_prefix(encoded: &[u8]) -> Result<(Vec<u8>, bool)> {
+    let first = match encoded.first() {
+        Some(first) => *first,
+        None => bail!("trie node path is empty"),
+    };
+    let flag = first >> 4;
+    if flag > 3 {
+        bail!("trie node path has invalid flag {}", flag);
+    }
+    let mut path = Vec::new();
+    if flag % 2 == 1 {
+        path.push(first & 0x0f);
+    } else if first & 0x0f != 0 {
+        bail!("trie node path has invalid padding");
+    }
+    path.extend(nibbles(&encoded[1..]));
+    Ok((path, flag >= 2))
+}
+
+/// `node` as referenced by its parent: embedded if shorter than a hash.
+fn reference(node: Vec<u8>) -> Vec<u8> {
+    if node.len() < 32 {
+        node
+    } else {
+        rlp::encode_bytes(&keccak256(&node))
+    }
+}
+
+/// encodes the node of `items`, whose keys share their first `depth` nibbles.
+/// `items` are sorted by key. the nodes on the path of `target` that are
+/// referenced by hash are pushed to `proof`, deepest first.
+fn build(
+    items: &[(Vec<u8>, &[u8])],
+    depth: usize,
+    target: Option<&[u8]>,
+    proof: &mut Vec<Vec<u8>>,
+) -> Vec<u8> {
+    let node = if items.len() == 1 {
+        let (key, value) = (&items[0].0, items[0].1);
+        rlp::encode_list(&[
+            rlp::encode_bytes(&hex_prefix(&key[depth..], true)),
+            rlp::encode_bytes(value),
+        ])
+    } else {
+        let first = &items[0].0;
+        let last = &items[items.len() - 1].0;
+        // sorted keys share the prefix their first and last key share
+        let shared = first[depth..]
+            .iter()
+            .zip(&last[depth..])
+            .take_while(|&(a, b)| a == b)
+            .count();
+        if shared > 0 {
+            let path = &first[depth..depth + shared];
+            let on_path = target.map_or(false, |target| target[depth..].starts_with(path));
+            let child_target = if on_path { target } else { None };
+            let child = build(items, depth + shared, child_target, proof);
+            rlp::encode_list(&[
+                rlp::encode_bytes(&hex_prefix(path, false)),
+                reference(child),
+            ])
+        } else {
+            let mut branch = Vec::with_capacity(17);
+            let mut value = rlp::encode_bytes(&[]);
+            let mut rest = items;
+            // a key ending here sorts first
+            if rest[0].0.len() == depth {
+                value = rlp::encode_bytes(rest[0].1);
+                rest = &rest[1..];
+            }
+            for nibble in 0..16u8 {
+                let count = rest.iter()
+                    .take_while(|&&(ref key, _)| key[depth] == nibble)
+                    .count();
+                if count == 0 {
+                    branch.push(rlp::encode_bytes(&[]));
+                    continue;
+                }
+                let on_path = target.map_or(false, |target| target.get(depth) == Some(&nibble));
+                let child_target = if on_path { target } else { None };
+                let child = build(&rest[..count], depth + 1, child_target, proof);
+                branch.push(reference(child));
+                rest = &rest[count..];
+            }
+            branch.push(value);
+            rlp::encode_list(&branch)
+        }
+    };
+    if target.is_some() && node.len() >= 32 {
+        proof.push(node.clone());
+    }
+    node
+}
+
+/// `items` as sorted `(nibbles of key, value)`.
+fn sorted(items: &[(Vec<u8>, Vec<u8>)]) -> Vec<(Vec<u8>, &[u8])> {
+    let mut sorted = items
+        .iter()
+        .map(|&(ref key, ref value)| (nibbles(key), &value[..]))
+        .collect::<Vec<_>>();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+    sorted
+}
+
+/// root of the trie of `items`, which have distinct keys.
+pub fn root(items: &[(Vec<u8>, Vec<u8>)]) -> H256 {
+    if items.is_empty() {
+        return keccak256(&rlp::encode_bytes(&[])).into();
+    }
+    keccak256(&build(&sorted(items), 0, None, &mut Vec::new())).into()
+}
+
+/// proof of `key` in the trie of `items`, root first.
+/// proves that `key` isn't in the trie if it isn't one of the keys of `items`.
+pub fn prove(items: &[(Vec<u8>, Vec<u8>)], key: &[u8]) -> Vec<Vec<u8>> {
+    if items.is_empty() {
+        return vec![rlp::encode_bytes(&[])];
+    }
+    let target = nibbles(key);
+    let mut proof = Vec::new();
+    let root = build(&sorted(items), 0, Some(&target), &mut proof);
+    // a root shorter than a hash still needs to be part of the proof
+    if root.len() < 32 {
+        proof.push(root);
+    }
+    proof.reverse();
+    proof
+}
+
+/// the value of `key` in the trie with `root` according to `proof`.
+/// `None` if the proof shows that `key` isn't in the trie.
+/// fails if `proof` doesn't belong to `root`.
+pub fn verify_proof(root: H256, key: &[u8], proof: &[Vec<u8>]) -> Result<Option<Vec<u8>>> {
+    let key = nibbles(key);
+    let mut remaining = &key[..];
+    let mut proof = proof.iter();
+    let mut node = match proof.next() {
+        Some(node) if H256::from(keccak256(node)) == root => node.clone(),
+        Some(_) => bail!("proof doesn't start with the root node {:?}", root),
+        None => bail!("proof is empty"),
+    };
+    let value = loop {
+        if node == rlp::encode_bytes(&[]) {
+            break None;
+        }
+        let next = {
+            let items = rlp::decode_list(&node)?;
+            let child = match items.len() {
+                17 => match remaining.split_first() {
+                    None => {
+                        let value = rlp::decode_bytes(items[16])?;
+                        break if value.is_empty() {
+                            None
+                        } else {
+                            Some(value.to_vec())
+                        };
+                    }
+                    Some((nibble, rest)) => {
+                        remaining = rest;
+                        items[*nibble as usize]
+                    }
+                },
+                2 => {
+                    let (path, leaf) = decode_hex_prefix(rlp::decode_bytes(items[0])?)?;
+                    if leaf {
+                        break if path[..] == remaining[..] {
+                            Some(rlp::decode_bytes(items[1])?.to_vec())
+                        } else {
+                            None
+                        };
+                    }
+                    if !remaining.starts_with(&path) {
+                        break None;
+                    }
+                    remaining = &remaining[path.len()..];
+                    items[1]
+                }
+                len => bail!("trie node has {} items", len),
+            };
+            match rlp::decode(child)? {
+                Item::List(_) => child.to_vec(),
+                Item::Bytes(bytes) if bytes.is_empty() => break None,
+                Item::Bytes(hash) if hash.len() == 32 => match proof.next() {
+                    Some(next) if keccak256(next)[..] == hash[..] => next.clone(),
+                    Some(_) => bail!("proof node doesn't match the hash {:?} of its parent", hash),
+                    None => bail!("proof ends before the node of hash {:?}", hash),
+                },
+                Item::Bytes(bytes) => {
+                    bail!("trie node reference of {} bytes is invalid", bytes.len())
+                }
+            }
+        };
+        node = next;
+    };
+    if proof.next().is_some() {
+        bail!("proof has nodes beyond the path of the key");
+    }
+    Ok(value)
+}
+
+/// items of the trie of a list, e.g. the receipts of a block, keyed by `rlp(index)`.
+pub fn ordered_items(values: Vec<Vec<u8>>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| (rlp::encode_u64(index as u64), value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use web3::types::H256;
+    use super::{decode_hex_prefix, hex_prefix, ordered_items, prove, root, verify_proof};
+
+    fn items(pairs: &[(&str, &str)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        pairs
+            .iter()
+            .map(|&(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    fn puppy() -> Vec<(Vec<u8>, Vec<u8>)> {
+        items(&[
+            ("do", "verb"),
+            ("dog", "puppy"),
+            ("doge", "coin"),
+            ("horse", "stallion"),
+        ])
+    }
+
+    fn hash(s: &str) -> H256 {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_hex_prefix() {
+        assert_eq!(vec![0x11, 0x23, 0x45], hex_prefix(&[1, 2, 3, 4, 5], false));
+        assert_eq!(vec![0x00, 0x01, 0x23, 0x45], hex_prefix(&[0, 1, 2, 3, 4, 5], false));
+        assert_eq!(vec![0x20, 0x0f, 0x1c, 0xb8], hex_prefix(&[0, 15, 1, 12, 11, 8], true));
+        assert_eq!(vec![0x3f, 0x1c, 0xb8], hex_prefix(&[15, 1, 12, 11, 8], true));
+        assert_eq!(vec![0x20], hex_prefix(&[], true));
+        let paths = [(vec![1, 2, 3], true), (vec![0, 1, 2, 3], false), (vec![], false)];
+        for &(ref path, leaf) in &paths {
+            assert_eq!(
+                (path.clone(), leaf),
+                decode_hex_prefix(&hex_prefix(path, leaf)).unwrap()
+            );
+        }
+        assert!(decode_hex_prefix(&[]).is_err());
+        assert!(decode_hex_prefix(&[0x01]).is_err());
+        assert!(decode_hex_prefix(&[0x40]).is_err());
+    }
+
+    #[test]
+    fn test_root_fixtures() {
+        assert_eq!(
+            hash("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"),
+            root(&[])
+        );
+        assert_eq!(
+            hash("8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"),
+            root(&items(&[("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")]))
+        );
+        let puppy = puppy();
+        let expected = hash("5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84");
+        assert_eq!(expected, root(&puppy));
+        // the root doesn't depend on the order of the items
+        let mut reversed = puppy.clone();
+        reversed.reverse();
+        assert_eq!(expected, root(&reversed));
+    }
+
+    #[test]
+    fn test_prove_every_key() {
+        let puppy = puppy();
+        let root_hash = root(&puppy);
+        for &(ref key, ref value) in &puppy {
+            let proof = prove(&puppy, key);
+            assert_eq!(Some(value.clone()), verify_proof(root_hash, key, &proof).unwrap());
+        }
+
+        // receipt tries have keys `rlp(index)`: `0x80`, `0x01` ... `0x7f`, `0x8180` ...
+        for count in &[1usize, 2, 16, 17, 128, 129, 300] {
+            let values = (0..*count)
+                .map(|index| format!("receipt {:0>40}", index).into_bytes())
+                .collect();
+            let trie = ordered_items(values);
+            let root_hash = root(&trie);
+            for &(ref key, ref value) in &trie {
+                let proof = prove(&trie, key);
+                assert_eq!(
+                    Some(value.clone()),
+                    verify_proof(root_hash, key, &proof).unwrap(),
+                    "{} items, key {:?}",
+                    count,
+                    key
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_prove_small_values() {
+        // nodes shorter than a hash are embedded in their parent
+        let trie = ordered_items((0..20u8).map(|index| vec![index]).collect());
+        let root_hash = root(&trie);
+        for &(ref key, ref value) in &trie {
+            let proof = prove(&trie, key);
+            assert_eq!(Some(value.clone()), verify_proof(root_hash, key, &proof).unwrap());
+        }
+        let single = items(&[("a", "b")]);
+        let proof = prove(&single, b"a");
+        assert_eq!(1, proof.len());
+        assert_eq!(Some(b"b".to_vec()), verify_proof(root(&single), b"a", &proof).unwrap());
+    }
+
+    #[test]
+    fn test_prove_absence() {
+        let puppy = puppy();
+        let root_hash = root(&puppy);
+        for key in &["d", "dogs", "doe", "horses", "cat", ""] {
+            let proof = prove(&puppy, key.as_bytes());
+            assert_eq!(
+                None,
+                verify_proof(root_hash, key.as_bytes(), &proof).unwrap(),
+                "{}",
+                key
+            );
+        }
+        assert_eq!(None, verify_proof(root(&[]), b"a", &prove(&[], b"a")).unwrap());
+    }
+
+    #[test]
+    fn test_verify_rejects_tampering() {
+        let trie = ordered_items(
+            (0..50)
+                .map(|index| format!("receipt {:0>40}", index).into_bytes())
+                .collect(),
+        );
+        let root_hash = root(&trie);
+        let key = &trie[7].0;
+        let proof = prove(&trie, key);
+        assert!(proof.len() > 1);
+
+        // another root
+        assert!(verify_proof(root(&trie[1..]), key, &proof).is_err());
+        // a modified node
+        for index in 0..proof.len() {
+            let mut modified = proof.clone();
+            let last = modified[index].len() - 1;
+            modified[index][last] ^= 1;
+            assert!(verify_proof(root_hash, key, &modified).is_err(), "node {}", index);
+        }
+        // a missing node
+        assert!(verify_proof(root_hash, key, &proof[..proof.len() - 1]).is_err());
+        assert!(verify_proof(root_hash, key, &[]).is_err());
+        // an extra node
+        let mut extended = proof.clone();
+        extended.push(proof[0].clone());
+        assert!(verify_proof(root_hash, key, &extended).is_err());
+        // the proof of another key
+        let other = prove(&trie, &trie[8].0);
+        assert!(verify_proof(root_hash, key, &other).is_err());
+    }
+}
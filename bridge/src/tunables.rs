@@ -0,0 +1,410 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use serde_json::{self, Value};
+use config::{Config, RelaySchedulingConfig, Transactions};
+
+/// settings that can be reloaded while the bridge runs, as named in the config file.
+/// a setting is tunable if it or one of its tables is listed.
+/// every other setting, e.g. `home.ipc`, `authorities` or the contract addresses,
+/// requires a restart.
+pub const TUNABLE_SETTINGS: &[&str] = &[
+    "transactions",
+    "home.poll_interval",
+    "foreign.poll_interval",
+    "relay_scheduling",
+    "log_level",
+];
+
+/// authority credentials, applied without a restart by `Bridge::rotate_authority`
+/// rather than through the `Tunables`. see `Config::with_credentials_of`.
+pub const CREDENTIAL_SETTINGS: &[&str] = &[
+    "home.account",
+    "home.signer",
+    "home.password_file",
+    "home.submitter",
+    "foreign.account",
+    "foreign.signer",
+    "foreign.password_file",
+    "foreign.submitter",
+];
+
+/// `true` if `setting` is `listed` or in one of its listed tables.
+fn is_listed(listed: &[&str], setting: &str) -> bool {
+    listed.iter().any(|table| {
+        setting == *table
+            || (setting.starts_with(table) && setting[table.len()..].starts_with('.'))
+    })
+}
+
+/// `true` if `setting`, e.g. `transactions.deposit_relay.gas_price`, can be reloaded.
+pub fn is_tunable(setting: &str) -> bool {
+    is_listed(TUNABLE_SETTINGS, setting)
+}
+
+/// `true` if `setting`, e.g. `home.submitter.account`, is an authority credential.
+pub fn is_credential(setting: &str) -> bool {
+    is_listed(CREDENTIAL_SETTINGS, setting)
+}
+
+/// Settings of the running bridge that can change without a restart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tunables {
+    /// gas settings of all transactions
+    pub txs: Transactions,
+    pub home_poll_interval: Duration,
+    pub foreign_poll_interval: Duration,
+    pub relay_scheduling: Option<RelaySchedulingConfig>,
+    pub log_level: Option<String>,
+}
+
+impl Tunables {
+    pub fn of(config: &Config) -> Self {
+        Tunables {
+            txs: config.txs.clone(),
+            home_poll_interval: config.home.poll_interval,
+            foreign_poll_interval: config.foreign.poll_interval,
+            relay_scheduling: config.relay_scheduling.clone(),
+            log_level: config.log_level.clone(),
+        }
+    }
+
+    /// `config` with these tunables.
+    pub fn apply_to(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+        config.txs = self.txs.clone();
+        config.home.poll_interval = self.home_poll_interval;
+        config.foreign.poll_interval = self.foreign_poll_interval;
+        config.relay_scheduling = self.relay_scheduling.clone();
+        config.log_level = self.log_level.clone();
+        config
+    }
+
+    /// poll interval of home if `on_home`, foreign otherwise.
+    pub fn poll_interval(&self, on_home: bool) -> Duration {
+        if on_home {
+            self.home_poll_interval
+        } else {
+            self.foreign_poll_interval
+        }
+    }
+}
+
+/// Cheaply cloneable handle to the `Tunables` shared by all components.
+///
+/// components read it whenever they use a tunable, so a reload applies
+/// from the next transaction or wait on.
+#[derive(Debug, Clone)]
+pub struct SharedTunables {
+    inner: Arc<RwLock<Tunables>>,
+}
+
+impl SharedTunables {
+    pub fn new(tunables: Tunables) -> Self {
+        SharedTunables {
+            inner: Arc::new(RwLock::new(tunables)),
+        }
+    }
+
+    /// handle to the tunables of `config`.
+    pub fn of(config: &Config) -> Self {
+        Self::new(Tunables::of(config))
+    }
+
+    /// snapshot of the current tunables.
+    pub fn get(&self) -> Tunables {
+        self.inner
+            .read()
+            .expect("no code panics while holding the lock; qed")
+            .clone()
+    }
+
+    pub fn set(&self, tunables: Tunables) {
+        *self.inner
+            .write()
+            .expect("no code panics while holding the lock; qed") = tunables;
+    }
+}
+
+/// A setting that differs between two configs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// e.g. `transactions.deposit_relay.gas_price`
+    pub setting: String,
+    /// `None` if unset
+    pub from: Option<String>,
+    /// `None` if unset
+    pub to: Option<String>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.setting,
+            self.from.as_ref().map_or("unset", String::as_str),
+            self.to.as_ref().map_or("unset", String::as_str)
+        )
+    }
+}
+
+/// collects the settings in `value` by their dotted path.
+fn flatten(prefix: &str, value: &Value, settings: &mut BTreeMap<String, String>) {
+    match *value {
+        Value::Object(ref object) => for (key, value) in object {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            flatten(&path, value, settings);
+        },
+        Value::Null => {}
+        ref value => {
+            settings.insert(prefix.to_owned(), value.to_string());
+        }
+    }
+}
+
+fn settings(config: &Config) -> BTreeMap<String, String> {
+    let value = serde_json::to_value(config).expect("serialization can't fail; qed");
+    let mut settings = BTreeMap::new();
+    flatten("", &value, &mut settings);
+    settings
+}
+
+/// every setting that differs between `running` and `reloaded`, ordered by name.
+/// passwords are never compared.
+pub fn diff_configs(running: &Config, reloaded: &Config) -> Vec<Change> {
+    let running = settings(running);
+    let mut reloaded = settings(reloaded);
+    let mut changes = Vec::new();
+    for (setting, from) in running {
+        let to = reloaded.remove(&setting);
+        if to.as_ref() != Some(&from) {
+            changes.push(Change {
+                setting,
+                from: Some(from),
+                to,
+            });
+        }
+    }
+    for (setting, to) in reloaded {
+        changes.push(Change {
+            setting,
+            from: None,
+            to: Some(to),
+        });
+    }
+    changes.sort_by(|a, b| a.setting.cmp(&b.setting));
+    changes
+}
+
+/// Outcome of reloading the config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reload {
+    /// changes of tunable settings, in effect now
+    pub applied: Vec<Change>,
+    /// changes of authority credentials, left to `Bridge::rotate_authority`
+    pub rotated: Vec<Change>,
+    /// changes of other settings, ignored until a restart
+    pub rejected: Vec<Change>,
+}
+
+impl Reload {
+    /// `true` if `setting` or one of its tables changed and was applied.
+    pub fn applied(&self, setting: &str) -> bool {
+        self.applied.iter().any(|change| {
+            change.setting == setting || change.setting.starts_with(&format!("{}.", setting))
+        })
+    }
+}
+
+/// applies the tunable settings of `reloaded` to `tunables` of the bridge running with `config`.
+/// changed credentials are returned as `rotated` for the caller to rotate to.
+/// changes of other settings are rejected. every change and its outcome is logged.
+pub fn reload(tunables: &SharedTunables, config: &Config, reloaded: &Config) -> Reload {
+    let running = tunables.get().apply_to(config);
+    let (applied, others): (Vec<_>, Vec<_>) = diff_configs(&running, reloaded)
+        .into_iter()
+        .partition(|change| is_tunable(&change.setting));
+    let (rotated, rejected): (Vec<_>, Vec<_>) = others
+        .into_iter()
+        .partition(|change| is_credential(&change.setting));
+    for change in &applied {
+        info!("config reload: applied {}", change);
+    }
+    for change in &rotated {
+        info!("config reload: rotating authority credentials, {}", change);
+    }
+    for change in &rejected {
+        warn!(
+            "config reload: rejected {}. this setting requires a restart",
+            change
+        );
+    }
+    if !applied.is_empty() {
+        tunables.set(Tunables::of(reloaded));
+    }
+    info!(
+        "config reload: {} changes applied, {} rotated, {} rejected",
+        applied.len(),
+        rotated.len(),
+        rejected.len()
+    );
+    Reload {
+        applied,
+        rotated,
+        rejected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use config::{Authorities, Config, Node, RelaySchedulingConfig};
+    use super::{is_credential, is_tunable, reload, Change, SharedTunables};
+
+    fn config() -> Config {
+        Config::builder()
+            .home(Node::new(1.into(), "/home.ipc".into()))
+            .foreign(Node::new(2.into(), "/foreign.ipc".into()))
+            .authorities(Authorities {
+                accounts: vec![1.into(), 2.into()],
+                required_signatures: 1,
+            })
+            .estimated_gas_cost_of_withdraw(0u64.into())
+            .max_total_home_contract_balance(0u64.into())
+            .max_single_deposit_value(0u64.into())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_is_tunable() {
+        assert!(is_tunable("transactions.deposit_relay.gas_price"));
+        assert!(is_tunable("home.poll_interval"));
+        assert!(is_tunable("relay_scheduling"));
+        assert!(!is_tunable("home.ipc"));
+        assert!(!is_tunable("home.poll_interval_extra"));
+        assert!(!is_tunable("authorities.accounts"));
+    }
+
+    #[test]
+    fn test_is_credential() {
+        assert!(is_credential("home.account"));
+        assert!(is_credential("foreign.submitter.account"));
+        assert!(!is_credential("home.accounts"));
+        assert!(!is_credential("authorities.accounts"));
+    }
+
+    #[test]
+    fn test_reload_applies_tunable_changes() {
+        let config = config();
+        let tunables = SharedTunables::of(&config);
+        let mut reloaded = config.clone();
+        reloaded.txs.deposit_relay.gas_price = 20;
+        reloaded.foreign.poll_interval = Duration::from_secs(5);
+        reloaded.relay_scheduling = Some(RelaySchedulingConfig {
+            max_in_flight: 8,
+            withdraw_share: 30,
+        });
+
+        let outcome = reload(&tunables, &config, &reloaded);
+        assert!(outcome.rejected.is_empty());
+        assert_eq!(
+            vec![
+                Change {
+                    setting: "foreign.poll_interval".into(),
+                    from: Some("1".into()),
+                    to: Some("5".into()),
+                },
+                Change {
+                    setting: "relay_scheduling.max_in_flight".into(),
+                    from: None,
+                    to: Some("8".into()),
+                },
+                Change {
+                    setting: "relay_scheduling.withdraw_share".into(),
+                    from: None,
+                    to: Some("30".into()),
+                },
+                Change {
+                    setting: "transactions.deposit_relay.gas_price".into(),
+                    from: Some("0".into()),
+                    to: Some("20".into()),
+                },
+            ],
+            outcome.applied
+        );
+        assert!(outcome.applied("foreign.poll_interval"));
+        assert!(!outcome.applied("home.poll_interval"));
+        let current = tunables.get();
+        assert_eq!(20, current.txs.deposit_relay.gas_price);
+        assert_eq!(Duration::from_secs(5), current.foreign_poll_interval);
+        assert_eq!(8, current.relay_scheduling.unwrap().max_in_flight);
+
+        // the running config is the applied one
+        let outcome = reload(&tunables, &config, &reloaded);
+        assert!(outcome.applied.is_empty());
+    }
+
+    #[test]
+    fn test_reload_rejects_identity_changes() {
+        let config = config();
+        let tunables = SharedTunables::of(&config);
+        let mut reloaded = config.clone();
+        reloaded.home.ipc = "/other-home.ipc".into();
+        reloaded.authorities.required_signatures = 2;
+
+        let outcome = reload(&tunables, &config, &reloaded);
+        assert!(outcome.applied.is_empty());
+        assert_eq!(
+            vec![
+                Change {
+                    setting: "authorities.required_signatures".into(),
+                    from: Some("1".into()),
+                    to: Some("2".into()),
+                },
+                Change {
+                    setting: "home.ipc".into(),
+                    from: Some("\"/home.ipc\"".into()),
+                    to: Some("\"/other-home.ipc\"".into()),
+                },
+            ],
+            outcome.rejected
+        );
+        assert_eq!(config.txs, tunables.get().txs);
+
+        // tunable changes are applied regardless
+        reloaded.txs.withdraw_confirm.gas = 100_000;
+        let outcome = reload(&tunables, &config, &reloaded);
+        assert_eq!(1, outcome.applied.len());
+        assert_eq!(2, outcome.rejected.len());
+        assert_eq!(100_000, tunables.get().txs.withdraw_confirm.gas);
+    }
+
+    #[test]
+    fn test_reload_leaves_credentials_to_the_rotation() {
+        let config = config();
+        let tunables = SharedTunables::of(&config);
+        let mut reloaded = config.clone();
+        reloaded.foreign.account = 1.into();
+
+        let outcome = reload(&tunables, &config, &reloaded);
+        assert!(outcome.applied.is_empty());
+        assert!(outcome.rejected.is_empty());
+        assert_eq!(
+            vec![
+                Change {
+                    setting: "foreign.account".into(),
+                    from: Some("\"0x0000000000000000000000000000000000000002\"".into()),
+                    to: Some("\"0x0000000000000000000000000000000000000001\"".into()),
+                },
+            ],
+            outcome.rotated
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Amounts written as decimals and with ether units, e.g. `20 gwei`.
+
+use ethereum_types::U256;
+use error::Result;
+
+/// units `parse_wei` accepts and their decimals: one unit is `10^decimals` wei
+pub const UNITS: &[(&str, usize)] = &[
+    ("wei", 0),
+    ("kwei", 3),
+    ("babbage", 3),
+    ("mwei", 6),
+    ("lovelace", 6),
+    ("gwei", 9),
+    ("nano", 9),
+    ("shannon", 9),
+    ("szabo", 12),
+    ("micro", 12),
+    ("finney", 15),
+    ("milli", 15),
+    ("ether", 18),
+    ("eth", 18),
+];
+
+/// decimals of ether: one ether is `10^ETHER_DECIMALS` wei
+pub const ETHER_DECIMALS: usize = 18;
+
+/// decimals of `unit`, matched case insensitively. `None` for unknown units.
+pub fn unit_decimals(unit: &str) -> Option<usize> {
+    let unit = unit.to_lowercase();
+    UNITS
+        .iter()
+        .find(|&&(name, _)| name == unit)
+        .map(|&(_, decimals)| decimals)
+}
+
+/// parses a non-negative decimal, e.g. `2301.55`, into units of `10^-decimals`.
+/// `None` if `value` isn't a decimal, has more than `decimals` decimals or doesn't fit `U256`.
+pub fn parse_decimal(value: &str, decimals: usize) -> Option<U256> {
+    let mut parts = value.splitn(2, '.');
+    let integer = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+    let valid = |digits: &str| digits.chars().all(|c| c.is_digit(10));
+    if integer.is_empty() || !valid(integer) || !valid(fraction) || fraction.len() > decimals {
+        return None;
+    }
+    let digits = format!("{}{:0<width$}", integer, fraction, width = decimals);
+    U256::from_dec_str(&digits).ok()
+}
+
+/// formats `value` in units of `10^-decimals` with all `decimals` decimals.
+pub fn format_decimal(value: U256, decimals: usize) -> String {
+    let digits = format!("{:0>width$}", value.to_string(), width = decimals + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+    if fraction.is_empty() {
+        integer.to_owned()
+    } else {
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+/// `a + b`. `None` if the sum doesn't fit `U256`.
+pub fn checked_add(a: U256, b: U256) -> Option<U256> {
+    match a.overflowing_add(b) {
+        (sum, false) => Some(sum),
+        (_, true) => None,
+    }
+}
+
+/// `a * b`. `None` if the product doesn't fit `U256`.
+pub fn checked_mul(a: U256, b: U256) -> Option<U256> {
+    match a.overflowing_mul(b) {
+        (product, false) => Some(product),
+        (_, true) => None,
+    }
+}
+
+/// parses an amount with a unit, e.g. `20 gwei`, `0.5 nano` or `1.5gwei`, into wei.
+/// the unit is required: a bare number is ambiguous.
+pub fn parse_wei(amount: &str) -> Result<U256> {
+    let amount = amount.trim();
+    let split = amount
+        .find(|c: char| !(c.is_digit(10) || c == '.'))
+        .unwrap_or_else(|| amount.len());
+    let (number, unit) = amount.split_at(split);
+    let unit = unit.trim();
+    if unit.is_empty() {
+        bail!(
+            "amount {:?} has no unit, expected e.g. \"20 gwei\"",
+            amount
+        );
+    }
+    let decimals = match unit_decimals(unit) {
+        Some(decimals) => decimals,
+        None => {
+            let known: Vec<_> = UNITS.iter().map(|&(name, _)| name).collect();
+            bail!(
+                "unknown unit {:?} of amount {:?}, expected one of {}",
+                unit,
+                amount,
+                known.join(", ")
+            );
+        }
+    };
+    match parse_decimal(number, decimals) {
+        Some(wei) => Ok(wei),
+        None => bail!(
+            "invalid amount {:?}, expected a decimal with at most {} decimals followed by the unit",
+            amount,
+            decimals
+        ),
+    }
+}
+
+/// formats `wei` in gwei if it is at least one gwei, e.g. `1.5 gwei`, otherwise in wei.
+pub fn format_wei(wei: U256) -> String {
+    let gwei = U256::from(1_000_000_000u64);
+    if wei < gwei {
+        return format!("{} wei", wei);
+    }
+    let formatted = format_decimal(wei, 9);
+    let formatted = formatted.trim_right_matches('0').trim_right_matches('.');
+    format!("{} gwei", formatted)
+}
+
+/// formats `wei` in ether with at least `min_decimals` decimals, e.g. `1.234500000000000000 ETH`
+/// with `ETHER_DECIMALS` or `1.2345 ETH` with `0`. only trailing zeros are trimmed, so the
+/// amount stays exact and `parse_wei` reads it back.
+pub fn format_ether(wei: U256, min_decimals: usize) -> String {
+    let formatted = format_decimal(wei, ETHER_DECIMALS);
+    let point = formatted.len() - ETHER_DECIMALS - 1;
+    let (integer, fraction) = (&formatted[..point], &formatted[point + 1..]);
+    let min_decimals = min_decimals.min(ETHER_DECIMALS);
+    let kept = fraction.trim_right_matches('0').len().max(min_decimals);
+    if kept == 0 {
+        format!("{} ETH", integer)
+    } else {
+        format!("{}.{} ETH", integer, &fraction[..kept])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_types::U256;
+    use super::{checked_add, checked_mul, format_decimal, format_ether, format_wei, parse_decimal,
+                parse_wei, unit_decimals, ETHER_DECIMALS, UNITS};
+
+    fn wei(amount: &str) -> U256 {
+        parse_wei(amount).unwrap()
+    }
+
+    #[test]
+    fn test_unit_decimals() {
+        assert_eq!(Some(0), unit_decimals("wei"));
+        assert_eq!(Some(9), unit_decimals("GWei"));
+        assert_eq!(Some(9), unit_decimals("shannon"));
+        assert_eq!(Some(18), unit_decimals("ETHER"));
+        assert_eq!(Some(18), unit_decimals("ETH"));
+        assert_eq!(None, unit_decimals("ethers"));
+        assert_eq!(None, unit_decimals(""));
+        for &(name, decimals) in UNITS {
+            assert_eq!(Some(decimals), unit_decimals(name));
+        }
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        assert_eq!(Some(2_301_550_000u64.into()), parse_decimal("2301.55", 6));
+        assert_eq!(Some(2_301_000_000u64.into()), parse_decimal("2301.", 6));
+        assert_eq!(Some(42.into()), parse_decimal("0.000042", 6));
+        assert_eq!(Some(7.into()), parse_decimal("7", 0));
+        assert_eq!(None, parse_decimal("0.0000001", 6));
+        assert_eq!(None, parse_decimal("1.5", 0));
+        assert_eq!(None, parse_decimal(".5", 6));
+        assert_eq!(None, parse_decimal("", 6));
+        assert_eq!(None, parse_decimal("-1", 6));
+        assert_eq!(None, parse_decimal("1.2.3", 6));
+        assert_eq!(None, parse_decimal("1e9", 6));
+        assert_eq!(None, parse_decimal(&"9".repeat(80), 0));
+    }
+
+    #[test]
+    fn test_format_decimal() {
+        assert_eq!("0.000042", format_decimal(42.into(), 6));
+        assert_eq!("2301.550000", format_decimal(2_301_550_000u64.into(), 6));
+        assert_eq!("0.000000", format_decimal(0.into(), 6));
+        assert_eq!("42", format_decimal(42.into(), 0));
+        let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(max, format_decimal(U256::max_value(), 0));
+        assert_eq!(Some(U256::max_value()), parse_decimal(max, 0));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let max = U256::max_value();
+        assert_eq!(Some(max), checked_add(max - U256::from(1), 1.into()));
+        assert_eq!(None, checked_add(max, 1.into()));
+        assert_eq!(Some(max), checked_mul(max, 1.into()));
+        assert_eq!(None, checked_mul(max, 2.into()));
+        assert_eq!(Some(U256::zero()), checked_mul(max, U256::zero()));
+    }
+
+    #[test]
+    fn test_parse_wei_units() {
+        assert_eq!(U256::from(20), wei("20 wei"));
+        assert_eq!(U256::from(20_000), wei("20 kwei"));
+        assert_eq!(U256::from(20_000), wei("20 babbage"));
+        assert_eq!(U256::from(20_000_000), wei("20 mwei"));
+        assert_eq!(U256::from(20_000_000), wei("20 lovelace"));
+        assert_eq!(U256::from(20_000_000_000u64), wei("20 gwei"));
+        assert_eq!(U256::from(20_000_000_000u64), wei("20 nano"));
+        assert_eq!(U256::from(20_000_000_000u64), wei("20 shannon"));
+        assert_eq!(U256::from(20_000_000_000_000u64), wei("20 szabo"));
+        assert_eq!(U256::from(20_000_000_000_000u64), wei("20 micro"));
+        assert_eq!(U256::from(20_000_000_000_000_000u64), wei("20 finney"));
+        assert_eq!(U256::from(20_000_000_000_000_000u64), wei("20 milli"));
+        assert_eq!(
+            U256::from_dec_str("20000000000000000000").unwrap(),
+            wei("20 ether")
+        );
+        assert_eq!(wei("20 ether"), wei("20 eth"));
+    }
+
+    #[test]
+    fn test_parse_wei_formats() {
+        assert_eq!(U256::from(500_000_000), wei("0.5 nano"));
+        assert_eq!(U256::from(1_500_000_000), wei("1.5gwei"));
+        assert_eq!(U256::from(1_500_000_000), wei("  1.5   GWEI "));
+        assert_eq!(U256::from(1), wei("0.000000001 gwei"));
+        assert_eq!(U256::from(0), wei("0 wei"));
+        assert_eq!(U256::from(3_000_000_000u64), wei("3. gwei"));
+        assert_eq!(U256::from(10_000_000_000_000_000u64), wei("0.01eth"));
+    }
+
+    #[test]
+    fn test_parse_wei_rejects() {
+        // a bare number is ambiguous
+        assert!(parse_wei("20").is_err());
+        assert!(parse_wei("").is_err());
+        assert!(parse_wei("gwei").is_err());
+        assert!(parse_wei("20 ethers").is_err());
+        assert!(parse_wei("20 gwei gwei").is_err());
+        assert!(parse_wei("-1 gwei").is_err());
+        assert!(parse_wei(".5 gwei").is_err());
+        assert!(parse_wei("1,5 gwei").is_err());
+        // fractions of a wei
+        assert!(parse_wei("1.5 wei").is_err());
+        assert!(parse_wei("0.0000000001 gwei").is_err());
+        assert!(parse_wei(&format!("{} ether", "9".repeat(70))).is_err());
+    }
+
+    #[test]
+    fn test_format_wei() {
+        assert_eq!("0 wei", format_wei(0.into()));
+        assert_eq!("20 wei", format_wei(20.into()));
+        assert_eq!("999999999 wei", format_wei(999_999_999.into()));
+        assert_eq!("1 gwei", format_wei(1_000_000_000.into()));
+        assert_eq!("1.5 gwei", format_wei(1_500_000_000.into()));
+        assert_eq!("20000000000 gwei", format_wei(wei("20 ether")));
+    }
+
+    #[test]
+    fn test_format_wei_roundtrip() {
+        for amount in &["1 gwei", "0.5 gwei", "20 wei", "1234.000000001 gwei"] {
+            assert_eq!(wei(amount), wei(&format_wei(wei(amount))));
+        }
+    }
+    #[test]
+    fn test_format_ether() {
+        let amount = wei("1.2345 ether");
+        assert_eq!("1.234500000000000000 ETH", format_ether(amount, ETHER_DECIMALS));
+        assert_eq!("1.2345 ETH", format_ether(amount, 0));
+        assert_eq!("1.23450 ETH", format_ether(amount, 5));
+        assert_eq!("1.2345 ETH", format_ether(amount, 2));
+        assert_eq!("1.234500000000000000 ETH", format_ether(amount, 100));
+        assert_eq!("0 ETH", format_ether(0.into(), 0));
+        assert_eq!("0.00 ETH", format_ether(0.into(), 2));
+        assert_eq!("20 ETH", format_ether(wei("20 ether"), 0));
+        assert_eq!("0.000000000000000001 ETH", format_ether(1.into(), 0));
+        assert_eq!(
+            "115792089237316195423570985008687907853269984665640564039457.584007913129639935 ETH",
+            format_ether(U256::max_value(), 0)
+        );
+    }
+
+    quickcheck! {
+        fn quickcheck_format_ether_roundtrips(
+            words: (u64, u64, u64, u64),
+            min_decimals: u8
+        ) -> bool {
+            let wei = U256([words.0, words.1, words.2, words.3]);
+            let formatted = format_ether(wei, min_decimals as usize % (ETHER_DECIMALS + 1));
+            parse_wei(&formatted).ok() == Some(wei)
+        }
+    }
+}
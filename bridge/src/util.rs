@@ -1,5 +1,7 @@
-use web3::types::{Address, FilterBuilder, H256};
+use web3::types::{Address, FilterBuilder, H256, Log, U256};
 use ethabi;
+use tiny_keccak::keccak256;
+use error::{ErrorKind, Result};
 
 fn web3_topic(topic: ethabi::Topic<ethabi::Hash>) -> Option<Vec<H256>> {
     let t: Vec<ethabi::Hash> = topic.into();
@@ -20,3 +22,78 @@ pub fn web3_filter(filter: ethabi::TopicFilter, address: Address) -> FilterBuild
         .address(vec![address])
         .topics(t0, t1, t2, t3)
 }
+
+/// hash of the transaction that emitted `log`.
+/// fails if `log` has not been mined yet.
+pub fn log_transaction_hash(log: &Log) -> Result<H256> {
+    log.transaction_hash.ok_or_else(|| ErrorKind::UnminedLog.into())
+}
+
+/// fails with `ErrorKind::MisalignedLogData` unless the data of `log` is whole 32 byte words.
+/// some nodes return truncated event data, which ethabi rejects without saying why.
+pub fn check_log_data(log: &Log) -> Result<()> {
+    if log.data.0.len() % 32 != 0 {
+        bail!(ErrorKind::MisalignedLogData(log.data.0.len()));
+    }
+    Ok(())
+}
+
+/// id the contracts key the deposit or withdraw in `log` by.
+///
+/// with `unique` the id is `keccak256(transaction_hash, log_index)`, so several
+/// deposits or withdraws emitted by one transaction get distinct ids.
+/// otherwise the id is the transaction hash, which all authorities of
+/// deployments from before unique ids use.
+/// fails if `log` has not been mined yet.
+pub fn log_id(log: &Log, unique: bool) -> Result<H256> {
+    event_id(log_transaction_hash(log)?, log.log_index, unique)
+}
+
+/// `log_id` of the log of `transaction` at `log_index`.
+pub fn event_id(transaction: H256, log_index: Option<U256>, unique: bool) -> Result<H256> {
+    if !unique {
+        return Ok(transaction);
+    }
+    let log_index = log_index.ok_or_else(|| ErrorKind::UnminedLog)?;
+    let mut bytes = [0u8; 64];
+    bytes[..32].copy_from_slice(&transaction.0);
+    log_index.to_big_endian(&mut bytes[32..]);
+    Ok(keccak256(&bytes).into())
+}
+
+/// transaction hashes shared by several of `logs`.
+/// the deposits or withdraws of these logs collide unless unique ids are used.
+pub fn shared_transaction_hashes(logs: &[Log]) -> Vec<H256> {
+    let mut shared = Vec::new();
+    for (i, log) in logs.iter().enumerate() {
+        if let Some(hash) = log.transaction_hash {
+            let repeated = logs[i + 1..]
+                .iter()
+                .any(|other| other.transaction_hash == Some(hash));
+            if repeated && !shared.contains(&hash) {
+                shared.push(hash);
+            }
+        }
+    }
+    shared
+}
+
+/// converts `web3::Log` to `ethabi::RawLog` since ethabi events can
+/// only be parsed from the latter
+pub fn raw_log(log: &Log) -> ethabi::RawLog {
+    ethabi::RawLog {
+        topics: log.topics.clone(),
+        data: log.data.0.clone(),
+    }
+}
+
+/// `true` if `authorization`, the value of an `Authorization` header, is `Bearer <token>`.
+pub fn is_bearer(authorization: &[u8], token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    // compares in constant time
+    authorization.len() == expected.len()
+        && authorization
+            .iter()
+            .zip(expected.as_bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
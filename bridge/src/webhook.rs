@@ -0,0 +1,229 @@
+//! Canonical json of webhook payloads and their HMAC signatures.
+//!
+//! the body of a webhook is exactly the canonical json of its payload, and its signature is the
+//! HMAC-SHA256 of those bytes. consumers verify the bytes they received with `verify_webhook`, or
+//! `canonical_json` of the payload they parsed, which is the same bytes. the canonical form is:
+//!
+//! - object keys sorted by their utf-8 bytes, every key once
+//! - no whitespace between tokens
+//! - integers in decimal, with a `-` only if negative. `U256` values are decimal strings,
+//!   see `u256`. floats are rejected, no payload has any
+//! - strings with `"` and `\` escaped, `\b`, `\f`, `\n`, `\r` and `\t` for those control
+//!   characters, `\u00xx` with lowercase hex for the others and everything else as utf-8
+//!
+//! `golden/` pins the canonical bytes of representative payloads. a payload whose fields change
+//! gets a new `PAYLOAD_VERSION`.
+
+use hmac::{Hmac, Mac};
+use rustc_hex::{FromHex, ToHex};
+use serde::Serializer;
+use serde_json::{self, Value};
+use sha2::Sha256;
+use web3::types::U256;
+use error::{Error, ResultExt};
+
+/// `payload_version` of the payloads the bridge sends.
+pub const PAYLOAD_VERSION: u64 = 2;
+/// header of the signature of a webhook, `sha256=` followed by the hex of the HMAC.
+pub const SIGNATURE_HEADER: &str = "X-Bridge-Signature";
+
+const SIGNATURE_PREFIX: &str = "sha256=";
+
+/// `value` as a decimal string, the canonical form of `U256` values in payloads.
+pub fn u256(value: U256) -> Value {
+    Value::String(value.to_string())
+}
+
+/// serializes `value` as `u256` does, for `U256` fields of structs embedded in payloads.
+pub fn serialize_u256<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_string())
+}
+
+fn write_string(out: &mut String, string: &str) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < '\u{20}' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_value(out: &mut String, value: &Value) -> Result<(), Error> {
+    match *value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(true) => out.push_str("true"),
+        Value::Bool(false) => out.push_str("false"),
+        Value::Number(ref number) => {
+            if let Some(n) = number.as_u64() {
+                out.push_str(&n.to_string());
+            } else if let Some(n) = number.as_i64() {
+                out.push_str(&n.to_string());
+            } else {
+                bail!("{} isn't an integer. canonical json has no floats", number);
+            }
+        }
+        Value::String(ref string) => write_string(out, string),
+        Value::Array(ref values) => {
+            out.push('[');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_value(out, value)?;
+            }
+            out.push(']');
+        }
+        Value::Object(ref map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+            out.push('{');
+            for (index, &(key, value)) in entries.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_string(out, key);
+                out.push(':');
+                write_value(out, value)?;
+            }
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+/// the canonical json of `value`. fails if `value` contains a float.
+pub fn canonical_json(value: &Value) -> Result<String, Error> {
+    let mut out = String::new();
+    write_value(&mut out, value)?;
+    Ok(out)
+}
+
+fn mac(secret: &[u8], body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret).expect("HMAC takes keys of any length; qed");
+    mac.input(body);
+    mac
+}
+
+/// value of `SIGNATURE_HEADER` of a webhook with `body` signed with `secret`.
+pub fn sign_webhook(secret: &[u8], body: &[u8]) -> String {
+    let code = mac(secret, body).result().code();
+    format!("{}{}", SIGNATURE_PREFIX, code.to_hex())
+}
+
+/// the payload of a webhook with `body` and the `signature` of its `SIGNATURE_HEADER`.
+/// fails unless `body` was signed with `secret` and its `payload_version` is `PAYLOAD_VERSION`.
+pub fn verify_webhook(secret: &[u8], body: &[u8], signature: &str) -> Result<Value, Error> {
+    if !signature.starts_with(SIGNATURE_PREFIX) {
+        bail!("webhook signature {:?} doesn't start with {}", signature, SIGNATURE_PREFIX);
+    }
+    let code: Vec<u8> = signature[SIGNATURE_PREFIX.len()..]
+        .from_hex()
+        .map_err(|_| format!("webhook signature {:?} isn't hex", signature))?;
+    // compares in constant time
+    if mac(secret, body).verify(&code).is_err() {
+        bail!("webhook signature doesn't match its body");
+    }
+    let payload: Value = serde_json::from_slice(body).chain_err(|| "webhook body isn't json")?;
+    match payload.get("payload_version").and_then(Value::as_u64) {
+        Some(PAYLOAD_VERSION) => Ok(payload),
+        Some(version) => bail!(
+            "webhook payload_version {} isn't supported, expected {}",
+            version,
+            PAYLOAD_VERSION
+        ),
+        None => bail!("webhook payload has no payload_version"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, Value};
+    use web3::types::U256;
+    use alert::{webhook_payload, Alert, Severity};
+    use super::{canonical_json, sign_webhook, u256, verify_webhook};
+
+    const ALERT: &str = include_str!("../golden/webhook_alert.json");
+    const ESCAPES: &str = include_str!("../golden/webhook_escapes.json");
+    const VALUES: &str = include_str!("../golden/canonical_values.json");
+    /// `sign_webhook` of `ALERT` with secret `secret`
+    const ALERT_SIGNATURE: &str =
+        "sha256=28c2f7644d4d06017af3868e2b365a1f2906f321e5247656f4774634648904f5";
+
+    fn alert() -> Alert {
+        Alert::new(
+            Severity::Critical,
+            "circuit_open",
+            "DepositRelay",
+            "circuit of DepositRelay opened",
+        )
+    }
+
+    #[test]
+    fn test_canonical_webhook_payloads() {
+        let payload = canonical_json(&webhook_payload(&alert())).unwrap();
+        assert_eq!(ALERT.trim_right(), payload);
+
+        let escapes = Alert::new(
+            Severity::Warning,
+            "persistence_degraded",
+            "/mnt/bridge/\"db\"",
+            "saving failed:\n\tno such file \\ directory \u{1} ünïcode",
+        );
+        assert_eq!(
+            ESCAPES.trim_right(),
+            canonical_json(&webhook_payload(&escapes)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_canonical_values() {
+        let value = json!({
+            "z": [3, -1, true, null],
+            "a": {
+                "value": u256(U256::max_value()),
+                "b": "",
+                "B": {},
+            },
+            "é": [],
+        });
+        assert_eq!(VALUES.trim_right(), canonical_json(&value).unwrap());
+        // parsing and serializing again gives the same bytes
+        let parsed: Value = serde_json::from_str(VALUES.trim_right()).unwrap();
+        assert_eq!(VALUES.trim_right(), canonical_json(&parsed).unwrap());
+
+        assert!(canonical_json(&json!({ "gas": 1.5 })).is_err());
+    }
+
+    #[test]
+    fn test_sign_and_verify_webhooks() {
+        let body = ALERT.trim_right().as_bytes();
+        assert_eq!(ALERT_SIGNATURE, sign_webhook(b"secret", body));
+        assert_eq!(
+            webhook_payload(&alert()),
+            verify_webhook(b"secret", body, ALERT_SIGNATURE).unwrap()
+        );
+
+        assert!(verify_webhook(b"other", body, ALERT_SIGNATURE).is_err());
+        // the same payload with insignificant whitespace is another body
+        let spaced = ALERT.trim_right().replace(",", ", ");
+        assert!(verify_webhook(b"secret", spaced.as_bytes(), ALERT_SIGNATURE).is_err());
+        assert!(verify_webhook(b"secret", body, &ALERT_SIGNATURE[7..]).is_err());
+        assert!(verify_webhook(b"secret", body, "sha256=zz").is_err());
+
+        let unversioned = br#"{"text":"hi"}"#;
+        let signature = sign_webhook(b"secret", unversioned);
+        assert!(verify_webhook(b"secret", unversioned, &signature).is_err());
+        let future = br#"{"payload_version":3}"#;
+        let signature = sign_webhook(b"secret", future);
+        assert!(verify_webhook(b"secret", future, &signature).is_err());
+    }
+}
@@ -1,6 +1,5 @@
 extern crate bridge;
 extern crate docopt;
-extern crate env_logger;
 extern crate futures;
 #[macro_use]
 extern crate log;
@@ -8,29 +7,126 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate tokio_core;
+extern crate tokio_signal;
+extern crate web3;
 
-use std::env;
+use std::{env, fs, process};
+use std::io::{BufReader, Read, Write};
 use std::sync::Arc;
 use std::path::PathBuf;
+use std::time::Duration;
 use docopt::Docopt;
-use futures::{future, Stream};
-use tokio_core::reactor::Core;
+use futures::{future, Future, Stream};
+use futures::sync::oneshot;
+use tokio_core::reactor::{Core, Handle};
+use tokio_signal::unix::{Signal, SIGHUP, SIGUSR2};
+use web3::Transport;
+use web3::types::{Address, H256, U256};
 
+use bridge::address::parse_address;
 use bridge::app::App;
+use bridge::bridge::{authority_latency_report, bootstrap_state_path, check_gas_limits,
+                     check_gas_prices, check_safe_confirmations, check_same_chain,
+                     check_wallet_factory, command_channel,
+                     create_audit, create_bootstrap,
+                     create_fairness, create_liability_report, create_prove_inclusion,
+                     create_rebuild, create_show_message,
+                     create_self_test, create_smoke_test,
+                     database_diff,
+                     deploy_record_dir, deploy_record_transaction, estimate_catch_up,
+                     find_deploy_blocks, lookup_deposit, lookup_withdraw,
+                     read_gas_records, summarize_skipped, verify_chain,
+                     verify_inclusion_proof,
+                     write_bootstrap_files, BootstrapPlan, BootstrapProgress, BridgeCommand,
+                     CommandSender, Day, DigestReason, SignedParams,
+                     FairnessRange, InclusionProof, Prices, SmokeTestMode, DEFAULT_DEADLINE_BLOCKS,
+                     DEFAULT_DRAIN_TIMEOUT_SECS, DRAINED_EXIT_CODE};
+#[cfg(not(feature = "chaos"))]
 use bridge::bridge::create_bridge;
-use bridge::config::Config;
-use bridge::error::Error;
-use bridge::database::Database;
+#[cfg(feature = "admin-api")]
+use bridge::bridge::serve_admin;
+#[cfg(feature = "rest-api")]
+use bridge::bridge::serve_history;
+#[cfg(feature = "chaos")]
+use bridge::bridge::{create_bridge_backed_by, FileBackend};
+use bridge::build_info::BuildInfo;
+#[cfg(feature = "chaos")]
+use bridge::chaos::Chaos;
+use bridge::capture;
+use bridge::config::{Config, Node, Password, Signer, DEFAULT_LIABILITY_SCAN_BLOCKS};
+use bridge::error::{Error, ResultExt};
+use bridge::instance_lock::InstanceLock;
+use bridge::jitter::{format_duration, Jitter};
+use bridge::log_level::{self, LogLevels};
+use bridge::outbox::{self, OutboxStatus};
+use bridge::database::{Database, Snapshot, SCHEMA_VERSION};
+use bridge::remote_signer::{RemoteSigner, RemoteSigningTransport};
+use bridge::simulation::simulate;
+use bridge::transport::{LoggingTransport, Recorder, RecordingTransport};
+use bridge::tunables::{self, SharedTunables};
+use bridge::units::{format_ether, parse_wei};
 
 #[derive(Debug, Deserialize)]
 pub struct Args {
     arg_config: PathBuf,
     arg_database: PathBuf,
+    flag_admin_sign: Option<String>,
+    flag_audit: Option<String>,
+    flag_authority_latency: bool,
+    flag_bootstrap: Option<PathBuf>,
+    flag_capture_dir: Option<PathBuf>,
+    flag_deadline: Option<String>,
+    flag_deposit_only: bool,
+    flag_drain_timeout: Option<String>,
+    flag_export_database: Option<PathBuf>,
+    flag_fairness: bool,
+    flag_force: bool,
+    flag_from: Option<String>,
+    flag_from_block: Option<String>,
+    flag_gas_records: Option<PathBuf>,
+    flag_heads: Option<PathBuf>,
+    flag_import_database: Option<PathBuf>,
+    flag_invoice: bool,
+    flag_json: bool,
+    flag_key_file: Option<PathBuf>,
+    flag_liability: bool,
+    flag_lookup: Option<String>,
+    flag_lookup_deposit: Option<String>,
+    flag_main_tx: Option<String>,
+    flag_max_catchup_blocks: Option<String>,
+    flag_migrate: bool,
+    flag_nonce: Option<String>,
+    flag_out: Option<PathBuf>,
+    flag_params: Option<String>,
+    flag_prices: Option<PathBuf>,
+    flag_print_config: bool,
+    flag_prove: bool,
+    flag_rebuild_database: bool,
+    flag_recipient: Option<String>,
+    flag_replay: bool,
+    flag_show_message: Option<String>,
+    flag_signed: Option<PathBuf>,
+    flag_simulate: Option<PathBuf>,
+    flag_since: Option<String>,
+    flag_self_test: bool,
+    flag_skipped: bool,
+    flag_smoke_test: bool,
+    flag_steal_lock: bool,
+    flag_to: Option<String>,
+    flag_to_block: Option<String>,
+    flag_value: Option<String>,
+    flag_verify: Option<PathBuf>,
+    flag_verbose: bool,
+    flag_verify_chain: Option<PathBuf>,
+    flag_version: bool,
+    flag_webhooks_replay: bool,
+    flag_webhooks_status: bool,
+    flag_withdraw_only: bool,
 }
 
 fn main() {
-    let _ = env_logger::init();
-    let result = execute(env::args());
+    let levels = log_level::init().ok();
+    let result = execute(env::args(), levels);
 
     match result {
         Ok(s) => println!("{}", s),
@@ -46,7 +142,7 @@ fn print_err(err: Error) {
     println!("{}", message);
 }
 
-fn execute<S, I>(command: I) -> Result<String, Error>
+fn execute<S, I>(command: I, levels: Option<LogLevels>) -> Result<String, Error>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<str>,
@@ -59,11 +155,186 @@ Parity-bridge
     Commit: {}
 
 Usage:
-    parity-bridge --config <config> --database <database>
+    parity-bridge --config <config> --database <database> [--migrate] [--max-catchup-blocks <blocks>] [--drain-timeout <seconds>] [--steal-lock]
+    parity-bridge --config <config> --database <database> --simulate <capture>
+    parity-bridge --config <config> --database <database> --replay --capture-dir <dir> --from <from> --to <to>
+    parity-bridge --config <config> --database <database> --rebuild-database
+    parity-bridge --config <config> --database <database> --audit <recipient> [--json]
+    parity-bridge --config <config> --database <database> --show-message <side-tx> [--json]
+    parity-bridge --config <config> --database <database> --fairness [--from-block <block>] [--to-block <block>] [--deadline <blocks>] [--json]
+    parity-bridge --config <config> --database <database> --prove --main-tx <main-tx>
+    parity-bridge --config <config> --database <database> --smoke-test --value <value> --recipient <recipient> [--deposit-only | --withdraw-only] [--json]
+    parity-bridge --config <config> --database <database> --self-test [--json]
+    parity-bridge --config <config> --database <database> --liability [--json]
+    parity-bridge --config <config> --print-config
+    parity-bridge --config <config> --database <database> --bootstrap <plan>
+    parity-bridge --database <database> --export-database <snapshot>
+    parity-bridge --database <database> --import-database <snapshot> [--force] [--steal-lock]
+    parity-bridge --database <database> --lookup <side-tx>
+    parity-bridge --database <database> --lookup-deposit <home-tx>
+    parity-bridge --database <database> --skipped [--since <time>]
+    parity-bridge --database <database> --authority-latency [--since <time>]
+    parity-bridge --database <database> --webhooks-status
+    parity-bridge --database <database> --webhooks-replay --since <time> [--steal-lock]
+    parity-bridge --prove --verify <bundle>
+    parity-bridge --verify-chain <chain> [--heads <heads>]
+    parity-bridge --invoice --from <from> --to <to> --gas-records <records> --prices <prices> --out <invoice> [--database <database>]
+    parity-bridge --admin-sign <method> --key-file <key> (--nonce <nonce> [--params <params>] | --signed <signed>)
+    parity-bridge --version [--verbose]
     parity-bridge -h | --help
 
 Options:
     -h, --help           Display help message and exit.
+    --version            Display the version and the commit and exit.
+    --verbose            Also display the build time, the cargo and solc
+                         versions and the hashes of the compiled-in
+                         contract ABIs.
+    --migrate            Upgrade a database written by an older version of
+                         the bridge before starting. the original file is
+                         kept as <database>.v<version>.bak.
+    --max-catchup-blocks <blocks>
+                         Refuse to start if either chain has more blocks
+                         the database hasn't processed than this.
+    --drain-timeout <seconds>
+                         How long a drain requested by SIGUSR2 waits for
+                         the relays in flight, 300 by default.
+    --steal-lock         Take the lock of the database left by an instance
+                         that didn't shut down cleanly. only if it doesn't
+                         run anymore, also not on another host.
+    --print-config       Print the effective config (file merged with
+                         environment overrides) and exit.
+    --bootstrap <plan>   Deploy the contracts of a new bridge on both
+                         chains, check their authorities, fund the
+                         authorities on foreign, write <config> and
+                         <database>, print a summary and exit. the steps
+                         done are saved to <plan>.state, an interrupted
+                         bootstrap continues from there.
+    --simulate <capture> Replay an rpc capture through the bridge without
+                         connecting to any node, print a report and exit.
+                         the database is only read.
+    --replay             Simulate with the rotated rpc captures of a period
+                         like --simulate.
+    --capture-dir <dir>  Directory of the rotated rpc captures and their
+                         indexes.
+    --rebuild-database   Rebuild the checked blocks of the database from
+                         the logs of both chains since the deployment of
+                         the contracts, print the changes and the rebuilt
+                         database and exit. the database is only written
+                         to save deployment blocks that were looked up.
+    --audit <recipient>  Reconcile the deposits and withdraws of an address
+                         in the logs of both chains since the deployment of
+                         the contracts, print their totals, the relays still
+                         pending and any discrepancies and exit.
+    --show-message <side-tx>
+                         Reconstruct the message the bridge signs for each
+                         withdraw in a foreign transaction, print it with
+                         its hash, compare it to the message and signatures
+                         submitted to the foreign contract and exit.
+    --fairness           Count the withdraw relays assigned to every authority
+                         by ForeignBridge.CollectedSignatures and the ones it
+                         performed, test whether the assignments are
+                         uniform, list the assignments not performed in
+                         time and exit.
+    --from-block <block> First foreign block of the assignments counted by
+                         --fairness. defaults to the deployment block.
+    --to-block <block>   Last foreign block of the assignments counted by
+                         --fairness. defaults to the confirmed head.
+    --deadline <blocks>  Foreign blocks an authority has to relay a withdraw
+                         before its assignment counts as failed. defaults to
+                         100.
+    --smoke-test         Send a deposit from the test account of
+                         smoke_test to HomeBridge, wait until the bridge
+                         relayed it to foreign, withdraw it to <recipient>
+                         on home, wait until the bridge relayed it, print
+                         the steps with their transactions and timings and
+                         exit. refuses to run on the chains listed in
+                         smoke_test.production_chain_ids.
+    --value <value>      Value of the smoke test transfers on home with a
+                         unit, e.g. "0.01 ether" or "0.01eth".
+    --recipient <recipient>
+                         Home account receiving the smoke test withdraw.
+    --deposit-only       Only send the smoke test deposit.
+    --withdraw-only      Only withdraw tokens the test account holds on
+                         foreign.
+    --self-test          Call every rpc method the configured features use
+                         on home and foreign with harmless params, print
+                         which succeeded with their latency and exit.
+                         fails if a required method is missing, naming the
+                         option of the feature that requires it.
+    --liability          Scan the deposits on home not yet executed on
+                         foreign, print their values by reason: awaiting
+                         confirmations, quarantined, deferred, awaiting
+                         signatures of other authorities or in flight, and
+                         exit. scans liability.scan_blocks blocks back
+                         and every deposit not relayed yet.
+    --json               Print the audit, the messages, the fairness, the
+                         smoke test, the self test or the liability as json.
+    --export-database <snapshot>
+                         Write the database as a json snapshot with record
+                         counts and a checksum and exit.
+    --import-database <snapshot>
+                         Check a json snapshot written by --export-database
+                         and write its database and exit.
+    --force              Import over an existing database.
+    --lookup <side-tx>   Print the attempts the database records of the
+                         withdraw in a foreign transaction as json, whether
+                         it was given up and at which phase, and exit.
+    --lookup-deposit <home-tx>
+                         Print why the deposits in a home transaction were
+                         recently not relayed as json and exit.
+    --skipped            Count the deposits recently not relayed by reason,
+                         print them as json and exit.
+    --authority-latency  Print the percentiles of how long every authority
+                         took to sign the withdraws recently observed, in
+                         seconds, as json and exit.
+    --webhooks-status    Print the webhook notifications not yet delivered
+                         per endpoint and the age of the oldest as json
+                         and exit.
+    --webhooks-replay    Mark the delivered webhook notifications created
+                         since --since undelivered and exit. the bridge
+                         delivers them again on its next start.
+    --since <time>       Only count the deposits --skipped skipped since,
+                         or the signatures --authority-latency counts,
+                         or replay the notifications created since,
+                         a unix time or YYYY-MM-DD[THH:MM[:SS]] in utc.
+    --prove              Prove the inclusion of a home transaction with its
+                         receipt, print the proof as json and exit.
+    --main-tx <main-tx>  Hash of the home transaction to prove, e.g. of a
+                         withdraw relay.
+    --verify <bundle>    Check a proof printed by --prove against the block
+                         hash in it only, print the proven receipt and exit.
+    --verify-chain <chain>
+                         Walk a relay chain written with relay_history.chain,
+                         report the first entry that breaks it and exit.
+    --heads <heads>      Also check the heads written to this head file
+                         against the chain.
+    --invoice            Bill the gas spent by the bridge in a period, write
+                         the invoice as json and print a summary.
+    --from <from>        First day of the invoiced period, YYYY-MM-DD in utc.
+                         start of the replayed period, a unix time or
+                         YYYY-MM-DD[THH:MM[:SS]] in utc.
+    --to <to>            First day after the invoiced period, YYYY-MM-DD in
+                         utc. end of the replayed period, inclusive.
+    --gas-records <records>
+                         Gas accounting records, one json object per line.
+    --prices <prices>    Csv of the price of a coin of each chain per day:
+                         date,home price[,foreign price].
+    --out <invoice>      File to write the invoice to.
+                         with --database, the invoice also sums the deposit
+                         and withdraw events the bridge observed in the
+                         period, relayed by this authority or not.
+    --admin-sign <method>
+                         Sign a call of the admin api method with the secret
+                         key of an operator, print its params as json and
+                         exit. see admin.signed_commands.
+    --key-file <key>     File with the hex secret key of the operator in its
+                         first line.
+    --nonce <nonce>      Nonce of the signed call, above that of the last
+                         signed call the bridge executed.
+    --params <params>    Json array of the params of the signed call, no
+                         params if left out.
+    --signed <signed>    File with the params printed by --admin-sign for
+                         another operator, to add the signature to.
 "#,
         env!("CARGO_PKG_VERSION"),
         env!("GIT_HASH")
@@ -74,23 +345,905 @@ Options:
         .and_then(|d| d.argv(command).deserialize())
         .map_err(|e| e.to_string())?;
 
+    if args.flag_version {
+        let build = BuildInfo::current();
+        if args.flag_verbose {
+            return Ok(build.to_string());
+        }
+        return Ok(format!("parity-bridge {}\ncommit: {}", build.version, build.commit));
+    }
+
+    if args.flag_invoice {
+        return invoice(&args);
+    }
+
+    if let Some(ref method) = args.flag_admin_sign {
+        return admin_sign(method, &args);
+    }
+
+    if let Some(ref snapshot_path) = args.flag_export_database {
+        let database = Database::load(&args.arg_database)?;
+        fs::File::create(snapshot_path)
+            .chain_err(|| format!("Cannot create snapshot {:?}", snapshot_path))?
+            .write_all(Snapshot::new(database).to_json().as_bytes())?;
+        return Ok(format!(
+            "Exported database {:?} to {:?}",
+            args.arg_database, snapshot_path
+        ));
+    }
+
+    if let Some(ref snapshot_path) = args.flag_import_database {
+        let _lock = InstanceLock::acquire(&args.arg_database, args.flag_steal_lock)?;
+        Database::import(&args.arg_database, &read_file(snapshot_path)?, args.flag_force)?;
+        return Ok(format!(
+            "Imported snapshot {:?} to database {:?}",
+            snapshot_path, args.arg_database
+        ));
+    }
+
+    if let Some(ref transaction) = args.flag_lookup {
+        let database = Database::load(&args.arg_database)?;
+        let transaction: H256 = transaction
+            .parse()
+            .map_err(|_| format!("Invalid transaction hash {}", transaction))?;
+        return Ok(lookup_withdraw(&database, transaction).to_json());
+    }
+
+    if let Some(ref transaction) = args.flag_lookup_deposit {
+        let database = Database::load(&args.arg_database)?;
+        let transaction: H256 = transaction
+            .parse()
+            .map_err(|_| format!("Invalid transaction hash {}", transaction))?;
+        return Ok(lookup_deposit(&database, transaction).to_json());
+    }
+
+    if args.flag_skipped {
+        let database = Database::load(&args.arg_database)?;
+        let since = match args.flag_since {
+            Some(ref since) => capture::parse_time(since)?,
+            None => 0,
+        };
+        return Ok(summarize_skipped(&database, since).to_json());
+    }
+
+    if args.flag_authority_latency {
+        let database = Database::load(&args.arg_database)?;
+        let since = match args.flag_since {
+            Some(ref since) => capture::parse_time(since)?,
+            None => 0,
+        };
+        return Ok(authority_latency_report(&database, since).to_json());
+    }
+
+    if args.flag_webhooks_status {
+        let database = Database::load(&args.arg_database)?;
+        return Ok(OutboxStatus::of(&database.webhook_outbox, capture::unix_time()).to_json());
+    }
+
+    if args.flag_webhooks_replay {
+        let _lock = InstanceLock::acquire(&args.arg_database, args.flag_steal_lock)?;
+        let since = match args.flag_since {
+            Some(ref since) => capture::parse_time(since)?,
+            None => return Err("--webhooks-replay needs --since".into()),
+        };
+        let mut database = Database::load(&args.arg_database)?;
+        let replayed = outbox::replay(&mut database.webhook_outbox, since);
+        let file = fs::File::create(&args.arg_database)
+            .chain_err(|| format!("Cannot write database {:?}", args.arg_database))?;
+        database.save(file)?;
+        return Ok(format!(
+            "Marked {} webhook notifications undelivered in database {:?}",
+            replayed, args.arg_database
+        ));
+    }
+
+    if let Some(ref chain_path) = args.flag_verify_chain {
+        return verify_relay_chain(chain_path, args.flag_heads.as_ref());
+    }
+
+    if let Some(ref bundle_path) = args.flag_verify {
+        let proof = InclusionProof::from_json(&read_file(bundle_path)?)?;
+        return Ok(verify_inclusion_proof(&proof)?.to_json());
+    }
+
+    if let Some(ref plan_path) = args.flag_bootstrap {
+        return bootstrap(&args, plan_path);
+    }
+
     info!(target: "bridge", "Loading config");
-    let config = Config::load(args.arg_config)?;
+    let config = Config::load(&args.arg_config)?;
+    if let Some(ref levels) = levels {
+        apply_log_level(levels, &config);
+        if let Some(ref tracing) = config.tracing {
+            levels.export_spans(tracing)?;
+        }
+    }
+    let reload = ConfigReload {
+        path: args.arg_config.clone(),
+        levels,
+    };
+    let max_catchup_blocks = match args.flag_max_catchup_blocks {
+        Some(ref blocks) => Some(blocks
+            .parse()
+            .map_err(|_| format!("Invalid block count {}", blocks))?),
+        None => None,
+    };
+    let drain_timeout = match args.flag_drain_timeout {
+        Some(ref seconds) => seconds
+            .parse()
+            .map_err(|_| format!("Invalid drain timeout {}", seconds))?,
+        None => DEFAULT_DRAIN_TIMEOUT_SECS,
+    };
+    if args.flag_print_config {
+        return Ok(config.to_string());
+    }
 
+    // instances with leader election share the database, the lease keeps them apart
+    let runs_bridge = args.flag_simulate.is_none() && !args.flag_replay
+        && !args.flag_rebuild_database && args.flag_audit.is_none()
+        && args.flag_show_message.is_none() && !args.flag_fairness && !args.flag_prove
+        && !args.flag_smoke_test && !args.flag_self_test && !args.flag_liability;
+    let lock = if runs_bridge && config.leader_election.is_none() {
+        Some(InstanceLock::acquire(&args.arg_database, args.flag_steal_lock)?)
+    } else {
+        None
+    };
+    let options = RunOptions {
+        reload,
+        max_catchup_blocks,
+        drain_timeout: Duration::from_secs(drain_timeout),
+        lock,
+    };
+
+    if args.flag_migrate {
+        match Database::migrate(&args.arg_database)? {
+            Some(version) => info!(
+                target: "bridge",
+                "Migrated database from schema version {} to {}",
+                version,
+                SCHEMA_VERSION
+            ),
+            None => info!(target: "bridge", "Database is up to date"),
+        }
+    }
+
+    if let Some(capture_path) = args.flag_simulate {
+        info!(target: "bridge", "Simulating with rpc capture {:?}", capture_path);
+        let mut capture = String::new();
+        fs::File::open(&capture_path)
+            .chain_err(|| format!("Cannot open rpc capture {:?}", capture_path))?
+            .read_to_string(&mut capture)?;
+        let database = Database::load(&args.arg_database)?;
+        return Ok(simulate(&config, &database, &capture)?.to_json());
+    }
+
+    if args.flag_replay {
+        let dir = args.flag_capture_dir.clone().expect("required by the usage; qed");
+        let from = capture::parse_time(&args.flag_from.clone().expect("required by the usage; qed"))?;
+        let to = capture::parse_time(&args.flag_to.clone().expect("required by the usage; qed"))?;
+        info!(target: "bridge", "Replaying rpc captures in {:?} from {} to {}", dir, from, to);
+        let capture = capture::read_captures(&dir, from, to)?;
+        let database = Database::load(&args.arg_database)?;
+        return Ok(simulate(&config, &database, &capture)?.to_json());
+    }
+
+    info!(target: "bridge", "Starting parity-bridge {}", BuildInfo::current().summary());
     info!(target: "bridge", "Starting event loop");
     let mut event_loop = Core::new().unwrap();
 
-    info!(target: "bridge", "Establishing ipc connection");
-    let app = App::new_ipc(config, &args.arg_database, &event_loop.handle())?;
+    info!(target: "bridge", "Connecting to the nodes");
+    let app = App::new(config, &args.arg_database, &event_loop.handle())?;
+    let app = with_remote_signers(app, &args.arg_database)?;
+
+    if args.flag_rebuild_database {
+        return rebuild(app, &mut event_loop, &args.arg_database);
+    }
+
+    if let Some(recipient) = args.flag_audit {
+        let recipient = parse_address("--audit", &recipient)?;
+        return audit(app, &mut event_loop, &args.arg_database, recipient, args.flag_json);
+    }
+
+    if let Some(transaction) = args.flag_show_message {
+        let transaction = transaction
+            .parse()
+            .map_err(|_| format!("Invalid transaction hash {}", transaction))?;
+        return show_message(app, &mut event_loop, &args.arg_database, transaction, args.flag_json);
+    }
+
+    if args.flag_fairness {
+        let block = |block: &Option<String>| -> Result<Option<u64>, Error> {
+            match *block {
+                Some(ref block) => Ok(Some(block
+                    .parse()
+                    .map_err(|_| format!("Invalid block number {}", block))?)),
+                None => Ok(None),
+            }
+        };
+        let range = FairnessRange {
+            from_block: block(&args.flag_from_block)?,
+            to_block: block(&args.flag_to_block)?,
+            deadline_blocks: block(&args.flag_deadline)?.unwrap_or(DEFAULT_DEADLINE_BLOCKS),
+        };
+        return fairness(app, &mut event_loop, &args.arg_database, range, args.flag_json);
+    }
+
+    if args.flag_prove {
+        let transaction = args.flag_main_tx.expect("required by the usage; qed");
+        let transaction = transaction
+            .parse()
+            .map_err(|_| format!("Invalid transaction hash {}", transaction))?;
+        return prove(app, &mut event_loop, transaction);
+    }
+
+    if args.flag_smoke_test {
+        // docopt only matches --smoke-test with --value and --recipient
+        let value = parse_wei(&args.flag_value.clone().expect("required by the usage; qed"))?;
+        let recipient = args.flag_recipient.clone().expect("required by the usage; qed");
+        let recipient = parse_address("--recipient", &recipient)?;
+        let mode = if args.flag_deposit_only {
+            SmokeTestMode::DepositOnly
+        } else if args.flag_withdraw_only {
+            SmokeTestMode::WithdrawOnly
+        } else {
+            SmokeTestMode::DepositAndWithdraw
+        };
+        return smoke_test(
+            app,
+            &mut event_loop,
+            &args.arg_database,
+            mode,
+            recipient,
+            value,
+            args.flag_json,
+        );
+    }
+
+    if args.flag_self_test {
+        return self_test(app, &mut event_loop, &args.arg_database, args.flag_json);
+    }
+
+    if args.flag_liability {
+        return liability(app, &mut event_loop, &args.arg_database, args.flag_json);
+    }
+
+    let rpc_trace = match app.config.rpc_trace.clone() {
+        None => return run(app, &mut event_loop, &args.arg_database, options),
+        Some(rpc_trace) => rpc_trace,
+    };
+    let max_size = rpc_trace.max_log_size;
+    let rotation = rpc_trace.capture.clone();
+    match (rpc_trace.log, rpc_trace.capture_file) {
+        (false, None) => run(app, &mut event_loop, &args.arg_database, options),
+        (true, None) => {
+            info!(target: "bridge", "Logging rpc traffic");
+            let app = app.with_transports(|t, chain| LoggingTransport::new(t, chain, max_size));
+            run(app, &mut event_loop, &args.arg_database, options)
+        }
+        (log, Some(capture_file)) => {
+            info!(target: "bridge", "Capturing rpc traffic to {:?}", capture_file);
+            let recorder = Arc::new(Recorder::with_rotation(capture_file, rotation)?);
+            let app = app.with_transports(|t, chain| {
+                RecordingTransport::new(t, chain, recorder.clone())
+            });
+            if log {
+                info!(target: "bridge", "Logging rpc traffic");
+                let app = app.with_transports(|t, chain| LoggingTransport::new(t, chain, max_size));
+                run(app, &mut event_loop, &args.arg_database, options)
+            } else {
+                run(app, &mut event_loop, &args.arg_database, options)
+            }
+        }
+    }
+}
+
+/// `app` signing with the remote signers of the nodes whose `signer` is `remote`. they may only
+/// sign calls to the contracts of the database at `database_path`.
+fn with_remote_signers<T: Transport + Clone>(
+    app: App<T>,
+    database_path: &PathBuf,
+) -> Result<App<RemoteSigningTransport<T>>, Error> {
+    let remote = |node: &Node| node.signer == Signer::Remote;
+    let (home, foreign) = if remote(&app.config.home) || remote(&app.config.foreign) {
+        let database = Database::load(database_path)
+            .chain_err(|| "Remote signers require the contracts to be deployed")?;
+        info!(target: "bridge", "Signing with the remote signers of the config");
+        (
+            RemoteSigner::of_node(&app.config.home, database.home_contract_address)?,
+            RemoteSigner::of_node(&app.config.foreign, database.foreign_contract_address)?,
+        )
+    } else {
+        (None, None)
+    };
+    Ok(app.with_transports(|t, chain| {
+        let signer = if chain == "home" {
+            home.clone()
+        } else {
+            foreign.clone()
+        };
+        RemoteSigningTransport::new(t, signer)
+    }))
+}
+
+fn rebuild<T: Transport>(
+    app: App<T>,
+    event_loop: &mut Core,
+    database_path: &PathBuf,
+) -> Result<String, Error> {
+    let app_ref = Arc::new(app.as_ref());
+
+    // contract addresses and deployment blocks are kept, checked blocks are rebuilt
+    let database = Database::load(database_path)?;
+    let database = with_deploy_blocks(&app_ref, event_loop, database, database_path)?;
+
+    info!(target: "bridge", "Rebuilding database from chain logs");
+    let rebuilt = event_loop.run(create_rebuild(app_ref, &database))?;
+
+    let diff = database_diff(&database, &rebuilt);
+    let changes = if diff.is_empty() {
+        "no changes".to_owned()
+    } else {
+        diff.join("\n")
+    };
+    Ok(format!("{}\n\n{}", changes, rebuilt))
+}
+
+fn audit<T: Transport>(
+    app: App<T>,
+    event_loop: &mut Core,
+    database_path: &PathBuf,
+    recipient: Address,
+    json: bool,
+) -> Result<String, Error> {
     let app_ref = Arc::new(app.as_ref());
 
-    let database = Database::load(&args.arg_database)?;
+    // contract addresses and deployment blocks bound the scan
+    let database = Database::load(database_path)?;
+    let database = with_deploy_blocks(&app_ref, event_loop, database, database_path)?;
+
+    info!(target: "bridge", "Auditing {:?} from chain logs", recipient);
+    let audit = event_loop.run(create_audit(app_ref, &database, recipient))?;
+    if json {
+        Ok(audit.to_json())
+    } else {
+        Ok(audit.to_string())
+    }
+}
+
+fn show_message<T: Transport>(
+    app: App<T>,
+    event_loop: &mut Core,
+    database_path: &PathBuf,
+    transaction: H256,
+    json: bool,
+) -> Result<String, Error> {
+    let app_ref = Arc::new(app.as_ref());
+    let database = Database::load(database_path)?;
+
+    info!(target: "bridge", "Reconstructing the messages of foreign transaction {:?}", transaction);
+    let shown = event_loop.run(create_show_message(app_ref, &database, transaction))?;
+    if json {
+        Ok(shown.to_json())
+    } else {
+        Ok(shown.to_string())
+    }
+}
+
+fn fairness<T: Transport>(
+    app: App<T>,
+    event_loop: &mut Core,
+    database_path: &PathBuf,
+    range: FairnessRange,
+    json: bool,
+) -> Result<String, Error> {
+    let app_ref = Arc::new(app.as_ref());
+    let database = Database::load(database_path)?;
+
+    info!(target: "bridge", "Scanning the withdraw relay assignments of all authorities");
+    let fairness = event_loop.run(create_fairness(app_ref, &database, range))?;
+    if json {
+        Ok(fairness.to_json())
+    } else {
+        Ok(fairness.to_string())
+    }
+}
+
+fn liability<T: Transport>(
+    app: App<T>,
+    event_loop: &mut Core,
+    database_path: &PathBuf,
+    json: bool,
+) -> Result<String, Error> {
+    let scan_blocks = app.config
+        .liability
+        .as_ref()
+        .map_or(DEFAULT_LIABILITY_SCAN_BLOCKS, |config| config.scan_blocks);
+    let app_ref = Arc::new(app.as_ref());
+    let database = Database::load(database_path)?;
+
+    info!(target: "bridge", "Scanning the deposits not yet executed on foreign");
+    let report = event_loop.run(create_liability_report(app_ref, &database, scan_blocks))?;
+    if json {
+        Ok(report.to_json())
+    } else {
+        Ok(report.to_string())
+    }
+}
+
+fn prove<T: Transport>(
+    app: App<T>,
+    event_loop: &mut Core,
+    transaction: H256,
+) -> Result<String, Error> {
+    let app_ref = Arc::new(app.as_ref());
+
+    info!(target: "bridge", "Proving the inclusion of home transaction {:?}", transaction);
+    Ok(event_loop.run(create_prove_inclusion(app_ref, transaction))?.to_json())
+}
+
+fn smoke_test<T: Transport>(
+    app: App<T>,
+    event_loop: &mut Core,
+    database_path: &PathBuf,
+    mode: SmokeTestMode,
+    recipient: Address,
+    value: U256,
+    json: bool,
+) -> Result<String, Error> {
+    let app_ref = Arc::new(app.as_ref());
+    let database = Database::load(database_path)?;
+
+    info!(
+        target: "bridge",
+        "Smoke testing the bridge with {} ({:?})",
+        format_ether(value, 0),
+        mode
+    );
+    let smoke_test = create_smoke_test(app_ref, &database, mode, recipient, value)?;
+    let report = event_loop.run(smoke_test)?;
+    let shown = if json {
+        report.to_json()
+    } else {
+        report.to_string()
+    };
+    if report.passed() {
+        Ok(shown)
+    } else {
+        Err(shown.into())
+    }
+}
+
+fn self_test<T: Transport>(
+    app: App<T>,
+    event_loop: &mut Core,
+    database_path: &PathBuf,
+    json: bool,
+) -> Result<String, Error> {
+    let app_ref = Arc::new(app.as_ref());
+    let database = Database::load(database_path)?;
+
+    info!(target: "bridge", "Probing the rpc methods of home and foreign");
+    let report = event_loop.run(create_self_test(app_ref, &database))?;
+    let shown = if json {
+        report.to_json()
+    } else {
+        report.to_string()
+    };
+    if report.passed() {
+        Ok(shown)
+    } else {
+        Err(shown.into())
+    }
+}
+
+/// runs or continues the bootstrap of the plan at `plan_path`, see `create_bootstrap`.
+/// a new bootstrap refuses to overwrite an existing config or database.
+fn bootstrap(args: &Args, plan_path: &PathBuf) -> Result<String, Error> {
+    let plan = BootstrapPlan::load(plan_path)?;
+    let state_path = bootstrap_state_path(plan_path);
+    if !state_path.exists() {
+        for path in &[&args.arg_config, &args.arg_database] {
+            if path.exists() {
+                let message = format!("{:?} already exists, refusing to bootstrap over it", path);
+                return Err(message.into());
+            }
+        }
+    }
+    let mut progress = BootstrapProgress::load_or_new(&state_path, &plan)?;
+    if !progress.written {
+        let mut event_loop = Core::new().unwrap();
+        info!(target: "bridge", "Establishing ipc connection");
+        let app = App::new_ipc(plan.deploy_config()?, &args.arg_database, &event_loop.handle())?;
+        let bootstrap = create_bootstrap(Arc::new(app), plan.clone(), progress, state_path.clone());
+        progress = event_loop.run(bootstrap)?;
+        write_bootstrap_files(&plan, &progress, &args.arg_config, &args.arg_database)?;
+        progress.written = true;
+        progress.save(&state_path)?;
+    }
+    Ok(progress.summary(&plan, &args.arg_config, &args.arg_database))
+}
+
+/// contents of the file at `path`.
+fn read_file(path: &PathBuf) -> Result<String, Error> {
+    let mut content = String::new();
+    fs::File::open(path)
+        .chain_err(|| format!("Cannot open {:?}", path))?
+        .read_to_string(&mut content)?;
+    Ok(content)
+}
+
+fn verify_relay_chain(
+    chain_path: &PathBuf,
+    heads_path: Option<&PathBuf>,
+) -> Result<String, Error> {
+    let chain = fs::File::open(chain_path)
+        .chain_err(|| format!("Cannot open relay chain {:?}", chain_path))?;
+    let heads = match heads_path {
+        Some(path) => Some(BufReader::new(fs::File::open(path)
+            .chain_err(|| format!("Cannot open relay chain heads {:?}", path))?)),
+        None => None,
+    };
+    let verification = verify_chain(BufReader::new(chain), heads)?;
+    if let Some(divergence) = verification.divergence {
+        return Err(format!(
+            "Relay chain {:?} diverges after {} valid entries at {}",
+            chain_path, verification.records, divergence
+        ).into());
+    }
+    Ok(format!(
+        "Relay chain {:?} is intact: {} entries, head {:?}",
+        chain_path, verification.records, verification.head
+    ))
+}
+
+/// params of a signed call of the admin api `method`, signed offline by the operator of
+/// `--key-file`.
+fn admin_sign(method: &str, args: &Args) -> Result<String, Error> {
+    // docopt only matches --admin-sign with --key-file and --nonce or --signed
+    let key_file = args.flag_key_file.clone().expect("required by the usage; qed");
+    let mut signed = match args.flag_signed {
+        Some(ref path) => SignedParams::from_json(&read_file(path)?)
+            .chain_err(|| format!("Invalid signed params {:?}", path))?,
+        None => {
+            let nonce = args.flag_nonce.clone().expect("required by the usage; qed");
+            let nonce = nonce
+                .parse()
+                .map_err(|_| format!("Invalid nonce {}", nonce))?;
+            let params = args.flag_params.clone().unwrap_or_else(|| "[]".into());
+            SignedParams::new(&params, nonce)?
+        }
+    };
+    signed.sign(Password::from_file(&key_file)?.as_str(), method)?;
+    Ok(signed.to_json())
+}
+
+fn invoice(args: &Args) -> Result<String, Error> {
+    // docopt only matches --invoice with all of its options
+    let from = Day::parse(&args.flag_from.clone().expect("required by the usage; qed"))?;
+    let to = Day::parse(&args.flag_to.clone().expect("required by the usage; qed"))?;
+    let records_path = args.flag_gas_records.clone().expect("required by the usage; qed");
+    let prices_path = args.flag_prices.clone().expect("required by the usage; qed");
+    let out = args.flag_out.clone().expect("required by the usage; qed");
+
+    let records = read_gas_records(&read_file(&records_path)?)
+        .chain_err(|| format!("Invalid gas records {:?}", records_path))?;
+    let prices = Prices::from_csv(&read_file(&prices_path)?)
+        .chain_err(|| format!("Invalid prices {:?}", prices_path))?;
+
+    // `--database` is optional here
+    let observed = if args.arg_database.as_os_str().is_empty() {
+        Vec::new()
+    } else {
+        Database::load(&args.arg_database)?.observed_traffic
+    };
+
+    info!(target: "bridge", "Billing {} gas records from {} until {}", records.len(), from, to);
+    let invoice = bridge::bridge::invoice(&records, &prices, &observed, from, to)?;
+    fs::File::create(&out)
+        .and_then(|mut file| file.write_all(invoice.to_json().as_bytes()))
+        .chain_err(|| format!("Cannot write invoice to {:?}", out))?;
+    Ok(invoice.to_string())
+}
+
+/// `database` with the deployment blocks it doesn't know (`0`) looked up on chain.
+/// looked up blocks are saved, so this happens once per database.
+/// the deployment transactions of the deploy records written by `parity-bridge-deploy`
+/// to the working directory are used if the nodes don't have the state of old blocks.
+fn with_deploy_blocks<T: Transport>(
+    app: &Arc<App<T>>,
+    event_loop: &mut Core,
+    database: Database,
+    database_path: &PathBuf,
+) -> Result<Database, Error> {
+    if database.home_deploy != 0 && database.foreign_deploy != 0 {
+        return Ok(database);
+    }
+    let home_creation =
+        deploy_record_transaction(deploy_record_dir("home", &database.home_contract_address))?;
+    let foreign_creation = deploy_record_transaction(deploy_record_dir(
+        "foreign",
+        &database.foreign_contract_address,
+    ))?;
+
+    info!(target: "bridge", "Looking up deployment blocks of the contracts");
+    let database = event_loop.run(find_deploy_blocks(
+        app.clone(),
+        &database,
+        home_creation,
+        foreign_creation,
+    ))?;
+    info!(
+        target: "bridge",
+        "Contracts deployed at home block {} and foreign block {}",
+        database.home_deploy,
+        database.foreign_deploy
+    );
+    database.save(fs::File::create(database_path)?)?;
+    Ok(database)
+}
+
+/// Config file reloaded on `SIGHUP`.
+struct ConfigReload {
+    path: PathBuf,
+    /// `None` if the logger couldn't be installed
+    levels: Option<LogLevels>,
+}
+
+/// Options of a running bridge.
+struct RunOptions {
+    reload: ConfigReload,
+    max_catchup_blocks: Option<u64>,
+    /// how long a drain waits for the relays in flight
+    drain_timeout: Duration,
+    /// exclusive access to the database. `None` with leader election
+    lock: Option<InstanceLock>,
+}
+
+/// filters logs by `log_level` of `config`, by `RUST_LOG` if it isn't set.
+fn apply_log_level(levels: &LogLevels, config: &Config) {
+    match config.log_level {
+        Some(ref filter) => levels.set_filter(filter),
+        None => levels.set_filter(&env::var("RUST_LOG").unwrap_or_default()),
+    }
+}
+
+/// applies the tunable settings of the config file to `shared` on every `SIGHUP`.
+/// see `tunables::reload`. a config file that fails to load changes nothing.
+/// changed accounts, signers, submitters and passwords in the password files are rotated to
+/// through `commands`.
+fn reload_on_sighup(
+    handle: &Handle,
+    shared: SharedTunables,
+    mut config: Config,
+    reload: ConfigReload,
+    commands: CommandSender,
+) {
+    let spawn_handle = handle.clone();
+    let reloads = Signal::new(SIGHUP, handle)
+        .flatten_stream()
+        .for_each(move |_| {
+            info!(target: "bridge", "SIGHUP received, reloading config {:?}", reload.path);
+            match Config::load(&reload.path) {
+                Ok(reloaded) => {
+                    let outcome = tunables::reload(&shared, &config, &reloaded);
+                    if let Some(ref levels) = reload.levels {
+                        if outcome.applied("log_level") {
+                            apply_log_level(levels, &reloaded);
+                        }
+                    }
+                    if !outcome.rotated.is_empty() || config.passwords_changed(&reloaded) {
+                        rotate_on_reload(&spawn_handle, &commands, &mut config, &reloaded);
+                    }
+                }
+                Err(err) => error!(
+                    target: "bridge",
+                    "Cannot reload config, keeping the running config: {}",
+                    err
+                ),
+            }
+            Ok(())
+        })
+        .map_err(|err| error!(target: "bridge", "Cannot handle SIGHUP: {}", err));
+    handle.spawn(reloads);
+}
+
+/// rotates the bridge to the credentials of `reloaded`, see `Bridge::rotate_authority`.
+/// `config` takes the rotated credentials, so the next reload compares against them.
+fn rotate_on_reload(
+    handle: &Handle,
+    commands: &CommandSender,
+    config: &mut Config,
+    reloaded: &Config,
+) {
+    let rotated = match config.with_credentials_of(reloaded) {
+        Ok(rotated) => rotated,
+        Err(err) => {
+            error!(
+                target: "bridge",
+                "Cannot rotate the authority credentials, keeping the running ones: {}",
+                err
+            );
+            return;
+        }
+    };
+    let rotation = commands
+        .send(BridgeCommand::RotateAuthority(Box::new(rotated.clone())))
+        .map(|reply| info!(target: "bridge", "Rotating the authority credentials: {}", reply))
+        .map_err(|err| {
+            error!(target: "bridge", "Cannot rotate the authority credentials: {}", err)
+        });
+    handle.spawn(rotation);
+    *config = rotated;
+}
+
+/// fires on the first `SIGUSR2`. unlike `SIGTERM`, which stops the bridge right away,
+/// a drain lets the relays in flight finish, so the next version starts without resending them.
+fn drain_on_sigusr2(handle: &Handle) -> oneshot::Receiver<()> {
+    let (sender, receiver) = oneshot::channel();
+    let mut sender = Some(sender);
+    let signals = Signal::new(SIGUSR2, handle)
+        .flatten_stream()
+        .for_each(move |_| {
+            match sender.take() {
+                Some(sender) => {
+                    info!(target: "bridge", "SIGUSR2 received, draining");
+                    // the bridge is gone if it stopped already
+                    let _ = sender.send(());
+                }
+                None => info!(target: "bridge", "SIGUSR2 received, already draining"),
+            }
+            Ok(())
+        })
+        .map_err(|err| error!(target: "bridge", "Cannot handle SIGUSR2: {}", err));
+    handle.spawn(signals);
+    receiver
+}
+
+fn run<T: Transport>(
+    app: App<T>,
+    event_loop: &mut Core,
+    database_path: &PathBuf,
+    options: RunOptions,
+) -> Result<String, Error> {
+    let app = app.as_ref();
+    #[cfg(feature = "chaos")]
+    let chaos = Chaos::new(app.config.chaos.clone().unwrap_or_default());
+    #[cfg(feature = "chaos")]
+    let app = app.with_transports(|transport, chain| chaos.transport(transport, chain));
+    let mut app = app;
+    #[cfg(feature = "admin-api")]
+    let levels = options.reload.levels.clone();
+    let (commands, command_receiver) = command_channel();
+    reload_on_sighup(
+        &event_loop.handle(),
+        app.tunables.clone(),
+        app.config.clone(),
+        options.reload,
+        commands.clone(),
+    );
+
+    info!(target: "bridge", "Checking that home and foreign are different chains");
+    event_loop.run(check_same_chain(&app))?;
+
+    if app.config.foreign_consensus.is_some() {
+        info!(target: "bridge", "Checking the confirmations of foreign against its consensus");
+        if let Some(confirmations) = event_loop.run(check_safe_confirmations(&app))? {
+            // every component reads its confirmations from the config
+            app.config.foreign.required_confirmations = confirmations.effective;
+        }
+    }
+    let app_ref = Arc::new(app);
+
+    if app_ref.config.derived_recipients.is_some() {
+        info!(target: "bridge", "Checking the factory of the contract wallets on foreign");
+        event_loop.run(check_wallet_factory(&app_ref))?;
+    }
+
+    let database = Database::load(database_path)?;
+    let database = with_deploy_blocks(&app_ref, event_loop, database, database_path)?;
+
+    info!(target: "bridge", "Estimating the backlog to catch up with");
+    let estimate = event_loop.run(estimate_catch_up(app_ref.clone(), &database))?;
+    info!(target: "bridge", "{}", estimate);
+    if let Some(max) = options.max_catchup_blocks {
+        if estimate.max_blocks() > max {
+            return Err(format!(
+                "Refusing to start: {} unprocessed blocks exceed --max-catchup-blocks {}.\n\n\
+                 {}\n\n\
+                 To relay the whole backlog, restart with a higher --max-catchup-blocks. \
+                 To skip it, set checked_deposit_relay, checked_withdraw_relay and \
+                 checked_withdraw_confirm in the database {:?} to the blocks to resume after",
+                estimate.max_blocks(),
+                max,
+                estimate,
+                database_path
+            ).into());
+        }
+    }
+
+    info!(target: "bridge", "Checking gas limits");
+    event_loop.run(check_gas_limits(&app_ref))?;
+
+    info!(target: "bridge", "Checking gas prices");
+    event_loop.run(check_gas_prices(&app_ref))?;
+
+    if let Some(ref range) = app_ref.config.startup_jitter {
+        let delay = Jitter::new(&app_ref.config.home.account).delay(range);
+        info!(
+            target: "bridge",
+            "Delaying the start by {} (startup_jitter {})",
+            format_duration(delay),
+            range
+        );
+        event_loop.run(app_ref.timer.sleep(delay))?;
+    }
+    if let Some(ref range) = app_ref.config.poll_jitter {
+        info!(target: "bridge", "Delaying every poll by {} (poll_jitter)", range);
+    }
 
     info!(target: "bridge", "Starting listening to events");
-    let bridge = create_bridge(app_ref, &database)
-        .and_then(|_| future::ok(true))
-        .collect();
-    event_loop.run(bridge)?;
+    #[cfg(not(feature = "chaos"))]
+    let mut bridge = create_bridge(app_ref.clone(), &database);
+    #[cfg(feature = "chaos")]
+    let mut bridge = {
+        let backend = FileBackend::new(app_ref.database_path.clone(), &database);
+        create_bridge_backed_by(app_ref.clone(), &database, chaos.backend(backend))
+    };
+    bridge.track_catch_up(estimate);
+    bridge.commands_from(command_receiver);
+    #[cfg(feature = "rest-api")]
+    let history_api = app_ref
+        .config
+        .relay_history
+        .as_ref()
+        .and_then(|history| history.api.as_ref());
+    // serves until dropped at the end of the run
+    #[cfg(feature = "rest-api")]
+    let _history_api = match (history_api, bridge.relay_history()) {
+        (Some(config), Some(relays)) => {
+            info!(target: "bridge", "Serving the relay history on {}", config.listen);
+            Some(serve_history(relays, config)?)
+        }
+        _ => None,
+    };
+    #[cfg(feature = "admin-api")]
+    let _admin_api = match (app_ref.config.admin.as_ref(), levels) {
+        (Some(config), Some(levels)) => {
+            info!(target: "bridge", "Serving the admin api on {}", config.listen);
+            Some(serve_admin(levels, config, event_loop.remote(), commands)?)
+        }
+        (Some(_), None) => {
+            warn!(target: "bridge", "Not serving the admin api, the logger isn't installed");
+            None
+        }
+        _ => None,
+    };
+    bridge.drain_on(drain_on_sigusr2(&event_loop.handle()), options.drain_timeout);
+    let result = event_loop.run(bridge.by_ref().and_then(|_| future::ok(true)).collect());
+
+    if let Err(err) = bridge.release_leadership() {
+        warn!(target: "bridge", "cannot release leadership lease: {}", err);
+    }
 
+    bridge.digest(DigestReason::Shutdown);
+    #[cfg(feature = "chaos")]
+    info!(target: "bridge", "chaos injected {}", chaos.counts());
+    if let Err(err) = bridge.write_relay_chain_head() {
+        warn!(target: "bridge", "cannot write relay chain head: {}", err);
+    }
+    let report = bridge.shutdown_report(result.as_ref().err());
+    info!(target: "bridge", "shutdown report: {}", report.to_json());
+    if let Some(ref path) = app_ref.config.shutdown_report {
+        report.write(path)?;
+    }
+
+    result?;
+    // the supervisor starts the next version on this exit code
+    if let Some(report) = bridge.drain_report() {
+        println!("{}", report.to_json());
+        // exiting skips the destructors
+        drop(options.lock);
+        process::exit(DRAINED_EXIT_CODE);
+    }
     Ok("Done".into())
 }
@@ -152,6 +152,8 @@ library Message {
         return value;
     }
 
+    /// transaction hash of the withdraw on `foreign`.
+    /// `keccak256(transactionHash, logIndex)` if the bridges use unique log ids
     function getTransactionHash(bytes message) internal pure returns (bytes32) {
         bytes32 hash;
         // solium-disable-next-line security/no-inline-assembly
@@ -192,6 +194,68 @@ library MessageTest {
 }
 
 
+/// Payout addresses accounts register for the withdraws sent to them.
+/// `HomeBridge.withdrawToPayout` pays a withdraw to the payout address
+/// its recipient registered here instead of the recipient itself.
+contract PayoutRegistry {
+    /// payout address registered by each account. 0 if none.
+    mapping (address => address) payoutAddresses;
+
+    /// Event created when an account registers or removes its payout address.
+    event PayoutAddressRegistered (address account, address payout);
+
+    /// withdraws to `msg.sender` are paid to `payout` from now on.
+    /// 0 removes the payout address.
+    function registerPayoutAddress(address payout) public {
+        payoutAddresses[msg.sender] = payout;
+        PayoutAddressRegistered(msg.sender, payout);
+    }
+
+    /// returns the payout address registered by `account`. 0 if none.
+    function payoutAddressOf(address account) public view returns (address) {
+        return payoutAddresses[account];
+    }
+}
+
+/// Receipts of the deposits relayed to foreign, recorded on home by the authorities.
+/// lets users see on home that their deposit was completed on foreign.
+contract ReceiptRegistry {
+    /// accounts allowed to record receipts.
+    address[] public authorities;
+
+    /// foreign transaction that completed the deposit of each home transaction. 0 if none.
+    mapping (bytes32 => bytes32) receipts;
+
+    /// Event created when the receipt of a deposit is recorded.
+    event ReceiptRecorded (bytes32 homeTransactionHash, bytes32 foreignTransactionHash);
+
+    function ReceiptRegistry(address[] authoritiesParam) public {
+        require(authoritiesParam.length != 0);
+        authorities = authoritiesParam;
+    }
+
+    /// require that sender is an authority
+    modifier onlyAuthority() {
+        require(Helpers.addressArrayContains(authorities, msg.sender));
+        _;
+    }
+
+    /// records that the deposit of `homeTransactionHash` was completed
+    /// in `foreignTransactionHash`. a receipt is recorded only once.
+    function recordReceipt(bytes32 homeTransactionHash, bytes32 foreignTransactionHash) public onlyAuthority() {
+        require(foreignTransactionHash != 0);
+        require(receipts[homeTransactionHash] == 0);
+        receipts[homeTransactionHash] = foreignTransactionHash;
+        ReceiptRecorded(homeTransactionHash, foreignTransactionHash);
+    }
+
+    /// returns the foreign transaction that completed the deposit of `homeTransactionHash`.
+    /// 0 if no receipt was recorded.
+    function receiptOf(bytes32 homeTransactionHash) public view returns (bytes32) {
+        return receipts[homeTransactionHash];
+    }
+}
+
 contract HomeBridge {
     /// Number of authorities signatures required to withdraw the money.
     ///
@@ -220,6 +284,10 @@ contract HomeBridge {
     /// Contract authorities.
     address[] public authorities;
 
+    /// `PayoutRegistry` of the payout addresses `withdrawToPayout` pays withdraws to.
+    /// 0 if withdraws are only paid to their recipients.
+    address public payoutRegistry;
+
     /// Used foreign transaction hashes.
     mapping (bytes32 => bool) withdraws;
 
@@ -229,13 +297,18 @@ contract HomeBridge {
     /// Event created on money withdraw.
     event Withdraw (address recipient, uint256 value, bytes32 transactionHash);
 
+    /// Event created after `Withdraw` if the withdraw was paid to the payout address
+    /// the recipient registered.
+    event PayoutSubstituted (address recipient, address payout, bytes32 transactionHash);
+
     /// Constructor.
     function HomeBridge(
         uint256 requiredSignaturesParam,
         address[] authoritiesParam,
         uint256 estimatedGasCostOfWithdrawParam,
         uint256 maxTotalHomeContractBalanceParam,
-        uint256 maxSingleDepositValueParam
+        uint256 maxSingleDepositValueParam,
+        address payoutRegistryParam
     ) public
     {
         require(requiredSignaturesParam != 0);
@@ -245,6 +318,7 @@ contract HomeBridge {
         estimatedGasCostOfWithdraw = estimatedGasCostOfWithdrawParam;
         maxTotalHomeContractBalance = maxTotalHomeContractBalanceParam;
         maxSingleDepositValue = maxSingleDepositValueParam;
+        payoutRegistry = payoutRegistryParam;
     }
 
     /// Should be used to deposit money.
@@ -268,6 +342,24 @@ contract HomeBridge {
     /// transfering any ether `value` out of this contract to `recipient`.
     /// bridge users must trust a majority of `requiredSignatures` of the `authorities`.
     function withdraw(uint8[] vs, bytes32[] rs, bytes32[] ss, bytes message) public {
+        performWithdraw(vs, rs, ss, message, address(0));
+    }
+
+    /// like `withdraw`, but transfers `value` to `payout` instead of `recipient`.
+    /// `payout` must be the payout address `recipient` registered in `payoutRegistry`,
+    /// so neither the caller nor the authorities can redirect a withdraw.
+    function withdrawToPayout(uint8[] vs, bytes32[] rs, bytes32[] ss, bytes message, address payout) public {
+        require(message.length == 116);
+        require(payoutRegistry != address(0));
+        require(payout != address(0));
+        require(PayoutRegistry(payoutRegistry).payoutAddressOf(Message.getRecipient(message)) == payout);
+
+        performWithdraw(vs, rs, ss, message, payout);
+    }
+
+    /// performs the withdraw of `message`. transfers `value` to `payout`
+    /// or to `recipient` if `payout` is 0.
+    function performWithdraw(uint8[] vs, bytes32[] rs, bytes32[] ss, bytes message, address payout) internal {
         require(message.length == 116);
 
         // check that at least `requiredSignatures` `authorities` have signed `message`
@@ -286,7 +378,8 @@ contract HomeBridge {
         // and effectively burning recipients withdrawn value.
         // see https://github.com/paritytech/parity-bridge/issues/112
         // for further explanation.
-        require((recipient == msg.sender) || (tx.gasprice == homeGasPrice));
+        // with `withdrawToPayout` the payout address of the recipient may as well.
+        require((recipient == msg.sender) || (payout != address(0) && payout == msg.sender) || (tx.gasprice == homeGasPrice));
 
         // The following two statements guard against reentry into this function.
         // Duplicated withdraw or reentry.
@@ -299,13 +392,25 @@ contract HomeBridge {
         // charge recipient for relay cost
         uint256 valueRemainingAfterSubtractingCost = value - estimatedWeiCostOfWithdraw;
 
-        // pay out recipient
-        recipient.transfer(valueRemainingAfterSubtractingCost);
+        // pay out recipient, or its payout address
+        if (payout == address(0)) {
+            recipient.transfer(valueRemainingAfterSubtractingCost);
+        } else {
+            payout.transfer(valueRemainingAfterSubtractingCost);
+        }
 
         // refund relay cost to relaying authority
         msg.sender.transfer(estimatedWeiCostOfWithdraw);
 
         Withdraw(recipient, valueRemainingAfterSubtractingCost, hash);
+        if (payout != address(0)) {
+            PayoutSubstituted(recipient, payout, hash);
+        }
+    }
+
+    /// returns `true` if the withdraw from foreign `transactionHash` has been executed.
+    function isWithdrawExecuted(bytes32 transactionHash) public view returns (bool) {
+        return withdraws[transactionHash];
     }
 }
 
@@ -455,6 +560,10 @@ contract ForeignBridge {
     /// deposit recipient (bytes20)
     /// deposit value (uint256)
     /// mainnet transaction hash (bytes32) // to avoid transaction duplication
+    ///
+    /// bridges with unique log ids enabled pass `keccak256(transactionHash, logIndex)`
+    /// as `transactionHash`, so several deposits of one transaction are told apart.
+    /// all authorities must use the same kind of id.
     function deposit(address recipient, uint256 value, bytes32 transactionHash) public onlyAuthority() {
         // Protection from misbehaving authority
         var hash = keccak256(recipient, value, transactionHash);
@@ -480,6 +589,13 @@ contract ForeignBridge {
         Deposit(recipient, value, transactionHash);
     }
 
+    /// returns `true` if `requiredSignatures` authorities have confirmed the deposit
+    /// and the tokens have been minted.
+    function isDepositExecuted(address recipient, uint256 value, bytes32 transactionHash) public view returns (bool) {
+        var hash = keccak256(recipient, value, transactionHash);
+        return deposits[hash].length >= requiredSignatures;
+    }
+
     /// Transfer `value` from `msg.sender`s local balance (on `foreign` chain) to `recipient` on `home` chain.
     ///
     /// immediately decreases `msg.sender`s local balance.
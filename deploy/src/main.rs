@@ -16,7 +16,7 @@ use docopt::Docopt;
 use tokio_core::reactor::Core;
 
 use bridge::app::App;
-use bridge::bridge::{DeployForeign, DeployHome};
+use bridge::bridge::{deploy_record_dir, DeployForeign, DeployHome};
 use bridge::config::Config;
 use bridge::error::Error;
 use bridge::database::Database;
@@ -79,22 +79,28 @@ Options:
     info!(target: "parity-bridge-deploy", "Starting event loop");
     let mut event_loop = Core::new().unwrap();
 
-    info!(target: "parity-bridge-deploy", "Establishing ipc connection");
-    let app = App::new_ipc(config, &args.arg_database, &event_loop.handle())?;
+    info!(target: "parity-bridge-deploy", "Connecting to the nodes");
+    let app = App::new(config, &args.arg_database, &event_loop.handle())?;
     let app_ref = Arc::new(app.as_ref());
 
     info!(target: "parity-bridge-deploy", "Deploying HomeBridge contract");
     let home_deployed = event_loop.run(DeployHome::new(app_ref.clone()))?;
-    home_deployed.dump_info(format!(
-        "deployment-home-{}",
-        home_deployed.contract_address
+    home_deployed.dump_info(deploy_record_dir(
+        "home",
+        &home_deployed
+            .receipt
+            .contract_address
+            .expect("contract creation receipt must have an address; qed"),
     ))?;
 
     info!(target: "parity-bridge-deploy", "Deploying ForeignBridge contract");
     let foreign_deployed = event_loop.run(DeployForeign::new(app_ref.clone()))?;
-    foreign_deployed.dump_info(format!(
-        "deployment-foreign-{}",
-        foreign_deployed.contract_address
+    foreign_deployed.dump_info(deploy_record_dir(
+        "foreign",
+        &foreign_deployed
+            .receipt
+            .contract_address
+            .expect("contract creation receipt must have an address; qed"),
     ))?;
 
     let database = Database::from_receipts(&home_deployed.receipt, &foreign_deployed.receipt);
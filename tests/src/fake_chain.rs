@@ -0,0 +1,538 @@
+//! In memory chain answering the subset of rpc the bridge uses.
+//!
+//! unlike `MockedTransport` requests are not expected in a fixed order. tests change the
+//! chain (mine blocks, emit events, reorg) and the components query it like a node.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use futures::{future, Future};
+use futures::sync::oneshot;
+use rpc;
+use serde_json;
+use web3::{self, RequestId, Transport};
+use web3::helpers::build_request;
+use web3::types::{Address, Bytes, H256, H520, Log, U256};
+use bridge::simulation::filter_matches;
+
+/// chain id answered for `eth_chainId`
+pub const FAKE_CHAIN_ID: u64 = 17;
+
+/// answers an `eth_call` with the call data
+type CallHandler = Box<Fn(&[u8]) -> Vec<u8> + Send>;
+
+/// Event emitted by a transaction of the next mined block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeEvent {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: Vec<u8>,
+    pub transaction_hash: H256,
+}
+
+/// Failure of a request, see `FakeChain::fail_next`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FakeFailure {
+    /// answers with a json-rpc error without handling the request
+    Reject {
+        code: i64,
+        message: String,
+        data: Option<rpc::Value>,
+    },
+    /// fails like a refused connection without handling the request
+    Refuse,
+    /// handles the request, then drops the connection before responding
+    DropResponse,
+}
+
+#[derive(Debug, Clone)]
+struct FakeBlock {
+    hash: H256,
+    parent_hash: H256,
+    transactions: Vec<H256>,
+    logs: Vec<rpc::Value>,
+}
+
+/// hash of block `number` of fork `fork`. blocks of different forks have different hashes.
+fn block_hash(number: u64, fork: u64) -> H256 {
+    let mut hash = H256::from(number);
+    hash.0[0] = 0xb1;
+    hash.0[1] = fork as u8;
+    hash
+}
+
+/// hash of the `n`th sent transaction
+fn transaction_hash(n: u64) -> H256 {
+    let mut hash = H256::from(n);
+    hash.0[0] = 0x7e;
+    hash
+}
+
+/// address of the contract created by the `n`th sent transaction
+fn contract_address(n: u64) -> Address {
+    let mut address = Address::from(n);
+    address.0[0] = 0xc0;
+    address
+}
+
+/// parses a hex encoded quantity.
+fn quantity(value: &rpc::Value) -> Option<u64> {
+    value
+        .as_str()
+        .and_then(|s| u64::from_str_radix(s.trim_left_matches("0x"), 16).ok())
+}
+
+struct ChainState {
+    blocks: Vec<FakeBlock>,
+    /// hash and params of the transactions of the next mined block
+    txpool: Vec<(H256, rpc::Value)>,
+    /// events of the next mined block
+    events: Vec<FakeEvent>,
+    /// every transaction sent, in order
+    sent: Vec<rpc::Value>,
+    balances: HashMap<Address, U256>,
+    /// code of the contracts. accounts without code aren't listed
+    codes: HashMap<Address, Bytes>,
+    /// contracts created by sent transactions
+    created: HashMap<H256, Address>,
+    /// `eth_call` handlers by function selector
+    calls: Vec<(Vec<u8>, CallHandler)>,
+    /// number of requests by method
+    requests: HashMap<String, u64>,
+    /// number of reorgs
+    forks: u64,
+    signatures: u64,
+    next_id: RequestId,
+    /// every response is sent this long after its request, like by a remote node
+    latency: Option<Duration>,
+    /// block answered for the `finalized` tag. `None` answers null like a node without it
+    finalized: Option<u64>,
+    /// failures of the next requests of a method
+    failures: Vec<(String, FakeFailure)>,
+}
+
+impl ChainState {
+    fn head(&self) -> u64 {
+        self.blocks.len() as u64 - 1
+    }
+
+    fn mine_block(&mut self) {
+        let number = self.blocks.len() as u64;
+        let hash = block_hash(number, self.forks);
+        let parent_hash = self.blocks
+            .last()
+            .map(|block| block.hash)
+            .unwrap_or_default();
+        let mut transactions = mem::replace(&mut self.txpool, Vec::new())
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect::<Vec<_>>();
+        let mut logs = Vec::new();
+        for (index, event) in mem::replace(&mut self.events, Vec::new())
+            .into_iter()
+            .enumerate()
+        {
+            if !transactions.contains(&event.transaction_hash) {
+                transactions.push(event.transaction_hash);
+            }
+            let transaction_index = transactions
+                .iter()
+                .position(|hash| *hash == event.transaction_hash)
+                .expect("transaction of the event was just added; qed");
+            logs.push(json!({
+                "address": event.address,
+                "topics": event.topics,
+                "data": Bytes(event.data),
+                "blockHash": hash,
+                "blockNumber": U256::from(number),
+                "transactionHash": event.transaction_hash,
+                "transactionIndex": U256::from(transaction_index),
+                "logIndex": U256::from(index),
+                "type": "mined"
+            }));
+        }
+        self.blocks.push(FakeBlock {
+            hash,
+            parent_hash,
+            transactions,
+            logs,
+        });
+    }
+
+    fn block(&self, number: u64) -> rpc::Value {
+        let block = match self.blocks.get(number as usize) {
+            Some(block) => block,
+            None => return rpc::Value::Null,
+        };
+        json!({
+            "hash": block.hash,
+            "parentHash": block.parent_hash,
+            "sha3Uncles": H256::zero(),
+            "miner": Address::zero(),
+            "stateRoot": H256::zero(),
+            "transactionsRoot": H256::zero(),
+            "receiptsRoot": H256::zero(),
+            "number": U256::from(number),
+            "gasUsed": "0x0",
+            "gasLimit": "0x0",
+            "extraData": "0x",
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+            "timestamp": U256::from(number),
+            "difficulty": "0x0",
+            "totalDifficulty": "0x0",
+            "sealFields": [],
+            "uncles": [],
+            "transactions": block.transactions,
+            "size": "0x0"
+        })
+    }
+
+    fn receipt(&self, hash: &H256) -> rpc::Value {
+        for (number, block) in self.blocks.iter().enumerate() {
+            let index = match block.transactions.iter().position(|tx| tx == hash) {
+                Some(index) => index,
+                None => continue,
+            };
+            let logs = block
+                .logs
+                .iter()
+                .filter(|log| log["transactionHash"] == json!(hash))
+                .cloned()
+                .collect::<Vec<_>>();
+            return json!({
+                "transactionHash": hash,
+                "transactionIndex": U256::from(index),
+                "blockHash": block.hash,
+                "blockNumber": U256::from(number),
+                "cumulativeGasUsed": "0x1",
+                "gasUsed": "0x1",
+                "contractAddress": self.created.get(hash),
+                "logs": logs,
+                "logsBloom": format!("0x{}", "0".repeat(512)),
+                "status": "0x1"
+            });
+        }
+        rpc::Value::Null
+    }
+
+    fn respond(&mut self, method: &str, params: &[rpc::Value]) -> Result<rpc::Value, String> {
+        *self.requests.entry(method.to_owned()).or_insert(0) += 1;
+        let param = |index: usize| params.get(index).cloned().unwrap_or(rpc::Value::Null);
+        match method {
+            "web3_clientVersion" => Ok(json!("FakeChain/v0.1.0")),
+            "eth_blockNumber" => Ok(json!(U256::from(self.head()))),
+            "eth_chainId" => Ok(json!(U256::from(FAKE_CHAIN_ID))),
+            "eth_getBlockByNumber" => {
+                let number = match param(0).as_str() {
+                    Some("latest") => Some(self.head()),
+                    Some("finalized") => self.finalized,
+                    _ => quantity(&param(0)),
+                };
+                Ok(number
+                    .map(|number| self.block(number))
+                    .unwrap_or(rpc::Value::Null))
+            }
+            "eth_getLogs" => {
+                let filter = param(0);
+                let logs = self.blocks
+                    .iter()
+                    .flat_map(|block| block.logs.iter())
+                    .filter(|log| filter_matches(&filter, log))
+                    .cloned()
+                    .collect();
+                Ok(rpc::Value::Array(logs))
+            }
+            "eth_sendTransaction" | "personal_sendTransaction" | "eth_sendRawTransaction" => {
+                self.sent.push(param(0));
+                let hash = transaction_hash(self.sent.len() as u64);
+                // transactions without `to` create a contract with their data as code
+                let transaction = param(0);
+                if transaction.is_object() && transaction["to"].is_null() {
+                    let address = contract_address(self.sent.len() as u64);
+                    let code = serde_json::from_value(transaction["data"].clone())
+                        .unwrap_or_else(|_| Bytes(Vec::new()));
+                    self.codes.insert(address, code);
+                    self.created.insert(hash, address);
+                }
+                self.txpool.push((hash, param(0)));
+                Ok(json!(hash))
+            }
+            "parity_pendingTransactions" => Ok(rpc::Value::Array(
+                self.txpool
+                    .iter()
+                    .map(|&(ref hash, ref transaction)| {
+                        json!({
+                            "hash": hash,
+                            "from": transaction["from"],
+                            "to": transaction["to"],
+                            "input": transaction["data"]
+                        })
+                    })
+                    .collect(),
+            )),
+            "eth_getTransactionCount" => {
+                // raw transactions carry no `from`,
+                // so every sent transaction counts for the queried account
+                let pending = self.sent.len();
+                match param(1).as_str() {
+                    Some("pending") => Ok(json!(U256::from(pending))),
+                    _ => Ok(json!(U256::from(pending - self.txpool.len()))),
+                }
+            }
+            "txpool_status" => Ok(json!({
+                "pending": U256::from(self.txpool.len()),
+                "queued": "0x0"
+            })),
+            "eth_getTransactionReceipt" => {
+                let hash: H256 = serde_json::from_value(param(0)).map_err(|err| err.to_string())?;
+                Ok(self.receipt(&hash))
+            }
+            "eth_getBalance" => {
+                let address: Address =
+                    serde_json::from_value(param(0)).map_err(|err| err.to_string())?;
+                Ok(json!(self.balances.get(&address).cloned().unwrap_or_default()))
+            }
+            "eth_getCode" => {
+                let address: Address =
+                    serde_json::from_value(param(0)).map_err(|err| err.to_string())?;
+                let code = self.codes.get(&address).cloned();
+                Ok(json!(code.unwrap_or_else(|| Bytes(Vec::new()))))
+            }
+            "eth_call" => {
+                let data: Bytes =
+                    serde_json::from_value(param(0)["data"].clone()).map_err(|err| err.to_string())?;
+                let handler = self.calls
+                    .iter()
+                    .find(|&&(ref selector, _)| data.0.starts_with(selector));
+                match handler {
+                    Some(&(_, ref handler)) => Ok(json!(Bytes(handler(&data.0)))),
+                    None => Err(format!("fake chain has no eth_call handler for {:?}", data)),
+                }
+            }
+            "eth_sign" | "personal_sign" => {
+                self.signatures += 1;
+                let mut signature = [0u8; 65];
+                signature[..32].copy_from_slice(&H256::from(self.signatures).0);
+                signature[64] = 27;
+                Ok(json!(H520::from(signature)))
+            }
+            _ => Err(format!("fake chain doesn't answer {}", method)),
+        }
+    }
+}
+
+/// In memory chain implementing `web3::Transport`.
+/// clones share the chain, so tests keep a clone to change it while components use it.
+#[derive(Clone)]
+pub struct FakeChain {
+    state: Arc<Mutex<ChainState>>,
+}
+
+impl fmt::Debug for FakeChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FakeChain at block {}", self.head())
+    }
+}
+
+impl FakeChain {
+    /// chain with only the genesis block
+    pub fn new() -> Self {
+        let mut state = ChainState {
+            blocks: Vec::new(),
+            txpool: Vec::new(),
+            events: Vec::new(),
+            sent: Vec::new(),
+            balances: HashMap::new(),
+            codes: HashMap::new(),
+            created: HashMap::new(),
+            calls: Vec::new(),
+            requests: HashMap::new(),
+            forks: 0,
+            signatures: 0,
+            next_id: 0,
+            latency: None,
+            finalized: None,
+            failures: Vec::new(),
+        };
+        state.mine_block();
+        FakeChain {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn state(&self) -> ::std::sync::MutexGuard<ChainState> {
+        self.state.lock().expect("fake chain is never poisoned; qed")
+    }
+
+    /// number of the latest block
+    pub fn head(&self) -> u64 {
+        self.state().head()
+    }
+
+    /// hash of block `number` of the current fork
+    pub fn block_hash(&self, number: u64) -> Option<H256> {
+        self.state()
+            .blocks
+            .get(number as usize)
+            .map(|block| block.hash)
+    }
+
+    /// mines `blocks` blocks. the first one includes the txpool and the emitted events.
+    pub fn mine(&self, blocks: u64) {
+        let mut state = self.state();
+        for _ in 0..blocks {
+            state.mine_block();
+        }
+    }
+
+    /// mines blocks until the head is at `number`.
+    pub fn mine_to(&self, number: u64) {
+        let head = self.head();
+        self.mine(number.saturating_sub(head));
+    }
+
+    /// emits `event` in the next mined block.
+    pub fn emit(&self, event: FakeEvent) {
+        self.state().events.push(event);
+    }
+
+    /// replaces the blocks from `fork_block` on by a new fork. the head is at `fork_block - 1`
+    /// until blocks are mined. transactions and events of the dropped blocks are gone.
+    pub fn reorg(&self, fork_block: u64) {
+        let mut state = self.state();
+        assert!(
+            fork_block > 0 && fork_block <= state.head(),
+            "fork block {} is not in 1..={}",
+            fork_block,
+            state.head()
+        );
+        state.blocks.truncate(fork_block as usize);
+        state.forks += 1;
+    }
+
+    /// logs of all blocks of the current fork.
+    pub fn logs(&self) -> Vec<Log> {
+        self.state()
+            .blocks
+            .iter()
+            .flat_map(|block| block.logs.iter())
+            .map(|log| serde_json::from_value(log.clone()).expect("fake logs are valid; qed"))
+            .collect()
+    }
+
+    /// number of sent transactions waiting for the next mined block.
+    pub fn pending(&self) -> usize {
+        self.state().txpool.len()
+    }
+
+    /// params of every transaction sent to the chain, in order.
+    pub fn sent_transactions(&self) -> Vec<rpc::Value> {
+        self.state().sent.clone()
+    }
+
+    /// number of `method` requests answered so far.
+    pub fn requests(&self, method: &str) -> u64 {
+        self.state()
+            .requests
+            .get(method)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// answers every request after `latency` from another thread.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state().latency = Some(latency);
+    }
+
+    /// fails the next `method` request with `failure`. failures of a method fail its requests
+    /// in the order they were added.
+    pub fn fail_next(&self, method: &str, failure: FakeFailure) {
+        self.state().failures.push((method.to_owned(), failure));
+    }
+
+    /// answers the `finalized` block tag with `block`.
+    pub fn set_finalized(&self, block: u64) {
+        self.state().finalized = Some(block);
+    }
+
+    pub fn set_balance(&self, address: Address, balance: U256) {
+        self.state().balances.insert(address, balance);
+    }
+
+    /// deploys a contract with `code` at `address`.
+    pub fn set_code(&self, address: Address, code: Vec<u8>) {
+        self.state().codes.insert(address, Bytes(code));
+    }
+
+    /// answers `eth_call`s of call data starting with `selector` with `handler`.
+    pub fn on_call<F>(&self, selector: &[u8], handler: F)
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+    {
+        self.state()
+            .calls
+            .push((selector.to_vec(), Box::new(handler)));
+    }
+}
+
+impl Transport for FakeChain {
+    type Out = web3::Result<rpc::Value>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let mut state = self.state();
+        state.next_id += 1;
+        (state.next_id, build_request(state.next_id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let request = serde_json::to_value(&request).unwrap_or(rpc::Value::Null);
+        let method = request["method"].as_str().unwrap_or_default();
+        let params = request["params"].as_array().cloned().unwrap_or_default();
+        let (response, latency) = {
+            let mut state = self.state();
+            let failure = state
+                .failures
+                .iter()
+                .position(|&(ref failing, _)| failing == method)
+                .map(|index| state.failures.remove(index).1);
+            let response = match failure {
+                None => state.respond(method, &params).map_err(web3::Error::Transport),
+                Some(FakeFailure::Reject {
+                    code,
+                    message,
+                    data,
+                }) => Err(web3::Error::Rpc(rpc::Error {
+                    code: rpc::ErrorCode::ServerError(code),
+                    message,
+                    data,
+                })),
+                Some(FakeFailure::Refuse) => {
+                    Err(web3::Error::Transport("connection refused".into()))
+                }
+                Some(FakeFailure::DropResponse) => {
+                    let _ = state.respond(method, &params);
+                    Err(web3::Error::Transport("connection reset by peer".into()))
+                }
+            };
+            (response, state.latency)
+        };
+        match latency {
+            None => Box::new(future::result(response)),
+            Some(latency) => {
+                let (sender, receiver) = oneshot::channel();
+                thread::spawn(move || {
+                    thread::sleep(latency);
+                    let _ = sender.send(response);
+                });
+                Box::new(
+                    receiver
+                        .map_err(|_| web3::Error::Transport("fake chain dropped a response".into()))
+                        .and_then(|response| response),
+                )
+            }
+        }
+    }
+}
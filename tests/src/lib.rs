@@ -4,11 +4,19 @@ extern crate futures;
 extern crate jsonrpc_core as rpc;
 #[macro_use]
 extern crate pretty_assertions;
+#[macro_use]
 extern crate serde_json;
 extern crate web3;
 
+mod fake_chain;
+mod stub_signer;
+
 use std::cell::Cell;
 use web3::Transport;
+use bridge::transport::CaptureEntry;
+
+pub use fake_chain::{FakeChain, FakeEvent, FakeFailure, FAKE_CHAIN_ID};
+pub use stub_signer::StubSigner;
 
 #[derive(Debug, Clone)]
 pub struct MockedRequest {
@@ -32,6 +40,37 @@ pub struct MockedTransport {
     pub mocked_responses: Vec<serde_json::Value>,
 }
 
+impl MockedTransport {
+    /// replays the requests to `chain` from a capture written by `RecordingTransport`.
+    /// every request is expected in captured order and answered with the captured response.
+    ///
+    /// panics on captured failed requests, which can't be mocked.
+    pub fn from_capture(capture: &str, chain: &str) -> Self {
+        let entries = capture
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str::<CaptureEntry>(line).expect("invalid capture entry"))
+            .filter(|entry| entry.chain == chain)
+            .collect::<Vec<_>>();
+
+        if let Some(entry) = entries.iter().find(|entry| entry.error.is_some()) {
+            panic!("cannot replay failed request {}", entry.method);
+        }
+
+        MockedTransport {
+            requests: Default::default(),
+            expected_requests: entries
+                .iter()
+                .map(|entry| MockedRequest {
+                    method: entry.method.clone(),
+                    params: entry.params.clone(),
+                })
+                .collect(),
+            mocked_responses: entries.into_iter().map(|entry| entry.response).collect(),
+        }
+    }
+}
+
 impl Transport for MockedTransport {
     type Out = web3::Result<rpc::Value>;
 
@@ -56,6 +95,10 @@ impl Transport for MockedTransport {
             .iter()
             .nth(self.requests.get() - 1)
             .expect("missing response");
+        // `{"error": message}` fails the request, e.g. to mock nodes without old state
+        if let Some(message) = response.get("error").and_then(|error| error.as_str()) {
+            return Box::new(futures::failed(web3::Error::Transport(message.into())));
+        }
         let f = futures::finished(response.clone());
         Box::new(f)
     }
@@ -124,6 +167,7 @@ macro_rules! test_app_stream {
                 TransactionConfig
             };
 			use self::bridge::database::Database;
+			use self::bridge::tunables::SharedTunables;
 
 			let home = $crate::MockedTransport {
 				requests: Default::default(),
@@ -147,38 +191,32 @@ macro_rules! test_app_stream {
 				mocked_responses: vec![$($foreign_res),*],
 			};
 
-			let config = Config {
-				txs: $txs,
-				home: Node {
+			let config = Config::builder()
+				.transactions($txs)
+				.home(Node {
 					account: $home_acc.parse().unwrap(),
-					ipc: "".into(),
-					contract: ContractConfig {
-						bin: Default::default(),
-					},
 					poll_interval: Duration::from_secs(0),
-					request_timeout: Duration::from_secs(5),
 					required_confirmations: $home_conf,
-				},
-				foreign: Node {
+					..Node::new(Default::default(), "".into())
+				})
+				.foreign(Node {
 					account: $foreign_acc.parse().unwrap(),
-					ipc: "".into(),
-					contract: ContractConfig {
-						bin: Default::default(),
-					},
 					poll_interval: Duration::from_secs(0),
-					request_timeout: Duration::from_secs(5),
 					required_confirmations: $foreign_conf,
-				},
-				authorities: Authorities {
+					..Node::new(Default::default(), "".into())
+				})
+				.authorities(Authorities {
 					accounts: $authorities_accs.iter().map(|a: &&str| a.parse().unwrap()).collect(),
 					required_signatures: $signatures,
-				},
-				estimated_gas_cost_of_withdraw: U256::from_dec_str("100000").unwrap(),
-				max_total_home_contract_balance: U256::from_dec_str("10000000000000000000").unwrap(),
-				max_single_deposit_value: U256::from_dec_str("1000000000000000000").unwrap(),
-			};
+				})
+				.estimated_gas_cost_of_withdraw(U256::from_dec_str("100000").unwrap())
+				.max_total_home_contract_balance(U256::from_dec_str("10000000000000000000").unwrap())
+				.max_single_deposit_value(U256::from_dec_str("1000000000000000000").unwrap())
+				.build()
+				.unwrap();
 
 			let app = App {
+				tunables: SharedTunables::of(&config),
 				config,
 				database_path: "".into(),
 				connections: Connections {
@@ -187,6 +225,10 @@ macro_rules! test_app_stream {
 				},
 				home_bridge: home::HomeBridge::default(),
 				foreign_bridge: foreign::ForeignBridge::default(),
+				abi: None,
+				node_gas_price: Default::default(),
+				chain_clocks: Default::default(),
+				node_versions: Default::default(),
 				timer: Default::default(),
 			};
 
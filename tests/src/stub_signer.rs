@@ -0,0 +1,116 @@
+//! Signing service answering the signing requests of a `RemoteSigner` over http.
+//!
+//! it signs nothing: every request is answered with the same signature and recorded,
+//! so tests check what the bridge asked for.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use serde_json;
+use web3::types::Bytes;
+
+#[derive(Debug, Default)]
+struct StubState {
+    /// bodies of the requests, in order
+    requests: Vec<serde_json::Value>,
+    /// `Authorization` headers of the requests
+    authorizations: Vec<Option<String>>,
+    /// status answered instead of a signature
+    failing: Option<u16>,
+}
+
+/// Signing service on a local port, running until the test ends.
+#[derive(Debug, Clone)]
+pub struct StubSigner {
+    url: String,
+    state: Arc<Mutex<StubState>>,
+}
+
+impl StubSigner {
+    /// answers every signing request with `signature`, 65 bytes.
+    pub fn start(signature: Vec<u8>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a local port is free");
+        let url = format!("http://{}/sign", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(StubState::default()));
+        let shared = state.clone();
+        let response = json!({ "signature": Bytes(signature) }).to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    answer(stream, &shared, &response);
+                }
+            }
+        });
+        StubSigner { url, state }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// bodies of the signing requests so far.
+    pub fn requests(&self) -> Vec<serde_json::Value> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// `Authorization` headers of the signing requests so far.
+    pub fn authorizations(&self) -> Vec<Option<String>> {
+        self.state.lock().unwrap().authorizations.clone()
+    }
+
+    /// answers the next requests with `status` instead of a signature. `None` signs again.
+    pub fn set_failing(&self, status: Option<u16>) {
+        self.state.lock().unwrap().failing = status;
+    }
+}
+
+fn answer(stream: TcpStream, state: &Mutex<StubState>, signature: &str) {
+    let mut reader = BufReader::new(stream.try_clone().expect("streams can be cloned"));
+    let mut length = 0;
+    let mut authorization = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let line = line.trim_right();
+        if line.is_empty() {
+            break;
+        }
+        let mut header = line.splitn(2, ':');
+        let name = header.next().unwrap_or("").to_lowercase();
+        let value = header.next().unwrap_or("").trim().to_owned();
+        match name.as_str() {
+            "content-length" => length = value.parse().unwrap_or(0),
+            "authorization" => authorization = Some(value),
+            _ => (),
+        }
+    }
+    let mut body = vec![0u8; length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let failing = {
+        let mut state = state.lock().unwrap();
+        state
+            .requests
+            .push(serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null));
+        state.authorizations.push(authorization);
+        state.failing
+    };
+    let (status, body) = match failing {
+        Some(status) => (format!("{} Failing", status), "{}".to_owned()),
+        None => ("200 OK".to_owned(), signature.to_owned()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let mut stream = stream;
+    let _ = stream.write_all(response.as_bytes());
+}
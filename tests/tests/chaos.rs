@@ -0,0 +1,278 @@
+#![cfg(feature = "chaos")]
+/// scenarios of the bridge converging against an in memory chain with the faults of `chaos`
+extern crate bridge;
+extern crate futures;
+extern crate rustc_hex;
+extern crate serde_json;
+extern crate tempdir;
+extern crate tests;
+extern crate web3;
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use futures::{Future, Stream};
+use rustc_hex::FromHex;
+use serde_json::Value;
+use tempdir::TempDir;
+use web3::Transport;
+use web3::types::U256;
+use bridge::app::{App, Connections};
+use bridge::bridge::{create_bridge_backed_by, FileBackend};
+use bridge::chaos::{Chaos, ChaosTransport};
+use bridge::config::{Authorities, ChaosConfig, CircuitBreakerConfig, Config,
+                     DegradedPersistenceConfig, FailureAction, FailurePolicyConfig, FailureRule,
+                     Node, PersistenceOutage};
+use bridge::contracts::{foreign, home};
+use bridge::database::Database;
+use bridge::tunables::SharedTunables;
+use tests::{FakeChain, FakeEvent};
+
+const DEPOSIT_TOPIC: &str = "e1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c";
+const DEPOSIT_TRANSACTIONS: [&str; 3] = [
+    "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364",
+    "1111111111111111111111111111111111111111111111111111111111111111",
+    "2222222222222222222222222222222222222222222222222222222222222222",
+];
+
+/// config relaying deposits only, with the faults of `chaos`
+fn config(chaos: ChaosConfig) -> Config {
+    let node = || Node {
+        account: "0000000000000000000000000000000000000001".parse().unwrap(),
+        poll_interval: Duration::from_secs(0),
+        required_confirmations: 12,
+        ..Node::new(Default::default(), "".into())
+    };
+    let config = Config::builder()
+        .home(node())
+        .foreign(node())
+        .authorities(Authorities {
+            accounts: vec![
+                "0000000000000000000000000000000000000001".parse().unwrap(),
+                "0000000000000000000000000000000000000002".parse().unwrap(),
+            ],
+            required_signatures: 1,
+        })
+        .estimated_gas_cost_of_withdraw(U256::from_dec_str("100000").unwrap())
+        .max_total_home_contract_balance(U256::from_dec_str("10000000000000000000").unwrap())
+        .max_single_deposit_value(U256::from_dec_str("1000000000000000000").unwrap())
+        .build()
+        .unwrap();
+    Config {
+        enable_withdrawals: false,
+        chaos: Some(chaos),
+        ..config
+    }
+}
+
+/// app whose nodes inject the faults of `chaos`
+fn app(
+    home: &FakeChain,
+    foreign: &FakeChain,
+    config: Config,
+    database_path: &Path,
+    chaos: &Chaos,
+) -> Arc<App<ChaosTransport<FakeChain>>> {
+    let app = App {
+        tunables: SharedTunables::of(&config),
+        config,
+        database_path: database_path.into(),
+        connections: Connections {
+            home: home.clone(),
+            foreign: foreign.clone(),
+        },
+        home_bridge: home::HomeBridge::default(),
+        foreign_bridge: foreign::ForeignBridge::default(),
+        abi: None,
+        node_gas_price: Default::default(),
+        chain_clocks: Default::default(),
+        node_versions: Default::default(),
+        timer: Default::default(),
+    };
+    Arc::new(app.with_transports(|transport, chain| chaos.transport(transport, chain)))
+}
+
+/// database at `path` checked up to block 5
+fn database_at(path: &Path) -> Database {
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    database.save(fs::File::create(path).unwrap()).unwrap();
+    database
+}
+
+/// deposit of 0xf0 to 0xaff3454fce5edbc8cca8697c15331677e6ebcccc in `transaction`
+fn deposit(transaction: &str) -> FakeEvent {
+    FakeEvent {
+        address: 0.into(),
+        topics: vec![DEPOSIT_TOPIC.parse().unwrap()],
+        data: "000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc\
+               00000000000000000000000000000000000000000000000000000000000000f0"
+            .from_hex()
+            .unwrap(),
+        transaction_hash: transaction.parse().unwrap(),
+    }
+}
+
+/// emits `event` in a new block `number`
+fn emit_at(chain: &FakeChain, number: u64, event: FakeEvent) {
+    chain.mine_to(number - 1);
+    chain.emit(event);
+    chain.mine(1);
+}
+
+/// the next `n` items of `stream`
+fn next<S: Stream>(stream: &mut S, n: u64) -> Vec<S::Item>
+where
+    S::Error: ::std::fmt::Debug,
+{
+    stream.by_ref().take(n).collect().wait().unwrap()
+}
+
+#[test]
+fn chaos_deposit_relay_converges_despite_dropped_responses_and_malformed_logs() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("chaos_deposit_relay_converges").unwrap();
+    let database_path = tempdir.path().join("db.toml");
+    let database = database_at(&database_path);
+    let chaos_config = ChaosConfig {
+        seed: 7,
+        drop_responses: 10,
+        malformed_logs: 20,
+        ..ChaosConfig::default()
+    };
+    let config = Config {
+        circuit_breaker: Some(CircuitBreakerConfig {
+            window: 20,
+            max_failures: 20,
+            cool_down: Duration::from_secs(300),
+            probes: 1,
+        }),
+        // sends whose response and lookup were both dropped are rebuilt at once as well
+        failure_policy: Some(FailurePolicyConfig {
+            rules: vec![FailureRule {
+                kind: "ambiguous_send".into(),
+                action: FailureAction::Alert,
+                severity: None,
+            }],
+        }),
+        ..config(chaos_config.clone())
+    };
+    let chaos = Chaos::new(chaos_config);
+    let app = app(&home, &foreign, config, &database_path, &chaos);
+    let backend = FileBackend::new(database_path.clone(), &database);
+    let mut bridge = create_bridge_backed_by(app, &database, chaos.backend(backend));
+
+    for (i, transaction) in DEPOSIT_TRANSACTIONS.iter().enumerate() {
+        emit_at(&home, 0x100 + i as u64, deposit(transaction));
+    }
+    home.mine_to(0x1011);
+    // failed relays are rebuilt from the last checked block until they get through
+    for _ in 0..100 {
+        if Database::load(&database_path).unwrap().checked_deposit_relay == 0x1005 {
+            break;
+        }
+        next(&mut bridge, 1);
+    }
+    assert_eq!(
+        0x1005,
+        Database::load(&database_path).unwrap().checked_deposit_relay
+    );
+
+    // every deposit is relayed, some more than once
+    let sent = foreign.sent_transactions();
+    for transaction in DEPOSIT_TRANSACTIONS.iter() {
+        assert!(
+            sent.iter()
+                .any(|sent| sent["data"].as_str().unwrap().ends_with(transaction))
+        );
+    }
+    let counts = chaos.counts();
+    assert!(counts.dropped_responses > 0);
+    assert!(counts.malformed_logs > 0);
+    assert_eq!(0, counts.failed_saves);
+}
+
+#[test]
+fn chaos_bridge_catches_up_on_the_database_after_an_outage() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("chaos_bridge_catches_up_on_the_database").unwrap();
+    let database_path = tempdir.path().join("db.toml");
+    let database = database_at(&database_path);
+    let chaos_config = ChaosConfig {
+        seed: 7,
+        persistence_outage: Some(PersistenceOutage {
+            after_saves: 1,
+            saves: 2,
+        }),
+        ..ChaosConfig::default()
+    };
+    let config = Config {
+        degraded_persistence: Some(DegradedPersistenceConfig {
+            max_buffered_saves: 100,
+            max_outage: Duration::from_secs(300),
+            retry_interval: Duration::from_secs(5),
+        }),
+        ..config(chaos_config.clone())
+    };
+    let chaos = Chaos::new(chaos_config);
+    let app = app(&home, &foreign, config, &database_path, &chaos);
+    let backend = FileBackend::new(database_path.clone(), &database);
+    let mut bridge = create_bridge_backed_by(app, &database, chaos.backend(backend));
+
+    emit_at(&home, 0x1007, deposit(DEPOSIT_TRANSACTIONS[0]));
+    home.mine_to(0x1011);
+    next(&mut bridge, 1);
+    assert_eq!(
+        0x1005,
+        Database::load(&database_path).unwrap().checked_deposit_relay
+    );
+
+    // the bridge keeps relaying while the database is unavailable
+    home.mine(1);
+    next(&mut bridge, 1);
+    home.mine(1);
+    next(&mut bridge, 1);
+    assert_eq!(1, foreign.sent_transactions().len());
+    assert_eq!(
+        0x1005,
+        Database::load(&database_path).unwrap().checked_deposit_relay
+    );
+
+    // and writes the checked blocks of the outage with the next save
+    home.mine(1);
+    next(&mut bridge, 1);
+    assert_eq!(
+        0x1008,
+        Database::load(&database_path).unwrap().checked_deposit_relay
+    );
+    assert_eq!(2, chaos.counts().failed_saves);
+    assert_eq!(1, foreign.sent_transactions().len());
+}
+
+#[test]
+fn chaos_receipts_are_delayed_by_blocks() {
+    let home = FakeChain::new();
+    let chaos = Chaos::new(ChaosConfig {
+        receipt_delay: 3,
+        ..ChaosConfig::default()
+    });
+    let transport = chaos.transport(home.clone(), "home");
+    let receipt = || {
+        let hash = Value::String(format!("0x{}", DEPOSIT_TRANSACTIONS[0]));
+        transport
+            .execute("eth_getTransactionReceipt", vec![hash])
+            .wait()
+            .unwrap()
+    };
+
+    emit_at(&home, 10, deposit(DEPOSIT_TRANSACTIONS[0]));
+    assert_eq!(Value::Null, receipt());
+    home.mine_to(12);
+    assert_eq!(Value::Null, receipt());
+    home.mine(1);
+    assert_eq!(Value::String("0xa".into()), receipt()["blockNumber"]);
+    assert_eq!(2, chaos.counts().delayed_receipts);
+}
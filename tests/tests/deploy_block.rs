@@ -0,0 +1,126 @@
+extern crate bridge;
+extern crate ethereum_types;
+/// test lookups of the deployment blocks of the contracts via RPC
+extern crate futures;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate tests;
+
+use bridge::bridge::find_deploy_blocks;
+
+const CONTRACT: &str = "0x0000000000000000000000000000000000000000";
+
+// home contract deployed at block 5, foreign deployment block known.
+// binary search over the home blocks up to the head.
+test_app_stream! {
+    name => deploy_block_binary_search,
+    database => Database {
+        foreign_deploy: 20,
+        checked_deposit_relay: 2,
+        checked_withdraw_relay: 30,
+        checked_withdraw_confirm: 30,
+        ..Default::default()
+    },
+    home =>
+        account => "0000000000000000000000000000000000000001",
+        confirmations => 12;
+    foreign =>
+        account => "0000000000000000000000000000000000000001",
+        confirmations => 12;
+    authorities =>
+        accounts => [
+            "0000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000002",
+        ],
+        signatures => 1;
+    txs => Transactions::default(),
+    init => |app, db| find_deploy_blocks(app, db, None, None).into_stream(),
+    expected => vec![Database {
+        home_deploy: 5,
+        foreign_deploy: 20,
+        checked_deposit_relay: 5,
+        checked_withdraw_relay: 30,
+        checked_withdraw_confirm: 30,
+        ..Default::default()
+    }],
+    home_transport => [
+        "eth_blockNumber" =>
+            req => json!([]),
+            res => json!("0x10");
+        "eth_getCode" =>
+            req => json!([CONTRACT, "0x10"]),
+            res => json!("0x6060");
+        "eth_getCode" =>
+            req => json!([CONTRACT, "0x8"]),
+            res => json!("0x6060");
+        "eth_getCode" =>
+            req => json!([CONTRACT, "0x4"]),
+            res => json!("0x");
+        "eth_getCode" =>
+            req => json!([CONTRACT, "0x6"]),
+            res => json!("0x6060");
+        "eth_getCode" =>
+            req => json!([CONTRACT, "0x5"]),
+            res => json!("0x6060");
+    ],
+    foreign_transport => []
+}
+
+// the node doesn't have the state of old blocks.
+// the deployment block is taken from the receipt of the deployment transaction.
+test_app_stream! {
+    name => deploy_block_pruned_state_uses_receipt,
+    database => Database {
+        home_deploy: 10,
+        ..Default::default()
+    },
+    home =>
+        account => "0000000000000000000000000000000000000001",
+        confirmations => 12;
+    foreign =>
+        account => "0000000000000000000000000000000000000001",
+        confirmations => 12;
+    authorities =>
+        accounts => [
+            "0000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000002",
+        ],
+        signatures => 1;
+    txs => Transactions::default(),
+    init => |app, db| find_deploy_blocks(app, db, None, Some(0x11.into())).into_stream(),
+    expected => vec![Database {
+        home_deploy: 10,
+        foreign_deploy: 3,
+        checked_deposit_relay: 10,
+        checked_withdraw_relay: 3,
+        checked_withdraw_confirm: 3,
+        ..Default::default()
+    }],
+    home_transport => [],
+    foreign_transport => [
+        "eth_blockNumber" =>
+            req => json!([]),
+            res => json!("0x1000");
+        "eth_getCode" =>
+            req => json!([CONTRACT, "0x1000"]),
+            res => json!("0x6060");
+        "eth_getCode" =>
+            req => json!([CONTRACT, "0x800"]),
+            res => json!({"error": "missing trie node 1a2b (path )"});
+        "eth_getTransactionReceipt" =>
+            req => json!(["0x0000000000000000000000000000000000000000000000000000000000000011"]),
+            res => json!({
+                "transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000011",
+                "transactionIndex": "0x0",
+                "blockHash": "0x0000000000000000000000000000000000000000000000000000000000000022",
+                "blockNumber": "0x3",
+                "cumulativeGasUsed": "0x1",
+                "gasUsed": "0x1",
+                "contractAddress": CONTRACT,
+                "logs": [],
+                "logsBloom": format!("0x{}", "0".repeat(512)),
+                "status": "0x1"
+            });
+    ]
+}
@@ -95,6 +95,8 @@ test_app_stream! {
                 "topics": [DEPOSIT_TOPIC],
                 "data": "0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0",
                 "type": "",
+                "blockNumber": "0x6",
+                "logIndex": "0x0",
                 "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
             }]);
         "eth_blockNumber" =>
@@ -123,6 +125,77 @@ test_app_stream! {
     ]
 }
 
+test_app_stream! {
+    name => deposit_relay_drain_sends_nothing_after_the_batch_in_flight,
+    database => Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    },
+    home =>
+        account => "0000000000000000000000000000000000000001",
+        confirmations => 12;
+    foreign =>
+        account => "0000000000000000000000000000000000000001",
+        confirmations => 12;
+    authorities =>
+        accounts => [
+            "0000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000002",
+        ],
+        signatures => 1;
+    txs => Transactions::default(),
+    init => |app, db| {
+        let mut relay = create_deposit_relay(app, db);
+        let mut relayed = false;
+        // the drain is acknowledged once the first batch is relayed.
+        // the relay ends without polling for new logs
+        futures::stream::poll_fn(move || {
+            if relayed {
+                relay.drain();
+            }
+            let polled = relay.poll();
+            if let Ok(futures::Async::Ready(Some(_))) = polled {
+                relayed = true;
+            }
+            polled
+        })
+    },
+    expected => vec![0x1005],
+    home_transport => [
+        "eth_blockNumber" =>
+            req => json!([]),
+            res => json!("0x1011");
+        "eth_getLogs" =>
+            req => json!([{
+                "address": ["0x0000000000000000000000000000000000000000"],
+                "fromBlock": "0x6",
+                "limit": null,
+                "toBlock": "0x1005",
+                "topics": [[DEPOSIT_TOPIC], null, null, null]
+            }]),
+            res => json!([{
+                "address": "0x0000000000000000000000000000000000000000",
+                "topics": [DEPOSIT_TOPIC],
+                "data": "0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0",
+                "type": "",
+                "blockNumber": "0x6",
+                "logIndex": "0x0",
+                "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
+            }]);
+    ],
+    foreign_transport => [
+        "eth_sendTransaction" =>
+            req => json!([{
+                "data": "0x26b3293f000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364",
+                "from": "0x0000000000000000000000000000000000000001",
+                "gas": "0x0",
+                "gasPrice": "0x0",
+                "to": "0x0000000000000000000000000000000000000000"
+            }]),
+            res => json!("0x1db8f385535c0d178b8f40016048f3a3cffee8f94e68978ea4b277f57b638f0b");
+    ]
+}
+
 test_app_stream! {
     name => deposit_relay_check_gas,
     database => Database {
@@ -145,6 +218,7 @@ test_app_stream! {
         deposit_relay: TransactionConfig {
             gas: 0xfd,
             gas_price: 0xa0,
+            estimate_gas: false,
         },
         ..Default::default()
     },
@@ -165,7 +239,7 @@ test_app_stream! {
             res => json!([{
                 "address": "0x0000000000000000000000000000000000000000",
                 "topics": [DEPOSIT_TOPIC],
-                "data": "0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0","type":"","transactionHash":"0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
+                "data": "0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0","type":"","blockNumber":"0x6","logIndex":"0x0","transactionHash":"0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
             }]);
     ],
     foreign_transport => [
@@ -220,6 +294,8 @@ test_app_stream! {
                 "topics": ["0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c"],
                 "data": "0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0",
                 "type": "",
+                "blockNumber": "0x1",
+                "logIndex": "0x0",
                 "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
             }]);
     ],
@@ -275,6 +351,8 @@ test_app_stream! {
                 "topics": [DEPOSIT_TOPIC],
                 "data": "0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0",
                 "type": "",
+                "blockNumber": "0x1",
+                "logIndex": "0x0",
                 "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
             }]);
     ],
@@ -327,6 +405,8 @@ test_app_stream! {
                     "topics": ["0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c"],
                     "data": "0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0",
                     "type": "",
+                    "blockNumber": "0x1",
+                    "logIndex": "0x0",
                     "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
                 },
                 {
@@ -334,6 +414,8 @@ test_app_stream! {
                     "topics": ["0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c"],
                     "data": "0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0",
                     "type": "",
+                    "blockNumber": "0x1",
+                    "logIndex": "0x1",
                     "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a942436f"
                 }
             ]);
@@ -359,3 +441,62 @@ test_app_stream! {
             res => json!("0x1db8f385535c0d178b8f40016048f3a3cffee8f94e68978ea4b277f57b638f0b");
     ]
 }
+
+// a log with more data than `max_log_data` is dropped before it's decoded. nothing is relayed
+test_app_stream! {
+    name => deposit_relay_drops_oversized_logs,
+    database => Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    },
+    home =>
+        account => "0000000000000000000000000000000000000001",
+        confirmations => 12;
+    foreign =>
+        account => "0000000000000000000000000000000000000001",
+        confirmations => 12;
+    authorities =>
+        accounts => [
+            "0000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000002",
+        ],
+        signatures => 1;
+    txs => Transactions::default(),
+    init => |app, db| create_deposit_relay(app, db).take(2),
+    expected => vec![0x1005, 0x1006],
+    home_transport => [
+        "eth_blockNumber" =>
+            req => json!([]),
+            res => json!("0x1011");
+        "eth_getLogs" =>
+            req => json!([{
+                "address": ["0x0000000000000000000000000000000000000000"],
+                "fromBlock": "0x6",
+                "limit": null,
+                "toBlock": "0x1005",
+                "topics": [[DEPOSIT_TOPIC], null, null, null]
+            }]),
+            res => json!([{
+                "address": "0x0000000000000000000000000000000000000000",
+                "topics": [DEPOSIT_TOPIC],
+                "data": format!("0x{}", "00".repeat(4128)),
+                "type": "",
+                "blockNumber": "0x6",
+                "logIndex": "0x0",
+                "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
+            }]);
+        "eth_blockNumber" =>
+            req => json!([]),
+            res => json!("0x1012");
+        "eth_getLogs" =>
+            req => json!([{
+                "address": ["0x0000000000000000000000000000000000000000"],
+                "fromBlock": "0x1006",
+                "limit": null,
+                "toBlock": "0x1006",
+                "topics": [[DEPOSIT_TOPIC], null, null, null]
+            }]),
+            res => json!([]);
+    ],
+    foreign_transport => []
+}
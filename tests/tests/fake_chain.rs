@@ -0,0 +1,2299 @@
+/// multi step scenarios of the bridge components against an in memory chain
+extern crate bridge;
+extern crate ethabi;
+extern crate futures;
+extern crate rustc_hex;
+#[macro_use]
+extern crate serde_json;
+extern crate tempdir;
+extern crate tests;
+extern crate web3;
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use futures::{executor, Async, Future, Stream};
+use futures::executor::Notify;
+use rustc_hex::{FromHex, ToHex};
+use tempdir::TempDir;
+use ethabi::Token;
+use web3::types::{Address, Bytes, H256, TransactionRequest, U256};
+use bridge::api::{self, NodeFailure};
+use bridge::app::{App, Connections};
+use bridge::bridge::{command_channel, create_authority_monitor, create_bootstrap, create_bridge,
+                     create_deposit_finalize,
+                     create_deposit_relay, create_execution_watch, create_finality_monitor,
+                     create_pause_monitor,
+                     create_reorg_monitor, create_withdraw_confirm, create_withdraw_relay,
+                     failure_kind, send_ordered_foreign_transactions,
+                     write_bootstrap_files, check_safe_confirmations, deposit_hash_payload,
+                     get_validators_payload,
+                     signed_deposit_topic, verify_relay_events, BootstrapPlan,
+                     BootstrapProgress, BridgeCommand, CircuitState, CommandAuthorization,
+                     Component, EventOutcome,
+                     ExpectedEvent, PauseState, RecipientKind, RelayedLog, Role, FINALIZED_FUNCTION,
+                     FINALIZE_DEPOSIT_FUNCTION, NUM_DEPOSIT_SIGNATURES_FUNCTION};
+use bridge::config::{Authorities, CircuitBreakerConfig, Config, Confirmation, ConsensusEngine,
+                     ContractRecipientsConfig,
+                     CooperativeDepositsConfig, ExecutionWatchConfig, Finality,
+                     ForeignConsensusConfig,
+                     LeaderElectionConfig, MissingEventAction,
+                     Node, OrderedRelaysConfig, RecipientPoliciesConfig, RecipientPolicy,
+                     RecipientPolicyRule, RelayEventsConfig, RelayHistoryConfig,
+                     ReorgMonitorConfig};
+use bridge::contracts::{foreign, home};
+use bridge::database::{Database, DeepReorg};
+use bridge::error::{Error, ErrorKind};
+use bridge::finality::FinalizedBlock;
+use bridge::message_to_mainnet::MessageToMainnet;
+use bridge::timer::{ManualClock, Timer};
+use bridge::util::log_id;
+use bridge::tunables::SharedTunables;
+use tests::{FakeChain, FakeEvent, FakeFailure};
+
+const DEPOSIT_TOPIC: &str = "e1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c";
+const WITHDRAW_TOPIC: &str = "f279e6a1f5e320cca91135676d9cb6e44ca8a08c0b88342bcdb1144f6511b568";
+const COLLECTED_SIGNATURES_TOPIC: &str =
+    "eb043d149eedb81369bec43d4c3a3a53087debc88d2525f13bfaa3eecda28b5c";
+const DEPOSIT_CONFIRMATION_TOPIC: &str =
+    "82e9885b59946d922664d6e9c439efafebc983ce46aede53b1916dbb897c137a";
+const TRANSFER_TOPIC: &str = "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+const PAUSED_SELECTOR: &str = "5c975abb";
+const PAUSED_TOPIC: &str = "62e78cea01bee320cd4e420270b5ea74000d11b0c9f74754ebdbfc544b05a258";
+const DEPOSIT_TRANSACTION: &str =
+    "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364";
+
+fn app(home: &FakeChain, foreign: &FakeChain) -> Arc<App<FakeChain>> {
+    let node = || Node {
+        account: "0000000000000000000000000000000000000001".parse().unwrap(),
+        poll_interval: Duration::from_secs(0),
+        required_confirmations: 12,
+        ..Node::new(Default::default(), "".into())
+    };
+    let config = Config::builder()
+        .home(node())
+        .foreign(node())
+        .authorities(Authorities {
+            accounts: vec![
+                "0000000000000000000000000000000000000001".parse().unwrap(),
+                "0000000000000000000000000000000000000002".parse().unwrap(),
+            ],
+            required_signatures: 1,
+        })
+        .estimated_gas_cost_of_withdraw(U256::from_dec_str("100000").unwrap())
+        .max_total_home_contract_balance(U256::from_dec_str("10000000000000000000").unwrap())
+        .max_single_deposit_value(U256::from_dec_str("1000000000000000000").unwrap())
+        .build()
+        .unwrap();
+
+    app_with(home, foreign, config, Path::new(""))
+}
+
+fn app_with(
+    home: &FakeChain,
+    foreign: &FakeChain,
+    config: Config,
+    database_path: &Path,
+) -> Arc<App<FakeChain>> {
+    app_with_timer(home, foreign, config, database_path, Timer::default())
+}
+
+/// `app_with` sleeping on `timer`, e.g. of a `ManualClock` the test advances
+fn app_with_timer(
+    home: &FakeChain,
+    foreign: &FakeChain,
+    config: Config,
+    database_path: &Path,
+    timer: Timer,
+) -> Arc<App<FakeChain>> {
+    Arc::new(App {
+        tunables: SharedTunables::of(&config),
+        config,
+        database_path: database_path.into(),
+        connections: Connections {
+            home: home.clone(),
+            foreign: foreign.clone(),
+        },
+        home_bridge: home::HomeBridge::default(),
+        foreign_bridge: foreign::ForeignBridge::default(),
+        abi: None,
+        node_gas_price: Default::default(),
+        chain_clocks: Default::default(),
+        node_versions: Default::default(),
+        timer,
+    })
+}
+
+/// app of instance `instance_id` relaying deposits. instances share `database_path`
+/// and the leadership lease next to it.
+fn leader_app(
+    home: &FakeChain,
+    foreign: &FakeChain,
+    instance_id: &str,
+    database_path: &Path,
+) -> Arc<App<FakeChain>> {
+    let config = Config {
+        enable_withdrawals: false,
+        leader_election: Some(LeaderElectionConfig {
+            instance_id: instance_id.into(),
+            lease_file: None,
+            lease_duration: Duration::from_millis(500),
+            renew_interval: Duration::from_millis(100),
+        }),
+        ..app(home, foreign).config.clone()
+    };
+
+    app_with(home, foreign, config, database_path)
+}
+
+/// deposit of 0xf0 to 0xaff3454fce5edbc8cca8697c15331677e6ebcccc in `transaction`
+fn deposit(transaction: &str) -> FakeEvent {
+    FakeEvent {
+        address: 0.into(),
+        topics: vec![DEPOSIT_TOPIC.parse().unwrap()],
+        data: "000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc\
+               00000000000000000000000000000000000000000000000000000000000000f0"
+            .from_hex()
+            .unwrap(),
+        transaction_hash: transaction.parse().unwrap(),
+    }
+}
+
+/// emits `event` in a new block `number`
+fn emit_at(chain: &FakeChain, number: u64, event: FakeEvent) {
+    chain.mine_to(number - 1);
+    chain.emit(event);
+    chain.mine(1);
+}
+
+struct Ignore;
+
+impl Notify for Ignore {
+    fn notify(&self, _id: usize) {}
+}
+
+/// polls `stream` once without waiting for it
+fn poll_once<S: Stream>(stream: &mut S) -> Async<Option<S::Item>>
+where
+    S::Error: ::std::fmt::Debug,
+{
+    executor::spawn(stream)
+        .poll_stream_notify(&Arc::new(Ignore), 0)
+        .unwrap()
+}
+
+/// polls `future` once without waiting for it
+fn poll_future<F: Future>(future: &mut executor::Spawn<F>) -> Async<F::Item>
+where
+    F::Error: ::std::fmt::Debug,
+{
+    future.poll_future_notify(&Arc::new(Ignore), 0).unwrap()
+}
+
+/// the next `n` items of `stream`
+fn next<S: Stream>(stream: &mut S, n: u64) -> Vec<S::Item>
+where
+    S::Error: ::std::fmt::Debug,
+{
+    stream.by_ref().take(n).collect().wait().unwrap()
+}
+
+#[test]
+fn fake_chain_deposit_relay_basic() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let mut relay = create_deposit_relay(app(&home, &foreign), &Database::default());
+
+    home.mine_to(0x1011);
+    assert_eq!(vec![0x1005], next(&mut relay, 1));
+    home.mine(1);
+    assert_eq!(vec![0x1006], next(&mut relay, 1));
+    assert!(foreign.sent_transactions().is_empty());
+}
+
+#[test]
+fn fake_chain_deposit_relay_single_log() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    let mut relay = create_deposit_relay(app(&home, &foreign), &database);
+
+    emit_at(&home, 0x100, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(0x1011);
+    assert_eq!(vec![0x1005], next(&mut relay, 1));
+    home.mine(1);
+    assert_eq!(vec![0x1006], next(&mut relay, 1));
+
+    let sent = foreign.sent_transactions();
+    assert_eq!(1, sent.len());
+    assert_eq!(
+        "0x26b3293f000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364",
+        sent[0]["data"]
+    );
+    assert_eq!(1, foreign.pending());
+}
+
+#[test]
+fn fake_chain_deposit_relay_ignores_deposits_reorged_before_confirmation() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let mut relay = create_deposit_relay(app(&home, &foreign), &Database::default());
+
+    emit_at(&home, 21, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(25);
+    assert_eq!(vec![13], next(&mut relay, 1));
+
+    // the deposit is orphaned before it has enough confirmations
+    home.reorg(21);
+    home.mine_to(40);
+    assert_eq!(vec![28], next(&mut relay, 1));
+    assert!(foreign.sent_transactions().is_empty());
+
+    // and included again later
+    emit_at(&home, 41, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(53);
+    assert_eq!(vec![41], next(&mut relay, 1));
+    assert_eq!(1, foreign.sent_transactions().len());
+}
+
+#[test]
+fn fake_chain_deposit_relay_restart() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let app = app(&home, &foreign);
+
+    emit_at(&home, 10, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(30);
+    let mut relay = create_deposit_relay(app.clone(), &Database::default());
+    assert_eq!(vec![18], next(&mut relay, 1));
+    assert_eq!(1, foreign.sent_transactions().len());
+    drop(relay);
+
+    // a restart continues after the checked block
+    home.mine(1);
+    let database = Database {
+        checked_deposit_relay: 18,
+        ..Default::default()
+    };
+    let mut relay = create_deposit_relay(app, &database);
+    assert_eq!(vec![19], next(&mut relay, 1));
+    assert_eq!(1, foreign.sent_transactions().len());
+}
+
+/// app relaying deposits from finalized home blocks only
+fn finalized_app(home: &FakeChain, foreign: &FakeChain) -> Arc<App<FakeChain>> {
+    let config = app(home, foreign).config.clone();
+    let config = Config {
+        home: Node {
+            finality: Finality::Finalized,
+            ..config.home.clone()
+        },
+        ..config
+    };
+
+    app_with(home, foreign, config, Path::new(""))
+}
+
+#[test]
+fn fake_chain_deposit_relay_waits_for_finalized_block() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let app = finalized_app(&home, &foreign);
+    let interval = app.timer.interval(Duration::from_millis(1));
+    let mut monitor = create_finality_monitor(app.clone(), interval);
+    let mut relay = create_deposit_relay(app, &Database::default());
+    relay.watch_home_finality(monitor.shared());
+
+    emit_at(&home, 20, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(40);
+    // nothing is final before the finalized block is known
+    assert_eq!(Async::NotReady, poll_once(&mut relay));
+
+    // the deposit has enough confirmations, but isn't finalized
+    home.set_finalized(15);
+    assert_eq!(vec![FinalizedBlock::At(15)], next(&mut monitor, 1));
+    assert_eq!(vec![15], next(&mut relay, 1));
+    assert!(foreign.sent_transactions().is_empty());
+
+    home.set_finalized(25);
+    assert_eq!(vec![FinalizedBlock::At(25)], next(&mut monitor, 1));
+    assert_eq!(vec![25], next(&mut relay, 1));
+    assert_eq!(1, foreign.sent_transactions().len());
+}
+
+#[test]
+fn fake_chain_deposit_relay_without_finality_source_waits_for_confirmations() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let app = finalized_app(&home, &foreign);
+    let interval = app.timer.interval(Duration::from_millis(1));
+    let mut monitor = create_finality_monitor(app.clone(), interval);
+    let mut relay = create_deposit_relay(app, &Database::default());
+    relay.watch_home_finality(monitor.shared());
+
+    // the node answers the `finalized` tag with null
+    emit_at(&home, 20, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(40);
+    assert_eq!(vec![FinalizedBlock::Unavailable], next(&mut monitor, 1));
+    assert_eq!(vec![28], next(&mut relay, 1));
+    assert_eq!(1, foreign.sent_transactions().len());
+}
+
+#[test]
+fn fake_chain_reorg_monitor_finds_unbacked_deposits() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let app = app(&home, &foreign);
+    let orphaned: H256 = DEPOSIT_TRANSACTION.parse().unwrap();
+    let included: H256 = 0x22.into();
+
+    emit_at(&home, 10, deposit(DEPOSIT_TRANSACTION));
+    emit_at(&home, 12, FakeEvent {
+        transaction_hash: included,
+        ..deposit(DEPOSIT_TRANSACTION)
+    });
+    home.mine_to(20);
+    let orphaned_blocks = (home.block_hash(10).unwrap(), home.block_hash(12).unwrap());
+
+    let mut monitor = create_reorg_monitor(
+        app.clone(),
+        app.timer.interval(Duration::from_millis(10)),
+        100,
+    );
+    monitor.track(home.logs().iter().filter_map(RelayedLog::from_log));
+
+    // the first deposit is dropped, the second is included again in another block
+    home.reorg(10);
+    home.mine(2);
+    emit_at(&home, 13, FakeEvent {
+        transaction_hash: included,
+        ..deposit(DEPOSIT_TRANSACTION)
+    });
+    home.mine_to(22);
+
+    let incident = next(&mut monitor, 1).remove(0);
+    assert_eq!(10, incident.fork_block);
+    assert_eq!(13, incident.depth);
+    assert_eq!(
+        vec![
+            (orphaned_blocks.0, home.block_hash(10).unwrap()),
+            (orphaned_blocks.1, home.block_hash(12).unwrap()),
+        ],
+        incident.block_hashes
+    );
+    assert_eq!(vec![orphaned, included], incident.orphaned);
+    assert_eq!(vec![orphaned], incident.unbacked);
+}
+
+#[test]
+fn fake_chain_execution_watch_relays_deposits_reorged_out_of_foreign() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let config = Config {
+        execution_watch: Some(ExecutionWatchConfig {
+            depth: 3,
+            check_interval: Duration::from_millis(10),
+        }),
+        ..app(&home, &foreign).config.clone()
+    };
+    let (timer, clock) = Timer::manual();
+    let app = app_with_timer(&home, &foreign, config, Path::new(""), timer);
+    let selector = foreign::ForeignBridge::default()
+        .functions()
+        .is_deposit_executed()
+        .input(Address::zero(), U256::zero(), [0u8; 32])[..4]
+        .to_vec();
+    foreign.on_call(&selector, |_| vec![0u8; 32]);
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    let mut relay = create_deposit_relay(app.clone(), &database);
+
+    emit_at(&home, 0x100, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(0x1011);
+    assert_eq!(vec![0x1005], next(&mut relay, 1));
+    foreign.mine(1);
+    let executions = relay.take_executions();
+    assert_eq!(1, executions.len());
+    assert_eq!(
+        DEPOSIT_TRANSACTION.parse::<H256>().unwrap(),
+        executions[0].home_transaction
+    );
+
+    // the watch finds the execution in foreign block 1
+    let mut watch = create_execution_watch(
+        app.clone(),
+        app.timer.interval(Duration::from_millis(10)),
+        3,
+        Vec::new(),
+    );
+    watch.track(executions);
+    clock.advance(Duration::from_millis(50));
+    assert_eq!(Async::NotReady, poll_once(&mut watch));
+    assert_eq!(Some(1), watch.watched()[0].block_number);
+    assert_eq!(foreign.block_hash(1), watch.watched()[0].block_hash);
+
+    // and keeps watching it after a restart
+    let tempdir = TempDir::new("fake_chain_execution_watch").unwrap();
+    let database_path = tempdir.path().join("db.toml");
+    let saved = Database {
+        watched_executions: watch.watched().to_vec(),
+        ..database.clone()
+    };
+    saved.save(fs::File::create(&database_path).unwrap()).unwrap();
+    drop(watch);
+    let loaded = Database::load(&database_path).unwrap();
+    let mut watch = create_execution_watch(
+        app.clone(),
+        app.timer.interval(Duration::from_millis(10)),
+        3,
+        loaded.watched_executions,
+    );
+
+    // a foreign reorg drops the block of the execution before it is 3 blocks deep
+    foreign.reorg(1);
+    foreign.mine_to(4);
+    clock.advance(Duration::from_millis(10));
+    let invalidated = next(&mut watch, 1).remove(0);
+    assert_eq!(1, invalidated.len());
+    assert!(watch.watched().is_empty());
+
+    // the deposit is checked for execution and relayed again
+    let calls = foreign.requests("eth_call");
+    relay.requeue(invalidated);
+    assert_eq!(vec![0x1005], next(&mut relay, 1));
+    assert_eq!(calls + 1, foreign.requests("eth_call"));
+    let sent = foreign.sent_transactions();
+    assert_eq!(2, sent.len());
+    assert_eq!(sent[0]["data"], sent[1]["data"]);
+    assert_eq!(1, relay.take_executions().len());
+}
+
+/// `SignedDeposit` of `authority` for the deposit in home transaction `main_tx_hash`
+fn signed_deposit(authority: u64, main_tx_hash: H256, transaction: &str) -> FakeEvent {
+    FakeEvent {
+        address: 0.into(),
+        topics: vec![signed_deposit_topic()],
+        data: ethabi::encode(&[
+            ethabi::Token::Address(authority.into()),
+            ethabi::Token::FixedBytes(main_tx_hash.to_vec()),
+        ]),
+        transaction_hash: transaction.parse().unwrap(),
+    }
+}
+
+/// answers the two-phase `ForeignBridge` calls with `required` signatures,
+/// the count of `signatures` and never finalized deposits.
+fn serve_finalize(chain: &FakeChain, required: u64, signatures: Arc<AtomicUsize>) {
+    let uint = |value: u64| ethabi::encode(&[ethabi::Token::Uint(value.into())]);
+    let selector = |function: &str| deposit_hash_payload(function, H256::zero()).0[..4].to_vec();
+    let required_signatures = foreign::ForeignBridge::default()
+        .functions()
+        .required_signatures()
+        .input();
+    chain.on_call(&required_signatures[..4], move |_| uint(required));
+    chain.on_call(&selector(NUM_DEPOSIT_SIGNATURES_FUNCTION), move |_| {
+        uint(signatures.load(Ordering::SeqCst) as u64)
+    });
+    chain.on_call(&selector(FINALIZED_FUNCTION), |_| {
+        ethabi::encode(&[ethabi::Token::Bool(false)])
+    });
+}
+
+#[test]
+fn fake_chain_deposit_finalize_after_own_signature() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let app = app(&home, &foreign);
+    serve_finalize(&foreign, 1, Arc::new(AtomicUsize::new(1)));
+    // authority 1 has the first slot of even hashes
+    let main_tx_hash = H256::from(0x10);
+    let mut finalize = create_deposit_finalize(app, &Database::default(), 20);
+
+    emit_at(&foreign, 0x100, signed_deposit(1, main_tx_hash, DEPOSIT_TRANSACTION));
+    foreign.mine_to(0x200);
+    let finalizations = next(&mut finalize, 1).remove(0);
+    assert_eq!(1, finalizations.len());
+    assert_eq!(main_tx_hash, finalizations[0].main_tx_hash);
+    let sent = foreign.sent_transactions();
+    assert_eq!(1, sent.len());
+    let payload = deposit_hash_payload(FINALIZE_DEPOSIT_FUNCTION, main_tx_hash);
+    assert_eq!(
+        Some(format!("0x{}", payload.0.to_hex()).as_str()),
+        sent[0]["data"].as_str()
+    );
+    // watched until it's reported finalized
+    assert_eq!(vec![main_tx_hash], finalize.pending());
+}
+
+#[test]
+fn fake_chain_deposit_finalize_waits_for_threshold_and_turn() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let app = app(&home, &foreign);
+    let signatures = Arc::new(AtomicUsize::new(1));
+    serve_finalize(&foreign, 2, signatures.clone());
+    // authority 1 has the second slot of odd hashes
+    let main_tx_hash = H256::from(0x11);
+    let mut finalize = create_deposit_finalize(app, &Database::default(), 20);
+
+    // below the threshold after the own signature
+    emit_at(&foreign, 0x100, signed_deposit(1, main_tx_hash, DEPOSIT_TRANSACTION));
+    foreign.mine_to(0x200);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(Async::NotReady, poll_once(&mut finalize));
+    assert_eq!(vec![main_tx_hash], finalize.pending());
+    assert!(foreign.sent_transactions().is_empty());
+
+    // the signature of the other authority meets the threshold. the first turn is its own
+    signatures.store(2, Ordering::SeqCst);
+    emit_at(
+        &foreign,
+        0x201,
+        signed_deposit(2, main_tx_hash, &format!("{:064x}", 2)),
+    );
+    foreign.mine_to(0x300);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(Async::NotReady, poll_once(&mut finalize));
+    assert!(foreign.sent_transactions().is_empty());
+
+    // and it's the turn of authority 1 if it didn't
+    foreign.mine(20);
+    let finalizations = next(&mut finalize, 1).remove(0);
+    assert_eq!(main_tx_hash, finalizations[0].main_tx_hash);
+    assert_eq!(1, foreign.sent_transactions().len());
+}
+
+/// app relaying deposits with `cooperative_deposits` on a manual clock, polling home every
+/// second. authority 1 of the `authorities` authorities 1, 2, .. relays the deposits,
+/// 1 signature is required. it waits 60 seconds, then 30 seconds more for each slot after
+/// the first fallback. `ForeignBridge.isDepositExecuted` returns `executed`.
+fn cooperative_app(
+    home: &FakeChain,
+    foreign: &FakeChain,
+    executed: bool,
+    authorities: u64,
+) -> (Arc<App<FakeChain>>, ManualClock) {
+    let mut config = Config {
+        authorities: Authorities {
+            accounts: (1..authorities + 1).map(Address::from).collect(),
+            required_signatures: 1,
+        },
+        cooperative_deposits: Some(CooperativeDepositsConfig {
+            grace_period: Duration::from_secs(60),
+            fallback_stagger: Duration::from_secs(30),
+        }),
+        ..app(home, foreign).config.clone()
+    };
+    config.home.poll_interval = Duration::from_secs(1);
+    let selector = foreign::ForeignBridge::default()
+        .functions()
+        .is_deposit_executed()
+        .input(Address::zero(), U256::zero(), [0u8; 32])[..4]
+        .to_vec();
+    foreign.on_call(&selector, move |_| {
+        let mut output = vec![0u8; 32];
+        output[31] = executed as u8;
+        output
+    });
+    let (timer, clock) = Timer::manual();
+    (app_with_timer(home, foreign, config, Path::new(""), timer), clock)
+}
+
+/// with 2 authorities this authority is responsible for the deposits of even home
+/// transactions, the other authority for those of odd ones
+#[test]
+fn fake_chain_cooperative_deposit_relay_leaves_deposits_to_the_responsible_authority() {
+    const OTHER_TRANSACTION: &str =
+        "1111111111111111111111111111111111111111111111111111111111111111";
+    const LATER_TRANSACTION: &str =
+        "2222222222222222222222222222222222222222222222222222222222222222";
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    let (app, clock) = cooperative_app(&home, &foreign, true, 2);
+    let mut relay = create_deposit_relay(app, &database);
+
+    emit_at(&home, 0x100, deposit(DEPOSIT_TRANSACTION));
+    emit_at(&home, 0x101, deposit(OTHER_TRANSACTION));
+    home.mine_to(0x1011);
+    // the deposit this authority is responsible for is relayed right away. the relay
+    // checks the blocks up to the one the other authority is responsible for
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(vec![0x100], next(&mut relay, 1));
+    let sent = foreign.sent_transactions();
+    assert_eq!(1, sent.len());
+    assert!(sent[0]["data"].as_str().unwrap().ends_with(DEPOSIT_TRANSACTION));
+    assert_eq!(0, foreign.requests("eth_call"));
+
+    // later deposits are relayed while it waits for the other authority
+    emit_at(&home, 0x1012, deposit(LATER_TRANSACTION));
+    home.mine_to(0x1020);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(vec![0x100], next(&mut relay, 1));
+    let sent = foreign.sent_transactions();
+    assert_eq!(2, sent.len());
+    assert!(sent[1]["data"].as_str().unwrap().ends_with(LATER_TRANSACTION));
+    assert_eq!(Async::NotReady, poll_once(&mut relay));
+
+    // the other deposit was executed by the other authority by its deadline, 60 seconds
+    // after the relay saw it
+    clock.advance(Duration::from_secs(58));
+    assert_eq!(Async::NotReady, poll_once(&mut relay));
+    assert_eq!(0, foreign.requests("eth_call"));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(vec![0x1014], next(&mut relay, 1));
+    assert_eq!(1, foreign.requests("eth_call"));
+    assert_eq!(2, foreign.sent_transactions().len());
+    let counts = relay.take_counts();
+    assert_eq!(1, counts.cooperative_waits);
+    assert_eq!(1, counts.skipped_executed);
+    assert_eq!(0, counts.cooperative_fallbacks);
+}
+
+#[test]
+fn fake_chain_cooperative_deposit_relay_falls_back_after_the_deadline() {
+    const OTHER_TRANSACTION: &str =
+        "1111111111111111111111111111111111111111111111111111111111111111";
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let (app, clock) = cooperative_app(&home, &foreign, false, 2);
+    let mut relay = create_deposit_relay(
+        app.clone(),
+        &Database {
+            checked_deposit_relay: 5,
+            ..Default::default()
+        },
+    );
+
+    emit_at(&home, 0x100, deposit(OTHER_TRANSACTION));
+    home.mine_to(0x1011);
+    // the other authority is responsible for the deposit. its block isn't checked meanwhile
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(vec![0xff], next(&mut relay, 1));
+    assert_eq!(Async::NotReady, poll_once(&mut relay));
+    assert!(foreign.sent_transactions().is_empty());
+    drop(relay);
+
+    // a restart reads the deposit again and waits for it 60 seconds from then on
+    clock.advance(Duration::from_secs(30));
+    let mut relay = create_deposit_relay(
+        app,
+        &Database {
+            checked_deposit_relay: 0xff,
+            ..Default::default()
+        },
+    );
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(vec![0xff], next(&mut relay, 1));
+    clock.advance(Duration::from_secs(59));
+    assert_eq!(Async::NotReady, poll_once(&mut relay));
+    assert!(foreign.sent_transactions().is_empty());
+
+    // the deposit is still unexecuted by its deadline and relayed by this authority
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(vec![0x1005], next(&mut relay, 1));
+    let sent = foreign.sent_transactions();
+    assert_eq!(1, sent.len());
+    assert!(sent[0]["data"].as_str().unwrap().ends_with(OTHER_TRANSACTION));
+    let counts = relay.take_counts();
+    assert_eq!(1, counts.cooperative_waits);
+    assert_eq!(1, counts.cooperative_fallbacks);
+}
+
+/// with 3 authorities this authority is in slot 1 for the deposit of transaction 1 and in
+/// slot 2 for that of transaction 2
+#[test]
+fn fake_chain_cooperative_deposit_relay_staggers_fallbacks_by_slot() {
+    let recipient = "aff3454fce5edbc8cca8697c15331677e6ebcccc".parse().unwrap();
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    let (app, clock) = cooperative_app(&home, &foreign, false, 3);
+    let mut relay = create_deposit_relay(app, &database);
+
+    emit_at(&home, 0x100, deposit_to(recipient, 1));
+    emit_at(&home, 0x101, deposit_to(recipient, 2));
+    home.mine_to(0x1011);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(vec![0xff], next(&mut relay, 1));
+
+    // the first fallback relays the deposit after the grace period
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(vec![0x100], next(&mut relay, 1));
+    let sent = foreign.sent_transactions();
+    assert_eq!(1, sent.len());
+    assert!(sent[0]["data"].as_str().unwrap().ends_with(&format!("{:064x}", 1)));
+    assert_eq!(Async::NotReady, poll_once(&mut relay));
+
+    // the second one the fallback stagger after it
+    clock.advance(Duration::from_secs(29));
+    assert_eq!(Async::NotReady, poll_once(&mut relay));
+    assert_eq!(1, foreign.sent_transactions().len());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(vec![0x1005], next(&mut relay, 1));
+    let sent = foreign.sent_transactions();
+    assert_eq!(2, sent.len());
+    assert!(sent[1]["data"].as_str().unwrap().ends_with(&format!("{:064x}", 2)));
+    let counts = relay.take_counts();
+    assert_eq!(2, counts.cooperative_waits);
+    assert_eq!(2, counts.cooperative_fallbacks);
+}
+
+#[test]
+fn fake_chain_leader_election_failover() {
+    const OTHER_TRANSACTION: &str =
+        "1111111111111111111111111111111111111111111111111111111111111111";
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("fake_chain_leader_election_failover").unwrap();
+    let database_path = tempdir.path().join("db.toml");
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    database
+        .save(fs::File::create(&database_path).unwrap())
+        .unwrap();
+
+    emit_at(&home, 0x100, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(0x1011);
+
+    // a acquires the lease first and relays the deposit
+    let mut a = create_bridge(leader_app(&home, &foreign, "a", &database_path), &database);
+    let mut b = create_bridge(leader_app(&home, &foreign, "b", &database_path), &database);
+    assert_eq!(vec![()], next(&mut a, 1));
+    assert_eq!(Role::Leader, a.role());
+    assert_eq!(1, foreign.sent_transactions().len());
+
+    // b only observes while a leads
+    assert_eq!(Async::NotReady, poll_once(&mut b));
+    assert_eq!(Role::Standby, b.role());
+
+    // a crashes without releasing the lease. once it expired b acquires it,
+    // but isn't promoted while the relay of a is still pending
+    drop(a);
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(Async::NotReady, poll_once(&mut b));
+    assert_eq!(Role::Promoting, b.role());
+    assert_eq!(1, foreign.sent_transactions().len());
+
+    // b continues after the block checked by a and relays the new deposit only
+    foreign.mine(1);
+    emit_at(&home, 0x1020, deposit(OTHER_TRANSACTION));
+    home.mine_to(0x1030);
+    assert_eq!(vec![()], next(&mut b, 1));
+    assert_eq!(Role::Leader, b.role());
+    let sent = foreign.sent_transactions();
+    assert_eq!(2, sent.len());
+    assert!(
+        sent[1]["data"]
+            .as_str()
+            .unwrap()
+            .ends_with(OTHER_TRANSACTION)
+    );
+    let database = Database::load(&database_path).unwrap();
+    assert_eq!(0x1024, database.checked_deposit_relay);
+
+    // a restarted a stays standby while b holds the lease
+    let mut a = create_bridge(leader_app(&home, &foreign, "a", &database_path), &database);
+    assert_eq!(Async::NotReady, poll_once(&mut a));
+    assert_eq!(Role::Standby, a.role());
+    assert_eq!(2, foreign.sent_transactions().len());
+
+    // and takes over without waiting for the lease to expire once b releases it
+    b.release_leadership().unwrap();
+    assert_eq!(Role::Standby, b.role());
+    foreign.mine(1);
+    home.mine(1);
+    assert_eq!(vec![()], next(&mut a, 1));
+    assert_eq!(Role::Leader, a.role());
+    assert_eq!(2, foreign.sent_transactions().len());
+}
+
+/// answers `authorities(i)` with `authorities[i]` and nothing past the end,
+/// and `requiredSignatures()` with `required_signatures`
+fn serve_authorities(chain: &FakeChain, authorities: Vec<Address>, required_signatures: u64) {
+    let functions = home::HomeBridge::default();
+    let getter = functions.functions().authorities().input(U256::zero());
+    chain.on_call(&getter[..4], move |data| {
+        let index = U256::from(&data[4..36]).low_u64() as usize;
+        match authorities.get(index) {
+            Some(authority) => H256::from(*authority).to_vec(),
+            None => Vec::new(),
+        }
+    });
+    let required = functions.functions().required_signatures().input();
+    chain.on_call(&required[..4], move |_| {
+        H256::from(U256::from(required_signatures)).to_vec()
+    });
+}
+
+#[test]
+fn fake_chain_authority_monitor_reports_differences() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let app = app(&home, &foreign);
+    // more authorities than fit into a page of getter calls
+    let authorities: Vec<Address> = (1..21u64).map(Address::from).collect();
+    serve_authorities(&home, authorities.clone(), 2);
+    let mut foreign_authorities = authorities[1..].to_vec();
+    foreign_authorities.push(Address::from(30));
+    serve_authorities(&foreign, foreign_authorities, 3);
+
+    let mut monitor = create_authority_monitor(
+        app.clone(),
+        &Database::default(),
+        app.timer.interval(Duration::from_millis(10)),
+    );
+    let diff = next(&mut monitor, 1).remove(0);
+    assert!(!diff.is_consistent());
+    assert_eq!(vec![Address::from(1)], diff.home_only);
+    assert_eq!(vec![Address::from(30)], diff.foreign_only);
+    assert_eq!(Some((2.into(), 3.into())), diff.required_signatures);
+}
+
+/// withdraw of `value` to 0xaff3454fce5edbc8cca8697c15331677e6ebcccc at a home gas price of 0x10
+fn withdraw(transaction: &str, value: u8) -> FakeEvent {
+    let mut data = "000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc\
+                    0000000000000000000000000000000000000000000000000000000000000000\
+                    0000000000000000000000000000000000000000000000000000000000000010"
+        .from_hex()
+        .unwrap();
+    data[63] = value;
+    FakeEvent {
+        address: 0.into(),
+        topics: vec![WITHDRAW_TOPIC.parse().unwrap()],
+        data,
+        transaction_hash: transaction.parse().unwrap(),
+    }
+}
+
+#[test]
+fn fake_chain_withdraw_confirm_signs_every_withdraw_of_a_transaction() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let config = Config {
+        unique_log_ids: true,
+        ..app(&home, &foreign).config.clone()
+    };
+    let app = app_with(&home, &foreign, config, Path::new(""));
+    let mut confirm = create_withdraw_confirm(app, &Database::default());
+
+    // a contract withdrew three times in one foreign transaction
+    foreign.mine_to(0x9f);
+    for value in 1..4 {
+        foreign.emit(withdraw(DEPOSIT_TRANSACTION, value));
+    }
+    foreign.mine(1);
+    foreign.mine_to(0x1011);
+    assert_eq!(vec![0x1005], next(&mut confirm, 1));
+
+    let logs = foreign.logs();
+    assert_eq!(3, logs.len());
+    let sent = foreign.sent_transactions();
+    assert_eq!(3, sent.len());
+    let mut ids = Vec::new();
+    for (log, sent) in logs.into_iter().zip(sent) {
+        let id = log_id(&log, true).unwrap();
+        ids.push(id);
+        let message = MessageToMainnet::from_log(log, true).unwrap();
+        assert_eq!(id, message.sidenet_transaction_hash);
+        // `submitSignature` carries the message with the id of its log
+        assert!(
+            sent["data"]
+                .as_str()
+                .unwrap()
+                .contains(&message.to_bytes().to_hex())
+        );
+    }
+    ids.sort();
+    ids.dedup();
+    assert_eq!(3, ids.len());
+}
+
+/// foreign with a withdraw in each of `count` transactions, all in blocks before 0x200
+/// and mined deep enough to be confirmed. home reports all but `unexecuted` as executed.
+fn catch_up_chains(count: u64, unexecuted: &'static [u64]) -> (FakeChain, FakeChain) {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    for n in 1..count + 1 {
+        emit_at(&foreign, 0x100 + n, withdraw(&format!("{:064x}", n), 1));
+    }
+    foreign.mine_to(0x1011);
+    let selector = home::HomeBridge::default()
+        .functions()
+        .is_withdraw_executed()
+        .input([0u8; 32])[..4]
+        .to_vec();
+    home.on_call(&selector, move |data| {
+        let id = H256::from(&data[4..36]);
+        let executed = !unexecuted.iter().any(|n| H256::from(*n) == id);
+        let mut output = vec![0u8; 32];
+        output[31] = executed as u8;
+        output
+    });
+    (home, foreign)
+}
+
+#[test]
+fn fake_chain_withdraw_confirm_catch_up_skips_executed_withdraws() {
+    let (home, foreign) = catch_up_chains(40, &[7]);
+    let config = Config {
+        authority_since_block: Some(0x200),
+        ..app(&home, &foreign).config.clone()
+    };
+    let app = app_with(&home, &foreign, config, Path::new(""));
+    let mut confirm = create_withdraw_confirm(app, &Database::default());
+    assert_eq!(vec![0x1005], next(&mut confirm, 1));
+
+    // one execution check per withdraw. only the unexecuted one is signed and submitted
+    assert_eq!(40, home.requests("eth_call"));
+    assert_eq!(1, foreign.requests("eth_sign"));
+    let sent = foreign.sent_transactions();
+    assert_eq!(1, sent.len());
+    assert!(sent[0]["data"].as_str().unwrap().contains(&format!("{:064x}", 7)));
+    assert_eq!(39, confirm.take_counts().skipped_executed);
+}
+
+#[test]
+fn fake_chain_withdraw_confirm_without_catch_up_signs_every_withdraw() {
+    let (home, foreign) = catch_up_chains(40, &[7]);
+    let mut confirm = create_withdraw_confirm(app(&home, &foreign), &Database::default());
+    assert_eq!(vec![0x1005], next(&mut confirm, 1));
+
+    assert_eq!(0, home.requests("eth_call"));
+    assert_eq!(40, foreign.requests("eth_sign"));
+    assert_eq!(40, foreign.sent_transactions().len());
+}
+
+#[test]
+fn fake_chain_withdraw_confirm_signs_withdraws_after_authority_since_block() {
+    let (home, foreign) = catch_up_chains(3, &[]);
+    let config = Config {
+        authority_since_block: Some(0x102),
+        ..app(&home, &foreign).config.clone()
+    };
+    let app = app_with(&home, &foreign, config, Path::new(""));
+    let mut confirm = create_withdraw_confirm(app, &Database {
+        checked_withdraw_confirm: 0x102,
+        ..Database::default()
+    });
+    // caught up: the withdraw of block 0x103 is signed without checking its execution
+    assert_eq!(vec![0x1005], next(&mut confirm, 1));
+    assert_eq!(0, home.requests("eth_call"));
+    assert_eq!(1, foreign.sent_transactions().len());
+}
+
+/// message of the withdraw of `n` in foreign transaction `n`
+fn withdraw_message(n: u64) -> MessageToMainnet {
+    MessageToMainnet {
+        recipient: "aff3454fce5edbc8cca8697c15331677e6ebcccc".parse().unwrap(),
+        value: n.into(),
+        sidenet_transaction_hash: n.into(),
+        mainnet_gas_price: 0x10.into(),
+    }
+}
+
+/// `CollectedSignatures` of `message` in foreign transaction `transaction`
+/// assigning its relay to `authority`
+fn collected_signatures(authority: u64, message: &MessageToMainnet, transaction: u64) -> FakeEvent {
+    FakeEvent {
+        address: 0.into(),
+        topics: vec![COLLECTED_SIGNATURES_TOPIC.parse().unwrap()],
+        data: ethabi::encode(&[
+            ethabi::Token::Address(authority.into()),
+            ethabi::Token::FixedBytes(message.hash().to_vec()),
+        ]),
+        transaction_hash: transaction.into(),
+    }
+}
+
+/// answers `ForeignBridge.message` and `ForeignBridge.signature` for `messages`
+/// and `HomeBridge.isWithdrawExecuted` with `executed` for every withdraw
+fn serve_withdraws(
+    home: &FakeChain,
+    foreign: &FakeChain,
+    messages: Vec<MessageToMainnet>,
+    executed: bool,
+) {
+    let functions = foreign::ForeignBridge::default();
+    let message = functions.functions().message().input([0u8; 32]);
+    foreign.on_call(&message[..4], move |data| {
+        let hash = H256::from(&data[4..36]);
+        messages
+            .iter()
+            .find(|message| message.hash() == hash)
+            .map(MessageToMainnet::to_payload)
+            .unwrap_or_default()
+    });
+    let signature = functions.functions().signature().input([0u8; 32], 0u32);
+    foreign.on_call(&signature[..4], |_| {
+        let mut signature = vec![0u8; 65];
+        signature[64] = 27;
+        ethabi::encode(&[ethabi::Token::Bytes(signature)])
+    });
+    let is_executed = home::HomeBridge::default()
+        .functions()
+        .is_withdraw_executed()
+        .input([0u8; 32]);
+    home.on_call(&is_executed[..4], move |_| {
+        ethabi::encode(&[ethabi::Token::Bool(executed)])
+    });
+}
+
+#[test]
+fn fake_chain_withdraw_relay_drops_repeated_collected_signatures() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let (first, second) = (withdraw_message(1), withdraw_message(2));
+    serve_withdraws(&home, &foreign, vec![first.clone(), second.clone()], false);
+    let mut relay = create_withdraw_relay(app(&home, &foreign), &Database::default());
+
+    // the log of the first withdraw is repeated within the batch
+    emit_at(&foreign, 0x100, collected_signatures(1, &first, 0x100));
+    emit_at(&foreign, 0x101, collected_signatures(1, &first, 0x101));
+    foreign.mine_to(0x1011);
+    assert_eq!(vec![0x1005], next(&mut relay, 1));
+    assert_eq!(1, home.sent_transactions().len());
+
+    // and after the withdraw was relayed
+    emit_at(&foreign, 0x1012, collected_signatures(1, &first, 0x102));
+    emit_at(&foreign, 0x1013, collected_signatures(1, &second, 0x103));
+    foreign.mine_to(0x101f);
+    assert_eq!(vec![0x1013], next(&mut relay, 1));
+    let sent = home.sent_transactions();
+    assert_eq!(2, sent.len());
+    assert!(
+        sent[1]["data"]
+            .as_str()
+            .unwrap()
+            .contains(&second.to_bytes().to_hex())
+    );
+    let counts = relay.take_counts();
+    assert_eq!(2, counts.relayed);
+    assert_eq!(2, counts.duplicates);
+    // relayed and in flight withdraws are known locally, without asking home
+    assert_eq!(0, home.requests("eth_call"));
+
+    // a restarted relay knows the relayed withdraws from the database
+    let database = Database {
+        checked_withdraw_relay: 0x1013,
+        relayed_withdraws: relay.relayed_messages().to_vec(),
+        ..Database::default()
+    };
+    let mut restarted = create_withdraw_relay(app(&home, &foreign), &database);
+    emit_at(&foreign, 0x1020, collected_signatures(1, &second, 0x104));
+    foreign.mine_to(0x102c);
+    assert_eq!(vec![0x1020], next(&mut restarted, 1));
+    assert_eq!(2, home.sent_transactions().len());
+    assert_eq!(1, restarted.take_counts().duplicates);
+}
+
+#[test]
+fn fake_chain_withdraw_relay_checks_repeated_withdraws_of_other_authorities() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let (first, second) = (withdraw_message(1), withdraw_message(2));
+    serve_withdraws(&home, &foreign, vec![first.clone(), second.clone()], true);
+    let mut relay = create_withdraw_relay(app(&home, &foreign), &Database::default());
+
+    // another authority relayed the first withdraw. its repeated log assigns it to this one
+    emit_at(&foreign, 0x100, collected_signatures(2, &first, 0x100));
+    emit_at(&foreign, 0x101, collected_signatures(1, &first, 0x101));
+    emit_at(&foreign, 0x102, collected_signatures(1, &second, 0x102));
+    foreign.mine_to(0x1011);
+    assert_eq!(vec![0x1005], next(&mut relay, 1));
+
+    // only the repeated withdraw is checked on home. the new one is relayed as usual
+    assert_eq!(1, home.requests("eth_call"));
+    let sent = home.sent_transactions();
+    assert_eq!(1, sent.len());
+    assert!(
+        sent[0]["data"]
+            .as_str()
+            .unwrap()
+            .contains(&second.to_bytes().to_hex())
+    );
+    assert_eq!(1, relay.take_counts().duplicates);
+}
+
+#[test]
+fn fake_chain_withdraw_relay_fetches_withdraws_it_did_not_observe() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let (first, second) = (withdraw_message(1), withdraw_message(2));
+    serve_withdraws(&home, &foreign, vec![first.clone(), second.clone()], false);
+    let config = Config {
+        check_withdraw_origins: true,
+        ..app(&home, &foreign).config.clone()
+    };
+    let app = app_with(&home, &foreign, config, Path::new(""));
+    let mut relay = create_withdraw_relay(app, &Database::default());
+
+    // signatures were collected before withdraw confirm observed the withdraws.
+    // the transaction of the second emitted no `Withdraw`
+    emit_at(&foreign, 0x100, withdraw(&format!("{:064x}", 1), 1));
+    emit_at(&foreign, 0x101, collected_signatures(1, &first, 0x101));
+    emit_at(&foreign, 0x102, collected_signatures(1, &second, 0x102));
+    foreign.mine_to(0x1011);
+    assert_eq!(vec![0x1005], next(&mut relay, 1));
+
+    assert_eq!(2, foreign.requests("eth_getTransactionReceipt"));
+    let sent = home.sent_transactions();
+    assert_eq!(1, sent.len());
+    assert!(
+        sent[0]["data"]
+            .as_str()
+            .unwrap()
+            .contains(&first.to_bytes().to_hex())
+    );
+    let origins = relay.withdraw_origins().unwrap().origins().to_vec();
+    assert_eq!(1, origins.len());
+    assert_eq!(H256::from(1), origins[0].transaction);
+    assert_eq!(0x100, origins[0].block);
+    assert_eq!(Some(first.hash()), origins[0].message_hash);
+    assert!(origins[0].fetched);
+    let counts = relay.take_counts();
+    assert_eq!(1, counts.relayed);
+    assert_eq!(1, counts.orphaned_withdraws);
+}
+
+/// config of `app` with aura on foreign and its validator set at 0x05
+fn aura_config(home: &FakeChain, foreign: &FakeChain, enforce: bool) -> Config {
+    Config {
+        foreign_consensus: Some(ForeignConsensusConfig {
+            engine: Some(ConsensusEngine::Aura),
+            validator_set: Some(5.into()),
+            enforce_safe_confirmations: enforce,
+        }),
+        ..app(home, foreign).config.clone()
+    }
+}
+
+#[test]
+fn fake_chain_safe_confirmations_of_aura() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    // 31 validators: blocks are safe once 16 validators built on them
+    foreign.on_call(&get_validators_payload().0, |_| {
+        let validators = (1..32)
+            .map(|validator| Token::Address(Address::from(validator)))
+            .collect();
+        ethabi::encode(&[Token::Array(validators)])
+    });
+
+    let app = app_with(&home, &foreign, aura_config(&home, &foreign, false), Path::new(""));
+    let confirmations = check_safe_confirmations(&app).wait().unwrap().unwrap();
+    assert_eq!(31, confirmations.validators);
+    assert_eq!(16, confirmations.safe);
+    assert_eq!(12, confirmations.configured);
+    // only warned about
+    assert_eq!(12, confirmations.effective);
+
+    let app = app_with(&home, &foreign, aura_config(&home, &foreign, true), Path::new(""));
+    let confirmations = check_safe_confirmations(&app).wait().unwrap().unwrap();
+    assert_eq!(16, confirmations.effective);
+    assert_eq!(2, foreign.requests("eth_call"));
+
+    // without the hint the engine is detected. the blocks of the fake chain have no aura seal
+    let config = Config {
+        foreign_consensus: Some(ForeignConsensusConfig {
+            engine: None,
+            ..aura_config(&home, &foreign, true).foreign_consensus.unwrap()
+        }),
+        ..app.config.clone()
+    };
+    let app = app_with(&home, &foreign, config, Path::new(""));
+    assert_eq!(None, check_safe_confirmations(&app).wait().unwrap());
+    assert_eq!(1, foreign.requests("web3_clientVersion"));
+    assert_eq!(2, foreign.requests("eth_call"));
+}
+
+/// deposit of 0xf0 to `recipient` in home transaction `transaction`
+fn deposit_to(recipient: Address, transaction: u64) -> FakeEvent {
+    FakeEvent {
+        data: ethabi::encode(&[Token::Address(recipient), Token::Uint(0xf0.into())]),
+        transaction_hash: transaction.into(),
+        ..deposit(DEPOSIT_TRANSACTION)
+    }
+}
+
+/// config relaying deposits to contracts with 500000 gas and keeping their history.
+/// simulated deposits to the `rejecting` recipients revert on `foreign`.
+fn contract_recipients_config(
+    home: &FakeChain,
+    foreign: &FakeChain,
+    rejecting: Vec<Address>,
+) -> Config {
+    let selector = foreign::ForeignBridge::default()
+        .functions()
+        .deposit()
+        .input(Address::zero(), U256::zero(), [0u8; 32])[..4]
+        .to_vec();
+    foreign.on_call(&selector, move |data| {
+        // the recipient is the first argument
+        if rejecting.iter().any(|recipient| data[16..36] == recipient.0[..]) {
+            let mut revert = "08c379a0".from_hex().unwrap();
+            revert.extend(ethabi::encode(&[Token::String("fallback out of gas".into())]));
+            revert
+        } else {
+            Vec::new()
+        }
+    });
+    Config {
+        contract_recipients: Some(ContractRecipientsConfig { gas: Some(500_000) }),
+        relay_history: Some(RelayHistoryConfig {
+            capacity: 10,
+            chain: None,
+        }),
+        ..app(home, foreign).config.clone()
+    }
+}
+
+#[test]
+fn fake_chain_deposit_relay_raises_gas_of_contract_recipients() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let account: Address = "aff3454fce5edbc8cca8697c15331677e6ebcccc".parse().unwrap();
+    let contract: Address = 0xc0.into();
+    foreign.set_code(contract, vec![0x60, 0x80]);
+    let config = contract_recipients_config(&home, &foreign, Vec::new());
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    let mut relay = create_deposit_relay(app_with(&home, &foreign, config, Path::new("")), &database);
+
+    emit_at(&home, 0x100, deposit_to(account, 1));
+    emit_at(&home, 0x101, deposit_to(contract, 2));
+    home.mine_to(0x1011);
+    assert_eq!(vec![0x1005], next(&mut relay, 1));
+
+    // the account gets the gas of `transactions.deposit_relay`, the contract the raised gas
+    // once its simulation succeeded
+    let sent = foreign.sent_transactions();
+    assert_eq!(2, sent.len());
+    assert_eq!("0x0", sent[0]["gas"]);
+    assert_eq!("0x7a120", sent[1]["gas"]);
+    assert_eq!(2, foreign.requests("eth_getCode"));
+    assert_eq!(1, foreign.requests("eth_call"));
+    let recipients = relay
+        .take_history()
+        .iter()
+        .map(|record| record.recipient)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![Some(RecipientKind::Account), Some(RecipientKind::Contract)],
+        recipients
+    );
+
+    // the kinds of known recipients aren't fetched again
+    emit_at(&home, 0x1012, deposit_to(contract, 3));
+    home.mine_to(0x1020);
+    assert_eq!(vec![0x1014], next(&mut relay, 1));
+    assert_eq!(3, foreign.sent_transactions().len());
+    assert_eq!(2, foreign.requests("eth_getCode"));
+    assert_eq!(2, foreign.requests("eth_call"));
+}
+
+#[test]
+fn fake_chain_bridge_quarantines_deposits_rejected_by_their_recipient() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("fake_chain_bridge_quarantines_deposits").unwrap();
+    let database_path = tempdir.path().join("db.toml");
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    database
+        .save(fs::File::create(&database_path).unwrap())
+        .unwrap();
+    let accepting: Address = 0xc0.into();
+    let rejecting: Address = 0xc1.into();
+    foreign.set_code(accepting, vec![0x60, 0x80]);
+    foreign.set_code(rejecting, vec![0x60, 0x80]);
+    let config = Config {
+        enable_withdrawals: false,
+        ..contract_recipients_config(&home, &foreign, vec![rejecting])
+    };
+    let mut bridge = create_bridge(app_with(&home, &foreign, config, &database_path), &database);
+
+    emit_at(&home, 0x100, deposit_to(rejecting, 1));
+    emit_at(&home, 0x101, deposit_to(accepting, 2));
+    home.mine_to(0x1011);
+    // the rejected deposit doesn't hold back the others
+    assert_eq!(vec![()], next(&mut bridge, 1));
+    let sent = foreign.sent_transactions();
+    assert_eq!(1, sent.len());
+    assert!(
+        sent[0]["data"]
+            .as_str()
+            .unwrap()
+            .contains(&accepting.0.to_hex())
+    );
+
+    let quarantined = bridge.quarantined_deposits().to_vec();
+    assert_eq!(1, quarantined.len());
+    assert_eq!(H256::from(1), quarantined[0].home_transaction);
+    assert_eq!(rejecting, quarantined[0].recipient);
+    assert_eq!(U256::from(0xf0), quarantined[0].value);
+    assert_eq!("fallback out of gas", quarantined[0].reason);
+
+    // and is kept in the database until the operator removes it
+    let saved = Database::load(&database_path).unwrap();
+    assert_eq!(0x1005, saved.checked_deposit_relay);
+    assert_eq!(quarantined, saved.quarantined_deposits);
+}
+
+#[test]
+fn fake_chain_bridge_rotates_authority_on_command() {
+    const OTHER_TRANSACTION: &str =
+        "1111111111111111111111111111111111111111111111111111111111111111";
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("fake_chain_bridge_rotates_authority_on_command").unwrap();
+    let database_path = tempdir.path().join("db.toml");
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    database
+        .save(fs::File::create(&database_path).unwrap())
+        .unwrap();
+    let config = Config {
+        enable_withdrawals: false,
+        ..app(&home, &foreign).config.clone()
+    };
+    let mut bridge = create_bridge(
+        app_with(&home, &foreign, config.clone(), &database_path),
+        &database,
+    );
+    let (commands, receiver) = command_channel();
+    bridge.commands_from(receiver);
+
+    let mut reloaded = config.clone();
+    reloaded.home.account = "0000000000000000000000000000000000000002".parse().unwrap();
+    reloaded.foreign.account = "0000000000000000000000000000000000000002".parse().unwrap();
+    let rotate = |config: &Config| BridgeCommand::RotateAuthority(Box::new(config.clone()));
+    let mut rotation = executor::spawn(commands.send(rotate(&reloaded)));
+    reloaded.foreign.account = "0000000000000000000000000000000000000003".parse().unwrap();
+    let mut rejected = executor::spawn(commands.send(rotate(&reloaded)));
+    // queued until the bridge is polled
+    assert_eq!(Async::NotReady, poll_future(&mut rotation));
+
+    emit_at(&home, 0x100, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(0x1011);
+    assert_eq!(vec![()], next(&mut bridge, 1));
+    assert_eq!(
+        Async::Ready(json!({
+            "rotating": true,
+            "home_account": "0x0000000000000000000000000000000000000002",
+            "foreign_account": "0x0000000000000000000000000000000000000002",
+        })),
+        poll_future(&mut rotation)
+    );
+    assert!(
+        rejected
+            .wait_future()
+            .unwrap_err()
+            .contains("is not one of authorities.accounts")
+    );
+    // the deposit in flight is relayed with the old account
+    let sent = foreign.sent_transactions();
+    assert_eq!("0x0000000000000000000000000000000000000001", sent[0]["from"]);
+
+    foreign.mine(1);
+    emit_at(&home, 0x1020, deposit(OTHER_TRANSACTION));
+    home.mine_to(0x1030);
+    assert_eq!(vec![()], next(&mut bridge, 1));
+    let sent = foreign.sent_transactions();
+    assert_eq!(2, sent.len());
+    assert_eq!("0x0000000000000000000000000000000000000002", sent[1]["from"]);
+}
+
+#[test]
+fn fake_chain_bridge_drains_on_command() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("fake_chain_bridge_drains_on_command").unwrap();
+    let database_path = tempdir.path().join("db.toml");
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    database
+        .save(fs::File::create(&database_path).unwrap())
+        .unwrap();
+    let config = Config {
+        enable_withdrawals: false,
+        ..app(&home, &foreign).config.clone()
+    };
+    let mut bridge = create_bridge(app_with(&home, &foreign, config, &database_path), &database);
+    let (commands, receiver) = command_channel();
+    bridge.commands_from(receiver);
+    bridge.drain_on(futures::sync::oneshot::channel().1, Duration::from_secs(60));
+
+    emit_at(&home, 0x100, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(0x1011);
+    assert_eq!(vec![()], next(&mut bridge, 1));
+    foreign.mine(1);
+    assert!(!bridge.is_draining());
+
+    let mut drain = executor::spawn(commands.send(BridgeCommand::Drain));
+    assert_eq!(Async::NotReady, poll_future(&mut drain));
+    let ended = (0..10).any(|_| poll_once(&mut bridge) == Async::Ready(None));
+    assert_eq!(
+        Async::Ready(json!({ "draining": true, "timeout": 60, "drained": [] })),
+        poll_future(&mut drain)
+    );
+    assert!(ended);
+    assert!(bridge.is_drained(Component::DepositRelay));
+    assert_eq!(
+        vec!["DepositRelay".to_owned()],
+        bridge.drain_report().unwrap().drained
+    );
+}
+
+#[test]
+fn fake_chain_bridge_accepts_each_nonce_of_a_signed_command_once() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("fake_chain_bridge_accepts_each_nonce_once").unwrap();
+    let database_path = tempdir.path().join("db.toml");
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    database
+        .save(fs::File::create(&database_path).unwrap())
+        .unwrap();
+    let config = Config {
+        enable_withdrawals: false,
+        ..app(&home, &foreign).config.clone()
+    };
+    let authorize = |nonce: u64| {
+        BridgeCommand::Authorize(CommandAuthorization {
+            method: "drain".into(),
+            params: Vec::new(),
+            nonce,
+            signers: vec!["7e5f4552091a69125d5dfcb7b8c2659029395bdf".parse().unwrap()],
+        })
+    };
+    let mut bridge = create_bridge(
+        app_with(&home, &foreign, config.clone(), &database_path),
+        &database,
+    );
+    let (commands, receiver) = command_channel();
+    bridge.commands_from(receiver);
+    let mut accepted = executor::spawn(commands.send(authorize(3)));
+    let mut replayed = executor::spawn(commands.send(authorize(3)));
+    poll_once(&mut bridge);
+    assert_eq!(Async::Ready(json!({ "nonce": 3 })), poll_future(&mut accepted));
+    assert_eq!(
+        "nonce 3 was used, the next signed command needs a nonce above 3",
+        replayed.wait_future().unwrap_err()
+    );
+
+    // the nonce is written right away and still used after a restart
+    let database = Database::load(&database_path).unwrap();
+    assert_eq!(Some(3), database.admin_nonce);
+    let mut bridge = create_bridge(app_with(&home, &foreign, config, &database_path), &database);
+    let (commands, receiver) = command_channel();
+    bridge.commands_from(receiver);
+    let mut older = executor::spawn(commands.send(authorize(2)));
+    let mut newer = executor::spawn(commands.send(authorize(4)));
+    poll_once(&mut bridge);
+    assert!(older.wait_future().is_err());
+    assert_eq!(Async::Ready(json!({ "nonce": 4 })), poll_future(&mut newer));
+}
+
+#[test]
+fn fake_chain_bridge_records_the_nodes_of_a_relay() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("fake_chain_bridge_records_the_nodes_of_a_relay").unwrap();
+    let database_path = tempdir.path().join("db.toml");
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    database
+        .save(fs::File::create(&database_path).unwrap())
+        .unwrap();
+    let config = app(&home, &foreign).config.clone();
+    let config = Config {
+        enable_withdrawals: false,
+        relay_history: Some(RelayHistoryConfig {
+            capacity: 10,
+            chain: None,
+        }),
+        home: Node {
+            ipc: "/nodes/home.ipc".into(),
+            ..config.home.clone()
+        },
+        foreign: Node {
+            ipc: "/nodes/foreign.ipc".into(),
+            ..config.foreign.clone()
+        },
+        ..config
+    };
+    let mut bridge = create_bridge(app_with(&home, &foreign, config, &database_path), &database);
+
+    emit_at(&home, 0x100, deposit(DEPOSIT_TRANSACTION));
+    home.mine_to(0x1011);
+    assert_eq!(vec![()], next(&mut bridge, 1));
+    assert_eq!(1, home.requests("web3_clientVersion"));
+    assert_eq!(1, foreign.requests("web3_clientVersion"));
+    let page = bridge
+        .relay_history()
+        .unwrap()
+        .read(|history, _| history.page(None, 0, 10));
+    let record = serde_json::to_value(&page.records[0]).unwrap();
+    assert_eq!(
+        json!({ "endpoint": "/nodes/home.ipc", "client_version": "FakeChain/v0.1.0" }),
+        record["observed_on"]
+    );
+    assert_eq!(
+        json!({ "endpoint": "/nodes/foreign.ipc", "client_version": "FakeChain/v0.1.0" }),
+        record["submitted_to"]
+    );
+}
+
+#[test]
+fn fake_chain_bridge_resumes_deposits_once_the_deep_reorg_is_acknowledged() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("fake_chain_bridge_resumes_deposits_once_the_deep_reorg").unwrap();
+    let database_path = tempdir.path().join("db.toml");
+    let reorg = DeepReorg {
+        fork_block: 4100,
+        depth: 14,
+        alarm_depth: 12,
+        time: 1_700_000_000,
+    };
+    let database = Database {
+        checked_deposit_relay: 5,
+        deep_reorg: Some(reorg.clone()),
+        ..Default::default()
+    };
+    database
+        .save(fs::File::create(&database_path).unwrap())
+        .unwrap();
+    let config = Config {
+        enable_withdrawals: false,
+        circuit_breaker: Some(CircuitBreakerConfig {
+            window: 10,
+            max_failures: 5,
+            cool_down: Duration::from_secs(60),
+            probes: 1,
+        }),
+        reorg_monitor: Some(ReorgMonitorConfig {
+            depth: 100,
+            check_interval: Duration::from_secs(60),
+            alarm_depth: Some(12),
+            halt_deposits: true,
+        }),
+        ..app(&home, &foreign).config.clone()
+    };
+    let mut bridge = create_bridge(app_with(&home, &foreign, config, &database_path), &database);
+    let (commands, receiver) = command_channel();
+    bridge.commands_from(receiver);
+    // halted across the restart
+    assert_eq!(
+        Some(CircuitState::ForcedOpen),
+        bridge.circuit_state(Component::DepositRelay)
+    );
+
+    let close = commands.send(BridgeCommand::ForceCloseCircuit(Component::DepositRelay));
+    let acknowledge = commands.send(BridgeCommand::AcknowledgeDeepReorg);
+    let again = commands.send(BridgeCommand::AcknowledgeDeepReorg);
+    let _ = poll_once(&mut bridge);
+    assert!(close.wait().unwrap_err().contains("acknowledge-deep-reorg"));
+    assert_eq!(
+        json!({
+            "acknowledged": {
+                "fork_block": 4100,
+                "depth": 14,
+                "alarm_depth": 12,
+                "time": 1_700_000_000,
+            },
+            "deposit_relay_resumed": true,
+        }),
+        acknowledge.wait().unwrap()
+    );
+    assert_eq!(
+        "no deep home reorg to acknowledge",
+        again.wait().unwrap_err()
+    );
+    assert_eq!(None, bridge.deep_reorg());
+    assert_eq!(
+        Some(CircuitState::Closed),
+        bridge.circuit_state(Component::DepositRelay)
+    );
+}
+
+#[test]
+fn fake_chain_deposit_relay_holds_deposits_to_recipients_with_extra_confirmations() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let account: Address = "aff3454fce5edbc8cca8697c15331677e6ebcccc".parse().unwrap();
+    let cold: Address = 0xc0.into();
+    let config = Config {
+        recipient_policies: Some(RecipientPoliciesConfig {
+            recipients: vec![RecipientPolicyRule {
+                address: cold,
+                policy: RecipientPolicy::ExtraConfirmations { confirmations: 0x20 },
+            }],
+        }),
+        ..app(&home, &foreign).config.clone()
+    };
+    let selector = foreign::ForeignBridge::default()
+        .functions()
+        .is_deposit_executed()
+        .input(Address::zero(), U256::zero(), [0u8; 32])[..4]
+        .to_vec();
+    foreign.on_call(&selector, |_| vec![0u8; 32]);
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    let mut relay = create_deposit_relay(app_with(&home, &foreign, config, Path::new("")), &database);
+
+    // the deposit to the cold address is held, the block before it is checked.
+    // a zero value deposit to it is skipped as it's read, whatever its policy
+    emit_at(&home, 0x100, deposit_to(cold, 1));
+    emit_at(&home, 0x101, deposit_to(account, 2));
+    let zero_value = FakeEvent {
+        data: ethabi::encode(&[Token::Address(cold), Token::Uint(0.into())]),
+        transaction_hash: 3.into(),
+        ..deposit(DEPOSIT_TRANSACTION)
+    };
+    emit_at(&home, 0x102, zero_value);
+    home.mine_to(0x10e);
+    assert_eq!(vec![0xff], next(&mut relay, 1));
+    let sent = sent_data(&foreign);
+    assert_eq!(1, sent.len());
+    assert!(sent[0].contains(&format!("{:x}", account)));
+    let counts = relay.take_counts();
+    assert_eq!(1, counts.held_deposits);
+    assert_eq!(1, counts.skipped_zero_value);
+    assert_eq!(1, relay.pending_relays().len());
+
+    // and relayed once its block has 0x20 more confirmations
+    home.mine_to(0x12b);
+    assert_eq!(vec![0xff], next(&mut relay, 1));
+    assert_eq!(1, foreign.sent_transactions().len());
+    home.mine_to(0x12c);
+    assert_eq!(vec![0xff, 0x120], next(&mut relay, 2));
+    let sent = sent_data(&foreign);
+    assert_eq!(2, sent.len());
+    assert!(sent[1].contains(&format!("{:x}", cold)));
+    assert_eq!(1, foreign.requests("eth_call"));
+    assert!(relay.pending_relays().is_empty());
+}
+
+#[test]
+fn fake_chain_deposit_relay_holds_deposits_to_manual_recipients_until_released() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let account: Address = "aff3454fce5edbc8cca8697c15331677e6ebcccc".parse().unwrap();
+    let cold: Address = 0xc0.into();
+    let config = Config {
+        recipient_policies: Some(RecipientPoliciesConfig {
+            recipients: vec![RecipientPolicyRule {
+                address: cold,
+                policy: RecipientPolicy::Manual,
+            }],
+        }),
+        ..app(&home, &foreign).config.clone()
+    };
+    let selector = foreign::ForeignBridge::default()
+        .functions()
+        .is_deposit_executed()
+        .input(Address::zero(), U256::zero(), [0u8; 32])[..4]
+        .to_vec();
+    foreign.on_call(&selector, |_| vec![0u8; 32]);
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    let mut relay = create_deposit_relay(app_with(&home, &foreign, config, Path::new("")), &database);
+
+    emit_at(&home, 0x100, deposit_to(cold, 1));
+    emit_at(&home, 0x101, deposit_to(account, 2));
+    emit_at(&home, 0x103, deposit_to(cold, 4));
+    home.mine_to(0x10f);
+    assert_eq!(vec![0xff], next(&mut relay, 1));
+    assert_eq!(1, sent_data(&foreign).len());
+    assert_eq!(2, relay.take_counts().held_deposits);
+    let held = relay.held_values();
+    assert_eq!(1, held.len());
+    assert_eq!(cold, held[0].recipient);
+    assert_eq!(RecipientPolicy::Manual, held[0].policy);
+    assert_eq!(U256::from(0x1e0), held[0].value);
+    assert_eq!(2, held[0].keys.len());
+
+    // however deep their blocks get
+    home.mine_to(0x200);
+    assert_eq!(vec![0xff], next(&mut relay, 1));
+    assert_eq!(1, sent_data(&foreign).len());
+    assert_eq!(2, relay.pending_relays().len());
+
+    let keys = held[0].keys.clone();
+    assert!(relay.release_held(&H256::from(0x2a)).is_err());
+    relay.release_held(&keys[0]).unwrap();
+    // the deposit still held keeps the checked block before it
+    assert_eq!(vec![0x102], next(&mut relay, 1));
+    let sent = sent_data(&foreign);
+    assert_eq!(2, sent.len());
+    assert!(sent[1].contains(&format!("{:x}", cold)));
+    assert_eq!(1, foreign.requests("eth_call"));
+    let held = relay.held_values();
+    assert_eq!(U256::from(0xf0), held[0].value);
+    assert_eq!(vec![keys[1]], held[0].keys);
+    assert_eq!(1, relay.pending_relays().len());
+}
+
+/// relay transaction `n` of a deposit to foreign, its expected events and
+/// the `DepositConfirmation` it emits
+fn deposit_relay(n: u64) -> (H256, ExpectedEvent, FakeEvent) {
+    let contract: Address = 0xf0.into();
+    let recipient: Address = "aff3454fce5edbc8cca8697c15331677e6ebcccc".parse().unwrap();
+    let value: U256 = n.into();
+    let id = H256::from(n);
+    let transaction = H256::from(0xa0 + n);
+    let request = TransactionRequest {
+        from: 1.into(),
+        to: Some(contract),
+        gas: None,
+        gas_price: None,
+        value: None,
+        data: Some(
+            foreign::ForeignBridge::default()
+                .functions()
+                .deposit()
+                .input(recipient, value, id.0)
+                .into(),
+        ),
+        nonce: None,
+        condition: None,
+    };
+    let event = FakeEvent {
+        address: contract,
+        topics: vec![DEPOSIT_CONFIRMATION_TOPIC.parse().unwrap()],
+        data: ethabi::encode(&[
+            Token::Address(recipient),
+            Token::Uint(value),
+            Token::FixedBytes(id.to_vec()),
+        ]),
+        transaction_hash: transaction,
+    };
+    (transaction, ExpectedEvent::for_relay(&request).unwrap(), event)
+}
+
+/// checks the events of three deposit relays confirmed by `confirmation`: the first emits
+/// its event after the check started, the second was mined without it before the check
+/// started and the third is never mined. returns foreign.
+fn check_relay_events(confirmation: Confirmation) -> FakeChain {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let mut config = app(&home, &foreign).config.clone();
+    config.foreign.confirmation = confirmation;
+    config.relay_events = Some(RelayEventsConfig {
+        missing: MissingEventAction::Fail,
+        reverted: MissingEventAction::Fail,
+        timeout: Duration::from_secs(1),
+    });
+    let app = app_with(&home, &foreign, config, Path::new(""));
+    let relays = (1..4).map(deposit_relay).collect::<Vec<_>>();
+    let transactions = relays.iter().map(|relay| relay.0).collect::<Vec<_>>();
+    let expected = relays.iter().map(|relay| Some(relay.1.clone())).collect();
+
+    foreign.mine_to(0x20);
+    foreign.emit(FakeEvent {
+        topics: vec![TRANSFER_TOPIC.parse().unwrap()],
+        ..relays[1].2.clone()
+    });
+    foreign.mine(1);
+    let verify = verify_relay_events(&app, false, &transactions, expected).unwrap();
+    let emitter = {
+        let foreign = foreign.clone();
+        let event = relays[0].2.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            foreign.emit(event);
+            foreign.mine(1);
+        })
+    };
+    let mut checks = verify.wait().unwrap();
+    emitter.join().unwrap();
+
+    checks.sort_by_key(|check| check.transaction);
+    // the relay confirmed the deposit without completing it
+    assert_eq!(
+        Some("DepositConfirmation(address,uint256,bytes32)"),
+        checks[0].event
+    );
+    assert_eq!(None, checks[1].event);
+    let outcomes = checks
+        .into_iter()
+        .map(|check| (check.transaction, check.outcome))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            (transactions[0], EventOutcome::Emitted),
+            (transactions[1], EventOutcome::Missing),
+            (transactions[2], EventOutcome::Unconfirmed),
+        ],
+        outcomes
+    );
+    foreign
+}
+
+#[test]
+fn fake_chain_relay_events_confirmed_by_receipt() {
+    let foreign = check_relay_events(Confirmation::Receipt);
+    assert!(foreign.requests("eth_getTransactionReceipt") > 3);
+}
+
+#[test]
+fn fake_chain_relay_events_confirmed_by_event() {
+    let foreign = check_relay_events(Confirmation::Event);
+    // the emitted relay once its event was seen, the others once at the deadline
+    assert_eq!(3, foreign.requests("eth_getTransactionReceipt"));
+    assert!(foreign.requests("eth_getLogs") > 0);
+}
+
+#[test]
+fn fake_chain_pause_monitor_follows_pause_flags() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let app = app(&home, &foreign);
+    let selector = PAUSED_SELECTOR.from_hex().unwrap();
+    // home hits a fallback function, foreign is unpaused
+    home.on_call(&selector, |_| Vec::new());
+    foreign.on_call(&selector, |_| vec![0u8; 32]);
+    home.mine_to(5);
+    foreign.mine_to(5);
+
+    let mut monitor = create_pause_monitor(app, &Database::default());
+    let mut pauses = next(&mut monitor, 1).remove(0);
+    while pauses.home == PauseState::Unknown || pauses.foreign == PauseState::Unknown {
+        pauses = next(&mut monitor, 1).remove(0);
+    }
+    assert_eq!(PauseState::Unsupported, pauses.home);
+    assert_eq!(PauseState::Unpaused, pauses.foreign);
+
+    emit_at(&foreign, 7, FakeEvent {
+        address: 0.into(),
+        topics: vec![PAUSED_TOPIC.parse().unwrap()],
+        data: vec![0u8; 32],
+        transaction_hash: 0x77.into(),
+    });
+    let pauses = next(&mut monitor, 1).remove(0);
+    assert_eq!(PauseState::Unsupported, pauses.home);
+    assert_eq!(PauseState::Paused, pauses.foreign);
+    assert!(pauses.suspends(Component::DepositRelay));
+    assert!(!pauses.suspends(Component::WithdrawRelay));
+}
+
+/// `--rebuild-database` of 50000 blocks of nodes answering after 20ms,
+/// fetching the logs one chunk at a time and `scan_concurrency` chunks at once.
+/// run with `cargo test --features bench -- --nocapture bench`
+#[cfg(feature = "bench")]
+#[test]
+fn bench_rebuild_scan_concurrency() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    for (i, number) in [100u64, 20_000, 45_000].iter().enumerate() {
+        emit_at(&home, *number, deposit(&format!("{:064x}", i + 1)));
+    }
+    home.mine_to(50_000);
+    foreign.mine_to(50_000);
+    home.set_latency(Duration::from_millis(20));
+    foreign.set_latency(Duration::from_millis(20));
+    let init = Database {
+        home_deploy: 1,
+        foreign_deploy: 1,
+        ..Database::default()
+    };
+
+    let rebuild = |scan_concurrency| {
+        let config = Config {
+            scan_concurrency,
+            ..app(&home, &foreign).config.clone()
+        };
+        let app = app_with(&home, &foreign, config, Path::new(""));
+        let start = ::std::time::Instant::now();
+        let database = bridge::bridge::create_rebuild(app, &init).wait().unwrap();
+        (database, start.elapsed())
+    };
+    let (serial, serial_time) = rebuild(1);
+    let (bounded, bounded_time) = rebuild(8);
+    println!(
+        "rebuild of 50000 blocks: {:?} with 1 request in flight, {:?} with 8",
+        serial_time, bounded_time
+    );
+    assert_eq!(serial, bounded);
+    assert!(bounded_time * 3 < serial_time);
+}
+
+/// config relaying the deposits to every recipient in order
+fn ordered_config(home: &FakeChain, foreign: &FakeChain) -> Config {
+    Config {
+        ordered_relays: Some(OrderedRelaysConfig {
+            recipients: None,
+            receipt_interval: Duration::from_millis(10),
+        }),
+        ..app(home, foreign).config.clone()
+    }
+}
+
+/// transaction to foreign with data `n`
+fn numbered_request(n: u8) -> TransactionRequest {
+    TransactionRequest {
+        from: 1.into(),
+        to: Some(0xf0.into()),
+        gas: None,
+        gas_price: None,
+        value: None,
+        data: Some(Bytes(vec![n])),
+        nonce: None,
+        condition: None,
+    }
+}
+
+/// data of the transactions sent to `chain`, in order
+fn sent_data(chain: &FakeChain) -> Vec<String> {
+    chain
+        .sent_transactions()
+        .iter()
+        .map(|sent| sent["data"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[test]
+fn fake_chain_ordered_transactions_wait_for_the_previous_of_their_key() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let (timer, clock) = Timer::manual();
+    let config = ordered_config(&home, &foreign);
+    let app = app_with_timer(&home, &foreign, config, Path::new(""), timer);
+    let (a, b): (Address, Address) = (0xa.into(), 0xb.into());
+    let requests = (0..5).map(numbered_request).collect();
+    let keys = vec![Some(a), Some(b), Some(a), None, Some(b)];
+    let mut sending = executor::spawn(send_ordered_foreign_transactions(
+        app,
+        requests,
+        keys,
+        usize::max_value(),
+    ));
+
+    // the first transactions of both keys are sent at once, the later ones of a key wait
+    assert_eq!(Async::NotReady, poll_future(&mut sending));
+    assert_eq!(vec!["0x00", "0x01", "0x03"], sent_data(&foreign));
+
+    // until the previous transaction of their key is mined
+    foreign.mine(1);
+    for _ in 0..100 {
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(Async::NotReady, poll_future(&mut sending));
+        if sent_data(&foreign).len() == 5 {
+            break;
+        }
+    }
+    assert_eq!(
+        vec!["0x00", "0x01", "0x03", "0x02", "0x04"],
+        sent_data(&foreign)
+    );
+
+    // and resolve in the order of the requests once the last of every key is mined
+    foreign.mine(1);
+    let mut hashes = None;
+    for _ in 0..100 {
+        clock.advance(Duration::from_millis(10));
+        if let Async::Ready(ready) = poll_future(&mut sending) {
+            hashes = Some(ready);
+            break;
+        }
+    }
+    // `FakeChain` numbers the hashes of transactions in the order they were sent
+    let sent = |n: u64| {
+        let mut hash = H256::from(n);
+        hash.0[0] = 0x7e;
+        hash
+    };
+    assert_eq!(
+        Some(vec![sent(1), sent(2), sent(4), sent(3), sent(5)]),
+        hashes
+    );
+}
+
+#[test]
+fn fake_chain_ordered_transactions_held_back_dont_take_the_quota() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let app = app_with(&home, &foreign, ordered_config(&home, &foreign), Path::new(""));
+    let (a, b): (Address, Address) = (0xa.into(), 0xb.into());
+    let requests = (0..3).map(numbered_request).collect();
+    let mut sending = executor::spawn(send_ordered_foreign_transactions(
+        app,
+        requests,
+        vec![Some(a), Some(a), Some(b)],
+        1,
+    ));
+
+    // the second transaction of `a` waits for the first to be mined,
+    // while the transaction of `b` takes the only place in flight
+    assert_eq!(Async::NotReady, poll_future(&mut sending));
+    assert_eq!(vec!["0x00", "0x02"], sent_data(&foreign));
+}
+
+#[test]
+fn fake_chain_deposit_relay_orders_the_relays_of_a_recipient() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let (a, b): (Address, Address) = (0xc0.into(), 0xc1.into());
+    let (timer, clock) = Timer::manual();
+    let config = ordered_config(&home, &foreign);
+    let app = app_with_timer(&home, &foreign, config, Path::new(""), timer);
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Default::default()
+    };
+    let mut relay = create_deposit_relay(app, &database);
+
+    emit_at(&home, 0x100, deposit_to(a, 1));
+    emit_at(&home, 0x101, deposit_to(b, 2));
+    emit_at(&home, 0x102, deposit_to(a, 3));
+    emit_at(&home, 0x103, deposit_to(b, 4));
+    home.mine_to(0x1011);
+    // relayed home transactions, in the order they were relayed
+    let relayed = || {
+        sent_data(&foreign)
+            .iter()
+            .map(|data| data[data.len() - 64..].parse::<H256>().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // the first deposits of both recipients are relayed at once
+    for _ in 0..100 {
+        if relayed().len() == 2 {
+            break;
+        }
+        assert_eq!(Async::NotReady, poll_once(&mut relay));
+        clock.advance(Duration::from_millis(10));
+    }
+    for _ in 0..5 {
+        assert_eq!(Async::NotReady, poll_once(&mut relay));
+        clock.advance(Duration::from_millis(20));
+    }
+    assert_eq!(vec![H256::from(1), H256::from(2)], relayed());
+
+    // the later ones once the previous relay to their recipient is mined
+    let mut polled = Async::NotReady;
+    for _ in 0..100 {
+        foreign.mine(1);
+        clock.advance(Duration::from_millis(10));
+        polled = poll_once(&mut relay);
+        if polled.is_ready() {
+            break;
+        }
+    }
+    assert_eq!(Async::Ready(Some(0x1005)), polled);
+    assert_eq!(
+        vec![H256::from(1), H256::from(2), H256::from(3), H256::from(4)],
+        relayed()
+    );
+}
+
+/// sends `numbered_request(0)` to `foreign` and waits for its hash
+fn send_one(home: &FakeChain, foreign: &FakeChain) -> Result<H256, Error> {
+    let app = app(home, foreign);
+    send_ordered_foreign_transactions(app, vec![numbered_request(0)], vec![None], 1)
+        .wait()
+        .map(|hashes| hashes[0])
+}
+
+#[test]
+fn fake_chain_send_rejected_by_the_node_keeps_the_rpc_error() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    foreign.fail_next(
+        "eth_sendTransaction",
+        FakeFailure::Reject {
+            code: -32010,
+            message: "Transaction nonce is too low".into(),
+            data: Some("0x01".into()),
+        },
+    );
+
+    let err = send_one(&home, &foreign).unwrap_err();
+    assert_eq!(
+        Some(NodeFailure::Rejected {
+            code: -32010,
+            message: "Transaction nonce is too low".into(),
+            data: Some("0x01".into()),
+        }),
+        api::node_failure(&err, true)
+    );
+    assert_eq!("rpc", failure_kind(&err));
+    // a rejected transaction wasn't sent, so it isn't looked for
+    assert_eq!(0, foreign.requests("parity_pendingTransactions"));
+}
+
+#[test]
+fn fake_chain_unreachable_node_fails_calls_without_effect() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    foreign.fail_next("eth_blockNumber", FakeFailure::Refuse);
+
+    let err = api::block_number(&foreign).wait().unwrap_err();
+    match api::node_failure(&err, false) {
+        Some(NodeFailure::Unreachable { ref source }) => {
+            assert!(source.contains("connection refused"), "{}", source)
+        }
+        other => panic!("expected an unreachable node, got {:?}", other),
+    }
+    assert_eq!("transport", failure_kind(&err));
+    // the next request reaches it
+    assert_eq!(U256::from(0), api::block_number(&foreign).wait().unwrap());
+}
+
+#[test]
+fn fake_chain_send_dropped_mid_response_is_found_pending() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    foreign.fail_next("eth_sendTransaction", FakeFailure::DropResponse);
+
+    // the node took the transaction, its hash is looked up instead of sending it again
+    let hash = send_one(&home, &foreign).unwrap();
+    assert_eq!(1, foreign.sent_transactions().len());
+    assert_eq!(1, foreign.requests("parity_pendingTransactions"));
+    foreign.mine(1);
+    let receipt = api::transaction_receipt(&foreign, hash).wait().unwrap();
+    assert!(receipt.is_some());
+}
+
+#[test]
+fn fake_chain_ambiguous_send_not_pending_is_not_resent() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    foreign.fail_next("eth_sendTransaction", FakeFailure::Refuse);
+
+    let err = send_one(&home, &foreign).unwrap_err();
+    match *err.kind() {
+        ErrorKind::AmbiguousSend("eth_sendTransaction", to) => assert_eq!(Some(0xf0.into()), to),
+        ref kind => panic!("expected an ambiguous send, got {:?}", kind),
+    }
+    assert_eq!(
+        Some(NodeFailure::AmbiguousSend { maybe_sent: true }),
+        api::node_failure(&err, true)
+    );
+    assert_eq!("ambiguous_send", failure_kind(&err));
+    assert!(foreign.sent_transactions().is_empty());
+
+    // a lookup that fails keeps the send ambiguous
+    foreign.fail_next("eth_sendTransaction", FakeFailure::DropResponse);
+    foreign.fail_next("parity_pendingTransactions", FakeFailure::Refuse);
+    let err = send_one(&home, &foreign).unwrap_err();
+    assert_eq!("ambiguous_send", failure_kind(&err));
+    assert_eq!(1, foreign.sent_transactions().len());
+}
+
+const BOOTSTRAP_PLAN: &str = r#"
+account = "0x0000000000000000000000000000000000000001"
+deployer = "0x00000000000000000000000000000000000000d0"
+authorities = [
+    "0x0000000000000000000000000000000000000001",
+    "0x0000000000000000000000000000000000000002",
+]
+required_signatures = 2
+estimated_gas_cost_of_withdraw = "100000"
+max_total_home_contract_balance = "10000000000000000000"
+max_single_deposit_value = "1000000000000000000"
+fund_authorities = "0.5 ether"
+
+[home]
+ipc = "/home.ipc"
+contract_bin = "../compiled_contracts/HomeBridge.bin"
+required_confirmations = 1
+
+[foreign]
+ipc = "/foreign.ipc"
+contract_bin = "../compiled_contracts/ForeignBridge.bin"
+required_confirmations = 1
+"#;
+
+/// mines a block on both chains every few milliseconds until the returned flag is set
+fn keep_mining(home: &FakeChain, foreign: &FakeChain) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let (home, foreign, stopped) = (home.clone(), foreign.clone(), stop.clone());
+    thread::spawn(move || {
+        while !stopped.load(Ordering::SeqCst) {
+            home.mine(1);
+            foreign.mine(1);
+            thread::sleep(Duration::from_millis(5));
+        }
+    });
+    stop
+}
+
+/// bootstraps `plan` on `home` and `foreign` from the progress saved at `state_path`
+fn bootstrap(
+    home: &FakeChain,
+    foreign: &FakeChain,
+    plan: &BootstrapPlan,
+    state_path: &Path,
+) -> Result<BootstrapProgress, Error> {
+    let mut config = plan.deploy_config().unwrap();
+    config.home.poll_interval = Duration::from_millis(1);
+    config.foreign.poll_interval = Duration::from_millis(1);
+    let progress = BootstrapProgress::load_or_new(state_path, plan).unwrap();
+    let app = app_with(home, foreign, config, Path::new(""));
+    create_bootstrap(app, plan.clone(), progress, state_path.into()).wait()
+}
+
+#[test]
+fn fake_chain_bootstrap_continues_where_it_failed() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("fake_chain_bootstrap_continues").unwrap();
+    let state_path = tempdir.path().join("plan.toml.state");
+    let plan = BootstrapPlan::load_from_str(BOOTSTRAP_PLAN).unwrap();
+    serve_authorities(&home, plan.authorities.clone(), 2);
+    serve_authorities(&foreign, plan.authorities.clone(), 2);
+    let stop = keep_mining(&home, &foreign);
+
+    // the foreign node refuses the deployment after home deployed its contract
+    foreign.fail_next("eth_sendTransaction", FakeFailure::Refuse);
+    assert!(bootstrap(&home, &foreign, &plan, &state_path).is_err());
+    let saved = BootstrapProgress::load_or_new(&state_path, &plan).unwrap();
+    assert!(saved.home.contract.is_some());
+    assert_eq!(None, saved.foreign.transaction);
+
+    // home isn't deployed again
+    let progress = bootstrap(&home, &foreign, &plan, &state_path).unwrap();
+    stop.store(true, Ordering::SeqCst);
+    assert_eq!(1, home.sent_transactions().len());
+    assert_eq!(saved.home, progress.home);
+    assert!(progress.verified);
+    assert!(progress.foreign.contract.is_some());
+    // the deployment and a funding of each authority
+    let sent = foreign.sent_transactions();
+    assert_eq!(3, sent.len());
+    assert!(sent[0]["to"].is_null());
+    let funded = progress
+        .funded
+        .iter()
+        .filter(|funding| funding.mined)
+        .map(|funding| funding.authority)
+        .collect::<Vec<_>>();
+    assert_eq!(plan.authorities, funded);
+    assert_eq!(progress, BootstrapProgress::load_or_new(&state_path, &plan).unwrap());
+
+    // a completed bootstrap sends nothing more
+    assert_eq!(progress, bootstrap(&home, &foreign, &plan, &state_path).unwrap());
+    assert_eq!(3, foreign.sent_transactions().len());
+
+    let config_path = tempdir.path().join("config.toml");
+    let database_path = tempdir.path().join("db.toml");
+    write_bootstrap_files(&plan, &progress, &config_path, &database_path).unwrap();
+    let database = Database::load(&database_path).unwrap();
+    assert_eq!(progress.home.contract, Some(database.home_contract_address));
+    assert_eq!(progress.foreign.contract, Some(database.foreign_contract_address));
+    assert_eq!(progress.home.block, Some(database.checked_deposit_relay));
+    assert_eq!(plan.account, Config::load(&config_path).unwrap().home.account);
+}
+
+#[test]
+fn fake_chain_bootstrap_stops_at_contracts_of_other_authorities() {
+    let (home, foreign) = (FakeChain::new(), FakeChain::new());
+    let tempdir = TempDir::new("fake_chain_bootstrap_stops").unwrap();
+    let state_path = tempdir.path().join("plan.toml.state");
+    let plan = BootstrapPlan::load_from_str(BOOTSTRAP_PLAN).unwrap();
+    serve_authorities(&home, plan.authorities.clone(), 2);
+    serve_authorities(&foreign, plan.authorities.clone(), 1);
+    let stop = keep_mining(&home, &foreign);
+
+    let err = bootstrap(&home, &foreign, &plan, &state_path).unwrap_err();
+    stop.store(true, Ordering::SeqCst);
+    assert!(err.to_string().starts_with("the contract on foreign lists the authorities"));
+    let saved = BootstrapProgress::load_or_new(&state_path, &plan).unwrap();
+    assert!(!saved.verified);
+    assert!(saved.funded.is_empty());
+}
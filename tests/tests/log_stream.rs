@@ -7,9 +7,13 @@ extern crate serde_json;
 extern crate tests;
 extern crate web3;
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use futures::{Future, Stream};
 use web3::types::{FilterBuilder, H160, H256, Log};
-use bridge::api::{log_stream, LogStreamInit, LogStreamItem};
+use bridge::api::{log_stream, LogStreamInit, LogStreamItem, OversizedLog};
+use bridge::config::{FinalityRpc, HeadRegression, LogsSanityCheck, DEFAULT_MAX_LOG_DATA};
+use bridge::jitter::{Jitter, JitterRange};
+use tests::MockedTransport;
 
 test_transport_stream! {
     name => log_stream_basic,
@@ -18,8 +22,15 @@ test_transport_stream! {
             after: 10,
             filter: FilterBuilder::default(),
             poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
             request_timeout: Duration::from_secs(5),
             confirmations: 10,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
         };
 
         log_stream(transport, Default::default(), init).take(2)
@@ -69,8 +80,15 @@ test_transport_stream! {
             after: 10,
             filter: FilterBuilder::default(),
             poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
             request_timeout: Duration::from_secs(5),
             confirmations: 10,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
         };
 
         log_stream(transport, Default::default(), init).take(2)
@@ -123,8 +141,15 @@ test_transport_stream! {
             after: 10,
             filter: FilterBuilder::default(),
             poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
             request_timeout: Duration::from_secs(5),
             confirmations: 10,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
         };
 
         log_stream(transport, Default::default(), init).take(1)
@@ -161,8 +186,15 @@ test_transport_stream! {
             after: 10,
             filter: FilterBuilder::default(),
             poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
             request_timeout: Duration::from_secs(5),
             confirmations: 0,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
         };
 
         log_stream(transport, Default::default(), init).take(3)
@@ -228,8 +260,15 @@ test_transport_stream! {
             after: 11,
             filter: FilterBuilder::default().address(vec![H160([0x11u8; 20])]),
             poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
             request_timeout: Duration::from_secs(5),
             confirmations: 0,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
         };
 
         log_stream(transport, Default::default(), init).take(2)
@@ -276,8 +315,15 @@ test_transport_stream! {
             after: 11,
             filter: FilterBuilder::default().topics(Some(vec![H256([0x22; 32])]), None, None, None),
             poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
             request_timeout: Duration::from_secs(5),
             confirmations: 0,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
         };
 
         log_stream(transport, Default::default(), init).take(2)
@@ -324,8 +370,15 @@ test_transport_stream! {
             after: 10,
             filter: FilterBuilder::default(),
             poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
             request_timeout: Duration::from_secs(5),
             confirmations: 10,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
         };
 
         log_stream(transport, Default::default(), init).take(1)
@@ -338,6 +391,11 @@ test_transport_stream! {
             topics: vec![],
             data: vec![0x10].into(),
             log_type: "".into(),
+            block_number: Some(0x1000u64.into()),
+            transaction_hash: Some(
+                "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364".into(),
+            ),
+            log_index: Some(0.into()),
             ..Default::default()
         }],
     }],
@@ -356,7 +414,10 @@ test_transport_stream! {
             "address": "0x0000000000000000000000000000000000000001",
             "topics": [],
             "data": "0x10",
-            "type": ""
+            "type": "",
+            "blockNumber": "0x1000",
+            "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364",
+            "logIndex": "0x0"
         }]);
 }
 
@@ -367,8 +428,15 @@ test_transport_stream! {
             after: 10,
             filter: FilterBuilder::default(),
             poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
             request_timeout: Duration::from_secs(5),
             confirmations: 10,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
         };
 
         log_stream(transport, Default::default(), init).take(3)
@@ -381,6 +449,11 @@ test_transport_stream! {
             topics: vec![],
             data: vec![0x10].into(),
             log_type: "".into(),
+            block_number: Some(0x1000u64.into()),
+            transaction_hash: Some(
+                "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364".into(),
+            ),
+            log_index: Some(0.into()),
             ..Default::default()
         }],
     }, LogStreamItem {
@@ -395,12 +468,18 @@ test_transport_stream! {
             topics: vec![],
             data: vec![0x20].into(),
             log_type: "".into(),
+            block_number: Some(0x1008u64.into()),
+            transaction_hash: Some(2.into()),
+            log_index: Some(0.into()),
             ..Default::default()
         }, Log {
             address: "0000000000000000000000000000000000000002".into(),
             topics: vec![],
             data: vec![0x30].into(),
             log_type: "".into(),
+            block_number: Some(0x1008u64.into()),
+            transaction_hash: Some(2.into()),
+            log_index: Some(1.into()),
             ..Default::default()
         }],
     }],
@@ -419,7 +498,10 @@ test_transport_stream! {
             "address": "0x0000000000000000000000000000000000000001",
             "topics": [],
             "data": "0x10",
-            "type": ""
+            "type": "",
+            "blockNumber": "0x1000",
+            "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364",
+            "logIndex": "0x0"
         }]);
     "eth_blockNumber" =>
         req => json!([]),
@@ -449,13 +531,776 @@ test_transport_stream! {
                 "address": "0x0000000000000000000000000000000000000002",
                 "topics": [],
                 "data": "0x20",
-                "type":""
+                "type":"",
+                "blockNumber": "0x1008",
+                "transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000002",
+                "logIndex": "0x0"
             },
             {
                 "address":"0x0000000000000000000000000000000000000002",
                 "topics": [],
                 "data": "0x30",
-                "type": ""
+                "type": "",
+                "blockNumber": "0x1008",
+                "transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000002",
+                "logIndex": "0x1"
             }
         ]);
 }
+
+// node returns a log, then stops returning it.
+// log stream must not advance until the node returns the log again.
+test_transport_stream! {
+    name => log_stream_sanity_check_halts_until_node_recovers,
+    init => |transport| {
+        let init = LogStreamInit {
+            after: 10,
+            filter: FilterBuilder::default(),
+            poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
+            request_timeout: Duration::from_secs(5),
+            confirmations: 10,
+            sanity_check: Some(LogsSanityCheck {
+                interval: 1,
+                halt: true,
+            }),
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
+        };
+
+        log_stream(transport, Default::default(), init).take(2)
+    },
+    expected => vec![LogStreamItem {
+        from: 0xb,
+        to: 0x1006,
+        logs: vec![Log {
+            address: "0000000000000000000000000000000000000001".into(),
+            topics: vec![],
+            data: vec![0x10].into(),
+            log_type: "".into(),
+            block_number: Some(0x1000u64.into()),
+            transaction_hash: Some(
+                "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364".into(),
+            ),
+            log_index: Some(0.into()),
+            ..Default::default()
+        }],
+    }, LogStreamItem {
+        from: 0x1007,
+        to: 0x1007,
+        logs: vec![],
+    }],
+    "eth_blockNumber" =>
+        req => json!([]),
+        res => json!("0x1010");
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0xb",
+            "limit": null,
+            "toBlock": "0x1006",
+            "topics": null
+        }]),
+        res => json!([{
+            "address": "0x0000000000000000000000000000000000000001",
+            "topics": [],
+            "data": "0x10",
+            "type": "",
+            "blockNumber": "0x1000",
+            "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364",
+            "logIndex": "0x0"
+        }]);
+    "eth_blockNumber" =>
+        req => json!([]),
+        res => json!("0x1011");
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0x1007",
+            "limit": null,
+            "toBlock": "0x1007",
+            "topics": null
+        }]),
+        res => json!([]);
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0x1000",
+            "limit": null,
+            "toBlock": "0x1000",
+            "topics": null
+        }]),
+        res => json!([]);
+    "eth_blockNumber" =>
+        req => json!([]),
+        res => json!("0x1011");
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0x1007",
+            "limit": null,
+            "toBlock": "0x1007",
+            "topics": null
+        }]),
+        res => json!([]);
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0x1000",
+            "limit": null,
+            "toBlock": "0x1000",
+            "topics": null
+        }]),
+        res => json!([{
+            "address": "0x0000000000000000000000000000000000000001",
+            "topics": [],
+            "data": "0x10",
+            "type": "",
+            "blockNumber": "0x1000",
+            "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364",
+            "logIndex": "0x0"
+        }]);
+}
+
+// logs are only released up to the last block the rollup posted to l1
+test_transport_stream! {
+    name => log_stream_l1_posted_finality,
+    init => |transport| {
+        let init = LogStreamInit {
+            after: 10,
+            filter: FilterBuilder::default(),
+            poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
+            request_timeout: Duration::from_secs(5),
+            confirmations: 10,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: Some(FinalityRpc {
+                method: "rollup_getInfo".into(),
+                result_path: "safe.number".into(),
+                poll_interval: Duration::from_secs(0),
+            }),
+        };
+
+        log_stream(transport, Default::default(), init).take(2)
+    },
+    expected => vec![LogStreamItem {
+        from: 0xb,
+        to: 0x100,
+        logs: vec![],
+    }, LogStreamItem {
+        from: 0x101,
+        to: 0x1008,
+        logs: vec![],
+    }],
+    "eth_blockNumber" =>
+        req => json!([]),
+        res => json!("0x1010");
+    "rollup_getInfo" =>
+        req => json!([]),
+        res => json!({"safe": {"number": "0x100"}});
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0xb",
+            "limit": null,
+            "toBlock": "0x100",
+            "topics": null
+        }]),
+        res => json!([]);
+    // nothing new was posted to l1
+    "eth_blockNumber" =>
+        req => json!([]),
+        res => json!("0x1011");
+    "rollup_getInfo" =>
+        req => json!([]),
+        res => json!({"safe": {"number": 256}});
+    // confirmations still apply to blocks posted to l1
+    "eth_blockNumber" =>
+        req => json!([]),
+        res => json!("0x1012");
+    "rollup_getInfo" =>
+        req => json!([]),
+        res => json!({"safe": {"number": "0x2000"}});
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0x101",
+            "limit": null,
+            "toBlock": "0x1008",
+            "topics": null
+        }]),
+        res => json!([]);
+}
+
+// node returns logs out of block order.
+// they are yielded in order and the stream continues after the fetched range
+test_transport_stream! {
+    name => log_stream_sorts_logs,
+    init => |transport| {
+        let init = LogStreamInit {
+            after: 10,
+            filter: FilterBuilder::default(),
+            poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
+            request_timeout: Duration::from_secs(5),
+            confirmations: 10,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
+        };
+
+        log_stream(transport, Default::default(), init).take(2)
+    },
+    expected => vec![LogStreamItem {
+        from: 0xb,
+        to: 0x20,
+        logs: vec![Log {
+            data: vec![0x10].into(),
+            block_number: Some(0x1f.into()),
+            transaction_hash: Some(1.into()),
+            transaction_index: Some(3.into()),
+            log_index: Some(0.into()),
+            log_type: "".into(),
+            ..Default::default()
+        }, Log {
+            data: vec![0x20].into(),
+            block_number: Some(0x20.into()),
+            transaction_hash: Some(2.into()),
+            transaction_index: Some(0.into()),
+            log_index: Some(1.into()),
+            log_type: "".into(),
+            ..Default::default()
+        }, Log {
+            data: vec![0x30].into(),
+            block_number: Some(0x20.into()),
+            transaction_hash: Some(3.into()),
+            transaction_index: Some(1.into()),
+            log_index: Some(0.into()),
+            log_type: "".into(),
+            ..Default::default()
+        }],
+    }, LogStreamItem {
+        from: 0x21,
+        to: 0x21,
+        logs: vec![],
+    }],
+    "eth_blockNumber" =>
+        req => json!([]),
+        res => json!("0x2a");
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0xb",
+            "limit": null,
+            "toBlock": "0x20",
+            "topics": null
+        }]),
+        res => json!([{
+            "address": "0x0000000000000000000000000000000000000000",
+            "topics": [],
+            "data": "0x30",
+            "type": "",
+            "blockNumber": "0x20",
+            "transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000003",
+            "transactionIndex": "0x1",
+            "logIndex": "0x0"
+        }, {
+            "address": "0x0000000000000000000000000000000000000000",
+            "topics": [],
+            "data": "0x10",
+            "type": "",
+            "blockNumber": "0x1f",
+            "transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "transactionIndex": "0x3",
+            "logIndex": "0x0"
+        }, {
+            "address": "0x0000000000000000000000000000000000000000",
+            "topics": [],
+            "data": "0x20",
+            "type": "",
+            "blockNumber": "0x20",
+            "transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000002",
+            "transactionIndex": "0x0",
+            "logIndex": "0x1"
+        }]);
+    "eth_blockNumber" =>
+        req => json!([]),
+        res => json!("0x2b");
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0x21",
+            "limit": null,
+            "toBlock": "0x21",
+            "topics": null
+        }]),
+        res => json!([]);
+}
+
+// node returns pending logs without block, transaction or index among mined logs.
+// only the mined logs are yielded
+test_transport_stream! {
+    name => log_stream_drops_pending_logs,
+    init => |transport| {
+        let init = LogStreamInit {
+            after: 10,
+            filter: FilterBuilder::default(),
+            poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
+            request_timeout: Duration::from_secs(5),
+            confirmations: 10,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
+        };
+
+        log_stream(transport, Default::default(), init).take(1)
+    },
+    expected => vec![LogStreamItem {
+        from: 0xb,
+        to: 0x20,
+        logs: vec![Log {
+            data: vec![0x10].into(),
+            block_number: Some(0x1f.into()),
+            transaction_hash: Some(1.into()),
+            log_index: Some(0.into()),
+            log_type: "".into(),
+            ..Default::default()
+        }],
+    }],
+    "eth_blockNumber" =>
+        req => json!([]),
+        res => json!("0x2a");
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0xb",
+            "limit": null,
+            "toBlock": "0x20",
+            "topics": null
+        }]),
+        res => json!([{
+            "address": "0x0000000000000000000000000000000000000000",
+            "topics": [],
+            "data": "0x20",
+            "type": "pending",
+            "blockNumber": null,
+            "transactionHash": null,
+            "logIndex": null
+        }, {
+            "address": "0x0000000000000000000000000000000000000000",
+            "topics": [],
+            "data": "0x10",
+            "type": "",
+            "blockNumber": "0x1f",
+            "transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "logIndex": "0x0"
+        }, {
+            "address": "0x0000000000000000000000000000000000000000",
+            "topics": [],
+            "data": "0x30",
+            "type": "pending",
+            "blockNumber": "0x20",
+            "transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000002",
+            "logIndex": null
+        }]);
+}
+
+#[test]
+fn log_stream_poll_jitter() {
+    let range = JitterRange::parse("40ms..60ms").unwrap();
+    let jitter = Jitter::from_seed(7);
+    let delay = jitter.clone().delay(&range);
+    let transport = MockedTransport {
+        requests: Default::default(),
+        expected_requests: vec![
+            ("eth_blockNumber", json!([])),
+            ("eth_getLogs", json!([{
+                "address": null,
+                "fromBlock": "0xb",
+                "limit": null,
+                "toBlock": "0x1006",
+                "topics": null
+            }])),
+        ].into_iter()
+            .map(Into::into)
+            .collect(),
+        mocked_responses: vec![json!("0x1010"), json!([])],
+    };
+    let init = LogStreamInit {
+        after: 10,
+        filter: FilterBuilder::default(),
+        poll_interval: Duration::from_secs(0),
+        poll_jitter: Some((range, jitter)),
+        chain: "home",
+        request_timeout: Duration::from_secs(5),
+        confirmations: 10,
+        sanity_check: None,
+        head_regression: HeadRegression::default(),
+        log_limit_errors: Vec::new(),
+        max_log_data: DEFAULT_MAX_LOG_DATA,
+        finality: None,
+    };
+
+    let started = Instant::now();
+    let item = log_stream(&transport, Default::default(), init)
+        .into_future()
+        .wait()
+        .map_err(|(err, _)| err)
+        .unwrap()
+        .0;
+    assert_eq!(
+        Some(LogStreamItem {
+            from: 0xb,
+            to: 0x1006,
+            logs: vec![],
+        }),
+        item
+    );
+    // the poll waited its jitter before fetching the block number
+    assert!(range.min <= delay && delay <= range.max);
+    assert!(started.elapsed() >= delay, "{:?} < {:?}", started.elapsed(), delay);
+}
+
+// quantities with leading zeros as some providers return them
+test_transport_stream! {
+    name => log_stream_quantities_with_leading_zeros,
+    init => |transport| {
+        let init = LogStreamInit {
+            after: 10,
+            filter: FilterBuilder::default(),
+            poll_interval: Duration::from_secs(0),
+            poll_jitter: None,
+            chain: "home",
+            request_timeout: Duration::from_secs(5),
+            confirmations: 10,
+            sanity_check: None,
+            head_regression: HeadRegression::default(),
+            log_limit_errors: Vec::new(),
+            max_log_data: DEFAULT_MAX_LOG_DATA,
+            finality: None,
+        };
+
+        log_stream(transport, Default::default(), init).take(1)
+    },
+    expected => vec![LogStreamItem {
+        from: 0xb,
+        to: 0x1006,
+        logs: vec![Log {
+            address: "0000000000000000000000000000000000000001".into(),
+            topics: vec![],
+            data: vec![0x00, 0x10].into(),
+            log_type: "".into(),
+            block_number: Some(0x1000u64.into()),
+            transaction_hash: Some(
+                "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364".into(),
+            ),
+            transaction_index: Some(2.into()),
+            log_index: Some(0.into()),
+            ..Default::default()
+        }],
+    }],
+    "eth_blockNumber" =>
+        req => json!([]),
+        res => json!("0x00001010");
+    "eth_getLogs" =>
+        req => json!([{
+            "address": null,
+            "fromBlock": "0xb",
+            "limit": null,
+            "toBlock": "0x1006",
+            "topics": null
+        }]),
+        res => json!([{
+            "address": "0x0000000000000000000000000000000000000001",
+            "topics": [],
+            "data": "0x0010",
+            "type": "",
+            "blockNumber": "0x0001000",
+            "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364",
+            "transactionIndex": "0x02",
+            "logIndex": "0x00"
+        }]);
+}
+
+#[test]
+fn log_stream_rejects_invalid_quantities() {
+    let transport = MockedTransport {
+        requests: Default::default(),
+        expected_requests: vec![
+            ("eth_blockNumber", json!([])),
+            ("eth_getLogs", json!([{
+                "address": null,
+                "fromBlock": "0xb",
+                "limit": null,
+                "toBlock": "0x1006",
+                "topics": null
+            }])),
+        ].into_iter()
+            .map(Into::into)
+            .collect(),
+        mocked_responses: vec![
+            json!("0x1010"),
+            json!([{
+                "address": "0x0000000000000000000000000000000000000001",
+                "topics": [],
+                "data": "0x10",
+                "blockNumber": "1000",
+                "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364",
+                "logIndex": "0x0"
+            }]),
+        ],
+    };
+    let init = LogStreamInit {
+        after: 10,
+        filter: FilterBuilder::default(),
+        poll_interval: Duration::from_secs(0),
+        poll_jitter: None,
+        chain: "home",
+        request_timeout: Duration::from_secs(5),
+        confirmations: 10,
+        sanity_check: None,
+        head_regression: HeadRegression::default(),
+        log_limit_errors: Vec::new(),
+        max_log_data: DEFAULT_MAX_LOG_DATA,
+        finality: None,
+    };
+
+    let err = log_stream(&transport, Default::default(), init)
+        .into_future()
+        .wait()
+        .map(|_| ())
+        .unwrap_err()
+        .0;
+    let message = err.to_string();
+    assert!(message.starts_with("invalid eth_getLogs result [{"), "{}", message);
+    assert!(
+        message.ends_with("blockNumber: quantity \"1000\" must start with 0x"),
+        "{}",
+        message
+    );
+}
+
+/// log of a transaction in `block` as the node returns it and as it's decoded.
+fn log_in_block(block: u64) -> (serde_json::Value, Log) {
+    let json = json!({
+        "address": "0x0000000000000000000000000000000000000001",
+        "topics": [],
+        "data": "0x10",
+        "type": "",
+        "blockNumber": format!("0x{:x}", block),
+        "transactionHash": format!("0x{:064x}", block),
+        "transactionIndex": "0x0",
+        "logIndex": "0x0"
+    });
+    let log = Log {
+        address: 1.into(),
+        topics: vec![],
+        data: vec![0x10].into(),
+        log_type: "".into(),
+        block_number: Some(block.into()),
+        transaction_hash: Some(block.into()),
+        transaction_index: Some(0.into()),
+        log_index: Some(0.into()),
+        ..Default::default()
+    };
+    (json, log)
+}
+
+fn get_logs(from: u64, to: u64) -> (&'static str, serde_json::Value) {
+    (
+        "eth_getLogs",
+        json!([{
+            "address": null,
+            "fromBlock": format!("0x{:x}", from),
+            "limit": null,
+            "toBlock": format!("0x{:x}", to),
+            "topics": null
+        }]),
+    )
+}
+
+#[test]
+fn log_stream_bisects_ranges_with_too_many_logs() {
+    let (json_1, log_1) = log_in_block(1);
+    let (json_3, log_3) = log_in_block(3);
+    let (json_7, log_7) = log_in_block(7);
+    let transport = MockedTransport {
+        requests: Default::default(),
+        expected_requests: vec![
+            ("eth_blockNumber", json!([])),
+            get_logs(1, 8),
+            get_logs(1, 4),
+            get_logs(1, 2),
+            get_logs(1, 1),
+            get_logs(2, 2),
+            get_logs(3, 4),
+            get_logs(5, 8),
+        ].into_iter()
+            .map(Into::into)
+            .collect(),
+        mocked_responses: vec![
+            json!("0x8"),
+            // infura
+            json!({"error": "query returned more than 10000 results"}),
+            // alchemy
+            json!({"error": "Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range"}),
+            // configured
+            json!({"error": "Response Too Large"}),
+            json!([json_1]),
+            json!([]),
+            json!([json_3]),
+            json!([json_7]),
+        ],
+    };
+    let init = LogStreamInit {
+        after: 0,
+        filter: FilterBuilder::default(),
+        poll_interval: Duration::from_secs(0),
+        poll_jitter: None,
+        chain: "home",
+        request_timeout: Duration::from_secs(5),
+        confirmations: 0,
+        sanity_check: None,
+        head_regression: HeadRegression::default(),
+        log_limit_errors: vec!["response too large".into()],
+        max_log_data: DEFAULT_MAX_LOG_DATA,
+        finality: None,
+    };
+
+    let bisections = bridge::api::log_bisections();
+    let item = log_stream(&transport, Default::default(), init)
+        .into_future()
+        .wait()
+        .map_err(|(err, _)| err)
+        .unwrap()
+        .0;
+    // one item for the whole range, logs in order
+    assert_eq!(
+        Some(LogStreamItem {
+            from: 1,
+            to: 8,
+            logs: vec![log_1, log_3, log_7],
+        }),
+        item
+    );
+    assert_eq!(8, transport.requests.get());
+    // other tests may bisect ranges at the same time
+    assert!(bridge::api::log_bisections() >= bisections + 3);
+}
+
+#[test]
+fn log_stream_fails_if_a_single_block_has_too_many_logs() {
+    let transport = MockedTransport {
+        requests: Default::default(),
+        expected_requests: vec![
+            ("eth_blockNumber", json!([])),
+            get_logs(1, 2),
+            get_logs(1, 1),
+        ].into_iter()
+            .map(Into::into)
+            .collect(),
+        mocked_responses: vec![
+            json!("0x2"),
+            json!({"error": "query returned more than 10000 results"}),
+            json!({"error": "query returned more than 10000 results"}),
+        ],
+    };
+    let init = LogStreamInit {
+        after: 0,
+        filter: FilterBuilder::default(),
+        poll_interval: Duration::from_secs(0),
+        poll_jitter: None,
+        chain: "home",
+        request_timeout: Duration::from_secs(5),
+        confirmations: 0,
+        sanity_check: None,
+        head_regression: HeadRegression::default(),
+        log_limit_errors: Vec::new(),
+        max_log_data: DEFAULT_MAX_LOG_DATA,
+        finality: None,
+    };
+
+    let err = log_stream(&transport, Default::default(), init)
+        .into_future()
+        .wait()
+        .map(|_| ())
+        .unwrap_err()
+        .0;
+    assert!(err.to_string().contains("query returned more than 10000 results"), "{}", err);
+    assert_eq!(3, transport.requests.get());
+}
+
+#[test]
+fn log_stream_drops_logs_with_oversized_data() {
+    let (json_1, log_1) = log_in_block(1);
+    let (mut json_2, _) = log_in_block(2);
+    json_2["data"] = json!(format!("0x{}", "00".repeat(DEFAULT_MAX_LOG_DATA + 1)));
+    let (json_3, log_3) = log_in_block(3);
+    let transport = MockedTransport {
+        requests: Default::default(),
+        expected_requests: vec![("eth_blockNumber", json!([])), get_logs(1, 3)]
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+        mocked_responses: vec![json!("0x3"), json!([json_1, json_2, json_3])],
+    };
+    let init = LogStreamInit {
+        after: 0,
+        filter: FilterBuilder::default(),
+        poll_interval: Duration::from_secs(0),
+        poll_jitter: None,
+        chain: "home",
+        request_timeout: Duration::from_secs(5),
+        confirmations: 0,
+        sanity_check: None,
+        head_regression: HeadRegression::default(),
+        log_limit_errors: Vec::new(),
+        max_log_data: DEFAULT_MAX_LOG_DATA,
+        finality: None,
+    };
+
+    let oversized = bridge::api::oversized_logs();
+    let mut stream = log_stream(&transport, Default::default(), init);
+    let item = stream
+        .by_ref()
+        .into_future()
+        .wait()
+        .map_err(|(err, _)| err)
+        .unwrap()
+        .0;
+    assert_eq!(
+        Some(LogStreamItem {
+            from: 1,
+            to: 3,
+            logs: vec![log_1, log_3],
+        }),
+        item
+    );
+    assert_eq!(
+        vec![OversizedLog {
+            address: 1.into(),
+            transaction_hash: Some(2.into()),
+            block: Some(2),
+            log_index: Some(0.into()),
+            size: DEFAULT_MAX_LOG_DATA + 1,
+        }],
+        stream.take_oversized_logs()
+    );
+    assert!(stream.take_oversized_logs().is_empty());
+    // other tests may drop logs at the same time
+    assert!(bridge::api::oversized_logs() >= oversized + 1);
+}
@@ -0,0 +1,121 @@
+/// test that the personal signer passes the password along with each request
+extern crate bridge;
+extern crate futures;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate tests;
+extern crate web3;
+
+use web3::types::{H256, H520, TransactionRequest};
+use bridge::api::{node_send_transaction, node_sign, personal_send_transaction};
+use bridge::config::{Node, Password, Signer};
+
+fn personal_node() -> Node {
+    Node {
+        signer: Signer::Personal,
+        password_file: Some("/node.pwd".into()),
+        password: Some(Password::new("hunter2")),
+        ..Node::new(
+            "0000000000000000000000000000000000000001".parse().unwrap(),
+            "".into(),
+        )
+    }
+}
+
+fn transaction() -> TransactionRequest {
+    TransactionRequest {
+        from: "0000000000000000000000000000000000000001".parse().unwrap(),
+        to: Some("49edf201c1e139282643d5e7c6fb0c7219ad1db8".parse().unwrap()),
+        gas: Some(0xfe.into()),
+        gas_price: Some(0xa1.into()),
+        value: None,
+        data: Some(vec![0x12, 0x34].into()),
+        nonce: None,
+        condition: None,
+    }
+}
+
+test_transport_stream! {
+    name => personal_send_transaction_basic,
+    init => |transport| {
+        personal_send_transaction(transport, transaction(), &Password::new("hunter2"))
+            .into_stream()
+    },
+    expected => vec![
+        "1db8f385535c0d178b8f40016048f3a3cffee8f94e68978ea4b277f57b638f0b"
+            .parse::<H256>()
+            .unwrap()
+    ],
+    "personal_sendTransaction" =>
+        req => json!([{
+            "data": "0x1234",
+            "from": "0x0000000000000000000000000000000000000001",
+            "gas": "0xfe",
+            "gasPrice": "0xa1",
+            "to": "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+        }, "hunter2"]),
+        res => json!("0x1db8f385535c0d178b8f40016048f3a3cffee8f94e68978ea4b277f57b638f0b");
+}
+
+test_transport_stream! {
+    name => node_send_transaction_uses_personal_signer,
+    init => |transport| {
+        node_send_transaction(transport, &personal_node(), transaction()).into_stream()
+    },
+    expected => vec![
+        "1db8f385535c0d178b8f40016048f3a3cffee8f94e68978ea4b277f57b638f0b"
+            .parse::<H256>()
+            .unwrap()
+    ],
+    "personal_sendTransaction" =>
+        req => json!([{
+            "data": "0x1234",
+            "from": "0x0000000000000000000000000000000000000001",
+            "gas": "0xfe",
+            "gasPrice": "0xa1",
+            "to": "0x49edf201c1e139282643d5e7c6fb0c7219ad1db8"
+        }, "hunter2"]),
+        res => json!("0x1db8f385535c0d178b8f40016048f3a3cffee8f94e68978ea4b277f57b638f0b");
+}
+
+test_transport_stream! {
+    name => node_sign_uses_personal_signer,
+    init => |transport| {
+        node_sign(transport, &personal_node(), vec![0x12, 0x34].into()).into_stream()
+    },
+    expected => vec![
+        "8697c15331677e6ebccccaff3454fce5edbc8cca8697c15331677aff3454fce5edbc8cca8697c15331677e6ebccccaff3454fce5edbc8cca8697c15331677e6ebc"
+            .parse::<H520>()
+            .unwrap()
+    ],
+    "personal_sign" =>
+        req => json!([
+            "0x1234",
+            "0x0000000000000000000000000000000000000001",
+            "hunter2"
+        ]),
+        res => json!("0x8697c15331677e6ebccccaff3454fce5edbc8cca8697c15331677aff3454fce5edbc8cca8697c15331677e6ebccccaff3454fce5edbc8cca8697c15331677e6ebc");
+}
+
+test_transport_stream! {
+    name => node_sign_uses_node_signer_by_default,
+    init => |transport| {
+        let node = Node::new(
+            "0000000000000000000000000000000000000001".parse().unwrap(),
+            "".into(),
+        );
+        node_sign(transport, &node, vec![0x12, 0x34].into()).into_stream()
+    },
+    expected => vec![
+        "8697c15331677e6ebccccaff3454fce5edbc8cca8697c15331677aff3454fce5edbc8cca8697c15331677e6ebccccaff3454fce5edbc8cca8697c15331677e6ebc"
+            .parse::<H520>()
+            .unwrap()
+    ],
+    "eth_sign" =>
+        req => json!([
+            "0x0000000000000000000000000000000000000001",
+            "0x1234"
+        ]),
+        res => json!("0x8697c15331677e6ebccccaff3454fce5edbc8cca8697c15331677aff3454fce5edbc8cca8697c15331677e6ebccccaff3454fce5edbc8cca8697c15331677e6ebc");
+}
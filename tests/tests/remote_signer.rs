@@ -0,0 +1,186 @@
+/// test that nodes with a remote signer only get transactions signed by it
+extern crate bridge;
+extern crate futures;
+#[macro_use]
+extern crate serde_json;
+extern crate tests;
+extern crate web3;
+
+use std::sync::Arc;
+use std::time::Duration;
+use futures::Future;
+use web3::types::{Address, Bytes, TransactionRequest};
+use bridge::api::{node_send_transaction, node_sign};
+use bridge::config::{Node, Password, RemoteSignerConfig, Signer};
+use bridge::error::{Error, ErrorKind};
+use bridge::message_to_mainnet::MESSAGE_LENGTH;
+use bridge::remote_signer::{is_remote_signer_error, Allowlist, RecoverableSignature, RemoteSigner,
+                            RemoteSigningTransport, UnsignedTransaction};
+use tests::{FakeChain, StubSigner, FAKE_CHAIN_ID};
+
+fn contract() -> Address {
+    "49edf201c1e139282643d5e7c6fb0c7219ad1db8".parse().unwrap()
+}
+
+fn signature() -> Vec<u8> {
+    let mut signature = vec![0x11u8; 65];
+    signature[64] = 1;
+    signature
+}
+
+fn remote_node(url: &str) -> Node {
+    let mut node = Node::new(1.into(), "".into());
+    node.signer = Signer::Remote;
+    node.remote_signer = Some(RemoteSignerConfig {
+        url: url.into(),
+        token_file: Some("/signer.token".into()),
+        token: Some(Password::new("secret")),
+        identity_file: None,
+        identity_password_file: None,
+        identity_password: None,
+        ca_file: None,
+        timeout: Duration::from_secs(5),
+        retry_delay: Duration::from_secs(30),
+    });
+    node
+}
+
+fn transport(chain: &FakeChain, node: &Node) -> RemoteSigningTransport<FakeChain> {
+    let signer = RemoteSigner::new(
+        node.remote_signer.as_ref().unwrap(),
+        Allowlist::new(vec![contract()]),
+    ).unwrap();
+    RemoteSigningTransport::new(chain.clone(), Some(Arc::new(signer)))
+}
+
+fn transaction(to: Address) -> TransactionRequest {
+    TransactionRequest {
+        from: 1.into(),
+        to: Some(to),
+        gas: Some(0xfe.into()),
+        gas_price: Some(0xa1.into()),
+        value: None,
+        data: Some(vec![0x12, 0x34].into()),
+        nonce: None,
+        condition: None,
+    }
+}
+
+fn transport_error(err: Error) -> String {
+    match *err.kind() {
+        ErrorKind::Web3(web3::Error::Transport(ref message)) => message.clone(),
+        ref other => panic!("expected a transport error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_remote_signer_sends_raw_transactions() {
+    let signer = StubSigner::start(signature());
+    let chain = FakeChain::new();
+    let node = remote_node(signer.url());
+    let transport = transport(&chain, &node);
+
+    node_send_transaction(&transport, &node, transaction(contract()))
+        .wait()
+        .unwrap();
+    node_send_transaction(&transport, &node, transaction(contract()))
+        .wait()
+        .unwrap();
+
+    assert_eq!(0, chain.requests("eth_sendTransaction"));
+    assert_eq!(2, chain.requests("eth_sendRawTransaction"));
+    let requests = signer.requests();
+    assert_eq!(2, requests.len());
+    assert_eq!("transaction", requests[0]["type"]);
+    assert_eq!(FAKE_CHAIN_ID, requests[0]["chain_id"]);
+    assert_eq!("0x0", requests[0]["transaction"]["nonce"]);
+    assert_eq!("0x1", requests[1]["transaction"]["nonce"]);
+    assert_eq!(
+        vec![Some("Bearer secret".to_owned()), Some("Bearer secret".to_owned())],
+        signer.authorizations()
+    );
+    // the chain id is only fetched once
+    assert_eq!(1, chain.requests("eth_chainId"));
+
+    let unsigned = UnsignedTransaction {
+        nonce: 0.into(),
+        gas_price: 0xa1.into(),
+        gas: 0xfe.into(),
+        to: contract(),
+        value: 0.into(),
+        data: Bytes(vec![0x12, 0x34]),
+    };
+    let signature = RecoverableSignature::from_bytes(&signature()).unwrap();
+    assert_eq!(
+        json!(unsigned.signed(FAKE_CHAIN_ID, &signature)),
+        chain.sent_transactions()[0]
+    );
+}
+
+#[test]
+fn test_remote_signer_refuses_other_transactions() {
+    let signer = StubSigner::start(signature());
+    let chain = FakeChain::new();
+    let node = remote_node(signer.url());
+    let transport = transport(&chain, &node);
+
+    let err = node_send_transaction(&transport, &node, transaction(2.into()))
+        .wait()
+        .unwrap_err();
+    assert!(is_remote_signer_error(&transport_error(err)));
+    let mut creation = transaction(contract());
+    creation.to = None;
+    let err = node_send_transaction(&transport, &node, creation)
+        .wait()
+        .unwrap_err();
+    assert!(is_remote_signer_error(&transport_error(err)));
+    assert!(signer.requests().is_empty());
+    assert!(chain.sent_transactions().is_empty());
+}
+
+#[test]
+fn test_remote_signer_signs_withdraw_messages() {
+    let signer = StubSigner::start(signature());
+    let chain = FakeChain::new();
+    let node = remote_node(signer.url());
+    let transport = transport(&chain, &node);
+
+    let signed = node_sign(&transport, &node, Bytes(vec![0u8; MESSAGE_LENGTH]))
+        .wait()
+        .unwrap();
+    // `v` is 27 or 28 like from `eth_sign`
+    assert_eq!(28, signed.0[64]);
+    assert_eq!(&signature()[..64], &signed.0[..64]);
+    assert_eq!(0, chain.requests("eth_sign"));
+    assert_eq!("message", signer.requests()[0]["type"]);
+
+    let err = node_sign(&transport, &node, Bytes(vec![0u8; 32]))
+        .wait()
+        .unwrap_err();
+    assert!(is_remote_signer_error(&transport_error(err)));
+    assert_eq!(1, signer.requests().len());
+}
+
+#[test]
+fn test_failed_signing_requests_send_nothing() {
+    let signer = StubSigner::start(signature());
+    let chain = FakeChain::new();
+    let node = remote_node(signer.url());
+    let transport = transport(&chain, &node);
+
+    signer.set_failing(Some(503));
+    let err = node_send_transaction(&transport, &node, transaction(contract()))
+        .wait()
+        .unwrap_err();
+    let message = transport_error(err);
+    assert!(is_remote_signer_error(&message));
+    assert!(message.contains("503"));
+    assert!(chain.sent_transactions().is_empty());
+
+    // the nonce of the failed transaction is used again
+    signer.set_failing(None);
+    node_send_transaction(&transport, &node, transaction(contract()))
+        .wait()
+        .unwrap();
+    assert_eq!("0x0", signer.requests()[1]["transaction"]["nonce"]);
+}
@@ -0,0 +1,107 @@
+/// test that captured rpc traffic can be replayed
+extern crate bridge;
+extern crate futures;
+#[macro_use]
+extern crate serde_json;
+extern crate tests;
+extern crate web3;
+
+use std::{env, fs};
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use futures::{Future, Stream};
+use web3::types::FilterBuilder;
+use bridge::api::{self, log_stream, LogStreamInit, LogStreamItem};
+use bridge::config::{HeadRegression, DEFAULT_MAX_LOG_DATA};
+use bridge::transport::{LoggingTransport, Recorder, RecordingTransport};
+use tests::{MockedRequest, MockedTransport};
+
+const CAPTURE: &str = r#"
+{"chain":"home","method":"eth_blockNumber","params":[],"response":"0x1010"}
+{"chain":"foreign","method":"eth_blockNumber","params":[],"response":"0x5"}
+{"chain":"home","method":"eth_getLogs","params":[{"address":null,"fromBlock":"0xb","limit":null,"toBlock":"0x1006","topics":null}],"response":[]}
+"#;
+
+fn log_stream_init() -> LogStreamInit {
+    LogStreamInit {
+        after: 10,
+        filter: FilterBuilder::default(),
+        poll_interval: Duration::from_secs(0),
+        poll_jitter: None,
+        chain: "home",
+        request_timeout: Duration::from_secs(5),
+        confirmations: 10,
+        sanity_check: None,
+        head_regression: HeadRegression::default(),
+        log_limit_errors: Vec::new(),
+        max_log_data: DEFAULT_MAX_LOG_DATA,
+        finality: None,
+    }
+}
+
+#[test]
+fn replay_capture() {
+    let transport = MockedTransport::from_capture(CAPTURE, "home");
+    let items = log_stream(&transport, Default::default(), log_stream_init())
+        .take(1)
+        .collect()
+        .wait()
+        .unwrap();
+
+    assert_eq!(
+        vec![LogStreamItem {
+            from: 0xb,
+            to: 0x1006,
+            logs: vec![],
+        }],
+        items
+    );
+    assert_eq!(transport.expected_requests.len(), transport.requests.get());
+}
+
+#[test]
+fn record_and_replay() {
+    let path = env::temp_dir().join("bridge-rpc-capture-record-and-replay.jsonl");
+    let _ = fs::remove_file(&path);
+
+    let transport = MockedTransport {
+        requests: Default::default(),
+        expected_requests: vec![
+            ("eth_blockNumber", json!([])).into(),
+            ("eth_getBalance", json!(["0x0000000000000000000000000000000000000001", "latest"]))
+                .into(),
+        ],
+        mocked_responses: vec![json!("0x1010"), json!("0x64")],
+    };
+    let recorder = Arc::new(Recorder::create(&path).unwrap());
+    let recording = RecordingTransport::new(&transport, "home", recorder.clone());
+    // middleware composes
+    let recording = LoggingTransport::new(recording, "home", 1024);
+
+    let block = api::block_number(&recording).wait().unwrap();
+    let balance = api::balance(&recording, 1.into()).wait().unwrap();
+    // entries are written on the writer thread
+    recorder.flush();
+    assert_eq!(0, recorder.dropped());
+
+    let mut capture = String::new();
+    fs::File::open(&path)
+        .unwrap()
+        .read_to_string(&mut capture)
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let replay = MockedTransport::from_capture(&capture, "home");
+    assert_eq!(
+        vec!["eth_blockNumber", "eth_getBalance"],
+        replay
+            .expected_requests
+            .iter()
+            .map(|request: &MockedRequest| request.method.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(block, api::block_number(&replay).wait().unwrap());
+    assert_eq!(balance, api::balance(&replay, 1.into()).wait().unwrap());
+    assert_eq!(replay.expected_requests.len(), replay.requests.get());
+}
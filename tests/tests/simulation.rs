@@ -0,0 +1,70 @@
+/// test that a capture replays through all bridge components
+extern crate bridge;
+extern crate ethereum_types;
+
+use std::time::Duration;
+use ethereum_types::U256;
+use bridge::config::{Authorities, Config, Node};
+use bridge::database::Database;
+use bridge::simulation::simulate;
+
+const CAPTURE: &str = r#"
+{"chain":"home","method":"eth_blockNumber","params":[],"response":"0x1011"}
+{"chain":"home","method":"eth_getLogs","params":[{"address":["0x0000000000000000000000000000000000000000"],"fromBlock":"0x6","limit":null,"toBlock":"0x1005","topics":[["0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c"],null,null,null]}],"response":[{"address":"0x0000000000000000000000000000000000000000","topics":["0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c"],"data":"0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0","type":"","blockNumber":"0x100","logIndex":"0x0","transactionHash":"0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"}]}
+{"chain":"foreign","method":"eth_blockNumber","params":[],"response":"0x20"}
+{"chain":"home","method":"eth_blockNumber","params":[],"response":"0x1012"}
+{"chain":"foreign","method":"eth_sendTransaction","params":[{}],"response":"0x1db8f385535c0d178b8f40016048f3a3cffee8f87e68978ea4b536ca9f9ec07a"}
+"#;
+
+fn config() -> Config {
+    let node = |account: &str| Node {
+        account: account.parse().unwrap(),
+        poll_interval: Duration::from_secs(5),
+        required_confirmations: 12,
+        ..Node::new(Default::default(), "".into())
+    };
+    Config::builder()
+        .home(node("0000000000000000000000000000000000000001"))
+        .foreign(node("0000000000000000000000000000000000000001"))
+        .authorities(Authorities {
+            accounts: vec![
+                "0000000000000000000000000000000000000001".parse().unwrap(),
+                "0000000000000000000000000000000000000002".parse().unwrap(),
+            ],
+            required_signatures: 1,
+        })
+        .estimated_gas_cost_of_withdraw(U256::from_dec_str("100000").unwrap())
+        .max_total_home_contract_balance(U256::from_dec_str("10000000000000000000").unwrap())
+        .max_single_deposit_value(U256::from_dec_str("1000000000000000000").unwrap())
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn simulate_capture() {
+    let database = Database {
+        checked_deposit_relay: 5,
+        ..Database::default()
+    };
+    let report = simulate(&config(), &database, CAPTURE).unwrap();
+    assert_eq!(3, report.components.len());
+
+    let deposit_relay = &report.components[0];
+    assert_eq!("DepositRelay", deposit_relay.component);
+    assert_eq!(Some(0x1006), deposit_relay.checked_block);
+    assert_eq!(1, deposit_relay.counts.relayed);
+    assert!(deposit_relay.error_chain.is_empty());
+    assert!(deposit_relay.replay.finished);
+    // the transaction is fabricated, not taken from the capture
+    assert_eq!(1, deposit_relay.replay.fabricated);
+    assert_eq!(Some(&3), deposit_relay.replay.requests.get("home eth_blockNumber"));
+    assert_eq!(Some(&2), deposit_relay.replay.requests.get("home eth_getLogs"));
+    assert_eq!(Some(&1), deposit_relay.replay.requests.get("foreign eth_sendTransaction"));
+
+    // every component replays the whole capture
+    for component in &report.components[1..] {
+        assert_eq!(Some(0x14), component.checked_block);
+        assert_eq!(0, component.counts.relayed);
+        assert!(component.error_chain.is_empty());
+    }
+}
@@ -195,6 +195,7 @@ test_app_stream! {
         withdraw_confirm: TransactionConfig {
             gas: 0xfe,
             gas_price: 0xa1,
+            estimate_gas: false,
         },
         ..Default::default()
     },
@@ -225,6 +226,8 @@ test_app_stream! {
                     Token::Uint(1000.into()),
                 ]).to_hex()),
                 "type": "",
+                "blockNumber": "0xf6",
+                "logIndex": "0x0",
                 "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
             }]);
         "eth_sign" =>
@@ -300,6 +303,7 @@ test_app_stream! {
         withdraw_confirm: TransactionConfig {
             gas: 0xff,
             gas_price: 0xaa,
+            estimate_gas: false,
         },
         ..Default::default()
     },
@@ -327,6 +331,8 @@ test_app_stream! {
                     Token::Uint(1000.into()),
                 ]).to_hex()),
                 "type": "",
+                "blockNumber": "0x1",
+                "logIndex": "0x0",
                 "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"},
             {
                 "address":"0x49edf201c1e139282643d5e7c6fb0c7219ad1db8",
@@ -337,6 +343,8 @@ test_app_stream! {
                     Token::Uint(100.into()),
                 ]).to_hex()),
                 "type":"",
+                "blockNumber": "0x1",
+                "logIndex": "0x1",
                 "transactionHash":"0xfffedad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
             }]);
         "eth_sign" =>
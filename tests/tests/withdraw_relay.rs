@@ -110,6 +110,8 @@ test_app_stream! {
                 "topics": [COLLECTED_SIGNATURES_TOPIC],
                 "data": "0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0",
                 "type": "",
+                "blockNumber": "0x1",
+                "logIndex": "0x0",
                 "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
             }]);
     ]
@@ -182,6 +184,8 @@ test_app_stream! {
                 "topics": [COLLECTED_SIGNATURES_TOPIC],
                 "data": "0x000000000000000000000000aff3454fce5edbc8cca8697c15331677e6ebcccc00000000000000000000000000000000000000000000000000000000000000f0",
                 "type": "",
+                "blockNumber": "0x1",
+                "logIndex": "0x0",
                 "transactionHash": "0x884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
             }]);
         // call to `message`
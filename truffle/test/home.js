@@ -1,4 +1,5 @@
 var HomeBridge = artifacts.require("HomeBridge");
+var PayoutRegistry = artifacts.require("PayoutRegistry");
 var helpers = require("./helpers/helpers");
 
 function newHomeBridge(options) {
@@ -11,12 +12,16 @@ function newHomeBridge(options) {
   if (options.maxSingleDepositValue === undefined) {
     options.maxSingleDepositValue = 0;
   }
+  if (options.payoutRegistry === undefined) {
+    options.payoutRegistry = "0x0000000000000000000000000000000000000000";
+  }
   return HomeBridge.new(
     options.requiredSignatures,
     options.authorities,
     options.estimatedGasCostOfWithdraw,
     options.maxTotalHomeContractBalance,
-    options.maxSingleDepositValue
+    options.maxSingleDepositValue,
+    options.payoutRegistry
   )
 }
 
@@ -690,4 +695,116 @@ contract('HomeBridge', function(accounts) {
       }, helpers.ignoreExpectedError)
     })
   })
+
+  it("should pay withdrawToPayout to the payout address the recipient registered", function() {
+    var homeBridge;
+    var registry;
+    var authorities = [accounts[0], accounts[1]];
+    var userAccount = accounts[2];
+    var recipientAccount = accounts[3];
+    var payoutAccount = accounts[4];
+    var initialBalances;
+    var value = web3.toBigNumber(web3.toWei(1, "ether"));
+    var homeGasPrice = web3.toBigNumber(0);
+    var transactionHash = "0x1045bfe274b88120a6b1e5d01b5ec00ab5d01098346e90e7c7a3c9b8f0181c80";
+    var message = helpers.createMessage(recipientAccount, value, transactionHash, homeGasPrice);
+
+    return PayoutRegistry.new().then(function(instance) {
+      registry = instance;
+
+      return registry.registerPayoutAddress(payoutAccount, {from: recipientAccount});
+    }).then(function(result) {
+      assert.equal("PayoutAddressRegistered", result.logs[0].event);
+
+      return newHomeBridge({
+        requiredSignatures: 1,
+        authorities: authorities,
+        payoutRegistry: registry.address,
+      });
+    }).then(function(instance) {
+      homeBridge = instance;
+
+      return homeBridge.sendTransaction({
+        value: value,
+        from: userAccount
+      })
+    }).then(function(result) {
+      return helpers.getBalances(accounts);
+    }).then(function(result) {
+      initialBalances = result;
+
+      return helpers.sign(authorities[0], message);
+    }).then(function(signature) {
+      var vrs = helpers.signatureToVRS(signature);
+      return homeBridge.withdrawToPayout(
+        [vrs.v],
+        [vrs.r],
+        [vrs.s],
+        message,
+        payoutAccount,
+        {from: userAccount, gasPrice: homeGasPrice}
+      );
+    }).then(function(result) {
+      assert.equal(2, result.logs.length, "Exactly two events should be created");
+      assert.equal("Withdraw", result.logs[0].event, "Event name should be Withdraw");
+      assert.equal(recipientAccount, result.logs[0].args.recipient, "Event recipient should match recipient in message");
+      assert.equal("PayoutSubstituted", result.logs[1].event, "Event name should be PayoutSubstituted");
+      assert.equal(payoutAccount, result.logs[1].args.payout);
+      assert.equal(transactionHash, result.logs[1].args.transactionHash);
+
+      return helpers.getBalances(accounts);
+    }).then(function(balances) {
+      assert(
+        balances[payoutAccount].equals(initialBalances[payoutAccount].plus(value)),
+        "Payout address received value");
+      assert(
+        balances[recipientAccount].equals(initialBalances[recipientAccount]),
+        "Recipient received nothing");
+    })
+  })
+
+  it("withdrawToPayout should fail if the payout address isn't registered by the recipient", function() {
+    var homeBridge;
+    var registry;
+    var authorities = [accounts[0], accounts[1]];
+    var userAccount = accounts[2];
+    var recipientAccount = accounts[3];
+    var value = web3.toBigNumber(web3.toWei(1, "ether"));
+    var homeGasPrice = web3.toBigNumber(0);
+    var transactionHash = "0x1045bfe274b88120a6b1e5d01b5ec00ab5d01098346e90e7c7a3c9b8f0181c80";
+    var message = helpers.createMessage(recipientAccount, value, transactionHash, homeGasPrice);
+
+    return PayoutRegistry.new().then(function(instance) {
+      registry = instance;
+
+      return registry.registerPayoutAddress(accounts[4], {from: recipientAccount});
+    }).then(function(result) {
+      return newHomeBridge({
+        requiredSignatures: 1,
+        authorities: authorities,
+        payoutRegistry: registry.address,
+      });
+    }).then(function(instance) {
+      homeBridge = instance;
+
+      return homeBridge.sendTransaction({
+        value: value,
+        from: userAccount
+      })
+    }).then(function(result) {
+      return helpers.sign(authorities[0], message);
+    }).then(function(signature) {
+      var vrs = helpers.signatureToVRS(signature);
+      return homeBridge.withdrawToPayout(
+        [vrs.v],
+        [vrs.r],
+        [vrs.s],
+        message,
+        accounts[5],
+        {from: userAccount, gasPrice: homeGasPrice}
+      );
+    }).then(function(result) {
+      assert(false, "should fail");
+    }, helpers.ignoreExpectedError)
+  })
 })
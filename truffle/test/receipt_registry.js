@@ -0,0 +1,73 @@
+var ReceiptRegistry = artifacts.require("ReceiptRegistry");
+var helpers = require("./helpers/helpers");
+
+contract("ReceiptRegistry", function(accounts) {
+  var homeTransactionHash = "0x1045bfe274b88120a6b1e5d01b5ec00ab5d01098346e90e7c7a3c9b8f0181c80";
+  var foreignTransactionHash = "0x35d3818e50234655f6aebb2a1cfbf30f59568d8a4ec72066fac5a25dbe7b8121";
+  var noReceipt = "0x0000000000000000000000000000000000000000000000000000000000000000";
+
+  it("should fail to deploy contract without authorities", function() {
+    return ReceiptRegistry.new([]).then(function(result) {
+      assert(false, "should fail");
+    }, helpers.ignoreExpectedError)
+  })
+
+  it("should record the receipt of a deposit", function() {
+    var registry;
+    var authorities = [accounts[0], accounts[1]];
+
+    return ReceiptRegistry.new(authorities).then(function(instance) {
+      registry = instance;
+
+      return registry.receiptOf.call(homeTransactionHash);
+    }).then(function(result) {
+      assert.equal(noReceipt, result, "no receipt should be recorded yet");
+
+      return registry.recordReceipt(homeTransactionHash, foreignTransactionHash, {from: authorities[1]});
+    }).then(function(result) {
+      assert.equal(1, result.logs.length, "Exactly one event should be created");
+      assert.equal("ReceiptRecorded", result.logs[0].event, "Event name should be ReceiptRecorded");
+      assert.equal(homeTransactionHash, result.logs[0].args.homeTransactionHash);
+      assert.equal(foreignTransactionHash, result.logs[0].args.foreignTransactionHash);
+
+      return registry.receiptOf.call(homeTransactionHash);
+    }).then(function(result) {
+      assert.equal(foreignTransactionHash, result, "receipt should be recorded");
+    })
+  })
+
+  it("should fail to record a receipt twice", function() {
+    var registry;
+    var authorities = [accounts[0], accounts[1]];
+
+    return ReceiptRegistry.new(authorities).then(function(instance) {
+      registry = instance;
+
+      return registry.recordReceipt(homeTransactionHash, foreignTransactionHash, {from: authorities[0]});
+    }).then(function(result) {
+      return registry.recordReceipt(homeTransactionHash, foreignTransactionHash, {from: authorities[1]});
+    }).then(function(result) {
+      assert(false, "should fail because the receipt is already recorded");
+    }, helpers.ignoreExpectedError)
+  })
+
+  it("should fail to record a receipt if not called by an authority", function() {
+    var authorities = [accounts[0], accounts[1]];
+
+    return ReceiptRegistry.new(authorities).then(function(instance) {
+      return instance.recordReceipt(homeTransactionHash, foreignTransactionHash, {from: accounts[2]});
+    }).then(function(result) {
+      assert(false, "should fail because the sender is not an authority");
+    }, helpers.ignoreExpectedError)
+  })
+
+  it("should fail to record an empty receipt", function() {
+    var authorities = [accounts[0], accounts[1]];
+
+    return ReceiptRegistry.new(authorities).then(function(instance) {
+      return instance.recordReceipt(homeTransactionHash, noReceipt, {from: accounts[0]});
+    }).then(function(result) {
+      assert(false, "should fail because the foreign transaction is 0");
+    }, helpers.ignoreExpectedError)
+  })
+})